@@ -0,0 +1,397 @@
+// Authentication and authorization of the admin API
+//
+// Every endpoint but /health needs a bearer token, and each route needs a
+// permission granted by the role of the token:
+//   viewer        - read the metrics, policies and users
+//   policy-editor - also create, update, delete and roll back policies
+//   admin         - also manage the users and the tokens, and read the audit log
+// Tokens are only stored as their SHA-256 hash. The ADMIN_TOKEN environment
+// variable adds an admin token; without it, an admin token is generated and
+// printed once when there is no token at all. Every mutation is recorded in
+// the audit log with its caller and outcome. A client presenting MAX_FAILURES
+// invalid tokens within FAILURE_WINDOW is locked out for LOCKOUT, even with a
+// valid token.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use warp::http::{Method, StatusCode};
+use warp::{Filter, Rejection, Reply};
+
+use crate::current_timestamp;
+use crate::storage::Database;
+
+const MAX_FAILURES: u32 = 5;
+const FAILURE_WINDOW: Duration = Duration::from_secs(300);
+const LOCKOUT: Duration = Duration::from_secs(900);
+// Id of the token given by ADMIN_TOKEN, which is not stored
+const ENV_TOKEN_ID: &str = "env";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    Viewer,
+    PolicyEditor,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::PolicyEditor => "policy-editor",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "viewer" => Some(Role::Viewer),
+            "policy-editor" => Some(Role::PolicyEditor),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Permission {
+    Read,
+    EditPolicies,
+    Admin,
+}
+
+impl Permission {
+    fn granted_to(self, role: Role) -> bool {
+        let needed = match self {
+            Permission::Read => Role::Viewer,
+            Permission::EditPolicies => Role::PolicyEditor,
+            Permission::Admin => Role::Admin,
+        };
+        role >= needed
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ApiToken {
+    pub id: String,
+    pub name: String,
+    pub role: Role,
+    pub created_at: u64,
+    pub created_by: String,
+    #[serde(skip)]
+    pub hash: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct CreateTokenRequest {
+    pub name: String,
+    pub role: Role,
+}
+
+// Caller of an authorized request
+#[derive(Clone, Debug, Serialize)]
+pub struct Principal {
+    pub token_id: String,
+    pub name: String,
+    pub role: Role,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AuditEntry {
+    pub timestamp: u64,
+    pub actor: String,
+    pub role: String,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub remote: String,
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    Missing,
+    Invalid,
+    Forbidden,
+    LockedOut(Duration),
+}
+
+impl warp::reject::Reject for AuthError {}
+
+struct Failures {
+    count: u32,
+    since: Instant,
+    locked_until: Option<Instant>,
+}
+
+pub struct Auth {
+    // Tokens by hash
+    tokens: Mutex<HashMap<String, ApiToken>>,
+    failures: Mutex<HashMap<IpAddr, Failures>>,
+    db: Database,
+}
+
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn bearer(authorization: Option<&str>) -> Option<&str> {
+    authorization
+        .and_then(|v| v.strip_prefix("Bearer ").or_else(|| v.strip_prefix("bearer ")))
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+}
+
+fn generate_token() -> String {
+    format!("arcus_{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+impl Auth {
+    // Load the saved tokens, along with the one of ADMIN_TOKEN if set
+    pub async fn load(db: Database, admin_token: Option<String>) -> Result<Self, sqlx::Error> {
+        let mut tokens: HashMap<String, ApiToken> = db
+            .load_tokens()
+            .await?
+            .into_iter()
+            .map(|token| (token.hash.clone(), token))
+            .collect();
+        match admin_token.filter(|t| !t.is_empty()) {
+            Some(secret) => {
+                let token = ApiToken {
+                    id: ENV_TOKEN_ID.to_string(),
+                    name: "ADMIN_TOKEN".to_string(),
+                    role: Role::Admin,
+                    created_at: current_timestamp(),
+                    created_by: "environment".to_string(),
+                    hash: hash_token(&secret),
+                };
+                tokens.insert(token.hash.clone(), token);
+            }
+            None if tokens.is_empty() => {
+                let (token, secret) = new_token("initial-admin", Role::Admin, "startup");
+                db.save_token(&token).await?;
+                println!("Created the initial admin token, it will not be shown again: {}", secret);
+                tokens.insert(token.hash.clone(), token);
+            }
+            None => {}
+        }
+        Ok(Auth {
+            tokens: Mutex::new(tokens),
+            failures: Mutex::new(HashMap::new()),
+            db,
+        })
+    }
+
+    fn authenticate(
+        &self,
+        authorization: Option<&str>,
+        remote: Option<IpAddr>,
+        permission: Permission,
+    ) -> Result<ApiToken, AuthError> {
+        // clients without an address share the lockout of the unspecified one
+        let client = remote.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        let now = Instant::now();
+        if let Some(until) = self.failures.lock().unwrap().get(&client).and_then(|f| f.locked_until) {
+            if until > now {
+                return Err(AuthError::LockedOut(until - now));
+            }
+        }
+
+        let secret = bearer(authorization).ok_or(AuthError::Missing)?;
+        let token = self.tokens.lock().unwrap().get(&hash_token(secret)).cloned();
+        let Some(token) = token else {
+            self.record_failure(client, now);
+            return Err(AuthError::Invalid);
+        };
+        self.failures.lock().unwrap().remove(&client);
+        if !permission.granted_to(token.role) {
+            return Err(AuthError::Forbidden);
+        }
+        Ok(token)
+    }
+
+    fn record_failure(&self, client: IpAddr, now: Instant) {
+        let mut failures = self.failures.lock().unwrap();
+        failures.retain(|_, f| {
+            now.duration_since(f.since) < FAILURE_WINDOW || f.locked_until.is_some_and(|t| t > now)
+        });
+        let entry = failures.entry(client).or_insert(Failures {
+            count: 0,
+            since: now,
+            locked_until: None,
+        });
+        if now.duration_since(entry.since) >= FAILURE_WINDOW {
+            *entry = Failures {
+                count: 0,
+                since: now,
+                locked_until: None,
+            };
+        }
+        entry.count += 1;
+        if entry.count >= MAX_FAILURES {
+            entry.locked_until = Some(now + LOCKOUT);
+            log::warn!("locked out {} after {} invalid tokens", client, entry.count);
+        }
+    }
+
+    pub fn tokens(&self) -> Vec<ApiToken> {
+        let mut tokens: Vec<ApiToken> = self.tokens.lock().unwrap().values().cloned().collect();
+        tokens.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+        tokens
+    }
+
+    // Create a token, returning it along with its secret, only known here
+    pub async fn create_token(&self, name: &str, role: Role, created_by: &str) -> Result<(ApiToken, String), sqlx::Error> {
+        let (token, secret) = new_token(name, role, created_by);
+        self.db.save_token(&token).await?;
+        self.tokens.lock().unwrap().insert(token.hash.clone(), token.clone());
+        Ok((token, secret))
+    }
+
+    // Revoke a token. The last admin token can not be revoked, nor the one of
+    // ADMIN_TOKEN.
+    pub async fn delete_token(&self, id: &str) -> Result<TokenDeletion, sqlx::Error> {
+        let token = {
+            let tokens = self.tokens.lock().unwrap();
+            let Some(token) = tokens.values().find(|t| t.id == id).cloned() else {
+                return Ok(TokenDeletion::NotFound);
+            };
+            let admins = tokens.values().filter(|t| t.role == Role::Admin).count();
+            if token.id == ENV_TOKEN_ID || (token.role == Role::Admin && admins <= 1) {
+                return Ok(TokenDeletion::Refused);
+            }
+            token
+        };
+        self.db.delete_token(id).await?;
+        self.tokens.lock().unwrap().remove(&token.hash);
+        Ok(TokenDeletion::Deleted)
+    }
+
+    // Token of an Authorization header, without counting a failure
+    fn lookup(&self, authorization: Option<&str>) -> Option<ApiToken> {
+        let secret = bearer(authorization)?;
+        self.tokens.lock().unwrap().get(&hash_token(secret)).cloned()
+    }
+
+    pub async fn audit_entries(&self, limit: u32) -> Result<Vec<AuditEntry>, sqlx::Error> {
+        self.db.load_audit(limit).await
+    }
+}
+
+pub enum TokenDeletion {
+    Deleted,
+    NotFound,
+    Refused,
+}
+
+fn new_token(name: &str, role: Role, created_by: &str) -> (ApiToken, String) {
+    let secret = generate_token();
+    let token = ApiToken {
+        id: Uuid::new_v4().to_string(),
+        name: name.to_string(),
+        role,
+        created_at: current_timestamp(),
+        created_by: created_by.to_string(),
+        hash: hash_token(&secret),
+    };
+    (token, secret)
+}
+
+// The token given as the subprotocol following `bearer`, by the WebSocket
+// clients as browsers can't set their Authorization header
+fn protocol_token(protocols: Option<&str>) -> Option<String> {
+    let mut protocols = protocols?.split(',').map(str::trim);
+    protocols.find(|p| *p == "bearer")?;
+    protocols.next().filter(|t| !t.is_empty()).map(|t| format!("Bearer {}", t))
+}
+
+// Reject the request unless its bearer token grants the permission
+pub fn authorize(auth: Arc<Auth>, permission: Permission) -> impl Filter<Extract = (Principal,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::header::optional::<String>("sec-websocket-protocol"))
+        .and(warp::addr::remote())
+        .and_then(move |authorization: Option<String>, protocols: Option<String>, remote: Option<SocketAddr>| {
+            let auth = auth.clone();
+            async move {
+                let authorization = authorization.or_else(|| protocol_token(protocols.as_deref()));
+                let token = auth
+                    .authenticate(authorization.as_deref(), remote.map(|addr| addr.ip()), permission)
+                    .map_err(warp::reject::custom)?;
+                Ok::<_, Rejection>(Principal {
+                    token_id: token.id,
+                    name: token.name,
+                    role: token.role,
+                })
+            }
+        })
+}
+
+// Same as authorize, for the routes not needing the caller
+pub fn require(auth: Arc<Auth>, permission: Permission) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    authorize(auth, permission).map(|_| ()).untuple_one()
+}
+
+// Record each mutation in the audit log with its caller and outcome, including
+// the rejected ones
+pub fn audit_log(auth: Arc<Auth>) -> warp::log::Log<impl Fn(warp::log::Info<'_>) + Clone> {
+    warp::log::custom(move |info| {
+        if matches!(*info.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+            return;
+        }
+        let authorization = info.request_headers().get("authorization").and_then(|v| v.to_str().ok());
+        let token = auth.lookup(authorization);
+        let entry = AuditEntry {
+            timestamp: current_timestamp(),
+            actor: token.as_ref().map(|t| t.name.clone()).unwrap_or_else(|| "anonymous".to_string()),
+            role: token.map(|t| t.role.as_str().to_string()).unwrap_or_default(),
+            method: info.method().to_string(),
+            path: info.path().to_string(),
+            status: info.status().as_u16(),
+            remote: info.remote_addr().map(|addr| addr.ip().to_string()).unwrap_or_default(),
+        };
+        log::info!(
+            "audit: {} ({}) {} {} -> {}",
+            entry.actor,
+            entry.role,
+            entry.method,
+            entry.path,
+            entry.status
+        );
+        let db = auth.db.clone();
+        tokio::spawn(async move {
+            if let Err(e) = db.save_audit(&entry).await {
+                log::error!("failed to save audit entry: {}", e);
+            }
+        });
+    })
+}
+
+// Turn the authentication failures into replies, leaving the other rejections
+// to warp
+pub async fn handle_rejection(err: Rejection) -> Result<warp::reply::Response, Rejection> {
+    let Some(error) = err.find::<AuthError>() else {
+        return Err(err);
+    };
+    let (status, message) = match error {
+        AuthError::Missing => (StatusCode::UNAUTHORIZED, "Missing bearer token"),
+        AuthError::Invalid => (StatusCode::UNAUTHORIZED, "Invalid token"),
+        AuthError::Forbidden => (StatusCode::FORBIDDEN, "Role of the token does not allow this"),
+        AuthError::LockedOut(_) => (StatusCode::TOO_MANY_REQUESTS, "Too many invalid tokens, try again later"),
+    };
+    let reply = warp::reply::with_status(warp::reply::json(&serde_json::json!({"error": message})), status);
+    let response = match error {
+        AuthError::Missing | AuthError::Invalid => {
+            warp::reply::with_header(reply, "www-authenticate", "Bearer").into_response()
+        }
+        AuthError::LockedOut(left) => {
+            warp::reply::with_header(reply, "retry-after", left.as_secs().max(1).to_string()).into_response()
+        }
+        AuthError::Forbidden => reply.into_response(),
+    };
+    Ok(response)
+}
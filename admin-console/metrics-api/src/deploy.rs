@@ -0,0 +1,606 @@
+// Deployment of the policies to the running g3icap and g3proxy nodes
+//
+// When the policies change, the current ones are written as arcus.v1 policy
+// documents and compiled by the arcus-policy command into the g3proxy and
+// g3icap config files, which validates them. The documents and the files are
+// then installed, and each node is told to reload them:
+//   g3icap  - the UPGRADE command of its handover socket starts a new process
+//             loading the new config, which takes over the listeners; if the
+//             config fails to load, the old process keeps serving
+//   g3proxy - g3proxy-ctl reloads each escaper, user group, auditor and server
+//             of the generated config
+// A deployment reports the state of each step and of each node. Deployments
+// run one at a time in the background, a deployment still pending when a
+// newer one is requested being superseded by it, as it would deploy the same
+// policies. The last MAX_DEPLOYMENTS of them are kept in memory.
+//
+// The pipeline is configured by the JSON file ARCUS_DEPLOY_CONFIG points to;
+// without it, the policies are only stored.
+
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+use tokio::process::Command;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::validation::{
+    ACTIONS, CERTIFICATE_GENERATIONS, EXPORT_TYPES, INSPECTION_MODES, LOG_LEVELS, POLICY_STATUSES, PRIORITIES, RULE_TYPES,
+};
+use crate::{current_timestamp, PolicyStore, SecurityPolicy};
+
+pub const MAX_DEPLOYMENTS: usize = 50;
+// Time arcus-policy and each g3proxy-ctl call have to complete
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+// Time a command on the g3icap handover socket has to be answered
+const SOCKET_TIMEOUT: Duration = Duration::from_secs(5);
+// Time the new g3icap process has to load its config and take over
+const G3ICAP_START_TIMEOUT: Duration = Duration::from_secs(60);
+const G3ICAP_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// The policies are stored as the console sends them, with lowercase values
+// for the enums of arcus-policy, which wants its variant names
+const ENUM_FIELDS: &[(&str, &[&str])] = &[
+    ("metadata.status", POLICY_STATUSES),
+    ("spec.priority", PRIORITIES),
+    ("spec.url_filtering.custom_rules[].action", ACTIONS),
+    ("spec.url_filtering.custom_rules[].rule_type", RULE_TYPES),
+    ("spec.content_security.malware_scanning.action", ACTIONS),
+    ("spec.content_security.data_loss_prevention.sensitive_data_patterns[].action", ACTIONS),
+    ("spec.https_inspection.mode", INSPECTION_MODES),
+    ("spec.https_inspection.certificate_generation", CERTIFICATE_GENERATIONS),
+    ("spec.audit.log_level", LOG_LEVELS),
+    ("spec.audit.export_targets[].target_type", EXPORT_TYPES),
+];
+#[derive(Clone, Debug, Deserialize)]
+pub struct DeployConfig {
+    // Where the generated g3proxy.yaml and g3icap.yaml are installed
+    pub config_dir: PathBuf,
+    // Where the policy documents are installed, the policy path of g3icap.
+    // The deployments own it: the .yaml files of the deleted policies are removed.
+    pub policy_dir: PathBuf,
+    #[serde(default = "default_compiler")]
+    pub compiler: String,
+    // Address of g3icap as seen by g3proxy, the arcus-policy default if not set
+    #[serde(default)]
+    pub icap_address: Option<String>,
+    #[serde(default = "default_g3proxy_ctl")]
+    pub g3proxy_ctl: String,
+    #[serde(default)]
+    pub nodes: Vec<NodeConfig>,
+}
+
+fn default_compiler() -> String {
+    "arcus-policy".to_string()
+}
+
+fn default_g3proxy_ctl() -> String {
+    "g3proxy-ctl".to_string()
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum NodeConfig {
+    G3icap {
+        name: String,
+        // The handover socket, g3icap-upgrade.sock in its control directory
+        control_socket: PathBuf,
+    },
+    G3proxy {
+        name: String,
+        #[serde(default)]
+        daemon_group: Option<String>,
+        #[serde(default)]
+        control_dir: Option<PathBuf>,
+    },
+}
+
+impl NodeConfig {
+    fn name(&self) -> &str {
+        match self {
+            NodeConfig::G3icap { name, .. } | NodeConfig::G3proxy { name, .. } => name,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            NodeConfig::G3icap { .. } => "g3icap",
+            NodeConfig::G3proxy { .. } => "g3proxy",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeploymentState {
+    Pending,
+    Compiling,
+    Installing,
+    Reloading,
+    Succeeded,
+    PartiallyFailed,
+    Failed,
+    Superseded,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeState {
+    Pending,
+    Reloading,
+    Reloaded,
+    Failed,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct NodeRollout {
+    pub name: String,
+    pub kind: &'static str,
+    pub state: NodeState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Deployment {
+    pub id: String,
+    pub requested_by: String,
+    pub requested_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<u64>,
+    pub state: DeploymentState,
+    // Number of compiled policies
+    pub policies: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    // What arcus-policy could not express in the configs
+    pub notes: Vec<String>,
+    pub nodes: Vec<NodeRollout>,
+}
+
+// What arcus-policy render prints
+#[derive(Debug, Deserialize)]
+struct RenderSummary {
+    policies: usize,
+    notes: Vec<String>,
+    g3proxy: G3proxyEntities,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct G3proxyEntities {
+    escaper: Vec<String>,
+    user_group: Vec<String>,
+    auditor: Vec<String>,
+    server: Vec<String>,
+}
+
+pub struct Deployer {
+    config: DeployConfig,
+    // Oldest first
+    deployments: Mutex<VecDeque<Deployment>>,
+    wake: Notify,
+}
+
+impl Deployer {
+    // Load the config ARCUS_DEPLOY_CONFIG points to, None if it is not set
+    pub fn load() -> Result<Option<Self>, String> {
+        let Ok(path) = std::env::var("ARCUS_DEPLOY_CONFIG") else {
+            return Ok(None);
+        };
+        let content = std::fs::read_to_string(&path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+        let config: DeployConfig = serde_json::from_str(&content).map_err(|e| format!("invalid deploy config {}: {}", path, e))?;
+        Ok(Some(Deployer {
+            config,
+            deployments: Mutex::new(VecDeque::new()),
+            wake: Notify::new(),
+        }))
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.config.nodes.len()
+    }
+
+    // Run the requested deployments, each with the policies current when it starts
+    pub fn spawn(self: &Arc<Self>, policies: PolicyStore) {
+        let deployer = self.clone();
+        tokio::spawn(async move {
+            loop {
+                deployer.wake.notified().await;
+                while let Some(id) = deployer.next_pending() {
+                    let mut current: Vec<(String, SecurityPolicy)> = policies
+                        .lock()
+                        .await
+                        .iter()
+                        .filter(|(_, record)| !record.deleted)
+                        .map(|(id, record)| (id.clone(), record.policy.clone()))
+                        .collect();
+                    current.sort_by(|a, b| a.0.cmp(&b.0));
+                    deployer.run(&id, current).await;
+                }
+            }
+        });
+    }
+
+    // Request a deployment of the current policies, returns its id
+    pub fn request(&self, requested_by: &str) -> String {
+        let id = Uuid::new_v4().to_string();
+        let now = current_timestamp();
+        let mut deployments = self.deployments.lock().unwrap();
+        for deployment in deployments.iter_mut().filter(|d| d.state == DeploymentState::Pending) {
+            deployment.state = DeploymentState::Superseded;
+            deployment.finished_at = Some(now);
+        }
+        deployments.push_back(Deployment {
+            id: id.clone(),
+            requested_by: requested_by.to_string(),
+            requested_at: now,
+            finished_at: None,
+            state: DeploymentState::Pending,
+            policies: 0,
+            error: None,
+            notes: Vec::new(),
+            nodes: self
+                .config
+                .nodes
+                .iter()
+                .map(|node| NodeRollout {
+                    name: node.name().to_string(),
+                    kind: node.kind(),
+                    state: NodeState::Pending,
+                    detail: None,
+                })
+                .collect(),
+        });
+        while deployments.len() > MAX_DEPLOYMENTS {
+            deployments.pop_front();
+        }
+        drop(deployments);
+        self.wake.notify_one();
+        id
+    }
+
+    // Kept deployments, newest first
+    pub fn deployments(&self) -> Vec<Deployment> {
+        self.deployments.lock().unwrap().iter().rev().cloned().collect()
+    }
+
+    pub fn deployment(&self, id: &str) -> Option<Deployment> {
+        self.deployments.lock().unwrap().iter().find(|d| d.id == id).cloned()
+    }
+
+    fn next_pending(&self) -> Option<String> {
+        self.deployments
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|d| d.state == DeploymentState::Pending)
+            .map(|d| d.id.clone())
+    }
+
+    fn update(&self, id: &str, f: impl FnOnce(&mut Deployment)) {
+        if let Some(deployment) = self.deployments.lock().unwrap().iter_mut().find(|d| d.id == id) {
+            f(deployment);
+        }
+    }
+
+    fn fail(&self, id: &str, error: String) {
+        log::error!("deployment {} failed: {}", id, error);
+        self.update(id, |d| {
+            d.state = DeploymentState::Failed;
+            d.error = Some(error);
+            d.finished_at = Some(current_timestamp());
+        });
+    }
+
+    async fn run(&self, id: &str, policies: Vec<(String, SecurityPolicy)>) {
+        self.update(id, |d| d.state = DeploymentState::Compiling);
+        let staging = self.config.config_dir.join(format!(".deploy-{}", id));
+        let summary = match self.compile(&staging, &policies).await {
+            Ok(summary) => summary,
+            Err(e) => {
+                let _ = tokio::fs::remove_dir_all(&staging).await;
+                return self.fail(id, e);
+            }
+        };
+        self.update(id, |d| {
+            d.state = DeploymentState::Installing;
+            d.policies = summary.policies;
+            d.notes = summary.notes.clone();
+        });
+
+        let installed = self.install(&staging).await;
+        let _ = tokio::fs::remove_dir_all(&staging).await;
+        if let Err(e) = installed {
+            return self.fail(id, e);
+        }
+
+        self.update(id, |d| d.state = DeploymentState::Reloading);
+        let mut failed = 0;
+        for (i, node) in self.config.nodes.iter().enumerate() {
+            self.update(id, |d| d.nodes[i].state = NodeState::Reloading);
+            let result = match node {
+                NodeConfig::G3icap { control_socket, .. } => reload_g3icap(control_socket).await,
+                NodeConfig::G3proxy { daemon_group, control_dir, .. } => {
+                    self.reload_g3proxy(daemon_group.as_deref(), control_dir.as_deref(), &summary.g3proxy)
+                        .await
+                }
+            };
+            if let Err(e) = &result {
+                log::warn!("deployment {}: node {} failed to reload: {}", id, node.name(), e);
+                failed += 1;
+            }
+            self.update(id, |d| {
+                let rollout = &mut d.nodes[i];
+                match result {
+                    Ok(detail) => {
+                        rollout.state = NodeState::Reloaded;
+                        rollout.detail = Some(detail);
+                    }
+                    Err(e) => {
+                        rollout.state = NodeState::Failed;
+                        rollout.detail = Some(e);
+                    }
+                }
+            });
+        }
+
+        let state = match failed {
+            0 => DeploymentState::Succeeded,
+            n if n == self.config.nodes.len() => DeploymentState::Failed,
+            _ => DeploymentState::PartiallyFailed,
+        };
+        log::info!("deployment {} of {} policies: {:?}", id, summary.policies, state);
+        self.update(id, |d| {
+            d.state = state;
+            d.finished_at = Some(current_timestamp());
+        });
+    }
+
+    // Write the policy documents in the staging directory and compile them
+    // there, next to them
+    async fn compile(&self, staging: &Path, policies: &[(String, SecurityPolicy)]) -> Result<RenderSummary, String> {
+        let policy_dir = staging.join("policies");
+        let _ = tokio::fs::remove_dir_all(staging).await;
+        tokio::fs::create_dir_all(&policy_dir)
+            .await
+            .map_err(|e| format!("failed to create {}: {}", policy_dir.display(), e))?;
+        for (id, policy) in policies {
+            let document = export(policy).map_err(|e| format!("policy {}: {}", id, e))?;
+            let path = policy_dir.join(file_name(id));
+            let content = serde_json::to_vec_pretty(&document).map_err(|e| e.to_string())?;
+            tokio::fs::write(&path, content)
+                .await
+                .map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+        }
+
+        let mut command = Command::new(&self.config.compiler);
+        command
+            .arg("render")
+            .arg("--policies")
+            .arg(&policy_dir)
+            .arg("--out")
+            .arg(staging)
+            .arg("--policy-path")
+            .arg(&self.config.policy_dir);
+        if let Some(address) = &self.config.icap_address {
+            command.arg("--icap-address").arg(address);
+        }
+        let output = run_command(command)
+            .await
+            .map_err(|e| format!("{}: {}", self.config.compiler, e))?;
+        serde_json::from_str(output.trim())
+            .map_err(|e| format!("unexpected output of {}: {}", self.config.compiler, e))
+    }
+
+    // Install the policy documents and the generated files of the staging directory
+    async fn install(&self, staging: &Path) -> Result<(), String> {
+        let policy_dir = &self.config.policy_dir;
+        tokio::fs::create_dir_all(policy_dir)
+            .await
+            .map_err(|e| format!("failed to create {}: {}", policy_dir.display(), e))?;
+        let mut installed = HashSet::new();
+        for entry in list_dir(&staging.join("policies")).await? {
+            let name = entry.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+            // copied then renamed, the directories may be on different file systems
+            let tmp = policy_dir.join(format!(".{}.tmp", name.to_string_lossy()));
+            let target = policy_dir.join(&name);
+            tokio::fs::copy(&entry, &tmp)
+                .await
+                .map_err(|e| format!("failed to write {}: {}", tmp.display(), e))?;
+            tokio::fs::rename(&tmp, &target)
+                .await
+                .map_err(|e| format!("failed to write {}: {}", target.display(), e))?;
+            installed.insert(name);
+        }
+        for entry in list_dir(policy_dir).await? {
+            let name = entry.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+            let is_policy = entry.extension().is_some_and(|ext| ext == "yaml");
+            if is_policy && !installed.contains(&name) {
+                tokio::fs::remove_file(&entry)
+                    .await
+                    .map_err(|e| format!("failed to remove {}: {}", entry.display(), e))?;
+            }
+        }
+
+        for name in ["g3proxy.yaml", "g3icap.yaml"] {
+            let target = self.config.config_dir.join(name);
+            tokio::fs::rename(staging.join(name), &target)
+                .await
+                .map_err(|e| format!("failed to write {}: {}", target.display(), e))?;
+        }
+        Ok(())
+    }
+
+    async fn reload_g3proxy(
+        &self,
+        daemon_group: Option<&str>,
+        control_dir: Option<&Path>,
+        entities: &G3proxyEntities,
+    ) -> Result<String, String> {
+        // the escapers are listed before the ones they route to, the leaves
+        // are reloaded first
+        let escapers: Vec<&String> = entities.escaper.iter().rev().collect();
+        let commands = [
+            ("reload-escaper", escapers),
+            ("reload-user-group", entities.user_group.iter().collect()),
+            ("reload-auditor", entities.auditor.iter().collect()),
+            ("reload-server", entities.server.iter().collect()),
+        ];
+        let mut count = 0;
+        for (subcommand, names) in commands {
+            for name in names {
+                let mut command = Command::new(&self.config.g3proxy_ctl);
+                if let Some(group) = daemon_group {
+                    command.arg("--daemon-group").arg(group);
+                }
+                if let Some(dir) = control_dir {
+                    command.arg("--control-dir").arg(dir);
+                }
+                command.arg(subcommand).arg(name);
+                run_command(command)
+                    .await
+                    .map_err(|e| format!("{} {}: {}", subcommand, name, e))?;
+                count += 1;
+            }
+        }
+        Ok(format!("reloaded {} escapers, user groups, auditors and servers", count))
+    }
+}
+
+// Ask g3icap for an upgrade, a new process loading the installed config, and
+// wait until that process answers on the handover socket
+async fn reload_g3icap(socket: &Path) -> Result<String, String> {
+    let reply = control_request(socket, "UPGRADE").await?;
+    let pid = match reply.strip_prefix("OK ") {
+        Some(pid) => pid.trim().parse::<u64>().map_err(|_| format!("unexpected reply {}", reply))?,
+        None => return Err(reply.strip_prefix("ERR ").unwrap_or(&reply).to_string()),
+    };
+
+    let deadline = Instant::now() + G3ICAP_START_TIMEOUT;
+    while Instant::now() < deadline {
+        tokio::time::sleep(G3ICAP_POLL_INTERVAL).await;
+        // the old process answers until the new one has taken over
+        let Ok(status) = control_request(socket, "STATUS").await else {
+            continue;
+        };
+        let status: Value = serde_json::from_str(&status).unwrap_or_default();
+        if status.get("pid").and_then(Value::as_u64) == Some(pid) {
+            return Ok(format!("upgraded to process {}", pid));
+        }
+    }
+    Err(format!(
+        "process {} did not take over within {}s, the previous one keeps serving",
+        pid,
+        G3ICAP_START_TIMEOUT.as_secs()
+    ))
+}
+
+// Send a command on the g3icap handover socket, returns the reply line
+async fn control_request(socket: &Path, command: &str) -> Result<String, String> {
+    let exchange = async {
+        let mut stream = UnixStream::connect(socket).await?;
+        stream.write_all(format!("{}\n", command).as_bytes()).await?;
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).await?;
+        Ok::<_, std::io::Error>(line)
+    };
+    match tokio::time::timeout(SOCKET_TIMEOUT, exchange).await {
+        Ok(Ok(line)) if !line.is_empty() => Ok(line.trim_end().to_string()),
+        Ok(Ok(_)) => Err(format!("{}: connection closed", socket.display())),
+        Ok(Err(e)) => Err(format!("{}: {}", socket.display(), e)),
+        Err(_) => Err(format!("{}: no reply within {}s", socket.display(), SOCKET_TIMEOUT.as_secs())),
+    }
+}
+
+// Run a command, returns its output if it succeeds
+async fn run_command(mut command: Command) -> Result<String, String> {
+    command.stdin(Stdio::null()).kill_on_drop(true);
+    let output = match tokio::time::timeout(COMMAND_TIMEOUT, command.output()).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => return Err(format!("failed to run: {}", e)),
+        Err(_) => return Err(format!("no result within {}s", COMMAND_TIMEOUT.as_secs())),
+    };
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("{}: {}", output.status, stderr.trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+async fn list_dir(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut entries = tokio::fs::read_dir(dir)
+        .await
+        .map_err(|e| format!("failed to read {}: {}", dir.display(), e))?;
+    let mut paths = Vec::new();
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .map_err(|e| format!("failed to read {}: {}", dir.display(), e))?
+    {
+        if entry.metadata().await.is_ok_and(|m| m.is_file()) {
+            paths.push(entry.path());
+        }
+    }
+    Ok(paths)
+}
+
+// The policy as an arcus.v1 document, refused with the path of the first
+// field arcus-policy would not accept
+fn export(policy: &SecurityPolicy) -> Result<Value, String> {
+    let mut document = serde_json::to_value(policy).map_err(|e| e.to_string())?;
+    for (path, variants) in ENUM_FIELDS {
+        let path: Vec<&str> = path.split('.').collect();
+        normalize(&mut document, &path, variants, String::new())?;
+    }
+    Ok(document)
+}
+
+// Replace the enum value at the path by its variant name, a `[]` suffix
+// going through each item of a list
+fn normalize(value: &mut Value, path: &[&str], variants: &[&str], at: String) -> Result<(), String> {
+    let Some((first, rest)) = path.split_first() else {
+        let Value::String(s) = value else {
+            return Err(format!("{} should be a string", at));
+        };
+        return match variants.iter().find(|v| v.eq_ignore_ascii_case(s)) {
+            Some(variant) => {
+                *s = variant.to_string();
+                Ok(())
+            }
+            None => {
+                let expected: Vec<String> = variants.iter().map(|v| v.to_lowercase()).collect();
+                Err(format!("{}: unknown value {}, expected one of {}", at, s, expected.join(", ")))
+            }
+        };
+    };
+    let (key, each) = match first.strip_suffix("[]") {
+        Some(key) => (key, true),
+        None => (*first, false),
+    };
+    let at = if at.is_empty() { key.to_string() } else { format!("{}.{}", at, key) };
+    match value.get_mut(key) {
+        None | Some(Value::Null) => Ok(()),
+        Some(Value::Array(items)) if each => items
+            .iter_mut()
+            .enumerate()
+            .try_for_each(|(i, item)| normalize(item, rest, variants, format!("{}[{}]", at, i))),
+        Some(child) => normalize(child, rest, variants, at),
+    }
+}
+
+// Name of the document of a policy, from its id which may be any string
+fn file_name(id: &str) -> String {
+    let name: String = id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    format!("{}.yaml", name)
+}
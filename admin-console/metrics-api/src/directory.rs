@@ -0,0 +1,807 @@
+// Sync of the users with a directory
+//
+// The users and their group memberships are imported from an LDAP directory
+// or a SCIM 2.0 endpoint, configured by the JSON file ARCUS_DIRECTORY_CONFIG
+// points to, at startup, every interval_secs and on POST /directory/sync. An
+// imported user is linked to its entry by its id in the directory: its name,
+// email, groups and status are those of the directory and can't be changed
+// through the API, while its role, bandwidth limit and quota are local.
+//
+// The users removed from the directory are kept, inactive and marked
+// `removed`, and are restored if they come back. A directory user deleted
+// through the API is recorded as deleted and is not imported again until it
+// is restored with DELETE /directory/deleted/{external_id}. The entries which
+// can't be imported are reported as conflicts: those without an id or an
+// email, the duplicates, and those with the email of a local user, unless
+// adopt_local_users links them to it. A sync getting no entries while users
+// are linked fails rather than removing them all.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use ldap3::adapters::PagedResults;
+use ldap3::{LdapConnAsync, LdapConnSettings, LdapError, Scope, SearchEntry};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::Notify;
+use uuid::Uuid;
+use warp::hyper::client::HttpConnector;
+use warp::hyper::{Body, Client, Request};
+
+use crate::storage::Database;
+use crate::{current_timestamp, User, UserStore};
+
+// Time a SCIM request or the connection to the LDAP server has to complete
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+// Time the whole directory has to be read
+const FETCH_TIMEOUT: Duration = Duration::from_secs(300);
+const LDAP_PAGE_SIZE: i32 = 500;
+// Most entries read from a directory
+const MAX_ENTRIES: usize = 100_000;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct DirectoryConfig {
+    pub source: SourceConfig,
+    #[serde(default = "default_interval")]
+    pub interval_secs: u64,
+    // Role of the imported users
+    #[serde(default = "default_role")]
+    pub default_role: String,
+    // Link the entries with the email of a local user to that user rather
+    // than reporting a conflict
+    #[serde(default)]
+    pub adopt_local_users: bool,
+}
+
+fn default_interval() -> u64 {
+    900
+}
+
+fn default_role() -> String {
+    "user".to_string()
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum SourceConfig {
+    Ldap {
+        // ldap://, ldaps:// or ldapi://
+        url: String,
+        #[serde(default)]
+        starttls: bool,
+        #[serde(default)]
+        bind_dn: Option<String>,
+        #[serde(default)]
+        bind_password: Option<String>,
+        base_dn: String,
+        #[serde(default = "default_user_filter")]
+        user_filter: String,
+        #[serde(default)]
+        attributes: LdapAttributes,
+    },
+    Scim {
+        // Base URL of the /Users and /Groups resources
+        url: String,
+        #[serde(default)]
+        token: Option<String>,
+        #[serde(default = "default_page_size")]
+        page_size: u32,
+    },
+}
+
+fn default_user_filter() -> String {
+    "(objectClass=inetOrgPerson)".to_string()
+}
+
+fn default_page_size() -> u32 {
+    100
+}
+
+impl SourceConfig {
+    fn kind(&self) -> &'static str {
+        match self {
+            SourceConfig::Ldap { .. } => "ldap",
+            SourceConfig::Scim { .. } => "scim",
+        }
+    }
+}
+
+// Attributes of the LDAP user entries. The groups are the values of the
+// groups attribute, the value of the first RDN of those which are DNs.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct LdapAttributes {
+    pub id: String,
+    pub name: String,
+    pub email: String,
+    pub groups: String,
+}
+
+impl Default for LdapAttributes {
+    fn default() -> Self {
+        LdapAttributes {
+            id: "entryUUID".to_string(),
+            name: "cn".to_string(),
+            email: "mail".to_string(),
+            groups: "memberOf".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkState {
+    Active,
+    Removed,
+}
+
+// The directory entry of an imported user
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirectoryLink {
+    pub source: String,
+    pub external_id: String,
+    pub state: LinkState,
+    pub linked_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub removed_at: Option<u64>,
+}
+
+// A directory user deleted through the API
+#[derive(Clone, Debug, Serialize)]
+pub struct DeletedUser {
+    pub source: String,
+    pub external_id: String,
+    pub name: String,
+    pub email: String,
+    pub deleted_at: u64,
+    pub deleted_by: String,
+}
+
+impl DeletedUser {
+    pub fn new(link: &DirectoryLink, user: &User, deleted_by: &str) -> Self {
+        DeletedUser {
+            source: link.source.clone(),
+            external_id: link.external_id.clone(),
+            name: user.name.clone(),
+            email: user.email.clone(),
+            deleted_at: current_timestamp(),
+            deleted_by: deleted_by.to_string(),
+        }
+    }
+}
+
+// The fields set by the directory which differ between two versions of a user
+pub fn managed_changes(current: &User, new: &User) -> Vec<&'static str> {
+    let groups = |user: &User| -> HashSet<String> { user.groups.iter().cloned().collect() };
+    let mut changed = Vec::new();
+    if current.name != new.name {
+        changed.push("name");
+    }
+    if !current.email.eq_ignore_ascii_case(&new.email) {
+        changed.push("email");
+    }
+    if groups(current) != groups(new) {
+        changed.push("groups");
+    }
+    if current.status != new.status {
+        changed.push("status");
+    }
+    changed
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncResult {
+    Succeeded,
+    Failed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictReason {
+    // No id or no email
+    Invalid,
+    // The id or email of an earlier entry
+    Duplicate,
+    // The email of a local user
+    EmailTaken,
+}
+
+// A directory entry which was not imported
+#[derive(Clone, Debug, Serialize)]
+pub struct Conflict {
+    pub external_id: String,
+    pub name: String,
+    pub email: String,
+    pub reason: ConflictReason,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SyncCounts {
+    pub fetched: usize,
+    pub created: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+    pub restored: usize,
+    // Entries of users deleted through the API
+    pub skipped_deleted: usize,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SyncRun {
+    pub started_at: u64,
+    pub finished_at: u64,
+    pub result: SyncResult,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(flatten)]
+    pub counts: SyncCounts,
+    pub conflicts: Vec<Conflict>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SyncStatus {
+    pub source: &'static str,
+    pub interval_secs: u64,
+    pub running: bool,
+    pub next_run_at: Option<u64>,
+    pub last_success_at: Option<u64>,
+    pub last_run: Option<SyncRun>,
+}
+
+#[derive(Default)]
+struct SyncState {
+    running: bool,
+    next_run_at: Option<u64>,
+    last_success_at: Option<u64>,
+    last_run: Option<SyncRun>,
+}
+
+// An entry of the directory
+struct DirectoryUser {
+    external_id: String,
+    name: String,
+    email: String,
+    groups: Vec<String>,
+    active: bool,
+}
+
+impl DirectoryUser {
+    fn conflict(&self, reason: ConflictReason, user_id: Option<String>) -> Conflict {
+        Conflict {
+            external_id: self.external_id.clone(),
+            name: self.name.clone(),
+            email: self.email.clone(),
+            reason,
+            user_id,
+        }
+    }
+
+    fn status(&self) -> &'static str {
+        if self.active {
+            "active"
+        } else {
+            "inactive"
+        }
+    }
+}
+
+pub struct DirectorySync {
+    config: DirectoryConfig,
+    state: Mutex<SyncState>,
+    wake: Notify,
+}
+
+impl DirectorySync {
+    // Load the config ARCUS_DIRECTORY_CONFIG points to, None if it is not set
+    pub fn load() -> Result<Option<Self>, String> {
+        let Ok(path) = std::env::var("ARCUS_DIRECTORY_CONFIG") else {
+            return Ok(None);
+        };
+        let content = std::fs::read_to_string(&path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+        let config: DirectoryConfig =
+            serde_json::from_str(&content).map_err(|e| format!("invalid directory config {}: {}", path, e))?;
+        Ok(Some(DirectorySync {
+            config,
+            state: Mutex::new(SyncState::default()),
+            wake: Notify::new(),
+        }))
+    }
+
+    pub fn source(&self) -> &'static str {
+        self.config.source.kind()
+    }
+
+    // Sync now, then every interval and when requested
+    pub fn spawn(self: &Arc<Self>, users: UserStore, db: Database) {
+        let sync = self.clone();
+        let interval = Duration::from_secs(self.config.interval_secs.max(1));
+        tokio::spawn(async move {
+            loop {
+                sync.state.lock().unwrap().running = true;
+                let run = sync.run(&users, &db).await;
+                match &run.error {
+                    Some(e) => log::warn!("directory sync failed: {}", e),
+                    None => log::info!(
+                        "directory sync: {} created, {} updated, {} removed, {} restored, {} conflicts",
+                        run.counts.created,
+                        run.counts.updated,
+                        run.counts.removed,
+                        run.counts.restored,
+                        run.conflicts.len()
+                    ),
+                }
+                {
+                    let mut state = sync.state.lock().unwrap();
+                    state.running = false;
+                    if run.result == SyncResult::Succeeded {
+                        state.last_success_at = Some(run.finished_at);
+                    }
+                    state.next_run_at = Some(run.finished_at + interval.as_secs());
+                    state.last_run = Some(run);
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(interval) => {}
+                    _ = sync.wake.notified() => {}
+                }
+            }
+        });
+    }
+
+    // Sync as soon as the current run, if any, is done
+    pub fn request(&self) {
+        self.wake.notify_one();
+    }
+
+    pub fn status(&self) -> SyncStatus {
+        let state = self.state.lock().unwrap();
+        SyncStatus {
+            source: self.source(),
+            interval_secs: self.config.interval_secs,
+            running: state.running,
+            next_run_at: state.next_run_at,
+            last_success_at: state.last_success_at,
+            last_run: state.last_run.clone(),
+        }
+    }
+
+    async fn run(&self, users: &UserStore, db: &Database) -> SyncRun {
+        let started_at = current_timestamp();
+        let result = async {
+            let entries = tokio::time::timeout(FETCH_TIMEOUT, fetch(&self.config.source))
+                .await
+                .map_err(|_| format!("reading the directory timed out after {}s", FETCH_TIMEOUT.as_secs()))??;
+            let deleted = db
+                .load_deleted_users()
+                .await
+                .map_err(|e| format!("failed to load the deleted users: {}", e))?;
+            self.apply(entries, deleted, users, db).await
+        }
+        .await;
+        let (result, error, counts, conflicts) = match result {
+            Ok((counts, conflicts)) => (SyncResult::Succeeded, None, counts, conflicts),
+            Err(e) => (SyncResult::Failed, Some(e), SyncCounts::default(), Vec::new()),
+        };
+        SyncRun {
+            started_at,
+            finished_at: current_timestamp(),
+            result,
+            error,
+            counts,
+            conflicts,
+        }
+    }
+
+    async fn apply(
+        &self,
+        entries: Vec<DirectoryUser>,
+        deleted: Vec<DeletedUser>,
+        users: &UserStore,
+        db: &Database,
+    ) -> Result<(SyncCounts, Vec<Conflict>), String> {
+        let source = self.source();
+        let now = current_timestamp();
+        let deleted: HashSet<String> = deleted
+            .into_iter()
+            .filter(|d| d.source == source)
+            .map(|d| d.external_id)
+            .collect();
+        let mut counts = SyncCounts {
+            fetched: entries.len(),
+            ..SyncCounts::default()
+        };
+        let mut conflicts = Vec::new();
+
+        let mut store = users.lock().await;
+        let linked: HashMap<String, String> = store
+            .iter()
+            .filter_map(|(id, user)| {
+                let link = user.directory.as_ref().filter(|link| link.source == source)?;
+                Some((link.external_id.clone(), id.clone()))
+            })
+            .collect();
+        if entries.is_empty() && !linked.is_empty() {
+            return Err(format!("the directory has no users while {} are linked to it", linked.len()));
+        }
+        let mut local: HashMap<String, String> = store
+            .iter()
+            .filter(|(_, user)| user.directory.is_none())
+            .map(|(id, user)| (user.email.to_lowercase(), id.clone()))
+            .collect();
+
+        let save_error = |e: sqlx::Error| format!("failed to save a user: {}", e);
+        let mut seen_ids = HashSet::new();
+        let mut seen_emails = HashSet::new();
+        for entry in entries {
+            let email = entry.email.trim().to_lowercase();
+            if entry.external_id.is_empty() || email.is_empty() {
+                conflicts.push(entry.conflict(ConflictReason::Invalid, None));
+                continue;
+            }
+            if !seen_ids.insert(entry.external_id.clone()) || !seen_emails.insert(email.clone()) {
+                conflicts.push(entry.conflict(ConflictReason::Duplicate, None));
+                continue;
+            }
+            if deleted.contains(&entry.external_id) {
+                counts.skipped_deleted += 1;
+                continue;
+            }
+
+            let id = match linked.get(&entry.external_id) {
+                Some(id) => id.clone(),
+                None => match local.get(&email) {
+                    Some(id) if self.config.adopt_local_users => {
+                        let id = id.clone();
+                        local.remove(&email);
+                        id
+                    }
+                    Some(id) => {
+                        conflicts.push(entry.conflict(ConflictReason::EmailTaken, Some(id.clone())));
+                        continue;
+                    }
+                    None => {
+                        let id = Uuid::new_v4().to_string();
+                        let user = User {
+                            id: id.clone(),
+                            name: entry.name.clone(),
+                            email: entry.email.trim().to_string(),
+                            groups: entry.groups.clone(),
+                            status: entry.status().to_string(),
+                            last_login: "Never".to_string(),
+                            created: format_timestamp(now),
+                            role: self.config.default_role.clone(),
+                            bandwidth_limit: None,
+                            daily_quota: None,
+                            directory: Some(DirectoryLink {
+                                source: source.to_string(),
+                                external_id: entry.external_id.clone(),
+                                state: LinkState::Active,
+                                linked_at: now,
+                                removed_at: None,
+                            }),
+                        };
+                        db.save_user(&id, &user).await.map_err(save_error)?;
+                        store.insert(id, user);
+                        counts.created += 1;
+                        continue;
+                    }
+                },
+            };
+
+            let Some(user) = store.get(&id) else {
+                continue;
+            };
+            let mut updated = user.clone();
+            updated.name = entry.name.clone();
+            updated.email = entry.email.trim().to_string();
+            updated.groups = entry.groups.clone();
+            updated.status = entry.status().to_string();
+            let restored = user.directory.as_ref().is_some_and(|link| link.state == LinkState::Removed);
+            updated.directory = Some(DirectoryLink {
+                source: source.to_string(),
+                external_id: entry.external_id.clone(),
+                state: LinkState::Active,
+                linked_at: user.directory.as_ref().map_or(now, |link| link.linked_at),
+                removed_at: None,
+            });
+            if managed_changes(user, &updated).is_empty() && updated.directory == user.directory {
+                counts.unchanged += 1;
+                continue;
+            }
+            db.save_user(&id, &updated).await.map_err(save_error)?;
+            store.insert(id, updated);
+            if restored {
+                counts.restored += 1;
+            } else {
+                counts.updated += 1;
+            }
+        }
+
+        for (external_id, id) in &linked {
+            if seen_ids.contains(external_id) {
+                continue;
+            }
+            let Some(user) = store.get(id) else {
+                continue;
+            };
+            let Some(link) = user.directory.as_ref().filter(|link| link.state == LinkState::Active) else {
+                continue;
+            };
+            let mut removed = user.clone();
+            removed.status = "inactive".to_string();
+            removed.directory = Some(DirectoryLink {
+                state: LinkState::Removed,
+                removed_at: Some(now),
+                ..link.clone()
+            });
+            db.save_user(id, &removed).await.map_err(save_error)?;
+            store.insert(id.clone(), removed);
+            counts.removed += 1;
+        }
+        Ok((counts, conflicts))
+    }
+}
+
+async fn fetch(source: &SourceConfig) -> Result<Vec<DirectoryUser>, String> {
+    match source {
+        SourceConfig::Ldap {
+            url,
+            starttls,
+            bind_dn,
+            bind_password,
+            base_dn,
+            user_filter,
+            attributes,
+        } => {
+            let bind = bind_dn.as_deref().map(|dn| (dn, bind_password.as_deref().unwrap_or_default()));
+            fetch_ldap(url, *starttls, bind, base_dn, user_filter, attributes).await
+        }
+        SourceConfig::Scim { url, token, page_size } => fetch_scim(url, token.as_deref(), *page_size).await,
+    }
+}
+
+async fn fetch_ldap(
+    url: &str,
+    starttls: bool,
+    bind: Option<(&str, &str)>,
+    base_dn: &str,
+    filter: &str,
+    attributes: &LdapAttributes,
+) -> Result<Vec<DirectoryUser>, String> {
+    let ldap_error = |e: LdapError| format!("LDAP {}: {}", url, e);
+    let settings = LdapConnSettings::new()
+        .set_conn_timeout(REQUEST_TIMEOUT)
+        .set_starttls(starttls);
+    let (conn, mut ldap) = LdapConnAsync::with_settings(settings, url).await.map_err(ldap_error)?;
+    ldap3::drive!(conn);
+    if let Some((dn, password)) = bind {
+        ldap.simple_bind(dn, password)
+            .await
+            .and_then(|result| result.success())
+            .map_err(ldap_error)?;
+    }
+
+    let names = vec![
+        attributes.id.as_str(),
+        attributes.name.as_str(),
+        attributes.email.as_str(),
+        attributes.groups.as_str(),
+        // disabled Active Directory accounts
+        "userAccountControl",
+    ];
+    let mut search = ldap
+        .streaming_search_with(PagedResults::new(LDAP_PAGE_SIZE), base_dn, Scope::Subtree, filter, names)
+        .await
+        .map_err(ldap_error)?;
+    let mut users = Vec::new();
+    while let Some(entry) = search.next().await.map_err(ldap_error)? {
+        if users.len() == MAX_ENTRIES {
+            return Err(format!("LDAP {}: more than {} users", url, MAX_ENTRIES));
+        }
+        users.push(ldap_user(&SearchEntry::construct(entry), attributes));
+    }
+    search.finish().await.success().map_err(ldap_error)?;
+    let _ = ldap.unbind().await;
+    Ok(users)
+}
+
+fn ldap_user(entry: &SearchEntry, attributes: &LdapAttributes) -> DirectoryUser {
+    let values = |name: &str| -> &[String] {
+        entry
+            .attrs
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map_or(&[], |(_, values)| values.as_slice())
+    };
+    let first = |name: &str| values(name).first().cloned();
+    // binary ids, like the objectGUID of Active Directory, are hex encoded
+    let external_id = first(&attributes.id).or_else(|| {
+        entry
+            .bin_attrs
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(&attributes.id))
+            .and_then(|(_, values)| values.first())
+            .map(hex::encode)
+    });
+    let mut groups: Vec<String> = values(&attributes.groups).iter().map(|group| group_name(group)).collect();
+    groups.sort();
+    groups.dedup();
+    let disabled = first("userAccountControl")
+        .and_then(|flags| flags.parse::<u32>().ok())
+        .is_some_and(|flags| flags & 0x2 != 0);
+    DirectoryUser {
+        external_id: external_id.unwrap_or_default(),
+        name: first(&attributes.name).unwrap_or_else(|| entry.dn.clone()),
+        email: first(&attributes.email).unwrap_or_default(),
+        groups,
+        active: !disabled,
+    }
+}
+
+// The value of the first RDN of a group DN, like developers for
+// cn=developers,ou=groups,dc=example,dc=com
+fn group_name(group: &str) -> String {
+    let rdn = group.split(',').next().unwrap_or(group);
+    match rdn.split_once('=') {
+        Some((_, value)) => value.trim().to_string(),
+        None => group.trim().to_string(),
+    }
+}
+
+async fn fetch_scim(url: &str, token: Option<&str>, page_size: u32) -> Result<Vec<DirectoryUser>, String> {
+    let connector = HttpsConnectorBuilder::new()
+        .with_webpki_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    let client = Client::builder().build(connector);
+    let base = url.trim_end_matches('/');
+    let users = scim_list(&client, base, "Users", token, page_size).await?;
+    let groups = scim_list(&client, base, "Groups", token, page_size).await?;
+
+    let mut memberships: HashMap<&str, Vec<String>> = HashMap::new();
+    for group in &groups {
+        let Some(name) = group["displayName"].as_str() else {
+            continue;
+        };
+        for member in group["members"].as_array().into_iter().flatten() {
+            if let Some(id) = member["value"].as_str() {
+                memberships.entry(id).or_default().push(name.to_string());
+            }
+        }
+    }
+
+    Ok(users
+        .iter()
+        .map(|user| {
+            let id = user["id"].as_str().unwrap_or_default();
+            let mut groups = memberships.remove(id).unwrap_or_default();
+            // the read-only groups attribute, which not all providers fill
+            for group in user["groups"].as_array().into_iter().flatten() {
+                if let Some(name) = group["display"].as_str() {
+                    groups.push(name.to_string());
+                }
+            }
+            groups.sort();
+            groups.dedup();
+            DirectoryUser {
+                external_id: id.to_string(),
+                name: scim_name(user),
+                email: scim_email(user),
+                groups,
+                active: user["active"].as_bool().unwrap_or(true),
+            }
+        })
+        .collect())
+}
+
+fn scim_name(user: &Value) -> String {
+    if let Some(name) = user["displayName"].as_str().or(user["name"]["formatted"].as_str()) {
+        return name.to_string();
+    }
+    let parts: Vec<&str> = [&user["name"]["givenName"], &user["name"]["familyName"]]
+        .into_iter()
+        .filter_map(Value::as_str)
+        .collect();
+    if parts.is_empty() {
+        user["userName"].as_str().unwrap_or_default().to_string()
+    } else {
+        parts.join(" ")
+    }
+}
+
+// The primary email, else the first one, else the user name if it is one
+fn scim_email(user: &Value) -> String {
+    let emails = user["emails"].as_array().map(Vec::as_slice).unwrap_or_default();
+    emails
+        .iter()
+        .find(|email| email["primary"].as_bool() == Some(true))
+        .or(emails.first())
+        .and_then(|email| email["value"].as_str())
+        .or(user["userName"].as_str().filter(|name| name.contains('@')))
+        .unwrap_or_default()
+        .to_string()
+}
+
+// All the resources of a SCIM list, page by page
+async fn scim_list(
+    client: &Client<HttpsConnector<HttpConnector>>,
+    base: &str,
+    resource: &str,
+    token: Option<&str>,
+    page_size: u32,
+) -> Result<Vec<Value>, String> {
+    let mut resources = Vec::new();
+    loop {
+        let uri = format!("{}/{}?startIndex={}&count={}", base, resource, resources.len() + 1, page_size.max(1));
+        let mut page = scim_get(client, &uri, token).await?;
+        let items = match page.get_mut("Resources").map(Value::take) {
+            Some(Value::Array(items)) => items,
+            _ => Vec::new(),
+        };
+        let total = page["totalResults"].as_u64().unwrap_or(0) as usize;
+        let done = items.is_empty();
+        resources.extend(items);
+        if done || resources.len() >= total {
+            return Ok(resources);
+        }
+        if resources.len() > MAX_ENTRIES {
+            return Err(format!("SCIM {}/{}: more than {} resources", base, resource, MAX_ENTRIES));
+        }
+    }
+}
+
+async fn scim_get(client: &Client<HttpsConnector<HttpConnector>>, uri: &str, token: Option<&str>) -> Result<Value, String> {
+    let mut request = Request::get(uri).header("accept", "application/scim+json");
+    if let Some(token) = token {
+        request = request.header("authorization", format!("Bearer {}", token));
+    }
+    let request = request
+        .body(Body::empty())
+        .map_err(|e| format!("invalid SCIM URL {}: {}", uri, e))?;
+    let response = async {
+        let response = client.request(request).await.map_err(|e| e.to_string())?;
+        let status = response.status();
+        let body = warp::hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|e| e.to_string())?;
+        if !status.is_success() {
+            return Err(format!("status {}", status));
+        }
+        serde_json::from_slice(&body).map_err(|e| format!("invalid response: {}", e))
+    };
+    match tokio::time::timeout(REQUEST_TIMEOUT, response).await {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(e)) => Err(format!("SCIM GET {}: {}", uri, e)),
+        Err(_) => Err(format!("SCIM GET {}: timed out", uri)),
+    }
+}
+
+// Seconds since the epoch as RFC 3339, the format of the user dates
+fn format_timestamp(secs: u64) -> String {
+    // the civil date of a day number, from Howard Hinnant's date algorithms
+    let days = (secs / 86400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    let time = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
@@ -0,0 +1,220 @@
+// Policy version history
+//
+// Every change to a policy gets a new version number, kept along with who made
+// it, when, and the fields it changed, so that a bad push can be looked at and
+// rolled back. Only the last MAX_HISTORY revisions of a policy are kept.
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{current_timestamp, SecurityPolicy};
+
+pub const MAX_HISTORY: usize = 50;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeAction {
+    Create,
+    Update,
+    Delete,
+    Rollback,
+}
+
+impl ChangeAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeAction::Create => "create",
+            ChangeAction::Update => "update",
+            ChangeAction::Delete => "delete",
+            ChangeAction::Rollback => "rollback",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "create" => Some(ChangeAction::Create),
+            "update" => Some(ChangeAction::Update),
+            "delete" => Some(ChangeAction::Delete),
+            "rollback" => Some(ChangeAction::Rollback),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FieldChange {
+    pub path: String,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PolicyChange {
+    pub version: u64,
+    pub action: ChangeAction,
+    pub changed_by: String,
+    pub changed_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rollback_of: Option<u64>,
+    pub diff: Vec<FieldChange>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PolicyRevision {
+    #[serde(flatten)]
+    pub change: PolicyChange,
+    pub policy: SecurityPolicy,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RollbackRequest {
+    pub version: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct PolicyRecord {
+    pub policy: SecurityPolicy,
+    pub version: u64,
+    pub deleted: bool,
+    history: VecDeque<PolicyRevision>,
+}
+
+impl PolicyRecord {
+    pub fn new(policy: SecurityPolicy, changed_by: &str) -> Self {
+        let mut record = PolicyRecord {
+            policy: policy.clone(),
+            version: 0,
+            deleted: false,
+            history: VecDeque::new(),
+        };
+        record.push(ChangeAction::Create, changed_by, None, Vec::new());
+        record
+    }
+
+    // Rebuild a record loaded from the storage, with its revisions oldest first
+    pub fn restore(policy: SecurityPolicy, version: u64, deleted: bool, history: Vec<PolicyRevision>) -> Self {
+        let mut history = VecDeque::from(history);
+        while history.len() > MAX_HISTORY {
+            history.pop_front();
+        }
+        PolicyRecord {
+            policy,
+            version,
+            deleted,
+            history,
+        }
+    }
+
+    pub fn etag(&self) -> String {
+        format!("\"{}\"", self.version)
+    }
+
+    pub fn update(&mut self, policy: SecurityPolicy, changed_by: &str) -> u64 {
+        let diff = diff_policies(&self.policy, &policy);
+        self.policy = policy;
+        self.deleted = false;
+        self.push(ChangeAction::Update, changed_by, None, diff)
+    }
+
+    pub fn delete(&mut self, changed_by: &str) -> u64 {
+        self.deleted = true;
+        self.push(ChangeAction::Delete, changed_by, None, Vec::new())
+    }
+
+    // Make the policy of an older revision current again, as a new version.
+    // Returns None if the revision is not in the history anymore.
+    pub fn rollback(&mut self, version: u64, changed_by: &str) -> Option<u64> {
+        let policy = self.revision(version)?.policy.clone();
+        let diff = diff_policies(&self.policy, &policy);
+        self.policy = policy;
+        self.deleted = false;
+        Some(self.push(ChangeAction::Rollback, changed_by, Some(version), diff))
+    }
+
+    pub fn revision(&self, version: u64) -> Option<&PolicyRevision> {
+        self.history.iter().find(|r| r.change.version == version)
+    }
+
+    pub fn latest(&self) -> Option<&PolicyRevision> {
+        self.history.back()
+    }
+
+    // Changes of the kept revisions, newest first
+    pub fn changes(&self) -> Vec<&PolicyChange> {
+        self.history.iter().rev().map(|r| &r.change).collect()
+    }
+
+    // Whether an If-Match header allows changing the current version.
+    // A missing header always matches, as does "*" unless the policy was deleted.
+    pub fn matches(&self, if_match: Option<&str>) -> bool {
+        let Some(if_match) = if_match else {
+            return true;
+        };
+        if if_match.trim() == "*" {
+            return !self.deleted;
+        }
+        if_match.split(',').any(|tag| {
+            let tag = tag.trim();
+            let tag = tag.strip_prefix("W/").unwrap_or(tag);
+            tag.trim_matches('"').parse::<u64>().ok() == Some(self.version)
+        })
+    }
+
+    fn push(
+        &mut self,
+        action: ChangeAction,
+        changed_by: &str,
+        rollback_of: Option<u64>,
+        diff: Vec<FieldChange>,
+    ) -> u64 {
+        self.version += 1;
+        self.history.push_back(PolicyRevision {
+            change: PolicyChange {
+                version: self.version,
+                action,
+                changed_by: changed_by.to_string(),
+                changed_at: current_timestamp(),
+                rollback_of,
+                diff,
+            },
+            policy: self.policy.clone(),
+        });
+        while self.history.len() > MAX_HISTORY {
+            self.history.pop_front();
+        }
+        self.version
+    }
+}
+
+fn diff_policies(old: &SecurityPolicy, new: &SecurityPolicy) -> Vec<FieldChange> {
+    let old = serde_json::to_value(old).unwrap_or(Value::Null);
+    let new = serde_json::to_value(new).unwrap_or(Value::Null);
+    let mut changes = Vec::new();
+    diff_values("", Some(&old), Some(&new), &mut changes);
+    changes
+}
+
+// Objects are compared field by field, anything else as a whole
+fn diff_values(path: &str, old: Option<&Value>, new: Option<&Value>, changes: &mut Vec<FieldChange>) {
+    if let (Some(Value::Object(old)), Some(Value::Object(new))) = (old, new) {
+        let mut keys: Vec<&String> = old.keys().chain(new.keys()).collect();
+        keys.sort();
+        keys.dedup();
+        for key in keys {
+            let path = if path.is_empty() {
+                key.to_string()
+            } else {
+                format!("{}.{}", path, key)
+            };
+            diff_values(&path, old.get(key), new.get(key), changes);
+        }
+    } else if old != new {
+        changes.push(FieldChange {
+            path: path.to_string(),
+            old: old.cloned(),
+            new: new.cloned(),
+        });
+    }
+}
@@ -0,0 +1,462 @@
+// Ingestion of the metrics of the g3icap and g3proxy nodes
+//
+// The nodes send their metrics as StatsD lines over UDP, in the format of
+// g3-statsd-client: `name:value|type[|@rate][|#key:value,...]`, several lines
+// per datagram. Prometheus endpoints can be scraped too, for the nodes and
+// exporters without StatsD. The values received during a step of STEP_SECS
+// are appended as one sample per series, a series being a name and its tags:
+//   c        - counter, the running total of the received increments
+//   g        - gauge, the last value, `+n` and `-n` changing the current one
+//   ms, h, d - timer, the mean of the step
+//   s        - set, the number of distinct values of the step
+// Each series keeps the samples of the last RING_SECS in a ring buffer, the
+// older ones are read from the database. The new samples of each step are
+// also broadcast to the live streams.
+//
+// The StatsD listen address is STATSD_LISTEN, 127.0.0.1:8125 by default and
+// disabled if empty; the Prometheus endpoints are the comma separated http://
+// URLs of PROMETHEUS_TARGETS.
+
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+use tokio::sync::broadcast;
+use tokio::time::MissedTickBehavior;
+use warp::hyper::client::HttpConnector;
+use warp::hyper::{Client, Uri};
+
+use crate::{current_timestamp, latest_sample, Metric, MetricValue, MetricsStore};
+
+pub const STEP_SECS: u64 = 10;
+pub const RING_SECS: u64 = 3600;
+pub const DEFAULT_STATSD_LISTEN: &str = "127.0.0.1:8125";
+const RING_CAPACITY: usize = (RING_SECS / STEP_SECS) as usize;
+// Series beyond this number are dropped, against a flood of names or tags
+const MAX_SERIES: usize = 10_000;
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(5);
+// Steps kept for the live streams, those of a slower stream are skipped
+const UPDATE_BUFFER: usize = 8;
+
+pub struct IngestConfig {
+    pub statsd_listen: Option<SocketAddr>,
+    pub prometheus_targets: Vec<Uri>,
+}
+
+impl IngestConfig {
+    pub fn from_env() -> Result<Self, String> {
+        let listen = std::env::var("STATSD_LISTEN").unwrap_or_else(|_| DEFAULT_STATSD_LISTEN.to_string());
+        let statsd_listen = match listen.trim() {
+            "" => None,
+            addr => Some(addr.parse().map_err(|e| format!("invalid STATSD_LISTEN {}: {}", addr, e))?),
+        };
+        let mut prometheus_targets = Vec::new();
+        let targets = std::env::var("PROMETHEUS_TARGETS").unwrap_or_default();
+        for target in targets.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+            let uri: Uri = target
+                .parse()
+                .map_err(|e| format!("invalid Prometheus target {}: {}", target, e))?;
+            if uri.scheme_str() != Some("http") || uri.host().is_none() {
+                return Err(format!("invalid Prometheus target {}: only http:// URLs are supported", target));
+            }
+            prometheus_targets.push(uri);
+        }
+        Ok(IngestConfig {
+            statsd_listen,
+            prometheus_targets,
+        })
+    }
+}
+
+// What was received for a series during the current step
+enum Pending {
+    // StatsD increments
+    Counter(f64),
+    // Prometheus counters, already a running total
+    Total(f64),
+    Gauge(f64),
+    Timer { sum: f64, count: u64 },
+    Set(HashSet<String>),
+}
+
+impl Pending {
+    fn kind(&self) -> &'static str {
+        match self {
+            Pending::Counter(_) | Pending::Total(_) => "counter",
+            Pending::Gauge(_) => "gauge",
+            Pending::Timer { .. } => "timer",
+            Pending::Set(_) => "set",
+        }
+    }
+}
+
+// The series which got a new sample at a step, with only that sample
+pub struct StepUpdate {
+    pub timestamp: u64,
+    pub metrics: Vec<Metric>,
+}
+
+struct PendingSeries {
+    name: String,
+    tags: BTreeMap<String, String>,
+    value: Pending,
+}
+
+pub struct Ingester {
+    store: MetricsStore,
+    pending: Mutex<HashMap<String, PendingSeries>>,
+    updates: broadcast::Sender<Arc<StepUpdate>>,
+}
+
+impl Ingester {
+    pub fn new(store: MetricsStore) -> Arc<Self> {
+        Arc::new(Ingester {
+            store,
+            pending: Mutex::new(HashMap::new()),
+            updates: broadcast::channel(UPDATE_BUFFER).0,
+        })
+    }
+
+    // The samples of the next steps
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<StepUpdate>> {
+        self.updates.subscribe()
+    }
+
+    // Listen for StatsD, scrape the Prometheus targets and append the samples
+    // at each step
+    pub async fn spawn(self: &Arc<Self>, config: &IngestConfig) -> Result<(), String> {
+        if let Some(addr) = config.statsd_listen {
+            let socket = UdpSocket::bind(addr)
+                .await
+                .map_err(|e| format!("failed to listen for StatsD on {}: {}", addr, e))?;
+            let ingester = self.clone();
+            tokio::spawn(async move {
+                let mut buf = vec![0u8; 65536];
+                loop {
+                    match socket.recv_from(&mut buf).await {
+                        Ok((len, _)) => ingester.ingest_statsd(&String::from_utf8_lossy(&buf[..len])),
+                        Err(e) => log::warn!("failed to receive StatsD datagram: {}", e),
+                    }
+                }
+            });
+        }
+
+        let client = Client::new();
+        for target in &config.prometheus_targets {
+            let ingester = self.clone();
+            let client = client.clone();
+            let target = target.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(Duration::from_secs(STEP_SECS));
+                interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                loop {
+                    interval.tick().await;
+                    match scrape(&client, &target).await {
+                        Ok(text) => ingester.ingest_prometheus(&text, &target),
+                        Err(e) => log::warn!("failed to scrape {}: {}", target, e),
+                    }
+                }
+            });
+        }
+
+        let ingester = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(STEP_SECS));
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                ingester.flush(current_timestamp());
+            }
+        });
+        Ok(())
+    }
+
+    fn ingest_statsd(&self, datagram: &str) {
+        for line in datagram.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            if let Err(e) = self.record_statsd(line) {
+                log::debug!("invalid StatsD line {:?}: {}", line, e);
+            }
+        }
+    }
+
+    fn record_statsd(&self, line: &str) -> Result<(), &'static str> {
+        let mut fields = line.split('|');
+        let (name, value) = fields
+            .next()
+            .and_then(|f| f.split_once(':'))
+            .ok_or("no value")?;
+        if name.is_empty() {
+            return Err("no name");
+        }
+        let kind = fields.next().ok_or("no type")?;
+        let mut rate = 1.0;
+        let mut tags = BTreeMap::new();
+        for field in fields {
+            if let Some(r) = field.strip_prefix('@') {
+                rate = r.parse::<f64>().ok().filter(|r| *r > 0.0 && *r <= 1.0).ok_or("invalid sample rate")?;
+            } else if let Some(t) = field.strip_prefix('#') {
+                for tag in t.split(',').filter(|t| !t.is_empty()) {
+                    let (key, value) = tag.split_once(':').unwrap_or((tag, ""));
+                    tags.insert(key.to_string(), value.to_string());
+                }
+            }
+        }
+
+        let number = || value.parse::<f64>().ok().filter(|v| v.is_finite()).ok_or("invalid value");
+        let mut pending = self.pending.lock().unwrap();
+        let key = series_key(name, &tags);
+        match kind {
+            "c" => {
+                let increment = number()? / rate;
+                match pending.get_mut(&key).map(|s| &mut s.value) {
+                    Some(Pending::Counter(total)) => *total += increment,
+                    _ => insert(&mut pending, key, name, tags, Pending::Counter(increment)),
+                }
+            }
+            "g" => {
+                let v = number()?;
+                let gauge = if value.starts_with('+') || value.starts_with('-') {
+                    let current = match pending.get(&key).map(|s| &s.value) {
+                        Some(Pending::Gauge(current)) => Some(*current),
+                        _ => self.last_value(&key),
+                    };
+                    current.unwrap_or(0.0) + v
+                } else {
+                    v
+                };
+                insert(&mut pending, key, name, tags, Pending::Gauge(gauge));
+            }
+            "ms" | "h" | "d" => {
+                let v = number()?;
+                // a sampled timer value stands for 1/rate of them
+                let count = (1.0 / rate).round().max(1.0) as u64;
+                match pending.get_mut(&key).map(|s| &mut s.value) {
+                    Some(Pending::Timer { sum, count: c }) => {
+                        *sum += v * count as f64;
+                        *c += count;
+                    }
+                    _ => insert(&mut pending, key, name, tags, Pending::Timer { sum: v * count as f64, count }),
+                }
+            }
+            "s" => match pending.get_mut(&key).map(|s| &mut s.value) {
+                Some(Pending::Set(values)) => {
+                    values.insert(value.to_string());
+                }
+                _ => insert(&mut pending, key, name, tags, Pending::Set(HashSet::from([value.to_string()]))),
+            },
+            _ => return Err("unknown type"),
+        }
+        Ok(())
+    }
+
+    // Record the samples of a Prometheus text exposition, tagged with the
+    // instance they come from unless they say otherwise
+    fn ingest_prometheus(&self, text: &str, target: &Uri) {
+        let instance = target.authority().map(|a| a.to_string()).unwrap_or_default();
+        let mut types: HashMap<&str, &str> = HashMap::new();
+        let mut pending = self.pending.lock().unwrap();
+        for line in text.lines().map(str::trim) {
+            if let Some(comment) = line.strip_prefix('#') {
+                let mut words = comment.split_whitespace();
+                if let (Some("TYPE"), Some(family), Some(kind)) = (words.next(), words.next(), words.next()) {
+                    types.insert(family, kind);
+                }
+                continue;
+            }
+            if line.is_empty() {
+                continue;
+            }
+            let Some((name, mut tags, value)) = parse_prometheus_line(line) else {
+                log::debug!("invalid Prometheus sample {:?} from {}", line, target);
+                continue;
+            };
+            if !value.is_finite() {
+                continue;
+            }
+            tags.entry("instance".to_string()).or_insert_with(|| instance.clone());
+            // the buckets, sums and counts of the histograms and summaries are
+            // running totals too, their quantiles gauges
+            let family = ["_bucket", "_sum", "_count"]
+                .iter()
+                .find_map(|suffix| name.strip_suffix(suffix))
+                .filter(|family| matches!(types.get(family), Some(&"histogram") | Some(&"summary")));
+            let value = if types.get(name) == Some(&"counter") || family.is_some() {
+                Pending::Total(value)
+            } else {
+                Pending::Gauge(value)
+            };
+            let key = series_key(name, &tags);
+            insert(&mut pending, key, name, tags, value);
+        }
+    }
+
+    fn last_value(&self, key: &str) -> Option<f64> {
+        let store = self.store.lock().unwrap();
+        store.get(key).and_then(|metric| metric.values.back()).map(|v| v.value)
+    }
+
+    // Append the values of the step to the series
+    fn flush(&self, now: u64) {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        let mut store = self.store.lock().unwrap();
+        let mut dropped = 0;
+        let mut updated = Vec::with_capacity(pending.len());
+        for (key, series) in pending {
+            if !store.contains_key(&key) && store.len() >= MAX_SERIES {
+                dropped += 1;
+                continue;
+            }
+            let metric = store.entry(key).or_insert_with(|| Metric {
+                name: series.name,
+                r#type: series.value.kind().to_string(),
+                tags: series.tags.into_iter().collect(),
+                values: VecDeque::new(),
+            });
+            let previous = metric.values.back().map(|v| v.value);
+            let value = match series.value {
+                Pending::Counter(increment) => previous.unwrap_or(0.0) + increment,
+                Pending::Total(value) | Pending::Gauge(value) => value,
+                Pending::Timer { sum, count } => sum / count as f64,
+                Pending::Set(values) => values.len() as f64,
+            };
+            match metric.values.back_mut() {
+                // a step ending in the same second as the previous one
+                Some(last) if last.timestamp == now => last.value = value,
+                _ => metric.values.push_back(MetricValue { value, timestamp: now }),
+            }
+            while metric.values.len() > RING_CAPACITY {
+                metric.values.pop_front();
+            }
+            updated.push(latest_sample(metric));
+        }
+        drop(store);
+        if dropped > 0 {
+            log::warn!("dropped {} new metric series, already {} of them", dropped, MAX_SERIES);
+        }
+        if !updated.is_empty() {
+            // no receiver is not an error, just no stream open
+            let _ = self.updates.send(Arc::new(StepUpdate {
+                timestamp: now,
+                metrics: updated,
+            }));
+        }
+    }
+}
+
+fn insert(
+    pending: &mut HashMap<String, PendingSeries>,
+    key: String,
+    name: &str,
+    tags: BTreeMap<String, String>,
+    value: Pending,
+) {
+    let series = PendingSeries {
+        name: name.to_string(),
+        tags,
+        value,
+    };
+    pending.insert(key, series);
+}
+
+// Key of a series in the store, the name and the sorted tags
+fn series_key(name: &str, tags: &BTreeMap<String, String>) -> String {
+    if tags.is_empty() {
+        return name.to_string();
+    }
+    let tags: Vec<String> = tags.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+    format!("{}{{{}}}", name, tags.join(","))
+}
+
+// Parse `name{label="value",...} value [timestamp]`
+fn parse_prometheus_line(line: &str) -> Option<(&str, BTreeMap<String, String>, f64)> {
+    let name_end = line.find(|c: char| c == '{' || c.is_whitespace())?;
+    let name = &line[..name_end];
+    let mut rest = &line[name_end..];
+    let mut tags = BTreeMap::new();
+    if let Some(labels) = rest.strip_prefix('{') {
+        rest = labels;
+        loop {
+            rest = rest.trim_start_matches([' ', ',']);
+            if let Some(after) = rest.strip_prefix('}') {
+                rest = after;
+                break;
+            }
+            let (key, after) = rest.split_once('=')?;
+            let mut chars = after.strip_prefix('"')?.char_indices();
+            let mut value = String::new();
+            let end = loop {
+                match chars.next()? {
+                    (i, '"') => break i,
+                    (_, '\\') => match chars.next()?.1 {
+                        'n' => value.push('\n'),
+                        c => value.push(c),
+                    },
+                    (_, c) => value.push(c),
+                }
+            };
+            tags.insert(key.trim().to_string(), value);
+            rest = &after[end + 2..];
+        }
+    }
+    let value = rest.split_whitespace().next()?.parse().ok()?;
+    Some((name, tags, value))
+}
+
+async fn scrape(client: &Client<HttpConnector>, target: &Uri) -> Result<String, String> {
+    let request = async {
+        let response = client.get(target.clone()).await.map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("status {}", response.status()));
+        }
+        let body = warp::hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|e| e.to_string())?;
+        String::from_utf8(body.to_vec()).map_err(|e| e.to_string())
+    };
+    tokio::time::timeout(SCRAPE_TIMEOUT, request)
+        .await
+        .map_err(|_| format!("no response within {}s", SCRAPE_TIMEOUT.as_secs()))?
+}
+
+// The samples of a series between two timestamps, as the last value of each
+// step for the counters and the mean of the step for the others, each
+// timestamped with the start of its step
+pub fn downsample(metric: &Metric, from: u64, to: u64, step: Option<u64>) -> Metric {
+    let samples = metric.values.iter().filter(|v| v.timestamp >= from && v.timestamp <= to);
+    let values = match step {
+        None => samples.cloned().collect(),
+        Some(step) => {
+            let mut values: VecDeque<MetricValue> = VecDeque::new();
+            let mut count = 0;
+            for sample in samples {
+                let start = from + (sample.timestamp - from) / step * step;
+                match values.back_mut() {
+                    Some(last) if last.timestamp == start => {
+                        count += 1;
+                        last.value = if metric.r#type == "counter" {
+                            sample.value
+                        } else {
+                            last.value + (sample.value - last.value) / count as f64
+                        };
+                    }
+                    _ => {
+                        count = 1;
+                        values.push_back(MetricValue {
+                            value: sample.value,
+                            timestamp: start,
+                        });
+                    }
+                }
+            }
+            values
+        }
+    };
+    Metric {
+        name: metric.name.clone(),
+        r#type: metric.r#type.clone(),
+        tags: metric.tags.clone(),
+        values,
+    }
+}
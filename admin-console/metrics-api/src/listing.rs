@@ -0,0 +1,355 @@
+// Pagination, filtering and sorting of the list endpoints
+//
+// GET /policies, /users and /metrics take:
+//   filter=status:active,groups:developers|admins,malware
+//       the items matching every term: `field:value` matches the items whose
+//       field contains the value, or one of the `|` separated values, and a
+//       term without a field matches the text fields. Lists, like the groups
+//       or tags, match if one of their elements is the value.
+//   sort=-updated_at,name
+//       the fields to sort by, descending with `-`, the id breaking the ties
+//   page=2&per_page=50 or cursor=...&per_page=50
+//       a page of the sorted items. The cursor of the next page, given as
+//       next_cursor, points after the last item rather than at a position,
+//       so that the pages don't shift as items are added or removed.
+// Without page, per_page or cursor every item is returned. The total_count
+// is the number of items matching the filter.
+
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::history::PolicyRecord;
+use crate::{Metric, User};
+
+pub const DEFAULT_PER_PAGE: usize = 50;
+pub const MAX_PER_PAGE: usize = 1000;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ListQuery {
+    page: Option<usize>,
+    per_page: Option<usize>,
+    cursor: Option<String>,
+    sort: Option<String>,
+    filter: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PageInfo {
+    pub total_count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub per_page: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+impl PageInfo {
+    // Every item, of a list which is not paged
+    pub fn unpaged(total_count: usize) -> Self {
+        PageInfo { total_count, page: None, per_page: None, next_cursor: None }
+    }
+}
+
+// The items of a list endpoint
+pub trait Listable {
+    // The fields of the filter and sort parameters
+    const FIELDS: &'static [&'static str];
+    // The fields a filter term without a field matches
+    const TEXT_FIELDS: &'static [&'static str];
+    const DEFAULT_SORT: &'static str;
+
+    // The value of one of the FIELDS
+    fn field(&self, name: &str) -> Value;
+}
+
+impl<T: Listable> Listable for &T {
+    const FIELDS: &'static [&'static str] = T::FIELDS;
+    const TEXT_FIELDS: &'static [&'static str] = T::TEXT_FIELDS;
+    const DEFAULT_SORT: &'static str = T::DEFAULT_SORT;
+
+    fn field(&self, name: &str) -> Value {
+        (*self).field(name)
+    }
+}
+
+// Where the next page starts
+#[derive(Serialize, Deserialize)]
+struct Cursor {
+    sort: String,
+    filter: String,
+    key: Vec<Value>,
+    id: String,
+}
+
+struct SortField {
+    name: String,
+    descending: bool,
+}
+
+struct Term {
+    field: Option<String>,
+    values: Vec<String>,
+}
+
+// The page of items, given with their ids, the query asks for
+pub fn list<T: Listable>(items: Vec<(String, T)>, query: &ListQuery) -> Result<(Vec<T>, PageInfo), String> {
+    let sort_spec = query.sort.as_deref().map(str::trim).filter(|s| !s.is_empty()).unwrap_or(T::DEFAULT_SORT);
+    let sort = parse_sort::<T>(sort_spec)?;
+    let filter_spec = query.filter.as_deref().map(str::trim).unwrap_or_default();
+    let terms = parse_filter::<T>(filter_spec)?;
+
+    let mut matching: Vec<(Vec<Value>, String, T)> = items
+        .into_iter()
+        .filter(|(_, item)| terms.iter().all(|term| term.matches(item)))
+        .map(|(id, item)| {
+            let key = sort.iter().map(|field| item.field(&field.name)).collect();
+            (key, id, item)
+        })
+        .collect();
+    matching.sort_by(|a, b| compare_keys(&sort, (&a.0, &a.1), (&b.0, &b.1)));
+    let total_count = matching.len();
+
+    let paged = query.page.is_some() || query.per_page.is_some() || query.cursor.is_some();
+    if !paged {
+        let items = matching.into_iter().map(|(_, _, item)| item).collect();
+        return Ok((items, PageInfo::unpaged(total_count)));
+    }
+    let per_page = query.per_page.unwrap_or(DEFAULT_PER_PAGE);
+    if per_page == 0 || per_page > MAX_PER_PAGE {
+        return Err(format!("per_page should be between 1 and {}", MAX_PER_PAGE));
+    }
+    let (start, page) = match (&query.cursor, query.page) {
+        (Some(_), Some(_)) => return Err("page and cursor can't be given together".to_string()),
+        (Some(cursor), None) => {
+            let cursor = decode_cursor(cursor)?;
+            if cursor.sort != sort_spec || cursor.filter != filter_spec {
+                return Err("the cursor is for another sort or filter".to_string());
+            }
+            let start = matching
+                .iter()
+                .position(|(key, id, _)| compare_keys(&sort, (key, id), (&cursor.key, &cursor.id)) == Ordering::Greater)
+                .unwrap_or(matching.len());
+            (start, None)
+        }
+        (None, page) => {
+            let page = page.unwrap_or(1);
+            if page == 0 {
+                return Err("page starts at 1".to_string());
+            }
+            ((page - 1).saturating_mul(per_page).min(matching.len()), Some(page))
+        }
+    };
+
+    let end = start.saturating_add(per_page).min(matching.len());
+    let next_cursor = if end < matching.len() {
+        let (key, id, _) = &matching[end - 1];
+        Some(encode_cursor(&Cursor {
+            sort: sort_spec.to_string(),
+            filter: filter_spec.to_string(),
+            key: key.clone(),
+            id: id.clone(),
+        }))
+    } else {
+        None
+    };
+    let items = matching.into_iter().skip(start).take(end - start).map(|(_, _, item)| item).collect();
+    Ok((items, PageInfo { total_count, page, per_page: Some(per_page), next_cursor }))
+}
+
+fn parse_sort<T: Listable>(spec: &str) -> Result<Vec<SortField>, String> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|field| {
+            let (name, descending) = match field.strip_prefix('-') {
+                Some(name) => (name, true),
+                None => (field.strip_prefix('+').unwrap_or(field), false),
+            };
+            check_field::<T>(name)?;
+            Ok(SortField { name: name.to_string(), descending })
+        })
+        .collect()
+}
+
+fn parse_filter<T: Listable>(spec: &str) -> Result<Vec<Term>, String> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|term| {
+            let (field, values) = match term.split_once(':') {
+                Some((field, values)) if T::FIELDS.contains(&field.trim()) => (Some(field.trim().to_string()), values),
+                Some((field, _)) if is_field_name(field) => return Err(unknown_field::<T>(field)),
+                _ => (None, term),
+            };
+            let values = values.split('|').map(|v| v.trim().to_lowercase()).filter(|v| !v.is_empty()).collect();
+            Ok(Term { field, values })
+        })
+        .collect()
+}
+
+// A filter term like `name:value`, rather than a search for `10:30`
+fn is_field_name(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_lowercase() || c == '_')
+}
+
+fn check_field<T: Listable>(name: &str) -> Result<(), String> {
+    if T::FIELDS.contains(&name) {
+        Ok(())
+    } else {
+        Err(unknown_field::<T>(name))
+    }
+}
+
+fn unknown_field<T: Listable>(name: &str) -> String {
+    format!("unknown field {}, expected one of {}", name, T::FIELDS.join(", "))
+}
+
+impl Term {
+    fn matches<T: Listable>(&self, item: &T) -> bool {
+        if self.values.is_empty() {
+            return true;
+        }
+        match &self.field {
+            Some(field) => self.matches_value(&item.field(field)),
+            None => T::TEXT_FIELDS.iter().any(|field| self.matches_value(&item.field(field))),
+        }
+    }
+
+    fn matches_value(&self, value: &Value) -> bool {
+        match value {
+            Value::Array(elements) => elements.iter().any(|element| self.values.contains(&text(element))),
+            Value::Null => false,
+            value => {
+                let value = text(value);
+                self.values.iter().any(|v| value.contains(v.as_str()))
+            }
+        }
+    }
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.to_lowercase(),
+        value => value.to_string(),
+    }
+}
+
+// The order of two items by their sort keys, then their ids
+fn compare_keys(sort: &[SortField], a: (&Vec<Value>, &String), b: (&Vec<Value>, &String)) -> Ordering {
+    for (i, field) in sort.iter().enumerate() {
+        let ordering = compare_values(a.0.get(i).unwrap_or(&Value::Null), b.0.get(i).unwrap_or(&Value::Null));
+        let ordering = if field.descending { ordering.reverse() } else { ordering };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    a.1.cmp(b.1)
+}
+
+// Missing values first, then numbers, booleans and text without case
+fn compare_values(a: &Value, b: &Value) -> Ordering {
+    let rank = |v: &Value| match v {
+        Value::Null => 0,
+        Value::Number(_) => 1,
+        Value::Bool(_) => 2,
+        _ => 3,
+    };
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => {
+            let (a, b) = (a.as_f64().unwrap_or_default(), b.as_f64().unwrap_or_default());
+            a.total_cmp(&b)
+        }
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (Value::Null, Value::Null) => Ordering::Equal,
+        _ if rank(a) != rank(b) => rank(a).cmp(&rank(b)),
+        _ => text(a).cmp(&text(b)).then_with(|| a.to_string().cmp(&b.to_string())),
+    }
+}
+
+fn encode_cursor(cursor: &Cursor) -> String {
+    hex::encode(serde_json::to_vec(cursor).unwrap_or_default())
+}
+
+fn decode_cursor(cursor: &str) -> Result<Cursor, String> {
+    hex::decode(cursor)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .ok_or_else(|| "invalid cursor".to_string())
+}
+
+impl Listable for PolicyRecord {
+    const FIELDS: &'static [&'static str] = &[
+        "name",
+        "description",
+        "status",
+        "priority",
+        "enabled",
+        "tags",
+        "user_groups",
+        "created_by",
+        "created_at",
+        "updated_at",
+        "version",
+    ];
+    const TEXT_FIELDS: &'static [&'static str] = &["name", "description"];
+    const DEFAULT_SORT: &'static str = "name";
+
+    fn field(&self, name: &str) -> Value {
+        let metadata = &self.policy.metadata;
+        match name {
+            "name" => json!(metadata.name),
+            "description" => json!(metadata.description),
+            "status" => json!(metadata.status),
+            "priority" => json!(self.policy.spec.priority),
+            "enabled" => json!(self.policy.spec.enabled),
+            "tags" => json!(metadata.tags),
+            "user_groups" => json!(self.policy.spec.targets.user_groups),
+            "created_by" => json!(metadata.created_by),
+            "created_at" => json!(metadata.created_at),
+            "updated_at" => json!(metadata.updated_at),
+            "version" => json!(self.version),
+            _ => Value::Null,
+        }
+    }
+}
+
+impl Listable for User {
+    const FIELDS: &'static [&'static str] = &["name", "email", "groups", "status", "role", "created", "last_login"];
+    const TEXT_FIELDS: &'static [&'static str] = &["name", "email"];
+    const DEFAULT_SORT: &'static str = "name";
+
+    fn field(&self, name: &str) -> Value {
+        match name {
+            "name" => json!(self.name),
+            "email" => json!(self.email),
+            "groups" => json!(self.groups),
+            "status" => json!(self.status),
+            "role" => json!(self.role),
+            "created" => json!(self.created),
+            "last_login" => json!(self.last_login),
+            _ => Value::Null,
+        }
+    }
+}
+
+impl Listable for Metric {
+    const FIELDS: &'static [&'static str] = &["name", "type", "tags", "value", "timestamp"];
+    const TEXT_FIELDS: &'static [&'static str] = &["name"];
+    const DEFAULT_SORT: &'static str = "name";
+
+    fn field(&self, name: &str) -> Value {
+        let latest = self.values.back();
+        match name {
+            "name" => json!(self.name),
+            "type" => json!(self.r#type),
+            // key=value
+            "tags" => json!(self.tags.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>()),
+            "value" => json!(latest.map(|sample| sample.value)),
+            "timestamp" => json!(latest.map(|sample| sample.timestamp)),
+            _ => Value::Null,
+        }
+    }
+}
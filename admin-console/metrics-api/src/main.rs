@@ -1,12 +1,45 @@
-use std::collections::HashMap;
+// The filter chain of the routes is deeper than the default limit
+#![recursion_limit = "256"]
+
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::{Arc, Mutex};
 use std::time::{SystemTime, UNIX_EPOCH};
-use warp::Filter;
-use std::thread;
+use warp::{Filter, Reply};
 use std::time::Duration;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+mod auth;
+mod deploy;
+mod directory;
+mod history;
+mod ingest;
+mod listing;
+mod storage;
+mod stream;
+mod validation;
+
+use auth::{Auth, CreateTokenRequest, Permission, Principal, TokenDeletion};
+use deploy::Deployer;
+use directory::{DeletedUser, DirectoryLink, DirectorySync};
+use history::{PolicyRecord, RollbackRequest};
+use ingest::{IngestConfig, Ingester};
+use listing::{ListQuery, PageInfo};
+use storage::{Database, DEFAULT_DATABASE_URL};
+use stream::StreamQuery;
+use validation::FieldError;
+
+// How often the metric samples are saved, and for how long they are kept
+const METRICS_SAVE_INTERVAL: Duration = Duration::from_secs(10);
+const METRICS_RETENTION_SECS: u64 = 24 * 3600;
+// Window of GET /metrics/{name}/history when no start is given, and the most
+// points it returns
+const METRICS_HISTORY_SECS: u64 = 3600;
+const METRICS_HISTORY_MAX_POINTS: u64 = 10_000;
+// Entries of GET /audit when no limit is given, and the most it returns
+const AUDIT_DEFAULT_LIMIT: u32 = 100;
+const AUDIT_MAX_LIMIT: u32 = 1000;
+
 #[derive(Clone, Debug, Serialize)]
 struct MetricValue {
     value: f64,
@@ -18,13 +51,28 @@ struct Metric {
     name: String,
     r#type: String,
     tags: HashMap<String, String>,
-    values: Vec<MetricValue>,
+    values: VecDeque<MetricValue>,
 }
 
 #[derive(Clone, Debug, Serialize)]
 struct MetricsResponse {
     metrics: Vec<Metric>,
-    total_count: usize,
+    #[serde(flatten)]
+    page: PageInfo,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct MetricHistoryQuery {
+    from: Option<u64>,
+    to: Option<u64>,
+    step: Option<u64>,
+    // Former name of from
+    since: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct AuditQuery {
+    limit: Option<u32>,
 }
 
 // Policy structures
@@ -192,7 +240,8 @@ struct SecurityPolicy {
 #[derive(Clone, Debug, Serialize)]
 struct PolicyResponse {
     policies: Vec<SecurityPolicy>,
-    total_count: usize,
+    #[serde(flatten)]
+    page: PageInfo,
 }
 
 // User structures
@@ -208,183 +257,140 @@ struct User {
     role: String,
     bandwidth_limit: Option<String>,
     daily_quota: Option<String>,
+    // The directory entry of the users imported by the directory sync
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    directory: Option<DirectoryLink>,
 }
 
 #[derive(Clone, Debug, Serialize)]
 struct UserResponse {
     users: Vec<User>,
-    total_count: usize,
+    #[serde(flatten)]
+    page: PageInfo,
 }
 
 type MetricsStore = Arc<Mutex<HashMap<String, Metric>>>;
-type PolicyStore = Arc<Mutex<HashMap<String, SecurityPolicy>>>;
-type UserStore = Arc<Mutex<HashMap<String, User>>>;
+// Changes to policies and users are saved to the database while holding the
+// store lock, so these use an async mutex
+type PolicyStore = Arc<tokio::sync::Mutex<HashMap<String, PolicyRecord>>>;
+type UserStore = Arc<tokio::sync::Mutex<HashMap<String, User>>>;
 
 #[tokio::main]
 async fn main() {
     env_logger::init();
     
-    let metrics_store: MetricsStore = Arc::new(Mutex::new(HashMap::new()));
-    let policy_store: PolicyStore = Arc::new(Mutex::new(HashMap::new()));
-    let user_store: UserStore = Arc::new(Mutex::new(HashMap::new()));
+    let database_url = std::env::var("DATABASE_URL").unwrap_or_else(|_| DEFAULT_DATABASE_URL.to_string());
+    let db = match Database::connect(&database_url).await {
+        Ok(db) => db,
+        Err(e) => exit_on_storage_error("open the database", e),
+    };
     
-    // Initialize with sample data
-    initialize_sample_data(policy_store.clone(), user_store.clone());
+    // Recover the state saved by the previous runs
+    let mut policies = db.load_policies().await.unwrap_or_else(|e| exit_on_storage_error("load the policies", e));
+    let mut users = db.load_users().await.unwrap_or_else(|e| exit_on_storage_error("load the users", e));
+    let metrics = load_metrics(&db).await.unwrap_or_else(|e| exit_on_storage_error("load the metrics", e));
+    println!("Loaded {} policies, {} users and {} metrics from {}", policies.len(), users.len(), metrics.len(), database_url);
+    let auth = Auth::load(db.clone(), std::env::var("ADMIN_TOKEN").ok())
+        .await
+        .unwrap_or_else(|e| exit_on_storage_error("load the API tokens", e));
+    let auth = Arc::new(auth);
+    let ingest_config = IngestConfig::from_env().unwrap_or_else(|e| {
+        eprintln!("Failed to load the metrics ingestion config: {}", e);
+        std::process::exit(1);
+    });
+    let deployer = match Deployer::load() {
+        Ok(deployer) => deployer.map(Arc::new),
+        Err(e) => {
+            eprintln!("Failed to load the deploy config: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let directory = match DirectorySync::load() {
+        Ok(directory) => directory.map(Arc::new),
+        Err(e) => {
+            eprintln!("Failed to load the directory config: {}", e);
+            std::process::exit(1);
+        }
+    };
     
-    // Start background thread to simulate realistic metrics data
+    // Initialize a new database with sample data
+    if policies.is_empty() && users.is_empty() {
+        initialize_sample_data(&mut policies, &mut users);
+        for (id, record) in &policies {
+            db.save_policy(id, record).await.unwrap_or_else(|e| exit_on_storage_error("save the sample policies", e));
+        }
+        for (id, user) in &users {
+            db.save_user(id, user).await.unwrap_or_else(|e| exit_on_storage_error("save the sample users", e));
+        }
+    }
+    
+    let metrics_store: MetricsStore = Arc::new(Mutex::new(metrics));
+    let policy_store: PolicyStore = Arc::new(tokio::sync::Mutex::new(policies));
+    let user_store: UserStore = Arc::new(tokio::sync::Mutex::new(users));
+    
+    // Deploy the policy changes to the nodes
+    if let Some(deployer) = &deployer {
+        deployer.spawn(policy_store.clone());
+        println!("Deploying the policy changes to {} nodes", deployer.node_count());
+    }
+    
+    // Import the users of the directory
+    if let Some(directory) = &directory {
+        directory.spawn(user_store.clone(), db.clone());
+        println!("Syncing the users with the {} directory", directory.source());
+    }
+    
+    // Collect the metrics of the nodes
+    let ingester = Ingester::new(metrics_store.clone());
+    if let Err(e) = ingester.spawn(&ingest_config).await {
+        eprintln!("Failed to start the metrics ingestion: {}", e);
+        std::process::exit(1);
+    }
+    if let Some(addr) = ingest_config.statsd_listen {
+        println!("Receiving StatsD metrics on udp://{}", addr);
+    }
+    for target in &ingest_config.prometheus_targets {
+        println!("Scraping Prometheus metrics from {}", target);
+    }
+    
+    // Periodically save the new metric samples
     let store_clone = metrics_store.clone();
-    thread::spawn(move || {
-        let mut request_count = 150.0;
-        let mut connection_count = 42.0;
-        let mut response_time = 125.5;
-        let mut error_count = 5.0;
-        let mut bytes_sent = 1024000.0;
-        let mut bytes_received = 2048000.0;
-        
+    let db_clone = db.clone();
+    tokio::spawn(async move {
+        let mut saved_until = store_clone
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|metric| metric.values.back().map(|v| v.timestamp))
+            .max()
+            .unwrap_or(0);
+        let mut interval = tokio::time::interval(METRICS_SAVE_INTERVAL);
         loop {
-            thread::sleep(Duration::from_secs(2));
-            
-            let mut store = store_clone.lock().unwrap();
-            let now = current_timestamp();
-            
-            // Simulate realistic request patterns
-            request_count += fastrand::f64() * 10.0; // Random increase 0-10 requests
-            
-            // Simulate connection fluctuations
-            connection_count = (connection_count + (fastrand::f64() - 0.5) * 5.0).max(10.0).min(100.0);
-            
-            // Simulate response time variations
-            response_time = (response_time + (fastrand::f64() - 0.5) * 20.0).max(50.0).min(500.0);
-            
-            // Simulate occasional errors
-            if fastrand::f64() < 0.1 {
-                error_count += 1.0;
+            interval.tick().await;
+            let metrics: Vec<(String, Metric)> = store_clone
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(_, metric)| metric.values.back().is_some_and(|v| v.timestamp > saved_until))
+                .map(|(key, metric)| {
+                    let mut metric = metric.clone();
+                    metric.values.retain(|v| v.timestamp > saved_until);
+                    (key.clone(), metric)
+                })
+                .collect();
+            let latest = metrics.iter().filter_map(|(_, metric)| metric.values.back()).map(|v| v.timestamp).max();
+            match db_clone.save_metrics(&metrics, METRICS_RETENTION_SECS).await {
+                Ok(()) => saved_until = latest.unwrap_or(saved_until),
+                Err(e) => log::error!("failed to save the metrics: {}", e),
             }
-            
-            // Simulate data transfer
-            bytes_sent += fastrand::f64() * 50000.0;
-            bytes_received += fastrand::f64() * 100000.0;
-            
-            // Update counter metrics
-            let mut requests_metric = Metric {
-                name: "requests_total".to_string(),
-                r#type: "counter".to_string(),
-                tags: HashMap::new(),
-                values: vec![],
-            };
-            requests_metric.tags.insert("method".to_string(), "GET".to_string());
-            requests_metric.tags.insert("status".to_string(), "200".to_string());
-            requests_metric.values.push(MetricValue {
-                value: request_count,
-                timestamp: now,
-            });
-            store.insert("requests_total_get_200".to_string(), requests_metric);
-            
-            // Error counter
-            let mut errors_metric = Metric {
-                name: "errors_total".to_string(),
-                r#type: "counter".to_string(),
-                tags: HashMap::new(),
-                values: vec![],
-            };
-            errors_metric.tags.insert("type".to_string(), "http_error".to_string());
-            errors_metric.values.push(MetricValue {
-                value: error_count,
-                timestamp: now,
-            });
-            store.insert("errors_total_http".to_string(), errors_metric);
-            
-            // Update gauge metrics
-            let mut connections_metric = Metric {
-                name: "active_connections".to_string(),
-                r#type: "gauge".to_string(),
-                tags: HashMap::new(),
-                values: vec![],
-            };
-            connections_metric.tags.insert("server".to_string(), "g3proxy".to_string());
-            connections_metric.values.push(MetricValue {
-                value: connection_count,
-                timestamp: now,
-            });
-            store.insert("active_connections_g3proxy".to_string(), connections_metric);
-            
-            // Response time metric
-            let mut response_time_metric = Metric {
-                name: "response_time_ms".to_string(),
-                r#type: "gauge".to_string(),
-                tags: HashMap::new(),
-                values: vec![],
-            };
-            response_time_metric.tags.insert("endpoint".to_string(), "/api/metrics".to_string());
-            response_time_metric.values.push(MetricValue {
-                value: response_time,
-                timestamp: now,
-            });
-            store.insert("response_time_ms_api_metrics".to_string(), response_time_metric);
-            
-            // Data transfer metrics
-            let mut bytes_sent_metric = Metric {
-                name: "bytes_sent_total".to_string(),
-                r#type: "counter".to_string(),
-                tags: HashMap::new(),
-                values: vec![],
-            };
-            bytes_sent_metric.tags.insert("direction".to_string(), "outbound".to_string());
-            bytes_sent_metric.values.push(MetricValue {
-                value: bytes_sent,
-                timestamp: now,
-            });
-            store.insert("bytes_sent_total".to_string(), bytes_sent_metric);
-            
-            let mut bytes_received_metric = Metric {
-                name: "bytes_received_total".to_string(),
-                r#type: "counter".to_string(),
-                tags: HashMap::new(),
-                values: vec![],
-            };
-            bytes_received_metric.tags.insert("direction".to_string(), "inbound".to_string());
-            bytes_received_metric.values.push(MetricValue {
-                value: bytes_received,
-                timestamp: now,
-            });
-            store.insert("bytes_received_total".to_string(), bytes_received_metric);
-            
-            // CPU usage simulation
-            let cpu_usage = 20.0 + fastrand::f64() * 40.0; // 20-60% CPU
-            let mut cpu_metric = Metric {
-                name: "cpu_usage_percent".to_string(),
-                r#type: "gauge".to_string(),
-                tags: HashMap::new(),
-                values: vec![],
-            };
-            cpu_metric.tags.insert("server".to_string(), "g3proxy".to_string());
-            cpu_metric.values.push(MetricValue {
-                value: cpu_usage,
-                timestamp: now,
-            });
-            store.insert("cpu_usage_percent".to_string(), cpu_metric);
-            
-            // Memory usage simulation
-            let memory_usage = 128.0 + fastrand::f64() * 64.0; // 128-192 MB
-            let mut memory_metric = Metric {
-                name: "memory_usage_mb".to_string(),
-                r#type: "gauge".to_string(),
-                tags: HashMap::new(),
-                values: vec![],
-            };
-            memory_metric.tags.insert("server".to_string(), "g3proxy".to_string());
-            memory_metric.values.push(MetricValue {
-                value: memory_usage,
-                timestamp: now,
-            });
-            store.insert("memory_usage_mb".to_string(), memory_metric);
         }
     });
     
     // CORS headers
     let cors = warp::cors()
         .allow_any_origin()
-        .allow_headers(vec!["content-type"])
+        .allow_headers(vec!["authorization", "content-type", "if-match"])
+        .expose_headers(vec!["etag", "retry-after"])
         .allow_methods(vec!["GET", "POST", "PUT", "DELETE"]);
     
     // Health check endpoint
@@ -394,108 +400,324 @@ async fn main() {
     
     // Metrics endpoints
     let metrics = warp::path("metrics")
+        .and(warp::path::end())
         .and(warp::get())
+        .and(auth::require(auth.clone(), Permission::Read))
+        .and(warp::query::<ListQuery>())
         .and(with_metrics(metrics_store.clone()))
         .and_then(get_metrics);
     
+    let metric_stream = warp::path("metrics")
+        .and(warp::path("stream"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(auth::require(auth.clone(), Permission::Read))
+        .and(warp::ws())
+        .and(warp::header::optional::<String>("sec-websocket-protocol"))
+        .and(warp::query::<StreamQuery>())
+        .and(with_metrics(metrics_store.clone()))
+        .and(with_updates(ingester.clone()))
+        .map(stream::upgrade);
+    
     let metric_by_name = warp::path("metrics")
         .and(warp::path::param::<String>())
+        .and(warp::path::end())
         .and(warp::get())
+        .and(auth::require(auth.clone(), Permission::Read))
         .and(with_metrics(metrics_store.clone()))
         .and_then(get_metric_by_name);
     
+    let metric_history = warp::path("metrics")
+        .and(warp::path::param::<String>())
+        .and(warp::path("history"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(auth::require(auth.clone(), Permission::Read))
+        .and(warp::query::<MetricHistoryQuery>())
+        .and(with_metrics(metrics_store.clone()))
+        .and(with_db(db.clone()))
+        .and_then(get_metric_history);
+    
     // Policy endpoints
     let policies = warp::path("policies")
+        .and(warp::path::end())
         .and(warp::get())
+        .and(auth::require(auth.clone(), Permission::Read))
+        .and(warp::query::<ListQuery>())
         .and(with_policies(policy_store.clone()))
         .and_then(get_policies);
     
     let policy_by_id = warp::path("policies")
         .and(warp::path::param::<String>())
+        .and(warp::path::end())
         .and(warp::get())
+        .and(auth::require(auth.clone(), Permission::Read))
         .and(with_policies(policy_store.clone()))
         .and_then(get_policy_by_id);
     
     let create_policy = warp::path("policies")
+        .and(warp::path::end())
         .and(warp::post())
-        .and(warp::body::json())
+        .and(with_changed_by(auth.clone()))
+        .and(with_valid_policy(user_store.clone()))
         .and(with_policies(policy_store.clone()))
+        .and(with_db(db.clone()))
+        .and(with_deployer(deployer.clone()))
         .and_then(create_policy_handler);
     
     let update_policy = warp::path("policies")
         .and(warp::path::param::<String>())
+        .and(warp::path::end())
         .and(warp::put())
-        .and(warp::body::json())
+        .and(warp::header::optional::<String>("if-match"))
+        .and(with_changed_by(auth.clone()))
+        .and(with_valid_policy(user_store.clone()))
         .and(with_policies(policy_store.clone()))
+        .and(with_db(db.clone()))
+        .and(with_deployer(deployer.clone()))
         .and_then(update_policy_handler);
     
     let delete_policy = warp::path("policies")
         .and(warp::path::param::<String>())
+        .and(warp::path::end())
         .and(warp::delete())
+        .and(warp::header::optional::<String>("if-match"))
+        .and(with_changed_by(auth.clone()))
         .and(with_policies(policy_store.clone()))
+        .and(with_db(db.clone()))
+        .and(with_deployer(deployer.clone()))
         .and_then(delete_policy_handler);
     
+    let policy_versions = warp::path("policies")
+        .and(warp::path::param::<String>())
+        .and(warp::path("versions"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(auth::require(auth.clone(), Permission::Read))
+        .and(with_policies(policy_store.clone()))
+        .and_then(get_policy_versions);
+    
+    let policy_version = warp::path("policies")
+        .and(warp::path::param::<String>())
+        .and(warp::path("versions"))
+        .and(warp::path::param::<u64>())
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(auth::require(auth.clone(), Permission::Read))
+        .and(with_policies(policy_store.clone()))
+        .and_then(get_policy_version);
+    
+    let rollback_policy = warp::path("policies")
+        .and(warp::path::param::<String>())
+        .and(warp::path("rollback"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::header::optional::<String>("if-match"))
+        .and(with_changed_by(auth.clone()))
+        .and(warp::body::json())
+        .and(with_policies(policy_store.clone()))
+        .and(with_db(db.clone()))
+        .and(with_deployer(deployer.clone()))
+        .and_then(rollback_policy_handler);
+    
+    // Deployment endpoints
+    let deployments = warp::path("deployments")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(auth::require(auth.clone(), Permission::Read))
+        .and(with_deployer(deployer.clone()))
+        .and_then(get_deployments);
+    
+    let deployment_by_id = warp::path("deployments")
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(auth::require(auth.clone(), Permission::Read))
+        .and(with_deployer(deployer.clone()))
+        .and_then(get_deployment_by_id);
+    
+    let create_deployment = warp::path("deployments")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(with_changed_by(auth.clone()))
+        .and(with_deployer(deployer.clone()))
+        .and_then(create_deployment_handler);
+    
     // User endpoints
     let users = warp::path("users")
+        .and(warp::path::end())
         .and(warp::get())
+        .and(auth::require(auth.clone(), Permission::Read))
+        .and(warp::query::<ListQuery>())
         .and(with_users(user_store.clone()))
         .and_then(get_users);
     
     let user_by_id = warp::path("users")
         .and(warp::path::param::<String>())
+        .and(warp::path::end())
         .and(warp::get())
+        .and(auth::require(auth.clone(), Permission::Read))
         .and(with_users(user_store.clone()))
         .and_then(get_user_by_id);
     
     let create_user = warp::path("users")
+        .and(warp::path::end())
         .and(warp::post())
+        .and(auth::require(auth.clone(), Permission::Admin))
         .and(warp::body::json())
         .and(with_users(user_store.clone()))
+        .and(with_db(db.clone()))
         .and_then(create_user_handler);
     
     let update_user = warp::path("users")
         .and(warp::path::param::<String>())
+        .and(warp::path::end())
         .and(warp::put())
+        .and(auth::require(auth.clone(), Permission::Admin))
         .and(warp::body::json())
         .and(with_users(user_store.clone()))
+        .and(with_db(db.clone()))
         .and_then(update_user_handler);
     
     let delete_user = warp::path("users")
         .and(warp::path::param::<String>())
+        .and(warp::path::end())
         .and(warp::delete())
+        .and(with_admin_name(auth.clone()))
         .and(with_users(user_store.clone()))
+        .and(with_db(db.clone()))
         .and_then(delete_user_handler);
     
+    // Directory sync endpoints
+    let directory_status = warp::path("directory")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(auth::require(auth.clone(), Permission::Read))
+        .and(with_directory(directory.clone()))
+        .and(with_db(db.clone()))
+        .and_then(get_directory_status);
+    
+    let sync_directory = warp::path("directory")
+        .and(warp::path("sync"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(auth::require(auth.clone(), Permission::Admin))
+        .and(with_directory(directory.clone()))
+        .and_then(sync_directory_handler);
+    
+    let restore_directory_user = warp::path("directory")
+        .and(warp::path("deleted"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(auth::require(auth.clone(), Permission::Admin))
+        .and(with_directory(directory.clone()))
+        .and(with_db(db.clone()))
+        .and_then(restore_directory_user_handler);
+    
+    // Authentication and token endpoints
+    let whoami = warp::path("auth")
+        .and(warp::path("whoami"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(auth::authorize(auth.clone(), Permission::Read))
+        .map(|principal: Principal| warp::reply::json(&principal));
+    
+    let tokens = warp::path("tokens")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(auth::require(auth.clone(), Permission::Admin))
+        .and(with_auth(auth.clone()))
+        .and_then(get_tokens);
+    
+    let create_token = warp::path("tokens")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(auth::authorize(auth.clone(), Permission::Admin))
+        .and(warp::body::json())
+        .and(with_auth(auth.clone()))
+        .and_then(create_token_handler);
+    
+    let delete_token = warp::path("tokens")
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(auth::require(auth.clone(), Permission::Admin))
+        .and(with_auth(auth.clone()))
+        .and_then(delete_token_handler);
+    
+    let audit = warp::path("audit")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(auth::require(auth.clone(), Permission::Admin))
+        .and(warp::query::<AuditQuery>())
+        .and(with_auth(auth.clone()))
+        .and_then(get_audit);
+    
     let routes = health
         .or(metrics)
+        .or(metric_stream)
         .or(metric_by_name)
+        .or(metric_history)
         .or(policies)
         .or(policy_by_id)
         .or(create_policy)
         .or(update_policy)
         .or(delete_policy)
+        .or(policy_versions)
+        .or(policy_version)
+        .or(rollback_policy)
+        .or(deployments)
+        .or(deployment_by_id)
+        .or(create_deployment)
         .or(users)
         .or(user_by_id)
         .or(create_user)
         .or(update_user)
         .or(delete_user)
+        .or(directory_status)
+        .or(sync_directory)
+        .or(restore_directory_user)
+        .or(whoami)
+        .or(tokens)
+        .or(create_token)
+        .or(delete_token)
+        .or(audit)
+        .recover(auth::handle_rejection)
+        .with(auth::audit_log(auth.clone()))
         .with(cors);
     
     println!("Starting Arcus Admin API on http://localhost:3001");
     println!("Available endpoints:");
     println!("  GET /health - Health check");
     println!("  GET /metrics - Get all metrics");
+    println!("  GET /metrics/stream - Stream the metric updates over WebSocket");
     println!("  GET /metrics/{{name}} - Get specific metric");
+    println!("  GET /metrics/{{name}}/history - Get the samples of a metric over a time range");
     println!("  GET /policies - Get all policies");
     println!("  GET /policies/{{id}} - Get specific policy");
     println!("  POST /policies - Create policy");
     println!("  PUT /policies/{{id}} - Update policy");
     println!("  DELETE /policies/{{id}} - Delete policy");
+    println!("  GET /policies/{{id}}/versions - Get policy version history");
+    println!("  GET /policies/{{id}}/versions/{{version}} - Get specific policy version");
+    println!("  POST /policies/{{id}}/rollback - Roll back policy to a version");
+    println!("  GET /deployments - Get the policy deployments");
+    println!("  GET /deployments/{{id}} - Get specific deployment");
+    println!("  POST /deployments - Deploy the current policies");
     println!("  GET /users - Get all users");
     println!("  GET /users/{{id}} - Get specific user");
     println!("  POST /users - Create user");
     println!("  PUT /users/{{id}} - Update user");
     println!("  DELETE /users/{{id}} - Delete user");
+    println!("  GET /directory - Get the directory sync status");
+    println!("  POST /directory/sync - Sync the users with the directory");
+    println!("  DELETE /directory/deleted/{{external_id}} - Let the sync import a deleted user again");
+    println!("  GET /auth/whoami - Get the caller of the token");
+    println!("  GET /tokens - Get all API tokens");
+    println!("  POST /tokens - Create API token");
+    println!("  DELETE /tokens/{{id}} - Revoke API token");
+    println!("  GET /audit - Get the audit log");
     
     let port = std::env::args()
         .nth(1)
@@ -513,27 +735,79 @@ fn with_metrics(metrics: MetricsStore) -> impl Filter<Extract = (MetricsStore,),
     warp::any().map(move || metrics.clone())
 }
 
+// The metric updates of the next steps, for a new stream
+fn with_updates(ingester: Arc<Ingester>) -> impl Filter<Extract = (tokio::sync::broadcast::Receiver<Arc<ingest::StepUpdate>>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || ingester.subscribe())
+}
+
 fn with_policies(policies: PolicyStore) -> impl Filter<Extract = (PolicyStore,), Error = std::convert::Infallible> + Clone {
     warp::any().map(move || policies.clone())
 }
 
+// Who makes a policy change, the name of the token of a caller allowed to,
+// for the version history
+fn with_changed_by(auth: Arc<Auth>) -> impl Filter<Extract = (String,), Error = warp::Rejection> + Clone {
+    auth::authorize(auth, Permission::EditPolicies).map(|principal: Principal| principal.name)
+}
+
+fn with_deployer(deployer: Option<Arc<Deployer>>) -> impl Filter<Extract = (Option<Arc<Deployer>>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || deployer.clone())
+}
+
+fn with_directory(directory: Option<Arc<DirectorySync>>) -> impl Filter<Extract = (Option<Arc<DirectorySync>>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || directory.clone())
+}
+
+// The name of the token of an admin, for the records of the deleted users
+fn with_admin_name(auth: Arc<Auth>) -> impl Filter<Extract = (String,), Error = warp::Rejection> + Clone {
+    auth::authorize(auth, Permission::Admin).map(|principal: Principal| principal.name)
+}
+
+fn with_auth(auth: Arc<Auth>) -> impl Filter<Extract = (Arc<Auth>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || auth.clone())
+}
+
 fn with_users(users: UserStore) -> impl Filter<Extract = (UserStore,), Error = std::convert::Infallible> + Clone {
     warp::any().map(move || users.clone())
 }
 
-async fn get_metrics(metrics: MetricsStore) -> Result<impl warp::Reply, warp::Rejection> {
-    let store = metrics.lock().unwrap();
-    let metrics_vec: Vec<Metric> = store.values().cloned().collect();
+// The policy document of the body, validated
+fn with_valid_policy(users: UserStore) -> impl Filter<Extract = (Result<SecurityPolicy, Vec<FieldError>>,), Error = warp::Rejection> + Clone {
+    warp::body::json()
+        .and(with_users(users))
+        .then(|document, users| async move { validate_policy(document, &users).await })
+}
+
+fn with_db(db: Database) -> impl Filter<Extract = (Database,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || db.clone())
+}
+
+fn exit_on_storage_error(action: &str, e: sqlx::Error) -> ! {
+    eprintln!("Failed to {}: {}", action, e);
+    std::process::exit(1);
+}
+
+async fn get_metrics(query: ListQuery, metrics: MetricsStore) -> Result<warp::reply::Response, warp::Rejection> {
+    let series: Vec<(String, Metric)> = metrics
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(key, metric)| (key.clone(), latest_sample(metric)))
+        .collect();
+    let (metrics_vec, page) = match listing::list(series, &query) {
+        Ok(listed) => listed,
+        Err(e) => return Ok(bad_list_query(&e)),
+    };
     
     let response = MetricsResponse {
-        total_count: metrics_vec.len(),
         metrics: metrics_vec,
+        page,
     };
     
     Ok(warp::reply::with_status(
         warp::reply::json(&response),
         warp::http::StatusCode::OK,
-    ))
+    ).into_response())
 }
 
 async fn get_metric_by_name(name: String, metrics: MetricsStore) -> Result<impl warp::Reply, warp::Rejection> {
@@ -543,7 +817,7 @@ async fn get_metric_by_name(name: String, metrics: MetricsStore) -> Result<impl
     let matching_metrics: Vec<Metric> = store
         .values()
         .filter(|metric| metric.name.contains(&name))
-        .cloned()
+        .map(latest_sample)
         .collect();
     
     if matching_metrics.is_empty() {
@@ -554,7 +828,7 @@ async fn get_metric_by_name(name: String, metrics: MetricsStore) -> Result<impl
     }
     
     let response = MetricsResponse {
-        total_count: matching_metrics.len(),
+        page: PageInfo::unpaged(matching_metrics.len()),
         metrics: matching_metrics,
     };
     
@@ -564,87 +838,410 @@ async fn get_metric_by_name(name: String, metrics: MetricsStore) -> Result<impl
     ))
 }
 
+async fn get_metric_history(name: String, query: MetricHistoryQuery, metrics: MetricsStore, db: Database) -> Result<warp::reply::Response, warp::Rejection> {
+    let now = current_timestamp();
+    let from = query
+        .from
+        .or(query.since)
+        .unwrap_or_else(|| now.saturating_sub(METRICS_HISTORY_SECS));
+    let to = query.to.unwrap_or(now);
+    if from > to {
+        return Ok(bad_history_query("from is after to"));
+    }
+    if query.step == Some(0) {
+        return Ok(bad_history_query("step must be at least 1 second"));
+    }
+    if (to - from) / query.step.unwrap_or(ingest::STEP_SECS) > METRICS_HISTORY_MAX_POINTS {
+        return Ok(bad_history_query("too many points, use a larger step or a shorter range"));
+    }
+    
+    // The ring buffers of the store cover the recent samples, the database
+    // the older ones
+    let matching: Vec<Metric> = if from >= now.saturating_sub(ingest::RING_SECS) {
+        metrics
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|metric| metric.name.contains(&name))
+            .cloned()
+            .collect()
+    } else {
+        match db.metric_history(&name, from, to).await {
+            Ok(metrics) => metrics,
+            Err(e) => {
+                log::error!("failed to load the metric history: {}", e);
+                return Ok(warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({"error": "Failed to load metric history"})),
+                    warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                ).into_response());
+            }
+        }
+    };
+    let metrics: Vec<Metric> = matching
+        .iter()
+        .map(|metric| ingest::downsample(metric, from, to, query.step))
+        .filter(|metric| !metric.values.is_empty())
+        .collect();
+    
+    let response = MetricsResponse {
+        page: PageInfo::unpaged(metrics.len()),
+        metrics,
+    };
+    
+    Ok(warp::reply::with_status(
+        warp::reply::json(&response),
+        warp::http::StatusCode::OK,
+    ).into_response())
+}
+
+fn bad_history_query(error: &str) -> warp::reply::Response {
+    warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({"error": error})),
+        warp::http::StatusCode::BAD_REQUEST,
+    ).into_response()
+}
+
+fn bad_list_query(error: &str) -> warp::reply::Response {
+    warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({"error": error})),
+        warp::http::StatusCode::BAD_REQUEST,
+    ).into_response()
+}
+
+// A metric with only its latest sample
+fn latest_sample(metric: &Metric) -> Metric {
+    Metric {
+        name: metric.name.clone(),
+        r#type: metric.r#type.clone(),
+        tags: metric.tags.clone(),
+        values: metric.values.back().cloned().into_iter().collect(),
+    }
+}
+
+// The metrics of the previous runs: the samples still in the ring buffers,
+// and the latest sample of the older series, so that the counters go on
+async fn load_metrics(db: &Database) -> Result<HashMap<String, Metric>, sqlx::Error> {
+    let mut metrics = db.load_latest_metrics().await?;
+    let now = current_timestamp();
+    for (key, metric) in db.metric_series(now.saturating_sub(ingest::RING_SECS), now).await? {
+        metrics.insert(key, metric);
+    }
+    Ok(metrics)
+}
+
 // Policy handlers
-async fn get_policies(policies: PolicyStore) -> Result<impl warp::Reply, warp::Rejection> {
-    let store = policies.lock().unwrap();
-    let policies_vec: Vec<SecurityPolicy> = store.values().cloned().collect();
+async fn get_policies(query: ListQuery, policies: PolicyStore) -> Result<warp::reply::Response, warp::Rejection> {
+    let store = policies.lock().await;
+    let records: Vec<(String, &PolicyRecord)> = store
+        .iter()
+        .filter(|(_, record)| !record.deleted)
+        .map(|(id, record)| (id.clone(), record))
+        .collect();
+    let (records, page) = match listing::list(records, &query) {
+        Ok(listed) => listed,
+        Err(e) => return Ok(bad_list_query(&e)),
+    };
     
     let response = PolicyResponse {
-        total_count: policies_vec.len(),
-        policies: policies_vec,
+        policies: records.into_iter().map(|record| record.policy.clone()).collect(),
+        page,
     };
     
     Ok(warp::reply::with_status(
         warp::reply::json(&response),
         warp::http::StatusCode::OK,
-    ))
+    ).into_response())
 }
 
-async fn get_policy_by_id(id: String, policies: PolicyStore) -> Result<impl warp::Reply, warp::Rejection> {
-    let store = policies.lock().unwrap();
+async fn get_policy_by_id(id: String, policies: PolicyStore) -> Result<warp::reply::Response, warp::Rejection> {
+    let store = policies.lock().await;
     
-    if let Some(policy) = store.get(&id) {
-        Ok(warp::reply::with_status(
-            warp::reply::json(policy),
-            warp::http::StatusCode::OK,
-        ))
-    } else {
-        Ok(warp::reply::with_status(
-            warp::reply::json(&serde_json::json!({"error": "Policy not found"})),
-            warp::http::StatusCode::NOT_FOUND,
-        ))
+    match store.get(&id).filter(|record| !record.deleted) {
+        Some(record) => Ok(warp::reply::with_header(
+            warp::reply::json(&record.policy),
+            "etag",
+            record.etag(),
+        ).into_response()),
+        None => Ok(policy_not_found()),
     }
 }
 
-async fn create_policy_handler(policy: SecurityPolicy, policies: PolicyStore) -> Result<impl warp::Reply, warp::Rejection> {
+async fn create_policy_handler(changed_by: String, policy: Result<SecurityPolicy, Vec<FieldError>>, policies: PolicyStore, db: Database, deployer: Option<Arc<Deployer>>) -> Result<warp::reply::Response, warp::Rejection> {
+    let policy = match policy {
+        Ok(policy) => policy,
+        Err(errors) => return Ok(invalid_policy(errors)),
+    };
     let id = Uuid::new_v4().to_string();
-    let mut store = policies.lock().unwrap();
-    store.insert(id.clone(), policy);
+    let record = PolicyRecord::new(policy, &changed_by);
+    let mut store = policies.lock().await;
+    if let Err(e) = db.save_policy(&id, &record).await {
+        return Ok(storage_error(e));
+    }
+    let etag = record.etag();
+    store.insert(id.clone(), record);
+    let deployment = request_deployment(deployer, &changed_by);
     
-    Ok(warp::reply::with_status(
-        warp::reply::json(&serde_json::json!({"id": id, "status": "created"})),
-        warp::http::StatusCode::CREATED,
-    ))
+    Ok(warp::reply::with_header(
+        warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({"id": id, "status": "created", "version": 1, "deployment": deployment})),
+            warp::http::StatusCode::CREATED,
+        ),
+        "etag",
+        etag,
+    ).into_response())
 }
 
-async fn update_policy_handler(id: String, policy: SecurityPolicy, policies: PolicyStore) -> Result<impl warp::Reply, warp::Rejection> {
-    let mut store = policies.lock().unwrap();
-    store.insert(id.clone(), policy);
+async fn update_policy_handler(id: String, if_match: Option<String>, changed_by: String, policy: Result<SecurityPolicy, Vec<FieldError>>, policies: PolicyStore, db: Database, deployer: Option<Arc<Deployer>>) -> Result<warp::reply::Response, warp::Rejection> {
+    let policy = match policy {
+        Ok(policy) => policy,
+        Err(errors) => return Ok(invalid_policy(errors)),
+    };
+    let mut store = policies.lock().await;
+    
+    // PUT to an unknown id creates the policy, unless a version was expected
+    let record = match store.get(&id) {
+        Some(record) => {
+            if !record.matches(if_match.as_deref()) {
+                return Ok(version_mismatch(&id, Some(record)));
+            }
+            let mut record = record.clone();
+            record.update(policy, &changed_by);
+            record
+        }
+        None => {
+            if if_match.is_some() {
+                return Ok(version_mismatch(&id, None));
+            }
+            PolicyRecord::new(policy, &changed_by)
+        }
+    };
+    if let Err(e) = db.save_policy(&id, &record).await {
+        return Ok(storage_error(e));
+    }
+    let deployment = request_deployment(deployer, &changed_by);
+    let reply = warp::reply::with_header(
+        warp::reply::json(&serde_json::json!({"id": id, "status": "updated", "version": record.version, "deployment": deployment})),
+        "etag",
+        record.etag(),
+    );
+    store.insert(id, record);
+    
+    Ok(reply.into_response())
+}
+
+async fn delete_policy_handler(id: String, if_match: Option<String>, changed_by: String, policies: PolicyStore, db: Database, deployer: Option<Arc<Deployer>>) -> Result<warp::reply::Response, warp::Rejection> {
+    let mut store = policies.lock().await;
+    
+    // The record is kept so that the deletion can be rolled back
+    let Some(record) = store.get(&id) else {
+        return Ok(warp::reply::json(&serde_json::json!({"id": id, "status": "deleted"})).into_response());
+    };
+    if !record.matches(if_match.as_deref()) {
+        return Ok(version_mismatch(&id, Some(record)));
+    }
+    let (version, deployment) = if record.deleted {
+        (record.version, None)
+    } else {
+        let mut record = record.clone();
+        record.delete(&changed_by);
+        if let Err(e) = db.save_policy(&id, &record).await {
+            return Ok(storage_error(e));
+        }
+        let version = record.version;
+        store.insert(id.clone(), record);
+        (version, request_deployment(deployer, &changed_by))
+    };
     
     Ok(warp::reply::with_status(
-        warp::reply::json(&serde_json::json!({"id": id, "status": "updated"})),
+        warp::reply::json(&serde_json::json!({"id": id, "status": "deleted", "version": version, "deployment": deployment})),
         warp::http::StatusCode::OK,
-    ))
+    ).into_response())
 }
 
-async fn delete_policy_handler(id: String, policies: PolicyStore) -> Result<impl warp::Reply, warp::Rejection> {
-    let mut store = policies.lock().unwrap();
-    store.remove(&id);
+async fn get_policy_versions(id: String, policies: PolicyStore) -> Result<warp::reply::Response, warp::Rejection> {
+    let store = policies.lock().await;
+    
+    let Some(record) = store.get(&id) else {
+        return Ok(policy_not_found());
+    };
+    let versions = record.changes();
     
     Ok(warp::reply::with_status(
-        warp::reply::json(&serde_json::json!({"id": id, "status": "deleted"})),
+        warp::reply::json(&serde_json::json!({
+            "id": id,
+            "version": record.version,
+            "deleted": record.deleted,
+            "total_count": versions.len(),
+            "versions": versions,
+        })),
         warp::http::StatusCode::OK,
-    ))
+    ).into_response())
+}
+
+async fn get_policy_version(id: String, version: u64, policies: PolicyStore) -> Result<warp::reply::Response, warp::Rejection> {
+    let store = policies.lock().await;
+    
+    let Some(record) = store.get(&id) else {
+        return Ok(policy_not_found());
+    };
+    match record.revision(version) {
+        Some(revision) => Ok(warp::reply::with_status(
+            warp::reply::json(revision),
+            warp::http::StatusCode::OK,
+        ).into_response()),
+        None => Ok(version_not_found()),
+    }
+}
+
+async fn rollback_policy_handler(id: String, if_match: Option<String>, changed_by: String, request: RollbackRequest, policies: PolicyStore, db: Database, deployer: Option<Arc<Deployer>>) -> Result<warp::reply::Response, warp::Rejection> {
+    let mut store = policies.lock().await;
+    
+    let Some(record) = store.get(&id) else {
+        return Ok(policy_not_found());
+    };
+    if !record.matches(if_match.as_deref()) {
+        return Ok(version_mismatch(&id, Some(record)));
+    }
+    let mut record = record.clone();
+    let Some(version) = record.rollback(request.version, &changed_by) else {
+        return Ok(version_not_found());
+    };
+    if let Err(e) = db.save_policy(&id, &record).await {
+        return Ok(storage_error(e));
+    }
+    let deployment = request_deployment(deployer, &changed_by);
+    let reply = warp::reply::with_header(
+        warp::reply::json(&serde_json::json!({
+            "id": id,
+            "status": "rolled_back",
+            "version": version,
+            "rollback_of": request.version,
+            "deployment": deployment,
+        })),
+        "etag",
+        record.etag(),
+    );
+    store.insert(id, record);
+    
+    Ok(reply.into_response())
+}
+
+// Deploy a policy change, the id of the deployment if the deployment is configured
+fn request_deployment(deployer: Option<Arc<Deployer>>, changed_by: &str) -> Option<String> {
+    deployer.map(|deployer| deployer.request(changed_by))
+}
+
+// Deployment handlers
+async fn get_deployments(deployer: Option<Arc<Deployer>>) -> Result<impl warp::Reply, warp::Rejection> {
+    let deployments = deployer.as_ref().map(|d| d.deployments()).unwrap_or_default();
+    
+    Ok(warp::reply::json(&serde_json::json!({
+        "enabled": deployer.is_some(),
+        "total_count": deployments.len(),
+        "deployments": deployments,
+    })))
+}
+
+async fn get_deployment_by_id(id: String, deployer: Option<Arc<Deployer>>) -> Result<warp::reply::Response, warp::Rejection> {
+    match deployer.and_then(|d| d.deployment(&id)) {
+        Some(deployment) => Ok(warp::reply::json(&deployment).into_response()),
+        None => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({"error": "Deployment not found"})),
+            warp::http::StatusCode::NOT_FOUND,
+        ).into_response()),
+    }
+}
+
+async fn create_deployment_handler(changed_by: String, deployer: Option<Arc<Deployer>>) -> Result<warp::reply::Response, warp::Rejection> {
+    let Some(deployer) = deployer else {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({"error": "Policy deployment is not configured"})),
+            warp::http::StatusCode::SERVICE_UNAVAILABLE,
+        ).into_response());
+    };
+    let id = deployer.request(&changed_by);
+    
+    Ok(warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({"id": id, "status": "pending"})),
+        warp::http::StatusCode::ACCEPTED,
+    ).into_response())
+}
+
+// Check a policy document, its user groups against those of the users
+async fn validate_policy(document: serde_json::Value, users: &UserStore) -> Result<SecurityPolicy, Vec<FieldError>> {
+    let groups: HashSet<String> = users
+        .lock()
+        .await
+        .values()
+        .flat_map(|user| user.groups.iter().cloned())
+        .collect();
+    validation::validate(document, &groups)
+}
+
+fn invalid_policy(errors: Vec<FieldError>) -> warp::reply::Response {
+    warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({"error": "Invalid policy", "errors": errors})),
+        warp::http::StatusCode::UNPROCESSABLE_ENTITY,
+    ).into_response()
+}
+
+fn policy_not_found() -> warp::reply::Response {
+    warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({"error": "Policy not found"})),
+        warp::http::StatusCode::NOT_FOUND,
+    ).into_response()
+}
+
+fn version_not_found() -> warp::reply::Response {
+    warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({"error": "Policy version not found"})),
+        warp::http::StatusCode::NOT_FOUND,
+    ).into_response()
+}
+
+// Reply to a change made against another version than the current one
+fn version_mismatch(id: &str, record: Option<&PolicyRecord>) -> warp::reply::Response {
+    warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({
+            "error": "Policy version does not match If-Match",
+            "id": id,
+            "version": record.map(|r| r.version),
+        })),
+        warp::http::StatusCode::PRECONDITION_FAILED,
+    ).into_response()
+}
+
+// Reply to a change that could not be saved, the stores are left unchanged
+fn storage_error(e: sqlx::Error) -> warp::reply::Response {
+    log::error!("failed to save change to the database: {}", e);
+    warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({"error": "Failed to save change"})),
+        warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+    ).into_response()
 }
 
 // User handlers
-async fn get_users(users: UserStore) -> Result<impl warp::Reply, warp::Rejection> {
-    let store = users.lock().unwrap();
-    let users_vec: Vec<User> = store.values().cloned().collect();
+async fn get_users(query: ListQuery, users: UserStore) -> Result<warp::reply::Response, warp::Rejection> {
+    let store = users.lock().await;
+    let users_vec: Vec<(String, &User)> = store.iter().map(|(id, user)| (id.clone(), user)).collect();
+    let (users_vec, page) = match listing::list(users_vec, &query) {
+        Ok(listed) => listed,
+        Err(e) => return Ok(bad_list_query(&e)),
+    };
     
     let response = UserResponse {
-        total_count: users_vec.len(),
-        users: users_vec,
+        users: users_vec.into_iter().cloned().collect(),
+        page,
     };
     
     Ok(warp::reply::with_status(
         warp::reply::json(&response),
         warp::http::StatusCode::OK,
-    ))
+    ).into_response())
 }
 
 async fn get_user_by_id(id: String, users: UserStore) -> Result<impl warp::Reply, warp::Rejection> {
-    let store = users.lock().unwrap();
+    let store = users.lock().await;
     
     if let Some(user) = store.get(&id) {
         Ok(warp::reply::with_status(
@@ -659,41 +1256,181 @@ async fn get_user_by_id(id: String, users: UserStore) -> Result<impl warp::Reply
     }
 }
 
-async fn create_user_handler(user: User, users: UserStore) -> Result<impl warp::Reply, warp::Rejection> {
+async fn create_user_handler(mut user: User, users: UserStore, db: Database) -> Result<warp::reply::Response, warp::Rejection> {
     let id = Uuid::new_v4().to_string();
-    let mut store = users.lock().unwrap();
+    user.directory = None;
+    let mut store = users.lock().await;
+    if let Err(e) = db.save_user(&id, &user).await {
+        return Ok(storage_error(e));
+    }
     store.insert(id.clone(), user);
     
     Ok(warp::reply::with_status(
         warp::reply::json(&serde_json::json!({"id": id, "status": "created"})),
         warp::http::StatusCode::CREATED,
-    ))
+    ).into_response())
 }
 
-async fn update_user_handler(id: String, user: User, users: UserStore) -> Result<impl warp::Reply, warp::Rejection> {
-    let mut store = users.lock().unwrap();
+async fn update_user_handler(id: String, mut user: User, users: UserStore, db: Database) -> Result<warp::reply::Response, warp::Rejection> {
+    let mut store = users.lock().await;
+    // The directory owns the fields it sets
+    user.directory = store.get(&id).and_then(|current| current.directory.clone());
+    if let Some(current) = store.get(&id).filter(|current| current.directory.is_some()) {
+        let changed = directory::managed_changes(current, &user);
+        if !changed.is_empty() {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({"error": "User fields are managed by the directory", "fields": changed})),
+                warp::http::StatusCode::CONFLICT,
+            ).into_response());
+        }
+    }
+    if let Err(e) = db.save_user(&id, &user).await {
+        return Ok(storage_error(e));
+    }
     store.insert(id.clone(), user);
     
     Ok(warp::reply::with_status(
         warp::reply::json(&serde_json::json!({"id": id, "status": "updated"})),
         warp::http::StatusCode::OK,
-    ))
+    ).into_response())
 }
 
-async fn delete_user_handler(id: String, users: UserStore) -> Result<impl warp::Reply, warp::Rejection> {
-    let mut store = users.lock().unwrap();
+async fn delete_user_handler(id: String, deleted_by: String, users: UserStore, db: Database) -> Result<warp::reply::Response, warp::Rejection> {
+    let mut store = users.lock().await;
+    // A user of the directory is recorded, so that the sync doesn't import it again
+    let deleted = store
+        .get(&id)
+        .and_then(|user| Some(DeletedUser::new(user.directory.as_ref()?, user, &deleted_by)));
+    let result = match &deleted {
+        Some(deleted) => db.delete_directory_user(&id, deleted).await,
+        None => db.delete_user(&id).await,
+    };
+    if let Err(e) = result {
+        return Ok(storage_error(e));
+    }
     store.remove(&id);
     
     Ok(warp::reply::with_status(
         warp::reply::json(&serde_json::json!({"id": id, "status": "deleted"})),
         warp::http::StatusCode::OK,
-    ))
+    ).into_response())
 }
 
-fn initialize_sample_data(policies: PolicyStore, users: UserStore) {
-    // Initialize sample policies
-    let mut policy_store = policies.lock().unwrap();
+// Directory sync handlers
+async fn get_directory_status(directory: Option<Arc<DirectorySync>>, db: Database) -> Result<warp::reply::Response, warp::Rejection> {
+    let Some(directory) = directory else {
+        return Ok(warp::reply::json(&serde_json::json!({"enabled": false})).into_response());
+    };
+    let deleted: Vec<DeletedUser> = match db.load_deleted_users().await {
+        Ok(deleted) => deleted.into_iter().filter(|d| d.source == directory.source()).collect(),
+        Err(e) => return Ok(storage_error(e)),
+    };
+    
+    Ok(warp::reply::json(&serde_json::json!({
+        "enabled": true,
+        "status": directory.status(),
+        "deleted_users": deleted,
+    })).into_response())
+}
+
+async fn sync_directory_handler(directory: Option<Arc<DirectorySync>>) -> Result<warp::reply::Response, warp::Rejection> {
+    let Some(directory) = directory else {
+        return Ok(directory_not_configured());
+    };
+    directory.request();
     
+    Ok(warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({"status": "requested"})),
+        warp::http::StatusCode::ACCEPTED,
+    ).into_response())
+}
+
+async fn restore_directory_user_handler(external_id: String, directory: Option<Arc<DirectorySync>>, db: Database) -> Result<warp::reply::Response, warp::Rejection> {
+    let Some(directory) = directory else {
+        return Ok(directory_not_configured());
+    };
+    match db.restore_deleted_user(directory.source(), &external_id).await {
+        Ok(true) => Ok(warp::reply::json(&serde_json::json!({"external_id": external_id, "status": "restored"})).into_response()),
+        Ok(false) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({"error": "Deleted user not found"})),
+            warp::http::StatusCode::NOT_FOUND,
+        ).into_response()),
+        Err(e) => Ok(storage_error(e)),
+    }
+}
+
+fn directory_not_configured() -> warp::reply::Response {
+    warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({"error": "Directory sync is not configured"})),
+        warp::http::StatusCode::SERVICE_UNAVAILABLE,
+    ).into_response()
+}
+
+// Token handlers
+async fn get_tokens(auth: Arc<Auth>) -> Result<impl warp::Reply, warp::Rejection> {
+    let tokens = auth.tokens();
+    
+    Ok(warp::reply::json(&serde_json::json!({"total_count": tokens.len(), "tokens": tokens})))
+}
+
+async fn create_token_handler(principal: Principal, request: CreateTokenRequest, auth: Arc<Auth>) -> Result<warp::reply::Response, warp::Rejection> {
+    if request.name.trim().is_empty() {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({"error": "Token name is empty"})),
+            warp::http::StatusCode::BAD_REQUEST,
+        ).into_response());
+    }
+    let (token, secret) = match auth.create_token(request.name.trim(), request.role, &principal.name).await {
+        Ok(created) => created,
+        Err(e) => return Ok(storage_error(e)),
+    };
+    
+    // The secret is only ever returned here
+    Ok(warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({"token": secret, "info": token})),
+        warp::http::StatusCode::CREATED,
+    ).into_response())
+}
+
+async fn delete_token_handler(id: String, auth: Arc<Auth>) -> Result<warp::reply::Response, warp::Rejection> {
+    let (status, body) = match auth.delete_token(&id).await {
+        Ok(TokenDeletion::Deleted) => (
+            warp::http::StatusCode::OK,
+            serde_json::json!({"id": id, "status": "deleted"}),
+        ),
+        Ok(TokenDeletion::NotFound) => (
+            warp::http::StatusCode::NOT_FOUND,
+            serde_json::json!({"error": "Token not found"}),
+        ),
+        Ok(TokenDeletion::Refused) => (
+            warp::http::StatusCode::CONFLICT,
+            serde_json::json!({"error": "The last admin token and ADMIN_TOKEN can not be revoked"}),
+        ),
+        Err(e) => return Ok(storage_error(e)),
+    };
+    
+    Ok(warp::reply::with_status(warp::reply::json(&body), status).into_response())
+}
+
+async fn get_audit(query: AuditQuery, auth: Arc<Auth>) -> Result<warp::reply::Response, warp::Rejection> {
+    let limit = query.limit.unwrap_or(AUDIT_DEFAULT_LIMIT).min(AUDIT_MAX_LIMIT);
+    match auth.audit_entries(limit).await {
+        Ok(entries) => Ok(warp::reply::json(&serde_json::json!({
+            "total_count": entries.len(),
+            "entries": entries,
+        })).into_response()),
+        Err(e) => {
+            log::error!("failed to load the audit log: {}", e);
+            Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({"error": "Failed to load audit log"})),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            ).into_response())
+        }
+    }
+}
+
+fn initialize_sample_data(policy_store: &mut HashMap<String, PolicyRecord>, user_store: &mut HashMap<String, User>) {
+    // Initialize sample policies
     let policy1 = SecurityPolicy {
         api_version: "arcus.v1".to_string(),
         kind: "SecurityPolicy".to_string(),
@@ -740,11 +1477,9 @@ fn initialize_sample_data(policies: PolicyStore, users: UserStore) {
         },
     };
     
-    policy_store.insert("policy-1".to_string(), policy1);
+    policy_store.insert("policy-1".to_string(), PolicyRecord::new(policy1, "admin@company.com"));
     
     // Initialize sample users
-    let mut user_store = users.lock().unwrap();
-    
     let user1 = User {
         id: "user-1".to_string(),
         name: "John Doe".to_string(),
@@ -756,6 +1491,7 @@ fn initialize_sample_data(policies: PolicyStore, users: UserStore) {
         role: "admin".to_string(),
         bandwidth_limit: Some("100Mbps".to_string()),
         daily_quota: Some("5GB".to_string()),
+        directory: None,
     };
     
     user_store.insert("user-1".to_string(), user1);
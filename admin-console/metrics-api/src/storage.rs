@@ -0,0 +1,521 @@
+// Persistent storage of the admin API state
+//
+// Policies with their version history, users and metric samples are written to
+// a SQL database as they change, and loaded back into the in-memory stores at
+// startup. SQLite is used by default; Postgres is available when built with the
+// `postgres` feature. Documents are stored as JSON text so the same schema and
+// queries work on both. Optional numbers are stored as 0 rather than NULL, which
+// the Any driver fails to decode.
+
+use std::collections::{HashMap, VecDeque};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sqlx::any::{install_default_drivers, AnyPoolOptions, AnyRow};
+use sqlx::{AnyPool, Row};
+
+use crate::auth::{ApiToken, AuditEntry, Role};
+use crate::directory::DeletedUser;
+use crate::history::{ChangeAction, PolicyChange, PolicyRecord, PolicyRevision, MAX_HISTORY};
+use crate::{current_timestamp, Metric, MetricValue, User};
+
+pub const DEFAULT_DATABASE_URL: &str = "sqlite://arcus-admin.db?mode=rwc";
+
+// Schema migrations, applied in order and recorded in schema_migrations.
+// Never edit an applied migration, add a new one instead.
+const MIGRATIONS: &[(i64, &[&str])] = &[
+    (
+        1,
+        &[
+            "CREATE TABLE IF NOT EXISTS policies (
+                id TEXT PRIMARY KEY,
+                version BIGINT NOT NULL,
+                deleted BIGINT NOT NULL,
+                document TEXT NOT NULL
+            )",
+            "CREATE TABLE IF NOT EXISTS policy_revisions (
+                policy_id TEXT NOT NULL,
+                version BIGINT NOT NULL,
+                action TEXT NOT NULL,
+                changed_by TEXT NOT NULL,
+                changed_at BIGINT NOT NULL,
+                rollback_of BIGINT NOT NULL,
+                diff TEXT NOT NULL,
+                document TEXT NOT NULL,
+                PRIMARY KEY (policy_id, version)
+            )",
+            "CREATE TABLE IF NOT EXISTS users (
+                id TEXT PRIMARY KEY,
+                document TEXT NOT NULL
+            )",
+        ],
+    ),
+    (
+        2,
+        &[
+            "CREATE TABLE IF NOT EXISTS metric_samples (
+                metric_key TEXT NOT NULL,
+                name TEXT NOT NULL,
+                metric_type TEXT NOT NULL,
+                tags TEXT NOT NULL,
+                value DOUBLE PRECISION NOT NULL,
+                timestamp BIGINT NOT NULL,
+                PRIMARY KEY (metric_key, timestamp)
+            )",
+            "CREATE INDEX IF NOT EXISTS metric_samples_timestamp ON metric_samples (timestamp)",
+        ],
+    ),
+    (
+        3,
+        &[
+            "CREATE TABLE IF NOT EXISTS api_tokens (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                role TEXT NOT NULL,
+                token_hash TEXT NOT NULL UNIQUE,
+                created_at BIGINT NOT NULL,
+                created_by TEXT NOT NULL
+            )",
+            "CREATE TABLE IF NOT EXISTS audit_log (
+                timestamp BIGINT NOT NULL,
+                actor TEXT NOT NULL,
+                role TEXT NOT NULL,
+                method TEXT NOT NULL,
+                path TEXT NOT NULL,
+                status BIGINT NOT NULL,
+                remote TEXT NOT NULL
+            )",
+            "CREATE INDEX IF NOT EXISTS audit_log_timestamp ON audit_log (timestamp)",
+        ],
+    ),
+    (
+        4,
+        &[
+            "CREATE TABLE IF NOT EXISTS directory_deleted_users (
+                source TEXT NOT NULL,
+                external_id TEXT NOT NULL,
+                name TEXT NOT NULL,
+                email TEXT NOT NULL,
+                deleted_at BIGINT NOT NULL,
+                deleted_by TEXT NOT NULL,
+                PRIMARY KEY (source, external_id)
+            )",
+        ],
+    ),
+];
+
+#[derive(Clone)]
+pub struct Database {
+    pool: AnyPool,
+}
+
+impl Database {
+    // Connect to the database and bring its schema up to date
+    pub async fn connect(url: &str) -> Result<Self, sqlx::Error> {
+        install_default_drivers();
+        let pool = AnyPoolOptions::new().max_connections(5).connect(url).await?;
+        let db = Database { pool };
+        db.migrate().await?;
+        Ok(db)
+    }
+
+    async fn migrate(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                version BIGINT PRIMARY KEY,
+                applied_at BIGINT NOT NULL
+            )",
+        )
+        .execute(&self.pool)
+        .await?;
+
+        let applied = sqlx::query("SELECT version FROM schema_migrations")
+            .fetch_all(&self.pool)
+            .await?
+            .iter()
+            .map(|row| row.try_get::<i64, _>("version"))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (version, statements) in MIGRATIONS {
+            if applied.contains(version) {
+                continue;
+            }
+            let mut tx = self.pool.begin().await?;
+            for statement in statements.iter() {
+                sqlx::query(statement).execute(&mut *tx).await?;
+            }
+            sqlx::query("INSERT INTO schema_migrations (version, applied_at) VALUES ($1, $2)")
+                .bind(*version)
+                .bind(current_timestamp() as i64)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            log::info!("applied database migration {}", version);
+        }
+        Ok(())
+    }
+
+    pub async fn load_policies(&self) -> Result<HashMap<String, PolicyRecord>, sqlx::Error> {
+        let mut histories: HashMap<String, Vec<PolicyRevision>> = HashMap::new();
+        let rows = sqlx::query(
+            "SELECT policy_id, version, action, changed_by, changed_at, rollback_of, diff, document
+             FROM policy_revisions ORDER BY policy_id, version",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        for row in rows {
+            let action: String = row.try_get("action")?;
+            let revision = PolicyRevision {
+                change: PolicyChange {
+                    version: row.try_get::<i64, _>("version")? as u64,
+                    action: ChangeAction::parse(&action)
+                        .ok_or_else(|| decode_error(format!("unknown policy change action {}", action)))?,
+                    changed_by: row.try_get("changed_by")?,
+                    changed_at: row.try_get::<i64, _>("changed_at")? as u64,
+                    rollback_of: Some(row.try_get::<i64, _>("rollback_of")? as u64).filter(|v| *v > 0),
+                    diff: from_json(&row, "diff")?,
+                },
+                policy: from_json(&row, "document")?,
+            };
+            histories
+                .entry(row.try_get("policy_id")?)
+                .or_default()
+                .push(revision);
+        }
+
+        let mut policies = HashMap::new();
+        let rows = sqlx::query("SELECT id, version, deleted, document FROM policies")
+            .fetch_all(&self.pool)
+            .await?;
+        for row in rows {
+            let id: String = row.try_get("id")?;
+            let record = PolicyRecord::restore(
+                from_json(&row, "document")?,
+                row.try_get::<i64, _>("version")? as u64,
+                row.try_get::<i64, _>("deleted")? != 0,
+                histories.remove(&id).unwrap_or_default(),
+            );
+            policies.insert(id, record);
+        }
+        Ok(policies)
+    }
+
+    // Save the current state of a policy along with its latest revision
+    pub async fn save_policy(&self, id: &str, record: &PolicyRecord) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO policies (id, version, deleted, document) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (id) DO UPDATE SET
+                version = excluded.version, deleted = excluded.deleted, document = excluded.document",
+        )
+        .bind(id)
+        .bind(record.version as i64)
+        .bind(record.deleted as i64)
+        .bind(to_json(&record.policy)?)
+        .execute(&mut *tx)
+        .await?;
+
+        if let Some(revision) = record.latest() {
+            let change = &revision.change;
+            sqlx::query(
+                "INSERT INTO policy_revisions
+                    (policy_id, version, action, changed_by, changed_at, rollback_of, diff, document)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                 ON CONFLICT (policy_id, version) DO NOTHING",
+            )
+            .bind(id)
+            .bind(change.version as i64)
+            .bind(change.action.as_str())
+            .bind(change.changed_by.as_str())
+            .bind(change.changed_at as i64)
+            .bind(change.rollback_of.unwrap_or(0) as i64)
+            .bind(to_json(&change.diff)?)
+            .bind(to_json(&revision.policy)?)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        sqlx::query("DELETE FROM policy_revisions WHERE policy_id = $1 AND version <= $2")
+            .bind(id)
+            .bind(record.version as i64 - MAX_HISTORY as i64)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await
+    }
+
+    pub async fn load_users(&self) -> Result<HashMap<String, User>, sqlx::Error> {
+        let rows = sqlx::query("SELECT id, document FROM users")
+            .fetch_all(&self.pool)
+            .await?;
+        rows.iter()
+            .map(|row| Ok((row.try_get("id")?, from_json(row, "document")?)))
+            .collect()
+    }
+
+    pub async fn save_user(&self, id: &str, user: &User) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO users (id, document) VALUES ($1, $2)
+             ON CONFLICT (id) DO UPDATE SET document = excluded.document",
+        )
+        .bind(id)
+        .bind(to_json(user)?)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete_user(&self, id: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    // Delete a user of the directory, recording it so that the sync doesn't
+    // import it again
+    pub async fn delete_directory_user(&self, id: &str, deleted: &DeletedUser) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "INSERT INTO directory_deleted_users (source, external_id, name, email, deleted_at, deleted_by)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             ON CONFLICT (source, external_id) DO UPDATE SET
+                name = excluded.name, email = excluded.email,
+                deleted_at = excluded.deleted_at, deleted_by = excluded.deleted_by",
+        )
+        .bind(&deleted.source)
+        .bind(&deleted.external_id)
+        .bind(&deleted.name)
+        .bind(&deleted.email)
+        .bind(deleted.deleted_at as i64)
+        .bind(&deleted.deleted_by)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await
+    }
+
+    pub async fn load_deleted_users(&self) -> Result<Vec<DeletedUser>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT source, external_id, name, email, deleted_at, deleted_by
+             FROM directory_deleted_users ORDER BY deleted_at DESC",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        rows.iter()
+            .map(|row| {
+                Ok(DeletedUser {
+                    source: row.try_get("source")?,
+                    external_id: row.try_get("external_id")?,
+                    name: row.try_get("name")?,
+                    email: row.try_get("email")?,
+                    deleted_at: row.try_get::<i64, _>("deleted_at")? as u64,
+                    deleted_by: row.try_get("deleted_by")?,
+                })
+            })
+            .collect()
+    }
+
+    // Forget a deleted user of the directory, returns whether there was one
+    pub async fn restore_deleted_user(&self, source: &str, external_id: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM directory_deleted_users WHERE source = $1 AND external_id = $2")
+            .bind(source)
+            .bind(external_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Append the current values of the metrics, and drop the samples older
+    // than the retention
+    pub async fn save_metrics(&self, metrics: &[(String, Metric)], retention: u64) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        for (key, metric) in metrics {
+            let tags = to_json(&metric.tags)?;
+            for value in &metric.values {
+                sqlx::query(
+                    "INSERT INTO metric_samples (metric_key, name, metric_type, tags, value, timestamp)
+                     VALUES ($1, $2, $3, $4, $5, $6)
+                     ON CONFLICT (metric_key, timestamp) DO NOTHING",
+                )
+                .bind(key.as_str())
+                .bind(metric.name.as_str())
+                .bind(metric.r#type.as_str())
+                .bind(tags.as_str())
+                .bind(value.value)
+                .bind(value.timestamp as i64)
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+        sqlx::query("DELETE FROM metric_samples WHERE timestamp < $1")
+            .bind(current_timestamp().saturating_sub(retention) as i64)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await
+    }
+
+    // Latest sample of each metric, to refill the metrics store at startup
+    pub async fn load_latest_metrics(&self) -> Result<HashMap<String, Metric>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT s.metric_key, s.name, s.metric_type, s.tags, s.value, s.timestamp
+             FROM metric_samples s
+             JOIN (SELECT metric_key, MAX(timestamp) AS timestamp FROM metric_samples GROUP BY metric_key) l
+             ON s.metric_key = l.metric_key AND s.timestamp = l.timestamp",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let mut metrics = HashMap::new();
+        collect_samples(&rows, &mut metrics)?;
+        Ok(metrics)
+    }
+
+    // Samples between two timestamps of the metrics whose name contains the
+    // name, one metric per series
+    pub async fn metric_history(&self, name: &str, from: u64, to: u64) -> Result<Vec<Metric>, sqlx::Error> {
+        Ok(self
+            .metric_series(from, to)
+            .await?
+            .into_values()
+            .filter(|metric| metric.name.contains(name))
+            .collect())
+    }
+
+    // Samples between two timestamps of each series
+    pub async fn metric_series(&self, from: u64, to: u64) -> Result<HashMap<String, Metric>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT metric_key, name, metric_type, tags, value, timestamp
+             FROM metric_samples WHERE timestamp >= $1 AND timestamp <= $2 ORDER BY metric_key, timestamp",
+        )
+        .bind(from as i64)
+        .bind(to as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut metrics = HashMap::new();
+        collect_samples(&rows, &mut metrics)?;
+        Ok(metrics)
+    }
+
+    pub async fn load_tokens(&self) -> Result<Vec<ApiToken>, sqlx::Error> {
+        let rows = sqlx::query("SELECT id, name, role, token_hash, created_at, created_by FROM api_tokens")
+            .fetch_all(&self.pool)
+            .await?;
+        rows.iter()
+            .map(|row| {
+                let role: String = row.try_get("role")?;
+                Ok(ApiToken {
+                    id: row.try_get("id")?,
+                    name: row.try_get("name")?,
+                    role: Role::parse(&role).ok_or_else(|| decode_error(format!("invalid token role {}", role)))?,
+                    created_at: row.try_get::<i64, _>("created_at")? as u64,
+                    created_by: row.try_get("created_by")?,
+                    hash: row.try_get("token_hash")?,
+                })
+            })
+            .collect()
+    }
+
+    pub async fn save_token(&self, token: &ApiToken) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO api_tokens (id, name, role, token_hash, created_at, created_by)
+             VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(token.id.as_str())
+        .bind(token.name.as_str())
+        .bind(token.role.as_str())
+        .bind(token.hash.as_str())
+        .bind(token.created_at as i64)
+        .bind(token.created_by.as_str())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete_token(&self, id: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM api_tokens WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn save_audit(&self, entry: &AuditEntry) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO audit_log (timestamp, actor, role, method, path, status, remote)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(entry.timestamp as i64)
+        .bind(entry.actor.as_str())
+        .bind(entry.role.as_str())
+        .bind(entry.method.as_str())
+        .bind(entry.path.as_str())
+        .bind(entry.status as i64)
+        .bind(entry.remote.as_str())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    // Latest entries of the audit log, newest first
+    pub async fn load_audit(&self, limit: u32) -> Result<Vec<AuditEntry>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT timestamp, actor, role, method, path, status, remote
+             FROM audit_log ORDER BY timestamp DESC LIMIT $1",
+        )
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.iter()
+            .map(|row| {
+                Ok(AuditEntry {
+                    timestamp: row.try_get::<i64, _>("timestamp")? as u64,
+                    actor: row.try_get("actor")?,
+                    role: row.try_get("role")?,
+                    method: row.try_get("method")?,
+                    path: row.try_get("path")?,
+                    status: row.try_get::<i64, _>("status")? as u16,
+                    remote: row.try_get("remote")?,
+                })
+            })
+            .collect()
+    }
+}
+
+fn collect_samples(rows: &[AnyRow], metrics: &mut HashMap<String, Metric>) -> Result<(), sqlx::Error> {
+    for row in rows {
+        let key: String = row.try_get("metric_key")?;
+        let value = MetricValue {
+            value: row.try_get("value")?,
+            timestamp: row.try_get::<i64, _>("timestamp")? as u64,
+        };
+        match metrics.get_mut(&key) {
+            Some(metric) => metric.values.push_back(value),
+            None => {
+                let metric = Metric {
+                    name: row.try_get("name")?,
+                    r#type: row.try_get("metric_type")?,
+                    tags: from_json(row, "tags")?,
+                    values: VecDeque::from([value]),
+                };
+                metrics.insert(key, metric);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn to_json<T: Serialize + ?Sized>(value: &T) -> Result<String, sqlx::Error> {
+    serde_json::to_string(value).map_err(|e| sqlx::Error::Protocol(format!("failed to encode document: {}", e)))
+}
+
+fn from_json<T: DeserializeOwned>(row: &AnyRow, column: &str) -> Result<T, sqlx::Error> {
+    let text: String = row.try_get(column)?;
+    serde_json::from_str(&text).map_err(|e| decode_error(format!("invalid {} document: {}", column, e)))
+}
+
+fn decode_error(msg: String) -> sqlx::Error {
+    sqlx::Error::Decode(msg.into())
+}
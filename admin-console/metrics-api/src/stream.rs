@@ -0,0 +1,206 @@
+// Live metric streams over WebSocket
+//
+// GET /metrics/stream upgrades to a WebSocket on which the new samples of
+// each ingestion step are pushed, so that the dashboards don't poll
+// GET /metrics. The server sends JSON text messages:
+//   {"type": "snapshot", "metrics": [...]}
+//       the latest sample of each matching series, on connect and after each
+//       change of the filter
+//   {"type": "update", "timestamp": t, "metrics": [...]}
+//       the new samples of the matching series at a step, if any
+//   {"type": "lagged", "skipped": n}
+//       n steps were skipped, the client not reading them fast enough
+//   {"type": "error", "error": "..."}
+//       a message of the client was not a filter
+// A series matches if its name contains one of the names, any name if none,
+// and it has all the tags. The filter is given by the `names` and `tags`
+// query parameters, `names=a,b&tags=key:value,...`, and changed by sending
+// {"names": [...], "tags": {"key": "value"}}.
+//
+// The server pings every HEARTBEAT_INTERVAL and closes the streams it has not
+// heard from for HEARTBEAT_TIMEOUT, pongs included. A stream keeps up to
+// UPDATE_BUFFER steps: the older ones are skipped when it falls behind, and
+// it is closed if a message can't be sent within SEND_TIMEOUT.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::stream::SplitSink;
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver;
+use tokio::time::{Instant, MissedTickBehavior};
+use warp::ws::{Message, WebSocket, Ws};
+use warp::Reply;
+
+use crate::ingest::StepUpdate;
+use crate::{latest_sample, Metric, MetricsStore};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(45);
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+// Largest message accepted from a client, a filter
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+// Close code of a stream too slow to read its messages, policy violation
+const CLOSE_TOO_SLOW: u16 = 1008;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct StreamQuery {
+    names: Option<String>,
+    tags: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Subscription {
+    #[serde(default)]
+    names: Vec<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+impl Subscription {
+    fn from_query(query: StreamQuery) -> Self {
+        let list = |s: Option<String>| -> Vec<String> {
+            s.unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+        Subscription {
+            names: list(query.names),
+            tags: list(query.tags)
+                .into_iter()
+                .map(|tag| match tag.split_once(':') {
+                    Some((key, value)) => (key.to_string(), value.to_string()),
+                    None => (tag, String::new()),
+                })
+                .collect(),
+        }
+    }
+
+    fn matches(&self, metric: &Metric) -> bool {
+        (self.names.is_empty() || self.names.iter().any(|name| metric.name.contains(name.as_str())))
+            && self.tags.iter().all(|(key, value)| metric.tags.get(key) == Some(value))
+    }
+}
+
+// Accept the WebSocket. A client which sent its token as the `bearer`
+// subprotocol gets that protocol selected, browsers refusing the connection
+// otherwise.
+pub fn upgrade(
+    ws: Ws,
+    protocols: Option<String>,
+    query: StreamQuery,
+    store: MetricsStore,
+    updates: Receiver<Arc<StepUpdate>>,
+) -> warp::reply::Response {
+    let subscription = Subscription::from_query(query);
+    let reply = ws
+        .max_message_size(MAX_MESSAGE_SIZE)
+        .on_upgrade(move |socket| serve(socket, subscription, store, updates));
+    let bearer = protocols.is_some_and(|p| p.split(',').any(|p| p.trim() == "bearer"));
+    if bearer {
+        warp::reply::with_header(reply, "sec-websocket-protocol", "bearer").into_response()
+    } else {
+        reply.into_response()
+    }
+}
+
+async fn serve(socket: WebSocket, mut subscription: Subscription, store: MetricsStore, mut updates: Receiver<Arc<StepUpdate>>) {
+    let (mut tx, mut rx) = socket.split();
+    if !send(&mut tx, snapshot(&store, &subscription)).await {
+        return;
+    }
+
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    heartbeat.tick().await;
+    let mut last_seen = Instant::now();
+    loop {
+        let message = tokio::select! {
+            received = rx.next() => {
+                let Some(Ok(received)) = received else {
+                    break;
+                };
+                last_seen = Instant::now();
+                if received.is_close() {
+                    break;
+                }
+                let Ok(text) = received.to_str() else {
+                    // pings and pongs
+                    continue;
+                };
+                match serde_json::from_str::<Subscription>(text) {
+                    Ok(new) => {
+                        subscription = new;
+                        snapshot(&store, &subscription)
+                    }
+                    Err(e) => serde_json::json!({"type": "error", "error": format!("invalid filter: {}", e)}),
+                }
+            }
+            update = updates.recv() => match update {
+                Ok(update) => {
+                    let metrics: Vec<&Metric> = update.metrics.iter().filter(|m| subscription.matches(m)).collect();
+                    if metrics.is_empty() {
+                        continue;
+                    }
+                    serde_json::json!({"type": "update", "timestamp": update.timestamp, "metrics": metrics})
+                }
+                Err(RecvError::Lagged(skipped)) => serde_json::json!({"type": "lagged", "skipped": skipped}),
+                Err(RecvError::Closed) => break,
+            },
+            _ = heartbeat.tick() => {
+                if last_seen.elapsed() > HEARTBEAT_TIMEOUT {
+                    log::debug!("closing a metric stream silent for {}s", last_seen.elapsed().as_secs());
+                    break;
+                }
+                if !send_message(&mut tx, Message::ping(Vec::new())).await {
+                    break;
+                }
+                continue;
+            }
+        };
+        if !send(&mut tx, message).await {
+            break;
+        }
+    }
+    let _ = tx.close().await;
+}
+
+fn snapshot(store: &MetricsStore, subscription: &Subscription) -> serde_json::Value {
+    let metrics: Vec<Metric> = store
+        .lock()
+        .unwrap()
+        .values()
+        .filter(|metric| subscription.matches(metric))
+        .map(latest_sample)
+        .collect();
+    serde_json::json!({"type": "snapshot", "metrics": metrics})
+}
+
+async fn send(tx: &mut SplitSink<WebSocket, Message>, message: serde_json::Value) -> bool {
+    send_message(tx, Message::text(message.to_string())).await
+}
+
+// Send a message, false if the stream is to be closed
+async fn send_message(tx: &mut SplitSink<WebSocket, Message>, message: Message) -> bool {
+    match tokio::time::timeout(SEND_TIMEOUT, tx.send(message)).await {
+        Ok(Ok(())) => true,
+        Ok(Err(e)) => {
+            log::debug!("failed to send to a metric stream: {}", e);
+            false
+        }
+        Err(_) => {
+            log::warn!("closing a metric stream not read for {}s", SEND_TIMEOUT.as_secs());
+            // the close frame may not get through either
+            let close = Message::close_with(CLOSE_TOO_SLOW, "too slow");
+            let _ = tokio::time::timeout(Duration::from_secs(1), tx.send(close)).await;
+            false
+        }
+    }
+}
@@ -0,0 +1,568 @@
+// Validation of the policy documents sent to the admin API
+//
+// A document is first checked against the schema of SecurityPolicy, so that a
+// missing field, a value of the wrong type or an unknown field is reported
+// with its path rather than as a deserialization error. Its values are then
+// checked: the enums, the URL categories, the CIDRs of the source networks,
+// the durations, rates, sizes, days and time ranges, the regex patterns, and
+// the user groups it targets, which must be the group of a user. All the
+// errors are reported, each with the path of its field, like
+// `spec.targets.source_networks[1]`.
+
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+use regex::Regex;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::SecurityPolicy;
+
+// The values of the enums of arcus-policy, named as it wants them; the
+// documents may use any case
+pub const POLICY_STATUSES: &[&str] = &["Active", "Inactive", "Draft", "Deprecated"];
+pub const PRIORITIES: &[&str] = &["Critical", "High", "Medium", "Low", "Default"];
+pub const ACTIONS: &[&str] = &["Allow", "Block", "Warn", "Inspect", "Quarantine", "Log"];
+pub const RULE_TYPES: &[&str] = &["Wildcard", "Regex", "Exact", "Domain", "Suffix"];
+pub const INSPECTION_MODES: &[&str] = &["Mitm", "Passthrough", "Selective"];
+pub const CERTIFICATE_GENERATIONS: &[&str] = &["Automatic", "Manual", "Hybrid"];
+pub const LOG_LEVELS: &[&str] = &["Minimal", "Standard", "Detailed", "Verbose"];
+pub const EXPORT_TYPES: &[&str] = &["Syslog", "Json", "Elasticsearch", "Splunk"];
+
+// URL categories offered by the policy editor
+pub const CATEGORIES: &[&str] = &[
+    "gambling",
+    "adult-content",
+    "malware",
+    "phishing",
+    "peer-to-peer",
+    "social-media",
+    "streaming",
+    "gaming",
+    "business-tools",
+    "productivity",
+    "news",
+    "education",
+];
+
+const WEEKDAYS: &[&str] = &["monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday"];
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+#[derive(Default)]
+struct Errors(Vec<FieldError>);
+
+impl Errors {
+    fn add(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.0.push(FieldError {
+            field: field.into(),
+            message: message.into(),
+        });
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    String,
+    Bool,
+    Integer,
+    Strings,
+    Object,
+    // of objects, whose fields are under `path[]`
+    List,
+}
+
+impl Kind {
+    fn matches(self, value: &Value) -> bool {
+        match self {
+            Kind::String => value.is_string(),
+            Kind::Bool => value.is_boolean(),
+            Kind::Integer => value.as_u64().is_some_and(|n| n <= u32::MAX as u64),
+            Kind::Strings => value.as_array().is_some_and(|items| items.iter().all(Value::is_string)),
+            Kind::Object => value.is_object(),
+            Kind::List => value.as_array().is_some_and(|items| items.iter().all(Value::is_object)),
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Kind::String => "a string",
+            Kind::Bool => "a boolean",
+            Kind::Integer => "a positive integer",
+            Kind::Strings => "a list of strings",
+            Kind::Object => "an object",
+            Kind::List => "a list of objects",
+        }
+    }
+}
+
+struct Field {
+    path: &'static str,
+    kind: Kind,
+    required: bool,
+}
+
+const fn req(path: &'static str, kind: Kind) -> Field {
+    Field { path, kind, required: true }
+}
+
+const fn opt(path: &'static str, kind: Kind) -> Field {
+    Field { path, kind, required: false }
+}
+
+// The fields of SecurityPolicy, a parent before its fields
+const SCHEMA: &[Field] = &[
+    req("api_version", Kind::String),
+    req("kind", Kind::String),
+    req("metadata", Kind::Object),
+    req("metadata.name", Kind::String),
+    req("metadata.version", Kind::String),
+    opt("metadata.description", Kind::String),
+    req("metadata.created_at", Kind::String),
+    req("metadata.updated_at", Kind::String),
+    req("metadata.created_by", Kind::String),
+    req("metadata.tags", Kind::Strings),
+    req("metadata.status", Kind::String),
+    req("spec", Kind::Object),
+    req("spec.priority", Kind::String),
+    req("spec.enabled", Kind::Bool),
+    req("spec.targets", Kind::Object),
+    req("spec.targets.user_groups", Kind::Strings),
+    req("spec.targets.users", Kind::Strings),
+    req("spec.targets.source_networks", Kind::Strings),
+    opt("spec.url_filtering", Kind::Object),
+    req("spec.url_filtering.categories", Kind::Object),
+    req("spec.url_filtering.categories.block", Kind::Strings),
+    req("spec.url_filtering.categories.warn", Kind::Strings),
+    req("spec.url_filtering.categories.allow", Kind::Strings),
+    req("spec.url_filtering.custom_rules", Kind::List),
+    req("spec.url_filtering.custom_rules[].name", Kind::String),
+    req("spec.url_filtering.custom_rules[].action", Kind::String),
+    opt("spec.url_filtering.custom_rules[].pattern", Kind::String),
+    opt("spec.url_filtering.custom_rules[].patterns", Kind::Strings),
+    req("spec.url_filtering.custom_rules[].rule_type", Kind::String),
+    opt("spec.url_filtering.custom_rules[].message", Kind::String),
+    opt("spec.url_filtering.custom_rules[].priority", Kind::Integer),
+    opt("spec.content_security", Kind::Object),
+    opt("spec.content_security.malware_scanning", Kind::Object),
+    req("spec.content_security.malware_scanning.enabled", Kind::Bool),
+    opt("spec.content_security.malware_scanning.icap_server", Kind::String),
+    req("spec.content_security.malware_scanning.action", Kind::String),
+    opt("spec.content_security.malware_scanning.timeout", Kind::String),
+    opt("spec.content_security.data_loss_prevention", Kind::Object),
+    req("spec.content_security.data_loss_prevention.enabled", Kind::Bool),
+    req("spec.content_security.data_loss_prevention.scan_uploads", Kind::Bool),
+    req("spec.content_security.data_loss_prevention.scan_downloads", Kind::Bool),
+    req("spec.content_security.data_loss_prevention.sensitive_data_patterns", Kind::List),
+    req("spec.content_security.data_loss_prevention.sensitive_data_patterns[].name", Kind::String),
+    opt("spec.content_security.data_loss_prevention.sensitive_data_patterns[].pattern", Kind::String),
+    opt("spec.content_security.data_loss_prevention.sensitive_data_patterns[].keywords", Kind::Strings),
+    req("spec.content_security.data_loss_prevention.sensitive_data_patterns[].action", Kind::String),
+    opt("spec.traffic_control", Kind::Object),
+    opt("spec.traffic_control.bandwidth_limits", Kind::Object),
+    opt("spec.traffic_control.bandwidth_limits.per_user", Kind::String),
+    opt("spec.traffic_control.bandwidth_limits.total", Kind::String),
+    opt("spec.traffic_control.quotas", Kind::Object),
+    opt("spec.traffic_control.quotas.daily_data_per_user", Kind::String),
+    opt("spec.traffic_control.quotas.monthly_data_per_user", Kind::String),
+    opt("spec.traffic_control.time_restrictions", Kind::Object),
+    opt("spec.traffic_control.time_restrictions.work_hours", Kind::Object),
+    req("spec.traffic_control.time_restrictions.work_hours.days", Kind::Strings),
+    req("spec.traffic_control.time_restrictions.work_hours.time_range", Kind::String),
+    req("spec.traffic_control.time_restrictions.work_hours.timezone", Kind::String),
+    req("spec.traffic_control.time_restrictions.work_hours.policies", Kind::Strings),
+    opt("spec.traffic_control.time_restrictions.after_hours", Kind::Object),
+    req("spec.traffic_control.time_restrictions.after_hours.days", Kind::Strings),
+    req("spec.traffic_control.time_restrictions.after_hours.time_range", Kind::String),
+    req("spec.traffic_control.time_restrictions.after_hours.timezone", Kind::String),
+    req("spec.traffic_control.time_restrictions.after_hours.policies", Kind::Strings),
+    opt("spec.https_inspection", Kind::Object),
+    req("spec.https_inspection.enabled", Kind::Bool),
+    req("spec.https_inspection.mode", Kind::String),
+    req("spec.https_inspection.certificate_generation", Kind::String),
+    opt("spec.https_inspection.ca_certificate", Kind::String),
+    opt("spec.https_inspection.ca_private_key", Kind::String),
+    req("spec.https_inspection.bypass_domains", Kind::Strings),
+    req("spec.https_inspection.inspect_domains", Kind::Strings),
+    opt("spec.audit", Kind::Object),
+    req("spec.audit.enabled", Kind::Bool),
+    req("spec.audit.log_level", Kind::String),
+    req("spec.audit.retention", Kind::String),
+    req("spec.audit.export_targets", Kind::List),
+    req("spec.audit.export_targets[].target_type", Kind::String),
+    req("spec.audit.export_targets[].endpoint", Kind::String),
+    opt("spec.audit.export_targets[].format", Kind::String),
+    opt("spec.audit.export_targets[].authentication", Kind::Object),
+    req("spec.audit.export_targets[].authentication.auth_type", Kind::String),
+    opt("spec.audit.export_targets[].authentication.token", Kind::String),
+    opt("spec.audit.export_targets[].authentication.username", Kind::String),
+    opt("spec.audit.export_targets[].authentication.password", Kind::String),
+];
+
+// Check a policy document, the groups being those of the users
+pub fn validate(document: Value, groups: &HashSet<String>) -> Result<SecurityPolicy, Vec<FieldError>> {
+    let mut errors = Errors::default();
+    check_schema(&document, &mut errors);
+    if !errors.0.is_empty() {
+        return Err(errors.0);
+    }
+    let policy: SecurityPolicy = serde_json::from_value(document).map_err(|e| {
+        vec![FieldError {
+            field: String::new(),
+            message: e.to_string(),
+        }]
+    })?;
+    check_policy(&policy, groups, &mut errors);
+    if errors.0.is_empty() {
+        Ok(policy)
+    } else {
+        Err(errors.0)
+    }
+}
+
+fn check_schema(document: &Value, errors: &mut Errors) {
+    if !document.is_object() {
+        errors.add("", "should be an object");
+        return;
+    }
+    for field in SCHEMA {
+        let (parent, name) = split_path(field.path);
+        for (at, object) in objects(document, parent) {
+            let path = join(&at, name);
+            match object.get(name) {
+                None | Some(Value::Null) => {
+                    if field.required {
+                        errors.add(path, "is required");
+                    }
+                }
+                Some(value) if !field.kind.matches(value) => {
+                    errors.add(path, format!("should be {}", field.kind.describe()))
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    // serde would silently drop the misspelled fields
+    let containers = SCHEMA.iter().filter_map(|field| match field.kind {
+        Kind::Object => Some(field.path.to_string()),
+        Kind::List => Some(format!("{}[]", field.path)),
+        _ => None,
+    });
+    for container in std::iter::once(String::new()).chain(containers) {
+        let known: Vec<&str> = SCHEMA
+            .iter()
+            .map(|field| split_path(field.path))
+            .filter(|(parent, _)| *parent == container)
+            .map(|(_, name)| name)
+            .collect();
+        for (at, object) in objects(document, &container) {
+            for key in object.keys().filter(|key| !known.contains(&key.as_str())) {
+                errors.add(join(&at, key), "unknown field");
+            }
+        }
+    }
+}
+
+fn split_path(path: &str) -> (&str, &str) {
+    path.rsplit_once('.').unwrap_or(("", path))
+}
+
+fn join(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", parent, name)
+    }
+}
+
+// The objects at a path of the schema with their own path, `[]` going through
+// each item of a list. The missing ones and those of the wrong type are left
+// out, they are reported by the checks of their fields.
+fn objects<'a>(document: &'a Value, path: &str) -> Vec<(String, &'a Map<String, Value>)> {
+    let mut found = vec![(String::new(), document.as_object().unwrap())];
+    for segment in path.split('.').filter(|s| !s.is_empty()) {
+        let (key, each) = match segment.strip_suffix("[]") {
+            Some(key) => (key, true),
+            None => (segment, false),
+        };
+        let mut next = Vec::new();
+        for (at, object) in found {
+            let at = join(&at, key);
+            match object.get(key) {
+                Some(Value::Array(items)) if each => {
+                    for (i, item) in items.iter().enumerate() {
+                        if let Value::Object(item) = item {
+                            next.push((format!("{}[{}]", at, i), item));
+                        }
+                    }
+                }
+                Some(Value::Object(child)) if !each => next.push((at, child)),
+                _ => {}
+            }
+        }
+        found = next;
+    }
+    found
+}
+
+fn check_policy(policy: &SecurityPolicy, groups: &HashSet<String>, errors: &mut Errors) {
+    if policy.api_version != "arcus.v1" {
+        errors.add("api_version", "should be arcus.v1");
+    }
+    if policy.kind != "SecurityPolicy" {
+        errors.add("kind", "should be SecurityPolicy");
+    }
+    if policy.metadata.name.trim().is_empty() {
+        errors.add("metadata.name", "should not be empty");
+    }
+    one_of(errors, "metadata.status", &policy.metadata.status, POLICY_STATUSES);
+
+    let spec = &policy.spec;
+    one_of(errors, "spec.priority", &spec.priority, PRIORITIES);
+    for (i, group) in spec.targets.user_groups.iter().enumerate() {
+        if !groups.contains(group) {
+            errors.add(format!("spec.targets.user_groups[{}]", i), format!("no user is in group {}", group));
+        }
+    }
+    for (i, network) in spec.targets.source_networks.iter().enumerate() {
+        if !is_cidr(network) {
+            errors.add(format!("spec.targets.source_networks[{}]", i), format!("invalid CIDR {}", network));
+        }
+    }
+
+    if let Some(url_filtering) = &spec.url_filtering {
+        let categories = &url_filtering.categories;
+        for (list, names) in [("block", &categories.block), ("warn", &categories.warn), ("allow", &categories.allow)] {
+            for (i, category) in names.iter().enumerate() {
+                if !CATEGORIES.contains(&category.as_str()) {
+                    errors.add(
+                        format!("spec.url_filtering.categories.{}[{}]", list, i),
+                        format!("unknown category {}, expected one of {}", category, CATEGORIES.join(", ")),
+                    );
+                }
+            }
+        }
+        for (i, rule) in url_filtering.custom_rules.iter().enumerate() {
+            let at = format!("spec.url_filtering.custom_rules[{}]", i);
+            one_of(errors, &format!("{}.action", at), &rule.action, ACTIONS);
+            one_of(errors, &format!("{}.rule_type", at), &rule.rule_type, RULE_TYPES);
+            let is_regex = rule.rule_type.eq_ignore_ascii_case("regex");
+            if let Some(pattern) = &rule.pattern {
+                check_pattern(errors, &format!("{}.pattern", at), pattern, is_regex);
+            }
+            for (j, pattern) in rule.patterns.iter().flatten().enumerate() {
+                check_pattern(errors, &format!("{}.patterns[{}]", at, j), pattern, is_regex);
+            }
+            if rule.pattern.is_none() && rule.patterns.as_ref().is_none_or(|p| p.is_empty()) {
+                errors.add(format!("{}.pattern", at), "a pattern or patterns is required");
+            }
+        }
+    }
+
+    if let Some(content_security) = &spec.content_security {
+        if let Some(scanning) = &content_security.malware_scanning {
+            let at = "spec.content_security.malware_scanning";
+            one_of(errors, &format!("{}.action", at), &scanning.action, ACTIONS);
+            if let Some(server) = set(&scanning.icap_server) {
+                if !server.starts_with("icap://") && !server.starts_with("icaps://") {
+                    errors.add(format!("{}.icap_server", at), format!("invalid ICAP URL {}", server));
+                }
+            }
+            if let Some(timeout) = set(&scanning.timeout) {
+                check_quantity(errors, &format!("{}.timeout", at), timeout, DURATION_UNITS, "duration");
+            }
+        }
+        if let Some(dlp) = &content_security.data_loss_prevention {
+            for (i, pattern) in dlp.sensitive_data_patterns.iter().enumerate() {
+                let at = format!("spec.content_security.data_loss_prevention.sensitive_data_patterns[{}]", i);
+                one_of(errors, &format!("{}.action", at), &pattern.action, ACTIONS);
+                if let Some(regex) = &pattern.pattern {
+                    check_pattern(errors, &format!("{}.pattern", at), regex, true);
+                }
+                if pattern.pattern.is_none() && pattern.keywords.as_ref().is_none_or(|k| k.is_empty()) {
+                    errors.add(format!("{}.pattern", at), "a pattern or keywords is required");
+                }
+            }
+        }
+    }
+
+    if let Some(traffic_control) = &spec.traffic_control {
+        if let Some(limits) = &traffic_control.bandwidth_limits {
+            for (name, value) in [("per_user", &limits.per_user), ("total", &limits.total)] {
+                if let Some(value) = set(value) {
+                    let field = format!("spec.traffic_control.bandwidth_limits.{}", name);
+                    check_quantity(errors, &field, value, RATE_UNITS, "rate");
+                }
+            }
+        }
+        if let Some(quotas) = &traffic_control.quotas {
+            let sizes = [
+                ("daily_data_per_user", &quotas.daily_data_per_user),
+                ("monthly_data_per_user", &quotas.monthly_data_per_user),
+            ];
+            for (name, value) in sizes {
+                if let Some(value) = set(value) {
+                    check_quantity(errors, &format!("spec.traffic_control.quotas.{}", name), value, SIZE_UNITS, "size");
+                }
+            }
+        }
+        if let Some(restrictions) = &traffic_control.time_restrictions {
+            let windows = [("work_hours", &restrictions.work_hours), ("after_hours", &restrictions.after_hours)];
+            for (name, window) in windows {
+                let Some(window) = window else {
+                    continue;
+                };
+                let at = format!("spec.traffic_control.time_restrictions.{}", name);
+                for (i, day) in window.days.iter().enumerate() {
+                    if !is_days(day) {
+                        errors.add(format!("{}.days[{}]", at, i), format!("invalid day {}", day));
+                    }
+                }
+                if let Err(e) = check_time_range(&window.time_range) {
+                    errors.add(format!("{}.time_range", at), e);
+                }
+                if !is_timezone(&window.timezone) {
+                    errors.add(format!("{}.timezone", at), format!("invalid timezone {}", window.timezone));
+                }
+            }
+        }
+    }
+
+    if let Some(inspection) = &spec.https_inspection {
+        let at = "spec.https_inspection";
+        one_of(errors, &format!("{}.mode", at), &inspection.mode, INSPECTION_MODES);
+        one_of(
+            errors,
+            &format!("{}.certificate_generation", at),
+            &inspection.certificate_generation,
+            CERTIFICATE_GENERATIONS,
+        );
+        if inspection.certificate_generation.eq_ignore_ascii_case("manual") {
+            for (name, value) in [("ca_certificate", &inspection.ca_certificate), ("ca_private_key", &inspection.ca_private_key)] {
+                if set(value).is_none() {
+                    errors.add(format!("{}.{}", at, name), "is required with manual certificate generation");
+                }
+            }
+        }
+    }
+
+    if let Some(audit) = &spec.audit {
+        one_of(errors, "spec.audit.log_level", &audit.log_level, LOG_LEVELS);
+        check_quantity(errors, "spec.audit.retention", &audit.retention, DURATION_UNITS, "duration");
+        for (i, target) in audit.export_targets.iter().enumerate() {
+            let at = format!("spec.audit.export_targets[{}]", i);
+            one_of(errors, &format!("{}.target_type", at), &target.target_type, EXPORT_TYPES);
+            if target.endpoint.trim().is_empty() {
+                errors.add(format!("{}.endpoint", at), "should not be empty");
+            }
+        }
+    }
+}
+
+// An optional value, the console sending the unset ones as empty strings
+fn set(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|v| !v.is_empty())
+}
+
+fn one_of(errors: &mut Errors, field: &str, value: &str, variants: &[&str]) {
+    if !variants.iter().any(|v| v.eq_ignore_ascii_case(value)) {
+        let expected: Vec<String> = variants.iter().map(|v| v.to_lowercase()).collect();
+        errors.add(field, format!("unknown value {}, expected one of {}", value, expected.join(", ")));
+    }
+}
+
+fn check_pattern(errors: &mut Errors, field: &str, pattern: &str, is_regex: bool) {
+    if pattern.trim().is_empty() {
+        errors.add(field, "should not be empty");
+    } else if is_regex {
+        if let Err(e) = Regex::new(pattern) {
+            errors.add(field, format!("invalid regex: {}", e));
+        }
+    }
+}
+
+const DURATION_UNITS: &[&str] = &["ms", "s", "m", "h", "d", "w"];
+const RATE_UNITS: &[&str] = &["bps", "kbps", "mbps", "gbps"];
+const SIZE_UNITS: &[&str] = &["b", "kb", "mb", "gb", "tb", "kib", "mib", "gib", "tib"];
+
+// A number and one of the units, like `30s`, `100Mbps` or `5GB`
+fn check_quantity(errors: &mut Errors, field: &str, value: &str, units: &[&str], what: &str) {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let valid = number.parse::<f64>().is_ok_and(|n| n > 0.0)
+        && units.iter().any(|u| u.eq_ignore_ascii_case(unit.trim()));
+    if !valid {
+        errors.add(
+            field,
+            format!("invalid {} {}, expected a number and one of {}", what, value, units.join(", ")),
+        );
+    }
+}
+
+// An address with an optional prefix length
+fn is_cidr(network: &str) -> bool {
+    let (addr, prefix) = match network.split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (network, None),
+    };
+    let Ok(addr) = addr.parse::<IpAddr>() else {
+        return false;
+    };
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    prefix.is_none_or(|p| p.parse::<u8>().is_ok_and(|p| p <= max))
+}
+
+// The day names, ranges and keywords arcus-policy accepts
+fn is_days(day: &str) -> bool {
+    let day = day.trim().to_ascii_lowercase();
+    let is_weekday = |d: &str| WEEKDAYS.iter().any(|w| *w == d || w[..3] == *d);
+    match day.as_str() {
+        "daily" | "all" | "*" | "weekdays" | "weekend" | "weekends" => true,
+        _ => match day.split_once('-') {
+            Some((from, to)) => is_weekday(from.trim()) && is_weekday(to.trim()),
+            None => is_weekday(&day),
+        },
+    }
+}
+
+fn check_time_range(range: &str) -> Result<(), String> {
+    let (start, end) = range
+        .split_once('-')
+        .ok_or_else(|| format!("invalid time range {}, expected HH:MM-HH:MM", range))?;
+    let (start, end) = (parse_time(start)?, parse_time(end)?);
+    if start == end || start == 24 * 3600 {
+        return Err(format!("empty time range {}", range));
+    }
+    Ok(())
+}
+
+// Seconds since midnight of HH:MM or HH:MM:SS, 24:00 being the end of the day
+fn parse_time(time: &str) -> Result<u32, String> {
+    let time = time.trim();
+    if time == "24:00" {
+        return Ok(24 * 3600);
+    }
+    let parts: Vec<&str> = time.split(':').collect();
+    let numbers: Option<Vec<u32>> = parts.iter().map(|p| p.parse().ok().filter(|_| p.len() == 2)).collect();
+    match numbers.as_deref() {
+        Some([h, m]) if *h < 24 && *m < 60 => Ok(h * 3600 + m * 60),
+        Some([h, m, s]) if *h < 24 && *m < 60 && *s < 60 => Ok(h * 3600 + m * 60 + s),
+        _ => Err(format!("invalid time {}, expected HH:MM", time)),
+    }
+}
+
+// UTC, or an IANA name like Europe/Paris; empty is UTC
+fn is_timezone(timezone: &str) -> bool {
+    let timezone = timezone.trim();
+    matches!(timezone, "" | "UTC" | "GMT")
+        || (timezone.contains('/')
+            && timezone
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '_' | '-' | '+')))
+}
@@ -1,16 +1,71 @@
 const API_BASE_URL = process.env.NEXT_PUBLIC_API_URL || 'http://localhost:3001';
+const TOKEN_STORAGE_KEY = 'arcus-admin-token';
+
+export type Role = 'viewer' | 'policy-editor' | 'admin';
+
+// Error of a field of a rejected policy, like spec.targets.source_networks[1]
+export interface FieldError {
+  field: string;
+  message: string;
+}
 
 export interface ApiResponse<T> {
   data?: T;
   error?: string;
   message?: string;
+  fieldErrors?: FieldError[];
+}
+
+// Series whose name contains one of the names, any if none, with all the tags
+export interface MetricFilter {
+  names?: string[];
+  tags?: Record<string, string>;
 }
 
+// Filter, sort and page of a list, see the API Endpoints of the README
+export interface ListParams {
+  filter?: string;
+  sort?: string;
+  page?: number;
+  per_page?: number;
+  cursor?: string;
+}
+
+export type MetricStreamMessage =
+  | { type: 'snapshot'; metrics: any[] }
+  | { type: 'update'; timestamp: number; metrics: any[] }
+  | { type: 'lagged'; skipped: number }
+  | { type: 'error'; error: string };
+
 export class ApiClient {
   private baseUrl: string;
+  private token?: string;
 
-  constructor(baseUrl: string = API_BASE_URL) {
+  constructor(baseUrl: string = API_BASE_URL, token?: string) {
     this.baseUrl = baseUrl;
+    this.token = token ?? ApiClient.savedToken();
+  }
+
+  // Token of the API, kept in the local storage of the browser
+  private static savedToken(): string | undefined {
+    if (typeof window !== 'undefined') {
+      const token = window.localStorage.getItem(TOKEN_STORAGE_KEY);
+      if (token) {
+        return token;
+      }
+    }
+    return process.env.NEXT_PUBLIC_ADMIN_TOKEN || undefined;
+  }
+
+  setToken(token?: string) {
+    this.token = token;
+    if (typeof window !== 'undefined') {
+      if (token) {
+        window.localStorage.setItem(TOKEN_STORAGE_KEY, token);
+      } else {
+        window.localStorage.removeItem(TOKEN_STORAGE_KEY);
+      }
+    }
   }
 
   private async request<T>(
@@ -20,13 +75,18 @@ export class ApiClient {
     try {
       const url = `${this.baseUrl}${endpoint}`;
       const response = await fetch(url, {
+        ...options,
         headers: {
           'Content-Type': 'application/json',
+          ...(this.token ? { Authorization: `Bearer ${this.token}` } : {}),
           ...options.headers,
         },
-        ...options,
       });
 
+      if (response.status === 422) {
+        const body = await response.json();
+        return { error: body.error, fieldErrors: body.errors };
+      }
       if (!response.ok) {
         throw new Error(`HTTP error! status: ${response.status}`);
       }
@@ -41,18 +101,66 @@ export class ApiClient {
     }
   }
 
+  private static listQuery(params: ListParams) {
+    const query = new URLSearchParams();
+    for (const [key, value] of Object.entries(params)) {
+      if (value !== undefined && value !== '') {
+        query.set(key, String(value));
+      }
+    }
+    const text = query.toString();
+    return text ? `?${text}` : '';
+  }
+
   // Metrics API
-  async getMetrics() {
-    return this.request('/metrics');
+  async getMetrics(params: ListParams = {}) {
+    return this.request(`/metrics${ApiClient.listQuery(params)}`);
   }
 
   async getMetric(name: string) {
     return this.request(`/metrics/${name}`);
   }
 
+  // Samples over a time range, in unix seconds, one per step if given
+  async getMetricHistory(name: string, range: { from?: number; to?: number; step?: number } = {}) {
+    const params = new URLSearchParams();
+    for (const [key, value] of Object.entries(range)) {
+      if (value !== undefined) {
+        params.set(key, String(value));
+      }
+    }
+    const query = params.toString();
+    return this.request(`/metrics/${name}/history${query ? `?${query}` : ''}`);
+  }
+
+  // Live metric updates: a snapshot, then the new samples of each step.
+  // Browsers can't set the Authorization header of a WebSocket, the token
+  // goes as the subprotocol following `bearer`.
+  streamMetrics(
+    filter: MetricFilter,
+    onMessage: (message: MetricStreamMessage) => void
+  ): WebSocket {
+    const params = new URLSearchParams();
+    if (filter.names?.length) {
+      params.set('names', filter.names.join(','));
+    }
+    if (filter.tags) {
+      params.set('tags', Object.entries(filter.tags).map(([key, value]) => `${key}:${value}`).join(','));
+    }
+    const url = `${this.baseUrl.replace(/^http/, 'ws')}/metrics/stream?${params}`;
+    const socket = new WebSocket(url, this.token ? ['bearer', this.token] : undefined);
+    socket.onmessage = (event) => onMessage(JSON.parse(event.data));
+    return socket;
+  }
+
+  // Change the filter of an open stream, answered by a new snapshot
+  setMetricFilter(socket: WebSocket, filter: MetricFilter) {
+    socket.send(JSON.stringify({ names: filter.names ?? [], tags: filter.tags ?? {} }));
+  }
+
   // Policies API
-  async getPolicies() {
-    return this.request('/policies');
+  async getPolicies(params: ListParams = {}) {
+    return this.request(`/policies${ApiClient.listQuery(params)}`);
   }
 
   async getPolicy(id: string) {
@@ -66,22 +174,65 @@ export class ApiClient {
     });
   }
 
-  async updatePolicy(id: string, policy: any) {
+  async updatePolicy(id: string, policy: any, version?: number) {
     return this.request(`/policies/${id}`, {
       method: 'PUT',
+      headers: this.versionHeaders(version),
       body: JSON.stringify(policy),
     });
   }
 
-  async deletePolicy(id: string) {
+  async deletePolicy(id: string, version?: number) {
     return this.request(`/policies/${id}`, {
       method: 'DELETE',
+      headers: this.versionHeaders(version),
+    });
+  }
+
+  async getPolicyVersions(id: string) {
+    return this.request(`/policies/${id}/versions`);
+  }
+
+  async getPolicyVersion(id: string, version: number) {
+    return this.request(`/policies/${id}/versions/${version}`);
+  }
+
+  async rollbackPolicy(id: string, version: number, currentVersion?: number) {
+    return this.request(`/policies/${id}/rollback`, {
+      method: 'POST',
+      headers: this.versionHeaders(currentVersion),
+      body: JSON.stringify({ version }),
+    });
+  }
+
+  // The change is refused with 412 if the policy is no longer at this version
+  private versionHeaders(version?: number): Record<string, string> {
+    const headers: Record<string, string> = { 'Content-Type': 'application/json' };
+    if (version !== undefined) {
+      headers['If-Match'] = `"${version}"`;
+    }
+    return headers;
+  }
+
+  // Deployments API, the policy changes pushed to the g3icap and g3proxy nodes
+  async getDeployments() {
+    return this.request('/deployments');
+  }
+
+  async getDeployment(id: string) {
+    return this.request(`/deployments/${id}`);
+  }
+
+  // Deploy the current policies again, answers the id of the deployment
+  async deploy() {
+    return this.request('/deployments', {
+      method: 'POST',
     });
   }
 
   // Users API
-  async getUsers() {
-    return this.request('/users');
+  async getUsers(params: ListParams = {}) {
+    return this.request(`/users${ApiClient.listQuery(params)}`);
   }
 
   async getUser(id: string) {
@@ -108,6 +259,51 @@ export class ApiClient {
     });
   }
 
+  // Directory sync API
+  async getDirectoryStatus() {
+    return this.request('/directory');
+  }
+
+  async syncDirectory() {
+    return this.request('/directory/sync', {
+      method: 'POST',
+    });
+  }
+
+  // Let the sync import a directory user deleted here again
+  async restoreDirectoryUser(externalId: string) {
+    return this.request(`/directory/deleted/${encodeURIComponent(externalId)}`, {
+      method: 'DELETE',
+    });
+  }
+
+  // Tokens API, for admin tokens only but whoami
+  async whoami() {
+    return this.request('/auth/whoami');
+  }
+
+  async getTokens() {
+    return this.request('/tokens');
+  }
+
+  // The secret of the token is only returned by this call
+  async createToken(name: string, role: Role) {
+    return this.request('/tokens', {
+      method: 'POST',
+      body: JSON.stringify({ name, role }),
+    });
+  }
+
+  async deleteToken(id: string) {
+    return this.request(`/tokens/${id}`, {
+      method: 'DELETE',
+    });
+  }
+
+  async getAuditLog(limit?: number) {
+    return this.request(limit === undefined ? '/audit' : `/audit?limit=${limit}`);
+  }
+
   // Health check
   async healthCheck() {
     return this.request('/health');
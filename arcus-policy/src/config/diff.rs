@@ -0,0 +1,143 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2025 ByteDance and/or its affiliates.
+ */
+
+//! Line diff of the generated configs against the deployed ones
+
+/// Lines of context around the changes
+const CONTEXT: usize = 3;
+
+/// Changes of a generated file, in unified diff format
+#[derive(Debug, Clone)]
+pub struct FileDiff {
+    pub name: String,
+    pub diff: String,
+}
+
+/// Changes of all the generated files
+#[derive(Debug, Clone, Default)]
+pub struct ConfigDiff {
+    pub files: Vec<FileDiff>,
+}
+
+impl ConfigDiff {
+    /// Check if the deployed files are up to date
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+enum Op<'a> {
+    Equal(&'a str),
+    Delete(&'a str),
+    Insert(&'a str),
+}
+
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Op<'a>> {
+    let (n, m) = (old.len(), new.len());
+    // longest common subsequence of the suffixes
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::with_capacity(n.max(m));
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old[i] == new[j] {
+            ops.push(Op::Equal(old[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            ops.push(Op::Delete(old[i]));
+            i += 1;
+        } else {
+            ops.push(Op::Insert(new[j]));
+            j += 1;
+        }
+    }
+    ops.extend(old[i..].iter().map(|l| Op::Delete(l)));
+    ops.extend(new[j..].iter().map(|l| Op::Insert(l)));
+    ops
+}
+
+fn hunk_range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, len),
+    }
+}
+
+/// Diff the old and new content of a file, `None` if they are the same
+pub fn unified_diff(old: &str, new: &str, name: &str) -> Option<String> {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let ops = diff_lines(&old_lines, &new_lines);
+
+    let changes: Vec<usize> = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| !matches!(op, Op::Equal(_)))
+        .map(|(i, _)| i)
+        .collect();
+    if changes.is_empty() {
+        return None;
+    }
+
+    // old and new line numbers before each op
+    let mut positions = Vec::with_capacity(ops.len() + 1);
+    let (mut o, mut n) = (0, 0);
+    for op in &ops {
+        positions.push((o, n));
+        match op {
+            Op::Equal(_) => {
+                o += 1;
+                n += 1;
+            }
+            Op::Delete(_) => o += 1,
+            Op::Insert(_) => n += 1,
+        }
+    }
+    positions.push((o, n));
+
+    let mut out = format!("--- a/{name}\n+++ b/{name}\n");
+    let mut k = 0;
+    while k < changes.len() {
+        let start = changes[k].saturating_sub(CONTEXT);
+        let mut last = changes[k];
+        k += 1;
+        // close changes share a hunk
+        while k < changes.len() && changes[k] - last - 1 <= 2 * CONTEXT {
+            last = changes[k];
+            k += 1;
+        }
+        let end = (last + CONTEXT + 1).min(ops.len());
+
+        let (old_start, new_start) = positions[start];
+        let (old_end, new_end) = positions[end];
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(old_start, old_end - old_start),
+            hunk_range(new_start, new_end - new_start)
+        ));
+        for op in &ops[start..end] {
+            let (prefix, line) = match op {
+                Op::Equal(l) => (' ', l),
+                Op::Delete(l) => ('-', l),
+                Op::Insert(l) => ('+', l),
+            };
+            out.push(prefix);
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    Some(out)
+}
@@ -8,64 +8,61 @@
 use serde::{Deserialize, Serialize};
 
 /// Escaper configuration
+///
+/// Only the keys used by the generated escapers are supported. The match
+/// rules are for the `route_upstream` and `route_client` escapers.
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct EscaperConfig {
     pub name: String,
+    #[serde(rename = "type")]
     pub escaper_type: String,
-    pub rules: Vec<RoutingRule>,
-    pub default_next: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub resolver: Option<String>,
-    pub upstream: Option<String>,
-    pub icap_service: Option<String>,
-    pub next: Option<String>,
-    pub message: Option<String>,
-    pub exact_match: Option<Vec<ExactMatchRule>>,
-    pub regex_match: Option<Vec<RegexMatchRule>>,
-    pub child_match: Option<Vec<ChildMatchRule>>,
-    pub subnet_match: Option<Vec<SubnetMatchRule>>,
-    pub suffix_match: Option<Vec<SuffixMatchRule>>,
-}
-
-/// Routing rule
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct RoutingRule {
-    pub rule_type: String,
-    pub pattern: Option<String>,
-    pub next: String,
-    pub priority: u32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exact_match: Vec<ExactMatchRule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub child_match: Vec<ChildMatchRule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub suffix_match: Vec<SuffixMatchRule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub regex_match: Vec<RegexMatchRule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subnet_match: Vec<SubnetMatchRule>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_next: Option<String>,
 }
 
 /// Exact match rule
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ExactMatchRule {
-    pub hosts: Vec<String>,
     pub next: String,
+    pub hosts: Vec<String>,
 }
 
 /// Regex match rule
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RegexMatchRule {
-    pub pattern: String,
     pub next: String,
+    pub rules: Vec<String>,
 }
 
-/// Child match rule (wildcard/domain matching)
+/// Child match rule (wildcard matching)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChildMatchRule {
-    pub domains: Vec<String>,
     pub next: String,
+    pub domains: Vec<String>,
 }
 
-/// Subnet match rule
+/// Subnet match rule, on the upstream or the client address
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SubnetMatchRule {
-    pub subnets: Vec<String>,
     pub next: String,
+    pub subnets: Vec<String>,
 }
 
-/// Suffix match rule
+/// Suffix match rule (domain matching)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SuffixMatchRule {
-    pub suffixes: Vec<String>,
     pub next: String,
+    pub suffixes: Vec<String>,
 }
@@ -3,14 +3,108 @@
  * Copyright 2025 ByteDance and/or its affiliates.
  */
 
-//! Configuration generator for translating policies to G3proxy config
+//! Configuration generator for translating policies to G3proxy and G3ICAP config
+//!
+//! G3proxy routes on the upstream host: the URL filtering rules of the
+//! policies become `route_upstream` match rules leading to a deny escaper or
+//! to the internet. The policies targeting source networks get their own
+//! route, selected by a `route_client` escaper on the client address, and
+//! the ones targeting user groups get their own route and HTTP proxy, as a
+//! G3proxy server has a single user group. All of them fall back to the
+//! route of the untargeted policies. The content is inspected by G3ICAP,
+//! wired through the ICAP services of the auditor, and which compiles the
+//! same policy files into its module configs.
+//!
+//! Policies are applied by decreasing priority, then by name, a pattern
+//! already routed by a policy of higher priority being skipped. What can not
+//! be expressed in the G3proxy config is reported in the notes.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use anyhow::Result;
+use tracing::{info, debug};
+
+use crate::policy::{SecurityPolicy, PolicyCollection, PolicyAction, RuleType, CustomRule, HttpsMode};
+use super::diff::{self, ConfigDiff, FileDiff};
+use super::escaper::{ChildMatchRule, ExactMatchRule, RegexMatchRule, SubnetMatchRule, SuffixMatchRule};
+use super::user_group::UserGroupSource;
+use super::{ConfigContext, GeneratedConfig, RuntimeConfig, StatConfig, ResolverConfig, AuditorConfig, ServerConfig, ServerListen, TlsServerConfig, CertPair, StatTarget, EscaperConfig, UserGroupConfig, IcapGeneratedConfig, IcapServerConfig, IcapPipelinesConfig};
+
+const CLIENT_ROUTER: &str = "client-router";
+const MAIN_ROUTER: &str = "main-router";
+const DENY: &str = "deny";
+const INTERNET_ACCESS: &str = "internet_access";
+
+const REQMOD_SERVICE: &str = "reqmod";
+const RESPMOD_SERVICE: &str = "respmod";
+
+/// File name of the generated G3proxy config
+pub const G3PROXY_FILE: &str = "g3proxy.yaml";
+/// File name of the generated G3ICAP config
+pub const G3ICAP_FILE: &str = "g3icap.yaml";
+
+/// Generated configuration files
+#[derive(Debug, Clone)]
+pub struct RenderedConfig {
+    pub g3proxy: String,
+    pub g3icap: String,
+    /// Policy settings that could not be expressed in the configuration
+    pub notes: Vec<String>,
+}
 
-use std::collections::HashMap;
-use anyhow::{Result, anyhow};
-use tracing::{info, debug, error};
+/// Match rules of a route, by next escaper
+#[derive(Default)]
+struct RouteRules {
+    exact: BTreeMap<String, Vec<String>>,
+    child: BTreeMap<String, Vec<String>>,
+    suffix: BTreeMap<String, Vec<String>>,
+    regex: BTreeMap<String, Vec<String>>,
+    /// Patterns already routed, with their next escaper
+    routed: BTreeMap<(&'static str, String), String>,
+}
 
-use crate::policy::{SecurityPolicy, PolicyCollection, PolicyPriority};
-use super::{ConfigContext, GeneratedConfig, RuntimeConfig, LogConfig, StatConfig, ResolverConfig, AuditorConfig, ServerConfig, ServerListen, TlsServerConfig, CertPair, LogOutput, StatTarget};
+impl RouteRules {
+    fn add(&mut self, kind: &'static str, pattern: String, next: &str, policy: &str, notes: &mut Vec<String>) {
+        if let Some(routed) = self.routed.get(&(kind, pattern.clone())) {
+            if routed != next {
+                notes.push(format!(
+                    "policy {}: {} {} is already routed to {} by a policy of higher priority",
+                    policy, kind, pattern, routed
+                ));
+            }
+            return;
+        }
+        self.routed.insert((kind, pattern.clone()), next.to_string());
+        let rules = match kind {
+            "exact" => &mut self.exact,
+            "child" => &mut self.child,
+            "suffix" => &mut self.suffix,
+            _ => &mut self.regex,
+        };
+        rules.entry(next.to_string()).or_default().push(pattern);
+    }
+
+    fn into_escaper(self, name: String, default_next: &str) -> EscaperConfig {
+        EscaperConfig {
+            name,
+            escaper_type: "route_upstream".to_string(),
+            exact_match: self.exact.into_iter()
+                .map(|(next, hosts)| ExactMatchRule { next, hosts })
+                .collect(),
+            child_match: self.child.into_iter()
+                .map(|(next, domains)| ChildMatchRule { next, domains })
+                .collect(),
+            suffix_match: self.suffix.into_iter()
+                .map(|(next, suffixes)| SuffixMatchRule { next, suffixes })
+                .collect(),
+            regex_match: self.regex.into_iter()
+                .map(|(next, rules)| RegexMatchRule { next, rules })
+                .collect(),
+            default_next: Some(default_next.to_string()),
+            ..Default::default()
+        }
+    }
+}
 
 /// Configuration generator
 pub struct ConfigGenerator {
@@ -25,75 +119,117 @@ impl ConfigGenerator {
     /// Generate complete G3proxy configuration from policy collection
     pub fn generate_config(&self, policies: &PolicyCollection) -> Result<GeneratedConfig> {
         info!("Generating G3proxy configuration from {} policies", policies.policies.len());
-        
-        let mut config = GeneratedConfig {
+
+        let sorted = sorted_policies(policies);
+        let mut notes = Vec::new();
+        for policy in &sorted {
+            if !policy.spec.targets.users.is_empty() {
+                notes.push(format!(
+                    "policy {}: user targets are only enforced by G3ICAP, G3proxy routes by user group",
+                    policy.metadata.name
+                ));
+            }
+        }
+
+        let escaper = self.generate_escaper_chain(&sorted, &mut notes);
+        let auditor = self.generate_auditor_config(&sorted, &mut notes);
+        // a policy with several targets is in several routes
+        let mut seen = BTreeSet::new();
+        notes.retain(|note| seen.insert(note.clone()));
+
+        let config = GeneratedConfig {
             runtime: self.generate_runtime_config(),
-            log: self.generate_log_config(),
+            log: "journal".to_string(),
             stat: self.generate_stat_config(),
             resolver: self.generate_resolver_config(),
-            escaper: Vec::new(),
-            user_group: Vec::new(),
-            auditor: Vec::new(),
-            server: Vec::new(),
+            escaper,
+            user_group: self.generate_user_groups(&sorted),
+            auditor,
+            server: self.generate_server_config(&sorted),
+            notes,
         };
 
-        // Generate escapers based on policies
-        config.escaper = self.generate_escaper_chain(policies)?;
-        
-        // Generate user groups
-        config.user_group = self.generate_user_groups(policies)?;
-        
-        // Generate auditors
-        config.auditor = self.generate_auditor_config();
-        
-        // Generate servers
-        config.server = self.generate_server_config(policies)?;
-
         info!("Generated configuration with {} escapers, {} user groups, {} auditors, {} servers",
               config.escaper.len(), config.user_group.len(), config.auditor.len(), config.server.len());
 
         Ok(config)
     }
 
-    fn generate_runtime_config(&self) -> RuntimeConfig {
-        RuntimeConfig {
-            thread_number: self.context.proxy_instances * 2, // 2 threads per instance
+    /// Generate the G3ICAP configuration inspecting the traffic of G3proxy
+    pub fn generate_icap_config(&self, policies: &PolicyCollection) -> Result<IcapGeneratedConfig> {
+        let sorted = sorted_policies(policies);
+
+        let mut respmod = Vec::new();
+        if sorted.iter().any(|p| dlp_scans(p, false)) {
+            respmod.push("content_filter".to_string());
         }
+        respmod.push("antivirus".to_string());
+
+        let mut definitions = BTreeMap::new();
+        definitions.insert(REQMOD_SERVICE.to_string(), vec!["content_filter".to_string()]);
+        definitions.insert(RESPMOD_SERVICE.to_string(), respmod);
+        let services: BTreeMap<String, String> = [REQMOD_SERVICE, RESPMOD_SERVICE]
+            .iter()
+            .map(|s| (s.to_string(), s.to_string()))
+            .collect();
+
+        Ok(IcapGeneratedConfig {
+            server: vec![IcapServerConfig {
+                name: "icap".to_string(),
+                server_type: "icapserver".to_string(),
+                listen: self.context.icap_listen.clone(),
+                policy: self.context.policy_path.clone(),
+                services: services.keys().cloned().collect(),
+                pipelines: IcapPipelinesConfig { definitions, services },
+            }],
+        })
     }
 
-    fn generate_log_config(&self) -> LogConfig {
-        LogConfig {
-            level: "info".to_string(),
-            format: Some("json".to_string()),
-            output: vec![
-                LogOutput {
-                    output_type: "file".to_string(),
-                    path: Some("/var/log/g3proxy/g3proxy.log".to_string()),
-                    level: Some("info".to_string()),
-                    max_size: Some("100M".to_string()),
-                    max_files: Some(10),
-                    facility: None,
-                    tag: None,
-                },
-                LogOutput {
-                    output_type: "file".to_string(),
-                    path: Some("/var/log/g3proxy/g3proxy_error.log".to_string()),
-                    level: Some("error".to_string()),
-                    max_size: Some("50M".to_string()),
-                    max_files: Some(5),
-                    facility: None,
-                    tag: None,
-                },
-                LogOutput {
-                    output_type: "syslog".to_string(),
-                    path: None,
-                    level: None,
-                    max_size: None,
-                    max_files: None,
-                    facility: Some("local0".to_string()),
-                    tag: Some("g3proxy".to_string()),
-                },
-            ],
+    /// Render the G3proxy and G3ICAP configuration files
+    ///
+    /// The same policies always give the same files, byte for byte.
+    pub fn render(&self, policies: &PolicyCollection) -> Result<RenderedConfig> {
+        let proxy = self.generate_config(policies)?;
+        let icap = self.generate_icap_config(policies)?;
+
+        let header = format!(
+            "# Generated by arcus-policy from {} policies of {}, do not edit\n",
+            sorted_policies(policies).len(),
+            policies.metadata.name
+        );
+        Ok(RenderedConfig {
+            g3proxy: format!("{}{}", header, serde_yaml::to_string(&proxy)?),
+            g3icap: format!("{}{}", header, serde_yaml::to_string(&icap)?),
+            notes: proxy.notes,
+        })
+    }
+
+    /// Diff the configuration files deployed in the directory with the ones
+    /// the policies give, a missing file being empty
+    pub fn diff(&self, policies: &PolicyCollection, deployed_dir: &Path) -> Result<ConfigDiff> {
+        let rendered = self.render(policies)?;
+
+        let mut config_diff = ConfigDiff::default();
+        for (name, content) in [(G3PROXY_FILE, &rendered.g3proxy), (G3ICAP_FILE, &rendered.g3icap)] {
+            let path = deployed_dir.join(name);
+            let deployed = match std::fs::read_to_string(&path) {
+                Ok(s) => s,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+                Err(e) => return Err(anyhow::anyhow!("failed to read {}: {}", path.display(), e)),
+            };
+            if let Some(diff) = diff::unified_diff(&deployed, content, name) {
+                config_diff.files.push(FileDiff {
+                    name: name.to_string(),
+                    diff,
+                });
+            }
+        }
+        Ok(config_diff)
+    }
+
+    fn generate_runtime_config(&self) -> RuntimeConfig {
+        RuntimeConfig {
+            thread_number: self.context.proxy_instances * 2, // 2 threads per instance
         }
     }
 
@@ -115,204 +251,216 @@ impl ConfigGenerator {
         ]
     }
 
-    fn generate_auditor_config(&self) -> Vec<AuditorConfig> {
+    fn generate_auditor_config(&self, policies: &[&SecurityPolicy], notes: &mut Vec<String>) -> Vec<AuditorConfig> {
+        let icap_service = |service: &str| format!("icap://{}/{}", self.context.icap_address, service);
+
+        let reqmod = policies.iter().any(|p| {
+            dlp_scans(p, true)
+                || p.spec.url_filtering.as_ref().map_or(false, |u| !u.custom_rules.is_empty())
+        });
+        // the malware scanner of the policy of the highest priority
+        let malware_scanning = policies.iter()
+            .filter_map(|p| p.spec.content_security.as_ref()?.malware_scanning.as_ref())
+            .find(|ms| ms.enabled);
+        let respmod = match malware_scanning {
+            Some(ms) => Some(ms.icap_server.clone().unwrap_or_else(|| icap_service(RESPMOD_SERVICE))),
+            _ if policies.iter().any(|p| dlp_scans(p, false)) => Some(icap_service(RESPMOD_SERVICE)),
+            _ => None,
+        };
+
+        let mut tls_cert_agent = None;
+        for policy in policies {
+            let Some(hi) = policy.spec.https_inspection.as_ref() else {
+                continue;
+            };
+            if !hi.enabled {
+                continue;
+            }
+            if !hi.bypass_domains.is_empty() || matches!(hi.mode, HttpsMode::Selective) {
+                notes.push(format!(
+                    "policy {}: G3proxy intercepts TLS for all domains, the bypass and inspect domains are skipped",
+                    policy.metadata.name
+                ));
+            }
+            if !matches!(hi.mode, HttpsMode::Passthrough) && tls_cert_agent.is_none() {
+                let mut agent = BTreeMap::new();
+                agent.insert(
+                    "query_peer_addr".to_string(),
+                    serde_yaml::Value::String(self.context.cert_agent_address.clone()),
+                );
+                tls_cert_agent = Some(agent);
+            }
+        }
+
         vec![
             AuditorConfig {
                 name: "default".to_string(),
-                protocol_inspection: HashMap::new(),
-                tls_cert_generator: HashMap::new(),
-                tls_ticketer: HashMap::new(),
-                tls_stream_dump: HashMap::new(),
+                protocol_inspection: BTreeMap::new(),
+                tls_cert_agent,
+                tls_ticketer: BTreeMap::new(),
+                tls_stream_dump: BTreeMap::new(),
+                icap_reqmod_service: reqmod.then(|| icap_service(REQMOD_SERVICE)),
+                icap_respmod_service: respmod,
             }
         ]
     }
 
-    fn generate_escaper_chain(&self, policies: &PolicyCollection) -> Result<Vec<super::EscaperConfig>> {
-        debug!("Generating escaper chain for {} policies", policies.policies.len());
-        
+    fn generate_escaper_chain(&self, policies: &[&SecurityPolicy], notes: &mut Vec<String>) -> Vec<EscaperConfig> {
+        debug!("Generating escaper chain for {} policies", policies.len());
+
         let mut escapers = Vec::new();
-        
-        // Main router escaper
-        escapers.push(super::EscaperConfig {
-            name: "main-router".to_string(),
-            escaper_type: "route_upstream".to_string(),
-            rules: self.generate_routing_rules(policies)?,
-            default_next: "internet_access".to_string(),
-            resolver: Some("default".to_string()),
-            ..Default::default()
-        });
 
-        // Content inspection escaper
-        if self.has_content_inspection(policies) {
-            escapers.push(super::EscaperConfig {
-                name: "content_inspection".to_string(),
-                escaper_type: "proxy_http".to_string(),
-                upstream: Some("icap://malware-scanner.company.com:1344".to_string()),
-                icap_service: Some("/scan".to_string()),
-                next: Some("internet_access".to_string()),
+        // The ACL of the source networks, selecting the route of the policy
+        let network_policies: Vec<&SecurityPolicy> = policies.iter()
+            .copied()
+            .filter(|p| !p.spec.targets.source_networks.is_empty())
+            .collect();
+        if !network_policies.is_empty() {
+            escapers.push(EscaperConfig {
+                name: CLIENT_ROUTER.to_string(),
+                escaper_type: "route_client".to_string(),
+                subnet_match: network_policies.iter()
+                    .map(|p| SubnetMatchRule {
+                        next: node_name("policy-", &p.metadata.name),
+                        subnets: p.spec.targets.source_networks.clone(),
+                    })
+                    .collect(),
+                default_next: Some(MAIN_ROUTER.to_string()),
                 ..Default::default()
             });
         }
 
-        // Malware scanning escaper
-        if self.has_malware_scanning(policies) {
-            escapers.push(super::EscaperConfig {
-                name: "malware_scan".to_string(),
-                escaper_type: "proxy_http".to_string(),
-                upstream: Some("icap://malware-scanner.company.com:1344".to_string()),
-                icap_service: Some("/scan".to_string()),
-                next: Some("internet_access".to_string()),
-                ..Default::default()
-            });
+        // Main router, with the rules of the policies without targets
+        let mut main_rules = RouteRules::default();
+        for policy in policies.iter().filter(|p| is_untargeted(p)) {
+            self.add_policy_rules(&mut main_rules, policy, notes);
         }
+        escapers.push(main_rules.into_escaper(MAIN_ROUTER.to_string(), INTERNET_ACCESS));
 
-        // Company resources escaper
-        escapers.push(super::EscaperConfig {
-            name: "company_resources".to_string(),
-            escaper_type: "direct_http".to_string(),
-            ..Default::default()
-        });
+        for policy in &network_policies {
+            let mut rules = RouteRules::default();
+            self.add_policy_rules(&mut rules, policy, notes);
+            escapers.push(rules.into_escaper(node_name("policy-", &policy.metadata.name), MAIN_ROUTER));
+        }
 
-        // Warning page escaper
-        escapers.push(super::EscaperConfig {
-            name: "warn_and_allow".to_string(),
-            escaper_type: "proxy_http".to_string(),
-            upstream: Some("http://warning-page.company.com/social-media-warning".to_string()),
-            next: Some("internet_access".to_string()),
-            ..Default::default()
-        });
+        for group in user_groups(policies) {
+            let mut rules = RouteRules::default();
+            for policy in policies.iter().filter(|p| p.spec.targets.user_groups.contains(&group)) {
+                self.add_policy_rules(&mut rules, policy, notes);
+            }
+            escapers.push(rules.into_escaper(node_name("group-", &group), MAIN_ROUTER));
+        }
 
-        // Deny access escaper
-        escapers.push(super::EscaperConfig {
-            name: "deny_access_security".to_string(),
-            escaper_type: "deny".to_string(),
-            message: Some("Access denied by company security policy".to_string()),
+        escapers.push(EscaperConfig {
+            name: DENY.to_string(),
+            escaper_type: "dummy_deny".to_string(),
             ..Default::default()
         });
-
-        // Internet access escaper
-        escapers.push(super::EscaperConfig {
-            name: "internet_access".to_string(),
-            escaper_type: "direct_http".to_string(),
+        escapers.push(EscaperConfig {
+            name: INTERNET_ACCESS.to_string(),
+            escaper_type: "direct_fixed".to_string(),
+            resolver: Some("default".to_string()),
             ..Default::default()
         });
 
-        Ok(escapers)
+        escapers
     }
 
-    fn generate_routing_rules(&self, policies: &PolicyCollection) -> Result<Vec<super::RoutingRule>> {
-        let mut rules = Vec::new();
-
-        // Sort policies by priority
-        let mut sorted_policies: Vec<_> = policies.policies.values().collect();
-        sorted_policies.sort_by_key(|p| std::cmp::Reverse(p.spec.priority as u32));
-
-        for policy in sorted_policies {
-            if !policy.spec.enabled {
-                continue;
-            }
+    fn add_policy_rules(&self, rules: &mut RouteRules, policy: &SecurityPolicy, notes: &mut Vec<String>) {
+        let Some(url_filtering) = &policy.spec.url_filtering else {
+            return;
+        };
+        let name = &policy.metadata.name;
+        let categories = &url_filtering.categories;
+        if !categories.block.is_empty() || !categories.warn.is_empty() || !categories.allow.is_empty() {
+            notes.push(format!("policy {}: URL categories are not supported", name));
+        }
 
-            if let Some(url_filtering) = &policy.spec.url_filtering {
-                // Generate rules for blocked categories
-                for category in &url_filtering.categories.block {
-                    rules.push(super::RoutingRule {
-                        rule_type: "regex_match".to_string(),
-                        pattern: Some(format!(".*({}).*", category)),
-                        next: "deny_access_security".to_string(),
-                        priority: policy.spec.priority as u32,
-                    });
+        let mut custom_rules: Vec<&CustomRule> = url_filtering.custom_rules.iter().collect();
+        custom_rules.sort_by_key(|r| std::cmp::Reverse(r.priority.unwrap_or(policy.spec.priority as u32)));
+        for custom_rule in custom_rules {
+            let next = match custom_rule.action {
+                PolicyAction::Block | PolicyAction::Quarantine => DENY,
+                PolicyAction::Allow | PolicyAction::Inspect | PolicyAction::Log => INTERNET_ACCESS,
+                PolicyAction::Warn => {
+                    notes.push(format!(
+                        "policy {}: rule {} warns, which G3proxy can not do, the hosts are allowed",
+                        name, custom_rule.name
+                    ));
+                    INTERNET_ACCESS
                 }
-
-                // Generate rules for custom patterns
-                for custom_rule in &url_filtering.custom_rules {
-                    if let Some(pattern) = &custom_rule.pattern {
-                        let next = match custom_rule.action {
-                            crate::policy::PolicyAction::Block => "deny_access_security".to_string(),
-                            crate::policy::PolicyAction::Warn => "warn_and_allow".to_string(),
-                            crate::policy::PolicyAction::Inspect => "content_inspection".to_string(),
-                            _ => "internet_access".to_string(),
-                        };
-
-                        rules.push(super::RoutingRule {
-                            rule_type: match custom_rule.rule_type {
-                                crate::policy::RuleType::Wildcard => "child_match".to_string(),
-                                crate::policy::RuleType::Regex => "regex_match".to_string(),
-                                crate::policy::RuleType::Exact => "exact_match".to_string(),
-                                crate::policy::RuleType::Domain => "child_match".to_string(),
-                                crate::policy::RuleType::Suffix => "suffix_match".to_string(),
-                            },
-                            pattern: Some(pattern.clone()),
-                            next,
-                            priority: custom_rule.priority.unwrap_or(policy.spec.priority as u32),
-                        });
+            };
+
+            let patterns = custom_rule.pattern.iter().chain(custom_rule.patterns.iter().flatten());
+            for pattern in patterns {
+                let pattern = pattern.trim();
+                // host names are matched lowercase
+                let pattern = match custom_rule.rule_type {
+                    RuleType::Regex => pattern.to_string(),
+                    _ => pattern.to_ascii_lowercase(),
+                };
+                if pattern.contains('/') {
+                    notes.push(format!(
+                        "policy {}: rule {} matches the URL path of {}, left to G3ICAP",
+                        name, custom_rule.name, pattern
+                    ));
+                    continue;
+                }
+                match custom_rule.rule_type {
+                    RuleType::Exact => rules.add("exact", pattern, next, name, notes),
+                    RuleType::Domain | RuleType::Suffix => {
+                        let suffix = pattern.trim_start_matches("*.").trim_start_matches('.');
+                        rules.add("suffix", suffix.to_string(), next, name, notes);
                     }
+                    RuleType::Wildcard => match pattern.strip_prefix("*.") {
+                        Some(domain) if !domain.contains('*') => {
+                            rules.add("child", domain.to_string(), next, name, notes);
+                        }
+                        _ => rules.add("regex", wildcard_regex(&pattern), next, name, notes),
+                    },
+                    RuleType::Regex => rules.add("regex", pattern, next, name, notes),
                 }
             }
         }
-
-        // Add default rule
-        rules.push(super::RoutingRule {
-            rule_type: "default".to_string(),
-            pattern: None,
-            next: "malware_scan".to_string(),
-            priority: 0,
-        });
-
-        Ok(rules)
     }
 
-    fn generate_user_groups(&self, policies: &PolicyCollection) -> Result<Vec<super::UserGroupConfig>> {
-        let mut user_groups = Vec::new();
-
-        // Extract unique user groups from policies
-        let mut group_names = std::collections::HashSet::new();
-        for policy in policies.policies.values() {
-            for group in &policy.spec.targets.user_groups {
-                group_names.insert(group.clone());
-            }
-        }
-
-        // Generate user group configurations
-        for group_name in group_names {
-            user_groups.push(super::UserGroupConfig {
-                name: group_name.clone(),
-                source: super::UserGroupSource {
-                    source_type: "file".to_string(),
-                    path: format!("/config/users_{}.json", group_name),
-                },
-                description: Some(format!("User group: {}", group_name)),
-            });
-        }
-
-        // Add default user group if none specified
-        if user_groups.is_empty() {
-            user_groups.push(super::UserGroupConfig {
-                name: "default".to_string(),
-                source: super::UserGroupSource {
-                    source_type: "file".to_string(),
-                    path: "/config/users.json".to_string(),
-                },
-                description: Some("Default user group".to_string()),
-            });
-        }
-
-        Ok(user_groups)
+    fn generate_user_groups(&self, policies: &[&SecurityPolicy]) -> Vec<UserGroupConfig> {
+        user_groups(policies)
+            .into_iter()
+            .map(|group| {
+                let name = node_name("", &group);
+                UserGroupConfig {
+                    source: UserGroupSource {
+                        source_type: "file".to_string(),
+                        path: format!("{}/{}.json", self.context.user_dir.trim_end_matches('/'), name),
+                    },
+                    name,
+                    group_type: "hashed_user".to_string(),
+                }
+            })
+            .collect()
     }
 
-    fn generate_server_config(&self, policies: &PolicyCollection) -> Result<Vec<ServerConfig>> {
+    fn generate_server_config(&self, policies: &[&SecurityPolicy]) -> Vec<ServerConfig> {
         let mut servers = Vec::new();
 
+        let escaper = if policies.iter().any(|p| !p.spec.targets.source_networks.is_empty()) {
+            CLIENT_ROUTER
+        } else {
+            MAIN_ROUTER
+        };
+
         // HTTP proxy server
         servers.push(ServerConfig {
             name: "http".to_string(),
             server_type: "http_proxy".to_string(),
-            escaper: "main-router".to_string(),
+            escaper: escaper.to_string(),
             auditor: Some("default".to_string()),
-            user_group: Some("default".to_string()),
+            user_group: None,
             listen: ServerListen {
-                address: "0.0.0.0:8080".to_string(),
+                address: self.context.http_listen.clone(),
             },
-            tls_client: Some(HashMap::new()),
+            tls_client: Some(BTreeMap::new()),
             tls_server: None,
         });
 
@@ -320,11 +468,11 @@ impl ConfigGenerator {
         servers.push(ServerConfig {
             name: "socks".to_string(),
             server_type: "socks_proxy".to_string(),
-            escaper: "main-router".to_string(),
+            escaper: escaper.to_string(),
             auditor: Some("default".to_string()),
-            user_group: Some("default".to_string()),
+            user_group: None,
             listen: ServerListen {
-                address: "0.0.0.0:1080".to_string(),
+                address: self.context.socks_listen.clone(),
             },
             tls_client: None,
             tls_server: None,
@@ -335,13 +483,13 @@ impl ConfigGenerator {
             servers.push(ServerConfig {
                 name: "https".to_string(),
                 server_type: "http_proxy".to_string(),
-                escaper: "main-router".to_string(),
+                escaper: escaper.to_string(),
                 auditor: Some("default".to_string()),
-                user_group: Some("default".to_string()),
+                user_group: None,
                 listen: ServerListen {
-                    address: "0.0.0.0:8443".to_string(),
+                    address: self.context.https_listen.clone(),
                 },
-                tls_client: Some(HashMap::new()),
+                tls_client: Some(BTreeMap::new()),
                 tls_server: Some(TlsServerConfig {
                     cert_pairs: CertPair {
                         certificate: "/etc/ssl/proxy-cert.pem".to_string(),
@@ -351,30 +499,81 @@ impl ConfigGenerator {
             });
         }
 
-        Ok(servers)
-    }
+        // An HTTP proxy server for each user group, on consecutive ports
+        let host = self.context.http_listen
+            .rsplit_once(':')
+            .map(|(host, _)| host)
+            .unwrap_or("0.0.0.0");
+        for (i, group) in user_groups(policies).into_iter().enumerate() {
+            let port = u32::from(self.context.user_group_base_port) + i as u32;
+            servers.push(ServerConfig {
+                name: node_name("http-", &group),
+                server_type: "http_proxy".to_string(),
+                escaper: node_name("group-", &group),
+                auditor: Some("default".to_string()),
+                user_group: Some(node_name("", &group)),
+                listen: ServerListen {
+                    address: format!("{}:{}", host, port),
+                },
+                tls_client: Some(BTreeMap::new()),
+                tls_server: None,
+            });
+        }
 
-    fn has_content_inspection(&self, policies: &PolicyCollection) -> bool {
-        policies.policies.values().any(|p| {
-            p.spec.content_security.as_ref()
-                .map(|cs| cs.data_loss_prevention.as_ref().map(|dlp| dlp.enabled).unwrap_or(false))
-                .unwrap_or(false)
-        })
+        servers
     }
 
-    fn has_malware_scanning(&self, policies: &PolicyCollection) -> bool {
-        policies.policies.values().any(|p| {
-            p.spec.content_security.as_ref()
-                .map(|cs| cs.malware_scanning.as_ref().map(|ms| ms.enabled).unwrap_or(false))
-                .unwrap_or(false)
-        })
-    }
-
-    fn has_https_inspection(&self, policies: &PolicyCollection) -> bool {
-        policies.policies.values().any(|p| {
+    fn has_https_inspection(&self, policies: &[&SecurityPolicy]) -> bool {
+        policies.iter().any(|p| {
             p.spec.https_inspection.as_ref()
                 .map(|hi| hi.enabled)
                 .unwrap_or(false)
         })
     }
 }
+
+/// The enabled policies, by decreasing priority then by name
+fn sorted_policies(policies: &PolicyCollection) -> Vec<&SecurityPolicy> {
+    let mut sorted: Vec<&SecurityPolicy> = policies.policies.values()
+        .map(|p| p.as_ref())
+        .filter(|p| p.spec.enabled)
+        .collect();
+    sorted.sort_by(|a, b| {
+        (b.spec.priority as u32).cmp(&(a.spec.priority as u32))
+            .then_with(|| a.metadata.name.cmp(&b.metadata.name))
+    });
+    sorted
+}
+
+fn is_untargeted(policy: &SecurityPolicy) -> bool {
+    let targets = &policy.spec.targets;
+    targets.user_groups.is_empty() && targets.users.is_empty() && targets.source_networks.is_empty()
+}
+
+/// The user groups targeted by the policies, sorted
+fn user_groups(policies: &[&SecurityPolicy]) -> BTreeSet<String> {
+    policies.iter()
+        .flat_map(|p| p.spec.targets.user_groups.iter().cloned())
+        .collect()
+}
+
+/// Check if DLP scans the uploads, or the downloads
+fn dlp_scans(policy: &SecurityPolicy, uploads: bool) -> bool {
+    policy.spec.content_security.as_ref()
+        .and_then(|cs| cs.data_loss_prevention.as_ref())
+        .map_or(false, |dlp| dlp.enabled && if uploads { dlp.scan_uploads } else { dlp.scan_downloads })
+}
+
+/// Build a G3proxy node name, replacing the characters it does not allow
+fn node_name(prefix: &str, name: &str) -> String {
+    let name: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+        .collect();
+    format!("{}{}", prefix, name)
+}
+
+/// Convert a host wildcard, like `ads*.example.com`, to a regex
+fn wildcard_regex(pattern: &str) -> String {
+    let escaped: Vec<String> = pattern.split('*').map(regex::escape).collect();
+    format!("^{}$", escaped.join(".*"))
+}
@@ -3,19 +3,21 @@
  * Copyright 2025 ByteDance and/or its affiliates.
  */
 
-//! Configuration generation for G3proxy
+//! Configuration generation for G3proxy and G3ICAP
+//!
+//! The generated configs are plain data, serialized in field order with
+//! sorted maps, so that the same policies always give the same YAML.
 
-use std::collections::HashMap;
-use anyhow::Result;
+use std::collections::BTreeMap;
 use serde::{Deserialize, Serialize};
 
 pub mod generator;
-pub mod g3proxy;
+pub mod diff;
 pub mod escaper;
 pub mod user_group;
 
-pub use generator::ConfigGenerator;
-pub use g3proxy::G3proxyConfig;
+pub use generator::{ConfigGenerator, RenderedConfig};
+pub use diff::{ConfigDiff, FileDiff};
 pub use escaper::EscaperConfig;
 pub use user_group::UserGroupConfig;
 
@@ -30,6 +32,24 @@ pub struct ConfigContext {
     pub tls_version: String,
     pub certificate_authority: String,
     pub key_rotation: String,
+    /// Address of the G3ICAP server, as seen by G3proxy
+    pub icap_address: String,
+    /// Listen address of the G3ICAP server
+    pub icap_listen: String,
+    /// Path of the policy files, as seen by G3ICAP
+    pub policy_path: String,
+    /// Directory of the user files of the user groups
+    pub user_dir: String,
+    /// Listen address of the HTTP proxy
+    pub http_listen: String,
+    /// Listen address of the SOCKS proxy
+    pub socks_listen: String,
+    /// Listen address of the HTTPS proxy
+    pub https_listen: String,
+    /// First port of the HTTP proxies of the user groups, one per group
+    pub user_group_base_port: u16,
+    /// Address of the certificate generator for TLS interception
+    pub cert_agent_address: String,
 }
 
 impl Default for ConfigContext {
@@ -43,21 +63,34 @@ impl Default for ConfigContext {
             tls_version: "1.2+".to_string(),
             certificate_authority: "internal".to_string(),
             key_rotation: "90d".to_string(),
+            icap_address: "127.0.0.1:1344".to_string(),
+            icap_listen: "0.0.0.0:1344".to_string(),
+            policy_path: "/etc/g3icap/policies".to_string(),
+            user_dir: "/etc/g3proxy/users".to_string(),
+            http_listen: "0.0.0.0:8080".to_string(),
+            socks_listen: "0.0.0.0:1080".to_string(),
+            https_listen: "0.0.0.0:8443".to_string(),
+            user_group_base_port: 8081,
+            cert_agent_address: "127.0.0.1:2999".to_string(),
         }
     }
 }
 
-/// Generated configuration components
+/// Generated G3proxy configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GeneratedConfig {
     pub runtime: RuntimeConfig,
-    pub log: LogConfig,
+    /// Log driver
+    pub log: String,
     pub stat: StatConfig,
     pub resolver: Vec<ResolverConfig>,
     pub escaper: Vec<EscaperConfig>,
     pub user_group: Vec<UserGroupConfig>,
     pub auditor: Vec<AuditorConfig>,
     pub server: Vec<ServerConfig>,
+    /// Policy settings that could not be expressed in the configuration
+    #[serde(skip)]
+    pub notes: Vec<String>,
 }
 
 /// Runtime configuration
@@ -66,26 +99,6 @@ pub struct RuntimeConfig {
     pub thread_number: u32,
 }
 
-/// Logging configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct LogConfig {
-    pub level: String,
-    pub format: Option<String>,
-    pub output: Vec<LogOutput>,
-}
-
-/// Log output configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct LogOutput {
-    pub output_type: String,
-    pub path: Option<String>,
-    pub level: Option<String>,
-    pub max_size: Option<String>,
-    pub max_files: Option<u32>,
-    pub facility: Option<String>,
-    pub tag: Option<String>,
-}
-
 /// Statistics configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StatConfig {
@@ -102,6 +115,7 @@ pub struct StatTarget {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ResolverConfig {
     pub name: String,
+    #[serde(rename = "type")]
     pub resolver_type: String,
     pub server: Vec<String>,
 }
@@ -110,22 +124,35 @@ pub struct ResolverConfig {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuditorConfig {
     pub name: String,
-    pub protocol_inspection: HashMap<String, serde_yaml::Value>,
-    pub tls_cert_generator: HashMap<String, serde_yaml::Value>,
-    pub tls_ticketer: HashMap<String, serde_yaml::Value>,
-    pub tls_stream_dump: HashMap<String, serde_yaml::Value>,
+    pub protocol_inspection: BTreeMap<String, serde_yaml::Value>,
+    /// Certificate generator, TLS interception is disabled if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_cert_agent: Option<BTreeMap<String, serde_yaml::Value>>,
+    pub tls_ticketer: BTreeMap<String, serde_yaml::Value>,
+    pub tls_stream_dump: BTreeMap<String, serde_yaml::Value>,
+    /// ICAP service for the requests, like `icap://127.0.0.1:1344/reqmod`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icap_reqmod_service: Option<String>,
+    /// ICAP service for the responses
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icap_respmod_service: Option<String>,
 }
 
 /// Server configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerConfig {
     pub name: String,
+    #[serde(rename = "type")]
     pub server_type: String,
     pub escaper: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub auditor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub user_group: Option<String>,
     pub listen: ServerListen,
-    pub tls_client: Option<HashMap<String, serde_yaml::Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_client: Option<BTreeMap<String, serde_yaml::Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub tls_server: Option<TlsServerConfig>,
 }
 
@@ -147,3 +174,32 @@ pub struct CertPair {
     pub certificate: String,
     pub private_key: String,
 }
+
+/// Generated G3ICAP configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IcapGeneratedConfig {
+    pub server: Vec<IcapServerConfig>,
+}
+
+/// G3ICAP server configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IcapServerConfig {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub server_type: String,
+    pub listen: String,
+    /// Policy files compiled into the module configs
+    pub policy: String,
+    /// Registered services, the ICAP URI paths
+    pub services: Vec<String>,
+    pub pipelines: IcapPipelinesConfig,
+}
+
+/// G3ICAP pipelines and their services
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IcapPipelinesConfig {
+    /// Module stages by pipeline name
+    pub definitions: BTreeMap<String, Vec<String>>,
+    /// Pipeline by service name
+    pub services: BTreeMap<String, String>,
+}
@@ -7,17 +7,20 @@
 
 use serde::{Deserialize, Serialize};
 
-/// User group configuration
+/// User group configuration, of the `hashed_user` type
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserGroupConfig {
     pub name: String,
+    #[serde(rename = "type")]
+    pub group_type: String,
+    /// Source of the dynamic users
     pub source: UserGroupSource,
-    pub description: Option<String>,
 }
 
 /// User group source configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserGroupSource {
+    #[serde(rename = "type")]
     pub source_type: String,
     pub path: String,
 }
@@ -0,0 +1,234 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2025 ByteDance and/or its affiliates.
+ */
+
+//! Analysis of the conflicting and overlapping rules of the policies
+//!
+//! The rules of the policies whose targets overlap are compared in the
+//! order the engine applies them, so that a domain both allowed and blocked,
+//! or a rule hidden by a broader one applied first, is reported before the
+//! policies are deployed, with what decided the winning rule.
+
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::policy::{PolicyAction, PolicyCollection, RuleType, SecurityPolicy};
+use super::resolver::{self, Resolution, Specificity};
+
+/// Kind of a conflict between two rules
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictKind {
+    /// The same pattern with different actions
+    Contradiction,
+    /// A broader pattern applied first hides a narrower one with a
+    /// different action, which never applies
+    Shadowed,
+    /// A narrower pattern applied first makes an exception to a broader one
+    Exception,
+    /// The same pattern with the same action
+    Redundant,
+}
+
+/// A rule of a policy, or a URL category
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuleRef {
+    pub policy: String,
+    /// Name of the custom rule, `category` for the URL categories
+    pub rule: String,
+    pub pattern: String,
+    pub action: PolicyAction,
+}
+
+/// A conflict between two rules, the winner being applied first
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyConflict {
+    pub kind: ConflictKind,
+    pub winner: RuleRef,
+    pub loser: RuleRef,
+    pub resolved_by: Resolution,
+}
+
+/// Conflicts of a set of policies
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConflictReport {
+    /// Names of the enabled policies, in the order they are applied to a
+    /// request matching all of them
+    pub order: Vec<String>,
+    pub conflicts: Vec<PolicyConflict>,
+}
+
+impl ConflictReport {
+    /// Check if some rules have a different action than a rule applied first
+    pub fn has_conflicts(&self) -> bool {
+        self.conflicts.iter().any(|c| {
+            matches!(c.kind, ConflictKind::Contradiction | ConflictKind::Shadowed)
+        })
+    }
+}
+
+/// What a rule pattern matches, for comparison with the other patterns
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Matcher {
+    /// The host and its subdomains
+    Domain(String),
+    /// The whole URL
+    Exact(String),
+    /// Compared as text only
+    Pattern(&'static str, String),
+    Category(String),
+}
+
+impl Matcher {
+    fn new(rule_type: &RuleType, pattern: &str) -> Self {
+        let pattern = pattern.trim();
+        match rule_type {
+            RuleType::Domain => Matcher::Domain(pattern.trim_start_matches("*.").to_ascii_lowercase()),
+            RuleType::Exact => Matcher::Exact(pattern.to_string()),
+            RuleType::Suffix => Matcher::Pattern("suffix", pattern.to_string()),
+            RuleType::Wildcard => Matcher::Pattern("wildcard", pattern.to_string()),
+            RuleType::Regex => Matcher::Pattern("regex", pattern.to_string()),
+        }
+    }
+
+    /// Check if all that the other matches is matched by this one too
+    fn covers(&self, other: &Matcher) -> bool {
+        if self == other {
+            return true;
+        }
+        let Matcher::Domain(domain) = self else {
+            return false;
+        };
+        let host = match other {
+            Matcher::Domain(host) => host.clone(),
+            Matcher::Exact(url) => match url::Url::parse(url).ok().and_then(|u| u.host_str().map(|h| h.to_string())) {
+                Some(host) => host,
+                None => return false,
+            },
+            _ => return false,
+        };
+        host == *domain || host.ends_with(&format!(".{}", domain))
+    }
+}
+
+struct Entry<'a> {
+    policy: &'a SecurityPolicy,
+    specificity: Specificity,
+    /// Index of the rule in the policy
+    index: usize,
+    matcher: Matcher,
+    rule: RuleRef,
+}
+
+fn entries(policy: &SecurityPolicy) -> Vec<Entry<'_>> {
+    let mut entries = Vec::new();
+    let Some(url_filtering) = &policy.spec.url_filtering else {
+        return entries;
+    };
+    let specificity = resolver::static_specificity(policy);
+    let mut push = |index: usize, matcher: Matcher, rule: &str, pattern: &str, action: PolicyAction| {
+        entries.push(Entry {
+            policy,
+            specificity,
+            index,
+            matcher,
+            rule: RuleRef {
+                policy: policy.metadata.name.clone(),
+                rule: rule.to_string(),
+                pattern: pattern.to_string(),
+                action,
+            },
+        });
+    };
+
+    // the blocked categories are checked first, the others are not applied
+    for category in &url_filtering.categories.block {
+        push(0, Matcher::Category(category.clone()), "category", category, PolicyAction::Block);
+    }
+    for (i, rule) in url_filtering.custom_rules.iter().enumerate() {
+        let patterns = rule.pattern.iter().chain(rule.patterns.iter().flatten());
+        for pattern in patterns {
+            push(i + 1, Matcher::new(&rule.rule_type, pattern), &rule.name, pattern, rule.action.clone());
+        }
+    }
+    entries
+}
+
+/// Compare the precedence of two rules, `Less` if `a` is applied first
+fn compare(a: &Entry<'_>, b: &Entry<'_>) -> (Ordering, Resolution) {
+    if std::ptr::eq(a.policy, b.policy) {
+        return (a.index.cmp(&b.index), Resolution::RuleOrder);
+    }
+    resolver::compare(a.policy, a.specificity, b.policy, b.specificity)
+}
+
+fn conflict_kind(winner: &Entry<'_>, loser: &Entry<'_>) -> Option<ConflictKind> {
+    let same_action = winner.rule.action == loser.rule.action;
+    if winner.matcher == loser.matcher {
+        return Some(if same_action {
+            ConflictKind::Redundant
+        } else {
+            ConflictKind::Contradiction
+        });
+    }
+    if same_action {
+        return None;
+    }
+    if winner.matcher.covers(&loser.matcher) {
+        Some(ConflictKind::Shadowed)
+    } else if loser.matcher.covers(&winner.matcher) {
+        Some(ConflictKind::Exception)
+    } else {
+        None
+    }
+}
+
+/// Analyze the rules of the enabled policies of the collection
+pub fn analyze(collection: &PolicyCollection) -> ConflictReport {
+    let mut policies: Vec<&Arc<SecurityPolicy>> = collection.policies.values()
+        .filter(|p| p.spec.enabled)
+        .collect();
+    policies.sort_by(|a, b| {
+        resolver::compare(a, resolver::static_specificity(a), b, resolver::static_specificity(b)).0
+    });
+
+    let mut all: Vec<Entry<'_>> = policies.iter().flat_map(|p| entries(p)).collect();
+    all.sort_by(|a, b| compare(a, b).0);
+
+    let mut conflicts = Vec::new();
+    for (i, winner) in all.iter().enumerate() {
+        for loser in &all[i + 1..] {
+            let same_policy = std::ptr::eq(winner.policy, loser.policy);
+            if !same_policy && !resolver::targets_overlap(&winner.policy.spec.targets, &loser.policy.spec.targets) {
+                continue;
+            }
+            // a decisive rule of a later policy wins over a warning
+            let (winner, loser, resolved_by) = if !same_policy
+                && !resolver::is_decisive(&winner.rule.action)
+                && resolver::is_decisive(&loser.rule.action)
+            {
+                (loser, winner, Resolution::Action)
+            } else {
+                (winner, loser, compare(winner, loser).1)
+            };
+            if let Some(kind) = conflict_kind(winner, loser) {
+                conflicts.push(PolicyConflict {
+                    kind,
+                    winner: winner.rule.clone(),
+                    loser: loser.rule.clone(),
+                    resolved_by,
+                });
+            }
+        }
+    }
+    debug!("Found {} conflicts in {} policies", conflicts.len(), policies.len());
+
+    ConflictReport {
+        order: policies.iter().map(|p| p.metadata.name.clone()).collect(),
+        conflicts,
+    }
+}
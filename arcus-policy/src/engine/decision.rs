@@ -31,6 +31,17 @@ impl PolicyDecision {
         }
     }
 
+    /// Allow explicitly, by a rule of a policy
+    pub fn allow_by(reason: String, policy_name: String) -> Self {
+        Self {
+            action: PolicyAction::Allow,
+            reason,
+            policy_name: Some(policy_name),
+            message: None,
+            metadata: HashMap::new(),
+        }
+    }
+
     pub fn block(reason: String, policy_name: String) -> Self {
         Self {
             action: PolicyAction::Block,
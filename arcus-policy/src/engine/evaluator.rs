@@ -10,6 +10,7 @@ use regex::Regex;
 use tracing::{debug, info, warn};
 
 use crate::policy::{SecurityPolicy, PolicyAction, RuleType};
+use crate::policy::presets::{ServicePreset, TransferDirection};
 use super::{PolicyRequest, PolicyDecision};
 
 /// Policy evaluator
@@ -34,12 +35,17 @@ impl PolicyEvaluator {
             return Ok(PolicyDecision::allow());
         }
 
-        // Check URL filtering rules
+        // Check URL filtering rules, an explicit allow still goes through
+        // the other checks
+        let mut allowed = None;
         if let Some(url_filtering) = &policy.spec.url_filtering {
             let decision = self.evaluate_url_filtering(url_filtering, request).await?;
             if decision.action != PolicyAction::Allow {
                 return Ok(decision);
             }
+            if decision.policy_name.is_some() {
+                allowed = Some(decision);
+            }
         }
 
         // Check content security policies
@@ -66,7 +72,7 @@ impl PolicyEvaluator {
             }
         }
 
-        Ok(PolicyDecision::allow())
+        Ok(allowed.unwrap_or_else(PolicyDecision::allow))
     }
 
     /// Evaluate URL filtering rules
@@ -101,6 +107,10 @@ impl PolicyEvaluator {
                         format!("URL requires inspection by rule: {}", rule.name),
                         rule.name.clone(),
                     ),
+                    PolicyAction::Allow => PolicyDecision::allow_by(
+                        format!("URL allowed by rule: {}", rule.name),
+                        rule.name.clone(),
+                    ),
                     _ => PolicyDecision::allow(),
                 };
 
@@ -132,12 +142,32 @@ impl PolicyEvaluator {
 
         // Check DLP requirements
         if let Some(dlp) = &content_security.data_loss_prevention {
-            if dlp.enabled {
+            if dlp.enabled && dlp.service_presets.is_empty() {
                 return Ok(PolicyDecision::inspect(
                     "Content requires DLP scanning".to_string(),
                     "data_loss_prevention".to_string(),
                 ));
             }
+            if dlp.enabled {
+                let direction = TransferDirection::from_method(&request.method);
+                let scanned = match direction {
+                    TransferDirection::Upload => dlp.scan_uploads,
+                    TransferDirection::Download => dlp.scan_downloads,
+                };
+                if scanned {
+                    let presets = ServicePreset::resolve(&dlp.service_presets)
+                        .map_err(|e| anyhow::anyhow!(e))?;
+                    if let Some(preset) = presets.iter().find(|p| p.matches(request, direction)) {
+                        debug!("Request matched DLP service preset {} ({})", preset.name, direction);
+                        return Ok(PolicyDecision::inspect(
+                            format!("{} {} requires DLP scanning", preset.description, direction),
+                            "data_loss_prevention".to_string(),
+                        )
+                        .with_metadata("dlp_preset".to_string(), preset.name.to_string())
+                        .with_metadata("dlp_direction".to_string(), direction.to_string()));
+                    }
+                }
+            }
         }
 
         Ok(PolicyDecision::allow())
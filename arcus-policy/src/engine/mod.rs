@@ -8,20 +8,29 @@
 use std::collections::HashMap;
 use std::sync::Arc;
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use tracing::{debug, info, warn};
 
 pub mod evaluator;
 pub mod context;
 pub mod decision;
+pub mod resolver;
+pub mod conflict;
+pub mod schedule;
 
 pub use evaluator::PolicyEvaluator;
 pub use context::PolicyContext;
 pub use decision::PolicyDecision;
+pub use resolver::{Resolution, Specificity};
+pub use conflict::{ConflictKind, ConflictReport, PolicyConflict};
+pub use schedule::{ActivationSchedule, TimeWindow, WindowKind};
 
 /// Policy engine for evaluating requests against policies
 pub struct PolicyEngine {
     evaluator: PolicyEvaluator,
     context: PolicyContext,
+    policies: Vec<Arc<SecurityPolicy>>,
+    schedule: ActivationSchedule,
 }
 
 impl PolicyEngine {
@@ -29,56 +38,104 @@ impl PolicyEngine {
         Self {
             evaluator: PolicyEvaluator::new(),
             context: PolicyContext::new(),
+            policies: Vec::new(),
+            schedule: ActivationSchedule::default(),
         }
     }
 
+    /// Load the enabled policies of a collection, replacing the previous ones
+    ///
+    /// Fails if the time restrictions of the policies are invalid, the
+    /// previous policies are then kept.
+    pub fn load_policies(&mut self, collection: &PolicyCollection) -> Result<()> {
+        let mut policies: Vec<(&PolicyId, &Arc<SecurityPolicy>)> = collection.policies.iter()
+            .filter(|(_, p)| p.spec.enabled)
+            .collect();
+        // policies with the same name are always applied in the same order
+        policies.sort_by(|(a_id, a), (b_id, b)| {
+            a.metadata.name.cmp(&b.metadata.name).then_with(|| a_id.cmp(b_id))
+        });
+        let schedule = ActivationSchedule::compile(policies.iter().map(|(_, p)| p.as_ref()))?;
+        self.policies = policies.into_iter().map(|(_, p)| p.clone()).collect();
+        self.schedule = schedule;
+        info!("Loaded {} enabled policies of collection {}", self.policies.len(), collection.metadata.name);
+        Ok(())
+    }
+
+    /// Get the next instant a policy is activated or deactivated at by its
+    /// time restrictions, after the given one
+    pub fn next_schedule_transition(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.schedule.next_transition(after)
+    }
+
+    /// Report the conflicting and overlapping rules of a collection, to be
+    /// checked before it is deployed
+    pub fn analyze_conflicts(collection: &PolicyCollection) -> ConflictReport {
+        let report = conflict::analyze(collection);
+        for c in report.conflicts.iter().filter(|c| c.kind == ConflictKind::Contradiction) {
+            warn!("Policies {} and {} have contradicting rules for {}, {:?} wins by {:?}",
+                  c.winner.policy, c.loser.policy, c.winner.pattern, c.winner.action, c.resolved_by);
+        }
+        report
+    }
+
     /// Evaluate a request against all applicable policies
-    pub async fn evaluate_request(&self, request: &PolicyRequest) -> Result<PolicyDecision> {
+    ///
+    /// The first allowing, blocking or quarantining decision of the policies,
+    /// in their order of precedence, wins. Otherwise the first warning or
+    /// inspection is returned, if any.
+    pub async fn evaluate_request(&mut self, request: &PolicyRequest) -> Result<PolicyDecision> {
         debug!("Evaluating request: {} {}", request.method, request.url);
         
         // Get applicable policies for this request
-        let applicable_policies = self.get_applicable_policies(request).await?;
+        let applicable_policies = self.get_applicable_policies(request);
         
         if applicable_policies.is_empty() {
-            return Ok(PolicyDecision::Allow);
+            return Ok(PolicyDecision::allow());
         }
 
-        // Evaluate each policy in priority order
-        for policy in applicable_policies {
-            let decision = self.evaluator.evaluate_policy(policy, request).await?;
-            
-            // If policy explicitly blocks or allows, return that decision
-            match decision.action {
-                PolicyAction::Block => {
-                    info!("Request blocked by policy: {}", policy.metadata.name);
-                    return Ok(decision);
-                }
-                PolicyAction::Allow => {
-                    info!("Request allowed by policy: {}", policy.metadata.name);
-                    return Ok(decision);
-                }
-                _ => {
-                    // Continue evaluating other policies
-                    debug!("Policy {} returned action: {:?}, continuing evaluation", 
-                           policy.metadata.name, decision.action);
-                }
+        let mut pending = None;
+        for (policy, specificity) in applicable_policies {
+            let decision = self.evaluator.evaluate_policy(&policy, request).await?;
+            if decision.policy_name.is_none() {
+                // nothing of the policy matched
+                continue;
+            }
+            let mut decision = decision
+                .with_metadata("policy".to_string(), policy.metadata.name.clone())
+                .with_metadata("specificity".to_string(), format!("{:?}", specificity));
+            if let Some(window) = self.schedule.open_window(&policy.metadata.name, request.time) {
+                decision = decision.with_metadata("time_window".to_string(), window.kind.as_str().to_string());
+            }
+
+            if resolver::is_decisive(&decision.action) {
+                info!("Request {:?} by policy: {}", decision.action, policy.metadata.name);
+                return Ok(decision);
+            }
+            debug!("Policy {} returned action: {:?}, continuing evaluation",
+                   policy.metadata.name, decision.action);
+            if pending.is_none() {
+                pending = Some(decision);
             }
         }
 
         // Default decision if no policy explicitly allows or blocks
-        Ok(PolicyDecision::Allow)
+        Ok(pending.unwrap_or_else(PolicyDecision::allow))
     }
 
-    /// Get policies applicable to this request
-    async fn get_applicable_policies(&self, request: &PolicyRequest) -> Result<Vec<&Arc<SecurityPolicy>>> {
-        let mut applicable = Vec::new();
-        
-        // This is a simplified implementation
-        // In a real system, you'd query the policy manager for applicable policies
-        // based on user, group, source network, etc.
-        
+    /// Get policies applicable to this request, in their order of precedence
+    ///
+    /// The policies restricted to some time windows only apply while one of
+    /// them is open at the time of the request.
+    fn get_applicable_policies(&self, request: &PolicyRequest) -> Vec<(Arc<SecurityPolicy>, Specificity)> {
+        let mut applicable: Vec<(Arc<SecurityPolicy>, Specificity)> = self.policies.iter()
+            .filter(|p| self.schedule.is_active(&p.metadata.name, request.time))
+            .filter_map(|p| resolver::specificity(p, request).map(|s| (p.clone(), s)))
+            .collect();
+        applicable.sort_by(|(a, a_spec), (b, b_spec)| resolver::compare(a, *a_spec, b, *b_spec).0);
+
         debug!("Found {} applicable policies for request", applicable.len());
-        Ok(applicable)
+        applicable
     }
 }
 
@@ -92,6 +149,8 @@ pub struct PolicyRequest {
     pub source_ip: String,
     pub user_agent: Option<String>,
     pub headers: HashMap<String, String>,
+    /// Time of the request, for the time restrictions of the policies
+    pub time: DateTime<Utc>,
 }
 
 impl PolicyRequest {
@@ -104,10 +163,9 @@ impl PolicyRequest {
             source_ip,
             user_agent: None,
             headers: HashMap::new(),
+            time: Utc::now(),
         }
     }
 }
 
-/// Policy action (re-exported from policy module)
-use crate::policy::PolicyAction;
-use crate::policy::SecurityPolicy;
+use crate::policy::{PolicyCollection, PolicyId, SecurityPolicy};
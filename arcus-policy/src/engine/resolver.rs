@@ -0,0 +1,141 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2025 ByteDance and/or its affiliates.
+ */
+
+//! Precedence of the policies matching a request
+//!
+//! When several policies match a request the first decisive one wins, the
+//! policies being applied by decreasing priority, then from the most
+//! specific target to the least specific one, a user before a user group,
+//! a user group before a source network, a longer network prefix before a
+//! shorter one and a network before the policies without targets. The name
+//! of the policies breaks the remaining ties, so that the outcome never
+//! depends on the order the policies were loaded in. Only allowing, blocking
+//! and quarantining decisions are decisive, a warning or an inspection is
+//! kept while the next policies are applied.
+
+use std::cmp::Ordering;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use ipnetwork::IpNetwork;
+use serde::{Deserialize, Serialize};
+
+use crate::policy::{PolicyAction, PolicyTargets, SecurityPolicy};
+use super::PolicyRequest;
+
+/// How specifically a policy targets a request, from the least specific
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Specificity {
+    /// The policy has no targets
+    Global,
+    /// A source network, with its prefix length
+    Network(u8),
+    UserGroup,
+    User,
+}
+
+/// What decided the precedence of two policies or rules
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resolution {
+    Priority,
+    Specificity,
+    /// Same priority and specificity, the name decided
+    Name,
+    /// Rules of the same policy, applied in their order
+    RuleOrder,
+    /// A decisive action of a policy applied later, over a warning or an
+    /// inspection
+    Action,
+}
+
+/// Check if a decision with the action ends the evaluation of the policies
+pub fn is_decisive(action: &PolicyAction) -> bool {
+    matches!(action, PolicyAction::Allow | PolicyAction::Block | PolicyAction::Quarantine)
+}
+
+fn is_global(targets: &PolicyTargets) -> bool {
+    targets.users.is_empty() && targets.user_groups.is_empty() && targets.source_networks.is_empty()
+}
+
+fn networks(targets: &PolicyTargets) -> impl Iterator<Item = IpNetwork> + '_ {
+    targets.source_networks.iter()
+        .filter_map(|s| IpNetwork::from_str(s.trim()).ok())
+}
+
+/// Get how specifically the policy targets the request, `None` if it does not apply
+pub fn specificity(policy: &SecurityPolicy, request: &PolicyRequest) -> Option<Specificity> {
+    let targets = &policy.spec.targets;
+    if is_global(targets) {
+        return Some(Specificity::Global);
+    }
+    if let Some(user) = &request.user {
+        if targets.users.contains(user) {
+            return Some(Specificity::User);
+        }
+    }
+    if targets.user_groups.iter().any(|g| request.user_groups.contains(g)) {
+        return Some(Specificity::UserGroup);
+    }
+    let ip = IpAddr::from_str(request.source_ip.trim()).ok()?;
+    networks(targets)
+        .filter(|n| n.contains(ip))
+        .map(|n| n.prefix())
+        .max()
+        .map(Specificity::Network)
+}
+
+/// Get the most specific target of the policy, for the analysis of the
+/// policies without a request
+pub fn static_specificity(policy: &SecurityPolicy) -> Specificity {
+    let targets = &policy.spec.targets;
+    if !targets.users.is_empty() {
+        Specificity::User
+    } else if !targets.user_groups.is_empty() {
+        Specificity::UserGroup
+    } else {
+        networks(targets)
+            .map(|n| n.prefix())
+            .max()
+            .map(Specificity::Network)
+            .unwrap_or(Specificity::Global)
+    }
+}
+
+/// Check if a request may match the targets of both policies
+///
+/// Policies with no targets in common are considered apart, even though a
+/// user of one may be in a user group of the other.
+pub fn targets_overlap(a: &PolicyTargets, b: &PolicyTargets) -> bool {
+    if is_global(a) || is_global(b) {
+        return true;
+    }
+    if a.users.iter().any(|u| b.users.contains(u)) {
+        return true;
+    }
+    if a.user_groups.iter().any(|g| b.user_groups.contains(g)) {
+        return true;
+    }
+    networks(a).any(|x| networks(b).any(|y| x.contains(y.network()) || y.contains(x.network())))
+}
+
+/// Compare the precedence of two policies, `Less` if `a` is applied first
+pub fn compare(
+    a: &SecurityPolicy,
+    a_specificity: Specificity,
+    b: &SecurityPolicy,
+    b_specificity: Specificity,
+) -> (Ordering, Resolution) {
+    let priority = (b.spec.priority as u32).cmp(&(a.spec.priority as u32));
+    if priority != Ordering::Equal {
+        return (priority, Resolution::Priority);
+    }
+    let specificity = b_specificity.cmp(&a_specificity);
+    if specificity != Ordering::Equal {
+        return (specificity, Resolution::Specificity);
+    }
+    (a.metadata.name.cmp(&b.metadata.name), Resolution::Name)
+}
@@ -0,0 +1,242 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2025 ByteDance and/or its affiliates.
+ */
+
+//! Activation of the policies by time of day and day of week
+//!
+//! The `work_hours` and `after_hours` windows of the time restrictions name
+//! the policies they activate, a window without policies activating the
+//! policy it is declared in. A policy named by some windows only applies
+//! while one of them is open, the other policies always apply. The wall
+//! clock times of the windows are resolved in their timezone to the instants
+//! they open and close at, day by day, so that daylight saving time changes
+//! neither move nor repeat them: a time skipped when the clocks go forward
+//! is the end of the gap, a time repeated when they go back is its first
+//! occurrence.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Datelike, Duration, LocalResult, NaiveDate, NaiveTime, TimeZone, Timelike, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+use crate::policy::{SecurityPolicy, TimePolicy};
+
+const SECONDS_PER_DAY: u32 = 86_400;
+
+/// Kind of a time window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WindowKind {
+    WorkHours,
+    AfterHours,
+}
+
+impl WindowKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WindowKind::WorkHours => "work_hours",
+            WindowKind::AfterHours => "after_hours",
+        }
+    }
+}
+
+/// Get the time windows declared by a policy
+pub fn time_policies(policy: &SecurityPolicy) -> impl Iterator<Item = (WindowKind, &TimePolicy)> + '_ {
+    policy.spec.traffic_control.as_ref()
+        .and_then(|t| t.time_restrictions.as_ref())
+        .into_iter()
+        .flat_map(|r| [(WindowKind::WorkHours, r.work_hours.as_ref()), (WindowKind::AfterHours, r.after_hours.as_ref())])
+        .filter_map(|(kind, p)| p.map(|p| (kind, p)))
+}
+
+fn parse_weekday(s: &str) -> Result<usize> {
+    Weekday::from_str(s)
+        .map(|d| d.num_days_from_monday() as usize)
+        .map_err(|_| anyhow!("Invalid day '{}'", s))
+}
+
+/// Parse the days of a window, indexed from Monday, all of them if empty
+///
+/// A day is a name like `monday` or `mon`, a range like `mon-fri`, which may
+/// wrap over the week end, `weekdays`, `weekend` or `daily`.
+fn parse_days(days: &[String]) -> Result<[bool; 7]> {
+    if days.is_empty() {
+        return Ok([true; 7]);
+    }
+    let mut mask = [false; 7];
+    for day in days {
+        let day = day.trim().to_ascii_lowercase();
+        match day.as_str() {
+            "daily" | "all" | "*" => mask = [true; 7],
+            "weekdays" => mask[..5].fill(true),
+            "weekend" | "weekends" => mask[5..].fill(true),
+            _ => match day.split_once('-') {
+                Some((from, to)) => {
+                    let (from, to) = (parse_weekday(from.trim())?, parse_weekday(to.trim())?);
+                    let mut d = from;
+                    loop {
+                        mask[d] = true;
+                        if d == to {
+                            break;
+                        }
+                        d = (d + 1) % 7;
+                    }
+                }
+                None => mask[parse_weekday(&day)?] = true,
+            },
+        }
+    }
+    Ok(mask)
+}
+
+/// Parse a wall clock time to the seconds since midnight, `24:00` being the
+/// end of the day
+fn parse_time(s: &str) -> Result<u32> {
+    let s = s.trim();
+    if s == "24:00" {
+        return Ok(SECONDS_PER_DAY);
+    }
+    NaiveTime::parse_from_str(s, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(s, "%H:%M:%S"))
+        .map(|t| t.num_seconds_from_midnight())
+        .map_err(|_| anyhow!("Invalid time '{}', expected HH:MM", s))
+}
+
+/// A time window compiled from its config
+#[derive(Debug, Clone)]
+pub struct TimeWindow {
+    pub kind: WindowKind,
+    days: [bool; 7],
+    /// Wall clock times it opens and closes at, in seconds since midnight,
+    /// closing the next day if not after the opening
+    start: u32,
+    end: u32,
+    timezone: Tz,
+}
+
+impl TimeWindow {
+    pub fn compile(kind: WindowKind, config: &TimePolicy) -> Result<Self> {
+        let (start, end) = config.time_range.split_once('-')
+            .ok_or_else(|| anyhow!("Invalid time range '{}', expected HH:MM-HH:MM", config.time_range))?;
+        let (start, end) = (parse_time(start)?, parse_time(end)?);
+        if start == end || start == SECONDS_PER_DAY {
+            return Err(anyhow!("Empty time range '{}'", config.time_range));
+        }
+        let timezone = match config.timezone.trim() {
+            "" => Tz::UTC,
+            name => Tz::from_str(name).map_err(|_| anyhow!("Unknown timezone '{}'", name))?,
+        };
+        Ok(Self {
+            kind,
+            days: parse_days(&config.days)?,
+            start,
+            end,
+            timezone,
+        })
+    }
+
+    /// Get the instant of a wall clock time of a day
+    fn instant(&self, date: NaiveDate, seconds: u32) -> DateTime<Utc> {
+        let midnight = date.and_hms_opt(0, 0, 0).expect("midnight is a valid time");
+        let mut local = midnight + Duration::seconds(seconds as i64);
+        // the clocks never go forward by more than a day
+        for _ in 0..=24 * 60 {
+            match self.timezone.from_local_datetime(&local) {
+                LocalResult::Single(t) | LocalResult::Ambiguous(t, _) => return t.with_timezone(&Utc),
+                LocalResult::None => local += Duration::minutes(1),
+            }
+        }
+        Utc.from_utc_datetime(&local)
+    }
+
+    /// Get the instants the window opened on the day opens and closes at
+    fn occurrence(&self, date: NaiveDate) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        if !self.days[date.weekday().num_days_from_monday() as usize] {
+            return None;
+        }
+        let end_date = if self.end > self.start { date } else { date.succ_opt()? };
+        Some((self.instant(date, self.start), self.instant(end_date, self.end)))
+    }
+
+    fn local_date(&self, at: DateTime<Utc>) -> NaiveDate {
+        at.with_timezone(&self.timezone).date_naive()
+    }
+
+    /// Check if the window is open at the instant
+    pub fn contains(&self, at: DateTime<Utc>) -> bool {
+        let today = self.local_date(at);
+        // a window opened the day before may still be open
+        [today.pred_opt(), Some(today)].into_iter()
+            .flatten()
+            .filter_map(|d| self.occurrence(d))
+            .any(|(start, end)| start <= at && at < end)
+    }
+
+    /// Get the next instant the window opens or closes at, after the given one
+    pub fn next_transition(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let today = self.local_date(after);
+        // a window opens at least once a week
+        (-1..=8)
+            .filter_map(|n| today.checked_add_signed(Duration::days(n)))
+            .filter_map(|d| self.occurrence(d))
+            .flat_map(|(start, end)| [start, end])
+            .filter(|t| *t > after)
+            .min()
+    }
+}
+
+/// Time windows of the policies, by the name of the policies they activate
+#[derive(Debug, Clone, Default)]
+pub struct ActivationSchedule {
+    windows: HashMap<String, Vec<TimeWindow>>,
+}
+
+impl ActivationSchedule {
+    /// Compile the time restrictions of the policies
+    pub fn compile<'a>(policies: impl IntoIterator<Item = &'a SecurityPolicy>) -> Result<Self> {
+        let mut windows: HashMap<String, Vec<TimeWindow>> = HashMap::new();
+        for policy in policies {
+            for (kind, config) in time_policies(policy) {
+                let window = TimeWindow::compile(kind, config)
+                    .map_err(|e| anyhow!("Policy {} {}: {}", policy.metadata.name, kind.as_str(), e))?;
+                if config.policies.is_empty() {
+                    windows.entry(policy.metadata.name.clone()).or_default().push(window);
+                } else {
+                    for name in &config.policies {
+                        windows.entry(name.clone()).or_default().push(window.clone());
+                    }
+                }
+            }
+        }
+        Ok(Self { windows })
+    }
+
+    /// Check if the policy only applies while some windows are open
+    pub fn is_restricted(&self, policy_name: &str) -> bool {
+        self.windows.contains_key(policy_name)
+    }
+
+    /// Get the open window activating the policy, `None` if there is none
+    pub fn open_window(&self, policy_name: &str, at: DateTime<Utc>) -> Option<&TimeWindow> {
+        self.windows.get(policy_name)?
+            .iter()
+            .find(|w| w.contains(at))
+    }
+
+    /// Check if the policy applies at the instant
+    pub fn is_active(&self, policy_name: &str, at: DateTime<Utc>) -> bool {
+        !self.is_restricted(policy_name) || self.open_window(policy_name, at).is_some()
+    }
+
+    /// Get the next instant a window opens or closes at, after the given one
+    pub fn next_transition(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.windows.values()
+            .flatten()
+            .filter_map(|w| w.next_transition(after))
+            .min()
+    }
+}
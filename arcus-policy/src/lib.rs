@@ -12,12 +12,6 @@
 pub mod policy;
 pub mod config;
 pub mod engine;
-pub mod filtering;
-pub mod security;
-pub mod traffic;
-pub mod user;
-pub mod monitoring;
-pub mod integration;
 
 pub use policy::PolicyManager;
 pub use config::ConfigGenerator;
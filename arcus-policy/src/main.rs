@@ -0,0 +1,178 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2025 ByteDance and/or its affiliates.
+ */
+
+//! Command line of the policy framework
+//!
+//! `arcus-policy render` compiles the policy documents of a directory into
+//! the G3proxy and G3ICAP config files and prints a summary as one line of
+//! JSON: the number of policies, the notes, and the names of the G3proxy
+//! escapers, user groups, auditors and servers, to be reloaded once the
+//! files are installed. `arcus-policy diff` prints what would change in the
+//! files already deployed.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use clap::{Args, Parser, Subcommand};
+use serde::Serialize;
+use uuid::Uuid;
+
+use arcus_policy::config::generator::{G3ICAP_FILE, G3PROXY_FILE};
+use arcus_policy::config::ConfigContext;
+use arcus_policy::policy::{PolicyCollection, SecurityPolicy};
+use arcus_policy::ConfigGenerator;
+
+#[derive(Parser)]
+#[command(name = "arcus-policy", version, about = "Arcus policy compiler")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Write the config files generated from the policies
+    Render {
+        #[command(flatten)]
+        input: Input,
+        /// Directory the g3proxy.yaml and g3icap.yaml files are written to
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Print the changes of the deployed config files
+    Diff {
+        #[command(flatten)]
+        input: Input,
+        /// Directory of the deployed g3proxy.yaml and g3icap.yaml files
+        #[arg(long)]
+        deployed: PathBuf,
+    },
+}
+
+#[derive(Args)]
+struct Input {
+    /// Directory of the policy documents, the .yaml, .yml and .json files
+    #[arg(long)]
+    policies: PathBuf,
+    /// Name of the policy collection, in the header of the generated files
+    #[arg(long, default_value = "arcus")]
+    collection: String,
+    /// Path of the policy files, as seen by G3ICAP
+    #[arg(long)]
+    policy_path: Option<String>,
+    /// Address of the G3ICAP server, as seen by G3proxy
+    #[arg(long)]
+    icap_address: Option<String>,
+}
+
+impl Input {
+    fn generator(&self) -> ConfigGenerator {
+        let mut context = ConfigContext::default();
+        if let Some(path) = &self.policy_path {
+            context.policy_path = path.clone();
+        }
+        if let Some(address) = &self.icap_address {
+            context.icap_address = address.clone();
+        }
+        ConfigGenerator::new(context)
+    }
+}
+
+/// What `render` prints once the files are written
+#[derive(Serialize)]
+struct RenderSummary {
+    policies: usize,
+    files: Vec<&'static str>,
+    notes: Vec<String>,
+    g3proxy: G3proxyEntities,
+}
+
+/// Names of the G3proxy entities in the generated config
+#[derive(Serialize)]
+struct G3proxyEntities {
+    escaper: Vec<String>,
+    user_group: Vec<String>,
+    auditor: Vec<String>,
+    server: Vec<String>,
+}
+
+fn main() {
+    let cli = Cli::parse();
+    if let Err(e) = run(cli.command) {
+        eprintln!("error: {e:#}");
+        std::process::exit(1);
+    }
+}
+
+fn run(command: Command) -> Result<()> {
+    match command {
+        Command::Render { input, out } => {
+            let policies = load_policies(&input.policies, &input.collection)?;
+            let generator = input.generator();
+            let rendered = generator.render(&policies)?;
+            let proxy = generator.generate_config(&policies)?;
+
+            std::fs::create_dir_all(&out)
+                .with_context(|| format!("failed to create {}", out.display()))?;
+            for (name, content) in [(G3PROXY_FILE, &rendered.g3proxy), (G3ICAP_FILE, &rendered.g3icap)] {
+                let path = out.join(name);
+                std::fs::write(&path, content)
+                    .with_context(|| format!("failed to write {}", path.display()))?;
+            }
+
+            let summary = RenderSummary {
+                policies: policies.policies.len(),
+                files: vec![G3PROXY_FILE, G3ICAP_FILE],
+                notes: rendered.notes,
+                g3proxy: G3proxyEntities {
+                    escaper: proxy.escaper.into_iter().map(|e| e.name).collect(),
+                    user_group: proxy.user_group.into_iter().map(|u| u.name).collect(),
+                    auditor: proxy.auditor.into_iter().map(|a| a.name).collect(),
+                    server: proxy.server.into_iter().map(|s| s.name).collect(),
+                },
+            };
+            println!("{}", serde_json::to_string(&summary)?);
+        }
+        Command::Diff { input, deployed } => {
+            let policies = load_policies(&input.policies, &input.collection)?;
+            let diff = input.generator().diff(&policies, &deployed)?;
+            for file in &diff.files {
+                print!("{}", file.diff);
+            }
+            if !diff.is_empty() {
+                std::process::exit(2);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Load the policy documents of a directory, in file name order
+fn load_policies(dir: &Path, collection: &str) -> Result<PolicyCollection> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))? {
+        let path = entry?.path();
+        let is_policy = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| matches!(ext, "yaml" | "yml" | "json"));
+        if path.is_file() && is_policy {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut policies = PolicyCollection::new(collection.to_string(), "arcus-policy".to_string());
+    for path in paths {
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        // JSON documents are YAML too
+        let policy: SecurityPolicy = serde_yaml::from_str(&content)
+            .map_err(|e| anyhow!("invalid policy file {}: {}", path.display(), e))?;
+        policies.policies.insert(Uuid::new_v4(), Arc::new(policy));
+    }
+    Ok(policies)
+}
@@ -14,6 +14,8 @@ use tracing::{info, debug, error, warn};
 
 use super::{PolicyCollection, SecurityPolicy, PolicyId, PolicyMetadata, PolicyStatus};
 use crate::config::{ConfigGenerator, ConfigContext};
+use crate::engine::schedule::{self, TimeWindow};
+use crate::engine::{ConflictReport, PolicyEngine};
 
 /// Policy manager for handling policy lifecycle
 pub struct PolicyManager {
@@ -110,7 +112,7 @@ impl PolicyManager {
     /// Delete policy
     pub fn delete_policy(&mut self, collection_name: &str, policy_id: &PolicyId) -> Result<Option<Arc<SecurityPolicy>>> {
         if let Some(collection) = self.collections.get_mut(collection_name) {
-            let result = collection.remove_policy(policy_id);
+            let result = Arc::make_mut(collection).remove_policy(policy_id);
             if result.is_some() {
                 info!("Deleted policy with ID: {}", policy_id);
             }
@@ -155,6 +157,13 @@ impl PolicyManager {
         Ok(results)
     }
 
+    /// Report the conflicting and overlapping rules of a collection
+    pub fn analyze_conflicts(&self, collection_name: &str) -> Result<ConflictReport> {
+        let collection = self.collections.get(collection_name)
+            .ok_or_else(|| anyhow!("Collection not found: {}", collection_name))?;
+        Ok(PolicyEngine::analyze_conflicts(collection))
+    }
+
     /// Validate a single policy
     fn validate_policy(&self, policy: &SecurityPolicy) -> Result<()> {
         // Basic validation
@@ -186,6 +195,12 @@ impl PolicyManager {
             }
         }
 
+        // Validate time restrictions
+        for (kind, time_policy) in schedule::time_policies(policy) {
+            TimeWindow::compile(kind, time_policy)
+                .map_err(|e| anyhow!("Invalid {} time restriction: {}", kind.as_str(), e))?;
+        }
+
         Ok(())
     }
 
@@ -13,11 +13,10 @@ use uuid::Uuid;
 
 pub mod schema;
 pub mod manager;
-pub mod validator;
+pub mod presets;
 
 pub use schema::*;
 pub use manager::PolicyManager;
-pub use validator::PolicyValidator;
 
 /// Policy priority levels
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
@@ -0,0 +1,166 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2025 ByteDance and/or its affiliates.
+ */
+
+//! Built-in service presets for DLP interception
+//!
+//! A preset describes how to recognize the upload (and download) endpoints
+//! of a common SaaS application, so that DLP can be enabled for them by name
+//! instead of writing URL regexes by hand.
+
+use std::fmt;
+
+use crate::engine::PolicyRequest;
+
+/// Direction of a content transfer, seen from the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferDirection {
+    Upload,
+    Download,
+}
+
+impl TransferDirection {
+    /// Guess the transfer direction from the HTTP method
+    pub fn from_method(method: &str) -> Self {
+        match method.to_ascii_uppercase().as_str() {
+            "POST" | "PUT" | "PATCH" => Self::Upload,
+            _ => Self::Download,
+        }
+    }
+}
+
+impl fmt::Display for TransferDirection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Upload => f.write_str("upload"),
+            Self::Download => f.write_str("download"),
+        }
+    }
+}
+
+/// Built-in description of a SaaS service endpoint
+#[derive(Debug)]
+pub struct ServicePreset {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Host names, sub domains are matched too
+    pub hosts: &'static [&'static str],
+    /// URL path prefixes of the transfer endpoints
+    pub path_prefixes: &'static [&'static str],
+    /// Request headers identifying the transfer endpoints, with a value
+    /// substring to look for (empty to only require the header)
+    pub headers: &'static [(&'static str, &'static str)],
+    /// Directions DLP should inspect for this service
+    pub directions: &'static [TransferDirection],
+}
+
+/// All built-in service presets
+pub const SERVICE_PRESETS: &[ServicePreset] = &[
+    ServicePreset {
+        name: "gmail_attachments",
+        description: "Gmail attachment uploads",
+        hosts: &["mail.google.com"],
+        path_prefixes: &["/upload/", "/mail/u/"],
+        headers: &[("x-goog-upload-protocol", "")],
+        directions: &[TransferDirection::Upload],
+    },
+    ServicePreset {
+        name: "office365",
+        description: "Outlook on the web attachments and SharePoint / OneDrive for Business files",
+        hosts: &[
+            "outlook.office.com",
+            "outlook.office365.com",
+            "attachments.office.net",
+            "sharepoint.com",
+        ],
+        path_prefixes: &["/owa/", "/mail/", "/_api/", "/_layouts/15/"],
+        headers: &[("x-owa-actionname", "")],
+        directions: &[TransferDirection::Upload, TransferDirection::Download],
+    },
+    ServicePreset {
+        name: "slack",
+        description: "Slack file uploads",
+        hosts: &["slack.com", "files.slack.com"],
+        path_prefixes: &[
+            "/api/files.upload",
+            "/api/files.getUploadURLExternal",
+            "/api/files.completeUploadExternal",
+            "/upload/",
+        ],
+        headers: &[],
+        directions: &[TransferDirection::Upload, TransferDirection::Download],
+    },
+    ServicePreset {
+        name: "google_drive",
+        description: "Google Drive file uploads",
+        hosts: &["drive.google.com", "docs.google.com", "content.googleapis.com", "www.googleapis.com"],
+        path_prefixes: &["/upload/drive/", "/upload/"],
+        headers: &[("x-goog-upload-protocol", "")],
+        directions: &[TransferDirection::Upload, TransferDirection::Download],
+    },
+];
+
+/// Names standing for several presets
+pub const SERVICE_PRESET_GROUPS: &[(&str, &[&str])] = &[(
+    "webmail_uploads",
+    &["gmail_attachments", "office365", "slack", "google_drive"],
+)];
+
+impl ServicePreset {
+    /// Find a built-in preset by name
+    pub fn get(name: &str) -> Option<&'static ServicePreset> {
+        SERVICE_PRESETS.iter().find(|p| p.name == name)
+    }
+
+    /// Expand preset and group names, without duplicates
+    pub fn resolve(names: &[String]) -> Result<Vec<&'static ServicePreset>, String> {
+        let mut presets: Vec<&'static ServicePreset> = Vec::new();
+        for name in names {
+            let expanded: Vec<&str> = match SERVICE_PRESET_GROUPS.iter().find(|(g, _)| g == name) {
+                Some((_, members)) => members.to_vec(),
+                None => vec![name.as_str()],
+            };
+            for member in expanded {
+                let preset = Self::get(member)
+                    .ok_or_else(|| format!("unknown DLP service preset: {}", member))?;
+                if !presets.iter().any(|p| p.name == preset.name) {
+                    presets.push(preset);
+                }
+            }
+        }
+        Ok(presets)
+    }
+
+    /// Check if the request is a transfer to this service in the given direction
+    pub fn matches(&self, request: &PolicyRequest, direction: TransferDirection) -> bool {
+        if !self.directions.contains(&direction) {
+            return false;
+        }
+
+        let Ok(url) = url::Url::parse(&request.url) else {
+            return false;
+        };
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let host = host.to_ascii_lowercase();
+        let host_matched = self
+            .hosts
+            .iter()
+            .any(|h| host == *h || host.ends_with(&format!(".{}", h)));
+        if !host_matched {
+            return false;
+        }
+
+        if self.path_prefixes.iter().any(|p| url.path().starts_with(p)) {
+            return true;
+        }
+        self.headers.iter().any(|(name, value)| {
+            request
+                .headers
+                .iter()
+                .any(|(k, v)| k.eq_ignore_ascii_case(name) && v.contains(value))
+        })
+    }
+}
@@ -137,6 +137,10 @@ pub struct DataLossPreventionConfig {
     pub scan_uploads: bool,
     pub scan_downloads: bool,
     pub sensitive_data_patterns: Vec<SensitiveDataPattern>,
+    /// Built-in service presets (or preset groups such as `webmail_uploads`)
+    /// restricting DLP to the matching endpoints, all traffic if empty
+    #[serde(default)]
+    pub service_presets: Vec<String>,
 }
 
 /// Sensitive data pattern
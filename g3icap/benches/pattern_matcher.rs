@@ -0,0 +1,62 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Regex pattern matching with many rules
+//!
+//! Compares matching the patterns one by one with the regex set of the
+//! `regex` engine and, when built with the `hyperscan` feature, with the
+//! Hyperscan database of the `auto` engine.
+
+#![feature(test)]
+
+extern crate test;
+use test::Bencher;
+
+use regex::Regex;
+
+use g3icap::modules::matcher::{PatternEngine, PatternMatcher};
+
+const PATTERNS: usize = 2000;
+
+fn patterns() -> Vec<Regex> {
+    (0..PATTERNS)
+        .map(|i| Regex::new(&format!(r"(?i)blocked-{i}[a-z]*\.example\.(com|net)")).unwrap())
+        .collect()
+}
+
+fn haystack() -> String {
+    let mut haystack = "http://www.example.org/search?q=".to_string();
+    haystack.push_str(&"allowed+words+".repeat(64));
+    haystack
+}
+
+#[bench]
+fn sequential(b: &mut Bencher) {
+    let patterns = patterns();
+    let haystack = haystack();
+    b.iter(|| patterns.iter().position(|p| p.is_match(&haystack)));
+}
+
+#[bench]
+fn regex_set(b: &mut Bencher) {
+    let matcher = PatternMatcher::new(patterns(), PatternEngine::Regex).unwrap();
+    let haystack = haystack();
+    b.iter(|| matcher.find(&haystack).map(|m| m.0));
+}
+
+#[bench]
+fn auto(b: &mut Bencher) {
+    let matcher = PatternMatcher::new(patterns(), PatternEngine::Auto).unwrap();
+    let haystack = haystack();
+    b.iter(|| matcher.find(&haystack).map(|m| m.0));
+}
+
+#[bench]
+fn auto_last_matches(b: &mut Bencher) {
+    let matcher = PatternMatcher::new(patterns(), PatternEngine::Auto).unwrap();
+    let mut haystack = haystack();
+    haystack.push_str(&format!("blocked-{}x.example.com", PATTERNS - 1));
+    b.iter(|| matcher.find(&haystack).map(|m| m.0));
+}
@@ -1,7 +1,24 @@
 //! Build script for G3 ICAP Server
 
+use std::process::Command;
+
 fn main() {
     g3_build_env::check_basic();
     g3_build_env::check_openssl();
     g3_build_env::check_rustls_provider();
+    check_git_commit();
+}
+
+/// Record the git commit, if built from a git checkout
+fn check_git_commit() {
+    let Ok(output) = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+    else {
+        return;
+    };
+    if output.status.success() {
+        let commit = String::from_utf8_lossy(&output.stdout);
+        println!("cargo:rustc-env=G3ICAP_GIT_COMMIT={}", commit.trim());
+    }
 }
@@ -15,6 +15,7 @@ use bytes::Bytes;
 use g3icap::modules::content_filter::{ContentFilterModule, ContentFilterConfig, BlockingAction};
 use g3icap::modules::{IcapModule, ModuleConfig};
 use g3icap::protocol::common::{IcapMethod, IcapRequest};
+use g3icap::transaction::TransactionCtx;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -85,7 +86,7 @@ async fn example_basic_filtering() -> Result<(), Box<dyn std::error::Error>> {
 
     for (url, body, should_block) in test_cases {
         let request = create_test_request(url, body);
-        let response = module.handle_reqmod(&request).await?;
+        let response = module.handle_reqmod(&request, &TransactionCtx::for_request(&request)).await?;
         
         let blocked = response.status == http::StatusCode::FORBIDDEN;
         let status = if blocked == should_block { "✓" } else { "✗" };
@@ -139,7 +140,7 @@ async fn example_advanced_patterns() -> Result<(), Box<dyn std::error::Error>> {
 
     for (url, body, should_block) in test_cases {
         let request = create_test_request(url, body);
-        let response = module.handle_reqmod(&request).await?;
+        let response = module.handle_reqmod(&request, &TransactionCtx::for_request(&request)).await?;
         
         let blocked = response.status == http::StatusCode::FORBIDDEN;
         let status = if blocked == should_block { "✓" } else { "✗" };
@@ -178,7 +179,7 @@ async fn example_blocking_actions() -> Result<(), Box<dyn std::error::Error>> {
         module.init(&module_config).await?;
 
         let request = create_test_request("http://example.com/malware", "clean content");
-        let response = module.handle_reqmod(&request).await?;
+        let response = module.handle_reqmod(&request, &TransactionCtx::for_request(&request)).await?;
 
         println!("  {} Action: {} | Status: {} | Body: {}", 
             "✓", action_name, response.status, 
@@ -227,7 +228,7 @@ async fn example_performance_testing() -> Result<(), Box<dyn std::error::Error>>
         };
 
         let request = create_test_request(url, body);
-        let _response = module.handle_reqmod(&request).await?;
+        let _response = module.handle_reqmod(&request, &TransactionCtx::for_request(&request)).await?;
     }
 
     let duration = start_time.elapsed();
@@ -280,7 +281,7 @@ async fn example_statistics_monitoring() -> Result<(), Box<dyn std::error::Error
         let mut request = create_test_request(url, body);
         request.headers.insert("content-type", content_type.parse().unwrap());
         
-        let response = module.handle_reqmod(&request).await?;
+        let response = module.handle_reqmod(&request, &TransactionCtx::for_request(&request)).await?;
         let blocked = response.status == http::StatusCode::FORBIDDEN;
         
         println!("  URL: {} | Content-Type: {} | Blocked: {}", 
@@ -342,6 +343,7 @@ fn create_module_config(name: &str) -> ModuleConfig {
         load_timeout: Duration::from_secs(5),
         max_memory: 1024 * 1024,
         sandbox: true,
+        metrics: g3icap::modules::metrics::MetricsRegistry::global().module(name),
     }
 }
 
@@ -356,11 +358,20 @@ fn create_default_config() -> ContentFilterConfig {
         blocked_extensions: Vec::new(),
         max_file_size: Some(10 * 1024 * 1024), // 10MB
         regex_cache_size: 1000,
+        pattern_engine: Default::default(),
         case_insensitive: true,
         enable_regex: true,
         blocking_action: BlockingAction::Forbidden,
         custom_message: None,
         enable_logging: true,
         enable_metrics: true,
+        user_rules: Default::default(),
+        group_rules: Default::default(),
+        detection_capture: Default::default(),
+        block_page: Default::default(),
+        blocked_file_types: Vec::new(),
+        file_type_detection: Default::default(),
+        confusable_domains: Default::default(),
+        ai_inspection: Default::default(),
     }
 }
@@ -0,0 +1,104 @@
+//! Embedded ICAP Engine Example
+//!
+//! This example runs the ICAP engine inside another service instead of the
+//! g3icap daemon, here a mail gateway scanning the attachments of the
+//! messages it relays. The gateway builds the server config in code, owns
+//! the statistics, runs the server in its own runtime and stops it with its
+//! own shutdown, without the daemon controller, signal handlers or config
+//! file.
+
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use g3_types::metrics::NodeName;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use g3icap::config::server::icap_server::IcapServerConfig;
+use g3icap::{IcapServer, IcapStats};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("G3 ICAP Embedded Example");
+    println!("========================");
+
+    // The config of the engine, as the gateway would derive it from its own
+    let mut config = IcapServerConfig::new(NodeName::from_str("mail-gateway")?);
+    config.host = "127.0.0.1".to_string();
+    // any free port, the gateway talks to the engine over loopback only
+    config.port = 0;
+    config.max_connections = 64;
+
+    // The statistics belong to the gateway, next to its own counters
+    let stats = Arc::new(IcapStats::new());
+    let mut server = IcapServer::new_with_stats(config, stats.clone())?;
+    let shutdown = server.shutdown_handle();
+    let handle = server.clone();
+    let engine = tokio::spawn(async move { server.start().await });
+
+    let addr = wait_listening(&handle).await?;
+    println!("ICAP engine listening on {addr}");
+
+    // Scan the attachment of a relayed message
+    let attachment = "Quarterly figures attached, see the spreadsheet.";
+    let status = scan_attachment(addr, "report.txt", attachment).await?;
+    println!("Scan of report.txt: {status}");
+
+    println!("\nEngine statistics:");
+    println!("  Total requests: {}", stats.total_requests());
+    println!("  RESPMOD requests: {}", stats.respmod_requests());
+    println!("  Blocked requests: {}", stats.blocked_requests());
+
+    // Stop the engine along with the gateway
+    shutdown.cancel();
+    engine.await??;
+    println!("\nICAP engine stopped");
+
+    Ok(())
+}
+
+/// Wait for the engine to bind its listener
+async fn wait_listening(server: &IcapServer) -> Result<SocketAddr, Box<dyn std::error::Error>> {
+    for _ in 0..50 {
+        if let Some(addr) = server.local_addr() {
+            return Ok(addr);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    Err("the ICAP engine did not start listening".into())
+}
+
+/// Send an attachment to the engine as a RESPMOD and return the status line
+async fn scan_attachment(
+    addr: SocketAddr,
+    file_name: &str,
+    content: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let req_hdr = format!("GET /attachments/{file_name} HTTP/1.1\r\nHost: mail-gateway\r\n\r\n");
+    let res_hdr = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\n\r\n",
+        content.len()
+    );
+    let request = format!(
+        "RESPMOD icap://{addr}/respmod ICAP/1.0\r\n\
+         Host: {addr}\r\n\
+         User-Agent: Mail-Gateway\r\n\
+         Encapsulated: req-hdr=0, res-hdr={}, res-body={}\r\n\
+         \r\n\
+         {req_hdr}{res_hdr}{:x}\r\n{content}\r\n0\r\n\r\n",
+        req_hdr.len(),
+        req_hdr.len() + res_hdr.len(),
+        content.len(),
+    );
+
+    let mut stream = TcpStream::connect(addr).await?;
+    stream.write_all(request.as_bytes()).await?;
+    stream.flush().await?;
+
+    let mut response = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut response)).await??;
+    let response = String::from_utf8_lossy(&response);
+    Ok(response.lines().next().unwrap_or_default().to_string())
+}
@@ -17,6 +17,7 @@ use g3icap::services::{ServiceConfig, ServiceManager, LoadBalancingStrategy};
 use g3icap::pipeline::{ContentPipeline, PipelineConfig, stages::{LoggingStage, ContentFilterStage, AntivirusStage}};
 use g3icap::protocol::common::{IcapMethod, IcapRequest, IcapResponse};
 use g3icap::opts::ProcArgs;
+use g3icap::transaction::TransactionCtx;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -36,6 +37,7 @@ async fn main() -> Result<()> {
         load_timeout: Duration::from_secs(30),
         max_memory: 100 * 1024 * 1024, // 100MB
         sandbox: true,
+        metrics: g3icap::modules::metrics::MetricsRegistry::global().module("example"),
     };
     
     let module_registry = ModuleRegistry::new(module_config);
@@ -43,7 +45,7 @@ async fn main() -> Result<()> {
     
     // 2. Initialize Service Manager
     println!("\n🔧 Initializing Service Manager...");
-    let service_manager = ServiceManager::new();
+    let service_manager = ServiceManager::with_registry(module_registry);
     
     // Register echo service
     let echo_config = ServiceConfig {
@@ -120,7 +122,7 @@ async fn main() -> Result<()> {
     
     // Process through echo service
     println!("📤 Processing request through echo service...");
-    match service_manager.handle_request(&sample_request).await {
+    match service_manager.handle_request(&sample_request, &TransactionCtx::for_request(&sample_request)).await {
         Ok(response) => {
             println!("✅ Echo service response: {} {:?}", response.status, response.version);
             println!("   Headers: {} headers", response.headers.len());
@@ -133,7 +135,7 @@ async fn main() -> Result<()> {
     
     // Process through logging service
     println!("\n📤 Processing request through logging service...");
-    match service_manager.handle_request(&sample_request).await {
+    match service_manager.handle_request(&sample_request, &TransactionCtx::for_request(&sample_request)).await {
         Ok(response) => {
             println!("✅ Logging service response: {} {:?}", response.status, response.version);
         }
@@ -145,7 +147,8 @@ async fn main() -> Result<()> {
     // 5. Demonstrate Pipeline Processing
     println!("\n🔄 Demonstrating Pipeline Processing...");
     
-    match pipeline.process_request(sample_request.clone()).await {
+    let ctx = TransactionCtx::for_request(&sample_request);
+    match pipeline.process_request(sample_request.clone(), &ctx).await {
         Ok(response) => {
             println!("✅ Pipeline processing completed");
             println!("   Response: {} {:?}", response.status, response.version);
@@ -189,7 +192,7 @@ async fn main() -> Result<()> {
     println!("\n⚡ Simulating load...");
     for i in 0..5 {
         let request = create_sample_request();
-        match service_manager.handle_request(&request).await {
+        match service_manager.handle_request(&request, &TransactionCtx::for_request(&request)).await {
             Ok(_) => println!("   Request {}: ✅ Success", i + 1),
             Err(e) => println!("   Request {}: ❌ Error: {}", i + 1, e),
         }
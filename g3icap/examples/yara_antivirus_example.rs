@@ -8,6 +8,7 @@ use g3icap::modules::antivirus::{
 };
 use g3icap::modules::{ModuleConfig, IcapModule};
 use g3icap::protocol::common::{IcapMethod, IcapRequest};
+use g3icap::transaction::TransactionCtx;
 use std::path::PathBuf;
 use std::time::Duration;
 use std::collections::HashMap;
@@ -72,6 +73,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             "https://rules.yara-rules.com".to_string(),
             "https://github.com/Yara-Rules/rules".to_string(),
         ],
+        threat_intel_batch: Default::default(),
+        secondary_engine: None,
+        hedging: Default::default(),
+        verdict_cache: None,
         yara_config: Some(yara_config),
     };
 
@@ -88,6 +93,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         load_timeout: std::time::Duration::from_secs(30),
         max_memory: 1024 * 1024 * 100, // 100MB
         sandbox: false,
+        metrics: g3icap::modules::metrics::MetricsRegistry::global().module("yara_antivirus"),
     };
 
     if let Err(e) = antivirus_module.init(&module_config).await {
@@ -113,7 +119,7 @@ async fn test_antivirus_module(module: &AntivirusModule) -> Result<(), Box<dyn s
     let clean_content = b"This is a clean document with no malicious content.";
     let clean_request = create_test_request(clean_content, Some("clean.txt"));
     
-    match module.handle_reqmod(&clean_request).await {
+    match module.handle_reqmod(&clean_request, &TransactionCtx::for_request(&clean_request)).await {
         Ok(response) => {
             if response.status == 200 {
                 println!("✓ Clean content passed - no threats detected");
@@ -131,7 +137,7 @@ async fn test_antivirus_module(module: &AntivirusModule) -> Result<(), Box<dyn s
     let malware_content = b"This file contains malware and virus code for testing purposes.";
     let malware_request = create_test_request(malware_content, Some("malware.exe"));
     
-    match module.handle_reqmod(&malware_request).await {
+    match module.handle_reqmod(&malware_request, &TransactionCtx::for_request(&malware_request)).await {
         Ok(response) => {
             if response.status == 403 {
                 println!("✓ Malware content blocked successfully");
@@ -149,7 +155,7 @@ async fn test_antivirus_module(module: &AntivirusModule) -> Result<(), Box<dyn s
     let phishing_content = b"Urgent: Verify your account immediately. Click here to confirm your identity.";
     let phishing_request = create_test_request(phishing_content, Some("phishing.html"));
     
-    match module.handle_reqmod(&phishing_request).await {
+    match module.handle_reqmod(&phishing_request, &TransactionCtx::for_request(&phishing_request)).await {
         Ok(response) => {
             if response.status == 403 {
                 println!("✓ Phishing content blocked successfully");
@@ -167,7 +173,7 @@ async fn test_antivirus_module(module: &AntivirusModule) -> Result<(), Box<dyn s
     let ransomware_content = b"Your files have been encrypted. Pay the ransom to decrypt your files.";
     let ransomware_request = create_test_request(ransomware_content, Some("ransomware.txt"));
     
-    match module.handle_reqmod(&ransomware_request).await {
+    match module.handle_reqmod(&ransomware_request, &TransactionCtx::for_request(&ransomware_request)).await {
         Ok(response) => {
             if response.status == 403 {
                 println!("✓ Ransomware content blocked successfully");
@@ -185,7 +191,7 @@ async fn test_antivirus_module(module: &AntivirusModule) -> Result<(), Box<dyn s
     let powershell_content = b"powershell.exe -WindowStyle Hidden -EncodedCommand Invoke-Expression";
     let powershell_request = create_test_request(powershell_content, Some("script.ps1"));
     
-    match module.handle_reqmod(&powershell_request).await {
+    match module.handle_reqmod(&powershell_request, &TransactionCtx::for_request(&powershell_request)).await {
         Ok(response) => {
             if response.status == 403 {
                 println!("✓ Suspicious PowerShell script blocked successfully");
@@ -0,0 +1,66 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Ring buffer of the latest audit events
+//!
+//! The events logged through the audit operations are kept in memory too, so
+//! that the control channel can show what happened recently without access
+//! to the audit log destination.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use super::ops::AuditEvent;
+
+/// Number of events kept, the oldest ones are dropped first
+pub const CAPACITY: usize = 256;
+
+static RECENT: Mutex<VecDeque<AuditEvent>> = Mutex::new(VecDeque::new());
+
+/// Keep a copy of the event
+pub fn push(event: &AuditEvent) {
+    let mut recent = RECENT.lock().unwrap();
+    if recent.len() >= CAPACITY {
+        recent.pop_front();
+    }
+    recent.push_back(event.clone());
+}
+
+/// Get the latest events, the oldest first
+pub fn tail(count: usize) -> Vec<AuditEvent> {
+    let recent = RECENT.lock().unwrap();
+    let skip = recent.len().saturating_sub(count);
+    recent.iter().skip(skip).cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::ops::{AuditEventType, AuditSeverity};
+
+    #[test]
+    fn ring() {
+        let event = |n: usize| AuditEvent {
+            timestamp: n as u64,
+            event_type: AuditEventType::ConfigChanged,
+            message: format!("event {n}"),
+            details: String::new(),
+            client_ip: None,
+            user_agent: None,
+            request_uri: None,
+            response_status: None,
+            metadata: Default::default(),
+            severity: AuditSeverity::Info,
+        };
+        for n in 0..CAPACITY + 10 {
+            push(&event(n));
+        }
+        let last = tail(3);
+        let times: Vec<u64> = last.iter().map(|e| e.timestamp).collect();
+        let n = (CAPACITY + 10) as u64;
+        assert_eq!(times, [n - 3, n - 2, n - 1]);
+        assert_eq!(tail(usize::MAX).len(), CAPACITY);
+    }
+}
@@ -0,0 +1,1198 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Kafka sink of the audit records
+//!
+//! A minimal producer speaking the Kafka protocol: the partition leaders are
+//! found with a Metadata (v1) request to the bootstrap brokers, and the
+//! records are sent as v2 record batches in Produce (v3) requests, to the
+//! partitions of a topic in turn. Batches which can not be delivered are
+//! appended to a spill file per topic and sent again before the next batch
+//! of the topic once the brokers are reachable.
+//!
+//! The connections to the brokers may use TLS, and are authenticated with a
+//! SaslHandshake (v1) then SaslAuthenticate (v0) requests if SASL is set, with
+//! the PLAIN or the SCRAM-SHA-256/512 mechanisms.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use rustls::{ClientConfig, ClientConnection, StreamOwned};
+use rustls_pki_types::ServerName;
+
+use super::sink::{AuditLine, AuditSink};
+use crate::config::server::audit::{
+    KafkaCompression, KafkaSaslConfig, KafkaSaslMechanism, KafkaSinkConfig,
+};
+
+const API_PRODUCE: i16 = 0;
+const API_METADATA: i16 = 3;
+const API_SASL_HANDSHAKE: i16 = 17;
+const API_SASL_AUTHENTICATE: i16 = 36;
+const PRODUCE_VERSION: i16 = 3;
+const METADATA_VERSION: i16 = 1;
+const SASL_HANDSHAKE_VERSION: i16 = 1;
+const SASL_AUTHENTICATE_VERSION: i16 = 0;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+const PRODUCE_TIMEOUT_MS: i32 = 5000;
+const MAX_RESPONSE_SIZE: usize = 16 * 1024 * 1024;
+
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x82f6_3b78
+            } else {
+                crc >> 1
+            };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32C (Castagnoli) checksum, as used by the record batches
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for b in data {
+        crc = CRC32C_TABLE[((crc ^ u32::from(*b)) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+fn unix_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Big-endian encoding of the protocol fields
+#[derive(Default)]
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn i8(&mut self, v: i8) {
+        self.0.push(v as u8);
+    }
+
+    fn i16(&mut self, v: i16) {
+        self.0.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn i32(&mut self, v: i32) {
+        self.0.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn i64(&mut self, v: i64) {
+        self.0.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn string(&mut self, s: &str) {
+        self.i16(s.len() as i16);
+        self.0.extend_from_slice(s.as_bytes());
+    }
+
+    fn bytes(&mut self, b: &[u8]) {
+        self.i32(b.len() as i32);
+        self.0.extend_from_slice(b);
+    }
+
+    /// Zigzag variable length integer of the records
+    fn varint(&mut self, v: i64) {
+        let mut z = ((v << 1) ^ (v >> 63)) as u64;
+        while z >= 0x80 {
+            self.0.push((z as u8) | 0x80);
+            z >>= 7;
+        }
+        self.0.push(z as u8);
+    }
+}
+
+struct Decoder<'a>(&'a [u8]);
+
+impl Decoder<'_> {
+    fn take(&mut self, n: usize) -> io::Result<&[u8]> {
+        if self.0.len() < n {
+            return Err(invalid_data("truncated kafka response"));
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn i8(&mut self) -> io::Result<i8> {
+        Ok(self.take(1)?[0] as i8)
+    }
+
+    fn i16(&mut self) -> io::Result<i16> {
+        Ok(i16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i64(&mut self) -> io::Result<i64> {
+        Ok(i64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> io::Result<Option<String>> {
+        let len = self.i16()?;
+        if len < 0 {
+            return Ok(None);
+        }
+        let s = self.take(len as usize)?;
+        Ok(Some(String::from_utf8_lossy(s).into_owned()))
+    }
+
+    fn bytes(&mut self) -> io::Result<Option<&[u8]>> {
+        let len = self.i32()?;
+        if len < 0 {
+            return Ok(None);
+        }
+        self.take(len as usize).map(Some)
+    }
+
+    fn array_len(&mut self) -> io::Result<usize> {
+        Ok(self.i32()?.max(0) as usize)
+    }
+}
+
+/// Client side of a SCRAM exchange, as in RFC 5802
+struct Scram {
+    digest: MessageDigest,
+    password: String,
+    nonce: String,
+    client_first_bare: String,
+    /// Set once the first message of the server is answered
+    auth_message: String,
+    salted_password: Vec<u8>,
+}
+
+impl Scram {
+    fn new(sasl: &KafkaSaslConfig, nonce: String) -> Self {
+        let digest = match sasl.mechanism {
+            KafkaSaslMechanism::ScramSha512 => MessageDigest::sha512(),
+            _ => MessageDigest::sha256(),
+        };
+        let username = sasl.username.replace('=', "=3D").replace(',', "=2C");
+        Scram {
+            digest,
+            password: sasl.password.clone(),
+            client_first_bare: format!("n={username},r={nonce}"),
+            nonce,
+            auth_message: String::new(),
+            salted_password: Vec::new(),
+        }
+    }
+
+    fn random_nonce() -> io::Result<String> {
+        let mut buf = [0u8; 18];
+        openssl::rand::rand_bytes(&mut buf).map_err(io::Error::other)?;
+        Ok(STANDARD.encode(buf))
+    }
+
+    fn client_first(&self) -> String {
+        format!("n,,{}", self.client_first_bare)
+    }
+
+    fn hmac(&self, key: &[u8], data: &[u8]) -> io::Result<Vec<u8>> {
+        let key = PKey::hmac(key).map_err(io::Error::other)?;
+        let mut signer = Signer::new(self.digest, &key).map_err(io::Error::other)?;
+        signer.update(data).map_err(io::Error::other)?;
+        signer.sign_to_vec().map_err(io::Error::other)
+    }
+
+    /// Answer the first message of the server with the proof of the password
+    fn client_final(&mut self, server_first: &[u8]) -> io::Result<String> {
+        let server_first = std::str::from_utf8(server_first)
+            .map_err(|_| invalid_data("invalid scram server first message"))?;
+        let mut nonce = None;
+        let mut salt = None;
+        let mut iterations = None;
+        for attr in server_first.split(',') {
+            match attr.split_once('=') {
+                Some(("r", v)) => nonce = Some(v),
+                Some(("s", v)) => {
+                    salt = Some(
+                        STANDARD
+                            .decode(v)
+                            .map_err(|_| invalid_data("invalid scram salt"))?,
+                    )
+                }
+                Some(("i", v)) => iterations = v.parse::<usize>().ok(),
+                _ => {}
+            }
+        }
+        let (Some(nonce), Some(salt), Some(iterations)) = (nonce, salt, iterations) else {
+            return Err(invalid_data("incomplete scram server first message"));
+        };
+        if !nonce.starts_with(&self.nonce) || iterations == 0 {
+            return Err(invalid_data("invalid scram server first message"));
+        }
+
+        let mut salted_password = vec![0u8; self.digest.size()];
+        openssl::pkcs5::pbkdf2_hmac(
+            self.password.as_bytes(),
+            &salt,
+            iterations,
+            self.digest,
+            &mut salted_password,
+        )
+        .map_err(io::Error::other)?;
+        let client_key = self.hmac(&salted_password, b"Client Key")?;
+        let stored_key = openssl::hash::hash(self.digest, &client_key).map_err(io::Error::other)?;
+        let without_proof = format!("c=biws,r={nonce}");
+        self.auth_message = format!("{},{server_first},{without_proof}", self.client_first_bare);
+        let signature = self.hmac(&stored_key, self.auth_message.as_bytes())?;
+        let proof: Vec<u8> = client_key
+            .iter()
+            .zip(signature)
+            .map(|(k, s)| k ^ s)
+            .collect();
+        self.salted_password = salted_password;
+        Ok(format!("{without_proof},p={}", STANDARD.encode(proof)))
+    }
+
+    /// Check that the server knows the password too
+    fn verify_server_final(&self, server_final: &[u8]) -> io::Result<()> {
+        let server_final = String::from_utf8_lossy(server_final);
+        if let Some(e) = server_final.strip_prefix("e=") {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("kafka scram authentication failed: {e}"),
+            ));
+        }
+        let server_key = self.hmac(&self.salted_password, b"Server Key")?;
+        let signature = self.hmac(&server_key, self.auth_message.as_bytes())?;
+        if server_final.strip_prefix("v=") != Some(STANDARD.encode(signature).as_str()) {
+            return Err(invalid_data("invalid scram server signature"));
+        }
+        Ok(())
+    }
+}
+
+/// Connection to a broker
+enum KafkaStream {
+    Tcp(TcpStream),
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Read for KafkaStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            KafkaStream::Tcp(s) => s.read(buf),
+            KafkaStream::Tls(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for KafkaStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            KafkaStream::Tcp(s) => s.write(buf),
+            KafkaStream::Tls(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            KafkaStream::Tcp(s) => s.flush(),
+            KafkaStream::Tls(s) => s.flush(),
+        }
+    }
+}
+
+/// Encode a v2 record batch of values, without key nor headers
+fn encode_batch(records: &[(i64, String)], compression: KafkaCompression) -> io::Result<Vec<u8>> {
+    let first_ts = records.first().map(|r| r.0).unwrap_or_default();
+    let max_ts = records.iter().map(|r| r.0).max().unwrap_or_default();
+
+    let mut body = Encoder::default();
+    for (delta, (ts, value)) in records.iter().enumerate() {
+        let mut record = Encoder::default();
+        record.i8(0); // attributes
+        record.varint(ts - first_ts);
+        record.varint(delta as i64);
+        record.varint(-1); // null key
+        record.varint(value.len() as i64);
+        record.0.extend_from_slice(value.as_bytes());
+        record.varint(0); // headers
+        body.varint(record.0.len() as i64);
+        body.0.extend_from_slice(&record.0);
+    }
+    let (attributes, body) = match compression {
+        KafkaCompression::None => (0i16, body.0),
+        KafkaCompression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&body.0)?;
+            (1i16, encoder.finish()?)
+        }
+    };
+
+    // the part covered by the checksum
+    let mut tail = Encoder::default();
+    tail.i16(attributes);
+    tail.i32(records.len() as i32 - 1); // last offset delta
+    tail.i64(first_ts);
+    tail.i64(max_ts);
+    tail.i64(-1); // producer id
+    tail.i16(-1); // producer epoch
+    tail.i32(-1); // base sequence
+    tail.i32(records.len() as i32);
+    tail.0.extend_from_slice(&body);
+
+    let mut batch = Encoder::default();
+    batch.i64(0); // base offset
+    batch.i32(4 + 1 + 4 + tail.0.len() as i32); // batch length
+    batch.i32(-1); // partition leader epoch
+    batch.i8(2); // magic
+    batch.0.extend_from_slice(&crc32c(&tail.0).to_be_bytes());
+    batch.0.extend_from_slice(&tail.0);
+    Ok(batch.0)
+}
+
+/// Partitions of a topic, with the node id of their leader
+struct TopicLeaders {
+    partitions: Vec<(i32, i32)>,
+    next: usize,
+}
+
+/// Blocking Kafka producer
+struct KafkaProducer {
+    config: KafkaSinkConfig,
+    correlation_id: i32,
+    /// Address of the brokers by node id
+    nodes: HashMap<i32, (String, u16)>,
+    topics: HashMap<String, TopicLeaders>,
+    connections: HashMap<i32, KafkaStream>,
+    tls_driver: Option<Arc<ClientConfig>>,
+}
+
+impl KafkaProducer {
+    fn new(config: KafkaSinkConfig) -> Self {
+        KafkaProducer {
+            config,
+            correlation_id: 0,
+            nodes: HashMap::new(),
+            topics: HashMap::new(),
+            connections: HashMap::new(),
+            tls_driver: None,
+        }
+    }
+
+    fn connect_tcp(host: &str, port: u16) -> io::Result<TcpStream> {
+        let mut last_err = None;
+        for addr in (host, port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+                    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+                    stream.set_nodelay(true)?;
+                    return Ok(stream);
+                }
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("no address for {host}"))
+        }))
+    }
+
+    /// Connect to a broker, with TLS and authenticated if configured
+    fn connect(&mut self, host: &str, port: u16) -> io::Result<KafkaStream> {
+        let tcp = Self::connect_tcp(host, port)?;
+        let mut stream = match &self.config.tls_client {
+            Some(tls_client) => {
+                let driver = match &self.tls_driver {
+                    Some(driver) => driver.clone(),
+                    None => {
+                        let tls_client = tls_client.build().map_err(io::Error::other)?;
+                        self.tls_driver.insert(tls_client.driver).clone()
+                    }
+                };
+                let name = self
+                    .config
+                    .tls_name
+                    .clone()
+                    .unwrap_or_else(|| host.to_string());
+                let server_name = ServerName::try_from(name)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                let conn = ClientConnection::new(driver, server_name).map_err(io::Error::other)?;
+                let mut tls = StreamOwned::new(conn, tcp);
+                while tls.conn.is_handshaking() {
+                    tls.conn.complete_io(&mut tls.sock)?;
+                }
+                KafkaStream::Tls(Box::new(tls))
+            }
+            None => KafkaStream::Tcp(tcp),
+        };
+        if let Some(sasl) = self.config.sasl.clone() {
+            self.authenticate(&mut stream, &sasl)?;
+        }
+        Ok(stream)
+    }
+
+    /// Authenticate the connection with the SASL mechanism
+    fn authenticate(&mut self, stream: &mut KafkaStream, sasl: &KafkaSaslConfig) -> io::Result<()> {
+        let mechanism = sasl.mechanism.name();
+        let mut body = Encoder::default();
+        body.string(mechanism);
+        let rsp = self.call(
+            stream,
+            API_SASL_HANDSHAKE,
+            SASL_HANDSHAKE_VERSION,
+            &body.0,
+            true,
+        )?;
+        let mut d = Decoder(&rsp);
+        let error_code = d.i16()?;
+        if error_code != 0 {
+            let mut enabled = Vec::new();
+            for _ in 0..d.array_len()? {
+                enabled.extend(d.string()?);
+            }
+            return Err(io::Error::other(format!(
+                "kafka sasl mechanism {mechanism} not enabled, the brokers offer {}",
+                enabled.join(", ")
+            )));
+        }
+
+        match sasl.mechanism {
+            KafkaSaslMechanism::Plain => {
+                let auth = format!("\0{}\0{}", sasl.username, sasl.password);
+                self.sasl_authenticate(stream, auth.as_bytes())?;
+            }
+            KafkaSaslMechanism::ScramSha256 | KafkaSaslMechanism::ScramSha512 => {
+                let mut scram = Scram::new(sasl, Scram::random_nonce()?);
+                let server_first =
+                    self.sasl_authenticate(stream, scram.client_first().as_bytes())?;
+                let client_final = scram.client_final(&server_first)?;
+                let server_final = self.sasl_authenticate(stream, client_final.as_bytes())?;
+                scram.verify_server_final(&server_final)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Send the SASL bytes of the client, returning the ones of the broker
+    fn sasl_authenticate(&mut self, stream: &mut KafkaStream, auth: &[u8]) -> io::Result<Vec<u8>> {
+        let mut body = Encoder::default();
+        body.bytes(auth);
+        let rsp = self.call(
+            stream,
+            API_SASL_AUTHENTICATE,
+            SASL_AUTHENTICATE_VERSION,
+            &body.0,
+            true,
+        )?;
+        let mut d = Decoder(&rsp);
+        let error_code = d.i16()?;
+        let message = d.string()?;
+        if error_code != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "kafka sasl authentication failed with error {error_code}: {}",
+                    message.unwrap_or_default()
+                ),
+            ));
+        }
+        Ok(d.bytes()?.unwrap_or_default().to_vec())
+    }
+
+    /// Send a request and read its response, if one is expected
+    fn call(
+        &mut self,
+        stream: &mut KafkaStream,
+        api_key: i16,
+        api_version: i16,
+        body: &[u8],
+        expect_response: bool,
+    ) -> io::Result<Vec<u8>> {
+        self.correlation_id = self.correlation_id.wrapping_add(1);
+        let mut req = Encoder::default();
+        req.i16(api_key);
+        req.i16(api_version);
+        req.i32(self.correlation_id);
+        req.string(&self.config.client_id);
+        req.0.extend_from_slice(body);
+        let mut frame = Encoder::default();
+        frame.bytes(&req.0);
+        stream.write_all(&frame.0)?;
+        stream.flush()?;
+        if !expect_response {
+            return Ok(Vec::new());
+        }
+
+        let mut len = [0u8; 4];
+        stream.read_exact(&mut len)?;
+        let len = i32::from_be_bytes(len);
+        if len < 4 || len as usize > MAX_RESPONSE_SIZE {
+            return Err(invalid_data(format!("invalid kafka response size {len}")));
+        }
+        let mut rsp = vec![0u8; len as usize];
+        stream.read_exact(&mut rsp)?;
+        let correlation_id = i32::from_be_bytes(rsp[..4].try_into().unwrap());
+        if correlation_id != self.correlation_id {
+            return Err(invalid_data("unexpected kafka correlation id"));
+        }
+        rsp.drain(..4);
+        Ok(rsp)
+    }
+
+    /// Find the partitions of the topic and their leaders
+    fn refresh_metadata(&mut self, topic: &str) -> io::Result<()> {
+        let mut body = Encoder::default();
+        body.i32(1);
+        body.string(topic);
+
+        let mut last_err = None;
+        for broker in self.config.brokers.clone() {
+            let r = self
+                .connect(&broker.host_str(), broker.port())
+                .and_then(|mut stream| {
+                    self.call(&mut stream, API_METADATA, METADATA_VERSION, &body.0, true)
+                });
+            match r {
+                Ok(rsp) => return self.parse_metadata(topic, &rsp),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| io::Error::other("no kafka broker configured")))
+    }
+
+    fn parse_metadata(&mut self, topic: &str, rsp: &[u8]) -> io::Result<()> {
+        let mut d = Decoder(rsp);
+        for _ in 0..d.array_len()? {
+            let node_id = d.i32()?;
+            let host = d.string()?.unwrap_or_default();
+            let port = d.i32()?;
+            let _rack = d.string()?;
+            self.nodes.insert(node_id, (host, port as u16));
+        }
+        let _controller_id = d.i32()?;
+        for _ in 0..d.array_len()? {
+            let error_code = d.i16()?;
+            let name = d.string()?.unwrap_or_default();
+            let _is_internal = d.i8()?;
+            let mut partitions = Vec::new();
+            for _ in 0..d.array_len()? {
+                let partition_error = d.i16()?;
+                let partition = d.i32()?;
+                let leader = d.i32()?;
+                for _ in 0..2 {
+                    // replicas and in-sync replicas
+                    for _ in 0..d.array_len()? {
+                        d.i32()?;
+                    }
+                }
+                if partition_error == 0 && leader >= 0 {
+                    partitions.push((partition, leader));
+                }
+            }
+            if name != topic {
+                continue;
+            }
+            if error_code != 0 {
+                return Err(io::Error::other(format!(
+                    "kafka metadata error {error_code} for topic {topic}"
+                )));
+            }
+            if partitions.is_empty() {
+                return Err(io::Error::other(format!(
+                    "no partition leader available for topic {topic}"
+                )));
+            }
+            partitions.sort_unstable();
+            self.topics.insert(
+                name,
+                TopicLeaders {
+                    partitions,
+                    next: 0,
+                },
+            );
+            return Ok(());
+        }
+        Err(io::Error::other(format!("topic {topic} not found")))
+    }
+
+    /// Produce a batch to the next partition of the topic
+    fn produce(&mut self, topic: &str, batch: &[u8]) -> io::Result<()> {
+        if !self.topics.contains_key(topic) {
+            self.refresh_metadata(topic)?;
+        }
+        let leaders = self.topics.get_mut(topic).unwrap();
+        let (partition, leader) = leaders.partitions[leaders.next % leaders.partitions.len()];
+        leaders.next = leaders.next.wrapping_add(1);
+
+        let r = self.produce_to(leader, topic, partition, batch);
+        if r.is_err() {
+            // the leader may have moved
+            self.connections.remove(&leader);
+            self.topics.remove(topic);
+        }
+        r
+    }
+
+    fn produce_to(
+        &mut self,
+        leader: i32,
+        topic: &str,
+        partition: i32,
+        batch: &[u8],
+    ) -> io::Result<()> {
+        let mut stream = match self.connections.remove(&leader) {
+            Some(stream) => stream,
+            None => {
+                let (host, port) = self.nodes.get(&leader).cloned().ok_or_else(|| {
+                    io::Error::other(format!("unknown kafka broker node {leader}"))
+                })?;
+                self.connect(&host, port)?
+            }
+        };
+
+        let mut body = Encoder::default();
+        body.i16(-1); // null transactional id
+        body.i16(self.config.acks);
+        body.i32(PRODUCE_TIMEOUT_MS);
+        body.i32(1);
+        body.string(topic);
+        body.i32(1);
+        body.i32(partition);
+        body.bytes(batch);
+        let expect_response = self.config.acks != 0;
+        let rsp = self.call(
+            &mut stream,
+            API_PRODUCE,
+            PRODUCE_VERSION,
+            &body.0,
+            expect_response,
+        )?;
+        self.connections.insert(leader, stream);
+        if !expect_response {
+            return Ok(());
+        }
+
+        let mut d = Decoder(&rsp);
+        for _ in 0..d.array_len()? {
+            let _topic = d.string()?;
+            for _ in 0..d.array_len()? {
+                let _partition = d.i32()?;
+                let error_code = d.i16()?;
+                let _base_offset = d.i64()?;
+                let _log_append_time = d.i64()?;
+                if error_code != 0 {
+                    return Err(io::Error::other(format!(
+                        "kafka produce error {error_code} for topic {topic}"
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Records waiting for their batch to be sent
+#[derive(Default)]
+struct PendingBatch {
+    records: Vec<(i64, String)>,
+    since: Option<Instant>,
+}
+
+/// Produce the audit records to Kafka topics
+pub struct KafkaSink {
+    producer: KafkaProducer,
+    access: PendingBatch,
+    detection: PendingBatch,
+}
+
+impl KafkaSink {
+    pub fn new(config: KafkaSinkConfig) -> Self {
+        KafkaSink {
+            producer: KafkaProducer::new(config),
+            access: PendingBatch::default(),
+            detection: PendingBatch::default(),
+        }
+    }
+
+    fn config(&self) -> &KafkaSinkConfig {
+        &self.producer.config
+    }
+
+    fn spill_path(&self, topic: &str) -> Option<PathBuf> {
+        let dir = self.config().spill_dir.as_ref()?;
+        Some(dir.join(format!("{topic}.spill")))
+    }
+
+    /// Keep the records that could not be delivered for a later retry
+    fn spill(&self, topic: &str, records: &[(i64, String)]) -> io::Result<()> {
+        let Some(path) = self.spill_path(topic) else {
+            return Ok(());
+        };
+        let size = std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        if size >= self.config().spill_max_size {
+            return Err(io::Error::other(format!(
+                "spill file {} is full, {} records dropped",
+                path.display(),
+                records.len()
+            )));
+        }
+        let mut buf = Vec::new();
+        for (ts, text) in records {
+            // the records are one line each, as written to the other sinks
+            buf.extend_from_slice(format!("{ts} {text}\n").as_bytes());
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?
+            .write_all(&buf)
+    }
+
+    /// Send the spilled records of the topic, before any new one
+    fn replay(&mut self, topic: &str) -> io::Result<()> {
+        let Some(path) = self.spill_path(topic) else {
+            return Ok(());
+        };
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let mut records = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if let Some((ts, text)) = line.split_once(' ')
+                && let Ok(ts) = ts.parse()
+            {
+                records.push((ts, text.to_string()));
+            }
+        }
+        let batch_size = self.config().batch_size;
+        for (i, chunk) in records.chunks(batch_size).enumerate() {
+            if let Err(e) = self.send(topic, chunk) {
+                // keep what is left for the next attempt
+                std::fs::remove_file(&path)?;
+                self.spill(topic, &records[i * batch_size..])?;
+                return Err(e);
+            }
+        }
+        std::fs::remove_file(&path)
+    }
+
+    fn send(&mut self, topic: &str, records: &[(i64, String)]) -> io::Result<()> {
+        let batch = encode_batch(records, self.config().compression)?;
+        self.producer.produce(topic, &batch)
+    }
+
+    /// Send the pending batch to the topic, spilling it on failure
+    fn send_pending(&mut self, detection: bool) -> io::Result<()> {
+        let topic = match &self.config().detection_topic {
+            Some(topic) if detection => topic.clone(),
+            _ => self.config().topic.clone(),
+        };
+        let pending = self.pending(detection);
+        let records = std::mem::take(&mut pending.records);
+        pending.since = None;
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        let r = self
+            .replay(&topic)
+            .and_then(|_| self.send(&topic, &records));
+        if let Err(e) = r {
+            self.spill(&topic, &records)?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn pending(&mut self, detection: bool) -> &mut PendingBatch {
+        if detection && self.config().detection_topic.is_some() {
+            &mut self.detection
+        } else {
+            &mut self.access
+        }
+    }
+}
+
+impl AuditSink for KafkaSink {
+    fn name(&self) -> String {
+        format!("kafka topic {}", self.config().topic)
+    }
+
+    fn write(&mut self, line: &AuditLine) -> io::Result<()> {
+        let batch_size = self.config().batch_size;
+        let pending = self.pending(line.detection);
+        pending.since.get_or_insert_with(Instant::now);
+        pending.records.push((unix_millis(), line.text.clone()));
+        if pending.records.len() >= batch_size {
+            self.send_pending(line.detection)?;
+        }
+        Ok(())
+    }
+
+    fn tick(&mut self) -> io::Result<()> {
+        let linger = self.config().linger;
+        let mut r = Ok(());
+        for detection in [false, true] {
+            let due = self
+                .pending(detection)
+                .since
+                .is_some_and(|since| since.elapsed() >= linger);
+            if due {
+                r = r.and(self.send_pending(detection));
+            }
+        }
+        r
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let r = self.send_pending(false);
+        r.and(self.send_pending(true))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    fn config(port: u16) -> KafkaSinkConfig {
+        KafkaSinkConfig {
+            brokers: vec![format!("127.0.0.1:{port}").parse().unwrap()],
+            topic: "access".to_string(),
+            detection_topic: Some("detections".to_string()),
+            client_id: "g3icap".to_string(),
+            acks: 1,
+            compression: KafkaCompression::None,
+            batch_size: 2,
+            linger: Duration::from_secs(60),
+            spill_dir: None,
+            spill_max_size: 1024 * 1024,
+            tls_client: None,
+            tls_name: None,
+            sasl: None,
+        }
+    }
+
+    fn line(text: &str, detection: bool) -> AuditLine {
+        AuditLine {
+            text: text.to_string(),
+            severity: 6,
+            detection,
+            data: Vec::new(),
+        }
+    }
+
+    fn varint(d: &mut Decoder) -> i64 {
+        let mut z = 0u64;
+        let mut shift = 0;
+        loop {
+            let b = d.take(1).unwrap()[0];
+            z |= u64::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                break;
+            }
+            shift += 7;
+        }
+        ((z >> 1) as i64) ^ -((z & 1) as i64)
+    }
+
+    /// Values of an uncompressed record batch
+    fn decode_batch(batch: &[u8]) -> Vec<String> {
+        let mut d = Decoder(batch);
+        d.i64().unwrap();
+        let len = d.i32().unwrap() as usize;
+        assert_eq!(len, batch.len() - 12);
+        d.i32().unwrap();
+        assert_eq!(d.i8().unwrap(), 2);
+        let crc = d.i32().unwrap() as u32;
+        assert_eq!(crc, crc32c(d.0));
+        assert_eq!(d.i16().unwrap(), 0);
+        d.take(4 + 8 + 8 + 8 + 2 + 4).unwrap();
+        let count = d.i32().unwrap();
+        let mut values = Vec::new();
+        for _ in 0..count {
+            varint(&mut d);
+            d.i8().unwrap();
+            varint(&mut d);
+            varint(&mut d);
+            assert_eq!(varint(&mut d), -1);
+            let len = varint(&mut d) as usize;
+            values.push(String::from_utf8(d.take(len).unwrap().to_vec()).unwrap());
+            assert_eq!(varint(&mut d), 0);
+        }
+        values
+    }
+
+    type Received = Arc<Mutex<Vec<(String, Vec<String>)>>>;
+
+    /// A single node cluster with one partition per topic
+    fn serve(listener: TcpListener, received: Received) {
+        let port = listener.local_addr().unwrap().port();
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let received = received.clone();
+            std::thread::spawn(move || {
+                loop {
+                    let mut len = [0u8; 4];
+                    if stream.read_exact(&mut len).is_err() {
+                        return;
+                    }
+                    let mut req = vec![0u8; i32::from_be_bytes(len) as usize];
+                    stream.read_exact(&mut req).unwrap();
+                    let mut d = Decoder(&req);
+                    let api_key = d.i16().unwrap();
+                    d.i16().unwrap();
+                    let correlation_id = d.i32().unwrap();
+                    d.string().unwrap();
+
+                    let mut rsp = Encoder::default();
+                    rsp.i32(correlation_id);
+                    if api_key == API_SASL_HANDSHAKE {
+                        let mechanism = d.string().unwrap().unwrap();
+                        rsp.i16(if mechanism == "PLAIN" { 0 } else { 33 });
+                        rsp.i32(1);
+                        rsp.string("PLAIN");
+                    } else if api_key == API_SASL_AUTHENTICATE {
+                        let auth = d.bytes().unwrap().unwrap();
+                        let auth = String::from_utf8_lossy(auth).into_owned();
+                        received
+                            .lock()
+                            .unwrap()
+                            .push(("sasl".to_string(), vec![auth]));
+                        rsp.i16(0);
+                        rsp.i16(-1);
+                        rsp.i32(0);
+                    } else if api_key == API_METADATA {
+                        d.i32().unwrap();
+                        let topic = d.string().unwrap().unwrap();
+                        rsp.i32(1);
+                        rsp.i32(0);
+                        rsp.string("127.0.0.1");
+                        rsp.i32(i32::from(port));
+                        rsp.i16(-1);
+                        rsp.i32(0);
+                        rsp.i32(1);
+                        rsp.i16(0);
+                        rsp.string(&topic);
+                        rsp.i8(0);
+                        rsp.i32(1);
+                        rsp.i16(0);
+                        rsp.i32(0);
+                        rsp.i32(0);
+                        rsp.i32(1);
+                        rsp.i32(0);
+                        rsp.i32(1);
+                        rsp.i32(0);
+                    } else {
+                        d.i16().unwrap();
+                        d.i16().unwrap();
+                        d.i32().unwrap();
+                        d.i32().unwrap();
+                        let topic = d.string().unwrap().unwrap();
+                        d.i32().unwrap();
+                        d.i32().unwrap();
+                        let len = d.i32().unwrap() as usize;
+                        let values = decode_batch(d.take(len).unwrap());
+                        received.lock().unwrap().push((topic.clone(), values));
+                        rsp.i32(1);
+                        rsp.string(&topic);
+                        rsp.i32(1);
+                        rsp.i32(0);
+                        rsp.i16(0);
+                        rsp.i64(0);
+                        rsp.i64(-1);
+                        rsp.i32(0);
+                    }
+                    let mut frame = Encoder::default();
+                    frame.bytes(&rsp.0);
+                    stream.write_all(&frame.0).unwrap();
+                }
+            });
+        }
+    }
+
+    fn broker() -> (u16, Received) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let received = Received::default();
+        let r = received.clone();
+        std::thread::spawn(move || serve(listener, r));
+        (port, received)
+    }
+
+    #[test]
+    fn checksum() {
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+    }
+
+    #[test]
+    fn batch_encoding() {
+        let records = vec![(1000, "a".to_string()), (1005, "bc".to_string())];
+        let batch = encode_batch(&records, KafkaCompression::None).unwrap();
+        assert_eq!(decode_batch(&batch), ["a", "bc"]);
+
+        let gzip = encode_batch(&records, KafkaCompression::Gzip).unwrap();
+        let mut d = Decoder(&gzip);
+        d.take(8 + 4 + 4 + 1 + 4).unwrap();
+        assert_eq!(d.i16().unwrap(), 1);
+    }
+
+    #[test]
+    fn topic_routing() {
+        let (port, received) = broker();
+        let mut sink = KafkaSink::new(config(port));
+        sink.write(&line("a1", false)).unwrap();
+        sink.write(&line("d1", true)).unwrap();
+        sink.write(&line("a2", false)).unwrap();
+        sink.write(&line("a3", false)).unwrap();
+        assert_eq!(received.lock().unwrap().len(), 1);
+        sink.flush().unwrap();
+
+        let received = received.lock().unwrap();
+        assert_eq!(
+            *received,
+            [
+                (
+                    "access".to_string(),
+                    vec!["a1".to_string(), "a2".to_string()]
+                ),
+                ("access".to_string(), vec!["a3".to_string()]),
+                ("detections".to_string(), vec!["d1".to_string()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn spill_and_replay() {
+        let dir = std::env::temp_dir().join(format!("g3icap-kafka-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // nothing listens on the port of a dropped listener
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut c = config(port);
+        c.spill_dir = Some(dir.clone());
+        c.detection_topic = None;
+        let mut sink = KafkaSink::new(c.clone());
+        sink.write(&line("s1", false)).unwrap();
+        sink.write(&line("s2", true)).unwrap_err();
+        sink.flush().unwrap();
+        let spilled = std::fs::read_to_string(dir.join("access.spill")).unwrap();
+        assert_eq!(spilled.lines().count(), 2);
+        assert!(spilled.ends_with(" s2\n"));
+
+        let (port, received) = broker();
+        c.brokers = vec![format!("127.0.0.1:{port}").parse().unwrap()];
+        let mut sink = KafkaSink::new(c);
+        sink.write(&line("n1", false)).unwrap();
+        sink.flush().unwrap();
+        assert!(!dir.join("access.spill").exists());
+        let values: Vec<_> = received
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(|(_, v)| v.clone())
+            .collect();
+        assert_eq!(values, ["s1", "s2", "n1"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sasl_plain() {
+        let (port, received) = broker();
+        let mut c = config(port);
+        c.sasl = Some(KafkaSaslConfig {
+            mechanism: KafkaSaslMechanism::Plain,
+            username: "g3icap".to_string(),
+            password: "s3cret".to_string(),
+        });
+        let mut sink = KafkaSink::new(c.clone());
+        sink.write(&line("a1", false)).unwrap();
+        sink.flush().unwrap();
+
+        // both the metadata and the produce connections are authenticated
+        let auth = ("sasl".to_string(), vec!["\0g3icap\0s3cret".to_string()]);
+        assert_eq!(
+            *received.lock().unwrap(),
+            [
+                auth.clone(),
+                auth,
+                ("access".to_string(), vec!["a1".to_string()]),
+            ]
+        );
+
+        c.sasl.as_mut().unwrap().mechanism = KafkaSaslMechanism::ScramSha256;
+        let mut sink = KafkaSink::new(c);
+        sink.write(&line("a2", false)).unwrap();
+        let e = sink.flush().unwrap_err();
+        assert!(e.to_string().contains("the brokers offer PLAIN"), "{e}");
+    }
+
+    #[test]
+    fn scram() {
+        // the example exchange of RFC 7677
+        let sasl = KafkaSaslConfig {
+            mechanism: KafkaSaslMechanism::ScramSha256,
+            username: "user".to_string(),
+            password: "pencil".to_string(),
+        };
+        let mut scram = Scram::new(&sasl, "rOprNGfwEbeRWgbNEkqO".to_string());
+        assert_eq!(scram.client_first(), "n,,n=user,r=rOprNGfwEbeRWgbNEkqO");
+        let client_final = scram
+            .client_final(
+                b"r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+                  s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096",
+            )
+            .unwrap();
+        assert_eq!(
+            client_final,
+            "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+             p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="
+        );
+        scram
+            .verify_server_final(b"v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=")
+            .unwrap();
+        assert!(scram.verify_server_final(b"v=AAAA").is_err());
+        assert!(scram.verify_server_final(b"e=invalid-proof").is_err());
+
+        // the nonce of the server should extend the one of the client
+        let mut scram = Scram::new(&sasl, "rOprNGfwEbeRWgbNEkqO".to_string());
+        assert!(scram.client_final(b"r=other,s=AAAA,i=4096").is_err());
+    }
+}
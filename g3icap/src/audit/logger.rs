@@ -0,0 +1,249 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Dispatch of the audit records to the sinks
+//!
+//! Records are serialized by the connection tasks and queued to a dedicated
+//! thread writing them to the sinks, so that a slow sink never delays a
+//! transaction. Records that do not fit in the queue are dropped and counted.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::time::Duration;
+
+use arc_swap::ArcSwapOption;
+use log::warn;
+
+use super::ops::{AuditEvent, AuditEventType, AuditSeverity};
+use super::record::{AuditRecord, AuditVerdict};
+use super::sink::{self, AuditLine, AuditSink};
+use crate::config::server::audit::{AuditFormat, AuditLogConfig};
+
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+
+static GLOBAL_AUDIT_LOGGER: ArcSwapOption<AuditLogger> = ArcSwapOption::const_empty();
+
+/// Install the audit logger of the server
+pub fn set_global(logger: Option<Arc<AuditLogger>>) {
+    GLOBAL_AUDIT_LOGGER.store(logger);
+}
+
+/// Get the audit logger in use
+pub fn get_global() -> Option<Arc<AuditLogger>> {
+    GLOBAL_AUDIT_LOGGER.load_full()
+}
+
+/// Queue of the audit records to the sinks
+pub struct AuditLogger {
+    format: AuditFormat,
+    sender: SyncSender<AuditLine>,
+    dropped: AtomicU64,
+}
+
+impl AuditLogger {
+    /// Start the thread writing to the sinks of the config
+    ///
+    /// The thread exits once the logger is dropped and the queue is empty.
+    pub fn spawn(config: &AuditLogConfig) -> std::io::Result<Self> {
+        let sinks = config.sinks.iter().map(sink::build).collect();
+        Self::with_sinks(config.format, config.queue_size, sinks)
+    }
+
+    fn with_sinks(
+        format: AuditFormat,
+        queue_size: usize,
+        sinks: Vec<Box<dyn AuditSink>>,
+    ) -> std::io::Result<Self> {
+        let (sender, receiver) = mpsc::sync_channel(queue_size);
+        std::thread::Builder::new()
+            .name("audit-log".to_string())
+            .spawn(move || write_lines(receiver, sinks))?;
+        Ok(AuditLogger {
+            format,
+            sender,
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    /// Queue the record of a transaction
+    pub fn log(&self, record: &AuditRecord) {
+        let text = match self.format {
+            AuditFormat::Json => record.to_json().to_string(),
+            AuditFormat::Cef => record.to_cef(),
+            AuditFormat::Leef => record.to_leef(),
+        };
+        let severity = match record.verdict {
+            AuditVerdict::Allowed | AuditVerdict::Modified => 6,
+            AuditVerdict::Error => 5,
+            AuditVerdict::Blocked => 4,
+        };
+        self.send(AuditLine {
+            text,
+            severity,
+            detection: record.verdict == AuditVerdict::Blocked,
+            data: record.fields(),
+        });
+    }
+
+    /// Queue a server event, e.g. a configuration change
+    pub fn log_event(&self, event: &AuditEvent) {
+        let text = match self.format {
+            AuditFormat::Json => event.to_json().to_string(),
+            AuditFormat::Cef => event.to_cef(),
+            AuditFormat::Leef => event.to_leef(),
+        };
+        let severity = match event.severity {
+            AuditSeverity::Info => 6,
+            AuditSeverity::Warning => 4,
+            AuditSeverity::Error => 3,
+            AuditSeverity::Critical => 2,
+        };
+        let detection = matches!(
+            event.event_type,
+            AuditEventType::RequestBlocked
+                | AuditEventType::ResponseBlocked
+                | AuditEventType::SecurityEvent
+        );
+        self.send(AuditLine {
+            text,
+            severity,
+            detection,
+            data: vec![
+                ("event_type", format!("{:?}", event.event_type)),
+                ("severity", format!("{:?}", event.severity)),
+            ],
+        });
+    }
+
+    fn send(&self, line: AuditLine) {
+        match self.sender.try_send(line) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                if dropped.is_power_of_two() {
+                    warn!("audit queue full, {dropped} records dropped");
+                }
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Number of records dropped as the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+struct SinkState {
+    sink: Box<dyn AuditSink>,
+    /// only the first error of a failing sink is logged
+    failing: bool,
+}
+
+impl SinkState {
+    fn check(&mut self, r: std::io::Result<()>) {
+        match r {
+            Ok(()) => self.failing = false,
+            Err(e) if !self.failing => {
+                warn!("failed to write audit record to {}: {e}", self.sink.name());
+                self.failing = true;
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+fn write_lines(receiver: Receiver<AuditLine>, sinks: Vec<Box<dyn AuditSink>>) {
+    let mut sinks: Vec<SinkState> = sinks
+        .into_iter()
+        .map(|sink| SinkState {
+            sink,
+            failing: false,
+        })
+        .collect();
+    loop {
+        match receiver.recv_timeout(TICK_INTERVAL) {
+            Ok(line) => {
+                for s in sinks.iter_mut() {
+                    let r = s.sink.write(&line);
+                    s.check(r);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        for s in sinks.iter_mut() {
+            let r = s.sink.tick();
+            s.check(r);
+        }
+    }
+    for s in sinks.iter_mut() {
+        let r = s.sink.flush();
+        s.check(r);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    #[derive(Clone, Default)]
+    struct MemorySink(Arc<Mutex<Vec<AuditLine>>>);
+
+    impl AuditSink for MemorySink {
+        fn name(&self) -> String {
+            "memory".to_string()
+        }
+
+        fn write(&mut self, line: &AuditLine) -> io::Result<()> {
+            self.0.lock().unwrap().push(line.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn dispatch() {
+        let sink = MemorySink::default();
+        let logger = AuditLogger::with_sinks(
+            AuditFormat::Json,
+            16,
+            vec![Box::new(sink.clone()), Box::new(sink.clone())],
+        )
+        .unwrap();
+        logger.log_event(&AuditEvent {
+            timestamp: 1_700_000_000,
+            event_type: AuditEventType::ConfigChanged,
+            message: "Service pipeline changed".to_string(),
+            details: "reqmod".to_string(),
+            client_ip: None,
+            user_agent: None,
+            request_uri: None,
+            response_status: None,
+            metadata: Default::default(),
+            severity: AuditSeverity::Warning,
+        });
+        drop(logger);
+
+        for _ in 0..100 {
+            if sink.0.lock().unwrap().len() == 2 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let lines = sink.0.lock().unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].severity, 4);
+        assert!(!lines[0].detection);
+        let v: serde_json::Value = serde_json::from_str(&lines[0].text).unwrap();
+        assert_eq!(v["event_type"], "ConfigChanged");
+        assert_eq!(v["details"], "reqmod");
+    }
+}
@@ -13,9 +13,17 @@ use std::sync::Arc;
 use anyhow::Result;
 use g3_types::metrics::NodeName;
 
+pub mod events;
 pub mod ops;
 pub mod registry;
 pub mod handle;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod logger;
+pub mod record;
+pub mod siem;
+pub mod sink;
+pub mod syslog;
 
 // Re-export key types
 pub use handle::{AuditHandle, AuditStats, AuditPerformanceMetrics};
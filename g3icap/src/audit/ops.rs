@@ -13,10 +13,12 @@ use std::collections::HashMap;
 use std::sync::Arc;
 
 use anyhow::Result;
+use chrono::DateTime;
 use g3_types::metrics::NodeName;
 use serde::{Serialize, Deserialize};
 
 use super::{IcapAuditHandle, AuditHandle};
+use super::siem::{self, CefWriter, LeefWriter, SiemWriter};
 use super::registry;
 
 /// Audit event types
@@ -44,6 +46,8 @@ pub enum AuditEventType {
     SecurityEvent,
     /// Compliance event
     ComplianceEvent,
+    /// Signed artifact loaded
+    ArtifactLoaded,
 }
 
 /// Audit event structure
@@ -71,6 +75,51 @@ pub struct AuditEvent {
     pub severity: AuditSeverity,
 }
 
+impl AuditEvent {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    /// CEF and LEEF severity, from 0 to 10
+    fn siem_severity(&self) -> u8 {
+        match self.severity {
+            AuditSeverity::Info => 1,
+            AuditSeverity::Warning => 5,
+            AuditSeverity::Error => 7,
+            AuditSeverity::Critical => 10,
+        }
+    }
+
+    fn write_siem(&self, w: &mut impl SiemWriter) {
+        let time = DateTime::from_timestamp(self.timestamp as i64, 0).unwrap_or_default();
+        w.time(time);
+        w.push(&siem::MESSAGE, &self.details);
+        w.push_opt(&siem::SOURCE, self.client_ip.as_deref());
+        w.push_opt(&siem::USER_AGENT, self.user_agent.as_deref());
+        w.push_opt(&siem::URL, self.request_uri.as_deref());
+        w.push_opt(
+            &siem::OUTCOME,
+            self.response_status.map(|s| s.to_string()).as_deref(),
+        );
+    }
+
+    /// Serialize as an ArcSight Common Event Format line
+    pub fn to_cef(&self) -> String {
+        let event_class = format!("{:?}", self.event_type);
+        let mut cef = CefWriter::new(&event_class, &self.message, self.siem_severity());
+        self.write_siem(&mut cef);
+        cef.finish()
+    }
+
+    /// Serialize as a QRadar Log Event Extended Format line
+    pub fn to_leef(&self) -> String {
+        let event_id = format!("{:?}", self.event_type);
+        let mut leef = LeefWriter::new(&event_id, self.siem_severity());
+        self.write_siem(&mut leef);
+        leef.finish()
+    }
+}
+
 /// Audit severity levels
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum AuditSeverity {
@@ -107,33 +156,13 @@ pub trait IcapAuditOps: Send + Sync {
     
     /// Log structured audit event
     fn log_structured_event(&self, event: AuditEvent) {
-        if self.get_audit_handle().is_enabled() {
-            // Log to console (in production, this would go to a proper audit log)
-            println!("AUDIT[{}]: {:?} - {} | {}", 
-                event.timestamp, 
-                event.event_type, 
-                event.message, 
-                event.details
-            );
-            
-            // Log additional metadata if present
-            if !event.metadata.is_empty() {
-                println!("AUDIT_METADATA: {:?}", event.metadata);
-            }
-            
-            // Log client information if available
-            if let Some(client_ip) = &event.client_ip {
-                println!("AUDIT_CLIENT: IP={}", client_ip);
-            }
-            if let Some(user_agent) = &event.user_agent {
-                println!("AUDIT_USER_AGENT: {}", user_agent);
-            }
-            if let Some(uri) = &event.request_uri {
-                println!("AUDIT_URI: {}", uri);
-            }
-            if let Some(status) = event.response_status {
-                println!("AUDIT_STATUS: {}", status);
-            }
+        if !self.get_audit_handle().is_enabled() {
+            return;
+        }
+        super::events::push(&event);
+        match super::logger::get_global() {
+            Some(logger) => logger.log_event(&event),
+            None => log::info!(target: "audit", "{}", event.to_json()),
         }
     }
     
@@ -201,6 +230,44 @@ pub trait IcapAuditOps: Send + Sync {
         });
     }
     
+    /// Log a configuration change made at runtime
+    fn log_config_changed(&self, event: &str, details: &str) {
+        self.log_structured_event(AuditEvent {
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            event_type: AuditEventType::ConfigChanged,
+            message: event.to_string(),
+            details: details.to_string(),
+            client_ip: None,
+            user_agent: None,
+            request_uri: None,
+            response_status: None,
+            metadata: HashMap::new(),
+            severity: AuditSeverity::Warning,
+        });
+    }
+
+    /// Log a signed artifact loaded, with its signer and digest
+    fn log_artifact_loaded(&self, kind: &str, name: &str, signer: &str, digest: &str) {
+        let metadata = HashMap::from([
+            ("artifact_kind".to_string(), kind.to_string()),
+            ("artifact".to_string(), name.to_string()),
+            ("signer".to_string(), signer.to_string()),
+            ("sha256".to_string(), digest.to_string()),
+        ]);
+        self.log_structured_event(AuditEvent {
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            event_type: AuditEventType::ArtifactLoaded,
+            message: "Signed artifact loaded".to_string(),
+            details: format!("{kind} {name} signed by {signer}, sha256 {digest}"),
+            client_ip: None,
+            user_agent: None,
+            request_uri: None,
+            response_status: None,
+            metadata,
+            severity: AuditSeverity::Info,
+        });
+    }
+
     /// Log security event
     fn log_security_event(&self, event: &str, details: &str, severity: AuditSeverity) {
         self.log_structured_event(AuditEvent {
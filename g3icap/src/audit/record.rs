@@ -0,0 +1,450 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Per-transaction audit records
+//!
+//! A record is started when the request has been read and finished with the
+//! response sent to the client, or with the failure of the transaction. It
+//! is serialized as one JSON object, one CEF line or one LEEF line.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use http::StatusCode;
+
+use super::siem::{self, CefWriter, LeefWriter, SiemWriter};
+use crate::auth::identity::HEADER_CLIENT_IP;
+use crate::modules::antivirus::HEADER_VIRUS_ID;
+use crate::modules::content_filter::{HEADER_DETECTED_TYPE, HEADER_RULE_CATEGORY, HEADER_RULE_ID};
+use crate::protocol::common::{IcapRequest, IcapResponse};
+use crate::transaction::{ModuleVerdict, TransactionCtx};
+
+/// Outcome of a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditVerdict {
+    /// The message is passed unmodified
+    Allowed,
+    /// The message is modified, e.g. sanitized
+    Modified,
+    /// The message is blocked by a rule or a scanner
+    Blocked,
+    /// The request is rejected or failed
+    Error,
+}
+
+impl AuditVerdict {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditVerdict::Allowed => "allowed",
+            AuditVerdict::Modified => "modified",
+            AuditVerdict::Blocked => "blocked",
+            AuditVerdict::Error => "error",
+        }
+    }
+
+    /// CEF severity, from 0 to 10
+    fn severity(&self) -> u8 {
+        match self {
+            AuditVerdict::Allowed => 1,
+            AuditVerdict::Modified => 3,
+            AuditVerdict::Error => 5,
+            AuditVerdict::Blocked => 7,
+        }
+    }
+
+    /// The verdict of the response sent to the client
+    pub(crate) fn of_response(response: &IcapResponse) -> Self {
+        let blocked_by = [HEADER_RULE_ID, HEADER_VIRUS_ID]
+            .iter()
+            .any(|h| response.headers.contains_key(*h));
+        match response.status {
+            StatusCode::NO_CONTENT | StatusCode::CONTINUE => AuditVerdict::Allowed,
+            StatusCode::FORBIDDEN => AuditVerdict::Blocked,
+            _ if blocked_by => AuditVerdict::Blocked,
+            // a 200 without body is how the fallback scanners allow a message
+            StatusCode::OK if response.body.is_empty() => AuditVerdict::Allowed,
+            StatusCode::OK | StatusCode::PARTIAL_CONTENT => AuditVerdict::Modified,
+            _ => AuditVerdict::Error,
+        }
+    }
+}
+
+/// Audit record of an ICAP transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    pub time: DateTime<Utc>,
+    pub transaction_id: String,
+    /// Id of the request, the one of the client if it sent one
+    pub request_id: String,
+    /// Address of the ICAP client, the proxy
+    pub peer: SocketAddr,
+    /// Address of the end user, as forwarded by the proxy
+    pub client_ip: Option<String>,
+    pub user: Option<String>,
+    /// Tenant of the end user, as forwarded by the proxy
+    pub tenant: Option<String>,
+    pub method: String,
+    /// ICAP service, the path of the ICAP URI
+    pub service: String,
+    pub url: String,
+    /// Status of the ICAP response, if one was sent
+    pub status: Option<u16>,
+    pub verdict: AuditVerdict,
+    /// Id of the matched filter rule
+    pub rule: Option<String>,
+    /// Category of the matched filter rule
+    pub category: Option<String>,
+    /// Threat found by the scanners
+    pub threat: Option<String>,
+    /// True type of the body, if detected
+    pub detected_type: Option<String>,
+    /// Failure of the transaction
+    pub error: Option<String>,
+    pub latency: Duration,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    /// Labels set on the transaction while it was processed
+    pub labels: BTreeMap<String, String>,
+    /// Verdicts of the modules the transaction went through
+    pub modules: Vec<ModuleVerdict>,
+}
+
+impl AuditRecord {
+    /// Start the record of the request of a transaction
+    pub fn new(ctx: &TransactionCtx, request: &IcapRequest) -> Self {
+        let client_ip = request
+            .headers
+            .get(HEADER_CLIENT_IP)
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty());
+        AuditRecord {
+            time: Utc::now(),
+            transaction_id: ctx.id().to_string(),
+            request_id: ctx.request_id().to_string(),
+            peer: ctx.peer(),
+            client_ip,
+            user: ctx.identity().username.clone(),
+            tenant: ctx.tenant().map(|s| s.to_string()),
+            method: ctx.method().to_string(),
+            service: ctx.service().trim_matches('/').to_string(),
+            url: request.uri.to_string(),
+            status: None,
+            verdict: AuditVerdict::Error,
+            rule: None,
+            category: None,
+            threat: None,
+            detected_type: None,
+            error: None,
+            latency: Duration::ZERO,
+            bytes_in: 0,
+            bytes_out: 0,
+            labels: BTreeMap::new(),
+            modules: Vec::new(),
+        }
+    }
+
+    /// Finish the record with the response sent to the client
+    pub fn finish(&mut self, response: &IcapResponse) {
+        let header = |name: &str| {
+            response
+                .headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|s| s.to_string())
+        };
+        self.status = Some(response.status.as_u16());
+        self.verdict = AuditVerdict::of_response(response);
+        self.rule = header(HEADER_RULE_ID);
+        self.category = header(HEADER_RULE_CATEGORY);
+        self.threat = header(HEADER_VIRUS_ID);
+        self.detected_type = header(HEADER_DETECTED_TYPE);
+    }
+
+    /// Finish the record of a failed transaction
+    pub fn fail(&mut self, error: impl ToString) {
+        self.verdict = AuditVerdict::Error;
+        self.error = Some(error.to_string());
+    }
+
+    /// Copy the labels and the module verdicts gathered by the transaction
+    pub fn set_context(&mut self, ctx: &TransactionCtx) {
+        self.labels = ctx.labels();
+        self.modules = ctx.verdicts();
+    }
+
+    /// Set the duration and the transferred bytes of the transaction
+    pub fn set_transfer(&mut self, latency: Duration, bytes_in: u64, bytes_out: u64) {
+        self.latency = latency;
+        self.bytes_in = bytes_in;
+        self.bytes_out = bytes_out;
+    }
+
+    /// Key fields of the record, sent apart from the text by some sinks,
+    /// e.g. as syslog structured data
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = vec![
+            ("request_id", self.request_id.clone()),
+            ("service", self.service.clone()),
+            ("verdict", self.verdict.as_str().to_string()),
+        ];
+        if let Some(status) = self.status {
+            fields.push(("status", status.to_string()));
+        }
+        let optional = [
+            ("rule", &self.rule),
+            ("category", &self.category),
+            ("threat", &self.threat),
+        ];
+        for (name, value) in optional {
+            if let Some(value) = value {
+                fields.push((name, value.clone()));
+            }
+        }
+        fields
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let modules: serde_json::Map<String, serde_json::Value> = self
+            .modules
+            .iter()
+            .map(|m| (m.module.clone(), m.verdict.as_str().into()))
+            .collect();
+        serde_json::json!({
+            "time": self.time.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            "transaction_id": self.transaction_id,
+            "request_id": self.request_id,
+            "peer": self.peer.to_string(),
+            "client_ip": self.client_ip,
+            "user": self.user,
+            "tenant": self.tenant,
+            "method": self.method,
+            "service": self.service,
+            "url": self.url,
+            "status": self.status,
+            "verdict": self.verdict.as_str(),
+            "rule": self.rule,
+            "category": self.category,
+            "threat": self.threat,
+            "detected_type": self.detected_type,
+            "error": self.error,
+            "latency_ms": self.latency.as_millis() as u64,
+            "bytes_in": self.bytes_in,
+            "bytes_out": self.bytes_out,
+            "labels": self.labels,
+            "modules": modules,
+        })
+    }
+
+    /// Write the fields of the record, in the same order in all formats
+    fn write_siem(&self, w: &mut impl SiemWriter) {
+        w.time(self.time);
+        w.push(&siem::PROXY, &self.peer.ip().to_string());
+        w.push_opt(&siem::SOURCE, self.client_ip.as_deref());
+        w.push_opt(&siem::USER, self.user.as_deref());
+        w.push(&siem::METHOD, &self.method);
+        w.push(&siem::URL, &self.url);
+        w.push(&siem::ACTION, self.verdict.as_str());
+        w.push_opt(
+            &siem::OUTCOME,
+            self.status.map(|s| s.to_string()).as_deref(),
+        );
+        w.push_opt(&siem::CATEGORY, self.category.as_deref());
+        w.push(&siem::SERVICE, &self.service);
+        w.push_opt(&siem::RULE, self.rule.as_deref());
+        w.push_opt(&siem::THREAT, self.threat.as_deref());
+        w.push_opt(&siem::FILE_TYPE, self.detected_type.as_deref());
+        w.push_opt(&siem::REASON, self.error.as_deref());
+        w.push(&siem::LATENCY, &self.latency.as_millis().to_string());
+        w.push(&siem::REQUEST_ID, &self.request_id);
+        w.push(&siem::BYTES_IN, &self.bytes_in.to_string());
+        w.push(&siem::BYTES_OUT, &self.bytes_out.to_string());
+    }
+
+    /// Serialize as an ArcSight Common Event Format line
+    pub fn to_cef(&self) -> String {
+        let mut cef = CefWriter::new(
+            self.verdict.as_str(),
+            &format!("ICAP {}", self.method),
+            self.verdict.severity(),
+        );
+        self.write_siem(&mut cef);
+        cef.finish()
+    }
+
+    /// Serialize as a QRadar Log Event Extended Format line
+    pub fn to_leef(&self) -> String {
+        let mut leef = LeefWriter::new(self.verdict.as_str(), self.verdict.severity());
+        self.write_siem(&mut leef);
+        leef.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use http::{HeaderMap, Version};
+
+    use crate::protocol::common::IcapMethod;
+    use crate::protocol::response_generator::IcapResponseGenerator;
+    use crate::transaction::HEADER_REQUEST_ID;
+
+    fn record() -> AuditRecord {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-client-username", "alice".parse().unwrap());
+        headers.insert("x-client-ip", "192.0.2.7".parse().unwrap());
+        headers.insert(HEADER_REQUEST_ID, "c0ffee-42".parse().unwrap());
+        let request = IcapRequest {
+            method: IcapMethod::Reqmod,
+            uri: "icap://icap.example.net/reqmod?a=b".parse().unwrap(),
+            version: Version::HTTP_11,
+            headers,
+            body: Bytes::new(),
+            encapsulated: None,
+        };
+        let ctx = TransactionCtx::new("10.0.0.1:40000".parse().unwrap(), &request);
+        ctx.set_label("pipeline", "strict");
+        ctx.add_verdict("waf", AuditVerdict::Allowed);
+        let mut record = AuditRecord::new(&ctx, &request);
+        record.set_context(&ctx);
+        record.time = DateTime::from_timestamp_millis(1_700_000_000_123).unwrap();
+        record
+    }
+
+    fn generator() -> IcapResponseGenerator {
+        IcapResponseGenerator::new("G3ICAP/1.0.0".to_string(), "g3icap-1.0.0".to_string())
+    }
+
+    #[test]
+    fn verdicts() {
+        let generator = generator();
+        let mut record = record();
+        assert_eq!(record.verdict, AuditVerdict::Error);
+
+        record.finish(&generator.no_modifications(None));
+        assert_eq!(record.verdict, AuditVerdict::Allowed);
+        assert_eq!(record.status, Some(204));
+
+        let mut blocked = generator.no_modifications(None);
+        blocked.status = StatusCode::OK;
+        blocked.body = Bytes::from_static(b"blocked");
+        blocked
+            .headers
+            .insert(HEADER_RULE_ID, "default:keyword:2".parse().unwrap());
+        blocked
+            .headers
+            .insert(HEADER_RULE_CATEGORY, "keyword".parse().unwrap());
+        record.finish(&blocked);
+        assert_eq!(record.verdict, AuditVerdict::Blocked);
+        assert_eq!(record.rule.as_deref(), Some("default:keyword:2"));
+        assert_eq!(record.category.as_deref(), Some("keyword"));
+
+        blocked.headers.clear();
+        record.finish(&blocked);
+        assert_eq!(record.verdict, AuditVerdict::Modified);
+    }
+
+    #[test]
+    fn json() {
+        let mut record = record();
+        record.finish(&generator().no_modifications(None));
+        record.set_transfer(Duration::from_millis(12), 300, 100);
+        let v = record.to_json();
+        assert_eq!(v["time"], "2023-11-14T22:13:20.123Z");
+        assert_eq!(v["peer"], "10.0.0.1:40000");
+        assert_eq!(v["client_ip"], "192.0.2.7");
+        assert_eq!(v["user"], "alice");
+        assert_eq!(v["method"], "REQMOD");
+        assert_eq!(v["service"], "reqmod");
+        assert_eq!(v["verdict"], "allowed");
+        assert_eq!(v["status"], 204);
+        assert!(v["rule"].is_null());
+        assert!(v["tenant"].is_null());
+        assert_eq!(v["transaction_id"].as_str().unwrap().len(), 32);
+        assert_eq!(v["request_id"], "c0ffee-42");
+        assert_eq!(v["labels"]["pipeline"], "strict");
+        assert_eq!(v["modules"]["waf"], "allowed");
+        assert_eq!(v["latency_ms"], 12);
+        assert_eq!(v["bytes_in"], 300);
+    }
+
+    #[test]
+    fn cef() {
+        let mut record = record();
+        record.user = Some("a=b\\c".to_string());
+        record.method = "RE|QMOD".to_string();
+        record.fail("transaction timeout\nexpired");
+        let line = record.to_cef();
+        assert!(line.starts_with(&format!(
+            "CEF:0|ByteDance|g3icap|{}|error|ICAP RE\\|QMOD|5|rt=1700000000123 ",
+            crate::version::VERSION
+        )));
+        assert!(line.contains(" suser=a\\=b\\\\c "));
+        assert!(line.contains(" src=192.0.2.7 "));
+        assert!(line.contains(" reason=transaction timeout\\nexpired "));
+        assert!(!line.contains("cs2"));
+        assert!(line.ends_with(" in=0 out=0"));
+    }
+
+    fn blocked_record() -> AuditRecord {
+        let mut record = record();
+        record.status = Some(200);
+        record.verdict = AuditVerdict::Blocked;
+        record.rule = Some("default:malware:1".to_string());
+        record.category = Some("malware".to_string());
+        record.threat = Some("Eicar-Test-Signature".to_string());
+        record.detected_type = Some("application/x-msdownload".to_string());
+        record.set_transfer(Duration::from_millis(25), 1024, 512);
+        record
+    }
+
+    // The golden lines are what the SIEM parsers are built on, a change here
+    // breaks them and should only ever add new fields.
+
+    #[test]
+    fn cef_golden() {
+        let version = crate::version::VERSION;
+        assert_eq!(
+            blocked_record().to_cef(),
+            format!(
+                "CEF:0|ByteDance|g3icap|{version}|blocked|ICAP REQMOD|7|\
+                 rt=1700000000123 dvchost=10.0.0.1 src=192.0.2.7 suser=alice \
+                 requestMethod=REQMOD request=icap://icap.example.net/reqmod?a\\=b \
+                 act=blocked outcome=200 cat=malware cs1Label=service cs1=reqmod \
+                 cs2Label=rule cs2=default:malware:1 cs3Label=threat cs3=Eicar-Test-Signature \
+                 fileType=application/x-msdownload cn1Label=latencyMs cn1=25 \
+                 cs4Label=requestId cs4=c0ffee-42 in=1024 out=512"
+            )
+        );
+    }
+
+    #[test]
+    fn leef_golden() {
+        let version = crate::version::VERSION;
+        assert_eq!(
+            blocked_record().to_leef(),
+            format!(
+                "LEEF:2.0|ByteDance|g3icap|{version}|blocked|x09|sev=7\t\
+                 devTime=Nov 14 2023 22:13:20.123 UTC\tdevTimeFormat=MMM dd yyyy HH:mm:ss.SSS z\t\
+                 dvchost=10.0.0.1\tsrc=192.0.2.7\tusrName=alice\trequestMethod=REQMOD\t\
+                 url=icap://icap.example.net/reqmod?a=b\taction=blocked\toutcome=200\t\
+                 cat=malware\tservice=reqmod\trule=default:malware:1\t\
+                 threat=Eicar-Test-Signature\tfileType=application/x-msdownload\t\
+                 latencyMs=25\trequestId=c0ffee-42\tsrcBytes=1024\tdstBytes=512"
+            )
+        );
+
+        let mut record = record();
+        record.fail("read\ttimeout");
+        assert!(
+            record
+                .to_leef()
+                .contains("\taction=error\tservice=reqmod\treason=read timeout\t")
+        );
+    }
+}
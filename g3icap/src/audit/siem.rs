@@ -0,0 +1,265 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! SIEM formats of the audit records
+//!
+//! The records and the server events are exported as ArcSight Common Event
+//! Format lines or as QRadar Log Event Extended Format lines. The keys of
+//! each field in both formats are the constants below: they are what the
+//! SIEM parsers are built on, so they must not change between releases,
+//! only new fields may be added.
+
+use std::fmt::Write;
+
+use chrono::{DateTime, Utc};
+
+pub const VENDOR: &str = "ByteDance";
+pub const PRODUCT: &str = "g3icap";
+
+/// Version of the CEF header
+pub const CEF_VERSION: u8 = 0;
+/// Version of the LEEF header
+pub const LEEF_VERSION: &str = "2.0";
+/// Delimiter of the LEEF attributes, a tab, as set in the LEEF 2.0 header
+pub const LEEF_DELIMITER: char = '\t';
+/// Format of the LEEF `devTime` attribute, in Java `SimpleDateFormat` syntax
+pub const LEEF_TIME_FORMAT: &str = "MMM dd yyyy HH:mm:ss.SSS z";
+
+/// A field of the exported records, with its key in each format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SiemField {
+    /// Key in the CEF extension
+    pub cef: &'static str,
+    /// Label of a CEF custom field, sent as `<cef>Label`
+    pub cef_label: Option<&'static str>,
+    /// Key in the LEEF attributes
+    pub leef: &'static str,
+}
+
+impl SiemField {
+    const fn new(cef: &'static str, leef: &'static str) -> Self {
+        SiemField {
+            cef,
+            cef_label: None,
+            leef,
+        }
+    }
+
+    const fn custom(cef: &'static str, label: &'static str, leef: &'static str) -> Self {
+        SiemField {
+            cef,
+            cef_label: Some(label),
+            leef,
+        }
+    }
+}
+
+/// Time of the event, milliseconds since the epoch in CEF
+pub const TIME: SiemField = SiemField::new("rt", "devTime");
+/// Address of the ICAP client, the proxy
+pub const PROXY: SiemField = SiemField::new("dvchost", "dvchost");
+/// Address of the end user
+pub const SOURCE: SiemField = SiemField::new("src", "src");
+pub const USER: SiemField = SiemField::new("suser", "usrName");
+/// ICAP method
+pub const METHOD: SiemField = SiemField::new("requestMethod", "requestMethod");
+/// URL of the request, the ICAP URI for the records
+pub const URL: SiemField = SiemField::new("request", "url");
+pub const USER_AGENT: SiemField = SiemField::new("requestClientApplication", "userAgent");
+/// Verdict, `allowed`, `modified`, `blocked` or `error`
+pub const ACTION: SiemField = SiemField::new("act", "action");
+/// Status of the response
+pub const OUTCOME: SiemField = SiemField::new("outcome", "outcome");
+/// Category of the matched filter rule
+pub const CATEGORY: SiemField = SiemField::new("cat", "cat");
+/// ICAP service
+pub const SERVICE: SiemField = SiemField::custom("cs1", "service", "service");
+/// Id of the matched filter rule
+pub const RULE: SiemField = SiemField::custom("cs2", "rule", "rule");
+/// Threat found by the scanners
+pub const THREAT: SiemField = SiemField::custom("cs3", "threat", "threat");
+/// True type of the body
+pub const FILE_TYPE: SiemField = SiemField::new("fileType", "fileType");
+/// Failure of the transaction
+pub const REASON: SiemField = SiemField::new("reason", "reason");
+/// Duration of the transaction in milliseconds
+pub const LATENCY: SiemField = SiemField::custom("cn1", "latencyMs", "latencyMs");
+/// Id of the request, the one of the client if it sent one
+pub const REQUEST_ID: SiemField = SiemField::custom("cs4", "requestId", "requestId");
+pub const BYTES_IN: SiemField = SiemField::new("in", "srcBytes");
+pub const BYTES_OUT: SiemField = SiemField::new("out", "dstBytes");
+/// Details of a server event
+pub const MESSAGE: SiemField = SiemField::new("msg", "msg");
+
+/// Escape a CEF header field
+fn cef_header(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('|', "\\|")
+}
+
+/// Escape a LEEF header field, which can not hold the `|` separator
+fn leef_header(s: &str) -> String {
+    s.replace('|', "_")
+}
+
+/// Writer of the fields of a line in one of the formats
+pub trait SiemWriter {
+    /// Push the time of the event, the first field of a line
+    fn time(&mut self, time: DateTime<Utc>);
+
+    fn push(&mut self, field: &SiemField, value: &str);
+
+    fn push_opt(&mut self, field: &SiemField, value: Option<&str>) {
+        if let Some(value) = value {
+            self.push(field, value);
+        }
+    }
+}
+
+/// Writer of a CEF line
+pub struct CefWriter(String);
+
+impl CefWriter {
+    /// Start a line with its header, the severity being from 0 to 10
+    pub fn new(event_class: &str, name: &str, severity: u8) -> Self {
+        CefWriter(format!(
+            "CEF:{CEF_VERSION}|{VENDOR}|{PRODUCT}|{}|{}|{}|{severity}|",
+            cef_header(crate::version::VERSION),
+            cef_header(event_class),
+            cef_header(name),
+        ))
+    }
+
+    fn push_pair(&mut self, key: &str, value: &str) {
+        if !self.0.ends_with('|') {
+            self.0.push(' ');
+        }
+        let _ = write!(self.0, "{key}=");
+        for c in value.chars() {
+            match c {
+                '\\' => self.0.push_str("\\\\"),
+                '=' => self.0.push_str("\\="),
+                '\n' => self.0.push_str("\\n"),
+                '\r' => self.0.push_str("\\r"),
+                c => self.0.push(c),
+            }
+        }
+    }
+
+    pub fn finish(self) -> String {
+        self.0
+    }
+}
+
+impl SiemWriter for CefWriter {
+    fn time(&mut self, time: DateTime<Utc>) {
+        self.push(&TIME, &time.timestamp_millis().to_string());
+    }
+
+    fn push(&mut self, field: &SiemField, value: &str) {
+        if let Some(label) = field.cef_label {
+            self.push_pair(&format!("{}Label", field.cef), label);
+        }
+        self.push_pair(field.cef, value);
+    }
+}
+
+/// Writer of a LEEF 2.0 line
+pub struct LeefWriter(String);
+
+impl LeefWriter {
+    /// Start a line with its header and its severity, from 0 to 10
+    pub fn new(event_id: &str, severity: u8) -> Self {
+        let mut line = format!(
+            "LEEF:{LEEF_VERSION}|{VENDOR}|{PRODUCT}|{}|{}|x{:02x}|",
+            leef_header(crate::version::VERSION),
+            leef_header(event_id),
+            LEEF_DELIMITER as u32,
+        );
+        let _ = write!(line, "sev={severity}");
+        LeefWriter(line)
+    }
+
+    fn push_pair(&mut self, key: &str, value: &str) {
+        self.0.push(LEEF_DELIMITER);
+        let _ = write!(self.0, "{key}=");
+        // values can not be escaped, the delimiter and line breaks are replaced
+        for c in value.chars() {
+            match c {
+                LEEF_DELIMITER | '\n' | '\r' => self.0.push(' '),
+                c => self.0.push(c),
+            }
+        }
+    }
+
+    pub fn finish(self) -> String {
+        self.0
+    }
+}
+
+impl SiemWriter for LeefWriter {
+    fn time(&mut self, time: DateTime<Utc>) {
+        self.push_pair(
+            TIME.leef,
+            &time.format("%b %d %Y %H:%M:%S%.3f UTC").to_string(),
+        );
+        self.push_pair("devTimeFormat", LEEF_TIME_FORMAT);
+    }
+
+    fn push(&mut self, field: &SiemField, value: &str) {
+        self.push_pair(field.leef, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escaping() {
+        let mut cef = CefWriter::new("a|b", "c\\d", 5);
+        cef.push(&SERVICE, "x=y\nz");
+        let line = cef.finish();
+        assert!(line.ends_with("|a\\|b|c\\\\d|5|cs1Label=service cs1=x\\=y\\nz"));
+
+        let mut leef = LeefWriter::new("a|b", 5);
+        leef.push(&SERVICE, "x=y\tz\n");
+        let line = leef.finish();
+        assert!(line.ends_with("|a_b|x09|sev=5\tservice=x=y z "));
+    }
+
+    #[test]
+    fn event_golden() {
+        use crate::audit::ops::{AuditEvent, AuditEventType, AuditSeverity};
+
+        let event = AuditEvent {
+            timestamp: 1_700_000_000,
+            event_type: AuditEventType::ConfigChanged,
+            message: "Service pipeline changed".to_string(),
+            details: "reqmod".to_string(),
+            client_ip: None,
+            user_agent: None,
+            request_uri: None,
+            response_status: None,
+            metadata: Default::default(),
+            severity: AuditSeverity::Warning,
+        };
+        let version = crate::version::VERSION;
+        assert_eq!(
+            event.to_cef(),
+            format!(
+                "CEF:0|ByteDance|g3icap|{version}|ConfigChanged|Service pipeline changed|5|\
+                 rt=1700000000000 msg=reqmod"
+            )
+        );
+        assert_eq!(
+            event.to_leef(),
+            format!(
+                "LEEF:2.0|ByteDance|g3icap|{version}|ConfigChanged|x09|sev=5\t\
+                 devTime=Nov 14 2023 22:13:20.000 UTC\t\
+                 devTimeFormat=MMM dd yyyy HH:mm:ss.SSS z\tmsg=reqmod"
+            )
+        );
+    }
+}
@@ -0,0 +1,275 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Destinations of the audit records
+//!
+//! The sinks are blocking, they are only used from the audit log thread.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[cfg(feature = "kafka")]
+use super::kafka::KafkaSink;
+use super::syslog::SyslogSink;
+use crate::config::server::audit::AuditSinkConfig;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A serialized audit record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditLine {
+    pub text: String,
+    /// Syslog severity, from 0 (emergency) to 7 (debug)
+    pub severity: u8,
+    /// A blocked transaction or a security event, rather than an access
+    pub detection: bool,
+    /// Fields of the record, for the sinks sending them apart from the text
+    pub data: Vec<(&'static str, String)>,
+}
+
+/// A destination of the audit records
+pub trait AuditSink: Send {
+    /// Name of the sink, for the logs
+    fn name(&self) -> String;
+
+    fn write(&mut self, line: &AuditLine) -> io::Result<()>;
+
+    /// Called periodically, to write the records which are due
+    fn tick(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Write all the buffered records
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Build the sink of the config
+pub fn build(config: &AuditSinkConfig) -> Box<dyn AuditSink> {
+    match config {
+        AuditSinkConfig::File {
+            path,
+            max_size,
+            max_files,
+        } => Box::new(FileSink::new(path.clone(), *max_size, *max_files)),
+        AuditSinkConfig::Syslog(config) => Box::new(SyslogSink::new(config.clone())),
+        AuditSinkConfig::Tcp { address } => Box::new(TcpSink::new(*address)),
+        #[cfg(feature = "kafka")]
+        AuditSinkConfig::Kafka(config) => Box::new(KafkaSink::new(config.clone())),
+        // refused by the config parser
+        #[cfg(not(feature = "kafka"))]
+        AuditSinkConfig::Kafka(_) => unreachable!("kafka audit sink without the kafka feature"),
+    }
+}
+
+/// Append to a file, rotated by size
+pub struct FileSink {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    file: Option<File>,
+    size: u64,
+}
+
+impl FileSink {
+    pub fn new(path: PathBuf, max_size: u64, max_files: usize) -> Self {
+        FileSink {
+            path,
+            max_size,
+            max_files,
+            file: None,
+            size: 0,
+        }
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{index}"));
+        PathBuf::from(name)
+    }
+
+    fn open(&mut self) -> io::Result<File> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = file.metadata()?.len();
+        Ok(file)
+    }
+
+    /// Shift the rotated files and move the current one to `<path>.1`
+    fn rotate(&self) -> io::Result<()> {
+        if self.max_files == 0 {
+            return remove_if_exists(&self.path);
+        }
+        remove_if_exists(&self.rotated(self.max_files))?;
+        for i in (1..self.max_files).rev() {
+            let from = self.rotated(i);
+            if from.exists() {
+                std::fs::rename(&from, self.rotated(i + 1))?;
+            }
+        }
+        std::fs::rename(&self.path, self.rotated(1))
+    }
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+impl AuditSink for FileSink {
+    fn name(&self) -> String {
+        format!("file {}", self.path.display())
+    }
+
+    fn write(&mut self, line: &AuditLine) -> io::Result<()> {
+        let len = line.text.len() as u64 + 1;
+        let mut file = match self.file.take() {
+            Some(file) => file,
+            None => self.open()?,
+        };
+        if self.size > 0 && self.size + len > self.max_size {
+            drop(file);
+            self.rotate()?;
+            file = self.open()?;
+        }
+        let mut buf = Vec::with_capacity(line.text.len() + 1);
+        buf.extend_from_slice(line.text.as_bytes());
+        buf.push(b'\n');
+        file.write_all(&buf)?;
+        self.size += len;
+        self.file = Some(file);
+        Ok(())
+    }
+}
+
+/// Stream to a TCP collector, one record per line
+pub struct TcpSink {
+    address: SocketAddr,
+    stream: Option<TcpStream>,
+}
+
+impl TcpSink {
+    pub fn new(address: SocketAddr) -> Self {
+        TcpSink {
+            address,
+            stream: None,
+        }
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let stream = TcpStream::connect_timeout(&self.address, CONNECT_TIMEOUT)?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        stream.set_nodelay(true)?;
+        Ok(stream)
+    }
+}
+
+impl AuditSink for TcpSink {
+    fn name(&self) -> String {
+        format!("tcp {}", self.address)
+    }
+
+    fn write(&mut self, line: &AuditLine) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(line.text.len() + 1);
+        buf.extend_from_slice(line.text.as_bytes());
+        buf.push(b'\n');
+        // an established connection may have been closed by the collector
+        for _ in 0..2 {
+            let stream = match &mut self.stream {
+                Some(stream) => stream,
+                None => self.stream.insert(self.connect()?),
+            };
+            match stream.write_all(&buf) {
+                Ok(()) => return Ok(()),
+                Err(_) => self.stream = None,
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::BrokenPipe,
+            format!("failed to send to {}", self.address),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+
+    fn line(text: &str) -> AuditLine {
+        AuditLine {
+            text: text.to_string(),
+            severity: 6,
+            detection: false,
+            data: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn file_rotation() {
+        let dir = std::env::temp_dir().join(format!("g3icap-audit-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.log");
+        let mut sink = FileSink::new(path.clone(), 20, 2);
+        for i in 0..8 {
+            // 10 bytes with the newline, two lines per file
+            sink.write(&line(&format!("record-{i:02}"))).unwrap();
+        }
+        let read = |p: &Path| std::fs::read_to_string(p).unwrap();
+        assert_eq!(read(&path), "record-06\nrecord-07\n");
+        assert_eq!(read(&sink.rotated(1)), "record-04\nrecord-05\n");
+        assert_eq!(read(&sink.rotated(2)), "record-02\nrecord-03\n");
+        assert!(!sink.rotated(3).exists());
+
+        // an existing file is appended to, and its size counted
+        let mut sink = FileSink::new(path.clone(), 30, 2);
+        sink.write(&line("record-08")).unwrap();
+        assert_eq!(read(&path), "record-06\nrecord-07\nrecord-08\n");
+        sink.write(&line("record-09")).unwrap();
+        assert_eq!(read(&path), "record-09\n");
+        assert_eq!(read(&sink.rotated(1)), "record-06\nrecord-07\nrecord-08\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn tcp_reconnect() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut sink = TcpSink::new(listener.local_addr().unwrap());
+
+        sink.write(&line("first")).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut received = String::new();
+        reader.read_line(&mut received).unwrap();
+        assert_eq!(received, "first\n");
+
+        // the collector goes away, the next records reach its new connection
+        drop(reader);
+        listener.set_nonblocking(true).unwrap();
+        let mut accepted = None;
+        for _ in 0..100 {
+            let _ = sink.write(&line("after"));
+            if let Ok((stream, _)) = listener.accept() {
+                accepted = Some(stream);
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        let stream = accepted.unwrap();
+        stream.set_nonblocking(false).unwrap();
+        let mut received = String::new();
+        BufReader::new(stream).read_line(&mut received).unwrap();
+        assert_eq!(received, "after\n");
+    }
+}
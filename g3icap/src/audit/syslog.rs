@@ -0,0 +1,346 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Syslog sink of the audit records
+//!
+//! Messages are sent to the local daemon on `/dev/log`, or to a remote
+//! server over UDP, one message per datagram, or over TCP or TLS with the
+//! octet counting framing of RFC 6587. In the RFC 5424 format the fields of
+//! the record, such as the verdict, the category and the threat, are sent as
+//! structured data.
+
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::os::unix::net::UnixDatagram;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use rustls::{ClientConfig, ClientConnection, StreamOwned};
+use rustls_pki_types::ServerName;
+
+use super::sink::{AuditLine, AuditSink};
+use crate::config::server::audit::{SyslogFormat, SyslogSinkConfig, SyslogTransport};
+
+const SYSLOG_PATH: &str = "/dev/log";
+const APP_NAME: &str = "g3icap";
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+enum SyslogSocket {
+    Unix(UnixDatagram),
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl SyslogSocket {
+    fn send(&mut self, message: &str) -> io::Result<()> {
+        match self {
+            SyslogSocket::Unix(s) => s.send(message.as_bytes()).map(|_| ()),
+            SyslogSocket::Udp(s) => s.send(message.as_bytes()).map(|_| ()),
+            SyslogSocket::Tcp(s) => s.write_all(&frame(message)),
+            SyslogSocket::Tls(s) => {
+                s.write_all(&frame(message))?;
+                s.flush()
+            }
+        }
+    }
+}
+
+/// Octet counting framing of a message on a stream
+fn frame(message: &str) -> Vec<u8> {
+    let mut buf = format!("{} ", message.len()).into_bytes();
+    buf.extend_from_slice(message.as_bytes());
+    buf
+}
+
+/// Hostname field of RFC 5424, printable ASCII only
+fn header_hostname(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .take(255)
+        .map(|c| if c.is_ascii_graphic() { c } else { '-' })
+        .collect();
+    if name.is_empty() {
+        "-".to_string()
+    } else {
+        name
+    }
+}
+
+/// Structured data element of the fields, `-` if there are none
+fn structured_data(sd_id: &str, data: &[(&str, String)]) -> String {
+    if data.is_empty() {
+        return "-".to_string();
+    }
+    let mut sd = format!("[{sd_id}");
+    for (name, value) in data {
+        sd.push(' ');
+        sd.push_str(name);
+        sd.push_str("=\"");
+        for c in value.chars() {
+            if matches!(c, '"' | '\\' | ']') {
+                sd.push('\\');
+            }
+            sd.push(c);
+        }
+        sd.push('"');
+    }
+    sd.push(']');
+    sd
+}
+
+/// Send to a syslog daemon or server
+pub struct SyslogSink {
+    config: SyslogSinkConfig,
+    hostname: String,
+    socket: Option<SyslogSocket>,
+    tls_driver: Option<Arc<ClientConfig>>,
+}
+
+impl SyslogSink {
+    pub fn new(config: SyslogSinkConfig) -> Self {
+        let hostname = match &config.hostname {
+            Some(name) => name.clone(),
+            None => g3_compat::hostname().to_string_lossy().into_owned(),
+        };
+        SyslogSink {
+            hostname: header_hostname(&hostname),
+            config,
+            socket: None,
+            tls_driver: None,
+        }
+    }
+
+    fn resolve(&self) -> io::Result<(String, SocketAddr)> {
+        let Some(address) = &self.config.address else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no syslog server address",
+            ));
+        };
+        let host = address.host_str().to_string();
+        let addr = (host.as_str(), address.port())
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("no address for {host}"))
+            })?;
+        Ok((host, addr))
+    }
+
+    fn connect_tcp(addr: SocketAddr) -> io::Result<TcpStream> {
+        let stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+        stream.set_read_timeout(Some(WRITE_TIMEOUT))?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        stream.set_nodelay(true)?;
+        Ok(stream)
+    }
+
+    fn connect(&mut self) -> io::Result<SyslogSocket> {
+        match self.config.transport {
+            SyslogTransport::Unix => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(SYSLOG_PATH)?;
+                Ok(SyslogSocket::Unix(socket))
+            }
+            SyslogTransport::Udp => {
+                let (_, addr) = self.resolve()?;
+                let bind: SocketAddr = if addr.is_ipv4() {
+                    ([0, 0, 0, 0], 0).into()
+                } else {
+                    ([0u16; 8], 0).into()
+                };
+                let socket = UdpSocket::bind(bind)?;
+                socket.connect(addr)?;
+                Ok(SyslogSocket::Udp(socket))
+            }
+            SyslogTransport::Tcp => {
+                let (_, addr) = self.resolve()?;
+                Ok(SyslogSocket::Tcp(Self::connect_tcp(addr)?))
+            }
+            SyslogTransport::Tls => {
+                let (host, addr) = self.resolve()?;
+                let driver = match &self.tls_driver {
+                    Some(driver) => driver.clone(),
+                    None => {
+                        let tls_client =
+                            self.config.tls_client.build().map_err(io::Error::other)?;
+                        self.tls_driver.insert(tls_client.driver).clone()
+                    }
+                };
+                let name = self.config.tls_name.clone().unwrap_or(host);
+                let server_name = ServerName::try_from(name)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                let conn = ClientConnection::new(driver, server_name).map_err(io::Error::other)?;
+                let mut tls = StreamOwned::new(conn, Self::connect_tcp(addr)?);
+                // fail here rather than on the first message if the server is not trusted
+                while tls.conn.is_handshaking() {
+                    tls.conn.complete_io(&mut tls.sock)?;
+                }
+                Ok(SyslogSocket::Tls(Box::new(tls)))
+            }
+        }
+    }
+
+    fn message(&self, line: &AuditLine, time: DateTime<Utc>) -> String {
+        let priority = u16::from(self.config.facility) * 8 + u16::from(line.severity.min(7));
+        let pid = std::process::id();
+        match self.config.format {
+            // the local daemon adds the time and the hostname
+            SyslogFormat::Rfc3164 if self.config.transport == SyslogTransport::Unix => {
+                format!("<{priority}>{APP_NAME}[{pid}]: {}", line.text)
+            }
+            SyslogFormat::Rfc3164 => format!(
+                "<{priority}>{} {} {APP_NAME}[{pid}]: {}",
+                time.format("%b %e %H:%M:%S"),
+                self.hostname,
+                line.text
+            ),
+            SyslogFormat::Rfc5424 => {
+                let msg_id = if line.detection { "detection" } else { "audit" };
+                format!(
+                    "<{priority}>1 {} {} {APP_NAME} {pid} {msg_id} {} {}",
+                    time.to_rfc3339_opts(SecondsFormat::Millis, true),
+                    self.hostname,
+                    structured_data(&self.config.sd_id, &line.data),
+                    line.text
+                )
+            }
+        }
+    }
+}
+
+impl AuditSink for SyslogSink {
+    fn name(&self) -> String {
+        match &self.config.address {
+            Some(addr) => format!("syslog {addr}"),
+            None => format!("syslog {SYSLOG_PATH}"),
+        }
+    }
+
+    fn write(&mut self, line: &AuditLine) -> io::Result<()> {
+        let message = self.message(line, Utc::now());
+        // the server may have restarted, try again on a new connection
+        for _ in 0..2 {
+            let socket = match &mut self.socket {
+                Some(socket) => socket,
+                None => {
+                    let socket = self.connect()?;
+                    self.socket.insert(socket)
+                }
+            };
+            match socket.send(&message) {
+                Ok(()) => return Ok(()),
+                Err(_) => self.socket = None,
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::BrokenPipe,
+            format!("failed to send to {}", self.name()),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use g3_types::net::RustlsClientConfigBuilder;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    fn config(transport: SyslogTransport, address: Option<SocketAddr>) -> SyslogSinkConfig {
+        SyslogSinkConfig {
+            transport,
+            address: address.map(Into::into),
+            format: SyslogFormat::Rfc5424,
+            facility: 16,
+            tls_client: RustlsClientConfigBuilder::default(),
+            tls_name: None,
+            sd_id: "g3icap@32473".to_string(),
+            hostname: Some("icap 1".to_string()),
+        }
+    }
+
+    fn line(detection: bool, data: Vec<(&'static str, String)>) -> AuditLine {
+        AuditLine {
+            text: "{}".to_string(),
+            severity: 4,
+            detection,
+            data,
+        }
+    }
+
+    #[test]
+    fn messages() {
+        let time = DateTime::from_timestamp_millis(1_700_000_000_123).unwrap();
+        let pid = std::process::id();
+        let mut sink = SyslogSink::new(config(SyslogTransport::Unix, None));
+        let data = vec![
+            ("verdict", "blocked".to_string()),
+            ("threat", "Eicar \"test\" [x]".to_string()),
+        ];
+        assert_eq!(
+            sink.message(&line(true, data), time),
+            format!(
+                "<132>1 2023-11-14T22:13:20.123Z icap-1 g3icap {pid} detection \
+                 [g3icap@32473 verdict=\"blocked\" threat=\"Eicar \\\"test\\\" [x\\]\"] {{}}"
+            )
+        );
+        assert_eq!(
+            sink.message(&line(false, Vec::new()), time),
+            format!("<132>1 2023-11-14T22:13:20.123Z icap-1 g3icap {pid} audit - {{}}")
+        );
+
+        sink.config.format = SyslogFormat::Rfc3164;
+        assert_eq!(
+            sink.message(&line(false, Vec::new()), time),
+            format!("<132>g3icap[{pid}]: {{}}")
+        );
+        // remote servers, like the SIEM collectors, need the header
+        sink.config.transport = SyslogTransport::Udp;
+        let mut cef = line(true, Vec::new());
+        cef.text = "CEF:0|ByteDance|g3icap".to_string();
+        assert_eq!(
+            sink.message(&cef, time),
+            format!("<132>Nov 14 22:13:20 icap-1 g3icap[{pid}]: CEF:0|ByteDance|g3icap")
+        );
+    }
+
+    #[test]
+    fn udp() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut sink = SyslogSink::new(config(SyslogTransport::Udp, Some(addr)));
+        sink.write(&line(false, vec![("verdict", "allowed".to_string())]))
+            .unwrap();
+        let mut buf = [0u8; 1024];
+        let n = server.recv(&mut buf).unwrap();
+        let msg = std::str::from_utf8(&buf[..n]).unwrap();
+        assert!(msg.starts_with("<132>1 "));
+        assert!(msg.ends_with(" audit [g3icap@32473 verdict=\"allowed\"] {}"));
+    }
+
+    #[test]
+    fn tcp_framing() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut sink = SyslogSink::new(config(SyslogTransport::Tcp, Some(addr)));
+        sink.write(&line(false, Vec::new())).unwrap();
+        sink.write(&line(true, Vec::new())).unwrap();
+        drop(sink);
+
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut received = String::new();
+        stream.read_to_string(&mut received).unwrap();
+        let (len, rest) = received.split_once(' ').unwrap();
+        let len: usize = len.parse().unwrap();
+        assert!(rest[..len].ends_with(" audit - {}"));
+        let (len2, rest) = rest[len..].split_once(' ').unwrap();
+        assert_eq!(len2.parse::<usize>().unwrap(), rest.len());
+        assert!(rest.ends_with(" detection - {}"));
+    }
+}
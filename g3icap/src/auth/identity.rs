@@ -0,0 +1,56 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! End user identity carried in ICAP requests
+//!
+//! The proxy forwards the user it authenticated in the `X-Client-Username`
+//! ICAP header, and optionally the user's groups in `X-Client-Groups`.
+
+use http::HeaderMap;
+
+/// ICAP header carrying the end user name
+pub const HEADER_CLIENT_USERNAME: &str = "x-client-username";
+/// ICAP header carrying the comma separated end user groups
+pub const HEADER_CLIENT_GROUPS: &str = "x-client-groups";
+/// ICAP header carrying the end user IP address
+pub const HEADER_CLIENT_IP: &str = "x-client-ip";
+/// ICAP header carrying the tenant of the end user, for multi-tenant proxies
+pub const HEADER_CLIENT_TENANT: &str = "x-client-tenant";
+
+/// Identity of the end user of an ICAP transaction
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientIdentity {
+    /// User name, if the proxy authenticated the user
+    pub username: Option<String>,
+    /// Groups of the user, in the order given by the proxy
+    pub groups: Vec<String>,
+}
+
+impl ClientIdentity {
+    /// Extract the identity from ICAP request headers
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let username = headers
+            .get(HEADER_CLIENT_USERNAME)
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string());
+        let groups = headers
+            .get_all(HEADER_CLIENT_GROUPS)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string())
+            .collect();
+        ClientIdentity { username, groups }
+    }
+
+    /// Check if the identity is anonymous
+    pub fn is_anonymous(&self) -> bool {
+        self.username.is_none() && self.groups.is_empty()
+    }
+}
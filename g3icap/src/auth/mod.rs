@@ -10,8 +10,10 @@
 
 pub mod ops;
 pub mod registry;
+pub mod identity;
+pub mod token;
 
-// Auth module placeholder
+use std::sync::Arc;
 
 /// User authentication result
 #[derive(Debug, Clone)]
@@ -66,6 +68,17 @@ pub trait IcapAuthHandler: Send + Sync {
 
 /// Load all authentication handlers
 pub async fn load_all() -> anyhow::Result<()> {
-    // For now, just return Ok as we don't have complex auth loading
+    use crate::config::server::AnyServerConfig;
+
+    // Client token authentication comes from the server config. Keys are
+    // replaced as a whole, so rotating a key only needs a config reload.
+    let mut authenticator = None;
+    for (_name, server) in crate::config::server::get_all() {
+        let AnyServerConfig::Icap(config) = server;
+        if let Some(auth_config) = config.client_auth() {
+            authenticator = Some(Arc::new(token::TokenAuthenticator::new(auth_config.clone())));
+        }
+    }
+    token::set_global(authenticator);
     Ok(())
 }
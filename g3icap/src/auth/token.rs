@@ -0,0 +1,437 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Token based authentication of ICAP clients
+//!
+//! Verifies the static API token or HS256 JWT presented by a client in the
+//! configured ICAP header, and keeps per-token usage statistics.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use arc_swap::ArcSwapOption;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use foldhash::fast::FixedState;
+use http::HeaderMap;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use thiserror::Error;
+
+use super::{AuthContext, AuthResult, IcapAuthHandler};
+use crate::config::server::client_auth::ClientAuthConfig;
+
+/// The authenticator currently in use, swapped on config reload
+static GLOBAL_AUTHENTICATOR: ArcSwapOption<TokenAuthenticator> = ArcSwapOption::const_empty();
+
+/// Install a new authenticator, or disable client authentication with `None`
+pub fn set_global(authenticator: Option<Arc<TokenAuthenticator>>) {
+    GLOBAL_AUTHENTICATOR.store(authenticator);
+}
+
+/// Get the authenticator currently in use
+pub fn get_global() -> Option<Arc<TokenAuthenticator>> {
+    GLOBAL_AUTHENTICATOR.load_full()
+}
+
+/// Reasons a credential is rejected
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum TokenAuthError {
+    #[error("no credential presented")]
+    Missing,
+    #[error("malformed credential")]
+    Malformed,
+    #[error("unknown token or key")]
+    UnknownKey,
+    #[error("invalid signature")]
+    BadSignature,
+    #[error("token expired")]
+    Expired,
+    #[error("token not yet valid")]
+    NotYetValid,
+    #[error("issuer mismatch")]
+    IssuerMismatch,
+}
+
+impl TokenAuthError {
+    /// Value of the `error` parameter in WWW-Authenticate
+    pub fn challenge_error(&self) -> &'static str {
+        match self {
+            TokenAuthError::Missing => "invalid_request",
+            _ => "invalid_token",
+        }
+    }
+}
+
+/// Identity of an authenticated client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenIdentity {
+    /// Token id (API token id or `jwt:<kid>`)
+    pub token_id: String,
+    /// Subject, the JWT `sub` claim or the token id
+    pub subject: String,
+}
+
+/// Usage statistics of a single token or JWT key
+#[derive(Debug, Default)]
+pub struct TokenStats {
+    accepted: AtomicU64,
+    rejected: AtomicU64,
+    last_used: AtomicU64,
+}
+
+impl TokenStats {
+    fn add_accepted(&self) {
+        self.accepted.fetch_add(1, Ordering::Relaxed);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        self.last_used.store(now, Ordering::Relaxed);
+    }
+
+    fn add_rejected(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of accepted requests
+    pub fn accepted(&self) -> u64 {
+        self.accepted.load(Ordering::Relaxed)
+    }
+
+    /// Number of rejected requests (e.g. expired JWT signed by this key)
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Unix timestamp of the last accepted request, 0 if never used
+    pub fn last_used(&self) -> u64 {
+        self.last_used.load(Ordering::Relaxed)
+    }
+}
+
+/// Verifies client credentials against a [`ClientAuthConfig`]
+pub struct TokenAuthenticator {
+    config: ClientAuthConfig,
+    /// API token secret -> id
+    api_tokens: HashMap<Vec<u8>, String, FixedState>,
+    /// JWT kid -> secret
+    jwt_keys: HashMap<String, Vec<u8>, FixedState>,
+    /// Per token / key usage stats
+    stats: HashMap<String, Arc<TokenStats>, FixedState>,
+    /// Requests without any usable credential
+    unauthenticated: AtomicU64,
+}
+
+impl TokenAuthenticator {
+    /// Create a new authenticator
+    pub fn new(config: ClientAuthConfig) -> Self {
+        let mut api_tokens = HashMap::with_hasher(FixedState::default());
+        let mut jwt_keys = HashMap::with_hasher(FixedState::default());
+        let mut stats = HashMap::with_hasher(FixedState::default());
+        for t in &config.api_tokens {
+            api_tokens.insert(t.secret.as_bytes().to_vec(), t.id.clone());
+            stats.insert(t.id.clone(), Arc::new(TokenStats::default()));
+        }
+        for k in &config.jwt_keys {
+            jwt_keys.insert(k.kid.clone(), k.secret.as_bytes().to_vec());
+            stats.insert(format!("jwt:{}", k.kid), Arc::new(TokenStats::default()));
+        }
+        TokenAuthenticator {
+            config,
+            api_tokens,
+            jwt_keys,
+            stats,
+            unauthenticated: AtomicU64::new(0),
+        }
+    }
+
+    /// Get the configuration
+    pub fn config(&self) -> &ClientAuthConfig {
+        &self.config
+    }
+
+    /// Value of the WWW-Authenticate header for a rejected request
+    pub fn challenge(&self, err: TokenAuthError) -> String {
+        format!(
+            "Bearer realm=\"{}\", error=\"{}\"",
+            self.config.realm,
+            err.challenge_error()
+        )
+    }
+
+    /// Authenticate the credential found in the ICAP request headers
+    pub fn authenticate_headers(
+        &self,
+        headers: &HeaderMap,
+    ) -> Result<TokenIdentity, TokenAuthError> {
+        let result = headers
+            .get(self.config.header.as_str())
+            .ok_or(TokenAuthError::Missing)
+            .and_then(|v| v.to_str().map_err(|_| TokenAuthError::Malformed))
+            .and_then(|v| self.verify(v));
+        if result.is_err() {
+            self.unauthenticated.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Verify a raw header value
+    pub fn verify(&self, value: &str) -> Result<TokenIdentity, TokenAuthError> {
+        let value = value.trim();
+        let credential = match value.split_once(' ') {
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("bearer") => rest.trim(),
+            _ => value,
+        };
+        if credential.is_empty() {
+            return Err(TokenAuthError::Missing);
+        }
+
+        if credential.matches('.').count() == 2 {
+            return self.verify_jwt(credential);
+        }
+        self.verify_api_token(credential)
+    }
+
+    fn verify_api_token(&self, credential: &str) -> Result<TokenIdentity, TokenAuthError> {
+        let input = credential.as_bytes();
+        // compare every configured secret in constant time
+        let mut found: Option<&String> = None;
+        for (secret, id) in &self.api_tokens {
+            if secret.len() == input.len() && openssl::memcmp::eq(secret, input) {
+                found = Some(id);
+            }
+        }
+        let id = found.ok_or(TokenAuthError::UnknownKey)?;
+        if let Some(stats) = self.stats.get(id) {
+            stats.add_accepted();
+        }
+        Ok(TokenIdentity {
+            token_id: id.clone(),
+            subject: id.clone(),
+        })
+    }
+
+    fn verify_jwt(&self, token: &str) -> Result<TokenIdentity, TokenAuthError> {
+        let mut parts = token.split('.');
+        let (Some(header_b64), Some(claims_b64), Some(sig_b64)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(TokenAuthError::Malformed);
+        };
+
+        let header = decode_json(header_b64)?;
+        if header.get("alg").and_then(|v| v.as_str()) != Some("HS256") {
+            return Err(TokenAuthError::Malformed);
+        }
+
+        // Select the key by kid, or try every key if the token has none
+        let candidates: Vec<(&String, &Vec<u8>)> = match header.get("kid").and_then(|v| v.as_str())
+        {
+            Some(kid) => self.jwt_keys.get_key_value(kid).into_iter().collect(),
+            None => self.jwt_keys.iter().collect(),
+        };
+        if candidates.is_empty() {
+            return Err(TokenAuthError::UnknownKey);
+        }
+
+        let signature = URL_SAFE_NO_PAD
+            .decode(sig_b64)
+            .map_err(|_| TokenAuthError::Malformed)?;
+        let signing_input = &token[..header_b64.len() + 1 + claims_b64.len()];
+        let kid = candidates
+            .into_iter()
+            .find(|(_, secret)| hs256_verify(secret, signing_input.as_bytes(), &signature))
+            .map(|(kid, _)| kid)
+            .ok_or(TokenAuthError::BadSignature)?;
+        let stats = self.stats.get(&format!("jwt:{kid}"));
+
+        let claims = decode_json(claims_b64)?;
+        if let Err(e) = self.check_claims(&claims) {
+            if let Some(stats) = stats {
+                stats.add_rejected();
+            }
+            return Err(e);
+        }
+
+        if let Some(stats) = stats {
+            stats.add_accepted();
+        }
+        let token_id = format!("jwt:{kid}");
+        let subject = claims
+            .get("sub")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| token_id.clone());
+        Ok(TokenIdentity { token_id, subject })
+    }
+
+    fn check_claims(&self, claims: &serde_json::Value) -> Result<(), TokenAuthError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let leeway = self.config.jwt_leeway.as_secs();
+
+        if let Some(exp) = claims.get("exp").and_then(|v| v.as_u64())
+            && now > exp.saturating_add(leeway)
+        {
+            return Err(TokenAuthError::Expired);
+        }
+        if let Some(nbf) = claims.get("nbf").and_then(|v| v.as_u64())
+            && now.saturating_add(leeway) < nbf
+        {
+            return Err(TokenAuthError::NotYetValid);
+        }
+        if let Some(issuer) = &self.config.jwt_issuer
+            && claims.get("iss").and_then(|v| v.as_str()) != Some(issuer.as_str())
+        {
+            return Err(TokenAuthError::IssuerMismatch);
+        }
+        Ok(())
+    }
+
+    /// Get the usage stats of all tokens and keys
+    pub fn token_stats(&self) -> Vec<(String, Arc<TokenStats>)> {
+        let mut all: Vec<_> = self
+            .stats
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        all.sort_by(|a, b| a.0.cmp(&b.0));
+        all
+    }
+
+    /// Number of requests rejected for missing or invalid credentials
+    pub fn unauthenticated(&self) -> u64 {
+        self.unauthenticated.load(Ordering::Relaxed)
+    }
+}
+
+impl IcapAuthHandler for TokenAuthenticator {
+    fn authenticate(&self, context: &AuthContext) -> AuthResult {
+        let value = context
+            .headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(&self.config.header))
+            .map(|(_, v)| v.as_str());
+        match value.map(|v| self.verify(v)) {
+            Some(Ok(identity)) => AuthResult::success(identity.subject, Vec::new()),
+            _ => {
+                self.unauthenticated.fetch_add(1, Ordering::Relaxed);
+                AuthResult::failure()
+            }
+        }
+    }
+
+    fn is_authorized(&self, _username: &str, _action: &str) -> bool {
+        true
+    }
+}
+
+fn decode_json(part: &str) -> Result<serde_json::Value, TokenAuthError> {
+    let data = URL_SAFE_NO_PAD
+        .decode(part)
+        .map_err(|_| TokenAuthError::Malformed)?;
+    serde_json::from_slice(&data).map_err(|_| TokenAuthError::Malformed)
+}
+
+fn hs256_sign(secret: &[u8], data: &[u8]) -> Option<Vec<u8>> {
+    let key = PKey::hmac(secret).ok()?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).ok()?;
+    signer.update(data).ok()?;
+    signer.sign_to_vec().ok()
+}
+
+fn hs256_verify(secret: &[u8], data: &[u8], signature: &[u8]) -> bool {
+    match hs256_sign(secret, data) {
+        Some(mac) => mac.len() == signature.len() && openssl::memcmp::eq(&mac, signature),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::server::client_auth::{ApiTokenConfig, JwtKeyConfig};
+
+    fn make_jwt(kid: &str, secret: &str, claims: serde_json::Value) -> String {
+        let header = serde_json::json!({"alg": "HS256", "typ": "JWT", "kid": kid});
+        let h = URL_SAFE_NO_PAD.encode(header.to_string());
+        let c = URL_SAFE_NO_PAD.encode(claims.to_string());
+        let input = format!("{h}.{c}");
+        let sig = hs256_sign(secret.as_bytes(), input.as_bytes()).unwrap();
+        format!("{input}.{}", URL_SAFE_NO_PAD.encode(sig))
+    }
+
+    fn authenticator() -> TokenAuthenticator {
+        TokenAuthenticator::new(ClientAuthConfig {
+            api_tokens: vec![ApiTokenConfig {
+                id: "proxy-a".to_string(),
+                secret: "s3cret".to_string(),
+            }],
+            jwt_keys: vec![
+                JwtKeyConfig {
+                    kid: "old".to_string(),
+                    secret: "old-key".to_string(),
+                },
+                JwtKeyConfig {
+                    kid: "new".to_string(),
+                    secret: "new-key".to_string(),
+                },
+            ],
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn api_token() {
+        let auth = authenticator();
+        let id = auth.verify("Bearer s3cret").unwrap();
+        assert_eq!(id.token_id, "proxy-a");
+        assert_eq!(auth.verify("wrong"), Err(TokenAuthError::UnknownKey));
+        let stats = auth.token_stats();
+        let (_, s) = stats.iter().find(|(k, _)| k == "proxy-a").unwrap();
+        assert_eq!(s.accepted(), 1);
+    }
+
+    #[test]
+    fn jwt_key_rotation() {
+        let auth = authenticator();
+        let claims = serde_json::json!({"sub": "proxy-b"});
+        let old = make_jwt("old", "old-key", claims.clone());
+        let new = make_jwt("new", "new-key", claims);
+        assert_eq!(auth.verify(&old).unwrap().token_id, "jwt:old");
+        assert_eq!(auth.verify(&new).unwrap().subject, "proxy-b");
+
+        let forged = make_jwt("new", "old-key", serde_json::json!({}));
+        assert_eq!(auth.verify(&forged), Err(TokenAuthError::BadSignature));
+    }
+
+    #[test]
+    fn jwt_expired() {
+        let auth = authenticator();
+        let token = make_jwt("new", "new-key", serde_json::json!({"exp": 1000}));
+        assert_eq!(auth.verify(&token), Err(TokenAuthError::Expired));
+    }
+
+    #[test]
+    fn missing_header() {
+        let auth = authenticator();
+        let headers = HeaderMap::new();
+        assert_eq!(
+            auth.authenticate_headers(&headers),
+            Err(TokenAuthError::Missing)
+        );
+        assert_eq!(auth.unauthenticated(), 1);
+        assert!(
+            auth.challenge(TokenAuthError::Missing)
+                .starts_with("Bearer realm=\"g3icap\"")
+        );
+    }
+}
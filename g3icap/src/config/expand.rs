@@ -0,0 +1,330 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Expansion of the config files
+//!
+//! So that the secrets and the large lists can live outside of the main
+//! config file, its values are expanded when it is loaded:
+//!
+//! - `${NAME}` is replaced by the value of the environment variable `NAME`,
+//!   and `${NAME:-default}` by the default if it is not set. `$$` is a
+//!   literal `$`. An unquoted value is typed after the replacement, so that
+//!   `port: ${PORT}` is an integer.
+//! - a value tagged `!include path` is replaced by the single document of
+//!   the file, the path being relative to the including file. The included
+//!   files are expanded too, and including a file from itself, directly or
+//!   not, is an error.
+//! - a value tagged `!secret ref` is replaced by the secret, see
+//!   [`super::secrets`].
+//!
+//! With trusted keys, the signature of each file is verified before it is
+//! parsed, see [`super::provenance`].
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser, Tag};
+use yaml_rust::scanner::{Marker, TScalarStyle};
+use yaml_rust::{Yaml, yaml};
+
+use super::provenance::{self, ArtifactKind};
+use super::secrets::{SecretRef, SecretResolver};
+
+/// Tag of the values replaced by an included file
+const INCLUDE_TAG: &str = "include";
+/// Tag of the values replaced by a secret
+const SECRET_TAG: &str = "secret";
+/// Handle of the core schema tags
+const CORE_HANDLE: &str = "tag:yaml.org,2002:";
+
+/// Load the expanded documents of a config file
+pub fn load(file: &Path) -> anyhow::Result<Vec<Yaml>> {
+    load_with_env(file, &|name| std::env::var(name).ok())
+}
+
+/// Load the expanded documents of a config file, with the variables looked
+/// up by `env`
+pub fn load_with_env(
+    file: &Path,
+    env: &dyn Fn(&str) -> Option<String>,
+) -> anyhow::Result<Vec<Yaml>> {
+    load_file(file, env, &mut Vec::new(), &mut SecretResolver::new(env))
+}
+
+fn load_file<'e>(
+    file: &Path,
+    env: &'e dyn Fn(&str) -> Option<String>,
+    chain: &mut Vec<PathBuf>,
+    secrets: &mut SecretResolver<'e>,
+) -> anyhow::Result<Vec<Yaml>> {
+    let path = file
+        .canonicalize()
+        .map_err(|e| anyhow!("failed to open config file {}: {e}", file.display()))?;
+    if chain.contains(&path) {
+        let cycle: Vec<String> = chain
+            .iter()
+            .chain([&path])
+            .map(|p| p.display().to_string())
+            .collect();
+        return Err(anyhow!("include cycle: {}", cycle.join(" -> ")));
+    }
+    let text = std::fs::read_to_string(&path)
+        .map_err(|e| anyhow!("failed to read config file {}: {e}", file.display()))?;
+    provenance::check_file(ArtifactKind::Config, &path, text.as_bytes())?;
+
+    chain.push(path);
+    let mut loader = Loader {
+        file,
+        env,
+        chain,
+        secrets,
+        docs: Vec::new(),
+        nodes: Vec::new(),
+        keys: Vec::new(),
+        anchors: HashMap::new(),
+        error: None,
+    };
+    let r = Parser::new_from_str(&text).load(&mut loader, true);
+    let Loader { docs, error, .. } = loader;
+    chain.pop();
+
+    r.map_err(|e| anyhow!("invalid yaml in config file {}: {e}", file.display()))?;
+    match error {
+        Some(e) => Err(e),
+        None => Ok(docs),
+    }
+}
+
+/// Replace the variables in a value
+fn substitute(value: &str, env: &dyn Fn(&str) -> Option<String>) -> anyhow::Result<String> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(n) = rest.find('$') {
+        expanded.push_str(&rest[..n]);
+        rest = &rest[n + 1..];
+        if let Some(r) = rest.strip_prefix('$') {
+            expanded.push('$');
+            rest = r;
+            continue;
+        }
+        let Some(r) = rest.strip_prefix('{') else {
+            expanded.push('$');
+            continue;
+        };
+        let end = r
+            .find('}')
+            .ok_or_else(|| anyhow!("unterminated variable in {value}"))?;
+        let (name, default) = match r[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&r[..end], None),
+        };
+        if name.is_empty() {
+            return Err(anyhow!("empty variable name in {value}"));
+        }
+        match env(name).or_else(|| default.map(str::to_string)) {
+            Some(v) => expanded.push_str(&v),
+            None => return Err(anyhow!("environment variable {name} is not set")),
+        }
+        rest = &r[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Builder of the expanded documents, from the parser events
+struct Loader<'a, 'e> {
+    file: &'a Path,
+    env: &'e dyn Fn(&str) -> Option<String>,
+    /// Files being loaded, the including ones first
+    chain: &'a mut Vec<PathBuf>,
+    secrets: &'a mut SecretResolver<'e>,
+    docs: Vec<Yaml>,
+    /// Collections being built, with their anchor
+    nodes: Vec<(Yaml, usize)>,
+    /// Key of each map being built, `BadValue` if a key is expected
+    keys: Vec<Yaml>,
+    anchors: HashMap<usize, Yaml>,
+    error: Option<anyhow::Error>,
+}
+
+impl Loader<'_, '_> {
+    fn scalar(
+        &mut self,
+        value: String,
+        style: TScalarStyle,
+        tag: Option<Tag>,
+    ) -> anyhow::Result<Yaml> {
+        let value = substitute(&value, self.env)?;
+        match tag {
+            Some(tag) if tag.handle == "!" && tag.suffix == INCLUDE_TAG => self.include(&value),
+            Some(tag) if tag.handle == "!" && tag.suffix == SECRET_TAG => {
+                let secret = value.parse::<SecretRef>()?;
+                let secret = self.secrets.resolve(&secret, self.file.parent())?;
+                Ok(Yaml::String(secret))
+            }
+            Some(tag) if tag.handle == CORE_HANDLE && tag.suffix == "str" => {
+                Ok(Yaml::String(value))
+            }
+            _ if style != TScalarStyle::Plain => Ok(Yaml::String(value)),
+            Some(tag) if tag.handle != CORE_HANDLE => Ok(Yaml::String(value)),
+            _ => Ok(Yaml::from_str(&value)),
+        }
+    }
+
+    fn include(&mut self, path: &str) -> anyhow::Result<Yaml> {
+        let path = match self.file.parent() {
+            Some(dir) => dir.join(path),
+            None => PathBuf::from(path),
+        };
+        let mut docs = load_file(&path, self.env, self.chain, self.secrets)?;
+        if docs.len() != 1 {
+            return Err(anyhow!(
+                "included file {} should have a single document",
+                path.display()
+            ));
+        }
+        Ok(docs.remove(0))
+    }
+
+    fn insert(&mut self, node: Yaml, anchor: usize) {
+        if anchor > 0 {
+            self.anchors.insert(anchor, node.clone());
+        }
+        match self.nodes.last_mut() {
+            Some((Yaml::Array(items), _)) => items.push(node),
+            Some((Yaml::Hash(map), _)) => {
+                let key = self.keys.last_mut().unwrap();
+                if let Yaml::BadValue = key {
+                    *key = node;
+                } else {
+                    map.insert(std::mem::replace(key, Yaml::BadValue), node);
+                }
+            }
+            _ => self.nodes.push((node, 0)),
+        }
+    }
+
+    fn on_event_impl(&mut self, ev: Event, mark: Marker) -> anyhow::Result<()> {
+        match ev {
+            Event::DocumentEnd => match self.nodes.pop() {
+                Some((doc, _)) => self.docs.push(doc),
+                None => self.docs.push(Yaml::BadValue),
+            },
+            Event::SequenceStart(anchor, _) => self.nodes.push((Yaml::Array(Vec::new()), anchor)),
+            Event::MappingStart(anchor, _) => {
+                self.nodes.push((Yaml::Hash(yaml::Hash::new()), anchor));
+                self.keys.push(Yaml::BadValue);
+            }
+            Event::SequenceEnd | Event::MappingEnd => {
+                if matches!(ev, Event::MappingEnd) {
+                    self.keys.pop();
+                }
+                if let Some((node, anchor)) = self.nodes.pop() {
+                    self.insert(node, anchor);
+                }
+            }
+            Event::Scalar(value, style, anchor, tag) => {
+                let node = self.scalar(value, style, tag).map_err(|e| {
+                    anyhow!(
+                        "{}:{}:{}: {e}",
+                        self.file.display(),
+                        mark.line(),
+                        mark.col() + 1
+                    )
+                })?;
+                self.insert(node, anchor);
+            }
+            Event::Alias(anchor) => {
+                let node = self.anchors.get(&anchor).cloned().unwrap_or(Yaml::BadValue);
+                self.insert(node, 0);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl MarkedEventReceiver for Loader<'_, '_> {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(e) = self.on_event_impl(ev, mark) {
+            self.error = Some(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(name: &str) -> Option<String> {
+        match name {
+            "CLAMD_ADDR" => Some("10.0.0.1:3310".to_string()),
+            "PORT" => Some("1344".to_string()),
+            _ => None,
+        }
+    }
+
+    fn write(dir: &Path, name: &str, text: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn variables() {
+        assert_eq!(substitute("${CLAMD_ADDR}", &env).unwrap(), "10.0.0.1:3310");
+        assert_eq!(substitute("a${MISSING:-b}c", &env).unwrap(), "abc");
+        assert_eq!(substitute("$$HOME $5", &env).unwrap(), "$HOME $5");
+        assert!(substitute("${MISSING}", &env).is_err());
+        assert!(substitute("${PORT", &env).is_err());
+    }
+
+    #[test]
+    fn includes() {
+        let dir = std::env::temp_dir().join(format!("g3icap-expand-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("rules")).unwrap();
+        write(&dir, "rules/domains.yaml", "- example.com\n- example.net\n");
+        let main = write(
+            &dir,
+            "main.yaml",
+            "port: ${PORT}\nname: '${PORT}'\nclamd: ${CLAMD_ADDR}\n\
+             domains: !include rules/domains.yaml\n---\nlog: stdout\n",
+        );
+        let docs = load_with_env(&main, &env).unwrap();
+        assert_eq!(docs.len(), 2);
+        assert_eq!(docs[0]["port"], Yaml::Integer(1344));
+        assert_eq!(docs[0]["name"], Yaml::String("1344".to_string()));
+        assert_eq!(docs[0]["clamd"].as_str(), Some("10.0.0.1:3310"));
+        assert_eq!(docs[0]["domains"][1].as_str(), Some("example.net"));
+
+        write(&dir, "a.yaml", "b: !include b.yaml\n");
+        write(&dir, "b.yaml", "a: !include a.yaml\n");
+        let e = load_with_env(&dir.join("a.yaml"), &env).unwrap_err();
+        assert!(e.to_string().contains("include cycle"), "{e}");
+
+        write(&dir, "api_key", "k3y\n");
+        let secret = write(
+            &dir,
+            "secret.yaml",
+            "api_key: !secret file:api_key\nport: !secret env:PORT\n",
+        );
+        let docs = load_with_env(&secret, &env).unwrap();
+        assert_eq!(docs[0]["api_key"].as_str(), Some("k3y"));
+        assert_eq!(docs[0]["port"].as_str(), Some("1344"));
+
+        let missing = write(&dir, "missing.yaml", "key: ${MISSING}\n");
+        let e = load_with_env(&missing, &env).unwrap_err();
+        assert!(
+            e.to_string()
+                .ends_with("missing.yaml:1:6: environment variable MISSING is not set"),
+            "{e}"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -13,6 +13,8 @@ use g3_types::sync::GlobalInit;
 
 static ICAP_DEFAULT_LOG_CONFIG_CONTAINER: GlobalInit<LogConfigContainer> =
     GlobalInit::new(LogConfigContainer::new());
+static ICAP_DETECTION_LOG_CONFIG_CONTAINER: GlobalInit<LogConfigContainer> =
+    GlobalInit::new(LogConfigContainer::new());
 
 pub(crate) fn load(v: &Yaml, conf_dir: &Path) -> anyhow::Result<()> {
     let mut default_log_config: Option<LogConfig> = None;
@@ -47,6 +49,12 @@ pub(crate) fn load(v: &Yaml, conf_dir: &Path) -> anyhow::Result<()> {
                     ICAP_DEFAULT_LOG_CONFIG_CONTAINER.with_mut(|l| l.set(config));
                     Ok(())
                 }
+                "detection" => {
+                    let config = LogConfig::parse_yaml(v, conf_dir, "g3icap")
+                        .context(format!("invalid value for key {k}"))?;
+                    ICAP_DETECTION_LOG_CONFIG_CONTAINER.with_mut(|l| l.set(config));
+                    Ok(())
+                }
                 _ => Err(anyhow!("invalid key {k}")),
             })?;
         }
@@ -71,4 +79,13 @@ pub fn get_server_default_config() -> LogConfig {
     ICAP_DEFAULT_LOG_CONFIG_CONTAINER
         .as_ref()
         .get("g3icap")
-}
\ No newline at end of file
+}
+/// Get detection logger configuration
+///
+/// Detection logs may contain body samples, so they are discarded unless
+/// explicitly configured.
+pub fn get_detection_config() -> LogConfig {
+    ICAP_DETECTION_LOG_CONFIG_CONTAINER
+        .as_ref()
+        .get("g3icap")
+}
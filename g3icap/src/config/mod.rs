@@ -8,11 +8,31 @@ use std::path::Path;
 use anyhow::anyhow;
 use yaml_rust::{Yaml, yaml};
 
+use report::{ConfigIssue, ConfigReport};
+
+/// Keys of the main config file
+const MAIN_KEYS: &[&str] = &[
+    "runtime",
+    "worker",
+    "log",
+    "stat",
+    "controller",
+    "server",
+    "user",
+    "user_group",
+    "auditor",
+];
+
 // Core configuration modules
 pub mod audit;
 pub mod auth;
 pub mod server;
+pub mod expand;
 pub mod log;
+pub mod provenance;
+pub mod report;
+pub mod schema;
+pub mod secrets;
 
 // Advanced configuration features following g3proxy patterns
 mod graphviz;
@@ -28,11 +48,22 @@ pub fn load() -> anyhow::Result<&'static Path> {
     let config_file =
         g3_daemon::opts::config_file().ok_or_else(|| anyhow!("no config file set"))?;
 
-    // allow multiple docs, and treat them as the same
-    g3_yaml::foreach_doc(config_file, |_, doc| match doc {
-        Yaml::Hash(map) => load_doc(map),
-        _ => Err(anyhow!("yaml doc root should be hash")),
-    })?;
+    // allow multiple docs, and treat them as the same,
+    // the errors of all of them are reported at once
+    let mut report = ConfigReport::new();
+    match expand::load(config_file) {
+        Ok(docs) => {
+            for doc in &docs {
+                match doc {
+                    Yaml::Hash(map) => load_doc(map, &mut report),
+                    _ => report.push("", anyhow!("yaml doc root should be hash")),
+                }
+            }
+        }
+        Err(e) => report.push("", e),
+    }
+    schema::locate(config_file, &mut report);
+    report.into_result()?;
 
     Ok(config_file)
 }
@@ -53,13 +84,19 @@ pub(crate) async fn reload() -> anyhow::Result<()> {
 
 #[allow(dead_code)]
 fn reload_blocking() -> anyhow::Result<()> {
+    // the running config is kept if the new one is not valid
+    if let Some(conf_file) = g3_daemon::opts::config_file() {
+        schema::validate(conf_file)?;
+    }
     clear_all();
     if let Some(conf_file) = g3_daemon::opts::config_file() {
         // allow multiple docs, and treat them as the same
-        g3_yaml::foreach_doc(conf_file, |_, doc| match doc {
-            Yaml::Hash(map) => reload_doc(map),
-            _ => Err(anyhow!("yaml doc root should be hash")),
-        })?;
+        for doc in expand::load(conf_file)? {
+            match doc {
+                Yaml::Hash(map) => reload_doc(&map)?,
+                _ => return Err(anyhow!("yaml doc root should be hash")),
+            }
+        }
     }
     Ok(())
 }
@@ -78,19 +115,35 @@ fn reload_doc(map: &yaml::Hash) -> anyhow::Result<()> {
     Ok(())
 }
 
-fn load_doc(map: &yaml::Hash) -> anyhow::Result<()> {
-    let conf_dir =
-        g3_daemon::opts::config_dir().ok_or_else(|| anyhow!("no valid config dir has been set"))?;
-    g3_yaml::foreach_kv(map, |k, v| match g3_yaml::key::normalize(k).as_str() {
-        "runtime" => g3_daemon::runtime::config::load(v),
-        "worker" => g3_daemon::runtime::config::load_worker(v),
-        "log" => log::load(v, conf_dir),
-        "stat" => g3_daemon::stat::config::load(v, "g3icap"),
-        "controller" => g3_daemon::control::config::load(v),
-        "server" => server::load_all(v, conf_dir),
-        "user" | "user_group" => auth::load_all(v, conf_dir),
-        "auditor" => audit::load_all(v, conf_dir),
-        _ => Err(anyhow!("invalid key {k} in main conf")),
-    })?;
-    Ok(())
+fn load_doc(map: &yaml::Hash, report: &mut ConfigReport) {
+    let Some(conf_dir) = g3_daemon::opts::config_dir() else {
+        report.push("", anyhow!("no valid config dir has been set"));
+        return;
+    };
+    for (k, v) in map.iter() {
+        let Yaml::String(k) = k else {
+            report.push("", anyhow!("key in hash should be string"));
+            continue;
+        };
+        let r = match g3_yaml::key::normalize(k).as_str() {
+            "runtime" => g3_daemon::runtime::config::load(v),
+            "worker" => g3_daemon::runtime::config::load_worker(v),
+            "log" => log::load(v, conf_dir),
+            "stat" => g3_daemon::stat::config::load(v, "g3icap"),
+            "controller" => g3_daemon::control::config::load(v),
+            "server" => server::load_all(v, conf_dir),
+            "user" | "user_group" => auth::load_all(v, conf_dir),
+            "auditor" => audit::load_all(v, conf_dir),
+            key => {
+                report.add(ConfigIssue {
+                    path: k.to_string(),
+                    message: "invalid key in main conf".to_string(),
+                    suggestion: report::did_you_mean(key, MAIN_KEYS),
+                    location: None,
+                });
+                continue;
+            }
+        };
+        report.check(k, r);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,373 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Provenance of the loaded artifacts
+//!
+//! With trusted keys, set by `--trusted-keys`, the config files, the
+//! blocklists and the YARA rule files are only loaded if they have a valid
+//! minisign detached signature made by one of the keys. The signature of a
+//! file is the file with `.minisig` appended to its name, and the signature
+//! of a remote list is at its url with `.minisig` appended to the path.
+//!
+//! Each loaded artifact is recorded by an audit event with its signer and
+//! SHA-256 digest, and each rejected one by a security event.
+//!
+//! The trusted keys file has a minisign public key per line, optionally
+//! followed by the name of the signer. Lines starting with `#` or
+//! `untrusted comment:` are skipped, so that minisign `.pub` files can be
+//! concatenated.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use anyhow::anyhow;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use g3_types::metrics::NodeName;
+use openssl::hash::MessageDigest;
+use openssl::pkey::{Id, PKey, Public};
+use openssl::sign::Verifier;
+use url::Url;
+
+use crate::audit::ops::{AuditSeverity, DefaultIcapAuditOps, IcapAuditOps};
+use crate::modules::threat_intel::sha256_hex;
+
+/// Extension of the detached signatures
+pub const SIGNATURE_EXTENSION: &str = "minisig";
+
+/// Signature of the data itself
+const ALG_PURE: &[u8] = b"Ed";
+/// Signature of the BLAKE2b-512 digest of the data
+const ALG_HASHED: &[u8] = b"ED";
+const UNTRUSTED_COMMENT: &str = "untrusted comment:";
+const TRUSTED_COMMENT: &str = "trusted comment: ";
+
+static TRUSTED_KEYS: OnceLock<TrustedKeys> = OnceLock::new();
+
+/// Kind of the signed artifacts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArtifactKind {
+    Config,
+    Blocklist,
+    YaraRules,
+    Policy,
+}
+
+impl ArtifactKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ArtifactKind::Config => "config",
+            ArtifactKind::Blocklist => "blocklist",
+            ArtifactKind::YaraRules => "yara_rules",
+            ArtifactKind::Policy => "policy",
+        }
+    }
+}
+
+/// Verified origin of an artifact
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    /// Name of the signer, the key id if the key has no name
+    pub signer: String,
+    pub key_id: String,
+    /// Trusted comment of the signature
+    pub comment: String,
+    /// SHA-256 of the artifact, in hex
+    pub digest: String,
+}
+
+struct TrustedKey {
+    id: [u8; 8],
+    name: Option<String>,
+    key: PKey<Public>,
+}
+
+/// Public keys the artifacts should be signed by
+pub struct TrustedKeys {
+    keys: Vec<TrustedKey>,
+}
+
+impl TrustedKeys {
+    pub fn load(file: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(file)
+            .map_err(|e| anyhow!("failed to read trusted keys file {}: {e}", file.display()))?;
+        TrustedKeys::parse(&text)
+            .map_err(|e| anyhow!("invalid trusted keys file {}: {e}", file.display()))
+    }
+
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut keys = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(UNTRUSTED_COMMENT) {
+                continue;
+            }
+            let (key, name) = match line.split_once(char::is_whitespace) {
+                Some((key, name)) => (key, Some(name.trim().to_string())),
+                None => (line, None),
+            };
+            let key = parse_public_key(key, name).map_err(|e| anyhow!("line {}: {e}", n + 1))?;
+            keys.push(key);
+        }
+        if keys.is_empty() {
+            return Err(anyhow!("no key found"));
+        }
+        Ok(TrustedKeys { keys })
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Verify the minisign detached signature of the data
+    pub fn verify(&self, data: &[u8], signature: &str) -> anyhow::Result<Provenance> {
+        let mut lines = signature
+            .lines()
+            .map(|line| line.trim_end_matches('\r'))
+            .filter(|line| !line.trim().is_empty());
+        let mut line = lines.next().ok_or_else(|| anyhow!("empty signature"))?;
+        if line.starts_with(UNTRUSTED_COMMENT) {
+            line = lines.next().ok_or_else(|| anyhow!("no signature"))?;
+        }
+        let sig = decode(line, 74, "signature")?;
+        let comment = lines
+            .next()
+            .and_then(|line| line.strip_prefix(TRUSTED_COMMENT))
+            .ok_or_else(|| anyhow!("no trusted comment in signature"))?;
+        let global_sig = lines
+            .next()
+            .ok_or_else(|| anyhow!("no trusted comment signature"))?;
+        let global_sig = decode(global_sig, 64, "trusted comment signature")?;
+
+        let (alg, rest) = sig.split_at(2);
+        let (id, sig) = rest.split_at(8);
+        let key = self
+            .keys
+            .iter()
+            .find(|k| k.id == id)
+            .ok_or_else(|| anyhow!("signed by untrusted key {}", key_id(id)))?;
+        let valid = match alg {
+            ALG_PURE => verify(&key.key, sig, data)?,
+            ALG_HASHED => {
+                let md = MessageDigest::from_name("BLAKE2b512")
+                    .ok_or_else(|| anyhow!("BLAKE2b-512 is not supported by openssl"))?;
+                let digest = openssl::hash::hash(md, data)?;
+                verify(&key.key, sig, &digest)?
+            }
+            _ => return Err(anyhow!("unsupported signature algorithm")),
+        };
+        if !valid {
+            return Err(anyhow!("invalid signature by key {}", key_id(&key.id)));
+        }
+        let mut signed = sig.to_vec();
+        signed.extend_from_slice(comment.as_bytes());
+        if !verify(&key.key, &global_sig, &signed)? {
+            return Err(anyhow!(
+                "invalid trusted comment signature by key {}",
+                key_id(&key.id)
+            ));
+        }
+
+        Ok(Provenance {
+            signer: key.name.clone().unwrap_or_else(|| key_id(&key.id)),
+            key_id: key_id(&key.id),
+            comment: comment.to_string(),
+            digest: sha256_hex(data),
+        })
+    }
+}
+
+fn parse_public_key(text: &str, name: Option<String>) -> anyhow::Result<TrustedKey> {
+    let data = decode(text, 42, "public key")?;
+    if &data[..2] != ALG_PURE {
+        return Err(anyhow!("unsupported public key algorithm"));
+    }
+    let key = PKey::public_key_from_raw_bytes(&data[10..], Id::ED25519)
+        .map_err(|e| anyhow!("invalid ed25519 public key: {e}"))?;
+    let mut id = [0u8; 8];
+    id.copy_from_slice(&data[2..10]);
+    Ok(TrustedKey { id, name, key })
+}
+
+fn decode(text: &str, len: usize, what: &str) -> anyhow::Result<Vec<u8>> {
+    let data = STANDARD
+        .decode(text.trim())
+        .map_err(|e| anyhow!("invalid base64 {what}: {e}"))?;
+    if data.len() != len {
+        return Err(anyhow!("invalid {what} length {}", data.len()));
+    }
+    Ok(data)
+}
+
+fn verify(key: &PKey<Public>, sig: &[u8], data: &[u8]) -> anyhow::Result<bool> {
+    let mut verifier = Verifier::new_without_digest(key)?;
+    Ok(verifier.verify_oneshot(sig, data).unwrap_or(false))
+}
+
+/// Key id as shown by minisign
+fn key_id(id: &[u8]) -> String {
+    let mut n = [0u8; 8];
+    n.copy_from_slice(id);
+    format!("{:016X}", u64::from_le_bytes(n))
+}
+
+/// Set the keys the artifacts should be signed by, from the command line
+pub fn set_trusted_keys(keys: TrustedKeys) -> anyhow::Result<()> {
+    TRUSTED_KEYS
+        .set(keys)
+        .map_err(|_| anyhow!("trusted keys are already set"))
+}
+
+/// Keys the artifacts should be signed by, none if not verified
+pub fn trusted_keys() -> Option<&'static TrustedKeys> {
+    TRUSTED_KEYS.get()
+}
+
+/// Path of the detached signature of a file
+pub fn signature_path(path: &Path) -> PathBuf {
+    let mut s = OsString::from(path.as_os_str());
+    s.push(".");
+    s.push(SIGNATURE_EXTENSION);
+    PathBuf::from(s)
+}
+
+/// Url of the detached signature of a remote artifact
+pub fn signature_url(url: &Url) -> Url {
+    let mut signature = url.clone();
+    signature.set_path(&format!("{}.{SIGNATURE_EXTENSION}", url.path()));
+    signature
+}
+
+/// Verify an artifact, recording the result in the audit log
+pub fn check(
+    keys: &TrustedKeys,
+    kind: ArtifactKind,
+    name: &str,
+    data: &[u8],
+    signature: &str,
+) -> anyhow::Result<Provenance> {
+    let audit_ops = DefaultIcapAuditOps::new(NodeName::new_static("g3icap"), true);
+    match keys.verify(data, signature) {
+        Ok(provenance) => {
+            audit_ops.log_artifact_loaded(
+                kind.as_str(),
+                name,
+                &provenance.signer,
+                &provenance.digest,
+            );
+            Ok(provenance)
+        }
+        Err(e) => {
+            audit_ops.log_security_event(
+                "Artifact signature rejected",
+                &format!("{} {name}: {e}", kind.as_str()),
+                AuditSeverity::Error,
+            );
+            Err(anyhow!(
+                "signature check of {} {name} failed: {e}",
+                kind.as_str()
+            ))
+        }
+    }
+}
+
+/// Verify a local file if there are trusted keys
+pub fn check_file(kind: ArtifactKind, path: &Path, data: &[u8]) -> anyhow::Result<()> {
+    let Some(keys) = trusted_keys() else {
+        return Ok(());
+    };
+    let signature_path = signature_path(path);
+    let signature = std::fs::read_to_string(&signature_path).map_err(|e| {
+        anyhow!(
+            "failed to read signature file {}: {e}",
+            signature_path.display()
+        )
+    })?;
+    check(keys, kind, &path.display().to_string(), data, &signature)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::pkey::Private;
+    use openssl::sign::Signer;
+
+    fn sign(key: &PKey<Private>, id: &[u8; 8], data: &[u8], comment: &str) -> String {
+        let sig = Signer::new_without_digest(key)
+            .unwrap()
+            .sign_oneshot_to_vec(data)
+            .unwrap();
+        let mut global = sig.clone();
+        global.extend_from_slice(comment.as_bytes());
+        let global = Signer::new_without_digest(key)
+            .unwrap()
+            .sign_oneshot_to_vec(&global)
+            .unwrap();
+        let mut line = ALG_PURE.to_vec();
+        line.extend_from_slice(id);
+        line.extend_from_slice(&sig);
+        format!(
+            "untrusted comment: signature from minisign secret key\n{}\n{TRUSTED_COMMENT}{comment}\n{}\n",
+            STANDARD.encode(line),
+            STANDARD.encode(global)
+        )
+    }
+
+    fn public_key(key: &PKey<Private>, id: &[u8; 8]) -> String {
+        let mut data = ALG_PURE.to_vec();
+        data.extend_from_slice(id);
+        data.extend_from_slice(&key.raw_public_key().unwrap());
+        STANDARD.encode(data)
+    }
+
+    #[test]
+    fn verify_signatures() {
+        let key = PKey::generate_ed25519().unwrap();
+        let other = PKey::generate_ed25519().unwrap();
+        let id = [1, 2, 3, 4, 5, 6, 7, 8];
+        let keys = TrustedKeys::parse(&format!(
+            "untrusted comment: minisign public key\n{} release-team\n",
+            public_key(&key, &id)
+        ))
+        .unwrap();
+        assert_eq!(keys.len(), 1);
+
+        let data = b"- example.com\n";
+        let signature = sign(&key, &id, data, "timestamp:1700000000");
+        let provenance = keys.verify(data, &signature).unwrap();
+        assert_eq!(provenance.signer, "release-team");
+        assert_eq!(provenance.key_id, "0807060504030201");
+        assert_eq!(provenance.comment, "timestamp:1700000000");
+        assert_eq!(provenance.digest, sha256_hex(data));
+
+        assert!(keys.verify(b"- example.net\n", &signature).is_err());
+        let forged = sign(&other, &id, data, "timestamp:1700000000");
+        assert!(keys.verify(data, &forged).is_err());
+        let untrusted = sign(&key, &[0; 8], data, "timestamp:1700000000");
+        let e = keys.verify(data, &untrusted).unwrap_err();
+        assert!(e.to_string().contains("untrusted key"), "{e}");
+        let tampered = signature.replace("timestamp:1700000000", "timestamp:1800000000");
+        assert!(keys.verify(data, &tampered).is_err());
+    }
+
+    #[test]
+    fn signature_locations() {
+        assert_eq!(
+            signature_path(Path::new("/etc/g3icap/main.yaml")),
+            PathBuf::from("/etc/g3icap/main.yaml.minisig")
+        );
+        let url = Url::parse("https://lists.example.com/domains.txt?v=2").unwrap();
+        assert_eq!(
+            signature_url(&url).as_str(),
+            "https://lists.example.com/domains.txt.minisig?v=2"
+        );
+    }
+}
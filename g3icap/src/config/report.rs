@@ -0,0 +1,252 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Aggregation of the errors found while loading the config
+//!
+//! Loading goes on after a section fails to parse, so that all the errors of
+//! the servers, services, pipelines and sinks are reported at once, each
+//! with the path of the failing section and, if possible, a suggestion. The
+//! issues are given the line of the failing key once the file is located.
+
+use std::fmt;
+use std::path::PathBuf;
+
+/// Position of a key in a config file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub file: PathBuf,
+    /// Line, from 1
+    pub line: usize,
+    /// Column, from 1
+    pub column: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file.display(), self.line, self.column)
+    }
+}
+
+/// An error found in the config
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigIssue {
+    /// Dotted path of the section, e.g. `server[0].pipelines`
+    pub path: String,
+    pub message: String,
+    pub suggestion: Option<String>,
+    /// Position of the failing key, if located
+    pub location: Option<Location>,
+}
+
+/// All the errors found in the config
+#[derive(Debug, Default)]
+pub struct ConfigReport {
+    issues: Vec<ConfigIssue>,
+}
+
+fn join_path(prefix: &str, path: &str) -> String {
+    if prefix.is_empty() {
+        path.to_string()
+    } else if path.is_empty() || path.starts_with('[') {
+        format!("{prefix}{path}")
+    } else {
+        format!("{prefix}.{path}")
+    }
+}
+
+impl ConfigReport {
+    pub fn new() -> Self {
+        ConfigReport::default()
+    }
+
+    pub fn issues(&self) -> &[ConfigIssue] {
+        &self.issues
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    pub fn add(&mut self, issue: ConfigIssue) {
+        self.issues.push(issue);
+    }
+
+    /// Add an error of the section at `path`
+    ///
+    /// The issues of a nested report are added with their path prefixed.
+    pub fn push(&mut self, path: &str, e: anyhow::Error) {
+        match e.downcast::<ConfigReport>() {
+            Ok(report) => self.merge(path, report),
+            Err(e) => self.add(ConfigIssue {
+                path: path.to_string(),
+                message: format!("{e:#}"),
+                suggestion: None,
+                location: None,
+            }),
+        }
+    }
+
+    /// Add the issues of a nested report, prefixing their path
+    pub fn merge(&mut self, prefix: &str, report: ConfigReport) {
+        for mut issue in report.issues {
+            issue.path = join_path(prefix, &issue.path);
+            self.issues.push(issue);
+        }
+    }
+
+    /// Keep the error of a result, if any
+    pub fn check<T>(&mut self, path: &str, r: anyhow::Result<T>) -> Option<T> {
+        match r {
+            Ok(v) => Some(v),
+            Err(e) => {
+                self.push(path, e);
+                None
+            }
+        }
+    }
+
+    /// Set the location of the issues not located yet
+    pub fn locate<F>(&mut self, f: F)
+    where
+        F: Fn(&ConfigIssue) -> Option<Location>,
+    {
+        for issue in &mut self.issues {
+            if issue.location.is_none() {
+                issue.location = f(issue);
+            }
+        }
+    }
+
+    pub fn into_result(self) -> anyhow::Result<()> {
+        if self.issues.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow::Error::new(self))
+        }
+    }
+}
+
+impl fmt::Display for ConfigReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.issues.len() {
+            1 => write!(f, "1 error found in config")?,
+            n => write!(f, "{n} errors found in config")?,
+        }
+        for issue in &self.issues {
+            let path = if issue.path.is_empty() {
+                "<root>"
+            } else {
+                &issue.path
+            };
+            match &issue.location {
+                Some(location) => write!(f, "\n  {location}: {path}: {}", issue.message)?,
+                None => write!(f, "\n  {path}: {}", issue.message)?,
+            }
+            if let Some(suggestion) = &issue.suggestion {
+                write!(f, " ({suggestion})")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigReport {}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cur = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                prev
+            } else {
+                1 + prev.min(cur).min(row[j])
+            };
+            prev = cur;
+        }
+    }
+    row[b.len()]
+}
+
+/// The candidate closest to a misspelt name, if any is close enough
+pub fn closest<'a>(name: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    let max = (name.chars().count() / 3).clamp(1, 2);
+    candidates
+        .iter()
+        .map(|c| (edit_distance(name, c), *c))
+        .filter(|(d, _)| *d > 0 && *d <= max)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c)
+}
+
+/// A `did you mean` suggestion for an unknown name
+pub fn did_you_mean(name: &str, candidates: &[&str]) -> Option<String> {
+    closest(name, candidates).map(|c| format!("did you mean {c}?"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn suggestions() {
+        let keys = ["pipelines", "services", "timeouts", "admin"];
+        assert_eq!(closest("pipelnes", &keys), Some("pipelines"));
+        assert_eq!(closest("servces", &keys), Some("services"));
+        assert_eq!(closest("pipelines", &keys), None);
+        assert_eq!(closest("name", &keys), None);
+        assert_eq!(closest("listen", &keys), None);
+        assert_eq!(
+            did_you_mean("admn", &keys).as_deref(),
+            Some("did you mean admin?")
+        );
+    }
+
+    #[test]
+    fn aggregate() {
+        let mut nested = ConfigReport::new();
+        nested.add(ConfigIssue {
+            path: "services.reqmod".to_string(),
+            message: "bound to undefined pipeline scan".to_string(),
+            suggestion: did_you_mean("scan", &["scan1", "strict"]),
+            location: None,
+        });
+        nested.push("definitions", anyhow!("unsupported pipeline stage av"));
+
+        let mut report = ConfigReport::new();
+        assert!(
+            report
+                .check("server[0].timeouts", Ok::<_, anyhow::Error>(1))
+                .is_some()
+        );
+        let mut servers = ConfigReport::new();
+        servers.push("[0].pipelines", nested.into_result().unwrap_err());
+        report.push("server", servers.into_result().unwrap_err());
+        let r: anyhow::Result<()> = Err(anyhow!("invalid value").context("failed to parse key a"));
+        assert!(report.check("server[1].audit", r).is_none());
+
+        let paths: Vec<_> = report.issues().iter().map(|i| i.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "server[0].pipelines.services.reqmod",
+                "server[0].pipelines.definitions",
+                "server[1].audit",
+            ]
+        );
+        let text = report.into_result().unwrap_err().to_string();
+        assert_eq!(
+            text,
+            "3 errors found in config\n  \
+             server[0].pipelines.services.reqmod: bound to undefined pipeline scan (did you mean scan1?)\n  \
+             server[0].pipelines.definitions: unsupported pipeline stage av\n  \
+             server[1].audit: failed to parse key a: invalid value"
+        );
+    }
+}
@@ -0,0 +1,326 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Strict validation of the config file
+//!
+//! Loading is lenient where the command line options take over, the keys of
+//! a server that are neither a section nor an option being ignored. When the
+//! config is tested, or before it is reloaded, it is validated strictly: the
+//! unknown keys, the values of the wrong type and the invalid values are all
+//! reported, each with the file, line and key path it is found at.
+//!
+//! The servers included from other files are only checked by the loader, and
+//! the issues in the files included with `!include` are located at the key
+//! of the include.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use yaml_rust::Yaml;
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::Marker;
+
+use super::report::{self, ConfigIssue, ConfigReport, Location};
+
+/// Context added by `g3_yaml::foreach_kv` to the errors of a value
+const KEY_CONTEXT: &str = "failed to parse value of key ";
+/// Error of the section parsers for an unknown key
+const INVALID_KEY: &str = "invalid key ";
+
+/// Type of a value checked by the schema
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ValueKind {
+    Bool,
+    Integer,
+    Port,
+    String,
+    Duration,
+}
+
+impl ValueKind {
+    pub(crate) fn check(self, v: &Yaml) -> anyhow::Result<()> {
+        match self {
+            ValueKind::Bool => g3_yaml::value::as_bool(v).map(|_| ()),
+            ValueKind::Integer => g3_yaml::value::as_u64(v).map(|_| ()),
+            ValueKind::Port => g3_yaml::value::as_u16(v).map(|_| ()),
+            ValueKind::String => g3_yaml::value::as_string(v).map(|_| ()),
+            ValueKind::Duration => g3_yaml::humanize::as_duration(v).map(|_| ()),
+        }
+    }
+}
+
+/// Validate the config file strictly, reporting all the issues at once
+pub fn validate(file: &Path) -> anyhow::Result<()> {
+    let text = std::fs::read_to_string(file)
+        .map_err(|e| anyhow!("failed to read config file {}: {e}", file.display()))?;
+    let docs = super::expand::load(file)?;
+
+    let mut report = ConfigReport::new();
+    for doc in &docs {
+        let Yaml::Hash(map) = doc else {
+            report.push("", anyhow!("yaml doc root should be hash"));
+            continue;
+        };
+        for (k, v) in map {
+            let Yaml::String(k) = k else {
+                report.push("", anyhow!("key in hash should be string"));
+                continue;
+            };
+            match g3_yaml::key::normalize(k).as_str() {
+                "server" => check_servers(k, v, &mut report),
+                key if !super::MAIN_KEYS.contains(&key) => report.add(ConfigIssue {
+                    path: k.to_string(),
+                    message: "invalid key in main conf".to_string(),
+                    suggestion: report::did_you_mean(key, super::MAIN_KEYS),
+                    location: None,
+                }),
+                _ => {}
+            }
+        }
+    }
+
+    let locations = KeyLocations::parse(file, &text)?;
+    report.locate(|issue| locations.locate(issue));
+    report.into_result()
+}
+
+fn check_servers(key: &str, v: &Yaml, report: &mut ConfigReport) {
+    let Yaml::Array(servers) = v else {
+        return;
+    };
+    for (i, server) in servers.iter().enumerate() {
+        if let Yaml::Hash(map) = server {
+            report.check(&format!("{key}[{i}]"), super::server::check_server(map));
+        }
+    }
+}
+
+/// Locate the issues of a report in the config file, if it can be parsed
+pub(crate) fn locate(file: &Path, report: &mut ConfigReport) {
+    let Ok(text) = std::fs::read_to_string(file) else {
+        return;
+    };
+    if let Ok(locations) = KeyLocations::parse(file, &text) {
+        report.locate(|issue| locations.locate(issue));
+    }
+}
+
+/// Positions of the keys of a config file, by the path of the report issues
+///
+/// The paths are the keys as written, joined by `.`, and the index of the
+/// sequence items in brackets, e.g. `server[0].pipelines.services`. The
+/// first document defining a path wins.
+#[derive(Debug)]
+pub struct KeyLocations {
+    file: PathBuf,
+    keys: HashMap<String, Marker>,
+}
+
+impl KeyLocations {
+    pub fn parse(file: &Path, text: &str) -> anyhow::Result<Self> {
+        let mut recorder = Recorder::default();
+        Parser::new_from_str(text)
+            .load(&mut recorder, true)
+            .map_err(|e| anyhow!("invalid yaml in config file {}: {e}", file.display()))?;
+        Ok(KeyLocations {
+            file: file.to_path_buf(),
+            keys: recorder.keys,
+        })
+    }
+
+    /// Location of the innermost key of the issue that is found in the file
+    ///
+    /// The keys of the nested values that failed to parse are only known from
+    /// the error message, and are appended to the path of the issue.
+    pub fn locate(&self, issue: &ConfigIssue) -> Option<Location> {
+        let mut path = issue.path.clone();
+        for key in message_keys(&issue.message) {
+            if !path.is_empty() {
+                path.push('.');
+            }
+            path.push_str(key);
+        }
+        loop {
+            if let Some(marker) = self.keys.get(&path) {
+                return Some(Location {
+                    file: self.file.clone(),
+                    line: marker.line(),
+                    column: marker.col() + 1,
+                });
+            }
+            let end = path.rfind(['.', '['])?;
+            path.truncate(end);
+        }
+    }
+}
+
+/// Keys of the nested values an error message is about, outermost first
+fn message_keys(message: &str) -> Vec<&str> {
+    let mut keys = Vec::new();
+    let mut rest = message;
+    while let Some(n) = rest.find(KEY_CONTEXT) {
+        rest = &rest[n + KEY_CONTEXT.len()..];
+        let end = rest.find(':').unwrap_or(rest.len());
+        keys.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    if let Some(n) = rest.find(INVALID_KEY)
+        && let Some(key) = rest[n + INVALID_KEY.len()..].split_whitespace().next()
+    {
+        keys.push(key);
+    }
+    keys
+}
+
+enum Frame {
+    /// The key of the value being parsed, none if a key is expected
+    Map(Option<String>),
+    /// Index of the item being parsed
+    Seq(usize),
+}
+
+#[derive(Default)]
+struct Recorder {
+    frames: Vec<Frame>,
+    keys: HashMap<String, Marker>,
+}
+
+impl Recorder {
+    fn path(&self) -> String {
+        let mut path = String::new();
+        for frame in &self.frames {
+            match frame {
+                Frame::Map(Some(key)) => {
+                    if !path.is_empty() {
+                        path.push('.');
+                    }
+                    path.push_str(key);
+                }
+                Frame::Map(None) => {}
+                Frame::Seq(i) => path.push_str(&format!("[{i}]")),
+            }
+        }
+        path
+    }
+
+    fn value_done(&mut self) {
+        match self.frames.last_mut() {
+            Some(Frame::Map(key)) => *key = None,
+            Some(Frame::Seq(i)) => *i += 1,
+            None => {}
+        }
+    }
+}
+
+impl MarkedEventReceiver for Recorder {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        match ev {
+            Event::Scalar(value, ..) => {
+                if let Some(Frame::Map(None)) = self.frames.last() {
+                    let mut path = self.path();
+                    if !path.is_empty() {
+                        // a map in a sequence is at its first key
+                        self.keys.entry(path.clone()).or_insert(mark);
+                        path.push('.');
+                    }
+                    path.push_str(&value);
+                    self.keys.entry(path).or_insert(mark);
+                    self.frames.pop();
+                    self.frames.push(Frame::Map(Some(value)));
+                } else {
+                    self.value_done();
+                }
+            }
+            Event::MappingStart(..) => self.frames.push(Frame::Map(None)),
+            Event::SequenceStart(..) => self.frames.push(Frame::Seq(0)),
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.frames.pop();
+                self.value_done();
+            }
+            Event::Alias(_) => self.value_done(),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "\
+log: stdout
+server:
+  - name: icap
+    type: IcapServer
+    waf:
+      rules: |
+        SecRuleEngine On
+      threshold: 0
+    pipelines:
+      services:
+        reqmod: scan
+---
+auditor: []
+";
+
+    fn issue(path: &str, message: &str) -> ConfigIssue {
+        ConfigIssue {
+            path: path.to_string(),
+            message: message.to_string(),
+            suggestion: None,
+            location: None,
+        }
+    }
+
+    #[test]
+    fn locations() {
+        let locations = KeyLocations::parse(Path::new("g3icap.yaml"), CONFIG).unwrap();
+        let at = |path: &str, message: &str| {
+            locations
+                .locate(&issue(path, message))
+                .map(|l| (l.line, l.column))
+        };
+        assert_eq!(at("log", ""), Some((1, 1)));
+        assert_eq!(at("server[0]", ""), Some((3, 5)));
+        assert_eq!(
+            at("server[0].waf", "waf anomaly_threshold should not be 0"),
+            Some((5, 5))
+        );
+        assert_eq!(
+            at(
+                "server[0].pipelines",
+                "failed to parse value of key services: x"
+            ),
+            Some((10, 7))
+        );
+        assert_eq!(
+            at(
+                "server[0].waf",
+                "failed to parse value of key threshold: out of range"
+            ),
+            Some((8, 7))
+        );
+        assert_eq!(
+            at("server[0].waf", "invalid key rule in waf config"),
+            Some((5, 5))
+        );
+        assert_eq!(at("server[0].pipelines.services.reqmod", ""), Some((11, 9)));
+        assert_eq!(at("auditor", ""), Some((13, 1)));
+        assert_eq!(at("user", ""), None);
+
+        let mut report = ConfigReport::new();
+        report.push(
+            "server[0].timeouts",
+            anyhow!("invalid key idle in timeouts config"),
+        );
+        report.locate(|issue| locations.locate(issue));
+        assert_eq!(
+            report.into_result().unwrap_err().to_string(),
+            "1 error found in config\n  \
+             g3icap.yaml:3:5: server[0].timeouts: invalid key idle in timeouts config"
+        );
+    }
+}
@@ -0,0 +1,230 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Secrets referenced by the config
+//!
+//! A value tagged `!secret ref` is replaced by the secret it references each
+//! time the config is loaded or reloaded, so that the credentials, like the
+//! API keys of the antivirus engines, are not written in the config files:
+//!
+//! - `env:NAME` is the environment variable `NAME`
+//! - `file:path` is the content of the file, without the trailing newline,
+//!   the path being relative to the config file
+//! - `vault:path#field` is a field of a secret of HashiCorp Vault, read from
+//!   `$VAULT_ADDR/v1/path` with the token in `VAULT_TOKEN`, or in the file at
+//!   `VAULT_TOKEN_FILE`. `VAULT_NAMESPACE` is sent if set. Both the KV v1 and
+//!   the KV v2 secret engines are supported, for KV v2 the path includes the
+//!   `data/` segment.
+//!
+//! Errors never contain the secret values.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use serde_json::Value;
+use url::Url;
+
+use crate::modules::blocklist::fetch;
+
+const VAULT_TIMEOUT: Duration = Duration::from_secs(10);
+const VAULT_MAX_RESPONSE_SIZE: usize = 1024 * 1024;
+
+/// Reference to a secret
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecretRef {
+    Env(String),
+    File(PathBuf),
+    Vault { path: String, field: String },
+}
+
+impl FromStr for SecretRef {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (backend, name) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("invalid secret reference {s}, no backend"))?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(anyhow!("invalid secret reference {s}, empty name"));
+        }
+        match backend.trim() {
+            "env" => Ok(SecretRef::Env(name.to_string())),
+            "file" => Ok(SecretRef::File(PathBuf::from(name))),
+            "vault" => match name.rsplit_once('#') {
+                Some((path, field)) if !path.is_empty() && !field.is_empty() => {
+                    Ok(SecretRef::Vault {
+                        path: path.trim_matches('/').to_string(),
+                        field: field.to_string(),
+                    })
+                }
+                _ => Err(anyhow!(
+                    "invalid secret reference {s}, should be vault:path#field"
+                )),
+            },
+            _ => Err(anyhow!(
+                "invalid secret reference {s}, unknown backend {backend}"
+            )),
+        }
+    }
+}
+
+/// Resolver of the secrets of one load of the config
+///
+/// Each Vault secret is read once per load, whatever the number of its fields
+/// referenced.
+pub struct SecretResolver<'a> {
+    env: &'a dyn Fn(&str) -> Option<String>,
+    vault: HashMap<String, Value>,
+}
+
+impl<'a> SecretResolver<'a> {
+    pub fn new(env: &'a dyn Fn(&str) -> Option<String>) -> Self {
+        SecretResolver {
+            env,
+            vault: HashMap::new(),
+        }
+    }
+
+    /// Get the secret, relative file paths being looked up in `dir`
+    pub fn resolve(&mut self, secret: &SecretRef, dir: Option<&Path>) -> anyhow::Result<String> {
+        match secret {
+            SecretRef::Env(name) => (self.env)(name)
+                .ok_or_else(|| anyhow!("secret environment variable {name} is not set")),
+            SecretRef::File(path) => {
+                let path = match dir {
+                    Some(dir) => dir.join(path),
+                    None => path.clone(),
+                };
+                read_file(&path)
+            }
+            SecretRef::Vault { path, field } => {
+                if !self.vault.contains_key(path) {
+                    let data = self.vault_read(path)?;
+                    self.vault.insert(path.clone(), data);
+                }
+                kv_field(&self.vault[path], field)
+                    .ok_or_else(|| anyhow!("no field {field} in vault secret {path}"))
+            }
+        }
+    }
+
+    fn vault_read(&self, path: &str) -> anyhow::Result<Value> {
+        let addr = (self.env)("VAULT_ADDR")
+            .ok_or_else(|| anyhow!("VAULT_ADDR is not set for vault secret {path}"))?;
+        let token = match (self.env)("VAULT_TOKEN") {
+            Some(token) => token,
+            None => match (self.env)("VAULT_TOKEN_FILE") {
+                Some(file) => read_file(Path::new(&file))?,
+                None => {
+                    return Err(anyhow!(
+                        "neither VAULT_TOKEN nor VAULT_TOKEN_FILE is set for vault secret {path}"
+                    ));
+                }
+            },
+        };
+        let namespace = (self.env)("VAULT_NAMESPACE");
+
+        let url = Url::parse(&addr)
+            .and_then(|base| base.join(&format!("v1/{path}")))
+            .map_err(|e| anyhow!("invalid VAULT_ADDR {addr}: {e}"))?;
+        let mut headers = vec![("X-Vault-Token", token.as_str())];
+        if let Some(namespace) = &namespace {
+            headers.push(("X-Vault-Namespace", namespace.as_str()));
+        }
+
+        // the config may be loaded from inside the runtime, so the request
+        // is sent from a runtime of its own
+        let body = std::thread::scope(|s| {
+            s.spawn(|| {
+                let rt = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()?;
+                rt.block_on(fetch::fetch(
+                    &url,
+                    &headers,
+                    VAULT_TIMEOUT,
+                    VAULT_MAX_RESPONSE_SIZE,
+                ))
+            })
+            .join()
+            .map_err(|_| anyhow!("vault request thread panicked"))?
+        })
+        .map_err(|e| anyhow!("failed to read vault secret {path}: {e}"))?;
+        serde_json::from_slice(&body)
+            .map_err(|e| anyhow!("invalid response for vault secret {path}: {e}"))
+    }
+}
+
+fn read_file(path: &Path) -> anyhow::Result<String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("failed to read secret file {}: {e}", path.display()))?;
+    Ok(text.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Get a field of a KV secret read from Vault
+fn kv_field(response: &Value, field: &str) -> Option<String> {
+    let data = response.get("data")?;
+    // KV v2 nests the secret data next to its metadata
+    let data = match (data.get("data"), data.get("metadata")) {
+        (Some(inner), Some(_)) if inner.is_object() => inner,
+        _ => data,
+    };
+    match data.get(field)? {
+        Value::String(s) => Some(s.clone()),
+        Value::Null => None,
+        v => Some(v.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn references() {
+        assert_eq!(
+            "env:AV_API_KEY".parse::<SecretRef>().unwrap(),
+            SecretRef::Env("AV_API_KEY".to_string())
+        );
+        assert_eq!(
+            "vault:secret/data/g3icap#api_key"
+                .parse::<SecretRef>()
+                .unwrap(),
+            SecretRef::Vault {
+                path: "secret/data/g3icap".to_string(),
+                field: "api_key".to_string()
+            }
+        );
+        assert!("vault:secret/g3icap".parse::<SecretRef>().is_err());
+        assert!("ssm:key".parse::<SecretRef>().is_err());
+        assert!("env:".parse::<SecretRef>().is_err());
+
+        let env = |name: &str| (name == "AV_API_KEY").then(|| "k3y".to_string());
+        let mut resolver = SecretResolver::new(&env);
+        let key = resolver
+            .resolve(&SecretRef::Env("AV_API_KEY".to_string()), None)
+            .unwrap();
+        assert_eq!(key, "k3y");
+        let e = resolver
+            .resolve(&"vault:secret/g3icap#key".parse().unwrap(), None)
+            .unwrap_err();
+        assert!(e.to_string().contains("VAULT_ADDR"), "{e}");
+    }
+
+    #[test]
+    fn kv_fields() {
+        let v1 = json!({"data": {"api_key": "k1", "port": 1344}});
+        assert_eq!(kv_field(&v1, "api_key").as_deref(), Some("k1"));
+        assert_eq!(kv_field(&v1, "port").as_deref(), Some("1344"));
+        let v2 = json!({"data": {"data": {"api_key": "k2"}, "metadata": {"version": 3}}});
+        assert_eq!(kv_field(&v2, "api_key").as_deref(), Some("k2"));
+        assert_eq!(kv_field(&v2, "password"), None);
+    }
+}
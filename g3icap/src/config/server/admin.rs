@@ -0,0 +1,118 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! HTTP admin endpoint configuration
+//!
+//! The admin endpoint mirrors the `g3icap-ctl` verbs over HTTP, for hosts
+//! without shell access. Every request carries one of the configured API
+//! tokens as a bearer token, and the role of the token limits the verbs it
+//! may use: viewers may only read, operators may also reload and switch
+//! the service pipelines, admins may also drain the server.
+
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use yaml_rust::Yaml;
+
+/// Role of an admin API token, each role may do what the previous ones can
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AdminRole {
+    /// Read the status and the services
+    Viewer,
+    /// Reload the lists and switch the service pipelines
+    Operator,
+    /// Drain the server
+    Admin,
+}
+
+impl AdminRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AdminRole::Viewer => "viewer",
+            AdminRole::Operator => "operator",
+            AdminRole::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for AdminRole {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match g3_yaml::key::normalize(s).as_str() {
+            "viewer" | "read_only" | "readonly" => Ok(AdminRole::Viewer),
+            "operator" => Ok(AdminRole::Operator),
+            "admin" => Ok(AdminRole::Admin),
+            _ => Err(anyhow!("unsupported admin role {s}")),
+        }
+    }
+}
+
+/// An admin API token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdminTokenConfig {
+    /// Token id, used for logging
+    pub id: String,
+    /// Token secret value
+    pub secret: String,
+    pub role: AdminRole,
+}
+
+/// HTTP admin endpoint of a server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdminConfig {
+    /// Listen address, loopback by default
+    pub listen: SocketAddr,
+    /// Accepted API tokens
+    pub tokens: Vec<AdminTokenConfig>,
+}
+
+impl AdminConfig {
+    /// Parse the `admin` section of a server config
+    pub fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let Yaml::Hash(map) = v else {
+            return Err(anyhow!("admin should be a map"));
+        };
+
+        let mut listen = SocketAddr::from(([127, 0, 0, 1], 1345));
+        let mut tokens = Vec::new();
+        g3_yaml::foreach_kv(map, |k, v| {
+            match g3_yaml::key::normalize(k).as_str() {
+                "listen" => {
+                    listen = g3_yaml::value::as_sockaddr(v)?;
+                }
+                "tokens" | "api_tokens" => {
+                    tokens = g3_yaml::value::as_list(v, parse_token)?;
+                }
+                _ => return Err(anyhow!("invalid key {k} in admin config")),
+            }
+            Ok(())
+        })?;
+
+        if tokens.is_empty() {
+            return Err(anyhow!("no api token configured for the admin endpoint"));
+        }
+        for (i, t) in tokens.iter().enumerate() {
+            if t.secret.is_empty() {
+                return Err(anyhow!("admin token {} has an empty secret", t.id));
+            }
+            if tokens[..i].iter().any(|o: &AdminTokenConfig| o.id == t.id) {
+                return Err(anyhow!("duplicate admin token id {}", t.id));
+            }
+        }
+        Ok(AdminConfig { listen, tokens })
+    }
+}
+
+fn parse_token(v: &Yaml) -> anyhow::Result<AdminTokenConfig> {
+    let Yaml::Hash(map) = v else {
+        return Err(anyhow!("admin token should be a map"));
+    };
+    let id = g3_yaml::hash_get_required_str(map, "id")?.to_string();
+    let secret = g3_yaml::hash_get_required_str(map, "secret")?.to_string();
+    let role = g3_yaml::hash_get_required_str(map, "role")?.parse()?;
+    Ok(AdminTokenConfig { id, secret, role })
+}
@@ -0,0 +1,56 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Early admission configuration
+//!
+//! The Content-Length of the encapsulated HTTP header tells the size of the
+//! body before it is read. It is used to reject messages over the size
+//! limits, to size the read buffer up front, and to route bodies that are
+//! too large to scan past the scanning modules.
+
+use anyhow::anyhow;
+use yaml_rust::Yaml;
+
+/// Decisions taken from the declared body size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdmissionConfig {
+    /// Most bytes reserved up front for a declared body
+    pub preallocate_limit: usize,
+    /// Declared bodies larger than this are not sent to the antivirus module
+    pub max_scan_size: Option<u64>,
+}
+
+impl Default for AdmissionConfig {
+    fn default() -> Self {
+        AdmissionConfig {
+            preallocate_limit: 1024 * 1024,
+            max_scan_size: None,
+        }
+    }
+}
+
+impl AdmissionConfig {
+    /// Parse the `admission` section of a server config
+    pub fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let Yaml::Hash(map) = v else {
+            return Err(anyhow!("admission should be a map"));
+        };
+
+        let mut config = AdmissionConfig::default();
+        g3_yaml::foreach_kv(map, |k, v| {
+            match g3_yaml::key::normalize(k).as_str() {
+                "preallocate_limit" => {
+                    config.preallocate_limit = g3_yaml::humanize::as_usize(v)?;
+                }
+                "max_scan_size" => {
+                    config.max_scan_size = Some(g3_yaml::humanize::as_u64(v)?);
+                }
+                _ => return Err(anyhow!("invalid key {k} in admission config")),
+            }
+            Ok(())
+        })?;
+        Ok(config)
+    }
+}
@@ -0,0 +1,64 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! AI API inspection configuration
+//!
+//! The content filter parses the JSON bodies of the configured content types
+//! and matches its body keyword rules against the text fields only, such as
+//! the prompt and the messages of a chat completion request. The matched
+//! messages are blocked, or forwarded with the matched spans redacted.
+
+use anyhow::anyhow;
+use yaml_rust::Yaml;
+
+use crate::modules::ai_inspection::{AiInspectionAction, AiInspectionConfig};
+
+/// Parse the `ai_inspection` section of a server config
+///
+/// The profile is enabled by the section.
+pub fn parse(v: &Yaml) -> anyhow::Result<AiInspectionConfig> {
+    let Yaml::Hash(map) = v else {
+        return Err(anyhow!("ai_inspection should be a map"));
+    };
+
+    let mut config = AiInspectionConfig {
+        enabled: true,
+        ..Default::default()
+    };
+    g3_yaml::foreach_kv(map, |k, v| {
+        match g3_yaml::key::normalize(k).as_str() {
+            "enabled" | "enable" => config.enabled = g3_yaml::value::as_bool(v)?,
+            "content_types" | "content_type" => {
+                config.content_types = g3_yaml::value::as_list(v, g3_yaml::value::as_string)?
+            }
+            "text_fields" | "fields" => {
+                config.text_fields = g3_yaml::value::as_list(v, g3_yaml::value::as_string)?
+            }
+            "max_tokens" => config.max_tokens = g3_yaml::humanize::as_usize(v)?,
+            "action" => {
+                let s = g3_yaml::value::as_string(v)?;
+                config.action = match s.to_ascii_lowercase().as_str() {
+                    "block" => AiInspectionAction::Block,
+                    "redact" => AiInspectionAction::Redact,
+                    _ => return Err(anyhow!("unsupported ai_inspection action {s}")),
+                };
+            }
+            "redaction" | "replacement" => config.redaction = g3_yaml::value::as_string(v)?,
+            _ => return Err(anyhow!("invalid key {k} in ai_inspection config")),
+        }
+        Ok(())
+    })?;
+
+    if config.content_types.is_empty() {
+        return Err(anyhow!("ai_inspection needs at least one content type"));
+    }
+    if config.text_fields.is_empty() {
+        return Err(anyhow!("ai_inspection needs at least one text field"));
+    }
+    if config.max_tokens == 0 {
+        return Err(anyhow!("ai_inspection max_tokens should not be 0"));
+    }
+    Ok(config)
+}
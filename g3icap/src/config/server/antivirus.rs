@@ -0,0 +1,123 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Antivirus configuration
+//!
+//! With a `clamd` address the antivirus scans with clamd instead of the
+//! built-in mock engine. The verdicts of the repeated objects may be cached,
+//! and shared with the other servers through Redis, and the hash of the
+//! content may be looked up in threat intelligence sources before the scan.
+
+use std::time::Duration;
+
+use anyhow::anyhow;
+use yaml_rust::Yaml;
+
+use crate::modules::verdict_cache::VerdictCacheConfig;
+
+/// Antivirus settings of a server
+#[derive(Debug, Clone, Default)]
+pub struct AntivirusServerConfig {
+    /// Socket path, or `host:port` address, of clamd
+    pub clamd: Option<String>,
+    /// Timeout of a scan
+    pub scan_timeout: Option<Duration>,
+    /// Cache of the verdicts of the repeated objects
+    pub verdict_cache: Option<VerdictCacheConfig>,
+    /// Threat intelligence source urls
+    pub threat_intel: Vec<String>,
+}
+
+/// Parse the `antivirus` section of a server config
+pub fn parse(v: &Yaml) -> anyhow::Result<AntivirusServerConfig> {
+    let Yaml::Hash(map) = v else {
+        return Err(anyhow!("antivirus should be a map"));
+    };
+
+    let mut config = AntivirusServerConfig::default();
+    g3_yaml::foreach_kv(map, |k, v| {
+        match g3_yaml::key::normalize(k).as_str() {
+            "clamd" | "clamd_addr" => config.clamd = Some(g3_yaml::value::as_string(v)?),
+            "scan_timeout" | "timeout" => {
+                config.scan_timeout = Some(g3_yaml::humanize::as_duration(v)?)
+            }
+            "verdict_cache" | "cache" => config.verdict_cache = Some(parse_verdict_cache(v)?),
+            "threat_intel" | "threat_intel_sources" => {
+                config.threat_intel = g3_yaml::value::as_list(v, g3_yaml::value::as_string)?
+            }
+            _ => return Err(anyhow!("invalid key {k} in antivirus config")),
+        }
+        Ok(())
+    })?;
+
+    if config.clamd.as_ref().is_some_and(|s| s.is_empty()) {
+        return Err(anyhow!("antivirus clamd address should not be empty"));
+    }
+    if config.scan_timeout.is_some_and(|t| t.is_zero()) {
+        return Err(anyhow!("antivirus scan_timeout should not be 0"));
+    }
+    Ok(config)
+}
+
+fn parse_verdict_cache(v: &Yaml) -> anyhow::Result<VerdictCacheConfig> {
+    let Yaml::Hash(map) = v else {
+        return Err(anyhow!("verdict_cache should be a map"));
+    };
+
+    let mut config = VerdictCacheConfig::default();
+    g3_yaml::foreach_kv(map, |k, v| {
+        match g3_yaml::key::normalize(k).as_str() {
+            "ttl" => config.ttl = g3_yaml::humanize::as_duration(v)?,
+            "max_entries" => config.max_entries = g3_yaml::humanize::as_usize(v)?,
+            "redis" | "redis_url" => {
+                let url = g3_yaml::value::as_string(v)?;
+                if !url.starts_with("redis://") && !url.starts_with("rediss://") {
+                    return Err(anyhow!("verdict_cache redis should be a redis:// url"));
+                }
+                config.redis = Some(url);
+            }
+            _ => return Err(anyhow!("invalid key {k} in verdict_cache config")),
+        }
+        Ok(())
+    })?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yaml_rust::YamlLoader;
+
+    fn parse_str(s: &str) -> anyhow::Result<AntivirusServerConfig> {
+        let docs = YamlLoader::load_from_str(s).unwrap();
+        parse(&docs[0])
+    }
+
+    #[test]
+    fn section() {
+        let config = parse_str(
+            "
+            clamd: 127.0.0.1:3310
+            scan_timeout: 10s
+            verdict_cache:
+              ttl: 5m
+              redis: redis://127.0.0.1:6379/1
+            threat_intel:
+              - http://127.0.0.1:8080/lookup
+            ",
+        )
+        .unwrap();
+        assert_eq!(config.clamd.as_deref(), Some("127.0.0.1:3310"));
+        assert_eq!(config.scan_timeout, Some(Duration::from_secs(10)));
+        let cache = config.verdict_cache.unwrap();
+        assert_eq!(cache.ttl, Duration::from_secs(300));
+        assert_eq!(cache.max_entries, VerdictCacheConfig::default().max_entries);
+        assert_eq!(cache.redis.as_deref(), Some("redis://127.0.0.1:6379/1"));
+        assert_eq!(config.threat_intel, ["http://127.0.0.1:8080/lookup"]);
+
+        assert!(parse_str("verdict_cache: {redis: 127.0.0.1:6379}").is_err());
+        assert!(parse_str("clamd_socket: /run/clamd.sock").is_err());
+    }
+}
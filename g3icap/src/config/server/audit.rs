@@ -0,0 +1,497 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Transaction audit log configuration
+//!
+//! Each ICAP transaction produces one audit record, serialized as JSON, CEF
+//! or LEEF and written to all the configured sinks: a file rotated by size,
+//! the syslog daemon, a TCP collector taking one record per line or Kafka
+//! topics. Syslog messages may be sent as RFC 5424 messages over UDP, TCP or
+//! TLS, with the verdict, the category and the threat as structured data, or
+//! as RFC 3164 messages, as most SIEMs expect the CEF and LEEF records.
+//! Kafka brokers may be reached over TLS and authenticated to with SASL
+//! PLAIN or SCRAM, the password being best given as a `!secret` reference.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use g3_types::net::{RustlsClientConfigBuilder, UpstreamAddr};
+use yaml_rust::{Yaml, yaml};
+
+const KAFKA_DEFAULT_PORT: u16 = 9092;
+const SYSLOG_DEFAULT_PORT: u16 = 514;
+const SYSLOG_TLS_DEFAULT_PORT: u16 = 6514;
+
+/// Serialization of the audit records
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AuditFormat {
+    /// One JSON object per line
+    #[default]
+    Json,
+    /// ArcSight Common Event Format
+    Cef,
+    /// QRadar Log Event Extended Format 2.0
+    Leef,
+}
+
+impl AuditFormat {
+    fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let s = g3_yaml::value::as_string(v)?;
+        match g3_yaml::key::normalize(&s).as_str() {
+            "json" => Ok(AuditFormat::Json),
+            "cef" => Ok(AuditFormat::Cef),
+            "leef" => Ok(AuditFormat::Leef),
+            _ => Err(anyhow!("unsupported audit format {s}")),
+        }
+    }
+}
+
+/// A destination of the audit records
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditSinkConfig {
+    /// Append to a file, rotated once it reaches `max_size`
+    File {
+        path: PathBuf,
+        max_size: u64,
+        /// Rotated files kept, as `<path>.1` to `<path>.<max_files>`
+        max_files: usize,
+    },
+    /// Send to the syslog daemon
+    Syslog(SyslogSinkConfig),
+    /// Stream to a TCP collector, reconnecting as needed
+    Tcp { address: SocketAddr },
+    /// Produce to Kafka topics
+    Kafka(KafkaSinkConfig),
+}
+
+/// Transport of the syslog messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyslogTransport {
+    /// The local `/dev/log` socket
+    Unix,
+    Udp,
+    /// Octet counting framing, as in RFC 6587
+    Tcp,
+    /// TCP with TLS, as in RFC 5425
+    Tls,
+}
+
+/// Format of the syslog messages
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyslogFormat {
+    /// `<pri>g3icap[pid]: msg`, as accepted by all daemons
+    Rfc3164,
+    /// With the fields of the record as structured data
+    #[default]
+    Rfc5424,
+}
+
+/// Syslog sink of the audit records
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyslogSinkConfig {
+    pub transport: SyslogTransport,
+    /// Address of the syslog server, unset for the unix transport
+    pub address: Option<UpstreamAddr>,
+    pub format: SyslogFormat,
+    pub facility: u8,
+    pub tls_client: RustlsClientConfigBuilder,
+    /// Name to verify the certificate of the server with, its host by default
+    pub tls_name: Option<String>,
+    /// SD-ID of the structured data, `name@private-enterprise-number`
+    pub sd_id: String,
+    /// Hostname in the messages, the name of the system by default
+    pub hostname: Option<String>,
+}
+
+impl SyslogSinkConfig {
+    fn parse(map: &yaml::Hash) -> anyhow::Result<Self> {
+        let mut transport = None;
+        let mut address = None;
+        let mut config = SyslogSinkConfig {
+            transport: SyslogTransport::Unix,
+            address: None,
+            format: SyslogFormat::default(),
+            facility: 16, // local0
+            tls_client: RustlsClientConfigBuilder::default(),
+            tls_name: None,
+            sd_id: "g3icap@32473".to_string(),
+            hostname: None,
+        };
+        g3_yaml::foreach_kv(map, |k, v| {
+            match g3_yaml::key::normalize(k).as_str() {
+                "type" => {}
+                "transport" | "protocol" => {
+                    let s = g3_yaml::value::as_string(v)?;
+                    transport = Some(match s.to_lowercase().as_str() {
+                        "unix" => SyslogTransport::Unix,
+                        "udp" => SyslogTransport::Udp,
+                        "tcp" => SyslogTransport::Tcp,
+                        "tls" => SyslogTransport::Tls,
+                        _ => return Err(anyhow!("unsupported syslog transport {s}")),
+                    });
+                }
+                "address" | "addr" => address = Some(v.clone()),
+                "format" => {
+                    let s = g3_yaml::value::as_string(v)?;
+                    config.format = match s.to_lowercase().as_str() {
+                        "rfc3164" | "bsd" => SyslogFormat::Rfc3164,
+                        "rfc5424" => SyslogFormat::Rfc5424,
+                        _ => return Err(anyhow!("unsupported syslog format {s}")),
+                    };
+                }
+                "facility" => config.facility = parse_facility(v)?,
+                "tls_client" | "tls" => {
+                    config.tls_client = g3_yaml::value::as_rustls_client_config_builder(
+                        v,
+                        g3_daemon::opts::config_dir(),
+                    )?;
+                }
+                "tls_name" => config.tls_name = Some(g3_yaml::value::as_string(v)?),
+                "sd_id" => config.sd_id = g3_yaml::value::as_string(v)?,
+                "hostname" => config.hostname = Some(g3_yaml::value::as_string(v)?),
+                _ => return Err(anyhow!("invalid key {k} in audit syslog sink config")),
+            }
+            Ok(())
+        })?;
+
+        // a remote server is reached over udp unless set otherwise
+        config.transport = match (transport, &address) {
+            (Some(t), _) => t,
+            (None, Some(_)) => SyslogTransport::Udp,
+            (None, None) => SyslogTransport::Unix,
+        };
+        let default_port = match config.transport {
+            SyslogTransport::Tls => SYSLOG_TLS_DEFAULT_PORT,
+            _ => SYSLOG_DEFAULT_PORT,
+        };
+        config.address = address
+            .map(|v| g3_yaml::value::as_upstream_addr(&v, default_port))
+            .transpose()?;
+        match (config.transport, &config.address) {
+            (SyslogTransport::Unix, Some(_)) => Err(anyhow!(
+                "no address should be set for the unix syslog transport"
+            )),
+            (SyslogTransport::Unix, None) => Ok(config),
+            (_, None) => Err(anyhow!("no address set for the audit syslog sink")),
+            (_, Some(_)) => Ok(config),
+        }
+    }
+}
+
+/// Compression of the Kafka record batches
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KafkaCompression {
+    #[default]
+    None,
+    Gzip,
+}
+
+/// SASL mechanism to authenticate to the Kafka brokers with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KafkaSaslMechanism {
+    Plain,
+    ScramSha256,
+    ScramSha512,
+}
+
+impl KafkaSaslMechanism {
+    /// Name of the mechanism in the SASL handshake
+    pub fn name(&self) -> &'static str {
+        match self {
+            KafkaSaslMechanism::Plain => "PLAIN",
+            KafkaSaslMechanism::ScramSha256 => "SCRAM-SHA-256",
+            KafkaSaslMechanism::ScramSha512 => "SCRAM-SHA-512",
+        }
+    }
+}
+
+/// SASL credentials of the Kafka producer
+///
+/// The password is usually a `!secret` reference, resolved when the config
+/// is loaded.
+#[derive(Clone, PartialEq, Eq)]
+pub struct KafkaSaslConfig {
+    pub mechanism: KafkaSaslMechanism,
+    pub username: String,
+    pub password: String,
+}
+
+impl fmt::Debug for KafkaSaslConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KafkaSaslConfig")
+            .field("mechanism", &self.mechanism)
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KafkaSaslConfig {
+    fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let Yaml::Hash(map) = v else {
+            return Err(anyhow!("kafka sasl should be a map"));
+        };
+        let mut mechanism = KafkaSaslMechanism::Plain;
+        let mut username = None;
+        let mut password = None;
+        g3_yaml::foreach_kv(map, |k, v| {
+            match g3_yaml::key::normalize(k).as_str() {
+                "mechanism" => {
+                    let s = g3_yaml::value::as_string(v)?;
+                    mechanism = match s.to_uppercase().replace('_', "-").as_str() {
+                        "PLAIN" => KafkaSaslMechanism::Plain,
+                        "SCRAM-SHA-256" => KafkaSaslMechanism::ScramSha256,
+                        "SCRAM-SHA-512" => KafkaSaslMechanism::ScramSha512,
+                        _ => return Err(anyhow!("unsupported kafka sasl mechanism {s}")),
+                    };
+                }
+                "username" | "user" => username = Some(g3_yaml::value::as_string(v)?),
+                "password" => password = Some(g3_yaml::value::as_string(v)?),
+                _ => return Err(anyhow!("invalid key {k} in kafka sasl config")),
+            }
+            Ok(())
+        })?;
+        let (Some(username), Some(password)) = (username, password) else {
+            return Err(anyhow!("kafka sasl needs both a username and a password"));
+        };
+        Ok(KafkaSaslConfig {
+            mechanism,
+            username,
+            password,
+        })
+    }
+}
+
+/// Kafka producer of the audit records
+///
+/// Blocked transactions and security events go to `detection_topic` if set,
+/// all the other records to `topic`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KafkaSinkConfig {
+    /// Bootstrap brokers, the partition leaders are found from their metadata
+    pub brokers: Vec<UpstreamAddr>,
+    pub topic: String,
+    pub detection_topic: Option<String>,
+    pub client_id: String,
+    /// Acknowledgements required: 0, 1 or -1 for all in-sync replicas
+    pub acks: i16,
+    pub compression: KafkaCompression,
+    /// Records sent in one batch at most
+    pub batch_size: usize,
+    /// Time a record may wait for its batch to fill
+    pub linger: Duration,
+    /// Directory keeping the batches that could not be delivered
+    pub spill_dir: Option<PathBuf>,
+    /// Size of a spill file above which records are dropped
+    pub spill_max_size: u64,
+    /// Connect to the brokers over TLS if set
+    pub tls_client: Option<RustlsClientConfigBuilder>,
+    /// Name to verify the certificates of the brokers with, their host by default
+    pub tls_name: Option<String>,
+    /// Authenticate to the brokers if set
+    pub sasl: Option<KafkaSaslConfig>,
+}
+
+impl KafkaSinkConfig {
+    fn parse(map: &yaml::Hash) -> anyhow::Result<Self> {
+        crate::features::KAFKA.require("the kafka audit sink")?;
+        let mut config = KafkaSinkConfig {
+            brokers: Vec::new(),
+            topic: String::new(),
+            detection_topic: None,
+            client_id: "g3icap".to_string(),
+            acks: 1,
+            compression: KafkaCompression::None,
+            batch_size: 500,
+            linger: Duration::from_secs(1),
+            spill_dir: None,
+            spill_max_size: 1024 * 1024 * 1024,
+            tls_client: None,
+            tls_name: None,
+            sasl: None,
+        };
+        g3_yaml::foreach_kv(map, |k, v| config.set(k, v))?;
+        config.check()?;
+        Ok(config)
+    }
+
+    fn set(&mut self, k: &str, v: &Yaml) -> anyhow::Result<()> {
+        match g3_yaml::key::normalize(k).as_str() {
+            "type" => {}
+            "brokers" | "bootstrap_servers" => {
+                self.brokers = g3_yaml::value::as_list(v, |v| {
+                    g3_yaml::value::as_upstream_addr(v, KAFKA_DEFAULT_PORT)
+                })?;
+            }
+            "topic" => self.topic = g3_yaml::value::as_string(v)?,
+            "detection_topic" => self.detection_topic = Some(g3_yaml::value::as_string(v)?),
+            "client_id" => self.client_id = g3_yaml::value::as_string(v)?,
+            "acks" => {
+                self.acks = match v {
+                    Yaml::String(s) if s == "all" => -1,
+                    _ => match g3_yaml::value::as_i32(v)? {
+                        a @ (-1 | 0 | 1) => a as i16,
+                        a => return Err(anyhow!("invalid kafka acks {a}")),
+                    },
+                };
+            }
+            "compression" => {
+                let s = g3_yaml::value::as_string(v)?;
+                self.compression = match s.to_lowercase().as_str() {
+                    "none" => KafkaCompression::None,
+                    "gzip" => KafkaCompression::Gzip,
+                    _ => return Err(anyhow!("unsupported kafka compression {s}")),
+                };
+            }
+            "batch_size" => self.batch_size = g3_yaml::value::as_usize(v)?,
+            "linger" => self.linger = g3_yaml::humanize::as_duration(v)?,
+            "spill_dir" => self.spill_dir = Some(g3_yaml::value::as_absolute_path(v)?),
+            "spill_max_size" => self.spill_max_size = g3_yaml::humanize::as_u64(v)?,
+            "tls_client" | "tls" => {
+                self.tls_client = Some(g3_yaml::value::as_rustls_client_config_builder(
+                    v,
+                    g3_daemon::opts::config_dir(),
+                )?);
+            }
+            "tls_name" => self.tls_name = Some(g3_yaml::value::as_string(v)?),
+            "sasl" => self.sasl = Some(KafkaSaslConfig::parse(v)?),
+            _ => return Err(anyhow!("invalid key {k} in audit kafka sink config")),
+        }
+        Ok(())
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        if self.brokers.is_empty() {
+            return Err(anyhow!("no brokers set for the audit kafka sink"));
+        }
+        if self.topic.is_empty() {
+            return Err(anyhow!("no topic set for the audit kafka sink"));
+        }
+        if self.batch_size == 0 {
+            return Err(anyhow!("kafka batch_size should not be zero"));
+        }
+        Ok(())
+    }
+}
+
+impl AuditSinkConfig {
+    fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let Yaml::Hash(map) = v else {
+            return Err(anyhow!("audit sink should be a map"));
+        };
+        let sink_type = g3_yaml::hash_get_required_str(map, "type")?;
+        match g3_yaml::key::normalize(sink_type).as_str() {
+            "syslog" => return SyslogSinkConfig::parse(map).map(AuditSinkConfig::Syslog),
+            "kafka" => return KafkaSinkConfig::parse(map).map(AuditSinkConfig::Kafka),
+            _ => {}
+        }
+
+        let mut path = None;
+        let mut max_size = 100 * 1024 * 1024;
+        let mut max_files = 5;
+        let mut address = None;
+        g3_yaml::foreach_kv(map, |k, v| {
+            match g3_yaml::key::normalize(k).as_str() {
+                "type" => {}
+                "path" => path = Some(g3_yaml::value::as_absolute_path(v)?),
+                "max_size" => max_size = g3_yaml::humanize::as_u64(v)?,
+                "max_files" => max_files = g3_yaml::value::as_usize(v)?,
+                "address" | "addr" => address = Some(g3_yaml::value::as_sockaddr(v)?),
+                _ => return Err(anyhow!("invalid key {k} in audit sink config")),
+            }
+            Ok(())
+        })?;
+
+        match g3_yaml::key::normalize(sink_type).as_str() {
+            "file" => {
+                let path = path.ok_or_else(|| anyhow!("no path set for the audit file sink"))?;
+                if max_size == 0 {
+                    return Err(anyhow!("audit file max_size should not be zero"));
+                }
+                Ok(AuditSinkConfig::File {
+                    path,
+                    max_size,
+                    max_files,
+                })
+            }
+            "tcp" => {
+                let address =
+                    address.ok_or_else(|| anyhow!("no address set for the audit tcp sink"))?;
+                Ok(AuditSinkConfig::Tcp { address })
+            }
+            _ => Err(anyhow!("unsupported audit sink type {sink_type}")),
+        }
+    }
+}
+
+fn parse_facility(v: &Yaml) -> anyhow::Result<u8> {
+    if let Yaml::Integer(_) = v {
+        let code = g3_yaml::value::as_u8(v)?;
+        if code > 23 {
+            return Err(anyhow!("invalid syslog facility code {code}"));
+        }
+        return Ok(code);
+    }
+    let s = g3_yaml::value::as_string(v)?;
+    let code = match s.to_lowercase().as_str() {
+        "user" => 1,
+        "daemon" => 3,
+        "auth" => 4,
+        "authpriv" => 10,
+        "local0" => 16,
+        "local1" => 17,
+        "local2" => 18,
+        "local3" => 19,
+        "local4" => 20,
+        "local5" => 21,
+        "local6" => 22,
+        "local7" => 23,
+        _ => return Err(anyhow!("unsupported syslog facility {s}")),
+    };
+    Ok(code)
+}
+
+/// Transaction audit log of a server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditLogConfig {
+    pub format: AuditFormat,
+    pub sinks: Vec<AuditSinkConfig>,
+    /// Records waiting for the sinks, more are dropped
+    pub queue_size: usize,
+}
+
+impl AuditLogConfig {
+    /// Parse the `audit` section of a server config
+    pub fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let Yaml::Hash(map) = v else {
+            return Err(anyhow!("audit should be a map"));
+        };
+
+        let mut config = AuditLogConfig {
+            format: AuditFormat::default(),
+            sinks: Vec::new(),
+            queue_size: 4096,
+        };
+        g3_yaml::foreach_kv(map, |k, v| {
+            match g3_yaml::key::normalize(k).as_str() {
+                "format" => config.format = AuditFormat::parse(v)?,
+                "sinks" => config.sinks = g3_yaml::value::as_list(v, AuditSinkConfig::parse)?,
+                "sink" => config.sinks = vec![AuditSinkConfig::parse(v)?],
+                "queue_size" => config.queue_size = g3_yaml::value::as_usize(v)?,
+                _ => return Err(anyhow!("invalid key {k} in audit config")),
+            }
+            Ok(())
+        })?;
+        if config.sinks.is_empty() {
+            return Err(anyhow!("no sink configured for the audit log"));
+        }
+        if config.queue_size == 0 {
+            return Err(anyhow!("audit queue_size should not be zero"));
+        }
+        Ok(config)
+    }
+}
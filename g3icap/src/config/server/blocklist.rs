@@ -0,0 +1,203 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Domain blocklist configuration
+//!
+//! Blocked domains are loaded from local files and remote HTTP(S) lists.
+//! Remote lists are fetched again on every refresh interval, local files
+//! are reloaded as soon as their modification time changes. Very large
+//! lists can be compiled into frozen files, which are mapped read-only.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use url::Url;
+use yaml_rust::Yaml;
+
+/// Format of a domain list
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlocklistFormat {
+    /// One domain per line
+    #[default]
+    Plain,
+    /// hosts file, `0.0.0.0 domain`
+    Hosts,
+    /// Adblock filter list, only `||domain^` rules are used
+    Adblock,
+    /// Frozen list compiled by `g3icap-listc`, mapped instead of loaded
+    Frozen,
+}
+
+impl std::str::FromStr for BlocklistFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match g3_yaml::key::normalize(s).as_str() {
+            "plain" | "domains" => Ok(BlocklistFormat::Plain),
+            "hosts" => Ok(BlocklistFormat::Hosts),
+            "adblock" | "abp" => Ok(BlocklistFormat::Adblock),
+            "frozen" | "g3list" => Ok(BlocklistFormat::Frozen),
+            _ => Err(anyhow!("unsupported blocklist format {s}")),
+        }
+    }
+}
+
+/// Where a domain list is loaded from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlocklistLocation {
+    File(PathBuf),
+    Url(Url),
+}
+
+impl std::fmt::Display for BlocklistLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlocklistLocation::File(path) => write!(f, "{}", path.display()),
+            BlocklistLocation::Url(url) => write!(f, "{url}"),
+        }
+    }
+}
+
+/// A single domain list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlocklistSource {
+    /// Name used in rule ids, defaults to the location
+    pub name: String,
+    pub location: BlocklistLocation,
+    pub format: BlocklistFormat,
+}
+
+impl BlocklistSource {
+    fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        match v {
+            Yaml::String(s) => Self::with_location(parse_location(s)?),
+            Yaml::Hash(map) => {
+                let mut name = None;
+                let mut location = None;
+                let mut format = BlocklistFormat::default();
+                g3_yaml::foreach_kv(map, |k, v| {
+                    match g3_yaml::key::normalize(k).as_str() {
+                        "name" => name = Some(g3_yaml::value::as_string(v)?),
+                        "file" | "path" => {
+                            location = Some(BlocklistLocation::File(
+                                g3_yaml::value::as_absolute_path(v)?,
+                            ));
+                        }
+                        "url" => location = Some(parse_location(&g3_yaml::value::as_string(v)?)?),
+                        "format" => format = g3_yaml::value::as_string(v)?.parse()?,
+                        _ => return Err(anyhow!("invalid key {k} in blocklist source")),
+                    }
+                    Ok(())
+                })?;
+                let location =
+                    location.ok_or_else(|| anyhow!("no file or url set for blocklist source"))?;
+                let mut source = Self::with_location(location)?;
+                source.format = format;
+                if let Some(name) = name {
+                    source.name = name;
+                }
+                if format == BlocklistFormat::Frozen
+                    && matches!(source.location, BlocklistLocation::Url(_))
+                {
+                    return Err(anyhow!(
+                        "frozen blocklist {} should be a local file",
+                        source.name
+                    ));
+                }
+                Ok(source)
+            }
+            _ => Err(anyhow!("blocklist source should be a string or a map")),
+        }
+    }
+
+    fn with_location(location: BlocklistLocation) -> anyhow::Result<Self> {
+        Ok(BlocklistSource {
+            name: location.to_string(),
+            location,
+            format: BlocklistFormat::default(),
+        })
+    }
+}
+
+fn parse_location(s: &str) -> anyhow::Result<BlocklistLocation> {
+    if s.starts_with("http://") || s.starts_with("https://") {
+        let url = Url::parse(s).map_err(|e| anyhow!("invalid blocklist url {s}: {e}"))?;
+        return Ok(BlocklistLocation::Url(url));
+    }
+    let path = PathBuf::from(s);
+    if path.is_relative() {
+        return Err(anyhow!("blocklist file {s} is not an absolute path"));
+    }
+    Ok(BlocklistLocation::File(path))
+}
+
+/// Domain blocklist of a server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlocklistConfig {
+    pub sources: Vec<BlocklistSource>,
+    /// Interval to fetch remote lists again
+    pub refresh_interval: Duration,
+    /// Interval to check local files for modifications
+    pub watch_interval: Duration,
+    /// Timeout of a remote list download
+    pub fetch_timeout: Duration,
+    /// Maximum size of a single list
+    pub max_list_size: usize,
+}
+
+impl Default for BlocklistConfig {
+    fn default() -> Self {
+        BlocklistConfig {
+            sources: Vec::new(),
+            refresh_interval: Duration::from_secs(3600),
+            watch_interval: Duration::from_secs(5),
+            fetch_timeout: Duration::from_secs(30),
+            max_list_size: 64 * 1024 * 1024,
+        }
+    }
+}
+
+impl BlocklistConfig {
+    /// Parse the `blocklist` section of a server config
+    pub fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let mut config = BlocklistConfig::default();
+        match v {
+            // a single source or a list of sources
+            Yaml::String(_) => config.sources.push(BlocklistSource::parse(v)?),
+            Yaml::Array(_) => config.sources = g3_yaml::value::as_list(v, BlocklistSource::parse)?,
+            Yaml::Hash(map) => {
+                g3_yaml::foreach_kv(map, |k, v| {
+                    match g3_yaml::key::normalize(k).as_str() {
+                        "sources" | "source" => {
+                            config.sources = g3_yaml::value::as_list(v, BlocklistSource::parse)?;
+                        }
+                        "refresh_interval" => {
+                            config.refresh_interval = g3_yaml::humanize::as_duration(v)?;
+                        }
+                        "watch_interval" => {
+                            config.watch_interval = g3_yaml::humanize::as_duration(v)?;
+                        }
+                        "fetch_timeout" => {
+                            config.fetch_timeout = g3_yaml::humanize::as_duration(v)?;
+                        }
+                        "max_list_size" => {
+                            config.max_list_size = g3_yaml::humanize::as_usize(v)?;
+                        }
+                        _ => return Err(anyhow!("invalid key {k} in blocklist config")),
+                    }
+                    Ok(())
+                })?;
+            }
+            _ => return Err(anyhow!("invalid value type for blocklist config")),
+        }
+        if config.refresh_interval.is_zero() || config.watch_interval.is_zero() {
+            return Err(anyhow!(
+                "blocklist refresh and watch intervals should not be zero"
+            ));
+        }
+        Ok(config)
+    }
+}
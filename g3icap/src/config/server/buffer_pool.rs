@@ -0,0 +1,72 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Read buffer pool configuration
+//!
+//! The connections take their read buffers from a pool instead of
+//! allocating them, and give them back once the request is parsed. The pool
+//! is split in shards to keep the worker threads from contending on it.
+
+use anyhow::anyhow;
+use yaml_rust::Yaml;
+
+/// Sizing of the read buffer pool of a server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferPoolConfig {
+    /// Initial capacity of the buffers
+    pub buffer_size: usize,
+    /// Buffers grown beyond this size are freed instead of pooled
+    pub max_retained_size: usize,
+    /// Number of shards, 0 for one per CPU
+    pub shards: usize,
+    /// Most idle buffers kept in each shard
+    pub max_idle_per_shard: usize,
+}
+
+impl Default for BufferPoolConfig {
+    fn default() -> Self {
+        BufferPoolConfig {
+            buffer_size: 64 * 1024,
+            max_retained_size: 1024 * 1024,
+            shards: 0,
+            max_idle_per_shard: 64,
+        }
+    }
+}
+
+impl BufferPoolConfig {
+    /// Parse the `buffer_pool` section of a server config
+    pub fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let Yaml::Hash(map) = v else {
+            return Err(anyhow!("buffer_pool should be a map"));
+        };
+
+        let mut config = BufferPoolConfig::default();
+        g3_yaml::foreach_kv(map, |k, v| {
+            match g3_yaml::key::normalize(k).as_str() {
+                "buffer_size" => {
+                    config.buffer_size = g3_yaml::humanize::as_usize(v)?;
+                }
+                "max_retained_size" => {
+                    config.max_retained_size = g3_yaml::humanize::as_usize(v)?;
+                }
+                "shards" => {
+                    config.shards = g3_yaml::value::as_usize(v)?;
+                }
+                "max_idle_per_shard" => {
+                    config.max_idle_per_shard = g3_yaml::value::as_usize(v)?;
+                }
+                _ => return Err(anyhow!("invalid key {k} in buffer_pool config")),
+            }
+            Ok(())
+        })?;
+        if config.max_retained_size < config.buffer_size {
+            return Err(anyhow!(
+                "max_retained_size should not be less than buffer_size"
+            ));
+        }
+        Ok(config)
+    }
+}
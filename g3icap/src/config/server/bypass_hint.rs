@@ -0,0 +1,69 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Bypass hint configuration
+//!
+//! Origins of the trusted static categories, e.g. CDNs of images and fonts,
+//! are allowed with a hint that the proxy may skip ICAP for further requests
+//! to the same origin for a while. The hint is only sent to the clients that
+//! opted in, after having seen it advertised in the OPTIONS response.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use yaml_rust::Yaml;
+
+/// Bypass hints of a server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BypassHintConfig {
+    /// How long the proxy may skip ICAP for the origin
+    pub ttl: Duration,
+    /// Domains of the trusted static categories, keyed by category
+    pub trusted_static: BTreeMap<String, Vec<String>>,
+}
+
+impl Default for BypassHintConfig {
+    fn default() -> Self {
+        BypassHintConfig {
+            ttl: Duration::from_secs(300),
+            trusted_static: BTreeMap::new(),
+        }
+    }
+}
+
+impl BypassHintConfig {
+    /// Parse the `bypass_hint` section of a server config
+    pub fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let Yaml::Hash(map) = v else {
+            return Err(anyhow!("bypass_hint should be a map"));
+        };
+
+        let mut config = BypassHintConfig::default();
+        g3_yaml::foreach_kv(map, |k, v| {
+            match g3_yaml::key::normalize(k).as_str() {
+                "ttl" => config.ttl = g3_yaml::humanize::as_duration(v)?,
+                "trusted_static" => {
+                    let Yaml::Hash(map) = v else {
+                        return Err(anyhow!("trusted_static should be a map of domain lists"));
+                    };
+                    g3_yaml::foreach_kv(map, |category, v| {
+                        let domains = g3_yaml::value::as_list(v, g3_yaml::value::as_string)?;
+                        config
+                            .trusted_static
+                            .insert(category.to_lowercase(), domains);
+                        Ok(())
+                    })?;
+                }
+                _ => return Err(anyhow!("invalid key {k} in bypass_hint config")),
+            }
+            Ok(())
+        })?;
+        if config.ttl < Duration::from_secs(1) {
+            return Err(anyhow!("bypass_hint ttl should be at least 1s"));
+        }
+        Ok(config)
+    }
+}
@@ -0,0 +1,148 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! ICAP client authentication configuration
+//!
+//! Clients (usually the proxies) present either a static API token or a
+//! HS256 signed JWT in an ICAP header. Several tokens and JWT keys may be
+//! configured at the same time, which allows keys to be rotated without
+//! rejecting clients that still use the previous one.
+
+use std::time::Duration;
+
+use anyhow::anyhow;
+use yaml_rust::Yaml;
+
+/// Default ICAP header carrying the credential
+pub const DEFAULT_AUTH_HEADER: &str = "Authorization";
+/// Default realm reported in WWW-Authenticate
+pub const DEFAULT_AUTH_REALM: &str = "g3icap";
+
+/// A static API token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiTokenConfig {
+    /// Token id, used for stats and logging
+    pub id: String,
+    /// Token secret value
+    pub secret: String,
+}
+
+/// A HS256 JWT verification key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JwtKeyConfig {
+    /// Key id matched against the `kid` JWT header
+    pub kid: String,
+    /// HMAC secret
+    pub secret: String,
+}
+
+/// Client authentication configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientAuthConfig {
+    /// ICAP header carrying the credential
+    pub header: String,
+    /// Realm reported in WWW-Authenticate
+    pub realm: String,
+    /// Accepted static API tokens
+    pub api_tokens: Vec<ApiTokenConfig>,
+    /// Accepted JWT verification keys
+    pub jwt_keys: Vec<JwtKeyConfig>,
+    /// Required `iss` claim, if any
+    pub jwt_issuer: Option<String>,
+    /// Allowed clock skew when checking `exp` and `nbf`
+    pub jwt_leeway: Duration,
+}
+
+impl Default for ClientAuthConfig {
+    fn default() -> Self {
+        ClientAuthConfig {
+            header: DEFAULT_AUTH_HEADER.to_string(),
+            realm: DEFAULT_AUTH_REALM.to_string(),
+            api_tokens: Vec::new(),
+            jwt_keys: Vec::new(),
+            jwt_issuer: None,
+            jwt_leeway: Duration::from_secs(30),
+        }
+    }
+}
+
+impl ClientAuthConfig {
+    /// Parse the `client_auth` section of a server config
+    pub fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let Yaml::Hash(map) = v else {
+            return Err(anyhow!("client_auth should be a map"));
+        };
+
+        let mut config = ClientAuthConfig::default();
+        g3_yaml::foreach_kv(map, |k, v| {
+            match g3_yaml::key::normalize(k).as_str() {
+                "header" => {
+                    config.header = g3_yaml::value::as_string(v)?;
+                }
+                "realm" => {
+                    config.realm = g3_yaml::value::as_string(v)?;
+                }
+                "tokens" | "api_tokens" => {
+                    config.api_tokens = g3_yaml::value::as_list(v, parse_api_token)?;
+                }
+                "jwt_keys" => {
+                    config.jwt_keys = g3_yaml::value::as_list(v, parse_jwt_key)?;
+                }
+                "jwt_issuer" => {
+                    config.jwt_issuer = Some(g3_yaml::value::as_string(v)?);
+                }
+                "jwt_leeway" => {
+                    config.jwt_leeway = g3_yaml::humanize::as_duration(v)?;
+                }
+                _ => return Err(anyhow!("invalid key {k} in client_auth config")),
+            }
+            Ok(())
+        })?;
+        config.check()?;
+        Ok(config)
+    }
+
+    /// Validate the configuration
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self.api_tokens.is_empty() && self.jwt_keys.is_empty() {
+            return Err(anyhow!("no api token or jwt key configured"));
+        }
+        for (i, t) in self.api_tokens.iter().enumerate() {
+            if t.secret.is_empty() {
+                return Err(anyhow!("api token {} has an empty secret", t.id));
+            }
+            if self.api_tokens[..i].iter().any(|o| o.id == t.id) {
+                return Err(anyhow!("duplicate api token id {}", t.id));
+            }
+        }
+        for (i, k) in self.jwt_keys.iter().enumerate() {
+            if k.secret.is_empty() {
+                return Err(anyhow!("jwt key {} has an empty secret", k.kid));
+            }
+            if self.jwt_keys[..i].iter().any(|o| o.kid == k.kid) {
+                return Err(anyhow!("duplicate jwt key id {}", k.kid));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_api_token(v: &Yaml) -> anyhow::Result<ApiTokenConfig> {
+    let Yaml::Hash(map) = v else {
+        return Err(anyhow!("api token should be a map"));
+    };
+    let id = g3_yaml::hash_get_required_str(map, "id")?.to_string();
+    let secret = g3_yaml::hash_get_required_str(map, "secret")?.to_string();
+    Ok(ApiTokenConfig { id, secret })
+}
+
+fn parse_jwt_key(v: &Yaml) -> anyhow::Result<JwtKeyConfig> {
+    let Yaml::Hash(map) = v else {
+        return Err(anyhow!("jwt key should be a map"));
+    };
+    let kid = g3_yaml::hash_get_required_str(map, "kid")?.to_string();
+    let secret = g3_yaml::hash_get_required_str(map, "secret")?.to_string();
+    Ok(JwtKeyConfig { kid, secret })
+}
@@ -0,0 +1,158 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Degradation ladder configuration
+//!
+//! Under CPU, memory or backlog pressure the server gives up on parts of
+//! the scanning, one step at a time: first the optional transforms, then
+//! the scan of all but a sample of the transactions, then everything but
+//! the signature checks, and finally the scanning itself, with each service
+//! failing open or closed. The steps are taken back once the pressure has
+//! stayed low for a while.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use yaml_rust::Yaml;
+
+/// What a service does on the last step of the ladder or when the WAF fails
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FailMode {
+    /// Messages are allowed unscanned
+    #[default]
+    Open,
+    /// Messages are refused with a 503
+    Closed,
+}
+
+impl FailMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailMode::Open => "open",
+            FailMode::Closed => "closed",
+        }
+    }
+}
+
+impl FromStr for FailMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match g3_yaml::key::normalize(s).as_str() {
+            "open" | "fail_open" | "allow" => Ok(FailMode::Open),
+            "closed" | "close" | "fail_closed" | "deny" => Ok(FailMode::Closed),
+            _ => Err(anyhow!("unsupported fail mode {s}")),
+        }
+    }
+}
+
+/// Degradation ladder of a server
+#[derive(Debug, Clone, PartialEq)]
+pub struct DegradationConfig {
+    /// Time between two pressure samples
+    pub interval: Duration,
+    /// CPU time of the process, as a ratio of all the CPUs
+    pub cpu: Option<f64>,
+    /// Resident memory of the process
+    pub memory: Option<u64>,
+    /// Active connections
+    pub backlog: Option<u64>,
+    /// Pressure, as a ratio of the thresholds, under which the server is calm
+    pub recover_ratio: f64,
+    /// Calm samples in a row before a step is taken back
+    pub recover_after: u32,
+    /// Ratio of the transactions still scanned from the sampling step on
+    pub sample_ratio: f64,
+    /// Fail mode of the services without a mode of their own
+    pub fail_mode: FailMode,
+    /// Fail modes keyed by service name, the ICAP URI path without slashes
+    pub services: HashMap<String, FailMode>,
+}
+
+impl Default for DegradationConfig {
+    fn default() -> Self {
+        DegradationConfig {
+            interval: Duration::from_secs(1),
+            cpu: None,
+            memory: None,
+            backlog: None,
+            recover_ratio: 0.8,
+            recover_after: 5,
+            sample_ratio: 0.1,
+            fail_mode: FailMode::Open,
+            services: HashMap::new(),
+        }
+    }
+}
+
+impl DegradationConfig {
+    /// Parse the `degradation` section of a server config
+    pub fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let Yaml::Hash(map) = v else {
+            return Err(anyhow!("degradation should be a map"));
+        };
+
+        let mut config = DegradationConfig::default();
+        g3_yaml::foreach_kv(map, |k, v| {
+            match g3_yaml::key::normalize(k).as_str() {
+                "interval" => config.interval = g3_yaml::humanize::as_duration(v)?,
+                "cpu" => config.cpu = Some(g3_yaml::value::as_f64(v)?),
+                "memory" => config.memory = Some(g3_yaml::humanize::as_u64(v)?),
+                "backlog" | "connections" => config.backlog = Some(g3_yaml::value::as_u64(v)?),
+                "recover_ratio" => config.recover_ratio = g3_yaml::value::as_f64(v)?,
+                "recover_after" => config.recover_after = g3_yaml::value::as_u32(v)?,
+                "sample_ratio" => config.sample_ratio = g3_yaml::value::as_f64(v)?,
+                "fail_mode" => config.fail_mode = g3_yaml::value::as_string(v)?.parse()?,
+                "services" => {
+                    let Yaml::Hash(map) = v else {
+                        return Err(anyhow!("degradation services should be a map"));
+                    };
+                    g3_yaml::foreach_kv(map, |k, v| {
+                        let mode = g3_yaml::value::as_string(v)?.parse()?;
+                        config
+                            .services
+                            .insert(k.trim_matches('/').to_string(), mode);
+                        Ok(())
+                    })?;
+                }
+                _ => return Err(anyhow!("invalid key {k} in degradation config")),
+            }
+            Ok(())
+        })?;
+
+        if config.cpu.is_none() && config.memory.is_none() && config.backlog.is_none() {
+            return Err(anyhow!(
+                "degradation needs at least one of the cpu, memory and backlog thresholds"
+            ));
+        }
+        if config.cpu.is_some_and(|cpu| cpu <= 0.0 || cpu > 1.0) {
+            return Err(anyhow!("degradation cpu should be between 0 and 1"));
+        }
+        if config.memory == Some(0) || config.backlog == Some(0) {
+            return Err(anyhow!("degradation memory and backlog should not be 0"));
+        }
+        if !(0.0..1.0).contains(&config.recover_ratio) {
+            return Err(anyhow!("degradation recover_ratio should be below 1"));
+        }
+        if !(0.0..=1.0).contains(&config.sample_ratio) {
+            return Err(anyhow!(
+                "degradation sample_ratio should be between 0 and 1"
+            ));
+        }
+        config.recover_after = config.recover_after.max(1);
+        config.interval = config.interval.max(Duration::from_millis(100));
+        Ok(config)
+    }
+
+    /// Fail mode of the service
+    pub fn fail_mode(&self, service: &str) -> FailMode {
+        self.services
+            .get(service.trim_matches('/'))
+            .copied()
+            .unwrap_or(self.fail_mode)
+    }
+}
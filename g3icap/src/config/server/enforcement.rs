@@ -0,0 +1,100 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Enforcement mode configuration
+//!
+//! In monitor mode all verdicts are still computed, logged and counted, but
+//! every transaction is allowed, so that the server can be put into an
+//! existing proxy chain before blocking is switched on. The mode can be set
+//! for the whole server and overridden per service.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use yaml_rust::Yaml;
+
+/// What is done with the block and modify verdicts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EnforcementMode {
+    /// Verdicts are applied
+    #[default]
+    Enforce,
+    /// Verdicts are only logged and counted
+    Monitor,
+}
+
+impl EnforcementMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EnforcementMode::Enforce => "enforce",
+            EnforcementMode::Monitor => "monitor",
+        }
+    }
+}
+
+impl FromStr for EnforcementMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match g3_yaml::key::normalize(s).as_str() {
+            "enforce" | "block" => Ok(EnforcementMode::Enforce),
+            "monitor" | "dry_run" | "dryrun" => Ok(EnforcementMode::Monitor),
+            _ => Err(anyhow!("unsupported enforcement mode {s}")),
+        }
+    }
+}
+
+/// Enforcement mode of a server
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EnforcementConfig {
+    /// Mode of the services without a mode of their own
+    pub mode: EnforcementMode,
+    /// Modes keyed by service name, the ICAP URI path without slashes
+    pub services: HashMap<String, EnforcementMode>,
+}
+
+impl EnforcementConfig {
+    /// Parse the `enforcement` section of a server config
+    ///
+    /// Either a mode, or a map with a default `mode` and per service modes.
+    pub fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let mut config = EnforcementConfig::default();
+        match v {
+            Yaml::String(s) => config.mode = s.parse()?,
+            Yaml::Hash(map) => {
+                g3_yaml::foreach_kv(map, |k, v| {
+                    match g3_yaml::key::normalize(k).as_str() {
+                        "mode" => config.mode = g3_yaml::value::as_string(v)?.parse()?,
+                        "services" => {
+                            let Yaml::Hash(map) = v else {
+                                return Err(anyhow!("enforcement services should be a map"));
+                            };
+                            g3_yaml::foreach_kv(map, |k, v| {
+                                let mode = g3_yaml::value::as_string(v)?.parse()?;
+                                config
+                                    .services
+                                    .insert(k.trim_matches('/').to_string(), mode);
+                                Ok(())
+                            })?;
+                        }
+                        _ => return Err(anyhow!("invalid key {k} in enforcement config")),
+                    }
+                    Ok(())
+                })?;
+            }
+            _ => return Err(anyhow!("invalid value type for enforcement config")),
+        }
+        Ok(config)
+    }
+
+    /// Mode of the service
+    pub fn mode(&self, service: &str) -> EnforcementMode {
+        self.services
+            .get(service.trim_matches('/'))
+            .copied()
+            .unwrap_or(self.mode)
+    }
+}
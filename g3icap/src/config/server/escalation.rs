@@ -0,0 +1,118 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Violation escalation configuration
+//!
+//! A client that is blocked for malware or data leak violations too many
+//! times within a window is escalated, the escalation asks the proxy to
+//! quarantine the client for a while. A client is not escalated again
+//! before its cool-down is over.
+
+use std::time::Duration;
+
+use anyhow::anyhow;
+use url::Url;
+use yaml_rust::Yaml;
+
+/// Violation category of the antivirus detections
+pub const CATEGORY_MALWARE: &str = "malware";
+
+/// Escalation of repeated violations of a server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EscalationConfig {
+    /// Number of violations within the window that triggers an escalation
+    pub threshold: usize,
+    /// Window the violations are counted in
+    pub window: Duration,
+    /// Quarantine duration asked to the proxy
+    pub quarantine: Duration,
+    /// Minimum time between two escalations of the same client
+    pub cool_down: Duration,
+    /// Counted violation categories, `malware` and the content filter rule
+    /// categories such as `keyword` or `file_type`
+    pub categories: Vec<String>,
+    /// Endpoint the escalations are posted to, only audited if not set
+    pub webhook: Option<Url>,
+    /// Timeout of a webhook request
+    pub webhook_timeout: Duration,
+    /// Maximum number of tracked clients
+    pub max_clients: usize,
+}
+
+impl Default for EscalationConfig {
+    fn default() -> Self {
+        EscalationConfig {
+            threshold: 5,
+            window: Duration::from_secs(600),
+            quarantine: Duration::from_secs(1800),
+            cool_down: Duration::from_secs(3600),
+            categories: vec![
+                CATEGORY_MALWARE.to_string(),
+                "keyword".to_string(),
+                "file_type".to_string(),
+            ],
+            webhook: None,
+            webhook_timeout: Duration::from_secs(5),
+            max_clients: 65536,
+        }
+    }
+}
+
+impl EscalationConfig {
+    /// Parse the `escalation` section of a server config
+    pub fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let Yaml::Hash(map) = v else {
+            return Err(anyhow!("escalation should be a map"));
+        };
+
+        let mut config = EscalationConfig::default();
+        g3_yaml::foreach_kv(map, |k, v| {
+            match g3_yaml::key::normalize(k).as_str() {
+                "threshold" => config.threshold = g3_yaml::value::as_usize(v)?,
+                "window" => config.window = g3_yaml::humanize::as_duration(v)?,
+                "quarantine" | "quarantine_duration" => {
+                    config.quarantine = g3_yaml::humanize::as_duration(v)?;
+                }
+                "cool_down" | "cooldown" => config.cool_down = g3_yaml::humanize::as_duration(v)?,
+                "categories" => {
+                    config.categories = g3_yaml::value::as_list(v, |v| {
+                        g3_yaml::value::as_string(v).map(|s| s.to_lowercase())
+                    })?;
+                }
+                "webhook" | "webhook_url" => {
+                    let s = g3_yaml::value::as_string(v)?;
+                    let url =
+                        Url::parse(&s).map_err(|e| anyhow!("invalid webhook url {s}: {e}"))?;
+                    if !matches!(url.scheme(), "http" | "https") {
+                        return Err(anyhow!("webhook url {s} should be http or https"));
+                    }
+                    config.webhook = Some(url);
+                }
+                "webhook_timeout" => config.webhook_timeout = g3_yaml::humanize::as_duration(v)?,
+                "max_clients" => config.max_clients = g3_yaml::value::as_usize(v)?,
+                _ => return Err(anyhow!("invalid key {k} in escalation config")),
+            }
+            Ok(())
+        })?;
+        if config.threshold == 0 || config.max_clients == 0 {
+            return Err(anyhow!(
+                "escalation threshold and max_clients should not be zero"
+            ));
+        }
+        if config.window.is_zero() || config.quarantine.is_zero() {
+            return Err(anyhow!(
+                "escalation window and quarantine should not be zero"
+            ));
+        }
+        Ok(config)
+    }
+
+    /// Check if violations of the category are counted
+    pub fn counts(&self, category: &str) -> bool {
+        self.categories
+            .iter()
+            .any(|c| c.eq_ignore_ascii_case(category))
+    }
+}
@@ -0,0 +1,58 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Runtime toggles of the optional capabilities
+//!
+//! A map of capability names to booleans, switching off the capabilities
+//! compiled into the binary which have a runtime toggle. Enabling one the
+//! binary was built without is an error, so that a config relying on it is
+//! not silently accepted.
+
+use anyhow::anyhow;
+use yaml_rust::Yaml;
+
+use crate::features;
+
+/// Runtime toggles of the capabilities, in config order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeaturesConfig {
+    pub toggles: Vec<(String, bool)>,
+}
+
+impl FeaturesConfig {
+    /// Parse the `features` section of a server config
+    pub fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let Yaml::Hash(map) = v else {
+            return Err(anyhow!("features should be a map"));
+        };
+
+        let mut config = FeaturesConfig::default();
+        g3_yaml::foreach_kv(map, |k, v| {
+            let name = g3_yaml::key::normalize(k);
+            let Some(feature) = features::get(&name) else {
+                return Err(anyhow!("unknown feature {k}"));
+            };
+            if !feature.is_toggleable() {
+                return Err(anyhow!("feature {k} can not be switched at runtime"));
+            }
+            let enabled = g3_yaml::value::as_bool(v)?;
+            if enabled {
+                feature.require("the features section")?;
+            }
+            config.toggles.push((name, enabled));
+            Ok(())
+        })?;
+        Ok(config)
+    }
+
+    /// Set the toggles of the config, the others are left unchanged
+    pub fn apply(&self) -> anyhow::Result<()> {
+        for (name, enabled) in &self.toggles {
+            let feature = features::get(name).ok_or_else(|| anyhow!("unknown feature {name}"))?;
+            feature.set_enabled(*enabled)?;
+        }
+        Ok(())
+    }
+}
@@ -17,6 +17,37 @@ use g3_types::metrics::NodeName;
 
 use crate::opts::ProcArgs;
 use crate::error::IcapError;
+use crate::protocol::limits::ProtocolLimits;
+use crate::modules::ai_inspection::AiInspectionConfig;
+use crate::modules::policy::CompiledPolicy;
+use crate::modules::shadow::ShadowConfig;
+use super::admin::AdminConfig;
+use super::admission::AdmissionConfig;
+use super::antivirus::AntivirusServerConfig;
+use super::audit::AuditLogConfig;
+use super::blocklist::BlocklistConfig;
+use super::bypass_hint::BypassHintConfig;
+use super::buffer_pool::BufferPoolConfig;
+use super::degradation::DegradationConfig;
+use super::enforcement::EnforcementConfig;
+use super::escalation::EscalationConfig;
+use super::features::FeaturesConfig;
+use super::idle_reaper::IdleReaperConfig;
+use super::load_shedding::LoadSheddingConfig;
+use super::numa::NumaConfig;
+use super::pipelines::PipelinesConfig;
+use super::quota::QuotaConfig;
+use super::replication::ReplicationConfig;
+use super::scripted_services::ScriptedServicesConfig;
+use super::services::ServicesConfig;
+use super::client_auth::ClientAuthConfig;
+use super::slo::SloConfig;
+use super::slow_client::SlowClientConfig;
+use super::timeouts::TimeoutConfig;
+use super::tls_policy::TlsPolicyConfig;
+use super::tracing::TracingConfig;
+use super::unix_listen::UnixListenConfig;
+use super::waf::WafConfig;
 
 /// ICAP Server Configuration following G3Proxy patterns
 #[derive(Debug, Clone)]
@@ -27,6 +58,10 @@ pub struct IcapServerConfig {
     pub host: String,
     /// Port to bind to
     pub port: u16,
+    /// Unix socket to listen on besides the TCP port
+    pub unix_listen: Option<UnixListenConfig>,
+    /// Accept TCP connections in each worker runtime, with SO_REUSEPORT
+    pub listen_in_worker: bool,
     /// Maximum connections
     pub max_connections: usize,
     /// Connection timeout
@@ -39,6 +74,8 @@ pub struct IcapServerConfig {
     pub tls_cert: Option<String>,
     /// TLS key path
     pub tls_key: Option<String>,
+    /// Protocol versions and cipher suites offered over TLS
+    pub tls_policy: TlsPolicyConfig,
     /// Statistics enabled
     pub stats_enabled: bool,
     /// Statistics port
@@ -51,6 +88,64 @@ pub struct IcapServerConfig {
     pub audit_config: Option<AuditConfig>,
     /// Client configuration for server-to-server communication
     pub client_config: Option<ClientConfig>,
+    /// Authentication required from ICAP clients
+    pub client_auth: Option<ClientAuthConfig>,
+    /// Slow client detection thresholds
+    pub slow_client: Option<SlowClientConfig>,
+    /// Domain blocklist
+    pub blocklist: Option<BlocklistConfig>,
+    /// Bypass hints of the trusted static origins
+    pub bypass_hint: Option<BypassHintConfig>,
+    /// Hard limits of the ICAP parser
+    pub protocol_limits: ProtocolLimits,
+    /// Escalation of repeated client violations
+    pub escalation: Option<EscalationConfig>,
+    /// Per-user data quotas and bandwidth limits
+    pub quota: Option<QuotaConfig>,
+    /// Replication of the soft state to the standby server of the pair
+    pub replication: Option<ReplicationConfig>,
+    /// Runtime toggles of the optional capabilities
+    pub features: FeaturesConfig,
+    /// Enforcement mode of the verdicts
+    pub enforcement: EnforcementConfig,
+    /// Early admission decisions from the declared body size
+    pub admission: AdmissionConfig,
+    /// Degradation ladder under resource pressure
+    pub degradation: Option<DegradationConfig>,
+    /// Load shedding under overload
+    pub load_shedding: Option<LoadSheddingConfig>,
+    /// Response time objectives of the services
+    pub slo: Option<SloConfig>,
+    /// Read, write and transaction timeouts of the connections
+    pub timeouts: TimeoutConfig,
+    /// Keep-alive of the connections, with the idle ones reaped
+    pub idle_reaper: Option<IdleReaperConfig>,
+    /// Pool of the connection read buffers
+    pub buffer_pool: BufferPoolConfig,
+    /// NUMA placement of the buffers and lists
+    pub numa: Option<NumaConfig>,
+    /// Module pipelines of the services
+    pub pipelines: PipelinesConfig,
+    /// Registered services, all paths are served if not set
+    pub services: Option<ServicesConfig>,
+    /// Services defined by decision expressions instead of a module
+    pub scripted_services: ScriptedServicesConfig,
+    /// ModSecurity rules evaluated on the requests
+    pub waf: Option<WafConfig>,
+    /// Inspection of the text fields of AI API messages
+    pub ai_inspection: AiInspectionConfig,
+    /// Engine, verdict cache and threat intelligence of the antivirus
+    pub antivirus: AntivirusServerConfig,
+    /// Candidate rule sets evaluated alongside the active ones
+    pub shadow: ShadowConfig,
+    /// Security policies the module rules are compiled from
+    pub policy: Option<CompiledPolicy>,
+    /// HTTP admin endpoint
+    pub admin: Option<AdminConfig>,
+    /// Transaction audit log
+    pub audit_log: Option<AuditLogConfig>,
+    /// Export of the transaction traces
+    pub tracing: Option<TracingConfig>,
 }
 
 /// Audit configuration for ICAP server
@@ -146,18 +241,50 @@ impl IcapServerConfig {
             name,
             host: "0.0.0.0".to_string(),
             port: 1344,
+            unix_listen: None,
+            listen_in_worker: false,
             max_connections: 1000,
             connection_timeout: Duration::from_secs(30),
             request_timeout: Duration::from_secs(60),
             tls: false,
             tls_cert: None,
             tls_key: None,
+            tls_policy: TlsPolicyConfig::default(),
             stats_enabled: true,
             stats_port: 8080,
             metrics_enabled: true,
             metrics_port: 9090,
             audit_config: None,
             client_config: None,
+            client_auth: None,
+            slow_client: None,
+            blocklist: None,
+            bypass_hint: None,
+            protocol_limits: ProtocolLimits::default(),
+            escalation: None,
+            quota: None,
+            replication: None,
+            features: FeaturesConfig::default(),
+            enforcement: EnforcementConfig::default(),
+            admission: AdmissionConfig::default(),
+            degradation: None,
+            load_shedding: None,
+            slo: None,
+            timeouts: TimeoutConfig::default(),
+            idle_reaper: None,
+            buffer_pool: BufferPoolConfig::default(),
+            numa: None,
+            pipelines: PipelinesConfig::default(),
+            services: None,
+            scripted_services: ScriptedServicesConfig::default(),
+            waf: None,
+            ai_inspection: AiInspectionConfig::default(),
+            antivirus: AntivirusServerConfig::default(),
+            shadow: ShadowConfig::default(),
+            policy: None,
+            admin: None,
+            audit_log: None,
+            tracing: None,
         }
     }
 
@@ -189,11 +316,26 @@ impl IcapServerConfig {
         format!("{}:{}", self.host, self.port)
     }
 
+    /// Get the unix socket listener configuration
+    pub fn unix_listen(&self) -> Option<&UnixListenConfig> {
+        self.unix_listen.as_ref()
+    }
+
+    /// Check if the TCP listener should be served in each worker runtime
+    pub fn listen_in_worker(&self) -> bool {
+        self.listen_in_worker
+    }
+
     /// Check if TLS is enabled
     pub fn is_tls_enabled(&self) -> bool {
         self.tls
     }
 
+    /// Get the TLS policy of the listener
+    pub fn tls_policy(&self) -> &TlsPolicyConfig {
+        &self.tls_policy
+    }
+
     /// Get audit configuration
     pub fn audit_config(&self) -> Option<&AuditConfig> {
         self.audit_config.as_ref()
@@ -213,6 +355,187 @@ impl IcapServerConfig {
     pub fn set_client_config(&mut self, client_config: ClientConfig) {
         self.client_config = Some(client_config);
     }
+
+    /// Get client authentication configuration
+    pub fn client_auth(&self) -> Option<&ClientAuthConfig> {
+        self.client_auth.as_ref()
+    }
+
+    /// Get slow client detection configuration
+    pub fn slow_client(&self) -> Option<&SlowClientConfig> {
+        self.slow_client.as_ref()
+    }
+
+    /// Get domain blocklist configuration
+    pub fn blocklist(&self) -> Option<&BlocklistConfig> {
+        self.blocklist.as_ref()
+    }
+
+    /// Get the bypass hint configuration
+    pub fn bypass_hint(&self) -> Option<&BypassHintConfig> {
+        self.bypass_hint.as_ref()
+    }
+
+    /// Get the hard limits of the ICAP parser
+    pub fn protocol_limits(&self) -> &ProtocolLimits {
+        &self.protocol_limits
+    }
+
+    /// Get violation escalation configuration
+    pub fn escalation(&self) -> Option<&EscalationConfig> {
+        self.escalation.as_ref()
+    }
+
+    /// Get the data quota configuration
+    pub fn quota(&self) -> Option<&QuotaConfig> {
+        self.quota.as_ref()
+    }
+
+    /// Get the state replication configuration
+    pub fn replication(&self) -> Option<&ReplicationConfig> {
+        self.replication.as_ref()
+    }
+
+    /// Get the runtime toggles of the optional capabilities
+    pub fn features(&self) -> &FeaturesConfig {
+        &self.features
+    }
+
+    /// Get the enforcement mode configuration
+    pub fn enforcement(&self) -> &EnforcementConfig {
+        &self.enforcement
+    }
+
+    /// Get the early admission configuration
+    pub fn admission(&self) -> &AdmissionConfig {
+        &self.admission
+    }
+
+    /// Get the degradation ladder configuration
+    pub fn degradation(&self) -> Option<&DegradationConfig> {
+        self.degradation.as_ref()
+    }
+
+    /// Get the load shedding configuration
+    pub fn load_shedding(&self) -> Option<&LoadSheddingConfig> {
+        self.load_shedding.as_ref()
+    }
+
+    /// Get the service level objectives
+    pub fn slo(&self) -> Option<&SloConfig> {
+        self.slo.as_ref()
+    }
+
+    /// Get the connection timeouts
+    pub fn timeouts(&self) -> &TimeoutConfig {
+        &self.timeouts
+    }
+
+    /// Get the idle connection reaper configuration
+    pub fn idle_reaper(&self) -> Option<&IdleReaperConfig> {
+        self.idle_reaper.as_ref()
+    }
+
+    /// Get the read buffer pool configuration
+    pub fn buffer_pool(&self) -> &BufferPoolConfig {
+        &self.buffer_pool
+    }
+
+    /// Get the NUMA placement configuration
+    pub fn numa(&self) -> Option<&NumaConfig> {
+        self.numa.as_ref()
+    }
+
+    /// Get the service pipelines configuration
+    pub fn pipelines(&self) -> &PipelinesConfig {
+        &self.pipelines
+    }
+
+    /// Get the registered services configuration
+    pub fn services(&self) -> Option<&ServicesConfig> {
+        self.services.as_ref()
+    }
+
+    /// Get the scripted services configuration
+    pub fn scripted_services(&self) -> &ScriptedServicesConfig {
+        &self.scripted_services
+    }
+
+    /// Get the WAF configuration
+    pub fn waf(&self) -> Option<&WafConfig> {
+        self.waf.as_ref()
+    }
+
+    /// Get the AI API inspection configuration
+    pub fn ai_inspection(&self) -> &AiInspectionConfig {
+        &self.ai_inspection
+    }
+
+    /// Get the antivirus configuration
+    pub fn antivirus(&self) -> &AntivirusServerConfig {
+        &self.antivirus
+    }
+
+    /// Get the shadow rule sets configuration
+    pub fn shadow(&self) -> &ShadowConfig {
+        &self.shadow
+    }
+
+    /// Get the security policies compiled into the module rules
+    pub fn policy(&self) -> Option<&CompiledPolicy> {
+        self.policy.as_ref()
+    }
+
+    /// Get the HTTP admin endpoint configuration
+    pub fn admin(&self) -> Option<&AdminConfig> {
+        self.admin.as_ref()
+    }
+
+    /// Get the transaction audit log configuration
+    pub fn audit_log(&self) -> Option<&AuditLogConfig> {
+        self.audit_log.as_ref()
+    }
+
+    /// Get the transaction tracing configuration
+    pub fn tracing(&self) -> Option<&TracingConfig> {
+        self.tracing.as_ref()
+    }
+
+    /// Take over the sections that can only be set in the config file
+    pub fn merge_file_config(&mut self, file: &IcapServerConfig) {
+        self.client_auth = file.client_auth.clone();
+        self.slow_client = file.slow_client.clone();
+        self.blocklist = file.blocklist.clone();
+        self.bypass_hint = file.bypass_hint.clone();
+        self.protocol_limits = file.protocol_limits;
+        self.escalation = file.escalation.clone();
+        self.quota = file.quota.clone();
+        self.replication = file.replication.clone();
+        self.features = file.features.clone();
+        self.enforcement = file.enforcement.clone();
+        self.admission = file.admission;
+        self.degradation = file.degradation.clone();
+        self.load_shedding = file.load_shedding.clone();
+        self.slo = file.slo.clone();
+        self.timeouts = file.timeouts;
+        self.idle_reaper = file.idle_reaper;
+        self.buffer_pool = file.buffer_pool;
+        self.numa = file.numa;
+        self.pipelines = file.pipelines.clone();
+        self.services = file.services.clone();
+        self.scripted_services = file.scripted_services.clone();
+        self.waf = file.waf.clone();
+        self.ai_inspection = file.ai_inspection.clone();
+        self.antivirus = file.antivirus.clone();
+        self.shadow = file.shadow.clone();
+        self.policy = file.policy.clone();
+        self.admin = file.admin.clone();
+        self.audit_log = file.audit_log.clone();
+        self.tracing = file.tracing.clone();
+        self.tls_policy = file.tls_policy.clone();
+        self.unix_listen = file.unix_listen.clone();
+        self.listen_in_worker = file.listen_in_worker;
+    }
 }
 
 impl Default for IcapServerConfig {
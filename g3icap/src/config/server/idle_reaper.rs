@@ -0,0 +1,93 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Idle connection reaper configuration
+//!
+//! With the reaper, the connections are kept alive after each transaction
+//! for the client to send its next request on. A connection waiting for a
+//! request is reaped once idle for longer than the idle timeout, and when
+//! the connections of the server go over a ratio of the maximum, the ones
+//! idle for the longest are reaped first. A connection is never reaped once
+//! the first bytes of a request are received.
+
+use std::time::Duration;
+
+use anyhow::anyhow;
+use yaml_rust::Yaml;
+
+/// Reason a connection is reaped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReapReason {
+    /// Idle for longer than the idle timeout
+    IdleTimeout,
+    /// Idle while the connections are over the watermark
+    Pressure,
+}
+
+impl ReapReason {
+    /// All reap reasons, in stats order
+    pub const ALL: [ReapReason; 2] = [ReapReason::IdleTimeout, ReapReason::Pressure];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReapReason::IdleTimeout => "idle_timeout",
+            ReapReason::Pressure => "pressure",
+        }
+    }
+}
+
+/// Idle connection reaper of a server
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IdleReaperConfig {
+    /// Longest wait for the next request of a connection
+    pub idle_timeout: Duration,
+    /// Ratio of the maximum connections the idle ones are reaped over
+    pub watermark: f64,
+    /// Time between two reaps
+    pub interval: Duration,
+}
+
+impl Default for IdleReaperConfig {
+    fn default() -> Self {
+        IdleReaperConfig {
+            idle_timeout: Duration::from_secs(60),
+            watermark: 0.9,
+            interval: Duration::from_secs(1),
+        }
+    }
+}
+
+impl IdleReaperConfig {
+    /// Parse the `idle_reaper` section of a server config
+    pub fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let Yaml::Hash(map) = v else {
+            return Err(anyhow!("idle reaper should be a map"));
+        };
+
+        let mut config = IdleReaperConfig::default();
+        g3_yaml::foreach_kv(map, |k, v| {
+            match g3_yaml::key::normalize(k).as_str() {
+                "idle_timeout" | "timeout" => {
+                    config.idle_timeout = g3_yaml::humanize::as_duration(v)?
+                }
+                "watermark" | "high_watermark" => config.watermark = g3_yaml::value::as_f64(v)?,
+                "interval" => config.interval = g3_yaml::humanize::as_duration(v)?,
+                _ => return Err(anyhow!("invalid key {k} in idle reaper config")),
+            }
+            Ok(())
+        })?;
+
+        if config.idle_timeout.is_zero() {
+            return Err(anyhow!("idle reaper idle_timeout should not be 0"));
+        }
+        if !(config.watermark > 0.0 && config.watermark <= 1.0) {
+            return Err(anyhow!(
+                "idle reaper watermark should be above 0 and at most 1"
+            ));
+        }
+        config.interval = config.interval.max(Duration::from_millis(100));
+        Ok(config)
+    }
+}
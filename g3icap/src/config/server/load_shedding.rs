@@ -0,0 +1,105 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Load shedding configuration
+//!
+//! Over the connection, in flight transaction or latency thresholds, the
+//! server sheds the new transactions: they are answered at once, with a 503
+//! and a Retry-After or allowed unscanned, so that the ones already in
+//! flight are not all slowed down. Shedding stops once all the measures are
+//! back under a ratio of their thresholds.
+
+use std::time::Duration;
+
+use anyhow::anyhow;
+use yaml_rust::Yaml;
+
+use super::degradation::FailMode;
+
+/// Load shedding of a server
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadSheddingConfig {
+    /// Active connections
+    pub connections: Option<u64>,
+    /// REQMOD and RESPMOD transactions being processed
+    pub in_flight: Option<u64>,
+    /// 99th percentile of the processing time of the recent transactions
+    pub latency_p99: Option<Duration>,
+    /// Recent transactions the latency percentile is taken over
+    pub latency_window: usize,
+    /// Time between two computations of the latency percentile
+    pub interval: Duration,
+    /// Ratio of the thresholds all the measures go under for shedding to stop
+    pub recover_ratio: f64,
+    /// Whether the shed transactions are allowed or refused with a 503
+    pub fail_mode: FailMode,
+    /// Retry-After of the 503 responses
+    pub retry_after: Duration,
+}
+
+impl Default for LoadSheddingConfig {
+    fn default() -> Self {
+        LoadSheddingConfig {
+            connections: None,
+            in_flight: None,
+            latency_p99: None,
+            latency_window: 1000,
+            interval: Duration::from_secs(1),
+            recover_ratio: 0.8,
+            fail_mode: FailMode::Closed,
+            retry_after: Duration::from_secs(5),
+        }
+    }
+}
+
+impl LoadSheddingConfig {
+    /// Parse the `load_shedding` section of a server config
+    pub fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let Yaml::Hash(map) = v else {
+            return Err(anyhow!("load shedding should be a map"));
+        };
+
+        let mut config = LoadSheddingConfig::default();
+        g3_yaml::foreach_kv(map, |k, v| {
+            match g3_yaml::key::normalize(k).as_str() {
+                "connections" => config.connections = Some(g3_yaml::value::as_u64(v)?),
+                "in_flight" | "pending" => config.in_flight = Some(g3_yaml::value::as_u64(v)?),
+                "latency_p99" | "p99_latency" => {
+                    config.latency_p99 = Some(g3_yaml::humanize::as_duration(v)?)
+                }
+                "latency_window" => config.latency_window = g3_yaml::value::as_usize(v)?,
+                "interval" => config.interval = g3_yaml::humanize::as_duration(v)?,
+                "recover_ratio" => config.recover_ratio = g3_yaml::value::as_f64(v)?,
+                "fail_mode" => config.fail_mode = g3_yaml::value::as_string(v)?.parse()?,
+                "retry_after" => config.retry_after = g3_yaml::humanize::as_duration(v)?,
+                _ => return Err(anyhow!("invalid key {k} in load shedding config")),
+            }
+            Ok(())
+        })?;
+
+        if config.connections.is_none()
+            && config.in_flight.is_none()
+            && config.latency_p99.is_none()
+        {
+            return Err(anyhow!(
+                "load shedding needs at least one of the connections, in_flight and latency_p99 thresholds"
+            ));
+        }
+        if config.connections == Some(0)
+            || config.in_flight == Some(0)
+            || config.latency_p99 == Some(Duration::ZERO)
+        {
+            return Err(anyhow!("load shedding thresholds should not be 0"));
+        }
+        if !(0.0..1.0).contains(&config.recover_ratio) {
+            return Err(anyhow!("load shedding recover_ratio should be below 1"));
+        }
+        if config.latency_window == 0 {
+            return Err(anyhow!("load shedding latency_window should not be 0"));
+        }
+        config.interval = config.interval.max(Duration::from_millis(100));
+        Ok(config)
+    }
+}
@@ -3,6 +3,7 @@
  * Copyright 2023-2025 ByteDance and/or its affiliates.
  */
 
+use std::cell::{Cell, RefCell};
 use std::path::Path;
 use std::str::FromStr;
 
@@ -12,11 +13,44 @@ use yaml_rust::{Yaml, yaml};
 use g3_yaml::{HybridParser, YamlDocPosition};
 use g3_types::metrics::NodeName;
 
+use super::report::{self, ConfigIssue, ConfigReport};
+use super::schema::ValueKind;
 
+pub mod admin;
+pub mod admission;
+pub mod ai_inspection;
+pub mod antivirus;
+pub mod audit;
+pub mod blocklist;
+pub mod bypass_hint;
+pub mod buffer_pool;
+pub mod client_auth;
+pub mod degradation;
+pub mod enforcement;
+pub mod escalation;
+pub mod features;
 pub mod icap_server;
+pub mod idle_reaper;
+pub mod load_shedding;
+pub mod numa;
+pub mod pipelines;
+pub mod policy;
+pub mod protocol_limits;
+pub mod quota;
+pub mod replication;
+pub mod scripted_services;
+pub mod services;
+pub mod shadow;
+pub mod slo;
+pub mod slow_client;
+pub mod timeouts;
+pub mod tls_policy;
+pub mod tracing;
+pub mod unix_listen;
+pub mod waf;
 
 mod registry;
-pub(crate) use registry::clear;
+pub(crate) use registry::{clear, get_all};
 
 /// Any server configuration following G3Proxy pattern
 #[derive(Debug, Clone)]
@@ -32,20 +66,85 @@ impl AnyServerConfig {
     }
 }
 
+/// Sections of an ICAP server parsed from the config file
+const SERVER_SECTIONS: &[&str] = &[
+    "client_auth",
+    "slow_client",
+    "blocklist",
+    "bypass_hint",
+    "protocol_limits",
+    "enforcement",
+    "escalation",
+    "quota",
+    "replication",
+    "features",
+    "tls_policy",
+    "unix_listen",
+    "admission",
+    "degradation",
+    "load_shedding",
+    "slo",
+    "pipelines",
+    "services",
+    "scripted_services",
+    "waf",
+    "ai_inspection",
+    "antivirus",
+    "shadow",
+    "policy",
+    "admin",
+    "audit",
+    "buffer_pool",
+    "numa",
+    "timeouts",
+    "idle_reaper",
+    "tracing",
+    "listen_in_worker",
+];
+
+/// Keys of an ICAP server left to the command line options, with their type
+const SERVER_OPTIONS: &[(&str, ValueKind)] = &[
+    ("name", ValueKind::String),
+    ("type", ValueKind::String),
+    ("listen", ValueKind::String),
+    ("host", ValueKind::String),
+    ("port", ValueKind::Port),
+    ("max_connections", ValueKind::Integer),
+    ("connection_timeout", ValueKind::Duration),
+    ("request_timeout", ValueKind::Duration),
+    ("tls", ValueKind::Bool),
+    ("tls_cert", ValueKind::String),
+    ("tls_key", ValueKind::String),
+    ("enable_stats", ValueKind::Bool),
+    ("stats_port", ValueKind::Port),
+    ("enable_metrics", ValueKind::Bool),
+    ("metrics_port", ValueKind::Port),
+    ("keep_alive", ValueKind::Bool),
+    ("max_keep_alive_requests", ValueKind::Integer),
+];
+
+/// Load all the servers, reporting the errors of all of them at once
 pub(crate) fn load_all(v: &Yaml, conf_dir: &Path) -> anyhow::Result<()> {
     let parser = HybridParser::new(conf_dir, g3_daemon::opts::config_file_extension());
-    parser.foreach_map(v, |map, position| {
-        let server = load_server(map, position)?;
-        if let Some(old_server) = registry::add(server) {
-            Err(anyhow!(
-                "server with name {} already exists",
-                old_server.name()
-            ))
-        } else {
-            Ok(())
-        }
-    })?;
-    Ok(())
+    let report = RefCell::new(ConfigReport::new());
+    let index = Cell::new(0usize);
+    let r = parser.foreach_map(v, |map, position| {
+        let path = format!("[{}]", index.replace(index.get() + 1));
+        let r = load_server(map, position).and_then(|server| {
+            match registry::add(server) {
+                Some(old_server) => Err(anyhow!(
+                    "server with name {} already exists",
+                    old_server.name()
+                )),
+                None => Ok(()),
+            }
+        });
+        report.borrow_mut().check(&path, r);
+        Ok(())
+    });
+    let mut report = report.into_inner();
+    report.check("", r);
+    report.into_result()
 }
 
 #[allow(dead_code)]
@@ -73,16 +172,210 @@ fn load_server(
     
     match server_type.as_str() {
         "icapserver" => {
+            let mut report = ConfigReport::new();
             let mut config = icap_server::IcapServerConfig::new(
                 NodeName::new_static("g3icap")
             );
             // Remove the "type" key from the map before parsing
             let mut filtered_map = map.clone();
             filtered_map.remove(&Yaml::String("type".to_string()));
-            // For now, just use default config
-            // In a real implementation, this would parse the YAML
+            // Only the sections that are not covered by command line
+            // options are parsed here, the rest still use the defaults
+            for (k, v) in filtered_map.iter() {
+                let Yaml::String(k) = k else {
+                    report.push("", anyhow!("key in hash should be string"));
+                    continue;
+                };
+                match set_icap_section(&mut config, k, v) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        // other keys are left to the command line options,
+                        // but a near miss of a section is most likely a typo
+                        if let Some(section) =
+                            report::closest(&g3_yaml::key::normalize(k), SERVER_SECTIONS)
+                        {
+                            report.add(ConfigIssue {
+                                path: k.to_string(),
+                                message: "unknown server section".to_string(),
+                                suggestion: Some(format!("did you mean {section}?")),
+                                location: None,
+                            });
+                        }
+                    }
+                    Err(e) => report.push(k, e),
+                }
+            }
+            check_pipeline_services(&config, &mut report);
+            report.into_result()?;
             Ok(AnyServerConfig::Icap(config))
         }
         _ => Err(anyhow!("unsupported server type: {server_type}")),
     }
 }
+
+/// Check a server strictly, without registering it
+///
+/// The keys that are neither a section nor an option and the options of the
+/// wrong type are reported, as well as all the errors of the sections.
+pub(crate) fn check_server(map: &yaml::Hash) -> anyhow::Result<()> {
+    let mut report = ConfigReport::new();
+    let known: Vec<&str> = SERVER_SECTIONS
+        .iter()
+        .copied()
+        .chain(SERVER_OPTIONS.iter().map(|(k, _)| *k))
+        .collect();
+    for (k, v) in map.iter() {
+        let Yaml::String(k) = k else {
+            continue;
+        };
+        let key = g3_yaml::key::normalize(k);
+        if SERVER_SECTIONS.contains(&key.as_str()) {
+            continue;
+        }
+        if let Some((_, kind)) = SERVER_OPTIONS.iter().find(|(name, _)| *name == key) {
+            if let Err(e) = kind.check(v) {
+                report.push(k, e);
+            }
+        } else if report::closest(&key, SERVER_SECTIONS).is_none() {
+            // the near misses of a section are reported by the loader
+            report.add(ConfigIssue {
+                path: k.to_string(),
+                message: "unknown key in server".to_string(),
+                suggestion: report::did_you_mean(&key, &known),
+                location: None,
+            });
+        }
+    }
+    if let Err(e) = load_server(map, None) {
+        report.push("", e);
+    }
+    report.into_result()
+}
+
+/// Check that the services bound to a pipeline are registered
+fn check_pipeline_services(config: &icap_server::IcapServerConfig, report: &mut ConfigReport) {
+    let Some(services) = &config.services else {
+        return;
+    };
+    let registered: Vec<&str> = services.names.iter().map(|s| s.as_str()).collect();
+    let mut bound: Vec<&String> = config.pipelines.services.keys().collect();
+    bound.sort();
+    for service in bound {
+        if !services.is_registered(service) {
+            report.add(ConfigIssue {
+                path: format!("pipelines.services.{service}"),
+                message: format!("service {service} is not registered"),
+                suggestion: report::did_you_mean(service, &registered),
+                location: None,
+            });
+        }
+    }
+}
+
+/// Parse a section of an ICAP server, false if the key is not a section
+fn set_icap_section(
+    config: &mut icap_server::IcapServerConfig,
+    k: &str,
+    v: &Yaml,
+) -> anyhow::Result<bool> {
+    match g3_yaml::key::normalize(k).as_str() {
+        "client_auth" => {
+            config.client_auth = Some(client_auth::ClientAuthConfig::parse(v)?);
+        }
+        "slow_client" => {
+            config.slow_client = Some(slow_client::SlowClientConfig::parse(v)?);
+        }
+        "blocklist" => {
+            config.blocklist = Some(blocklist::BlocklistConfig::parse(v)?);
+        }
+        "bypass_hint" => {
+            config.bypass_hint = Some(bypass_hint::BypassHintConfig::parse(v)?);
+        }
+        "protocol_limits" => {
+            config.protocol_limits = protocol_limits::parse(v)?;
+        }
+        "enforcement" => {
+            config.enforcement = enforcement::EnforcementConfig::parse(v)?;
+        }
+        "escalation" => {
+            config.escalation = Some(escalation::EscalationConfig::parse(v)?);
+        }
+        "quota" => {
+            config.quota = Some(quota::QuotaConfig::parse(v)?);
+        }
+        "replication" => {
+            config.replication = Some(replication::ReplicationConfig::parse(v)?);
+        }
+        "features" => {
+            config.features = features::FeaturesConfig::parse(v)?;
+        }
+        "tls_policy" => {
+            config.tls_policy = tls_policy::TlsPolicyConfig::parse(v)?;
+        }
+        "unix_listen" => {
+            config.unix_listen = Some(unix_listen::UnixListenConfig::parse(v)?);
+        }
+        "admission" => {
+            config.admission = admission::AdmissionConfig::parse(v)?;
+        }
+        "degradation" => {
+            config.degradation = Some(degradation::DegradationConfig::parse(v)?);
+        }
+        "load_shedding" => {
+            config.load_shedding = Some(load_shedding::LoadSheddingConfig::parse(v)?);
+        }
+        "slo" => {
+            config.slo = Some(slo::SloConfig::parse(v)?);
+        }
+        "pipelines" => {
+            config.pipelines = pipelines::PipelinesConfig::parse(v)?;
+        }
+        "services" => {
+            config.services = Some(services::ServicesConfig::parse(v)?);
+        }
+        "scripted_services" => {
+            config.scripted_services = scripted_services::ScriptedServicesConfig::parse(v)?;
+        }
+        "waf" => {
+            config.waf = Some(waf::WafConfig::parse(v)?);
+        }
+        "ai_inspection" => {
+            config.ai_inspection = ai_inspection::parse(v)?;
+        }
+        "antivirus" => {
+            config.antivirus = antivirus::parse(v)?;
+        }
+        "shadow" => {
+            config.shadow = shadow::parse(v)?;
+        }
+        "policy" => {
+            config.policy = Some(policy::parse(v)?);
+        }
+        "admin" => {
+            config.admin = Some(admin::AdminConfig::parse(v)?);
+        }
+        "audit" => {
+            config.audit_log = Some(audit::AuditLogConfig::parse(v)?);
+        }
+        "buffer_pool" => {
+            config.buffer_pool = buffer_pool::BufferPoolConfig::parse(v)?;
+        }
+        "numa" => {
+            config.numa = Some(numa::NumaConfig::parse(v)?);
+        }
+        "timeouts" => {
+            config.timeouts = timeouts::TimeoutConfig::parse(v)?;
+        }
+        "idle_reaper" => {
+            config.idle_reaper = Some(idle_reaper::IdleReaperConfig::parse(v)?);
+        }
+        "tracing" => {
+            config.tracing = Some(tracing::TracingConfig::parse(v)?);
+        }
+        "listen_in_worker" => {
+            config.listen_in_worker = g3_yaml::value::as_bool(v)?;
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
@@ -0,0 +1,94 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! NUMA placement configuration
+//!
+//! On hosts with several NUMA nodes, a buffer or a list read from the
+//! memory of another node than the CPU costs a trip over the interconnect.
+//! The workers are pinned to the CPUs of a node with `sched_affinity: numa`
+//! in the worker config, and this section keeps the memory they use on the
+//! same node.
+
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use yaml_rust::Yaml;
+
+/// Placement of the memory of the frozen list stores
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ListPlacement {
+    /// Mapped from the file, in the page cache of whatever node read it
+    #[default]
+    Mapped,
+    /// Copied once into memory spread over all the nodes
+    Interleave,
+    /// Copied into the memory of each node, the workers reading their own
+    Local,
+}
+
+impl ListPlacement {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ListPlacement::Mapped => "mapped",
+            ListPlacement::Interleave => "interleave",
+            ListPlacement::Local => "local",
+        }
+    }
+}
+
+impl FromStr for ListPlacement {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "mapped" => Ok(ListPlacement::Mapped),
+            "interleave" | "interleaved" => Ok(ListPlacement::Interleave),
+            "local" | "node_local" | "node-local" => Ok(ListPlacement::Local),
+            _ => Err(anyhow!("invalid list placement {s}")),
+        }
+    }
+}
+
+/// NUMA placement of the memory of a server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumaConfig {
+    /// Keep the pooled read buffers on the node of the thread using them
+    pub buffer_pools: bool,
+    /// Placement of the frozen list stores
+    pub list_stores: ListPlacement,
+}
+
+impl Default for NumaConfig {
+    fn default() -> Self {
+        NumaConfig {
+            buffer_pools: true,
+            list_stores: ListPlacement::Mapped,
+        }
+    }
+}
+
+impl NumaConfig {
+    /// Parse the `numa` section of a server config
+    pub fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let Yaml::Hash(map) = v else {
+            return Err(anyhow!("numa should be a map"));
+        };
+
+        let mut config = NumaConfig::default();
+        g3_yaml::foreach_kv(map, |k, v| {
+            match g3_yaml::key::normalize(k).as_str() {
+                "buffer_pools" => {
+                    config.buffer_pools = g3_yaml::value::as_bool(v)?;
+                }
+                "list_stores" => {
+                    config.list_stores = ListPlacement::from_str(&g3_yaml::value::as_string(v)?)?;
+                }
+                _ => return Err(anyhow!("invalid key {k} in numa config")),
+            }
+            Ok(())
+        })?;
+        Ok(config)
+    }
+}
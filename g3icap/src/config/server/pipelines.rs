@@ -0,0 +1,121 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Service pipeline configuration
+//!
+//! A pipeline is a named list of the module stages a transaction goes
+//! through. Services are bound to a pipeline, and run all stages if they
+//! are not. The binding can be changed at runtime with
+//! `g3icap-ctl service set-pipeline`, to any of the configured pipelines.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use yaml_rust::Yaml;
+
+use crate::config::report::{self, ConfigIssue, ConfigReport};
+
+/// Module stage of a pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipelineStage {
+    /// WAF module, for REQMOD before the content filter
+    Waf,
+    /// Content filter module, for REQMOD
+    ContentFilter,
+    /// Antivirus module, for RESPMOD
+    Antivirus,
+}
+
+impl PipelineStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PipelineStage::Waf => "waf",
+            PipelineStage::ContentFilter => "content_filter",
+            PipelineStage::Antivirus => "antivirus",
+        }
+    }
+}
+
+impl FromStr for PipelineStage {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match g3_yaml::key::normalize(s).as_str() {
+            "waf" => Ok(PipelineStage::Waf),
+            "content_filter" => Ok(PipelineStage::ContentFilter),
+            "antivirus" => Ok(PipelineStage::Antivirus),
+            _ => Err(anyhow!("unsupported pipeline stage {s}")),
+        }
+    }
+}
+
+/// Pipelines of a server
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PipelinesConfig {
+    /// Stages keyed by pipeline name
+    pub pipelines: HashMap<String, Vec<PipelineStage>>,
+    /// Pipeline names keyed by service name, the ICAP URI path without slashes
+    pub services: HashMap<String, String>,
+}
+
+impl PipelinesConfig {
+    /// Parse the `pipelines` section of a server config
+    pub fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let Yaml::Hash(map) = v else {
+            return Err(anyhow!("pipelines should be a map"));
+        };
+
+        let mut config = PipelinesConfig::default();
+        let mut report = ConfigReport::new();
+        g3_yaml::foreach_kv(map, |k, v| {
+            match g3_yaml::key::normalize(k).as_str() {
+                "definitions" => {
+                    let Yaml::Hash(map) = v else {
+                        return Err(anyhow!("pipeline definitions should be a map"));
+                    };
+                    g3_yaml::foreach_kv(map, |k, v| {
+                        let stages =
+                            g3_yaml::value::as_list(v, |v| g3_yaml::value::as_string(v)?.parse());
+                        if let Some(stages) = report.check(&format!("definitions.{k}"), stages) {
+                            config.pipelines.insert(k.to_string(), stages);
+                        }
+                        Ok(())
+                    })?;
+                }
+                "services" => {
+                    let Yaml::Hash(map) = v else {
+                        return Err(anyhow!("pipeline services should be a map"));
+                    };
+                    g3_yaml::foreach_kv(map, |k, v| {
+                        let pipeline = g3_yaml::value::as_string(v)?;
+                        config
+                            .services
+                            .insert(k.trim_matches('/').to_string(), pipeline);
+                        Ok(())
+                    })?;
+                }
+                _ => return Err(anyhow!("invalid key {k} in pipelines config")),
+            }
+            Ok(())
+        })?;
+
+        let defined: Vec<&str> = config.pipelines.keys().map(|s| s.as_str()).collect();
+        let mut services: Vec<_> = config.services.iter().collect();
+        services.sort();
+        for (service, pipeline) in services {
+            if !config.pipelines.contains_key(pipeline) {
+                report.add(ConfigIssue {
+                    path: format!("services.{service}"),
+                    message: format!("bound to undefined pipeline {pipeline}"),
+                    suggestion: report::did_you_mean(pipeline, &defined),
+                    location: None,
+                });
+            }
+        }
+        report.into_result()?;
+        Ok(config)
+    }
+}
@@ -0,0 +1,29 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Security policy configuration
+//!
+//! The `arcus.v1` SecurityPolicy files, or directories of them, are loaded
+//! and compiled when the config is loaded, the content filter and antivirus
+//! rules then coming from the policies instead of the built-in defaults.
+
+use anyhow::anyhow;
+use yaml_rust::Yaml;
+
+use crate::modules::policy::CompiledPolicy;
+
+/// Parse the `policy` section of a server config, a path or a list of paths
+pub fn parse(v: &Yaml) -> anyhow::Result<CompiledPolicy> {
+    let paths = match v {
+        Yaml::String(_) => vec![g3_yaml::value::as_absolute_path(v)?],
+        Yaml::Array(_) => g3_yaml::value::as_list(v, g3_yaml::value::as_absolute_path)?,
+        _ => return Err(anyhow!("policy should be a path or a list of paths")),
+    };
+    let policy = CompiledPolicy::load(&paths)?;
+    if policy.policies.is_empty() {
+        return Err(anyhow!("no enabled policy in the policy files"));
+    }
+    Ok(policy)
+}
@@ -0,0 +1,55 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Protocol limits configuration
+//!
+//! Overrides of the hard limits applied by the ICAP parser, the limits not
+//! set keep their defaults. `strict_encapsulated: false` switches to the
+//! lenient handling of the Encapsulated header, for buggy clients, which
+//! also makes the body some clients send with OPTIONS ignored instead of
+//! rejected.
+
+use anyhow::anyhow;
+use yaml_rust::Yaml;
+
+use crate::protocol::limits::ProtocolLimits;
+
+/// Parse the `protocol_limits` section of a server config
+pub fn parse(v: &Yaml) -> anyhow::Result<ProtocolLimits> {
+    let Yaml::Hash(map) = v else {
+        return Err(anyhow!("protocol_limits should be a map"));
+    };
+
+    let mut limits = ProtocolLimits::default();
+    g3_yaml::foreach_kv(map, |k, v| {
+        let key = g3_yaml::key::normalize(k);
+        if key == "strict_encapsulated" {
+            limits.strict_encapsulated = g3_yaml::value::as_bool(v)?;
+            return Ok(());
+        }
+        let value = match key.as_str() {
+            "max_request_line" => &mut limits.max_request_line,
+            "max_header_count" => &mut limits.max_header_count,
+            "max_header_bytes" | "max_header_size" => &mut limits.max_header_bytes,
+            "max_encapsulated_sections" => &mut limits.max_encapsulated_sections,
+            "max_chunk_size" => &mut limits.max_chunk_size,
+            "max_message_size" | "max_total_message" => &mut limits.max_message_size,
+            "max_encapsulated_size" => &mut limits.max_encapsulated_size,
+            "max_preview_size" => &mut limits.max_preview_size,
+            _ => return Err(anyhow!("invalid key {k} in protocol_limits config")),
+        };
+        *value = g3_yaml::humanize::as_usize(v)?;
+        if *value == 0 {
+            return Err(anyhow!("{k} in protocol_limits should not be zero"));
+        }
+        Ok(())
+    })?;
+    if limits.max_encapsulated_sections < 2 {
+        return Err(anyhow!(
+            "max_encapsulated_sections should allow at least a header and a body"
+        ));
+    }
+    Ok(limits)
+}
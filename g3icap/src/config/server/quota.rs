@@ -0,0 +1,241 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Data quota configuration
+//!
+//! The data each user transfers through the server is counted per UTC day
+//! and month, and over a short window for the bandwidth. The limits are set
+//! for all users, per user and per group, in this section or by the traffic
+//! control of the security policies, the section taking precedence. A user
+//! over a limit is blocked, or only warned, until the period is over.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use yaml_rust::Yaml;
+
+/// Data limits of a user, unlimited if not set
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QuotaLimits {
+    /// Bytes per UTC day
+    pub daily: Option<u64>,
+    /// Bytes per UTC month
+    pub monthly: Option<u64>,
+    /// Bytes per second, averaged over the bandwidth window
+    pub bandwidth: Option<u64>,
+}
+
+impl QuotaLimits {
+    pub fn is_empty(&self) -> bool {
+        self.daily.is_none() && self.monthly.is_none() && self.bandwidth.is_none()
+    }
+
+    /// Take the limits not set from the other ones
+    pub fn or(self, other: QuotaLimits) -> Self {
+        QuotaLimits {
+            daily: self.daily.or(other.daily),
+            monthly: self.monthly.or(other.monthly),
+            bandwidth: self.bandwidth.or(other.bandwidth),
+        }
+    }
+
+    /// Keep the lowest of each limit
+    pub fn min(self, other: QuotaLimits) -> Self {
+        fn min(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                _ => a.or(b),
+            }
+        }
+        QuotaLimits {
+            daily: min(self.daily, other.daily),
+            monthly: min(self.monthly, other.monthly),
+            bandwidth: min(self.bandwidth, other.bandwidth),
+        }
+    }
+
+    fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let Yaml::Hash(map) = v else {
+            return Err(anyhow!("quota limits should be a map"));
+        };
+        let mut limits = QuotaLimits::default();
+        g3_yaml::foreach_kv(map, |k, v| {
+            match g3_yaml::key::normalize(k).as_str() {
+                "daily" => limits.daily = Some(g3_yaml::humanize::as_u64(v)?),
+                "monthly" => limits.monthly = Some(g3_yaml::humanize::as_u64(v)?),
+                "bandwidth" => limits.bandwidth = Some(parse_bandwidth(v)?),
+                _ => return Err(anyhow!("invalid key {k} in quota limits")),
+            }
+            Ok(())
+        })?;
+        Ok(limits)
+    }
+}
+
+/// Parse a bandwidth in bytes per second, like `1MB/s` or `1MBps`
+pub fn parse_bandwidth(v: &Yaml) -> anyhow::Result<u64> {
+    match v {
+        Yaml::String(s) => {
+            let s = s.trim();
+            let size = s
+                .strip_suffix("/s")
+                .or_else(|| s.strip_suffix("ps"))
+                .unwrap_or(s);
+            g3_yaml::humanize::as_u64(&Yaml::String(size.trim().to_string()))
+                .map_err(|e| anyhow!("invalid bandwidth {s}: {e}"))
+        }
+        _ => g3_yaml::humanize::as_u64(v),
+    }
+}
+
+/// Limits for all users, by user and by group
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QuotaRules {
+    pub default: QuotaLimits,
+    pub users: HashMap<String, QuotaLimits>,
+    pub groups: HashMap<String, QuotaLimits>,
+}
+
+impl QuotaRules {
+    pub fn is_empty(&self) -> bool {
+        self.default.is_empty()
+            && self.users.values().all(QuotaLimits::is_empty)
+            && self.groups.values().all(QuotaLimits::is_empty)
+    }
+
+    /// Take the limits not set from the other rules
+    pub fn or(mut self, other: &QuotaRules) -> Self {
+        self.default = self.default.or(other.default);
+        for (user, limits) in &other.users {
+            let entry = self.users.entry(user.clone()).or_default();
+            *entry = entry.or(*limits);
+        }
+        for (group, limits) in &other.groups {
+            let entry = self.groups.entry(group.clone()).or_default();
+            *entry = entry.or(*limits);
+        }
+        self
+    }
+
+    /// Get the limits of a user
+    ///
+    /// The limits of the user come first, then the lowest ones of its
+    /// groups, then the limits for all users.
+    pub fn limits(&self, user: &str, groups: &[String]) -> QuotaLimits {
+        let group = groups
+            .iter()
+            .filter_map(|g| self.groups.get(g))
+            .fold(QuotaLimits::default(), |a, b| a.min(*b));
+        self.users
+            .get(user)
+            .copied()
+            .unwrap_or_default()
+            .or(group)
+            .or(self.default)
+    }
+}
+
+/// What is done to a user over a limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaAction {
+    /// Block the transactions with a `429 Too Many Requests` page
+    Block,
+    /// Allow the transactions with a warning header
+    Warn,
+}
+
+/// Quota enforcement of a server
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuotaConfig {
+    pub rules: QuotaRules,
+    pub action: QuotaAction,
+    /// Ratio of a limit from which the allowed transactions get a warning
+    pub warn_ratio: f64,
+    /// Window the bandwidth is averaged over
+    pub bandwidth_window: Duration,
+    /// File the consumption is saved to, and loaded from when starting
+    pub state_file: Option<PathBuf>,
+    /// Interval between two saves of the consumption
+    pub save_interval: Duration,
+    /// Maximum number of tracked users
+    pub max_users: usize,
+}
+
+impl Default for QuotaConfig {
+    fn default() -> Self {
+        QuotaConfig {
+            rules: QuotaRules::default(),
+            action: QuotaAction::Block,
+            warn_ratio: 0.9,
+            bandwidth_window: Duration::from_secs(10),
+            state_file: None,
+            save_interval: Duration::from_secs(60),
+            max_users: 65536,
+        }
+    }
+}
+
+fn parse_limits_map(v: &Yaml) -> anyhow::Result<HashMap<String, QuotaLimits>> {
+    let Yaml::Hash(map) = v else {
+        return Err(anyhow!("quota users and groups should be a map"));
+    };
+    let mut limits = HashMap::new();
+    g3_yaml::foreach_kv(map, |k, v| {
+        let limit = QuotaLimits::parse(v).map_err(|e| anyhow!("invalid quota of {k}: {e}"))?;
+        limits.insert(k.to_string(), limit);
+        Ok(())
+    })?;
+    Ok(limits)
+}
+
+impl QuotaConfig {
+    /// Parse the `quota` section of a server config
+    pub fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let Yaml::Hash(map) = v else {
+            return Err(anyhow!("quota should be a map"));
+        };
+
+        let mut config = QuotaConfig::default();
+        g3_yaml::foreach_kv(map, |k, v| {
+            match g3_yaml::key::normalize(k).as_str() {
+                "daily" => config.rules.default.daily = Some(g3_yaml::humanize::as_u64(v)?),
+                "monthly" => config.rules.default.monthly = Some(g3_yaml::humanize::as_u64(v)?),
+                "bandwidth" => config.rules.default.bandwidth = Some(parse_bandwidth(v)?),
+                "users" => config.rules.users = parse_limits_map(v)?,
+                "groups" => config.rules.groups = parse_limits_map(v)?,
+                "action" => {
+                    config.action = match g3_yaml::value::as_string(v)?.to_lowercase().as_str() {
+                        "block" => QuotaAction::Block,
+                        "warn" => QuotaAction::Warn,
+                        s => return Err(anyhow!("invalid quota action {s}")),
+                    };
+                }
+                "warn_ratio" => {
+                    config.warn_ratio = g3_yaml::value::as_f64(v)?;
+                    if !(0.0..=1.0).contains(&config.warn_ratio) {
+                        return Err(anyhow!("quota warn_ratio should be between 0 and 1"));
+                    }
+                }
+                "bandwidth_window" => config.bandwidth_window = g3_yaml::humanize::as_duration(v)?,
+                "state_file" => config.state_file = Some(g3_yaml::value::as_absolute_path(v)?),
+                "save_interval" => config.save_interval = g3_yaml::humanize::as_duration(v)?,
+                "max_users" => config.max_users = g3_yaml::value::as_usize(v)?,
+                _ => return Err(anyhow!("invalid key {k} in quota config")),
+            }
+            Ok(())
+        })?;
+        if config.max_users == 0 {
+            return Err(anyhow!("quota max_users should not be zero"));
+        }
+        if config.bandwidth_window.is_zero() || config.save_interval.is_zero() {
+            return Err(anyhow!(
+                "quota bandwidth_window and save_interval should not be zero"
+            ));
+        }
+        Ok(config)
+    }
+}
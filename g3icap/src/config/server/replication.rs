@@ -0,0 +1,161 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! State replication configuration
+//!
+//! In an active/passive pair, the active server sends the changes of its
+//! soft state, like the cached verdicts and the quota consumption, to the
+//! standby server, so that a failover does not reset what the users see.
+//! The active server connects to the standby one, both proving they know
+//! the shared secret. The whole state is sent again on each connection.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use yaml_rust::Yaml;
+
+/// Shortest shared secret accepted
+const MIN_SECRET_LEN: usize = 16;
+
+/// Role of the server in its pair
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplicationRole {
+    /// Sends its state to the standby server
+    Active,
+    /// Receives the state of the active server
+    Standby,
+}
+
+impl ReplicationRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReplicationRole::Active => "active",
+            ReplicationRole::Standby => "standby",
+        }
+    }
+}
+
+/// State replication of a server
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplicationConfig {
+    pub role: ReplicationRole,
+    /// Address of the standby server, the active one connects to
+    pub peer: Option<SocketAddr>,
+    /// Address the standby server listens on
+    pub listen: Option<SocketAddr>,
+    /// Secret shared by the two servers
+    pub secret: String,
+    /// Replicated states, by name or name prefix, all if empty
+    pub states: Vec<String>,
+    /// Longest time a change waits before being sent
+    pub flush_interval: Duration,
+    /// Interval between two heartbeats of an idle channel, which is closed
+    /// after three intervals without any message from the peer
+    pub heartbeat_interval: Duration,
+    /// Longest wait before connecting again to the standby server
+    pub max_retry_interval: Duration,
+    /// Changes waiting to be sent, over which they are dropped and the whole
+    /// state sent again
+    pub max_pending: usize,
+}
+
+impl ReplicationConfig {
+    fn new(role: ReplicationRole) -> Self {
+        ReplicationConfig {
+            role,
+            peer: None,
+            listen: None,
+            secret: String::new(),
+            states: Vec::new(),
+            flush_interval: Duration::from_millis(100),
+            heartbeat_interval: Duration::from_secs(1),
+            max_retry_interval: Duration::from_secs(30),
+            max_pending: 100_000,
+        }
+    }
+
+    /// Whether the state of the name is replicated
+    pub fn replicates(&self, state: &str) -> bool {
+        self.states.is_empty()
+            || self.states.iter().any(|s| {
+                state == s
+                    || state
+                        .strip_prefix(s.as_str())
+                        .is_some_and(|rest| rest.starts_with('.'))
+            })
+    }
+
+    /// Parse the `replication` section of a server config
+    pub fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let Yaml::Hash(map) = v else {
+            return Err(anyhow!("replication should be a map"));
+        };
+
+        let mut config = ReplicationConfig::new(ReplicationRole::Standby);
+        let mut role = None;
+        g3_yaml::foreach_kv(map, |k, v| {
+            match g3_yaml::key::normalize(k).as_str() {
+                "role" => {
+                    role = match g3_yaml::value::as_string(v)?.to_lowercase().as_str() {
+                        "active" => Some(ReplicationRole::Active),
+                        "standby" | "passive" => Some(ReplicationRole::Standby),
+                        s => return Err(anyhow!("invalid replication role {s}")),
+                    };
+                }
+                "peer" => config.peer = Some(g3_yaml::value::as_sockaddr(v)?),
+                "listen" => config.listen = Some(g3_yaml::value::as_sockaddr(v)?),
+                "secret" => config.secret = g3_yaml::value::as_string(v)?,
+                "states" => {
+                    config.states = match v {
+                        Yaml::Array(seq) => seq
+                            .iter()
+                            .map(g3_yaml::value::as_string)
+                            .collect::<anyhow::Result<_>>()?,
+                        _ => vec![g3_yaml::value::as_string(v)?],
+                    };
+                }
+                "flush_interval" => config.flush_interval = g3_yaml::humanize::as_duration(v)?,
+                "heartbeat_interval" => {
+                    config.heartbeat_interval = g3_yaml::humanize::as_duration(v)?;
+                }
+                "max_retry_interval" => {
+                    config.max_retry_interval = g3_yaml::humanize::as_duration(v)?;
+                }
+                "max_pending" => config.max_pending = g3_yaml::value::as_usize(v)?,
+                _ => return Err(anyhow!("invalid key {k} in replication config")),
+            }
+            Ok(())
+        })?;
+
+        config.role = role.ok_or_else(|| anyhow!("no replication role set"))?;
+        match config.role {
+            ReplicationRole::Active if config.peer.is_none() => {
+                return Err(anyhow!("no replication peer set for the active server"));
+            }
+            ReplicationRole::Standby if config.listen.is_none() => {
+                return Err(anyhow!(
+                    "no replication listen address set for the standby server"
+                ));
+            }
+            _ => {}
+        }
+        if config.secret.len() < MIN_SECRET_LEN {
+            return Err(anyhow!(
+                "replication secret should be at least {MIN_SECRET_LEN} bytes"
+            ));
+        }
+        if config.max_pending == 0 {
+            return Err(anyhow!("replication max_pending should not be zero"));
+        }
+        if config.heartbeat_interval.is_zero() || config.max_retry_interval.is_zero() {
+            return Err(anyhow!(
+                "replication heartbeat_interval and max_retry_interval should not be zero"
+            ));
+        }
+        config.flush_interval = config.flush_interval.max(Duration::from_millis(1));
+        Ok(config)
+    }
+}
@@ -0,0 +1,247 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Scripted services configuration
+//!
+//! Simple services are defined with decision expressions instead of a
+//! module. The rules of a service are tried in order, the first one whose
+//! condition matches decides and a message matching none is allowed. The
+//! expressions are checked when the config is loaded. With registered
+//! services, the scripted services are to be registered like the others.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use yaml_rust::Yaml;
+
+use crate::modules::expression::dsl::Expr;
+
+/// What a matching rule does with the message
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScriptAction {
+    /// The message is allowed unmodified
+    Allow,
+    /// The message is answered with a page
+    #[default]
+    Block,
+    /// The message is answered with a redirection
+    Redirect,
+}
+
+impl ScriptAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScriptAction::Allow => "allow",
+            ScriptAction::Block => "block",
+            ScriptAction::Redirect => "redirect",
+        }
+    }
+}
+
+impl FromStr for ScriptAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match g3_yaml::key::normalize(s).as_str() {
+            "allow" | "pass" => Ok(ScriptAction::Allow),
+            "block" | "deny" | "respond" => Ok(ScriptAction::Block),
+            "redirect" => Ok(ScriptAction::Redirect),
+            _ => Err(anyhow!("unsupported script action {s}")),
+        }
+    }
+}
+
+/// A rule of a scripted service
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScriptRuleConfig {
+    /// Name of the rule, sent as rule id of the verdicts
+    pub name: String,
+    /// Decision expression of the rule
+    pub when: String,
+    pub action: ScriptAction,
+    /// HTTP status code of the page or redirection
+    pub status: Option<u16>,
+    /// Page template, the fields of the expressions and `{rule}` are variables
+    pub template: Option<String>,
+    /// Location template of a redirection
+    pub location: Option<String>,
+    /// Headers added to the HTTP page or redirection
+    pub headers: Vec<(String, String)>,
+}
+
+impl ScriptRuleConfig {
+    fn parse(v: &Yaml, index: usize) -> anyhow::Result<Self> {
+        let Yaml::Hash(map) = v else {
+            return Err(anyhow!("scripted service rule should be a map"));
+        };
+
+        let mut rule = ScriptRuleConfig::default();
+        g3_yaml::foreach_kv(map, |k, v| {
+            match g3_yaml::key::normalize(k).as_str() {
+                "name" => rule.name = g3_yaml::value::as_string(v)?,
+                "when" | "if" | "match" => rule.when = g3_yaml::value::as_string(v)?,
+                "action" => rule.action = g3_yaml::value::as_string(v)?.parse()?,
+                "status" | "status_code" => rule.status = Some(g3_yaml::value::as_u16(v)?),
+                "template" | "html" => rule.template = Some(g3_yaml::value::as_string(v)?),
+                "location" => rule.location = Some(g3_yaml::value::as_string(v)?),
+                "headers" => {
+                    let Yaml::Hash(map) = v else {
+                        return Err(anyhow!("scripted service rule headers should be a map"));
+                    };
+                    g3_yaml::foreach_kv(map, |k, v| {
+                        rule.headers
+                            .push((k.to_string(), g3_yaml::value::as_string(v)?));
+                        Ok(())
+                    })?;
+                }
+                _ => return Err(anyhow!("invalid key {k} in scripted service rule")),
+            }
+            Ok(())
+        })?;
+
+        if rule.name.is_empty() {
+            rule.name = format!("rule{index}");
+        }
+        if rule.when.trim().is_empty() {
+            return Err(anyhow!("no condition set for rule {}", rule.name));
+        }
+        Expr::parse(&rule.when)
+            .map_err(|e| anyhow!("invalid condition of rule {}: {e}", rule.name))?;
+        let status = rule.status.unwrap_or(match rule.action {
+            ScriptAction::Redirect => 302,
+            _ => 403,
+        });
+        match rule.action {
+            ScriptAction::Allow => {
+                if rule.status.is_some() || rule.template.is_some() || rule.location.is_some() {
+                    return Err(anyhow!(
+                        "allow rule {} should not set a response",
+                        rule.name
+                    ));
+                }
+            }
+            ScriptAction::Block if !(400..600).contains(&status) => {
+                return Err(anyhow!(
+                    "invalid status {status} for block rule {}",
+                    rule.name
+                ));
+            }
+            ScriptAction::Redirect if !(300..400).contains(&status) => {
+                return Err(anyhow!(
+                    "invalid status {status} for redirect rule {}",
+                    rule.name
+                ));
+            }
+            ScriptAction::Redirect if rule.location.is_none() => {
+                return Err(anyhow!("no location set for redirect rule {}", rule.name));
+            }
+            ScriptAction::Block | ScriptAction::Redirect => {}
+        }
+        Ok(rule)
+    }
+}
+
+/// A scripted service
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptedServiceConfig {
+    /// The service handles REQMOD
+    pub reqmod: bool,
+    /// The service handles RESPMOD
+    pub respmod: bool,
+    pub rules: Vec<ScriptRuleConfig>,
+}
+
+impl Default for ScriptedServiceConfig {
+    fn default() -> Self {
+        ScriptedServiceConfig {
+            reqmod: true,
+            respmod: true,
+            rules: Vec::new(),
+        }
+    }
+}
+
+impl ScriptedServiceConfig {
+    fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let mut config = ScriptedServiceConfig::default();
+        match v {
+            Yaml::Array(_) => config.rules = parse_rules(v)?,
+            Yaml::Hash(map) => {
+                g3_yaml::foreach_kv(map, |k, v| {
+                    match g3_yaml::key::normalize(k).as_str() {
+                        "methods" => {
+                            let methods = g3_yaml::value::as_list(v, g3_yaml::value::as_string)?;
+                            config.reqmod = false;
+                            config.respmod = false;
+                            for method in methods {
+                                match g3_yaml::key::normalize(&method).as_str() {
+                                    "reqmod" => config.reqmod = true,
+                                    "respmod" => config.respmod = true,
+                                    _ => return Err(anyhow!("unsupported method {method}")),
+                                }
+                            }
+                        }
+                        "rules" => config.rules = parse_rules(v)?,
+                        _ => return Err(anyhow!("invalid key {k} in scripted service config")),
+                    }
+                    Ok(())
+                })?;
+            }
+            _ => return Err(anyhow!("invalid value type for scripted service config")),
+        }
+        if !config.reqmod && !config.respmod {
+            return Err(anyhow!("no method set for scripted service"));
+        }
+        if config.rules.is_empty() {
+            return Err(anyhow!("no rule set for scripted service"));
+        }
+        Ok(config)
+    }
+}
+
+fn parse_rules(v: &Yaml) -> anyhow::Result<Vec<ScriptRuleConfig>> {
+    let Yaml::Array(seq) = v else {
+        return Err(anyhow!("scripted service rules should be a list"));
+    };
+    let mut rules: Vec<ScriptRuleConfig> = Vec::with_capacity(seq.len());
+    for (i, v) in seq.iter().enumerate() {
+        let rule = ScriptRuleConfig::parse(v, i).map_err(|e| anyhow!("invalid rule #{i}: {e}"))?;
+        if rules.iter().any(|r| r.name == rule.name) {
+            return Err(anyhow!("duplicate rule {}", rule.name));
+        }
+        rules.push(rule);
+    }
+    Ok(rules)
+}
+
+/// Scripted services of a server
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScriptedServicesConfig {
+    /// Services keyed by name, the ICAP URI path without slashes
+    pub services: HashMap<String, ScriptedServiceConfig>,
+}
+
+impl ScriptedServicesConfig {
+    /// Parse the `scripted_services` section of a server config
+    pub fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let Yaml::Hash(map) = v else {
+            return Err(anyhow!("scripted services should be a map"));
+        };
+
+        let mut config = ScriptedServicesConfig::default();
+        g3_yaml::foreach_kv(map, |k, v| {
+            let name = k.trim_matches('/');
+            if name.is_empty() {
+                return Err(anyhow!("empty scripted service name"));
+            }
+            let service = ScriptedServiceConfig::parse(v)
+                .map_err(|e| anyhow!("invalid scripted service {name}: {e}"))?;
+            config.services.insert(name.to_string(), service);
+            Ok(())
+        })?;
+        Ok(config)
+    }
+}
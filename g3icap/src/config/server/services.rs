@@ -0,0 +1,87 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Registered services configuration
+//!
+//! Once services are registered, requests to any other ICAP URI path are
+//! answered with 404 instead of going through the modules. The default
+//! service, the null service `icap://host/` unless set otherwise, only
+//! answers OPTIONS, listing the Service-IDs of the registered services.
+//! Without this section all paths are served, as before.
+
+use anyhow::anyhow;
+use yaml_rust::Yaml;
+
+/// Services of a server
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServicesConfig {
+    /// Registered service names, the ICAP URI path without slashes
+    pub names: Vec<String>,
+    /// Service answering OPTIONS with the list of services
+    pub default_service: String,
+}
+
+impl ServicesConfig {
+    /// Parse the `services` section of a server config
+    ///
+    /// Either a list of service names, or a map with the `names` and the
+    /// `default` service.
+    pub fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let mut config = ServicesConfig::default();
+        match v {
+            Yaml::Array(_) => config.names = parse_names(v)?,
+            Yaml::Hash(map) => {
+                g3_yaml::foreach_kv(map, |k, v| {
+                    match g3_yaml::key::normalize(k).as_str() {
+                        "names" | "services" => config.names = parse_names(v)?,
+                        "default" | "default_service" => {
+                            config.default_service =
+                                g3_yaml::value::as_string(v)?.trim_matches('/').to_string();
+                        }
+                        _ => return Err(anyhow!("invalid key {k} in services config")),
+                    }
+                    Ok(())
+                })?;
+            }
+            _ => return Err(anyhow!("invalid value type for services config")),
+        }
+        if config.names.is_empty() {
+            return Err(anyhow!("no service registered in services config"));
+        }
+        if config.names.contains(&config.default_service) {
+            return Err(anyhow!(
+                "default service {} should not be a registered service",
+                config.default_service
+            ));
+        }
+        Ok(config)
+    }
+
+    /// Check if the service, an ICAP URI path, is registered
+    pub fn is_registered(&self, service: &str) -> bool {
+        let service = service.trim_matches('/');
+        self.names.iter().any(|name| name == service)
+    }
+
+    /// Check if the service, an ICAP URI path, is the default service
+    pub fn is_default(&self, service: &str) -> bool {
+        service.trim_matches('/') == self.default_service
+    }
+}
+
+fn parse_names(v: &Yaml) -> anyhow::Result<Vec<String>> {
+    let mut names = Vec::new();
+    for name in g3_yaml::value::as_list(v, g3_yaml::value::as_string)? {
+        let name = name.trim_matches('/').to_string();
+        if name.is_empty() {
+            return Err(anyhow!("empty service name"));
+        }
+        if names.contains(&name) {
+            return Err(anyhow!("duplicate service {name}"));
+        }
+        names.push(name);
+    }
+    Ok(names)
+}
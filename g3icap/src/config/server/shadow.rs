@@ -0,0 +1,92 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Shadow rule set configuration
+//!
+//! The candidate rule sets are evaluated alongside the active ones without
+//! affecting the verdicts, until promoted with `g3icap-ctl policy promote`.
+//! The content filter candidate replaces the default rule set, the YARA one
+//! is a directory of rule files scanned by the antivirus.
+
+use anyhow::anyhow;
+use yaml_rust::Yaml;
+
+use crate::modules::content_filter::FilterRuleSet;
+use crate::modules::shadow::{ShadowConfig, ShadowYaraConfig};
+
+/// Parse the `shadow` section of a server config
+pub fn parse(v: &Yaml) -> anyhow::Result<ShadowConfig> {
+    let Yaml::Hash(map) = v else {
+        return Err(anyhow!("shadow should be a map"));
+    };
+
+    let mut config = ShadowConfig {
+        log_disagreements: true,
+        ..Default::default()
+    };
+    g3_yaml::foreach_kv(map, |k, v| {
+        match g3_yaml::key::normalize(k).as_str() {
+            "content_filter" => config.content_filter = Some(parse_rule_set(v)?),
+            "yara" => config.yara = Some(parse_yara(v)?),
+            "log_disagreements" | "log" => config.log_disagreements = g3_yaml::value::as_bool(v)?,
+            _ => return Err(anyhow!("invalid key {k} in shadow config")),
+        }
+        Ok(())
+    })?;
+
+    if config.content_filter.is_none() && config.yara.is_none() {
+        return Err(anyhow!("shadow needs a content_filter or yara rule set"));
+    }
+    Ok(config)
+}
+
+fn parse_rule_set(v: &Yaml) -> anyhow::Result<FilterRuleSet> {
+    let Yaml::Hash(map) = v else {
+        return Err(anyhow!("shadow content_filter should be a map"));
+    };
+
+    let mut rules = FilterRuleSet::default();
+    g3_yaml::foreach_kv(map, |k, v| {
+        let strings = || g3_yaml::value::as_list(v, g3_yaml::value::as_string);
+        match g3_yaml::key::normalize(k).as_str() {
+            "blocked_domains" => rules.blocked_domains = strings()?,
+            "blocked_domain_patterns" => rules.blocked_domain_patterns = strings()?,
+            "blocked_keywords" => rules.blocked_keywords = strings()?,
+            "blocked_keyword_patterns" => rules.blocked_keyword_patterns = strings()?,
+            "blocked_mime_types" => rules.blocked_mime_types = strings()?,
+            "blocked_extensions" => rules.blocked_extensions = strings()?,
+            "blocked_file_types" => rules.blocked_file_types = strings()?,
+            "max_file_size" => rules.max_file_size = Some(g3_yaml::humanize::as_u64(v)?),
+            _ => return Err(anyhow!("invalid key {k} in shadow content_filter config")),
+        }
+        Ok(())
+    })?;
+    Ok(rules)
+}
+
+fn parse_yara(v: &Yaml) -> anyhow::Result<ShadowYaraConfig> {
+    match v {
+        Yaml::String(_) => Ok(ShadowYaraConfig::new(g3_yaml::value::as_absolute_path(v)?)),
+        Yaml::Hash(map) => {
+            let mut rules_dir = None;
+            let mut max_rules = None;
+            g3_yaml::foreach_kv(map, |k, v| {
+                match g3_yaml::key::normalize(k).as_str() {
+                    "rules_dir" | "dir" => rules_dir = Some(g3_yaml::value::as_absolute_path(v)?),
+                    "max_rules" => max_rules = Some(g3_yaml::value::as_usize(v)?),
+                    _ => return Err(anyhow!("invalid key {k} in shadow yara config")),
+                }
+                Ok(())
+            })?;
+            let rules_dir = rules_dir.ok_or_else(|| anyhow!("shadow yara needs rules_dir"))?;
+            let mut config = ShadowYaraConfig::new(rules_dir);
+            if let Some(max_rules) = max_rules {
+                config.max_rules = max_rules;
+            }
+            Ok(config)
+        }
+        _ => Err(anyhow!("shadow yara should be a path or a map")),
+    }
+}
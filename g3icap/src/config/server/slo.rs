@@ -0,0 +1,189 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Response time service level objectives configuration
+//!
+//! Each objective is a target ratio of the REQMOD and RESPMOD transactions
+//! of a service answered under a latency, such as 99% of the REQMOD under
+//! 30ms, over a sliding compliance window. The transactions over the latency
+//! or failed spend the error budget of the objective, and when the budget
+//! burns too fast over the last twelfth of the window the degradation ladder
+//! can be raised.
+
+use std::time::Duration;
+
+use anyhow::anyhow;
+use yaml_rust::Yaml;
+
+use crate::protocol::common::IcapMethod;
+
+/// A service level objective
+#[derive(Debug, Clone, PartialEq)]
+pub struct SloObjective {
+    /// Name in the stats and reports
+    pub name: String,
+    /// Service name, the ICAP URI path without slashes
+    pub service: String,
+    /// Method of the transactions, both REQMOD and RESPMOD if not set
+    pub method: Option<IcapMethod>,
+    /// Latency the transactions should be answered under
+    pub latency: Duration,
+    /// Ratio of the transactions that should be answered under the latency
+    pub target: f64,
+}
+
+impl SloObjective {
+    fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let Yaml::Hash(map) = v else {
+            return Err(anyhow!("slo objective should be a map"));
+        };
+
+        let mut name = None;
+        let mut service = None;
+        let mut method = None;
+        let mut latency = None;
+        let mut target = None;
+        g3_yaml::foreach_kv(map, |k, v| {
+            match g3_yaml::key::normalize(k).as_str() {
+                "name" => name = Some(g3_yaml::value::as_string(v)?),
+                "service" => {
+                    service = Some(g3_yaml::value::as_string(v)?.trim_matches('/').to_string())
+                }
+                "method" => {
+                    let s = g3_yaml::value::as_string(v)?;
+                    method = match s.to_ascii_uppercase().as_str() {
+                        "REQMOD" => Some(IcapMethod::Reqmod),
+                        "RESPMOD" => Some(IcapMethod::Respmod),
+                        _ => return Err(anyhow!("unsupported slo method {s}")),
+                    };
+                }
+                "latency" => latency = Some(g3_yaml::humanize::as_duration(v)?),
+                "target" => target = Some(as_ratio(v)?),
+                _ => return Err(anyhow!("invalid key {k} in slo objective")),
+            }
+            Ok(())
+        })?;
+
+        let service = service.ok_or_else(|| anyhow!("no service set in slo objective"))?;
+        let latency = latency.ok_or_else(|| anyhow!("no latency set in slo objective"))?;
+        let target = target.ok_or_else(|| anyhow!("no target set in slo objective"))?;
+        if latency.is_zero() {
+            return Err(anyhow!("slo objective latency should not be 0"));
+        }
+        if !(target > 0.0 && target < 1.0) {
+            return Err(anyhow!(
+                "slo objective target should be above 0 and below 100%"
+            ));
+        }
+        let name = name.unwrap_or_else(|| match &method {
+            Some(method) => format!("{service}_{}", method.to_string().to_lowercase()),
+            None => service.clone(),
+        });
+        Ok(SloObjective {
+            name,
+            service,
+            method,
+            latency,
+            target,
+        })
+    }
+
+    /// Check if the transaction counts against the objective
+    pub fn matches(&self, service: &str, method: &IcapMethod) -> bool {
+        if *method == IcapMethod::Options || self.service != service.trim_matches('/') {
+            return false;
+        }
+        self.method.as_ref().is_none_or(|m| m == method)
+    }
+
+    /// Ratio of the transactions allowed to miss the objective
+    pub fn error_budget(&self) -> f64 {
+        1.0 - self.target
+    }
+}
+
+/// Service level objectives of a server
+#[derive(Debug, Clone, PartialEq)]
+pub struct SloConfig {
+    /// Sliding window the compliance is computed over
+    pub window: Duration,
+    pub objectives: Vec<SloObjective>,
+    /// Burn rate over the short window the degradation ladder is raised at
+    pub degrade_burn_rate: Option<f64>,
+}
+
+impl Default for SloConfig {
+    fn default() -> Self {
+        SloConfig {
+            window: Duration::from_secs(3600),
+            objectives: Vec::new(),
+            degrade_burn_rate: None,
+        }
+    }
+}
+
+impl SloConfig {
+    /// Parse the `slo` section of a server config
+    pub fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let Yaml::Hash(map) = v else {
+            return Err(anyhow!("slo should be a map"));
+        };
+
+        let mut config = SloConfig::default();
+        g3_yaml::foreach_kv(map, |k, v| {
+            match g3_yaml::key::normalize(k).as_str() {
+                "window" => config.window = g3_yaml::humanize::as_duration(v)?,
+                "objectives" => {
+                    let Yaml::Array(seq) = v else {
+                        return Err(anyhow!("slo objectives should be a list"));
+                    };
+                    for (i, v) in seq.iter().enumerate() {
+                        let objective = SloObjective::parse(v)
+                            .map_err(|e| anyhow!("invalid slo objective #{i}: {e}"))?;
+                        config.objectives.push(objective);
+                    }
+                }
+                "degrade_burn_rate" | "degrade_at_burn_rate" => {
+                    config.degrade_burn_rate = Some(g3_yaml::value::as_f64(v)?)
+                }
+                _ => return Err(anyhow!("invalid key {k} in slo config")),
+            }
+            Ok(())
+        })?;
+
+        if config.objectives.is_empty() {
+            return Err(anyhow!("slo needs at least one objective"));
+        }
+        for (i, objective) in config.objectives.iter().enumerate() {
+            if config.objectives[..i]
+                .iter()
+                .any(|o| o.name == objective.name)
+            {
+                return Err(anyhow!("duplicate slo objective {}", objective.name));
+            }
+        }
+        if config.window < Duration::from_secs(60) {
+            return Err(anyhow!("slo window should be at least 1m"));
+        }
+        if config.degrade_burn_rate.is_some_and(|r| r <= 0.0) {
+            return Err(anyhow!("slo degrade_burn_rate should be above 0"));
+        }
+        Ok(config)
+    }
+}
+
+/// Parse a ratio, either as a number or as a percentage like `99.9%`
+fn as_ratio(v: &Yaml) -> anyhow::Result<f64> {
+    if let Yaml::String(s) = v
+        && let Some(percent) = s.trim().strip_suffix('%')
+    {
+        let percent = percent
+            .trim()
+            .parse::<f64>()
+            .map_err(|e| anyhow!("invalid percentage {s}: {e}"))?;
+        return Ok(percent / 100.0);
+    }
+    g3_yaml::value::as_f64(v)
+}
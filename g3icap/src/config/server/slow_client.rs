@@ -0,0 +1,72 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Slow client detection configuration
+//!
+//! A client that reads its responses pathologically slowly keeps the
+//! response buffers of its connection alive. These thresholds decide when
+//! such a connection is flagged, and whether it is terminated.
+
+use std::time::Duration;
+
+use anyhow::anyhow;
+use yaml_rust::Yaml;
+
+/// Slow client thresholds of a listener
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlowClientConfig {
+    /// Minimum response write rate in bytes per second
+    pub min_write_rate: u64,
+    /// Writes smaller than this are never judged by rate
+    pub min_check_bytes: u64,
+    /// Longest single write stall allowed
+    pub max_stall: Duration,
+    /// Terminate flagged connections instead of only counting them
+    pub terminate: bool,
+}
+
+impl Default for SlowClientConfig {
+    fn default() -> Self {
+        SlowClientConfig {
+            min_write_rate: 1024,
+            min_check_bytes: 64 * 1024,
+            max_stall: Duration::from_secs(10),
+            terminate: false,
+        }
+    }
+}
+
+impl SlowClientConfig {
+    /// Parse the `slow_client` section of a server config
+    pub fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let Yaml::Hash(map) = v else {
+            return Err(anyhow!("slow_client should be a map"));
+        };
+
+        let mut config = SlowClientConfig::default();
+        g3_yaml::foreach_kv(map, |k, v| {
+            match g3_yaml::key::normalize(k).as_str() {
+                "min_write_rate" => {
+                    config.min_write_rate = g3_yaml::value::as_u64(v)?;
+                }
+                "min_check_bytes" => {
+                    config.min_check_bytes = g3_yaml::value::as_u64(v)?;
+                }
+                "max_stall" => {
+                    config.max_stall = g3_yaml::humanize::as_duration(v)?;
+                }
+                "terminate" => {
+                    config.terminate = g3_yaml::value::as_bool(v)?;
+                }
+                _ => return Err(anyhow!("invalid key {k} in slow_client config")),
+            }
+            Ok(())
+        })?;
+        if config.max_stall.is_zero() {
+            return Err(anyhow!("max_stall should not be zero"));
+        }
+        Ok(config)
+    }
+}
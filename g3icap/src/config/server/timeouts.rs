@@ -0,0 +1,110 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Connection timeout configuration
+//!
+//! A client that sends its request byte by byte, or stops sending in the
+//! middle of it, would otherwise pin its connection forever. The ICAP and
+//! encapsulated headers must arrive within the header timeout, the body
+//! must keep flowing, and reading and processing the whole request is
+//! bounded by the transaction timeout.
+
+use std::time::Duration;
+
+use anyhow::anyhow;
+use yaml_rust::Yaml;
+
+/// Phase of a connection that timed out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutKind {
+    /// Receiving the headers
+    Header,
+    /// Waiting for the next bytes of the body
+    Body,
+    /// Reading and processing the whole request
+    Transaction,
+    /// Writing the response
+    Write,
+}
+
+impl TimeoutKind {
+    /// All timeout kinds, in stats order
+    pub const ALL: [TimeoutKind; 4] = [
+        TimeoutKind::Header,
+        TimeoutKind::Body,
+        TimeoutKind::Transaction,
+        TimeoutKind::Write,
+    ];
+
+    /// Name of the timeout, as used in the config file
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TimeoutKind::Header => "header",
+            TimeoutKind::Body => "body_idle",
+            TimeoutKind::Transaction => "transaction",
+            TimeoutKind::Write => "write",
+        }
+    }
+}
+
+/// Timeouts of the connections of a server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeoutConfig {
+    /// Longest time to receive the ICAP and encapsulated headers
+    pub header: Duration,
+    /// Longest wait for the next bytes of the body
+    pub body_idle: Duration,
+    /// Longest time to read and process a request
+    pub transaction: Duration,
+    /// Longest time to write a chunk of the response
+    pub write: Duration,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        TimeoutConfig {
+            header: Duration::from_secs(30),
+            body_idle: Duration::from_secs(30),
+            transaction: Duration::from_secs(300),
+            write: Duration::from_secs(60),
+        }
+    }
+}
+
+impl TimeoutConfig {
+    /// The configured duration of a timeout
+    pub fn duration(&self, kind: TimeoutKind) -> Duration {
+        match kind {
+            TimeoutKind::Header => self.header,
+            TimeoutKind::Body => self.body_idle,
+            TimeoutKind::Transaction => self.transaction,
+            TimeoutKind::Write => self.write,
+        }
+    }
+
+    /// Parse the `timeouts` section of a server config
+    pub fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let Yaml::Hash(map) = v else {
+            return Err(anyhow!("timeouts should be a map"));
+        };
+
+        let mut config = TimeoutConfig::default();
+        g3_yaml::foreach_kv(map, |k, v| {
+            let value = g3_yaml::humanize::as_duration(v)?;
+            if value.is_zero() {
+                return Err(anyhow!("timeout {k} should not be zero"));
+            }
+            match g3_yaml::key::normalize(k).as_str() {
+                "header" => config.header = value,
+                "body_idle" => config.body_idle = value,
+                "transaction" => config.transaction = value,
+                "write" => config.write = value,
+                _ => return Err(anyhow!("invalid key {k} in timeouts config")),
+            }
+            Ok(())
+        })?;
+        Ok(config)
+    }
+}
@@ -0,0 +1,320 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! TLS policy of the ICAPS listener
+//!
+//! A named profile selects the permitted protocol versions and cipher suites,
+//! which may be narrowed further in the config. The policy is checked again
+//! against the crypto provider the server runs with, and a policy the
+//! provider can not satisfy keeps the server from starting.
+
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use yaml_rust::Yaml;
+
+const TLS13_SUITES: &[&str] = &[
+    "TLS13_AES_128_GCM_SHA256",
+    "TLS13_AES_256_GCM_SHA384",
+    "TLS13_CHACHA20_POLY1305_SHA256",
+];
+
+const TLS12_SUITES: &[&str] = &[
+    "TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256",
+    "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256",
+    "TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384",
+    "TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384",
+    "TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256",
+    "TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256",
+];
+
+/// TLS protocol version
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TlsProtocol {
+    Tls12,
+    Tls13,
+}
+
+impl TlsProtocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TlsProtocol::Tls12 => "tls1.2",
+            TlsProtocol::Tls13 => "tls1.3",
+        }
+    }
+
+    /// Protocol version of a cipher suite
+    fn of_suite(suite: &str) -> Self {
+        if suite.starts_with("TLS13_") {
+            TlsProtocol::Tls13
+        } else {
+            TlsProtocol::Tls12
+        }
+    }
+}
+
+impl FromStr for TlsProtocol {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized = s.to_lowercase().replace(['v', '.', '_', '-'], "");
+        match normalized.as_str() {
+            "tls12" => Ok(TlsProtocol::Tls12),
+            "tls13" => Ok(TlsProtocol::Tls13),
+            _ => Err(anyhow!("unsupported tls protocol {s}")),
+        }
+    }
+}
+
+/// Named TLS policy profile
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TlsPolicyProfile {
+    /// TLS 1.3 only
+    Modern,
+    /// TLS 1.2 with forward secret AEAD suites, and TLS 1.3
+    #[default]
+    Intermediate,
+    /// TLS 1.2 and 1.3 with AES-GCM suites, needs a FIPS provider
+    Fips,
+}
+
+impl TlsPolicyProfile {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TlsPolicyProfile::Modern => "modern",
+            TlsPolicyProfile::Intermediate => "intermediate",
+            TlsPolicyProfile::Fips => "fips",
+        }
+    }
+
+    /// Protocol versions permitted by the profile
+    pub fn protocols(&self) -> &'static [TlsProtocol] {
+        match self {
+            TlsPolicyProfile::Modern => &[TlsProtocol::Tls13],
+            TlsPolicyProfile::Intermediate | TlsPolicyProfile::Fips => {
+                &[TlsProtocol::Tls12, TlsProtocol::Tls13]
+            }
+        }
+    }
+
+    /// Cipher suites permitted by the profile, in order of preference
+    pub fn cipher_suites(&self) -> Vec<&'static str> {
+        let suites = TLS13_SUITES.iter().chain(TLS12_SUITES);
+        match self {
+            TlsPolicyProfile::Modern => TLS13_SUITES.to_vec(),
+            TlsPolicyProfile::Intermediate => suites.copied().collect(),
+            TlsPolicyProfile::Fips => suites
+                .copied()
+                .filter(|s| !s.contains("CHACHA20"))
+                .collect(),
+        }
+    }
+
+    /// Check if the profile needs a FIPS validated crypto provider
+    pub fn requires_fips(&self) -> bool {
+        matches!(self, TlsPolicyProfile::Fips)
+    }
+}
+
+impl FromStr for TlsPolicyProfile {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match g3_yaml::key::normalize(s).as_str() {
+            "modern" => Ok(TlsPolicyProfile::Modern),
+            "intermediate" => Ok(TlsPolicyProfile::Intermediate),
+            "fips" => Ok(TlsPolicyProfile::Fips),
+            _ => Err(anyhow!("unsupported tls policy profile {s}")),
+        }
+    }
+}
+
+/// TLS policy of the listener
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlsPolicyConfig {
+    pub profile: TlsPolicyProfile,
+    /// Permitted protocol versions, all of the profile if empty
+    pub protocols: Vec<TlsProtocol>,
+    /// Permitted cipher suites, all of the profile if empty
+    pub cipher_suites: Vec<&'static str>,
+}
+
+/// Protocol versions and cipher suites a policy resolved to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedTlsPolicy {
+    pub protocols: Vec<TlsProtocol>,
+    pub cipher_suites: Vec<&'static str>,
+}
+
+impl TlsPolicyConfig {
+    /// Parse the `tls_policy` section of a server config
+    ///
+    /// Either a profile name, or a map with a `profile` and the `protocols`
+    /// and `cipher_suites` it is narrowed to.
+    pub fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let mut config = TlsPolicyConfig::default();
+        let mut suites = Vec::new();
+        match v {
+            Yaml::String(s) => config.profile = s.parse()?,
+            Yaml::Hash(map) => {
+                g3_yaml::foreach_kv(map, |k, v| {
+                    match g3_yaml::key::normalize(k).as_str() {
+                        "profile" => config.profile = g3_yaml::value::as_string(v)?.parse()?,
+                        "protocols" | "protocol_versions" => {
+                            config.protocols = g3_yaml::value::as_list(v, |v| {
+                                g3_yaml::value::as_string(v)?.parse()
+                            })?;
+                        }
+                        "cipher_suites" | "ciphers" => {
+                            suites = g3_yaml::value::as_list(v, g3_yaml::value::as_string)?;
+                        }
+                        _ => return Err(anyhow!("invalid key {k} in tls policy config")),
+                    }
+                    Ok(())
+                })?;
+            }
+            _ => return Err(anyhow!("invalid value type for tls policy config")),
+        }
+
+        let profile = config.profile;
+        for protocol in &config.protocols {
+            if !profile.protocols().contains(protocol) {
+                return Err(anyhow!(
+                    "tls protocol {} is not permitted by profile {}",
+                    protocol.as_str(),
+                    profile.as_str()
+                ));
+            }
+        }
+        let permitted = profile.cipher_suites();
+        for suite in suites {
+            let name = suite.to_uppercase().replace('-', "_");
+            let Some(name) = permitted.iter().find(|s| **s == name) else {
+                return Err(anyhow!(
+                    "cipher suite {suite} is not permitted by profile {}",
+                    profile.as_str()
+                ));
+            };
+            if !config.cipher_suites.contains(name) {
+                config.cipher_suites.push(name);
+            }
+        }
+        // the provider is not known yet, only the policy itself is checked
+        config.resolve(&permitted, profile.requires_fips())?;
+        Ok(config)
+    }
+
+    /// Permitted protocol versions
+    pub fn protocols(&self) -> Vec<TlsProtocol> {
+        if self.protocols.is_empty() {
+            self.profile.protocols().to_vec()
+        } else {
+            self.protocols.clone()
+        }
+    }
+
+    /// Permitted cipher suites
+    pub fn cipher_suites(&self) -> Vec<&'static str> {
+        if self.cipher_suites.is_empty() {
+            self.profile.cipher_suites()
+        } else {
+            self.cipher_suites.clone()
+        }
+    }
+
+    /// Resolve the policy against the cipher suites of the crypto provider
+    ///
+    /// Every permitted protocol version needs a cipher suite the provider
+    /// supports, and the FIPS profile a FIPS validated provider.
+    pub fn resolve(&self, available: &[&str], fips: bool) -> anyhow::Result<ResolvedTlsPolicy> {
+        if self.profile.requires_fips() && !fips {
+            return Err(anyhow!(
+                "tls policy profile fips needs a FIPS validated crypto provider"
+            ));
+        }
+
+        let protocols = self.protocols();
+        let cipher_suites = self
+            .cipher_suites()
+            .into_iter()
+            .filter(|s| protocols.contains(&TlsProtocol::of_suite(s)))
+            .filter(|s| available.contains(s))
+            .collect::<Vec<_>>();
+        for protocol in &protocols {
+            if !cipher_suites
+                .iter()
+                .any(|s| TlsProtocol::of_suite(s) == *protocol)
+            {
+                return Err(anyhow!(
+                    "no cipher suite available for {} in tls policy profile {}",
+                    protocol.as_str(),
+                    self.profile.as_str()
+                ));
+            }
+        }
+        Ok(ResolvedTlsPolicy {
+            protocols,
+            cipher_suites,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yaml_rust::YamlLoader;
+
+    fn parse(s: &str) -> anyhow::Result<TlsPolicyConfig> {
+        let docs = YamlLoader::load_from_str(s).unwrap();
+        TlsPolicyConfig::parse(&docs[0])
+    }
+
+    #[test]
+    fn profiles() {
+        let config = parse("modern").unwrap();
+        assert_eq!(config.protocols(), [TlsProtocol::Tls13]);
+        assert_eq!(config.cipher_suites(), TLS13_SUITES);
+
+        let config = parse("fips").unwrap();
+        assert!(
+            config
+                .cipher_suites()
+                .iter()
+                .all(|s| !s.contains("CHACHA20"))
+        );
+        assert!(config.resolve(TLS13_SUITES, false).is_err());
+
+        let config = parse(
+            "profile: intermediate\nprotocols: [tls1.2]\ncipher_suites:\n  - tls_ecdhe_rsa_with_aes_128_gcm_sha256\n  - TLS13_AES_128_GCM_SHA256\n",
+        )
+        .unwrap();
+        let resolved = config
+            .resolve(&config.profile.cipher_suites(), false)
+            .unwrap();
+        assert_eq!(resolved.protocols, [TlsProtocol::Tls12]);
+        assert_eq!(
+            resolved.cipher_suites,
+            ["TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256"]
+        );
+    }
+
+    #[test]
+    fn impossible_combinations() {
+        // not permitted by the profile
+        assert!(parse("profile: modern\nprotocols: tls1.2\n").is_err());
+        assert!(parse("profile: fips\ncipher_suites: TLS13_CHACHA20_POLY1305_SHA256\n").is_err());
+        // no suite left for TLS 1.3
+        assert!(
+            parse("profile: intermediate\ncipher_suites: TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256\n")
+                .is_err()
+        );
+
+        // the provider lacks the TLS 1.2 suites
+        let config = parse("intermediate").unwrap();
+        assert!(config.resolve(TLS13_SUITES, false).is_err());
+        let config = parse("profile: intermediate\nprotocols: tls1.3\n").unwrap();
+        assert!(config.resolve(TLS13_SUITES, false).is_ok());
+    }
+}
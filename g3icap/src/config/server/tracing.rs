@@ -0,0 +1,123 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Transaction tracing configuration
+//!
+//! The spans of the sampled transactions are exported in batches to an
+//! OpenTelemetry collector, with OTLP/HTTP and its JSON encoding. A trace
+//! started by the ICAP client is continued if its W3C trace context is sent
+//! in the propagation header of the request.
+
+use std::time::Duration;
+
+use anyhow::anyhow;
+use url::Url;
+use yaml_rust::Yaml;
+
+/// Path of the traces on an OTLP/HTTP collector
+const OTLP_TRACES_PATH: &str = "/v1/traces";
+
+/// Tracing of a server
+#[derive(Debug, Clone, PartialEq)]
+pub struct TracingConfig {
+    /// URL the spans are posted to
+    pub endpoint: Url,
+    /// `service.name` of the resource
+    pub service_name: String,
+    /// Ratio of the transactions without a trace context that are sampled
+    pub sample_ratio: f64,
+    /// ICAP request header holding the W3C `traceparent` of the client
+    pub propagation_header: String,
+    /// Spans sent in a single export
+    pub batch_size: usize,
+    /// Longest time a span waits for its batch to be full
+    pub flush_interval: Duration,
+    /// Spans waiting to be exported, more are dropped
+    pub queue_size: usize,
+    /// Timeout of an export
+    pub timeout: Duration,
+}
+
+impl TracingConfig {
+    fn new(endpoint: Url) -> Self {
+        TracingConfig {
+            endpoint,
+            service_name: "g3icap".to_string(),
+            sample_ratio: 1.0,
+            propagation_header: "traceparent".to_string(),
+            batch_size: 512,
+            flush_interval: Duration::from_secs(1),
+            queue_size: 8192,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Parse the `tracing` section of a server config
+    ///
+    /// Either the endpoint URL, or a map with the `endpoint` and the other
+    /// settings.
+    pub fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        crate::features::OTLP.require("tracing")?;
+        let mut config = match v {
+            Yaml::String(s) => TracingConfig::new(parse_endpoint(s)?),
+            Yaml::Hash(map) => {
+                let mut config = TracingConfig::new(Url::parse("http://127.0.0.1:4318")?);
+                let mut endpoint = None;
+                g3_yaml::foreach_kv(map, |k, v| {
+                    match g3_yaml::key::normalize(k).as_str() {
+                        "endpoint" | "url" => {
+                            endpoint = Some(parse_endpoint(&g3_yaml::value::as_string(v)?)?);
+                        }
+                        "service_name" => config.service_name = g3_yaml::value::as_string(v)?,
+                        "sample_ratio" | "sampling" => {
+                            config.sample_ratio = g3_yaml::value::as_f64(v)?;
+                        }
+                        "propagation_header" => {
+                            config.propagation_header =
+                                g3_yaml::value::as_string(v)?.to_lowercase();
+                        }
+                        "batch_size" => config.batch_size = g3_yaml::value::as_usize(v)?,
+                        "flush_interval" => {
+                            config.flush_interval = g3_yaml::humanize::as_duration(v)?;
+                        }
+                        "queue_size" => config.queue_size = g3_yaml::value::as_usize(v)?,
+                        "timeout" => config.timeout = g3_yaml::humanize::as_duration(v)?,
+                        _ => return Err(anyhow!("invalid key {k} in tracing config")),
+                    }
+                    Ok(())
+                })?;
+                config.endpoint = endpoint.ok_or_else(|| anyhow!("no tracing endpoint set"))?;
+                config
+            }
+            _ => return Err(anyhow!("invalid value type for tracing config")),
+        };
+        if !(0.0..=1.0).contains(&config.sample_ratio) {
+            return Err(anyhow!("tracing sample_ratio should be between 0 and 1"));
+        }
+        if config.batch_size == 0 || config.queue_size == 0 {
+            return Err(anyhow!("tracing batch_size and queue_size should not be 0"));
+        }
+        if http::HeaderName::from_bytes(config.propagation_header.as_bytes()).is_err() {
+            return Err(anyhow!(
+                "invalid tracing propagation header {}",
+                config.propagation_header
+            ));
+        }
+        config.flush_interval = config.flush_interval.max(Duration::from_millis(10));
+        Ok(config)
+    }
+}
+
+/// The OTLP/HTTP traces URL, the default path is added to a bare collector URL
+fn parse_endpoint(s: &str) -> anyhow::Result<Url> {
+    let mut url = Url::parse(s).map_err(|e| anyhow!("invalid tracing endpoint {s}: {e}"))?;
+    if url.scheme() != "http" {
+        return Err(anyhow!("tracing endpoint {s} should be http"));
+    }
+    if url.path() == "/" || url.path().is_empty() {
+        url.set_path(OTLP_TRACES_PATH);
+    }
+    Ok(url)
+}
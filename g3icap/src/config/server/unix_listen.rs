@@ -0,0 +1,80 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Unix domain socket listener configuration
+//!
+//! A proxy on the same host can reach the server over a unix socket instead
+//! of TCP. Access to the socket is controlled by its file mode and owner,
+//! so that only the proxy user can connect.
+
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use yaml_rust::Yaml;
+
+/// Unix socket listener of a server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnixListenConfig {
+    /// Path of the socket, a stale socket file is replaced
+    pub path: PathBuf,
+    /// File mode of the socket
+    pub mode: Option<u32>,
+    /// Owner of the socket, a user name or uid
+    pub owner: Option<String>,
+    /// Group of the socket, a group name or gid
+    pub group: Option<String>,
+}
+
+impl UnixListenConfig {
+    /// Parse the `unix_listen` section of a server config
+    ///
+    /// Either the socket path, or a map with the `path` and the `mode`,
+    /// `owner` and `group` of the socket. The mode is written in octal,
+    /// e.g. `"0660"`.
+    pub fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let mut config = UnixListenConfig {
+            path: PathBuf::new(),
+            mode: None,
+            owner: None,
+            group: None,
+        };
+        match v {
+            Yaml::String(s) => config.path = PathBuf::from(s),
+            Yaml::Hash(map) => {
+                g3_yaml::foreach_kv(map, |k, v| {
+                    match g3_yaml::key::normalize(k).as_str() {
+                        "path" => config.path = PathBuf::from(g3_yaml::value::as_string(v)?),
+                        "mode" | "permissions" => {
+                            config.mode = Some(parse_mode(&g3_yaml::value::as_string(v)?)?);
+                        }
+                        "owner" | "user" => config.owner = Some(g3_yaml::value::as_string(v)?),
+                        "group" => config.group = Some(g3_yaml::value::as_string(v)?),
+                        _ => return Err(anyhow!("invalid key {k} in unix listen config")),
+                    }
+                    Ok(())
+                })?;
+            }
+            _ => return Err(anyhow!("invalid value type for unix listen config")),
+        }
+        if config.path.as_os_str().is_empty() {
+            return Err(anyhow!("unix listen path should be set"));
+        }
+        if !config.path.is_absolute() {
+            return Err(anyhow!(
+                "unix listen path {} should be absolute",
+                config.path.display()
+            ));
+        }
+        Ok(config)
+    }
+}
+
+fn parse_mode(s: &str) -> anyhow::Result<u32> {
+    let digits = s.strip_prefix("0o").unwrap_or(s);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o777 => Ok(mode),
+        _ => Err(anyhow!("invalid unix socket mode {s}")),
+    }
+}
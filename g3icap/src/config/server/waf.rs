@@ -0,0 +1,107 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! WAF configuration
+//!
+//! The REQMOD requests are evaluated against ModSecurity rules, such as the
+//! request rules of the OWASP CRS, before the content filter. The rule files
+//! are loaded in order and the inline rules after them, all of them being
+//! parsed when the config is loaded. The rules using what is not supported
+//! are skipped, the syntax errors failing the config.
+
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use yaml_rust::Yaml;
+
+use crate::modules::waf::rules::RuleSet;
+
+/// WAF of a server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WafConfig {
+    /// Rule files, in load order
+    pub rule_files: Vec<PathBuf>,
+    /// Rules loaded after the files
+    pub rules: Option<String>,
+    /// Anomaly score of the matched blocking rules a request is denied at
+    pub anomaly_threshold: u32,
+    /// Highest CRS paranoia level of the rules evaluated
+    pub paranoia_level: u8,
+    /// Bytes of the request body inspected
+    pub body_limit: usize,
+}
+
+impl Default for WafConfig {
+    fn default() -> Self {
+        WafConfig {
+            rule_files: Vec::new(),
+            rules: None,
+            anomaly_threshold: 5,
+            paranoia_level: 1,
+            body_limit: 128 * 1024,
+        }
+    }
+}
+
+impl WafConfig {
+    /// Parse the `waf` section of a server config
+    pub fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let Yaml::Hash(map) = v else {
+            return Err(anyhow!("waf should be a map"));
+        };
+
+        let mut config = WafConfig::default();
+        g3_yaml::foreach_kv(map, |k, v| {
+            match g3_yaml::key::normalize(k).as_str() {
+                "rule_files" | "files" => {
+                    config.rule_files =
+                        g3_yaml::value::as_list(v, g3_yaml::value::as_absolute_path)?
+                }
+                "rules" => config.rules = Some(g3_yaml::value::as_string(v)?),
+                "anomaly_threshold" | "threshold" => {
+                    config.anomaly_threshold = g3_yaml::value::as_u32(v)?
+                }
+                "paranoia_level" => config.paranoia_level = g3_yaml::value::as_u8(v)?,
+                "body_limit" | "max_body_size" => {
+                    config.body_limit = g3_yaml::humanize::as_usize(v)?
+                }
+                _ => return Err(anyhow!("invalid key {k} in waf config")),
+            }
+            Ok(())
+        })?;
+
+        if config.rule_files.is_empty() && config.rules.is_none() {
+            return Err(anyhow!("waf needs rule_files or rules"));
+        }
+        if config.anomaly_threshold == 0 {
+            return Err(anyhow!("waf anomaly_threshold should not be 0"));
+        }
+        if !(1..=4).contains(&config.paranoia_level) {
+            return Err(anyhow!("waf paranoia_level should be between 1 and 4"));
+        }
+        config.load_rules()?;
+        Ok(config)
+    }
+
+    /// Load the rules of the files then the inline ones, up to the paranoia
+    /// level
+    pub fn load_rules(&self) -> anyhow::Result<RuleSet> {
+        let mut rules = RuleSet::default();
+        for path in &self.rule_files {
+            let text = std::fs::read_to_string(path)
+                .map_err(|e| anyhow!("failed to read waf rule file {}: {e}", path.display()))?;
+            rules
+                .load(&text, path.parent())
+                .map_err(|e| anyhow!("invalid waf rule file {}: {e}", path.display()))?;
+        }
+        if let Some(text) = &self.rules {
+            rules
+                .load(text, None)
+                .map_err(|e| anyhow!("invalid waf rules: {e}"))?;
+        }
+        rules.retain_paranoia_level(self.paranoia_level);
+        Ok(rules)
+    }
+}
@@ -0,0 +1,551 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! HTTP admin endpoint
+//!
+//! A minimal HTTP/1.1 JSON API mirroring the `g3icap-ctl` verbs, for the
+//! admin console and for hosts without shell access:
+//!
+//! | request                              | role     |
+//! |--------------------------------------|----------|
+//! | `GET /status`                        | viewer   |
+//! | `GET /services`                      | viewer   |
+//! | `GET /rules`                         | viewer   |
+//! | `GET /metrics`                       | viewer   |
+//! | `POST /policy/test`                  | viewer   |
+//! | `PUT /services/<service>/pipeline`   | operator |
+//! | `PUT /features/<feature>`            | operator |
+//! | `POST /reload`                       | operator |
+//! | `POST /drain`                        | admin    |
+//!
+//! Each connection carries a single request, authenticated by an API token
+//! in the `Authorization: Bearer` header. The metrics are in the Prometheus
+//! text format, all the other responses are JSON.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use http::StatusCode;
+use log::{info, warn};
+use serde_json::{Value, json};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use g3_types::metrics::NodeName;
+
+use crate::audit::ops::{DefaultIcapAuditOps, IcapAuditOps};
+use crate::config::server::admin::{AdminConfig, AdminRole, AdminTokenConfig};
+use crate::control::{command, handover};
+use crate::modules::blocklist::BlocklistProvider;
+use crate::server::what_if::{self, WhatIfRequest};
+use crate::stats::IcapStats;
+
+/// Largest request header accepted
+const MAX_HEADER_SIZE: usize = 16 * 1024;
+/// Largest request body accepted
+const MAX_BODY_SIZE: usize = 4 * 1024;
+/// Time a client has to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Parts of the server the admin verbs act on
+#[derive(Clone)]
+pub struct AdminState {
+    pub stats: Arc<IcapStats>,
+    pub blocklist: Option<Arc<BlocklistProvider>>,
+}
+
+/// An admin verb
+#[derive(Debug, PartialEq, Eq)]
+enum Verb {
+    Status,
+    ListServices,
+    RuleStats,
+    Metrics,
+    PolicyTest,
+    SetPipeline(String),
+    SetFeature(String),
+    Reload,
+    Drain,
+}
+
+impl Verb {
+    /// Find the verb of the request
+    fn route(method: &str, path: &str) -> Result<Self, StatusCode> {
+        let path = path.split('?').next().unwrap_or_default();
+        let (verb, allowed) = match path {
+            "/status" => (Verb::Status, "GET"),
+            "/services" => (Verb::ListServices, "GET"),
+            "/rules" => (Verb::RuleStats, "GET"),
+            "/metrics" => (Verb::Metrics, "GET"),
+            "/policy/test" => (Verb::PolicyTest, "POST"),
+            "/reload" => (Verb::Reload, "POST"),
+            "/drain" => (Verb::Drain, "POST"),
+            _ => match path
+                .strip_prefix("/services/")
+                .and_then(|p| p.strip_suffix("/pipeline"))
+            {
+                Some(service) if !service.is_empty() => {
+                    (Verb::SetPipeline(service.to_string()), "PUT")
+                }
+                _ => match path.strip_prefix("/features/") {
+                    Some(feature) if !feature.is_empty() && !feature.contains('/') => {
+                        (Verb::SetFeature(feature.to_string()), "PUT")
+                    }
+                    _ => return Err(StatusCode::NOT_FOUND),
+                },
+            },
+        };
+        if method != allowed {
+            return Err(StatusCode::METHOD_NOT_ALLOWED);
+        }
+        Ok(verb)
+    }
+
+    /// Role needed to use the verb
+    fn role(&self) -> AdminRole {
+        match self {
+            Verb::Status
+            | Verb::ListServices
+            | Verb::RuleStats
+            | Verb::Metrics
+            | Verb::PolicyTest => AdminRole::Viewer,
+            Verb::SetPipeline(_) | Verb::SetFeature(_) | Verb::Reload => AdminRole::Operator,
+            Verb::Drain => AdminRole::Admin,
+        }
+    }
+}
+
+/// A parsed admin request
+struct AdminRequest {
+    method: String,
+    path: String,
+    authorization: Option<String>,
+    body: Vec<u8>,
+}
+
+/// Find the token of the Authorization header value
+fn authenticate<'a>(
+    tokens: &'a [AdminTokenConfig],
+    authorization: Option<&str>,
+) -> Option<&'a AdminTokenConfig> {
+    let (scheme, credential) = authorization?.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("bearer") {
+        return None;
+    }
+    let credential = credential.trim().as_bytes();
+    tokens.iter().find(|t| {
+        let secret = t.secret.as_bytes();
+        secret.len() == credential.len() && openssl::memcmp::eq(secret, credential)
+    })
+}
+
+/// Serve the admin endpoint until the process exits
+pub async fn spawn(config: AdminConfig, state: AdminState) -> anyhow::Result<SocketAddr> {
+    let listener = TcpListener::bind(config.listen)
+        .await
+        .with_context(|| format!("failed to bind admin endpoint to {}", config.listen))?;
+    let addr = listener.local_addr()?;
+    info!("admin endpoint listening on {addr}");
+    let config = Arc::new(config);
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(v) => v,
+                Err(e) => {
+                    warn!("failed to accept admin connection: {e}");
+                    continue;
+                }
+            };
+            let config = config.clone();
+            let state = state.clone();
+            tokio::spawn(async move {
+                if let Err(e) = serve(stream, peer, &config, &state).await {
+                    warn!("admin connection from {peer} failed: {e}");
+                }
+            });
+        }
+    });
+    Ok(addr)
+}
+
+async fn serve<S>(
+    mut stream: S,
+    peer: SocketAddr,
+    config: &AdminConfig,
+    state: &AdminState,
+) -> std::io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (status, body) = match tokio::time::timeout(READ_TIMEOUT, read_request(&mut stream)).await {
+        Ok(Ok(request)) => handle(request, peer, config, state).await,
+        Ok(Err(status)) => (status, error_body(status)),
+        Err(_) => (
+            StatusCode::REQUEST_TIMEOUT,
+            error_body(StatusCode::REQUEST_TIMEOUT),
+        ),
+    };
+    stream.write_all(&serialize_response(status, &body)).await?;
+    stream.shutdown().await
+}
+
+async fn handle(
+    request: AdminRequest,
+    peer: SocketAddr,
+    config: &AdminConfig,
+    state: &AdminState,
+) -> (StatusCode, Value) {
+    let verb = match Verb::route(&request.method, &request.path) {
+        Ok(verb) => verb,
+        Err(status) => return (status, error_body(status)),
+    };
+    let Some(token) = authenticate(&config.tokens, request.authorization.as_deref()) else {
+        warn!(
+            "admin request {} {} from {peer} not authenticated",
+            request.method, request.path
+        );
+        return (
+            StatusCode::UNAUTHORIZED,
+            error_body(StatusCode::UNAUTHORIZED),
+        );
+    };
+    if token.role < verb.role() {
+        warn!(
+            "admin token {} of {peer} is {}, {} needed for {} {}",
+            token.id,
+            token.role.as_str(),
+            verb.role().as_str(),
+            request.method,
+            request.path
+        );
+        return (StatusCode::FORBIDDEN, error_body(StatusCode::FORBIDDEN));
+    }
+
+    match verb {
+        Verb::Status => (StatusCode::OK, status(state)),
+        Verb::ListServices => match crate::server::pipelines::get_global() {
+            Some(pipelines) => {
+                let services: serde_json::Map<String, Value> = pipelines
+                    .bindings()
+                    .into_iter()
+                    .map(|(service, pipeline)| (service, Value::String(pipeline)))
+                    .collect();
+                let body = json!({"pipelines": pipelines.names(), "services": services});
+                (StatusCode::OK, body)
+            }
+            None => unavailable("no server is running"),
+        },
+        Verb::RuleStats => {
+            let report = crate::modules::rule_hits::global().report();
+            (StatusCode::OK, json!(report))
+        }
+        Verb::Metrics => {
+            let mut metrics =
+                crate::modules::metrics::MetricsRegistry::global().render_prometheus();
+            if let Some(slo) = crate::server::slo::get_global() {
+                metrics.push_str(&slo.render_prometheus());
+            }
+            if let Some(quota) = crate::server::quota::get_global() {
+                metrics.push_str(&quota.render_prometheus());
+            }
+            if let Some(replication) = crate::replication::get_global() {
+                metrics.push_str(&replication.render_prometheus());
+            }
+            (StatusCode::OK, Value::String(metrics))
+        }
+        Verb::PolicyTest => {
+            let Ok(what_if) = serde_json::from_slice::<WhatIfRequest>(&request.body) else {
+                return bad_request("expected a json body describing the request");
+            };
+            let Some(modules) = crate::server::modules::get_global() else {
+                return unavailable("no module loaded");
+            };
+            match what_if::evaluate(&modules, &what_if).await {
+                Ok(report) => (StatusCode::OK, json!(report)),
+                Err(e) => bad_request(&format!("{e}")),
+            }
+        }
+        Verb::SetPipeline(service) => {
+            let Some(pipeline) = serde_json::from_slice::<Value>(&request.body)
+                .ok()
+                .and_then(|v| v.get("pipeline")?.as_str().map(str::to_string))
+            else {
+                return bad_request("expected a json body with the pipeline name");
+            };
+            info!(
+                "admin token {} sets pipeline {pipeline} for service {service}",
+                token.id
+            );
+            match command::set_pipeline(&service, &pipeline) {
+                Ok(previous) => (
+                    StatusCode::OK,
+                    json!({"service": service, "pipeline": pipeline, "previous": previous}),
+                ),
+                Err(e) => bad_request(&format!("{e}")),
+            }
+        }
+        Verb::SetFeature(name) => {
+            let Some(enabled) = serde_json::from_slice::<Value>(&request.body)
+                .ok()
+                .and_then(|v| v.get("enabled")?.as_bool())
+            else {
+                return bad_request("expected a json body with the enabled boolean");
+            };
+            let Some(feature) = crate::features::get(&name) else {
+                return (StatusCode::NOT_FOUND, error_body(StatusCode::NOT_FOUND));
+            };
+            info!(
+                "admin token {} sets feature {name} enabled to {enabled}",
+                token.id
+            );
+            match feature.set_enabled(enabled) {
+                Ok(previous) => (
+                    StatusCode::OK,
+                    json!({"feature": name, "enabled": enabled, "previous": previous}),
+                ),
+                Err(e) => (StatusCode::CONFLICT, json!({"error": format!("{e}")})),
+            }
+        }
+        Verb::Reload => {
+            info!("admin token {} reloads the lists", token.id);
+            let reloaded = match &state.blocklist {
+                Some(blocklist) => blocklist.refresh(true).await,
+                None => 0,
+            };
+            (StatusCode::OK, json!({"reloaded_blocklists": reloaded}))
+        }
+        Verb::Drain => {
+            info!("admin token {} drains the server", token.id);
+            let audit_ops = DefaultIcapAuditOps::new(NodeName::new_static("g3icap"), true);
+            audit_ops.log_config_changed(
+                "Server drained",
+                &format!("admin token {} from {peer}", token.id),
+            );
+            handover::drain();
+            (StatusCode::OK, json!({"draining": true}))
+        }
+    }
+}
+
+fn status(state: &AdminState) -> Value {
+    let stats = &state.stats;
+    json!({
+        "version": crate::version::VersionReport::current(),
+        "draining": handover::is_released(),
+        "connections": {
+            "active": stats.active_connections(),
+            "total": stats.get_total_connections(),
+        },
+        "requests": {
+            "total": stats.total_requests(),
+            "reqmod": stats.reqmod_requests(),
+            "respmod": stats.respmod_requests(),
+            "options": stats.options_requests(),
+            "blocked": stats.blocked_requests(),
+            "error_responses": stats.error_responses(),
+        },
+        "blocked_domains": state.blocklist.as_ref().map(|b| b.len()).unwrap_or(0),
+        "slo": crate::server::slo::get_global().map(|slo| slo.reports()),
+    })
+}
+
+fn error_body(status: StatusCode) -> Value {
+    json!({"error": status.canonical_reason().unwrap_or("error")})
+}
+
+fn bad_request(reason: &str) -> (StatusCode, Value) {
+    (StatusCode::BAD_REQUEST, json!({"error": reason}))
+}
+
+fn unavailable(reason: &str) -> (StatusCode, Value) {
+    (StatusCode::SERVICE_UNAVAILABLE, json!({"error": reason}))
+}
+
+/// Serialize the response, a string body being sent as Prometheus text
+fn serialize_response(status: StatusCode, body: &Value) -> Vec<u8> {
+    let (content_type, body) = match body {
+        Value::String(text) => ("text/plain; version=0.0.4", text.clone()),
+        body => ("application/json", body.to_string()),
+    };
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        status.as_str(),
+        status.canonical_reason().unwrap_or_default(),
+        body.len()
+    );
+    if status == StatusCode::UNAUTHORIZED {
+        head.push_str("WWW-Authenticate: Bearer realm=\"g3icap-admin\"\r\n");
+    }
+    head.push_str("\r\n");
+    let mut data = head.into_bytes();
+    data.extend_from_slice(body.as_bytes());
+    data
+}
+
+async fn read_request<S: AsyncRead + Unpin>(stream: &mut S) -> Result<AdminRequest, StatusCode> {
+    let mut data = Vec::with_capacity(1024);
+    let header_end = loop {
+        if let Some(end) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end;
+        }
+        if data.len() > MAX_HEADER_SIZE {
+            return Err(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+        }
+        let mut buf = [0u8; 1024];
+        match stream.read(&mut buf).await {
+            Ok(0) | Err(_) => return Err(StatusCode::BAD_REQUEST),
+            Ok(n) => data.extend_from_slice(&buf[..n]),
+        }
+    };
+    let (mut request, content_length) = parse_head(&data[..header_end])?;
+    if content_length > MAX_BODY_SIZE {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    let mut body = data.split_off(header_end + 4);
+    while body.len() < content_length {
+        let mut buf = [0u8; 1024];
+        match stream.read(&mut buf).await {
+            Ok(0) | Err(_) => return Err(StatusCode::BAD_REQUEST),
+            Ok(n) => body.extend_from_slice(&buf[..n]),
+        }
+    }
+    body.truncate(content_length);
+    request.body = body;
+    Ok(request)
+}
+
+/// Parse the request line and headers, returns the request and its body size
+fn parse_head(head: &[u8]) -> Result<(AdminRequest, usize), StatusCode> {
+    let head = std::str::from_utf8(head).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let mut lines = head.split("\r\n");
+    let mut parts = lines.next().unwrap_or_default().split(' ');
+    let (Some(method), Some(path), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(StatusCode::BAD_REQUEST);
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(StatusCode::HTTP_VERSION_NOT_SUPPORTED);
+    }
+
+    let mut authorization = None;
+    let mut content_length = 0;
+    for line in lines {
+        let (name, value) = line.split_once(':').ok_or(StatusCode::BAD_REQUEST)?;
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("authorization") {
+            authorization = Some(value.to_string());
+        } else if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.parse().map_err(|_| StatusCode::BAD_REQUEST)?;
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            return Err(StatusCode::LENGTH_REQUIRED);
+        }
+    }
+    let request = AdminRequest {
+        method: method.to_string(),
+        path: path.to_string(),
+        authorization,
+        body: Vec::new(),
+    };
+    Ok((request, content_length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(id: &str, role: AdminRole) -> AdminTokenConfig {
+        AdminTokenConfig {
+            id: id.to_string(),
+            secret: format!("{id}-secret"),
+            role,
+        }
+    }
+
+    #[test]
+    fn routes() {
+        assert_eq!(Verb::route("GET", "/status"), Ok(Verb::Status));
+        assert_eq!(Verb::route("GET", "/services?x=1"), Ok(Verb::ListServices));
+        assert_eq!(
+            Verb::route("PUT", "/services/av/respmod/pipeline"),
+            Ok(Verb::SetPipeline("av/respmod".to_string()))
+        );
+        assert_eq!(Verb::route("POST", "/drain"), Ok(Verb::Drain));
+        assert_eq!(Verb::route("GET", "/rules"), Ok(Verb::RuleStats));
+        assert_eq!(Verb::route("GET", "/metrics"), Ok(Verb::Metrics));
+        assert_eq!(Verb::route("POST", "/policy/test"), Ok(Verb::PolicyTest));
+        assert_eq!(
+            Verb::route("PUT", "/features/cdr"),
+            Ok(Verb::SetFeature("cdr".to_string()))
+        );
+        assert_eq!(
+            Verb::route("GET", "/reload"),
+            Err(StatusCode::METHOD_NOT_ALLOWED)
+        );
+        assert_eq!(
+            Verb::route("PUT", "/services//pipeline"),
+            Err(StatusCode::NOT_FOUND)
+        );
+        assert_eq!(Verb::route("GET", "/"), Err(StatusCode::NOT_FOUND));
+    }
+
+    #[test]
+    fn tokens_and_roles() {
+        let tokens = [
+            token("console", AdminRole::Operator),
+            token("ops", AdminRole::Admin),
+        ];
+        let t = authenticate(&tokens, Some("Bearer console-secret")).unwrap();
+        assert_eq!(t.id, "console");
+        assert!(t.role >= Verb::Reload.role());
+        assert!(t.role < Verb::Drain.role());
+        assert_eq!(
+            authenticate(&tokens, Some("bearer ops-secret")).unwrap().id,
+            "ops"
+        );
+        assert!(authenticate(&tokens, Some("Bearer console")).is_none());
+        assert!(authenticate(&tokens, Some("Basic console-secret")).is_none());
+        assert!(authenticate(&tokens, None).is_none());
+    }
+
+    #[tokio::test]
+    async fn request_parsing() {
+        let data = b"PUT /services/reqmod/pipeline HTTP/1.1\r\n\
+            Host: localhost\r\n\
+            Authorization: Bearer s\r\n\
+            Content-Length: 22\r\n\r\n\
+            {\"pipeline\":\"lenient\"}";
+        let request = read_request(&mut &data[..]).await.unwrap();
+        assert_eq!(request.method, "PUT");
+        assert_eq!(request.path, "/services/reqmod/pipeline");
+        assert_eq!(request.authorization.as_deref(), Some("Bearer s"));
+        assert_eq!(request.body, b"{\"pipeline\":\"lenient\"}");
+
+        let chunked = b"POST /reload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n";
+        assert_eq!(
+            read_request(&mut &chunked[..]).await.err(),
+            Some(StatusCode::LENGTH_REQUIRED)
+        );
+        let truncated = b"POST /reload HTTP/1.1\r\nContent-Length: 10\r\n\r\n{}";
+        assert_eq!(
+            read_request(&mut &truncated[..]).await.err(),
+            Some(StatusCode::BAD_REQUEST)
+        );
+    }
+
+    #[test]
+    fn responses() {
+        let data = serialize_response(
+            StatusCode::UNAUTHORIZED,
+            &error_body(StatusCode::UNAUTHORIZED),
+        );
+        let text = String::from_utf8(data).unwrap();
+        assert!(text.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+        assert!(text.contains("WWW-Authenticate: Bearer"));
+        assert!(text.ends_with("\r\n\r\n{\"error\":\"Unauthorized\"}"));
+    }
+}
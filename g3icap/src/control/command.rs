@@ -0,0 +1,622 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Commands of `g3icap-ctl` served on the handover socket
+//!
+//! Besides the listener handover, the handover socket of the running daemon
+//! serves the commands of `g3icap-ctl`, one line each, answered by one line:
+//!
+//! - `VERSION`, sent by `g3icap-ctl version`: answered by the version report
+//!   of the running daemon as one line of JSON
+//! - `SET-PIPELINE <service> <pipeline>`, sent by `g3icap-ctl service
+//!   set-pipeline`: the service is bound to the pipeline from its next
+//!   transaction on, answered by `OK <previous pipeline>` or `ERR <reason>`
+//! - `STATS`, sent by `g3icap-ctl stats`: answered by the server, service,
+//!   pipeline, quarantine and module counters as one line of JSON
+//! - `RULE-STATS`, sent by `g3icap-ctl stats --rules`: answered by the rule
+//!   hit counters as one line of JSON
+//! - `UNUSED-RULES <days>`, sent by `g3icap-ctl unused-rules`: answered by the
+//!   rules without any hit for that many days as one line of JSON
+//! - `STATUS`, sent by `g3icap-ctl status`: answered by the process id, the
+//!   version, the drain state and the response time objectives compliance as
+//!   one line of JSON
+//! - `SERVICES`, sent by `g3icap-ctl service list`: answered by the state of
+//!   the services as one line of JSON
+//! - `SERVICE-ENABLE <service>` and `SERVICE-DISABLE <service>`, sent by
+//!   `g3icap-ctl service enable|disable`: the service is taken in or out of
+//!   rotation, answered by `OK changed`, `OK unchanged` or `ERR <reason>`
+//! - `SERVICE-RELOAD <service>`, sent by `g3icap-ctl service reload`: the
+//!   modules of the service are reloaded, answered by `OK <modules>` or
+//!   `ERR <reason>`
+//! - `MODULE-RELOAD <module>`, sent by `g3icap-ctl module reload`: the module
+//!   is reloaded, answered by `OK` or `ERR <reason>`
+//! - `LOG-SET-LEVEL <component> <level> <seconds>`, sent by `g3icap-ctl log
+//!   set-level`: the component is logged at the level for that many seconds,
+//!   answered by `OK <log target> <unix time of the revert>` or `ERR <reason>`
+//! - `LOG-RESET <component>`, sent by `g3icap-ctl log reset`: the component is
+//!   logged at the process level again, answered by `OK changed`,
+//!   `OK unchanged` or `ERR <reason>`
+//! - `LOG-LEVELS`, sent by `g3icap-ctl log list`: answered by the levels set
+//!   at runtime as one line of JSON
+//! - `SHADOW-STATS`, sent by `g3icap-ctl policy status`: answered by the
+//!   disagreements of the candidate rule sets as one line of JSON
+//! - `POLICY-PROMOTE <rule set> [<unix time> [<seconds>]]`, sent by
+//!   `g3icap-ctl policy promote`: the candidate rules become the active ones,
+//!   at the unix time if given, and the previous ones are active again after
+//!   that many seconds unless confirmed. Answered by `OK`, `OK <unix time of
+//!   the promotion> <unix time of the revert or ->` if scheduled, or
+//!   `ERR <reason>`
+//! - `POLICY-CONFIRM <rule set>`, sent by `g3icap-ctl policy confirm`: the
+//!   scheduled promotion is no longer reverted, answered by `OK <state>` or
+//!   `ERR <reason>`
+//! - `POLICY-REVERT <rule set>`, sent by `g3icap-ctl policy revert`: the
+//!   scheduled promotion is cancelled, or the previous rules are active again,
+//!   answered by `OK cancelled`, `OK reverted` or `ERR <reason>`
+//! - `POLICY-SCHEDULE`, sent by `g3icap-ctl policy status`: answered by the
+//!   scheduled promotions as one line of JSON
+//! - `POLICY-TEST <request>`, sent by `g3icap-ctl policy test`: the synthetic
+//!   request described in JSON is evaluated, answered by the matched rules
+//!   and the verdict as one line of JSON, or `ERR <reason>`
+//! - `EVENTS <count>`, sent by `g3icap-ctl top`: answered by the latest audit
+//!   events, the oldest first, as one line of JSON
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, anyhow};
+use g3_types::metrics::NodeName;
+use log::info;
+
+use super::handover::{IO_TIMEOUT, is_released};
+use crate::audit::ops::{DefaultIcapAuditOps, IcapAuditOps};
+use crate::config::server::pipelines::PipelineStage;
+use crate::modules::shadow::ShadowTarget;
+use crate::server::policy_schedule::{self, RevertOutcome, ScheduleState, ScheduledPromotion};
+use crate::server::what_if::{self, WhatIfReport, WhatIfRequest};
+
+/// Time a module has to reload, rules being compiled again
+const RELOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Get the version report of the daemon serving the handover socket, as JSON
+pub fn request_version(path: &Path) -> anyhow::Result<String> {
+    let stream = UnixStream::connect(path)
+        .with_context(|| format!("failed to connect to {}", path.display()))?;
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    (&stream).write_all(b"VERSION\n")?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    if !line.starts_with('{') {
+        return Err(anyhow!("unexpected reply {:?}", line.trim()));
+    }
+    Ok(line.trim_end().to_string())
+}
+
+/// Bind a service of the daemon serving the handover socket to another pipeline
+///
+/// Returns the name of the previous pipeline of the service, if any.
+pub fn request_set_pipeline(
+    path: &Path,
+    service: &str,
+    pipeline: &str,
+) -> anyhow::Result<Option<String>> {
+    let stream = UnixStream::connect(path)
+        .with_context(|| format!("failed to connect to {}", path.display()))?;
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    (&stream).write_all(format!("SET-PIPELINE {service} {pipeline}\n").as_bytes())?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    match line.trim().split_once(' ') {
+        Some(("OK", "-")) => Ok(None),
+        Some(("OK", previous)) => Ok(Some(previous.to_string())),
+        Some(("ERR", reason)) => Err(anyhow!("set pipeline failed: {reason}")),
+        _ => Err(anyhow!("unexpected reply {:?}", line.trim())),
+    }
+}
+
+/// Get the stats of the daemon serving the handover socket, as JSON
+pub fn request_stats(path: &Path) -> anyhow::Result<String> {
+    request_json(path, "STATS")
+}
+
+/// Get the rule hit counters of the daemon serving the handover socket, as JSON
+pub fn request_rule_stats(path: &Path) -> anyhow::Result<String> {
+    request_json(path, "RULE-STATS")
+}
+
+/// Get the rules of the daemon serving the handover socket which were not hit
+/// for the given number of days, as a JSON array
+pub fn request_unused_rules(path: &Path, days: u64) -> anyhow::Result<String> {
+    request_json(path, &format!("UNUSED-RULES {days}"))
+}
+
+/// Get the status of the daemon serving the handover socket, as JSON
+pub fn request_status(path: &Path) -> anyhow::Result<String> {
+    request_json(path, "STATUS")
+}
+
+/// Get the state of the services of the daemon serving the handover socket,
+/// as a JSON array
+pub fn request_services(path: &Path) -> anyhow::Result<String> {
+    request_json(path, "SERVICES")
+}
+
+/// Take a service of the daemon serving the handover socket in or out of
+/// rotation, returns false if it already was
+pub fn request_service_state(path: &Path, service: &str, enabled: bool) -> anyhow::Result<bool> {
+    let command = if enabled {
+        "SERVICE-ENABLE"
+    } else {
+        "SERVICE-DISABLE"
+    };
+    let reply = request_ok(path, &format!("{command} {service}"), IO_TIMEOUT)?;
+    Ok(reply == "changed")
+}
+
+/// Reload the modules of a service of the daemon serving the handover socket,
+/// returns the reloaded modules
+pub fn request_service_reload(path: &Path, service: &str) -> anyhow::Result<Vec<String>> {
+    let reply = request_ok(path, &format!("SERVICE-RELOAD {service}"), RELOAD_TIMEOUT)?;
+    Ok(reply.split(',').map(str::to_string).collect())
+}
+
+/// Reload a module of the daemon serving the handover socket
+pub fn request_module_reload(path: &Path, module: &str) -> anyhow::Result<()> {
+    request_ok(path, &format!("MODULE-RELOAD {module}"), RELOAD_TIMEOUT)?;
+    Ok(())
+}
+
+/// Set the log level of a component of the daemon serving the handover socket
+/// for some time
+///
+/// Returns the log target of the component and the unix time of the revert.
+pub fn request_log_level(
+    path: &Path,
+    component: &str,
+    level: &str,
+    duration: Duration,
+) -> anyhow::Result<(String, u64)> {
+    let command = format!("LOG-SET-LEVEL {component} {level} {}", duration.as_secs());
+    let reply = request_ok(path, &command, IO_TIMEOUT)?;
+    reply
+        .split_once(' ')
+        .and_then(|(target, until)| Some((target.to_string(), until.parse().ok()?)))
+        .ok_or_else(|| anyhow!("unexpected reply {reply:?}"))
+}
+
+/// Log a component of the daemon serving the handover socket at the process
+/// level again, returns false if it already was
+pub fn request_log_reset(path: &Path, component: &str) -> anyhow::Result<bool> {
+    let reply = request_ok(path, &format!("LOG-RESET {component}"), IO_TIMEOUT)?;
+    Ok(reply == "changed")
+}
+
+/// Get the log levels set at runtime of the daemon serving the handover
+/// socket, as a JSON array
+pub fn request_log_levels(path: &Path) -> anyhow::Result<String> {
+    request_json(path, "LOG-LEVELS")
+}
+
+/// Get the disagreements of the candidate rule sets of the daemon serving the
+/// handover socket, as a JSON array
+pub fn request_shadow_stats(path: &Path) -> anyhow::Result<String> {
+    request_json(path, "SHADOW-STATS")
+}
+
+/// Make the candidate rules of a rule set of the daemon serving the handover
+/// socket the active ones
+///
+/// The promotion happens at the unix time if given, and is reverted after
+/// the duration unless confirmed. Returns the unix times of the promotion
+/// and of the revert if scheduled.
+pub fn request_policy_promote(
+    path: &Path,
+    rule_set: &str,
+    activate_at: Option<u64>,
+    revert_after: Option<Duration>,
+) -> anyhow::Result<Option<(u64, Option<u64>)>> {
+    let mut command = format!("POLICY-PROMOTE {rule_set}");
+    if activate_at.is_some() || revert_after.is_some() {
+        command.push_str(&format!(" {}", activate_at.unwrap_or_default()));
+    }
+    if let Some(revert_after) = revert_after {
+        command.push_str(&format!(" {}", revert_after.as_secs()));
+    }
+    let reply = request_ok(path, &command, RELOAD_TIMEOUT)?;
+    if reply.is_empty() {
+        return Ok(None);
+    }
+    reply
+        .split_once(' ')
+        .and_then(|(activate_at, revert_at)| {
+            Some((activate_at.parse().ok()?, revert_at.parse().ok()))
+        })
+        .map(Some)
+        .ok_or_else(|| anyhow!("unexpected reply {reply:?}"))
+}
+
+/// Keep the scheduled promotion of a rule set of the daemon serving the
+/// handover socket, returns the state of the promotion
+pub fn request_policy_confirm(path: &Path, rule_set: &str) -> anyhow::Result<ScheduleState> {
+    let reply = request_ok(path, &format!("POLICY-CONFIRM {rule_set}"), IO_TIMEOUT)?;
+    match reply.as_str() {
+        "pending" => Ok(ScheduleState::Pending),
+        "active" => Ok(ScheduleState::Active),
+        _ => Err(anyhow!("unexpected reply {reply:?}")),
+    }
+}
+
+/// Cancel or revert the promotion of a rule set of the daemon serving the
+/// handover socket, returns true if the previous rules are active again
+pub fn request_policy_revert(path: &Path, rule_set: &str) -> anyhow::Result<bool> {
+    let reply = request_ok(path, &format!("POLICY-REVERT {rule_set}"), RELOAD_TIMEOUT)?;
+    Ok(reply == "reverted")
+}
+
+/// Get the scheduled promotions of the daemon serving the handover socket
+pub fn request_policy_schedule(path: &Path) -> anyhow::Result<Vec<ScheduledPromotion>> {
+    let reply = request_json(path, "POLICY-SCHEDULE")?;
+    Ok(serde_json::from_str(&reply)?)
+}
+
+/// Evaluate a synthetic request on the daemon serving the handover socket,
+/// returns the rules it matches and the verdict
+pub fn request_policy_test(path: &Path, request: &WhatIfRequest) -> anyhow::Result<WhatIfReport> {
+    let reply = request_json(
+        path,
+        &format!("POLICY-TEST {}", serde_json::to_string(request)?),
+    )?;
+    Ok(serde_json::from_str(&reply)?)
+}
+
+/// Get the latest audit events of the daemon serving the handover socket, the
+/// oldest first, as a JSON array
+pub fn request_events(path: &Path, count: usize) -> anyhow::Result<String> {
+    request_json(path, &format!("EVENTS {count}"))
+}
+
+/// Send the command, returns what follows the `OK` of the reply
+fn request_ok(path: &Path, command: &str, timeout: Duration) -> anyhow::Result<String> {
+    let stream = UnixStream::connect(path)
+        .with_context(|| format!("failed to connect to {}", path.display()))?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    (&stream).write_all(format!("{command}\n").as_bytes())?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let line = line.trim();
+    match line.split_once(' ').unwrap_or((line, "")) {
+        ("OK", reply) => Ok(reply.to_string()),
+        ("ERR", reason) => Err(anyhow!("{reason}")),
+        _ => Err(anyhow!("unexpected reply {line:?}")),
+    }
+}
+
+fn request_json(path: &Path, command: &str) -> anyhow::Result<String> {
+    let stream = UnixStream::connect(path)
+        .with_context(|| format!("failed to connect to {}", path.display()))?;
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    (&stream).write_all(format!("{command}\n").as_bytes())?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    if let Some(reason) = line.trim().strip_prefix("ERR ") {
+        return Err(anyhow!("{command} failed: {reason}"));
+    }
+    if !line.starts_with(['{', '[']) {
+        return Err(anyhow!("unexpected reply {:?}", line.trim()));
+    }
+    Ok(line.trim_end().to_string())
+}
+
+/// Handle a command, returns the reply line
+pub(super) fn handle(cmd: &str) -> String {
+    match cmd {
+        "VERSION" => {
+            let report = crate::version::VersionReport::current().to_json();
+            format!("{report}\n")
+        }
+        "STATS" => {
+            let stats = crate::stat::get_global_stats().unwrap_or_default();
+            let report = crate::stats::report::StatsReport::collect(&stats);
+            let reply = serde_json::to_string(&report).unwrap_or_default();
+            format!("{reply}\n")
+        }
+        "RULE-STATS" => {
+            let report = crate::modules::rule_hits::global().report().to_json();
+            format!("{report}\n")
+        }
+        "STATUS" => {
+            let status = serde_json::json!({
+                "pid": std::process::id(),
+                "version": crate::version::VersionReport::current(),
+                "draining": is_released(),
+                "slo": crate::server::slo::get_global().map(|slo| slo.reports()),
+            });
+            format!("{status}\n")
+        }
+        "SERVICES" => {
+            let services = crate::server::rotation::get_global()
+                .map(|r| r.list())
+                .unwrap_or_default();
+            let reply = serde_json::to_string(&services).unwrap_or_default();
+            format!("{reply}\n")
+        }
+        cmd if cmd.starts_with("SERVICE-") || cmd.starts_with("MODULE-RELOAD ") => {
+            match service_command(cmd) {
+                Ok(reply) if reply.is_empty() => "OK\n".to_string(),
+                Ok(reply) => format!("OK {reply}\n"),
+                Err(e) => format!("ERR {e}\n"),
+            }
+        }
+        "LOG-LEVELS" => {
+            let reply = serde_json::to_string(&crate::log::level::list()).unwrap_or_default();
+            format!("{reply}\n")
+        }
+        cmd if cmd.starts_with("LOG-SET-LEVEL ") || cmd.starts_with("LOG-RESET ") => {
+            match log_command(cmd) {
+                Ok(reply) => format!("OK {reply}\n"),
+                Err(e) => format!("ERR {e}\n"),
+            }
+        }
+        "SHADOW-STATS" => {
+            let modules = crate::server::modules::get_global();
+            let reports: Vec<_> = ShadowTarget::ALL
+                .into_iter()
+                .map(|target| {
+                    let loaded = modules.as_ref().is_some_and(|m| m.shadow(target).is_some());
+                    target.stats().report(target, loaded)
+                })
+                .collect();
+            let reply = serde_json::to_string(&reports).unwrap_or_default();
+            format!("{reply}\n")
+        }
+        "POLICY-SCHEDULE" => {
+            let reply = serde_json::to_string(&policy_schedule::list()).unwrap_or_default();
+            format!("{reply}\n")
+        }
+        cmd if cmd.starts_with("POLICY-TEST ") => match policy_test(&cmd["POLICY-TEST ".len()..]) {
+            Ok(report) => format!("{}\n", serde_json::to_string(&report).unwrap_or_default()),
+            Err(e) => format!("ERR {e}\n"),
+        },
+        cmd if cmd.starts_with("POLICY-") => match policy_command(cmd) {
+            Ok(reply) if reply.is_empty() => "OK\n".to_string(),
+            Ok(reply) => format!("OK {reply}\n"),
+            Err(e) => format!("ERR {e}\n"),
+        },
+        cmd if cmd.starts_with("UNUSED-RULES ") => {
+            match cmd["UNUSED-RULES ".len()..].trim().parse::<u64>() {
+                Ok(days) => {
+                    let unused = crate::modules::rule_hits::global().unused(days);
+                    format!("{}\n", serde_json::to_string(&unused).unwrap_or_default())
+                }
+                Err(_) => "ERR usage: UNUSED-RULES <days>\n".to_string(),
+            }
+        }
+        cmd if cmd.starts_with("EVENTS ") => match cmd["EVENTS ".len()..].trim().parse::<usize>() {
+            Ok(count) => {
+                let events = crate::audit::events::tail(count);
+                format!("{}\n", serde_json::to_string(&events).unwrap_or_default())
+            }
+            Err(_) => "ERR usage: EVENTS <count>\n".to_string(),
+        },
+        cmd if cmd.starts_with("SET-PIPELINE ") => {
+            match set_pipeline_command(&cmd["SET-PIPELINE ".len()..]) {
+                Ok(previous) => format!("OK {}\n", previous.as_deref().unwrap_or("-")),
+                Err(e) => format!("ERR {e}\n"),
+            }
+        }
+        cmd => format!("ERR unknown command {cmd}\n"),
+    }
+}
+
+fn set_pipeline_command(args: &str) -> anyhow::Result<Option<String>> {
+    let mut args = args.split_whitespace();
+    let (Some(service), Some(pipeline), None) = (args.next(), args.next(), args.next()) else {
+        return Err(anyhow!("usage: SET-PIPELINE <service> <pipeline>"));
+    };
+    set_pipeline(service, pipeline)
+}
+
+/// Handle a service or module management command, returns the reply after `OK`
+fn service_command(cmd: &str) -> anyhow::Result<String> {
+    let (verb, name) = cmd.split_once(' ').unwrap_or((cmd, ""));
+    let name = name.trim();
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return Err(anyhow!("usage: {verb} <name>"));
+    }
+    match verb {
+        "SERVICE-ENABLE" => set_service_enabled(name, true),
+        "SERVICE-DISABLE" => set_service_enabled(name, false),
+        "SERVICE-RELOAD" => Ok(reload_service(name)?.join(",")),
+        "MODULE-RELOAD" => {
+            reload_module(name)?;
+            Ok(String::new())
+        }
+        _ => Err(anyhow!("unknown command {verb}")),
+    }
+}
+
+/// Handle a log level command, returns the reply after `OK`
+fn log_command(cmd: &str) -> anyhow::Result<String> {
+    let args: Vec<&str> = cmd.split_whitespace().collect();
+    let audit_ops = DefaultIcapAuditOps::new(NodeName::new_static("g3icap"), true);
+    match args[..] {
+        ["LOG-SET-LEVEL", component, level, seconds] => {
+            let seconds = seconds
+                .parse::<u64>()
+                .map_err(|_| anyhow!("invalid duration {seconds}"))?;
+            let entry = crate::log::level::set(component, level, Duration::from_secs(seconds))?;
+            info!(
+                "log level of {} set to {} for {seconds}s",
+                entry.target, entry.level
+            );
+            audit_ops.log_config_changed(
+                "Log level changed",
+                &format!("{}: {} for {seconds}s", entry.target, entry.level),
+            );
+            Ok(format!("{} {}", entry.target, entry.until))
+        }
+        ["LOG-SET-LEVEL", ..] => Err(anyhow!(
+            "usage: LOG-SET-LEVEL <component> <level> <seconds>"
+        )),
+        ["LOG-RESET", component] => {
+            if !crate::log::level::reset(component)? {
+                return Ok("unchanged".to_string());
+            }
+            info!("log level of {component} reset");
+            audit_ops.log_config_changed("Log level changed", &format!("{component}: reset"));
+            Ok("changed".to_string())
+        }
+        _ => Err(anyhow!("usage: LOG-RESET <component>")),
+    }
+}
+
+fn set_service_enabled(service: &str, enabled: bool) -> anyhow::Result<String> {
+    let rotation =
+        crate::server::rotation::get_global().ok_or_else(|| anyhow!("no server is running"))?;
+    if !rotation.set_enabled(service, enabled)? {
+        return Ok("unchanged".to_string());
+    }
+    let state = if enabled { "enabled" } else { "disabled" };
+    info!("service {service} {state}");
+    let audit_ops = DefaultIcapAuditOps::new(NodeName::new_static("g3icap"), true);
+    audit_ops.log_config_changed(
+        "Service rotation changed",
+        &format!("service {service} {state}"),
+    );
+    Ok("changed".to_string())
+}
+
+/// Reload the modules the transactions of the service go through
+fn reload_service(service: &str) -> anyhow::Result<Vec<String>> {
+    let service = service.trim_matches('/');
+    let rotation =
+        crate::server::rotation::get_global().ok_or_else(|| anyhow!("no server is running"))?;
+    let modules =
+        crate::server::modules::get_global().ok_or_else(|| anyhow!("no module loaded"))?;
+    if !rotation.is_known(service) {
+        return Err(anyhow!("no service named {service}"));
+    }
+    let names: Vec<String> = if rotation.is_scripted(service) {
+        vec![format!("expression/{service}")]
+    } else {
+        let pipeline = crate::server::pipelines::get_global().and_then(|p| p.pipeline(service));
+        let stages: &[PipelineStage] = match &pipeline {
+            Some(pipeline) => pipeline.stages(),
+            None => &[
+                PipelineStage::Waf,
+                PipelineStage::ContentFilter,
+                PipelineStage::Antivirus,
+            ],
+        };
+        stages
+            .iter()
+            .filter(|s| **s != PipelineStage::Waf || modules.waf().is_some())
+            .map(|s| s.as_str().to_string())
+            .collect()
+    };
+    for name in &names {
+        reload_module(name)?;
+    }
+    Ok(names)
+}
+
+fn reload_module(name: &str) -> anyhow::Result<()> {
+    let modules =
+        crate::server::modules::get_global().ok_or_else(|| anyhow!("no module loaded"))?;
+    block_on(modules.reload(name))
+}
+
+/// Handle a policy promotion command, returns the reply after `OK`
+fn policy_command(cmd: &str) -> anyhow::Result<String> {
+    let args: Vec<&str> = cmd.split_whitespace().collect();
+    let (verb, rule_set, rest) = match args[..] {
+        [verb, rule_set, ref rest @ ..] => (verb, rule_set, rest),
+        [verb] => return Err(anyhow!("usage: {verb} <rule set>")),
+        [] => return Err(anyhow!("empty command")),
+    };
+    let target: ShadowTarget = rule_set.parse()?;
+    let modules =
+        crate::server::modules::get_global().ok_or_else(|| anyhow!("no module loaded"))?;
+    match (verb, rest) {
+        ("POLICY-PROMOTE", [] | [_] | [_, _]) => {
+            let parse = |s: &str| s.parse::<u64>().map_err(|_| anyhow!("invalid number {s}"));
+            let activate_at = rest.first().map(|s| parse(s)).transpose()?;
+            let revert_after = rest
+                .get(1)
+                .map(|s| parse(s).map(Duration::from_secs))
+                .transpose()?;
+            let scheduled = block_on(policy_schedule::schedule(
+                &modules,
+                target,
+                activate_at.unwrap_or_default(),
+                revert_after,
+            ))?;
+            Ok(scheduled
+                .map(|s| {
+                    let revert_at = s.revert_at.map(|t| t.to_string());
+                    format!("{} {}", s.activate_at, revert_at.as_deref().unwrap_or("-"))
+                })
+                .unwrap_or_default())
+        }
+        ("POLICY-PROMOTE", _) => Err(anyhow!(
+            "usage: POLICY-PROMOTE <rule set> [<unix time> [<seconds>]]"
+        )),
+        ("POLICY-CONFIRM", []) => {
+            let state = policy_schedule::confirm(target)?;
+            info!("promotion of {rule_set} confirmed");
+            let audit_ops = DefaultIcapAuditOps::new(NodeName::new_static("g3icap"), true);
+            audit_ops.log_config_changed(
+                "Rules promotion confirmed",
+                &format!("{} rules", target.as_str()),
+            );
+            Ok(state.as_str().to_string())
+        }
+        ("POLICY-REVERT", []) => match block_on(policy_schedule::revert(&modules, target))? {
+            RevertOutcome::Cancelled => {
+                info!("scheduled promotion of {rule_set} cancelled");
+                Ok("cancelled".to_string())
+            }
+            RevertOutcome::Reverted => Ok("reverted".to_string()),
+        },
+        _ => Err(anyhow!("usage: {verb} <rule set>")),
+    }
+}
+
+/// Evaluate a synthetic request described in JSON
+fn policy_test(request: &str) -> anyhow::Result<WhatIfReport> {
+    let request: WhatIfRequest =
+        serde_json::from_str(request).map_err(|e| anyhow!("invalid request: {e}"))?;
+    let modules =
+        crate::server::modules::get_global().ok_or_else(|| anyhow!("no module loaded"))?;
+    block_on(what_if::evaluate(&modules, &request))
+}
+
+/// Bind the service to another pipeline, returns the previous pipeline
+pub(crate) fn set_pipeline(service: &str, pipeline: &str) -> anyhow::Result<Option<String>> {
+    let pipelines =
+        crate::server::pipelines::get_global().ok_or_else(|| anyhow!("no server is running"))?;
+    let previous = pipelines.set_pipeline(service, pipeline)?;
+    let previous_name = previous.as_deref().unwrap_or("all stages");
+    info!("service {service} switched from pipeline {previous_name} to {pipeline}");
+    let audit_ops = DefaultIcapAuditOps::new(NodeName::new_static("g3icap"), true);
+    audit_ops.log_config_changed(
+        "Service pipeline changed",
+        &format!("service {service}: pipeline {previous_name} -> {pipeline}"),
+    );
+    Ok(previous)
+}
+
+/// Run a future to completion from a command
+///
+/// The commands are served on the blocking threads of the runtime, so the
+/// future is driven by the runtime they belong to.
+fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Handle::current().block_on(future)
+}
@@ -0,0 +1,487 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Listener handover between the old and the new daemon process
+//!
+//! The running daemon serves a Unix socket in the control directory. On an
+//! upgrade the new process connects to it and receives the listening sockets
+//! as SCM_RIGHTS ancillary data, so it accepts the connections queued on them
+//! instead of binding new sockets. The old daemon keeps accepting until the
+//! new one reports it is ready, then stops and exits after a drain period.
+//!
+//! The protocol is line based:
+//!
+//! - `UPGRADE`, sent by `g3icap-ctl upgrade`: the daemon starts a new copy of
+//!   itself and answers `OK <pid>` or `ERR <reason>`
+//! - `LISTENERS`, sent by the new process: answered by `LISTENERS <count>` and
+//!   the address of each listener on its own line, with the sockets attached
+//! - `READY` or `CANCEL`, sent by the new process once it has started or
+//!   failed to: the old daemon answers `READY` by `DONE`, after which it no
+//!   longer accepts connections
+//!
+//! Any other line is a command of `g3icap-ctl`, answered as described in
+//! [`super::command`].
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::mem;
+use std::net::{SocketAddr, TcpListener};
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::ptr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use anyhow::{Context, anyhow};
+use log::{info, warn};
+
+use super::command;
+
+/// Name of the handover socket in the control directory
+pub const SOCKET_NAME: &str = "g3icap-upgrade.sock";
+
+/// Set by the old daemon to the handover socket path when it starts the new one
+pub const ENV_HANDOVER_SOCKET: &str = "G3ICAP_HANDOVER_SOCKET";
+
+/// Maximum number of sockets in one SCM_RIGHTS message
+const MAX_FDS: usize = 253;
+pub(super) const IO_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time the new process has to load its config and start after taking the listeners
+const START_TIMEOUT: Duration = Duration::from_secs(60);
+/// Time the old daemon waits for its connections after the handover
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Dups of the listening sockets of this process, handed over on upgrade
+static LISTENERS: Mutex<Vec<(SocketAddr, OwnedFd)>> = Mutex::new(Vec::new());
+/// Listening sockets received from the old daemon, not yet taken by a server
+static INHERITED: Mutex<Vec<(SocketAddr, OwnedFd)>> = Mutex::new(Vec::new());
+/// Connection to the old daemon, kept until the new process is ready
+static OLD_DAEMON: Mutex<Option<UnixStream>> = Mutex::new(None);
+static RELEASED: AtomicBool = AtomicBool::new(false);
+
+/// Path of the handover socket of this process
+pub fn socket_path() -> PathBuf {
+    g3_daemon::opts::control_dir().join(SOCKET_NAME)
+}
+
+/// Register a listening socket, to be handed over on upgrade
+pub fn register_listener(listener: &TcpListener) -> io::Result<()> {
+    let addr = listener.local_addr()?;
+    let fd = listener.as_fd().try_clone_to_owned()?;
+    LISTENERS.lock().unwrap().push((addr, fd));
+    Ok(())
+}
+
+/// Check if the listeners were handed over to a new process
+///
+/// The servers stop accepting connections once this is set.
+pub fn is_released() -> bool {
+    RELEASED.load(Ordering::Acquire)
+}
+
+/// Stop accepting connections, the established ones are still served
+///
+/// The listeners can not be handed over to a new process anymore.
+pub fn drain() {
+    info!("draining, no more connections are accepted");
+    release();
+}
+
+fn release() {
+    RELEASED.store(true, Ordering::Release);
+    LISTENERS.lock().unwrap().clear();
+}
+
+/// Take the listening socket received from the old daemon for this address
+pub fn take_inherited(addr: &str) -> Option<TcpListener> {
+    let addr = addr.parse::<SocketAddr>().ok()?;
+    let mut inherited = INHERITED.lock().unwrap();
+    let index = inherited.iter().position(|(a, _)| *a == addr)?;
+    let (_, fd) = inherited.swap_remove(index);
+    Some(TcpListener::from(fd))
+}
+
+/// Close the received listening sockets that no server took
+pub fn close_inherited() {
+    for (addr, _) in INHERITED.lock().unwrap().drain(..) {
+        info!("closed inherited listener {addr} which is no longer configured");
+    }
+}
+
+/// Receive the listening sockets of the old daemon
+///
+/// Returns the number of received sockets. The connection is kept open, the
+/// old daemon stops accepting when [`notify_ready`] is called.
+pub fn receive_listeners(path: &Path) -> io::Result<usize> {
+    let stream = UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    (&stream).write_all(b"LISTENERS\n")?;
+
+    let mut buf = [0u8; 4096];
+    let (n, fds) = recv_with_fds(&stream, &mut buf)?;
+    let mut data = buf[..n].to_vec();
+    let addrs = loop {
+        if let Some(addrs) = parse_listeners(&data)? {
+            break addrs;
+        }
+        let n = (&stream).read(&mut buf)?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        data.extend_from_slice(&buf[..n]);
+    };
+    if addrs.len() != fds.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("got {} sockets for {} listeners", fds.len(), addrs.len()),
+        ));
+    }
+
+    let count = addrs.len();
+    INHERITED.lock().unwrap().extend(addrs.into_iter().zip(fds));
+    *OLD_DAEMON.lock().unwrap() = Some(stream);
+    Ok(count)
+}
+
+/// Tell the old daemon the new process has started, and wait for it to stop
+/// accepting connections
+pub fn notify_ready() -> io::Result<()> {
+    let Some(stream) = OLD_DAEMON.lock().unwrap().take() else {
+        return Ok(());
+    };
+    (&stream).write_all(b"READY\n")?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    if line.trim() != "DONE" {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unexpected reply {:?}", line.trim()),
+        ));
+    }
+    Ok(())
+}
+
+/// Tell the old daemon the new process failed to start
+pub fn notify_cancel() {
+    if let Some(stream) = OLD_DAEMON.lock().unwrap().take() {
+        let _ = (&stream).write_all(b"CANCEL\n");
+    }
+}
+
+/// Ask the daemon serving the handover socket to upgrade itself
+///
+/// Returns the pid of the new process.
+pub fn request_upgrade(path: &Path) -> anyhow::Result<u32> {
+    let stream = UnixStream::connect(path)
+        .with_context(|| format!("failed to connect to {}", path.display()))?;
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    (&stream).write_all(b"UPGRADE\n")?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    match line.trim().split_once(' ') {
+        Some(("OK", pid)) => pid
+            .parse()
+            .map_err(|_| anyhow!("invalid pid {pid} in reply")),
+        Some(("ERR", reason)) => Err(anyhow!("upgrade failed: {reason}")),
+        _ => Err(anyhow!("unexpected reply {:?}", line.trim())),
+    }
+}
+
+/// Serve the handover socket of this process
+pub fn spawn_server() -> anyhow::Result<()> {
+    let path = socket_path();
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create control dir {}", dir.display()))?;
+    }
+    if path.exists() {
+        if UnixStream::connect(&path).is_ok() {
+            return Err(anyhow!("{} is served by another daemon", path.display()));
+        }
+        std::fs::remove_file(&path)
+            .with_context(|| format!("failed to remove stale socket {}", path.display()))?;
+    }
+    let listener = tokio::net::UnixListener::bind(&path)
+        .with_context(|| format!("failed to bind to {}", path.display()))?;
+
+    tokio::spawn(async move {
+        // sent by the connection the listeners were handed over on
+        let (released_tx, mut released_rx) = tokio::sync::mpsc::channel::<()>(1);
+        loop {
+            let stream = tokio::select! {
+                _ = released_rx.recv() => break,
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        warn!("failed to accept handover connection: {e}");
+                        continue;
+                    }
+                },
+            };
+            // the clients are served concurrently, a new process holds its
+            // connection until it has started
+            let path = path.clone();
+            let released_tx = released_tx.clone();
+            tokio::spawn(async move {
+                let handled = tokio::task::spawn_blocking(move || {
+                    let stream = stream.into_std()?;
+                    stream.set_nonblocking(false)?;
+                    handle_client(stream, &path)
+                })
+                .await;
+                match handled {
+                    Ok(Ok(true)) => {
+                        let _ = released_tx.send(()).await;
+                    }
+                    Ok(Ok(false)) => {}
+                    Ok(Err(e)) => warn!("handover connection error: {e}"),
+                    Err(e) => warn!("handover task failed: {e}"),
+                }
+            });
+        }
+
+        info!(
+            "listeners handed over, exiting in {}s",
+            DRAIN_TIMEOUT.as_secs()
+        );
+        tokio::time::sleep(DRAIN_TIMEOUT).await;
+        std::process::exit(0);
+    });
+    Ok(())
+}
+
+/// Handle a handover connection, returns true if the listeners were released
+fn handle_client(stream: UnixStream, path: &Path) -> io::Result<bool> {
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    match line.trim() {
+        "UPGRADE" => {
+            let reply = match spawn_new_daemon(path) {
+                Ok(pid) => {
+                    info!("started new daemon process {pid} for upgrade");
+                    format!("OK {pid}\n")
+                }
+                Err(e) => format!("ERR {e}\n"),
+            };
+            (&stream).write_all(reply.as_bytes())?;
+            Ok(false)
+        }
+        "LISTENERS" => {
+            send_listeners(&stream)?;
+            stream.set_read_timeout(Some(START_TIMEOUT))?;
+            line.clear();
+            reader.read_line(&mut line)?;
+            if line.trim() != "READY" {
+                warn!("new daemon process failed to start, keep serving");
+                return Ok(false);
+            }
+            release();
+            // the new process serves the handover socket from now on
+            let _ = std::fs::remove_file(path);
+            (&stream).write_all(b"DONE\n")?;
+            Ok(true)
+        }
+        cmd => {
+            (&stream).write_all(command::handle(cmd).as_bytes())?;
+            Ok(false)
+        }
+    }
+}
+
+fn spawn_new_daemon(path: &Path) -> io::Result<u32> {
+    let mut child = Command::new(std::env::current_exe()?)
+        .args(std::env::args_os().skip(1))
+        .env(ENV_HANDOVER_SOCKET, path)
+        .stdin(Stdio::null())
+        .spawn()?;
+    let pid = child.id();
+    // reap the process if it exits before this one
+    std::thread::spawn(move || child.wait());
+    Ok(pid)
+}
+
+fn send_listeners(stream: &UnixStream) -> io::Result<()> {
+    let listeners = LISTENERS.lock().unwrap();
+    if listeners.len() > MAX_FDS {
+        return Err(io::Error::other(format!(
+            "too many listeners: {}",
+            listeners.len()
+        )));
+    }
+    let mut data = format!("LISTENERS {}\n", listeners.len());
+    for (addr, _) in listeners.iter() {
+        data.push_str(&format!("{addr}\n"));
+    }
+    let fds = listeners
+        .iter()
+        .map(|(_, fd)| fd.as_raw_fd())
+        .collect::<Vec<_>>();
+    send_with_fds(stream, data.as_bytes(), &fds)
+}
+
+/// Parse the listener list, returns `None` if it is not complete yet
+fn parse_listeners(data: &[u8]) -> io::Result<Option<Vec<SocketAddr>>> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let text = std::str::from_utf8(data).map_err(|e| invalid(e.to_string()))?;
+    let mut lines = text.split_inclusive('\n').filter(|l| l.ends_with('\n'));
+    let Some(header) = lines.next() else {
+        return Ok(None);
+    };
+    let count = header
+        .trim()
+        .strip_prefix("LISTENERS ")
+        .and_then(|n| n.parse::<usize>().ok())
+        .ok_or_else(|| invalid(format!("unexpected reply {:?}", header.trim())))?;
+    let addrs = lines
+        .take(count)
+        .map(|l| {
+            l.trim()
+                .parse::<SocketAddr>()
+                .map_err(|_| invalid(format!("invalid listener address {:?}", l.trim())))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    Ok((addrs.len() == count).then_some(addrs))
+}
+
+/// Buffer for control messages, aligned for `cmsghdr`
+fn cmsg_buffer(fds: usize) -> Vec<u64> {
+    let space = unsafe { libc::CMSG_SPACE((fds * mem::size_of::<RawFd>()) as u32) } as usize;
+    vec![0u64; space.div_ceil(mem::size_of::<u64>())]
+}
+
+fn send_with_fds(stream: &UnixStream, data: &[u8], fds: &[RawFd]) -> io::Result<()> {
+    if fds.is_empty() {
+        return (&*stream).write_all(data);
+    }
+
+    let mut cmsg_buf = cmsg_buffer(fds.len());
+    let mut iov = libc::iovec {
+        iov_base: data.as_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = cmsg_buf.as_mut_ptr().cast();
+    msg.msg_controllen = mem::size_of_val(cmsg_buf.as_slice()) as _;
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of_val(fds) as u32) as _;
+        ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg).cast(), fds.len());
+    }
+
+    let n = unsafe { libc::sendmsg(stream.as_raw_fd(), &msg, 0) };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // the sockets went with the first part, the rest is plain data
+    (&*stream).write_all(&data[n as usize..])
+}
+
+fn recv_with_fds(stream: &UnixStream, buf: &mut [u8]) -> io::Result<(usize, Vec<OwnedFd>)> {
+    let mut cmsg_buf = cmsg_buffer(MAX_FDS);
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = cmsg_buf.as_mut_ptr().cast();
+    msg.msg_controllen = mem::size_of_val(cmsg_buf.as_slice()) as _;
+
+    let n = unsafe { libc::recvmsg(stream.as_raw_fd(), &mut msg, 0) };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut fds = Vec::new();
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(cmsg);
+                let len = (*cmsg).cmsg_len as usize - (data as usize - cmsg as usize);
+                for i in 0..len / mem::size_of::<RawFd>() {
+                    let fd = ptr::read_unaligned(data.cast::<RawFd>().add(i));
+                    libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+                    fds.push(OwnedFd::from_raw_fd(fd));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        return Err(io::Error::other("listening sockets truncated"));
+    }
+    Ok((n as usize, fds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listener_list() {
+        assert!(parse_listeners(b"").unwrap().is_none());
+        assert!(
+            parse_listeners(b"LISTENERS 2\n0.0.0.0:1344\n")
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            parse_listeners(b"LISTENERS 1\n127.0.0.1:13")
+                .unwrap()
+                .is_none()
+        );
+        let addrs = parse_listeners(b"LISTENERS 2\n0.0.0.0:1344\n[::1]:11344\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(addrs.len(), 2);
+        assert_eq!(addrs[1], "[::1]:11344".parse().unwrap());
+        assert!(
+            parse_listeners(b"LISTENERS 0\n")
+                .unwrap()
+                .unwrap()
+                .is_empty()
+        );
+        assert!(parse_listeners(b"ERR busy\n").is_err());
+        assert!(parse_listeners(b"LISTENERS 1\nlocalhost\n").is_err());
+    }
+
+    #[test]
+    fn pass_listening_socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (a, b) = UnixStream::pair().unwrap();
+
+        let data = format!("LISTENERS 1\n{addr}\n");
+        send_with_fds(&a, data.as_bytes(), &[listener.as_raw_fd()]).unwrap();
+        drop(listener);
+
+        let mut buf = [0u8; 128];
+        let (n, mut fds) = recv_with_fds(&b, &mut buf).unwrap();
+        assert_eq!(parse_listeners(&buf[..n]).unwrap().unwrap(), [addr]);
+        assert_eq!(fds.len(), 1);
+
+        // the received socket still listens on the same address
+        let received = TcpListener::from(fds.pop().unwrap());
+        assert_eq!(received.local_addr().unwrap(), addr);
+        let client = std::net::TcpStream::connect(addr).unwrap();
+        assert_eq!(
+            received.accept().unwrap().0.peer_addr().unwrap(),
+            client.local_addr().unwrap()
+        );
+    }
+}
@@ -15,6 +15,10 @@ pub use upgrade::UpgradeActor;
 mod local;
 pub use local::{DaemonController, UniqueController};
 
+pub mod admin;
+pub mod command;
+pub mod handover;
+
 #[allow(dead_code)]
 static IO_MUTEX: Mutex<Option<Mutex<()>>> = Mutex::const_new(Some(Mutex::const_new(())));
 
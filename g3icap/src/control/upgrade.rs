@@ -5,53 +5,69 @@
 
 //! Upgrade actor for ICAP server
 
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use rustix::process::{Pid, Signal, kill_process, test_kill_process};
+
+use super::handover;
+
+/// Name of the PID file of the daemon in the control directory
+const PID_FILE_NAME: &str = "g3icap.pid";
+/// Time given to the old daemon to stop after SIGTERM
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
 /// Upgrade actor following G3Proxy pattern
 pub struct UpgradeActor;
 
 impl UpgradeActor {
+    /// Check if this process was started by the old daemon to replace it
+    pub fn requested() -> bool {
+        std::env::var_os(handover::ENV_HANDOVER_SOCKET).is_some()
+    }
+
     /// Connect to old daemon during upgrade
+    ///
+    /// The listening sockets of the old daemon are taken over if it serves
+    /// the handover socket. Otherwise it is stopped, unless it started this
+    /// process, so that the listen addresses can be bound again.
     pub fn connect_to_old_daemon() {
-        // Implementation for connecting to old daemon during upgrade
-        // This handles graceful shutdown of the old daemon process
-        // and ensures smooth transition during service updates
-        
-        // Check for existing daemon PID file
-        let pid_file = std::path::Path::new("/var/run/g3icap.pid");
-        if pid_file.exists() {
-            // Read PID from file
-            if let Ok(pid_str) = std::fs::read_to_string(pid_file) {
-                if let Ok(pid) = pid_str.trim().parse::<u32>() {
-                    // Check if process is still running
-                    if is_process_running(pid) {
-                        // Send SIGTERM to old daemon
-                        if let Err(e) = std::process::Command::new("kill")
-                            .arg("-TERM")
-                            .arg(pid.to_string())
-                            .output()
-                        {
-                            eprintln!("Failed to send SIGTERM to old daemon: {}", e);
-                        } else {
-                            // Wait for graceful shutdown
-                            std::thread::sleep(std::time::Duration::from_secs(5));
-                            
-                            // Check if process is still running
-                            if is_process_running(pid) {
-                                // Force kill if still running
-                                let _ = std::process::Command::new("kill")
-                                    .arg("-KILL")
-                                    .arg(pid.to_string())
-                                    .output();
-                            }
-                        }
-                    }
+        let path = std::env::var_os(handover::ENV_HANDOVER_SOCKET)
+            .map(PathBuf::from)
+            .unwrap_or_else(handover::socket_path);
+        if path.exists() {
+            match handover::receive_listeners(&path) {
+                Ok(n) => {
+                    info!("took over {n} listeners from the old daemon");
+                    return;
                 }
+                Err(e) => warn!("failed to take over listeners from the old daemon: {e}"),
             }
-            
-            // Clean up PID file
-            let _ = std::fs::remove_file(pid_file);
+        }
+        if !Self::requested() {
+            stop_old_daemon();
         }
     }
-    
+
+    /// Tell the old daemon this process has started, it stops accepting
+    ///
+    /// This process is then the one stopped by the next upgrade.
+    pub fn finish() {
+        if let Err(e) = handover::notify_ready() {
+            warn!("failed to finish the listener handover: {e}");
+        }
+        if let Err(e) = write_pid_file() {
+            warn!("failed to write pid file {}: {e}", pid_file().display());
+        }
+    }
+
+    /// Tell the old daemon this process failed to start, it keeps serving
+    pub fn cancel() {
+        handover::notify_cancel();
+    }
 
     /// Handle upgrade action
     pub async fn handle_upgrade(&self) -> anyhow::Result<()> {
@@ -60,13 +76,59 @@ impl UpgradeActor {
     }
 }
 
+/// Path of the PID file of the daemon, in the control directory
+fn pid_file() -> PathBuf {
+    g3_daemon::opts::control_dir().join(PID_FILE_NAME)
+}
+
+/// Record this process as the daemon to stop on the next upgrade
+fn write_pid_file() -> io::Result<()> {
+    let pid = rustix::process::getpid().as_raw_nonzero();
+    std::fs::write(pid_file(), format!("{pid}\n"))
+}
+
+/// Stop the old daemon found in the PID file
+fn stop_old_daemon() {
+    let pid_file = pid_file();
+    let content = match std::fs::read_to_string(&pid_file) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+        Err(e) => {
+            warn!("failed to read pid file {}: {e}", pid_file.display());
+            return;
+        }
+    };
+    let Some(pid) = content.trim().parse().ok().and_then(Pid::from_raw) else {
+        warn!("invalid pid file {}", pid_file.display());
+        return;
+    };
+    if pid == rustix::process::getpid() {
+        return;
+    }
+
+    if is_process_running(pid) {
+        let raw = pid.as_raw_nonzero();
+        info!("stopping the old daemon {raw}");
+        if let Err(e) = kill_process(pid, Signal::TERM) {
+            warn!("failed to send SIGTERM to the old daemon {raw}: {e}");
+            return;
+        }
+        let deadline = Instant::now() + STOP_TIMEOUT;
+        while is_process_running(pid) && Instant::now() < deadline {
+            std::thread::sleep(STOP_CHECK_INTERVAL);
+        }
+        if is_process_running(pid) {
+            warn!("the old daemon {raw} did not stop in {STOP_TIMEOUT:?}, killing it");
+            if let Err(e) = kill_process(pid, Signal::KILL) {
+                warn!("failed to send SIGKILL to the old daemon {raw}: {e}");
+            }
+        }
+    }
+    let _ = std::fs::remove_file(&pid_file);
+}
+
 /// Check if process is running
-fn is_process_running(pid: u32) -> bool {
-    // Check if process exists by sending signal 0
-    std::process::Command::new("kill")
-        .arg("-0")
-        .arg(pid.to_string())
-        .output()
-        .map(|output| output.status.success())
-        .unwrap_or(false)
+fn is_process_running(pid: Pid) -> bool {
+    // signal 0 only checks that the process exists
+    test_kill_process(pid).is_ok()
 }
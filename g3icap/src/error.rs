@@ -279,6 +279,37 @@ impl IcapError {
     }
 }
 
+/// Source of the errors of transactions aborted by the client
+#[derive(Error, Debug)]
+#[error("client aborted the transaction")]
+pub struct ClientAborted;
+
+impl IcapError {
+    /// Create the error of a transaction aborted by the client
+    pub fn client_aborted(message: impl Into<String>) -> Self {
+        Self::Network {
+            message: message.into(),
+            address: None,
+            context: None,
+            source: Some(Box::new(ClientAborted)),
+        }
+    }
+
+    /// Check if the client aborted the transaction
+    ///
+    /// These are part of normal operation, as proxies drop the connection
+    /// whenever their own client goes away, and are not logged as errors.
+    pub fn is_client_abort(&self) -> bool {
+        match self {
+            Self::Network {
+                source: Some(source),
+                ..
+            } => source.is::<ClientAborted>(),
+            _ => false,
+        }
+    }
+}
+
 /// Error severity levels
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum ErrorSeverity {
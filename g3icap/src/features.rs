@@ -0,0 +1,207 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Optional capabilities of the server
+//!
+//! The heavyweight subsystems are behind cargo features, so that a minimal
+//! build leaves them and their dependencies out. The registry tells which of
+//! them this binary supports, for the version report and the admin status,
+//! and lets the config reject the sections needing a missing one. Those which
+//! can be switched off without breaking the transactions in flight also have
+//! a runtime toggle, set by the `features` section of the server config or
+//! by the admin API.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::anyhow;
+use serde::Serialize;
+
+/// An optional capability of the server
+pub struct Feature {
+    name: &'static str,
+    description: &'static str,
+    compiled: bool,
+    /// Runtime toggle, for the capabilities which can be switched off safely
+    toggle: Option<AtomicBool>,
+}
+
+/// Content disarm and reconstruction of office and PDF documents
+pub static CDR: Feature = Feature::toggleable(
+    "cdr",
+    "content disarm and reconstruction of office and PDF documents",
+    cfg!(feature = "cdr"),
+);
+
+/// Export of the transaction traces to an OpenTelemetry collector
+pub static OTLP: Feature = Feature::toggleable(
+    "otlp",
+    "export of the transaction traces to an OpenTelemetry collector",
+    cfg!(feature = "otlp"),
+);
+
+/// Audit sink producing to Kafka topics
+pub static KAFKA: Feature = Feature::fixed(
+    "kafka",
+    "audit sink producing to Kafka topics",
+    cfg!(feature = "kafka"),
+);
+
+/// Hyperscan engine of the regex patterns
+pub static HYPERSCAN: Feature = Feature::fixed(
+    "hyperscan",
+    "Hyperscan engine of the content filter regex patterns",
+    cfg!(feature = "hyperscan"),
+);
+
+static ALL: [&Feature; 4] = [&CDR, &OTLP, &KAFKA, &HYPERSCAN];
+
+impl Feature {
+    const fn fixed(name: &'static str, description: &'static str, compiled: bool) -> Self {
+        Feature {
+            name,
+            description,
+            compiled,
+            toggle: None,
+        }
+    }
+
+    const fn toggleable(name: &'static str, description: &'static str, compiled: bool) -> Self {
+        Feature {
+            name,
+            description,
+            compiled,
+            toggle: Some(AtomicBool::new(true)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Whether this binary was built with the capability
+    pub fn is_compiled(&self) -> bool {
+        self.compiled
+    }
+
+    /// Whether the capability can be switched off at runtime
+    pub fn is_toggleable(&self) -> bool {
+        self.toggle.is_some()
+    }
+
+    /// Whether the capability is compiled in and not switched off
+    pub fn is_enabled(&self) -> bool {
+        self.compiled
+            && self
+                .toggle
+                .as_ref()
+                .is_none_or(|t| t.load(Ordering::Relaxed))
+    }
+
+    /// Fail if this binary was built without the capability
+    pub fn require(&self, what: &str) -> anyhow::Result<()> {
+        if self.compiled {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "{what} needs the {} feature, which this binary was built without",
+                self.name
+            ))
+        }
+    }
+
+    /// Switch the capability on or off, returning whether it was enabled
+    pub fn set_enabled(&self, enabled: bool) -> anyhow::Result<bool> {
+        let Some(toggle) = &self.toggle else {
+            return Err(anyhow!(
+                "feature {} can not be switched at runtime",
+                self.name
+            ));
+        };
+        if enabled {
+            self.require(&format!("enabling {}", self.name))?;
+        }
+        let previous = toggle.swap(enabled, Ordering::Relaxed);
+        if previous != enabled {
+            log::info!(
+                "feature {} {}",
+                self.name,
+                if enabled { "enabled" } else { "disabled" }
+            );
+        }
+        Ok(previous && self.compiled)
+    }
+
+    fn status(&self) -> FeatureStatus {
+        FeatureStatus {
+            name: self.name,
+            description: self.description,
+            compiled: self.compiled,
+            toggleable: self.is_toggleable(),
+            enabled: self.is_enabled(),
+        }
+    }
+}
+
+/// State of a capability, as reported by the version and the admin status
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FeatureStatus {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub compiled: bool,
+    pub toggleable: bool,
+    pub enabled: bool,
+}
+
+/// All the optional capabilities
+pub fn all() -> &'static [&'static Feature] {
+    &ALL
+}
+
+/// Find a capability by name
+pub fn get(name: &str) -> Option<&'static Feature> {
+    ALL.iter().copied().find(|f| f.name == name)
+}
+
+/// State of all the capabilities
+pub fn report() -> Vec<FeatureStatus> {
+    ALL.iter().map(|f| f.status()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toggles() {
+        static TOGGLED: Feature = Feature::toggleable("toggled", "", true);
+        static MISSING: Feature = Feature::toggleable("missing", "", false);
+        static FIXED: Feature = Feature::fixed("fixed", "", true);
+
+        assert!(TOGGLED.is_enabled());
+        assert!(TOGGLED.set_enabled(false).unwrap());
+        assert!(!TOGGLED.is_enabled());
+        assert!(!TOGGLED.set_enabled(true).unwrap());
+        assert!(TOGGLED.is_enabled());
+
+        assert!(!MISSING.is_enabled());
+        assert!(MISSING.require("a test").is_err());
+        assert!(MISSING.set_enabled(true).is_err());
+        assert!(!MISSING.set_enabled(false).unwrap());
+
+        assert!(FIXED.is_enabled());
+        assert!(FIXED.set_enabled(false).is_err());
+    }
+
+    #[test]
+    fn registry() {
+        assert!(get("cdr").is_some());
+        assert!(get("archive").is_none());
+        let report = report();
+        assert_eq!(report.len(), all().len());
+        let kafka = report.iter().find(|f| f.name == "kafka").unwrap();
+        assert_eq!(kafka.compiled, cfg!(feature = "kafka"));
+        assert!(!kafka.toggleable);
+    }
+}
@@ -20,16 +20,28 @@
 //! # Quick Start
 //!
 //! ```rust,no_run
-//! use g3icap::{IcapServer, IcapError};
-//! use std::net::SocketAddr;
+//! use std::str::FromStr;
+//! use std::sync::Arc;
+//!
+//! use g3icap::config::server::icap_server::IcapServerConfig;
+//! use g3icap::{IcapError, IcapServer, IcapStats};
+//! use g3_types::metrics::NodeName;
 //!
 //! #[tokio::main]
 //! async fn main() -> Result<(), IcapError> {
-//!     let server = IcapServer::new("0.0.0.0:1344".parse()?)?;
+//!     let name = NodeName::from_str("embedded").map_err(|e| IcapError::config_simple(e.to_string()))?;
+//!     let mut config = IcapServerConfig::new(name);
+//!     config.host = "127.0.0.1".to_string();
+//!
+//!     let stats = Arc::new(IcapStats::new());
+//!     let mut server = IcapServer::new_with_stats(config, stats)?;
 //!     server.start().await?;
 //!     Ok(())
 //! }
 //! ```
+//!
+//! See `examples/embedded.rs` for a service running the server next to its
+//! own work, without the daemon controller.
 
 #![deny(clippy::missing_docs_in_private_items)]
 #![deny(clippy::missing_errors_doc)]
@@ -47,12 +59,17 @@ pub mod audit;
 pub mod auth;
 pub mod config;
 pub mod control;
+pub mod features;
+pub mod numa;
 pub mod opts;
 pub mod protocol;
+pub mod replication;
 pub mod server;
 pub mod serve;
 pub mod signal;
 pub mod stat;
+pub mod trace;
+pub mod transaction;
 
 // ICAP-specific modules
 pub mod modules;
@@ -69,3 +86,4 @@ mod version;
 // Re-export commonly used types
 pub use error::{IcapError, IcapResult};
 pub use server::IcapServer;
+pub use stats::IcapStats;
@@ -0,0 +1,47 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+use slog::{Logger, slog_o};
+
+pub(crate) fn get_logger(module_name: &str) -> Option<Logger> {
+    let config = crate::config::log::get_detection_config();
+    let logger_name = format!("ld-{module_name}");
+    let common_values = slog_o!(
+        "daemon_name" => crate::opts::daemon_group(),
+        "log_type" => super::LOG_TYPE_DETECTION,
+        "pid" => std::process::id(),
+        "module" => module_name.to_string(),
+    );
+    config.build_logger(logger_name, super::LOG_TYPE_DETECTION, common_values)
+}
+
+/// A blocked message, with the captured body sample
+pub(crate) struct DetectionEvent<'a> {
+    pub(crate) uri: &'a str,
+    pub(crate) rule_id: &'a str,
+    pub(crate) reason: &'a str,
+    pub(crate) body_size: usize,
+    pub(crate) sample_encoding: &'a str,
+    pub(crate) sample: &'a str,
+    pub(crate) sample_truncated: bool,
+    pub(crate) redacted: usize,
+    pub(crate) quarantine_ref: Option<&'a str>,
+}
+
+impl DetectionEvent<'_> {
+    pub(crate) fn log(&self, logger: &Logger) {
+        slog::info!(logger, "";
+            "uri" => self.uri,
+            "rule_id" => self.rule_id,
+            "reason" => self.reason,
+            "body_size" => self.body_size,
+            "sample_encoding" => self.sample_encoding,
+            "sample" => self.sample,
+            "sample_truncated" => self.sample_truncated,
+            "redacted" => self.redacted,
+            "quarantine_ref" => self.quarantine_ref,
+        );
+    }
+}
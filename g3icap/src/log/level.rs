@@ -0,0 +1,189 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Runtime log level of the components
+//!
+//! The level of the process log is set by the command line. The one of a
+//! component, like the ICAP parser or a module, can be changed at runtime
+//! through the control socket to debug it in production. The change is
+//! temporary, the component is logged at the process level again once its
+//! time is up.
+
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+use anyhow::anyhow;
+use log::LevelFilter;
+use serde::{Deserialize, Serialize};
+
+/// Longest time a changed level lasts
+pub const MAX_DURATION: Duration = Duration::from_secs(86400);
+
+/// Components named by something else than their module
+const COMPONENTS: &[(&str, &str)] = &[
+    ("all", "g3icap"),
+    ("parser", "g3icap::protocol"),
+    ("connection", "g3icap::server::connection"),
+];
+
+/// Top level modules of the crate, which are components on their own
+const CRATE_MODULES: &[&str] = &[
+    "audit",
+    "auth",
+    "config",
+    "control",
+    "replication",
+    "server",
+    "trace",
+];
+
+/// Modules under `modules`, named as the modules themselves
+const MODULES: &[&str] = &[
+    "antivirus",
+    "batcher",
+    "blocklist",
+    "cdr",
+    "content_filter",
+    "detection",
+    "escalation",
+    "expression",
+    "hedge",
+    "list_store",
+    "threat_intel",
+    "verdict_cache",
+    "waf",
+];
+
+static OVERRIDES: Mutex<Vec<LevelOverride>> = Mutex::new(Vec::new());
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// A log level changed at runtime
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LevelOverride {
+    /// Component, as given to `set`
+    pub component: String,
+    /// Log target of the component
+    pub target: String,
+    /// Level the component is logged at, like `debug`
+    pub level: String,
+    /// Unix time at which the level is reverted
+    pub until: u64,
+    #[serde(skip)]
+    generation: u64,
+}
+
+/// Get the log target of a component
+///
+/// A component is a module, like `antivirus`, one of the top level modules
+/// of the crate, a named one like `parser`, or a log target itself.
+pub fn target(component: &str) -> Option<String> {
+    if let Some((_, target)) = COMPONENTS.iter().find(|(name, _)| *name == component) {
+        return Some(target.to_string());
+    }
+    if CRATE_MODULES.contains(&component) {
+        return Some(format!("g3icap::{component}"));
+    }
+    if MODULES.contains(&component) {
+        return Some(format!("g3icap::modules::{component}"));
+    }
+    component
+        .strip_prefix("g3icap::")
+        .filter(|path| {
+            path.split("::")
+                .all(|m| !m.is_empty() && m.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+        })
+        .map(|_| component.to_string())
+}
+
+/// Set the log level of a component for some time
+pub fn set(component: &str, level: &str, duration: Duration) -> anyhow::Result<LevelOverride> {
+    let target = target(component).ok_or_else(|| anyhow!("unknown component {component}"))?;
+    let level_filter =
+        LevelFilter::from_str(level).map_err(|_| anyhow!("invalid log level {level}"))?;
+    if duration.is_zero() || duration > MAX_DURATION {
+        return Err(anyhow!(
+            "duration should be within 1s and {}s",
+            MAX_DURATION.as_secs()
+        ));
+    }
+    // the revert is scheduled on the runtime of the daemon
+    let runtime = tokio::runtime::Handle::try_current()
+        .map_err(|_| anyhow!("no runtime to revert the log level on"))?;
+
+    let until = SystemTime::now() + duration;
+    let generation = GENERATION.fetch_add(1, Ordering::Relaxed);
+    let entry = LevelOverride {
+        component: component.to_string(),
+        target: target.clone(),
+        level: level_filter.as_str().to_ascii_lowercase(),
+        until: until
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        generation,
+    };
+    {
+        let mut overrides = OVERRIDES.lock().unwrap();
+        overrides.retain(|o| o.target != target);
+        overrides.push(entry.clone());
+        g3_daemon::log::process::set_target_level(&target, Some(level_filter));
+    }
+
+    runtime.spawn(async move {
+        tokio::time::sleep(duration).await;
+        if revert(|o| o.target == target && o.generation == generation) {
+            log::info!("log level of {target} reverted");
+        }
+    });
+    Ok(entry)
+}
+
+/// Log a component at the process level again, returns false if it already was
+pub fn reset(component: &str) -> anyhow::Result<bool> {
+    let target = target(component).ok_or_else(|| anyhow!("unknown component {component}"))?;
+    Ok(revert(|o| o.target == target))
+}
+
+/// Get the log levels changed at runtime
+pub fn list() -> Vec<LevelOverride> {
+    OVERRIDES.lock().unwrap().clone()
+}
+
+fn revert<F>(matches: F) -> bool
+where
+    F: Fn(&LevelOverride) -> bool,
+{
+    let mut overrides = OVERRIDES.lock().unwrap();
+    let Some(index) = overrides.iter().position(matches) else {
+        return false;
+    };
+    let entry = overrides.remove(index);
+    g3_daemon::log::process::set_target_level(&entry.target, None);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn component_target() {
+        assert_eq!(target("parser").as_deref(), Some("g3icap::protocol"));
+        assert_eq!(target("control").as_deref(), Some("g3icap::control"));
+        assert_eq!(
+            target("antivirus").as_deref(),
+            Some("g3icap::modules::antivirus")
+        );
+        assert_eq!(
+            target("g3icap::server::listener").as_deref(),
+            Some("g3icap::server::listener")
+        );
+        assert_eq!(target("g3icap::"), None);
+        assert_eq!(target("g3proxy::serve"), None);
+        assert_eq!(target("unknown"), None);
+    }
+}
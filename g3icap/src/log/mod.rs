@@ -6,10 +6,13 @@
 mod shared;
 
 pub(crate) mod connection;
+pub(crate) mod detection;
+pub(crate) mod level;
 pub(crate) mod server;
 
 const LOG_TYPE_CONNECTION: &str = "Connection";
 const LOG_TYPE_SERVER: &str = "Server";
+const LOG_TYPE_DETECTION: &str = "Detection";
 
 use slog::{Logger, slog_o};
 
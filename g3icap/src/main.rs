@@ -4,7 +4,7 @@
  */
 
 use anyhow::Context;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 
 // use g3_daemon::control::{QuitAction, UpgradeAction};
 
@@ -38,7 +38,16 @@ fn main() -> anyhow::Result<()> {
 
     // set up process logger early, only proc args is used inside
     g3_daemon::log::process::setup(&proc_args.daemon_config);
-    if proc_args.daemon_config.need_daemon_controller() {
+
+    if let Some(file) = &proc_args.trusted_keys {
+        let keys = g3icap::config::provenance::TrustedKeys::load(file)?;
+        info!("loaded {} trusted keys from {}", keys.len(), file.display());
+        g3icap::config::provenance::set_trusted_keys(keys)?;
+    }
+
+    if proc_args.daemon_config.need_daemon_controller()
+        || g3icap::control::UpgradeActor::requested()
+    {
         g3icap::control::UpgradeActor::connect_to_old_daemon();
     }
 
@@ -46,13 +55,18 @@ fn main() -> anyhow::Result<()> {
         Ok(c) => c,
         Err(e) => {
             g3_daemon::control::upgrade::cancel_old_shutdown();
+            g3icap::control::UpgradeActor::cancel();
             return Err(e.context(format!("failed to load config, opts: {:?}", &proc_args)));
         }
     };
     debug!("loaded config from {}", config_file.display());
 
     if proc_args.daemon_config.test_config {
+        g3icap::config::schema::validate(config_file)?;
         info!("the format of the config file is ok");
+        if proc_args.deep_test {
+            deep_test_config()?;
+        }
         return Ok(());
     }
 
@@ -63,7 +77,7 @@ fn main() -> anyhow::Result<()> {
     // Initialize global statistics
     g3icap::stat::init_global_stats();
 
-    let stat_join = if let Some(stat_config) = g3_daemon::stat::config::get_global_stat_config() {
+    let stat_threads = if let Some(stat_config) = g3_daemon::stat::config::get_global_stat_config() {
         Some(
             g3icap::stat::spawn_working_threads(stat_config)
                 .context("failed to start stat thread")?,
@@ -76,11 +90,8 @@ fn main() -> anyhow::Result<()> {
         g3_daemon::runtime::worker::spawn_workers().context("failed to spawn workers")?;
     let ret = tokio_run(&proc_args);
 
-    if let Some(handlers) = stat_join {
-        g3icap::stat::stop_working_threads();
-        for handle in handlers {
-            let _ = handle.join();
-        }
+    if let Some(threads) = stat_threads {
+        g3icap::stat::stop_working_threads(threads);
     }
 
     match ret {
@@ -117,12 +128,20 @@ fn tokio_run(args: &ProcArgs) -> anyhow::Result<()> {
         g3_daemon::control::panic::set_hook(&args.daemon_config);
 
         match load_and_spawn().await {
-            Ok(_) => g3_daemon::control::upgrade::finish(),
+            Ok(_) => {
+                g3_daemon::control::upgrade::finish();
+                g3icap::control::UpgradeActor::finish();
+            }
             Err(e) => {
                 g3_daemon::control::upgrade::cancel_old_shutdown();
+                g3icap::control::UpgradeActor::cancel();
                 return Err(e);
             }
         }
+        // listeners are handed over to the next process on upgrade
+        if let Err(e) = g3icap::control::handover::spawn_server() {
+            warn!("failed to serve the upgrade socket: {e:?}");
+        }
 
         // Wait for quit signal
         tokio::signal::ctrl_c().await?;
@@ -146,4 +165,21 @@ async fn load_and_spawn() -> anyhow::Result<()> {
         .await
         .context("failed to spawn all servers")?;
     Ok(())
-}
\ No newline at end of file
+}
+
+fn deep_test_config() -> anyhow::Result<()> {
+    // a runtime of its own, the daemon one is not started in config test mode
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .context("failed to start config test runtime")?;
+    let report = rt.block_on(g3icap::server::check::run())?;
+    print!("{report}");
+    if report.is_ok() {
+        info!("the servers and modules of the config are ok");
+        Ok(())
+    } else {
+        let failed: Vec<&str> = report.failed().map(|c| c.component.as_str()).collect();
+        Err(anyhow::anyhow!("config test failed for {}", failed.join(", ")))
+    }
+}
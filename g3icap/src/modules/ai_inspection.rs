@@ -0,0 +1,367 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Inspection of AI API traffic
+//!
+//! Requests to LLM APIs, in the OpenAI or Anthropic style, carry the user
+//! text in a few JSON string fields such as `prompt` or `messages[].content`,
+//! next to model parameters, tool schemas and base64 encoded images. Matching
+//! the keywords against the raw JSON misses the ones split by escapes like
+//! `\n` or `\u00e9` and matches the ones in any other field, so the profile
+//! parses the body and only matches the text of those fields.
+//!
+//! Prompts may be huge, the text inspected is cut at a budget of estimated
+//! tokens, on a character boundary. Matches can be redacted inside the
+//! strings, the message being forwarded with the same JSON structure.
+
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::protocol::common::{IcapMethod, IcapRequest};
+
+/// ICAP response header carrying the number of redacted spans
+pub const HEADER_AI_REDACTED: &str = "x-ai-redacted";
+
+/// ASCII characters per token, as estimated for the common tokenizers
+const ASCII_CHARS_PER_TOKEN: usize = 4;
+
+/// What to do with an AI API message matching a body keyword rule
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AiInspectionAction {
+    /// Block the message like any other one
+    #[default]
+    Block,
+    /// Redact the matched spans and forward the message
+    Redact,
+}
+
+/// AI API inspection profile
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AiInspectionConfig {
+    /// Enable the profile
+    pub enabled: bool,
+    /// Content types of the bodies parsed as AI API messages
+    pub content_types: Vec<String>,
+    /// Names of the JSON fields holding text, at any depth
+    pub text_fields: Vec<String>,
+    /// Estimated tokens of text inspected per message
+    pub max_tokens: usize,
+    /// Action taken on a message matching a body keyword rule
+    pub action: AiInspectionAction,
+    /// Replacement of the redacted spans
+    pub redaction: String,
+}
+
+impl Default for AiInspectionConfig {
+    fn default() -> Self {
+        AiInspectionConfig {
+            enabled: false,
+            content_types: vec!["application/json".to_string()],
+            text_fields: [
+                "prompt",
+                "content",
+                "text",
+                "input",
+                "system",
+                "instructions",
+            ]
+            .into_iter()
+            .map(str::to_string)
+            .collect(),
+            max_tokens: 32768,
+            action: AiInspectionAction::Block,
+            redaction: "[REDACTED]".to_string(),
+        }
+    }
+}
+
+impl AiInspectionConfig {
+    /// Check if the body of the message is to be parsed
+    pub fn applies(&self, request: &IcapRequest) -> bool {
+        if !self.enabled || request.body.is_empty() {
+            return false;
+        }
+        let Some(content_type) = body_content_type(request) else {
+            return false;
+        };
+        let mime = content_type.split(';').next().unwrap_or_default().trim();
+        self.content_types
+            .iter()
+            .any(|t| mime.eq_ignore_ascii_case(t))
+    }
+}
+
+/// Content type of the encapsulated body, the ICAP one if not encapsulated
+fn body_content_type(request: &IcapRequest) -> Option<&str> {
+    let encapsulated = request.encapsulated.as_ref();
+    let headers = match request.method {
+        IcapMethod::Reqmod => encapsulated.and_then(|e| e.req_hdr.as_ref()),
+        _ => encapsulated.and_then(|e| e.res_hdr.as_ref()),
+    };
+    headers
+        .and_then(|h| h.get(http::header::CONTENT_TYPE))
+        .or_else(|| request.headers.get(http::header::CONTENT_TYPE))
+        .and_then(|v| v.to_str().ok())
+}
+
+/// Estimate the number of tokens of a text
+///
+/// ASCII text counts for a token every few characters, any other character
+/// for a token on its own.
+pub fn estimate_tokens(text: &str) -> usize {
+    let ascii = text.bytes().filter(u8::is_ascii).count();
+    let other = text.chars().filter(|c| !c.is_ascii()).count();
+    ascii.div_ceil(ASCII_CHARS_PER_TOKEN) + other
+}
+
+/// Length of the longest prefix of the text within the token budget
+fn prefix_within(text: &str, budget: usize) -> usize {
+    let mut ascii: usize = 0;
+    let mut other = 0;
+    for (i, c) in text.char_indices() {
+        if c.is_ascii() {
+            ascii += 1;
+        } else {
+            other += 1;
+        }
+        if ascii.div_ceil(ASCII_CHARS_PER_TOKEN) + other > budget {
+            return i;
+        }
+    }
+    text.len()
+}
+
+/// Text of a field of an AI API message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromptText {
+    /// JSON pointer of the field
+    pub pointer: String,
+    /// Text inspected, the beginning of the field past the token budget
+    pub text: String,
+}
+
+/// An AI API message parsed for inspection
+#[derive(Debug)]
+pub struct AiMessage {
+    value: Value,
+    texts: Vec<PromptText>,
+    truncated: bool,
+}
+
+impl AiMessage {
+    /// Parse the body and extract its text fields
+    ///
+    /// Returns `None` if the body is not a JSON object or array.
+    pub fn parse(config: &AiInspectionConfig, body: &[u8]) -> Option<Self> {
+        let value: Value = serde_json::from_slice(body).ok()?;
+        if !value.is_object() && !value.is_array() {
+            return None;
+        }
+        let mut texts = Vec::new();
+        collect(config, &value, &mut String::new(), false, &mut texts);
+
+        let mut budget = config.max_tokens;
+        let mut truncated = false;
+        let mut inspected = Vec::with_capacity(texts.len());
+        for mut field in texts {
+            if budget == 0 {
+                truncated = true;
+                break;
+            }
+            let tokens = estimate_tokens(&field.text);
+            if tokens > budget {
+                let len = prefix_within(&field.text, budget);
+                field.text.truncate(len);
+                truncated = true;
+                budget = 0;
+            } else {
+                budget -= tokens;
+            }
+            inspected.push(field);
+        }
+        Some(AiMessage {
+            value,
+            texts: inspected,
+            truncated,
+        })
+    }
+
+    /// Text fields inspected, in the order of their JSON pointers
+    pub fn texts(&self) -> &[PromptText] {
+        &self.texts
+    }
+
+    /// Check if some text was left out by the token budget
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Replace the spans found by `find` in the inspected text of each field
+    ///
+    /// Returns the number of redacted spans.
+    pub fn redact<F>(&mut self, find: F, replacement: &str) -> usize
+    where
+        F: Fn(&str) -> Vec<Range<usize>>,
+    {
+        let mut redacted = 0;
+        for field in &self.texts {
+            let spans = merge(find(&field.text), &field.text);
+            if spans.is_empty() {
+                continue;
+            }
+            let Some(Value::String(s)) = self.value.pointer_mut(&field.pointer) else {
+                continue;
+            };
+            // the inspected text is a prefix of the field
+            for span in spans.iter().rev() {
+                s.replace_range(span.clone(), replacement);
+            }
+            redacted += spans.len();
+        }
+        redacted
+    }
+
+    /// Serialize the message back into a body
+    pub fn to_body(&self) -> Vec<u8> {
+        serde_json::to_vec(&self.value).unwrap_or_default()
+    }
+}
+
+/// Collect the text fields of the value, `text` if the value is held by one
+fn collect(
+    config: &AiInspectionConfig,
+    value: &Value,
+    pointer: &mut String,
+    text: bool,
+    texts: &mut Vec<PromptText>,
+) {
+    match value {
+        Value::String(s) if text && !s.is_empty() => texts.push(PromptText {
+            pointer: pointer.clone(),
+            text: s.clone(),
+        }),
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                let len = pointer.len();
+                pointer.push_str(&format!("/{i}"));
+                collect(config, item, pointer, text, texts);
+                pointer.truncate(len);
+            }
+        }
+        Value::Object(map) => {
+            for (key, item) in map {
+                let len = pointer.len();
+                pointer.push('/');
+                pointer.push_str(&key.replace('~', "~0").replace('/', "~1"));
+                let text = config.text_fields.iter().any(|f| f == key);
+                collect(config, item, pointer, text, texts);
+                pointer.truncate(len);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Sort and merge the overlapping spans, dropping the ones not on character
+/// boundaries
+fn merge(mut spans: Vec<Range<usize>>, text: &str) -> Vec<Range<usize>> {
+    spans.retain(|s| {
+        s.start < s.end && text.is_char_boundary(s.start) && text.is_char_boundary(s.end)
+    });
+    spans.sort_by_key(|s| s.start);
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(spans.len());
+    for span in spans {
+        match merged.last_mut() {
+            Some(last) if span.start <= last.end => last.end = last.end.max(span.end),
+            _ => merged.push(span),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find_secret(text: &str) -> Vec<Range<usize>> {
+        text.match_indices("secret")
+            .map(|(i, m)| i..i + m.len())
+            .collect()
+    }
+
+    #[test]
+    fn extract_and_redact() {
+        let config = AiInspectionConfig::default();
+        let body = br#"{
+            "model": "secret-model",
+            "system": "be nice",
+            "messages": [
+                {"role": "user", "content": "my secret is secret"},
+                {"role": "user", "content": [
+                    {"type": "text", "text": "top secret"},
+                    {"type": "image", "source": {"data": "c2VjcmV0"}}
+                ]}
+            ]
+        }"#;
+        let mut message = AiMessage::parse(&config, body).unwrap();
+        let pointers: Vec<&str> = message.texts().iter().map(|t| t.pointer.as_str()).collect();
+        assert_eq!(
+            pointers,
+            [
+                "/messages/0/content",
+                "/messages/1/content/0/text",
+                "/system"
+            ]
+        );
+        assert_eq!(message.texts()[0].text, "my secret is secret");
+        assert!(!message.is_truncated());
+
+        assert_eq!(message.redact(find_secret, "***"), 3);
+        let value: Value = serde_json::from_slice(&message.to_body()).unwrap();
+        assert_eq!(value["messages"][0]["content"], "my *** is ***");
+        assert_eq!(value["messages"][1]["content"][0]["text"], "top ***");
+        // other fields are left alone
+        assert_eq!(value["model"], "secret-model");
+        assert_eq!(
+            value["messages"][1]["content"][1]["source"]["data"],
+            "c2VjcmV0"
+        );
+
+        assert!(AiMessage::parse(&config, b"\"secret\"").is_none());
+        assert!(AiMessage::parse(&config, b"secret").is_none());
+    }
+
+    #[test]
+    fn token_budget() {
+        assert_eq!(estimate_tokens("abcdefgh"), 2);
+        assert_eq!(estimate_tokens("abcde"), 2);
+        assert_eq!(estimate_tokens("日本語"), 3);
+
+        let config = AiInspectionConfig {
+            max_tokens: 3,
+            ..Default::default()
+        };
+        let body = r#"{"prompt": ["abcdefgh", "ijkl日本語", "mnop"]}"#;
+        let message = AiMessage::parse(&config, body.as_bytes()).unwrap();
+        assert!(message.is_truncated());
+        let texts: Vec<&str> = message.texts().iter().map(|t| t.text.as_str()).collect();
+        assert_eq!(texts, ["abcdefgh", "ijkl"]);
+
+        // spans past the inspected prefix are not redacted
+        let config = AiInspectionConfig {
+            max_tokens: 2,
+            ..Default::default()
+        };
+        let mut message = AiMessage::parse(&config, br#"{"prompt": "secretsecret"}"#).unwrap();
+        assert_eq!(message.texts()[0].text, "secretse");
+        assert_eq!(message.redact(find_secret, "*"), 1);
+        let value: Value = serde_json::from_slice(&message.to_body()).unwrap();
+        assert_eq!(value["prompt"], "*secret");
+    }
+}
@@ -15,6 +15,7 @@
 //! - Comprehensive reporting and monitoring
 
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
 use tokio::sync::RwLock as TokioRwLock;
 use std::time::{Duration, Instant};
@@ -26,8 +27,60 @@ use serde::{Deserialize, Serialize};
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
 
+use crate::config::provenance::{self, ArtifactKind};
 use crate::protocol::common::{IcapMethod, IcapRequest, IcapResponse};
 use crate::modules::{IcapModule, ModuleConfig, ModuleError, ModuleMetrics};
+use crate::modules::batcher::BatchConfig;
+use crate::modules::hedge::{HedgeConfig, Hedger};
+use crate::modules::metrics::{Counter, Histogram, MetricsRegistry};
+use crate::modules::threat_intel::{self, Reputation, ThreatIntel};
+use crate::modules::verdict_cache::{VerdictCache, VerdictCacheConfig};
+use crate::transaction::TransactionCtx;
+
+/// ICAP response header carrying the name of the detected threat
+pub const HEADER_VIRUS_ID: &str = "x-virus-id";
+
+/// ISTag of the service before the engine version is known
+const DEFAULT_ISTAG: &str = "antivirus-1.0.0";
+
+static QUARANTINE_STATS: QuarantineStats = QuarantineStats::new();
+
+/// Get the quarantine statistics of this process
+pub fn quarantine_stats() -> &'static QuarantineStats {
+    &QUARANTINE_STATS
+}
+
+/// Files quarantined by all the antivirus modules
+pub struct QuarantineStats {
+    files: AtomicU64,
+    bytes: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl QuarantineStats {
+    const fn new() -> Self {
+        QuarantineStats {
+            files: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+        }
+    }
+
+    /// Files written to the quarantine directory
+    pub fn files(&self) -> u64 {
+        self.files.load(Ordering::Relaxed)
+    }
+
+    /// Bytes of the quarantined files
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Files that could not be quarantined
+    pub fn failed(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+}
 
 /// Antivirus engine types
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -65,6 +118,7 @@ pub enum AntivirusEngine {
 
 /// Antivirus configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct AntivirusConfig {
     /// Antivirus engine to use
     pub engine: AntivirusEngine,
@@ -92,6 +146,18 @@ pub struct AntivirusConfig {
     pub enable_threat_intel: bool,
     /// Threat intelligence sources
     pub threat_intel_sources: Vec<String>,
+    /// Micro-batching of the threat intelligence lookups
+    #[serde(default)]
+    pub threat_intel_batch: BatchConfig,
+    /// Redundant engine the slow scans are hedged with
+    #[serde(default)]
+    pub secondary_engine: Option<AntivirusEngine>,
+    /// Hedging of the scans with the secondary engine
+    #[serde(default)]
+    pub hedging: HedgeConfig,
+    /// Cache of the verdicts of the repeated objects, none if not cached
+    #[serde(default)]
+    pub verdict_cache: Option<VerdictCacheConfig>,
     /// YARA-specific configuration
     pub yara_config: Option<YaraConfig>,
 }
@@ -229,6 +295,15 @@ pub struct YaraMatch {
     pub priority: u8,
 }
 
+/// Upper bounds of the scan duration buckets, in milliseconds
+const SCAN_DURATION_BUCKETS: &[u64] = &[1, 5, 10, 50, 100, 500, 1000, 5000, 30000];
+
+/// Metrics registered by the antivirus module
+struct AntivirusMetrics {
+    infected: Arc<Counter>,
+    scan_duration_ms: Arc<Histogram>,
+}
+
 /// Antivirus statistics
 #[derive(Debug, Clone, Default)]
 pub struct AntivirusStats {
@@ -302,6 +377,18 @@ pub struct AntivirusModule {
     quarantine: Arc<RwLock<HashMap<String, QuarantineEntry>>>,
     /// Engine client
     engine_client: Arc<TokioRwLock<Option<Box<dyn AntivirusEngineClient + Send + Sync>>>>,
+    /// Secondary engine client with its hedger, created on init
+    secondary: Option<(Box<dyn AntivirusEngineClient + Send + Sync>, Hedger)>,
+    /// Threat intelligence lookups, created on init
+    threat_intel: Option<ThreatIntel>,
+    /// Service the verdicts are cached for, the module name set on init
+    service: String,
+    /// ISTag of the service, changing with the engine version
+    istag: RwLock<String>,
+    /// Verdicts of the repeated objects, created on init
+    verdict_cache: Option<Arc<VerdictCache<ScanResult>>>,
+    /// Registered metrics, if metrics are enabled
+    registered_metrics: Option<AntivirusMetrics>,
     /// YARA rules (if using YARA engine)
     #[allow(dead_code)]
     yara_rules: Arc<RwLock<HashMap<String, YaraRule>>>,
@@ -340,11 +427,23 @@ impl AntivirusModule {
             metrics: Arc::new(Mutex::new(ModuleMetrics::default())),
             quarantine: Arc::new(RwLock::new(HashMap::new())),
             engine_client: Arc::new(TokioRwLock::new(None)),
+            secondary: None,
+            threat_intel: None,
+            service: "antivirus".to_string(),
+            istag: RwLock::new(DEFAULT_ISTAG.to_string()),
+            verdict_cache: None,
+            registered_metrics: None,
             yara_rules: Arc::new(RwLock::new(HashMap::new())),
             yara_cache: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
+    /// Rename the module, its metrics and verdicts being counted apart, like
+    /// for a copy with candidate rules
+    pub fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+
     /// Create with default configuration
     pub fn with_defaults() -> Self {
         Self::new(AntivirusConfig {
@@ -364,29 +463,17 @@ impl AntivirusModule {
             update_interval: Duration::from_secs(24 * 60 * 60), // 24 hours
             enable_threat_intel: false,
             threat_intel_sources: Vec::new(),
+            threat_intel_batch: Default::default(),
+            secondary_engine: None,
+            hedging: Default::default(),
+            verdict_cache: None,
             yara_config: None,
         })
     }
 
     /// Initialize the antivirus engine
     async fn init_engine(&mut self) -> Result<(), ModuleError> {
-        let mut client: Box<dyn AntivirusEngineClient + Send + Sync> = match &self.config.engine {
-            AntivirusEngine::ClamAV { socket_path, timeout } => {
-                Box::new(ClamAVClient::new(socket_path.clone(), *timeout))
-            }
-            AntivirusEngine::Sophos { endpoint, api_key, timeout } => {
-                Box::new(SophosClient::new(endpoint.clone(), api_key.clone(), *timeout))
-            }
-            AntivirusEngine::YARA { rules_dir, timeout, max_rules, enable_compilation } => {
-                Box::new(YaraClient::new(rules_dir.clone(), *timeout, *max_rules, *enable_compilation))
-            }
-            AntivirusEngine::Custom { command, args, timeout } => {
-                Box::new(CustomClient::new(command.clone(), args.clone(), *timeout))
-            }
-            AntivirusEngine::Mock { simulate_threats, scan_delay } => {
-                Box::new(MockClient::new(*simulate_threats, *scan_delay))
-            }
-        };
+        let mut client = engine_client(&self.config.engine);
 
         // Initialize the engine
         client.init().await?;
@@ -398,6 +485,18 @@ impl AntivirusModule {
         Ok(())
     }
 
+    /// Initialize the secondary engine the slow scans are hedged with
+    async fn init_secondary(&mut self, registry: &MetricsRegistry) -> Result<(), ModuleError> {
+        let Some(engine) = &self.config.secondary_engine else {
+            return Ok(());
+        };
+        let mut client = engine_client(engine);
+        client.init().await?;
+        let hedger = Hedger::new(self.config.hedging, registry)?;
+        self.secondary = Some((client, hedger));
+        Ok(())
+    }
+
     /// Scan content for viruses
     async fn scan_content(&self, data: &[u8], filename: Option<&str>) -> Result<ScanResult, ModuleError> {
         let start_time = Instant::now();
@@ -424,8 +523,36 @@ impl AntivirusModule {
             }
         }
 
+        let sha256 = (self.threat_intel.is_some() || self.verdict_cache.is_some())
+            .then(|| threat_intel::sha256_hex(data));
+
+        // Known threats are reported without an engine scan
+        if let Some(intel) = &self.threat_intel
+            && let Some(sha256) = &sha256
+            && let Some(reputation) = intel.lookup(sha256).await
+        {
+            let result = threat_intel_result(reputation, sha256.clone(), data.len(), start_time.elapsed());
+            self.update_stats(&result, start_time.elapsed()).await;
+            return Ok(result);
+        }
+
+        // Repeated objects get the verdict of their last scan
+        let istag = self.istag();
+        let cache = self.verdict_cache.as_ref().zip(sha256.as_ref());
+        if let Some((cache, sha256)) = cache
+            && let Some(mut result) = cache.lookup(&self.service, &istag, sha256).await
+        {
+            result.scan_duration = start_time.elapsed();
+            result.metadata.insert("cache".to_string(), "hit".to_string());
+            self.update_stats(&result, start_time.elapsed()).await;
+            return Ok(result);
+        }
+
         // Scan the content
         let result = self.scan_with_engine(data, filename).await?;
+        if let Some((cache, sha256)) = cache {
+            cache.store(&self.service, &istag, sha256, result.clone()).await;
+        }
 
         // Update statistics
         self.update_stats(&result, start_time.elapsed()).await;
@@ -467,11 +594,18 @@ impl AntivirusModule {
         let quarantine_path = quarantine_dir.join(format!("{}.quarantine", quarantine_id));
 
         // Write file to quarantine
-        let mut file = File::create(&quarantine_path).await
-            .map_err(|e| ModuleError::ExecutionFailed(format!("Failed to create quarantine file: {}", e)))?;
-        
-        file.write_all(data).await
-            .map_err(|e| ModuleError::ExecutionFailed(format!("Failed to write quarantine file: {}", e)))?;
+        let written = async {
+            let mut file = File::create(&quarantine_path).await
+                .map_err(|e| ModuleError::ExecutionFailed(format!("Failed to create quarantine file: {}", e)))?;
+            file.write_all(data).await
+                .map_err(|e| ModuleError::ExecutionFailed(format!("Failed to write quarantine file: {}", e)))
+        };
+        if let Err(e) = written.await {
+            QUARANTINE_STATS.failed.fetch_add(1, Ordering::Relaxed);
+            return Err(e);
+        }
+        QUARANTINE_STATS.files.fetch_add(1, Ordering::Relaxed);
+        QUARANTINE_STATS.bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
 
         // Create quarantine entry
         let entry = QuarantineEntry {
@@ -504,6 +638,13 @@ impl AntivirusModule {
             stats.infected_files += 1;
         }
 
+        if let Some(registered) = &self.registered_metrics {
+            registered.scan_duration_ms.record(scan_duration.as_millis() as u64);
+            if !result.is_clean {
+                registered.infected.inc();
+            }
+        }
+
         // Update module metrics
         let mut metrics = self.metrics.lock().unwrap();
         metrics.requests_total = stats.total_scans;
@@ -531,20 +672,134 @@ impl AntivirusModule {
         Ok(())
     }
 
+    /// ISTag of the service
+    pub fn istag(&self) -> String {
+        self.istag.read().unwrap().clone()
+    }
+
+    /// Take the ISTag from the version of the engine, the cached verdicts
+    /// being dropped if it changed
+    async fn refresh_istag(&self) -> Result<(), ModuleError> {
+        let engine_client = self.engine_client.read().await;
+        let client = engine_client.as_ref()
+            .ok_or_else(|| ModuleError::ExecutionFailed("Antivirus engine not initialized".to_string()))?;
+        let istag = engine_istag(&client.get_version().await?);
+        let mut current = self.istag.write().unwrap();
+        if *current != istag {
+            if self.config.enable_logging {
+                log::info!("Antivirus ISTag changed from {} to {istag}", *current);
+            }
+            *current = istag;
+        }
+        Ok(())
+    }
+
+    /// Update the definitions of the engine, and the ISTag with them
+    pub async fn update_definitions(&self) -> Result<(), ModuleError> {
+        {
+            let engine_client = self.engine_client.read().await;
+            let client = engine_client.as_ref()
+                .ok_or_else(|| ModuleError::ExecutionFailed("Antivirus engine not initialized".to_string()))?;
+            client.update_definitions().await?;
+        }
+        self.stats.write().unwrap().last_update = Some(Instant::now());
+        self.refresh_istag().await
+    }
+
+    /// Response generator of the service, with its current ISTag
+    fn response_generator(&self) -> crate::protocol::response_generator::IcapResponseGenerator {
+        crate::protocol::response_generator::IcapResponseGenerator::with_service_id(
+            "G3ICAP-Antivirus/1.0.0".to_string(),
+            self.istag(),
+            Some("antivirus-scanner".to_string())
+        )
+    }
+
     /// Scan with engine client
     async fn scan_with_engine(&self, data: &[u8], _filename: Option<&str>) -> Result<ScanResult, ModuleError> {
         let engine_client = self.engine_client.read().await;
         let client = engine_client.as_ref()
             .ok_or_else(|| ModuleError::ExecutionFailed("Antivirus engine not initialized".to_string()))?;
         
-        client.scan_file(data, _filename).await
+        match &self.secondary {
+            Some((secondary, hedger)) => {
+                hedger
+                    .run(client.scan_file(data, _filename), secondary.scan_file(data, _filename))
+                    .await
+            }
+            None => client.scan_file(data, _filename).await,
+        }
     }
 }
 
+/// Create the client of an engine
+fn engine_client(engine: &AntivirusEngine) -> Box<dyn AntivirusEngineClient + Send + Sync> {
+    match engine {
+        AntivirusEngine::ClamAV { socket_path, timeout } => {
+            Box::new(ClamAVClient::new(socket_path.clone(), *timeout))
+        }
+        AntivirusEngine::Sophos { endpoint, api_key, timeout } => {
+            Box::new(SophosClient::new(endpoint.clone(), api_key.clone(), *timeout))
+        }
+        AntivirusEngine::YARA { rules_dir, timeout, max_rules, enable_compilation } => {
+            Box::new(YaraClient::new(rules_dir.clone(), *timeout, *max_rules, *enable_compilation))
+        }
+        AntivirusEngine::Custom { command, args, timeout } => {
+            Box::new(CustomClient::new(command.clone(), args.clone(), *timeout))
+        }
+        AntivirusEngine::Mock { simulate_threats, scan_delay } => {
+            Box::new(MockClient::new(*simulate_threats, *scan_delay))
+        }
+    }
+}
+
+/// ISTag of an engine version, at most 32 characters as RFC 3507 requires
+fn engine_istag(version: &str) -> String {
+    const PREFIX: &str = "antivirus-";
+    let version: String = version
+        .trim()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '_' { c } else { '-' })
+        .take(32 - PREFIX.len())
+        .collect();
+    format!("{PREFIX}{version}")
+}
+
+/// Scan result of content known to the threat intelligence sources
+fn threat_intel_result(
+    reputation: Reputation,
+    sha256: String,
+    size: usize,
+    scan_duration: Duration,
+) -> ScanResult {
+    let mut metadata = HashMap::new();
+    metadata.insert("sha256".to_string(), sha256);
+    metadata.insert("source".to_string(), reputation.source.clone());
+    ScanResult {
+        is_clean: false,
+        threat_name: Some(
+            reputation
+                .threat
+                .unwrap_or_else(|| format!("ThreatIntel.{}", reputation.source)),
+        ),
+        threat_type: Some(ThreatType::Malware),
+        engine: "threat_intel".to_string(),
+        scan_duration,
+        file_size: size as u64,
+        metadata,
+    }
+}
+
+/// Size of the chunks the content is streamed to clamd in
+const CLAMD_CHUNK_SIZE: usize = 64 * 1024;
+
 /// ClamAV client implementation
+///
+/// The content is streamed to clamd with the `INSTREAM` command. The
+/// `socket_path` is the path of the local socket of clamd if absolute, or
+/// the `host:port` address of its TCP socket otherwise.
 pub struct ClamAVClient {
     socket_path: String,
-    #[allow(dead_code)]
     timeout: Duration,
 }
 
@@ -552,49 +807,107 @@ impl ClamAVClient {
     pub fn new(socket_path: String, timeout: Duration) -> Self {
         Self { socket_path, timeout }
     }
+
+    /// Send a command with its stream, if any, and read the reply of clamd
+    async fn command(&self, command: &str, stream: Option<&[u8]>) -> Result<String, ModuleError> {
+        let exchange = async {
+            if self.socket_path.starts_with('/') {
+                let conn = tokio::net::UnixStream::connect(&self.socket_path).await?;
+                clamd_exchange(conn, command, stream).await
+            } else {
+                let conn = tokio::net::TcpStream::connect(&self.socket_path).await?;
+                clamd_exchange(conn, command, stream).await
+            }
+        };
+        match tokio::time::timeout(self.timeout, exchange).await {
+            Ok(Ok(reply)) => Ok(reply),
+            Ok(Err(e)) => Err(ModuleError::ExecutionFailed(format!(
+                "clamd {} {} failed: {}",
+                self.socket_path, command, e
+            ))),
+            Err(_) => Err(ModuleError::ExecutionFailed(format!(
+                "clamd {} {} timed out after {:?}",
+                self.socket_path, command, self.timeout
+            ))),
+        }
+    }
+}
+
+/// Run a `z` prefixed command on a clamd connection, the reply is read
+/// until its NUL terminator
+async fn clamd_exchange<S>(mut conn: S, command: &str, stream: Option<&[u8]>) -> std::io::Result<String>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    use tokio::io::AsyncReadExt;
+
+    conn.write_all(format!("z{command}\0").as_bytes()).await?;
+    if let Some(data) = stream {
+        for chunk in data.chunks(CLAMD_CHUNK_SIZE) {
+            conn.write_all(&(chunk.len() as u32).to_be_bytes()).await?;
+            conn.write_all(chunk).await?;
+        }
+        conn.write_all(&0u32.to_be_bytes()).await?;
+    }
+    conn.flush().await?;
+
+    let mut reply = Vec::new();
+    conn.read_to_end(&mut reply).await?;
+    let reply = reply.strip_suffix(b"\0").unwrap_or(&reply);
+    Ok(String::from_utf8_lossy(reply).trim().to_string())
+}
+
+/// Scan result of an `INSTREAM` reply, `stream: OK` or `stream: <name> FOUND`
+fn clamd_scan_result(reply: &str, size: usize, scan_duration: Duration) -> Result<ScanResult, ModuleError> {
+    let verdict = reply.strip_prefix("stream:").unwrap_or(reply).trim();
+    let threat_name = if verdict == "OK" {
+        None
+    } else if let Some(name) = verdict.strip_suffix(" FOUND") {
+        Some(name.to_string())
+    } else {
+        return Err(ModuleError::ExecutionFailed(format!("clamd scan failed: {reply}")));
+    };
+    Ok(ScanResult {
+        is_clean: threat_name.is_none(),
+        threat_type: threat_name.as_ref().map(|_| ThreatType::Virus),
+        threat_name,
+        engine: "ClamAV".to_string(),
+        scan_duration,
+        file_size: size as u64,
+        metadata: HashMap::new(),
+    })
 }
 
 #[async_trait]
 impl AntivirusEngineClient for ClamAVClient {
     async fn init(&mut self) -> Result<(), ModuleError> {
-        // Check if ClamAV socket exists
-        if !std::path::Path::new(&self.socket_path).exists() {
-            return Err(ModuleError::InitFailed(
-                format!("ClamAV socket not found: {}", self.socket_path)
-            ));
+        match self.command("PING", None).await {
+            Ok(reply) if reply == "PONG" => Ok(()),
+            Ok(reply) => Err(ModuleError::InitFailed(format!(
+                "unexpected reply of clamd {} to PING: {}",
+                self.socket_path, reply
+            ))),
+            Err(e) => Err(ModuleError::InitFailed(e.to_string())),
         }
-        Ok(())
     }
 
     async fn scan_file(&self, data: &[u8], _filename: Option<&str>) -> Result<ScanResult, ModuleError> {
-        // Simulate ClamAV scanning
-        // In a real implementation, this would connect to ClamAV daemon
-        tokio::time::sleep(Duration::from_millis(50)).await;
-
-        // Mock scan result
-        Ok(ScanResult {
-            is_clean: true,
-            threat_name: None,
-            threat_type: None,
-            engine: "ClamAV".to_string(),
-            scan_duration: Duration::from_millis(50),
-            file_size: data.len() as u64,
-            metadata: HashMap::new(),
-        })
+        let start = Instant::now();
+        let reply = self.command("INSTREAM", Some(data)).await?;
+        clamd_scan_result(&reply, data.len(), start.elapsed())
     }
 
     async fn is_healthy(&self) -> bool {
-        std::path::Path::new(&self.socket_path).exists()
+        self.command("PING", None).await.is_ok_and(|reply| reply == "PONG")
     }
 
     async fn update_definitions(&self) -> Result<(), ModuleError> {
-        // Simulate definition update
-        tokio::time::sleep(Duration::from_millis(100)).await;
-        Ok(())
+        // clamd reloads the definitions freshclam downloads on its own
+        self.command("RELOAD", None).await.map(|_| ())
     }
 
     async fn get_version(&self) -> Result<String, ModuleError> {
-        Ok("ClamAV 0.103.0".to_string())
+        self.command("VERSION", None).await
     }
 }
 
@@ -751,10 +1064,9 @@ impl YaraClient {
             if path.extension().and_then(|s| s.to_str()) == Some("yar") || 
                path.extension().and_then(|s| s.to_str()) == Some("yara") {
                 
-                if let Ok(rule) = self.parse_yara_rule(&path).await {
-                    self.rules.insert(rule.name.clone(), rule);
-                    rule_count += 1;
-                }
+                let rule = self.parse_yara_rule(&path).await?;
+                self.rules.insert(rule.name.clone(), rule);
+                rule_count += 1;
             }
         }
 
@@ -765,6 +1077,9 @@ impl YaraClient {
     async fn parse_yara_rule(&self, path: &PathBuf) -> Result<YaraRule, ModuleError> {
         let content = tokio::fs::read_to_string(path).await
             .map_err(|e| ModuleError::InitFailed(format!("Failed to read rule file {}: {}", path.display(), e)))?;
+        // rule packs are only loaded signed if there are trusted keys
+        provenance::check_file(ArtifactKind::YaraRules, path, content.as_bytes())
+            .map_err(|e| ModuleError::InitFailed(e.to_string()))?;
 
         // Simple YARA rule parser (in production, use a proper YARA library)
         let mut rule = YaraRule {
@@ -979,12 +1294,45 @@ impl IcapModule for AntivirusModule {
 
     async fn init(&mut self, config: &ModuleConfig) -> Result<(), ModuleError> {
         // Load configuration from module config
-        if let Ok(antivirus_config) = serde_json::from_value::<AntivirusConfig>(config.config.clone()) {
+        if let Some(antivirus_config) = config.parse_config()? {
             self.config = antivirus_config;
         }
 
         // Initialize the antivirus engine
         self.init_engine().await?;
+        self.refresh_istag().await?;
+        let registry = if self.config.enable_metrics {
+            config.metrics.clone()
+        } else {
+            MetricsRegistry::detached()
+        };
+        self.init_secondary(&registry).await?;
+
+        self.service = config.name.clone();
+        if let Some(cache_config) = self.config.verdict_cache.clone() {
+            let cache = Arc::new(VerdictCache::new(cache_config, &registry)?);
+            cache.replicate(&format!("verdict_cache.{}", self.service));
+            self.verdict_cache = Some(cache);
+        }
+
+        if self.config.enable_threat_intel && !self.config.threat_intel_sources.is_empty() {
+            let threat_intel = ThreatIntel::new(
+                &self.config.threat_intel_sources,
+                self.config.threat_intel_batch,
+                self.config.scan_timeout,
+            )
+            .map_err(|e| ModuleError::InitFailed(format!("{e:#}")))?;
+            self.threat_intel = Some(threat_intel);
+        }
+
+        if self.config.enable_metrics {
+            self.registered_metrics = Some(AntivirusMetrics {
+                infected: config.metrics.counter("infected")?,
+                scan_duration_ms: config
+                    .metrics
+                    .histogram("scan_duration_ms", SCAN_DURATION_BUCKETS)?,
+            });
+        }
 
         if self.config.enable_logging {
             log::info!("Antivirus module initialized with engine: {:?}", self.config.engine);
@@ -993,21 +1341,17 @@ impl IcapModule for AntivirusModule {
         Ok(())
     }
 
-    async fn handle_reqmod(&self, request: &IcapRequest) -> Result<IcapResponse, ModuleError> {
+    async fn handle_reqmod(&self, request: &IcapRequest, ctx: &TransactionCtx) -> Result<IcapResponse, ModuleError> {
         if self.config.enable_logging {
             log::debug!("Processing REQMOD request for antivirus scanning: {}", request.uri);
         }
 
         // Scan the request body
-        let scan_result = self.scan_content(&request.body, None).await?;
+        let scan_result = crate::modules::until_cancelled(ctx, self.scan_content(&request.body, None)).await?;
 
         if scan_result.is_clean {
             // Allow the request - use response generator for proper headers
-            let response_generator = crate::protocol::response_generator::IcapResponseGenerator::with_service_id(
-                "G3ICAP-Antivirus/1.0.0".to_string(),
-                "antivirus-1.0.0".to_string(),
-                Some("antivirus-scanner".to_string())
-            );
+            let response_generator = self.response_generator();
             Ok(response_generator.no_modifications(None))
         } else {
             // Block the request due to threat
@@ -1022,39 +1366,35 @@ impl IcapModule for AntivirusModule {
             }
 
             // Use response generator for proper error response with chunked support
-            let response_generator = crate::protocol::response_generator::IcapResponseGenerator::with_service_id(
-                "G3ICAP-Antivirus/1.0.0".to_string(),
-                "antivirus-1.0.0".to_string(),
-                Some("antivirus-scanner".to_string())
-            );
+            let response_generator = self.response_generator();
             
             // Use chunked response for large threat descriptions
             let threat_message = format!("Request blocked by antivirus: {}", threat_name);
             let should_chunk = response_generator.should_use_chunked_encoding(Some(threat_message.len()));
             
-            if should_chunk {
-                Ok(response_generator.forbidden_chunked(Some(&threat_message)))
+            let mut response = if should_chunk {
+                response_generator.forbidden_chunked(Some(&threat_message))
             } else {
-                Ok(response_generator.forbidden(Some(&threat_message)))
+                response_generator.forbidden(Some(&threat_message))
+            };
+            if let Ok(value) = threat_name.parse() {
+                response.headers.insert(HEADER_VIRUS_ID, value);
             }
+            Ok(response)
         }
     }
 
-    async fn handle_respmod(&self, request: &IcapRequest) -> Result<IcapResponse, ModuleError> {
+    async fn handle_respmod(&self, request: &IcapRequest, ctx: &TransactionCtx) -> Result<IcapResponse, ModuleError> {
         if self.config.enable_logging {
             log::debug!("Processing RESPMOD request for antivirus scanning: {}", request.uri);
         }
 
         // Scan the response body
-        let scan_result = self.scan_content(&request.body, None).await?;
+        let scan_result = crate::modules::until_cancelled(ctx, self.scan_content(&request.body, None)).await?;
 
         if scan_result.is_clean {
             // Allow the response - use response generator for proper headers
-            let response_generator = crate::protocol::response_generator::IcapResponseGenerator::with_service_id(
-                "G3ICAP-Antivirus/1.0.0".to_string(),
-                "antivirus-1.0.0".to_string(),
-                Some("antivirus-scanner".to_string())
-            );
+            let response_generator = self.response_generator();
             Ok(response_generator.no_modifications(None))
         } else {
             // Block the response due to threat
@@ -1069,27 +1409,29 @@ impl IcapModule for AntivirusModule {
             }
 
             // Use response generator for proper error response with chunked support
-            let response_generator = crate::protocol::response_generator::IcapResponseGenerator::with_service_id(
-                "G3ICAP-Antivirus/1.0.0".to_string(),
-                "antivirus-1.0.0".to_string(),
-                Some("antivirus-scanner".to_string())
-            );
+            let response_generator = self.response_generator();
             
             // Use chunked response for large threat descriptions
             let threat_message = format!("Response blocked by antivirus: {}", threat_name);
             let should_chunk = response_generator.should_use_chunked_encoding(Some(threat_message.len()));
             
-            if should_chunk {
-                Ok(response_generator.forbidden_chunked(Some(&threat_message)))
+            let mut response = if should_chunk {
+                response_generator.forbidden_chunked(Some(&threat_message))
             } else {
-                Ok(response_generator.forbidden(Some(&threat_message)))
+                response_generator.forbidden(Some(&threat_message))
+            };
+            if let Ok(value) = threat_name.parse() {
+                response.headers.insert(HEADER_VIRUS_ID, value);
             }
+            Ok(response)
         }
     }
 
     async fn handle_options(&self, request: &IcapRequest) -> Result<IcapResponse, ModuleError> {
         let mut headers = http::HeaderMap::new();
-        headers.insert("ISTag", "\"antivirus-1.0\"".parse().unwrap());
+        if let Ok(istag) = format!("\"{}\"", self.istag()).parse() {
+            headers.insert("ISTag", istag);
+        }
         headers.insert("Methods", "REQMOD, RESPMOD".parse().unwrap());
         headers.insert("Service", "Antivirus Scanning Service".parse().unwrap());
         headers.insert("Max-Connections", "1000".parse().unwrap());
@@ -1133,6 +1475,7 @@ mod tests {
     use super::*;
     use http::{HeaderMap, Version};
     use bytes::Bytes;
+    use tokio_util::sync::CancellationToken;
 
     fn create_test_request(uri: &str, body: &str) -> IcapRequest {
         let mut headers = HeaderMap::new();
@@ -1163,7 +1506,7 @@ mod tests {
         module.init(&module_config).await.unwrap();
 
         let request = create_test_request("http://example.com/clean", "clean content");
-        let response = module.handle_reqmod(&request).await.unwrap();
+        let response = module.handle_reqmod(&request, &TransactionCtx::for_request(&request)).await.unwrap();
         assert_eq!(response.status, http::StatusCode::NO_CONTENT);
     }
 
@@ -1186,6 +1529,10 @@ mod tests {
             update_interval: Duration::from_secs(3600),
             enable_threat_intel: false,
             threat_intel_sources: Vec::new(),
+            threat_intel_batch: Default::default(),
+            secondary_engine: None,
+            hedging: Default::default(),
+            verdict_cache: None,
             yara_config: None,
         };
         let mut module = AntivirusModule::new(config);
@@ -1193,8 +1540,87 @@ mod tests {
         module.init(&module_config).await.unwrap();
 
         let request = create_test_request("http://example.com/virus", "virus content");
-        let response = module.handle_reqmod(&request).await.unwrap();
+        let response = module.handle_reqmod(&request, &TransactionCtx::for_request(&request)).await.unwrap();
         assert_eq!(response.status, http::StatusCode::FORBIDDEN);
+
+        let registered = module.registered_metrics.as_ref().unwrap();
+        assert_eq!(registered.infected.get(), 1);
+        assert_eq!(registered.scan_duration_ms.count(), 1);
+        assert_eq!(
+            module_config.metrics.names(),
+            [
+                "module.antivirus_test.infected",
+                "module.antivirus_test.scan_duration_ms"
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_hedged_scanning() {
+        let config = AntivirusConfig {
+            engine: AntivirusEngine::Mock {
+                simulate_threats: false,
+                scan_delay: Duration::from_secs(5),
+            },
+            secondary_engine: Some(AntivirusEngine::Mock {
+                simulate_threats: true,
+                scan_delay: Duration::from_millis(10),
+            }),
+            hedging: HedgeConfig {
+                max_delay: Duration::from_millis(20),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut module = AntivirusModule::new(config);
+        let module_config = create_module_config("antivirus_test");
+        module.init(&module_config).await.unwrap();
+
+        // the slow primary is not waited for
+        let request = create_test_request("http://example.com/virus", "virus content");
+        let response = module.handle_reqmod(&request, &TransactionCtx::for_request(&request)).await.unwrap();
+        assert_eq!(response.status, http::StatusCode::FORBIDDEN);
+        let wins = module_config.metrics.counter("hedge.secondary_wins").unwrap();
+        assert_eq!(wins.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_verdict_cache() {
+        let config = AntivirusConfig {
+            engine: AntivirusEngine::Mock {
+                simulate_threats: true,
+                scan_delay: Duration::from_millis(10),
+            },
+            enable_quarantine: false,
+            verdict_cache: Some(VerdictCacheConfig::default()),
+            ..Default::default()
+        };
+        let mut module = AntivirusModule::new(config);
+        let module_config = create_module_config("antivirus_test");
+        module.init(&module_config).await.unwrap();
+        assert_eq!(module.istag(), "antivirus-Mock-1.0.0");
+
+        let request = create_test_request("http://example.com/virus", "virus content");
+        for _ in 0..2 {
+            let response = module.handle_reqmod(&request, &TransactionCtx::for_request(&request)).await.unwrap();
+            assert_eq!(response.status, http::StatusCode::FORBIDDEN);
+            assert_eq!(response.headers.get(HEADER_VIRUS_ID).unwrap(), "MockVirus");
+        }
+        let hits = module_config.metrics.counter("verdict_cache.hits").unwrap();
+        assert_eq!(hits.get(), 1);
+        assert_eq!(module.get_stats().infected_files, 2);
+
+        let response = module.handle_options(&request).await.unwrap();
+        assert_eq!(response.headers.get("ISTag").unwrap(), "\"antivirus-Mock-1.0.0\"");
+    }
+
+    #[tokio::test]
+    async fn test_invalid_config() {
+        let mut module = AntivirusModule::with_defaults();
+        let mut module_config = create_module_config("antivirus_test");
+        module_config.config = serde_json::json!({ "max_file_sise": 1024 });
+        let e = module.init(&module_config).await.unwrap_err();
+        assert!(e.to_string().contains("unknown field `max_file_sise`"), "{e}");
     }
 
     #[tokio::test]
@@ -1213,10 +1639,99 @@ mod tests {
 
         let large_content = "x".repeat(200);
         let request = create_test_request("http://example.com/large", &large_content);
-        let result = module.handle_reqmod(&request).await;
+        let result = module.handle_reqmod(&request, &TransactionCtx::for_request(&request)).await;
         assert!(result.is_err());
     }
 
+    #[tokio::test]
+    async fn test_cancelled_scan() {
+        let config = AntivirusConfig {
+            engine: AntivirusEngine::Mock {
+                simulate_threats: false,
+                scan_delay: Duration::from_secs(30),
+            },
+            ..Default::default()
+        };
+        let mut module = AntivirusModule::new(config);
+        let module_config = create_module_config("antivirus_test");
+        module.init(&module_config).await.unwrap();
+
+        // the scan stops once the transaction is cancelled
+        let cancel = CancellationToken::new();
+        let canceller = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            canceller.cancel();
+        });
+        let request = create_test_request("http://example.com/slow", "slow content");
+        let ctx = TransactionCtx::for_request(&request).with_cancel(cancel);
+        let started = Instant::now();
+        let result = module.handle_reqmod(&request, &ctx).await;
+        assert!(matches!(result, Err(ModuleError::Cancelled)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_clamd_instream() {
+        use tokio::io::AsyncReadExt;
+
+        // a clamd answering each connection, finding the streams with "virus"
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut conn, _)) = listener.accept().await {
+                let mut command = Vec::new();
+                while !command.ends_with(b"\0") {
+                    command.push(conn.read_u8().await.unwrap());
+                }
+                let reply: &[u8] = match &command[..] {
+                    b"zPING\0" => b"PONG\0",
+                    b"zVERSION\0" => b"ClamAV 1.4.1/27400/Thu Sep 12 08:00:00 2024\0",
+                    b"zINSTREAM\0" => {
+                        let mut data = Vec::new();
+                        loop {
+                            let len = conn.read_u32().await.unwrap() as usize;
+                            if len == 0 {
+                                break;
+                            }
+                            let start = data.len();
+                            data.resize(start + len, 0);
+                            conn.read_exact(&mut data[start..]).await.unwrap();
+                        }
+                        if data.windows(5).any(|w| w == b"virus") {
+                            b"stream: Test-Signature FOUND\0"
+                        } else {
+                            b"stream: OK\0"
+                        }
+                    }
+                    _ => b"UNKNOWN COMMAND\0",
+                };
+                conn.write_all(reply).await.unwrap();
+            }
+        });
+
+        let config = AntivirusConfig {
+            engine: AntivirusEngine::ClamAV {
+                socket_path: addr.to_string(),
+                timeout: Duration::from_secs(5),
+            },
+            enable_quarantine: false,
+            ..Default::default()
+        };
+        let mut module = AntivirusModule::new(config);
+        module.init(&create_module_config("antivirus_test")).await.unwrap();
+        assert!(module.istag().starts_with("antivirus-ClamAV-1.4.1"));
+
+        let clean = module.scan_content(&[b'x'; 200_000], None).await.unwrap();
+        assert!(clean.is_clean);
+        assert_eq!(clean.engine, "ClamAV");
+        let infected = module.scan_content(b"a virus", None).await.unwrap();
+        assert!(!infected.is_clean);
+        assert_eq!(infected.threat_name.as_deref(), Some("Test-Signature"));
+
+        assert!(clamd_scan_result("stream: Size limit exceeded. ERROR", 0, Duration::ZERO).is_err());
+    }
+
     fn create_module_config(name: &str) -> ModuleConfig {
         ModuleConfig {
             name: name.to_string(),
@@ -1227,6 +1742,7 @@ mod tests {
             load_timeout: Duration::from_secs(5),
             max_memory: 1024 * 1024,
             sandbox: true,
+            metrics: crate::modules::metrics::MetricsRegistry::detached().module(name),
         }
     }
 }
@@ -1250,6 +1766,10 @@ impl Default for AntivirusConfig {
             update_interval: Duration::from_secs(24 * 60 * 60), // 24 hours
             enable_threat_intel: false,
             threat_intel_sources: Vec::new(),
+            threat_intel_batch: Default::default(),
+            secondary_engine: None,
+            hedging: Default::default(),
+            verdict_cache: None,
             yara_config: None,
         }
     }
@@ -0,0 +1,289 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Micro-batching of backend lookups
+//!
+//! Lookup-style modules, such as hash or URL reputation checks, send each key
+//! to a [`MicroBatcher`]. The keys are collected until the batch is full or
+//! the oldest key waited for the maximum delay, then the whole batch is sent
+//! to the backend in one call. Duplicate keys of a batch are looked up once,
+//! and a few batches can be in flight at the same time, so that collecting the
+//! next batch does not wait for the backend.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Semaphore, mpsc, oneshot};
+use tokio::time::Instant;
+
+/// Backend answering a batch of lookups
+#[async_trait]
+pub trait BatchLookup: Send + Sync + 'static {
+    type Key: Clone + Eq + Hash + Send + Sync + 'static;
+    type Value: Clone + Send + 'static;
+
+    /// Look up the unique keys of a batch, keys missing in the result have
+    /// no value
+    async fn lookup_batch(
+        &self,
+        keys: &[Self::Key],
+    ) -> anyhow::Result<HashMap<Self::Key, Self::Value>>;
+}
+
+/// Micro-batching configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BatchConfig {
+    /// Maximum number of keys sent in one batch
+    pub max_batch_size: usize,
+    /// Maximum time a key waits for its batch to fill
+    pub max_delay: Duration,
+    /// Maximum number of batches in flight
+    pub max_in_flight: usize,
+    /// Maximum number of keys waiting for a batch, lookups fail fast beyond
+    pub queue_size: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        BatchConfig {
+            max_batch_size: 64,
+            max_delay: Duration::from_millis(5),
+            max_in_flight: 4,
+            queue_size: 4096,
+        }
+    }
+}
+
+/// Batch lookup errors
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum BatchError {
+    #[error("lookup queue is full")]
+    QueueFull,
+    #[error("batcher is closed")]
+    Closed,
+    #[error("backend lookup failed: {0}")]
+    Backend(String),
+}
+
+/// Micro-batcher counters
+#[derive(Debug, Default)]
+pub struct BatcherStats {
+    /// Lookups requested
+    pub lookups: AtomicU64,
+    /// Batches sent to the backend
+    pub batches: AtomicU64,
+    /// Unique keys sent to the backend
+    pub backend_keys: AtomicU64,
+    /// Lookups rejected as the queue was full
+    pub rejected: AtomicU64,
+    /// Failed batches
+    pub failed_batches: AtomicU64,
+}
+
+type Reply<V> = oneshot::Sender<Result<Option<V>, BatchError>>;
+
+struct PendingLookup<B: BatchLookup> {
+    key: B::Key,
+    reply: Reply<B::Value>,
+}
+
+/// Batches the lookups of all callers to a backend
+pub struct MicroBatcher<B: BatchLookup> {
+    sender: mpsc::Sender<PendingLookup<B>>,
+    stats: Arc<BatcherStats>,
+}
+
+impl<B: BatchLookup> Clone for MicroBatcher<B> {
+    fn clone(&self) -> Self {
+        MicroBatcher {
+            sender: self.sender.clone(),
+            stats: Arc::clone(&self.stats),
+        }
+    }
+}
+
+impl<B: BatchLookup> MicroBatcher<B> {
+    /// Create the batcher, its worker task is spawned on the current runtime
+    /// and stops when the last batcher handle is dropped
+    pub fn new(backend: Arc<B>, config: BatchConfig) -> Self {
+        let (sender, receiver) = mpsc::channel(config.queue_size.max(1));
+        let stats = Arc::new(BatcherStats::default());
+        tokio::spawn(run_worker(backend, config, receiver, Arc::clone(&stats)));
+        MicroBatcher { sender, stats }
+    }
+
+    /// Look up the key in the next batch
+    pub async fn lookup(&self, key: B::Key) -> Result<Option<B::Value>, BatchError> {
+        self.stats.lookups.fetch_add(1, Ordering::Relaxed);
+        let (reply, receiver) = oneshot::channel();
+        match self.sender.try_send(PendingLookup { key, reply }) {
+            Ok(_) => {}
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.stats.rejected.fetch_add(1, Ordering::Relaxed);
+                return Err(BatchError::QueueFull);
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => return Err(BatchError::Closed),
+        }
+        receiver.await.map_err(|_| BatchError::Closed)?
+    }
+
+    /// Batcher counters
+    pub fn stats(&self) -> &BatcherStats {
+        &self.stats
+    }
+}
+
+async fn run_worker<B: BatchLookup>(
+    backend: Arc<B>,
+    config: BatchConfig,
+    mut receiver: mpsc::Receiver<PendingLookup<B>>,
+    stats: Arc<BatcherStats>,
+) {
+    let max_batch_size = config.max_batch_size.max(1);
+    let in_flight = Arc::new(Semaphore::new(config.max_in_flight.max(1)));
+    while let Some(first) = receiver.recv().await {
+        let mut batch = Vec::with_capacity(max_batch_size);
+        batch.push(first);
+        let deadline = Instant::now() + config.max_delay;
+        while batch.len() < max_batch_size {
+            match tokio::time::timeout_at(deadline, receiver.recv()).await {
+                Ok(Some(pending)) => batch.push(pending),
+                Ok(None) | Err(_) => break,
+            }
+        }
+
+        let Ok(permit) = Arc::clone(&in_flight).acquire_owned().await else {
+            break;
+        };
+        let backend = Arc::clone(&backend);
+        let stats = Arc::clone(&stats);
+        tokio::spawn(async move {
+            run_batch(backend.as_ref(), batch, &stats).await;
+            drop(permit);
+        });
+    }
+}
+
+async fn run_batch<B: BatchLookup>(
+    backend: &B,
+    batch: Vec<PendingLookup<B>>,
+    stats: &BatcherStats,
+) {
+    let mut waiters: HashMap<B::Key, Vec<Reply<B::Value>>> = HashMap::with_capacity(batch.len());
+    let mut keys = Vec::with_capacity(batch.len());
+    for pending in batch {
+        waiters
+            .entry(pending.key)
+            .or_insert_with_key(|key| {
+                keys.push(key.clone());
+                Vec::new()
+            })
+            .push(pending.reply);
+    }
+
+    stats.batches.fetch_add(1, Ordering::Relaxed);
+    stats
+        .backend_keys
+        .fetch_add(keys.len() as u64, Ordering::Relaxed);
+    match backend.lookup_batch(&keys).await {
+        Ok(mut values) => {
+            for (key, replies) in waiters {
+                let value = values.remove(&key);
+                for reply in replies {
+                    let _ = reply.send(Ok(value.clone()));
+                }
+            }
+        }
+        Err(e) => {
+            stats.failed_batches.fetch_add(1, Ordering::Relaxed);
+            let e = BatchError::Backend(format!("{e:#}"));
+            for reply in waiters.into_values().flatten() {
+                let _ = reply.send(Err(e.clone()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct SquareBackend {
+        batches: Mutex<Vec<Vec<u64>>>,
+    }
+
+    #[async_trait]
+    impl BatchLookup for SquareBackend {
+        type Key = u64;
+        type Value = u64;
+
+        async fn lookup_batch(&self, keys: &[u64]) -> anyhow::Result<HashMap<u64, u64>> {
+            self.batches.lock().unwrap().push(keys.to_vec());
+            if keys.contains(&13) {
+                return Err(anyhow::anyhow!("unlucky"));
+            }
+            Ok(keys
+                .iter()
+                .filter(|k| k.is_multiple_of(2))
+                .map(|k| (*k, k * k))
+                .collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn batches_and_dedup() {
+        let backend = Arc::new(SquareBackend::default());
+        let config = BatchConfig {
+            max_batch_size: 16,
+            max_delay: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let batcher = MicroBatcher::new(Arc::clone(&backend), config);
+
+        let mut tasks = Vec::new();
+        for i in 0..40u64 {
+            let batcher = batcher.clone();
+            tasks.push(tokio::spawn(async move { batcher.lookup(i % 10).await }));
+        }
+        for (i, task) in tasks.into_iter().enumerate() {
+            let key = i as u64 % 10;
+            let expected = key.is_multiple_of(2).then_some(key * key);
+            assert_eq!(task.await.unwrap().unwrap(), expected);
+        }
+
+        let batches = backend.batches.lock().unwrap();
+        assert!(batches.len() <= 3);
+        assert!(batches.iter().all(|b| b.len() <= 16));
+        assert_eq!(batcher.stats().lookups.load(Ordering::Relaxed), 40);
+        assert_eq!(
+            batcher.stats().batches.load(Ordering::Relaxed),
+            batches.len() as u64
+        );
+    }
+
+    #[tokio::test]
+    async fn backend_error() {
+        let backend = Arc::new(SquareBackend::default());
+        let config = BatchConfig {
+            max_delay: Duration::from_millis(20),
+            ..Default::default()
+        };
+        let batcher = MicroBatcher::new(backend, config);
+
+        let (unlucky, other) = tokio::join!(batcher.lookup(13), batcher.lookup(4));
+        assert!(matches!(unlucky, Err(BatchError::Backend(_))));
+        assert!(matches!(other, Err(BatchError::Backend(_))));
+        assert_eq!(batcher.lookup(4).await.unwrap(), Some(16));
+        assert_eq!(batcher.stats().failed_batches.load(Ordering::Relaxed), 1);
+    }
+}
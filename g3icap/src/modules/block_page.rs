@@ -0,0 +1,316 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Block page templates
+//!
+//! Blocked requests are answered with an encapsulated HTTP error response
+//! carrying an HTML page, so that browsers show a page explaining the block
+//! instead of a connection error. A page can be configured for each rule
+//! category, templates may use the variables `{url}`, `{category}`,
+//! `{rule}`, `{reason}`, `{client_ip}` and `{ticket_id}`. Values are HTML
+//! escaped, unknown variables are left untouched.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use http::{HeaderMap, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::identity::HEADER_CLIENT_IP;
+use crate::modules::{HEADER_URL_CATEGORY, ModuleError};
+
+/// Built-in block page
+pub const DEFAULT_TEMPLATE: &str = "<!DOCTYPE html>\n\
+<html>\n\
+<head><meta charset=\"utf-8\"><title>Access blocked</title></head>\n\
+<body>\n\
+<div style=\"text-align: center;\"><h1>Access blocked</h1>\n\
+<p>Access to <b>{url}</b> has been blocked by your organization's web policy.</p>\n\
+<p>Category: {category}</p>\n\
+<p>If you believe this is a mistake, contact your administrator with ticket <b>{ticket_id}</b>.</p></div>\n\
+</body>\n\
+</html>\n";
+
+/// Where a template is read from
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BlockPageSource {
+    /// Inline HTML template
+    pub html: Option<String>,
+    /// Template file, used if no inline template is set
+    pub file: Option<PathBuf>,
+}
+
+impl BlockPageSource {
+    fn load(&self, name: &str) -> Result<Option<String>, ModuleError> {
+        if let Some(html) = &self.html {
+            return Ok(Some(html.clone()));
+        }
+        match &self.file {
+            Some(path) => std::fs::read_to_string(path).map(Some).map_err(|e| {
+                ModuleError::InitFailed(format!(
+                    "failed to read block page {name} from {}: {e}",
+                    path.display()
+                ))
+            }),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Block page configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BlockPageConfig {
+    /// Serve block pages instead of ICAP error responses
+    pub enabled: bool,
+    /// HTTP status code of the block page
+    pub status_code: u16,
+    /// Page of the categories without a page of their own
+    pub template: BlockPageSource,
+    /// Pages keyed by rule category
+    pub categories: HashMap<String, BlockPageSource>,
+}
+
+impl Default for BlockPageConfig {
+    fn default() -> Self {
+        BlockPageConfig {
+            enabled: false,
+            status_code: 403,
+            template: BlockPageSource::default(),
+            categories: HashMap::new(),
+        }
+    }
+}
+
+/// Values of the template variables
+#[derive(Debug, Clone, Default)]
+pub struct BlockPageVars<'a> {
+    pub url: &'a str,
+    pub category: &'a str,
+    pub rule: &'a str,
+    pub reason: &'a str,
+    pub client_ip: &'a str,
+    pub ticket_id: &'a str,
+}
+
+impl BlockPageVars<'_> {
+    fn get(&self, name: &str) -> Option<&str> {
+        match name {
+            "url" => Some(self.url),
+            "category" => Some(self.category),
+            "rule" => Some(self.rule),
+            "reason" => Some(self.reason),
+            "client_ip" => Some(self.client_ip),
+            "ticket_id" => Some(self.ticket_id),
+            _ => None,
+        }
+    }
+}
+
+/// Loaded block page templates
+pub struct BlockPages {
+    status: StatusCode,
+    default_template: String,
+    categories: HashMap<String, String>,
+}
+
+impl Default for BlockPages {
+    fn default() -> Self {
+        BlockPages {
+            status: StatusCode::FORBIDDEN,
+            default_template: DEFAULT_TEMPLATE.to_string(),
+            categories: HashMap::new(),
+        }
+    }
+}
+
+impl BlockPages {
+    /// Load the templates, template files are read once here
+    pub fn new(config: &BlockPageConfig) -> Result<Self, ModuleError> {
+        let status = StatusCode::from_u16(config.status_code)
+            .ok()
+            .filter(|s| s.is_client_error() || s.is_server_error())
+            .ok_or_else(|| {
+                ModuleError::InitFailed(format!(
+                    "invalid block page status code {}",
+                    config.status_code
+                ))
+            })?;
+        let default_template = config
+            .template
+            .load("template")?
+            .unwrap_or_else(|| DEFAULT_TEMPLATE.to_string());
+        let mut categories = HashMap::with_capacity(config.categories.len());
+        for (category, source) in &config.categories {
+            let Some(template) = source.load(&format!("categories.{category}"))? else {
+                return Err(ModuleError::InitFailed(format!(
+                    "no html or file set for block page of category {category}"
+                )));
+            };
+            categories.insert(category.to_lowercase(), template);
+        }
+        Ok(BlockPages {
+            status,
+            default_template,
+            categories,
+        })
+    }
+
+    /// HTTP status code of the block page
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Render the page of the category
+    pub fn render(&self, vars: &BlockPageVars) -> String {
+        let template = self
+            .categories
+            .get(&vars.category.to_lowercase())
+            .unwrap_or(&self.default_template);
+        render_template(template, vars)
+    }
+
+    /// Generate a new ticket id to correlate the page with the logs
+    pub fn new_ticket_id() -> String {
+        uuid::Uuid::new_v4().simple().to_string()
+    }
+
+    /// Category assigned by the proxy, if any
+    pub fn request_category(headers: &HeaderMap) -> Option<&str> {
+        header_str(headers, HEADER_URL_CATEGORY)
+    }
+
+    /// IP address of the end user, if forwarded by the proxy
+    pub fn client_ip(headers: &HeaderMap) -> Option<&str> {
+        header_str(headers, HEADER_CLIENT_IP)
+    }
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+}
+
+fn render_template(template: &str, vars: &BlockPageVars) -> String {
+    render_with(template, |name| vars.get(name).map(str::to_string))
+}
+
+/// Render a template with the variables of the lookup
+///
+/// Values are HTML escaped, unknown variables are left untouched.
+pub fn render_with(template: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    substitute(template, lookup, escape_html)
+}
+
+/// Render a template that is not HTML, such as a header value
+///
+/// The control characters of the values are dropped.
+pub fn render_text(template: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    substitute(template, lookup, |s, out| {
+        out.extend(s.chars().filter(|c| !c.is_control()))
+    })
+}
+
+fn substitute(
+    template: &str,
+    lookup: impl Fn(&str) -> Option<String>,
+    escape: impl Fn(&str, &mut String),
+) -> String {
+    let mut page = String::with_capacity(template.len() + 256);
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        page.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        let value = rest
+            .find('}')
+            .and_then(|end| lookup(&rest[..end]).map(|v| (end, v)));
+        match value {
+            Some((end, value)) => {
+                escape(&value, &mut page);
+                rest = &rest[end + 1..];
+            }
+            // keep css blocks and unknown variables
+            None => page.push('{'),
+        }
+    }
+    page.push_str(rest);
+    page
+}
+
+fn escape_html(s: &str, out: &mut String) {
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_category_page() {
+        let mut config = BlockPageConfig {
+            enabled: true,
+            ..Default::default()
+        };
+        config.categories.insert(
+            "Gambling".to_string(),
+            BlockPageSource {
+                html: Some(
+                    "<style>p {color: red}</style><p>{url} ({category}) {rule} {unknown} {ticket_id}</p>"
+                        .to_string(),
+                ),
+                file: None,
+            },
+        );
+        let pages = BlockPages::new(&config).unwrap();
+        assert_eq!(pages.status(), StatusCode::FORBIDDEN);
+
+        let vars = BlockPageVars {
+            url: "http://casino.example/?a=1&b=<script>",
+            category: "gambling",
+            rule: "default:domain:0",
+            ticket_id: "42",
+            ..Default::default()
+        };
+        assert_eq!(
+            pages.render(&vars),
+            "<style>p {color: red}</style><p>http://casino.example/?a=1&amp;b=&lt;script&gt; \
+             (gambling) default:domain:0 {unknown} 42</p>"
+        );
+
+        let vars = BlockPageVars {
+            category: "keyword",
+            ..vars
+        };
+        assert!(pages.render(&vars).contains("Category: keyword"));
+    }
+
+    #[test]
+    fn invalid_config() {
+        let config = BlockPageConfig {
+            status_code: 200,
+            ..Default::default()
+        };
+        assert!(BlockPages::new(&config).is_err());
+
+        let mut config = BlockPageConfig::default();
+        config
+            .categories
+            .insert("malware".to_string(), BlockPageSource::default());
+        assert!(BlockPages::new(&config).is_err());
+    }
+}
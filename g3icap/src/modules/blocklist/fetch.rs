@@ -0,0 +1,208 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Download of remote domain lists
+//!
+//! A minimal HTTP/1.1 GET, lists are plain files served by web servers or
+//! CDNs, redirects are not followed. The config secrets are read from Vault
+//! with it too.
+
+use std::time::Duration;
+
+use anyhow::{Context, anyhow};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use url::Url;
+
+use g3_types::net::RustlsClientConfigBuilder;
+
+/// Size allowed for the response header, in addition to the list size
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+
+/// Download the list at the url, with the extra request headers
+pub(crate) async fn fetch(
+    url: &Url,
+    headers: &[(&str, &str)],
+    timeout: Duration,
+    max_size: usize,
+) -> anyhow::Result<Vec<u8>> {
+    tokio::time::timeout(timeout, fetch_inner(url, headers, max_size))
+        .await
+        .map_err(|_| anyhow!("timed out after {timeout:?}"))?
+}
+
+async fn fetch_inner(
+    url: &Url,
+    headers: &[(&str, &str)],
+    max_size: usize,
+) -> anyhow::Result<Vec<u8>> {
+    let host = url.host_str().ok_or_else(|| anyhow!("no host in url"))?;
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| anyhow!("no port in url"))?;
+    let host_header = match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    };
+    let path = match url.query() {
+        Some(query) => format!("{}?{query}", url.path()),
+        None => url.path().to_string(),
+    };
+    let mut request = format!(
+        "GET {path} HTTP/1.1\r\nHost: {host_header}\r\nUser-Agent: g3icap\r\nAccept: */*\r\nConnection: close\r\n"
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{name}: {value}\r\n"));
+    }
+    request.push_str("\r\n");
+
+    let stream = TcpStream::connect((host.trim_matches(['[', ']']), port))
+        .await
+        .context("failed to connect")?;
+    let response = if url.scheme() == "https" {
+        let tls_client = RustlsClientConfigBuilder::default().build()?;
+        let server_name =
+            rustls::pki_types::ServerName::try_from(host.trim_matches(['[', ']']).to_string())
+                .map_err(|e| anyhow!("invalid tls server name {host}: {e}"))?;
+        let stream = TlsConnector::from(tls_client.driver)
+            .connect(server_name, stream)
+            .await
+            .context("tls handshake failed")?;
+        exchange(stream, &request, max_size).await?
+    } else {
+        exchange(stream, &request, max_size).await?
+    };
+    parse_response(&response, max_size)
+}
+
+async fn exchange<S>(mut stream: S, request: &str, max_size: usize) -> anyhow::Result<Vec<u8>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .context("failed to send request")?;
+    stream.flush().await?;
+
+    let limit = max_size + MAX_HEADER_SIZE;
+    let mut response = Vec::new();
+    (&mut stream)
+        .take(limit as u64 + 1)
+        .read_to_end(&mut response)
+        .await
+        .context("failed to read response")?;
+    if response.len() > limit {
+        return Err(anyhow!("list is larger than {max_size} bytes"));
+    }
+    Ok(response)
+}
+
+/// Get the body of a complete HTTP response
+fn parse_response(data: &[u8], max_size: usize) -> anyhow::Result<Vec<u8>> {
+    let header_end = data
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| anyhow!("incomplete response header"))?;
+    let header = std::str::from_utf8(&data[..header_end]).context("invalid response header")?;
+    let body = &data[header_end + 4..];
+
+    let mut lines = header.split("\r\n");
+    let status_line = lines.next().unwrap_or_default();
+    let mut parts = status_line.splitn(3, ' ');
+    if !parts.next().unwrap_or_default().starts_with("HTTP/1.") {
+        return Err(anyhow!("invalid status line {status_line}"));
+    }
+    let status = parts.next().unwrap_or_default();
+    if status != "200" {
+        return Err(anyhow!("unexpected response status: {status_line}"));
+    }
+
+    let mut chunked = false;
+    let mut content_length = None;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value.to_ascii_lowercase().contains("chunked");
+        } else if name.eq_ignore_ascii_case("content-length") {
+            content_length = Some(
+                value
+                    .parse::<usize>()
+                    .map_err(|_| anyhow!("invalid content-length {value}"))?,
+            );
+        }
+    }
+
+    let body = if chunked {
+        decode_chunked(body)?
+    } else if let Some(len) = content_length {
+        if body.len() < len {
+            return Err(anyhow!(
+                "truncated response body, {} of {len} bytes",
+                body.len()
+            ));
+        }
+        body[..len].to_vec()
+    } else {
+        body.to_vec()
+    };
+    if body.len() > max_size {
+        return Err(anyhow!("list is larger than {max_size} bytes"));
+    }
+    Ok(body)
+}
+
+fn decode_chunked(mut data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut body = Vec::with_capacity(data.len());
+    loop {
+        let line_end = data
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or_else(|| anyhow!("truncated chunk size"))?;
+        let size_line = std::str::from_utf8(&data[..line_end]).context("invalid chunk size")?;
+        let size_str = size_line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size_str, 16)
+            .map_err(|_| anyhow!("invalid chunk size {size_line}"))?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Ok(body);
+        }
+        if data.len() < size + 2 {
+            return Err(anyhow!("truncated chunk"));
+        }
+        body.extend_from_slice(&data[..size]);
+        data = &data[size + 2..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn responses() {
+        let plain = b"HTTP/1.1 200 OK\r\nContent-Length: 12\r\n\r\nmalware.com\nextra";
+        assert_eq!(parse_response(plain, 1024).unwrap(), b"malware.com\n");
+
+        let chunked = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                        4;ext=1\r\nspam\r\n5\r\n.org\n\r\n0\r\n\r\n";
+        assert_eq!(parse_response(chunked, 1024).unwrap(), b"spam.org\n");
+
+        let eof = b"HTTP/1.0 200 OK\r\nConnection: close\r\n\r\nvirus.example\n";
+        assert_eq!(parse_response(eof, 1024).unwrap(), b"virus.example\n");
+        assert!(parse_response(eof, 4).is_err());
+
+        let moved = b"HTTP/1.1 301 Moved Permanently\r\nLocation: /v2\r\n\r\n";
+        let err = parse_response(moved, 1024).unwrap_err().to_string();
+        assert!(err.contains("301"), "{err}");
+
+        let truncated = b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\nshort";
+        assert!(parse_response(truncated, 1024).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Domain list formats
+
+use crate::config::server::blocklist::BlocklistFormat;
+
+/// Host names of hosts files that are not blocking entries
+const HOSTS_LOCAL_NAMES: &[&str] = &[
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+    "ip6-localnet",
+    "ip6-mcastprefix",
+    "ip6-allnodes",
+    "ip6-allrouters",
+    "ip6-allhosts",
+    "0.0.0.0",
+];
+
+/// Extract the domains of a list, invalid lines are skipped
+pub fn parse_domains(text: &str, format: BlocklistFormat) -> Vec<String> {
+    let mut domains = Vec::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        match format {
+            BlocklistFormat::Plain => {
+                let line = strip_comment(line);
+                if let Some(domain) = normalize_domain(line) {
+                    domains.push(domain);
+                }
+            }
+            BlocklistFormat::Hosts => {
+                let mut fields = strip_comment(line).split_whitespace();
+                if fields.next().is_none() {
+                    continue;
+                }
+                domains.extend(
+                    fields
+                        .filter(|h| !HOSTS_LOCAL_NAMES.contains(&h.to_ascii_lowercase().as_str()))
+                        .filter_map(normalize_domain),
+                );
+            }
+            BlocklistFormat::Adblock => {
+                if let Some(domain) = parse_adblock_rule(line) {
+                    domains.push(domain);
+                }
+            }
+            // frozen lists are mapped, not parsed
+            BlocklistFormat::Frozen => break,
+        }
+    }
+    domains
+}
+
+fn strip_comment(line: &str) -> &str {
+    line.split('#').next().unwrap_or_default().trim()
+}
+
+/// Only keep the rules blocking a whole domain, `||example.com^`
+fn parse_adblock_rule(line: &str) -> Option<String> {
+    // comments, exceptions and the list header
+    if line.starts_with('!') || line.starts_with("@@") || line.starts_with('[') {
+        return None;
+    }
+    let rule = line.strip_prefix("||")?;
+    let (pattern, options) = match rule.split_once('$') {
+        Some((pattern, options)) => (pattern, Some(options)),
+        None => (rule, None),
+    };
+    // rules restricted to some request types or origins do not block the domain
+    if options.is_some_and(|o| !o.split(',').all(|o| o == "important" || o == "all")) {
+        return None;
+    }
+    let domain = pattern.strip_suffix('^').unwrap_or(pattern);
+    normalize_domain(domain)
+}
+
+fn normalize_domain(s: &str) -> Option<String> {
+    let domain = s.trim().trim_end_matches('.').to_ascii_lowercase();
+    let valid = !domain.is_empty()
+        && domain.len() <= 253
+        && domain
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.' || b == b'_')
+        && !domain.split('.').any(|label| label.is_empty());
+    valid.then_some(domain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats() {
+        let plain = "# blocked\nMalware.COM.\n\nbad..domain\nphishing.net # reported\n";
+        assert_eq!(
+            parse_domains(plain, BlocklistFormat::Plain),
+            ["malware.com", "phishing.net"]
+        );
+
+        let hosts = "127.0.0.1 localhost\n::1 ip6-localhost\n0.0.0.0 0.0.0.0\n\
+                     0.0.0.0 ads.example.com tracker.example.com # ads\n#0.0.0.0 off.example\n";
+        assert_eq!(
+            parse_domains(hosts, BlocklistFormat::Hosts),
+            ["ads.example.com", "tracker.example.com"]
+        );
+
+        let adblock = "[Adblock Plus 2.0]\n! Title: test\n||ads.example.com^\n\
+                       ||cdn.example.com^$third-party\n@@||good.example.com^\n\
+                       ||evil.example^$important\n||example.org/banner^\n/ads/*\n";
+        assert_eq!(
+            parse_domains(adblock, BlocklistFormat::Adblock),
+            ["ads.example.com", "evil.example"]
+        );
+    }
+}
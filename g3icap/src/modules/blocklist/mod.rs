@@ -0,0 +1,459 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Domain blocklist provider
+//!
+//! The domains of all configured lists are compiled into a single matcher
+//! which is swapped atomically after each reload, requests in flight keep
+//! using the matcher they started with. A list that fails to reload keeps
+//! its previous domains.
+//!
+//! Frozen lists are not compiled into the matcher, they are mapped as they
+//! are and searched after it. A modified frozen file is simply mapped again.
+//!
+//! With trusted keys, a list whose signature is not valid fails to load, see
+//! [`crate::config::provenance`].
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Instant, SystemTime};
+
+use anyhow::{Context, anyhow};
+use arc_swap::ArcSwap;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+use crate::config::provenance::{self, ArtifactKind};
+use crate::config::server::blocklist::{
+    BlocklistConfig, BlocklistFormat, BlocklistLocation, BlocklistSource,
+};
+use crate::modules::list_store::{FrozenList, ListKind};
+use crate::modules::matcher::{DomainSuffixMatcher, RuleMatch};
+
+pub(crate) mod fetch;
+mod list;
+
+pub use list::parse_domains;
+
+/// Rule id prefix of blocklist matches
+pub const RULE_ID_PREFIX: &str = "blocklist";
+
+/// Size allowed for the detached signature of a remote list
+const MAX_SIGNATURE_SIZE: usize = 4096;
+
+#[derive(Default)]
+struct LoadedList {
+    domains: Vec<String>,
+    frozen: Option<Arc<FrozenList>>,
+    /// Modification time of a local file when it was read
+    modified: Option<SystemTime>,
+    loaded: bool,
+}
+
+impl LoadedList {
+    fn len(&self) -> usize {
+        self.domains.len() + self.frozen.as_ref().map(|f| f.len()).unwrap_or(0)
+    }
+}
+
+/// Blocklist reload counters
+#[derive(Debug, Default)]
+pub struct BlocklistStats {
+    /// Successful list loads
+    pub loads: AtomicU64,
+    /// Failed list loads
+    pub failures: AtomicU64,
+}
+
+/// Frozen lists, with the rule id of their matches
+type FrozenLists = Vec<(String, Arc<FrozenList>)>;
+
+/// Provider of the blocked domains loaded from files and urls
+pub struct BlocklistProvider {
+    config: BlocklistConfig,
+    matcher: ArcSwap<DomainSuffixMatcher>,
+    frozen: ArcSwap<FrozenLists>,
+    lists: Mutex<Vec<LoadedList>>,
+    stats: BlocklistStats,
+}
+
+impl BlocklistProvider {
+    /// Create an empty provider, call [`BlocklistProvider::load`] to fill it
+    pub fn new(config: BlocklistConfig) -> Self {
+        let lists = config
+            .sources
+            .iter()
+            .map(|_| LoadedList::default())
+            .collect();
+        BlocklistProvider {
+            config,
+            matcher: ArcSwap::from_pointee(DomainSuffixMatcher::default()),
+            frozen: ArcSwap::from_pointee(Vec::new()),
+            lists: Mutex::new(lists),
+            stats: BlocklistStats::default(),
+        }
+    }
+
+    /// Load all lists, failing if any of them can not be loaded
+    pub async fn load(&self) -> anyhow::Result<()> {
+        let mut lists = self.lists.lock().await;
+        for (source, list) in self.config.sources.iter().zip(lists.iter_mut()) {
+            self.load_list(source, list)
+                .await
+                .context(format!("failed to load blocklist {}", source.name))?;
+        }
+        self.rebuild(&lists)
+    }
+
+    /// Reload the modified local files, and the remote lists if `remote` is set
+    ///
+    /// Returns the number of reloaded lists.
+    pub async fn refresh(&self, remote: bool) -> usize {
+        let mut lists = self.lists.lock().await;
+        let mut reloaded = 0;
+        for (source, list) in self.config.sources.iter().zip(lists.iter_mut()) {
+            let stale = match &source.location {
+                BlocklistLocation::File(path) => {
+                    !list.loaded
+                        || tokio::fs::metadata(path)
+                            .await
+                            .and_then(|m| m.modified())
+                            .map(|t| list.modified != Some(t))
+                            .unwrap_or(false)
+                }
+                BlocklistLocation::Url(_) => remote || !list.loaded,
+            };
+            if !stale {
+                continue;
+            }
+            match self.load_list(source, list).await {
+                Ok(_) => reloaded += 1,
+                Err(e) => log::warn!(
+                    "failed to reload blocklist {}, keeping {} previous domains: {e:?}",
+                    source.name,
+                    list.len()
+                ),
+            }
+        }
+        if reloaded > 0 {
+            if let Err(e) = self.rebuild(&lists) {
+                log::warn!("failed to rebuild blocklist matcher: {e:?}");
+                return 0;
+            }
+            log::info!(
+                "reloaded {reloaded} blocklists, {} domains blocked",
+                self.len()
+            );
+        }
+        reloaded
+    }
+
+    /// Reload the lists in the background until the provider is dropped
+    pub fn spawn_refresh(self: &Arc<Self>) -> JoinHandle<()> {
+        let provider = Arc::downgrade(self);
+        let watch_interval = self.config.watch_interval;
+        let refresh_interval = self.config.refresh_interval;
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(watch_interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            interval.tick().await;
+            let mut last_remote = Instant::now();
+            loop {
+                interval.tick().await;
+                let Some(provider) = provider.upgrade() else {
+                    break;
+                };
+                let remote = last_remote.elapsed() >= refresh_interval;
+                if remote {
+                    last_remote = Instant::now();
+                }
+                provider.refresh(remote).await;
+            }
+        })
+    }
+
+    /// Find the blocked domain that the host is equal to or a subdomain of
+    ///
+    /// The compiled lists are searched first, then the frozen lists in order.
+    pub fn find(&self, host: &str) -> Option<RuleMatch> {
+        if let Some(m) = self.matcher.load().find(host) {
+            return Some(m.clone());
+        }
+        self.frozen.load().iter().find_map(|(rule_id, list)| {
+            list.find_domain(host).map(|pattern| RuleMatch {
+                rule_id: rule_id.clone(),
+                pattern,
+            })
+        })
+    }
+
+    /// Number of blocked domains
+    pub fn len(&self) -> usize {
+        let frozen: usize = self.frozen.load().iter().map(|(_, list)| list.len()).sum();
+        self.matcher.load().len() + frozen
+    }
+
+    /// Check if no domain is blocked
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reload counters
+    pub fn stats(&self) -> &BlocklistStats {
+        &self.stats
+    }
+
+    async fn load_list(
+        &self,
+        source: &BlocklistSource,
+        list: &mut LoadedList,
+    ) -> anyhow::Result<()> {
+        if source.format == BlocklistFormat::Frozen {
+            return self.map_list(source, list).await;
+        }
+        let result = self.read_source(source).await;
+        let (data, modified) = match result {
+            Ok(v) => v,
+            Err(e) => {
+                self.stats.failures.fetch_add(1, Ordering::Relaxed);
+                return Err(e);
+            }
+        };
+        let text = String::from_utf8_lossy(&data);
+        list.domains = list::parse_domains(&text, source.format);
+        list.modified = modified;
+        list.loaded = true;
+        self.stats.loads.fetch_add(1, Ordering::Relaxed);
+        log::debug!(
+            "loaded {} domains from blocklist {}",
+            list.domains.len(),
+            source.name
+        );
+        Ok(())
+    }
+
+    async fn map_list(
+        &self,
+        source: &BlocklistSource,
+        list: &mut LoadedList,
+    ) -> anyhow::Result<()> {
+        let BlocklistLocation::File(path) = &source.location else {
+            return Err(anyhow!("frozen lists can only be local files"));
+        };
+        let path = path.clone();
+        let name = source.name.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            if let Some(keys) = provenance::trusted_keys() {
+                let data = std::fs::read(&path)?;
+                let signature = std::fs::read_to_string(provenance::signature_path(&path))
+                    .context("failed to read list signature")?;
+                provenance::check(keys, ArtifactKind::Blocklist, &name, &data, &signature)?;
+            }
+            let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok();
+            let frozen = FrozenList::open(&path)?;
+            if frozen.kind() != ListKind::Domains {
+                return Err(anyhow!(
+                    "{} is a list of {}",
+                    path.display(),
+                    frozen.kind().as_str()
+                ));
+            }
+            Ok((frozen, modified))
+        })
+        .await
+        .map_err(|e| anyhow!("list mapping task failed: {e}"))
+        .and_then(|r| r);
+        let (frozen, modified) = match result {
+            Ok(v) => v,
+            Err(e) => {
+                self.stats.failures.fetch_add(1, Ordering::Relaxed);
+                return Err(e);
+            }
+        };
+        log::debug!(
+            "mapped {} domains from blocklist {}",
+            frozen.len(),
+            source.name
+        );
+        list.domains.clear();
+        list.frozen = Some(Arc::new(frozen));
+        list.modified = modified;
+        list.loaded = true;
+        self.stats.loads.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    async fn read_source(
+        &self,
+        source: &BlocklistSource,
+    ) -> anyhow::Result<(Vec<u8>, Option<SystemTime>)> {
+        match &source.location {
+            BlocklistLocation::File(path) => {
+                let metadata = tokio::fs::metadata(path).await?;
+                if metadata.len() > self.config.max_list_size as u64 {
+                    return Err(anyhow!(
+                        "list is larger than {} bytes",
+                        self.config.max_list_size
+                    ));
+                }
+                let data = tokio::fs::read(path).await?;
+                if let Some(keys) = provenance::trusted_keys() {
+                    let signature = tokio::fs::read_to_string(provenance::signature_path(path))
+                        .await
+                        .context("failed to read list signature")?;
+                    provenance::check(
+                        keys,
+                        ArtifactKind::Blocklist,
+                        &source.name,
+                        &data,
+                        &signature,
+                    )?;
+                }
+                Ok((data, metadata.modified().ok()))
+            }
+            BlocklistLocation::Url(url) => {
+                let data = fetch::fetch(
+                    url,
+                    &[],
+                    self.config.fetch_timeout,
+                    self.config.max_list_size,
+                )
+                .await?;
+                if let Some(keys) = provenance::trusted_keys() {
+                    let signature = fetch::fetch(
+                        &provenance::signature_url(url),
+                        &[],
+                        self.config.fetch_timeout,
+                        MAX_SIGNATURE_SIZE,
+                    )
+                    .await
+                    .context("failed to fetch list signature")?;
+                    provenance::check(
+                        keys,
+                        ArtifactKind::Blocklist,
+                        &source.name,
+                        &data,
+                        &String::from_utf8_lossy(&signature),
+                    )?;
+                }
+                Ok((data, None))
+            }
+        }
+    }
+
+    fn rebuild(&self, lists: &[LoadedList]) -> anyhow::Result<()> {
+        let domains = self
+            .config
+            .sources
+            .iter()
+            .zip(lists)
+            .flat_map(|(source, list)| {
+                let rule_id = format!("{RULE_ID_PREFIX}:{}", source.name);
+                list.domains
+                    .iter()
+                    .map(move |d| (rule_id.clone(), d.clone()))
+            });
+        let matcher = DomainSuffixMatcher::new(domains).map_err(|e| anyhow!("{e}"))?;
+        let frozen = self
+            .config
+            .sources
+            .iter()
+            .zip(lists)
+            .filter_map(|(source, list)| {
+                let rule_id = format!("{RULE_ID_PREFIX}:{}", source.name);
+                list.frozen.clone().map(|f| (rule_id, f))
+            })
+            .collect();
+        self.matcher.store(Arc::new(matcher));
+        self.frozen.store(Arc::new(frozen));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reload_modified_file() {
+        let dir = std::env::temp_dir().join(format!("g3icap-blocklist-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("hosts");
+        std::fs::write(&path, "0.0.0.0 ads.example.com\n").unwrap();
+
+        let config = BlocklistConfig {
+            sources: vec![BlocklistSource {
+                name: "ads".to_string(),
+                location: BlocklistLocation::File(path.clone()),
+                format: BlocklistFormat::Hosts,
+            }],
+            ..Default::default()
+        };
+        let provider = BlocklistProvider::new(config);
+        provider.load().await.unwrap();
+        let m = provider.find("cdn.ads.example.com:443").unwrap();
+        assert_eq!(m.rule_id, "blocklist:ads");
+        assert!(provider.find("example.com").is_none());
+        assert_eq!(provider.refresh(false).await, 0);
+
+        // make sure the modification time changes
+        let old = std::fs::metadata(&path).unwrap().modified().unwrap();
+        std::fs::write(&path, "0.0.0.0 tracker.example.com\n").unwrap();
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(old + std::time::Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(provider.refresh(false).await, 1);
+        assert!(provider.find("ads.example.com").is_none());
+        assert!(provider.find("tracker.example.com").is_some());
+
+        // a failed reload keeps the previous domains
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(provider.refresh(false).await, 0);
+        assert_eq!(provider.len(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn remap_frozen_list() {
+        use crate::modules::list_store::write_list;
+
+        let dir = std::env::temp_dir().join(format!("g3icap-frozen-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("domains.g3list");
+        let write = |domains: &[&str]| {
+            let tmp = dir.join("domains.tmp");
+            let mut file = std::fs::File::create(&tmp).unwrap();
+            let entries = domains.iter().map(|d| d.as_bytes().to_vec()).collect();
+            write_list(ListKind::Domains, entries, &mut file).unwrap();
+            std::fs::rename(&tmp, &path).unwrap();
+        };
+        write(&["ads.example.com", "tracker.example.net"]);
+
+        let config = BlocklistConfig {
+            sources: vec![BlocklistSource {
+                name: "big".to_string(),
+                location: BlocklistLocation::File(path.clone()),
+                format: BlocklistFormat::Frozen,
+            }],
+            ..Default::default()
+        };
+        let provider = BlocklistProvider::new(config);
+        provider.load().await.unwrap();
+        assert_eq!(provider.len(), 2);
+        let m = provider.find("cdn.ads.example.com").unwrap();
+        assert_eq!(m.rule_id, "blocklist:big");
+        assert_eq!(m.pattern, "ads.example.com");
+
+        let old = std::fs::metadata(&path).unwrap().modified().unwrap();
+        write(&["malware.example.org"]);
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(old + std::time::Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(provider.refresh(false).await, 1);
+        assert!(provider.find("ads.example.com").is_none());
+        assert!(provider.find("malware.example.org").is_some());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
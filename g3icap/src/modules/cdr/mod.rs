@@ -0,0 +1,611 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Content Disarm and Reconstruction (CDR) Module for G3ICAP
+//!
+//! Office and PDF documents in RESPMOD bodies are rebuilt without their
+//! active content, and the sanitized document is returned as the adapted
+//! HTTP response body:
+//! - OOXML packages lose their VBA projects, ActiveX controls, embedded
+//!   OLE objects, ribbon customizations and remote templates
+//! - PDF documents lose their JavaScript, open / additional / launch
+//!   actions, embedded files and rich media
+//! - legacy OLE2 documents can not be rebuilt, those with macros or
+//!   embedded objects are handled as sanitization failures
+//!
+//! The action is selected by the user groups of the client first, then by
+//! the URL category given by the proxy, then the default one applies.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use crate::auth::identity::ClientIdentity;
+use crate::modules::{IcapModule, ModuleConfig, ModuleError, ModuleMetrics};
+use crate::protocol::common::{EncapsulatedData, IcapMethod, IcapRequest, IcapResponse};
+use crate::protocol::response_generator::IcapResponseGenerator;
+use crate::transaction::TransactionCtx;
+
+mod ooxml;
+mod pdf;
+mod zip;
+
+pub use crate::modules::HEADER_URL_CATEGORY;
+
+/// ICAP response header listing the removed active content
+pub const HEADER_CDR_REMOVED: &str = "x-cdr-removed";
+
+static CDR_STATS: CdrStats = CdrStats::new();
+
+/// Get the CDR statistics of this process
+pub fn global_stats() -> &'static CdrStats {
+    &CDR_STATS
+}
+
+/// What to do with documents containing active content
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CdrAction {
+    /// Return the rebuilt document
+    Sanitize,
+    /// Return the original document
+    PassThrough,
+    /// Block the document
+    Block,
+}
+
+/// Document types handled by the CDR module
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DocumentKind {
+    /// docx, xlsx, pptx and their macro enabled variants
+    Ooxml,
+    /// PDF documents
+    Pdf,
+    /// Legacy doc, xls and ppt documents
+    Ole2,
+}
+
+impl DocumentKind {
+    /// Detect the document type from its leading bytes
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        if data.starts_with(OLE2_SIGNATURE) {
+            Some(DocumentKind::Ole2)
+        } else if ooxml::is_package(data) {
+            Some(DocumentKind::Ooxml)
+        } else if pdf::is_document(data) {
+            Some(DocumentKind::Pdf)
+        } else {
+            None
+        }
+    }
+
+    /// Metric tag value of the document type
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DocumentKind::Ooxml => "ooxml",
+            DocumentKind::Pdf => "pdf",
+            DocumentKind::Ole2 => "ole2",
+        }
+    }
+}
+
+const OLE2_SIGNATURE: &[u8] = &[0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1];
+
+/// Storage names of OLE2 documents holding active content, in UTF-16LE
+const OLE2_ACTIVE_STORAGES: &[&str] = &["_VBA_PROJECT", "Macros", "ObjectPool"];
+
+/// CDR configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CdrConfig {
+    /// Action when no group or category action applies
+    pub default_action: CdrAction,
+    /// Actions keyed by user group
+    pub group_actions: HashMap<String, CdrAction>,
+    /// Actions keyed by URL category
+    pub category_actions: HashMap<String, CdrAction>,
+    /// Document types to process
+    pub document_types: Vec<DocumentKind>,
+    /// Larger documents are handled as sanitization failures
+    pub max_document_size: u64,
+    /// Block documents that can not be sanitized instead of passing them through
+    pub block_on_failure: bool,
+    /// Enable logging
+    pub enable_logging: bool,
+}
+
+impl Default for CdrConfig {
+    fn default() -> Self {
+        CdrConfig {
+            default_action: CdrAction::Sanitize,
+            group_actions: HashMap::new(),
+            category_actions: HashMap::new(),
+            document_types: vec![DocumentKind::Ooxml, DocumentKind::Pdf, DocumentKind::Ole2],
+            max_document_size: 32 * 1024 * 1024,
+            block_on_failure: true,
+            enable_logging: true,
+        }
+    }
+}
+
+/// CDR error types
+#[derive(Debug, thiserror::Error)]
+pub enum CdrError {
+    #[error("malformed document: {0}")]
+    Malformed(String),
+    #[error("unsupported document: {0}")]
+    Unsupported(String),
+}
+
+/// Result of the sanitization of a document
+#[derive(Debug, Clone)]
+pub enum SanitizeOutcome {
+    /// No active content found
+    Clean,
+    /// Active content removed
+    Sanitized {
+        /// The rebuilt document
+        body: Bytes,
+        /// Description of each removed item
+        removed: Vec<String>,
+    },
+}
+
+/// Sanitize a document of the given type
+pub fn sanitize_document(kind: DocumentKind, data: &[u8]) -> Result<SanitizeOutcome, CdrError> {
+    match kind {
+        DocumentKind::Ooxml => ooxml::sanitize(data),
+        DocumentKind::Pdf => pdf::sanitize(data),
+        DocumentKind::Ole2 => {
+            for storage in OLE2_ACTIVE_STORAGES {
+                let name: Vec<u8> = storage
+                    .encode_utf16()
+                    .flat_map(|c| c.to_le_bytes())
+                    .collect();
+                if data.windows(name.len()).any(|w| w == name.as_slice()) {
+                    return Err(CdrError::Unsupported(format!(
+                        "legacy document with {storage} storage"
+                    )));
+                }
+            }
+            Ok(SanitizeOutcome::Clean)
+        }
+    }
+}
+
+/// CDR statistics
+#[derive(Debug)]
+pub struct CdrStats {
+    sanitized: AtomicU64,
+    passed_through: AtomicU64,
+    blocked: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl CdrStats {
+    const fn new() -> Self {
+        CdrStats {
+            sanitized: AtomicU64::new(0),
+            passed_through: AtomicU64::new(0),
+            blocked: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+        }
+    }
+
+    /// Documents returned after removing active content
+    pub fn sanitized(&self) -> u64 {
+        self.sanitized.load(Ordering::Relaxed)
+    }
+
+    /// Documents returned unchanged, because they are clean or by policy
+    pub fn passed_through(&self) -> u64 {
+        self.passed_through.load(Ordering::Relaxed)
+    }
+
+    /// Documents blocked by policy or after a sanitization failure
+    pub fn blocked(&self) -> u64 {
+        self.blocked.load(Ordering::Relaxed)
+    }
+
+    /// Documents that could not be sanitized
+    pub fn failed(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+}
+
+/// CDR module
+pub struct CdrModule {
+    /// Module name
+    name: String,
+    /// Module version
+    version: String,
+    /// CDR configuration
+    config: CdrConfig,
+    /// Module metrics
+    metrics: Arc<Mutex<ModuleMetrics>>,
+}
+
+impl CdrModule {
+    /// Create a new CDR module
+    pub fn new(config: CdrConfig) -> Self {
+        Self {
+            name: "cdr".to_string(),
+            version: "1.0.0".to_string(),
+            config,
+            metrics: Arc::new(Mutex::new(ModuleMetrics::default())),
+        }
+    }
+
+    fn response_generator() -> IcapResponseGenerator {
+        IcapResponseGenerator::with_service_id(
+            "G3ICAP-CDR/1.0.0".to_string(),
+            "cdr-1.0.0".to_string(),
+            Some("cdr".to_string()),
+        )
+    }
+
+    /// Select the action for the client of the request
+    fn select_action(&self, request: &IcapRequest) -> CdrAction {
+        let identity = ClientIdentity::from_headers(&request.headers);
+        if let Some(action) = identity
+            .groups
+            .iter()
+            .find_map(|g| self.config.group_actions.get(g))
+        {
+            return *action;
+        }
+        request
+            .headers
+            .get(HEADER_URL_CATEGORY)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|c| self.config.category_actions.get(c.trim()))
+            .copied()
+            .unwrap_or(self.config.default_action)
+    }
+
+    fn handle_failure(
+        &self,
+        request: &IcapRequest,
+        kind: DocumentKind,
+        reason: &str,
+    ) -> IcapResponse {
+        CDR_STATS.failed.fetch_add(1, Ordering::Relaxed);
+        if self.config.enable_logging {
+            log::warn!(
+                "CDR failed for {} document {}: {}",
+                kind.as_str(),
+                request.uri,
+                reason
+            );
+        }
+        if self.config.block_on_failure {
+            CDR_STATS.blocked.fetch_add(1, Ordering::Relaxed);
+            Self::response_generator()
+                .forbidden(Some(&format!("Document could not be sanitized: {reason}")))
+        } else {
+            CDR_STATS.passed_through.fetch_add(1, Ordering::Relaxed);
+            Self::response_generator().no_modifications(None)
+        }
+    }
+
+    /// Build the adapted response carrying the sanitized document
+    fn sanitized_response(
+        &self,
+        request: &IcapRequest,
+        body: Bytes,
+        removed: &[String],
+    ) -> IcapResponse {
+        let mut res_hdr = request
+            .encapsulated
+            .as_ref()
+            .and_then(|e| e.res_hdr.clone())
+            .unwrap_or_default();
+        // the Content-Length is fixed when the response is built
+        res_hdr.remove("content-md5");
+        res_hdr.remove(http::header::ETAG);
+        let encapsulated = EncapsulatedData {
+            req_hdr: None,
+            req_body: None,
+            res_hdr: Some(res_hdr),
+            res_body: Some(body.clone()),
+            null_body: false,
+            trailers: None,
+            ieof: false,
+            req_line: None,
+            status_line: None,
+        };
+
+        let mut response = Self::response_generator().ok_modified(Some(encapsulated), body);
+        if let Ok(value) = removed.join(", ").parse() {
+            response.headers.insert(HEADER_CDR_REMOVED, value);
+        }
+        response
+    }
+
+    fn update_metrics(&self) {
+        let mut metrics = self.metrics.lock().unwrap();
+        metrics.requests_total += 1;
+        metrics.last_activity = Some(std::time::Instant::now());
+    }
+}
+
+#[async_trait]
+impl IcapModule for CdrModule {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn version(&self) -> &str {
+        &self.version
+    }
+
+    fn supported_methods(&self) -> Vec<IcapMethod> {
+        vec![IcapMethod::Respmod, IcapMethod::Options]
+    }
+
+    async fn init(&mut self, config: &ModuleConfig) -> Result<(), ModuleError> {
+        if let Some(cdr_config) = config.parse_config()? {
+            self.config = cdr_config;
+        }
+
+        if self.config.enable_logging {
+            log::info!(
+                "CDR module initialized, default action {:?}, {} group and {} category actions",
+                self.config.default_action,
+                self.config.group_actions.len(),
+                self.config.category_actions.len()
+            );
+        }
+        Ok(())
+    }
+
+    async fn handle_reqmod(
+        &self,
+        _request: &IcapRequest,
+        _ctx: &TransactionCtx,
+    ) -> Result<IcapResponse, ModuleError> {
+        Ok(Self::response_generator().no_modifications(None))
+    }
+
+    async fn handle_respmod(
+        &self,
+        request: &IcapRequest,
+        ctx: &TransactionCtx,
+    ) -> Result<IcapResponse, ModuleError> {
+        self.update_metrics();
+
+        // documents pass unchanged while CDR is switched off at runtime
+        if !crate::features::CDR.is_enabled() {
+            CDR_STATS.passed_through.fetch_add(1, Ordering::Relaxed);
+            return Ok(Self::response_generator().no_modifications(None));
+        }
+
+        let body = request
+            .encapsulated
+            .as_ref()
+            .and_then(|e| e.res_body.clone())
+            .unwrap_or_else(|| request.body.clone());
+        let Some(kind) =
+            DocumentKind::detect(&body).filter(|k| self.config.document_types.contains(k))
+        else {
+            return Ok(Self::response_generator().no_modifications(None));
+        };
+
+        let action = self.select_action(request);
+        if action == CdrAction::PassThrough {
+            CDR_STATS.passed_through.fetch_add(1, Ordering::Relaxed);
+            return Ok(Self::response_generator().no_modifications(None));
+        }
+        if body.len() as u64 > self.config.max_document_size {
+            return Ok(self.handle_failure(request, kind, "document too large"));
+        }
+
+        // the extraction is not waited for once the transaction is cancelled
+        let data = body.clone();
+        let sanitized = crate::modules::until_cancelled(ctx, async move {
+            tokio::task::spawn_blocking(move || sanitize_document(kind, &data))
+                .await
+                .map_err(|e| ModuleError::ExecutionFailed(format!("CDR task failed: {e}")))
+        })
+        .await?;
+        match sanitized {
+            Ok(SanitizeOutcome::Clean) => {
+                CDR_STATS.passed_through.fetch_add(1, Ordering::Relaxed);
+                Ok(Self::response_generator().no_modifications(None))
+            }
+            Ok(SanitizeOutcome::Sanitized { body, removed }) => {
+                if action == CdrAction::Block {
+                    CDR_STATS.blocked.fetch_add(1, Ordering::Relaxed);
+                    if self.config.enable_logging {
+                        log::warn!(
+                            "CDR blocked {} document {}: {}",
+                            kind.as_str(),
+                            request.uri,
+                            removed.join(", ")
+                        );
+                    }
+                    return Ok(Self::response_generator()
+                        .forbidden(Some("Document contains active content")));
+                }
+                CDR_STATS.sanitized.fetch_add(1, Ordering::Relaxed);
+                if self.config.enable_logging {
+                    log::info!(
+                        "CDR sanitized {} document {}: {}",
+                        kind.as_str(),
+                        request.uri,
+                        removed.join(", ")
+                    );
+                }
+                Ok(self.sanitized_response(request, body, &removed))
+            }
+            Err(e) => Ok(self.handle_failure(request, kind, &e.to_string())),
+        }
+    }
+
+    async fn handle_options(&self, _request: &IcapRequest) -> Result<IcapResponse, ModuleError> {
+        let mut capabilities = HashMap::new();
+        capabilities.insert(
+            "Service".to_string(),
+            "G3ICAP Content Disarm and Reconstruction".to_string(),
+        );
+        capabilities.insert("Allow".to_string(), "204".to_string());
+        Ok(Self::response_generator().options_response(&[IcapMethod::Respmod], capabilities))
+    }
+
+    fn is_healthy(&self) -> bool {
+        true
+    }
+
+    fn get_metrics(&self) -> ModuleMetrics {
+        self.metrics.lock().unwrap().clone()
+    }
+
+    async fn cleanup(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::{HeaderMap, Uri, Version};
+
+    fn create_respmod_request(body: &[u8]) -> IcapRequest {
+        IcapRequest {
+            method: IcapMethod::Respmod,
+            uri: "icap://localhost/cdr".parse::<Uri>().unwrap(),
+            version: Version::HTTP_11,
+            headers: HeaderMap::new(),
+            body: Bytes::copy_from_slice(body),
+            encapsulated: None,
+        }
+    }
+
+    fn create_docm() -> Vec<u8> {
+        let entries = vec![
+            zip::ZipEntry::new(
+                "[Content_Types].xml",
+                br#"<Types><Override PartName="/word/document.xml" ContentType="application/vnd.ms-word.document.macroEnabled.main+xml"/><Override PartName="/word/vbaProject.bin" ContentType="application/vnd.ms-office.vbaProject"/></Types>"#,
+            )
+            .unwrap(),
+            zip::ZipEntry::new("word/document.xml", b"<w:document/>").unwrap(),
+            zip::ZipEntry::new(
+                "word/_rels/document.xml.rels",
+                br#"<Relationships><Relationship Id="rId1" Type="http://schemas.microsoft.com/office/2006/relationships/vbaProject" Target="vbaProject.bin"/><Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/attachedTemplate" Target="http://evil.example/t.dotm" TargetMode="External"/><Relationship Id="rId3" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles" Target="styles.xml"/></Relationships>"#,
+            )
+            .unwrap(),
+            zip::ZipEntry::new("word/vbaProject.bin", b"Attribute VB_Name").unwrap(),
+        ];
+        zip::write_archive(&entries).unwrap()
+    }
+
+    #[test]
+    fn test_ooxml_sanitize() {
+        let docm = create_docm();
+        assert_eq!(DocumentKind::detect(&docm), Some(DocumentKind::Ooxml));
+
+        let SanitizeOutcome::Sanitized { body, removed } =
+            sanitize_document(DocumentKind::Ooxml, &docm).unwrap()
+        else {
+            panic!("active content not removed");
+        };
+        assert_eq!(
+            removed,
+            vec!["word/vbaProject.bin", "http://evil.example/t.dotm"]
+        );
+
+        let entries = zip::read_archive(&body).unwrap();
+        let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "[Content_Types].xml",
+                "word/document.xml",
+                "word/_rels/document.xml.rels"
+            ]
+        );
+        let content_types = String::from_utf8(entries[0].content().unwrap()).unwrap();
+        assert!(!content_types.contains("macroEnabled"));
+        assert!(!content_types.contains("vbaProject"));
+        let rels = String::from_utf8(entries[2].content().unwrap()).unwrap();
+        assert!(rels.contains("styles.xml"));
+        assert!(!rels.contains("rId1") && !rels.contains("rId2"));
+
+        // a rebuilt document is clean
+        assert!(matches!(
+            sanitize_document(DocumentKind::Ooxml, &body).unwrap(),
+            SanitizeOutcome::Clean
+        ));
+    }
+
+    #[test]
+    fn test_pdf_sanitize() {
+        let pdf = b"%PDF-1.7\n1 0 obj\n<< /Type /Catalog /OpenAction 2 0 R /Names << /J#61vaScript 3 0 R >> >>\nendobj\n\
+            2 0 obj\n<< /S /JavaScript /JS (app.alert\\(1\\)) >>\nendobj\n\
+            4 0 obj\n<< /Length 4 >>\nstream\n/JS \nendstream\nendobj\n%%EOF\n";
+        let SanitizeOutcome::Sanitized { body, removed } =
+            sanitize_document(DocumentKind::Pdf, pdf).unwrap()
+        else {
+            panic!("active content not removed");
+        };
+        assert_eq!(body.len(), pdf.len());
+        assert_eq!(removed, vec!["/OpenAction", "/JavaScript", "/JS"]);
+        let text = String::from_utf8_lossy(&body);
+        assert!(!text.contains("/OpenAction") && !text.contains("/S /JavaScript"));
+        // stream data is left untouched
+        assert!(text.contains("stream\n/JS \nendstream"));
+    }
+
+    #[tokio::test]
+    async fn test_respmod_actions() {
+        let mut config = CdrConfig::default();
+        config
+            .group_actions
+            .insert("developers".to_string(), CdrAction::PassThrough);
+        config
+            .category_actions
+            .insert("file-sharing".to_string(), CdrAction::Block);
+        let module = CdrModule::new(config);
+        let docm = create_docm();
+
+        let request = create_respmod_request(&docm);
+        let response = module.handle_respmod(&request, &TransactionCtx::for_request(&request)).await.unwrap();
+        assert_eq!(response.status, http::StatusCode::OK);
+        assert!(response.headers.contains_key(HEADER_CDR_REMOVED));
+
+        let mut request = create_respmod_request(&docm);
+        request
+            .headers
+            .insert("x-client-groups", "developers".parse().unwrap());
+        request
+            .headers
+            .insert(HEADER_URL_CATEGORY, "file-sharing".parse().unwrap());
+        let response = module.handle_respmod(&request, &TransactionCtx::for_request(&request)).await.unwrap();
+        assert_eq!(response.status, http::StatusCode::NO_CONTENT);
+
+        let mut request = create_respmod_request(&docm);
+        request
+            .headers
+            .insert(HEADER_URL_CATEGORY, "file-sharing".parse().unwrap());
+        let response = module.handle_respmod(&request, &TransactionCtx::for_request(&request)).await.unwrap();
+        assert_eq!(response.status, http::StatusCode::FORBIDDEN);
+
+        let request = create_respmod_request(b"plain text");
+        let response = module
+            .handle_respmod(&request, &TransactionCtx::for_request(&request))
+            .await
+            .unwrap();
+        assert_eq!(response.status, http::StatusCode::NO_CONTENT);
+
+        let request = create_respmod_request(&docm);
+        let ctx = TransactionCtx::for_request(&request);
+        ctx.cancel();
+        let result = module.handle_respmod(&request, &ctx).await;
+        assert!(matches!(result, Err(ModuleError::Cancelled)));
+    }
+}
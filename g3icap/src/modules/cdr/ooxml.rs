@@ -0,0 +1,219 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Office Open XML (docx/xlsx/pptx and their macro enabled variants)
+//!
+//! The package is rebuilt without VBA projects, ActiveX controls, embedded
+//! OLE objects and ribbon customizations. Relationships pointing to removed
+//! parts and to remote templates are dropped, and macro enabled content
+//! types are turned into their plain equivalents.
+
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+use super::zip::{self, ZipEntry};
+use super::{CdrError, SanitizeOutcome};
+
+const CONTENT_TYPES_PART: &str = "[Content_Types].xml";
+
+/// Relationship types that always point to active content
+const ACTIVE_RELATIONSHIP_TYPES: &[&str] = &[
+    "/vbaProject",
+    "/wordVbaData",
+    "/oleObject",
+    "/activeXControl",
+    "/activeXControlBinary",
+    "/package",
+    "/ui/extensibility",
+    "/keyMapCustomizations",
+];
+
+/// Macro enabled main content types and their plain equivalents
+const MACRO_CONTENT_TYPES: &[(&str, &str)] = &[
+    (
+        "application/vnd.ms-word.document.macroEnabled.main+xml",
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document.main+xml",
+    ),
+    (
+        "application/vnd.ms-word.template.macroEnabledTemplate.main+xml",
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.template.main+xml",
+    ),
+    (
+        "application/vnd.ms-excel.sheet.macroEnabled.main+xml",
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml",
+    ),
+    (
+        "application/vnd.ms-excel.template.macroEnabled.main+xml",
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.template.main+xml",
+    ),
+    (
+        "application/vnd.ms-powerpoint.presentation.macroEnabled.main+xml",
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation.main+xml",
+    ),
+    (
+        "application/vnd.ms-powerpoint.slideshow.macroEnabled.main+xml",
+        "application/vnd.openxmlformats-officedocument.presentationml.slideshow.main+xml",
+    ),
+    (
+        "application/vnd.ms-powerpoint.template.macroEnabled.main+xml",
+        "application/vnd.openxmlformats-officedocument.presentationml.template.main+xml",
+    ),
+];
+
+static OVERRIDE_ELEMENT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<Override\b[^>]*>").unwrap());
+static RELATIONSHIP_ELEMENT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<Relationship\b[^>]*>").unwrap());
+
+/// Check if a zip archive is an OOXML package
+pub(super) fn is_package(data: &[u8]) -> bool {
+    data.starts_with(b"PK\x03\x04")
+        && data
+            .windows(CONTENT_TYPES_PART.len())
+            .any(|w| w == CONTENT_TYPES_PART.as_bytes())
+}
+
+/// Rebuild the package without its active parts
+pub(super) fn sanitize(data: &[u8]) -> Result<SanitizeOutcome, CdrError> {
+    let entries = zip::read_archive(data)?;
+
+    // XLM macro sheets are referenced from the workbook itself, dropping them
+    // would leave an unreadable workbook
+    if let Some(entry) = entries
+        .iter()
+        .find(|e| e.name.to_ascii_lowercase().contains("/macrosheets/"))
+    {
+        return Err(CdrError::Unsupported(format!(
+            "excel 4.0 macro sheet {}",
+            entry.name
+        )));
+    }
+
+    let dropped_parts: Vec<String> = entries
+        .iter()
+        .filter(|e| is_active_part(&e.name))
+        .map(|e| e.name.clone())
+        .collect();
+    let mut removed = dropped_parts.clone();
+
+    let mut kept: Vec<ZipEntry> = Vec::with_capacity(entries.len());
+    for mut entry in entries {
+        if dropped_parts.contains(&entry.name) {
+            continue;
+        }
+        if entry.name == CONTENT_TYPES_PART {
+            let xml = xml_text(&entry)?;
+            let cleaned = clean_content_types(&xml, &dropped_parts);
+            if cleaned != xml {
+                entry.set_content(cleaned.as_bytes())?;
+            }
+        } else if entry.name.ends_with(".rels") {
+            let xml = xml_text(&entry)?;
+            let (cleaned, targets) = clean_relationships(&xml, &dropped_parts);
+            if !targets.is_empty() {
+                entry.set_content(cleaned.as_bytes())?;
+                // relationships to removed parts are already accounted for
+                removed.extend(
+                    targets
+                        .into_iter()
+                        .filter(|t| !dropped_parts.iter().any(|p| part_matches(p, t))),
+                );
+            }
+        }
+        kept.push(entry);
+    }
+
+    if removed.is_empty() {
+        return Ok(SanitizeOutcome::Clean);
+    }
+    Ok(SanitizeOutcome::Sanitized {
+        body: zip::write_archive(&kept)?.into(),
+        removed,
+    })
+}
+
+fn is_active_part(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    let file_name = name.rsplit('/').next().unwrap_or(&name);
+    file_name.starts_with("vbaproject")
+        || file_name.starts_with("vbadata")
+        || file_name.starts_with("oleobject")
+        || name.contains("/activex/")
+        || name.contains("/embeddings/")
+        || name.starts_with("customui/")
+}
+
+fn xml_text(entry: &ZipEntry) -> Result<String, CdrError> {
+    String::from_utf8(entry.content()?)
+        .map_err(|_| CdrError::Malformed(format!("{} is not valid UTF-8", entry.name)))
+}
+
+fn attribute<'a>(element: &'a str, name: &str) -> Option<&'a str> {
+    let pattern = format!(" {name}=\"");
+    let start = element.find(&pattern)? + pattern.len();
+    let len = element[start..].find('"')?;
+    Some(&element[start..start + len])
+}
+
+/// Check if a relationship target, relative to any source part, is the given part
+fn part_matches(part: &str, target: &str) -> bool {
+    let mut target = target.trim_start_matches('/');
+    while let Some(t) = target.strip_prefix("../") {
+        target = t;
+    }
+    let target = target.trim_start_matches("./");
+    !target.is_empty()
+        && (part == target
+            || (part.ends_with(target) && part[..part.len() - target.len()].ends_with('/')))
+}
+
+fn clean_content_types(xml: &str, dropped_parts: &[String]) -> String {
+    let mut cleaned = OVERRIDE_ELEMENT
+        .replace_all(xml, |caps: &regex::Captures| {
+            let element = &caps[0];
+            match attribute(element, "PartName") {
+                Some(part)
+                    if dropped_parts
+                        .iter()
+                        .any(|p| p == part.trim_start_matches('/')) =>
+                {
+                    String::new()
+                }
+                _ => element.to_string(),
+            }
+        })
+        .into_owned();
+    for (macro_type, plain_type) in MACRO_CONTENT_TYPES {
+        cleaned = cleaned.replace(macro_type, plain_type);
+    }
+    cleaned
+}
+
+/// Remove relationships to active content, returns the removed targets
+fn clean_relationships(xml: &str, dropped_parts: &[String]) -> (String, Vec<String>) {
+    let mut targets = Vec::new();
+    let cleaned = RELATIONSHIP_ELEMENT
+        .replace_all(xml, |caps: &regex::Captures| {
+            let element = &caps[0];
+            let rel_type = attribute(element, "Type").unwrap_or_default();
+            let target = attribute(element, "Target").unwrap_or_default();
+            let external = attribute(element, "TargetMode") == Some("External");
+
+            let drop = ACTIVE_RELATIONSHIP_TYPES
+                .iter()
+                .any(|t| rel_type.ends_with(t))
+                || (external && rel_type.ends_with("/attachedTemplate"))
+                || (!external && dropped_parts.iter().any(|p| part_matches(p, target)));
+            if drop {
+                targets.push(target.to_string());
+                String::new()
+            } else {
+                element.to_string()
+            }
+        })
+        .into_owned();
+    (cleaned, targets)
+}
@@ -0,0 +1,215 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! PDF documents
+//!
+//! Active content is reached through a small set of dictionary keys
+//! (JavaScript actions, open actions, launch actions, embedded files...).
+//! These keys are renamed in place to names no reader knows about, which
+//! keeps every object at its offset so the cross reference table stays
+//! valid. Compressed object streams can not be rewritten this way, a
+//! document with active content inside them is reported as unsupported.
+
+use std::io::Read;
+use std::ops::Range;
+
+use flate2::read::ZlibDecoder;
+
+use super::{CdrError, SanitizeOutcome};
+
+/// Names of dictionary keys leading to active content
+const ACTIVE_NAMES: &[&[u8]] = &[
+    b"JavaScript",
+    b"JS",
+    b"OpenAction",
+    b"AA",
+    b"Launch",
+    b"EmbeddedFile",
+    b"EmbeddedFiles",
+    b"RichMedia",
+];
+
+/// Check if the data is a PDF document
+pub(super) fn is_document(data: &[u8]) -> bool {
+    // the header may be preceded by some garbage
+    data.len() > 5
+        && data[..data.len().min(1024)]
+            .windows(5)
+            .any(|w| w == b"%PDF-")
+}
+
+/// Neutralize the active content keys of the document
+pub(super) fn sanitize(data: &[u8]) -> Result<SanitizeOutcome, CdrError> {
+    let streams = find_streams(data);
+
+    for stream in &streams {
+        let dict = &data[stream.dict.clone()];
+        if !contains(dict, b"/ObjStm") {
+            continue;
+        }
+        let decoded = decode_stream(dict, &data[stream.data.clone()])?;
+        if let Some((_, name)) = find_active_names(&decoded, &[]).into_iter().next() {
+            return Err(CdrError::Unsupported(format!(
+                "/{name} in compressed object stream"
+            )));
+        }
+    }
+
+    let found = find_active_names(data, &streams);
+    if found.is_empty() {
+        return Ok(SanitizeOutcome::Clean);
+    }
+
+    let mut body = data.to_vec();
+    let mut removed = Vec::new();
+    for (range, name) in found {
+        // keep the leading '/' and the token length
+        body[range.start + 1..range.end].fill(b'X');
+        let name = format!("/{name}");
+        if !removed.contains(&name) {
+            removed.push(name);
+        }
+    }
+    Ok(SanitizeOutcome::Sanitized {
+        body: body.into(),
+        removed,
+    })
+}
+
+struct PdfStream {
+    /// Stream dictionary, from the object header to the `stream` keyword
+    dict: Range<usize>,
+    /// Raw stream data
+    data: Range<usize>,
+}
+
+fn find_streams(data: &[u8]) -> Vec<PdfStream> {
+    let mut streams = Vec::new();
+    let mut pos = 0;
+    while let Some(found) = find(&data[pos..], b"stream") {
+        let keyword = pos + found;
+        pos = keyword + 6;
+        if keyword >= 3 && &data[keyword - 3..keyword] == b"end" {
+            continue;
+        }
+        let data_start = if data[pos..].starts_with(b"\r\n") {
+            pos + 2
+        } else if data[pos..].starts_with(b"\n") || data[pos..].starts_with(b"\r") {
+            pos + 1
+        } else {
+            continue;
+        };
+        let Some(len) = find(&data[data_start..], b"endstream") else {
+            break;
+        };
+        let dict_start = rfind(&data[..keyword], b"obj").unwrap_or(0);
+        streams.push(PdfStream {
+            dict: dict_start..keyword,
+            data: data_start..data_start + len,
+        });
+        pos = data_start + len + 9;
+    }
+    streams
+}
+
+fn decode_stream(dict: &[u8], raw: &[u8]) -> Result<Vec<u8>, CdrError> {
+    if !contains(dict, b"/Filter") {
+        return Ok(raw.to_vec());
+    }
+    if !contains(dict, b"/FlateDecode") || contains(dict, b"/DecodeParms") {
+        return Err(CdrError::Unsupported("object stream filter".to_string()));
+    }
+    let mut decoded = Vec::new();
+    ZlibDecoder::new(raw)
+        .read_to_end(&mut decoded)
+        .map_err(|e| CdrError::Unsupported(format!("undecodable object stream: {e}")))?;
+    Ok(decoded)
+}
+
+/// Find active name tokens outside of the skipped ranges
+fn find_active_names(data: &[u8], skip: &[PdfStream]) -> Vec<(Range<usize>, String)> {
+    let mut found = Vec::new();
+    let mut skip = skip.iter().map(|s| s.data.clone()).peekable();
+    let mut i = 0;
+    while i < data.len() {
+        if let Some(range) = skip.peek() {
+            if i >= range.end {
+                skip.next();
+                continue;
+            }
+            if i >= range.start {
+                i = range.end;
+                continue;
+            }
+        }
+        if data[i] != b'/' {
+            i += 1;
+            continue;
+        }
+        let mut end = i + 1;
+        while end < data.len() && !is_delimiter(data[end]) {
+            end += 1;
+        }
+        let name = decode_name(&data[i + 1..end]);
+        if ACTIVE_NAMES.contains(&name.as_slice()) {
+            found.push((i..end, String::from_utf8_lossy(&name).into_owned()));
+        }
+        i = end.max(i + 1);
+    }
+    found
+}
+
+/// Decode the `#xx` escapes of a name
+fn decode_name(raw: &[u8]) -> Vec<u8> {
+    let mut name = Vec::with_capacity(raw.len());
+    let mut i = 0;
+    while i < raw.len() {
+        if raw[i] == b'#' && i + 2 < raw.len() {
+            let hex = std::str::from_utf8(&raw[i + 1..i + 3]).ok();
+            if let Some(b) = hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                name.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        name.push(raw[i]);
+        i += 1;
+    }
+    name
+}
+
+fn is_delimiter(b: u8) -> bool {
+    matches!(
+        b,
+        b'\0'
+            | b'\t'
+            | b'\n'
+            | b'\x0c'
+            | b'\r'
+            | b' '
+            | b'('
+            | b')'
+            | b'<'
+            | b'>'
+            | b'['
+            | b']'
+            | b'{'
+            | b'}'
+            | b'/'
+            | b'%'
+    )
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn rfind(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).rposition(|w| w == needle)
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    find(haystack, needle).is_some()
+}
@@ -0,0 +1,265 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Minimal ZIP archive reader and writer
+//!
+//! Only what is needed to rebuild OOXML containers: stored and deflated
+//! entries, no ZIP64, no encryption. Entries are copied without being
+//! recompressed unless their content is replaced.
+
+use std::io::{Read, Write};
+
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+
+use super::CdrError;
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIR_SIGNATURE: u32 = 0x06054b50;
+const END_OF_CENTRAL_DIR_SIZE: usize = 22;
+
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATED: u16 = 8;
+
+const FLAG_ENCRYPTED: u16 = 0x0001;
+const FLAG_DATA_DESCRIPTOR: u16 = 0x0008;
+
+/// A single archive entry
+#[derive(Debug, Clone)]
+pub(super) struct ZipEntry {
+    pub(super) name: String,
+    method: u16,
+    flags: u16,
+    mod_time: u16,
+    mod_date: u16,
+    crc32: u32,
+    uncompressed_size: u32,
+    external_attr: u32,
+    /// Entry data as stored in the archive
+    data: Vec<u8>,
+}
+
+impl ZipEntry {
+    /// Create a deflated entry
+    #[cfg(test)]
+    pub(super) fn new(name: &str, content: &[u8]) -> Result<Self, CdrError> {
+        let mut entry = ZipEntry {
+            name: name.to_string(),
+            method: METHOD_DEFLATED,
+            flags: 0,
+            mod_time: 0,
+            mod_date: 0x21, // 1980-01-01
+            crc32: 0,
+            uncompressed_size: 0,
+            external_attr: 0,
+            data: Vec::new(),
+        };
+        entry.set_content(content)?;
+        Ok(entry)
+    }
+
+    /// Decompressed entry content
+    pub(super) fn content(&self) -> Result<Vec<u8>, CdrError> {
+        match self.method {
+            METHOD_STORED => Ok(self.data.clone()),
+            METHOD_DEFLATED => {
+                let mut buf = Vec::with_capacity(self.uncompressed_size as usize);
+                DeflateDecoder::new(self.data.as_slice())
+                    .read_to_end(&mut buf)
+                    .map_err(|e| {
+                        CdrError::Malformed(format!("failed to inflate {}: {e}", self.name))
+                    })?;
+                Ok(buf)
+            }
+            m => Err(CdrError::Unsupported(format!(
+                "compression method {m} of {}",
+                self.name
+            ))),
+        }
+    }
+
+    /// Replace the entry content, the entry is deflated again
+    pub(super) fn set_content(&mut self, content: &[u8]) -> Result<(), CdrError> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(content)
+            .map_err(|e| CdrError::Malformed(format!("failed to deflate {}: {e}", self.name)))?;
+        self.data = encoder
+            .finish()
+            .map_err(|e| CdrError::Malformed(format!("failed to deflate {}: {e}", self.name)))?;
+        let mut crc = flate2::Crc::new();
+        crc.update(content);
+        self.crc32 = crc.sum();
+        self.uncompressed_size = u32::try_from(content.len())
+            .map_err(|_| CdrError::Unsupported(format!("{} is too large", self.name)))?;
+        self.method = METHOD_DEFLATED;
+        Ok(())
+    }
+}
+
+/// Read all entries of an archive, using its central directory
+pub(super) fn read_archive(data: &[u8]) -> Result<Vec<ZipEntry>, CdrError> {
+    let eocd = find_end_of_central_dir(data)?;
+    let entry_count = u16_at(data, eocd + 10)? as usize;
+    let cd_offset = u32_at(data, eocd + 16)?;
+    if entry_count == 0xFFFF || cd_offset == 0xFFFF_FFFF {
+        return Err(CdrError::Unsupported("zip64 archive".to_string()));
+    }
+
+    let mut entries = Vec::with_capacity(entry_count);
+    let mut offset = cd_offset as usize;
+    for _ in 0..entry_count {
+        if u32_at(data, offset)? != CENTRAL_HEADER_SIGNATURE {
+            return Err(CdrError::Malformed(
+                "invalid central directory header".to_string(),
+            ));
+        }
+        let flags = u16_at(data, offset + 8)?;
+        let method = u16_at(data, offset + 10)?;
+        let mod_time = u16_at(data, offset + 12)?;
+        let mod_date = u16_at(data, offset + 14)?;
+        let crc32 = u32_at(data, offset + 16)?;
+        let compressed_size = u32_at(data, offset + 20)?;
+        let uncompressed_size = u32_at(data, offset + 24)?;
+        let name_len = u16_at(data, offset + 28)? as usize;
+        let extra_len = u16_at(data, offset + 30)? as usize;
+        let comment_len = u16_at(data, offset + 32)? as usize;
+        let external_attr = u32_at(data, offset + 38)?;
+        let local_offset = u32_at(data, offset + 42)?;
+        let name = slice(data, offset + 46, name_len)?;
+        let name = String::from_utf8_lossy(name).into_owned();
+        offset += 46 + name_len + extra_len + comment_len;
+
+        if flags & FLAG_ENCRYPTED != 0 {
+            return Err(CdrError::Unsupported(format!("encrypted entry {name}")));
+        }
+        if compressed_size == 0xFFFF_FFFF || local_offset == 0xFFFF_FFFF {
+            return Err(CdrError::Unsupported("zip64 archive".to_string()));
+        }
+
+        let local = local_offset as usize;
+        if u32_at(data, local)? != LOCAL_HEADER_SIGNATURE {
+            return Err(CdrError::Malformed(format!(
+                "invalid local header of {name}"
+            )));
+        }
+        let local_name_len = u16_at(data, local + 26)? as usize;
+        let local_extra_len = u16_at(data, local + 28)? as usize;
+        let data_start = local + 30 + local_name_len + local_extra_len;
+        let entry_data = slice(data, data_start, compressed_size as usize)?.to_vec();
+
+        entries.push(ZipEntry {
+            name,
+            method,
+            flags: flags & !FLAG_DATA_DESCRIPTOR,
+            mod_time,
+            mod_date,
+            crc32,
+            uncompressed_size,
+            external_attr,
+            data: entry_data,
+        });
+    }
+    Ok(entries)
+}
+
+/// Write a new archive with the given entries
+pub(super) fn write_archive(entries: &[ZipEntry]) -> Result<Vec<u8>, CdrError> {
+    let too_large = || CdrError::Unsupported("rebuilt archive is too large".to_string());
+    let entry_count = u16::try_from(entries.len()).map_err(|_| too_large())?;
+
+    let mut out = Vec::new();
+    let mut offsets = Vec::with_capacity(entries.len());
+    for entry in entries {
+        offsets.push(u32::try_from(out.len()).map_err(|_| too_large())?);
+        let compressed_size = u32::try_from(entry.data.len()).map_err(|_| too_large())?;
+        put_u32(&mut out, LOCAL_HEADER_SIGNATURE);
+        put_u16(&mut out, 20);
+        put_u16(&mut out, entry.flags);
+        put_u16(&mut out, entry.method);
+        put_u16(&mut out, entry.mod_time);
+        put_u16(&mut out, entry.mod_date);
+        put_u32(&mut out, entry.crc32);
+        put_u32(&mut out, compressed_size);
+        put_u32(&mut out, entry.uncompressed_size);
+        put_u16(&mut out, entry.name.len() as u16);
+        put_u16(&mut out, 0);
+        out.extend_from_slice(entry.name.as_bytes());
+        out.extend_from_slice(&entry.data);
+    }
+
+    let cd_offset = u32::try_from(out.len()).map_err(|_| too_large())?;
+    for (entry, offset) in entries.iter().zip(offsets) {
+        put_u32(&mut out, CENTRAL_HEADER_SIGNATURE);
+        put_u16(&mut out, 20);
+        put_u16(&mut out, 20);
+        put_u16(&mut out, entry.flags);
+        put_u16(&mut out, entry.method);
+        put_u16(&mut out, entry.mod_time);
+        put_u16(&mut out, entry.mod_date);
+        put_u32(&mut out, entry.crc32);
+        put_u32(&mut out, entry.data.len() as u32);
+        put_u32(&mut out, entry.uncompressed_size);
+        put_u16(&mut out, entry.name.len() as u16);
+        put_u16(&mut out, 0);
+        put_u16(&mut out, 0);
+        put_u16(&mut out, 0);
+        put_u16(&mut out, 0);
+        put_u32(&mut out, entry.external_attr);
+        put_u32(&mut out, offset);
+        out.extend_from_slice(entry.name.as_bytes());
+    }
+    let cd_size = u32::try_from(out.len()).map_err(|_| too_large())? - cd_offset;
+
+    put_u32(&mut out, END_OF_CENTRAL_DIR_SIGNATURE);
+    put_u16(&mut out, 0);
+    put_u16(&mut out, 0);
+    put_u16(&mut out, entry_count);
+    put_u16(&mut out, entry_count);
+    put_u32(&mut out, cd_size);
+    put_u32(&mut out, cd_offset);
+    put_u16(&mut out, 0);
+    Ok(out)
+}
+
+fn find_end_of_central_dir(data: &[u8]) -> Result<usize, CdrError> {
+    if data.len() < END_OF_CENTRAL_DIR_SIZE {
+        return Err(CdrError::Malformed("archive is too short".to_string()));
+    }
+    // the record is followed by a comment of at most 64KiB
+    let last = data.len() - END_OF_CENTRAL_DIR_SIZE;
+    let first = last.saturating_sub(u16::MAX as usize);
+    (first..=last)
+        .rev()
+        .find(|&i| data[i..i + 4] == END_OF_CENTRAL_DIR_SIGNATURE.to_le_bytes())
+        .ok_or_else(|| CdrError::Malformed("no end of central directory record".to_string()))
+}
+
+fn slice(data: &[u8], offset: usize, len: usize) -> Result<&[u8], CdrError> {
+    offset
+        .checked_add(len)
+        .and_then(|end| data.get(offset..end))
+        .ok_or_else(|| CdrError::Malformed("truncated archive".to_string()))
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16, CdrError> {
+    let b = slice(data, offset, 2)?;
+    Ok(u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32, CdrError> {
+    let b = slice(data, offset, 4)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn put_u16(out: &mut Vec<u8>, v: u16) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, v: u32) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
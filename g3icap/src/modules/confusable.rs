@@ -0,0 +1,387 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Confusable domain detection
+//!
+//! Phishing domains often imitate a brand with look-alike characters, e.g.
+//! a Cyrillic `а` in place of the Latin `a`, or mix several scripts in one
+//! label. Domains are reduced to their skeleton as described in UTS#39
+//! (Unicode Security Mechanisms), which maps each character to the
+//! prototype it is confusable with, and the skeleton is compared with the
+//! skeletons of the protected brand names.
+//!
+//! Only the part of the confusables table covering the scripts commonly
+//! used to imitate Latin brand names is built in: Cyrillic, Greek,
+//! Armenian, fullwidth forms and a few ASCII look-alikes.
+
+use serde::{Deserialize, Serialize};
+
+/// What to do with a confusable domain
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfusableAction {
+    /// Let the message through, with risk headers added to the response
+    #[default]
+    Warn,
+    /// Block the message like a blocked domain
+    Block,
+}
+
+/// Confusable domain detection configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConfusableConfig {
+    /// Enable the check
+    pub enabled: bool,
+    /// Protected brand names, e.g. `paypal` or `paypal.com`
+    pub brands: Vec<String>,
+    /// Also flag labels mixing scripts, even without a brand match
+    pub flag_mixed_script: bool,
+    /// Action taken on a flagged domain
+    pub action: ConfusableAction,
+}
+
+impl Default for ConfusableConfig {
+    fn default() -> Self {
+        ConfusableConfig {
+            enabled: false,
+            brands: Vec::new(),
+            flag_mixed_script: true,
+            action: ConfusableAction::Warn,
+        }
+    }
+}
+
+/// A flagged domain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfusableMatch {
+    /// The domain, with punycode labels decoded
+    pub domain: String,
+    /// The imitated brand, if any
+    pub brand: Option<String>,
+    /// Whether a label mixes scripts
+    pub mixed_script: bool,
+    /// Phishing risk, from 0 to 100
+    pub score: u8,
+}
+
+/// Risk score of a domain imitating a brand
+const SCORE_BRAND: u8 = 90;
+/// Risk score of a domain mixing scripts in one label
+const SCORE_MIXED_SCRIPT: u8 = 60;
+
+/// Compiled confusable domain detector
+#[derive(Debug, Clone, Default)]
+pub struct ConfusableDetector {
+    /// Brand names with their skeletons
+    brands: Vec<(String, String)>,
+    flag_mixed_script: bool,
+}
+
+impl ConfusableDetector {
+    /// Build the detector of a config
+    pub fn new(config: &ConfusableConfig) -> Self {
+        let brands = config
+            .brands
+            .iter()
+            .map(|b| brand_label(b))
+            .filter(|b| !b.is_empty())
+            .map(|b| {
+                let s = skeleton(&b);
+                (b, s)
+            })
+            .collect();
+        ConfusableDetector {
+            brands,
+            flag_mixed_script: config.flag_mixed_script,
+        }
+    }
+
+    /// Check a domain, as found in the Host header
+    pub fn check(&self, host: &str) -> Option<ConfusableMatch> {
+        let host = strip_port(host).trim_end_matches('.');
+        if host.is_empty() {
+            return None;
+        }
+        let (domain, _) = idna::domain_to_unicode(host);
+        let domain = domain.to_lowercase();
+
+        let mixed_script = self.flag_mixed_script && domain.split('.').any(is_mixed_script);
+        let brand = self.find_brand(&domain);
+        let score = match (&brand, mixed_script) {
+            (Some(_), true) => 100,
+            (Some(_), false) => SCORE_BRAND,
+            (None, true) => SCORE_MIXED_SCRIPT,
+            (None, false) => return None,
+        };
+        Some(ConfusableMatch {
+            domain,
+            brand,
+            mixed_script,
+            score,
+        })
+    }
+
+    /// Find a brand imitated by a label of the domain
+    ///
+    /// A label spelled exactly like the brand is the brand itself and is
+    /// not flagged.
+    fn find_brand(&self, domain: &str) -> Option<String> {
+        for label in domain.split('.') {
+            let s = skeleton(label);
+            for (brand, brand_skeleton) in &self.brands {
+                if s == *brand_skeleton && label != brand {
+                    return Some(brand.clone());
+                }
+            }
+        }
+        None
+    }
+}
+
+/// The label of a brand name to protect, `paypal` for `paypal.com`
+fn brand_label(brand: &str) -> String {
+    let brand = brand.trim().to_lowercase();
+    let (brand, _) = idna::domain_to_unicode(&brand);
+    brand.split('.').next().unwrap_or_default().to_string()
+}
+
+fn strip_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        return host;
+    }
+    match host.rsplit_once(':') {
+        Some((h, port)) if port.bytes().all(|b| b.is_ascii_digit()) => h,
+        _ => host,
+    }
+}
+
+/// The UTS#39 skeleton of a string
+///
+/// Each character is mapped to its prototype, then the sequences that look
+/// like a single character, such as `rn` for `m`, are folded.
+pub fn skeleton(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars().flat_map(char::to_lowercase) {
+        match prototype(c) {
+            Some(p) => out.push_str(p),
+            None => out.push(c),
+        }
+    }
+    out.replace("rn", "m").replace("vv", "w").replace("cl", "d")
+}
+
+/// Prototype of a confusable character
+fn prototype(c: char) -> Option<&'static str> {
+    let p = match c {
+        // fullwidth forms
+        '\u{ff21}'..='\u{ff3a}' | '\u{ff41}'..='\u{ff5a}' | '\u{ff10}'..='\u{ff19}' => {
+            return fullwidth(c);
+        }
+        // ASCII look-alikes
+        '0' => "o",
+        '1' | 'i' | '|' => "l",
+        '5' => "s",
+        // Latin
+        'ı' | 'ǀ' | 'ɩ' | 'ł' => "l",
+        'ſ' => "f",
+        'ɑ' | 'α' => "a",
+        'ɡ' => "g",
+        'ʏ' => "y",
+        // Cyrillic
+        'а' => "a",
+        'в' => "b",
+        'с' | 'ϲ' => "c",
+        'ԁ' => "d",
+        'е' | 'ё' => "e",
+        'һ' => "h",
+        'і' | 'ӏ' => "l",
+        'ј' | 'ϳ' => "j",
+        'к' => "k",
+        'м' => "m",
+        'н' => "h",
+        'о' | 'ο' | 'օ' | 'σ' => "o",
+        'р' | 'ρ' => "p",
+        'ԛ' => "q",
+        'г' => "r",
+        'ѕ' => "s",
+        'т' | 'τ' => "t",
+        'υ' | 'ս' => "u",
+        'ѵ' | 'ν' => "v",
+        'ԝ' | 'ω' => "w",
+        'х' | 'χ' => "x",
+        'у' | 'γ' => "y",
+        'ʐ' => "z",
+        // Greek
+        'β' => "b",
+        'ε' => "e",
+        'ι' => "l",
+        'κ' => "k",
+        'η' => "n",
+        // Armenian
+        'ց' => "g",
+        'հ' => "h",
+        'ո' => "n",
+        _ => return None,
+    };
+    Some(p)
+}
+
+fn fullwidth(c: char) -> Option<&'static str> {
+    const LOWER: [&str; 26] = [
+        "a", "b", "c", "d", "e", "f", "g", "h", "l", "j", "k", "l", "m", "n", "o", "p", "q", "r",
+        "s", "t", "u", "v", "w", "x", "y", "z",
+    ];
+    const DIGITS: [&str; 10] = ["o", "l", "2", "3", "4", "s", "6", "7", "8", "9"];
+    match c {
+        '\u{ff21}'..='\u{ff3a}' => Some(LOWER[c as usize - 0xff21]),
+        '\u{ff41}'..='\u{ff5a}' => Some(LOWER[c as usize - 0xff41]),
+        '\u{ff10}'..='\u{ff19}' => Some(DIGITS[c as usize - 0xff10]),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Script {
+    Latin,
+    Greek,
+    Cyrillic,
+    Armenian,
+    Hebrew,
+    Arabic,
+    Han,
+    Hiragana,
+    Katakana,
+    Hangul,
+    Bopomofo,
+    Other,
+}
+
+/// Script of a letter, `None` for characters common to all scripts
+fn script(c: char) -> Option<Script> {
+    let s = match c {
+        '0'..='9' | '-' | '_' => return None,
+        'a'..='z' | 'A'..='Z' => Script::Latin,
+        '\u{00c0}'..='\u{024f}' | '\u{1e00}'..='\u{1eff}' => Script::Latin,
+        '\u{ff21}'..='\u{ff3a}' | '\u{ff41}'..='\u{ff5a}' => Script::Latin,
+        '\u{0370}'..='\u{03ff}' | '\u{1f00}'..='\u{1fff}' => Script::Greek,
+        '\u{0400}'..='\u{052f}' => Script::Cyrillic,
+        '\u{0530}'..='\u{058f}' => Script::Armenian,
+        '\u{0590}'..='\u{05ff}' => Script::Hebrew,
+        '\u{0600}'..='\u{06ff}' | '\u{0750}'..='\u{077f}' => Script::Arabic,
+        '\u{3040}'..='\u{309f}' => Script::Hiragana,
+        '\u{30a0}'..='\u{30ff}' => Script::Katakana,
+        '\u{3100}'..='\u{312f}' | '\u{31a0}'..='\u{31bf}' => Script::Bopomofo,
+        '\u{1100}'..='\u{11ff}' | '\u{3130}'..='\u{318f}' | '\u{ac00}'..='\u{d7af}' => {
+            Script::Hangul
+        }
+        '\u{4e00}'..='\u{9fff}' | '\u{3400}'..='\u{4dbf}' => Script::Han,
+        '\u{3000}'..='\u{303f}' | '\u{ff10}'..='\u{ff19}' => return None,
+        _ if c.is_ascii() => return None,
+        _ => Script::Other,
+    };
+    Some(s)
+}
+
+/// Whether a label mixes scripts
+///
+/// The combinations allowed by the UTS#39 highly restrictive level are not
+/// mixed: Latin with Han and the Japanese kana, with Han and Bopomofo, or
+/// with Han and Hangul.
+fn is_mixed_script(label: &str) -> bool {
+    let mut scripts: Vec<Script> = Vec::new();
+    for s in label.chars().filter_map(script) {
+        if !scripts.contains(&s) {
+            scripts.push(s);
+        }
+    }
+    if scripts.len() <= 1 {
+        return false;
+    }
+
+    const ALLOWED: [&[Script]; 3] = [
+        &[
+            Script::Latin,
+            Script::Han,
+            Script::Hiragana,
+            Script::Katakana,
+        ],
+        &[Script::Latin, Script::Han, Script::Bopomofo],
+        &[Script::Latin, Script::Han, Script::Hangul],
+    ];
+    !ALLOWED
+        .iter()
+        .any(|set| scripts.iter().all(|s| set.contains(s)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detector(brands: &[&str]) -> ConfusableDetector {
+        ConfusableDetector::new(&ConfusableConfig {
+            enabled: true,
+            brands: brands.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn skeletons() {
+        assert_eq!(skeleton("paypal"), skeleton("pаypаl"));
+        assert_eq!(skeleton("paypal"), skeleton("PAYPA1"));
+        assert_eq!(skeleton("microsoft"), skeleton("rnicrosoft"));
+        assert_eq!(skeleton("google"), skeleton("g00gle"));
+        assert_eq!(skeleton("apple"), skeleton("ａｐｐｌｅ"));
+        assert_ne!(skeleton("paypal"), skeleton("paypa"));
+    }
+
+    #[test]
+    fn brand_lookalike() {
+        let d = detector(&["paypal.com", "apple"]);
+
+        let m = d.check("www.pаypal.com").unwrap();
+        assert_eq!(m.brand.as_deref(), Some("paypal"));
+        assert!(m.mixed_script);
+        assert_eq!(m.score, 100);
+
+        // the punycode form of the same domain
+        let m = d.check("www.xn--pypal-4ve.com:443").unwrap();
+        assert_eq!(m.domain, "www.pаypal.com");
+        assert_eq!(m.brand.as_deref(), Some("paypal"));
+
+        let m = d.check("paypa1.com").unwrap();
+        assert_eq!(m.brand.as_deref(), Some("paypal"));
+        assert!(!m.mixed_script);
+        assert_eq!(m.score, SCORE_BRAND);
+
+        assert!(d.check("paypal.com").is_none());
+        assert!(d.check("www.PayPal.com").is_none());
+        assert!(d.check("example.com").is_none());
+    }
+
+    #[test]
+    fn mixed_script() {
+        let d = detector(&[]);
+
+        let m = d.check("exаmple.com").unwrap();
+        assert!(m.mixed_script);
+        assert!(m.brand.is_none());
+        assert_eq!(m.score, SCORE_MIXED_SCRIPT);
+
+        // single script and highly restrictive combinations
+        assert!(d.check("пример.рф").is_none());
+        assert!(d.check("bücher.de").is_none());
+        assert!(d.check("abc日本語ひらがな.jp").is_none());
+        assert!(d.check("abc한국어.kr").is_none());
+
+        let d = ConfusableDetector::new(&ConfusableConfig {
+            enabled: true,
+            flag_mixed_script: false,
+            ..Default::default()
+        });
+        assert!(d.check("exаmple.com").is_none());
+    }
+}
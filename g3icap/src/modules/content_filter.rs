@@ -22,11 +22,24 @@ use async_trait::async_trait;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 
+use crate::auth::identity::ClientIdentity;
 use crate::protocol::common::{IcapMethod, IcapRequest, IcapResponse};
-use crate::modules::{IcapModule, ModuleConfig, ModuleError, ModuleMetrics};
+use crate::modules::{ExplainedMatch, IcapModule, ModuleConfig, ModuleError, ModuleMetrics};
+use crate::modules::ai_inspection::{AiInspectionAction, AiInspectionConfig, AiMessage, HEADER_AI_REDACTED};
+use crate::modules::block_page::{BlockPageConfig, BlockPageVars, BlockPages};
+use crate::modules::blocklist::BlocklistProvider;
+use crate::modules::confusable::{ConfusableAction, ConfusableConfig, ConfusableDetector, ConfusableMatch};
+use crate::modules::detection::{DetectionCapture, DetectionCaptureConfig};
+use crate::modules::file_type::{self, FileType, FileTypeDetectionConfig};
+use crate::modules::matcher::{DomainSuffixMatcher, KeywordMatcher, PatternEngine, PatternMatcher};
+use crate::modules::regex_cache::RegexCache;
+use crate::modules::rule_hits;
+use crate::modules::url_normalize::NormalizedUrl;
+use crate::transaction::TransactionCtx;
 
 /// Content filter configuration
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
 pub struct ContentFilterConfig {
     /// Blocked domains (exact match)
     pub blocked_domains: Vec<String>,
@@ -56,6 +69,204 @@ pub struct ContentFilterConfig {
     pub enable_metrics: bool,
     /// Cache size for compiled regex patterns
     pub regex_cache_size: usize,
+    /// Engine matching the regex patterns
+    #[serde(default)]
+    pub pattern_engine: PatternEngine,
+    /// Rule sets keyed by user name, taking precedence over group rules
+    #[serde(default)]
+    pub user_rules: HashMap<String, FilterRuleSet>,
+    /// Rule sets keyed by group name
+    #[serde(default)]
+    pub group_rules: HashMap<String, FilterRuleSet>,
+    /// Body capture of blocked messages
+    #[serde(default)]
+    pub detection_capture: DetectionCaptureConfig,
+    /// HTML block pages served for the `Forbidden` action
+    #[serde(default)]
+    pub block_page: BlockPageConfig,
+    /// Blocked detected file types, by kind, MIME type or extension
+    #[serde(default)]
+    pub blocked_file_types: Vec<String>,
+    /// Detection of the true file type of bodies
+    #[serde(default)]
+    pub file_type_detection: FileTypeDetectionConfig,
+    /// Detection of domains imitating protected brands
+    #[serde(default)]
+    pub confusable_domains: ConfusableConfig,
+    /// Inspection of the text fields of AI API messages
+    #[serde(default)]
+    pub ai_inspection: AiInspectionConfig,
+}
+
+impl ContentFilterConfig {
+    /// Check that all regex patterns compile
+    ///
+    /// The error points to the offending rule, e.g.
+    /// `group_rules.staff.blocked_keyword_patterns[2]`.
+    pub fn validate(&self) -> Result<(), ModuleError> {
+        self.pattern_engine.check()?;
+        if !self.enable_regex {
+            return Ok(());
+        }
+
+        let mut rule_sets = vec![(String::new(), self.default_rule_set())];
+        rule_sets.extend(self.user_rules.iter().map(|(k, v)| (format!("user_rules.{}.", k), v.clone())));
+        rule_sets.extend(self.group_rules.iter().map(|(k, v)| (format!("group_rules.{}.", k), v.clone())));
+        for (prefix, rules) in rule_sets {
+            for (field, patterns) in [
+                ("blocked_domain_patterns", &rules.blocked_domain_patterns),
+                ("blocked_keyword_patterns", &rules.blocked_keyword_patterns),
+            ] {
+                for (i, pattern) in patterns.iter().enumerate() {
+                    if let Err(e) = Regex::new(pattern) {
+                        return Err(ModuleError::InitFailed(format!(
+                            "invalid regex '{}' at {}{}[{}]: {}", pattern, prefix, field, i, e
+                        )));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The default rule set, applied to users without a rule set of their own
+    pub fn default_rule_set(&self) -> FilterRuleSet {
+        FilterRuleSet {
+            blocked_domains: self.blocked_domains.clone(),
+            blocked_domain_patterns: self.blocked_domain_patterns.clone(),
+            blocked_keywords: self.blocked_keywords.clone(),
+            blocked_keyword_patterns: self.blocked_keyword_patterns.clone(),
+            blocked_mime_types: self.blocked_mime_types.clone(),
+            blocked_extensions: self.blocked_extensions.clone(),
+            max_file_size: self.max_file_size,
+            blocked_file_types: self.blocked_file_types.clone(),
+            blocking_action: Some(self.blocking_action.clone()),
+            inherit_default: false,
+        }
+    }
+}
+
+/// Filter rules applied to a specific user or group
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct FilterRuleSet {
+    /// Blocked domains (exact match)
+    pub blocked_domains: Vec<String>,
+    /// Blocked domain patterns (regex)
+    pub blocked_domain_patterns: Vec<String>,
+    /// Blocked keywords
+    pub blocked_keywords: Vec<String>,
+    /// Blocked keyword patterns (regex)
+    pub blocked_keyword_patterns: Vec<String>,
+    /// Blocked MIME types
+    pub blocked_mime_types: Vec<String>,
+    /// Blocked file extensions
+    pub blocked_extensions: Vec<String>,
+    /// Maximum file size (bytes)
+    pub max_file_size: Option<u64>,
+    /// Blocked detected file types, by kind, MIME type or extension
+    pub blocked_file_types: Vec<String>,
+    /// Blocking action, the global one is used if not set
+    pub blocking_action: Option<BlockingAction>,
+    /// Apply the default rules before this rule set
+    pub inherit_default: bool,
+}
+
+/// A rule set with its regex patterns compiled
+#[derive(Debug, Clone, Default)]
+struct CompiledRuleSet {
+    /// Rule set name, `default`, `user:<name>` or `group:<name>`
+    name: String,
+    rules: FilterRuleSet,
+    domain_patterns: PatternMatcher,
+    keyword_patterns: PatternMatcher,
+    /// Automaton of the exact keywords
+    keyword_matcher: KeywordMatcher,
+    /// Automaton of the blocked domains
+    domain_matcher: DomainSuffixMatcher,
+}
+
+impl CompiledRuleSet {
+    fn compile(
+        name: String,
+        rules: FilterRuleSet,
+        config: &ContentFilterConfig,
+        cache: &RegexCache,
+    ) -> Result<Self, ModuleError> {
+        let keyword_matcher = KeywordMatcher::new(
+            rules.blocked_keywords.iter().enumerate()
+                .map(|(i, k)| (format!("{}:keyword:{}", name, i), k.clone())),
+            config.case_insensitive,
+        )?;
+        let domain_matcher = DomainSuffixMatcher::new(
+            rules.blocked_domains.iter().enumerate()
+                .map(|(i, d)| (format!("{}:domain:{}", name, i), d.clone())),
+        )?;
+        let mut compiled = CompiledRuleSet {
+            name,
+            rules,
+            domain_patterns: PatternMatcher::default(),
+            keyword_patterns: PatternMatcher::default(),
+            keyword_matcher,
+            domain_matcher,
+        };
+        if !config.enable_regex {
+            return Ok(compiled);
+        }
+
+        let build = |pattern: &str| {
+            if config.case_insensitive {
+                cache.get_or_compile(&format!("(?i){}", pattern))
+            } else {
+                cache.get_or_compile(pattern)
+            }
+        };
+
+        // Compile domain patterns
+        let mut domain_patterns = Vec::new();
+        for (i, pattern) in compiled.rules.blocked_domain_patterns.iter().enumerate() {
+            let regex = build(pattern)
+                .map_err(|e| ModuleError::InitFailed(format!("Invalid domain pattern '{}' at {}: {}", pattern, compiled.rule_id("domain_pattern", i), e)))?;
+            domain_patterns.push(regex);
+        }
+        compiled.domain_patterns = PatternMatcher::new(domain_patterns, config.pattern_engine)?;
+
+        // Compile keyword patterns
+        let mut keyword_patterns = Vec::new();
+        for (i, pattern) in compiled.rules.blocked_keyword_patterns.iter().enumerate() {
+            let regex = build(pattern)
+                .map_err(|e| ModuleError::InitFailed(format!("Invalid keyword pattern '{}' at {}: {}", pattern, compiled.rule_id("keyword_pattern", i), e)))?;
+            keyword_patterns.push(regex);
+        }
+        compiled.keyword_patterns = PatternMatcher::new(keyword_patterns, config.pattern_engine)?;
+
+        Ok(compiled)
+    }
+
+    fn rule_id(&self, kind: &str, index: usize) -> String {
+        format!("{}:{}:{}", self.name, kind, index)
+    }
+
+    /// Ids of all the rules of the rule set
+    fn rule_ids(&self) -> Vec<String> {
+        let rules = &self.rules;
+        let mut ids = Vec::new();
+        for (kind, count) in [
+            ("domain", rules.blocked_domains.len()),
+            ("domain_pattern", rules.blocked_domain_patterns.len()),
+            ("keyword", rules.blocked_keywords.len()),
+            ("keyword_pattern", rules.blocked_keyword_patterns.len()),
+            ("mime_type", rules.blocked_mime_types.len()),
+            ("extension", rules.blocked_extensions.len()),
+            ("file_type", rules.blocked_file_types.len()),
+        ] {
+            ids.extend((0..count).map(|i| self.rule_id(kind, i)));
+        }
+        if rules.max_file_size.is_some() {
+            ids.push(format!("{}:max_file_size", self.name));
+        }
+        ids
+    }
 }
 
 /// Blocking action types
@@ -100,10 +311,25 @@ pub struct ContentFilterStats {
     pub blocked_by_regex: u64,
     /// Processing time (microseconds)
     pub total_processing_time: u64,
+    /// Regex lookups served from the compiled regex cache
+    pub regex_cache_hits: u64,
+    /// Regex lookups that needed a compilation
+    pub regex_cache_misses: u64,
     /// Last reset time
     pub last_reset: Instant,
 }
 
+impl ContentFilterStats {
+    /// Ratio of regex lookups served from the cache
+    pub fn regex_cache_hit_rate(&self) -> f64 {
+        let total = self.regex_cache_hits + self.regex_cache_misses;
+        if total == 0 {
+            return 0.0;
+        }
+        self.regex_cache_hits as f64 / total as f64
+    }
+}
+
 impl Default for ContentFilterStats {
     fn default() -> Self {
         Self {
@@ -116,6 +342,8 @@ impl Default for ContentFilterStats {
             blocked_by_file_size: 0,
             blocked_by_regex: 0,
             total_processing_time: 0,
+            regex_cache_hits: 0,
+            regex_cache_misses: 0,
             last_reset: Instant::now(),
         }
     }
@@ -129,32 +357,61 @@ pub struct ContentFilterModule {
     version: String,
     /// Filter configuration
     config: ContentFilterConfig,
-    /// Compiled regex patterns
-    domain_patterns: Vec<Regex>,
-    keyword_patterns: Vec<Regex>,
+    /// Default rule set
+    default_rules: CompiledRuleSet,
+    /// Rule sets keyed by user name
+    user_rules: HashMap<String, CompiledRuleSet>,
+    /// Rule sets keyed by group name
+    group_rules: HashMap<String, CompiledRuleSet>,
     /// Statistics
     stats: Arc<RwLock<ContentFilterStats>>,
     /// Metrics
     metrics: Arc<Mutex<ModuleMetrics>>,
-    /// Cache for frequently accessed patterns
-    pattern_cache: Arc<RwLock<HashMap<String, bool>>>,
+    /// Compiled regex patterns, shared by all rule sets
+    regex_cache: Arc<RegexCache>,
+    /// Body capture of blocked messages
+    detection: DetectionCapture,
+    /// Domains blocked by the server blocklist, for all rule sets
+    blocklist: Option<Arc<BlocklistProvider>>,
+    /// Loaded block page templates
+    block_pages: BlockPages,
+    /// Detector of domains imitating protected brands
+    confusable: ConfusableDetector,
 }
 
 impl ContentFilterModule {
     /// Create a new content filter module
     pub fn new(config: ContentFilterConfig) -> Self {
+        let regex_cache = Arc::new(RegexCache::new(config.regex_cache_size));
+        let confusable = ConfusableDetector::new(&config.confusable_domains);
         Self {
             name: "content_filter".to_string(),
             version: "1.0.0".to_string(),
             config,
-            domain_patterns: Vec::new(),
-            keyword_patterns: Vec::new(),
+            default_rules: CompiledRuleSet::default(),
+            user_rules: HashMap::new(),
+            group_rules: HashMap::new(),
             stats: Arc::new(RwLock::new(ContentFilterStats::default())),
             metrics: Arc::new(Mutex::new(ModuleMetrics::default())),
-            pattern_cache: Arc::new(RwLock::new(HashMap::new())),
+            regex_cache,
+            detection: DetectionCapture::disabled(),
+            blocklist: None,
+            block_pages: BlockPages::default(),
+            confusable,
         }
     }
 
+    /// Also block the domains of the blocklist
+    pub fn set_blocklist(&mut self, blocklist: Option<Arc<BlocklistProvider>>) {
+        self.blocklist = blocklist;
+    }
+
+    /// Rename the module, its rule hits and metrics being counted apart,
+    /// like for a copy with candidate rules
+    pub fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+
     /// Create with default configuration
     pub fn with_defaults() -> Self {
         Self::new(ContentFilterConfig {
@@ -172,101 +429,233 @@ impl ContentFilterModule {
             enable_logging: true,
             enable_metrics: true,
             regex_cache_size: 1000,
+            pattern_engine: PatternEngine::Auto,
+            user_rules: Default::default(),
+            group_rules: Default::default(),
+            detection_capture: Default::default(),
+            block_page: Default::default(),
+            blocked_file_types: Vec::new(),
+            file_type_detection: Default::default(),
+            confusable_domains: Default::default(),
+            ai_inspection: Default::default(),
         })
     }
 
-    /// Compile regex patterns
+    /// Compile regex patterns of the default and all user / group rule sets
     fn compile_patterns(&mut self) -> Result<(), ModuleError> {
-        if !self.config.enable_regex {
-            return Ok(());
+        self.regex_cache.resize(self.config.regex_cache_size);
+        self.default_rules = CompiledRuleSet::compile(
+            "default".to_string(),
+            self.config.default_rule_set(),
+            &self.config,
+            &self.regex_cache,
+        )?;
+
+        self.user_rules.clear();
+        for (user, rules) in &self.config.user_rules {
+            let compiled = CompiledRuleSet::compile(format!("user:{}", user), rules.clone(), &self.config, &self.regex_cache)?;
+            let key = self.normalize_identity(user);
+            self.user_rules.insert(key, compiled);
         }
 
-        // Compile domain patterns
-        for pattern in &self.config.blocked_domain_patterns {
-            let regex = if self.config.case_insensitive {
-                Regex::new(&format!("(?i){}", pattern))
-            } else {
-                Regex::new(pattern)
-            }.map_err(|e| ModuleError::InitFailed(format!("Invalid domain pattern '{}': {}", pattern, e)))?;
-            self.domain_patterns.push(regex);
+        self.group_rules.clear();
+        for (group, rules) in &self.config.group_rules {
+            let compiled = CompiledRuleSet::compile(format!("group:{}", group), rules.clone(), &self.config, &self.regex_cache)?;
+            let key = self.normalize_identity(group);
+            self.group_rules.insert(key, compiled);
         }
 
-        // Compile keyword patterns
-        for pattern in &self.config.blocked_keyword_patterns {
-            let regex = if self.config.case_insensitive {
-                Regex::new(&format!("(?i){}", pattern))
-            } else {
-                Regex::new(pattern)
-            }.map_err(|e| ModuleError::InitFailed(format!("Invalid keyword pattern '{}': {}", pattern, e)))?;
-            self.keyword_patterns.push(regex);
+        Ok(())
+    }
+
+    fn normalize_identity(&self, name: &str) -> String {
+        if self.config.case_insensitive {
+            name.to_lowercase()
+        } else {
+            name.to_string()
         }
+    }
 
-        Ok(())
+    /// Select the rule sets to apply for a client, in evaluation order
+    ///
+    /// A user rule set wins over group rule sets, and the first group of the
+    /// client that has a rule set wins over the others. Clients without any
+    /// matching rule set fall back to the default rules.
+    fn select_rule_sets(&self, identity: &ClientIdentity) -> Vec<&CompiledRuleSet> {
+        let user_rules = identity
+            .username
+            .as_ref()
+            .and_then(|u| self.user_rules.get(&self.normalize_identity(u)));
+        let selected = user_rules.or_else(|| {
+            identity
+                .groups
+                .iter()
+                .find_map(|g| self.group_rules.get(&self.normalize_identity(g)))
+        });
+
+        match selected {
+            Some(rules) if rules.rules.inherit_default => vec![&self.default_rules, rules],
+            Some(rules) => vec![rules],
+            None => vec![&self.default_rules],
+        }
     }
 
     /// Check if content should be blocked
     async fn should_block(&self, request: &IcapRequest) -> Result<Option<BlockReason>, ModuleError> {
+        Ok(self.evaluate(request).await?.map(|(m, _)| m.reason))
+    }
+
+    /// Evaluate the request against the rule sets of its client
+    ///
+    /// Returns the matched rule together with the rule set it belongs to.
+    async fn evaluate(&self, request: &IcapRequest) -> Result<Option<(BlockMatch, &CompiledRuleSet)>, ModuleError> {
         let start_time = Instant::now();
+        let identity = ClientIdentity::from_headers(&request.headers);
+        let rule_sets = self.select_rule_sets(&identity);
+        let detected = self.detect_file_type(request, &rule_sets);
+
+        for rules in rule_sets {
+            if let Some(mut m) = self.check_rule_set(rules, request, detected).await? {
+                m.detected_type = detected;
+                rule_hits::global().record(&self.name, &m.rule_id);
+                return Ok(Some((m, rules)));
+            }
+        }
+
+        if self.config.confusable_domains.action == ConfusableAction::Block
+            && let Some(c) = self.check_confusable(request)
+        {
+            let m = BlockMatch::new(
+                BlockReason::Confusable { domain: c.domain, brand: c.brand, score: c.score },
+                &self.default_rules.rule_id("confusable", 0),
+            );
+            rule_hits::global().record(&self.name, &m.rule_id);
+            return Ok(Some((m, &self.default_rules)));
+        }
+
+        // Update statistics
+        let processing_time = start_time.elapsed().as_micros() as u64;
+        self.update_stats(false, None, processing_time).await;
+
+        Ok(None)
+    }
 
+    /// Check the host for a domain imitating a protected brand
+    fn check_confusable(&self, request: &IcapRequest) -> Option<ConfusableMatch> {
+        if !self.config.confusable_domains.enabled {
+            return None;
+        }
+        let host = request.headers.get("host").and_then(|h| h.to_str().ok())?;
+        self.confusable.check(host)
+    }
+
+    /// Add the phishing risk headers of a confusable host to an allowed message
+    fn add_confusable_warning(&self, request: &IcapRequest, response: &mut IcapResponse) {
+        if self.config.confusable_domains.action != ConfusableAction::Warn {
+            return;
+        }
+        let Some(c) = self.check_confusable(request) else {
+            return;
+        };
+        if self.config.enable_logging {
+            log::warn!("confusable domain {} (brand: {}, mixed script: {}, score {}) in {}",
+                c.domain, c.brand.as_deref().unwrap_or("none"), c.mixed_script, c.score, request.uri);
+        }
+        response.headers.insert(HEADER_PHISHING_SCORE, http::HeaderValue::from(u16::from(c.score)));
+        if let Some(value) = c.brand.and_then(|b| http::HeaderValue::from_str(&b).ok()) {
+            response.headers.insert(HEADER_PHISHING_BRAND, value);
+        }
+    }
+
+    /// Detect the true type of the body, if enabled or used by a rule set
+    fn detect_file_type(&self, request: &IcapRequest, rule_sets: &[&CompiledRuleSet]) -> Option<&'static FileType> {
+        if !self.config.file_type_detection.enabled
+            && rule_sets.iter().all(|r| r.rules.blocked_file_types.is_empty())
+        {
+            return None;
+        }
+        let detected = file_type::detect(&request.body);
+        if let Some(t) = detected {
+            if self.config.enable_logging {
+                log::debug!("detected file type {} ({}) for {}", t.mime, t.kind.as_str(), request.uri);
+            }
+        }
+        detected
+    }
+
+    /// Check a single rule set
+    async fn check_rule_set(
+        &self,
+        rules: &CompiledRuleSet,
+        request: &IcapRequest,
+        detected: Option<&'static FileType>,
+    ) -> Result<Option<BlockMatch>, ModuleError> {
         // Check domain blocking
-        if let Some(reason) = self.check_domain_blocking(request).await? {
-            return Ok(Some(reason));
+        if let Some(m) = self.check_domain_blocking(rules, request).await? {
+            return Ok(Some(m));
         }
 
         // Check keyword blocking in URI
-        if let Some(reason) = self.check_uri_keywords(request).await? {
-            return Ok(Some(reason));
+        if let Some(m) = self.check_uri_keywords(rules, request).await? {
+            return Ok(Some(m));
         }
 
         // Check MIME type blocking
-        if let Some(reason) = self.check_mime_type_blocking(request).await? {
-            return Ok(Some(reason));
+        if let Some(m) = self.check_mime_type_blocking(rules, request).await? {
+            return Ok(Some(m));
+        }
+
+        // Check the detected file type
+        if let Some(m) = self.check_file_type_blocking(rules, request, detected).await? {
+            return Ok(Some(m));
         }
 
         // Check file size blocking
-        if let Some(reason) = self.check_file_size_blocking(request).await? {
-            return Ok(Some(reason));
+        if let Some(m) = self.check_file_size_blocking(rules, request).await? {
+            return Ok(Some(m));
         }
 
         // Check keyword blocking in body
-        if let Some(reason) = self.check_body_keywords(request).await? {
-            return Ok(Some(reason));
+        if let Some(m) = self.check_body_keywords(rules, request).await? {
+            return Ok(Some(m));
         }
 
-        // Update statistics
-        let processing_time = start_time.elapsed().as_micros() as u64;
-        self.update_stats(false, None, processing_time).await;
-
         Ok(None)
     }
 
     /// Check domain blocking
-    async fn check_domain_blocking(&self, request: &IcapRequest) -> Result<Option<BlockReason>, ModuleError> {
+    async fn check_domain_blocking(&self, rules: &CompiledRuleSet, request: &IcapRequest) -> Result<Option<BlockMatch>, ModuleError> {
         // Extract host from headers
         let host = request.headers
             .get("host")
             .and_then(|h| h.to_str().ok())
             .unwrap_or("");
 
-        if host.is_empty() {
-            return Ok(None);
+        // Also match the canonical forms of the header and URI hosts, so
+        // that encoded, numeric or userinfo prefixed hosts are caught
+        let mut hosts = Vec::with_capacity(3);
+        if !host.is_empty() {
+            hosts.push(host.to_string());
+            hosts.extend(NormalizedUrl::parse(&format!("//{host}")).host);
         }
+        hosts.extend(NormalizedUrl::parse(&request.uri.to_string()).host);
+        hosts.dedup();
 
-        // Check exact domain matches
-        for domain in &self.config.blocked_domains {
-            if self.config.case_insensitive {
-                if host.to_lowercase().contains(&domain.to_lowercase()) {
-                    return Ok(Some(BlockReason::Domain(domain.clone())));
-                }
-            } else if host.contains(domain) {
-                return Ok(Some(BlockReason::Domain(domain.clone())));
+        for host in &hosts {
+            // Check the blocked domains and their subdomains
+            if let Some(m) = rules.domain_matcher.find(host) {
+                return Ok(Some(BlockMatch::new(BlockReason::Domain(m.pattern.clone()), &m.rule_id)));
+            }
+            if let Some(m) = self.blocklist.as_ref().and_then(|b| b.find(host)) {
+                return Ok(Some(BlockMatch::new(BlockReason::Domain(m.pattern), &m.rule_id)));
             }
-        }
 
-        // Check regex domain patterns
-        for pattern in &self.domain_patterns {
-            if pattern.is_match(host) {
-                return Ok(Some(BlockReason::DomainPattern(pattern.as_str().to_string())));
+            // Check regex domain patterns
+            if let Some((i, pattern)) = rules.domain_patterns.find(host) {
+                return Ok(Some(BlockMatch::new(
+                    BlockReason::DomainPattern(pattern.as_str().to_string()),
+                    &rules.rule_id("domain_pattern", i),
+                )));
             }
         }
 
@@ -274,31 +663,24 @@ impl ContentFilterModule {
     }
 
     /// Check keyword blocking in URI
-    async fn check_uri_keywords(&self, request: &IcapRequest) -> Result<Option<BlockReason>, ModuleError> {
+    async fn check_uri_keywords(&self, rules: &CompiledRuleSet, request: &IcapRequest) -> Result<Option<BlockMatch>, ModuleError> {
         let uri = request.uri.to_string();
-
-        // Check exact keyword matches
-        for keyword in &self.config.blocked_keywords {
-            let search_text = if self.config.case_insensitive {
-                uri.to_lowercase()
-            } else {
-                uri.clone()
-            };
-            let search_keyword = if self.config.case_insensitive {
-                keyword.to_lowercase()
-            } else {
-                keyword.clone()
-            };
-
-            if search_text.contains(&search_keyword) {
-                return Ok(Some(BlockReason::Keyword(keyword.clone())));
+        // Also match the canonical form, undoing encodings and dot segments
+        let normalized = NormalizedUrl::parse(&uri).to_string();
+        let uris = if normalized == uri { vec![uri] } else { vec![uri, normalized] };
+
+        for uri in &uris {
+            // Check exact keyword matches
+            if let Some(m) = rules.keyword_matcher.find(uri.as_bytes()) {
+                return Ok(Some(BlockMatch::new(BlockReason::Keyword(m.pattern.clone()), &m.rule_id)));
             }
-        }
 
-        // Check regex keyword patterns
-        for pattern in &self.keyword_patterns {
-            if pattern.is_match(&uri) {
-                return Ok(Some(BlockReason::KeywordPattern(pattern.as_str().to_string())));
+            // Check regex keyword patterns
+            if let Some((i, pattern)) = rules.keyword_patterns.find(uri) {
+                return Ok(Some(BlockMatch::new(
+                    BlockReason::KeywordPattern(pattern.as_str().to_string()),
+                    &rules.rule_id("keyword_pattern", i),
+                )));
             }
         }
 
@@ -306,13 +688,16 @@ impl ContentFilterModule {
     }
 
     /// Check MIME type blocking
-    async fn check_mime_type_blocking(&self, request: &IcapRequest) -> Result<Option<BlockReason>, ModuleError> {
+    async fn check_mime_type_blocking(&self, rules: &CompiledRuleSet, request: &IcapRequest) -> Result<Option<BlockMatch>, ModuleError> {
         // Check Content-Type header
         if let Some(content_type) = request.headers.get("content-type") {
             if let Ok(mime_type) = content_type.to_str() {
-                for blocked_mime in &self.config.blocked_mime_types {
+                for (i, blocked_mime) in rules.rules.blocked_mime_types.iter().enumerate() {
                     if mime_type.contains(blocked_mime) {
-                        return Ok(Some(BlockReason::MimeType(blocked_mime.clone())));
+                        return Ok(Some(BlockMatch::new(
+                            BlockReason::MimeType(blocked_mime.clone()),
+                            &rules.rule_id("mime_type", i),
+                        )));
                     }
                 }
             }
@@ -322,9 +707,12 @@ impl ContentFilterModule {
         let path = request.uri.path();
         if let Some(extension) = std::path::Path::new(path).extension() {
             if let Some(ext_str) = extension.to_str() {
-                for blocked_ext in &self.config.blocked_extensions {
+                for (i, blocked_ext) in rules.rules.blocked_extensions.iter().enumerate() {
                     if ext_str.eq_ignore_ascii_case(blocked_ext) {
-                        return Ok(Some(BlockReason::Extension(blocked_ext.clone())));
+                        return Ok(Some(BlockMatch::new(
+                            BlockReason::Extension(blocked_ext.clone()),
+                            &rules.rule_id("extension", i),
+                        )));
                     }
                 }
             }
@@ -333,15 +721,79 @@ impl ContentFilterModule {
         Ok(None)
     }
 
+    /// Check the detected file type against the MIME type, extension and file type rules
+    async fn check_file_type_blocking(
+        &self,
+        rules: &CompiledRuleSet,
+        request: &IcapRequest,
+        detected: Option<&'static FileType>,
+    ) -> Result<Option<BlockMatch>, ModuleError> {
+        let Some(detected) = detected else {
+            return Ok(None);
+        };
+
+        for (i, blocked) in rules.rules.blocked_file_types.iter().enumerate() {
+            if detected.matches(blocked) {
+                return Ok(Some(BlockMatch::new(
+                    BlockReason::FileType(detected.mime.to_string()),
+                    &rules.rule_id("file_type", i),
+                )));
+            }
+        }
+
+        if !self.config.file_type_detection.enabled {
+            return Ok(None);
+        }
+
+        // A renamed or mislabelled file is blocked as its true type
+        for (i, blocked_mime) in rules.rules.blocked_mime_types.iter().enumerate() {
+            if detected.has_mime(blocked_mime) {
+                return Ok(Some(BlockMatch::new(
+                    BlockReason::MimeType(blocked_mime.clone()),
+                    &rules.rule_id("mime_type", i),
+                )));
+            }
+        }
+        for (i, blocked_ext) in rules.rules.blocked_extensions.iter().enumerate() {
+            if blocked_ext.trim_start_matches('.').eq_ignore_ascii_case(detected.extension) {
+                return Ok(Some(BlockMatch::new(
+                    BlockReason::Extension(blocked_ext.clone()),
+                    &rules.rule_id("extension", i),
+                )));
+            }
+        }
+
+        if self.config.file_type_detection.block_mismatch {
+            let declared = request
+                .headers
+                .get("content-type")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default();
+            if !detected.is_declared_as(declared) {
+                return Ok(Some(BlockMatch::new(
+                    BlockReason::TypeMismatch {
+                        declared: declared.to_string(),
+                        detected: detected.mime.to_string(),
+                    },
+                    &format!("{}:type_mismatch", rules.name),
+                )));
+            }
+        }
+
+        Ok(None)
+    }
+
     /// Check file size blocking
-    async fn check_file_size_blocking(&self, request: &IcapRequest) -> Result<Option<BlockReason>, ModuleError> {
-        if let Some(max_size) = self.config.max_file_size {
+    async fn check_file_size_blocking(&self, rules: &CompiledRuleSet, request: &IcapRequest) -> Result<Option<BlockMatch>, ModuleError> {
+        if let Some(max_size) = rules.rules.max_file_size {
+            let rule_id = format!("{}:max_file_size", rules.name);
+
             // Check Content-Length header
             if let Some(content_length) = request.headers.get("content-length") {
                 if let Ok(length_str) = content_length.to_str() {
                     if let Ok(length) = length_str.parse::<u64>() {
                         if length > max_size {
-                            return Ok(Some(BlockReason::FileSize(length)));
+                            return Ok(Some(BlockMatch::new(BlockReason::FileSize(length), &rule_id)));
                         }
                     }
                 }
@@ -349,7 +801,7 @@ impl ContentFilterModule {
 
             // Check actual body size
             if request.body.len() as u64 > max_size {
-                return Ok(Some(BlockReason::FileSize(request.body.len() as u64)));
+                return Ok(Some(BlockMatch::new(BlockReason::FileSize(request.body.len() as u64), &rule_id)));
             }
         }
 
@@ -357,50 +809,133 @@ impl ContentFilterModule {
     }
 
     /// Check keyword blocking in body
-    async fn check_body_keywords(&self, request: &IcapRequest) -> Result<Option<BlockReason>, ModuleError> {
+    ///
+    /// Only the text fields of the AI API messages are checked.
+    async fn check_body_keywords(&self, rules: &CompiledRuleSet, request: &IcapRequest) -> Result<Option<BlockMatch>, ModuleError> {
         if request.body.is_empty() {
             return Ok(None);
         }
 
-        let body_text = String::from_utf8_lossy(&request.body);
+        if let Some(message) = self.ai_message(request) {
+            return Ok(message.texts().iter().find_map(|t| self.match_body_text(rules, t.text.as_bytes())));
+        }
+        Ok(self.match_body_text(rules, &request.body))
+    }
 
+    /// Match the body keywords and keyword patterns against the text
+    fn match_body_text(&self, rules: &CompiledRuleSet, text: &[u8]) -> Option<BlockMatch> {
         // Check exact keyword matches
-        for keyword in &self.config.blocked_keywords {
-            let search_text = if self.config.case_insensitive {
-                body_text.to_lowercase()
-            } else {
-                body_text.to_string()
-            };
-            let search_keyword = if self.config.case_insensitive {
-                keyword.to_lowercase()
-            } else {
-                keyword.clone()
-            };
-
-            if search_text.contains(&search_keyword) {
-                return Ok(Some(BlockReason::BodyKeyword(keyword.clone())));
-            }
+        if let Some(m) = rules.keyword_matcher.find(text) {
+            return Some(BlockMatch::new(BlockReason::BodyKeyword(m.pattern.clone()), &m.rule_id));
         }
 
         // Check regex keyword patterns
-        for pattern in &self.keyword_patterns {
-            if pattern.is_match(&body_text) {
-                return Ok(Some(BlockReason::BodyKeywordPattern(pattern.as_str().to_string())));
+        if !rules.keyword_patterns.is_empty() {
+            let body_text = String::from_utf8_lossy(text);
+            if let Some((i, pattern)) = rules.keyword_patterns.find(&body_text) {
+                return Some(BlockMatch::new(
+                    BlockReason::BodyKeywordPattern(pattern.as_str().to_string()),
+                    &rules.rule_id("keyword_pattern", i),
+                ));
             }
         }
 
-        Ok(None)
+        None
+    }
+
+    /// Parse the body as an AI API message, if the inspection profile applies
+    fn ai_message(&self, request: &IcapRequest) -> Option<AiMessage> {
+        let config = &self.config.ai_inspection;
+        if !config.applies(request) {
+            return None;
+        }
+        let message = AiMessage::parse(config, &request.body)?;
+        if message.is_truncated() && self.config.enable_logging {
+            log::debug!("AI API message text of {} inspected up to {} tokens", request.uri, config.max_tokens);
+        }
+        Some(message)
+    }
+
+    /// Redact the body keywords in the text fields of an AI API message
+    ///
+    /// Returns the adapted message, or `None` if the match is to be blocked.
+    fn redacted_response(&self, request: &IcapRequest, m: &BlockMatch, rules: &CompiledRuleSet) -> Option<IcapResponse> {
+        if self.config.ai_inspection.action != AiInspectionAction::Redact
+            || !matches!(m.reason, BlockReason::BodyKeyword(_) | BlockReason::BodyKeywordPattern(_))
+        {
+            return None;
+        }
+        let mut message = self.ai_message(request)?;
+        let redacted = message.redact(
+            |text| {
+                let mut spans = rules.keyword_matcher.find_spans(text);
+                for pattern in rules.keyword_patterns.patterns() {
+                    spans.extend(pattern.find_iter(text).map(|m| m.range()));
+                }
+                spans
+            },
+            &self.config.ai_inspection.redaction,
+        );
+        if redacted == 0 {
+            return None;
+        }
+        if self.config.enable_logging {
+            log::info!("redacted {} spans matching rule {} in {}", redacted, m.rule_id, request.uri);
+        }
+
+        let body = bytes::Bytes::from(message.to_body());
+        let encapsulated = request.encapsulated.as_ref();
+        let encapsulated = if request.method == IcapMethod::Reqmod {
+            crate::protocol::common::EncapsulatedData {
+                req_hdr: Some(encapsulated.and_then(|e| e.req_hdr.clone()).unwrap_or_default()),
+                req_body: Some(body.clone()),
+                res_hdr: None,
+                res_body: None,
+                null_body: false,
+                trailers: None,
+                ieof: false,
+                req_line: None,
+                status_line: None,
+            }
+        } else {
+            crate::protocol::common::EncapsulatedData {
+                req_hdr: None,
+                req_body: None,
+                res_hdr: Some(encapsulated.and_then(|e| e.res_hdr.clone()).unwrap_or_default()),
+                res_body: Some(body.clone()),
+                null_body: false,
+                trailers: None,
+                ieof: false,
+                req_line: None,
+                status_line: None,
+            }
+        };
+        let response_generator = crate::protocol::response_generator::IcapResponseGenerator::with_service_id(
+            "G3ICAP-ContentFilter/1.0.0".to_string(),
+            "content-filter-1.0.0".to_string(),
+            Some("content-filter".to_string())
+        );
+        let mut response = response_generator.ok_modified(Some(encapsulated), body);
+        response.headers.insert(HEADER_AI_REDACTED, http::HeaderValue::from(redacted));
+        m.set_headers(&mut response);
+        Some(response)
     }
 
     /// Create blocking response using proper response generator
-    fn create_blocking_response(&self, reason: &BlockReason) -> IcapResponse {
+    fn create_blocking_response(&self, request: &IcapRequest, m: &BlockMatch, rules: &CompiledRuleSet) -> IcapResponse {
+        let reason = &m.reason;
         let response_generator = crate::protocol::response_generator::IcapResponseGenerator::with_service_id(
             "G3ICAP-ContentFilter/1.0.0".to_string(),
             "content-filter-1.0.0".to_string(),
             Some("content-filter".to_string())
         );
 
-        match &self.config.blocking_action {
+        let blocking_action = rules.rules.blocking_action.as_ref()
+            .unwrap_or(&self.config.blocking_action);
+        match blocking_action {
+            BlockingAction::Forbidden if self.config.block_page.enabled => {
+                self.create_block_page(request, m, &response_generator)
+            }
             BlockingAction::Forbidden => {
                 let message = format!("Content blocked by filter: {}", reason);
                 let should_chunk = response_generator.should_use_chunked_encoding(Some(message.len()));
@@ -439,6 +974,52 @@ impl ContentFilterModule {
         }
     }
 
+    /// Render the block page of the rule category
+    ///
+    /// The category assigned by the proxy wins over the kind of the matched rule.
+    fn create_block_page(
+        &self,
+        request: &IcapRequest,
+        m: &BlockMatch,
+        response_generator: &crate::protocol::response_generator::IcapResponseGenerator,
+    ) -> IcapResponse {
+        let ticket_id = BlockPages::new_ticket_id();
+        let url = request.uri.to_string();
+        let reason = m.reason.to_string();
+        let vars = BlockPageVars {
+            url: &url,
+            category: BlockPages::request_category(&request.headers).unwrap_or(m.reason.category()),
+            rule: &m.rule_id,
+            reason: &reason,
+            client_ip: BlockPages::client_ip(&request.headers).unwrap_or_default(),
+            ticket_id: &ticket_id,
+        };
+        if self.config.enable_logging {
+            log::info!("block page ticket {} for rule {}: {}", ticket_id, m.rule_id, url);
+        }
+        let page = self.block_pages.render(&vars);
+        response_generator.http_page_response(self.block_pages.status(), &page)
+    }
+
+    /// Capture the offending body into the detection log
+    async fn record_detection(&self, request: &IcapRequest, m: &BlockMatch) {
+        if !self.detection.is_enabled() {
+            return;
+        }
+        let matched = match &m.reason {
+            BlockReason::Keyword(k) | BlockReason::BodyKeyword(k) => {
+                DetectionCapture::literal_matcher(k, self.config.case_insensitive)
+            }
+            BlockReason::KeywordPattern(p) | BlockReason::BodyKeywordPattern(p) => {
+                DetectionCapture::pattern_matcher(p)
+            }
+            _ => None,
+        };
+        self.detection
+            .record(&request.uri.to_string(), &m.rule_id, &m.reason.to_string(), &request.body, matched.as_ref())
+            .await;
+    }
+
     /// Update statistics
     async fn update_stats(&self, blocked: bool, reason: Option<BlockReason>, processing_time: u64) {
         let mut stats = self.stats.write().unwrap();
@@ -449,14 +1030,14 @@ impl ContentFilterModule {
             stats.blocked_requests += 1;
             if let Some(reason) = reason {
                 match reason {
-                    BlockReason::Domain(_) | BlockReason::DomainPattern(_) => {
+                    BlockReason::Domain(_) | BlockReason::DomainPattern(_) | BlockReason::Confusable { .. } => {
                         stats.blocked_by_domain += 1;
                     }
                     BlockReason::Keyword(_) | BlockReason::KeywordPattern(_) | 
                     BlockReason::BodyKeyword(_) | BlockReason::BodyKeywordPattern(_) => {
                         stats.blocked_by_keyword += 1;
                     }
-                    BlockReason::MimeType(_) => {
+                    BlockReason::MimeType(_) | BlockReason::FileType(_) | BlockReason::TypeMismatch { .. } => {
                         stats.blocked_by_mime_type += 1;
                     }
                     BlockReason::FileSize(_) => {
@@ -482,7 +1063,10 @@ impl ContentFilterModule {
 
     /// Get statistics
     pub fn get_stats(&self) -> ContentFilterStats {
-        self.stats.read().unwrap().clone()
+        let mut stats = self.stats.read().unwrap().clone();
+        stats.regex_cache_hits = self.regex_cache.hits();
+        stats.regex_cache_misses = self.regex_cache.misses();
+        stats
     }
 
     /// Reset statistics
@@ -495,6 +1079,70 @@ impl ContentFilterModule {
     }
 }
 
+/// ICAP response header carrying the id of the matched rule
+pub const HEADER_RULE_ID: &str = "x-rule-id";
+
+/// ICAP response header carrying the category of the matched rule
+pub const HEADER_RULE_CATEGORY: &str = "x-rule-category";
+
+/// ICAP response header carrying the detected type of the blocked body
+pub const HEADER_DETECTED_TYPE: &str = "x-detected-type";
+
+/// ICAP response header carrying the phishing risk score of a confusable host
+pub const HEADER_PHISHING_SCORE: &str = "x-phishing-score";
+
+/// ICAP response header carrying the brand imitated by a confusable host
+pub const HEADER_PHISHING_BRAND: &str = "x-phishing-brand";
+
+/// A matched blocking rule
+#[derive(Debug, Clone)]
+pub struct BlockMatch {
+    /// Why the content is blocked
+    pub reason: BlockReason,
+    /// Id of the matched rule, `<rule set>:<kind>:<index>`
+    pub rule_id: String,
+    /// True type of the body, if detected
+    pub detected_type: Option<&'static FileType>,
+}
+
+impl BlockMatch {
+    fn new(reason: BlockReason, rule_id: &str) -> Self {
+        BlockMatch {
+            reason,
+            rule_id: rule_id.to_string(),
+            detected_type: None,
+        }
+    }
+
+    fn explain(&self, module: &str, rule_set: &str) -> ExplainedMatch {
+        ExplainedMatch {
+            module: module.to_string(),
+            rule_set: rule_set.to_string(),
+            rule_id: self.rule_id.clone(),
+            category: self.reason.category().to_string(),
+            reason: self.reason.to_string(),
+            policy: None,
+            rule: None,
+        }
+    }
+
+    /// Insert the rule id, category and detected type headers into the response
+    fn set_headers(&self, response: &mut IcapResponse) {
+        if let Ok(value) = self.rule_id.parse() {
+            response.headers.insert(HEADER_RULE_ID, value);
+        }
+        response
+            .headers
+            .insert(HEADER_RULE_CATEGORY, http::HeaderValue::from_static(self.reason.category()));
+        if let Some(t) = self.detected_type {
+            response.headers.insert(HEADER_DETECTED_TYPE, http::HeaderValue::from_static(t.mime));
+        }
+        if let BlockReason::Confusable { score, .. } = &self.reason {
+            response.headers.insert(HEADER_PHISHING_SCORE, http::HeaderValue::from(u16::from(*score)));
+        }
+    }
+}
+
 /// Blocking reason
 #[derive(Debug, Clone)]
 pub enum BlockReason {
@@ -507,6 +1155,27 @@ pub enum BlockReason {
     MimeType(String),
     Extension(String),
     FileSize(u64),
+    FileType(String),
+    TypeMismatch { declared: String, detected: String },
+    Confusable { domain: String, brand: Option<String>, score: u8 },
+}
+
+impl BlockReason {
+    /// Rule category, used to select the block page
+    pub fn category(&self) -> &'static str {
+        match self {
+            BlockReason::Domain(_) | BlockReason::DomainPattern(_) => "domain",
+            BlockReason::Keyword(_)
+            | BlockReason::KeywordPattern(_)
+            | BlockReason::BodyKeyword(_)
+            | BlockReason::BodyKeywordPattern(_) => "keyword",
+            BlockReason::MimeType(_) => "mime_type",
+            BlockReason::Extension(_) => "extension",
+            BlockReason::FileSize(_) => "file_size",
+            BlockReason::FileType(_) | BlockReason::TypeMismatch { .. } => "file_type",
+            BlockReason::Confusable { .. } => "phishing",
+        }
+    }
 }
 
 impl std::fmt::Display for BlockReason {
@@ -521,6 +1190,14 @@ impl std::fmt::Display for BlockReason {
             BlockReason::MimeType(mime_type) => write!(f, "Blocked MIME type: {}", mime_type),
             BlockReason::Extension(ext) => write!(f, "Blocked extension: {}", ext),
             BlockReason::FileSize(size) => write!(f, "File too large: {} bytes", size),
+            BlockReason::FileType(file_type) => write!(f, "Blocked file type: {}", file_type),
+            BlockReason::TypeMismatch { declared, detected } => {
+                write!(f, "File type mismatch: declared {}, detected {}", declared, detected)
+            }
+            BlockReason::Confusable { domain, brand: Some(brand), .. } => {
+                write!(f, "Confusable domain imitating {}: {}", brand, domain)
+            }
+            BlockReason::Confusable { domain, brand: None, .. } => write!(f, "Mixed script domain: {}", domain),
         }
     }
 }
@@ -541,32 +1218,55 @@ impl IcapModule for ContentFilterModule {
 
     async fn init(&mut self, config: &ModuleConfig) -> Result<(), ModuleError> {
         // Load configuration from module config
-        if let Ok(filter_config) = serde_json::from_value::<ContentFilterConfig>(config.config.clone()) {
+        if let Some(filter_config) = config.parse_config()? {
             self.config = filter_config;
         }
 
+        // Reject invalid patterns before touching the active rule sets
+        self.config.validate()?;
+
         // Compile regex patterns
         self.compile_patterns()?;
+        let rule_ids = std::iter::once(&self.default_rules)
+            .chain(self.user_rules.values())
+            .chain(self.group_rules.values())
+            .flat_map(|rules| rules.rule_ids());
+        rule_hits::global().register(&self.name, rule_ids);
+
+        self.confusable = ConfusableDetector::new(&self.config.confusable_domains);
+        self.detection = DetectionCapture::new(&self.name, self.config.detection_capture.clone())?;
+        self.block_pages = BlockPages::new(&self.config.block_page)?;
 
         if self.config.enable_logging {
-            log::info!("Content filter module initialized with {} domain patterns and {} keyword patterns", 
-                self.domain_patterns.len(), self.keyword_patterns.len());
+            let patterns = [&self.default_rules.domain_patterns, &self.default_rules.keyword_patterns];
+            log::info!("Content filter module initialized with {} domain patterns, {} keyword patterns ({} matched by hyperscan), {} user and {} group rule sets, regex cache hit rate {:.2}",
+                self.default_rules.domain_patterns.len(), self.default_rules.keyword_patterns.len(),
+                patterns.iter().map(|p| p.hyperscan_len()).sum::<usize>(),
+                self.user_rules.len(), self.group_rules.len(), self.regex_cache.hit_rate());
         }
 
         Ok(())
     }
 
-    async fn handle_reqmod(&self, request: &IcapRequest) -> Result<IcapResponse, ModuleError> {
+    async fn handle_reqmod(&self, request: &IcapRequest, ctx: &TransactionCtx) -> Result<IcapResponse, ModuleError> {
         if self.config.enable_logging {
             log::debug!("Processing REQMOD request: {}", request.uri);
         }
 
-        match self.should_block(request).await? {
-            Some(reason) => {
+        match crate::modules::until_cancelled(ctx, self.evaluate(request)).await? {
+            Some((m, rules)) => {
+                if let Some(response) = self.redacted_response(request, &m, rules) {
+                    self.record_detection(request, &m).await;
+                    return Ok(response);
+                }
                 if self.config.enable_logging {
-                    log::warn!("REQMOD request blocked: {} - {}", request.uri, reason);
+                    log::warn!("REQMOD request blocked by rule {}: {} - {} (detected type: {})", m.rule_id, request.uri, m.reason,
+                        m.detected_type.map(|t| t.mime).unwrap_or("unknown"));
                 }
-                Ok(self.create_blocking_response(&reason))
+                self.record_detection(request, &m).await;
+                let mut response = self.create_blocking_response(request, &m, rules);
+                m.set_headers(&mut response);
+                Ok(response)
             }
             None => {
                 // Allow the request to pass through - use response generator for proper headers
@@ -575,22 +1275,32 @@ impl IcapModule for ContentFilterModule {
                     "content-filter-1.0.0".to_string(),
                     Some("content-filter".to_string())
                 );
-                Ok(response_generator.no_modifications(None))
+                let mut response = response_generator.no_modifications(None);
+                self.add_confusable_warning(request, &mut response);
+                Ok(response)
             }
         }
     }
 
-    async fn handle_respmod(&self, request: &IcapRequest) -> Result<IcapResponse, ModuleError> {
+    async fn handle_respmod(&self, request: &IcapRequest, ctx: &TransactionCtx) -> Result<IcapResponse, ModuleError> {
         if self.config.enable_logging {
             log::debug!("Processing RESPMOD request: {}", request.uri);
         }
 
-        match self.should_block(request).await? {
-            Some(reason) => {
+        match crate::modules::until_cancelled(ctx, self.evaluate(request)).await? {
+            Some((m, rules)) => {
+                if let Some(response) = self.redacted_response(request, &m, rules) {
+                    self.record_detection(request, &m).await;
+                    return Ok(response);
+                }
                 if self.config.enable_logging {
-                    log::warn!("RESPMOD request blocked: {} - {}", request.uri, reason);
+                    log::warn!("RESPMOD request blocked by rule {}: {} - {} (detected type: {})", m.rule_id, request.uri, m.reason,
+                        m.detected_type.map(|t| t.mime).unwrap_or("unknown"));
                 }
-                Ok(self.create_blocking_response(&reason))
+                self.record_detection(request, &m).await;
+                let mut response = self.create_blocking_response(request, &m, rules);
+                m.set_headers(&mut response);
+                Ok(response)
             }
             None => {
                 // Allow the response to pass through - use response generator for proper headers
@@ -599,7 +1309,9 @@ impl IcapModule for ContentFilterModule {
                     "content-filter-1.0.0".to_string(),
                     Some("content-filter".to_string())
                 );
-                Ok(response_generator.no_modifications(None))
+                let mut response = response_generator.no_modifications(None);
+                self.add_confusable_warning(request, &mut response);
+                Ok(response)
             }
         }
     }
@@ -623,6 +1335,30 @@ impl IcapModule for ContentFilterModule {
         })
     }
 
+    async fn explain(&self, request: &IcapRequest) -> Result<Vec<ExplainedMatch>, ModuleError> {
+        let identity = ClientIdentity::from_headers(&request.headers);
+        let rule_sets = self.select_rule_sets(&identity);
+        let detected = self.detect_file_type(request, &rule_sets);
+
+        // the first match of each rule set, the first one being the verdict
+        let mut matches = Vec::new();
+        for rules in rule_sets {
+            if let Some(m) = self.check_rule_set(rules, request, detected).await? {
+                matches.push(m.explain(&self.name, &rules.name));
+            }
+        }
+        if self.config.confusable_domains.action == ConfusableAction::Block
+            && let Some(c) = self.check_confusable(request)
+        {
+            let m = BlockMatch::new(
+                BlockReason::Confusable { domain: c.domain, brand: c.brand, score: c.score },
+                &self.default_rules.rule_id("confusable", 0),
+            );
+            matches.push(m.explain(&self.name, &self.default_rules.name));
+        }
+        Ok(matches)
+    }
+
     fn is_healthy(&self) -> bool {
         true
     }
@@ -633,7 +1369,7 @@ impl IcapModule for ContentFilterModule {
 
     async fn cleanup(&mut self) {
         // Clear caches
-        self.pattern_cache.write().unwrap().clear();
+        self.regex_cache.clear();
         
         if self.config.enable_logging {
             log::info!("Content filter module cleaned up");
@@ -673,12 +1409,21 @@ mod tests {
             blocked_extensions: Vec::new(),
             max_file_size: None,
             regex_cache_size: 1000,
+            pattern_engine: Default::default(),
             case_insensitive: true,
             enable_regex: true,
             blocking_action: BlockingAction::Forbidden,
             custom_message: None,
             enable_logging: true,
             enable_metrics: true,
+            user_rules: Default::default(),
+            group_rules: Default::default(),
+            detection_capture: Default::default(),
+            block_page: Default::default(),
+            blocked_file_types: Vec::new(),
+            file_type_detection: Default::default(),
+            confusable_domains: Default::default(),
+            ai_inspection: Default::default(),
         };
         let mut module = ContentFilterModule::new(config);
         module.compile_patterns().unwrap();
@@ -689,6 +1434,28 @@ mod tests {
         assert!(result.is_some());
     }
 
+    #[tokio::test]
+    async fn test_evasive_uri_blocking() {
+        let config = ContentFilterConfig {
+            blocked_domains: vec!["malware.com".to_string()],
+            blocked_keywords: vec!["/payload.exe".to_string()],
+            ..Default::default()
+        };
+        let mut module = ContentFilterModule::new(config);
+        module.compile_patterns().unwrap();
+
+        let request = create_test_request("http://example.com/files/%2570ayload%2Eexe", "");
+        assert!(module.should_block(&request).await.unwrap().is_some());
+        let request = create_test_request("http://example.com/x/..%2fpayload.exe", "");
+        assert!(module.should_block(&request).await.unwrap().is_some());
+        let mut request = create_test_request("http://example.com/", "");
+        request.headers.insert("host", "MALWARE.com.".parse().unwrap());
+        assert!(module.should_block(&request).await.unwrap().is_some());
+        let mut request = create_test_request("http://example.com@malware.com/", "");
+        request.headers.remove("host");
+        assert!(module.should_block(&request).await.unwrap().is_some());
+    }
+
     #[tokio::test]
     async fn test_keyword_blocking() {
         let config = ContentFilterConfig {
@@ -703,6 +1470,46 @@ mod tests {
         assert!(result.is_some());
     }
 
+    #[tokio::test]
+    async fn test_ai_inspection() {
+        let mut config = ContentFilterConfig {
+            blocked_keywords: vec!["secret".to_string()],
+            blocked_keyword_patterns: vec![r"\d{4}-\d{4}".to_string()],
+            enable_regex: true,
+            ai_inspection: AiInspectionConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let body = r#"{"model": "secret-model", "messages": [{"role": "user", "content": "card 1234-5678"}]}"#;
+        let mut request = create_test_request("http://api.example.com/v1/chat/completions", body);
+        request.headers.insert("content-type", "application/json".parse().unwrap());
+
+        let mut module = ContentFilterModule::new(config.clone());
+        module.compile_patterns().unwrap();
+        // only the text fields are matched
+        let m = module.evaluate(&request).await.unwrap().unwrap().0;
+        assert_eq!(m.rule_id, "default:keyword_pattern:0");
+        let response = module.handle_reqmod(&request, &TransactionCtx::for_request(&request)).await.unwrap();
+        assert_eq!(response.status, http::StatusCode::FORBIDDEN);
+
+        config.ai_inspection.action = AiInspectionAction::Redact;
+        let mut module = ContentFilterModule::new(config);
+        module.compile_patterns().unwrap();
+        let response = module.handle_reqmod(&request, &TransactionCtx::for_request(&request)).await.unwrap();
+        assert_eq!(response.status, http::StatusCode::OK);
+        assert_eq!(response.headers.get(HEADER_AI_REDACTED).unwrap(), "1");
+        let value: serde_json::Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(value["messages"][0]["content"], "card [REDACTED]");
+        assert_eq!(value["model"], "secret-model");
+
+        // not an AI API message
+        let request = create_test_request("http://example.com/", body);
+        let response = module.handle_reqmod(&request, &TransactionCtx::for_request(&request)).await.unwrap();
+        assert_eq!(response.status, http::StatusCode::FORBIDDEN);
+    }
+
     #[tokio::test]
     async fn test_mime_type_blocking() {
         let config = ContentFilterConfig {
@@ -718,6 +1525,52 @@ mod tests {
         assert!(result.is_some());
     }
 
+    #[tokio::test]
+    async fn test_file_type_detection() {
+        let mut config = ContentFilterConfig {
+            blocked_extensions: vec![".exe".to_string()],
+            file_type_detection: FileTypeDetectionConfig {
+                enabled: true,
+                block_mismatch: true,
+            },
+            ..Default::default()
+        };
+        config.group_rules.insert("staff".to_string(), FilterRuleSet {
+            blocked_file_types: vec!["archive".to_string()],
+            ..Default::default()
+        });
+        let mut module = ContentFilterModule::new(config);
+        module.compile_patterns().unwrap();
+
+        // a renamed executable is blocked as its true type
+        let mut request = create_test_request("http://example.com/report.pdf", "MZ\u{0}\u{0}");
+        request.headers.insert("content-type", "application/octet-stream".parse().unwrap());
+        let response = module.handle_reqmod(&request, &TransactionCtx::for_request(&request)).await.unwrap();
+        assert_eq!(response.headers.get(HEADER_RULE_ID).unwrap(), "default:extension:0");
+        assert_eq!(response.headers.get(HEADER_DETECTED_TYPE).unwrap(), "application/x-msdownload");
+
+        // a PDF sent as an image
+        let mut request = create_test_request("http://example.com/upload", "%PDF-1.7\n");
+        request.headers.insert("content-type", "image/png".parse().unwrap());
+        match module.should_block(&request).await.unwrap() {
+            Some(BlockReason::TypeMismatch { declared, detected }) => {
+                assert_eq!(declared, "image/png");
+                assert_eq!(detected, "application/pdf");
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        request.headers.insert("content-type", "application/pdf".parse().unwrap());
+        assert!(module.should_block(&request).await.unwrap().is_none());
+
+        // file types as a group condition
+        let mut request = create_test_request("http://example.com/upload", "PK\u{3}\u{4}");
+        request.headers.insert("content-type", "application/zip".parse().unwrap());
+        assert!(module.should_block(&request).await.unwrap().is_none());
+        request.headers.insert("x-client-groups", "staff".parse().unwrap());
+        let response = module.handle_reqmod(&request, &TransactionCtx::for_request(&request)).await.unwrap();
+        assert_eq!(response.headers.get(HEADER_RULE_ID).unwrap(), "group:staff:file_type:0");
+    }
+
     #[tokio::test]
     async fn test_file_size_blocking() {
         let config = ContentFilterConfig {
@@ -732,6 +1585,161 @@ mod tests {
         assert!(result.is_some());
     }
 
+    #[tokio::test]
+    async fn test_user_and_group_rules() {
+        let mut config = ContentFilterConfig {
+            blocked_keywords: vec!["games".to_string()],
+            case_insensitive: true,
+            ..Default::default()
+        };
+        config.user_rules.insert("Alice".to_string(), FilterRuleSet {
+            blocked_keywords: vec!["news".to_string()],
+            ..Default::default()
+        });
+        config.group_rules.insert("students".to_string(), FilterRuleSet {
+            blocked_keywords: vec!["social".to_string()],
+            inherit_default: true,
+            ..Default::default()
+        });
+        let mut module = ContentFilterModule::new(config);
+        module.compile_patterns().unwrap();
+
+        // anonymous clients get the default rules
+        let request = create_test_request("http://example.com/games", "");
+        assert!(module.should_block(&request).await.unwrap().is_some());
+
+        // user rules replace the default rules
+        let mut request = create_test_request("http://example.com/games", "");
+        request.headers.insert("x-client-username", "alice".parse().unwrap());
+        assert!(module.should_block(&request).await.unwrap().is_none());
+        let mut request = create_test_request("http://example.com/news", "");
+        request.headers.insert("x-client-username", "alice".parse().unwrap());
+        assert!(module.should_block(&request).await.unwrap().is_some());
+
+        // group rules may inherit the default rules
+        let mut request = create_test_request("http://example.com/games", "");
+        request.headers.insert("x-client-username", "bob".parse().unwrap());
+        request.headers.insert("x-client-groups", "staff, students".parse().unwrap());
+        assert!(module.should_block(&request).await.unwrap().is_some());
+        let mut request = create_test_request("http://example.com/social", "");
+        request.headers.insert("x-client-groups", "students".parse().unwrap());
+        assert!(module.should_block(&request).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_explain() {
+        let mut config = ContentFilterConfig {
+            blocked_keywords: vec!["games".to_string()],
+            ..Default::default()
+        };
+        config.group_rules.insert("students".to_string(), FilterRuleSet {
+            blocked_keywords: vec!["social".to_string()],
+            inherit_default: true,
+            ..Default::default()
+        });
+        let mut module = ContentFilterModule::new(config);
+        module.compile_patterns().unwrap();
+
+        // each rule set reports its first match
+        let mut request = create_test_request("http://example.com/games/social", "");
+        request.headers.insert("x-client-groups", "students".parse().unwrap());
+        let matches = module.explain(&request).await.unwrap();
+        let ids: Vec<_> = matches.iter().map(|m| m.rule_id.as_str()).collect();
+        assert_eq!(ids, ["default:keyword:0", "group:students:keyword:0"]);
+        assert_eq!(matches[1].rule_set, "group:students");
+        assert_eq!(matches[1].category, "keyword");
+
+        let request = create_test_request("http://example.com/news", "");
+        assert!(module.explain(&request).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_matched_rule_id() {
+        let config = ContentFilterConfig {
+            blocked_domains: vec!["ads.net".to_string(), "malware.com".to_string()],
+            blocked_keywords: vec!["Casino".to_string()],
+            case_insensitive: true,
+            ..Default::default()
+        };
+        let mut module = ContentFilterModule::new(config);
+        module.compile_patterns().unwrap();
+
+        // subdomains match, unrelated domains sharing a suffix do not
+        let mut request = create_test_request("http://cdn.malware.com/", "");
+        request.headers.insert("host", "cdn.malware.com:8080".parse().unwrap());
+        let response = module.handle_reqmod(&request, &TransactionCtx::for_request(&request)).await.unwrap();
+        assert_eq!(response.headers.get(HEADER_RULE_ID).unwrap(), "default:domain:1");
+        let mut request = create_test_request("http://notmalware.com/", "");
+        request.headers.insert("host", "notmalware.com".parse().unwrap());
+        assert!(module.should_block(&request).await.unwrap().is_none());
+
+        let request = create_test_request("http://example.com/", "online CASINO bonus");
+        let response = module.handle_reqmod(&request, &TransactionCtx::for_request(&request)).await.unwrap();
+        assert_eq!(response.headers.get(HEADER_RULE_ID).unwrap(), "default:keyword:0");
+    }
+
+    #[tokio::test]
+    async fn test_block_page() {
+        let mut config = ContentFilterConfig {
+            blocked_keywords: vec!["casino".to_string()],
+            ..Default::default()
+        };
+        config.block_page.enabled = true;
+        config.block_page.categories.insert(
+            "gambling".to_string(),
+            crate::modules::block_page::BlockPageSource {
+                html: Some("{category} {rule} {client_ip}".to_string()),
+                file: None,
+            },
+        );
+        let mut module = ContentFilterModule::new(config);
+        module.compile_patterns().unwrap();
+        module.block_pages = BlockPages::new(&module.config.block_page).unwrap();
+
+        let mut request = create_test_request("http://example.com/casino", "");
+        request.headers.insert("x-url-category", "Gambling".parse().unwrap());
+        request.headers.insert("x-client-ip", "192.0.2.1".parse().unwrap());
+        let response = module.handle_reqmod(&request, &TransactionCtx::for_request(&request)).await.unwrap();
+        assert_eq!(response.status, http::StatusCode::OK);
+        let body = String::from_utf8_lossy(&response.body);
+        assert!(body.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+        assert!(body.contains("Gambling default:keyword:0 192.0.2.1"));
+        assert_eq!(response.headers.get(HEADER_RULE_ID).unwrap(), "default:keyword:0");
+
+        // the built-in page is used for the categories without a page
+        let request = create_test_request("http://example.com/casino", "");
+        let response = module.handle_reqmod(&request, &TransactionCtx::for_request(&request)).await.unwrap();
+        assert!(String::from_utf8_lossy(&response.body).contains("Category: keyword"));
+    }
+
+    #[tokio::test]
+    async fn test_regex_validation_and_cache() {
+        let mut config = ContentFilterConfig {
+            blocked_keyword_patterns: vec!["casino[0-9]+".to_string()],
+            enable_regex: true,
+            regex_cache_size: 16,
+            ..Default::default()
+        };
+        config.group_rules.insert("staff".to_string(), FilterRuleSet {
+            blocked_keyword_patterns: vec!["casino[0-9]+".to_string(), "poker(".to_string()],
+            ..Default::default()
+        });
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("group_rules.staff.blocked_keyword_patterns[1]"), "{}", err);
+
+        config.group_rules.get_mut("staff").unwrap().blocked_keyword_patterns[1] = "poker[0-9]+".to_string();
+        config.validate().unwrap();
+        let mut module = ContentFilterModule::new(config);
+        module.compile_patterns().unwrap();
+        // the pattern shared by both rule sets is compiled once
+        let stats = module.get_stats();
+        assert_eq!(stats.regex_cache_hits, 1);
+        assert_eq!(stats.regex_cache_misses, 2);
+
+        module.compile_patterns().unwrap();
+        assert_eq!(module.get_stats().regex_cache_hits, 4);
+    }
+
     #[tokio::test]
     async fn test_allow_clean_content() {
         let config = ContentFilterConfig {
@@ -745,4 +1753,38 @@ mod tests {
         let result = module.should_block(&request).await.unwrap();
         assert!(result.is_none());
     }
+
+    #[tokio::test]
+    async fn test_confusable_domains() {
+        let mut config = ContentFilterConfig {
+            confusable_domains: ConfusableConfig {
+                enabled: true,
+                brands: vec!["paypal.com".to_string()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let module = ContentFilterModule::new(config.clone());
+        let mut request = create_test_request("http://xn--pypal-4ve.com/login", "");
+        request.headers.insert("host", "xn--pypal-4ve.com".parse().unwrap());
+
+        // warned, not blocked
+        assert!(module.should_block(&request).await.unwrap().is_none());
+        let response = module.handle_reqmod(&request, &TransactionCtx::for_request(&request)).await.unwrap();
+        assert_eq!(response.status, http::StatusCode::NO_CONTENT);
+        assert_eq!(response.headers.get(HEADER_PHISHING_SCORE).unwrap(), "100");
+        assert_eq!(response.headers.get(HEADER_PHISHING_BRAND).unwrap(), "paypal");
+
+        config.confusable_domains.action = ConfusableAction::Block;
+        let mut module = ContentFilterModule::new(config);
+        module.compile_patterns().unwrap();
+        let (m, _) = module.evaluate(&request).await.unwrap().unwrap();
+        assert_eq!(m.rule_id, "default:confusable:0");
+        assert_eq!(m.reason.category(), "phishing");
+        let response = module.handle_reqmod(&request, &TransactionCtx::for_request(&request)).await.unwrap();
+        assert_eq!(response.headers.get(HEADER_RULE_CATEGORY).unwrap(), "phishing");
+
+        request.headers.insert("host", "paypal.com".parse().unwrap());
+        assert!(module.should_block(&request).await.unwrap().is_none());
+    }
 }
@@ -0,0 +1,293 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Capture of offending bodies on detection
+//!
+//! When a message is blocked, the first bytes of its body may be written to
+//! the detection log to help incident triage. Sensitive data is masked in
+//! the sample before it leaves the process: the data that triggered the
+//! detection and anything matching the configured redaction patterns. The
+//! complete body can optionally be kept in a quarantine directory, the log
+//! then only references it.
+
+use std::fmt::Write;
+use std::path::PathBuf;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use regex::bytes::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use slog::Logger;
+
+use crate::log::detection::DetectionEvent;
+use crate::modules::ModuleError;
+
+/// Byte used to mask redacted data
+const REDACTION_MASK: u8 = b'*';
+
+/// Encoding of the captured sample
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureEncoding {
+    Hex,
+    #[default]
+    Base64,
+}
+
+impl CaptureEncoding {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CaptureEncoding::Hex => "hex",
+            CaptureEncoding::Base64 => "base64",
+        }
+    }
+
+    fn encode(&self, data: &[u8]) -> String {
+        match self {
+            CaptureEncoding::Hex => {
+                let mut s = String::with_capacity(data.len() * 2);
+                for b in data {
+                    let _ = write!(s, "{b:02x}");
+                }
+                s
+            }
+            CaptureEncoding::Base64 => STANDARD.encode(data),
+        }
+    }
+}
+
+/// Detection capture configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DetectionCaptureConfig {
+    /// Capture body samples of blocked messages
+    pub enabled: bool,
+    /// Maximum size of the captured sample (bytes)
+    pub max_sample_size: usize,
+    /// Encoding of the sample in the detection log
+    pub encoding: CaptureEncoding,
+    /// Mask the data which triggered the detection
+    pub redact_matches: bool,
+    /// Regex patterns of data to mask in the sample
+    pub redact_patterns: Vec<String>,
+    /// Directory to store the complete bodies in
+    pub quarantine_dir: Option<PathBuf>,
+}
+
+impl Default for DetectionCaptureConfig {
+    fn default() -> Self {
+        DetectionCaptureConfig {
+            enabled: false,
+            max_sample_size: 4096,
+            encoding: CaptureEncoding::Base64,
+            redact_matches: true,
+            redact_patterns: Vec::new(),
+            quarantine_dir: None,
+        }
+    }
+}
+
+/// A redacted body sample
+#[derive(Debug, Clone)]
+pub struct CapturedSample {
+    /// Encoded sample
+    pub sample: String,
+    /// Whether the body is larger than the sample
+    pub truncated: bool,
+    /// Number of masked ranges
+    pub redacted: usize,
+}
+
+/// Body capture of a module
+pub struct DetectionCapture {
+    config: DetectionCaptureConfig,
+    redact_patterns: Vec<Regex>,
+    logger: Option<Logger>,
+}
+
+impl DetectionCapture {
+    /// Create the capture, the detection logger is only built if enabled
+    pub fn new(module_name: &str, config: DetectionCaptureConfig) -> Result<Self, ModuleError> {
+        let mut redact_patterns = Vec::with_capacity(config.redact_patterns.len());
+        for (i, pattern) in config.redact_patterns.iter().enumerate() {
+            let regex = Regex::new(pattern).map_err(|e| {
+                ModuleError::InitFailed(format!("invalid redact_patterns[{i}] '{pattern}': {e}"))
+            })?;
+            redact_patterns.push(regex);
+        }
+        let logger = if config.enabled {
+            crate::log::detection::get_logger(module_name)
+        } else {
+            None
+        };
+        Ok(DetectionCapture {
+            config,
+            redact_patterns,
+            logger,
+        })
+    }
+
+    /// Create a disabled capture
+    pub fn disabled() -> Self {
+        DetectionCapture {
+            config: DetectionCaptureConfig::default(),
+            redact_patterns: Vec::new(),
+            logger: None,
+        }
+    }
+
+    /// Check if samples will be captured
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Build a matcher masking the given literal in samples
+    pub fn literal_matcher(literal: &str, case_insensitive: bool) -> Option<Regex> {
+        RegexBuilder::new(&regex::escape(literal))
+            .case_insensitive(case_insensitive)
+            .build()
+            .ok()
+    }
+
+    /// Build a matcher masking the given pattern in samples
+    pub fn pattern_matcher(pattern: &str) -> Option<Regex> {
+        Regex::new(pattern).ok()
+    }
+
+    /// Cut and redact the sample of the body
+    ///
+    /// `matched` masks the data which triggered the detection, and is
+    /// ignored unless `redact_matches` is set.
+    pub fn capture(&self, body: &[u8], matched: Option<&Regex>) -> CapturedSample {
+        let len = body.len().min(self.config.max_sample_size);
+        let mut sample = body[..len].to_vec();
+
+        let matched = matched.filter(|_| self.config.redact_matches);
+        let mut ranges = Vec::new();
+        for regex in matched.into_iter().chain(self.redact_patterns.iter()) {
+            // match on the whole body so that data cut by the sample end is still found
+            ranges.extend(
+                regex
+                    .find_iter(body)
+                    .filter(|m| m.start() < len && !m.is_empty())
+                    .map(|m| m.start()..m.end().min(len)),
+            );
+        }
+        ranges.sort_by_key(|r| r.start);
+
+        let mut redacted = 0;
+        let mut masked_end = 0;
+        for range in ranges {
+            if range.end <= masked_end {
+                continue;
+            }
+            if range.start >= masked_end {
+                redacted += 1;
+            }
+            sample[range.start.max(masked_end)..range.end].fill(REDACTION_MASK);
+            masked_end = range.end;
+        }
+
+        CapturedSample {
+            sample: self.config.encoding.encode(&sample),
+            truncated: body.len() > len,
+            redacted,
+        }
+    }
+
+    /// Capture the body of a blocked message and write it to the detection log
+    pub async fn record(
+        &self,
+        uri: &str,
+        rule_id: &str,
+        reason: &str,
+        body: &[u8],
+        matched: Option<&Regex>,
+    ) {
+        let Some(logger) = &self.logger else {
+            return;
+        };
+
+        let quarantine_ref = match &self.config.quarantine_dir {
+            Some(dir) if !body.is_empty() => {
+                let name = format!("{}.bin", uuid::Uuid::new_v4());
+                match tokio::fs::write(dir.join(&name), body).await {
+                    Ok(_) => Some(name),
+                    Err(e) => {
+                        log::warn!(
+                            "failed to quarantine body of {uri} in {}: {e}",
+                            dir.display()
+                        );
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        let captured = self.capture(body, matched);
+        DetectionEvent {
+            uri,
+            rule_id,
+            reason,
+            body_size: body.len(),
+            sample_encoding: self.config.encoding.as_str(),
+            sample: &captured.sample,
+            sample_truncated: captured.truncated,
+            redacted: captured.redacted,
+            quarantine_ref: quarantine_ref.as_deref(),
+        }
+        .log(logger);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacted_sample() {
+        let config = DetectionCaptureConfig {
+            enabled: true,
+            max_sample_size: 24,
+            encoding: CaptureEncoding::Hex,
+            redact_patterns: vec![r"\d{4}-\d{4}".to_string()],
+            ..Default::default()
+        };
+        let capture = DetectionCapture::new("test", config).unwrap();
+        let body = b"card 1234-5678 secret=Top SECRET data";
+        let matched = DetectionCapture::literal_matcher("secret", true);
+
+        let captured = capture.capture(body, matched.as_ref());
+        assert!(captured.truncated);
+        assert_eq!(captured.redacted, 2);
+        assert_eq!(
+            captured.sample,
+            CaptureEncoding::Hex.encode(b"card ********* ******=To")
+        );
+    }
+
+    #[test]
+    fn keep_matches() {
+        let config = DetectionCaptureConfig {
+            enabled: true,
+            redact_matches: false,
+            encoding: CaptureEncoding::Base64,
+            ..Default::default()
+        };
+        let capture = DetectionCapture::new("test", config).unwrap();
+        let matched = DetectionCapture::pattern_matcher("casino");
+        let captured = capture.capture(b"online casino", matched.as_ref());
+        assert!(!captured.truncated);
+        assert_eq!(captured.redacted, 0);
+        assert_eq!(captured.sample, "b25saW5lIGNhc2lubw==");
+
+        let config = DetectionCaptureConfig {
+            redact_patterns: vec!["(".to_string()],
+            ..Default::default()
+        };
+        assert!(DetectionCapture::new("test", config).is_err());
+    }
+}
@@ -0,0 +1,350 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Escalation of repeated client violations
+//!
+//! The violations of each client are counted in a sliding window, a client
+//! reaching the threshold is escalated: the escalation is returned to the
+//! caller for auditing and posted to the webhook, so that the proxy can
+//! quarantine the client. Clients are keyed by the IP address forwarded by
+//! the proxy, or by user name if no address is forwarded.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use http::HeaderMap;
+
+use crate::auth::identity::{ClientIdentity, HEADER_CLIENT_IP};
+use crate::config::server::escalation::EscalationConfig;
+
+mod webhook;
+
+/// An escalated client
+#[derive(Debug, Clone)]
+pub struct Escalation {
+    /// Client IP address or user name
+    pub client: String,
+    /// Category of the last violation
+    pub category: String,
+    /// Description of the last violation
+    pub reason: String,
+    /// Violations counted within the window
+    pub violations: usize,
+    /// Quarantine duration asked to the proxy
+    pub quarantine: Duration,
+    pub time: DateTime<Utc>,
+}
+
+impl Escalation {
+    /// Body posted to the webhook
+    pub fn to_json(&self, window: Duration) -> serde_json::Value {
+        serde_json::json!({
+            "action": "quarantine",
+            "client": self.client,
+            "category": self.category,
+            "reason": self.reason,
+            "violations": self.violations,
+            "window_secs": window.as_secs(),
+            "quarantine_secs": self.quarantine.as_secs(),
+            "time": self.time.to_rfc3339(),
+        })
+    }
+}
+
+impl std::fmt::Display for Escalation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "client {} escalated after {} violations, quarantine {}s, last {} violation: {}",
+            self.client,
+            self.violations,
+            self.quarantine.as_secs(),
+            self.category,
+            self.reason
+        )
+    }
+}
+
+/// Escalation counters
+#[derive(Debug, Default)]
+pub struct EscalationStats {
+    /// Counted violations
+    pub violations: AtomicU64,
+    /// Escalated clients
+    pub escalations: AtomicU64,
+    /// Escalations skipped as the client was in cool-down
+    pub suppressed: AtomicU64,
+    /// Violations not counted as too many clients are tracked
+    pub dropped: AtomicU64,
+    /// Failed webhook notifications
+    pub notify_failures: AtomicU64,
+}
+
+#[derive(Default)]
+struct ClientState {
+    violations: VecDeque<Instant>,
+    last_escalation: Option<Instant>,
+}
+
+impl ClientState {
+    fn expire(&mut self, now: Instant, config: &EscalationConfig) {
+        while self
+            .violations
+            .front()
+            .is_some_and(|t| now.duration_since(*t) > config.window)
+        {
+            self.violations.pop_front();
+        }
+    }
+
+    fn in_cool_down(&self, now: Instant, config: &EscalationConfig) -> bool {
+        self.last_escalation
+            .is_some_and(|t| now.duration_since(t) < config.cool_down)
+    }
+}
+
+/// Tracker of the violations of all clients of a server
+pub struct EscalationTracker {
+    config: EscalationConfig,
+    clients: Mutex<HashMap<String, ClientState>>,
+    stats: EscalationStats,
+}
+
+impl EscalationTracker {
+    pub fn new(config: EscalationConfig) -> Self {
+        EscalationTracker {
+            config,
+            clients: Mutex::new(HashMap::new()),
+            stats: EscalationStats::default(),
+        }
+    }
+
+    /// Client key of an ICAP request, `None` if the proxy forwarded no client
+    pub fn client_key(headers: &HeaderMap) -> Option<String> {
+        let ip = headers
+            .get(HEADER_CLIENT_IP)
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty());
+        match ip {
+            Some(ip) => Some(ip.to_string()),
+            None => ClientIdentity::from_headers(headers)
+                .username
+                .map(|u| format!("user:{u}")),
+        }
+    }
+
+    /// Record a violation of the client
+    ///
+    /// Returns the escalation if the client reached the threshold, the
+    /// caller should audit it and pass it to [`EscalationTracker::notify`].
+    pub fn record(&self, client: &str, category: &str, reason: &str) -> Option<Escalation> {
+        self.record_at(client, category, reason, Instant::now())
+    }
+
+    fn record_at(
+        &self,
+        client: &str,
+        category: &str,
+        reason: &str,
+        now: Instant,
+    ) -> Option<Escalation> {
+        if !self.config.counts(category) {
+            return None;
+        }
+
+        let mut clients = self.clients.lock().unwrap();
+        if !clients.contains_key(client) && clients.len() >= self.config.max_clients {
+            clients.retain(|_, state| {
+                state.expire(now, &self.config);
+                !state.violations.is_empty() || state.in_cool_down(now, &self.config)
+            });
+            if clients.len() >= self.config.max_clients {
+                self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+        }
+
+        self.stats.violations.fetch_add(1, Ordering::Relaxed);
+        let state = clients.entry(client.to_string()).or_default();
+        state.expire(now, &self.config);
+        state.violations.push_back(now);
+        let violations = state.violations.len();
+        if violations < self.config.threshold {
+            return None;
+        }
+        if state.in_cool_down(now, &self.config) {
+            self.stats.suppressed.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        state.last_escalation = Some(now);
+        state.violations.clear();
+        self.stats.escalations.fetch_add(1, Ordering::Relaxed);
+        Some(Escalation {
+            client: client.to_string(),
+            category: category.to_string(),
+            reason: reason.to_string(),
+            violations,
+            quarantine: self.config.quarantine,
+            time: Utc::now(),
+        })
+    }
+
+    /// Post the escalation to the webhook in the background
+    pub fn notify(self: &Arc<Self>, escalation: Escalation) {
+        let Some(url) = self.config.webhook.clone() else {
+            return;
+        };
+        let tracker = Arc::clone(self);
+        tokio::spawn(async move {
+            let body = escalation.to_json(tracker.config.window).to_string();
+            if let Err(e) = webhook::post(&url, &body, tracker.config.webhook_timeout).await {
+                tracker
+                    .stats
+                    .notify_failures
+                    .fetch_add(1, Ordering::Relaxed);
+                log::warn!(
+                    "failed to notify escalation of client {} to {url}: {e:?}",
+                    escalation.client
+                );
+            }
+        });
+    }
+
+    /// Escalation counters
+    pub fn stats(&self) -> &EscalationStats {
+        &self.stats
+    }
+
+    /// Number of tracked clients
+    pub fn tracked_clients(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn threshold_and_cool_down() {
+        let config = EscalationConfig {
+            threshold: 3,
+            window: Duration::from_secs(60),
+            cool_down: Duration::from_secs(600),
+            ..Default::default()
+        };
+        let tracker = EscalationTracker::new(config);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        // not counted categories and expired violations
+        assert!(
+            tracker
+                .record_at("10.0.0.1", "domain", "blocked", at(0))
+                .is_none()
+        );
+        assert!(
+            tracker
+                .record_at("10.0.0.1", "malware", "Eicar", at(0))
+                .is_none()
+        );
+        assert!(
+            tracker
+                .record_at("10.0.0.1", "malware", "Eicar", at(10))
+                .is_none()
+        );
+        assert!(
+            tracker
+                .record_at("10.0.0.1", "keyword", "secret", at(100))
+                .is_none()
+        );
+        assert!(
+            tracker
+                .record_at("10.0.0.2", "malware", "Eicar", at(100))
+                .is_none()
+        );
+
+        assert!(
+            tracker
+                .record_at("10.0.0.1", "keyword", "secret", at(110))
+                .is_none()
+        );
+        let e = tracker
+            .record_at("10.0.0.1", "file_type", "exe", at(120))
+            .unwrap();
+        assert_eq!(e.client, "10.0.0.1");
+        assert_eq!(e.violations, 3);
+        assert_eq!(e.category, "file_type");
+        assert_eq!(e.to_json(Duration::from_secs(60))["quarantine_secs"], 1800);
+
+        // in cool-down
+        for secs in [130, 140, 150] {
+            assert!(
+                tracker
+                    .record_at("10.0.0.1", "malware", "Eicar", at(secs))
+                    .is_none()
+            );
+        }
+        assert_eq!(tracker.stats().suppressed.load(Ordering::Relaxed), 1);
+        for secs in [730, 740] {
+            assert!(
+                tracker
+                    .record_at("10.0.0.1", "malware", "Eicar", at(secs))
+                    .is_none()
+            );
+        }
+        assert!(
+            tracker
+                .record_at("10.0.0.1", "malware", "Eicar", at(750))
+                .is_some()
+        );
+        assert_eq!(tracker.stats().escalations.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn max_clients() {
+        let config = EscalationConfig {
+            max_clients: 1,
+            window: Duration::from_secs(60),
+            ..Default::default()
+        };
+        let tracker = EscalationTracker::new(config);
+        let start = Instant::now();
+        tracker.record_at("10.0.0.1", "malware", "Eicar", start);
+        tracker.record_at("10.0.0.2", "malware", "Eicar", start);
+        assert_eq!(tracker.stats().dropped.load(Ordering::Relaxed), 1);
+
+        // expired clients make room for new ones
+        tracker.record_at(
+            "10.0.0.2",
+            "malware",
+            "Eicar",
+            start + Duration::from_secs(61),
+        );
+        assert_eq!(tracker.tracked_clients(), 1);
+        assert_eq!(tracker.stats().violations.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn client_key() {
+        let mut headers = HeaderMap::new();
+        assert!(EscalationTracker::client_key(&headers).is_none());
+        headers.insert("x-client-username", "alice".parse().unwrap());
+        assert_eq!(
+            EscalationTracker::client_key(&headers).unwrap(),
+            "user:alice"
+        );
+        headers.insert(HEADER_CLIENT_IP, "192.0.2.1".parse().unwrap());
+        assert_eq!(
+            EscalationTracker::client_key(&headers).unwrap(),
+            "192.0.2.1"
+        );
+    }
+}
@@ -0,0 +1,110 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Escalation webhook
+//!
+//! A minimal HTTP/1.1 POST of a JSON body, any 2xx status is a success.
+
+use std::time::Duration;
+
+use anyhow::{Context, anyhow};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use url::Url;
+
+use g3_types::net::RustlsClientConfigBuilder;
+
+/// Size read for the response status line
+const MAX_STATUS_SIZE: u64 = 4096;
+
+/// Post the JSON body to the url
+pub(super) async fn post(url: &Url, body: &str, timeout: Duration) -> anyhow::Result<()> {
+    tokio::time::timeout(timeout, post_inner(url, body))
+        .await
+        .map_err(|_| anyhow!("timed out after {timeout:?}"))?
+}
+
+async fn post_inner(url: &Url, body: &str) -> anyhow::Result<()> {
+    let host = url.host_str().ok_or_else(|| anyhow!("no host in url"))?;
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| anyhow!("no port in url"))?;
+    let host_header = match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    };
+    let path = match url.query() {
+        Some(query) => format!("{}?{query}", url.path()),
+        None => url.path().to_string(),
+    };
+    let request = format!(
+        "POST {path} HTTP/1.1\r\nHost: {host_header}\r\nUser-Agent: g3icap\r\n\
+         Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+
+    let stream = TcpStream::connect((host.trim_matches(['[', ']']), port))
+        .await
+        .context("failed to connect")?;
+    let status_line = if url.scheme() == "https" {
+        let tls_client = RustlsClientConfigBuilder::default().build()?;
+        let server_name =
+            rustls::pki_types::ServerName::try_from(host.trim_matches(['[', ']']).to_string())
+                .map_err(|e| anyhow!("invalid tls server name {host}: {e}"))?;
+        let stream = TlsConnector::from(tls_client.driver)
+            .connect(server_name, stream)
+            .await
+            .context("tls handshake failed")?;
+        exchange(stream, &request).await?
+    } else {
+        exchange(stream, &request).await?
+    };
+    check_status(&status_line)
+}
+
+async fn exchange<S>(mut stream: S, request: &str) -> anyhow::Result<String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .context("failed to send request")?;
+    stream.flush().await?;
+
+    let mut response = Vec::new();
+    let mut reader = (&mut stream).take(MAX_STATUS_SIZE);
+    let mut buf = [0u8; 1024];
+    loop {
+        let n = reader
+            .read(&mut buf)
+            .await
+            .context("failed to read response")?;
+        if n == 0 {
+            break;
+        }
+        response.extend_from_slice(&buf[..n]);
+        if response.windows(2).any(|w| w == b"\r\n") {
+            break;
+        }
+    }
+    let line_end = response
+        .windows(2)
+        .position(|w| w == b"\r\n")
+        .ok_or_else(|| anyhow!("incomplete response status line"))?;
+    Ok(String::from_utf8_lossy(&response[..line_end]).into_owned())
+}
+
+fn check_status(status_line: &str) -> anyhow::Result<()> {
+    let mut parts = status_line.splitn(3, ' ');
+    if !parts.next().unwrap_or_default().starts_with("HTTP/1.") {
+        return Err(anyhow!("invalid status line {status_line}"));
+    }
+    match parts.next().unwrap_or_default().parse::<u16>() {
+        Ok(200..=299) => Ok(()),
+        _ => Err(anyhow!("unexpected response status: {status_line}")),
+    }
+}
@@ -0,0 +1,733 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Decision expressions
+//!
+//! Conditions on the fields of a transaction, combined with `&&`, `||`, `!`
+//! and parentheses:
+//!
+//! ```text
+//! host in ["ads.example.net", "tracker.example.org"] && !(path starts_with "/static/")
+//! status >= 400 || header.user-agent matches "(?i)^curl/"
+//! ```
+//!
+//! The fields are `icap.method`, `service`, `method`, `url`, `host`, `path`,
+//! `query`, `status`, `content_type`, `body_size`, `client_ip`, `user`, and
+//! the headers `header.<name>` of the HTTP request, `res.header.<name>` of
+//! the HTTP response and `icap.header.<name>` of the ICAP request. A field
+//! alone is true if it is set and not empty. The operators are `==`, `!=`,
+//! `<`, `<=`, `>`, `>=`, `contains`, `starts_with`, `ends_with`, `matches`
+//! with a regular expression and `in` with a list of strings. A comparison
+//! with a field that is not set is false, but for `!=`.
+
+use std::borrow::Cow;
+
+use http::HeaderMap;
+use regex::Regex;
+
+use crate::auth::identity::{HEADER_CLIENT_IP, HEADER_CLIENT_USERNAME};
+use crate::modules::url_normalize::normalize_host;
+use crate::protocol::common::{IcapMethod, IcapRequest};
+
+/// Error of an expression that does not compile
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{message} at offset {offset}")]
+pub struct ParseError {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    fn new(offset: usize, message: impl Into<String>) -> Self {
+        ParseError {
+            offset,
+            message: message.into(),
+        }
+    }
+}
+
+/// A field of the transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Field {
+    IcapMethod,
+    Service,
+    Method,
+    Url,
+    Host,
+    Path,
+    Query,
+    Status,
+    ContentType,
+    BodySize,
+    ClientIp,
+    User,
+    Header(String),
+    ResHeader(String),
+    IcapHeader(String),
+}
+
+impl Field {
+    /// Parse a field name, also used for the template variables
+    pub fn parse(name: &str) -> Option<Self> {
+        let lower = name.to_ascii_lowercase();
+        let field = match lower.as_str() {
+            "icap.method" => Field::IcapMethod,
+            "service" => Field::Service,
+            "method" => Field::Method,
+            "url" => Field::Url,
+            "host" => Field::Host,
+            "path" => Field::Path,
+            "query" => Field::Query,
+            "status" => Field::Status,
+            "content_type" => Field::ContentType,
+            "body_size" => Field::BodySize,
+            "client_ip" => Field::ClientIp,
+            "user" => Field::User,
+            _ => {
+                if let Some(h) = lower.strip_prefix("icap.header.") {
+                    Field::IcapHeader(h.to_string())
+                } else if let Some(h) = lower.strip_prefix("res.header.") {
+                    Field::ResHeader(h.to_string())
+                } else if let Some(h) = lower.strip_prefix("header.") {
+                    Field::Header(h.to_string())
+                } else {
+                    return None;
+                }
+            }
+        };
+        if let Field::Header(h) | Field::ResHeader(h) | Field::IcapHeader(h) = &field
+            && http::HeaderName::from_bytes(h.as_bytes()).is_err()
+        {
+            return None;
+        }
+        Some(field)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+    StartsWith,
+    EndsWith,
+    Matches,
+    In,
+}
+
+/// Right hand side of a comparison
+#[derive(Debug, Clone)]
+enum Operand {
+    Str(String),
+    Num(f64),
+    List(Vec<String>),
+    Regex(Regex),
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Const(bool),
+    Present(Field),
+    Compare(Field, CmpOp, Operand),
+    Not(Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+}
+
+impl Node {
+    fn eval(&self, subject: &Subject) -> bool {
+        match self {
+            Node::Const(b) => *b,
+            Node::Present(field) => subject.value(field).is_some_and(|v| !v.is_empty()),
+            Node::Compare(field, op, operand) => compare(subject.value(field), *op, operand),
+            Node::Not(n) => !n.eval(subject),
+            Node::And(a, b) => a.eval(subject) && b.eval(subject),
+            Node::Or(a, b) => a.eval(subject) || b.eval(subject),
+        }
+    }
+}
+
+/// A compiled expression
+#[derive(Debug, Clone)]
+pub struct Expr(Node);
+
+impl Expr {
+    /// Compile an expression
+    pub fn parse(s: &str) -> Result<Self, ParseError> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            end: s.len(),
+        };
+        let node = parser.or()?;
+        match parser.peek() {
+            None => Ok(Expr(node)),
+            Some((offset, _)) => Err(ParseError::new(*offset, "unexpected token")),
+        }
+    }
+
+    /// Evaluate the expression on the transaction
+    pub fn eval(&self, subject: &Subject) -> bool {
+        self.0.eval(subject)
+    }
+}
+
+fn compare(value: Option<Cow<str>>, op: CmpOp, operand: &Operand) -> bool {
+    let Some(value) = value else {
+        return op == CmpOp::Ne;
+    };
+    match (op, operand) {
+        (CmpOp::Eq, Operand::Str(s)) => value == s.as_str(),
+        (CmpOp::Ne, Operand::Str(s)) => value != s.as_str(),
+        (CmpOp::Contains, Operand::Str(s)) => value.contains(s.as_str()),
+        (CmpOp::StartsWith, Operand::Str(s)) => value.starts_with(s.as_str()),
+        (CmpOp::EndsWith, Operand::Str(s)) => value.ends_with(s.as_str()),
+        (CmpOp::Matches, Operand::Regex(r)) => r.is_match(&value),
+        (CmpOp::In, Operand::List(l)) => l.iter().any(|s| *s == value),
+        (op, Operand::Num(n)) => {
+            let Ok(v) = value.trim().parse::<f64>() else {
+                return op == CmpOp::Ne;
+            };
+            match op {
+                CmpOp::Eq => v == *n,
+                CmpOp::Ne => v != *n,
+                CmpOp::Lt => v < *n,
+                CmpOp::Le => v <= *n,
+                CmpOp::Gt => v > *n,
+                CmpOp::Ge => v >= *n,
+                _ => false,
+            }
+        }
+        // operand types are checked when compiling
+        _ => false,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Num(f64),
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+    And,
+    Or,
+    Not,
+    Op(CmpOp),
+}
+
+fn tokenize(s: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let bytes = s.as_bytes();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let c = bytes[pos];
+        let start = pos;
+        let two = bytes.get(pos..pos + 2);
+        let token = match c {
+            b' ' | b'\t' | b'\r' | b'\n' => {
+                pos += 1;
+                continue;
+            }
+            b'(' => Token::LParen,
+            b')' => Token::RParen,
+            b'[' => Token::LBracket,
+            b']' => Token::RBracket,
+            b',' => Token::Comma,
+            _ if two == Some(b"&&") => Token::And,
+            _ if two == Some(b"||") => Token::Or,
+            _ if two == Some(b"==") => Token::Op(CmpOp::Eq),
+            _ if two == Some(b"!=") => Token::Op(CmpOp::Ne),
+            _ if two == Some(b"<=") => Token::Op(CmpOp::Le),
+            _ if two == Some(b">=") => Token::Op(CmpOp::Ge),
+            b'!' => Token::Not,
+            b'<' => Token::Op(CmpOp::Lt),
+            b'>' => Token::Op(CmpOp::Gt),
+            b'"' | b'\'' => {
+                let (value, end) = string(s, pos)?;
+                tokens.push((start, Token::Str(value)));
+                pos = end;
+                continue;
+            }
+            b'0'..=b'9' => {
+                let end = s[pos..]
+                    .find(|c: char| !c.is_ascii_digit() && c != '.')
+                    .map_or(s.len(), |n| pos + n);
+                let n = s[pos..end]
+                    .parse()
+                    .map_err(|_| ParseError::new(start, "invalid number"))?;
+                tokens.push((start, Token::Num(n)));
+                pos = end;
+                continue;
+            }
+            c if c.is_ascii_alphabetic() || c == b'_' => {
+                let end = s[pos..]
+                    .find(|c: char| !c.is_ascii_alphanumeric() && !matches!(c, '_' | '.' | '-'))
+                    .map_or(s.len(), |n| pos + n);
+                let word = &s[pos..end];
+                let token = match word {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    "contains" => Token::Op(CmpOp::Contains),
+                    "starts_with" => Token::Op(CmpOp::StartsWith),
+                    "ends_with" => Token::Op(CmpOp::EndsWith),
+                    "matches" => Token::Op(CmpOp::Matches),
+                    "in" => Token::Op(CmpOp::In),
+                    _ => Token::Ident(word.to_string()),
+                };
+                tokens.push((start, token));
+                pos = end;
+                continue;
+            }
+            _ => return Err(ParseError::new(start, "unexpected character")),
+        };
+        pos += match token {
+            Token::And | Token::Or => 2,
+            Token::Op(CmpOp::Eq | CmpOp::Ne | CmpOp::Le | CmpOp::Ge) => 2,
+            _ => 1,
+        };
+        tokens.push((start, token));
+    }
+    Ok(tokens)
+}
+
+/// Read a quoted string, returning its value and the offset after it
+fn string(s: &str, start: usize) -> Result<(String, usize), ParseError> {
+    let mut chars = s[start..].char_indices();
+    let (_, quote) = chars.next().unwrap_or((0, '"'));
+    let mut value = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some((_, c)) => value.push(c),
+                None => break,
+            },
+            c if c == quote => return Ok((value, start + i + 1)),
+            c => value.push(c),
+        }
+    }
+    Err(ParseError::new(start, "unterminated string"))
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&(usize, Token)> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<(usize, Token), ParseError> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| ParseError::new(self.end, "unexpected end of expression"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek().is_some_and(|(_, t)| t == token) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn or(&mut self) -> Result<Node, ParseError> {
+        let mut node = self.and()?;
+        while self.eat(&Token::Or) {
+            node = Node::Or(Box::new(node), Box::new(self.and()?));
+        }
+        Ok(node)
+    }
+
+    fn and(&mut self) -> Result<Node, ParseError> {
+        let mut node = self.unary()?;
+        while self.eat(&Token::And) {
+            node = Node::And(Box::new(node), Box::new(self.unary()?));
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> Result<Node, ParseError> {
+        if self.eat(&Token::Not) {
+            return Ok(Node::Not(Box::new(self.unary()?)));
+        }
+        let (offset, token) = self.next()?;
+        match token {
+            Token::LParen => {
+                let node = self.or()?;
+                if !self.eat(&Token::RParen) {
+                    return Err(ParseError::new(offset, "unclosed parenthesis"));
+                }
+                Ok(node)
+            }
+            Token::Ident(name) if name == "true" => Ok(Node::Const(true)),
+            Token::Ident(name) if name == "false" => Ok(Node::Const(false)),
+            Token::Ident(name) => {
+                let field = Field::parse(&name)
+                    .ok_or_else(|| ParseError::new(offset, format!("unknown field {name}")))?;
+                let Some((_, Token::Op(op))) = self.peek().cloned() else {
+                    return Ok(Node::Present(field));
+                };
+                self.pos += 1;
+                let operand = self.operand(op)?;
+                Ok(Node::Compare(field, op, operand))
+            }
+            _ => Err(ParseError::new(offset, "expected a field")),
+        }
+    }
+
+    fn operand(&mut self, op: CmpOp) -> Result<Operand, ParseError> {
+        let (offset, token) = self.next()?;
+        match (op, token) {
+            (CmpOp::In, Token::LBracket) => {
+                let mut list = Vec::new();
+                loop {
+                    match self.next()? {
+                        (_, Token::RBracket) => break,
+                        (_, Token::Str(s)) => list.push(s),
+                        (offset, _) => return Err(ParseError::new(offset, "expected a string")),
+                    }
+                    if !self.eat(&Token::Comma) {
+                        if !self.eat(&Token::RBracket) {
+                            return Err(ParseError::new(offset, "unclosed list"));
+                        }
+                        break;
+                    }
+                }
+                Ok(Operand::List(list))
+            }
+            (CmpOp::In, _) => Err(ParseError::new(offset, "expected a list")),
+            (CmpOp::Matches, Token::Str(s)) => Regex::new(&s)
+                .map(Operand::Regex)
+                .map_err(|e| ParseError::new(offset, format!("invalid regex: {e}"))),
+            (
+                CmpOp::Lt | CmpOp::Le | CmpOp::Gt | CmpOp::Ge | CmpOp::Eq | CmpOp::Ne,
+                Token::Num(n),
+            ) => Ok(Operand::Num(n)),
+            (CmpOp::Lt | CmpOp::Le | CmpOp::Gt | CmpOp::Ge, _) => {
+                Err(ParseError::new(offset, "expected a number"))
+            }
+            (_, Token::Str(s)) => Ok(Operand::Str(s)),
+            _ => Err(ParseError::new(offset, "expected a string")),
+        }
+    }
+}
+
+/// The fields of a transaction, as seen by the expressions
+pub struct Subject<'a> {
+    request: &'a IcapRequest,
+    service: &'a str,
+    request_line: Option<&'a str>,
+    method: Option<&'a str>,
+    target: Option<&'a str>,
+    status: Option<&'a str>,
+    host: Option<String>,
+}
+
+impl<'a> Subject<'a> {
+    pub fn new(request: &'a IcapRequest, service: &'a str) -> Self {
+        let sections = encapsulated_sections(request);
+        let start_line = |name: &str| {
+            let offset = sections.iter().find(|(n, _)| n == name)?.1;
+            let data = request.body.get(offset..)?;
+            let end = data.windows(2).position(|w| w == b"\r\n")?;
+            std::str::from_utf8(&data[..end]).ok()
+        };
+        let request_line = start_line("req-hdr");
+        let (method, target) = match request_line
+            .map(|l| l.split(' ').collect::<Vec<_>>())
+            .as_deref()
+        {
+            Some([method, target, ..]) => (Some(*method), Some(*target)),
+            _ => (None, None),
+        };
+        let status = start_line("res-hdr").and_then(|l| l.split(' ').nth(1));
+
+        let mut subject = Subject {
+            request,
+            service,
+            request_line,
+            method,
+            target,
+            status,
+            host: None,
+        };
+        let host = header_str(subject.req_hdr(), "host")
+            .or_else(|| authority(target?))
+            .map(strip_port)
+            .map(normalize_host);
+        subject.host = host;
+        subject
+    }
+
+    /// Start line of the HTTP request
+    pub fn request_line(&self) -> Option<&'a str> {
+        self.request_line
+    }
+
+    fn req_hdr(&self) -> Option<&'a HeaderMap> {
+        self.request.encapsulated.as_ref()?.req_hdr.as_ref()
+    }
+
+    fn res_hdr(&self) -> Option<&'a HeaderMap> {
+        self.request.encapsulated.as_ref()?.res_hdr.as_ref()
+    }
+
+    /// Path and query of the request target
+    fn origin_form(&self) -> Option<&'a str> {
+        let target = self.target?;
+        match target.split_once("://") {
+            Some((_, rest)) => Some(rest.find('/').map_or("/", |n| &rest[n..])),
+            None => Some(target),
+        }
+    }
+
+    /// Value of the field, if it is set
+    pub fn value(&self, field: &Field) -> Option<Cow<'a, str>> {
+        let value = match field {
+            Field::IcapMethod => Cow::Owned(self.request.method.to_string()),
+            Field::Service => Cow::Borrowed(self.service),
+            Field::Method => Cow::Borrowed(self.method?),
+            Field::Url => {
+                let target = self.target?;
+                if target.contains("://") || self.method == Some("CONNECT") {
+                    Cow::Borrowed(target)
+                } else {
+                    Cow::Owned(format!("http://{}{}", self.host.as_deref()?, target))
+                }
+            }
+            Field::Host => Cow::Owned(self.host.clone()?),
+            Field::Path => {
+                let path = self.origin_form()?;
+                Cow::Borrowed(path.split_once('?').map_or(path, |(p, _)| p))
+            }
+            Field::Query => Cow::Borrowed(self.origin_form()?.split_once('?')?.1),
+            Field::Status => Cow::Borrowed(self.status?),
+            Field::ContentType => {
+                let headers = match self.request.method {
+                    IcapMethod::Respmod => self.res_hdr(),
+                    _ => self.req_hdr(),
+                };
+                let value = header_str(headers, "content-type")?;
+                let mime = value.split(';').next().unwrap_or_default().trim();
+                Cow::Owned(mime.to_ascii_lowercase())
+            }
+            Field::BodySize => {
+                let encapsulated = self.request.encapsulated.as_ref()?;
+                let body = match self.request.method {
+                    IcapMethod::Respmod => encapsulated.res_body.as_ref(),
+                    _ => encapsulated.req_body.as_ref(),
+                };
+                Cow::Owned(body.map_or(0, |b| b.len()).to_string())
+            }
+            Field::ClientIp => {
+                Cow::Borrowed(header_str(Some(&self.request.headers), HEADER_CLIENT_IP)?)
+            }
+            Field::User => Cow::Borrowed(header_str(
+                Some(&self.request.headers),
+                HEADER_CLIENT_USERNAME,
+            )?),
+            Field::Header(name) => Cow::Borrowed(header_str(self.req_hdr(), name)?),
+            Field::ResHeader(name) => Cow::Borrowed(header_str(self.res_hdr(), name)?),
+            Field::IcapHeader(name) => {
+                Cow::Borrowed(header_str(Some(&self.request.headers), name)?)
+            }
+        };
+        Some(value)
+    }
+}
+
+fn header_str<'a>(headers: Option<&'a HeaderMap>, name: &str) -> Option<&'a str> {
+    headers?.get(name)?.to_str().ok().map(|v| v.trim())
+}
+
+fn authority(target: &str) -> Option<&str> {
+    match target.split_once("://") {
+        Some((_, rest)) => rest.split('/').next(),
+        // CONNECT
+        None if !target.starts_with('/') => Some(target),
+        None => None,
+    }
+}
+
+fn strip_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((name, port)) if !name.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => {
+            // the colons of an IPv6 address without brackets
+            if name.contains(':') && !name.ends_with(']') {
+                return host;
+            }
+            name
+        }
+        _ => host,
+    }
+}
+
+fn encapsulated_sections(request: &IcapRequest) -> Vec<(String, usize)> {
+    let Some(value) = header_str(Some(&request.headers), "encapsulated") else {
+        return Vec::new();
+    };
+    value
+        .split(',')
+        .filter_map(|entry| {
+            let (name, offset) = entry.split_once('=')?;
+            Some((
+                name.trim().to_ascii_lowercase(),
+                offset.trim().parse().ok()?,
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use http::Version;
+
+    use crate::protocol::common::EncapsulatedData;
+
+    fn reqmod(http_header: &str) -> IcapRequest {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "encapsulated",
+            format!("req-hdr=0, null-body={}", http_header.len())
+                .parse()
+                .unwrap(),
+        );
+        headers.insert(HEADER_CLIENT_IP, "10.0.0.7".parse().unwrap());
+        let mut req_hdr = HeaderMap::new();
+        for line in http_header.trim_end().lines().skip(1) {
+            let (name, value) = line.split_once(':').unwrap();
+            req_hdr.append(
+                http::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                value.trim().parse().unwrap(),
+            );
+        }
+        IcapRequest {
+            method: IcapMethod::Reqmod,
+            uri: "icap://icap.example.net/blocker".parse().unwrap(),
+            version: Version::HTTP_11,
+            headers,
+            body: Bytes::from(http_header.to_string()),
+            encapsulated: Some(EncapsulatedData {
+                req_hdr: Some(req_hdr),
+                req_body: None,
+                res_hdr: None,
+                res_body: None,
+                null_body: true,
+                trailers: None,
+                ieof: false,
+                req_line: None,
+                status_line: None,
+            }),
+        }
+    }
+
+    fn eval(expr: &str, request: &IcapRequest) -> bool {
+        Expr::parse(expr)
+            .unwrap()
+            .eval(&Subject::new(request, "blocker"))
+    }
+
+    #[test]
+    fn fields() {
+        let request = reqmod(
+            "GET /download/setup.exe?v=2 HTTP/1.1\r\nHost: Files.Example.NET:8080\r\nUser-Agent: curl/8.5.0\r\n\r\n",
+        );
+        let subject = Subject::new(&request, "blocker");
+        let value = |name: &str| subject.value(&Field::parse(name).unwrap());
+        assert_eq!(value("icap.method").unwrap(), "REQMOD");
+        assert_eq!(value("service").unwrap(), "blocker");
+        assert_eq!(value("method").unwrap(), "GET");
+        assert_eq!(value("host").unwrap(), "files.example.net");
+        assert_eq!(
+            value("url").unwrap(),
+            "http://files.example.net/download/setup.exe?v=2"
+        );
+        assert_eq!(value("path").unwrap(), "/download/setup.exe");
+        assert_eq!(value("query").unwrap(), "v=2");
+        assert_eq!(value("header.User-Agent").unwrap(), "curl/8.5.0");
+        assert_eq!(value("client_ip").unwrap(), "10.0.0.7");
+        assert_eq!(value("body_size").unwrap(), "0");
+        assert!(value("status").is_none());
+        assert!(value("user").is_none());
+        assert!(Field::parse("header.bad header").is_none());
+        assert!(Field::parse("unknown").is_none());
+
+        let request = reqmod("GET http://[::1]:8080/a HTTP/1.1\r\n\r\n");
+        let subject = Subject::new(&request, "blocker");
+        assert_eq!(subject.value(&Field::Host).unwrap(), "[::1]");
+        assert_eq!(subject.value(&Field::Path).unwrap(), "/a");
+    }
+
+    #[test]
+    fn evaluation() {
+        let request = reqmod(
+            "POST /upload HTTP/1.1\r\nHost: files.example.net\r\nContent-Type: Text/Plain; charset=utf-8\r\nContent-Length: 42\r\n\r\n",
+        );
+        assert!(eval(
+            r#"host in ["a.example", "files.example.net"]"#,
+            &request
+        ));
+        assert!(eval(
+            r#"method == "POST" && path starts_with "/up""#,
+            &request
+        ));
+        assert!(eval(r#"content_type == 'text/plain'"#, &request));
+        assert!(eval(
+            "header.content-length >= 42 and not (header.content-length > 42)",
+            &request
+        ));
+        assert!(eval(
+            r#"url matches "^http://files\\.example\\.net/" || false"#,
+            &request
+        ));
+        assert!(eval(r#"url ends_with "/upload" && !status"#, &request));
+        assert!(eval("header.host", &request));
+        assert!(!eval("header.cookie", &request));
+        // a field that is not set is only different
+        assert!(!eval(r#"status == "200""#, &request));
+        assert!(eval(r#"user != "alice""#, &request));
+        assert!(eval("status != 200", &request));
+        // && binds tighter than ||
+        assert!(eval("true || false && false", &request));
+        assert!(!eval("(true || false) && false", &request));
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = |s: &str| Expr::parse(s).unwrap_err();
+        assert_eq!(error("hots == \"a\"").message, "unknown field hots");
+        assert_eq!(error("host == ").message, "unexpected end of expression");
+        assert_eq!(error("host in \"a\"").offset, 8);
+        assert_eq!(error("status > \"a\"").message, "expected a number");
+        assert!(
+            error("url matches \"(\"")
+                .message
+                .starts_with("invalid regex")
+        );
+        assert_eq!(error("(host").message, "unclosed parenthesis");
+        assert_eq!(error("host == \"a").message, "unterminated string");
+        assert_eq!(error("host host").message, "unexpected token");
+        assert_eq!(error("host # 1").message, "unexpected character");
+    }
+}
@@ -0,0 +1,409 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Expression module
+//!
+//! The module of a scripted service, compiled from the rules of its config.
+//! The first rule whose decision expression matches the transaction decides,
+//! a block rule answers with its page and a redirect rule with its location,
+//! both carrying the rule name as rule id. A transaction matching no rule, or
+//! an allow rule, is allowed unmodified.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use http::{HeaderName, HeaderValue, StatusCode};
+
+use crate::config::server::scripted_services::{
+    ScriptAction, ScriptRuleConfig, ScriptedServiceConfig,
+};
+use crate::modules::block_page::{render_text, render_with};
+use crate::modules::content_filter::HEADER_RULE_ID;
+use crate::modules::rule_hits;
+use crate::modules::{IcapModule, ModuleConfig, ModuleError, ModuleMetrics};
+use crate::protocol::common::{IcapMethod, IcapRequest, IcapResponse};
+use crate::protocol::response_generator::IcapResponseGenerator;
+use crate::transaction::TransactionCtx;
+
+pub mod dsl;
+
+use dsl::{Expr, Field, Subject};
+
+/// Page of the block rules without a template
+const DEFAULT_TEMPLATE: &str = "<!DOCTYPE html>\n\
+<html>\n\
+<head><meta charset=\"utf-8\"><title>Access blocked</title></head>\n\
+<body><h1>Access blocked</h1><p>Access to <b>{url}</b> has been blocked by rule {rule}.</p></body>\n\
+</html>\n";
+
+/// Page of the redirect rules, for clients not following the Location
+const REDIRECT_TEMPLATE: &str = "<!DOCTYPE html>\n\
+<html><body><p>Moved to <a href=\"{location}\">{location}</a>.</p></body></html>\n";
+
+#[derive(Debug)]
+enum Action {
+    Allow,
+    Block {
+        status: StatusCode,
+        template: String,
+    },
+    Redirect {
+        status: StatusCode,
+        location: String,
+    },
+}
+
+#[derive(Debug)]
+struct Rule {
+    name: String,
+    when: Expr,
+    action: Action,
+    headers: Vec<(HeaderName, String)>,
+}
+
+impl Rule {
+    fn compile(config: &ScriptRuleConfig) -> Result<Self, ModuleError> {
+        let invalid =
+            |e: String| ModuleError::InitFailed(format!("invalid rule {}: {e}", config.name));
+        let when = Expr::parse(&config.when).map_err(|e| invalid(e.to_string()))?;
+        let status = |default| {
+            StatusCode::from_u16(config.status.unwrap_or(default))
+                .map_err(|e| invalid(e.to_string()))
+        };
+        let action = match config.action {
+            ScriptAction::Allow => Action::Allow,
+            ScriptAction::Block => Action::Block {
+                status: status(403)?,
+                template: config
+                    .template
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_TEMPLATE.to_string()),
+            },
+            ScriptAction::Redirect => Action::Redirect {
+                status: status(302)?,
+                location: config.location.clone().unwrap_or_default(),
+            },
+        };
+        let headers = config
+            .headers
+            .iter()
+            .map(|(name, value)| {
+                let name = HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|e| invalid(format!("invalid header {name}: {e}")))?;
+                Ok((name, value.clone()))
+            })
+            .collect::<Result<_, ModuleError>>()?;
+        Ok(Rule {
+            name: config.name.clone(),
+            when,
+            action,
+            headers,
+        })
+    }
+}
+
+/// Module of a scripted service
+pub struct ExpressionModule {
+    name: String,
+    version: String,
+    service: String,
+    methods: Vec<IcapMethod>,
+    rules: Vec<Rule>,
+    generator: IcapResponseGenerator,
+    metrics: Mutex<ModuleMetrics>,
+}
+
+impl ExpressionModule {
+    /// Compile the rules of the service
+    pub fn new(service: &str, config: &ScriptedServiceConfig) -> Result<Self, ModuleError> {
+        let mut methods = vec![IcapMethod::Options];
+        if config.reqmod {
+            methods.push(IcapMethod::Reqmod);
+        }
+        if config.respmod {
+            methods.push(IcapMethod::Respmod);
+        }
+        let rules = config
+            .rules
+            .iter()
+            .map(Rule::compile)
+            .collect::<Result<_, _>>()?;
+        let version = "1.0.0".to_string();
+        Ok(ExpressionModule {
+            name: format!("expression/{service}"),
+            generator: IcapResponseGenerator::with_service_id(
+                format!("G3ICAP-Expression/{version}"),
+                format!("expression-{version}"),
+                Some(service.to_string()),
+            ),
+            version,
+            service: service.to_string(),
+            methods,
+            rules,
+            metrics: Mutex::new(ModuleMetrics::default()),
+        })
+    }
+
+    /// Name of the first rule matching the transaction, and its response
+    fn decide(&self, request: &IcapRequest) -> (Option<&str>, IcapResponse) {
+        let subject = Subject::new(request, &self.service);
+        let Some(rule) = self.rules.iter().find(|r| r.when.eval(&subject)) else {
+            return (None, self.generator.no_modifications(None));
+        };
+        rule_hits::global().record(&self.name, &rule.name);
+
+        let var = |name: &str| match name {
+            "rule" => Some(rule.name.clone()),
+            _ => subject.value(&Field::parse(name)?).map(|v| v.into_owned()),
+        };
+        let mut headers: Vec<(HeaderName, HeaderValue)> = rule
+            .headers
+            .iter()
+            .filter_map(|(name, value)| {
+                let value = HeaderValue::from_str(&render_text(value, var)).ok()?;
+                Some((name.clone(), value))
+            })
+            .collect();
+        let mut response = match &rule.action {
+            Action::Allow => return (Some(&rule.name), self.generator.no_modifications(None)),
+            Action::Block { status, template } => {
+                let page = render_with(template, var);
+                self.generator
+                    .http_page_response_with_headers(*status, &page, &headers)
+            }
+            Action::Redirect { status, location } => {
+                let location = render_text(location, var);
+                let page = render_with(REDIRECT_TEMPLATE, |name| {
+                    (name == "location").then(|| location.clone())
+                });
+                match HeaderValue::from_str(&location) {
+                    Ok(value) => headers.push((http::header::LOCATION, value)),
+                    Err(_) => {
+                        log::warn!("invalid location of rule {}: {location}", rule.name);
+                        return (Some(&rule.name), self.generator.no_modifications(None));
+                    }
+                }
+                self.generator
+                    .http_page_response_with_headers(*status, &page, &headers)
+            }
+        };
+        if let Ok(value) = HeaderValue::from_str(&rule.name) {
+            response.headers.insert(HEADER_RULE_ID, value);
+        }
+        (Some(&rule.name), response)
+    }
+
+    fn handle(&self, request: &IcapRequest) -> IcapResponse {
+        let start = Instant::now();
+        let (rule, response) = self.decide(request);
+        if let Some(rule) = rule {
+            log::debug!(
+                "scripted service {} rule {rule} matched {}",
+                self.service,
+                request.uri
+            );
+        }
+        self.update_metrics(start.elapsed());
+        response
+    }
+
+    fn update_metrics(&self, elapsed: Duration) {
+        let mut metrics = self.metrics.lock().unwrap();
+        metrics.requests_total += 1;
+        let total = u32::try_from(metrics.requests_total).unwrap_or(u32::MAX);
+        metrics.average_response_time =
+            (metrics.average_response_time * (total - 1) + elapsed) / total;
+        metrics.last_activity = Some(Instant::now());
+    }
+}
+
+#[async_trait]
+impl IcapModule for ExpressionModule {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn version(&self) -> &str {
+        &self.version
+    }
+
+    fn supported_methods(&self) -> Vec<IcapMethod> {
+        self.methods.clone()
+    }
+
+    async fn init(&mut self, _config: &ModuleConfig) -> Result<(), ModuleError> {
+        rule_hits::global().register(&self.name, self.rules.iter().map(|r| r.name.clone()));
+        Ok(())
+    }
+
+    async fn handle_reqmod(
+        &self,
+        request: &IcapRequest,
+        _ctx: &TransactionCtx,
+    ) -> Result<IcapResponse, ModuleError> {
+        Ok(self.handle(request))
+    }
+
+    async fn handle_respmod(
+        &self,
+        request: &IcapRequest,
+        _ctx: &TransactionCtx,
+    ) -> Result<IcapResponse, ModuleError> {
+        Ok(self.handle(request))
+    }
+
+    async fn handle_options(&self, _request: &IcapRequest) -> Result<IcapResponse, ModuleError> {
+        Ok(self
+            .generator
+            .options_response(&self.methods, Default::default()))
+    }
+
+    fn is_healthy(&self) -> bool {
+        true
+    }
+
+    fn get_metrics(&self) -> ModuleMetrics {
+        self.metrics.lock().unwrap().clone()
+    }
+
+    async fn cleanup(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use http::{HeaderMap, Version};
+
+    use crate::protocol::common::EncapsulatedData;
+
+    fn service(yaml: &str) -> ExpressionModule {
+        let yaml = yaml_rust::YamlLoader::load_from_str(yaml)
+            .unwrap()
+            .remove(0);
+        let config =
+            crate::config::server::scripted_services::ScriptedServicesConfig::parse(&yaml).unwrap();
+        ExpressionModule::new("blocker", &config.services["blocker"]).unwrap()
+    }
+
+    fn reqmod(host: &str, path: &str) -> IcapRequest {
+        let http_header = format!("GET {path} HTTP/1.1\r\nHost: {host}\r\n\r\n");
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "encapsulated",
+            format!("req-hdr=0, null-body={}", http_header.len())
+                .parse()
+                .unwrap(),
+        );
+        let mut req_hdr = HeaderMap::new();
+        req_hdr.insert(http::header::HOST, host.parse().unwrap());
+        IcapRequest {
+            method: IcapMethod::Reqmod,
+            uri: "icap://icap.example.net/blocker".parse().unwrap(),
+            version: Version::HTTP_11,
+            headers,
+            body: Bytes::from(http_header),
+            encapsulated: Some(EncapsulatedData {
+                req_hdr: Some(req_hdr),
+                req_body: None,
+                res_hdr: None,
+                res_body: None,
+                null_body: true,
+                trailers: None,
+                ieof: false,
+                req_line: None,
+                status_line: None,
+            }),
+        }
+    }
+
+    const CONFIG: &str = r#"
+blocker:
+  methods: [reqmod]
+  rules:
+    - name: trusted
+      when: 'host == "intranet.example.net"'
+      action: allow
+    - name: bad-urls
+      when: 'host in ["a.example", "b.example"] || path starts_with "/malware/"'
+      template: "<p>{url} blocked by {rule}</p>"
+      headers:
+        X-Blocked-By: "g3icap {rule}"
+    - name: moved
+      when: 'host == "old.example.net"'
+      action: redirect
+      location: "https://new.example.net{path}"
+"#;
+
+    #[test]
+    fn decisions() {
+        let module = service(CONFIG);
+        assert_eq!(
+            module.supported_methods(),
+            vec![IcapMethod::Options, IcapMethod::Reqmod]
+        );
+
+        let (rule, response) = module.decide(&reqmod("a.example", "/<x>"));
+        assert_eq!(rule, Some("bad-urls"));
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.headers.get(HEADER_RULE_ID).unwrap(), "bad-urls");
+        let body = String::from_utf8_lossy(&response.body);
+        assert!(body.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+        assert!(body.contains("x-blocked-by: g3icap bad-urls\r\n"));
+        assert!(body.contains("<p>http://a.example/&lt;x&gt; blocked by bad-urls</p>"));
+
+        let (rule, response) = module.decide(&reqmod("old.example.net", "/docs/"));
+        assert_eq!(rule, Some("moved"));
+        let body = String::from_utf8_lossy(&response.body);
+        assert!(body.starts_with("HTTP/1.1 302 Found\r\n"));
+        assert!(body.contains("location: https://new.example.net/docs/\r\n"));
+
+        // allowed by a rule before the block rule, or by no rule
+        let (rule, response) = module.decide(&reqmod("intranet.example.net", "/malware/"));
+        assert_eq!(rule, Some("trusted"));
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        let (rule, response) = module.decide(&reqmod("example.net", "/"));
+        assert_eq!(rule, None);
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        assert!(!response.headers.contains_key(HEADER_RULE_ID));
+    }
+
+    #[test]
+    fn invalid_rules() {
+        let parse = |s: &str| {
+            let yaml = yaml_rust::YamlLoader::load_from_str(s).unwrap().remove(0);
+            crate::config::server::scripted_services::ScriptedServicesConfig::parse(&yaml)
+                .unwrap_err()
+                .to_string()
+        };
+        // the expressions are checked when the config is loaded
+        assert_eq!(
+            parse("blocker: [{ when: 'hots == \"a\"' }]"),
+            "invalid scripted service blocker: invalid rule #0: \
+             invalid condition of rule rule0: unknown field hots at offset 0"
+        );
+        assert_eq!(
+            parse("blocker: [{ when: 'true', action: redirect }]"),
+            "invalid scripted service blocker: invalid rule #0: \
+             no location set for redirect rule rule0"
+        );
+
+        let mut config = ScriptedServiceConfig::default();
+        config.rules.push(ScriptRuleConfig {
+            name: "bad".to_string(),
+            when: "true".to_string(),
+            headers: vec![("bad header".to_string(), "1".to_string())],
+            ..Default::default()
+        });
+        let Err(e) = ExpressionModule::new("blocker", &config) else {
+            panic!("compiled an invalid header");
+        };
+        assert!(
+            e.to_string()
+                .starts_with("Module initialization failed: invalid rule bad: invalid header")
+        );
+    }
+}
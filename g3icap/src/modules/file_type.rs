@@ -0,0 +1,398 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! File type detection
+//!
+//! The true type of a body is detected from its leading magic bytes, so that
+//! renamed executables and bodies sent with a misleading Content-Type can be
+//! recognised. Only the formats worth a policy decision are known, a body
+//! that matches no signature is left undetected.
+
+use serde::{Deserialize, Serialize};
+
+/// Number of leading bytes looked at
+pub const SNIFF_SIZE: usize = 512;
+
+/// File type detection configuration
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FileTypeDetectionConfig {
+    /// Detect the type of the bodies and apply the MIME type, extension and
+    /// file type rules to the detected type
+    pub enabled: bool,
+    /// Block bodies whose detected type does not match their Content-Type
+    pub block_mismatch: bool,
+}
+
+/// Broad family of a file type, usable as a rule value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    Executable,
+    Script,
+    Archive,
+    Document,
+    Image,
+    Audio,
+    Video,
+}
+
+impl FileKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileKind::Executable => "executable",
+            FileKind::Script => "script",
+            FileKind::Archive => "archive",
+            FileKind::Document => "document",
+            FileKind::Image => "image",
+            FileKind::Audio => "audio",
+            FileKind::Video => "video",
+        }
+    }
+}
+
+/// A detected file type
+#[derive(Debug, PartialEq, Eq)]
+pub struct FileType {
+    /// Canonical MIME type
+    pub mime: &'static str,
+    /// Usual file extension, without the dot
+    pub extension: &'static str,
+    pub kind: FileKind,
+    /// Other MIME types used for the same content, e.g. the office formats
+    /// sharing the zip container
+    aliases: &'static [&'static str],
+}
+
+macro_rules! file_type {
+    ($name:ident, $mime:literal, $ext:literal, $kind:ident) => {
+        file_type!($name, $mime, $ext, $kind, []);
+    };
+    ($name:ident, $mime:literal, $ext:literal, $kind:ident, [$($alias:literal),*]) => {
+        const $name: FileType = FileType {
+            mime: $mime,
+            extension: $ext,
+            kind: FileKind::$kind,
+            aliases: &[$($alias),*],
+        };
+    };
+}
+
+file_type!(
+    PE,
+    "application/x-msdownload",
+    "exe",
+    Executable,
+    [
+        "application/x-dosexec",
+        "application/vnd.microsoft.portable-executable",
+        "application/x-msdos-program"
+    ]
+);
+file_type!(
+    ELF,
+    "application/x-executable",
+    "elf",
+    Executable,
+    ["application/x-elf", "application/x-sharedlib"]
+);
+file_type!(MACH_O, "application/x-mach-binary", "macho", Executable);
+file_type!(JAVA_CLASS, "application/java-vm", "class", Executable);
+file_type!(WASM, "application/wasm", "wasm", Executable);
+file_type!(
+    SHELL_SCRIPT,
+    "text/x-shellscript",
+    "sh",
+    Script,
+    [
+        "application/x-sh",
+        "text/x-script.python",
+        "text/x-python",
+        "application/x-perl"
+    ]
+);
+file_type!(
+    ZIP,
+    "application/zip",
+    "zip",
+    Archive,
+    [
+        "application/x-zip-compressed",
+        "application/java-archive",
+        "application/vnd.android.package-archive",
+        "application/epub+zip",
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "application/vnd.oasis.opendocument.text",
+        "application/vnd.oasis.opendocument.spreadsheet"
+    ]
+);
+file_type!(
+    GZIP,
+    "application/gzip",
+    "gz",
+    Archive,
+    ["application/x-gzip", "application/x-tar"]
+);
+file_type!(BZIP2, "application/x-bzip2", "bz2", Archive);
+file_type!(XZ, "application/x-xz", "xz", Archive);
+file_type!(SEVEN_ZIP, "application/x-7z-compressed", "7z", Archive);
+file_type!(
+    RAR,
+    "application/vnd.rar",
+    "rar",
+    Archive,
+    ["application/x-rar-compressed"]
+);
+file_type!(RPM, "application/x-rpm", "rpm", Archive);
+file_type!(DEB, "application/vnd.debian.binary-package", "deb", Archive);
+file_type!(CAB, "application/vnd.ms-cab-compressed", "cab", Archive);
+file_type!(PDF, "application/pdf", "pdf", Document);
+file_type!(RTF, "application/rtf", "rtf", Document, ["text/rtf"]);
+file_type!(
+    OLE2,
+    "application/x-ole-storage",
+    "doc",
+    Document,
+    [
+        "application/msword",
+        "application/vnd.ms-excel",
+        "application/vnd.ms-powerpoint",
+        "application/x-msi",
+        "application/vnd.ms-outlook"
+    ]
+);
+file_type!(PNG, "image/png", "png", Image);
+file_type!(JPEG, "image/jpeg", "jpg", Image);
+file_type!(GIF, "image/gif", "gif", Image);
+file_type!(BMP, "image/bmp", "bmp", Image);
+file_type!(WEBP, "image/webp", "webp", Image);
+file_type!(
+    ICO,
+    "image/x-icon",
+    "ico",
+    Image,
+    ["image/vnd.microsoft.icon"]
+);
+file_type!(MP3, "audio/mpeg", "mp3", Audio);
+file_type!(
+    WAV,
+    "audio/wav",
+    "wav",
+    Audio,
+    ["audio/x-wav", "audio/wave"]
+);
+file_type!(
+    OGG,
+    "audio/ogg",
+    "ogg",
+    Audio,
+    ["video/ogg", "application/ogg"]
+);
+file_type!(FLAC, "audio/flac", "flac", Audio, ["audio/x-flac"]);
+file_type!(
+    MP4,
+    "video/mp4",
+    "mp4",
+    Video,
+    ["audio/mp4", "video/quicktime", "image/heic", "image/avif"]
+);
+file_type!(AVI, "video/x-msvideo", "avi", Video);
+file_type!(
+    MATROSKA,
+    "video/x-matroska",
+    "mkv",
+    Video,
+    ["video/webm", "audio/webm"]
+);
+
+/// Signatures found at a fixed offset
+const SIGNATURES: &[(usize, &[u8], &FileType)] = &[
+    (0, b"MZ", &PE),
+    (0, b"\x7fELF", &ELF),
+    (0, b"\xfe\xed\xfa\xce", &MACH_O),
+    (0, b"\xfe\xed\xfa\xcf", &MACH_O),
+    (0, b"\xce\xfa\xed\xfe", &MACH_O),
+    (0, b"\xcf\xfa\xed\xfe", &MACH_O),
+    (0, b"\0asm", &WASM),
+    (0, b"#!", &SHELL_SCRIPT),
+    (0, b"PK\x03\x04", &ZIP),
+    (0, b"PK\x05\x06", &ZIP),
+    (0, b"\x1f\x8b", &GZIP),
+    (0, b"BZh", &BZIP2),
+    (0, b"\xfd7zXZ\0", &XZ),
+    (0, b"7z\xbc\xaf\x27\x1c", &SEVEN_ZIP),
+    (0, b"Rar!\x1a\x07", &RAR),
+    (0, b"\xed\xab\xee\xdb", &RPM),
+    (0, b"!<arch>\ndebian", &DEB),
+    (0, b"MSCF", &CAB),
+    (0, b"%PDF-", &PDF),
+    (0, b"{\\rtf", &RTF),
+    (0, b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1", &OLE2),
+    (0, b"\x89PNG\r\n\x1a\n", &PNG),
+    (0, b"\xff\xd8\xff", &JPEG),
+    (0, b"GIF87a", &GIF),
+    (0, b"GIF89a", &GIF),
+    (0, b"BM", &BMP),
+    (0, b"\0\0\x01\0", &ICO),
+    (0, b"ID3", &MP3),
+    (0, b"OggS", &OGG),
+    (0, b"fLaC", &FLAC),
+    (0, b"\x1a\x45\xdf\xa3", &MATROSKA),
+    (4, b"ftyp", &MP4),
+];
+
+/// RIFF containers, told apart by their form type
+const RIFF_FORMS: &[(&[u8], &FileType)] = &[(b"WEBP", &WEBP), (b"WAVE", &WAV), (b"AVI ", &AVI)];
+
+/// Detect the type of a body from its leading bytes
+pub fn detect(data: &[u8]) -> Option<&'static FileType> {
+    let data = &data[..data.len().min(SNIFF_SIZE)];
+
+    // java class files and mach-o fat binaries share their magic, class
+    // files have a major version of at least 45 where fat binaries have a
+    // small architecture count
+    if data.starts_with(b"\xca\xfe\xba\xbe") && data.len() >= 8 {
+        let major = u16::from_be_bytes([data[6], data[7]]);
+        return Some(if major >= 45 { &JAVA_CLASS } else { &MACH_O });
+    }
+    if data.starts_with(b"RIFF") && data.len() >= 12 {
+        return RIFF_FORMS
+            .iter()
+            .find(|(form, _)| &data[8..12] == *form)
+            .map(|(_, t)| *t);
+    }
+    // MPEG audio frame sync, without an ID3 tag
+    if data.len() >= 2 && data[0] == 0xff && matches!(data[1], 0xfb | 0xf3 | 0xf2) {
+        return Some(&MP3);
+    }
+
+    SIGNATURES
+        .iter()
+        .find(|(offset, magic, _)| data.get(*offset..).is_some_and(|d| d.starts_with(magic)))
+        .map(|(_, _, t)| *t)
+}
+
+impl FileType {
+    /// Check if a rule value names this type
+    ///
+    /// The value may be a kind (`executable`), a MIME type or an extension
+    /// with or without its leading dot.
+    pub fn matches(&self, value: &str) -> bool {
+        let value = value.trim();
+        value.eq_ignore_ascii_case(self.kind.as_str())
+            || self.has_mime(value)
+            || value
+                .trim_start_matches('.')
+                .eq_ignore_ascii_case(self.extension)
+    }
+
+    /// Check if the MIME type is this type or one of its aliases
+    pub fn has_mime(&self, mime: &str) -> bool {
+        mime.eq_ignore_ascii_case(self.mime)
+            || self.aliases.iter().any(|a| mime.eq_ignore_ascii_case(a))
+    }
+
+    /// Check if a declared Content-Type is compatible with this type
+    ///
+    /// Generic binary types are compatible with every type, text types only
+    /// with scripts and RTF documents.
+    pub fn is_declared_as(&self, content_type: &str) -> bool {
+        let mime = content_type.split(';').next().unwrap_or_default().trim();
+        if mime.is_empty()
+            || mime.eq_ignore_ascii_case("application/octet-stream")
+            || mime.eq_ignore_ascii_case("binary/octet-stream")
+            || mime.eq_ignore_ascii_case("application/x-download")
+            || mime.eq_ignore_ascii_case("application/force-download")
+        {
+            return true;
+        }
+        if self.has_mime(mime) {
+            return true;
+        }
+        let Some((top, _)) = mime.split_once('/') else {
+            return false;
+        };
+        match self.kind {
+            FileKind::Script => top.eq_ignore_ascii_case("text"),
+            FileKind::Image | FileKind::Audio | FileKind::Video => {
+                // only the family is checked, the subtypes of media are used loosely
+                self.mime
+                    .split('/')
+                    .next()
+                    .is_some_and(|t| t.eq_ignore_ascii_case(top))
+            }
+            _ => self == &RTF && top.eq_ignore_ascii_case("text"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_signatures() {
+        let pe = detect(b"MZ\x90\0\x03\0\0\0").unwrap();
+        assert_eq!(pe.mime, "application/x-msdownload");
+        assert_eq!(pe.kind, FileKind::Executable);
+        assert_eq!(detect(b"\x7fELF\x02\x01\x01").unwrap().extension, "elf");
+        assert_eq!(detect(b"#!/bin/sh\necho").unwrap().kind, FileKind::Script);
+        assert_eq!(detect(b"PK\x03\x04\x14\0").unwrap().extension, "zip");
+        assert_eq!(detect(b"%PDF-1.7\n").unwrap().mime, "application/pdf");
+        assert_eq!(
+            detect(b"\x89PNG\r\n\x1a\n\0\0").unwrap().kind,
+            FileKind::Image
+        );
+        assert_eq!(detect(b"RIFF\0\0\0\0WEBPVP8 ").unwrap().mime, "image/webp");
+        assert_eq!(detect(b"RIFF\0\0\0\0WAVEfmt ").unwrap().mime, "audio/wav");
+        assert_eq!(detect(b"\0\0\0\x18ftypmp42").unwrap().mime, "video/mp4");
+        assert_eq!(
+            detect(b"\xca\xfe\xba\xbe\0\0\0\x34").unwrap().extension,
+            "class"
+        );
+        assert_eq!(
+            detect(b"\xca\xfe\xba\xbe\0\0\0\x02").unwrap().extension,
+            "macho"
+        );
+        assert!(detect(b"<html><body>hello</body></html>").is_none());
+        assert!(detect(b"").is_none());
+        assert!(detect(b"RIFF\0\0\0\0XXXX").is_none());
+    }
+
+    #[test]
+    fn match_rule_values() {
+        let pe = detect(b"MZ\x90\0").unwrap();
+        assert!(pe.matches("executable"));
+        assert!(pe.matches(".EXE"));
+        assert!(pe.matches("application/x-dosexec"));
+        assert!(!pe.matches("archive"));
+
+        let zip = detect(b"PK\x03\x04").unwrap();
+        assert!(zip.matches("application/java-archive"));
+    }
+
+    #[test]
+    fn declared_type() {
+        let pe = detect(b"MZ\x90\0").unwrap();
+        assert!(pe.is_declared_as("application/octet-stream"));
+        assert!(pe.is_declared_as("application/x-msdownload; charset=binary"));
+        assert!(!pe.is_declared_as("image/png"));
+        assert!(!pe.is_declared_as("text/plain"));
+
+        let zip = detect(b"PK\x03\x04").unwrap();
+        assert!(zip.is_declared_as(
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+        ));
+        assert!(!zip.is_declared_as("application/pdf"));
+
+        let png = detect(b"\x89PNG\r\n\x1a\n").unwrap();
+        assert!(png.is_declared_as("image/jpeg"));
+        assert!(!png.is_declared_as("text/html"));
+
+        let script = detect(b"#!/usr/bin/env python3").unwrap();
+        assert!(script.is_declared_as("text/plain"));
+    }
+}
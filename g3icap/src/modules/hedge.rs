@@ -0,0 +1,300 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Hedged requests to redundant backends
+//!
+//! A scan is sent to the primary backend first. If the primary has not
+//! answered within a delay taken at a percentile of its recent latencies, a
+//! duplicate scan is sent to the secondary backend and the first verdict
+//! wins, the other scan being cancelled. A backend failing is no verdict,
+//! the other one is waited for then. Hedges are capped to a ratio of the
+//! scans, so that a slow primary does not double the load of the backends,
+//! and the work wasted on the cancelled scans is counted.
+
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use super::ModuleError;
+use super::metrics::{Counter, MetricsRegistry};
+
+/// Hedging configuration
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HedgeConfig {
+    /// Percentile of the primary latencies the hedging delay is taken at
+    pub percentile: f64,
+    /// Lower bound of the hedging delay
+    pub min_delay: Duration,
+    /// Upper bound of the hedging delay, used until enough latencies are known
+    pub max_delay: Duration,
+    /// Maximum ratio of the scans that are hedged
+    pub max_ratio: f64,
+    /// Recent primary latencies the percentile is taken over
+    pub window: usize,
+    /// Latencies needed before the percentile is used
+    pub min_samples: usize,
+}
+
+impl Default for HedgeConfig {
+    fn default() -> Self {
+        HedgeConfig {
+            percentile: 0.95,
+            min_delay: Duration::from_millis(5),
+            max_delay: Duration::from_secs(1),
+            max_ratio: 0.1,
+            window: 1000,
+            min_samples: 20,
+        }
+    }
+}
+
+/// Hedging counters, registered under `hedge.`
+struct HedgeMetrics {
+    /// Scans sent to the primary
+    requests: Arc<Counter>,
+    /// Scans duplicated to the secondary
+    hedged: Arc<Counter>,
+    /// Hedged scans answered first by the secondary
+    secondary_wins: Arc<Counter>,
+    /// Hedges not sent as over the maximum ratio
+    capped: Arc<Counter>,
+    /// Scans cancelled as the other backend answered first
+    wasted: Arc<Counter>,
+    /// Time spent in the cancelled scans
+    wasted_ms: Arc<Counter>,
+}
+
+impl HedgeMetrics {
+    fn register(registry: &MetricsRegistry) -> Result<Self, ModuleError> {
+        Ok(HedgeMetrics {
+            requests: registry.counter("hedge.requests")?,
+            hedged: registry.counter("hedge.hedged")?,
+            secondary_wins: registry.counter("hedge.secondary_wins")?,
+            capped: registry.counter("hedge.capped")?,
+            wasted: registry.counter("hedge.wasted")?,
+            wasted_ms: registry.counter("hedge.wasted_ms")?,
+        })
+    }
+
+    fn waste(&self, time: Duration) {
+        self.wasted.inc();
+        self.wasted_ms.add(time.as_millis() as u64);
+    }
+}
+
+struct HedgeState {
+    /// Recent primary latencies in microseconds, as a ring
+    latencies: Vec<u64>,
+    next: usize,
+    /// Hedges that can be sent, earned by each scan
+    budget: f64,
+}
+
+/// Hedges the scans of a primary backend with a secondary one
+pub struct Hedger {
+    config: HedgeConfig,
+    state: Mutex<HedgeState>,
+    metrics: HedgeMetrics,
+}
+
+impl Hedger {
+    /// Create the hedger, with its counters in the registry
+    pub fn new(config: HedgeConfig, registry: &MetricsRegistry) -> Result<Self, ModuleError> {
+        if !(0.0..=1.0).contains(&config.percentile) || !(0.0..=1.0).contains(&config.max_ratio) {
+            return Err(ModuleError::InitFailed(
+                "hedging percentile and max_ratio should be between 0 and 1".to_string(),
+            ));
+        }
+        if config.window == 0 || config.min_delay > config.max_delay {
+            return Err(ModuleError::InitFailed(
+                "hedging window should not be 0 and min_delay not over max_delay".to_string(),
+            ));
+        }
+        Ok(Hedger {
+            config,
+            state: Mutex::new(HedgeState {
+                latencies: Vec::with_capacity(config.window),
+                next: 0,
+                budget: 1.0,
+            }),
+            metrics: HedgeMetrics::register(registry)?,
+        })
+    }
+
+    /// Delay after which a scan is hedged
+    pub fn delay(&self) -> Duration {
+        let state = self.state.lock().unwrap();
+        if state.latencies.len() < self.config.min_samples.max(1) {
+            return self.config.max_delay;
+        }
+        let mut latencies = state.latencies.clone();
+        drop(state);
+        let index = ((latencies.len() as f64 * self.config.percentile).ceil() as usize)
+            .clamp(1, latencies.len())
+            - 1;
+        let delay = Duration::from_micros(*latencies.select_nth_unstable(index).1);
+        delay.clamp(self.config.min_delay, self.config.max_delay)
+    }
+
+    /// Record a latency of the primary, a cancelled scan giving a lower bound
+    fn record(&self, latency: Duration) {
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        let mut state = self.state.lock().unwrap();
+        let next = state.next;
+        if state.latencies.len() < self.config.window {
+            state.latencies.push(micros);
+        } else {
+            state.latencies[next] = micros;
+        }
+        state.next = (next + 1) % self.config.window;
+    }
+
+    /// Earn the share of a hedge of a scan
+    fn earn(&self) {
+        let mut state = self.state.lock().unwrap();
+        // a burst of hedges is allowed after a calm period
+        let burst = (self.config.max_ratio * 100.0).max(1.0);
+        state.budget = (state.budget + self.config.max_ratio).min(burst);
+    }
+
+    /// Spend a hedge, if the ratio allows it
+    fn spend(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.budget < 1.0 {
+            return false;
+        }
+        state.budget -= 1.0;
+        true
+    }
+
+    /// Run the scan of the primary, hedged by the one of the secondary
+    ///
+    /// The futures are lazy, the secondary scan is only started if the scan
+    /// is hedged. The scan losing the race is cancelled by being dropped.
+    pub async fn run<T, E, P, S>(&self, primary: P, secondary: S) -> Result<T, E>
+    where
+        P: Future<Output = Result<T, E>>,
+        S: Future<Output = Result<T, E>>,
+    {
+        let started = Instant::now();
+        self.metrics.requests.inc();
+        self.earn();
+        tokio::pin!(primary);
+        if let Ok(result) = tokio::time::timeout(self.delay(), &mut primary).await {
+            self.record(started.elapsed());
+            return result;
+        }
+        if !self.spend() {
+            self.metrics.capped.inc();
+            let result = primary.await;
+            self.record(started.elapsed());
+            return result;
+        }
+
+        self.metrics.hedged.inc();
+        let hedged = Instant::now();
+        tokio::pin!(secondary);
+        tokio::select! {
+            result = &mut primary => {
+                self.record(started.elapsed());
+                match result {
+                    Ok(verdict) => {
+                        self.metrics.waste(hedged.elapsed());
+                        Ok(verdict)
+                    }
+                    Err(_) => secondary.await,
+                }
+            }
+            result = &mut secondary => match result {
+                Ok(verdict) => {
+                    self.metrics.secondary_wins.inc();
+                    self.record(started.elapsed());
+                    self.metrics.waste(started.elapsed());
+                    Ok(verdict)
+                }
+                Err(_) => {
+                    let result = primary.await;
+                    self.record(started.elapsed());
+                    result
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hedger(config: HedgeConfig) -> (Hedger, MetricsRegistry) {
+        let registry = MetricsRegistry::detached().module("test");
+        (Hedger::new(config, &registry).unwrap(), registry)
+    }
+
+    async fn answer(delay: u64, verdict: Result<&'static str, ()>) -> Result<&'static str, ()> {
+        tokio::time::sleep(Duration::from_millis(delay)).await;
+        verdict
+    }
+
+    #[test]
+    fn delay() {
+        let (hedger, _) = hedger(HedgeConfig {
+            window: 100,
+            min_samples: 10,
+            ..Default::default()
+        });
+        assert_eq!(hedger.delay(), Duration::from_secs(1));
+        for i in 1..=100 {
+            hedger.record(Duration::from_millis(i));
+        }
+        assert_eq!(hedger.delay(), Duration::from_millis(95));
+        // the older latencies are forgotten
+        for _ in 0..100 {
+            hedger.record(Duration::ZERO);
+        }
+        assert_eq!(hedger.delay(), Duration::from_millis(5));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn hedging() {
+        let (hedger, registry) = hedger(HedgeConfig {
+            max_delay: Duration::from_millis(50),
+            max_ratio: 0.5,
+            ..Default::default()
+        });
+        let counter = |name: &str| registry.counter(name).unwrap().get();
+
+        // answered before the delay
+        let verdict = hedger.run(answer(10, Ok("primary")), answer(0, Ok("secondary")));
+        assert_eq!(verdict.await, Ok("primary"));
+        assert_eq!(counter("hedge.hedged"), 0);
+
+        // the secondary answers first
+        let verdict = hedger.run(answer(500, Ok("primary")), answer(10, Ok("secondary")));
+        assert_eq!(verdict.await, Ok("secondary"));
+        assert_eq!(counter("hedge.secondary_wins"), 1);
+        assert_eq!(counter("hedge.wasted"), 1);
+        assert_eq!(counter("hedge.wasted_ms"), 60);
+
+        // a failure is no verdict
+        let verdict = hedger.run(answer(80, Ok("primary")), answer(10, Err(())));
+        assert_eq!(verdict.await, Ok("primary"));
+        assert_eq!(counter("hedge.hedged"), 2);
+        assert_eq!(counter("hedge.wasted"), 1);
+
+        // over the ratio
+        let verdict = hedger.run(answer(500, Ok("primary")), answer(10, Ok("secondary")));
+        assert_eq!(verdict.await, Ok("secondary"));
+        let verdict = hedger.run(answer(500, Ok("primary")), answer(10, Ok("secondary")));
+        assert_eq!(verdict.await, Ok("primary"));
+        assert_eq!(counter("hedge.capped"), 1);
+        assert_eq!(counter("hedge.hedged"), 3);
+        assert_eq!(counter("hedge.requests"), 5);
+    }
+}
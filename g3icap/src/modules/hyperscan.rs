@@ -0,0 +1,259 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Hyperscan backend of the regex pattern matcher
+//!
+//! All the supported patterns of a rule set are compiled into one block mode
+//! database, scanned once per haystack. Vectorscan, the port of Hyperscan to
+//! ARM and other platforms, provides the same library and is used the same
+//! way. The patterns are written for the regex crate, so the ones Hyperscan
+//! can't compile are left to the regex engine, and so are the ones using a
+//! construct Hyperscan, following PCRE, reads differently:
+//!
+//! - `$` also matches before a trailing newline
+//! - `\<`, `\>` and the `\b{start}` like boundaries are literals or errors
+//! - nested classes and the `&&`, `--` and `~~` class operations are literals
+//! - the whitespaces of the classes are kept in `x` mode, there is no `R`
+//!   CRLF mode, and `\w`, `\b` and the other classes are Unicode even after
+//!   `(?-u)`
+
+use std::fmt;
+use std::sync::Mutex;
+
+use hyperscan::Flags;
+use hyperscan::prelude::*;
+use regex_syntax::ast::{self, AssertionKind, Ast, ClassSetBinaryOp, ClassSetItem, FlagsItemKind};
+
+use crate::modules::ModuleError;
+
+/// Database of the patterns supported by Hyperscan
+pub(crate) struct HyperscanSet {
+    database: BlockDatabase,
+    /// Index of each pattern of the database in the rule set
+    indexes: Vec<usize>,
+    /// Idle scratch spaces, a scan needs one of its own
+    scratches: Mutex<Vec<Scratch>>,
+}
+
+impl HyperscanSet {
+    /// Compile the supported patterns, returning the indexes of the other ones
+    ///
+    /// No database is returned if no pattern is supported.
+    pub(crate) fn build<'a, I>(patterns: I) -> Result<(Option<Self>, Vec<usize>), ModuleError>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let flags = Flags::SINGLEMATCH | Flags::UTF8 | Flags::UCP;
+        let mut supported = Vec::new();
+        let mut indexes = Vec::new();
+        let mut unsupported = Vec::new();
+        for (i, expression) in patterns.into_iter().enumerate() {
+            if let Err(construct) = same_dialect(expression) {
+                log::debug!("pattern '{expression}' left to the regex engine: {construct}");
+                unsupported.push(i);
+                continue;
+            }
+            let pattern = Pattern::with_flags(expression, flags).and_then(|p| p.info().map(|_| p));
+            match pattern {
+                Ok(mut pattern) => {
+                    pattern.id = Some(indexes.len());
+                    supported.push(pattern);
+                    indexes.push(i);
+                }
+                Err(e) => {
+                    log::debug!("pattern '{expression}' left to the regex engine: {e}");
+                    unsupported.push(i);
+                }
+            }
+        }
+        if supported.is_empty() {
+            return Ok((None, unsupported));
+        }
+
+        let database: BlockDatabase = Patterns(supported).build().map_err(|e| {
+            ModuleError::InitFailed(format!("failed to build hyperscan database: {e}"))
+        })?;
+        let scratch = database.alloc_scratch().map_err(|e| {
+            ModuleError::InitFailed(format!("failed to allocate hyperscan scratch: {e}"))
+        })?;
+        let set = HyperscanSet {
+            database,
+            indexes,
+            scratches: Mutex::new(vec![scratch]),
+        };
+        Ok((Some(set), unsupported))
+    }
+
+    /// Number of patterns in the database
+    pub(crate) fn len(&self) -> usize {
+        self.indexes.len()
+    }
+
+    /// Index of the first pattern of the rule set matching the haystack
+    ///
+    /// The caller falls back to the regex engine if the scan fails.
+    pub(crate) fn find(&self, haystack: &str) -> Result<Option<usize>, hyperscan::Error> {
+        let idle = self.scratches.lock().ok().and_then(|mut s| s.pop());
+        let scratch = match idle {
+            Some(scratch) => scratch,
+            None => self.database.alloc_scratch()?,
+        };
+        let mut first: Option<usize> = None;
+        let result = self
+            .database
+            .scan(haystack, &scratch, |id, _from, _to, _flags| {
+                let index = self.indexes[id as usize];
+                first = Some(first.map_or(index, |f| f.min(index)));
+                Matching::Continue
+            });
+        if let Ok(mut scratches) = self.scratches.lock() {
+            scratches.push(scratch);
+        }
+        result.map(|_| first)
+    }
+}
+
+impl fmt::Debug for HyperscanSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HyperscanSet")
+            .field("patterns", &self.indexes.len())
+            .finish()
+    }
+}
+
+/// Check that Hyperscan reads the pattern the way the regex crate does
+///
+/// The construct it reads differently is returned otherwise.
+fn same_dialect(expression: &str) -> Result<(), &'static str> {
+    let ast = ast::parse::Parser::new()
+        .parse(expression)
+        .map_err(|_| "invalid pattern")?;
+    ast::visit(&ast, DialectCheck)
+}
+
+struct DialectCheck;
+
+impl DialectCheck {
+    fn check_flags(flags: &ast::Flags) -> Result<(), &'static str> {
+        let mut negated = false;
+        for item in &flags.items {
+            match &item.kind {
+                FlagsItemKind::Negation => negated = true,
+                FlagsItemKind::Flag(ast::Flag::IgnoreWhitespace) => {
+                    return Err("whitespace insensitive mode");
+                }
+                FlagsItemKind::Flag(ast::Flag::CRLF) => return Err("CRLF mode"),
+                FlagsItemKind::Flag(ast::Flag::Unicode) if negated => {
+                    return Err("ASCII classes");
+                }
+                FlagsItemKind::Flag(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ast::Visitor for DialectCheck {
+    type Output = ();
+    type Err = &'static str;
+
+    fn finish(self) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn visit_pre(&mut self, ast: &Ast) -> Result<(), &'static str> {
+        match ast {
+            Ast::Assertion(assertion) => match assertion.kind {
+                AssertionKind::StartLine
+                | AssertionKind::StartText
+                | AssertionKind::EndText
+                | AssertionKind::WordBoundary
+                | AssertionKind::NotWordBoundary => Ok(()),
+                AssertionKind::EndLine => Err("end of line assertion"),
+                _ => Err("word start or end assertion"),
+            },
+            Ast::Flags(set) => Self::check_flags(&set.flags),
+            Ast::Group(group) => match &group.kind {
+                ast::GroupKind::NonCapturing(flags) => Self::check_flags(flags),
+                _ => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn visit_class_set_item_pre(&mut self, item: &ClassSetItem) -> Result<(), &'static str> {
+        match item {
+            ClassSetItem::Bracketed(_) => Err("nested class"),
+            _ => Ok(()),
+        }
+    }
+
+    fn visit_class_set_binary_op_pre(
+        &mut self,
+        _op: &ClassSetBinaryOp,
+    ) -> Result<(), &'static str> {
+        Err("class operation")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    #[test]
+    fn dialects() {
+        let patterns = [
+            r"casino\.\w+",
+            r"(?i)poker",
+            r"\bcasino\b",
+            r"^GET /admin",
+            r"[0-9]{4}-[0-9]{4}",
+            r"(?s)begin.+end",
+            r"\p{Greek}+",
+            r"token$",
+            r"\<bet",
+            r"[\w&&[^_]]+_",
+            r"(?x) [a b]",
+            r"(?-u:\w)é",
+            r"(?m)^admin$",
+        ];
+        let haystacks = [
+            "play POKER at the casino",
+            "casino.example",
+            "GET /admin HTTP/1.1",
+            "card 1234-5678",
+            "begin\nend",
+            "αβγ",
+            "token\n",
+            "token",
+            "<bet",
+            "bet",
+            "a_",
+            "_",
+            " ",
+            "b",
+            "xé",
+            "éé",
+            "user\nadmin\n",
+            "chess",
+        ];
+
+        let (set, unsupported) = HyperscanSet::build(patterns).unwrap();
+        let set = set.unwrap();
+        assert_eq!(unsupported, [7, 8, 9, 10, 11, 12]);
+        assert_eq!(set.len(), 7);
+
+        let regexes: Vec<Regex> = patterns.iter().map(|p| Regex::new(p).unwrap()).collect();
+        for haystack in haystacks {
+            let expected = set
+                .indexes
+                .iter()
+                .copied()
+                .find(|i| regexes[*i].is_match(haystack));
+            assert_eq!(set.find(haystack).unwrap(), expected, "{haystack:?}");
+        }
+    }
+}
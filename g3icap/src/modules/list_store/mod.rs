@@ -0,0 +1,436 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Memory-mapped frozen list stores
+//!
+//! Very large lists, such as host lists or digest sets of several GB, are
+//! compiled offline by `g3icap-listc` into a frozen file of sorted entries,
+//! which the server maps read-only instead of loading it. Lookups are binary
+//! searches in the mapping, whose pages are shared by all the workers
+//! through the page cache, and a reload only maps the new file.
+//!
+//! File layout, with integers in little endian:
+//!
+//! | offset | size | field                                      |
+//! |--------|------|--------------------------------------------|
+//! | 0      | 8    | magic, `G3ILIST1`                          |
+//! | 8      | 4    | kind, 1 for domains and 2 for digests      |
+//! | 12     | 4    | width of the entries, 0 if they vary       |
+//! | 16     | 8    | number of entries                          |
+//! | 24     | 8    | size of the entry data                     |
+//!
+//! Variable width entries are then indexed by `count + 1` offsets in the
+//! entry data, as u64. The entry data follows, sorted in byte order and
+//! without duplicates. Files must be replaced by renaming a new file over
+//! them, never modified in place, as they may be mapped.
+//!
+//! On multi node hosts, the `numa` config of the server can have the lists
+//! copied into memory interleaved over the NUMA nodes, or into the memory of
+//! each node, the lookups reading the copy of the node they run on.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::ops::Deref;
+use std::path::Path;
+
+use anyhow::{Context, anyhow};
+use g3_compat::MemoryPolicy;
+use log::warn;
+use memmap2::{Mmap, MmapMut};
+
+use crate::config::server::numa::ListPlacement;
+use crate::modules::matcher::strip_port;
+use crate::numa;
+
+/// Magic bytes of a frozen list file
+pub const MAGIC: &[u8; 8] = b"G3ILIST1";
+
+const HEADER_LEN: usize = 32;
+
+/// Kind of the entries of a frozen list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListKind {
+    /// Lowercase domain names, matching their subdomains too
+    Domains,
+    /// Raw digests, such as SHA-256 of known malicious content
+    Digests,
+}
+
+impl ListKind {
+    fn code(self) -> u32 {
+        match self {
+            ListKind::Domains => 1,
+            ListKind::Digests => 2,
+        }
+    }
+
+    fn from_code(code: u32) -> Option<Self> {
+        match code {
+            1 => Some(ListKind::Domains),
+            2 => Some(ListKind::Digests),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ListKind::Domains => "domains",
+            ListKind::Digests => "digests",
+        }
+    }
+}
+
+enum Storage {
+    Mapped(Mmap),
+    Owned(Vec<u8>),
+    /// A copy in the memory of each NUMA node, by node index
+    Replicas(Vec<Mmap>),
+}
+
+impl Deref for Storage {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Storage::Mapped(map) => map,
+            Storage::Owned(data) => data,
+            Storage::Replicas(maps) => {
+                let node = numa::current_node().unwrap_or_default();
+                &maps[node.min(maps.len() - 1)]
+            }
+        }
+    }
+}
+
+impl Storage {
+    /// Map the file, or copy it into the memory of the nodes as placed by
+    /// the server config
+    fn open(file: &File, path: &Path) -> anyhow::Result<Self> {
+        // SAFETY: list files are replaced by renaming, never truncated or
+        // modified in place while mapped
+        let map =
+            unsafe { Mmap::map(file) }.context(format!("failed to map {}", path.display()))?;
+        let placement = numa::get_global()
+            .map(|c| c.list_stores)
+            .unwrap_or_default();
+        let nodes = numa::node_count();
+        if nodes < 2 || map.is_empty() {
+            // nothing to place on a single node
+            return Ok(Storage::Mapped(map));
+        }
+        match placement {
+            ListPlacement::Mapped => Ok(Storage::Mapped(map)),
+            ListPlacement::Interleave => {
+                let nodes: Vec<usize> = (0..nodes).filter_map(numa::node_id).collect();
+                let copy = placed_copy(&map, MemoryPolicy::Interleave, &nodes)
+                    .context(format!("failed to copy {}", path.display()))?;
+                Ok(Storage::Mapped(copy))
+            }
+            ListPlacement::Local => {
+                let mut copies = Vec::with_capacity(nodes);
+                for index in 0..nodes {
+                    let node = numa::node_id(index).unwrap_or(index);
+                    let copy = placed_copy(&map, MemoryPolicy::Preferred, &[node])
+                        .context(format!("failed to copy {} to node {node}", path.display()))?;
+                    copies.push(copy);
+                }
+                Ok(Storage::Replicas(copies))
+            }
+        }
+    }
+}
+
+/// Copy the data into anonymous memory placed with the policy
+fn placed_copy(data: &[u8], policy: MemoryPolicy, nodes: &[usize]) -> io::Result<Mmap> {
+    let mut copy = MmapMut::map_anon(data.len())?;
+    // the policy applies to the pages not touched yet, so before the copy
+    if let Err(e) = g3_compat::set_memory_policy(&mut copy, policy, nodes) {
+        warn!("failed to place list memory on NUMA nodes {nodes:?}: {e}");
+    }
+    copy.copy_from_slice(data);
+    copy.make_read_only()
+}
+
+/// A frozen list, mapped read-only
+pub struct FrozenList {
+    storage: Storage,
+    kind: ListKind,
+    width: usize,
+    count: usize,
+    /// Start of the entry data
+    data: usize,
+}
+
+impl FrozenList {
+    /// Map a frozen list file, or copy it as placed by the server config
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let file = File::open(path).context(format!("failed to open {}", path.display()))?;
+        let storage = Storage::open(&file, path)?;
+        Self::new(storage).context(format!("invalid list file {}", path.display()))
+    }
+
+    /// Read a frozen list from memory
+    pub fn from_bytes(data: Vec<u8>) -> anyhow::Result<Self> {
+        Self::new(Storage::Owned(data))
+    }
+
+    fn new(storage: Storage) -> anyhow::Result<Self> {
+        let header = storage
+            .get(..HEADER_LEN)
+            .ok_or_else(|| anyhow!("truncated header"))?;
+        if &header[..8] != MAGIC {
+            return Err(anyhow!("not a frozen list"));
+        }
+        let kind = ListKind::from_code(read_u32(header, 8))
+            .ok_or_else(|| anyhow!("unknown list kind {}", read_u32(header, 8)))?;
+        let width = read_u32(header, 12) as usize;
+        let count = usize::try_from(read_u64(header, 16))?;
+        let data_len = usize::try_from(read_u64(header, 24))?;
+
+        let index_len = if width == 0 {
+            count
+                .checked_add(1)
+                .and_then(|n| n.checked_mul(8))
+                .ok_or_else(|| anyhow!("invalid entry count {count}"))?
+        } else {
+            let expected = count.checked_mul(width);
+            if expected != Some(data_len) {
+                return Err(anyhow!(
+                    "{count} entries of {width} bytes in {data_len} bytes"
+                ));
+            }
+            0
+        };
+        let data = HEADER_LEN + index_len;
+        if data.checked_add(data_len) != Some(storage.len()) {
+            return Err(anyhow!(
+                "size {} does not match the header, expected {}",
+                storage.len(),
+                data.saturating_add(data_len)
+            ));
+        }
+        Ok(FrozenList {
+            storage,
+            kind,
+            width,
+            count,
+            data,
+        })
+    }
+
+    /// Kind of the entries
+    pub fn kind(&self) -> ListKind {
+        self.kind
+    }
+
+    /// Number of entries
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Check if the list has no entry
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    fn entry<'a>(&self, storage: &'a [u8], i: usize) -> &'a [u8] {
+        let (start, end) = if self.width == 0 {
+            let offset = HEADER_LEN + i * 8;
+            (
+                read_u64(storage, offset) as usize,
+                read_u64(storage, offset + 8) as usize,
+            )
+        } else {
+            (i * self.width, (i + 1) * self.width)
+        };
+        // a corrupted index gives no match instead of a panic
+        storage
+            .get(self.data + start..self.data + end)
+            .unwrap_or_default()
+    }
+
+    /// Check if the entry is in the list
+    pub fn contains(&self, key: &[u8]) -> bool {
+        // the copy of the node is picked once for the whole search
+        let storage: &[u8] = &self.storage;
+        let (mut low, mut high) = (0, self.count);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match self.entry(storage, mid).cmp(key) {
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+                std::cmp::Ordering::Equal => return true,
+            }
+        }
+        false
+    }
+
+    /// Find the listed domain that the host is equal to or a subdomain of
+    ///
+    /// The most specific listed domain is returned.
+    pub fn find_domain(&self, host: &str) -> Option<String> {
+        let host = strip_port(host).trim_end_matches('.').to_ascii_lowercase();
+        let mut suffix = host.as_str();
+        loop {
+            if self.contains(suffix.as_bytes()) {
+                return Some(suffix.to_string());
+            }
+            suffix = suffix.split_once('.')?.1;
+        }
+    }
+
+    /// Check if the digest, in hexadecimal, is in the list
+    pub fn contains_hex_digest(&self, digest: &str) -> bool {
+        decode_hex(digest).is_some_and(|d| self.contains(&d))
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(buf)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(buf)
+}
+
+/// Decode a hexadecimal digest
+pub fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim().as_bytes();
+    if s.is_empty() || !s.len().is_multiple_of(2) {
+        return None;
+    }
+    s.chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).ok()?;
+            u8::from_str_radix(pair, 16).ok()
+        })
+        .collect()
+}
+
+/// Write a frozen list of the entries, which are sorted and deduplicated first
+///
+/// Digests must all have the same size, domains are stored as given.
+pub fn write_list<W: Write>(
+    kind: ListKind,
+    mut entries: Vec<Vec<u8>>,
+    out: &mut W,
+) -> io::Result<()> {
+    entries.sort_unstable();
+    entries.dedup();
+    let width = match kind {
+        ListKind::Digests => {
+            let width = entries.first().map(|e| e.len()).unwrap_or(0);
+            if entries.iter().any(|e| e.len() != width) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "digests of different sizes",
+                ));
+            }
+            width
+        }
+        ListKind::Domains => 0,
+    };
+    let data_len: usize = entries.iter().map(|e| e.len()).sum();
+
+    out.write_all(MAGIC)?;
+    out.write_all(&kind.code().to_le_bytes())?;
+    out.write_all(&(width as u32).to_le_bytes())?;
+    out.write_all(&(entries.len() as u64).to_le_bytes())?;
+    out.write_all(&(data_len as u64).to_le_bytes())?;
+    if width == 0 {
+        let mut offset = 0u64;
+        out.write_all(&offset.to_le_bytes())?;
+        for entry in &entries {
+            offset += entry.len() as u64;
+            out.write_all(&offset.to_le_bytes())?;
+        }
+    }
+    for entry in &entries {
+        out.write_all(entry)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(kind: ListKind, entries: &[&[u8]]) -> FrozenList {
+        let mut data = Vec::new();
+        write_list(
+            kind,
+            entries.iter().map(|e| e.to_vec()).collect(),
+            &mut data,
+        )
+        .unwrap();
+        FrozenList::from_bytes(data).unwrap()
+    }
+
+    #[test]
+    fn domains() {
+        let list = build(
+            ListKind::Domains,
+            &[
+                b"example.com",
+                b"ads.example.net",
+                b"tracker.org",
+                b"example.com",
+            ],
+        );
+        assert_eq!(list.kind(), ListKind::Domains);
+        assert_eq!(list.len(), 3);
+        assert_eq!(
+            list.find_domain("cdn.Example.COM:443").as_deref(),
+            Some("example.com")
+        );
+        assert_eq!(
+            list.find_domain("ads.example.net.").as_deref(),
+            Some("ads.example.net")
+        );
+        assert_eq!(list.find_domain("example.net"), None);
+        assert_eq!(list.find_domain("notexample.com"), None);
+    }
+
+    #[test]
+    fn digests() {
+        let a = decode_hex(&"ab".repeat(32)).unwrap();
+        let b = decode_hex(&"01".repeat(32)).unwrap();
+        let list = build(ListKind::Digests, &[&a, &b]);
+        assert!(list.contains_hex_digest(&"AB".repeat(32)));
+        assert!(list.contains_hex_digest(&"01".repeat(32)));
+        assert!(!list.contains_hex_digest(&"02".repeat(32)));
+        assert!(!list.contains_hex_digest("not hex"));
+
+        let mut data = Vec::new();
+        let e = write_list(ListKind::Digests, vec![a, b"short".to_vec()], &mut data);
+        assert!(e.is_err());
+    }
+
+    #[test]
+    fn invalid_files() {
+        assert!(FrozenList::from_bytes(b"G3ILIST1".to_vec()).is_err());
+        let mut data = Vec::new();
+        write_list(ListKind::Domains, vec![b"example.com".to_vec()], &mut data).unwrap();
+        data.pop();
+        assert!(FrozenList::from_bytes(data.clone()).is_err());
+        data[0] = b'X';
+        assert!(FrozenList::from_bytes(data).is_err());
+    }
+
+    #[test]
+    fn open_mapped() {
+        let path = std::env::temp_dir().join(format!("g3icap-list-{}.g3list", std::process::id()));
+        let mut file = File::create(&path).unwrap();
+        write_list(ListKind::Domains, vec![b"example.com".to_vec()], &mut file).unwrap();
+        drop(file);
+        let list = FrozenList::open(&path).unwrap();
+        assert!(list.find_domain("www.example.com").is_some());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,382 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Multi-pattern matchers for content filtering
+//!
+//! Keywords and domain suffixes are compiled into Aho-Corasick automatons,
+//! and regex patterns into a regex set, or a Hyperscan database if the server
+//! is built with the `hyperscan` feature, so each request is scanned once
+//! whatever the number of patterns. The automatons are rebuilt whenever the
+//! filter configuration is reloaded.
+
+use std::ops::Range;
+#[cfg(feature = "hyperscan")]
+use std::sync::Arc;
+
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder, MatchKind};
+use regex::{Regex, RegexSet};
+use serde::{Deserialize, Serialize};
+
+use crate::modules::ModuleError;
+#[cfg(feature = "hyperscan")]
+use crate::modules::hyperscan::HyperscanSet;
+
+/// A pattern that matched, with the id of the rule it comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleMatch {
+    /// Rule id
+    pub rule_id: String,
+    /// The configured pattern
+    pub pattern: String,
+}
+
+/// Keyword matcher returning the first matching keyword
+#[derive(Debug, Clone, Default)]
+pub struct KeywordMatcher {
+    automaton: Option<AhoCorasick>,
+    rules: Vec<RuleMatch>,
+    case_insensitive: bool,
+}
+
+impl KeywordMatcher {
+    /// Build a matcher from `(rule id, keyword)` pairs
+    pub fn new<I>(keywords: I, case_insensitive: bool) -> Result<Self, ModuleError>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let rules: Vec<RuleMatch> = keywords
+            .into_iter()
+            .filter(|(_, k)| !k.is_empty())
+            .map(|(rule_id, pattern)| RuleMatch { rule_id, pattern })
+            .collect();
+        if rules.is_empty() {
+            return Ok(KeywordMatcher {
+                automaton: None,
+                rules,
+                case_insensitive,
+            });
+        }
+
+        let patterns = rules
+            .iter()
+            .map(|r| normalize(&r.pattern, case_insensitive));
+        let automaton = AhoCorasickBuilder::new()
+            .match_kind(MatchKind::LeftmostFirst)
+            .ascii_case_insensitive(case_insensitive)
+            .build(patterns)
+            .map_err(|e| {
+                ModuleError::InitFailed(format!("failed to build keyword matcher: {e}"))
+            })?;
+        Ok(KeywordMatcher {
+            automaton: Some(automaton),
+            rules,
+            case_insensitive,
+        })
+    }
+
+    /// Number of keywords
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Check if there is no keyword
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Find the first keyword contained in the haystack
+    pub fn find(&self, haystack: &[u8]) -> Option<&RuleMatch> {
+        let automaton = self.automaton.as_ref()?;
+        let found = if self.case_insensitive && !haystack.is_ascii() {
+            let lowered = String::from_utf8_lossy(haystack).to_lowercase();
+            automaton.find(lowered.as_bytes())
+        } else {
+            automaton.find(haystack)
+        };
+        found.map(|m| &self.rules[m.pattern().as_usize()])
+    }
+
+    /// Find the spans of all the keywords in the text
+    ///
+    /// A non ASCII text is only matched case insensitively if lowering it
+    /// keeps the byte offsets.
+    pub fn find_spans(&self, text: &str) -> Vec<Range<usize>> {
+        let Some(automaton) = self.automaton.as_ref() else {
+            return Vec::new();
+        };
+        let lowered = (self.case_insensitive && !text.is_ascii())
+            .then(|| text.to_lowercase())
+            .filter(|lowered| lowered.len() == text.len());
+        let haystack = lowered.as_deref().unwrap_or(text);
+        automaton.find_iter(haystack).map(|m| m.range()).collect()
+    }
+}
+
+/// Domain matcher, matching the domain itself and all of its subdomains
+#[derive(Debug, Clone, Default)]
+pub struct DomainSuffixMatcher {
+    automaton: Option<AhoCorasick>,
+    rules: Vec<RuleMatch>,
+}
+
+impl DomainSuffixMatcher {
+    /// Build a matcher from `(rule id, domain)` pairs
+    pub fn new<I>(domains: I) -> Result<Self, ModuleError>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let rules: Vec<RuleMatch> = domains
+            .into_iter()
+            .map(|(rule_id, d)| RuleMatch {
+                rule_id,
+                pattern: d
+                    .trim()
+                    .trim_start_matches("*.")
+                    .trim_matches('.')
+                    .to_lowercase(),
+            })
+            .filter(|r| !r.pattern.is_empty())
+            .collect();
+        if rules.is_empty() {
+            return Ok(DomainSuffixMatcher {
+                automaton: None,
+                rules,
+            });
+        }
+
+        let automaton = AhoCorasickBuilder::new()
+            .match_kind(MatchKind::Standard)
+            .ascii_case_insensitive(true)
+            .build(rules.iter().map(|r| r.pattern.as_str()))
+            .map_err(|e| ModuleError::InitFailed(format!("failed to build domain matcher: {e}")))?;
+        Ok(DomainSuffixMatcher {
+            automaton: Some(automaton),
+            rules,
+        })
+    }
+
+    /// Number of domains
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Check if there is no domain
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Find the longest configured domain that the host is equal to or a subdomain of
+    pub fn find(&self, host: &str) -> Option<&RuleMatch> {
+        let automaton = self.automaton.as_ref()?;
+        let host = strip_port(host).trim_end_matches('.');
+        let bytes = host.as_bytes();
+        automaton
+            .find_overlapping_iter(bytes)
+            .filter(|m| m.end() == bytes.len() && (m.start() == 0 || bytes[m.start() - 1] == b'.'))
+            .max_by_key(|m| m.end() - m.start())
+            .map(|m| &self.rules[m.pattern().as_usize()])
+    }
+}
+
+/// Engine matching the regex patterns
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PatternEngine {
+    /// Hyperscan if the server is built with it, the regex crate otherwise
+    #[default]
+    Auto,
+    /// The regex crate
+    Regex,
+    /// Hyperscan, the server must be built with the `hyperscan` feature
+    Hyperscan,
+}
+
+impl PatternEngine {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PatternEngine::Auto => "auto",
+            PatternEngine::Regex => "regex",
+            PatternEngine::Hyperscan => "hyperscan",
+        }
+    }
+
+    /// Check that the engine is available in this build
+    pub fn check(&self) -> Result<(), ModuleError> {
+        if *self == PatternEngine::Hyperscan {
+            crate::features::HYPERSCAN
+                .require("the hyperscan pattern engine")
+                .map_err(|e| ModuleError::InitFailed(e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// Check if the patterns go to Hyperscan first
+    pub fn uses_hyperscan(&self) -> bool {
+        cfg!(feature = "hyperscan") && *self != PatternEngine::Regex
+    }
+}
+
+/// Regex pattern matcher returning the first matching pattern
+///
+/// Patterns not supported by Hyperscan, or all of them if it is not used,
+/// are matched by a regex set.
+#[derive(Debug, Clone, Default)]
+pub struct PatternMatcher {
+    patterns: Vec<Regex>,
+    /// Set of the patterns left to the regex engine
+    set: Option<RegexSet>,
+    /// Index of each pattern of the set
+    set_indexes: Vec<usize>,
+    #[cfg(feature = "hyperscan")]
+    hyperscan: Option<Arc<HyperscanSet>>,
+}
+
+impl PatternMatcher {
+    /// Build a matcher from compiled patterns, the first one taking precedence
+    pub fn new(patterns: Vec<Regex>, engine: PatternEngine) -> Result<Self, ModuleError> {
+        engine.check()?;
+        let mut matcher = PatternMatcher {
+            patterns,
+            ..Default::default()
+        };
+        if matcher.patterns.is_empty() {
+            return Ok(matcher);
+        }
+
+        let all = (0..matcher.patterns.len()).collect();
+        #[cfg(feature = "hyperscan")]
+        let set_indexes = if engine.uses_hyperscan() {
+            let (hyperscan, unsupported) =
+                HyperscanSet::build(matcher.patterns.iter().map(|p| p.as_str()))?;
+            matcher.hyperscan = hyperscan.map(Arc::new);
+            unsupported
+        } else {
+            all
+        };
+        #[cfg(not(feature = "hyperscan"))]
+        let set_indexes: Vec<usize> = all;
+        matcher.set = matcher.build_set(&set_indexes)?;
+        matcher.set_indexes = set_indexes;
+        Ok(matcher)
+    }
+
+    fn build_set(&self, indexes: &[usize]) -> Result<Option<RegexSet>, ModuleError> {
+        if indexes.is_empty() {
+            return Ok(None);
+        }
+        RegexSet::new(indexes.iter().map(|i| self.patterns[*i].as_str()))
+            .map(Some)
+            .map_err(|e| ModuleError::InitFailed(format!("failed to build pattern matcher: {e}")))
+    }
+
+    /// Number of patterns
+    pub fn len(&self) -> usize {
+        self.patterns.len()
+    }
+
+    /// Check if there is no pattern
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// The compiled patterns, in precedence order
+    pub fn patterns(&self) -> &[Regex] {
+        &self.patterns
+    }
+
+    /// Number of patterns matched by Hyperscan
+    pub fn hyperscan_len(&self) -> usize {
+        #[cfg(feature = "hyperscan")]
+        if let Some(hyperscan) = &self.hyperscan {
+            return hyperscan.len();
+        }
+        0
+    }
+
+    /// Find the first pattern matching the haystack, with its index
+    pub fn find(&self, haystack: &str) -> Option<(usize, &Regex)> {
+        let found = self.find_set(haystack);
+        #[cfg(feature = "hyperscan")]
+        let found = match &self.hyperscan {
+            Some(hyperscan) => match hyperscan.find(haystack) {
+                Ok(first) => first.into_iter().chain(found).min(),
+                Err(e) => {
+                    log::warn!("hyperscan scan failed, using the regex engine: {e}");
+                    self.patterns.iter().position(|p| p.is_match(haystack))
+                }
+            },
+            None => found,
+        };
+        found.map(|i| (i, &self.patterns[i]))
+    }
+
+    fn find_set(&self, haystack: &str) -> Option<usize> {
+        let set = self.set.as_ref()?;
+        // the matches are in the order of the set
+        set.matches(haystack)
+            .iter()
+            .next()
+            .map(|i| self.set_indexes[i])
+    }
+}
+
+fn normalize(pattern: &str, case_insensitive: bool) -> String {
+    if case_insensitive {
+        pattern.to_lowercase()
+    } else {
+        pattern.to_string()
+    }
+}
+
+pub(crate) fn strip_port(host: &str) -> &str {
+    // keep bracketed IPv6 addresses intact
+    if host.starts_with('[') {
+        return host.split(']').next().map(|h| &h[1..]).unwrap_or(host);
+    }
+    match host.rsplit_once(':') {
+        Some((h, port)) if port.bytes().all(|b| b.is_ascii_digit()) => h,
+        _ => host,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pattern_precedence() {
+        let patterns = [r"casino\.\w+", r"(?i)poker", r"\bcasino\b"]
+            .iter()
+            .map(|p| Regex::new(p).unwrap())
+            .collect();
+        let matcher = PatternMatcher::new(patterns, PatternEngine::Auto).unwrap();
+        assert_eq!(matcher.len(), 3);
+        assert_eq!(
+            matcher.find("play POKER at the casino").map(|m| m.0),
+            Some(1)
+        );
+        assert_eq!(matcher.find("casino.example").map(|m| m.0), Some(0));
+        assert_eq!(
+            matcher.find("a casino").map(|m| m.1.as_str()),
+            Some(r"\bcasino\b")
+        );
+        assert!(matcher.find("chess").is_none());
+
+        let matcher = PatternMatcher::new(Vec::new(), PatternEngine::Regex).unwrap();
+        assert!(matcher.is_empty());
+        assert!(matcher.find("casino").is_none());
+    }
+
+    #[test]
+    fn engine_check() {
+        assert!(PatternEngine::Auto.check().is_ok());
+        assert!(PatternEngine::Regex.check().is_ok());
+        assert!(!PatternEngine::Regex.uses_hyperscan());
+        assert_eq!(
+            PatternEngine::Hyperscan.check().is_ok(),
+            cfg!(feature = "hyperscan")
+        );
+    }
+}
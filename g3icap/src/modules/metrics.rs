@@ -0,0 +1,371 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Custom metrics of the modules
+//!
+//! A module gets a [`MetricsRegistry`] handle in its [`ModuleConfig`] and may
+//! register its own counters, gauges and histograms with it when it is
+//! initialized. The names are put under `module.<module name>.`, so that
+//! modules can not clash with each other or with the server metrics, and all
+//! registered metrics are emitted to StatsD with the server metrics and can be
+//! rendered in the Prometheus text format.
+//!
+//! [`ModuleConfig`]: super::ModuleConfig
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, RwLock};
+
+use g3_statsd_client::{StatsdClient, StatsdTagGroup};
+
+use super::ModuleError;
+
+const ROOT_PREFIX: &str = "module";
+const TAG_KEY_LE: &str = "le";
+
+/// Metrics of all modules of the process
+static GLOBAL_METRICS: LazyLock<Arc<RwLock<BTreeMap<String, Metric>>>> =
+    LazyLock::new(|| Arc::new(RwLock::new(BTreeMap::new())));
+
+/// Monotonic counter
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Value that goes up and down
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub fn set(&self, v: i64) {
+        self.0.store(v, Ordering::Relaxed);
+    }
+
+    pub fn add(&self, n: i64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn sub(&self, n: i64) {
+        self.0.fetch_sub(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Distribution of values over fixed buckets
+#[derive(Debug)]
+pub struct Histogram {
+    /// Inclusive upper bounds of the buckets, in increasing order
+    bounds: Vec<u64>,
+    /// Count of each bucket, and of the values above the last bound
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    fn new(bounds: &[u64]) -> Self {
+        let mut bounds = bounds.to_vec();
+        bounds.sort_unstable();
+        bounds.dedup();
+        let buckets = (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect();
+        Histogram {
+            bounds,
+            buckets,
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record(&self, v: u64) {
+        let i = self.bounds.partition_point(|b| *b < v);
+        self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(v, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub fn sum(&self) -> u64 {
+        self.sum.load(Ordering::Relaxed)
+    }
+
+    /// Upper bound and cumulative count of each bucket, `None` for +Inf
+    pub fn cumulative_buckets(&self) -> Vec<(Option<u64>, u64)> {
+        let mut total = 0;
+        self.buckets
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                total += c.load(Ordering::Relaxed);
+                (self.bounds.get(i).copied(), total)
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone)]
+enum Metric {
+    Counter(Arc<Counter>),
+    Gauge(Arc<Gauge>),
+    Histogram(Arc<Histogram>),
+}
+
+impl Metric {
+    fn kind(&self) -> &'static str {
+        match self {
+            Metric::Counter(_) => "counter",
+            Metric::Gauge(_) => "gauge",
+            Metric::Histogram(_) => "histogram",
+        }
+    }
+}
+
+/// Handle to register metrics under a name prefix
+#[derive(Clone)]
+pub struct MetricsRegistry {
+    prefix: String,
+    metrics: Arc<RwLock<BTreeMap<String, Metric>>>,
+}
+
+impl fmt::Debug for MetricsRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetricsRegistry")
+            .field("prefix", &self.prefix)
+            .finish()
+    }
+}
+
+impl Default for MetricsRegistry {
+    fn default() -> Self {
+        Self::global()
+    }
+}
+
+impl MetricsRegistry {
+    /// Registry of the module metrics of the process
+    pub fn global() -> Self {
+        MetricsRegistry {
+            prefix: ROOT_PREFIX.to_string(),
+            metrics: GLOBAL_METRICS.clone(),
+        }
+    }
+
+    /// Registry that is not shared with the rest of the process
+    pub fn detached() -> Self {
+        MetricsRegistry {
+            prefix: ROOT_PREFIX.to_string(),
+            metrics: Arc::new(RwLock::new(BTreeMap::new())),
+        }
+    }
+
+    /// Handle for the metrics of a module
+    pub fn module(&self, name: &str) -> Self {
+        let name = name
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | '0'..='9' | '_' => c,
+                'A'..='Z' => c.to_ascii_lowercase(),
+                _ => '_',
+            })
+            .collect::<String>();
+        MetricsRegistry {
+            prefix: format!("{ROOT_PREFIX}.{name}"),
+            metrics: self.metrics.clone(),
+        }
+    }
+
+    fn register<F>(&self, name: &str, create: F) -> Result<Metric, ModuleError>
+    where
+        F: FnOnce() -> Metric,
+    {
+        let valid = !name.is_empty()
+            && !name.starts_with('.')
+            && !name.ends_with('.')
+            && name
+                .chars()
+                .all(|c| matches!(c, 'a'..='z' | '0'..='9' | '_' | '.'));
+        if !valid {
+            return Err(ModuleError::InitFailed(format!(
+                "invalid metric name {name}"
+            )));
+        }
+
+        let full_name = format!("{}.{name}", self.prefix);
+        let new = create();
+        let mut metrics = self.metrics.write().unwrap();
+        match metrics.get(&full_name) {
+            // registered again, e.g. by a reloaded module, the values are kept
+            Some(old) if old.kind() == new.kind() => Ok(old.clone()),
+            Some(old) => Err(ModuleError::InitFailed(format!(
+                "metric {full_name} is already registered as {}",
+                old.kind()
+            ))),
+            None => {
+                metrics.insert(full_name, new.clone());
+                Ok(new)
+            }
+        }
+    }
+
+    /// Register a counter
+    pub fn counter(&self, name: &str) -> Result<Arc<Counter>, ModuleError> {
+        match self.register(name, || Metric::Counter(Arc::default()))? {
+            Metric::Counter(c) => Ok(c),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Register a gauge
+    pub fn gauge(&self, name: &str) -> Result<Arc<Gauge>, ModuleError> {
+        match self.register(name, || Metric::Gauge(Arc::default()))? {
+            Metric::Gauge(g) => Ok(g),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Register a histogram with the upper bounds of its buckets
+    ///
+    /// The buckets of a histogram that is already registered are kept.
+    pub fn histogram(&self, name: &str, bounds: &[u64]) -> Result<Arc<Histogram>, ModuleError> {
+        match self.register(name, || Metric::Histogram(Arc::new(Histogram::new(bounds))))? {
+            Metric::Histogram(h) => Ok(h),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Full names of the registered metrics under the prefix
+    pub fn names(&self) -> Vec<String> {
+        let prefix = format!("{}.", self.prefix);
+        self.metrics
+            .read()
+            .unwrap()
+            .keys()
+            .filter(|k| k.starts_with(&prefix))
+            .cloned()
+            .collect()
+    }
+
+    /// Emit the registered metrics to StatsD
+    ///
+    /// A histogram is emitted as its count and sum, and the cumulative count
+    /// of each bucket with the `le` tag.
+    pub fn emit_stats(&self, client: &mut StatsdClient, tags: &StatsdTagGroup) {
+        let metrics = self.metrics.read().unwrap().clone();
+        for (name, metric) in metrics {
+            match metric {
+                Metric::Counter(c) => client.count_with_tags(&name, c.get(), tags).send(),
+                Metric::Gauge(g) => client.gauge_with_tags(&name, g.get(), tags).send(),
+                Metric::Histogram(h) => {
+                    let count_name = format!("{name}.count");
+                    client.count_with_tags(&count_name, h.count(), tags).send();
+                    let sum_name = format!("{name}.sum");
+                    client.count_with_tags(&sum_name, h.sum(), tags).send();
+                    let bucket_name = format!("{name}.bucket");
+                    for (bound, count) in h.cumulative_buckets() {
+                        let mut tags = tags.clone();
+                        match bound {
+                            Some(b) => tags.add_tag(TAG_KEY_LE, b.to_string()),
+                            None => tags.add_tag(TAG_KEY_LE, "inf"),
+                        }
+                        client.count_with_tags(&bucket_name, count, &tags).send();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Render the registered metrics in the Prometheus text format
+    pub fn render_prometheus(&self) -> String {
+        let metrics = self.metrics.read().unwrap().clone();
+        let mut out = String::new();
+        for (name, metric) in metrics {
+            let name = name.replace('.', "_");
+            let _ = writeln!(out, "# TYPE {name} {}", metric.kind());
+            match metric {
+                Metric::Counter(c) => {
+                    let _ = writeln!(out, "{name} {}", c.get());
+                }
+                Metric::Gauge(g) => {
+                    let _ = writeln!(out, "{name} {}", g.get());
+                }
+                Metric::Histogram(h) => {
+                    for (bound, count) in h.cumulative_buckets() {
+                        let le = bound.map(|b| b.to_string());
+                        let le = le.as_deref().unwrap_or("+Inf");
+                        let _ = writeln!(out, "{name}_bucket{{le=\"{le}\"}} {count}");
+                    }
+                    let _ = writeln!(out, "{name}_sum {}", h.sum());
+                    let _ = writeln!(out, "{name}_count {}", h.count());
+                }
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn namespaced_registration() {
+        let registry = MetricsRegistry::detached();
+        let filter = registry.module("Content-Filter");
+        let hits = filter.counter("rule_hits").unwrap();
+        hits.add(3);
+        // registered again by a reloaded module
+        filter.counter("rule_hits").unwrap().inc();
+        assert_eq!(hits.get(), 4);
+
+        let av = registry.module("antivirus");
+        av.gauge("engine_connections").unwrap().set(2);
+        assert!(av.counter("engine_connections").is_err());
+        assert!(av.counter("Bad-Name").is_err());
+        assert!(av.counter("").is_err());
+
+        assert_eq!(filter.names(), ["module.content_filter.rule_hits"]);
+        assert_eq!(av.names(), ["module.antivirus.engine_connections"]);
+    }
+
+    #[test]
+    fn histogram_buckets() {
+        let registry = MetricsRegistry::detached().module("cdr");
+        let h = registry.histogram("duration_ms", &[100, 10, 1000]).unwrap();
+        for v in [5, 10, 50, 5000] {
+            h.record(v);
+        }
+        assert_eq!(h.count(), 4);
+        assert_eq!(h.sum(), 5065);
+        assert_eq!(
+            h.cumulative_buckets(),
+            [(Some(10), 2), (Some(100), 3), (Some(1000), 3), (None, 4)]
+        );
+
+        let text = registry.render_prometheus();
+        assert!(text.contains("# TYPE module_cdr_duration_ms histogram\n"));
+        assert!(text.contains("module_cdr_duration_ms_bucket{le=\"100\"} 3\n"));
+        assert!(text.contains("module_cdr_duration_ms_bucket{le=\"+Inf\"} 4\n"));
+        assert!(text.contains("module_cdr_duration_ms_count 4\n"));
+    }
+}
@@ -10,13 +10,19 @@
 
 use std::collections::HashMap;
 use std::path::PathBuf;
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, RwLock};
 use std::time::Duration;
 
 use anyhow::Result;
 use async_trait::async_trait;
+use g3_statsd_client::{StatsdClient, StatsdTagGroup};
+use serde::{Deserialize, Serialize};
 
 use crate::protocol::common::{IcapMethod, IcapRequest, IcapResponse};
+use crate::stats::resource::ResourceUsage;
+use crate::transaction::TransactionCtx;
+use metrics::MetricsRegistry;
 // use crate::error::IcapError;
 
 /// Module configuration
@@ -38,6 +44,25 @@ pub struct ModuleConfig {
     pub max_memory: usize,
     /// Enable sandboxing
     pub sandbox: bool,
+    /// Registry of the custom metrics of the module
+    pub metrics: MetricsRegistry,
+}
+
+impl ModuleConfig {
+    /// Parse the module specific config, none if it is empty and the module
+    /// keeps its defaults
+    ///
+    /// A config that does not parse, e.g. with an unknown key or a value of
+    /// the wrong type, fails the module instead of being ignored.
+    pub fn parse_config<T: serde::de::DeserializeOwned>(&self) -> Result<Option<T>, ModuleError> {
+        match &self.config {
+            serde_json::Value::Null => Ok(None),
+            serde_json::Value::Object(map) if map.is_empty() => Ok(None),
+            config => T::deserialize(config)
+                .map(Some)
+                .map_err(|e| ModuleError::InitFailed(format!("invalid {} config: {e}", self.name))),
+        }
+    }
 }
 
 /// Module error types
@@ -55,6 +80,19 @@ pub enum ModuleError {
     DependencyMissing(String),
     #[error("Module version incompatible: {0}")]
     VersionIncompatible(String),
+    #[error("Module execution cancelled")]
+    Cancelled,
+}
+
+/// Run some work of a transaction until the transaction is cancelled
+pub async fn until_cancelled<T, F>(ctx: &TransactionCtx, work: F) -> Result<T, ModuleError>
+where
+    F: Future<Output = Result<T, ModuleError>>,
+{
+    ctx.cancel_token()
+        .run_until_cancelled(work)
+        .await
+        .unwrap_or(Err(ModuleError::Cancelled))
 }
 
 /// ICAP module trait
@@ -73,24 +111,97 @@ pub trait IcapModule: Send + Sync {
     async fn init(&mut self, config: &ModuleConfig) -> Result<(), ModuleError>;
     
     /// Handle REQMOD request
-    async fn handle_reqmod(&self, request: &IcapRequest) -> Result<IcapResponse, ModuleError>;
+    ///
+    /// The transaction of the context is cancelled when its deadline expires
+    /// or the client goes away, long-running work should then stop promptly.
+    async fn handle_reqmod(&self, request: &IcapRequest, ctx: &TransactionCtx) -> Result<IcapResponse, ModuleError>;
     
     /// Handle RESPMOD request
-    async fn handle_respmod(&self, request: &IcapRequest) -> Result<IcapResponse, ModuleError>;
+    ///
+    /// The transaction is cancelled like the one of `handle_reqmod`.
+    async fn handle_respmod(&self, request: &IcapRequest, ctx: &TransactionCtx) -> Result<IcapResponse, ModuleError>;
     
     /// Handle OPTIONS request
     async fn handle_options(&self, request: &IcapRequest) -> Result<IcapResponse, ModuleError>;
     
     /// Get module health status
     fn is_healthy(&self) -> bool;
+
+    /// Preview and transfer settings the module asks the clients for
+    ///
+    /// Advertised in the OPTIONS responses of the services using the module.
+    fn transfer_settings(&self) -> TransferSettings {
+        TransferSettings::default()
+    }
     
     /// Get module metrics
     fn get_metrics(&self) -> ModuleMetrics;
     
+    /// Clean up after a cancelled transaction
+    ///
+    /// Called once the transaction of the id is cancelled, to release what
+    /// was kept for it outside of the cancelled call, like temporary files or
+    /// the jobs of an external scanner.
+    fn handle_cancel(&self, _transaction_id: &str) {}
+
+    /// Explain what the module would match in the request
+    ///
+    /// The request is evaluated like by `handle_reqmod` or `handle_respmod`,
+    /// without counting or logging anything, for the what-if evaluations.
+    async fn explain(&self, _request: &IcapRequest) -> Result<Vec<ExplainedMatch>, ModuleError> {
+        Ok(Vec::new())
+    }
+
     /// Cleanup module resources
     async fn cleanup(&mut self);
 }
 
+/// A rule a module would match in a request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExplainedMatch {
+    pub module: String,
+    /// Rule set of the rule, like `default` or `group:<name>`
+    pub rule_set: String,
+    pub rule_id: String,
+    pub category: String,
+    pub reason: String,
+    /// Policy and policy rule the rule is compiled from, if known
+    pub policy: Option<String>,
+    pub rule: Option<String>,
+}
+
+/// Preview and transfer settings of a module
+///
+/// The file extensions are the ones of the `Transfer-*` OPTIONS headers,
+/// `*` standing for all the others.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferSettings {
+    /// Body bytes wanted in a preview, none if the module wants no preview
+    pub preview: Option<usize>,
+    /// Extensions to send a preview of
+    pub preview_extensions: Vec<String>,
+    /// Extensions not to send at all
+    pub ignore_extensions: Vec<String>,
+    /// Extensions to send in full without a preview
+    pub complete_extensions: Vec<String>,
+}
+
+impl TransferSettings {
+    /// Bytes of the preview of the modules without settings of their own
+    pub const DEFAULT_PREVIEW: usize = 1024;
+}
+
+impl Default for TransferSettings {
+    fn default() -> Self {
+        TransferSettings {
+            preview: Some(Self::DEFAULT_PREVIEW),
+            preview_extensions: vec!["*".to_string()],
+            ignore_extensions: Vec::new(),
+            complete_extensions: Vec::new(),
+        }
+    }
+}
+
 /// Module metrics
 #[derive(Debug, Clone, Default)]
 pub struct ModuleMetrics {
@@ -102,20 +213,156 @@ pub struct ModuleMetrics {
     pub average_response_time: Duration,
     /// Error rate
     pub error_rate: f64,
-    /// Memory usage in bytes
+    /// Memory usage in bytes, the one of the process shared by the modules
     pub memory_usage: usize,
-    /// CPU usage percentage
+    /// CPU usage percentage, the one of the process shared by the modules
     pub cpu_usage: f64,
     /// Last activity timestamp
     pub last_activity: Option<std::time::Instant>,
 }
 
+impl ModuleMetrics {
+    /// Fill in the resource usage sampled for the process
+    pub fn with_resource_usage(mut self, usage: &ResourceUsage) -> Self {
+        if let Some(memory) = usage.memory {
+            self.memory_usage = memory as usize;
+        }
+        if let Some(cpu) = usage.cpu {
+            self.cpu_usage = cpu * 100.0;
+        }
+        self
+    }
+}
+
+const METRIC_NAME_MODULE_REQUESTS: &str = "icap.module.requests";
+const METRIC_NAME_MODULE_ERROR_RATE: &str = "icap.module.error_rate";
+const METRIC_NAME_MODULE_RESPONSE_TIME: &str = "icap.module.response_time";
+const METRIC_NAME_MODULE_HEALTHY: &str = "icap.module.healthy";
+const METRIC_NAME_MODULE_GENERATION: &str = "icap.module.generation";
+
+const TAG_KEY_MODULE: &str = "module";
+
+/// Modules loaded by the servers of the process
+static GLOBAL_REGISTRY: LazyLock<ModuleRegistry> = LazyLock::new(|| {
+    ModuleRegistry::new(ModuleConfig {
+        name: "server".to_string(),
+        path: PathBuf::new(),
+        version: crate::version::VERSION.to_string(),
+        config: serde_json::Value::Object(serde_json::Map::new()),
+        dependencies: Vec::new(),
+        load_timeout: Duration::from_secs(5),
+        max_memory: 1024 * 1024,
+        sandbox: true,
+        metrics: MetricsRegistry::global(),
+    })
+});
+
 /// Module registry
+#[derive(Clone)]
 pub struct ModuleRegistry {
-    modules: Arc<RwLock<HashMap<String, Box<dyn IcapModule>>>>,
+    modules: Arc<RwLock<HashMap<String, RegisteredModule>>>,
     #[allow(dead_code)]
     config: ModuleConfig,
     metrics: Arc<RwLock<HashMap<String, ModuleMetrics>>>,
+    generation: Arc<AtomicU64>,
+}
+
+/// A module handle with the generation it was registered at
+#[derive(Clone)]
+struct RegisteredModule {
+    module: Arc<dyn IcapModule>,
+    generation: u64,
+}
+
+/// A registered module, cleaned up when its last handle is dropped
+///
+/// The handles are shared by the registry, the servers and the transactions,
+/// whichever drops the last one spawns the cleanup on its runtime.
+struct ManagedModule {
+    name: String,
+    /// Taken on drop only
+    module: Option<Box<dyn IcapModule>>,
+}
+
+impl ManagedModule {
+    fn inner(&self) -> &dyn IcapModule {
+        self.module.as_deref().unwrap()
+    }
+}
+
+impl Drop for ManagedModule {
+    fn drop(&mut self) {
+        let Some(mut module) = self.module.take() else {
+            return;
+        };
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            log::warn!("module {} dropped out of a runtime, its cleanup is skipped", self.name);
+            return;
+        };
+        let name = std::mem::take(&mut self.name);
+        runtime.spawn(async move {
+            module.cleanup().await;
+            log::debug!("module {name} cleaned up");
+        });
+    }
+}
+
+#[async_trait]
+impl IcapModule for ManagedModule {
+    fn name(&self) -> &str {
+        self.inner().name()
+    }
+
+    fn version(&self) -> &str {
+        self.inner().version()
+    }
+
+    fn supported_methods(&self) -> Vec<IcapMethod> {
+        self.inner().supported_methods()
+    }
+
+    async fn init(&mut self, config: &ModuleConfig) -> Result<(), ModuleError> {
+        match self.module.as_deref_mut() {
+            Some(module) => module.init(config).await,
+            None => Err(ModuleError::NotFound(self.name.clone())),
+        }
+    }
+
+    async fn handle_reqmod(&self, request: &IcapRequest, ctx: &TransactionCtx) -> Result<IcapResponse, ModuleError> {
+        self.inner().handle_reqmod(request, ctx).await
+    }
+
+    async fn handle_respmod(&self, request: &IcapRequest, ctx: &TransactionCtx) -> Result<IcapResponse, ModuleError> {
+        self.inner().handle_respmod(request, ctx).await
+    }
+
+    async fn handle_options(&self, request: &IcapRequest) -> Result<IcapResponse, ModuleError> {
+        self.inner().handle_options(request).await
+    }
+
+    fn is_healthy(&self) -> bool {
+        self.inner().is_healthy()
+    }
+
+    fn transfer_settings(&self) -> TransferSettings {
+        self.inner().transfer_settings()
+    }
+
+    fn get_metrics(&self) -> ModuleMetrics {
+        self.inner().get_metrics()
+    }
+
+    fn handle_cancel(&self, transaction_id: &str) {
+        self.inner().handle_cancel(transaction_id)
+    }
+
+    async fn explain(&self, request: &IcapRequest) -> Result<Vec<ExplainedMatch>, ModuleError> {
+        self.inner().explain(request).await
+    }
+
+    async fn cleanup(&mut self) {
+        // run when the last handle is dropped
+    }
 }
 
 impl ModuleRegistry {
@@ -125,9 +372,15 @@ impl ModuleRegistry {
             modules: Arc::new(RwLock::new(HashMap::new())),
             config,
             metrics: Arc::new(RwLock::new(HashMap::new())),
+            generation: Arc::new(AtomicU64::new(0)),
         }
     }
     
+    /// Registry of the modules loaded by the servers of the process
+    pub fn global() -> Self {
+        GLOBAL_REGISTRY.clone()
+    }
+
     /// Load module from file
     pub async fn load_module(&self, _name: &str, path: PathBuf) -> Result<(), ModuleError> {
         // Check if file exists
@@ -145,29 +398,65 @@ impl ModuleRegistry {
         // This is a production limitation that should be documented
         Err(ModuleError::LoadFailed("Dynamic module loading not supported in this build. Use built-in modules only.".to_string()))
     }
+
+    /// Register an initialized module, replacing the module of the same name
+    ///
+    /// Returns the shared handle of the new module and its generation. Handles
+    /// of a replaced module that are still in use keep working, the module is
+    /// cleaned up once the last of them is dropped.
+    pub fn register_module(&self, name: &str, module: Box<dyn IcapModule>) -> (Arc<dyn IcapModule>, u64) {
+        let module: Arc<dyn IcapModule> = Arc::new(ManagedModule {
+            name: name.to_string(),
+            module: Some(module),
+        });
+        let generation = self.generation.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut modules = self.modules.write().unwrap();
+        modules.insert(
+            name.to_string(),
+            RegisteredModule {
+                module: Arc::clone(&module),
+                generation,
+            },
+        );
+        (module, generation)
+    }
     
     /// Unload module
-    pub async fn unload_module(&self, name: &str) -> Result<(), ModuleError> {
-        let mut modules = self.modules.write().unwrap();
-        if let Some(mut module) = modules.remove(name) {
-            module.cleanup().await;
-            Ok(())
-        } else {
-            Err(ModuleError::NotFound(name.to_string()))
+    ///
+    /// The module is cleaned up once the transactions still using it drop
+    /// their handles.
+    pub fn unload_module(&self, name: &str) -> Result<(), ModuleError> {
+        let removed = self.modules.write().unwrap().remove(name);
+        let Some(registered) = removed else {
+            return Err(ModuleError::NotFound(name.to_string()));
+        };
+        self.metrics.write().unwrap().remove(name);
+
+        let handles = Arc::strong_count(&registered.module) - 1;
+        if handles > 0 {
+            log::debug!("module {name} unloaded with {handles} handles in use, cleaned up when they are dropped");
         }
+        Ok(())
     }
     
-    /// Get module by name
-    pub fn get_module(&self, name: &str) -> Option<Box<dyn IcapModule>> {
+    /// Get a shared handle of the module
+    pub fn get_module(&self, name: &str) -> Option<Arc<dyn IcapModule>> {
         let modules = self.modules.read().unwrap();
-        // Return a clone of the module if it exists
-        // Note: This requires cloning the trait object, which may not be possible
-        // depending on the module implementation. This is a design limitation.
-        modules.get(name).map(|_| {
-            // For now, return a default echo module as a fallback
-            // In a real implementation, this would need proper cloning or reference handling
-            Box::new(builtin::EchoModule::new()) as Box<dyn IcapModule>
-        })
+        modules.get(name).map(|r| Arc::clone(&r.module))
+    }
+
+    /// Get a shared handle of the module with its generation
+    pub fn get_module_with_generation(&self, name: &str) -> Option<(Arc<dyn IcapModule>, u64)> {
+        let modules = self.modules.read().unwrap();
+        modules
+            .get(name)
+            .map(|r| (Arc::clone(&r.module), r.generation))
+    }
+
+    /// Get the generation of the module, which changes each time it is replaced
+    pub fn module_generation(&self, name: &str) -> Option<u64> {
+        let modules = self.modules.read().unwrap();
+        modules.get(name).map(|r| r.generation)
     }
     
     /// List all loaded modules
@@ -177,9 +466,16 @@ impl ModuleRegistry {
     }
     
     /// Get module metrics
+    ///
+    /// The metrics updated in the registry win over the ones reported by the
+    /// module itself. The resource usage is the latest one of the process.
     pub fn get_module_metrics(&self, name: &str) -> Option<ModuleMetrics> {
-        let metrics = self.metrics.read().unwrap();
-        metrics.get(name).cloned()
+        let metrics = self.metrics.read().unwrap().get(name).cloned();
+        let metrics = metrics.or_else(|| self.get_module(name).map(|m| m.get_metrics()))?;
+        Some(match crate::stats::resource::latest() {
+            Some(usage) => metrics.with_resource_usage(&usage),
+            None => metrics,
+        })
     }
     
     /// Update module metrics
@@ -187,14 +483,134 @@ impl ModuleRegistry {
         let mut module_metrics = self.metrics.write().unwrap();
         module_metrics.insert(name.to_string(), metrics);
     }
+
+    /// Check if both registries hold the same modules
+    pub fn shares_modules(&self, other: &ModuleRegistry) -> bool {
+        Arc::ptr_eq(&self.modules, &other.modules)
+    }
+
+    /// Emit the metrics of each module, tagged with its name
+    ///
+    /// The metrics updated in the registry win over the ones reported by the
+    /// module itself.
+    pub fn emit_stats(&self, client: &mut StatsdClient, tags: &StatsdTagGroup) {
+        let modules: Vec<(String, RegisteredModule)> = {
+            let modules = self.modules.read().unwrap();
+            modules.iter().map(|(n, r)| (n.clone(), r.clone())).collect()
+        };
+        for (name, registered) in modules {
+            let metrics = self
+                .get_module_metrics(&name)
+                .unwrap_or_else(|| registered.module.get_metrics());
+            let mut tags = tags.clone();
+            tags.add_tag(TAG_KEY_MODULE, &name);
+            client
+                .count_with_tags(METRIC_NAME_MODULE_REQUESTS, metrics.requests_total, &tags)
+                .send();
+            client
+                .gauge_float_with_tags(METRIC_NAME_MODULE_ERROR_RATE, metrics.error_rate, &tags)
+                .send();
+            client
+                .gauge_with_tags(
+                    METRIC_NAME_MODULE_RESPONSE_TIME,
+                    metrics.average_response_time.as_millis() as u64,
+                    &tags,
+                )
+                .send();
+            client
+                .gauge_with_tags(
+                    METRIC_NAME_MODULE_HEALTHY,
+                    u8::from(registered.module.is_healthy()),
+                    &tags,
+                )
+                .send();
+            client
+                .gauge_with_tags(METRIC_NAME_MODULE_GENERATION, registered.generation, &tags)
+                .send();
+        }
+    }
 }
 
 /// Content filter module
 pub mod content_filter;
 
+/// Multi-pattern matchers
+pub mod matcher;
+
+/// Hyperscan backend of the regex pattern matcher
+#[cfg(feature = "hyperscan")]
+mod hyperscan;
+
+/// URL normalization for rule matching
+pub mod url_normalize;
+
+/// Memory-mapped frozen list stores
+pub mod list_store;
+
+/// Domain blocklist provider
+pub mod blocklist;
+
+/// Confusable domain detection
+pub mod confusable;
+
+/// Inspection of the text fields of AI API messages
+pub mod ai_inspection;
+
+/// Block page templates
+pub mod block_page;
+
+/// Escalation of repeated client violations
+pub mod escalation;
+
+/// File type detection from magic bytes
+pub mod file_type;
+
+/// Body capture on detection
+pub mod detection;
+
+/// Compiled regex cache
+pub mod regex_cache;
+
+/// Per-rule hit counters
+pub mod rule_hits;
+
+/// Shadow evaluation of candidate rule sets
+pub mod shadow;
+
+/// Security policies compiled into module configs
+pub mod policy;
+
 /// Antivirus module
 pub mod antivirus;
 
+/// Micro-batching of backend lookups
+pub mod batcher;
+
+/// Hedged requests to redundant backends
+pub mod hedge;
+
+/// Cache of the scan verdicts by content hash
+pub mod verdict_cache;
+
+/// Custom metrics registered by the modules
+pub mod metrics;
+
+/// Threat intelligence reputation lookups
+pub mod threat_intel;
+
+/// Content disarm and reconstruction module
+#[cfg(feature = "cdr")]
+pub mod cdr;
+
+/// ICAP header carrying the URL category assigned by the proxy
+pub const HEADER_URL_CATEGORY: &str = "x-url-category";
+
+/// Expression module of the scripted services
+pub mod expression;
+
+/// ModSecurity rule evaluation of the requests
+pub mod waf;
+
 /// Built-in modules
 pub mod builtin {
     use super::*;
@@ -234,7 +650,7 @@ pub mod builtin {
             Ok(())
         }
         
-        async fn handle_reqmod(&self, request: &IcapRequest) -> Result<IcapResponse, ModuleError> {
+        async fn handle_reqmod(&self, request: &IcapRequest, _ctx: &TransactionCtx) -> Result<IcapResponse, ModuleError> {
             // Echo the request back
             Ok(IcapResponse {
                 status: http::StatusCode::NO_CONTENT,
@@ -245,7 +661,7 @@ pub mod builtin {
             })
         }
         
-        async fn handle_respmod(&self, request: &IcapRequest) -> Result<IcapResponse, ModuleError> {
+        async fn handle_respmod(&self, request: &IcapRequest, _ctx: &TransactionCtx) -> Result<IcapResponse, ModuleError> {
             // Echo the request back
             Ok(IcapResponse {
                 status: http::StatusCode::NO_CONTENT,
@@ -319,7 +735,7 @@ pub mod builtin {
             Ok(())
         }
         
-        async fn handle_reqmod(&self, request: &IcapRequest) -> Result<IcapResponse, ModuleError> {
+        async fn handle_reqmod(&self, request: &IcapRequest, _ctx: &TransactionCtx) -> Result<IcapResponse, ModuleError> {
             // Log the request
             log::info!("REQMOD request: {:?} {}", request.method, request.uri);
             
@@ -333,7 +749,7 @@ pub mod builtin {
             })
         }
         
-        async fn handle_respmod(&self, request: &IcapRequest) -> Result<IcapResponse, ModuleError> {
+        async fn handle_respmod(&self, request: &IcapRequest, _ctx: &TransactionCtx) -> Result<IcapResponse, ModuleError> {
             // Log the request
             log::info!("RESPMOD request: {:?} {}", request.method, request.uri);
             
@@ -393,6 +809,15 @@ pub mod builtin {
                     enable_logging: true,
                     enable_metrics: true,
                     regex_cache_size: 1000,
+                    pattern_engine: Default::default(),
+                    user_rules: Default::default(),
+                    group_rules: Default::default(),
+                    detection_capture: Default::default(),
+                    block_page: Default::default(),
+                    blocked_file_types: Vec::new(),
+                    file_type_detection: Default::default(),
+                    confusable_domains: Default::default(),
+                    ai_inspection: Default::default(),
                 },
             }
         }
@@ -414,13 +839,13 @@ pub mod builtin {
 
         async fn init(&mut self, config: &ModuleConfig) -> Result<(), ModuleError> {
             // Load configuration
-            if let Ok(filter_config) = serde_json::from_value::<super::content_filter::ContentFilterConfig>(config.config.clone()) {
+            if let Some(filter_config) = config.parse_config()? {
                 self.config = filter_config;
             }
             Ok(())
         }
 
-        async fn handle_reqmod(&self, request: &IcapRequest) -> Result<IcapResponse, ModuleError> {
+        async fn handle_reqmod(&self, request: &IcapRequest, _ctx: &TransactionCtx) -> Result<IcapResponse, ModuleError> {
             // Simple content filtering implementation
             let uri = request.uri.to_string();
             let body = String::from_utf8_lossy(&request.body);
@@ -466,9 +891,9 @@ pub mod builtin {
             })
         }
 
-        async fn handle_respmod(&self, request: &IcapRequest) -> Result<IcapResponse, ModuleError> {
+        async fn handle_respmod(&self, request: &IcapRequest, ctx: &TransactionCtx) -> Result<IcapResponse, ModuleError> {
             // Similar to REQMOD but for responses
-            self.handle_reqmod(request).await
+            self.handle_reqmod(request, ctx).await
         }
 
         async fn handle_options(&self, request: &IcapRequest) -> Result<IcapResponse, ModuleError> {
@@ -530,6 +955,10 @@ pub mod builtin {
                     update_interval: std::time::Duration::from_secs(24 * 60 * 60), // 24 hours
                     enable_threat_intel: false,
                     threat_intel_sources: Vec::new(),
+                    threat_intel_batch: Default::default(),
+                    secondary_engine: None,
+                    hedging: Default::default(),
+                    verdict_cache: None,
                     yara_config: None,
                 },
             }
@@ -552,13 +981,13 @@ pub mod builtin {
 
         async fn init(&mut self, config: &ModuleConfig) -> Result<(), ModuleError> {
             // Load configuration
-            if let Ok(antivirus_config) = serde_json::from_value::<super::antivirus::AntivirusConfig>(config.config.clone()) {
+            if let Some(antivirus_config) = config.parse_config()? {
                 self.config = antivirus_config;
             }
             Ok(())
         }
 
-        async fn handle_reqmod(&self, request: &IcapRequest) -> Result<IcapResponse, ModuleError> {
+        async fn handle_reqmod(&self, request: &IcapRequest, _ctx: &TransactionCtx) -> Result<IcapResponse, ModuleError> {
             // Simple antivirus scanning implementation
             let body = String::from_utf8_lossy(&request.body);
 
@@ -586,9 +1015,9 @@ pub mod builtin {
             })
         }
 
-        async fn handle_respmod(&self, request: &IcapRequest) -> Result<IcapResponse, ModuleError> {
+        async fn handle_respmod(&self, request: &IcapRequest, ctx: &TransactionCtx) -> Result<IcapResponse, ModuleError> {
             // Similar to REQMOD but for responses
-            self.handle_reqmod(request).await
+            self.handle_reqmod(request, ctx).await
         }
 
         async fn handle_options(&self, request: &IcapRequest) -> Result<IcapResponse, ModuleError> {
@@ -619,3 +1048,136 @@ pub mod builtin {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use super::builtin::{EchoModule, LoggingModule};
+    use super::*;
+
+    fn registry() -> ModuleRegistry {
+        ModuleRegistry::new(ModuleConfig {
+            name: "test".to_string(),
+            path: PathBuf::from("/tmp"),
+            version: "1.0.0".to_string(),
+            config: serde_json::Value::Object(serde_json::Map::new()),
+            dependencies: Vec::new(),
+            load_timeout: Duration::from_secs(5),
+            max_memory: 1024 * 1024,
+            sandbox: true,
+            metrics: Default::default(),
+        })
+    }
+
+    /// Echo module counting its cleanups
+    struct CleanupProbe {
+        echo: EchoModule,
+        cleanups: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl IcapModule for CleanupProbe {
+        fn name(&self) -> &str {
+            "probe"
+        }
+
+        fn version(&self) -> &str {
+            self.echo.version()
+        }
+
+        fn supported_methods(&self) -> Vec<IcapMethod> {
+            self.echo.supported_methods()
+        }
+
+        async fn init(&mut self, config: &ModuleConfig) -> Result<(), ModuleError> {
+            self.echo.init(config).await
+        }
+
+        async fn handle_reqmod(&self, request: &IcapRequest, ctx: &TransactionCtx) -> Result<IcapResponse, ModuleError> {
+            self.echo.handle_reqmod(request, ctx).await
+        }
+
+        async fn handle_respmod(&self, request: &IcapRequest, ctx: &TransactionCtx) -> Result<IcapResponse, ModuleError> {
+            self.echo.handle_respmod(request, ctx).await
+        }
+
+        async fn handle_options(&self, request: &IcapRequest) -> Result<IcapResponse, ModuleError> {
+            self.echo.handle_options(request).await
+        }
+
+        fn is_healthy(&self) -> bool {
+            true
+        }
+
+        fn get_metrics(&self) -> ModuleMetrics {
+            self.echo.get_metrics()
+        }
+
+        async fn cleanup(&mut self) {
+            self.cleanups.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Let the spawned cleanups run, returning how many did
+    async fn cleanups(count: &AtomicUsize, expected: usize) -> usize {
+        for _ in 0..100 {
+            if count.load(Ordering::Relaxed) >= expected {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        count.load(Ordering::Relaxed)
+    }
+
+    #[tokio::test]
+    async fn hot_swap() {
+        let registry = registry();
+
+        let (_, generation) = registry.register_module("scan", Box::new(EchoModule::new()));
+        let first = registry.get_module("scan").unwrap();
+        let second = registry.get_module("scan").unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(first.name(), "echo");
+        assert_eq!(registry.module_generation("scan"), Some(generation));
+
+        // replacing the module bumps the generation, old handles keep working
+        let (_, new_generation) = registry.register_module("scan", Box::new(LoggingModule::new()));
+        assert!(new_generation > generation);
+        let (module, current) = registry.get_module_with_generation("scan").unwrap();
+        assert_eq!(current, new_generation);
+        assert_eq!(module.name(), "logging");
+        assert_eq!(first.name(), "echo");
+
+        drop(module);
+        registry.unload_module("scan").unwrap();
+        assert!(registry.get_module("scan").is_none());
+        assert!(registry.module_generation("scan").is_none());
+        assert!(registry.unload_module("scan").is_err());
+    }
+
+    #[tokio::test]
+    async fn cleanup_on_last_handle() {
+        let registry = registry();
+        let count = Arc::new(AtomicUsize::new(0));
+        let probe = || {
+            Box::new(CleanupProbe {
+                echo: EchoModule::new(),
+                cleanups: count.clone(),
+            })
+        };
+
+        // unloaded while in use, cleaned up when released
+        let (module, _) = registry.register_module("scan", probe());
+        registry.unload_module("scan").unwrap();
+        assert_eq!(cleanups(&count, 1).await, 0);
+        assert!(module.is_healthy());
+        drop(module);
+        assert_eq!(cleanups(&count, 1).await, 1);
+
+        // replaced and not in use
+        registry.register_module("scan", probe());
+        registry.register_module("scan", Box::new(EchoModule::new()));
+        assert_eq!(cleanups(&count, 2).await, 2);
+    }
+}
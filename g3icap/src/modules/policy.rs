@@ -0,0 +1,1027 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Security policies compiled into module configs
+//!
+//! The `arcus.v1` SecurityPolicy documents of the policy framework are
+//! compiled when the config is loaded into the rule sets of the content
+//! filter and the settings of the antivirus:
+//!
+//! - the blocking URL filtering rules become blocked domains, domain
+//!   patterns for the host patterns and keyword patterns for the URL ones,
+//! - the blocking DLP patterns and keywords become keyword patterns and
+//!   keywords, matched in the URIs and the bodies,
+//! - malware scanning enables the antivirus, with its action and timeout,
+//! - the traffic control quotas and per-user bandwidth become quota limits.
+//!
+//! The rules of a policy targeting users or groups go to the rule sets of
+//! these users and groups, on top of the default rules, the ones of a policy
+//! without targets to the default rules. The policies are compiled by
+//! decreasing priority. What the modules can not enforce, like the URL
+//! categories, the source networks or the non blocking actions, is skipped
+//! and reported. The policy rule each compiled rule comes from is kept by
+//! rule id, to tell which policy a verdict is due to.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use yaml_rust::{Yaml, YamlLoader};
+
+use super::content_filter::{ContentFilterConfig, FilterRuleSet};
+use crate::config::provenance::{self, ArtifactKind};
+use crate::config::server::quota::{self, QuotaLimits, QuotaRules};
+
+/// API version of the supported policy documents
+pub const API_VERSION: &str = "arcus.v1";
+/// Kind of the supported policy documents
+pub const POLICY_KIND: &str = "SecurityPolicy";
+
+/// Action of a policy rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyAction {
+    Allow,
+    Block,
+    Warn,
+    Inspect,
+    Quarantine,
+    Log,
+}
+
+impl PolicyAction {
+    /// Check if the action denies the transaction
+    fn is_blocking(&self) -> bool {
+        matches!(self, PolicyAction::Block | PolicyAction::Quarantine)
+    }
+}
+
+impl FromStr for PolicyAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "allow" => Ok(PolicyAction::Allow),
+            "block" => Ok(PolicyAction::Block),
+            "warn" => Ok(PolicyAction::Warn),
+            "inspect" => Ok(PolicyAction::Inspect),
+            "quarantine" => Ok(PolicyAction::Quarantine),
+            "log" => Ok(PolicyAction::Log),
+            _ => Err(anyhow!("invalid policy action {s}")),
+        }
+    }
+}
+
+/// How the patterns of a URL filtering rule match
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleType {
+    Wildcard,
+    Regex,
+    Exact,
+    Domain,
+    Suffix,
+}
+
+impl FromStr for RuleType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "wildcard" => Ok(RuleType::Wildcard),
+            "regex" => Ok(RuleType::Regex),
+            "exact" => Ok(RuleType::Exact),
+            "domain" => Ok(RuleType::Domain),
+            "suffix" => Ok(RuleType::Suffix),
+            _ => Err(anyhow!("invalid rule type {s}")),
+        }
+    }
+}
+
+/// Users, groups and networks a policy applies to
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PolicyTargets {
+    pub user_groups: Vec<String>,
+    pub users: Vec<String>,
+    pub source_networks: Vec<String>,
+}
+
+impl PolicyTargets {
+    fn is_empty(&self) -> bool {
+        self.user_groups.is_empty() && self.users.is_empty() && self.source_networks.is_empty()
+    }
+}
+
+/// Custom URL filtering rule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomRule {
+    pub name: String,
+    pub action: PolicyAction,
+    pub patterns: Vec<String>,
+    pub rule_type: RuleType,
+}
+
+/// URL filtering of a policy
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UrlFiltering {
+    /// Categories blocked, warned about or allowed
+    pub categories: Vec<String>,
+    pub custom_rules: Vec<CustomRule>,
+}
+
+/// Malware scanning of a policy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MalwareScanning {
+    pub enabled: bool,
+    pub action: PolicyAction,
+    pub timeout: Option<Duration>,
+}
+
+/// Sensitive data looked for by the DLP
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SensitiveDataPattern {
+    pub name: String,
+    pub pattern: Option<String>,
+    pub keywords: Vec<String>,
+    pub action: PolicyAction,
+}
+
+/// Data loss prevention of a policy
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DataLossPrevention {
+    pub enabled: bool,
+    pub sensitive_data_patterns: Vec<SensitiveDataPattern>,
+    pub service_presets: Vec<String>,
+}
+
+/// Traffic control of a policy
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrafficControl {
+    /// Daily and monthly data, and bandwidth, of each user
+    pub limits: QuotaLimits,
+    /// Bandwidth of all users together
+    pub total_bandwidth: Option<u64>,
+}
+
+/// A SecurityPolicy document, with the sections the modules enforce
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecurityPolicy {
+    pub name: String,
+    pub priority: u32,
+    pub enabled: bool,
+    pub targets: PolicyTargets,
+    pub url_filtering: Option<UrlFiltering>,
+    pub malware_scanning: Option<MalwareScanning>,
+    pub data_loss_prevention: Option<DataLossPrevention>,
+    pub traffic_control: Option<TrafficControl>,
+}
+
+impl SecurityPolicy {
+    /// Parse a policy document
+    ///
+    /// The keys may be in camel case, as written by the policy framework, or
+    /// in snake case. The sections enforced elsewhere, like the time
+    /// restrictions or the HTTPS inspection, are ignored.
+    pub fn parse(v: &Yaml) -> anyhow::Result<Self> {
+        let Yaml::Hash(map) = v else {
+            return Err(anyhow!("policy should be a map"));
+        };
+
+        let mut api_version = None;
+        let mut kind = None;
+        let mut name = None;
+        let mut spec = None;
+        g3_yaml::foreach_kv(map, |k, v| {
+            match g3_yaml::key::normalize(k).as_str() {
+                "apiversion" | "api_version" => api_version = Some(g3_yaml::value::as_string(v)?),
+                "kind" => kind = Some(g3_yaml::value::as_string(v)?),
+                "metadata" => name = Some(parse_metadata_name(v)?),
+                "spec" => spec = Some(v.clone()),
+                _ => return Err(anyhow!("invalid key {k} in policy")),
+            }
+            Ok(())
+        })?;
+
+        if api_version.as_deref() != Some(API_VERSION) {
+            return Err(anyhow!("policy apiVersion should be {API_VERSION}"));
+        }
+        if kind.as_deref() != Some(POLICY_KIND) {
+            return Err(anyhow!("policy kind should be {POLICY_KIND}"));
+        }
+        let name = name.ok_or_else(|| anyhow!("policy needs a metadata name"))?;
+        let mut policy = SecurityPolicy {
+            name,
+            priority: PRIORITY_DEFAULT,
+            enabled: true,
+            targets: PolicyTargets::default(),
+            url_filtering: None,
+            malware_scanning: None,
+            data_loss_prevention: None,
+            traffic_control: None,
+        };
+        if let Some(spec) = spec {
+            policy
+                .parse_spec(&spec)
+                .map_err(|e| anyhow!("invalid spec of policy {}: {e}", policy.name))?;
+        }
+        Ok(policy)
+    }
+
+    fn parse_spec(&mut self, v: &Yaml) -> anyhow::Result<()> {
+        let Yaml::Hash(map) = v else {
+            return Err(anyhow!("spec should be a map"));
+        };
+        g3_yaml::foreach_kv(map, |k, v| {
+            match g3_yaml::key::normalize(k).as_str() {
+                "priority" => self.priority = parse_priority(v)?,
+                "enabled" => self.enabled = g3_yaml::value::as_bool(v)?,
+                "targets" => self.targets = parse_targets(v)?,
+                "urlfiltering" | "url_filtering" => {
+                    self.url_filtering = Some(parse_url_filtering(v)?)
+                }
+                "contentsecurity" | "content_security" => self.parse_content_security(v)?,
+                "trafficcontrol" | "traffic_control" => {
+                    self.traffic_control = Some(parse_traffic_control(v)?)
+                }
+                "httpsinspection" | "https_inspection" | "audit" => {}
+                _ => return Err(anyhow!("invalid key {k}")),
+            }
+            Ok(())
+        })
+    }
+
+    fn parse_content_security(&mut self, v: &Yaml) -> anyhow::Result<()> {
+        let Yaml::Hash(map) = v else {
+            return Err(anyhow!("contentSecurity should be a map"));
+        };
+        g3_yaml::foreach_kv(map, |k, v| {
+            match g3_yaml::key::normalize(k).as_str() {
+                "malwarescanning" | "malware_scanning" => {
+                    self.malware_scanning = Some(parse_malware_scanning(v)?)
+                }
+                "datalossprevention" | "data_loss_prevention" => {
+                    self.data_loss_prevention = Some(parse_dlp(v)?)
+                }
+                _ => return Err(anyhow!("invalid key {k} in contentSecurity")),
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Priority of the policies not setting one
+const PRIORITY_DEFAULT: u32 = 100;
+
+fn parse_metadata_name(v: &Yaml) -> anyhow::Result<String> {
+    let Yaml::Hash(map) = v else {
+        return Err(anyhow!("metadata should be a map"));
+    };
+    let mut name = None;
+    g3_yaml::foreach_kv(map, |k, v| {
+        if g3_yaml::key::normalize(k) == "name" {
+            name = Some(g3_yaml::value::as_string(v)?);
+        }
+        Ok(())
+    })?;
+    name.ok_or_else(|| anyhow!("metadata needs a name"))
+}
+
+/// Parse a priority, a number or a level of the policy framework
+fn parse_priority(v: &Yaml) -> anyhow::Result<u32> {
+    if let Yaml::String(s) = v {
+        return match s.to_lowercase().as_str() {
+            "critical" => Ok(1000),
+            "high" => Ok(800),
+            "medium" => Ok(500),
+            "low" => Ok(200),
+            "default" => Ok(PRIORITY_DEFAULT),
+            _ => Err(anyhow!("invalid priority {s}")),
+        };
+    }
+    g3_yaml::value::as_u32(v)
+}
+
+fn parse_strings(v: &Yaml) -> anyhow::Result<Vec<String>> {
+    g3_yaml::value::as_list(v, g3_yaml::value::as_string)
+}
+
+fn parse_action(v: &Yaml) -> anyhow::Result<PolicyAction> {
+    g3_yaml::value::as_string(v)?.parse()
+}
+
+fn parse_targets(v: &Yaml) -> anyhow::Result<PolicyTargets> {
+    let Yaml::Hash(map) = v else {
+        return Err(anyhow!("targets should be a map"));
+    };
+    let mut targets = PolicyTargets::default();
+    g3_yaml::foreach_kv(map, |k, v| {
+        match g3_yaml::key::normalize(k).as_str() {
+            "usergroups" | "user_groups" => targets.user_groups = parse_strings(v)?,
+            "users" => targets.users = parse_strings(v)?,
+            "sourcenetworks" | "source_networks" => targets.source_networks = parse_strings(v)?,
+            _ => return Err(anyhow!("invalid key {k} in targets")),
+        }
+        Ok(())
+    })?;
+    Ok(targets)
+}
+
+fn parse_url_filtering(v: &Yaml) -> anyhow::Result<UrlFiltering> {
+    let Yaml::Hash(map) = v else {
+        return Err(anyhow!("urlFiltering should be a map"));
+    };
+    let mut filtering = UrlFiltering::default();
+    g3_yaml::foreach_kv(map, |k, v| {
+        match g3_yaml::key::normalize(k).as_str() {
+            "categories" => {
+                let Yaml::Hash(map) = v else {
+                    return Err(anyhow!("categories should be a map"));
+                };
+                g3_yaml::foreach_kv(map, |_, v| {
+                    filtering.categories.extend(parse_strings(v)?);
+                    Ok(())
+                })?;
+            }
+            "customrules" | "custom_rules" => {
+                filtering.custom_rules = g3_yaml::value::as_list(v, parse_custom_rule)?
+            }
+            _ => return Err(anyhow!("invalid key {k} in urlFiltering")),
+        }
+        Ok(())
+    })?;
+    Ok(filtering)
+}
+
+fn parse_custom_rule(v: &Yaml) -> anyhow::Result<CustomRule> {
+    let Yaml::Hash(map) = v else {
+        return Err(anyhow!("custom rule should be a map"));
+    };
+    let mut name = None;
+    let mut action = None;
+    let mut patterns = Vec::new();
+    let mut rule_type = RuleType::Wildcard;
+    g3_yaml::foreach_kv(map, |k, v| {
+        match g3_yaml::key::normalize(k).as_str() {
+            "name" => name = Some(g3_yaml::value::as_string(v)?),
+            "action" => action = Some(parse_action(v)?),
+            "pattern" => patterns.push(g3_yaml::value::as_string(v)?),
+            "patterns" => patterns.extend(parse_strings(v)?),
+            "type" | "ruletype" | "rule_type" => {
+                rule_type = g3_yaml::value::as_string(v)?.parse()?
+            }
+            "message" | "priority" => {}
+            _ => return Err(anyhow!("invalid key {k} in custom rule")),
+        }
+        Ok(())
+    })?;
+    let name = name.ok_or_else(|| anyhow!("custom rule needs a name"))?;
+    if patterns.is_empty() {
+        return Err(anyhow!("custom rule {name} needs a pattern"));
+    }
+    Ok(CustomRule {
+        action: action.ok_or_else(|| anyhow!("custom rule {name} needs an action"))?,
+        name,
+        patterns,
+        rule_type,
+    })
+}
+
+fn parse_malware_scanning(v: &Yaml) -> anyhow::Result<MalwareScanning> {
+    let Yaml::Hash(map) = v else {
+        return Err(anyhow!("malwareScanning should be a map"));
+    };
+    let mut scanning = MalwareScanning {
+        enabled: true,
+        action: PolicyAction::Block,
+        timeout: None,
+    };
+    g3_yaml::foreach_kv(map, |k, v| {
+        match g3_yaml::key::normalize(k).as_str() {
+            "enabled" => scanning.enabled = g3_yaml::value::as_bool(v)?,
+            "action" => scanning.action = parse_action(v)?,
+            "timeout" => scanning.timeout = Some(g3_yaml::humanize::as_duration(v)?),
+            "icapserver" | "icap_server" => {}
+            _ => return Err(anyhow!("invalid key {k} in malwareScanning")),
+        }
+        Ok(())
+    })?;
+    Ok(scanning)
+}
+
+fn parse_dlp(v: &Yaml) -> anyhow::Result<DataLossPrevention> {
+    let Yaml::Hash(map) = v else {
+        return Err(anyhow!("dataLossPrevention should be a map"));
+    };
+    let mut dlp = DataLossPrevention {
+        enabled: true,
+        ..Default::default()
+    };
+    g3_yaml::foreach_kv(map, |k, v| {
+        match g3_yaml::key::normalize(k).as_str() {
+            "enabled" => dlp.enabled = g3_yaml::value::as_bool(v)?,
+            "scanuploads" | "scan_uploads" | "scandownloads" | "scan_downloads" => {
+                g3_yaml::value::as_bool(v)?;
+            }
+            "sensitivedatapatterns" | "sensitive_data_patterns" => {
+                dlp.sensitive_data_patterns = g3_yaml::value::as_list(v, parse_sensitive_data)?
+            }
+            "servicepresets" | "service_presets" => dlp.service_presets = parse_strings(v)?,
+            _ => return Err(anyhow!("invalid key {k} in dataLossPrevention")),
+        }
+        Ok(())
+    })?;
+    Ok(dlp)
+}
+
+fn parse_traffic_control(v: &Yaml) -> anyhow::Result<TrafficControl> {
+    let Yaml::Hash(map) = v else {
+        return Err(anyhow!("trafficControl should be a map"));
+    };
+    let mut traffic = TrafficControl::default();
+    g3_yaml::foreach_kv(map, |k, v| {
+        match g3_yaml::key::normalize(k).as_str() {
+            "bandwidthlimits" | "bandwidth_limits" => {
+                let Yaml::Hash(map) = v else {
+                    return Err(anyhow!("bandwidthLimits should be a map"));
+                };
+                g3_yaml::foreach_kv(map, |k, v| {
+                    match g3_yaml::key::normalize(k).as_str() {
+                        "peruser" | "per_user" => {
+                            traffic.limits.bandwidth = parse_limit(v, quota::parse_bandwidth)?
+                        }
+                        "total" => traffic.total_bandwidth = parse_limit(v, quota::parse_bandwidth)?,
+                        _ => return Err(anyhow!("invalid key {k} in bandwidthLimits")),
+                    }
+                    Ok(())
+                })?;
+            }
+            "quotas" => {
+                let Yaml::Hash(map) = v else {
+                    return Err(anyhow!("quotas should be a map"));
+                };
+                g3_yaml::foreach_kv(map, |k, v| {
+                    match g3_yaml::key::normalize(k).as_str() {
+                        "dailydataperuser" | "daily_data_per_user" => {
+                            traffic.limits.daily = parse_limit(v, g3_yaml::humanize::as_u64)?
+                        }
+                        "monthlydataperuser" | "monthly_data_per_user" => {
+                            traffic.limits.monthly = parse_limit(v, g3_yaml::humanize::as_u64)?
+                        }
+                        _ => return Err(anyhow!("invalid key {k} in quotas")),
+                    }
+                    Ok(())
+                })?;
+            }
+            "timerestrictions" | "time_restrictions" => {}
+            _ => return Err(anyhow!("invalid key {k} in trafficControl")),
+        }
+        Ok(())
+    })?;
+    Ok(traffic)
+}
+
+/// Parse a limit, not set if empty as written by the policy editor
+fn parse_limit(
+    v: &Yaml,
+    parse: fn(&Yaml) -> anyhow::Result<u64>,
+) -> anyhow::Result<Option<u64>> {
+    match v {
+        Yaml::String(s) if s.trim().is_empty() => Ok(None),
+        Yaml::Null => Ok(None),
+        _ => parse(v).map(Some),
+    }
+}
+
+fn parse_sensitive_data(v: &Yaml) -> anyhow::Result<SensitiveDataPattern> {
+    let Yaml::Hash(map) = v else {
+        return Err(anyhow!("sensitive data pattern should be a map"));
+    };
+    let mut name = None;
+    let mut pattern = None;
+    let mut keywords = Vec::new();
+    let mut action = PolicyAction::Block;
+    g3_yaml::foreach_kv(map, |k, v| {
+        match g3_yaml::key::normalize(k).as_str() {
+            "name" => name = Some(g3_yaml::value::as_string(v)?),
+            "pattern" => pattern = Some(g3_yaml::value::as_string(v)?),
+            "keywords" => keywords = parse_strings(v)?,
+            "action" => action = parse_action(v)?,
+            _ => return Err(anyhow!("invalid key {k} in sensitive data pattern")),
+        }
+        Ok(())
+    })?;
+    let name = name.ok_or_else(|| anyhow!("sensitive data pattern needs a name"))?;
+    if let Some(pattern) = &pattern {
+        regex::Regex::new(pattern)
+            .map_err(|e| anyhow!("invalid pattern of sensitive data {name}: {e}"))?;
+    }
+    Ok(SensitiveDataPattern {
+        name,
+        pattern,
+        keywords,
+        action,
+    })
+}
+
+/// Kinds of the content filter rules compiled from the policies, as in the
+/// rule ids
+const RULE_KINDS: [&str; 4] = ["domain", "domain_pattern", "keyword", "keyword_pattern"];
+
+fn rule_counts(rules: &FilterRuleSet) -> [usize; RULE_KINDS.len()] {
+    [
+        rules.blocked_domains.len(),
+        rules.blocked_domain_patterns.len(),
+        rules.blocked_keywords.len(),
+        rules.blocked_keyword_patterns.len(),
+    ]
+}
+
+/// Policy rule a compiled rule comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleOrigin {
+    pub policy: String,
+    pub rule: String,
+}
+
+/// Rules compiled from a policy, with the policy rule of each one
+#[derive(Default)]
+struct PolicyRules {
+    policy: String,
+    rules: FilterRuleSet,
+    /// Name of the policy rule of each rule, by kind of rule
+    names: [Vec<String>; RULE_KINDS.len()],
+}
+
+impl PolicyRules {
+    fn new(policy: &SecurityPolicy) -> Self {
+        PolicyRules {
+            policy: policy.name.clone(),
+            rules: FilterRuleSet {
+                inherit_default: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Name the rules added since the last call
+    fn name_added(&mut self, rule: &str) {
+        for (names, len) in self.names.iter_mut().zip(rule_counts(&self.rules)) {
+            names.resize(len, rule.to_string());
+        }
+    }
+
+    /// Add the rules to the rule set of the name, recording their origin by
+    /// their id in it
+    fn extend(
+        &self,
+        name: &str,
+        rules: &mut FilterRuleSet,
+        origins: &mut HashMap<String, RuleOrigin>,
+    ) {
+        for ((kind, names), base) in RULE_KINDS.iter().zip(&self.names).zip(rule_counts(rules)) {
+            for (i, rule) in names.iter().enumerate() {
+                origins.insert(
+                    format!("{name}:{kind}:{}", base + i),
+                    RuleOrigin {
+                        policy: self.policy.clone(),
+                        rule: rule.clone(),
+                    },
+                );
+            }
+        }
+        extend_rules(rules, &self.rules);
+    }
+}
+
+/// Module settings compiled from the policies
+#[derive(Debug, Clone, Default)]
+pub struct CompiledPolicy {
+    /// Names of the compiled policies, by decreasing priority
+    pub policies: Vec<String>,
+    /// Rules of the policies without targets
+    pub default_rules: FilterRuleSet,
+    /// Rule sets keyed by user name
+    pub user_rules: HashMap<String, FilterRuleSet>,
+    /// Rule sets keyed by group name
+    pub group_rules: HashMap<String, FilterRuleSet>,
+    /// Malware scanning of the policy of the highest priority setting it
+    pub malware_scanning: Option<MalwareScanning>,
+    /// Quota limits, each one from the policy of the highest priority
+    /// setting it for the target
+    pub quotas: QuotaRules,
+    /// What could not be compiled, as `<policy>: <reason>`
+    pub skipped: Vec<String>,
+    /// Policy rule of each compiled rule, by content filter rule id
+    pub origins: HashMap<String, RuleOrigin>,
+}
+
+impl CompiledPolicy {
+    /// Load and compile the policies of the files
+    ///
+    /// A directory path stands for its `.yaml` and `.yml` files, in name
+    /// order. A file may hold several policy documents.
+    pub fn load(paths: &[PathBuf]) -> anyhow::Result<Self> {
+        let mut policies = Vec::new();
+        for path in paths {
+            if path.is_dir() {
+                let mut files = Vec::new();
+                for entry in std::fs::read_dir(path)
+                    .map_err(|e| anyhow!("failed to read policy dir {}: {e}", path.display()))?
+                {
+                    let file = entry?.path();
+                    if file.is_file()
+                        && file
+                            .extension()
+                            .is_some_and(|ext| ext == "yaml" || ext == "yml")
+                    {
+                        files.push(file);
+                    }
+                }
+                files.sort();
+                for file in files {
+                    policies.extend(load_file(&file)?);
+                }
+            } else {
+                policies.extend(load_file(path)?);
+            }
+        }
+        Ok(Self::compile(policies))
+    }
+
+    /// Compile the policies, by decreasing priority then name
+    pub fn compile(mut policies: Vec<SecurityPolicy>) -> Self {
+        policies.retain(|p| p.enabled);
+        policies.sort_by(|a, b| b.priority.cmp(&a.priority).then(a.name.cmp(&b.name)));
+
+        let mut compiled = CompiledPolicy::default();
+        for policy in &policies {
+            compiled.policies.push(policy.name.clone());
+            let mut rules = PolicyRules::new(policy);
+            compiled.compile_policy(policy, &mut rules);
+
+            let limits = policy
+                .traffic_control
+                .as_ref()
+                .map(|t| t.limits)
+                .unwrap_or_default();
+
+            let targets = &policy.targets;
+            if targets.is_empty() {
+                rules.extend("default", &mut compiled.default_rules, &mut compiled.origins);
+                extend_limits(&mut compiled.quotas.default, limits);
+                continue;
+            }
+            for user in &targets.users {
+                rules.extend(
+                    &format!("user:{user}"),
+                    compiled.user_rules.entry(user.clone()).or_default(),
+                    &mut compiled.origins,
+                );
+                extend_limits(compiled.quotas.users.entry(user.clone()).or_default(), limits);
+            }
+            for group in &targets.user_groups {
+                rules.extend(
+                    &format!("group:{group}"),
+                    compiled.group_rules.entry(group.clone()).or_default(),
+                    &mut compiled.origins,
+                );
+                extend_limits(compiled.quotas.groups.entry(group.clone()).or_default(), limits);
+            }
+            if !targets.source_networks.is_empty() {
+                compiled.skip(policy, "source network targets are not supported");
+            }
+        }
+        for rules in compiled
+            .user_rules
+            .values_mut()
+            .chain(compiled.group_rules.values_mut())
+        {
+            rules.inherit_default = true;
+        }
+        compiled
+    }
+
+    fn skip(&mut self, policy: &SecurityPolicy, reason: &str) {
+        self.skipped.push(format!("{}: {reason}", policy.name));
+    }
+
+    fn compile_policy(&mut self, policy: &SecurityPolicy, compiled: &mut PolicyRules) {
+        if let Some(filtering) = &policy.url_filtering {
+            if !filtering.categories.is_empty() {
+                self.skip(policy, "URL categories are not supported");
+            }
+            for rule in &filtering.custom_rules {
+                if !rule.action.is_blocking() {
+                    self.skip(
+                        policy,
+                        &format!("action of rule {} is not supported", rule.name),
+                    );
+                    continue;
+                }
+                for pattern in &rule.patterns {
+                    if let Err(e) = compile_url_pattern(rule.rule_type, pattern, &mut compiled.rules)
+                    {
+                        self.skip(policy, &format!("pattern of rule {}: {e}", rule.name));
+                    }
+                }
+                compiled.name_added(&rule.name);
+            }
+        }
+
+        if let Some(dlp) = policy.data_loss_prevention.as_ref().filter(|d| d.enabled) {
+            if !dlp.service_presets.is_empty() {
+                self.skip(
+                    policy,
+                    "DLP service presets are not supported, all traffic is scanned",
+                );
+            }
+            for data in &dlp.sensitive_data_patterns {
+                if !data.action.is_blocking() {
+                    self.skip(
+                        policy,
+                        &format!("action of sensitive data {} is not supported", data.name),
+                    );
+                    continue;
+                }
+                let rules = &mut compiled.rules;
+                rules.blocked_keyword_patterns.extend(data.pattern.clone());
+                rules.blocked_keywords.extend(data.keywords.iter().cloned());
+                compiled.name_added(&data.name);
+            }
+        }
+
+        if policy
+            .traffic_control
+            .as_ref()
+            .is_some_and(|t| t.total_bandwidth.is_some())
+        {
+            self.skip(policy, "total bandwidth limit is not supported");
+        }
+
+        if let Some(scanning) = &policy.malware_scanning {
+            if scanning.enabled && !scanning.action.is_blocking() {
+                self.skip(policy, "action of malware scanning is not supported");
+                return;
+            }
+            if !policy.targets.is_empty() {
+                self.skip(policy, "malware scanning applies to all users");
+            }
+            if self.malware_scanning.is_none() {
+                self.malware_scanning = Some(scanning.clone());
+            }
+        }
+    }
+
+    /// Replace the rules of the content filter config with the compiled ones
+    ///
+    /// The MIME types, extensions and file types, not set by the policies,
+    /// are kept.
+    pub fn apply_content_filter(&self, config: &mut ContentFilterConfig) {
+        let rules = &self.default_rules;
+        config.blocked_domains = rules.blocked_domains.clone();
+        config.blocked_domain_patterns = rules.blocked_domain_patterns.clone();
+        config.blocked_keywords = rules.blocked_keywords.clone();
+        config.blocked_keyword_patterns = rules.blocked_keyword_patterns.clone();
+        config.user_rules = self.user_rules.clone();
+        config.group_rules = self.group_rules.clone();
+    }
+}
+
+fn load_file(path: &Path) -> anyhow::Result<Vec<SecurityPolicy>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("failed to read policy file {}: {e}", path.display()))?;
+    provenance::check_file(ArtifactKind::Policy, path, text.as_bytes())?;
+    let docs = YamlLoader::load_from_str(&text)
+        .map_err(|e| anyhow!("invalid policy file {}: {e}", path.display()))?;
+    docs.iter()
+        .map(|doc| {
+            SecurityPolicy::parse(doc)
+                .map_err(|e| anyhow!("invalid policy file {}: {e}", path.display()))
+        })
+        .collect()
+}
+
+/// Take the limits of a policy of lower priority not set yet
+fn extend_limits(limits: &mut QuotaLimits, other: QuotaLimits) {
+    *limits = limits.or(other);
+}
+
+fn extend_rules(rules: &mut FilterRuleSet, other: &FilterRuleSet) {
+    rules
+        .blocked_domains
+        .extend(other.blocked_domains.iter().cloned());
+    rules
+        .blocked_domain_patterns
+        .extend(other.blocked_domain_patterns.iter().cloned());
+    rules
+        .blocked_keywords
+        .extend(other.blocked_keywords.iter().cloned());
+    rules
+        .blocked_keyword_patterns
+        .extend(other.blocked_keyword_patterns.iter().cloned());
+}
+
+/// Add a blocking URL pattern to the rules
+///
+/// The patterns without a path are matched against the host, the others
+/// against the URI.
+fn compile_url_pattern(
+    rule_type: RuleType,
+    pattern: &str,
+    rules: &mut FilterRuleSet,
+) -> anyhow::Result<()> {
+    let is_host = !pattern.contains('/');
+    let regex = match rule_type {
+        RuleType::Domain if is_host => {
+            rules
+                .blocked_domains
+                .push(pattern.trim_start_matches("*.").to_string());
+            return Ok(());
+        }
+        RuleType::Domain => return Err(anyhow!("domain {pattern} has a path")),
+        RuleType::Regex => {
+            regex::Regex::new(pattern).map_err(|e| anyhow!("invalid regex: {e}"))?;
+            pattern.to_string()
+        }
+        RuleType::Wildcard => {
+            let escaped = regex::escape(pattern)
+                .replace(r"\*", ".*")
+                .replace(r"\?", ".");
+            format!("^{escaped}$")
+        }
+        RuleType::Exact => format!("^{}$", regex::escape(pattern)),
+        RuleType::Suffix => format!("{}$", regex::escape(pattern)),
+    };
+    if is_host {
+        rules.blocked_domain_patterns.push(regex);
+    } else {
+        rules.blocked_keyword_patterns.push(regex);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICIES: &str = r#"
+apiVersion: arcus.v1
+kind: SecurityPolicy
+metadata:
+  name: staff-web
+spec:
+  priority: 100
+  targets:
+    userGroups: [staff]
+    sourceNetworks: [10.0.0.0/8]
+  trafficControl:
+    bandwidthLimits:
+      perUser: 125000
+      total: ''
+    quotas:
+      dailyDataPerUser: 2000000000
+      monthlyDataPerUser: ''
+  urlFiltering:
+    categories:
+      block: [gambling]
+    customRules:
+      - name: block-crypto
+        action: block
+        pattern: "*.crypto*"
+        type: wildcard
+      - name: block-casino
+        action: block
+        patterns: [casino.example, "*.poker.example"]
+        type: domain
+      - name: warn-news
+        action: warn
+        pattern: news.example
+        type: domain
+---
+apiVersion: arcus.v1
+kind: SecurityPolicy
+metadata:
+  name: global
+spec:
+  priority: high
+  urlFiltering:
+    customRules:
+      - name: block-downloads
+        action: block
+        pattern: "http://example.com/setup.exe"
+        type: exact
+  contentSecurity:
+    malwareScanning:
+      enabled: true
+      action: quarantine
+      timeout: 10s
+    dataLossPrevention:
+      enabled: true
+      sensitiveDataPatterns:
+        - name: ssn
+          pattern: '\d{3}-\d{2}-\d{4}'
+          action: block
+        - name: internal
+          keywords: [confidential]
+          action: log
+"#;
+
+    fn policies() -> Vec<SecurityPolicy> {
+        YamlLoader::load_from_str(POLICIES)
+            .unwrap()
+            .iter()
+            .map(|doc| SecurityPolicy::parse(doc).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn parse() {
+        let policies = policies();
+        assert_eq!(policies[0].name, "staff-web");
+        assert_eq!(policies[0].targets.user_groups, ["staff"]);
+        let filtering = policies[0].url_filtering.as_ref().unwrap();
+        assert_eq!(filtering.categories, ["gambling"]);
+        assert_eq!(filtering.custom_rules[1].rule_type, RuleType::Domain);
+        assert_eq!(filtering.custom_rules[1].patterns.len(), 2);
+        assert_eq!(policies[1].priority, 800);
+        let scanning = policies[1].malware_scanning.as_ref().unwrap();
+        assert_eq!(scanning.action, PolicyAction::Quarantine);
+        assert_eq!(scanning.timeout, Some(Duration::from_secs(10)));
+
+        let doc = YamlLoader::load_from_str(
+            "apiVersion: arcus.v2\nkind: SecurityPolicy\nmetadata: {name: x}",
+        )
+        .unwrap();
+        assert!(SecurityPolicy::parse(&doc[0]).is_err());
+    }
+
+    #[test]
+    fn compile() {
+        let compiled = CompiledPolicy::compile(policies());
+        assert_eq!(compiled.policies, ["global", "staff-web"]);
+
+        let default = &compiled.default_rules;
+        assert_eq!(
+            default.blocked_keyword_patterns,
+            [r"^http://example\.com/setup\.exe$", r"\d{3}-\d{2}-\d{4}"]
+        );
+        assert!(default.blocked_keywords.is_empty());
+
+        let staff = &compiled.group_rules["staff"];
+        assert!(staff.inherit_default);
+        assert_eq!(staff.blocked_domain_patterns, [r"^.*\.crypto.*$"]);
+        assert_eq!(staff.blocked_domains, ["casino.example", "poker.example"]);
+        let pattern = regex::Regex::new(&staff.blocked_domain_patterns[0]).unwrap();
+        assert!(pattern.is_match("www.crypto-exchange.example"));
+
+        assert_eq!(
+            compiled.malware_scanning.as_ref().map(|s| s.action),
+            Some(PolicyAction::Quarantine)
+        );
+        assert_eq!(compiled.skipped.len(), 4);
+        assert!(
+            compiled.skipped.contains(
+                &"global: action of sensitive data internal is not supported".to_string()
+            )
+        );
+        assert!(
+            compiled
+                .skipped
+                .contains(&"staff-web: source network targets are not supported".to_string())
+        );
+
+        let mut config = ContentFilterConfig::default();
+        compiled.apply_content_filter(&mut config);
+        assert_eq!(
+            config.blocked_keyword_patterns,
+            default.blocked_keyword_patterns
+        );
+        assert!(config.group_rules.contains_key("staff"));
+
+        let staff = compiled.quotas.limits("bob", &["staff".to_string()]);
+        assert_eq!(staff.daily, Some(2_000_000_000));
+        assert_eq!(staff.bandwidth, Some(125_000));
+        assert_eq!(staff.monthly, None);
+        assert!(compiled.quotas.limits("bob", &[]).is_empty());
+
+        let origin = |policy: &str, rule: &str| RuleOrigin {
+            policy: policy.to_string(),
+            rule: rule.to_string(),
+        };
+        assert_eq!(
+            compiled.origins["default:keyword_pattern:1"],
+            origin("global", "ssn")
+        );
+        assert_eq!(
+            compiled.origins["group:staff:domain:1"],
+            origin("staff-web", "block-casino")
+        );
+        assert_eq!(
+            compiled.origins["group:staff:domain_pattern:0"],
+            origin("staff-web", "block-crypto")
+        );
+    }
+}
@@ -0,0 +1,146 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Bounded cache of compiled regular expressions
+//!
+//! Rule sets of different users and groups often share patterns, and most
+//! patterns survive a configuration reload. Compiled expressions are kept
+//! in a LRU cache so that they are only compiled once.
+
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use lru::LruCache;
+use regex::Regex;
+
+/// LRU cache of compiled regular expressions
+#[derive(Debug)]
+pub struct RegexCache {
+    /// `None` if caching is disabled
+    cache: Mutex<Option<LruCache<String, Regex>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl RegexCache {
+    /// Create a cache holding at most `capacity` expressions, 0 disables caching
+    pub fn new(capacity: usize) -> Self {
+        RegexCache {
+            cache: Mutex::new(NonZeroUsize::new(capacity).map(LruCache::new)),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Change the capacity, evicting the least recently used expressions if needed
+    pub fn resize(&self, capacity: usize) {
+        let mut cache = self.cache.lock().unwrap();
+        let Some(capacity) = NonZeroUsize::new(capacity) else {
+            *cache = None;
+            return;
+        };
+        if let Some(c) = cache.as_mut() {
+            c.resize(capacity);
+        } else {
+            *cache = Some(LruCache::new(capacity));
+        }
+    }
+
+    /// Get the compiled expression of the pattern, compiling it if not cached
+    pub fn get_or_compile(&self, pattern: &str) -> Result<Regex, regex::Error> {
+        let mut cache = self.cache.lock().unwrap();
+        let Some(cache) = cache.as_mut() else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return Regex::new(pattern);
+        };
+
+        if let Some(regex) = cache.get(pattern) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(regex.clone());
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let regex = Regex::new(pattern)?;
+        cache.put(pattern.to_string(), regex.clone());
+        Ok(regex)
+    }
+
+    /// Number of cached expressions
+    pub fn len(&self) -> usize {
+        self.cache
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|c| c.len())
+            .unwrap_or(0)
+    }
+
+    /// Check if no expression is cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Lookups served from the cache
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Lookups that needed a compilation
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Ratio of lookups served from the cache
+    pub fn hit_rate(&self) -> f64 {
+        let hits = self.hits();
+        let total = hits + self.misses();
+        if total == 0 {
+            return 0.0;
+        }
+        hits as f64 / total as f64
+    }
+
+    /// Drop all cached expressions
+    pub fn clear(&self) {
+        if let Some(cache) = self.cache.lock().unwrap().as_mut() {
+            cache.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lru_eviction() {
+        let cache = RegexCache::new(2);
+        cache.get_or_compile("a+").unwrap();
+        cache.get_or_compile("b+").unwrap();
+        cache.get_or_compile("a+").unwrap();
+        cache.get_or_compile("c+").unwrap(); // evicts b+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.hits(), 1);
+
+        cache.get_or_compile("b+").unwrap();
+        assert_eq!(cache.misses(), 4);
+        assert!(cache.get_or_compile("(").is_err());
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn disabled() {
+        let cache = RegexCache::new(0);
+        cache.get_or_compile("a+").unwrap();
+        cache.get_or_compile("a+").unwrap();
+        assert_eq!(cache.hits(), 0);
+        assert!(cache.is_empty());
+
+        cache.resize(8);
+        cache.get_or_compile("a+").unwrap();
+        cache.get_or_compile("a+").unwrap();
+        assert_eq!(cache.hits(), 1);
+    }
+}
@@ -0,0 +1,321 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Per-rule hit counters
+//!
+//! Filter modules register the ids of their rules when they are initialized
+//! and record a hit each time a rule matches. The counters keep the number
+//! of hits and the time of the last one, so that rules which never match can
+//! be found and pruned. A rule that was never hit ages from the time it was
+//! registered, a reload keeps the counters of the rules that are still there.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+static GLOBAL_RULE_HITS: LazyLock<RuleHits> = LazyLock::new(RuleHits::default);
+
+/// Get the rule hit counters of this process
+pub fn global() -> &'static RuleHits {
+    &GLOBAL_RULE_HITS
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Category of a rule id, its kind without the rule set and the index
+///
+/// `group:staff:domain_pattern:2` is in category `domain_pattern`.
+pub fn category(rule_id: &str) -> &str {
+    let id = match rule_id.rsplit_once(':') {
+        Some((head, index)) if !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()) => {
+            head
+        }
+        _ => rule_id,
+    };
+    id.rsplit(':').next().unwrap_or(id)
+}
+
+#[derive(Debug)]
+struct RuleCounter {
+    hits: AtomicU64,
+    /// Unix time of the last hit, 0 if never hit
+    last_hit: AtomicU64,
+    /// Unix time the rule was registered
+    registered: u64,
+}
+
+impl RuleCounter {
+    fn new(registered: u64) -> Self {
+        RuleCounter {
+            hits: AtomicU64::new(0),
+            last_hit: AtomicU64::new(0),
+            registered,
+        }
+    }
+
+    /// Unix time the rule was last active, its last hit or its registration
+    fn last_active(&self) -> u64 {
+        match self.last_hit.load(Ordering::Relaxed) {
+            0 => self.registered,
+            t => t,
+        }
+    }
+}
+
+type ModuleRules = HashMap<String, Arc<RuleCounter>>;
+
+/// Hit counters of the rules of all modules
+#[derive(Debug, Default)]
+pub struct RuleHits {
+    modules: RwLock<HashMap<String, ModuleRules>>,
+}
+
+/// Hits of a single rule
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuleHitStats {
+    pub module: String,
+    pub rule: String,
+    pub category: String,
+    pub hits: u64,
+    /// Unix time of the last hit
+    pub last_hit: Option<u64>,
+    /// Whole days since the last hit, or since the rule was registered
+    pub idle_days: u64,
+}
+
+/// Hits of all the rules of a category of a module
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CategoryHitStats {
+    pub module: String,
+    pub category: String,
+    pub rules: usize,
+    pub hits: u64,
+    pub last_hit: Option<u64>,
+}
+
+/// Snapshot of the rule hit counters
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RuleHitReport {
+    pub rules: Vec<RuleHitStats>,
+    pub categories: Vec<CategoryHitStats>,
+}
+
+impl RuleHitReport {
+    /// Serialize the report as one line of JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+impl RuleHits {
+    /// Set the rules of a module, replacing the previous ones
+    ///
+    /// Counters of the rules which are still registered are kept.
+    pub fn register<I>(&self, module: &str, rule_ids: I)
+    where
+        I: IntoIterator<Item = String>,
+    {
+        self.register_at(module, rule_ids, unix_now());
+    }
+
+    fn register_at<I>(&self, module: &str, rule_ids: I, now: u64)
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut modules = self.modules.write().unwrap();
+        let previous = modules.remove(module).unwrap_or_default();
+        let rules = rule_ids
+            .into_iter()
+            .map(|id| {
+                let counter = previous
+                    .get(&id)
+                    .cloned()
+                    .unwrap_or_else(|| Arc::new(RuleCounter::new(now)));
+                (id, counter)
+            })
+            .collect();
+        modules.insert(module.to_string(), rules);
+    }
+
+    /// Record a hit of a rule
+    pub fn record(&self, module: &str, rule_id: &str) {
+        self.record_at(module, rule_id, unix_now());
+    }
+
+    fn record_at(&self, module: &str, rule_id: &str, now: u64) {
+        let counter = self
+            .modules
+            .read()
+            .unwrap()
+            .get(module)
+            .and_then(|rules| rules.get(rule_id))
+            .cloned();
+        let counter = match counter {
+            Some(counter) => counter,
+            // rules added at runtime, e.g. from lists, are counted from their first hit
+            None => self
+                .modules
+                .write()
+                .unwrap()
+                .entry(module.to_string())
+                .or_default()
+                .entry(rule_id.to_string())
+                .or_insert_with(|| Arc::new(RuleCounter::new(now)))
+                .clone(),
+        };
+        counter.hits.fetch_add(1, Ordering::Relaxed);
+        counter.last_hit.fetch_max(now, Ordering::Relaxed);
+    }
+
+    /// Snapshot of the counters, sorted by module and rule id
+    pub fn report(&self) -> RuleHitReport {
+        self.report_at(unix_now())
+    }
+
+    fn report_at(&self, now: u64) -> RuleHitReport {
+        let modules = self.modules.read().unwrap();
+        let mut rules = Vec::new();
+        for (module, counters) in modules.iter() {
+            for (rule, counter) in counters {
+                let last_hit = counter.last_hit.load(Ordering::Relaxed);
+                rules.push(RuleHitStats {
+                    module: module.clone(),
+                    rule: rule.clone(),
+                    category: category(rule).to_string(),
+                    hits: counter.hits.load(Ordering::Relaxed),
+                    last_hit: (last_hit != 0).then_some(last_hit),
+                    idle_days: now.saturating_sub(counter.last_active()) / SECONDS_PER_DAY,
+                });
+            }
+        }
+        rules.sort_by(|a, b| (&a.module, &a.rule).cmp(&(&b.module, &b.rule)));
+
+        let mut categories: BTreeMap<(&str, &str), CategoryHitStats> = BTreeMap::new();
+        for r in &rules {
+            let c = categories
+                .entry((&r.module, &r.category))
+                .or_insert_with(|| CategoryHitStats {
+                    module: r.module.clone(),
+                    category: r.category.clone(),
+                    rules: 0,
+                    hits: 0,
+                    last_hit: None,
+                });
+            c.rules += 1;
+            c.hits += r.hits;
+            c.last_hit = c.last_hit.max(r.last_hit);
+        }
+        let categories = categories.into_values().collect();
+
+        RuleHitReport { rules, categories }
+    }
+
+    /// Rules without any hit for at least the given number of days
+    pub fn unused(&self, days: u64) -> Vec<RuleHitStats> {
+        self.unused_at(days, unix_now())
+    }
+
+    fn unused_at(&self, days: u64, now: u64) -> Vec<RuleHitStats> {
+        let mut rules = self.report_at(now).rules;
+        rules.retain(|r| r.idle_days >= days);
+        rules
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = SECONDS_PER_DAY;
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn categories() {
+        assert_eq!(category("default:domain:3"), "domain");
+        assert_eq!(category("group:staff:domain_pattern:2"), "domain_pattern");
+        assert_eq!(category("user:alice:max_file_size"), "max_file_size");
+        assert_eq!(category("default:type_mismatch"), "type_mismatch");
+        assert_eq!(category("blocklist"), "blocklist");
+    }
+
+    #[test]
+    fn count_and_age() {
+        let hits = RuleHits::default();
+        let start = 100 * DAY;
+        hits.register_at(
+            "filter",
+            ids(&["default:domain:0", "default:domain:1", "default:keyword:0"]),
+            start,
+        );
+        hits.record_at("filter", "default:domain:0", start + DAY);
+        hits.record_at("filter", "default:domain:0", start + 2 * DAY);
+        hits.record_at("filter", "default:confusable:0", start + 2 * DAY);
+
+        let report = hits.report_at(start + 10 * DAY);
+        assert_eq!(report.rules.len(), 4);
+        let domain = &report.rules[1];
+        assert_eq!(domain.rule, "default:domain:0");
+        assert_eq!(domain.hits, 2);
+        assert_eq!(domain.last_hit, Some(start + 2 * DAY));
+        assert_eq!(domain.idle_days, 8);
+        let never = &report.rules[2];
+        assert_eq!(never.rule, "default:domain:1");
+        assert_eq!(never.last_hit, None);
+        assert_eq!(never.idle_days, 10);
+
+        assert_eq!(report.categories.len(), 3);
+        let domain = &report.categories[1];
+        assert_eq!(domain.category, "domain");
+        assert_eq!((domain.rules, domain.hits), (2, 2));
+        assert_eq!(domain.last_hit, Some(start + 2 * DAY));
+
+        let unused = hits.unused_at(9, start + 10 * DAY);
+        let unused: Vec<_> = unused.iter().map(|r| r.rule.as_str()).collect();
+        assert_eq!(unused, ["default:domain:1", "default:keyword:0"]);
+        assert_eq!(hits.unused_at(0, start + 10 * DAY).len(), 4);
+    }
+
+    #[test]
+    fn reregister() {
+        let hits = RuleHits::default();
+        hits.register_at("filter", ids(&["default:domain:0", "default:domain:1"]), 0);
+        hits.record_at("filter", "default:domain:0", DAY);
+        hits.register_at("other", ids(&["default:domain:0"]), DAY);
+
+        // a reload keeps the counters of the rules still configured
+        hits.register_at(
+            "filter",
+            ids(&["default:domain:0", "default:keyword:0"]),
+            2 * DAY,
+        );
+        let report = hits.report_at(3 * DAY);
+        let rules: Vec<_> = report
+            .rules
+            .iter()
+            .map(|r| (r.module.as_str(), r.rule.as_str(), r.hits, r.idle_days))
+            .collect();
+        assert_eq!(
+            rules,
+            [
+                ("filter", "default:domain:0", 1, 2),
+                ("filter", "default:keyword:0", 0, 1),
+                ("other", "default:domain:0", 0, 2),
+            ]
+        );
+    }
+}
@@ -0,0 +1,391 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Shadow evaluation of candidate rule sets
+//!
+//! A candidate rule set, for the content filter or the YARA rules of the
+//! antivirus, is loaded in a copy of the module of its own, evaluated on the
+//! same transactions as the active one. Its verdicts are never used: they are
+//! compared with the active ones and the disagreements are counted, and
+//! logged if asked to, so that new rules can be validated against the live
+//! traffic before being promoted with `g3icap-ctl policy promote`.
+
+use std::future::Future;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+use super::content_filter::{FilterRuleSet, HEADER_RULE_ID};
+use super::{IcapModule, ModuleError};
+use crate::modules::antivirus::HEADER_VIRUS_ID;
+use crate::protocol::common::{IcapMethod, IcapRequest, IcapResponse};
+use crate::transaction::TransactionCtx;
+
+/// Prefix of the names of the candidate modules
+pub const SHADOW_PREFIX: &str = "shadow/";
+
+static CONTENT_FILTER_STATS: ShadowStats = ShadowStats::new();
+static YARA_STATS: ShadowStats = ShadowStats::new();
+
+/// Candidate YARA rules of the antivirus
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShadowYaraConfig {
+    /// Directory of the rule files
+    pub rules_dir: PathBuf,
+    /// Maximum number of rules to load
+    pub max_rules: usize,
+}
+
+impl ShadowYaraConfig {
+    pub fn new(rules_dir: PathBuf) -> Self {
+        ShadowYaraConfig {
+            rules_dir,
+            max_rules: 1000,
+        }
+    }
+}
+
+/// Candidate rule sets evaluated in shadow mode
+#[derive(Debug, Clone, Default)]
+pub struct ShadowConfig {
+    /// Default rule set of the content filter
+    pub content_filter: Option<FilterRuleSet>,
+    /// YARA rules of the antivirus
+    pub yara: Option<ShadowYaraConfig>,
+    /// Log each transaction the verdicts disagree on
+    pub log_disagreements: bool,
+}
+
+impl ShadowConfig {
+    /// Check if a candidate rule set is configured for the target
+    pub fn has_candidate(&self, target: ShadowTarget) -> bool {
+        match target {
+            ShadowTarget::ContentFilter => self.content_filter.is_some(),
+            ShadowTarget::Yara => self.yara.is_some(),
+        }
+    }
+}
+
+/// Rule set that can have a candidate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ShadowTarget {
+    /// Rules of the content filter, for REQMOD
+    ContentFilter,
+    /// YARA rules of the antivirus, for RESPMOD
+    Yara,
+}
+
+impl ShadowTarget {
+    pub const ALL: [ShadowTarget; 2] = [ShadowTarget::ContentFilter, ShadowTarget::Yara];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ShadowTarget::ContentFilter => "content_filter",
+            ShadowTarget::Yara => "yara",
+        }
+    }
+
+    /// Name of the active module using the rule set
+    pub fn module(&self) -> &'static str {
+        match self {
+            ShadowTarget::ContentFilter => "content_filter",
+            ShadowTarget::Yara => "antivirus",
+        }
+    }
+
+    /// Disagreement counters of the candidate
+    pub fn stats(&self) -> &'static ShadowStats {
+        match self {
+            ShadowTarget::ContentFilter => &CONTENT_FILTER_STATS,
+            ShadowTarget::Yara => &YARA_STATS,
+        }
+    }
+
+    /// What blocked the message, if the response blocks it
+    fn blocked_by<'a>(&self, response: &'a IcapResponse) -> Option<&'a str> {
+        let header = match self {
+            ShadowTarget::ContentFilter => HEADER_RULE_ID,
+            ShadowTarget::Yara => HEADER_VIRUS_ID,
+        };
+        response.headers.get(header).and_then(|v| v.to_str().ok())
+    }
+}
+
+impl FromStr for ShadowTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "content_filter" => Ok(ShadowTarget::ContentFilter),
+            "yara" | "antivirus" => Ok(ShadowTarget::Yara),
+            _ => Err(anyhow!("no rule set named {s}")),
+        }
+    }
+}
+
+/// Outcome of the comparison of the verdicts on a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Agreement {
+    /// Both allowed the message, or blocked it by the same rule
+    Agreed,
+    /// Both blocked the message, by different rules
+    RuleChanged,
+    /// Only the candidate blocked the message
+    NewlyBlocked,
+    /// Only the active rule set blocked the message
+    NewlyAllowed,
+}
+
+impl Agreement {
+    pub fn of(active: Option<&str>, candidate: Option<&str>) -> Self {
+        match (active, candidate) {
+            (None, None) => Agreement::Agreed,
+            (Some(a), Some(c)) if a == c => Agreement::Agreed,
+            (Some(_), Some(_)) => Agreement::RuleChanged,
+            (None, Some(_)) => Agreement::NewlyBlocked,
+            (Some(_), None) => Agreement::NewlyAllowed,
+        }
+    }
+}
+
+/// Disagreement counters of a candidate rule set
+pub struct ShadowStats {
+    evaluated: AtomicU64,
+    agreed: AtomicU64,
+    rule_changed: AtomicU64,
+    newly_blocked: AtomicU64,
+    newly_allowed: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl ShadowStats {
+    const fn new() -> Self {
+        ShadowStats {
+            evaluated: AtomicU64::new(0),
+            agreed: AtomicU64::new(0),
+            rule_changed: AtomicU64::new(0),
+            newly_blocked: AtomicU64::new(0),
+            newly_allowed: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, agreement: Agreement) {
+        self.evaluated.fetch_add(1, Ordering::Relaxed);
+        let counter = match agreement {
+            Agreement::Agreed => &self.agreed,
+            Agreement::RuleChanged => &self.rule_changed,
+            Agreement::NewlyBlocked => &self.newly_blocked,
+            Agreement::NewlyAllowed => &self.newly_allowed,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Start counting again, for a new candidate
+    pub fn reset(&self) {
+        for counter in [
+            &self.evaluated,
+            &self.agreed,
+            &self.rule_changed,
+            &self.newly_blocked,
+            &self.newly_allowed,
+            &self.errors,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Snapshot of the counters, `loaded` telling if the candidate is evaluated
+    pub fn report(&self, target: ShadowTarget, loaded: bool) -> ShadowReport {
+        let evaluated = self.evaluated.load(Ordering::Relaxed);
+        let agreed = self.agreed.load(Ordering::Relaxed);
+        ShadowReport {
+            name: target.as_str().to_string(),
+            module: target.module().to_string(),
+            loaded,
+            evaluated,
+            agreed,
+            rule_changed: self.rule_changed.load(Ordering::Relaxed),
+            newly_blocked: self.newly_blocked.load(Ordering::Relaxed),
+            newly_allowed: self.newly_allowed.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            disagreement_rate: if evaluated == 0 {
+                0.0
+            } else {
+                (evaluated - agreed) as f64 / evaluated as f64
+            },
+        }
+    }
+}
+
+/// Disagreements of a candidate rule set with the active one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShadowReport {
+    /// Rule set, `content_filter` or `yara`
+    pub name: String,
+    /// Module using the rule set
+    pub module: String,
+    /// Whether a candidate is evaluated
+    pub loaded: bool,
+    /// Transactions both rule sets gave a verdict on
+    pub evaluated: u64,
+    pub agreed: u64,
+    /// Transactions both blocked by different rules
+    pub rule_changed: u64,
+    /// Transactions only the candidate blocked
+    pub newly_blocked: u64,
+    /// Transactions only the active rule set blocked
+    pub newly_allowed: u64,
+    /// Transactions the candidate failed on
+    pub errors: u64,
+    pub disagreement_rate: f64,
+}
+
+/// Module loaded with a candidate rule set
+#[derive(Clone)]
+pub struct ShadowModule {
+    target: ShadowTarget,
+    module: Arc<dyn IcapModule>,
+    log_disagreements: bool,
+}
+
+impl ShadowModule {
+    pub fn new(target: ShadowTarget, module: Arc<dyn IcapModule>, log_disagreements: bool) -> Self {
+        ShadowModule {
+            target,
+            module,
+            log_disagreements,
+        }
+    }
+
+    pub fn target(&self) -> ShadowTarget {
+        self.target
+    }
+
+    pub fn module(&self) -> &Arc<dyn IcapModule> {
+        &self.module
+    }
+
+    async fn evaluate(
+        &self,
+        request: &IcapRequest,
+        ctx: &TransactionCtx,
+    ) -> Result<IcapResponse, ModuleError> {
+        match request.method {
+            IcapMethod::Reqmod => self.module.handle_reqmod(request, ctx).await,
+            IcapMethod::Respmod => self.module.handle_respmod(request, ctx).await,
+            IcapMethod::Options => self.module.handle_options(request).await,
+        }
+    }
+
+    /// Compare the verdict of the candidate with the one of the active rule set
+    fn compare(
+        &self,
+        request: &IcapRequest,
+        ctx: &TransactionCtx,
+        active: &Result<IcapResponse, ModuleError>,
+        candidate: Result<IcapResponse, ModuleError>,
+    ) {
+        let stats = self.target.stats();
+        // the basic checks are used if the active module failed
+        let Ok(active) = active else {
+            return;
+        };
+        let candidate = match candidate {
+            Ok(response) => response,
+            Err(ModuleError::Cancelled) => return,
+            Err(e) => {
+                stats.record_error();
+                log::debug!("candidate {} rules failed: {e}", self.target.as_str());
+                return;
+            }
+        };
+
+        let active = self.target.blocked_by(active);
+        let candidate = self.target.blocked_by(&candidate);
+        let agreement = Agreement::of(active, candidate);
+        stats.record(agreement);
+        if self.log_disagreements && agreement != Agreement::Agreed {
+            log::info!(
+                "candidate {} rules disagree on {} (request {}): active {}, candidate {}",
+                self.target.as_str(),
+                request.uri,
+                ctx.request_id(),
+                active.map_or_else(|| "allowed".to_string(), |r| format!("blocked by {r}")),
+                candidate.map_or_else(|| "allowed".to_string(), |r| format!("blocked by {r}")),
+            );
+        }
+    }
+}
+
+/// Run the work of the active module, and the candidate alongside if any
+///
+/// The result of the active module is returned as is, the one of the
+/// candidate only being compared with it.
+pub async fn evaluate_alongside<F>(
+    shadow: Option<&ShadowModule>,
+    request: &IcapRequest,
+    ctx: &TransactionCtx,
+    active: F,
+) -> Result<IcapResponse, ModuleError>
+where
+    F: Future<Output = Result<IcapResponse, ModuleError>>,
+{
+    let Some(shadow) = shadow else {
+        return active.await;
+    };
+    let (result, candidate) = tokio::join!(active, shadow.evaluate(request, ctx));
+    shadow.compare(request, ctx, &result, candidate);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn agreement() {
+        assert_eq!(Agreement::of(None, None), Agreement::Agreed);
+        assert_eq!(Agreement::of(Some("a"), Some("a")), Agreement::Agreed);
+        assert_eq!(Agreement::of(Some("a"), Some("b")), Agreement::RuleChanged);
+        assert_eq!(Agreement::of(None, Some("b")), Agreement::NewlyBlocked);
+        assert_eq!(Agreement::of(Some("a"), None), Agreement::NewlyAllowed);
+    }
+
+    #[test]
+    fn stats_report() {
+        let stats = ShadowStats::new();
+        for agreement in [
+            Agreement::Agreed,
+            Agreement::Agreed,
+            Agreement::NewlyBlocked,
+            Agreement::RuleChanged,
+        ] {
+            stats.record(agreement);
+        }
+        stats.record_error();
+
+        let report = stats.report(ShadowTarget::Yara, true);
+        assert_eq!(report.module, "antivirus");
+        assert_eq!(report.evaluated, 4);
+        assert_eq!(report.newly_blocked, 1);
+        assert_eq!(report.newly_allowed, 0);
+        assert_eq!(report.errors, 1);
+        assert_eq!(report.disagreement_rate, 0.5);
+
+        stats.reset();
+        let report = stats.report(ShadowTarget::Yara, false);
+        assert_eq!(report.evaluated, 0);
+        assert_eq!(report.disagreement_rate, 0.0);
+    }
+}
@@ -0,0 +1,170 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! HTTP reputation backend
+//!
+//! A batch is posted as `{"indicators": [...]}`, the source answers with
+//! `{"results": {"<indicator>": {"malicious": true, "threat": "..."}}}`, where
+//! unknown indicators may be left out. The request is sent as HTTP/1.0 so that
+//! the response body is delimited by the end of the connection.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::{Context, anyhow};
+use async_trait::async_trait;
+use serde::Deserialize;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use url::Url;
+
+use g3_types::net::RustlsClientConfigBuilder;
+
+use super::Reputation;
+use crate::modules::batcher::BatchLookup;
+
+/// Maximum size of a batch response
+const MAX_RESPONSE_SIZE: u64 = 4 * 1024 * 1024;
+
+#[derive(Deserialize)]
+struct BatchResponse {
+    #[serde(default)]
+    results: HashMap<String, Reputation>,
+}
+
+pub(super) struct HttpReputationBackend {
+    url: Url,
+    timeout: Duration,
+}
+
+impl HttpReputationBackend {
+    pub(super) fn new(url: Url, timeout: Duration) -> Self {
+        HttpReputationBackend { url, timeout }
+    }
+
+    async fn post(&self, body: &str) -> anyhow::Result<Vec<u8>> {
+        let url = &self.url;
+        let host = url.host_str().ok_or_else(|| anyhow!("no host in url"))?;
+        let port = url
+            .port_or_known_default()
+            .ok_or_else(|| anyhow!("no port in url"))?;
+        let host_header = match url.port() {
+            Some(port) => format!("{host}:{port}"),
+            None => host.to_string(),
+        };
+        let path = match url.query() {
+            Some(query) => format!("{}?{query}", url.path()),
+            None => url.path().to_string(),
+        };
+        let request = format!(
+            "POST {path} HTTP/1.0\r\nHost: {host_header}\r\nUser-Agent: g3icap\r\n\
+             Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+
+        let host = host.trim_matches(['[', ']']);
+        let stream = TcpStream::connect((host, port))
+            .await
+            .context("failed to connect")?;
+        if url.scheme() == "https" {
+            let tls_client = RustlsClientConfigBuilder::default().build()?;
+            let server_name = rustls::pki_types::ServerName::try_from(host.to_string())
+                .map_err(|e| anyhow!("invalid tls server name {host}: {e}"))?;
+            let stream = TlsConnector::from(tls_client.driver)
+                .connect(server_name, stream)
+                .await
+                .context("tls handshake failed")?;
+            exchange(stream, &request).await
+        } else {
+            exchange(stream, &request).await
+        }
+    }
+}
+
+#[async_trait]
+impl BatchLookup for HttpReputationBackend {
+    type Key = String;
+    type Value = Reputation;
+
+    async fn lookup_batch(&self, keys: &[String]) -> anyhow::Result<HashMap<String, Reputation>> {
+        let body = serde_json::json!({ "indicators": keys }).to_string();
+        let response = tokio::time::timeout(self.timeout, self.post(&body))
+            .await
+            .map_err(|_| anyhow!("timed out after {:?}", self.timeout))??;
+        let body = response_body(&response)?;
+        let response: BatchResponse =
+            serde_json::from_slice(body).context("invalid reputation response")?;
+
+        let source = self.url.host_str().unwrap_or_default();
+        Ok(response
+            .results
+            .into_iter()
+            .map(|(indicator, mut reputation)| {
+                reputation.source = source.to_string();
+                (indicator, reputation)
+            })
+            .collect())
+    }
+}
+
+async fn exchange<S>(mut stream: S, request: &str) -> anyhow::Result<Vec<u8>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .context("failed to send request")?;
+    stream.flush().await?;
+
+    let mut response = Vec::new();
+    (&mut stream)
+        .take(MAX_RESPONSE_SIZE + 1)
+        .read_to_end(&mut response)
+        .await
+        .context("failed to read response")?;
+    if response.len() as u64 > MAX_RESPONSE_SIZE {
+        return Err(anyhow!("response larger than {MAX_RESPONSE_SIZE} bytes"));
+    }
+    Ok(response)
+}
+
+/// Check the status of the response and return its body
+fn response_body(response: &[u8]) -> anyhow::Result<&[u8]> {
+    let header_end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| anyhow!("incomplete response header"))?;
+    let header = String::from_utf8_lossy(&response[..header_end]);
+    let status_line = header.lines().next().unwrap_or_default();
+    let mut parts = status_line.splitn(3, ' ');
+    if !parts.next().unwrap_or_default().starts_with("HTTP/1.") {
+        return Err(anyhow!("invalid status line {status_line}"));
+    }
+    match parts.next().unwrap_or_default().parse::<u16>() {
+        Ok(200..=299) => Ok(&response[header_end + 4..]),
+        _ => Err(anyhow!("unexpected response status: {status_line}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_response() {
+        let response = b"HTTP/1.0 200 OK\r\nContent-Type: application/json\r\n\r\n\
+            {\"results\": {\"abc\": {\"malicious\": true, \"threat\": \"Eicar\"}, \"def\": {}}}";
+        let body = response_body(response).unwrap();
+        let response: BatchResponse = serde_json::from_slice(body).unwrap();
+        assert!(response.results["abc"].malicious);
+        assert_eq!(response.results["abc"].threat.as_deref(), Some("Eicar"));
+        assert!(!response.results["def"].malicious);
+
+        assert!(response_body(b"HTTP/1.1 503 Service Unavailable\r\n\r\n").is_err());
+        assert!(response_body(b"HTTP/1.1 200 OK\r\n").is_err());
+    }
+}
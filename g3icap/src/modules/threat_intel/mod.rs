@@ -0,0 +1,128 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Threat intelligence reputation lookups
+//!
+//! Indicators, such as the SHA-256 of scanned content, are looked up in all
+//! configured sources. The lookups of concurrent transactions are sent to each
+//! source in micro-batches, so that a traffic burst does not turn into one
+//! backend request per transaction.
+//!
+//! A `file://` source is a frozen digest list compiled by `g3icap-listc`,
+//! mapped read-only and searched in place before the remote sources.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use serde::Deserialize;
+use url::Url;
+
+use super::batcher::{BatchConfig, MicroBatcher};
+use super::list_store::{FrozenList, ListKind};
+
+mod backend;
+use backend::HttpReputationBackend;
+
+/// Reputation of an indicator in a source
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Reputation {
+    /// Whether the indicator is known to be malicious
+    pub malicious: bool,
+    /// Name of the threat
+    pub threat: Option<String>,
+    /// Source that reported the reputation
+    #[serde(skip)]
+    pub source: String,
+}
+
+/// Reputation lookups in the threat intelligence sources
+pub struct ThreatIntel {
+    local: Vec<(String, FrozenList)>,
+    sources: Vec<MicroBatcher<HttpReputationBackend>>,
+}
+
+impl ThreatIntel {
+    /// Create the lookups of the source urls, must be called within a runtime
+    pub fn new(sources: &[String], batch: BatchConfig, timeout: Duration) -> anyhow::Result<Self> {
+        let mut local = Vec::new();
+        let mut batchers = Vec::with_capacity(sources.len());
+        for source in sources {
+            let url = Url::parse(source)
+                .map_err(|e| anyhow!("invalid threat intel url {source}: {e}"))?;
+            if url.scheme() == "file" {
+                let path = url
+                    .to_file_path()
+                    .map_err(|_| anyhow!("invalid threat intel file {source}"))?;
+                let list = FrozenList::open(&path)?;
+                if list.kind() != ListKind::Digests {
+                    return Err(anyhow!(
+                        "threat intel file {source} is a list of {}",
+                        list.kind().as_str()
+                    ));
+                }
+                local.push((source.clone(), list));
+                continue;
+            }
+            if !matches!(url.scheme(), "http" | "https") {
+                return Err(anyhow!(
+                    "threat intel url {source} should be http, https or file"
+                ));
+            }
+            let backend = Arc::new(HttpReputationBackend::new(url, timeout));
+            batchers.push(MicroBatcher::new(backend, batch));
+        }
+        Ok(ThreatIntel {
+            local,
+            sources: batchers,
+        })
+    }
+
+    /// Look up the indicator in all sources
+    ///
+    /// Returns the first malicious reputation. Failed lookups are logged and
+    /// treated as unknown, the caller falls back to its own checks.
+    pub async fn lookup(&self, indicator: &str) -> Option<Reputation> {
+        if let Some((source, _)) = self
+            .local
+            .iter()
+            .find(|(_, list)| list.contains_hex_digest(indicator))
+        {
+            return Some(Reputation {
+                malicious: true,
+                threat: None,
+                source: source.clone(),
+            });
+        }
+        let lookups = self
+            .sources
+            .iter()
+            .map(|source| source.lookup(indicator.to_string()));
+        let mut found = None;
+        for result in futures_util::future::join_all(lookups).await {
+            match result {
+                Ok(Some(reputation)) if reputation.malicious => {
+                    found.get_or_insert(reputation);
+                }
+                Ok(_) => {}
+                Err(e) => log::debug!("threat intel lookup of {indicator} failed: {e}"),
+            }
+        }
+        found
+    }
+}
+
+/// Hex encoded SHA-256 of the data, the indicator of scanned content
+pub fn sha256_hex(data: &[u8]) -> String {
+    use std::fmt::Write;
+
+    openssl::sha::sha256(data)
+        .iter()
+        .fold(String::with_capacity(64), |mut s, b| {
+            let _ = write!(s, "{b:02x}");
+            s
+        })
+}
@@ -0,0 +1,258 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Rule evasion regression suite
+//!
+//! The corpus in `test_data/evasion_corpus.txt` holds disguised forms of
+//! blocked URLs, each of which must still be blocked once normalized, and
+//! look-alikes of them that must not be. Most of it is produced by
+//! [`variants`] from the [`SEEDS`], the rest are hand written cases found
+//! along the way. `corpus_is_up_to_date` lists the generated lines missing
+//! from the corpus whenever the generator learns a new trick.
+
+use super::NormalizedUrl;
+use crate::modules::matcher::{DomainSuffixMatcher, KeywordMatcher};
+
+const CORPUS: &str = include_str!("test_data/evasion_corpus.txt");
+
+/// Blocked URLs the variants are generated from, with the rule blocking them
+const SEEDS: &[(&str, &str, &str)] = &[
+    (
+        "domain",
+        "evil.example.com",
+        "http://evil.example.com/login",
+    ),
+    (
+        "keyword",
+        "/malware/payload.exe",
+        "http://downloads.example.net/malware/payload.exe",
+    ),
+    ("domain", "192.168.1.1", "http://192.168.1.1/admin"),
+];
+
+/// Host used as the decoy in userinfo and fragment tricks
+const DECOY: &str = "trusted.example.org";
+
+/// Percent-encode all the characters of the string
+fn encode_all(s: &str, upper: bool) -> String {
+    s.bytes()
+        .map(|b| {
+            if upper {
+                format!("%{b:02X}")
+            } else {
+                format!("%{b:02x}")
+            }
+        })
+        .collect()
+}
+
+/// Percent-encode the characters that are not slashes, `times` times over
+fn encode_path(path: &str, times: usize) -> String {
+    path.split('/')
+        .map(|segment| {
+            let mut encoded = segment.to_string();
+            for _ in 0..times {
+                encoded = encode_all(&encoded, false);
+            }
+            encoded
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn alternate_case(s: &str) -> String {
+    s.chars()
+        .enumerate()
+        .map(|(i, c)| {
+            if i % 2 == 0 {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+fn fullwidth(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            'a'..='z' => char::from_u32(c as u32 - 'a' as u32 + 0xff41).unwrap(),
+            c => c,
+        })
+        .collect()
+}
+
+/// Numeric forms of a dotted IPv4 address
+fn numeric_hosts(host: &str) -> Vec<String> {
+    let Ok(ip) = host.parse::<std::net::Ipv4Addr>() else {
+        return Vec::new();
+    };
+    let o = ip.octets();
+    let n = u32::from(ip);
+    vec![
+        n.to_string(),
+        format!("0x{n:x}"),
+        format!("0{:o}.0{:o}.0{:o}.0{:o}", o[0], o[1], o[2], o[3]),
+        format!(
+            "0x{:x}.{}.{}",
+            o[0],
+            o[1],
+            u32::from(o[2]) << 8 | u32::from(o[3])
+        ),
+    ]
+}
+
+/// Disguised forms of a URL, all pointing to the same resource
+fn variants(url: &str) -> Vec<String> {
+    let (scheme, rest) = url.split_once("://").unwrap();
+    let (host, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    let first = &path[1..2];
+    let tail = &path[2..];
+
+    let mut v = vec![
+        // case
+        format!("{}://{}{path}", scheme.to_uppercase(), host.to_uppercase()),
+        format!("{scheme}://{}{path}", alternate_case(host)),
+        // encoding, once or more, in lower or upper case hex
+        format!("{scheme}://{host}{}", encode_path(path, 1)),
+        format!("{scheme}://{host}/{}{tail}", encode_all(first, true)),
+        format!("{scheme}://{host}{}", encode_path(path, 2)),
+        format!("{scheme}://{host}{}", encode_path(path, 3)),
+        format!("{scheme}://{host}/%u{:04x}{tail}", first.as_bytes()[0]),
+        // userinfo
+        format!("{scheme}://{DECOY}@{host}{path}"),
+        format!("{scheme}://user:password@{host}{path}"),
+        format!("{scheme}://{DECOY}:443@{host}{path}"),
+        format!("{scheme}://{DECOY}%40{host}{path}"),
+        // fragment
+        format!("{scheme}://{host}{path}#@{DECOY}"),
+        format!("{scheme}://{host}{path}#{scheme}://{DECOY}/"),
+        // path segments
+        format!("{scheme}://{host}/decoy/..{path}"),
+        format!("{scheme}://{host}/decoy/%2e%2e{path}"),
+        format!("{scheme}://{host}/decoy/..%2f{}", &path[1..]),
+        format!("{scheme}://{host}/.{path}"),
+        format!("{scheme}://{host}/{path}"),
+        // host and authority
+        format!("{scheme}://{host}.{path}"),
+        format!("{scheme}://{host}:80{path}"),
+        format!("{scheme}:{}", format!("//{host}{path}").replace('/', "\\")),
+        format!("{scheme}:/{host}{path}"),
+        format!(
+            "{scheme}://{}{path}",
+            encode_all(&host[..1], false) + &host[1..]
+        ),
+    ];
+    if host.parse::<std::net::Ipv4Addr>().is_ok() {
+        v.extend(
+            numeric_hosts(host)
+                .into_iter()
+                .map(|h| format!("{scheme}://{h}{path}")),
+        );
+    } else {
+        v.push(format!("{scheme}://{}{path}", fullwidth(host)));
+    }
+    v
+}
+
+/// A corpus case
+struct Case<'a> {
+    block: bool,
+    kind: &'a str,
+    pattern: &'a str,
+    url: &'a str,
+}
+
+fn corpus() -> impl Iterator<Item = Case<'static>> {
+    CORPUS
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut fields = line.splitn(4, ' ');
+            let mut next = || {
+                fields
+                    .next()
+                    .unwrap_or_else(|| panic!("bad corpus line {line}"))
+            };
+            let block = match next() {
+                "block" => true,
+                "allow" => false,
+                verdict => panic!("bad verdict {verdict} in corpus line {line}"),
+            };
+            Case {
+                block,
+                kind: next(),
+                pattern: next(),
+                url: next(),
+            }
+        })
+}
+
+/// Check if the normalized URL is blocked by the rule
+fn is_blocked(kind: &str, pattern: &str, url: &str) -> bool {
+    let rule = ("test".to_string(), pattern.to_string());
+    let url = NormalizedUrl::parse(url);
+    match kind {
+        "domain" => {
+            let matcher = DomainSuffixMatcher::new([rule]).unwrap();
+            url.host.is_some_and(|host| matcher.find(&host).is_some())
+        }
+        "keyword" => {
+            let matcher = KeywordMatcher::new([rule], true).unwrap();
+            matcher.find(url.to_string().as_bytes()).is_some()
+        }
+        kind => panic!("bad rule kind {kind}"),
+    }
+}
+
+#[test]
+fn corpus_verdicts() {
+    let mut count = 0;
+    for case in corpus() {
+        assert_eq!(
+            is_blocked(case.kind, case.pattern, case.url),
+            case.block,
+            "{} {} {}: normalized to {}",
+            case.kind,
+            case.pattern,
+            case.url,
+            NormalizedUrl::parse(case.url)
+        );
+        count += 1;
+    }
+    assert!(count > 50);
+}
+
+#[test]
+fn generated_variants_are_blocked() {
+    for (kind, pattern, url) in SEEDS {
+        assert!(is_blocked(kind, pattern, url), "seed {url}");
+        for variant in variants(url) {
+            assert!(
+                is_blocked(kind, pattern, &variant),
+                "{variant} normalized to {}",
+                NormalizedUrl::parse(&variant)
+            );
+        }
+    }
+}
+
+#[test]
+fn corpus_is_up_to_date() {
+    let missing: Vec<String> = SEEDS
+        .iter()
+        .flat_map(|(kind, pattern, url)| {
+            variants(url)
+                .into_iter()
+                .map(move |v| format!("block {kind} {pattern} {v}"))
+        })
+        .filter(|line| !CORPUS.lines().any(|l| l == line))
+        .collect();
+    assert!(
+        missing.is_empty(),
+        "lines missing from the corpus:\n{}",
+        missing.join("\n")
+    );
+}
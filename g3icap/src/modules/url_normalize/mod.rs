@@ -0,0 +1,306 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! URL normalization for rule matching
+//!
+//! The same resource can be written in many ways: percent-encoded, more than
+//! once, in mixed case, with credentials before the host, with a fragment,
+//! dot segments or a numeric host. URLs are reduced to one canonical form,
+//! the way a browser or server would read them, before the rules are
+//! matched, so a rule written for the plain URL also blocks its disguises.
+
+use std::fmt;
+use std::net::Ipv4Addr;
+
+#[cfg(test)]
+mod evasion;
+
+/// Most rounds of percent-decoding, undoing nested encodings
+const MAX_DECODE_ROUNDS: usize = 4;
+
+/// A URL in canonical form
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NormalizedUrl {
+    /// Lowercase scheme
+    pub scheme: Option<String>,
+    /// Decoded host, lowercase and in ASCII form, without userinfo and port
+    pub host: Option<String>,
+    /// Port, if not the default one of the scheme
+    pub port: Option<u16>,
+    /// Decoded path, with dot segments and empty segments removed
+    pub path: String,
+    /// Decoded query
+    pub query: Option<String>,
+}
+
+impl NormalizedUrl {
+    /// Normalize an absolute URL, a scheme relative URL or a path
+    pub fn parse(url: &str) -> Self {
+        // tabs and newlines are dropped anywhere in a URL, and backslashes
+        // are read as slashes, as browsers do
+        let url: String = url
+            .trim_matches(|c: char| c <= ' ')
+            .chars()
+            .filter(|c| !matches!(c, '\t' | '\n' | '\r'))
+            .map(|c| if c == '\\' { '/' } else { c })
+            .collect();
+        // the fragment is not sent to the server
+        let url = url.split('#').next().unwrap_or_default();
+
+        let (scheme, rest) = split_scheme(url);
+        let authority = match (&scheme, rest.strip_prefix("//")) {
+            (Some(s), _) if is_special(s) => Some(rest.trim_start_matches('/')),
+            (_, Some(rest)) => Some(rest),
+            _ => None,
+        };
+        let (host, port, path_query) = match authority {
+            Some(rest) => {
+                let end = rest.find(['/', '?']).unwrap_or(rest.len());
+                let (host, port) = parse_authority(&rest[..end]);
+                (host, port, &rest[end..])
+            }
+            None => (None, None, rest),
+        };
+        let port = port.filter(|p| Some(*p) != scheme.as_deref().and_then(default_port));
+        let (path, query) = match path_query.split_once('?') {
+            Some((path, query)) => (path, Some(decode(query))),
+            None => (path_query, None),
+        };
+        NormalizedUrl {
+            scheme,
+            host,
+            port,
+            path: normalize_path(path),
+            query,
+        }
+    }
+}
+
+impl fmt::Display for NormalizedUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(scheme) = &self.scheme {
+            write!(f, "{scheme}:")?;
+        }
+        if let Some(host) = &self.host {
+            write!(f, "//{host}")?;
+        }
+        if let Some(port) = self.port {
+            write!(f, ":{port}")?;
+        }
+        f.write_str(&self.path)?;
+        if let Some(query) = &self.query {
+            write!(f, "?{query}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Normalize a host name, from a URL or a Host header
+pub fn normalize_host(host: &str) -> String {
+    let host = decode(host).to_lowercase();
+    // credentials smuggled in an encoded userinfo
+    let host = host.rsplit('@').next().unwrap_or_default();
+    let host = host.trim_end_matches('.');
+    if host.starts_with('[') {
+        return host.to_string();
+    }
+    if let Some(ip) = parse_ipv4(host) {
+        return ip.to_string();
+    }
+    if host.is_ascii() {
+        return host.to_string();
+    }
+    idna::domain_to_ascii(host).unwrap_or_else(|_| host.to_string())
+}
+
+fn split_scheme(url: &str) -> (Option<String>, &str) {
+    match url.split_once(':') {
+        Some((scheme, rest))
+            if scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.')) =>
+        {
+            (Some(scheme.to_ascii_lowercase()), rest)
+        }
+        _ => (None, url),
+    }
+}
+
+/// Schemes always followed by an authority, whatever the number of slashes
+fn is_special(scheme: &str) -> bool {
+    matches!(scheme, "http" | "https" | "ftp" | "ws" | "wss")
+}
+
+fn default_port(scheme: &str) -> Option<u16> {
+    match scheme {
+        "http" | "ws" => Some(80),
+        "https" | "wss" => Some(443),
+        "ftp" => Some(21),
+        "icap" => Some(1344),
+        _ => None,
+    }
+}
+
+fn parse_authority(authority: &str) -> (Option<String>, Option<u16>) {
+    // only the part after the last @ is the host
+    let host_port = authority.rsplit('@').next().unwrap_or_default();
+    let (host, port) = match host_port.rfind(':') {
+        Some(i) if !host_port[i..].contains(']') => {
+            (&host_port[..i], host_port[i + 1..].parse().ok())
+        }
+        _ => (host_port, None),
+    };
+    let host = normalize_host(host);
+    ((!host.is_empty()).then_some(host), port)
+}
+
+/// Parse a host as an IPv4 address, in any of the numeric forms accepted by browsers
+///
+/// Each part may be decimal, octal with a leading `0` or hexadecimal with a
+/// leading `0x`, and the last part fills the bytes left by the missing ones,
+/// so `3232235777`, `0xc0a80101` and `0300.0250.1.1` are all `192.168.1.1`.
+fn parse_ipv4(host: &str) -> Option<Ipv4Addr> {
+    let parts = host
+        .split('.')
+        .map(parse_ipv4_number)
+        .collect::<Option<Vec<u64>>>()?;
+    let (last, init) = parts.split_last()?;
+    if init.len() > 3 || init.iter().any(|n| *n > 255) || *last >> (8 * (4 - init.len())) != 0 {
+        return None;
+    }
+    let mut addr = *last;
+    for (i, n) in init.iter().enumerate() {
+        addr |= n << (8 * (3 - i));
+    }
+    u32::try_from(addr).ok().map(Ipv4Addr::from)
+}
+
+fn parse_ipv4_number(s: &str) -> Option<u64> {
+    let (digits, radix) = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => (hex, 16),
+        None if s.len() > 1 && s.starts_with('0') => (&s[1..], 8),
+        None if s.is_empty() => return None,
+        None => (s, 10),
+    };
+    if digits.is_empty() {
+        return Some(0);
+    }
+    if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    u64::from_str_radix(digits, radix).ok()
+}
+
+fn normalize_path(path: &str) -> String {
+    let path = decode(path).replace('\\', "/");
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            s => segments.push(s),
+        }
+    }
+    let mut normalized = String::with_capacity(path.len() + 1);
+    for segment in &segments {
+        normalized.push('/');
+        normalized.push_str(segment);
+    }
+    let is_dir = path.ends_with('/') || path.ends_with("/.") || path.ends_with("/..");
+    if segments.is_empty() || is_dir {
+        normalized.push('/');
+    }
+    normalized
+}
+
+/// Percent-decode until nothing is left to decode, dropping control characters
+fn decode(s: &str) -> String {
+    let mut decoded = s.to_string();
+    for _ in 0..MAX_DECODE_ROUNDS {
+        let next = decode_once(&decoded);
+        if next == decoded {
+            break;
+        }
+        decoded = next;
+    }
+    decoded.retain(|c| !c.is_control());
+    decoded
+}
+
+/// Decode the `%XX` escapes, and the non-standard `%uXXXX` ones of IIS
+fn decode_once(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if let Some(b) = hex_value(bytes.get(i + 1..i + 3)) {
+                out.push(b as u8);
+                i += 3;
+                continue;
+            }
+            if matches!(bytes.get(i + 1), Some(b'u' | b'U'))
+                && let Some(c) = hex_value(bytes.get(i + 2..i + 6)).and_then(char::from_u32)
+            {
+                let mut buf = [0u8; 4];
+                out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                i += 6;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn hex_value(digits: Option<&[u8]>) -> Option<u32> {
+    let digits = std::str::from_utf8(digits?).ok()?;
+    if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(digits, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonical_form() {
+        let url =
+            NormalizedUrl::parse("HTTP://User:Pw@WWW.Example.COM.:80/a/./b/../%63?q=%2541#frag");
+        assert_eq!(url.scheme.as_deref(), Some("http"));
+        assert_eq!(url.host.as_deref(), Some("www.example.com"));
+        assert_eq!(url.port, None);
+        assert_eq!(url.path, "/a/c");
+        assert_eq!(url.query.as_deref(), Some("q=A"));
+        assert_eq!(url.to_string(), "http://www.example.com/a/c?q=A");
+
+        let url = NormalizedUrl::parse("https://example.com:8443");
+        assert_eq!(url.to_string(), "https://example.com:8443/");
+        let url = NormalizedUrl::parse("/files/../x/");
+        assert_eq!(url.to_string(), "/x/");
+    }
+
+    #[test]
+    fn numeric_hosts() {
+        for host in [
+            "3232235777",
+            "0xc0a80101",
+            "0300.0250.1.1",
+            "192.168.257",
+            "0xC0.168.0x101",
+        ] {
+            assert_eq!(normalize_host(host), "192.168.1.1", "{host}");
+        }
+        assert_eq!(normalize_host("1.example"), "1.example");
+        assert_eq!(normalize_host("256.1.1.1"), "256.1.1.1");
+        assert_eq!(normalize_host("[::1]"), "[::1]");
+    }
+}
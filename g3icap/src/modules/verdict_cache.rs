@@ -0,0 +1,542 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Cache of the scan verdicts by content hash
+//!
+//! The same objects are downloaded again and again, so the verdict of a scan
+//! is kept for a while keyed by the service and the SHA-256 of the scanned
+//! body, and a repeated object is answered without being scanned. The
+//! verdicts are only valid for the ISTag of the service they were given
+//! under: when the ISTag changes, as the definitions or rules of the engine
+//! were updated, the cached verdicts of the service are dropped.
+//!
+//! The verdicts may be replicated to a standby server, which gets them along
+//! with the ISTag they were given under and the time left before they expire.
+//!
+//! With a `redis` server, the verdicts are shared with the other servers too:
+//! a verdict not cached locally is looked up there, keyed by the service, the
+//! ISTag and the hash, and the new verdicts are stored there with the TTL.
+
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use lru::LruCache;
+use redis::AsyncCommands;
+use redis::aio::MultiplexedConnection;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::time::Instant;
+
+use super::ModuleError;
+use super::metrics::{Counter, Gauge, MetricsRegistry};
+use crate::replication::{self, ReplicatedState};
+
+/// Time a Redis lookup or store may take, so that a slow server does not
+/// hold the scans
+const REDIS_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Verdict cache configuration
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VerdictCacheConfig {
+    /// Time a verdict is reused for
+    pub ttl: Duration,
+    /// Maximum number of cached verdicts
+    pub max_entries: usize,
+    /// Redis server the verdicts are shared through, as a `redis://` url
+    pub redis: Option<String>,
+}
+
+impl Default for VerdictCacheConfig {
+    fn default() -> Self {
+        VerdictCacheConfig {
+            ttl: Duration::from_secs(600),
+            max_entries: 10000,
+            redis: None,
+        }
+    }
+}
+
+/// Cache counters, registered under `verdict_cache.`
+struct VerdictCacheMetrics {
+    /// Lookups answered from the cache
+    hits: Arc<Counter>,
+    /// Lookups of objects not cached, or no longer
+    misses: Arc<Counter>,
+    /// Verdicts dropped as older than the TTL
+    expired: Arc<Counter>,
+    /// Verdicts dropped as over the maximum entries
+    evictions: Arc<Counter>,
+    /// Verdicts dropped as the ISTag of their service changed
+    invalidated: Arc<Counter>,
+    /// Cached verdicts
+    entries: Arc<Gauge>,
+    /// Local misses answered by the Redis server
+    shared_hits: Arc<Counter>,
+    /// Failed lookups and stores on the Redis server
+    shared_errors: Arc<Counter>,
+}
+
+impl VerdictCacheMetrics {
+    fn register(registry: &MetricsRegistry) -> Result<Self, ModuleError> {
+        Ok(VerdictCacheMetrics {
+            hits: registry.counter("verdict_cache.hits")?,
+            misses: registry.counter("verdict_cache.misses")?,
+            expired: registry.counter("verdict_cache.expired")?,
+            evictions: registry.counter("verdict_cache.evictions")?,
+            invalidated: registry.counter("verdict_cache.invalidated")?,
+            entries: registry.gauge("verdict_cache.entries")?,
+            shared_hits: registry.counter("verdict_cache.shared_hits")?,
+            shared_errors: registry.counter("verdict_cache.shared_errors")?,
+        })
+    }
+}
+
+/// Key of a verdict, the service and the hex encoded SHA-256 of the body
+type Key = (String, String);
+
+struct Entry<V> {
+    verdict: V,
+    expire: Instant,
+}
+
+struct VerdictCacheState<V> {
+    entries: LruCache<Key, Entry<V>>,
+    /// Current ISTag of each service
+    istags: HashMap<String, String>,
+}
+
+impl<V> VerdictCacheState<V> {
+    /// Make the ISTag current for the service, dropping the verdicts given
+    /// under another one
+    fn update_istag(&mut self, service: &str, istag: &str) -> usize {
+        match self.istags.get_mut(service) {
+            Some(current) if current == istag => return 0,
+            Some(current) => *current = istag.to_string(),
+            None => {
+                self.istags.insert(service.to_string(), istag.to_string());
+                return 0;
+            }
+        }
+        let stale: Vec<Key> = self
+            .entries
+            .iter()
+            .filter(|((s, _), _)| s == service)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &stale {
+            self.entries.pop(key);
+        }
+        stale.len()
+    }
+}
+
+/// Name of a replicated cache, with the encoding of its verdicts
+type Replication<V> = (String, fn(&V) -> Value);
+
+/// A verdict as replicated to the standby server
+#[derive(Deserialize)]
+struct ReplicatedVerdict<V> {
+    service: String,
+    istag: String,
+    sha256: String,
+    /// Milliseconds left before the verdict expires
+    ttl_ms: u64,
+    verdict: V,
+}
+
+/// Key and value of a replicated verdict
+fn replicated_entry(
+    service: &str,
+    istag: &str,
+    sha256: &str,
+    ttl: Duration,
+    verdict: Value,
+) -> (String, Value) {
+    let value = json!({
+        "service": service,
+        "istag": istag,
+        "sha256": sha256,
+        "ttl_ms": ttl.as_millis() as u64,
+        "verdict": verdict,
+    });
+    (format!("{service}:{sha256}"), value)
+}
+
+/// Verdicts shared with the other servers through Redis
+///
+/// The connection is multiplexed for all transactions, made on first use and
+/// made again after a failure.
+struct SharedVerdicts {
+    client: redis::Client,
+    conn: tokio::sync::Mutex<Option<MultiplexedConnection>>,
+}
+
+impl SharedVerdicts {
+    fn new(url: &str) -> Result<Self, ModuleError> {
+        let client = redis::Client::open(url).map_err(|e| {
+            ModuleError::InitFailed(format!("invalid verdict cache redis url {url}: {e}"))
+        })?;
+        Ok(SharedVerdicts {
+            client,
+            conn: tokio::sync::Mutex::new(None),
+        })
+    }
+
+    fn key(service: &str, istag: &str, sha256: &str) -> String {
+        format!("g3icap:verdict:{service}:{istag}:{sha256}")
+    }
+
+    async fn connection(&self) -> redis::RedisResult<MultiplexedConnection> {
+        let mut conn = self.conn.lock().await;
+        if let Some(conn) = conn.as_ref() {
+            return Ok(conn.clone());
+        }
+        let new = self.client.get_multiplexed_async_connection().await?;
+        *conn = Some(new.clone());
+        Ok(new)
+    }
+
+    /// Run a command, the connection is dropped if it fails
+    async fn run<T, F, Fut>(&self, command: F) -> anyhow::Result<T>
+    where
+        F: FnOnce(MultiplexedConnection) -> Fut,
+        Fut: Future<Output = redis::RedisResult<T>>,
+    {
+        let result = tokio::time::timeout(REDIS_TIMEOUT, async {
+            command(self.connection().await?).await
+        })
+        .await;
+        match result {
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(e)) => {
+                self.conn.lock().await.take();
+                Err(e.into())
+            }
+            Err(_) => {
+                self.conn.lock().await.take();
+                Err(anyhow::anyhow!("timed out after {REDIS_TIMEOUT:?}"))
+            }
+        }
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        self.run(|mut conn| async move { conn.get(key).await })
+            .await
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Duration) -> anyhow::Result<()> {
+        let ttl = ttl.as_secs().max(1);
+        self.run(|mut conn| async move { conn.set_ex(key, value, ttl).await })
+            .await
+    }
+}
+
+/// Scan verdicts of the services by content hash
+pub struct VerdictCache<V> {
+    ttl: Duration,
+    state: Mutex<VerdictCacheState<V>>,
+    metrics: VerdictCacheMetrics,
+    /// Set if the verdicts are replicated
+    replication: OnceLock<Replication<V>>,
+    /// Set if the verdicts are shared through Redis
+    shared: Option<SharedVerdicts>,
+}
+
+impl<V: Clone> VerdictCache<V> {
+    /// Create the cache, with its counters in the registry
+    pub fn new(
+        config: VerdictCacheConfig,
+        registry: &MetricsRegistry,
+    ) -> Result<Self, ModuleError> {
+        let Some(max_entries) = NonZeroUsize::new(config.max_entries) else {
+            return Err(ModuleError::InitFailed(
+                "verdict cache max_entries should not be 0".to_string(),
+            ));
+        };
+        if config.ttl.is_zero() {
+            return Err(ModuleError::InitFailed(
+                "verdict cache ttl should not be 0".to_string(),
+            ));
+        }
+        let shared = config
+            .redis
+            .as_deref()
+            .map(SharedVerdicts::new)
+            .transpose()?;
+        Ok(VerdictCache {
+            ttl: config.ttl,
+            state: Mutex::new(VerdictCacheState {
+                entries: LruCache::new(max_entries),
+                istags: HashMap::new(),
+            }),
+            metrics: VerdictCacheMetrics::register(registry)?,
+            replication: OnceLock::new(),
+            shared,
+        })
+    }
+
+    /// Get the verdict of the body given under the current ISTag of the
+    /// service
+    pub fn get(&self, service: &str, istag: &str, sha256: &str) -> Option<V> {
+        let mut state = self.state.lock().unwrap();
+        let invalidated = state.update_istag(service, istag);
+        self.metrics.invalidated.add(invalidated as u64);
+
+        let key = (service.to_string(), sha256.to_string());
+        let verdict = match state.entries.get(&key) {
+            Some(entry) if entry.expire > Instant::now() => Some(entry.verdict.clone()),
+            Some(_) => {
+                state.entries.pop(&key);
+                self.metrics.expired.inc();
+                None
+            }
+            None => None,
+        };
+        self.metrics.entries.set(state.entries.len() as i64);
+        match verdict {
+            Some(verdict) => {
+                self.metrics.hits.inc();
+                Some(verdict)
+            }
+            None => {
+                self.metrics.misses.inc();
+                None
+            }
+        }
+    }
+
+    /// Cache the verdict of the body, if given under the current ISTag of the
+    /// service
+    ///
+    /// A scan started before the ISTag changed has an outdated verdict,
+    /// which is not cached.
+    pub fn insert(&self, service: &str, istag: &str, sha256: &str, verdict: V) {
+        let mut state = self.state.lock().unwrap();
+        if state
+            .istags
+            .get(service)
+            .is_some_and(|current| current != istag)
+        {
+            return;
+        }
+        if let Some((name, encode)) = self.replication.get() {
+            replication::publish(name, &format!("{service}:{sha256}"), || {
+                replicated_entry(service, istag, sha256, self.ttl, encode(&verdict)).1
+            });
+        }
+        let key = (service.to_string(), sha256.to_string());
+        self.push(&mut state, key, verdict, Instant::now() + self.ttl);
+    }
+
+    fn push(&self, state: &mut VerdictCacheState<V>, key: Key, verdict: V, expire: Instant) {
+        let entry = Entry { verdict, expire };
+        if let Some((evicted, _)) = state.entries.push(key.clone(), entry)
+            && evicted != key
+        {
+            self.metrics.evictions.inc();
+        }
+        self.metrics.entries.set(state.entries.len() as i64);
+    }
+
+    /// Number of cached verdicts
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    /// Check if no verdict is cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<V> VerdictCache<V>
+where
+    V: Clone + Serialize + DeserializeOwned,
+{
+    /// Get the verdict of the body, from the Redis server if not cached
+    /// locally
+    ///
+    /// A verdict found on the Redis server is cached locally as well.
+    pub async fn lookup(&self, service: &str, istag: &str, sha256: &str) -> Option<V> {
+        if let Some(verdict) = self.get(service, istag, sha256) {
+            return Some(verdict);
+        }
+        let shared = self.shared.as_ref()?;
+        let data = match shared
+            .get(&SharedVerdicts::key(service, istag, sha256))
+            .await
+        {
+            Ok(data) => data?,
+            Err(e) => {
+                self.metrics.shared_errors.inc();
+                log::debug!("failed to get the verdict of {sha256} from redis: {e:#}");
+                return None;
+            }
+        };
+        match serde_json::from_slice::<V>(&data) {
+            Ok(verdict) => {
+                self.metrics.shared_hits.inc();
+                self.insert(service, istag, sha256, verdict.clone());
+                Some(verdict)
+            }
+            Err(e) => {
+                self.metrics.shared_errors.inc();
+                log::debug!("invalid verdict of {sha256} in redis: {e}");
+                None
+            }
+        }
+    }
+
+    /// Cache the verdict of the body, and store it on the Redis server
+    pub async fn store(&self, service: &str, istag: &str, sha256: &str, verdict: V) {
+        let data = self
+            .shared
+            .as_ref()
+            .and_then(|_| serde_json::to_vec(&verdict).ok());
+        self.insert(service, istag, sha256, verdict);
+        let Some((shared, data)) = self.shared.as_ref().zip(data) else {
+            return;
+        };
+        let key = SharedVerdicts::key(service, istag, sha256);
+        if let Err(e) = shared.set(&key, data, self.ttl).await {
+            self.metrics.shared_errors.inc();
+            log::debug!("failed to store the verdict of {sha256} in redis: {e:#}");
+        }
+    }
+}
+
+impl<V> VerdictCache<V>
+where
+    V: Clone + Serialize + DeserializeOwned + Send + 'static,
+{
+    /// Replicate the verdicts to the standby server as the named state
+    pub fn replicate(self: &Arc<Self>, name: &str) {
+        let encode: fn(&V) -> Value = |v| serde_json::to_value(v).unwrap_or_default();
+        if self.replication.set((name.to_string(), encode)).is_ok() {
+            replication::register(name, self);
+        }
+    }
+}
+
+impl<V> ReplicatedState for VerdictCache<V>
+where
+    V: Clone + Serialize + DeserializeOwned + Send + 'static,
+{
+    fn snapshot(&self) -> Vec<(String, Value)> {
+        let now = Instant::now();
+        let state = self.state.lock().unwrap();
+        state
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.expire > now)
+            .filter_map(|((service, sha256), entry)| {
+                let istag = state.istags.get(service)?;
+                let verdict = serde_json::to_value(&entry.verdict).ok()?;
+                Some(replicated_entry(
+                    service,
+                    istag,
+                    sha256,
+                    entry.expire - now,
+                    verdict,
+                ))
+            })
+            .collect()
+    }
+
+    fn apply(&self, _key: &str, value: Value) -> anyhow::Result<()> {
+        let replicated: ReplicatedVerdict<V> = serde_json::from_value(value)?;
+        let ttl = Duration::from_millis(replicated.ttl_ms).min(self.ttl);
+        let mut state = self.state.lock().unwrap();
+        let invalidated = state.update_istag(&replicated.service, &replicated.istag);
+        self.metrics.invalidated.add(invalidated as u64);
+        let key = (replicated.service, replicated.sha256);
+        self.push(&mut state, key, replicated.verdict, Instant::now() + ttl);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(max_entries: usize) -> (VerdictCache<bool>, MetricsRegistry) {
+        let registry = MetricsRegistry::detached().module("verdict_cache_test");
+        let config = VerdictCacheConfig {
+            ttl: Duration::from_secs(60),
+            max_entries,
+            redis: None,
+        };
+        (VerdictCache::new(config, &registry).unwrap(), registry)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ttl_and_eviction() {
+        let (cache, registry) = cache(2);
+        assert_eq!(cache.get("av", "t1", "aa"), None);
+        cache.insert("av", "t1", "aa", true);
+        cache.insert("av", "t1", "bb", false);
+        assert_eq!(cache.get("av", "t1", "aa"), Some(true));
+        // another service has its own verdicts
+        assert_eq!(cache.get("yara", "t1", "aa"), None);
+
+        // bb is the least recently used
+        cache.insert("av", "t1", "cc", true);
+        assert_eq!(cache.get("av", "t1", "bb"), None);
+        assert_eq!(cache.len(), 2);
+
+        tokio::time::advance(Duration::from_secs(61)).await;
+        assert_eq!(cache.get("av", "t1", "aa"), None);
+        assert_eq!(cache.len(), 1);
+
+        let counter = |name| registry.counter(name).unwrap().get();
+        assert_eq!(counter("verdict_cache.hits"), 1);
+        assert_eq!(counter("verdict_cache.misses"), 4);
+        assert_eq!(counter("verdict_cache.evictions"), 1);
+        assert_eq!(counter("verdict_cache.expired"), 1);
+        assert_eq!(registry.gauge("verdict_cache.entries").unwrap().get(), 1);
+    }
+
+    #[tokio::test]
+    async fn istag_change() {
+        let (cache, registry) = cache(10);
+        assert_eq!(cache.get("av", "t1", "aa"), None);
+        cache.insert("av", "t1", "aa", true);
+        assert_eq!(cache.get("yara", "y1", "aa"), None);
+        cache.insert("yara", "y1", "aa", true);
+
+        assert_eq!(cache.get("av", "t2", "aa"), None);
+        assert_eq!(cache.get("yara", "y1", "aa"), Some(true));
+        let invalidated = registry.counter("verdict_cache.invalidated").unwrap();
+        assert_eq!(invalidated.get(), 1);
+
+        // a scan that started under the old ISTag
+        cache.insert("av", "t1", "aa", true);
+        assert_eq!(cache.get("av", "t2", "aa"), None);
+        cache.insert("av", "t2", "aa", false);
+        assert_eq!(cache.get("av", "t2", "aa"), Some(false));
+    }
+
+    #[tokio::test]
+    async fn replicated() {
+        let (active, _) = cache(10);
+        let (standby, _) = cache(10);
+        assert_eq!(active.get("av", "t1", "aa"), None);
+        active.insert("av", "t1", "aa", true);
+        active.insert("av", "t1", "bb", false);
+
+        for (key, value) in active.snapshot() {
+            standby.apply(&key, value).unwrap();
+        }
+        assert_eq!(standby.len(), 2);
+        assert_eq!(standby.get("av", "t1", "bb"), Some(false));
+        // the verdicts of another ISTag are dropped on the standby too
+        assert_eq!(standby.get("av", "t2", "aa"), None);
+        assert!(standby.apply("av:cc", json!({"service": "av"})).is_err());
+    }
+}
@@ -0,0 +1,384 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! SQL injection and XSS detection
+//!
+//! The `@detectSQLi` and `@detectXSS` operators, detecting like libinjection
+//! does but without its C library. A value is tokenized as SQL as is, and as
+//! if it followed an opening quote when it contains one, the fingerprint of
+//! its first tokens being matched against the shapes of the injections. A
+//! value is XSS if it opens a dangerous tag, has an event handler or a
+//! script URL in a tag or an attribute it breaks into, or is a script URL.
+
+use std::sync::LazyLock;
+
+use regex::RegexSet;
+
+/// Tokens of a fingerprint
+const FINGERPRINT_TOKENS: usize = 8;
+
+/// Shapes of the SQL injections, over the fingerprints
+///
+/// `s` is a string, `1` a number, `n` a name, `k` a keyword, `U` union, `E`
+/// a statement, `f` a function, `&` a logical operator, `o` another
+/// operator, `c` a comment, the punctuation being itself.
+static INJECTIONS: LazyLock<RegexSet> = LazyLock::new(|| {
+    RegexSet::new([
+        // ' or 1=1, ') or ('a'='a
+        r"^s\)*&\(*[1snf]",
+        // 1 or 1=1, 1) and sleep(5)
+        r"^1\)*&\(*(1o|f\()",
+        // admin'--, 1)#
+        r"^[s1]\)*c",
+        // '; drop table users
+        r"^[s1]\)*;E",
+        // union [all] select
+        r"Uk?E",
+        // and sleep(5)
+        r"&f\(",
+    ])
+    .unwrap()
+});
+
+const STATEMENTS: &[&str] = &[
+    "select", "insert", "update", "delete", "drop", "create", "alter", "truncate", "exec",
+    "execute", "declare", "shutdown", "waitfor", "grant", "revoke",
+];
+
+const KEYWORDS: &[&str] = &[
+    "from",
+    "where",
+    "into",
+    "values",
+    "table",
+    "like",
+    "having",
+    "limit",
+    "order",
+    "group",
+    "by",
+    "as",
+    "is",
+    "in",
+    "between",
+    "case",
+    "when",
+    "then",
+    "else",
+    "end",
+    "all",
+    "distinct",
+    "delay",
+    "procedure",
+    "database",
+    "information_schema",
+];
+
+const FUNCTIONS: &[&str] = &[
+    "sleep",
+    "benchmark",
+    "pg_sleep",
+    "load_file",
+    "extractvalue",
+    "updatexml",
+    "char",
+    "concat",
+    "concat_ws",
+    "group_concat",
+    "version",
+    "user",
+    "database",
+    "ascii",
+    "substring",
+    "substr",
+    "mid",
+    "ord",
+    "hex",
+    "unhex",
+    "count",
+    "if",
+    "ifnull",
+    "dbms_pipe.receive_message",
+    "utl_inaddr.get_host_name",
+    "xp_cmdshell",
+];
+
+/// Tags that run or load content
+const DANGEROUS_TAGS: &[&str] = &[
+    "script", "iframe", "frame", "frameset", "object", "embed", "applet", "base", "meta", "link",
+    "style", "svg", "math", "xml", "import", "isindex",
+];
+
+/// Attributes holding a URL
+const URL_ATTRIBUTES: &[&str] = &[
+    "href",
+    "src",
+    "action",
+    "formaction",
+    "data",
+    "xlink:href",
+    "background",
+    "lowsrc",
+    "dynsrc",
+    "poster",
+];
+
+const SCRIPT_SCHEMES: &[&str] = &["javascript:", "vbscript:", "livescript:", "data:text/html"];
+
+/// Whether the value is a SQL injection
+pub fn detect_sqli(value: &str) -> bool {
+    if matches(&fingerprint(value)) {
+        return true;
+    }
+    ['\'', '"']
+        .into_iter()
+        .filter(|quote| value.contains(*quote))
+        .any(|quote| matches(&fingerprint(&format!("{quote}{value}"))))
+}
+
+fn matches(fingerprint: &str) -> bool {
+    !fingerprint.is_empty() && INJECTIONS.is_match(fingerprint)
+}
+
+/// Types of the first tokens of the value, tokenized as SQL
+fn fingerprint(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut fingerprint = String::with_capacity(FINGERPRINT_TOKENS);
+    let mut i = 0;
+    while i < bytes.len() && fingerprint.len() < FINGERPRINT_TOKENS {
+        let b = bytes[i];
+        let (token, end) = match b {
+            b if b.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+            b'\'' | b'"' | b'`' => ('s', string_end(bytes, i)),
+            b'0'..=b'9' => {
+                let end = bytes[i..]
+                    .iter()
+                    .position(|b| !(b.is_ascii_alphanumeric() || *b == b'.'))
+                    .map_or(bytes.len(), |n| i + n);
+                ('1', end)
+            }
+            b'#' => ('c', bytes.len()),
+            b'-' if bytes.get(i + 1) == Some(&b'-') => ('c', bytes.len()),
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                let end =
+                    memchr::memmem::find(&bytes[i + 2..], b"*/").map_or(bytes.len(), |n| i + n + 4);
+                ('c', end)
+            }
+            b'(' | b')' | b',' | b';' => (b as char, i + 1),
+            b'|' if bytes.get(i + 1) == Some(&b'|') => ('&', i + 2),
+            b'&' if bytes.get(i + 1) == Some(&b'&') => ('&', i + 2),
+            b'=' | b'<' | b'>' | b'!' | b'+' | b'-' | b'*' | b'/' | b'%' | b'|' | b'&' | b'^'
+            | b'~' => {
+                let end = bytes[i..]
+                    .iter()
+                    .position(|b| !b"=<>!".contains(b))
+                    .map_or(bytes.len(), |n| i + n)
+                    .max(i + 1);
+                ('o', end)
+            }
+            b if b.is_ascii_alphabetic() || b == b'_' || b == b'@' || b == b'$' => {
+                let end = bytes[i + 1..]
+                    .iter()
+                    .position(|b| !(b.is_ascii_alphanumeric() || b"_$.@".contains(b)))
+                    .map_or(bytes.len(), |n| i + 1 + n);
+                (word(&value[i..end], &bytes[end..]), end)
+            }
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        fingerprint.push(token);
+        i = end;
+    }
+    fingerprint
+}
+
+/// End of the string starting at the quote, the doubled or escaped quotes
+/// being part of it
+fn string_end(bytes: &[u8], start: usize) -> usize {
+    let quote = bytes[start];
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b if b == quote && bytes.get(i + 1) == Some(&quote) => i += 2,
+            b if b == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+fn word(word: &str, rest: &[u8]) -> char {
+    let word = word.to_ascii_lowercase();
+    match word.as_str() {
+        "and" | "or" | "xor" | "not" | "div" => return '&',
+        "union" => return 'U',
+        "true" | "false" | "null" => return '1',
+        _ => {}
+    }
+    if STATEMENTS.contains(&word.as_str()) {
+        'E'
+    } else if FUNCTIONS.contains(&word.as_str())
+        && rest.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'(')
+    {
+        'f'
+    } else if KEYWORDS.contains(&word.as_str()) {
+        'k'
+    } else {
+        'n'
+    }
+}
+
+/// Whether the value is a cross site scripting attack
+pub fn detect_xss(value: &str) -> bool {
+    let value = value.to_ascii_lowercase();
+    if is_script_url(&value) {
+        return true;
+    }
+    let mut rest = value.as_str();
+    while let Some(n) = rest.find('<') {
+        rest = &rest[n + 1..];
+        let tag = rest.strip_prefix('/').unwrap_or(rest);
+        let name_len = tag
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == ':'))
+            .unwrap_or(tag.len());
+        let name = &tag[..name_len];
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            continue;
+        }
+        if DANGEROUS_TAGS.contains(&name) {
+            return true;
+        }
+        let end = tag.find('>').unwrap_or(tag.len());
+        if has_script_attribute(&tag[name_len..end]) {
+            return true;
+        }
+    }
+    // breaking out of a quoted attribute value
+    ['"', '\'']
+        .into_iter()
+        .filter_map(|quote| value.find(quote))
+        .any(|n| has_script_attribute(&value[n + 1..]))
+}
+
+/// Whether the value, once the characters ignored by the browsers are
+/// dropped, is a URL running a script
+fn is_script_url(value: &str) -> bool {
+    let compact: String = value
+        .trim_start()
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_control())
+        .take(16)
+        .collect();
+    SCRIPT_SCHEMES.iter().any(|s| compact.starts_with(s))
+}
+
+/// Whether the attributes of a tag have an event handler, a script URL or a
+/// script style
+fn has_script_attribute(attributes: &str) -> bool {
+    let mut rest = attributes;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == '/');
+        let name_len = rest
+            .find(|c: char| c.is_ascii_whitespace() || "=>/".contains(c))
+            .unwrap_or(rest.len());
+        if name_len == 0 {
+            return false;
+        }
+        let name = &rest[..name_len];
+        rest = rest[name_len..].trim_start();
+        let Some(value) = rest.strip_prefix('=') else {
+            continue;
+        };
+        if name.len() > 2 && name.starts_with("on") {
+            return true;
+        }
+        let value = value.trim_start();
+        let (value, next) = match value.chars().next() {
+            Some(quote @ ('"' | '\'' | '`')) => {
+                let value = &value[1..];
+                let end = value.find(quote).unwrap_or(value.len());
+                (&value[..end], &value[(end + 1).min(value.len())..])
+            }
+            _ => {
+                let end = value
+                    .find(|c: char| c.is_ascii_whitespace() || c == '>')
+                    .unwrap_or(value.len());
+                (&value[..end], &value[end..])
+            }
+        };
+        if URL_ATTRIBUTES.contains(&name) && is_script_url(value) {
+            return true;
+        }
+        if name == "style" && (value.contains("expression(") || value.contains("javascript:")) {
+            return true;
+        }
+        rest = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sqli() {
+        for value in [
+            "' or 1=1--",
+            "1' OR '1'='1",
+            "admin'--",
+            "1 or 1=1",
+            "') or ('a'='a",
+            "1; DROP TABLE users",
+            "x' UNION ALL SELECT password FROM users--",
+            "1 AND SLEEP(5)",
+            "\" or \"\"=\"",
+        ] {
+            assert!(detect_sqli(value), "{value}");
+        }
+        for value in [
+            "hello world",
+            "O'Brien",
+            "rock 'n' roll or jazz",
+            "select a size",
+            "2024-01-01",
+            "cats and dogs",
+            "john@example.net",
+        ] {
+            assert!(!detect_sqli(value), "{value}");
+        }
+    }
+
+    #[test]
+    fn xss() {
+        for value in [
+            "<script>alert(1)</script>",
+            "<img src=x onerror=alert(1)>",
+            "<a href=\"java\tscript:alert(1)\">",
+            "javascript:alert(document.cookie)",
+            "\" onmouseover=\"alert(1)",
+            "<svg/onload=alert(1)>",
+            "<div style=\"width: expression(alert(1))\">",
+            "<IFRAME SRC=//evil.example>",
+        ] {
+            assert!(detect_xss(value), "{value}");
+        }
+        for value in [
+            "hello world",
+            "a < b and c > d",
+            "<b>bold</b>",
+            "<a href=\"https://example.net/\">link</a>",
+            "he said \"only once\"",
+            "mention <3",
+        ] {
+            assert!(!detect_xss(value), "{value}");
+        }
+    }
+}
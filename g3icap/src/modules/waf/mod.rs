@@ -0,0 +1,475 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! WAF module
+//!
+//! Evaluates ModSecurity rules, such as the request rules of the OWASP CRS,
+//! on the HTTP requests of REQMOD. A request denied by a rule, or whose
+//! anomaly score reaches the threshold, is answered with a page carrying the
+//! rule id, the other requests are allowed unmodified. The query and the
+//! URL encoded or JSON bodies are parsed in the `ARGS`, the JSON ones being
+//! keyed by their path.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use http::{HeaderValue, StatusCode};
+
+use crate::config::server::waf::WafConfig;
+use crate::modules::block_page::render_with;
+use crate::modules::content_filter::{HEADER_RULE_CATEGORY, HEADER_RULE_ID};
+use crate::modules::expression::dsl::Subject;
+use crate::modules::rule_hits;
+use crate::modules::{IcapModule, ModuleConfig, ModuleError, ModuleMetrics, TransferSettings};
+use crate::protocol::common::{IcapMethod, IcapRequest, IcapResponse};
+use crate::protocol::response_generator::IcapResponseGenerator;
+use crate::transaction::TransactionCtx;
+
+pub mod injection;
+pub mod rules;
+
+use rules::{Evaluation, RuleSet};
+
+/// Page of the denied requests
+const DENIED_TEMPLATE: &str = "<!DOCTYPE html>\n\
+<html>\n\
+<head><meta charset=\"utf-8\"><title>Request denied</title></head>\n\
+<body><h1>Request denied</h1><p>The request has been denied by rule {rule}: {msg}</p></body>\n\
+</html>\n";
+
+/// Category of the denied requests, for the rules without an attack tag
+const DEFAULT_CATEGORY: &str = "waf";
+
+/// Parts of an HTTP request the rules inspect
+#[derive(Debug, Clone, Default)]
+pub struct Transaction {
+    pub method: String,
+    /// Request target, with the query
+    pub uri: String,
+    pub protocol: String,
+    pub headers: Vec<(String, String)>,
+    /// Request body, up to the inspection limit
+    pub body: String,
+    args_get: Vec<(String, String)>,
+    args_post: Vec<(String, String)>,
+    cookies: Vec<(String, String)>,
+}
+
+impl Transaction {
+    pub fn new(
+        method: &str,
+        uri: &str,
+        protocol: &str,
+        headers: Vec<(String, String)>,
+        body: String,
+    ) -> Self {
+        let mut tx = Transaction {
+            method: method.to_string(),
+            uri: uri.to_string(),
+            protocol: protocol.to_string(),
+            headers,
+            body,
+            ..Default::default()
+        };
+        tx.args_get = parse_urlencoded(tx.query());
+        let content_type = tx
+            .header("content-type")
+            .map(|v| v.to_ascii_lowercase())
+            .unwrap_or_default();
+        if content_type.starts_with("application/x-www-form-urlencoded") {
+            tx.args_post = parse_urlencoded(&tx.body);
+        } else if content_type.contains("json")
+            && let Ok(json) = serde_json::from_str(&tx.body)
+        {
+            flatten_json("json", &json, &mut tx.args_post);
+        }
+        tx.cookies = tx
+            .headers
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("cookie"))
+            .flat_map(|(_, value)| value.split(';'))
+            .filter_map(|cookie| {
+                let (name, value) = cookie.trim().split_once('=')?;
+                Some((name.to_string(), value.to_string()))
+            })
+            .collect();
+        tx
+    }
+
+    /// Transaction of the HTTP request of a REQMOD request
+    pub fn from_request(request: &IcapRequest, body_limit: usize) -> Option<Self> {
+        let subject = Subject::new(request, "");
+        let mut request_line = subject.request_line()?.split(' ');
+        let method = request_line.next()?;
+        let uri = request_line.next()?;
+        let protocol = request_line.next().unwrap_or_default();
+
+        let encapsulated = request.encapsulated.as_ref()?;
+        let headers = encapsulated
+            .req_hdr
+            .iter()
+            .flatten()
+            .map(|(name, value)| {
+                let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+                (name.as_str().to_string(), value)
+            })
+            .collect();
+        let body = encapsulated
+            .req_body
+            .as_ref()
+            .map(|body| String::from_utf8_lossy(&body[..body.len().min(body_limit)]).into_owned())
+            .unwrap_or_default();
+        Some(Transaction::new(method, uri, protocol, headers, body))
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Path and query of the request target
+    fn origin_form(&self) -> &str {
+        match self.uri.split_once("://") {
+            Some((_, rest)) => rest.find('/').map_or("/", |n| &rest[n..]),
+            None => &self.uri,
+        }
+    }
+
+    fn path(&self) -> &str {
+        let origin = self.origin_form();
+        origin.split_once('?').map_or(origin, |(path, _)| path)
+    }
+
+    fn query(&self) -> &str {
+        self.origin_form()
+            .split_once('?')
+            .map_or("", |(_, query)| query)
+    }
+}
+
+/// Names and values of a URL encoded form, decoded
+fn parse_urlencoded(s: &str) -> Vec<(String, String)> {
+    let decode = |s: &str| rules::url_decode(s, false);
+    s.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((name, value)) => (decode(name), decode(value)),
+            None => (decode(pair), String::new()),
+        })
+        .collect()
+}
+
+/// Add the scalar values of a JSON document, keyed by their path
+fn flatten_json(path: &str, value: &serde_json::Value, args: &mut Vec<(String, String)>) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map {
+                flatten_json(&format!("{path}.{key}"), value, args);
+            }
+        }
+        serde_json::Value::Array(values) => {
+            for (i, value) in values.iter().enumerate() {
+                flatten_json(&format!("{path}.{i}"), value, args);
+            }
+        }
+        serde_json::Value::String(s) => args.push((path.to_string(), s.clone())),
+        serde_json::Value::Null => args.push((path.to_string(), String::new())),
+        value => args.push((path.to_string(), value.to_string())),
+    }
+}
+
+/// Module evaluating ModSecurity rules on the requests
+pub struct WafModule {
+    name: String,
+    version: String,
+    rules: RuleSet,
+    anomaly_threshold: u32,
+    body_limit: usize,
+    generator: IcapResponseGenerator,
+    metrics: Mutex<ModuleMetrics>,
+}
+
+impl WafModule {
+    /// Load the rules of the config
+    pub fn new(config: &WafConfig) -> Result<Self, ModuleError> {
+        let rules = config
+            .load_rules()
+            .map_err(|e| ModuleError::InitFailed(e.to_string()))?;
+        for skipped in &rules.skipped {
+            log::debug!("waf {skipped}");
+        }
+        log::info!(
+            "waf loaded {} rules, {} skipped as unsupported",
+            rules.rules().len(),
+            rules.skipped.len()
+        );
+        let version = "1.0.0".to_string();
+        Ok(WafModule {
+            name: "waf".to_string(),
+            generator: IcapResponseGenerator::new(
+                format!("G3ICAP-WAF/{version}"),
+                format!("waf-{version}"),
+            ),
+            version,
+            rules,
+            anomaly_threshold: config.anomaly_threshold,
+            body_limit: config.body_limit,
+            metrics: Mutex::new(ModuleMetrics::default()),
+        })
+    }
+
+    /// Evaluate the rules on the HTTP request of the transaction
+    pub fn evaluate(&self, request: &IcapRequest) -> Evaluation {
+        match Transaction::from_request(request, self.body_limit) {
+            Some(tx) => self.rules.evaluate(&tx, self.anomaly_threshold),
+            None => Evaluation::default(),
+        }
+    }
+
+    fn handle(&self, request: &IcapRequest) -> IcapResponse {
+        let start = Instant::now();
+        let evaluation = self.evaluate(request);
+        for m in &evaluation.matches {
+            rule_hits::global().record(&self.name, &m.id.to_string());
+            log::debug!(
+                "waf rule {} matched {} in {}: {}",
+                m.id,
+                request.uri,
+                m.variable,
+                m.msg.as_deref().unwrap_or_default()
+            );
+        }
+        let response = match evaluation.denied_by() {
+            Some(m) => {
+                log::info!(
+                    "waf rule {} denied {} at anomaly score {}",
+                    m.id,
+                    request.uri,
+                    evaluation.score
+                );
+                let status = m
+                    .status
+                    .and_then(|s| StatusCode::from_u16(s).ok())
+                    .unwrap_or(StatusCode::FORBIDDEN);
+                let page = render_with(DENIED_TEMPLATE, |name| match name {
+                    "rule" => Some(m.id.to_string()),
+                    "msg" => Some(m.msg.clone().unwrap_or_default()),
+                    _ => None,
+                });
+                let mut response =
+                    self.generator
+                        .http_page_response_with_headers(status, &page, &[]);
+                response
+                    .headers
+                    .insert(HEADER_RULE_ID, HeaderValue::from(m.id));
+                let category = m.category.as_deref().unwrap_or(DEFAULT_CATEGORY);
+                if let Ok(value) = HeaderValue::from_str(category) {
+                    response.headers.insert(HEADER_RULE_CATEGORY, value);
+                }
+                response
+            }
+            None => self.generator.no_modifications(None),
+        };
+        self.update_metrics(start.elapsed());
+        response
+    }
+
+    fn update_metrics(&self, elapsed: Duration) {
+        let mut metrics = self.metrics.lock().unwrap();
+        metrics.requests_total += 1;
+        let total = u32::try_from(metrics.requests_total).unwrap_or(u32::MAX);
+        metrics.average_response_time =
+            (metrics.average_response_time * (total - 1) + elapsed) / total;
+        metrics.last_activity = Some(Instant::now());
+    }
+}
+
+#[async_trait]
+impl IcapModule for WafModule {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn version(&self) -> &str {
+        &self.version
+    }
+
+    fn supported_methods(&self) -> Vec<IcapMethod> {
+        vec![IcapMethod::Options, IcapMethod::Reqmod]
+    }
+
+    async fn init(&mut self, _config: &ModuleConfig) -> Result<(), ModuleError> {
+        rule_hits::global().register(
+            &self.name,
+            self.rules.rules().iter().map(|r| r.id.to_string()),
+        );
+        Ok(())
+    }
+
+    async fn handle_reqmod(
+        &self,
+        request: &IcapRequest,
+        _ctx: &TransactionCtx,
+    ) -> Result<IcapResponse, ModuleError> {
+        Ok(self.handle(request))
+    }
+
+    async fn handle_respmod(
+        &self,
+        _request: &IcapRequest,
+        _ctx: &TransactionCtx,
+    ) -> Result<IcapResponse, ModuleError> {
+        Ok(self.generator.no_modifications(None))
+    }
+
+    async fn handle_options(&self, _request: &IcapRequest) -> Result<IcapResponse, ModuleError> {
+        Ok(self
+            .generator
+            .options_response(&self.supported_methods(), Default::default()))
+    }
+
+    fn is_healthy(&self) -> bool {
+        true
+    }
+
+    fn transfer_settings(&self) -> TransferSettings {
+        // the rules see the request body up to the inspection limit
+        TransferSettings {
+            preview: Some(self.body_limit),
+            ..Default::default()
+        }
+    }
+
+    fn get_metrics(&self) -> ModuleMetrics {
+        self.metrics.lock().unwrap().clone()
+    }
+
+    async fn cleanup(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use http::{HeaderMap, Version};
+
+    use crate::protocol::common::EncapsulatedData;
+
+    fn module(rules: &str) -> WafModule {
+        let config = WafConfig {
+            rules: Some(rules.to_string()),
+            ..Default::default()
+        };
+        WafModule::new(&config).unwrap()
+    }
+
+    fn reqmod(target: &str, headers: &[(&str, &str)], body: &str) -> IcapRequest {
+        let mut http_header = format!("POST {target} HTTP/1.1\r\n");
+        let mut req_hdr = HeaderMap::new();
+        for (name, value) in headers {
+            http_header.push_str(&format!("{name}: {value}\r\n"));
+            req_hdr.append(
+                http::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                value.parse().unwrap(),
+            );
+        }
+        http_header.push_str("\r\n");
+        let mut icap_headers = HeaderMap::new();
+        icap_headers.insert(
+            "encapsulated",
+            format!("req-hdr=0, req-body={}", http_header.len())
+                .parse()
+                .unwrap(),
+        );
+        IcapRequest {
+            method: IcapMethod::Reqmod,
+            uri: "icap://icap.example.net/reqmod".parse().unwrap(),
+            version: Version::HTTP_11,
+            headers: icap_headers,
+            body: Bytes::from(http_header),
+            encapsulated: Some(EncapsulatedData {
+                req_hdr: Some(req_hdr),
+                req_body: Some(Bytes::from(body.to_string())),
+                res_hdr: None,
+                res_body: None,
+                null_body: false,
+                trailers: None,
+                ieof: false,
+                req_line: None,
+                status_line: None,
+            }),
+        }
+    }
+
+    const RULES: &str = r#"
+SecRule ARGS|REQUEST_COOKIES "@detectSQLi" \
+    "id:942100,phase:2,block,t:none,msg:'SQL Injection',tag:'attack-sqli',severity:'CRITICAL'"
+SecRule ARGS|REQUEST_HEADERS:Referer "@detectXSS" \
+    "id:941100,phase:2,block,t:none,t:htmlEntityDecode,msg:'XSS',tag:'attack-xss',severity:'CRITICAL'"
+SecRule REQUEST_BODY "@rx (?i)<!entity" "id:921000,phase:2,deny,status:400,msg:'XXE'"
+"#;
+
+    #[test]
+    fn transaction() {
+        let request = reqmod(
+            "http://shop.example.net/cart?item=42&q=a+b",
+            &[
+                ("Content-Type", "application/json"),
+                ("Cookie", "session=abc; theme=dark"),
+            ],
+            r#"{"user": {"name": "x", "roles": ["a", 1]}}"#,
+        );
+        let tx = Transaction::from_request(&request, 1024).unwrap();
+        assert_eq!(
+            (tx.method.as_str(), tx.protocol.as_str()),
+            ("POST", "HTTP/1.1")
+        );
+        assert_eq!((tx.path(), tx.query()), ("/cart", "item=42&q=a+b"));
+        let pairs = |v: &[(String, String)]| {
+            v.iter()
+                .map(|(k, v)| format!("{k}={v}"))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(pairs(&tx.args_get), ["item=42", "q=a b"]);
+        assert_eq!(
+            pairs(&tx.args_post),
+            [
+                "json.user.name=x",
+                "json.user.roles.0=a",
+                "json.user.roles.1=1"
+            ]
+        );
+        assert_eq!(pairs(&tx.cookies), ["session=abc", "theme=dark"]);
+
+        let request = reqmod("/upload", &[], "0123456789");
+        let tx = Transaction::from_request(&request, 4).unwrap();
+        assert_eq!(tx.body, "0123");
+    }
+
+    #[test]
+    fn handle() {
+        let waf = module(RULES);
+        let response = waf.handle(&reqmod("/search?q=shoes", &[], ""));
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+
+        let response = waf.handle(&reqmod("/search?q=1'+or+'1'='1", &[], ""));
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.headers[HEADER_RULE_ID], "942100");
+        assert_eq!(response.headers[HEADER_RULE_CATEGORY], "sqli");
+
+        let form = [("Content-Type", "application/x-www-form-urlencoded")];
+        let response = waf.handle(&reqmod("/comment", &form, "text=%3Cscript%3Ealert(1)"));
+        assert_eq!(response.headers[HEADER_RULE_ID], "941100");
+
+        let xml = "<?xml version=\"1.0\"?><!DOCTYPE a [<!ENTITY x SYSTEM \"file:///etc/passwd\">]>";
+        let response = waf.handle(&reqmod("/api", &[("Content-Type", "text/xml")], xml));
+        assert_eq!(response.headers[HEADER_RULE_ID], "921000");
+        assert_eq!(response.headers[HEADER_RULE_CATEGORY], DEFAULT_CATEGORY);
+        assert_eq!(waf.get_metrics().requests_total, 4);
+    }
+}
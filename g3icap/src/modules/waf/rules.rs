@@ -0,0 +1,1359 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! ModSecurity rule language
+//!
+//! The subset of the language the request rules of the OWASP CRS are
+//! written in, so that the CRS files can be loaded as they are:
+//!
+//! ```text
+//! SecRule ARGS|REQUEST_HEADERS:User-Agent|!ARGS:token "@detectSQLi" \
+//!     "id:942100,phase:2,block,t:none,t:urlDecodeUni,msg:'SQL Injection',severity:'CRITICAL'"
+//! ```
+//!
+//! The variables are the request line, `ARGS`, the headers, the cookies and
+//! the body, with `:` selectors, `!` exclusions and `&` counts. The operators
+//! are `@rx`, which is the default, `@pm`, `@pmFromFile`, the string and
+//! number comparisons, `@validateByteRange`, `@detectSQLi` and `@detectXSS`.
+//! `id`, `phase`, `msg`, `severity`, `status`, `tag`, `t`, `chain` and the
+//! disruptive actions are used, the other actions are ignored, so that the
+//! anomaly scoring is done from the severity of the blocking rules instead
+//! of their `setvar`s. Rules needing anything else, like the `TX` variables,
+//! macros or regular expressions with look-arounds, are skipped, as are the
+//! response rules. `SecRuleEngine` and `SecRuleRemoveById` are applied, the
+//! other directives are ignored.
+
+use std::borrow::Cow;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use aho_corasick::AhoCorasick;
+use regex::{Regex, RegexBuilder};
+
+use super::Transaction;
+use super::injection;
+
+/// Size limit of the compiled regular expressions, the CRS ones being large
+const REGEX_SIZE_LIMIT: usize = 64 * 1024 * 1024;
+
+/// Tag prefix of the paranoia level of the CRS rules
+const PARANOIA_TAG: &str = "paranoia-level/";
+
+/// Error of a rule file
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{message} at line {line}")]
+pub struct RuleError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl RuleError {
+    fn new(line: usize, message: impl Into<String>) -> Self {
+        RuleError {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+/// `SecRuleEngine` mode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Engine {
+    #[default]
+    On,
+    Off,
+    /// Rules are evaluated but requests are never denied
+    DetectionOnly,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Emergency,
+    Alert,
+    Critical,
+    Error,
+    Warning,
+    Notice,
+    Info,
+    Debug,
+}
+
+impl Severity {
+    fn parse(s: &str) -> Option<Self> {
+        let severity = match s.to_ascii_uppercase().as_str() {
+            "0" | "EMERGENCY" => Severity::Emergency,
+            "1" | "ALERT" => Severity::Alert,
+            "2" | "CRITICAL" => Severity::Critical,
+            "3" | "ERROR" => Severity::Error,
+            "4" | "WARNING" => Severity::Warning,
+            "5" | "NOTICE" => Severity::Notice,
+            "6" | "INFO" => Severity::Info,
+            "7" | "DEBUG" => Severity::Debug,
+            _ => return None,
+        };
+        Some(severity)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Emergency => "EMERGENCY",
+            Severity::Alert => "ALERT",
+            Severity::Critical => "CRITICAL",
+            Severity::Error => "ERROR",
+            Severity::Warning => "WARNING",
+            Severity::Notice => "NOTICE",
+            Severity::Info => "INFO",
+            Severity::Debug => "DEBUG",
+        }
+    }
+
+    /// Anomaly score of a blocking rule of the severity, as in the CRS
+    pub fn score(&self) -> u32 {
+        match self {
+            Severity::Emergency | Severity::Alert | Severity::Critical => 5,
+            Severity::Error => 4,
+            Severity::Warning => 3,
+            Severity::Notice => 2,
+            Severity::Info | Severity::Debug => 0,
+        }
+    }
+}
+
+/// What a matching rule does with the request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Disruptive {
+    /// The match is only logged
+    #[default]
+    Pass,
+    /// The severity of the rule adds to the anomaly score
+    Block,
+    /// The request is denied at once
+    Deny,
+    /// The request is allowed without evaluating the next rules
+    Allow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Collection {
+    RequestUri,
+    RequestFilename,
+    RequestBasename,
+    QueryString,
+    RequestMethod,
+    RequestProtocol,
+    RequestLine,
+    Args,
+    ArgsNames,
+    ArgsGet,
+    ArgsGetNames,
+    ArgsPost,
+    ArgsPostNames,
+    RequestHeaders,
+    RequestHeadersNames,
+    RequestCookies,
+    RequestCookiesNames,
+    RequestBody,
+}
+
+const COLLECTIONS: &[(&str, Collection)] = &[
+    ("REQUEST_URI", Collection::RequestUri),
+    ("REQUEST_URI_RAW", Collection::RequestUri),
+    ("REQUEST_FILENAME", Collection::RequestFilename),
+    ("REQUEST_BASENAME", Collection::RequestBasename),
+    ("QUERY_STRING", Collection::QueryString),
+    ("REQUEST_METHOD", Collection::RequestMethod),
+    ("REQUEST_PROTOCOL", Collection::RequestProtocol),
+    ("REQUEST_LINE", Collection::RequestLine),
+    ("ARGS", Collection::Args),
+    ("ARGS_NAMES", Collection::ArgsNames),
+    ("ARGS_GET", Collection::ArgsGet),
+    ("ARGS_GET_NAMES", Collection::ArgsGetNames),
+    ("ARGS_POST", Collection::ArgsPost),
+    ("ARGS_POST_NAMES", Collection::ArgsPostNames),
+    ("REQUEST_HEADERS", Collection::RequestHeaders),
+    ("REQUEST_HEADERS_NAMES", Collection::RequestHeadersNames),
+    ("REQUEST_COOKIES", Collection::RequestCookies),
+    ("REQUEST_COOKIES_NAMES", Collection::RequestCookiesNames),
+    ("REQUEST_BODY", Collection::RequestBody),
+];
+
+impl Collection {
+    fn parse(name: &str) -> Option<Self> {
+        COLLECTIONS
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, c)| *c)
+    }
+
+    fn name(&self) -> &'static str {
+        COLLECTIONS
+            .iter()
+            .find(|(_, c)| c == self)
+            .map(|(n, _)| *n)
+            .unwrap_or_default()
+    }
+
+    /// Keys and values of the collection in the transaction
+    fn items<'a>(&self, tx: &'a Transaction) -> Vec<(Option<&'a str>, Cow<'a, str>)> {
+        let single = |value: &'a str| vec![(None, Cow::Borrowed(value))];
+        let values = |pairs: &'a [(String, String)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| (Some(k.as_str()), Cow::Borrowed(v.as_str())))
+                .collect()
+        };
+        let names = |pairs: &'a [(String, String)]| {
+            pairs
+                .iter()
+                .map(|(k, _)| (Some(k.as_str()), Cow::Borrowed(k.as_str())))
+                .collect()
+        };
+        match self {
+            Collection::RequestUri => single(&tx.uri),
+            Collection::RequestFilename => single(tx.path()),
+            Collection::RequestBasename => single(tx.path().rsplit('/').next().unwrap_or_default()),
+            Collection::QueryString => single(tx.query()),
+            Collection::RequestMethod => single(&tx.method),
+            Collection::RequestProtocol => single(&tx.protocol),
+            Collection::RequestLine => vec![(
+                None,
+                Cow::Owned(format!("{} {} {}", tx.method, tx.uri, tx.protocol)),
+            )],
+            Collection::Args => {
+                let mut items: Vec<_> = values(&tx.args_get);
+                items.extend(values(&tx.args_post));
+                items
+            }
+            Collection::ArgsNames => {
+                let mut items: Vec<_> = names(&tx.args_get);
+                items.extend(names(&tx.args_post));
+                items
+            }
+            Collection::ArgsGet => values(&tx.args_get),
+            Collection::ArgsGetNames => names(&tx.args_get),
+            Collection::ArgsPost => values(&tx.args_post),
+            Collection::ArgsPostNames => names(&tx.args_post),
+            Collection::RequestHeaders => values(&tx.headers),
+            Collection::RequestHeadersNames => names(&tx.headers),
+            Collection::RequestCookies => values(&tx.cookies),
+            Collection::RequestCookiesNames => names(&tx.cookies),
+            Collection::RequestBody => single(&tx.body),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Selector {
+    Key(String),
+    Pattern(Regex),
+}
+
+impl Selector {
+    fn matches(&self, key: &str) -> bool {
+        match self {
+            Selector::Key(name) => name.eq_ignore_ascii_case(key),
+            Selector::Pattern(regex) => regex.is_match(key),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Variable {
+    collection: Collection,
+    selector: Option<Selector>,
+    /// The number of the items is inspected instead of their values
+    count: bool,
+}
+
+impl Variable {
+    fn selects(&self, collection: Collection, key: Option<&str>) -> bool {
+        self.collection == collection
+            && match (&self.selector, key) {
+                (None, _) => true,
+                (Some(selector), Some(key)) => selector.matches(key),
+                (Some(_), None) => false,
+            }
+    }
+
+    /// Names and values of the variable, but the excluded ones
+    fn values<'a>(
+        &self,
+        tx: &'a Transaction,
+        excludes: &[Variable],
+    ) -> Vec<(String, Cow<'a, str>)> {
+        let items = self
+            .collection
+            .items(tx)
+            .into_iter()
+            .filter(|(key, _)| self.selects(self.collection, *key))
+            .filter(|(key, _)| !excludes.iter().any(|e| e.selects(self.collection, *key)));
+        let name = self.collection.name();
+        if self.count {
+            let count = items.count();
+            return vec![(format!("&{name}"), Cow::Owned(count.to_string()))];
+        }
+        items
+            .map(|(key, value)| match key {
+                Some(key) => (format!("{name}:{key}"), value),
+                None => (name.to_string(), value),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transform {
+    Lowercase,
+    Uppercase,
+    UrlDecode,
+    UrlDecodeUni,
+    HtmlEntityDecode,
+    CompressWhitespace,
+    RemoveWhitespace,
+    RemoveNulls,
+    ReplaceNulls,
+    ReplaceComments,
+    Trim,
+    TrimLeft,
+    TrimRight,
+    NormalizePath,
+    NormalizePathWin,
+    CmdLine,
+    Length,
+}
+
+impl Transform {
+    /// Parse the transformation, `None` being `t:none`
+    fn parse(name: &str) -> Result<Option<Self>, String> {
+        let transform = match name.to_ascii_lowercase().as_str() {
+            "none" => return Ok(None),
+            "lowercase" => Transform::Lowercase,
+            "uppercase" => Transform::Uppercase,
+            "urldecode" => Transform::UrlDecode,
+            "urldecodeuni" => Transform::UrlDecodeUni,
+            "htmlentitydecode" => Transform::HtmlEntityDecode,
+            "compresswhitespace" => Transform::CompressWhitespace,
+            "removewhitespace" => Transform::RemoveWhitespace,
+            "removenulls" => Transform::RemoveNulls,
+            "replacenulls" => Transform::ReplaceNulls,
+            "replacecomments" => Transform::ReplaceComments,
+            "trim" => Transform::Trim,
+            "trimleft" => Transform::TrimLeft,
+            "trimright" => Transform::TrimRight,
+            "normalizepath" | "normalisepath" => Transform::NormalizePath,
+            "normalizepathwin" | "normalisepathwin" => Transform::NormalizePathWin,
+            "cmdline" => Transform::CmdLine,
+            "length" => Transform::Length,
+            _ => return Err(format!("unsupported transformation {name}")),
+        };
+        Ok(Some(transform))
+    }
+
+    fn apply(&self, value: String) -> String {
+        match self {
+            Transform::Lowercase => value.to_lowercase(),
+            Transform::Uppercase => value.to_uppercase(),
+            Transform::UrlDecode => url_decode(&value, false),
+            Transform::UrlDecodeUni => url_decode(&value, true),
+            Transform::HtmlEntityDecode => html_entity_decode(&value),
+            Transform::CompressWhitespace => value.split_whitespace().collect::<Vec<_>>().join(" "),
+            Transform::RemoveWhitespace => value.chars().filter(|c| !c.is_whitespace()).collect(),
+            Transform::RemoveNulls => value.replace('\0', ""),
+            Transform::ReplaceNulls => value.replace('\0', " "),
+            Transform::ReplaceComments => replace_comments(&value),
+            Transform::Trim => value.trim().to_string(),
+            Transform::TrimLeft => value.trim_start().to_string(),
+            Transform::TrimRight => value.trim_end().to_string(),
+            Transform::NormalizePath => normalize_path(&value),
+            Transform::NormalizePathWin => normalize_path(&value.replace('\\', "/")),
+            Transform::CmdLine => cmd_line(&value),
+            Transform::Length => value.len().to_string(),
+        }
+    }
+}
+
+/// Decode the `%XX` escapes and the `+`, and the `%uXXXX` ones if asked
+pub(super) fn url_decode(value: &str, unicode: bool) -> String {
+    let bytes = value.as_bytes();
+    let hex = |b: &[u8]| {
+        b.iter()
+            .try_fold(0, |n, b| Some(n * 16 + (*b as char).to_digit(16)?))
+    };
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let unicode_escape = if unicode && matches!(bytes.get(i + 1), Some(b'u' | b'U')) {
+            bytes.get(i + 2..i + 6).and_then(hex)
+        } else {
+            None
+        };
+        let escape = bytes.get(i + 1..i + 3).and_then(hex);
+        if bytes[i] == b'%'
+            && let Some(c) = unicode_escape
+        {
+            // as IIS does, the full width ASCII being mapped to ASCII
+            let c = match c {
+                0xff01..=0xff5e => c - 0xfee0,
+                c => c,
+            };
+            let c = char::from_u32(c).unwrap_or(char::REPLACEMENT_CHARACTER);
+            out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            i += 6;
+        } else if bytes[i] == b'%'
+            && let Some(b) = escape
+        {
+            out.push(b as u8);
+            i += 3;
+        } else {
+            out.push(if bytes[i] == b'+' { b' ' } else { bytes[i] });
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn html_entity_decode(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(n) = rest.find('&') {
+        out.push_str(&rest[..n]);
+        rest = &rest[n..];
+        let end = rest[1..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '#'))
+            .map_or(rest.len(), |n| n + 1);
+        let entity = &rest[1..end];
+        let decoded = match entity.to_ascii_lowercase().as_str() {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{a0}'),
+            e => e
+                .strip_prefix("#x")
+                .map(|h| u32::from_str_radix(h, 16))
+                .or_else(|| e.strip_prefix('#').map(|d| d.parse()))
+                .and_then(|c| c.ok())
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = rest[end..].strip_prefix(';').unwrap_or(&rest[end..]);
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Replace the C style comments by a space, an unterminated one included
+fn replace_comments(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(n) = rest.find("/*") {
+        out.push_str(&rest[..n]);
+        out.push(' ');
+        rest = match rest[n + 2..].find("*/") {
+            Some(end) => &rest[n + 2 + end + 2..],
+            None => "",
+        };
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Resolve the `.` and `..` segments and merge the slashes
+fn normalize_path(value: &str) -> String {
+    let absolute = value.starts_with('/');
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in value.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            s => segments.push(s),
+        }
+    }
+    let mut path = segments.join("/");
+    if absolute {
+        path.insert(0, '/');
+    }
+    if value.ends_with('/') && !path.ends_with('/') {
+        path.push('/');
+    }
+    path
+}
+
+/// Normalize a command line as the shells parse it
+fn cmd_line(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' | '"' | '\'' | '^' => {}
+            ',' | ';' | ' ' | '\t' | '\r' | '\n' => {
+                if !out.ends_with(' ') {
+                    out.push(' ');
+                }
+            }
+            '/' | '(' => {
+                if out.ends_with(' ') {
+                    out.pop();
+                }
+                out.push(c);
+            }
+            c => out.extend(c.to_lowercase()),
+        }
+    }
+    out
+}
+
+#[derive(Debug, Clone)]
+enum Operator {
+    Rx(Regex),
+    Pm(AhoCorasick),
+    StrEq(String),
+    Contains(String),
+    ContainsWord(String),
+    BeginsWith(String),
+    EndsWith(String),
+    Within(String),
+    Eq(i64),
+    Ge(i64),
+    Gt(i64),
+    Le(i64),
+    Lt(i64),
+    ValidateByteRange(Vec<RangeInclusive<u8>>),
+    DetectSqli,
+    DetectXss,
+    UnconditionalMatch,
+    NoMatch,
+}
+
+impl Operator {
+    /// Parse the operator and its negation
+    fn parse(s: &str, dir: Option<&Path>) -> Result<(Self, bool), String> {
+        let (negated, s) = match s.strip_prefix('!') {
+            Some(s) => (true, s),
+            None => (false, s),
+        };
+        let (name, param) = match s.strip_prefix('@') {
+            Some(s) => s.split_once(char::is_whitespace).unwrap_or((s, "")),
+            None => ("rx", s),
+        };
+        if param.contains("%{") {
+            return Err(format!("unsupported macro in operator {name}"));
+        }
+        let number = || {
+            param
+                .trim()
+                .parse::<i64>()
+                .map_err(|_| format!("invalid number {param} of operator {name}"))
+        };
+        let operator = match name.to_ascii_lowercase().as_str() {
+            "rx" => Operator::Rx(
+                RegexBuilder::new(param)
+                    .size_limit(REGEX_SIZE_LIMIT)
+                    .build()
+                    .map_err(|e| format!("unsupported regular expression: {e}"))?,
+            ),
+            "pm" => pm(param.split_whitespace())?,
+            "pmfromfile" | "pmf" => {
+                let mut phrases = Vec::new();
+                for file in param.split_whitespace() {
+                    let path = match dir {
+                        Some(dir) => dir.join(file),
+                        None => Path::new(file).to_path_buf(),
+                    };
+                    let content = std::fs::read_to_string(&path)
+                        .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+                    phrases.extend(
+                        content
+                            .lines()
+                            .map(str::trim)
+                            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                            .map(str::to_string),
+                    );
+                }
+                pm(phrases.iter())?
+            }
+            "streq" => Operator::StrEq(param.to_string()),
+            "contains" => Operator::Contains(param.to_string()),
+            "containsword" => Operator::ContainsWord(param.to_string()),
+            "beginswith" => Operator::BeginsWith(param.to_string()),
+            "endswith" => Operator::EndsWith(param.to_string()),
+            "within" => Operator::Within(param.to_string()),
+            "eq" => Operator::Eq(number()?),
+            "ge" => Operator::Ge(number()?),
+            "gt" => Operator::Gt(number()?),
+            "le" => Operator::Le(number()?),
+            "lt" => Operator::Lt(number()?),
+            "validatebyterange" => Operator::ValidateByteRange(byte_ranges(param)?),
+            "detectsqli" => Operator::DetectSqli,
+            "detectxss" => Operator::DetectXss,
+            "unconditionalmatch" => Operator::UnconditionalMatch,
+            "nomatch" => Operator::NoMatch,
+            _ => return Err(format!("unsupported operator @{name}")),
+        };
+        Ok((operator, negated))
+    }
+
+    fn matches(&self, value: &str) -> bool {
+        let number = || value.trim().parse::<i64>().unwrap_or(0);
+        match self {
+            Operator::Rx(regex) => regex.is_match(value),
+            Operator::Pm(phrases) => phrases.is_match(value),
+            Operator::StrEq(s) => value == s,
+            Operator::Contains(s) => value.contains(s.as_str()),
+            Operator::ContainsWord(s) => value
+                .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .any(|w| w == s),
+            Operator::BeginsWith(s) => value.starts_with(s.as_str()),
+            Operator::EndsWith(s) => value.ends_with(s.as_str()),
+            Operator::Within(s) => !value.is_empty() && s.contains(value),
+            Operator::Eq(n) => number() == *n,
+            Operator::Ge(n) => number() >= *n,
+            Operator::Gt(n) => number() > *n,
+            Operator::Le(n) => number() <= *n,
+            Operator::Lt(n) => number() < *n,
+            Operator::ValidateByteRange(ranges) => value
+                .bytes()
+                .any(|b| !ranges.iter().any(|r| r.contains(&b))),
+            Operator::DetectSqli => injection::detect_sqli(value),
+            Operator::DetectXss => injection::detect_xss(value),
+            Operator::UnconditionalMatch => true,
+            Operator::NoMatch => false,
+        }
+    }
+}
+
+fn pm<I, P>(phrases: I) -> Result<Operator, String>
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<[u8]>,
+{
+    let phrases = AhoCorasick::builder()
+        .ascii_case_insensitive(true)
+        .build(phrases)
+        .map_err(|e| format!("invalid phrases: {e}"))?;
+    Ok(Operator::Pm(phrases))
+}
+
+/// Parse the byte ranges of `@validateByteRange`, as `9,10,32-126`
+fn byte_ranges(param: &str) -> Result<Vec<RangeInclusive<u8>>, String> {
+    param
+        .split(',')
+        .map(|range| {
+            let range = range.trim();
+            let byte = |s: &str| {
+                s.trim()
+                    .parse::<u8>()
+                    .map_err(|_| format!("invalid byte range {range}"))
+            };
+            match range.split_once('-') {
+                Some((start, end)) => Ok(byte(start)?..=byte(end)?),
+                None => byte(range).map(|b| b..=b),
+            }
+        })
+        .collect()
+}
+
+/// A rule, with the rules chained to it
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub id: u64,
+    /// Processing phase, 1 for the headers and 2 for the body
+    pub phase: u8,
+    pub disruptive: Disruptive,
+    pub msg: Option<String>,
+    pub severity: Option<Severity>,
+    /// HTTP status of the denied requests
+    pub status: Option<u16>,
+    pub tags: Vec<String>,
+    variables: Vec<Variable>,
+    excludes: Vec<Variable>,
+    operator: Operator,
+    negated: bool,
+    transforms: Vec<Transform>,
+    chain: Vec<Rule>,
+}
+
+impl Rule {
+    /// CRS paranoia level of the rule, from its tags
+    pub fn paranoia_level(&self) -> u8 {
+        self.tags
+            .iter()
+            .find_map(|t| t.strip_prefix(PARANOIA_TAG)?.parse().ok())
+            .unwrap_or(1)
+    }
+
+    /// Attack category of the rule, from its `attack-` tag
+    pub fn category(&self) -> Option<&str> {
+        self.tags
+            .iter()
+            .find_map(|t| t.strip_prefix("attack-"))
+            .filter(|c| !c.is_empty())
+    }
+
+    /// Name of the first variable matching the operator
+    fn matched_variable(&self, tx: &Transaction) -> Option<String> {
+        for variable in &self.variables {
+            for (name, value) in variable.values(tx, &self.excludes) {
+                let value = self
+                    .transforms
+                    .iter()
+                    .fold(value.into_owned(), |v, t| t.apply(v));
+                if self.operator.matches(&value) != self.negated {
+                    return Some(name);
+                }
+            }
+        }
+        None
+    }
+
+    /// Name of the matched variable of the rule, if it and its chain match
+    fn evaluate(&self, tx: &Transaction) -> Option<String> {
+        let name = self.matched_variable(tx)?;
+        self.chain
+            .iter()
+            .all(|r| r.matched_variable(tx).is_some())
+            .then_some(name)
+    }
+}
+
+/// A rule which matched a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    pub id: u64,
+    pub msg: Option<String>,
+    pub severity: Option<Severity>,
+    pub category: Option<String>,
+    /// Variable the rule matched in
+    pub variable: String,
+    pub status: Option<u16>,
+}
+
+/// Result of the evaluation of a request
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Evaluation {
+    /// Rules matched, in evaluation order
+    pub matches: Vec<Match>,
+    /// Anomaly score of the matched blocking rules
+    pub score: u32,
+    /// Index of the match the request is denied by
+    pub denied: Option<usize>,
+}
+
+impl Evaluation {
+    /// The match the request is denied by
+    pub fn denied_by(&self) -> Option<&Match> {
+        self.matches.get(self.denied?)
+    }
+}
+
+/// Rules loaded from rule files
+#[derive(Debug, Clone, Default)]
+pub struct RuleSet {
+    pub engine: Engine,
+    rules: Vec<Rule>,
+    /// Rules left out as they need what is not supported
+    pub skipped: Vec<RuleError>,
+}
+
+/// A rule being parsed, with the reason it is left out
+struct Pending {
+    rule: Rule,
+    line: usize,
+    unsupported: Option<String>,
+}
+
+impl RuleSet {
+    /// Parse a rule file
+    ///
+    /// The files of `@pmFromFile` are relative to the directory.
+    pub fn parse(text: &str, dir: Option<&Path>) -> Result<Self, RuleError> {
+        let mut set = RuleSet::default();
+        set.load(text, dir)?;
+        Ok(set)
+    }
+
+    /// Add the rules of a rule file, its directives applying to the rules
+    /// already loaded
+    pub fn load(&mut self, text: &str, dir: Option<&Path>) -> Result<(), RuleError> {
+        let mut chain: Option<Pending> = None;
+        let mut chain_open = false;
+        let mut last_line = 0;
+        for (line, directive) in directives(text) {
+            last_line = line;
+            let args = split_args(&directive).map_err(|e| RuleError::new(line, e))?;
+            let Some((name, args)) = args.split_first() else {
+                continue;
+            };
+            if !name.eq_ignore_ascii_case("SecRule") {
+                if chain_open {
+                    return Err(RuleError::new(line, "chain without a following rule"));
+                }
+                self.directive(name, args)
+                    .map_err(|e| RuleError::new(line, e))?;
+                continue;
+            }
+
+            let (rule, has_chain, unsupported) =
+                parse_rule(args, dir, chain_open).map_err(|e| RuleError::new(line, e))?;
+            if chain_open {
+                let head = chain.as_mut().unwrap();
+                head.rule.chain.push(rule);
+                head.unsupported = head.unsupported.take().or(unsupported);
+            } else {
+                chain = Some(Pending {
+                    rule,
+                    line,
+                    unsupported,
+                });
+            }
+            chain_open = has_chain;
+            if !chain_open && let Some(pending) = chain.take() {
+                match pending.unsupported {
+                    Some(reason) => self.skipped.push(RuleError::new(
+                        pending.line,
+                        format!("rule {} skipped: {reason}", pending.rule.id),
+                    )),
+                    None => self.rules.push(pending.rule),
+                }
+            }
+        }
+        if chain_open {
+            return Err(RuleError::new(last_line, "chain without a following rule"));
+        }
+        Ok(())
+    }
+
+    fn directive(&mut self, name: &str, args: &[String]) -> Result<(), String> {
+        if name.eq_ignore_ascii_case("SecRuleEngine") {
+            self.engine = match args.first().map(|s| s.to_ascii_lowercase()).as_deref() {
+                Some("on") => Engine::On,
+                Some("off") => Engine::Off,
+                Some("detectiononly") => Engine::DetectionOnly,
+                _ => return Err("invalid SecRuleEngine mode".to_string()),
+            };
+        } else if name.eq_ignore_ascii_case("SecRuleRemoveById") {
+            for arg in args.iter().flat_map(|a| a.split_whitespace()) {
+                let id = |s: &str| {
+                    s.parse::<u64>()
+                        .map_err(|_| format!("invalid rule id {arg}"))
+                };
+                let range = match arg.split_once('-') {
+                    Some((start, end)) => id(start)?..=id(end)?,
+                    None => id(arg)?..=id(arg)?,
+                };
+                self.rules.retain(|r| !range.contains(&r.id));
+            }
+        }
+        Ok(())
+    }
+
+    /// Keep the rules up to the CRS paranoia level
+    pub fn retain_paranoia_level(&mut self, level: u8) {
+        self.rules.retain(|r| r.paranoia_level() <= level);
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Evaluate the rules on a request, phase by phase
+    ///
+    /// The request is denied by a denying rule, or by the blocking rule its
+    /// anomaly score reaches the threshold at.
+    pub fn evaluate(&self, tx: &Transaction, threshold: u32) -> Evaluation {
+        let mut evaluation = Evaluation::default();
+        if self.engine == Engine::Off {
+            return evaluation;
+        }
+        let rules = [1, 2]
+            .into_iter()
+            .flat_map(|phase| self.rules.iter().filter(move |r| r.phase == phase));
+        for rule in rules {
+            let Some(variable) = rule.evaluate(tx) else {
+                continue;
+            };
+            evaluation.matches.push(Match {
+                id: rule.id,
+                msg: rule.msg.clone(),
+                severity: rule.severity,
+                category: rule.category().map(str::to_string),
+                variable,
+                status: rule.status,
+            });
+            let index = evaluation.matches.len() - 1;
+            match rule.disruptive {
+                Disruptive::Pass => {}
+                Disruptive::Allow => break,
+                Disruptive::Deny => {
+                    evaluation.denied = Some(index);
+                    break;
+                }
+                Disruptive::Block => {
+                    evaluation.score += rule.severity.map(|s| s.score()).unwrap_or_default();
+                    if evaluation.score >= threshold {
+                        evaluation.denied = Some(index);
+                        break;
+                    }
+                }
+            }
+        }
+        if self.engine == Engine::DetectionOnly {
+            evaluation.denied = None;
+        }
+        evaluation
+    }
+}
+
+/// Directives of a rule file with their first line, the continued lines
+/// being joined
+fn directives(text: &str) -> Vec<(usize, String)> {
+    let mut directives = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+    for (n, line) in text.lines().enumerate() {
+        let (start, mut directive) = match current.take() {
+            Some(current) => current,
+            None => {
+                let trimmed = line.trim_start();
+                if trimmed.is_empty() || trimmed.starts_with('#') {
+                    continue;
+                }
+                (n + 1, String::new())
+            }
+        };
+        match line.trim_end().strip_suffix('\\') {
+            Some(line) => {
+                directive.push_str(line);
+                current = Some((start, directive));
+            }
+            None => {
+                directive.push_str(line);
+                directives.push((start, directive));
+            }
+        }
+    }
+    directives.extend(current);
+    directives
+}
+
+/// Split a directive in its arguments, the quoted ones keeping their
+/// escapes but for the quotes
+fn split_args(directive: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut chars = directive.trim().chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut arg = String::new();
+        if c == '"' {
+            chars.next();
+            let mut closed = false;
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' if chars.peek() == Some(&'"') => arg.push(chars.next().unwrap()),
+                    '"' => {
+                        closed = true;
+                        break;
+                    }
+                    c => arg.push(c),
+                }
+            }
+            if !closed {
+                return Err("unterminated quoted argument".to_string());
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                arg.push(c);
+                chars.next();
+            }
+        }
+        args.push(arg);
+    }
+    Ok(args)
+}
+
+/// Split the actions of a rule, the single quoted values keeping their commas
+fn split_actions(actions: &str) -> Vec<(String, String)> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in actions.chars() {
+        match c {
+            _ if escaped => {
+                current.push(c);
+                escaped = false;
+            }
+            '\\' if quoted => escaped = true,
+            '\'' => quoted = !quoted,
+            ',' if !quoted => parts.push(std::mem::take(&mut current)),
+            c => current.push(c),
+        }
+    }
+    parts.push(current);
+    parts
+        .into_iter()
+        .map(|part| {
+            let part = part.trim();
+            match part.split_once(':') {
+                Some((name, value)) => (name.trim().to_string(), value.trim().to_string()),
+                None => (part.to_string(), String::new()),
+            }
+        })
+        .filter(|(name, _)| !name.is_empty())
+        .collect()
+}
+
+/// Parse the arguments of a `SecRule`, returning the rule, whether a rule is
+/// chained to it and why it is not supported
+fn parse_rule(
+    args: &[String],
+    dir: Option<&Path>,
+    chained: bool,
+) -> Result<(Rule, bool, Option<String>), String> {
+    let (variables, operator, actions) = match args {
+        [variables, operator] => (variables, operator, ""),
+        [variables, operator, actions] => (variables, operator, actions.as_str()),
+        _ => return Err("SecRule needs variables, an operator and actions".to_string()),
+    };
+
+    let mut unsupported = None;
+    let mut rule = Rule {
+        id: 0,
+        phase: 2,
+        disruptive: Disruptive::Pass,
+        msg: None,
+        severity: None,
+        status: None,
+        tags: Vec::new(),
+        variables: Vec::new(),
+        excludes: Vec::new(),
+        operator: Operator::NoMatch,
+        negated: false,
+        transforms: Vec::new(),
+        chain: Vec::new(),
+    };
+    let mut has_chain = false;
+    for (name, value) in split_actions(actions) {
+        match name.to_ascii_lowercase().as_str() {
+            "id" => {
+                rule.id = value
+                    .parse()
+                    .map_err(|_| format!("invalid rule id {value}"))?
+            }
+            "phase" => {
+                rule.phase = match value.to_ascii_lowercase().as_str() {
+                    "request" => 2,
+                    "response" => 4,
+                    "logging" => 5,
+                    v => v.parse().map_err(|_| format!("invalid phase {value}"))?,
+                }
+            }
+            "msg" => rule.msg = Some(value),
+            "severity" => {
+                rule.severity = Some(
+                    Severity::parse(&value).ok_or_else(|| format!("invalid severity {value}"))?,
+                )
+            }
+            "status" => {
+                rule.status = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid status {value}"))?,
+                )
+            }
+            "tag" => rule.tags.push(value),
+            "t" => match Transform::parse(&value) {
+                Ok(Some(t)) => rule.transforms.push(t),
+                Ok(None) => rule.transforms.clear(),
+                Err(e) => unsupported = unsupported.or(Some(e)),
+            },
+            "chain" => has_chain = true,
+            "deny" | "drop" | "redirect" => rule.disruptive = Disruptive::Deny,
+            "block" => rule.disruptive = Disruptive::Block,
+            "pass" => rule.disruptive = Disruptive::Pass,
+            "allow" => rule.disruptive = Disruptive::Allow,
+            _ => {}
+        }
+    }
+    if chained {
+        if rule.id != 0 {
+            return Err("chained rule with an id".to_string());
+        }
+    } else if rule.id == 0 {
+        return Err("rule without id".to_string());
+    } else if !(1..=2).contains(&rule.phase) {
+        unsupported = unsupported.or(Some(format!("phase {} is not a request one", rule.phase)));
+    }
+
+    match parse_variables(variables) {
+        Ok((variables, excludes)) => {
+            rule.variables = variables;
+            rule.excludes = excludes;
+        }
+        Err(e) => unsupported = unsupported.or(Some(e)),
+    }
+    match Operator::parse(operator, dir) {
+        Ok((operator, negated)) => {
+            rule.operator = operator;
+            rule.negated = negated;
+        }
+        Err(e) => unsupported = unsupported.or(Some(e)),
+    }
+    Ok((rule, has_chain, unsupported))
+}
+
+/// Parse the variables of a rule and its exclusions
+///
+/// The collections not inspected, such as `XML` or `FILES`, are dropped,
+/// the rule being unsupported if none is left.
+fn parse_variables(s: &str) -> Result<(Vec<Variable>, Vec<Variable>), String> {
+    let mut variables = Vec::new();
+    let mut excludes = Vec::new();
+    let mut dropped = Vec::new();
+    for part in split_variables(s) {
+        let (exclude, count, part) = match part.as_bytes().first() {
+            Some(b'!') => (true, false, &part[1..]),
+            Some(b'&') => (false, true, &part[1..]),
+            _ => (false, false, part),
+        };
+        let (name, selector) = match part.split_once(':') {
+            Some((name, selector)) => (name, Some(selector)),
+            None => (part, None),
+        };
+        let Some(collection) = Collection::parse(name) else {
+            dropped.push(name);
+            continue;
+        };
+        let selector = match selector.map(|s| s.trim_matches('\'')) {
+            Some(s) if s.len() > 1 && s.starts_with('/') && s.ends_with('/') => {
+                Some(Selector::Pattern(
+                    RegexBuilder::new(&s[1..s.len() - 1])
+                        .case_insensitive(true)
+                        .build()
+                        .map_err(|e| format!("unsupported selector {s}: {e}"))?,
+                ))
+            }
+            Some(s) => Some(Selector::Key(s.to_string())),
+            None => None,
+        };
+        let variable = Variable {
+            collection,
+            selector,
+            count,
+        };
+        if exclude {
+            excludes.push(variable);
+        } else {
+            variables.push(variable);
+        }
+    }
+    if variables.is_empty() {
+        return Err(format!("unsupported variables {}", dropped.join("|")));
+    }
+    Ok((variables, excludes))
+}
+
+/// Split the variables on `|`, but in the regular expression selectors
+fn split_variables(s: &str) -> Vec<&str> {
+    let bytes = s.as_bytes();
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'/' if i > 0 && bytes[i - 1] == b':' => {
+                // a regular expression ends with a slash, unlike an XPath
+                if let Some(end) = regex_end(&bytes[i + 1..]) {
+                    i += end + 2;
+                    continue;
+                }
+            }
+            b'|' => {
+                parts.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    parts.push(&s[start..]);
+    parts.into_iter().filter(|p| !p.is_empty()).collect()
+}
+
+/// Position of the slash closing a regular expression selector, which is
+/// the first one not escaped and ends the variable
+fn regex_end(bytes: &[u8]) -> Option<usize> {
+    let end = (0..bytes.len()).find(|&n| bytes[n] == b'/' && (n == 0 || bytes[n - 1] != b'\\'))?;
+    matches!(bytes.get(end + 1), None | Some(b'|')).then_some(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tx(uri: &str, headers: &[(&str, &str)], body: &str) -> Transaction {
+        Transaction::new(
+            "POST",
+            uri,
+            "HTTP/1.1",
+            headers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            body.to_string(),
+        )
+    }
+
+    const RULES: &str = r#"
+# a comment
+SecRule REQUEST_FILENAME "@beginsWith /admin" \
+    "id:1000,phase:1,deny,status:401,msg:'Admin area, denied'"
+SecRule ARGS|REQUEST_COOKIES|!ARGS:token "@detectSQLi" \
+    "id:942100,phase:2,block,t:none,t:urlDecodeUni,\
+    msg:'SQL Injection Attack Detected via libinjection',\
+    tag:'attack-sqli',tag:'paranoia-level/1',severity:'CRITICAL'"
+SecRule REQUEST_HEADERS:User-Agent "@pm nikto sqlmap" \
+    "id:913100,phase:1,block,t:lowercase,severity:'WARNING',tag:'attack-reputation-scanner'"
+SecRule REQUEST_METHOD "@streq POST" "id:920000,phase:1,pass,chain,tag:'paranoia-level/2'"
+    SecRule &REQUEST_HEADERS:Content-Type "@eq 0" "t:none"
+SecRule TX:EXECUTING_PARANOIA_LEVEL "@lt 1" "id:942011,phase:1,pass,skipAfter:END"
+SecRule REQUEST_URI "@rx (?<=a)b" "id:942012,phase:1,deny"
+SecRule XML:/*|ARGS_NAMES "@rx __proto__" "id:934130,phase:2,deny,tag:'attack-injection-generic'"
+"#;
+
+    #[test]
+    fn parse() {
+        let set = RuleSet::parse(RULES, None).unwrap();
+        let ids: Vec<u64> = set.rules().iter().map(|r| r.id).collect();
+        assert_eq!(ids, [1000, 942100, 913100, 920000, 934130]);
+        assert_eq!(set.skipped.len(), 2);
+        assert_eq!(set.skipped[0].line, 13);
+        assert!(set.skipped[0].message.ends_with("unsupported variables TX"));
+        assert!(set.skipped[1].message.contains("regular expression"));
+
+        let rule = &set.rules()[1];
+        assert_eq!(
+            rule.msg.as_deref(),
+            Some("SQL Injection Attack Detected via libinjection")
+        );
+        assert_eq!(rule.severity, Some(Severity::Critical));
+        assert_eq!(rule.category(), Some("sqli"));
+        assert_eq!(set.rules()[0].msg.as_deref(), Some("Admin area, denied"));
+        assert_eq!(set.rules()[3].paranoia_level(), 2);
+
+        assert_eq!(
+            RuleSet::parse("SecRule ARGS \"@rx a\" \"id:1\" deny", None).unwrap_err(),
+            RuleError::new(1, "SecRule needs variables, an operator and actions")
+        );
+        assert_eq!(
+            RuleSet::parse("SecRule ARGS \"@rx a\" \"id:1,chain\"", None)
+                .unwrap_err()
+                .message,
+            "chain without a following rule"
+        );
+        assert_eq!(
+            RuleSet::parse("SecRule ARGS \"@rx a\" \"phase:2\"", None)
+                .unwrap_err()
+                .message,
+            "rule without id"
+        );
+        assert!(RuleSet::parse("SecRule ARGS \"@rx a", None).is_err());
+    }
+
+    #[test]
+    fn evaluate() {
+        let mut set = RuleSet::parse(RULES, None).unwrap();
+        let allowed = tx(
+            "/search?q=hello+world",
+            &[("User-Agent", "curl/8.0"), ("Content-Type", "text/plain")],
+            "",
+        );
+        assert_eq!(set.evaluate(&allowed, 5), Evaluation::default());
+
+        let evaluation = set.evaluate(&tx("/admin/users", &[], ""), 5);
+        assert_eq!(evaluation.denied_by().map(|m| m.id), Some(1000));
+        assert_eq!(evaluation.denied_by().unwrap().status, Some(401));
+
+        let sqli = tx("/search?q=1%27%20or%20%271%27%3D%271", &[], "");
+        let evaluation = set.evaluate(&sqli, 5);
+        let denied = evaluation.denied_by().unwrap();
+        assert_eq!((denied.id, denied.variable.as_str()), (942100, "ARGS:q"));
+        assert_eq!(evaluation.score, 5);
+        // excluded argument
+        let token = tx("/search?token=1%27%20or%20%271%27%3D%271", &[], "");
+        assert_eq!(set.evaluate(&token, 5).denied, None);
+
+        // anomaly scoring
+        let scanner = tx("/", &[("User-Agent", "Mozilla/5.0 sqlmap/1.7")], "");
+        let evaluation = set.evaluate(&scanner, 5);
+        assert_eq!((evaluation.score, evaluation.denied), (3, None));
+        assert_eq!(evaluation.matches[0].id, 913100);
+        assert!(set.evaluate(&scanner, 3).denied.is_some());
+
+        // chain, the paranoia level
+        let posted = tx("/", &[], "a=b");
+        assert_eq!(set.evaluate(&posted, 5).matches[0].id, 920000);
+        set.retain_paranoia_level(1);
+        assert!(set.evaluate(&posted, 5).matches.is_empty());
+
+        let proto = tx(
+            "/",
+            &[("Content-Type", "application/json")],
+            r#"{"__proto__": {"admin": true}}"#,
+        );
+        assert_eq!(set.evaluate(&proto, 5).denied_by().unwrap().id, 934130);
+
+        set.load(
+            "SecRuleRemoveById 1000 942000-942999\nSecRuleEngine DetectionOnly",
+            None,
+        )
+        .unwrap();
+        assert_eq!(set.rules().len(), 2);
+        let evaluation = set.evaluate(&scanner, 3);
+        assert_eq!((evaluation.matches.len(), evaluation.denied), (1, None));
+    }
+
+    #[test]
+    fn transforms() {
+        let apply = |t: Transform, v: &str| t.apply(v.to_string());
+        assert_eq!(apply(Transform::UrlDecode, "a%27+b%2"), "a' b%2");
+        assert_eq!(apply(Transform::UrlDecodeUni, "%u003cx%uff1e"), "<x>");
+        assert_eq!(
+            apply(
+                Transform::HtmlEntityDecode,
+                "&lt;a&#x20;&#39;x&amp&unknown;"
+            ),
+            "<a 'x&&unknown;"
+        );
+        assert_eq!(
+            apply(Transform::ReplaceComments, "sel/**/ect/*x"),
+            "sel ect "
+        );
+        assert_eq!(
+            apply(Transform::NormalizePath, "/a/./b/../../etc//passwd"),
+            "/etc/passwd"
+        );
+        assert_eq!(
+            apply(Transform::CmdLine, "C^at  \"/ETC/passwd\";id"),
+            "cat/etc/passwd id"
+        );
+        assert_eq!(apply(Transform::CompressWhitespace, " a \t b "), "a b");
+    }
+}
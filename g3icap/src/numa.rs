@@ -0,0 +1,170 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! NUMA topology of the host and placement of the server memory
+//!
+//! The topology is read once, at first use. Nodes are referred to by their
+//! index in the topology, from 0, which is mapped to the kernel node ID
+//! only when placing memory. Hosts with a single node have no placement to
+//! do, and no current node is reported on them.
+
+use std::sync::{Arc, LazyLock};
+
+use arc_swap::ArcSwapOption;
+use g3_compat::NumaNode;
+use log::{info, warn};
+
+use crate::config::server::numa::NumaConfig;
+
+static TOPOLOGY: LazyLock<Topology> = LazyLock::new(Topology::detect);
+static GLOBAL_CONFIG: ArcSwapOption<NumaConfig> = ArcSwapOption::const_empty();
+
+#[derive(Default)]
+struct Topology {
+    nodes: Vec<NumaNode>,
+    /// Node index of each CPU ID
+    cpu_nodes: Vec<Option<usize>>,
+}
+
+impl Topology {
+    fn detect() -> Self {
+        match g3_compat::numa_nodes() {
+            Ok(nodes) => Topology::new(nodes),
+            Err(e) => {
+                warn!("failed to detect the NUMA topology: {e}");
+                Topology::default()
+            }
+        }
+    }
+
+    fn new(nodes: Vec<NumaNode>) -> Self {
+        let max_cpu = nodes
+            .iter()
+            .flat_map(|n| n.cpu_id_list().iter().copied())
+            .max();
+        let mut cpu_nodes = vec![None; max_cpu.map_or(0, |id| id + 1)];
+        for (index, node) in nodes.iter().enumerate() {
+            for cpu in node.cpu_id_list() {
+                cpu_nodes[*cpu] = Some(index);
+            }
+        }
+        Topology { nodes, cpu_nodes }
+    }
+
+    fn node_of_cpus(&self, cpus: &[usize]) -> Option<usize> {
+        let (first, others) = cpus.split_first()?;
+        let node = self.cpu_nodes.get(*first).copied().flatten()?;
+        others
+            .iter()
+            .all(|cpu| self.cpu_nodes.get(*cpu).copied().flatten() == Some(node))
+            .then_some(node)
+    }
+}
+
+/// Install the NUMA placement of the server, logging the topology if changed
+pub fn set_global(config: Option<NumaConfig>) {
+    let old = GLOBAL_CONFIG.swap(config.map(Arc::new));
+    if let Some(config) = config
+        && old.as_deref() != Some(&config)
+    {
+        log_topology(&config);
+    }
+}
+
+/// Get the NUMA placement currently in use
+pub fn get_global() -> Option<NumaConfig> {
+    GLOBAL_CONFIG.load().as_deref().copied()
+}
+
+/// Number of NUMA nodes with CPUs, at least 1
+pub fn node_count() -> usize {
+    TOPOLOGY.nodes.len().max(1)
+}
+
+/// Kernel ID of the node at the index
+pub fn node_id(index: usize) -> Option<usize> {
+    TOPOLOGY.nodes.get(index).map(|n| n.id())
+}
+
+/// Index of the node the current thread runs on, on multi node hosts
+pub fn current_node() -> Option<usize> {
+    if TOPOLOGY.nodes.len() < 2 {
+        return None;
+    }
+    let cpu = g3_compat::current_cpu_id()?;
+    TOPOLOGY.cpu_nodes.get(cpu).copied().flatten()
+}
+
+fn log_topology(config: &NumaConfig) {
+    let topology = &*TOPOLOGY;
+    info!("NUMA topology: {} nodes", topology.nodes.len());
+    for node in &topology.nodes {
+        info!(
+            "NUMA node {}: CPUs {}",
+            node.id(),
+            format_cpu_list(node.cpu_id_list())
+        );
+    }
+    let _ = g3_daemon::runtime::worker::foreach(|worker| {
+        let node = worker
+            .cpu_affinity
+            .as_ref()
+            .and_then(|cpus| topology.node_of_cpus(cpus.cpu_id_list()))
+            .and_then(|index| topology.nodes.get(index));
+        match node {
+            Some(node) => info!("worker {} is on NUMA node {}", worker.id, node.id()),
+            None => info!("worker {} is not pinned to a NUMA node", worker.id),
+        }
+        Ok::<(), ()>(())
+    });
+    info!(
+        "NUMA placement: node-local buffer pools {}, list stores {}",
+        if config.buffer_pools { "on" } else { "off" },
+        config.list_stores.as_str()
+    );
+}
+
+/// Format CPU IDs in the kernel list format, like `0-3,8`
+fn format_cpu_list(cpus: &[usize]) -> String {
+    let mut cpus = cpus.to_vec();
+    cpus.sort_unstable();
+    cpus.dedup();
+
+    let mut parts = Vec::new();
+    let mut iter = cpus.into_iter().peekable();
+    while let Some(start) = iter.next() {
+        let mut end = start;
+        while iter.next_if_eq(&(end + 1)).is_some() {
+            end += 1;
+        }
+        if end == start {
+            parts.push(start.to_string());
+        } else {
+            parts.push(format!("{start}-{end}"));
+        }
+    }
+    parts.join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_list() {
+        assert_eq!(format_cpu_list(&[8, 0, 1, 2, 3, 10, 11]), "0-3,8,10-11");
+        assert_eq!(format_cpu_list(&[]), "");
+    }
+
+    #[test]
+    fn single_node() {
+        // hosts with a single node do no placement
+        if TOPOLOGY.nodes.len() < 2 {
+            assert_eq!(current_node(), None);
+            assert_eq!(node_count(), 1);
+        }
+        assert!(node_count() >= 1);
+    }
+}
@@ -8,7 +8,7 @@ use std::path::PathBuf;
 use g3_daemon::opts::{DaemonArgs, DaemonArgsExt};
 use clap::{Arg, ArgAction, Command, ValueHint, value_parser};
 
-use crate::version::VERSION;
+use crate::version::{VERSION, VersionReport};
 
 /// Command line arguments for G3 ICAP Server
 #[derive(Debug)]
@@ -53,6 +53,12 @@ pub struct ProcArgs {
     
     /// Metrics port
     pub metrics_port: u16,
+
+    /// Keys the loaded artifacts should be signed by
+    pub trusted_keys: Option<PathBuf>,
+
+    /// Also create the server and initialize its modules when testing the config
+    pub deep_test: bool,
 }
 
 impl Default for ProcArgs {
@@ -72,6 +78,8 @@ impl Default for ProcArgs {
             stats_port: 8080,
             metrics: false,
             metrics_port: 9090,
+            trusted_keys: None,
+            deep_test: false,
         }
     }
 }
@@ -180,9 +188,38 @@ impl ProcArgs {
                     .default_value("9090")
                     .value_parser(value_parser!(u16))
             )
+            .arg(
+                Arg::new("trusted-keys")
+                    .long("trusted-keys")
+                    .value_name("FILE")
+                    .help("Only load the config, lists and rules signed by the minisign keys in file")
+                    .value_hint(ValueHint::FilePath)
+            )
+            .arg(
+                Arg::new("deep")
+                    .long("deep")
+                    .help("Also create the server, load its lists and initialize its modules when testing the config")
+                    .action(ArgAction::SetTrue)
+                    .requires("test-config")
+            )
+            .arg(
+                Arg::new("version-json")
+                    .long("version-json")
+                    .help("Show version and build information as JSON")
+                    .action(ArgAction::SetTrue)
+            )
             .get_matches();
 
-        let daemon_config = DaemonArgs::new("g3icap");
+        if matches.get_flag("version-json") {
+            println!("{}", VersionReport::build().to_json());
+            return None;
+        }
+
+        let mut daemon_config = DaemonArgs::new("g3icap");
+        // the daemon options, like --test-config, apply to this process too
+        if let Err(e) = daemon_config.parse_clap(&matches) {
+            eprintln!("Failed to parse daemon options: {}", e);
+        }
         
         // Set config file if provided
         if let Some(config_file) = matches.get_one::<String>("config") {
@@ -210,6 +247,8 @@ impl ProcArgs {
             stats_port: *matches.get_one::<u16>("stats-port").unwrap_or(&8080),
             metrics: matches.get_flag("metrics"),
             metrics_port: *matches.get_one::<u16>("metrics-port").unwrap_or(&9090),
+            trusted_keys: matches.get_one::<String>("trusted-keys").map(PathBuf::from),
+            deep_test: matches.get_flag("deep"),
         })
     }
 }
@@ -231,6 +270,8 @@ impl Clone for ProcArgs {
             stats_port: self.stats_port,
             metrics: self.metrics,
             metrics_port: self.metrics_port,
+            trusted_keys: self.trusted_keys.clone(),
+            deep_test: self.deep_test,
         }
     }
 }
@@ -16,6 +16,7 @@ use anyhow::Result;
 use async_trait::async_trait;
 
 use crate::protocol::common::{IcapRequest, IcapResponse};
+use crate::transaction::TransactionCtx;
 
 /// Pipeline configuration
 #[derive(Debug, Clone)]
@@ -66,7 +67,7 @@ pub enum StageType {
 
 /// Pipeline context
 #[derive(Debug, Clone)]
-pub struct PipelineContext {
+pub struct PipelineContext<'a> {
     /// Original request
     pub request: IcapRequest,
     /// Current response
@@ -79,6 +80,8 @@ pub struct PipelineContext {
     pub start_time: Instant,
     /// Current stage
     pub current_stage: Option<String>,
+    /// Transaction of the request, stages should stop once it is cancelled
+    pub transaction: &'a TransactionCtx,
 }
 
 /// Stage result
@@ -109,7 +112,7 @@ pub trait PipelineStage: Send + Sync {
     fn can_handle(&self, content_type: &str) -> bool;
     
     /// Process the pipeline context
-    async fn process(&self, context: &mut PipelineContext) -> Result<(), PipelineError>;
+    async fn process(&self, context: &mut PipelineContext<'_>) -> Result<(), PipelineError>;
     
     /// Initialize stage
     async fn init(&mut self, config: &StageConfig) -> Result<(), PipelineError>;
@@ -145,7 +148,7 @@ impl ContentPipeline {
     }
     
     /// Process request through pipeline
-    pub async fn process_request(&mut self, request: IcapRequest) -> Result<IcapResponse, PipelineError> {
+    pub async fn process_request(&mut self, request: IcapRequest, ctx: &TransactionCtx) -> Result<IcapResponse, PipelineError> {
         let start_time = Instant::now();
         let mut context = PipelineContext {
             request,
@@ -154,10 +157,15 @@ impl ContentPipeline {
             stage_results: Vec::new(),
             start_time,
             current_stage: None,
+            transaction: ctx,
         };
         
         // Process through each stage
         for stage in &self.stages {
+            // the remaining stages are skipped once the transaction is cancelled
+            if context.transaction.is_cancelled() {
+                return Err(PipelineError::ProcessingFailed("transaction cancelled".to_string()));
+            }
             context.current_stage = Some(stage.name().to_string());
             let stage_start = Instant::now();
             
@@ -220,7 +228,7 @@ impl ContentPipeline {
     }
     
     /// Update pipeline metrics
-    fn update_metrics(&mut self, context: &PipelineContext) {
+    fn update_metrics(&mut self, context: &PipelineContext<'_>) {
         self.metrics.requests_total += 1;
         self.metrics.total_processing_time += context.start_time.elapsed();
         
@@ -304,7 +312,7 @@ pub mod stages {
             true
         }
         
-        async fn process(&self, context: &mut PipelineContext) -> Result<(), PipelineError> {
+        async fn process(&self, context: &mut PipelineContext<'_>) -> Result<(), PipelineError> {
             log::info!(
                 "Processing request: {:?} {}",
                 context.request.method,
@@ -355,7 +363,7 @@ pub mod stages {
             content_type.starts_with("text/") || content_type.starts_with("application/")
         }
         
-        async fn process(&self, context: &mut PipelineContext) -> Result<(), PipelineError> {
+        async fn process(&self, context: &mut PipelineContext<'_>) -> Result<(), PipelineError> {
             // Simple content filtering based on patterns
             let content = String::from_utf8_lossy(&context.request.body);
             
@@ -413,7 +421,7 @@ pub mod stages {
             !content_type.starts_with("audio/") && !content_type.starts_with("video/")
         }
         
-        async fn process(&self, context: &mut PipelineContext) -> Result<(), PipelineError> {
+        async fn process(&self, context: &mut PipelineContext<'_>) -> Result<(), PipelineError> {
             // Simulate antivirus scanning
             // In a real implementation, this would integrate with an actual antivirus engine
             
@@ -447,4 +455,86 @@ pub mod stages {
             // Cleanup resources
         }
     }
+    
+    /// Content disarm and reconstruction stage
+    #[cfg(feature = "cdr")]
+    pub struct CdrStage {
+        name: String,
+        module: crate::modules::cdr::CdrModule,
+    }
+    
+    #[cfg(feature = "cdr")]
+    impl CdrStage {
+        pub fn new(name: String, config: crate::modules::cdr::CdrConfig) -> Self {
+            Self {
+                name,
+                module: crate::modules::cdr::CdrModule::new(config),
+            }
+        }
+    }
+    
+    #[cfg(feature = "cdr")]
+    #[async_trait]
+    impl PipelineStage for CdrStage {
+        fn name(&self) -> &str {
+            &self.name
+        }
+        
+        fn stage_type(&self) -> StageType {
+            StageType::ContentTransform
+        }
+        
+        fn can_handle(&self, content_type: &str) -> bool {
+            content_type.starts_with("application/pdf")
+                || content_type.starts_with("application/vnd.openxmlformats-officedocument.")
+                || content_type.starts_with("application/vnd.ms-")
+                || content_type.starts_with("application/msword")
+                || content_type.starts_with("application/octet-stream")
+        }
+        
+        async fn process(&self, context: &mut PipelineContext<'_>) -> Result<(), PipelineError> {
+            use crate::modules::IcapModule;
+            
+            if context.request.method != crate::protocol::common::IcapMethod::Respmod {
+                return Ok(());
+            }
+            
+            let response = self.module.handle_respmod(&context.request, context.transaction).await
+                .map_err(|e| PipelineError::StageError(e.to_string()))?;
+            if let Some(removed) = response.headers.get(crate::modules::cdr::HEADER_CDR_REMOVED) {
+                context.metadata.insert(
+                    "cdr_removed".to_string(),
+                    removed.to_str().unwrap_or_default().to_string(),
+                );
+            }
+            // only adapted or blocked documents replace the response
+            if response.status != http::StatusCode::NO_CONTENT {
+                context.response = Some(response);
+            }
+            
+            Ok(())
+        }
+        
+        async fn init(&mut self, config: &StageConfig) -> Result<(), PipelineError> {
+            use crate::modules::IcapModule;
+            
+            let module_config = crate::modules::ModuleConfig {
+                name: self.name.clone(),
+                path: std::path::PathBuf::new(),
+                version: "1.0.0".to_string(),
+                config: config.config.clone(),
+                dependencies: Vec::new(),
+                load_timeout: config.timeout,
+                max_memory: 0,
+                sandbox: false,
+                metrics: crate::modules::metrics::MetricsRegistry::global().module(&self.name),
+            };
+            self.module.init(&module_config).await
+                .map_err(|e| PipelineError::InvalidConfiguration(e.to_string()))
+        }
+        
+        async fn cleanup(&mut self) {
+            // Cleanup resources
+        }
+    }
 }
@@ -3,9 +3,18 @@
 //! This module implements RFC 3507 compliant chunked transfer encoding
 //! for ICAP encapsulated HTTP bodies. All encapsulated HTTP bodies MUST
 //! use chunked transfer encoding according to the ICAP specification.
+//!
+//! Chunk extensions are accepted after the chunk size, and the `ieof`
+//! extension of the last chunk tells that a preview is the whole body.
+//! The `use-original-body` extension ends the body of a 206 response.
+//! Trailer fields after the last chunk are parsed, and can be written after
+//! the last chunk of a generated body.
 
 use crate::error::IcapError;
+use crate::protocol::limits::ProtocolLimits;
+use crate::protocol::partial::USE_ORIGINAL_BODY;
 use bytes::Bytes;
+use http::{HeaderMap, HeaderName, HeaderValue};
 use std::str;
 
 /// Chunked transfer encoding parser with state machine
@@ -14,6 +23,9 @@ pub struct ChunkedParser {
     state: ChunkState,
     current_chunk_size: usize,
     current_chunk_read: usize,
+    max_chunk_size: usize,
+    trailers: HeaderMap,
+    ieof: bool,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -52,10 +64,18 @@ impl From<ChunkedParseError> for IcapError {
 impl ChunkedParser {
     /// Create a new chunked parser
     pub fn new() -> Self {
+        Self::with_max_chunk_size(ProtocolLimits::default().max_chunk_size)
+    }
+
+    /// Create a new chunked parser rejecting chunks larger than `max_chunk_size`
+    pub fn with_max_chunk_size(max_chunk_size: usize) -> Self {
         Self {
             state: ChunkState::ReadingSize,
             current_chunk_size: 0,
             current_chunk_read: 0,
+            max_chunk_size,
+            trailers: HeaderMap::new(),
+            ieof: false,
         }
     }
     
@@ -70,15 +90,12 @@ impl ChunkedParser {
             match self.state {
                 ChunkState::ReadingSize => {
                     if let Some(crlf_pos) = find_crlf(&input[pos..]) {
-                        let size_str = str::from_utf8(&input[pos..pos + crlf_pos])
-                            .map_err(|_| ChunkedParseError::InvalidEncoding)?;
-                        
-                        // Parse hexadecimal chunk size
-                        self.current_chunk_size = usize::from_str_radix(size_str.trim(), 16)
-                            .map_err(|e| ChunkedParseError::InvalidChunkSize(e.to_string()))?;
+                        // Parse hexadecimal chunk size and its extensions
+                        let (size, ieof) = parse_size_line(&input[pos..pos + crlf_pos])?;
+                        self.current_chunk_size = size;
                         
                         // Validate chunk size (prevent excessive memory usage)
-                        if self.current_chunk_size > 1024 * 1024 * 1024 { // 1GB limit
+                        if self.current_chunk_size > self.max_chunk_size {
                             return Err(ChunkedParseError::ChunkSizeTooLarge(self.current_chunk_size));
                         }
                         
@@ -86,6 +103,7 @@ impl ChunkedParser {
                         consumed = pos;
                         
                         if self.current_chunk_size == 0 {
+                            self.ieof = ieof;
                             self.state = ChunkState::ReadingTrailers;
                         } else {
                             self.state = ChunkState::ReadingChunk;
@@ -118,7 +136,14 @@ impl ChunkedParser {
                 },
                 
                 ChunkState::ReadingTrailers => {
-                    if let Some(end_pos) = find_double_crlf(&input[pos..]) {
+                    if input[pos..].starts_with(b"\r\n") {
+                        // No trailers, only the final CRLF
+                        pos += 2;
+                        consumed = pos;
+                        self.state = ChunkState::Complete;
+                        break;
+                    } else if let Some(end_pos) = find_double_crlf(&input[pos..]) {
+                        self.trailers = parse_trailers(&input[pos..pos + end_pos + 2])?;
                         pos += end_pos + 4; // Skip trailers and final CRLF
                         consumed = pos;
                         self.state = ChunkState::Complete;
@@ -142,11 +167,28 @@ impl ChunkedParser {
         self.state == ChunkState::Complete
     }
     
+    /// Get the trailer fields, once parsing is complete
+    pub fn trailers(&self) -> &HeaderMap {
+        &self.trailers
+    }
+
+    /// Take the trailer fields, once parsing is complete
+    pub fn take_trailers(&mut self) -> HeaderMap {
+        std::mem::take(&mut self.trailers)
+    }
+
+    /// Check if the last chunk has the `ieof` extension
+    pub fn is_ieof(&self) -> bool {
+        self.ieof
+    }
+
     /// Reset parser to initial state
     pub fn reset(&mut self) {
         self.state = ChunkState::ReadingSize;
         self.current_chunk_size = 0;
         self.current_chunk_read = 0;
+        self.trailers.clear();
+        self.ieof = false;
     }
     
     /// Get current parsing state
@@ -188,6 +230,94 @@ pub fn encode_chunked(data: &[u8]) -> Bytes {
     Bytes::from(result)
 }
 
+/// Encode data as chunked transfer encoding, with trailer fields after the last chunk
+pub fn encode_chunked_with_trailers(data: &[u8], trailers: &HeaderMap) -> Bytes {
+    let encoded = encode_chunked(data);
+    // the last chunk ends with an empty trailer section
+    let mut result = encoded[..encoded.len() - 2].to_vec();
+    encode_trailers(&mut result, trailers);
+    Bytes::from(result)
+}
+
+/// Encode a preview, ended by an `ieof` last chunk if it is the whole body
+pub fn encode_preview(data: &[u8], ieof: bool) -> Bytes {
+    let encoded = encode_chunked(data);
+    if !ieof {
+        return encoded;
+    }
+    let mut result = encoded[..encoded.len() - 5].to_vec();
+    result.extend_from_slice(b"0; ieof\r\n\r\n");
+    Bytes::from(result)
+}
+
+/// Encode the start of a 206 body, the client going on with its original body from the offset
+pub fn encode_partial(data: &[u8], original_offset: usize) -> Bytes {
+    let encoded = encode_chunked(data);
+    let mut result = encoded[..encoded.len() - 5].to_vec();
+    result.extend_from_slice(format!("0; {USE_ORIGINAL_BODY}={original_offset}\r\n\r\n").as_bytes());
+    Bytes::from(result)
+}
+
+/// Write the trailer fields and the empty line ending them
+pub fn encode_trailers(output: &mut Vec<u8>, trailers: &HeaderMap) {
+    for (name, value) in trailers {
+        output.extend_from_slice(name.as_str().as_bytes());
+        output.extend_from_slice(b": ");
+        output.extend_from_slice(value.as_bytes());
+        output.extend_from_slice(b"\r\n");
+    }
+    output.extend_from_slice(b"\r\n");
+}
+
+/// Check if a line is a chunk size, maybe followed by extensions
+pub fn is_chunk_size_line(line: &[u8]) -> bool {
+    let size = line.split(|b| *b == b';').next().unwrap_or_default().trim_ascii();
+    !size.is_empty() && size.iter().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Parse a chunk size line, returning the size and whether the `ieof` extension is present
+///
+/// Extensions are `; name` or `; name=value`, the unknown ones are ignored.
+fn parse_size_line(line: &[u8]) -> Result<(usize, bool), ChunkedParseError> {
+    let line = str::from_utf8(line).map_err(|_| ChunkedParseError::InvalidEncoding)?;
+    let mut parts = line.split(';');
+    let size = parts.next().unwrap_or_default().trim();
+    let size = usize::from_str_radix(size, 16)
+        .map_err(|e| ChunkedParseError::InvalidChunkSize(e.to_string()))?;
+    let mut ieof = false;
+    for extension in parts {
+        let name = extension.split('=').next().unwrap_or_default().trim();
+        if name.is_empty() {
+            return Err(ChunkedParseError::InvalidEncoding);
+        }
+        if name.eq_ignore_ascii_case("ieof") {
+            ieof = true;
+        }
+    }
+    Ok((size, ieof))
+}
+
+/// Parse the trailer fields, each ended by a CRLF
+fn parse_trailers(data: &[u8]) -> Result<HeaderMap, ChunkedParseError> {
+    let mut trailers = HeaderMap::new();
+    for line in data.split(|b| *b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            continue;
+        }
+        let colon = line
+            .iter()
+            .position(|b| *b == b':')
+            .ok_or(ChunkedParseError::InvalidTrailer)?;
+        let name = HeaderName::from_bytes(&line[..colon])
+            .map_err(|_| ChunkedParseError::InvalidTrailer)?;
+        let value = HeaderValue::from_bytes(line[colon + 1..].trim_ascii())
+            .map_err(|_| ChunkedParseError::InvalidTrailer)?;
+        trailers.append(name, value);
+    }
+    Ok(trailers)
+}
+
 /// Find CRLF sequence in data
 fn find_crlf(data: &[u8]) -> Option<usize> {
     data.windows(2).position(|window| window == b"\r\n")
@@ -275,6 +405,44 @@ mod tests {
         assert!(matches!(result.unwrap_err(), ChunkedParseError::InvalidChunkSize(_)));
     }
     
+    #[test]
+    fn test_chunk_extensions() {
+        let chunked_data = b"5;name=value\r\nhello\r\n0; ieof\r\n\r\n";
+        let mut parser = ChunkedParser::new();
+
+        let (decoded, consumed) = parser.parse_chunk(chunked_data).unwrap();
+        assert_eq!(decoded, b"hello");
+        assert_eq!(consumed, chunked_data.len());
+        assert!(parser.is_complete());
+        assert!(parser.is_ieof());
+
+        assert!(is_chunk_size_line(b"0; ieof"));
+        assert!(!is_chunk_size_line(b"; ieof"));
+        assert_eq!(encode_preview(b"hello", true).as_ref(), b"5\r\nhello\r\n0; ieof\r\n\r\n");
+        assert_eq!(encode_preview(b"hello", false), encode_chunked(b"hello"));
+        assert_eq!(encode_partial(b"", 0).as_ref(), b"0; use-original-body=0\r\n\r\n");
+    }
+
+    #[test]
+    fn test_trailers() {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("x-scan-result", "clean".parse().unwrap());
+        trailers.insert("x-digest", "abc".parse().unwrap());
+        let encoded = encode_chunked_with_trailers(b"hello", &trailers);
+
+        let mut parser = ChunkedParser::new();
+        let (decoded, consumed) = parser.parse_chunk(&encoded).unwrap();
+        assert_eq!(decoded, b"hello");
+        assert_eq!(consumed, encoded.len());
+        assert!(parser.is_complete());
+        assert!(!parser.is_ieof());
+        assert_eq!(parser.take_trailers(), trailers);
+
+        let mut parser = ChunkedParser::new();
+        let result = parser.parse_chunk(b"0\r\nno colon\r\n\r\n");
+        assert!(matches!(result.unwrap_err(), ChunkedParseError::InvalidTrailer));
+    }
+
     #[test]
     fn test_chunk_size_too_large() {
         let large_size = "1000000000"; // 1GB in hex
@@ -2,6 +2,7 @@
 
 use crate::error::IcapError;
 use crate::protocol::chunked::ChunkedParser;
+use crate::protocol::start_line::{self, RequestLine, StatusLine};
 use bytes::Bytes;
 use http::{HeaderMap, StatusCode, Uri, Version};
 use std::collections::HashMap;
@@ -70,6 +71,36 @@ pub struct IcapResponse {
     pub encapsulated: Option<EncapsulatedData>,
 }
 
+impl IcapRequest {
+    /// Trailer fields sent after the chunked body, if any
+    pub fn trailers(&self) -> Option<&HeaderMap> {
+        self.encapsulated.as_ref()?.trailers.as_ref()
+    }
+
+    /// Check if the preview is the whole body, as told by an `ieof` chunk extension
+    pub fn is_ieof(&self) -> bool {
+        self.encapsulated.as_ref().is_some_and(|e| e.ieof)
+    }
+}
+
+impl IcapResponse {
+    /// Trailer fields sent after the chunked body, if any
+    pub fn trailers(&self) -> Option<&HeaderMap> {
+        self.encapsulated.as_ref()?.trailers.as_ref()
+    }
+
+    /// Set the trailer fields to send after the chunked body
+    ///
+    /// They are announced in a Trailer header and written after the last
+    /// chunk of the body when the response is serialized. Responses without
+    /// encapsulated data have no chunked body, so nothing is set.
+    pub fn set_trailers(&mut self, trailers: HeaderMap) {
+        if let Some(encapsulated) = &mut self.encapsulated {
+            encapsulated.trailers = Some(trailers);
+        }
+    }
+}
+
 /// Encapsulated data for REQMOD/RESPMOD
 #[derive(Debug, Clone)]
 pub struct EncapsulatedData {
@@ -83,6 +114,14 @@ pub struct EncapsulatedData {
     pub res_body: Option<Bytes>,
     /// Null body indicator
     pub null_body: bool,
+    /// Trailer fields following the chunked body
+    pub trailers: Option<HeaderMap>,
+    /// The preview ended with an `ieof` chunk, so it is the whole body
+    pub ieof: bool,
+    /// Request line of the HTTP request headers
+    pub req_line: Option<RequestLine>,
+    /// Status line of the HTTP response headers
+    pub status_line: Option<StatusLine>,
 }
 
 /// ICAP service information
@@ -104,18 +143,28 @@ pub struct IcapParser;
 impl IcapParser {
     /// Parse ICAP request from bytes using nom parser
     pub fn parse_request(data: &[u8]) -> Result<IcapRequest, IcapError> {
-        let data_str = std::str::from_utf8(data)
-            .map_err(|e| IcapError::protocol_error(&format!("Invalid UTF-8: {}", e), "PARSER"))?;
-        
-        crate::protocol::parser::parse_icap_request(data_str)
+        crate::protocol::parser::parse_icap_request(data)
+    }
+
+    /// Parse ICAP request from bytes, failing if it exceeds any of the limits
+    pub fn parse_request_with_limits(
+        data: &[u8],
+        limits: &crate::protocol::limits::ProtocolLimits,
+    ) -> Result<IcapRequest, IcapError> {
+        crate::protocol::parser::parse_icap_request_with_limits(data, limits)
     }
 
     /// Parse ICAP response from bytes using nom parser
     pub fn parse_response(data: &[u8]) -> Result<IcapResponse, IcapError> {
-        let data_str = std::str::from_utf8(data)
-            .map_err(|e| IcapError::protocol_error(&format!("Invalid UTF-8: {}", e), "PARSER"))?;
-        
-        crate::protocol::parser::parse_icap_response(data_str)
+        crate::protocol::parser::parse_icap_response(data)
+    }
+
+    /// Parse ICAP response from bytes, failing if it exceeds any of the limits
+    pub fn parse_response_with_limits(
+        data: &[u8],
+        limits: &crate::protocol::limits::ProtocolLimits,
+    ) -> Result<IcapResponse, IcapError> {
+        crate::protocol::parser::parse_icap_response_with_limits(data, limits)
     }
 }
 
@@ -165,6 +214,8 @@ fn parse_encapsulated_data(header: &http::HeaderValue, body: &[u8]) -> Result<En
     let mut res_hdr = None;
     let mut req_body = None;
     let mut res_body = None;
+    let mut req_line = None;
+    let mut status_line = None;
     
     if let Some(offset) = req_hdr_offset {
         // Find the end of request headers by looking for the next offset or end of data
@@ -178,6 +229,7 @@ fn parse_encapsulated_data(header: &http::HeaderValue, body: &[u8]) -> Result<En
         
         if offset < safe_end_offset && offset < body.len() {
             req_hdr = Some(parse_http_headers(&body[offset..safe_end_offset])?);
+            req_line = start_line::first_line(&body[offset..safe_end_offset]).and_then(RequestLine::parse);
         }
     }
     
@@ -191,6 +243,7 @@ fn parse_encapsulated_data(header: &http::HeaderValue, body: &[u8]) -> Result<En
         
         if offset < safe_end_offset && offset < body.len() {
             res_hdr = Some(parse_http_headers(&body[offset..safe_end_offset])?);
+            status_line = start_line::first_line(&body[offset..safe_end_offset]).and_then(StatusLine::parse);
         }
     }
     
@@ -225,6 +278,10 @@ fn parse_encapsulated_data(header: &http::HeaderValue, body: &[u8]) -> Result<En
         res_hdr,
         res_body,
         null_body,
+        trailers: None,
+        ieof: false,
+        req_line,
+        status_line,
     })
 }
 
@@ -308,7 +365,7 @@ impl IcapSerializer {
         
         // Serialize body
         if !request.body.is_empty() {
-            output.extend_from_slice(&request.body);
+            write_body(&mut output, &request.body, request.trailers());
         }
         
         Ok(Bytes::from(output))
@@ -336,6 +393,14 @@ impl IcapSerializer {
             println!("DEBUG: Response header: {}", header_line.trim());
             output.extend_from_slice(header_line.as_bytes());
         }
+
+        // Announce the trailer fields
+        if let Some(trailers) = response.trailers().filter(|t| !t.is_empty())
+            && !response.headers.contains_key("trailer")
+        {
+            let names = trailers.keys().map(|k| k.as_str()).collect::<Vec<_>>();
+            output.extend_from_slice(format!("Trailer: {}\r\n", names.join(", ")).as_bytes());
+        }
         
         // Serialize encapsulated header if present and not already in headers
         if let Some(encapsulated) = &response.encapsulated {
@@ -356,7 +421,7 @@ impl IcapSerializer {
             println!("DEBUG: 204 No Modifications response - skipping body as per RFC 3507");
         } else if !response.body.is_empty() {
             println!("DEBUG: Adding response body: {} bytes", response.body.len());
-            output.extend_from_slice(&response.body);
+            write_body(&mut output, &response.body, response.trailers());
         }
         
         let result = Bytes::from(output);
@@ -367,6 +432,18 @@ impl IcapSerializer {
     }
 }
 
+/// Write a chunked body, with the trailer fields after its last chunk
+fn write_body(output: &mut Vec<u8>, body: &[u8], trailers: Option<&HeaderMap>) {
+    match trailers.filter(|t| !t.is_empty()) {
+        Some(trailers) if body.ends_with(b"0\r\n\r\n") => {
+            // the last chunk is followed by the trailer fields, then the empty line
+            output.extend_from_slice(&body[..body.len() - 2]);
+            crate::protocol::chunked::encode_trailers(output, trailers);
+        }
+        _ => output.extend_from_slice(body),
+    }
+}
+
 /// Format HTTP version to string
 fn format_http_version(version: Version) -> &'static str {
     match version {
@@ -456,11 +533,7 @@ fn is_chunked_data(data: &[u8]) -> bool {
     // Look for the first line to see if it's a hex number followed by CRLF
     if let Some(crlf_pos) = data.windows(2).position(|w| w == b"\r\n") {
         if crlf_pos > 0 && crlf_pos < 20 { // Reasonable chunk size line length
-            let first_line = &data[..crlf_pos];
-            if let Ok(line_str) = std::str::from_utf8(first_line) {
-                // Check if it's a valid hex number
-                return line_str.chars().all(|c| c.is_ascii_hexdigit());
-            }
+            return crate::protocol::chunked::is_chunk_size_line(&data[..crlf_pos]);
         }
     }
     
@@ -0,0 +1,317 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Executable RFC 3507 conformance spec
+//!
+//! The requirements the server is known to meet are listed in
+//! [`REQUIREMENTS`]. Each one is covered by a test function, linked to it in
+//! the coverage table of the tests. The table refers to the test functions
+//! themselves, so removing a covering test breaks the test build, and a
+//! requirement without an entry in the table fails the coverage test.
+
+/// Requirement level, as of RFC 2119
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Must,
+    Should,
+}
+
+/// A requirement of RFC 3507
+#[derive(Debug, Clone, Copy)]
+pub struct Requirement {
+    /// Stable identifier, referred to by the coverage table
+    pub id: &'static str,
+    /// Section of RFC 3507
+    pub section: &'static str,
+    pub level: Level,
+    pub text: &'static str,
+}
+
+/// The covered requirements
+pub const REQUIREMENTS: &[Requirement] = &[
+    Requirement {
+        id: "status-line",
+        section: "4.3.3",
+        level: Level::Must,
+        text: "The status line of a response starts with ICAP/1.0 and the status code",
+    },
+    Requirement {
+        id: "status-400-malformed",
+        section: "4.3.3",
+        level: Level::Must,
+        text: "A request without a valid Encapsulated header is rejected with 400 Bad Request",
+    },
+    Requirement {
+        id: "status-501-method",
+        section: "4.3.3",
+        level: Level::Must,
+        text: "A method the service does not implement is answered with 501",
+    },
+    Requirement {
+        id: "status-505-version",
+        section: "4.3.3",
+        level: Level::Must,
+        text: "A request of an ICAP version other than 1.0 is answered with 505",
+    },
+    Requirement {
+        id: "encapsulated-present",
+        section: "4.4.1",
+        level: Level::Must,
+        text: "Every response carries an Encapsulated header, null-body=0 if there is no body",
+    },
+    Requirement {
+        id: "encapsulated-offsets",
+        section: "4.4.1",
+        level: Level::Must,
+        text: "The offsets of the Encapsulated header increase, other messages are rejected",
+    },
+    Requirement {
+        id: "encapsulated-order",
+        section: "4.4.1",
+        level: Level::Must,
+        text: "The request header, response header and body sections are listed in this order",
+    },
+    Requirement {
+        id: "no-content-empty",
+        section: "4.6",
+        level: Level::Must,
+        text: "A 204 response has an ISTag, a null-body Encapsulated header and no body",
+    },
+    Requirement {
+        id: "no-content-allowed",
+        section: "4.6",
+        level: Level::Must,
+        text: "Outside of a preview, a 204 is only sent if the client lists 204 in Allow",
+    },
+    Requirement {
+        id: "options-headers",
+        section: "4.10.2",
+        level: Level::Must,
+        text: "An OPTIONS response carries the Methods, ISTag and Encapsulated headers",
+    },
+    Requirement {
+        id: "options-service",
+        section: "4.10.2",
+        level: Level::Should,
+        text: "An OPTIONS response describes the service in a Service header",
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{HashMap, HashSet};
+
+    use bytes::Bytes;
+    use http::{HeaderMap, StatusCode};
+
+    use crate::protocol::common::{EncapsulatedData, IcapMethod};
+    use crate::protocol::errors::IcapErrorCode;
+    use crate::protocol::headers::allows_204;
+    use crate::protocol::parser::{parse_icap_request, parse_icap_response};
+    use crate::protocol::response_generator::IcapResponseGenerator;
+
+    /// Requirement id and covering test
+    const COVERAGE: &[(&str, fn())] = &[
+        ("status-line", status_line),
+        ("status-400-malformed", status_400_malformed),
+        ("status-501-method", status_501_method),
+        ("status-505-version", status_505_version),
+        ("encapsulated-present", encapsulated_present),
+        ("encapsulated-offsets", encapsulated_offsets),
+        ("encapsulated-order", encapsulated_order),
+        ("no-content-empty", no_content_empty),
+        ("no-content-allowed", no_content_allowed),
+        ("options-headers", options_headers),
+        ("options-service", options_service),
+    ];
+
+    fn generator() -> IcapResponseGenerator {
+        IcapResponseGenerator::new("G3ICAP/1.0.0".to_string(), "g3icap-1.0.0".to_string())
+    }
+
+    fn header<'a>(headers: &'a HeaderMap, name: &str) -> &'a str {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_else(|| panic!("missing {name} header"))
+    }
+
+    #[test]
+    fn every_requirement_is_covered() {
+        let mut ids = HashSet::new();
+        for requirement in REQUIREMENTS {
+            assert!(
+                ids.insert(requirement.id),
+                "duplicate requirement {}",
+                requirement.id
+            );
+            let covering = COVERAGE
+                .iter()
+                .filter(|(id, _)| *id == requirement.id)
+                .count();
+            assert_eq!(
+                covering, 1,
+                "requirement {} (RFC 3507 {}) should have exactly one covering test",
+                requirement.id, requirement.section
+            );
+        }
+        for (id, _) in COVERAGE {
+            assert!(ids.contains(id), "test covers unknown requirement {id}");
+        }
+    }
+
+    #[test]
+    fn status_line() {
+        let generator = generator();
+        let data = generator.serialize_response(&generator.no_modifications(None));
+        assert!(data.starts_with(b"ICAP/1.0 204 No Content\r\n"));
+
+        let data = generator.serialize_response(&generator.bad_request(None));
+        assert!(data.starts_with(b"ICAP/1.0 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    fn status_400_malformed() {
+        let missing =
+            "REQMOD icap://icap.example.net/reqmod ICAP/1.0\r\nHost: icap.example.net\r\n\r\n";
+        assert!(parse_icap_request(missing.as_bytes()).is_err());
+        let invalid = "REQMOD icap://icap.example.net/reqmod ICAP/1.0\r\nHost: icap.example.net\r\nEncapsulated: req-hdr\r\n\r\n";
+        assert!(parse_icap_request(invalid.as_bytes()).is_err());
+
+        assert_eq!(
+            IcapErrorCode::InvalidEncapsulated.status_code(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            generator()
+                .bad_request(Some("invalid Encapsulated header"))
+                .status,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn status_501_method() {
+        assert_eq!(
+            IcapErrorCode::NotImplemented.status_code(),
+            StatusCode::NOT_IMPLEMENTED
+        );
+        let response = generator().not_implemented(Some(&IcapMethod::Respmod));
+        assert_eq!(response.status, StatusCode::NOT_IMPLEMENTED);
+    }
+
+    #[test]
+    fn status_505_version() {
+        let request = "REQMOD icap://icap.example.net/reqmod ICAP/2.0\r\nHost: icap.example.net\r\nEncapsulated: null-body=0\r\n\r\n";
+        assert!(parse_icap_request(request.as_bytes()).is_err());
+
+        assert_eq!(
+            IcapErrorCode::HttpVersionNotSupported.status_code(),
+            StatusCode::HTTP_VERSION_NOT_SUPPORTED
+        );
+        let response = generator().version_not_supported(Some("ICAP/2.0"));
+        assert_eq!(response.status, StatusCode::HTTP_VERSION_NOT_SUPPORTED);
+    }
+
+    #[test]
+    fn encapsulated_present() {
+        let generator = generator();
+        let responses = [
+            generator.continue_response(),
+            generator.no_modifications(None),
+            generator.bad_request(None),
+            generator.forbidden(None),
+            generator.not_found(None),
+            generator.internal_server_error(None),
+            generator.not_implemented(None),
+            generator.service_unavailable(None),
+            generator.version_not_supported(None),
+        ];
+        for response in responses {
+            assert_eq!(
+                header(&response.headers, "encapsulated"),
+                "null-body=0",
+                "status {}",
+                response.status
+            );
+        }
+    }
+
+    #[test]
+    fn encapsulated_offsets() {
+        let request = "REQMOD icap://icap.example.net/reqmod ICAP/1.0\r\nHost: icap.example.net\r\nEncapsulated: req-hdr=37, req-body=0\r\n\r\nGET / HTTP/1.1\r\nHost: example.com\r\n\r\n0\r\n\r\n";
+        assert!(parse_icap_request(request.as_bytes()).is_err());
+
+        let request = "REQMOD icap://icap.example.net/reqmod ICAP/1.0\r\nHost: icap.example.net\r\nEncapsulated: req-hdr=0, req-body=37\r\n\r\nGET / HTTP/1.1\r\nHost: example.com\r\n\r\n0\r\n\r\n";
+        assert!(parse_icap_request(request.as_bytes()).is_ok());
+
+        let response = "ICAP/1.0 200 OK\r\nISTag: \"T\"\r\nEncapsulated: res-hdr=19, res-body=19\r\n\r\nHTTP/1.1 200 OK\r\n\r\n0\r\n\r\n";
+        assert!(parse_icap_response(response.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn encapsulated_order() {
+        let mut http_headers = HeaderMap::new();
+        http_headers.insert("host", "example.com".parse().unwrap());
+        let encapsulated = EncapsulatedData {
+            req_hdr: Some(http_headers.clone()),
+            req_body: None,
+            res_hdr: Some(http_headers),
+            res_body: Some(Bytes::from_static(b"hello")),
+            null_body: false,
+            trailers: None,
+            ieof: false,
+            req_line: None,
+            status_line: None,
+        };
+        let response = generator().ok_modified(Some(encapsulated), Bytes::new());
+        let sections = header(&response.headers, "encapsulated")
+            .split(',')
+            .filter_map(|s| s.trim().split_once('=').map(|(name, _)| name))
+            .collect::<Vec<_>>();
+        assert_eq!(sections, ["req-hdr", "res-hdr", "res-body"]);
+    }
+
+    #[test]
+    fn no_content_empty() {
+        let generator = generator();
+        for response in [
+            generator.no_modifications(None),
+            generator.no_modifications_preview(b"preview"),
+        ] {
+            assert_eq!(response.status, StatusCode::NO_CONTENT);
+            assert!(response.body.is_empty());
+            assert!(response.headers.contains_key("istag"));
+            assert_eq!(header(&response.headers, "encapsulated"), "null-body=0");
+        }
+    }
+
+    #[test]
+    fn no_content_allowed() {
+        let mut headers = HeaderMap::new();
+        assert!(!allows_204(&headers));
+        headers.insert("allow", "trailers".parse().unwrap());
+        assert!(!allows_204(&headers));
+        headers.insert("allow", "trailers, 204".parse().unwrap());
+        assert!(allows_204(&headers));
+    }
+
+    #[test]
+    fn options_headers() {
+        let methods = [IcapMethod::Reqmod, IcapMethod::Respmod];
+        let response = generator().options_response(&methods, HashMap::new());
+        let listed = header(&response.headers, "methods");
+        assert!(listed.contains("REQMOD") && listed.contains("RESPMOD"));
+        assert!(response.headers.contains_key("istag"));
+        assert_eq!(header(&response.headers, "encapsulated"), "null-body=0");
+    }
+
+    #[test]
+    fn options_service() {
+        let response = generator().options_response(&[IcapMethod::Respmod], HashMap::new());
+        assert!(!header(&response.headers, "service").is_empty());
+    }
+}
@@ -0,0 +1,360 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Encapsulated header validation
+//!
+//! The Encapsulated header gives the offset of each section of the
+//! encapsulated HTTP message, relative to the end of the ICAP header. In
+//! strict mode, the default, it must follow RFC 3507 §4.4: known sections
+//! starting at 0 with increasing offsets, the headers in order followed by
+//! exactly one body section, only the sections allowed for the method, and
+//! offsets within the received body. Messages failing these checks are
+//! answered with 400.
+//!
+//! Lenient mode is kept for clients known to get the header wrong: the
+//! sections are sorted by offset and the offsets past the end of the body
+//! are clamped, as done before strict validation was added.
+//!
+//! An OPTIONS request has no body. The Encapsulated header may be left out,
+//! as in the examples of RFC 3507 §4.10, or be `null-body=0`. Any other
+//! section, or data sent after the ICAP header, is answered with 400 in
+//! strict mode and ignored in lenient mode.
+
+use crate::error::IcapError;
+use crate::protocol::common::IcapMethod;
+
+/// Protocol name of the validation errors
+const ENCAPSULATED_PROTOCOL: &str = "ENCAPSULATED";
+
+/// Header sections, in the order they must appear
+const HEADER_SECTIONS: &[&str] = &["req-hdr", "res-hdr"];
+
+/// Body sections, one of which ends the list
+const BODY_SECTIONS: &[&str] = &["req-body", "res-body", "opt-body", "null-body"];
+
+/// A violation of RFC 3507 §4.4 in the Encapsulated header
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum InvalidEncapsulated {
+    #[error("missing Encapsulated header")]
+    Missing,
+    #[error("malformed Encapsulated header")]
+    Syntax,
+    #[error("unknown encapsulated section {0}")]
+    UnknownSection(String),
+    #[error("duplicate encapsulated section {0}")]
+    DuplicateSection(String),
+    #[error("first encapsulated section at offset {0} instead of 0")]
+    FirstOffset(usize),
+    #[error("encapsulated offset {offset} of {section} not after the previous one")]
+    OffsetNotIncreasing { section: String, offset: usize },
+    #[error("encapsulated section {0} out of order")]
+    OutOfOrder(String),
+    #[error("no encapsulated body section")]
+    MissingBody,
+    #[error("encapsulated section {section} not allowed in {method}")]
+    NotAllowed {
+        section: String,
+        method: &'static str,
+    },
+    #[error("encapsulated offset {offset} of {section} past the {len} bytes of the body")]
+    OutOfBounds {
+        section: String,
+        offset: usize,
+        len: usize,
+    },
+    #[error("OPTIONS request carrying a body")]
+    OptionsBody,
+}
+
+impl From<InvalidEncapsulated> for IcapError {
+    fn from(e: InvalidEncapsulated) -> Self {
+        IcapError::Protocol {
+            message: e.to_string(),
+            protocol: Some(ENCAPSULATED_PROTOCOL.to_string()),
+            context: None,
+            source: Some(Box::new(e)),
+        }
+    }
+}
+
+impl IcapError {
+    /// Get the Encapsulated header violation that caused this error, if any
+    pub fn invalid_encapsulated(&self) -> Option<&InvalidEncapsulated> {
+        match self {
+            IcapError::Protocol {
+                source: Some(source),
+                ..
+            } => source.downcast_ref::<InvalidEncapsulated>(),
+            _ => None,
+        }
+    }
+}
+
+/// Sections a request of the method may contain
+fn allowed_sections(method: &IcapMethod) -> &'static [&'static str] {
+    match method {
+        IcapMethod::Reqmod => &["req-hdr", "req-body", "null-body"],
+        IcapMethod::Respmod => &["req-hdr", "res-hdr", "res-body", "null-body"],
+        IcapMethod::Options => &["opt-body", "null-body"],
+    }
+}
+
+fn method_name(method: &IcapMethod) -> &'static str {
+    match method {
+        IcapMethod::Reqmod => "REQMOD",
+        IcapMethod::Respmod => "RESPMOD",
+        IcapMethod::Options => "OPTIONS",
+    }
+}
+
+fn is_known(section: &str) -> bool {
+    HEADER_SECTIONS.contains(&section) || BODY_SECTIONS.contains(&section)
+}
+
+/// Check the sections of a request
+pub fn check_request(
+    method: &IcapMethod,
+    sections: &[(String, usize)],
+) -> Result<(), InvalidEncapsulated> {
+    let allowed = allowed_sections(method);
+    if let Some((section, _)) = sections
+        .iter()
+        .find(|(s, _)| is_known(s) && !allowed.contains(&s.as_str()))
+    {
+        return Err(InvalidEncapsulated::NotAllowed {
+            section: section.clone(),
+            method: method_name(method),
+        });
+    }
+    check_sections(sections)
+}
+
+/// Check the sections of a response
+///
+/// The sections allowed depend on the method of the request, which is not
+/// known here, so only the order and offsets are checked.
+pub fn check_response(sections: &[(String, usize)]) -> Result<(), InvalidEncapsulated> {
+    check_sections(sections)
+}
+
+/// Check the names, order and offsets of the sections
+fn check_sections(sections: &[(String, usize)]) -> Result<(), InvalidEncapsulated> {
+    if let Some((_, offset)) = sections.first()
+        && *offset != 0
+    {
+        return Err(InvalidEncapsulated::FirstOffset(*offset));
+    }
+
+    let mut header_rank = 0;
+    for (i, (section, offset)) in sections.iter().enumerate() {
+        if !is_known(section) {
+            return Err(InvalidEncapsulated::UnknownSection(section.clone()));
+        }
+        if sections[..i].iter().any(|(s, _)| s == section) {
+            return Err(InvalidEncapsulated::DuplicateSection(section.clone()));
+        }
+        match HEADER_SECTIONS.iter().position(|s| s == section) {
+            Some(rank) if rank < header_rank => {
+                return Err(InvalidEncapsulated::OutOfOrder(section.clone()));
+            }
+            Some(rank) => header_rank = rank,
+            // the body section ends the list
+            None if i != sections.len() - 1 => {
+                return Err(InvalidEncapsulated::OutOfOrder(section.clone()));
+            }
+            None => {}
+        }
+        if i > 0 && *offset <= sections[i - 1].1 {
+            return Err(InvalidEncapsulated::OffsetNotIncreasing {
+                section: section.clone(),
+                offset: *offset,
+            });
+        }
+    }
+
+    match sections.last() {
+        Some((section, _)) if BODY_SECTIONS.contains(&section.as_str()) => Ok(()),
+        _ => Err(InvalidEncapsulated::MissingBody),
+    }
+}
+
+/// Check that the sections start within the received body
+pub fn check_bounds(sections: &[(String, usize)], len: usize) -> Result<(), InvalidEncapsulated> {
+    match sections.iter().find(|(_, offset)| *offset > len) {
+        Some((section, offset)) => Err(InvalidEncapsulated::OutOfBounds {
+            section: section.clone(),
+            offset: *offset,
+            len,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Check that an OPTIONS request carries no body
+///
+/// `sections` is `None` without an Encapsulated header, and `extra` is the
+/// size of the data received after the ICAP header.
+pub fn check_options(
+    sections: Option<&[(String, usize)]>,
+    extra: usize,
+) -> Result<(), InvalidEncapsulated> {
+    let declared = match sections {
+        None => false,
+        Some([(section, 0)]) => section != "null-body",
+        Some(_) => true,
+    };
+    if declared || extra > 0 {
+        return Err(InvalidEncapsulated::OptionsBody);
+    }
+    Ok(())
+}
+
+/// Make the best of the sections sent by a client in lenient mode
+pub fn make_lenient(sections: &mut [(String, usize)]) {
+    sections.sort_by_key(|(_, offset)| *offset);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Method, sections and the error they fail with
+    type InvalidCase = (
+        IcapMethod,
+        &'static [(&'static str, usize)],
+        InvalidEncapsulated,
+    );
+
+    fn sections(list: &[(&str, usize)]) -> Vec<(String, usize)> {
+        list.iter().map(|(s, o)| (s.to_string(), *o)).collect()
+    }
+
+    #[test]
+    fn valid_requests() {
+        let cases: &[(IcapMethod, &[(&str, usize)])] = &[
+            (IcapMethod::Reqmod, &[("req-hdr", 0), ("req-body", 40)]),
+            (IcapMethod::Reqmod, &[("req-hdr", 0), ("null-body", 40)]),
+            (IcapMethod::Reqmod, &[("req-body", 0)]),
+            (
+                IcapMethod::Respmod,
+                &[("req-hdr", 0), ("res-hdr", 40), ("res-body", 80)],
+            ),
+            (IcapMethod::Respmod, &[("res-hdr", 0), ("null-body", 40)]),
+            (IcapMethod::Options, &[("null-body", 0)]),
+            (IcapMethod::Options, &[("opt-body", 0)]),
+        ];
+        for (method, list) in cases {
+            assert_eq!(check_request(method, &sections(list)), Ok(()), "{list:?}");
+        }
+    }
+
+    #[test]
+    fn invalid_requests() {
+        let cases: &[InvalidCase] = &[
+            (
+                IcapMethod::Reqmod,
+                &[("req-hdr", 10), ("req-body", 40)],
+                InvalidEncapsulated::FirstOffset(10),
+            ),
+            (
+                IcapMethod::Reqmod,
+                &[("req-hdr", 0), ("req-body", 0)],
+                InvalidEncapsulated::OffsetNotIncreasing {
+                    section: "req-body".to_string(),
+                    offset: 0,
+                },
+            ),
+            (
+                IcapMethod::Reqmod,
+                &[("req-hdr", 0)],
+                InvalidEncapsulated::MissingBody,
+            ),
+            (
+                IcapMethod::Reqmod,
+                &[("req-body", 0), ("req-hdr", 40)],
+                InvalidEncapsulated::OutOfOrder("req-body".to_string()),
+            ),
+            (
+                IcapMethod::Reqmod,
+                &[("res-hdr", 0), ("null-body", 40)],
+                InvalidEncapsulated::NotAllowed {
+                    section: "res-hdr".to_string(),
+                    method: "REQMOD",
+                },
+            ),
+            (
+                IcapMethod::Respmod,
+                &[("res-hdr", 0), ("req-hdr", 40), ("res-body", 80)],
+                InvalidEncapsulated::OutOfOrder("req-hdr".to_string()),
+            ),
+            (
+                IcapMethod::Respmod,
+                &[("res-hdr", 0), ("res-hdr", 40), ("res-body", 80)],
+                InvalidEncapsulated::DuplicateSection("res-hdr".to_string()),
+            ),
+            (
+                IcapMethod::Respmod,
+                &[("res-hdr", 0), ("trailer", 40), ("res-body", 80)],
+                InvalidEncapsulated::UnknownSection("trailer".to_string()),
+            ),
+            (
+                IcapMethod::Options,
+                &[("req-body", 0)],
+                InvalidEncapsulated::NotAllowed {
+                    section: "req-body".to_string(),
+                    method: "OPTIONS",
+                },
+            ),
+        ];
+        for (method, list, error) in cases {
+            assert_eq!(
+                check_request(method, &sections(list)).as_ref(),
+                Err(error),
+                "{list:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn bounds() {
+        let list = sections(&[("res-hdr", 0), ("res-body", 40)]);
+        assert_eq!(check_bounds(&list, 40), Ok(()));
+        assert_eq!(
+            check_bounds(&list, 39),
+            Err(InvalidEncapsulated::OutOfBounds {
+                section: "res-body".to_string(),
+                offset: 40,
+                len: 39,
+            })
+        );
+    }
+
+    #[test]
+    fn options() {
+        let null = sections(&[("null-body", 0)]);
+        assert_eq!(check_options(None, 0), Ok(()));
+        assert_eq!(check_options(Some(&null), 0), Ok(()));
+        assert_eq!(
+            check_options(None, 2),
+            Err(InvalidEncapsulated::OptionsBody)
+        );
+        let body = sections(&[("opt-body", 0)]);
+        assert_eq!(
+            check_options(Some(&body), 0),
+            Err(InvalidEncapsulated::OptionsBody)
+        );
+        assert_eq!(
+            check_options(Some(&[]), 0),
+            Err(InvalidEncapsulated::OptionsBody)
+        );
+    }
+
+    #[test]
+    fn lenient() {
+        let mut list = sections(&[("req-hdr", 37), ("req-body", 0)]);
+        make_lenient(&mut list);
+        assert_eq!(list, sections(&[("req-body", 0), ("req-hdr", 37)]));
+    }
+}
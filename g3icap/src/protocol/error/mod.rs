@@ -4,6 +4,9 @@
 
 use crate::error::IcapError;
 use crate::protocol::common::IcapResponse;
+use crate::protocol::encapsulated::InvalidEncapsulated;
+use crate::protocol::errors::IcapErrorCode;
+use crate::protocol::limits::LimitExceeded;
 use bytes::Bytes;
 use http::{HeaderMap, StatusCode, Version};
 
@@ -56,6 +59,18 @@ impl ErrorResponseBuilder {
         Self::create_error_response(StatusCode::SERVICE_UNAVAILABLE, "Service Unavailable", message)
     }
 
+    /// Create the response of a message exceeding a protocol limit
+    pub fn limit_exceeded(limit: &LimitExceeded) -> IcapResponse {
+        let code = limit.error_code();
+        Self::create_error_response(code.status_code(), code.message(), &limit.to_string())
+    }
+
+    /// Create the response of a message with an invalid Encapsulated header
+    pub fn invalid_encapsulated(e: &InvalidEncapsulated) -> IcapResponse {
+        let code = IcapErrorCode::InvalidEncapsulated;
+        Self::create_error_response(code.status_code(), code.message(), &e.to_string())
+    }
+
     /// Create a 100 Continue response (for preview mode)
     pub fn continue_response() -> IcapResponse {
         let mut headers = HeaderMap::new();
@@ -105,6 +120,9 @@ impl ErrorResponseBuilder {
 
 /// Convert IcapError to appropriate ICAP response
 pub fn error_to_response(error: &IcapError) -> IcapResponse {
+    if let Some(limit) = error.limit_exceeded() {
+        return ErrorResponseBuilder::limit_exceeded(limit);
+    }
     match error {
         IcapError::Config { message, .. } => ErrorResponseBuilder::internal_server_error(&format!("Configuration error: {}", message)),
         IcapError::Protocol { message, .. } => ErrorResponseBuilder::bad_request(&format!("Protocol error: {}", message)),
@@ -32,6 +32,7 @@ pub enum IcapErrorCode {
     UnsupportedMediaType = 415,
     RequestedRangeNotSatisfiable = 416,
     ExpectationFailed = 417,
+    RequestHeaderFieldsTooLarge = 431,
 
     // 5xx Server Errors
     InternalServerError = 500,
@@ -74,6 +75,7 @@ impl IcapErrorCode {
             IcapErrorCode::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
             IcapErrorCode::RequestedRangeNotSatisfiable => StatusCode::RANGE_NOT_SATISFIABLE,
             IcapErrorCode::ExpectationFailed => StatusCode::EXPECTATION_FAILED,
+            IcapErrorCode::RequestHeaderFieldsTooLarge => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
 
             // 5xx Server Errors
             IcapErrorCode::InternalServerError => StatusCode::INTERNAL_SERVER_ERROR,
@@ -116,6 +118,7 @@ impl IcapErrorCode {
             IcapErrorCode::UnsupportedMediaType => "Unsupported Media Type",
             IcapErrorCode::RequestedRangeNotSatisfiable => "Requested Range Not Satisfiable",
             IcapErrorCode::ExpectationFailed => "Expectation Failed",
+            IcapErrorCode::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
 
             // 5xx Server Errors
             IcapErrorCode::InternalServerError => "Internal Server Error",
@@ -158,6 +161,7 @@ impl IcapErrorCode {
             IcapErrorCode::UnsupportedMediaType => "The server is refusing to service the request because the entity of the request is in a format not supported by the requested resource for the requested method.",
             IcapErrorCode::RequestedRangeNotSatisfiable => "The server cannot fulfill the request because the requested range is not satisfiable.",
             IcapErrorCode::ExpectationFailed => "The expectation given in an Expect request-header field could not be met by this server.",
+            IcapErrorCode::RequestHeaderFieldsTooLarge => "The server is unwilling to process the request because its header fields are too large.",
 
             // 5xx Server Errors
             IcapErrorCode::InternalServerError => "The server encountered an unexpected condition which prevented it from fulfilling the request.",
@@ -200,6 +204,7 @@ impl IcapErrorCode {
                 | IcapErrorCode::UnsupportedMediaType
                 | IcapErrorCode::RequestedRangeNotSatisfiable
                 | IcapErrorCode::ExpectationFailed
+                | IcapErrorCode::RequestHeaderFieldsTooLarge
                 | IcapErrorCode::InvalidRequest
                 | IcapErrorCode::InvalidResponse
                 | IcapErrorCode::InvalidEncapsulated
@@ -261,6 +266,7 @@ impl From<u16> for IcapErrorCode {
             415 => IcapErrorCode::UnsupportedMediaType,
             416 => IcapErrorCode::RequestedRangeNotSatisfiable,
             417 => IcapErrorCode::ExpectationFailed,
+            431 => IcapErrorCode::RequestHeaderFieldsTooLarge,
             500 => IcapErrorCode::InternalServerError,
             501 => IcapErrorCode::NotImplemented,
             502 => IcapErrorCode::BadGateway,
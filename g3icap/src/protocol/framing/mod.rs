@@ -0,0 +1,395 @@
+//! Framing of modified encapsulated HTTP messages
+//!
+//! A module that changes an encapsulated body leaves the Content-Length of the
+//! HTTP header section stale, and the proxy would then cut or wait for the
+//! body. Before a modified message is sent, its Content-Length is recomputed
+//! from the decoded body, or removed if the message uses chunked transfer
+//! coding, which is also what a message of unknown length is switched to.
+//!
+//! A request sent by its client with `Expect: 100-continue`, as curl does for
+//! uploads, is sent back with its whole body, so the expectation is dropped
+//! from it: the proxy would otherwise wait for a 100 from the origin before
+//! sending a body that it already holds.
+
+use bytes::Bytes;
+use http::header::{CONTENT_LENGTH, EXPECT, TRANSFER_ENCODING};
+use http::{HeaderMap, HeaderValue};
+
+use crate::protocol::chunked::ChunkedParser;
+use crate::protocol::common::EncapsulatedData;
+
+/// How the length of a modified HTTP body is announced
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyFraming {
+    /// The decoded body has this length
+    ContentLength(usize),
+    /// The length is unknown, the body is sent chunked
+    Chunked,
+}
+
+/// Check if the last transfer coding of the header section is chunked
+fn is_chunked(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(TRANSFER_ENCODING)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .next_back()
+        .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
+}
+
+fn is_continue_expectation(value: &[u8]) -> bool {
+    value.trim_ascii().eq_ignore_ascii_case(b"100-continue")
+}
+
+/// Check if the HTTP request header section expects a 100 before its body
+pub fn expects_continue(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(EXPECT)
+        .iter()
+        .any(|v| is_continue_expectation(v.as_bytes()))
+}
+
+/// Drop the 100-continue expectation of a request sent with its whole body
+///
+/// Returns true if the expectation was set. Other expectations are kept.
+pub fn strip_expect_continue(headers: &mut HeaderMap) -> bool {
+    if !expects_continue(headers) {
+        return false;
+    }
+    let others: Vec<HeaderValue> = headers
+        .get_all(EXPECT)
+        .iter()
+        .filter(|v| !is_continue_expectation(v.as_bytes()))
+        .cloned()
+        .collect();
+    headers.remove(EXPECT);
+    for value in others {
+        headers.append(EXPECT, value);
+    }
+    true
+}
+
+/// Fix the framing headers of an HTTP header section whose body changed
+pub fn fix_http_headers(headers: &mut HeaderMap, framing: BodyFraming) {
+    let chunked = is_chunked(headers);
+    match framing {
+        BodyFraming::ContentLength(len) if !chunked => {
+            headers.insert(CONTENT_LENGTH, HeaderValue::from(len));
+        }
+        _ => {
+            // chunked framing is valid for any length, and wins over Content-Length
+            headers.remove(CONTENT_LENGTH);
+            if !chunked {
+                headers.append(TRANSFER_ENCODING, HeaderValue::from_static("chunked"));
+            }
+        }
+    }
+}
+
+/// Fix the HTTP header sections of modified encapsulated data
+///
+/// The bodies are the decoded bodies of the sections.
+pub fn fix_encapsulated(encapsulated: &mut EncapsulatedData) {
+    if let (Some(headers), Some(body)) = (&mut encapsulated.req_hdr, &encapsulated.req_body) {
+        fix_http_headers(headers, BodyFraming::ContentLength(body.len()));
+        strip_expect_continue(headers);
+    }
+    if let (Some(headers), Some(body)) = (&mut encapsulated.res_hdr, &encapsulated.res_body) {
+        fix_http_headers(headers, BodyFraming::ContentLength(body.len()));
+    }
+}
+
+/// Fix the Content-Length of a serialized encapsulated message
+///
+/// The message is the ICAP body, the HTTP header section followed by the
+/// chunked HTTP body at the offsets of the Encapsulated header. Returns the
+/// new Encapsulated header and ICAP body if the HTTP header section changed,
+/// or `None` if it was right or the message could not be parsed.
+pub fn fix_serialized(encapsulated: &str, body: &[u8]) -> Option<(String, Bytes)> {
+    let mut sections = Vec::new();
+    for part in encapsulated.split(',') {
+        let (name, offset) = part.trim().split_once('=')?;
+        sections.push((
+            name.trim().to_ascii_lowercase(),
+            offset.trim().parse::<usize>().ok()?,
+        ));
+    }
+    let [.., (hdr_name, hdr_offset), (body_name, body_offset)] = sections.as_slice() else {
+        return None;
+    };
+    if !hdr_name.ends_with("-hdr") || !body_name.ends_with("-body") || body_name == "null-body" {
+        return None;
+    }
+    let (hdr_offset, body_offset) = (*hdr_offset, *body_offset);
+    if hdr_offset >= body_offset || body_offset > body.len() {
+        return None;
+    }
+
+    let header = &body[hdr_offset..body_offset];
+    let mut parser = ChunkedParser::new();
+    let (decoded, _) = parser.parse_chunk(&body[body_offset..]).ok()?;
+    if !parser.is_complete() {
+        return None;
+    }
+
+    let new_header = rewrite_header(header, decoded.len())?;
+    if new_header == header {
+        return None;
+    }
+
+    let delta = new_header.len() as isize - header.len() as isize;
+    let encapsulated = sections
+        .iter()
+        .map(|(name, offset)| {
+            let offset = if *offset >= body_offset {
+                offset.checked_add_signed(delta).unwrap_or(*offset)
+            } else {
+                *offset
+            };
+            format!("{name}={offset}")
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut new_body = Vec::with_capacity(body.len() + new_header.len());
+    new_body.extend_from_slice(&body[..hdr_offset]);
+    new_body.extend_from_slice(&new_header);
+    new_body.extend_from_slice(&body[body_offset..]);
+    Some((encapsulated, Bytes::from(new_body)))
+}
+
+/// Rewrite the Content-Length line of a serialized HTTP header section
+///
+/// The other lines and the position of the Content-Length line are kept,
+/// but for the 100-continue expectation of a request.
+fn rewrite_header(header: &[u8], body_len: usize) -> Option<Vec<u8>> {
+    let text = std::str::from_utf8(header).ok()?;
+    let text = text.strip_suffix("\r\n\r\n")?;
+    let mut lines = text.split("\r\n");
+    let start_line = lines.next()?;
+    let is_request = !start_line.starts_with("HTTP/");
+
+    let mut fields = Vec::new();
+    let mut chunked = false;
+    for line in lines {
+        let (name, value) = line.split_once(':')?;
+        if name.trim().eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value
+                .rsplit(',')
+                .next()
+                .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"));
+        }
+        fields.push((name, value, line));
+    }
+
+    let body_len = body_len.to_string();
+    let mut new_header = Vec::with_capacity(header.len() + 24);
+    new_header.extend_from_slice(start_line.as_bytes());
+    new_header.extend_from_slice(b"\r\n");
+    let mut has_length = false;
+    for (name, value, line) in fields {
+        if is_request
+            && name.trim().eq_ignore_ascii_case("expect")
+            && is_continue_expectation(value.as_bytes())
+        {
+            continue;
+        }
+        if name.trim().eq_ignore_ascii_case("content-length") {
+            // chunked messages must not have a Content-Length, and only one is kept
+            if chunked || has_length {
+                continue;
+            }
+            has_length = true;
+            if value.trim() != body_len {
+                new_header.extend_from_slice(format!("{name}: {body_len}\r\n").as_bytes());
+                continue;
+            }
+        }
+        new_header.extend_from_slice(line.as_bytes());
+        new_header.extend_from_slice(b"\r\n");
+    }
+    if !chunked && !has_length {
+        new_header.extend_from_slice(format!("Content-Length: {body_len}\r\n").as_bytes());
+    }
+    new_header.extend_from_slice(b"\r\n");
+    Some(new_header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::chunked::encode_chunked;
+
+    fn message(header: &str, body: &[u8]) -> (String, Vec<u8>) {
+        let mut data = header.as_bytes().to_vec();
+        data.extend_from_slice(&encode_chunked(body));
+        (format!("res-hdr=0, res-body={}", header.len()), data)
+    }
+
+    #[test]
+    fn grow_and_shrink() {
+        let header = "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 5\r\n\r\n";
+
+        // the body grew from 5 to 12 bytes
+        let (encapsulated, data) = message(header, b"hello world!");
+        let (encapsulated, data) = fix_serialized(&encapsulated, &data).unwrap();
+        let expected = "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 12\r\n\r\n";
+        assert_eq!(
+            encapsulated,
+            format!("res-hdr=0, res-body={}", expected.len())
+        );
+        assert!(data.starts_with(expected.as_bytes()));
+        assert!(data.ends_with(&encode_chunked(b"hello world!")));
+
+        // the body shrank to nothing
+        let header = "HTTP/1.1 200 OK\r\nContent-Length: 1024\r\n\r\n";
+        let (encapsulated, data) = message(header, b"");
+        let (encapsulated, data) = fix_serialized(&encapsulated, &data).unwrap();
+        let expected = "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n";
+        assert_eq!(
+            encapsulated,
+            format!("res-hdr=0, res-body={}", expected.len())
+        );
+        assert_eq!(&data[..expected.len()], expected.as_bytes());
+
+        // the position of the Content-Length line is kept
+        let header = "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nContent-Type: text/plain\r\n\r\n";
+        let (encapsulated, data) = message(header, b"hi");
+        let (_, data) = fix_serialized(&encapsulated, &data).unwrap();
+        assert!(data.starts_with(
+            b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nContent-Type: text/plain\r\n\r\n"
+        ));
+
+        // unchanged
+        let header = "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n";
+        let (encapsulated, data) = message(header, b"hello");
+        assert!(fix_serialized(&encapsulated, &data).is_none());
+    }
+
+    #[test]
+    fn serialized_chunked_and_request() {
+        // a chunked message has no Content-Length
+        let header = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nContent-Length: 5\r\n\r\n";
+        let (encapsulated, data) = message(header, b"hello world");
+        let (_, data) = fix_serialized(&encapsulated, &data).unwrap();
+        assert!(data.starts_with(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n"));
+
+        // the request header of a RESPMOD message is kept
+        let req = "GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+        let res = "HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\n";
+        let mut data = format!("{req}{res}").into_bytes();
+        data.extend_from_slice(&encode_chunked(b"hi"));
+        let encapsulated = format!(
+            "req-hdr=0, res-hdr={}, res-body={}",
+            req.len(),
+            req.len() + res.len()
+        );
+        let (encapsulated, data) = fix_serialized(&encapsulated, &data).unwrap();
+        let res = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n";
+        assert_eq!(
+            encapsulated,
+            format!(
+                "req-hdr=0, res-hdr={}, res-body={}",
+                req.len(),
+                req.len() + res.len()
+            )
+        );
+        assert_eq!(
+            &data[..req.len() + res.len()],
+            format!("{req}{res}").as_bytes()
+        );
+
+        // no body
+        assert!(fix_serialized("res-hdr=0, null-body=38", res.as_bytes()).is_none());
+    }
+
+    /// Upload as sent by curl, which expects a 100 for bodies over 1 MiB
+    const CURL_UPLOAD: &str = "POST /upload HTTP/1.1\r\n\
+                               Host: files.example.net\r\n\
+                               User-Agent: curl/8.5.0\r\n\
+                               Accept: */*\r\n\
+                               Content-Length: 2097152\r\n\
+                               Content-Type: application/octet-stream\r\n\
+                               Expect: 100-continue\r\n\
+                               \r\n";
+
+    #[test]
+    fn curl_upload() {
+        // the body was replaced, the request is sent back with the new one
+        let mut data = CURL_UPLOAD.as_bytes().to_vec();
+        data.extend_from_slice(&encode_chunked(b"redacted"));
+        let encapsulated = format!("req-hdr=0, req-body={}", CURL_UPLOAD.len());
+        let (encapsulated, data) = fix_serialized(&encapsulated, &data).unwrap();
+        let expected = CURL_UPLOAD
+            .replace("Content-Length: 2097152", "Content-Length: 8")
+            .replace("Expect: 100-continue\r\n", "");
+        assert_eq!(
+            encapsulated,
+            format!("req-hdr=0, req-body={}", expected.len())
+        );
+        assert_eq!(&data[..expected.len()], expected.as_bytes());
+        assert!(data.ends_with(&encode_chunked(b"redacted")));
+
+        // the expectation is only dropped from requests
+        let header = "HTTP/1.1 200 OK\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n";
+        let (encapsulated, data) = message(header, b"hello");
+        assert!(fix_serialized(&encapsulated, &data).is_none());
+
+        let mut req_hdr = HeaderMap::new();
+        req_hdr.insert(CONTENT_LENGTH, HeaderValue::from_static("2097152"));
+        req_hdr.append(EXPECT, HeaderValue::from_static("100-Continue"));
+        req_hdr.append(EXPECT, HeaderValue::from_static("x-other"));
+        assert!(expects_continue(&req_hdr));
+        let mut encapsulated = EncapsulatedData {
+            req_hdr: Some(req_hdr),
+            req_body: Some(Bytes::from_static(b"redacted")),
+            res_hdr: None,
+            res_body: None,
+            null_body: false,
+            trailers: None,
+            ieof: false,
+            req_line: None,
+            status_line: None,
+        };
+        fix_encapsulated(&mut encapsulated);
+        let req_hdr = encapsulated.req_hdr.unwrap();
+        assert!(!expects_continue(&req_hdr));
+        assert_eq!(req_hdr.get(EXPECT).unwrap(), "x-other");
+        assert_eq!(req_hdr.get(CONTENT_LENGTH).unwrap(), "8");
+    }
+
+    #[test]
+    fn structured_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_LENGTH, HeaderValue::from_static("10"));
+        fix_http_headers(&mut headers, BodyFraming::ContentLength(25));
+        assert_eq!(headers.get(CONTENT_LENGTH).unwrap(), "25");
+
+        // unknown length switches to chunked
+        fix_http_headers(&mut headers, BodyFraming::Chunked);
+        assert!(!headers.contains_key(CONTENT_LENGTH));
+        assert_eq!(headers.get(TRANSFER_ENCODING).unwrap(), "chunked");
+        fix_http_headers(&mut headers, BodyFraming::ContentLength(3));
+        assert!(!headers.contains_key(CONTENT_LENGTH));
+
+        let mut res_hdr = HeaderMap::new();
+        res_hdr.insert(CONTENT_LENGTH, HeaderValue::from_static("4096"));
+        let mut encapsulated = EncapsulatedData {
+            req_hdr: None,
+            req_body: None,
+            res_hdr: Some(res_hdr),
+            res_body: Some(Bytes::from_static(b"sanitized")),
+            null_body: false,
+            trailers: None,
+            ieof: false,
+            req_line: None,
+            status_line: None,
+        };
+        fix_encapsulated(&mut encapsulated);
+        assert_eq!(
+            encapsulated.res_hdr.unwrap().get(CONTENT_LENGTH).unwrap(),
+            "9"
+        );
+    }
+}
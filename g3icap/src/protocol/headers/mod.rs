@@ -297,6 +297,35 @@ impl IcapHeaders {
 }
 
 /// ICAP header errors
+/// Check if the client allows a 204 response outside of a preview
+///
+/// RFC 3507 4.6: the client lists 204 in the Allow header.
+pub fn allows_204(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(constants::ALLOW)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|code| code.trim() == "204")
+}
+
+/// Check if the client allows a 206 response to a previewed message
+///
+/// RFC 3507 errata: the client lists 206 in the Allow header.
+pub fn allows_206(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(constants::ALLOW)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|code| code.trim() == "206")
+}
+
+/// Get the size of the preview sent by the client, if it sent one
+pub fn preview_size(headers: &HeaderMap) -> Option<usize> {
+    headers.get(constants::PREVIEW)?.to_str().ok()?.trim().parse().ok()
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum IcapHeaderError {
     #[error("Invalid ICAP version: {0}")]
@@ -0,0 +1,412 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Hard limits of the ICAP parser
+//!
+//! Every ICAP message is checked against these limits before and while it is
+//! parsed, so that a single client can not make the server buffer unbounded
+//! data. Each limit fails with its own error, which maps to a specific ICAP
+//! error code.
+
+use serde::Serialize;
+
+use crate::error::IcapError;
+use crate::protocol::errors::IcapErrorCode;
+
+/// Protocol name of the limit errors
+const LIMITS_PROTOCOL: &str = "LIMITS";
+
+/// Hard limits applied when reading and parsing ICAP messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ProtocolLimits {
+    /// Maximum length of the request or status line, CRLF excluded
+    pub max_request_line: usize,
+    /// Maximum number of header fields, of the ICAP headers and of each encapsulated HTTP header
+    pub max_header_count: usize,
+    /// Maximum size of a header section, terminating empty line included
+    pub max_header_bytes: usize,
+    /// Maximum number of entries in the Encapsulated header
+    pub max_encapsulated_sections: usize,
+    /// Maximum size of a single chunk of an encapsulated body
+    pub max_chunk_size: usize,
+    /// Maximum size of a whole ICAP message
+    pub max_message_size: usize,
+    /// Maximum size of the encapsulated part of a message, after the ICAP header
+    pub max_encapsulated_size: usize,
+    /// Maximum preview size a client may announce
+    pub max_preview_size: usize,
+    /// Reject Encapsulated headers not following RFC 3507, instead of making the best of them
+    pub strict_encapsulated: bool,
+}
+
+impl Default for ProtocolLimits {
+    fn default() -> Self {
+        ProtocolLimits {
+            max_request_line: 8 * 1024,
+            max_header_count: 100,
+            max_header_bytes: 64 * 1024,
+            max_encapsulated_sections: 4,
+            max_chunk_size: 16 * 1024 * 1024,
+            max_message_size: 128 * 1024 * 1024,
+            max_encapsulated_size: 128 * 1024 * 1024,
+            max_preview_size: 1024 * 1024,
+            strict_encapsulated: true,
+        }
+    }
+}
+
+/// A protocol limit that has been exceeded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum LimitExceeded {
+    #[error("request line longer than {limit} bytes")]
+    RequestLine { limit: usize },
+    #[error("more than {limit} header fields")]
+    HeaderCount { limit: usize },
+    #[error("header section larger than {limit} bytes")]
+    HeaderBytes { limit: usize },
+    #[error("more than {limit} encapsulated sections")]
+    EncapsulatedSections { limit: usize },
+    #[error("chunk size {size} larger than {limit} bytes")]
+    ChunkSize { size: usize, limit: usize },
+    #[error("message larger than {limit} bytes")]
+    MessageSize { limit: usize },
+    #[error("encapsulated part larger than {limit} bytes")]
+    EncapsulatedSize { limit: usize },
+    #[error("preview size {size} larger than {limit} bytes")]
+    PreviewSize { size: usize, limit: usize },
+}
+
+impl LimitExceeded {
+    /// ICAP error code to answer with
+    pub fn error_code(&self) -> IcapErrorCode {
+        match self {
+            LimitExceeded::RequestLine { .. } => IcapErrorCode::RequestUriTooLarge,
+            LimitExceeded::HeaderCount { .. } | LimitExceeded::HeaderBytes { .. } => {
+                IcapErrorCode::RequestHeaderFieldsTooLarge
+            }
+            LimitExceeded::EncapsulatedSections { .. } => IcapErrorCode::InvalidEncapsulated,
+            LimitExceeded::ChunkSize { .. }
+            | LimitExceeded::MessageSize { .. }
+            | LimitExceeded::EncapsulatedSize { .. }
+            | LimitExceeded::PreviewSize { .. } => IcapErrorCode::RequestEntityTooLarge,
+        }
+    }
+
+    /// Value of the limit
+    pub fn limit(&self) -> usize {
+        match self {
+            LimitExceeded::RequestLine { limit }
+            | LimitExceeded::HeaderCount { limit }
+            | LimitExceeded::HeaderBytes { limit }
+            | LimitExceeded::EncapsulatedSections { limit }
+            | LimitExceeded::ChunkSize { limit, .. }
+            | LimitExceeded::MessageSize { limit }
+            | LimitExceeded::EncapsulatedSize { limit }
+            | LimitExceeded::PreviewSize { limit, .. } => *limit,
+        }
+    }
+
+    /// Name of the limit, as used in the config file
+    pub fn limit_name(&self) -> &'static str {
+        match self {
+            LimitExceeded::RequestLine { .. } => "max_request_line",
+            LimitExceeded::HeaderCount { .. } => "max_header_count",
+            LimitExceeded::HeaderBytes { .. } => "max_header_bytes",
+            LimitExceeded::EncapsulatedSections { .. } => "max_encapsulated_sections",
+            LimitExceeded::ChunkSize { .. } => "max_chunk_size",
+            LimitExceeded::MessageSize { .. } => "max_message_size",
+            LimitExceeded::EncapsulatedSize { .. } => "max_encapsulated_size",
+            LimitExceeded::PreviewSize { .. } => "max_preview_size",
+        }
+    }
+}
+
+impl From<LimitExceeded> for IcapError {
+    fn from(e: LimitExceeded) -> Self {
+        IcapError::Protocol {
+            message: e.to_string(),
+            protocol: Some(LIMITS_PROTOCOL.to_string()),
+            context: Some(e.limit_name().to_string()),
+            source: Some(Box::new(e)),
+        }
+    }
+}
+
+impl IcapError {
+    /// Get the protocol limit that caused this error, if any
+    pub fn limit_exceeded(&self) -> Option<&LimitExceeded> {
+        match self {
+            IcapError::Protocol {
+                source: Some(source),
+                ..
+            } => source.downcast_ref::<LimitExceeded>(),
+            _ => None,
+        }
+    }
+}
+
+impl ProtocolLimits {
+    /// Check the size of a whole message
+    pub fn check_message_size(&self, len: usize) -> Result<(), LimitExceeded> {
+        if len > self.max_message_size {
+            return Err(LimitExceeded::MessageSize {
+                limit: self.max_message_size,
+            });
+        }
+        Ok(())
+    }
+
+    /// Check the size of the encapsulated part of a message
+    pub fn check_encapsulated_size(&self, len: usize) -> Result<(), LimitExceeded> {
+        if len > self.max_encapsulated_size {
+            return Err(LimitExceeded::EncapsulatedSize {
+                limit: self.max_encapsulated_size,
+            });
+        }
+        Ok(())
+    }
+
+    /// Check the preview size announced by a client
+    pub fn check_preview_size(&self, size: usize) -> Result<(), LimitExceeded> {
+        if size > self.max_preview_size {
+            return Err(LimitExceeded::PreviewSize {
+                size,
+                limit: self.max_preview_size,
+            });
+        }
+        Ok(())
+    }
+
+    /// Check the length of the request or status line
+    pub fn check_request_line(&self, len: usize) -> Result<(), LimitExceeded> {
+        if len > self.max_request_line {
+            return Err(LimitExceeded::RequestLine {
+                limit: self.max_request_line,
+            });
+        }
+        Ok(())
+    }
+
+    /// Check the size of a header section
+    pub fn check_header_bytes(&self, len: usize) -> Result<(), LimitExceeded> {
+        if len > self.max_header_bytes {
+            return Err(LimitExceeded::HeaderBytes {
+                limit: self.max_header_bytes,
+            });
+        }
+        Ok(())
+    }
+
+    /// Check the number of fields of a header section
+    pub fn check_header_count(&self, count: usize) -> Result<(), LimitExceeded> {
+        if count > self.max_header_count {
+            return Err(LimitExceeded::HeaderCount {
+                limit: self.max_header_count,
+            });
+        }
+        Ok(())
+    }
+
+    /// Check the number of entries in the Encapsulated header
+    pub fn check_encapsulated_sections(&self, count: usize) -> Result<(), LimitExceeded> {
+        if count > self.max_encapsulated_sections {
+            return Err(LimitExceeded::EncapsulatedSections {
+                limit: self.max_encapsulated_sections,
+            });
+        }
+        Ok(())
+    }
+
+    /// Check the size of a single chunk
+    pub fn check_chunk_size(&self, size: usize) -> Result<(), LimitExceeded> {
+        if size > self.max_chunk_size {
+            return Err(LimitExceeded::ChunkSize {
+                size,
+                limit: self.max_chunk_size,
+            });
+        }
+        Ok(())
+    }
+
+    /// Check a partially received message, before its header is complete
+    ///
+    /// Lets the reader fail early instead of buffering a message that can
+    /// only fail to parse.
+    pub fn check_partial_header(&self, data: &[u8]) -> Result<(), LimitExceeded> {
+        match data.windows(2).position(|w| w == b"\r\n") {
+            Some(line_end) => {
+                self.check_request_line(line_end)?;
+                let header = &data[line_end + 2..];
+                match header.windows(4).position(|w| w == b"\r\n\r\n") {
+                    Some(end) => self.check_header_bytes(end + 4),
+                    None => self.check_header_bytes(header.len()),
+                }
+            }
+            // the CR of the CRLF may already be received
+            None => self.check_request_line(data.len().saturating_sub(1)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::parser::{
+        parse_icap_request_with_limits, parse_icap_response_with_limits,
+    };
+
+    const REQUEST: &str = "REQMOD icap://icap.example.net/filter ICAP/1.0\r\n\
+                           Host: icap.example.net\r\n\
+                           Encapsulated: req-hdr=0, req-body=44\r\n\
+                           \r\n\
+                           POST /upload HTTP/1.1\r\n\
+                           Host: example.com\r\n\
+                           \r\n\
+                           5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n";
+
+    fn limit_error(input: &str, limits: ProtocolLimits) -> LimitExceeded {
+        let e = parse_icap_request_with_limits(input.as_bytes(), &limits).unwrap_err();
+        *e.limit_exceeded().unwrap()
+    }
+
+    #[test]
+    fn within_limits() {
+        let req = parse_icap_request_with_limits(REQUEST.as_bytes(), &ProtocolLimits::default()).unwrap();
+        let enc = req.encapsulated.unwrap();
+        assert_eq!(enc.req_body.unwrap().as_ref(), b"hello world");
+        assert!(enc.req_hdr.is_some());
+    }
+
+    #[test]
+    fn request_line() {
+        let limits = ProtocolLimits {
+            max_request_line: 40,
+            ..Default::default()
+        };
+        let e = limit_error(REQUEST, limits);
+        assert_eq!(e, LimitExceeded::RequestLine { limit: 40 });
+        assert_eq!(e.error_code(), IcapErrorCode::RequestUriTooLarge);
+
+        let partial = "REQMOD icap://icap.example.net/filter ICAP";
+        assert!(limits.check_partial_header(partial.as_bytes()).is_err());
+        assert!(
+            limits
+                .check_partial_header(&partial.as_bytes()[..40])
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn header_count() {
+        let limits = ProtocolLimits {
+            max_header_count: 1,
+            ..Default::default()
+        };
+        let e = limit_error(REQUEST, limits);
+        assert_eq!(e, LimitExceeded::HeaderCount { limit: 1 });
+        assert_eq!(e.error_code(), IcapErrorCode::RequestHeaderFieldsTooLarge);
+
+        // encapsulated HTTP headers are counted on their own
+        let limits = ProtocolLimits {
+            max_header_count: 2,
+            ..Default::default()
+        };
+        let input = REQUEST.replace(
+            "Host: example.com\r\n",
+            "Host: example.com\r\nAccept: */*\r\nCookie: a=b\r\n",
+        );
+        let input = input.replace("req-body=44", "req-body=70");
+        assert_eq!(
+            limit_error(&input, limits),
+            LimitExceeded::HeaderCount { limit: 2 }
+        );
+    }
+
+    #[test]
+    fn header_bytes() {
+        let limits = ProtocolLimits {
+            max_header_bytes: 32,
+            ..Default::default()
+        };
+        let e = limit_error(REQUEST, limits);
+        assert_eq!(e, LimitExceeded::HeaderBytes { limit: 32 });
+        assert_eq!(e.error_code(), IcapErrorCode::RequestHeaderFieldsTooLarge);
+
+        let partial = "REQMOD icap://icap.example.net/filter ICAP/1.0\r\nHost: icap.example.net\r\nX-Padding: 0123456789";
+        assert!(limits.check_partial_header(partial.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn encapsulated_sections() {
+        let limits = ProtocolLimits {
+            max_encapsulated_sections: 1,
+            ..Default::default()
+        };
+        let e = limit_error(REQUEST, limits);
+        assert_eq!(e, LimitExceeded::EncapsulatedSections { limit: 1 });
+        assert_eq!(e.error_code(), IcapErrorCode::InvalidEncapsulated);
+    }
+
+    #[test]
+    fn chunk_size() {
+        let limits = ProtocolLimits {
+            max_chunk_size: 5,
+            ..Default::default()
+        };
+        let e = limit_error(REQUEST, limits);
+        assert_eq!(e, LimitExceeded::ChunkSize { size: 6, limit: 5 });
+        assert_eq!(e.error_code(), IcapErrorCode::RequestEntityTooLarge);
+    }
+
+    #[test]
+    fn message_size() {
+        let limits = ProtocolLimits {
+            max_message_size: 100,
+            ..Default::default()
+        };
+        let e = limit_error(REQUEST, limits);
+        assert_eq!(e, LimitExceeded::MessageSize { limit: 100 });
+        assert_eq!(e.error_code(), IcapErrorCode::RequestEntityTooLarge);
+
+        let response =
+            "ICAP/1.0 204 No Content\r\nISTag: \"T\"\r\nEncapsulated: null-body=0\r\n\r\n";
+        let limits = ProtocolLimits {
+            max_message_size: 32,
+            ..Default::default()
+        };
+        let e = parse_icap_response_with_limits(response.as_bytes(), &limits).unwrap_err();
+        assert_eq!(
+            e.limit_exceeded(),
+            Some(&LimitExceeded::MessageSize { limit: 32 })
+        );
+    }
+
+    #[test]
+    fn encapsulated_size() {
+        let limits = ProtocolLimits {
+            max_encapsulated_size: 64,
+            ..Default::default()
+        };
+        let e = limit_error(REQUEST, limits);
+        assert_eq!(e, LimitExceeded::EncapsulatedSize { limit: 64 });
+        assert_eq!(e.error_code(), IcapErrorCode::RequestEntityTooLarge);
+        assert_eq!(e.limit(), 64);
+    }
+
+    #[test]
+    fn preview_size() {
+        let limits = ProtocolLimits {
+            max_preview_size: 4,
+            ..Default::default()
+        };
+        let input = REQUEST.replace(
+            "Host: icap.example.net\r\n",
+            "Host: icap.example.net\r\nPreview: 5\r\n",
+        );
+        let e = limit_error(&input, limits);
+        assert_eq!(e, LimitExceeded::PreviewSize { size: 5, limit: 4 });
+        assert_eq!(e.limit_name(), "max_preview_size");
+    }
+}
@@ -12,10 +12,16 @@ pub mod respmod;
 pub mod headers;
 pub mod errors;
 pub mod chunked;
+pub mod encapsulated;
+pub mod conformance;
+pub mod framing;
+pub mod limits;
 pub mod parser;
 pub mod streaming;
 pub mod workflows;
 pub mod response_generator;
+pub mod partial;
+pub mod start_line;
 
 pub use common::*;
 pub use error::*;
@@ -26,7 +32,12 @@ pub use respmod::*;
 pub use headers::*;
 pub use errors::*;
 pub use chunked::*;
+pub use encapsulated::*;
+pub use framing::*;
+pub use limits::*;
 pub use parser::*;
 pub use streaming::*;
 pub use workflows::*;
 pub use response_generator::*;
+pub use partial::*;
+pub use start_line::*;
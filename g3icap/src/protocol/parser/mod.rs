@@ -1,21 +1,39 @@
 //! Nom-based ICAP Protocol Parser (RFC 3507 compliant)
+//!
+//! The parser works on bytes, so binary bodies need no UTF-8 validation. The
+//! request line and header section are parsed as soon as they are received,
+//! with the streaming parsers telling when more data is needed, and the body
+//! is then taken as a slice of the received message instead of a copy.
 
 use crate::error::IcapError;
+use crate::protocol::chunked::ChunkedParser;
 use crate::protocol::common::{IcapMethod, IcapRequest, IcapResponse, EncapsulatedData};
+use crate::protocol::encapsulated::{self, InvalidEncapsulated};
+use crate::protocol::limits::{LimitExceeded, ProtocolLimits};
+use crate::protocol::start_line::{self, StatusLine};
 use bytes::Bytes;
 use http::{HeaderMap, HeaderName, HeaderValue, StatusCode, Uri, Version};
 use nom::{
-    bytes::complete::{tag, take_until},
-    character::complete::{space1, digit1, multispace0},
+    bytes::streaming::{tag, take_until},
+    character::streaming::{space1, digit1},
     combinator::{map_res, value},
-    sequence::tuple,
+    sequence::{terminated, tuple},
     branch::alt,
-    multi::{many0, separated_list1},
-    IResult,
+    multi::separated_list1,
+    Err, IResult,
 };
 
+/// Method, URI and version of a request line
+type RequestLine<'a> = (IcapMethod, &'a [u8], &'a [u8]);
+
+/// Version, status code and reason of a status line
+type StatusLine<'a> = (&'a [u8], u16, &'a [u8]);
+
+/// Name and value of a header field
+type Field<'a> = (&'a [u8], &'a [u8]);
+
 /// Parse ICAP method
-fn parse_icap_method(input: &str) -> IResult<&str, IcapMethod> {
+fn parse_icap_method(input: &[u8]) -> IResult<&[u8], IcapMethod> {
     alt((
         value(IcapMethod::Reqmod, tag("REQMOD")),
         value(IcapMethod::Respmod, tag("RESPMOD")),
@@ -23,8 +41,16 @@ fn parse_icap_method(input: &str) -> IResult<&str, IcapMethod> {
     ))(input)
 }
 
+/// Parse a decimal number
+fn parse_number<T: std::str::FromStr>(input: &[u8]) -> Result<T, IcapError> {
+    std::str::from_utf8(input)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| IcapError::protocol_error("Invalid number", "PARSER"))
+}
+
 /// Parse ICAP request line
-fn parse_icap_request_line(input: &str) -> IResult<&str, (IcapMethod, String, String)> {
+fn parse_icap_request_line(input: &[u8]) -> IResult<&[u8], RequestLine<'_>> {
     let (input, (method, _, uri, _, version, _)) = tuple((
         parse_icap_method,
         space1,
@@ -33,48 +59,56 @@ fn parse_icap_request_line(input: &str) -> IResult<&str, (IcapMethod, String, St
         take_until("\r\n"),
         tag("\r\n"),
     ))(input)?;
-    Ok((input, (method, uri.to_string(), version.to_string())))
+    Ok((input, (method, uri, version)))
 }
 
 /// Parse ICAP status line
-fn parse_icap_status_line(input: &str) -> IResult<&str, (String, u16, String)> {
-    let (input, (version, _, status_code, _, _reason, _)) = tuple((
+fn parse_icap_status_line(input: &[u8]) -> IResult<&[u8], StatusLine<'_>> {
+    let (input, (version, _, status_code, _, reason, _)) = tuple((
         take_until(" "),
         space1,
-        map_res(digit1, |s: &str| s.parse::<u16>()),
+        map_res(digit1, parse_number::<u16>),
         space1,
         take_until("\r\n"),
         tag("\r\n"),
     ))(input)?;
-    Ok((input, (version.to_string(), status_code, _reason.to_string())))
+    Ok((input, (version, status_code, reason)))
 }
 
-/// Parse header line
-fn parse_header_line(input: &str) -> IResult<&str, (String, String)> {
-    let (input, key) = take_until(":")(input)?;
-    let (input, _) = tag(":")(input)?;
-    let (input, _) = multispace0(input)?;
-    let (input, val) = take_until("\r\n")(input)?;
-    let (input, _) = tag("\r\n")(input)?;
-    Ok((input, (key.trim().to_ascii_lowercase(), val.trim().to_string())))
+/// Parse a header field, from a line without its CRLF
+fn parse_header_field(line: &[u8]) -> IResult<&[u8], Field<'_>> {
+    let (value, name) = terminated(
+        nom::bytes::complete::take_until(":"),
+        nom::bytes::complete::tag(":"),
+    )(line)?;
+    Ok((&[], (name.trim_ascii(), value.trim_ascii())))
 }
 
-/// Parse headers section
-fn parse_headers(input: &str) -> IResult<&str, Vec<(String, String)>> {
-    many0(parse_header_line)(input)
+/// Parse header lines, up to and including the empty line ending them
+fn parse_header_lines(mut input: &[u8]) -> IResult<&[u8], Vec<Field<'_>>> {
+    let mut fields = Vec::new();
+    loop {
+        let (rest, line) = terminated(take_until("\r\n"), tag("\r\n"))(input)?;
+        if line.is_empty() {
+            return Ok((rest, fields));
+        }
+        let (_, field) = parse_header_field(line)?;
+        fields.push(field);
+        input = rest;
+    }
 }
 
 /// Parse encapsulated section entry
-fn parse_encapsulated_section(input: &str) -> IResult<&str, (String, usize)> {
-    let (input, section) = take_until("=")(input)?;
-    let (input, _) = tag("=")(input)?;
-    let (input, offset) = map_res(digit1, |s: &str| s.parse::<usize>())(input)?;
-    Ok((input, (section.trim().to_ascii_lowercase(), offset)))
+fn parse_encapsulated_section(input: &[u8]) -> IResult<&[u8], (String, usize)> {
+    let (input, section) = nom::bytes::complete::take_until("=")(input)?;
+    let (input, _) = nom::bytes::complete::tag("=")(input)?;
+    let (input, offset) = map_res(nom::character::complete::digit1, parse_number::<usize>)(input)?;
+    Ok((input, (String::from_utf8_lossy(section).trim().to_ascii_lowercase(), offset)))
 }
 
 /// Parse encapsulated header value
-fn parse_encapsulated_header(input: &str) -> IResult<&str, Vec<(String, usize)>> {
-    separated_list1(tag(", "), parse_encapsulated_section)(input)
+fn parse_encapsulated_header(input: &[u8]) -> IResult<&[u8], Vec<(String, usize)>> {
+    separated_list1(nom::bytes::complete::tag(", "), parse_encapsulated_section)(input)
 }
 
 /// Find next section offset or body end
@@ -86,205 +120,385 @@ fn find_next_section_offset(sections: &[(String, usize)], current: usize, body_l
         .unwrap_or(body_len)
 }
 
-/// Parse ICAP request
-pub fn parse_icap_request(input: &str) -> Result<IcapRequest, IcapError> {
-    let (rem, (method, uri_s, version_s)) = parse_icap_request_line(input)
-        .map_err(|e| IcapError::protocol_error(&format!("Bad request line: {:?}", e), "PARSER"))?;
-    let uri = uri_s.parse::<Uri>()
-        .map_err(|e| IcapError::protocol_error(&format!("Invalid URI: {}", e), "PARSER"))?;
-    let version = match version_s.as_str() {
-        "ICAP/1.0" => Version::HTTP_11, // ICAP/1.0 maps to HTTP/1.1 for compatibility
-        _ => return Err(IcapError::protocol_error(&format!("Unsupported version: {}", version_s), "PARSER")),
-    };
-    
-    let idx = rem.find("\r\n\r\n")
-        .ok_or_else(|| IcapError::protocol_error("Missing header terminator", "PARSER"))?;
-    let (hdrs_str, body_str) = rem.split_at(idx + 4);
-    let (_, kvs) = parse_headers(hdrs_str)
-        .map_err(|e| IcapError::protocol_error(&format!("Header parse failure: {:?}", e), "PARSER"))?;
-    
+/// Build the header map of a message
+fn header_map(fields: Vec<Field<'_>>, limits: &ProtocolLimits) -> Result<HeaderMap, IcapError> {
+    limits.check_header_count(fields.len())?;
     let mut headers = HeaderMap::new();
-    for (k, v) in kvs {
-        let name = HeaderName::from_bytes(k.as_bytes()).map_err(|_| IcapError::protocol_error("Bad header name", "PARSER"))?;
-        let val = HeaderValue::from_str(&v).map_err(|_| IcapError::protocol_error("Bad header value", "PARSER"))?;
-            headers.insert(name, val);
+    for (k, v) in fields {
+        let name = HeaderName::from_bytes(k).map_err(|_| IcapError::protocol_error("Bad header name", "PARSER"))?;
+        let val = HeaderValue::from_bytes(v).map_err(|_| IcapError::protocol_error("Bad header value", "PARSER"))?;
+        headers.insert(name, val);
     }
+    Ok(headers)
+}
 
-    // Required header checks
-    if !headers.contains_key("host") {
-        return Err(IcapError::protocol_error("Host header required", "PARSER"));
+/// Start line and header section of a message
+struct Head<T> {
+    line: T,
+    headers: HeaderMap,
+    len: usize,
+}
+
+/// Parse the start line and header section of a partially received message
+///
+/// Returns `None` until the header section is complete, checking the received
+/// part against the limits meanwhile.
+fn parse_head<'a, T>(
+    input: &'a [u8],
+    limits: &ProtocolLimits,
+    parse_line: impl Fn(&'a [u8]) -> IResult<&'a [u8], T>,
+) -> Result<Option<Head<T>>, IcapError> {
+    let incomplete = || -> Result<Option<Head<T>>, IcapError> {
+        limits.check_partial_header(input)?;
+        Ok(None)
+    };
+    let (rem, line) = match parse_line(input) {
+        Ok(r) => r,
+        Err(Err::Incomplete(_)) => return incomplete(),
+        Err(e) => return Err(IcapError::protocol_error(format!("Bad start line: {:?}", e), "PARSER")),
+    };
+    let line_len = input.len() - rem.len();
+    limits.check_request_line(line_len - 2)?;
+    let (rest, fields) = match parse_header_lines(rem) {
+        Ok(r) => r,
+        Err(Err::Incomplete(_)) => return incomplete(),
+        Err(e) => return Err(IcapError::protocol_error(format!("Header parse failure: {:?}", e), "PARSER")),
+    };
+    limits.check_header_bytes(rem.len() - rest.len())?;
+    Ok(Some(Head {
+        line,
+        headers: header_map(fields, limits)?,
+        len: input.len() - rest.len(),
+    }))
+}
+
+/// Get the sections of the Encapsulated header
+///
+/// In strict mode they are checked by the caller, which knows the method,
+/// in lenient mode they are sorted by offset.
+fn encapsulated_sections(headers: &HeaderMap, limits: &ProtocolLimits) -> Result<Vec<(String, usize)>, IcapError> {
+    let enc_hdr = headers.get("encapsulated").ok_or(InvalidEncapsulated::Missing)?;
+    let (rest, mut sections) = parse_encapsulated_header(enc_hdr.as_bytes())
+        .map_err(|_| InvalidEncapsulated::Syntax)?;
+    limits.check_encapsulated_sections(sections.len())?;
+    if limits.strict_encapsulated {
+        if !rest.trim_ascii().is_empty() {
+            return Err(InvalidEncapsulated::Syntax.into());
+        }
+    } else {
+        encapsulated::make_lenient(&mut sections);
+    }
+    Ok(sections)
+}
+
+/// Request line and header section of an ICAP request
+#[derive(Debug, Clone)]
+pub struct RequestHead {
+    /// ICAP method
+    pub method: IcapMethod,
+    /// Request URI
+    pub uri: Uri,
+    /// ICAP version
+    pub version: Version,
+    /// Request headers
+    pub headers: HeaderMap,
+    sections: Vec<(String, usize)>,
+    len: usize,
+}
+
+impl RequestHead {
+    /// Size of the request line and header section
+    pub fn size(&self) -> usize {
+        self.len
     }
-    let enc_hdr = headers.get("encapsulated")
-        .ok_or_else(|| IcapError::protocol_error("Encapsulated header required", "PARSER"))?;
-    let enc_str = enc_hdr.to_str()
-        .map_err(|_| IcapError::protocol_error("Invalid encapsulated value", "PARSER"))?;
-    let (_, sections) = parse_encapsulated_header(enc_str)
-        .map_err(|e| IcapError::protocol_error(&format!("Encap parse error: {:?}", e), "PARSER"))?;
-
-    // Offsets must increase
-    for w in sections.windows(2) {
-        if w[1].1 <= w[0].1 {
-            return Err(IcapError::protocol_error("Encap offsets not increasing", "PARSER"));
+
+    /// Check that an OPTIONS request carries no body
+    ///
+    /// `received` is the size of the message received so far, the head
+    /// included.
+    pub fn check_options(&self, received: usize) -> Result<(), InvalidEncapsulated> {
+        let sections = self
+            .headers
+            .contains_key("encapsulated")
+            .then_some(self.sections.as_slice());
+        encapsulated::check_options(sections, received.saturating_sub(self.len))
+    }
+
+    /// Complete the request with the whole message
+    ///
+    /// `message` starts with the head, which is not parsed again, and the
+    /// body and its raw sections are slices of it.
+    pub fn into_request(self, message: Bytes, limits: &ProtocolLimits) -> Result<IcapRequest, IcapError> {
+        if self.method == IcapMethod::Options {
+            if limits.strict_encapsulated {
+                self.check_options(message.len())?;
+            }
+            // whatever was sent along is ignored in lenient mode
+            return Ok(IcapRequest {
+                method: self.method,
+                uri: self.uri,
+                version: self.version,
+                headers: self.headers,
+                body: Bytes::new(),
+                encapsulated: None,
+            });
+        }
+
+        let body = message.slice(self.len..);
+        limits.check_encapsulated_size(body.len())?;
+        if limits.strict_encapsulated {
+            encapsulated::check_bounds(&self.sections, body.len())?;
+        }
+
+        // Body must be chunked
+        if !has_chunked_body(&self.sections, &body) && !self.sections.iter().any(|(t, _)| t == "null-body") {
+            return Err(IcapError::protocol_error("Chunked encoding required", "PARSER"));
         }
+
+        // Parse encapsulated data
+        let encapsulated = Some(parse_encapsulated_data(&self.sections, &body, limits)?);
+
+        Ok(IcapRequest {
+            method: self.method,
+            uri: self.uri,
+            version: self.version,
+            headers: self.headers,
+            body,
+            encapsulated,
+        })
     }
+}
 
-    // Body must be chunked
-    let body_bytes = body_str.as_bytes();
-    if !is_chunked_data(body_bytes) && !sections.iter().any(|(t, _)| t == "null-body") {
-        return Err(IcapError::protocol_error("Chunked encoding required", "PARSER"));
+/// Parse the request line and header section of a partially received request
+///
+/// Returns `None` until the header section is complete.
+pub fn parse_request_head(input: &[u8], limits: &ProtocolLimits) -> Result<Option<RequestHead>, IcapError> {
+    let Some(head) = parse_head(input, limits, parse_icap_request_line)? else {
+        return Ok(None);
+    };
+    let (method, uri_s, version_s) = head.line;
+    let uri = Uri::try_from(uri_s)
+        .map_err(|e| IcapError::protocol_error(format!("Invalid URI: {}", e), "PARSER"))?;
+    let version = match version_s {
+        b"ICAP/1.0" => Version::HTTP_11, // ICAP/1.0 maps to HTTP/1.1 for compatibility
+        _ => return Err(IcapError::protocol_error(
+            format!("Unsupported version: {}", String::from_utf8_lossy(version_s)),
+            "PARSER",
+        )),
+    };
+
+    // Required header checks
+    let headers = head.headers;
+    if !headers.contains_key("host") {
+        return Err(IcapError::protocol_error("Host header required", "PARSER"));
     }
+    if let Some(preview) = headers.get("preview").and_then(|v| v.to_str().ok()?.trim().parse().ok()) {
+        limits.check_preview_size(preview)?;
+    }
+    let sections = if method == IcapMethod::Options {
+        // an OPTIONS request has no body, its sections are only checked by check_options
+        encapsulated_sections(&headers, limits).unwrap_or_default()
+    } else {
+        let sections = encapsulated_sections(&headers, limits)?;
+        if limits.strict_encapsulated {
+            encapsulated::check_request(&method, &sections)?;
+        }
+        sections
+    };
 
-    // Parse encapsulated data
-    let encapsulated = Some(parse_encapsulated_data(enc_hdr, body_bytes)?);
-    
-    Ok(IcapRequest {
+    Ok(Some(RequestHead {
         method,
         uri,
         version,
         headers,
-        body: Bytes::from(body_bytes.to_vec()),
-        encapsulated,
-    })
+        sections,
+        len: head.len,
+    }))
+}
+
+/// Parse ICAP request
+pub fn parse_icap_request(input: &[u8]) -> Result<IcapRequest, IcapError> {
+    parse_icap_request_with_limits(input, &ProtocolLimits::default())
+}
+
+/// Parse ICAP request, failing if it exceeds any of the limits
+pub fn parse_icap_request_with_limits(input: &[u8], limits: &ProtocolLimits) -> Result<IcapRequest, IcapError> {
+    limits.check_message_size(input.len())?;
+    let head = parse_request_head(input, limits)?
+        .ok_or_else(|| IcapError::protocol_error("Missing header terminator", "PARSER"))?;
+    head.into_request(Bytes::copy_from_slice(input), limits)
 }
 
 /// Parse ICAP response
-pub fn parse_icap_response(input: &str) -> Result<IcapResponse, IcapError> {
-    let (rem, (vers, code, _reason)) = parse_icap_status_line(input)
-        .map_err(|e| IcapError::protocol_error(&format!("Bad status line: {:?}", e), "PARSER"))?;
-    let version = match vers.as_str() {
-        "ICAP/1.0" => Version::HTTP_11, // ICAP/1.0 maps to HTTP/1.1 for compatibility
-        _ => return Err(IcapError::protocol_error(&format!("Unsupported version: {}", vers), "PARSER")),
-    };
-    let status = StatusCode::from_u16(code)
-        .map_err(|_| IcapError::protocol_error(&format!("Invalid status code: {}", code), "PARSER"))?;
+pub fn parse_icap_response(input: &[u8]) -> Result<IcapResponse, IcapError> {
+    parse_icap_response_with_limits(input, &ProtocolLimits::default())
+}
 
-    let idx = rem.find("\r\n\r\n")
+/// Parse ICAP response, failing if it exceeds any of the limits
+pub fn parse_icap_response_with_limits(input: &[u8], limits: &ProtocolLimits) -> Result<IcapResponse, IcapError> {
+    limits.check_message_size(input.len())?;
+    let head = parse_head(input, limits, parse_icap_status_line)?
         .ok_or_else(|| IcapError::protocol_error("Missing header terminator", "PARSER"))?;
-    let (hdrs_str, body_str) = rem.split_at(idx + 4);
-    let (_, kvs) = parse_headers(hdrs_str)
-        .map_err(|e| IcapError::protocol_error(&format!("Header parse failure: {:?}", e), "PARSER"))?;
-    
-    let mut headers = HeaderMap::new();
-    for (k, v) in kvs {
-        let name = HeaderName::from_bytes(k.as_bytes()).map_err(|_| IcapError::protocol_error("Bad header name", "PARSER"))?;
-        let val = HeaderValue::from_str(&v).map_err(|_| IcapError::protocol_error("Bad header value", "PARSER"))?;
-            headers.insert(name, val);
-    }
+    let (vers, code, _reason) = head.line;
+    let version = match vers {
+        b"ICAP/1.0" => Version::HTTP_11, // ICAP/1.0 maps to HTTP/1.1 for compatibility
+        _ => return Err(IcapError::protocol_error(
+            format!("Unsupported version: {}", String::from_utf8_lossy(vers)),
+            "PARSER",
+        )),
+    };
+    let status = StatusCode::from_u16(code)
+        .map_err(|_| IcapError::protocol_error(format!("Invalid status code: {}", code), "PARSER"))?;
 
     // Required response headers
+    let headers = head.headers;
     if !headers.contains_key("istag") {
         return Err(IcapError::protocol_error("ISTag required", "PARSER"));
     }
-    let enc_hdr = headers.get("encapsulated")
-        .ok_or_else(|| IcapError::protocol_error("Encapsulated header required", "PARSER"))?;
-    let enc_str = enc_hdr.to_str()
-        .map_err(|_| IcapError::protocol_error("Invalid encapsulated value", "PARSER"))?;
-    let (_, sections) = parse_encapsulated_header(enc_str)
-        .map_err(|e| IcapError::protocol_error(&format!("Encap parse error: {:?}", e), "PARSER"))?;
-    for w in sections.windows(2) {
-        if w[1].1 <= w[0].1 {
-            return Err(IcapError::protocol_error("Encap offsets not increasing", "PARSER"));
-        }
+    let sections = encapsulated_sections(&headers, limits)?;
+    let body = Bytes::copy_from_slice(&input[head.len..]);
+    limits.check_encapsulated_size(body.len())?;
+    if limits.strict_encapsulated {
+        encapsulated::check_response(&sections)?;
+        encapsulated::check_bounds(&sections, body.len())?;
     }
-    let body_bytes = body_str.as_bytes();
-    if !is_chunked_data(body_bytes) && !sections.iter().any(|(t, _)| t == "null-body") {
+    if !has_chunked_body(&sections, &body) && !sections.iter().any(|(t, _)| t == "null-body") {
         return Err(IcapError::protocol_error("Chunked encoding required", "PARSER"));
     }
 
-    let encapsulated = Some(parse_encapsulated_data(enc_hdr, body_bytes)?);
-    
+    let encapsulated = Some(parse_encapsulated_data(&sections, &body, limits)?);
+
     Ok(IcapResponse {
         status,
         version,
         headers,
-        body: Bytes::from(body_bytes.to_vec()),
+        body,
         encapsulated,
     })
 }
 
+/// Check the chunked transfer-coding of the body section, which follows the encapsulated headers
+fn has_chunked_body(sections: &[(String, usize)], body: &[u8]) -> bool {
+    let body_offset = sections
+        .iter()
+        .find(|(t, _)| matches!(t.as_str(), "req-body" | "res-body" | "opt-body"))
+        .map(|(_, off)| *off)
+        .unwrap_or(0);
+    is_chunked_data(body.get(body_offset..).unwrap_or_default())
+}
+
 /// Check chunked transfer-coding
 fn is_chunked_data(data: &[u8]) -> bool {
-    if let Some(pos) = data.windows(2).position(|w| w == b"\r\n") {
-        if pos > 0 && pos < 20 {
-            if let Ok(line) = std::str::from_utf8(&data[..pos]) {
-                return line.chars().all(|c| c.is_ascii_hexdigit());
-            }
-        }
+    match data.windows(2).position(|w| w == b"\r\n") {
+        Some(pos) if pos > 0 && pos < 20 => crate::protocol::chunked::is_chunk_size_line(&data[..pos]),
+        _ => false,
     }
-    false
 }
 
-/// Parse chunked body (delegates to chunked parser)
-fn parse_chunked_body(data: &[u8]) -> Result<Bytes, IcapError> {
-    use crate::protocol::chunked::ChunkedParser;
-    let mut p = ChunkedParser::new();
-    let (decoded, _consumed) =
-        p.parse_chunk(data).map_err(|e| IcapError::protocol_error(&e.to_string(), "CHUNKED"))?;
+/// Decode a body section if it is chunked, with its trailer fields and `ieof` flag
+fn parse_body_section(slice: Bytes, limits: &ProtocolLimits) -> Result<(Bytes, Option<HeaderMap>, bool), IcapError> {
+    if !is_chunked_data(&slice) {
+        return Ok((slice, None, false));
+    }
+    let (data, mut parser) = parse_chunked_body(&slice, limits)?;
+    let trailers = Some(parser.take_trailers()).filter(|t| !t.is_empty());
+    Ok((data, trailers, parser.is_ieof()))
+}
+
+/// Parse chunked body (delegates to chunked parser), returning the parser holding the trailers
+fn parse_chunked_body(data: &[u8], limits: &ProtocolLimits) -> Result<(Bytes, ChunkedParser), IcapError> {
+    use crate::protocol::chunked::ChunkedParseError;
+    let mut p = ChunkedParser::with_max_chunk_size(limits.max_chunk_size);
+    let (decoded, _consumed) = p.parse_chunk(data).map_err(|e| match e {
+        ChunkedParseError::ChunkSizeTooLarge(size) => IcapError::from(LimitExceeded::ChunkSize {
+            size,
+            limit: limits.max_chunk_size,
+        }),
+        e => IcapError::protocol_error(e.to_string(), "CHUNKED"),
+    })?;
     if !p.is_complete() {
         return Err(IcapError::protocol_error("Incomplete chunked data", "CHUNKED"));
     }
-    Ok(Bytes::from(decoded))
+    Ok((Bytes::from(decoded), p))
 }
 
-/// Parse and split encapsulated data sections
-fn parse_encapsulated_data(header: &HeaderValue, body: &[u8]) -> Result<EncapsulatedData, IcapError> {
-    let s = header.to_str()
-        .map_err(|_| IcapError::protocol_error("Bad encapsulated header", "PARSER"))?;
-    let (_, sections) = parse_encapsulated_header(s)
-        .map_err(|e| IcapError::protocol_error(&format!("Encap parse error: {:?}", e), "PARSER"))?;
-
+/// Split the encapsulated data in its sections
+///
+/// Chunked bodies are decoded, the other sections are slices of `body`.
+fn parse_encapsulated_data(sections: &[(String, usize)], body: &Bytes, limits: &ProtocolLimits) -> Result<EncapsulatedData, IcapError> {
     let mut req_hdr = None;
     let mut res_hdr = None;
     let mut req_body = None;
     let mut res_body = None;
     let mut null_body = false;
-    
-    for (typ, off) in &sections {
-        let end = find_next_section_offset(&sections, *off, body.len());
+    let mut trailers = None;
+    let mut ieof = false;
+    let mut req_line = None;
+    let mut status_line = None;
+
+    for (typ, off) in sections {
+        let end = find_next_section_offset(sections, *off, body.len()).min(body.len());
         match typ.as_str() {
             "req-hdr" if *off < end => {
-                req_hdr = Some(parse_http_headers(&body[*off..end])?);
+                req_hdr = Some(parse_http_headers(&body[*off..end], limits)?);
+                let line = start_line::first_line(&body[*off..end])
+                    .and_then(start_line::RequestLine::parse)
+                    .ok_or_else(|| IcapError::protocol_error("Invalid HTTP request line", "PARSER"))?;
+                req_line = Some(line);
             }
             "res-hdr" if *off < end => {
-                res_hdr = Some(parse_http_headers(&body[*off..end])?);
+                res_hdr = Some(parse_http_headers(&body[*off..end], limits)?);
+                let line = start_line::first_line(&body[*off..end])
+                    .and_then(StatusLine::parse)
+                    .ok_or_else(|| IcapError::protocol_error("Invalid HTTP status line", "PARSER"))?;
+                status_line = Some(line);
             }
-            "req-body" if *off < body.len() => {
-                let slice = if end <= body.len() { &body[*off..end] } else { &body[*off..] };
-                req_body = Some(if is_chunked_data(slice) { parse_chunked_body(slice)? } else { Bytes::from(slice.to_vec()) });
+            "req-body" if *off < end => {
+                let (data, t, i) = parse_body_section(body.slice(*off..end), limits)?;
+                (req_body, trailers, ieof) = (Some(data), t, i);
             }
             "res-body" if *off < body.len() => {
-                let slice = &body[*off..];
-                res_body = Some(if is_chunked_data(slice) { parse_chunked_body(slice)? } else { Bytes::from(slice.to_vec()) });
+                let (data, t, i) = parse_body_section(body.slice(*off..), limits)?;
+                (res_body, trailers, ieof) = (Some(data), t, i);
             }
             "null-body" => null_body = true,
             _ => {}
         }
     }
-    
+
     Ok(EncapsulatedData {
         req_hdr,
         res_hdr,
         req_body,
         res_body,
         null_body,
+        trailers,
+        ieof,
+        req_line,
+        status_line,
     })
 }
 
-/// Parse HTTP headers from byte slice
-fn parse_http_headers(data: &[u8]) -> Result<HeaderMap, IcapError> {
+/// Parse the header fields of an encapsulated HTTP header section
+///
+/// The request or status line is skipped, and fields that are not valid
+/// HTTP fields are ignored.
+fn parse_http_headers(data: &[u8], limits: &ProtocolLimits) -> Result<HeaderMap, IcapError> {
     let mut map = HeaderMap::new();
     if data.is_empty() {
         return Ok(map);
     }
-    let s = std::str::from_utf8(data)
-        .map_err(|e| IcapError::protocol_error(&format!("Invalid UTF-8: {}", e), "PARSER"))?;
-    let (_, kvs) = parse_headers(s)
-        .map_err(|e| IcapError::protocol_error(&format!("HTTP header parse failure: {:?}", e), "PARSER"))?;
+    limits.check_header_bytes(data.len())?;
+    let fields = match data.windows(2).position(|w| w == b"\r\n") {
+        Some(line_end) => &data[line_end + 2..],
+        None => return Err(IcapError::protocol_error("Missing HTTP start line terminator", "PARSER")),
+    };
+    let (_, kvs) = parse_header_lines(fields)
+        .map_err(|e| IcapError::protocol_error(format!("HTTP header parse failure: {:?}", e), "PARSER"))?;
+    limits.check_header_count(kvs.len())?;
     for (k, v) in kvs {
-        if let Ok(name) = HeaderName::from_bytes(k.as_bytes()) {
-            if let Ok(val) = HeaderValue::from_str(&v) {
-                map.insert(name, val);
-            }
+        if let (Ok(name), Ok(val)) = (HeaderName::from_bytes(k), HeaderValue::from_bytes(v)) {
+            map.insert(name, val);
         }
     }
     Ok(map)
@@ -293,20 +507,140 @@ fn parse_http_headers(data: &[u8]) -> Result<HeaderMap, IcapError> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    
+
     #[test]
     fn test_parse_icap_request_minimal() {
-        let msg = "REQMOD icap://ex/s ICAP/1.0\r\nHost: ex\r\nEncapsulated: null-body=0\r\n\r\n";
+        let msg = b"REQMOD icap://ex/s ICAP/1.0\r\nHost: ex\r\nEncapsulated: null-body=0\r\n\r\n";
         let req = parse_icap_request(msg).unwrap();
         assert_eq!(req.method, IcapMethod::Reqmod);
         assert!(req.encapsulated.unwrap().null_body);
     }
-    
+
     #[test]
     fn test_parse_icap_response_minimal() {
-        let msg = "ICAP/1.0 204 No Content\r\nISTag: \"T\"\r\nEncapsulated: null-body=0\r\n\r\n";
+        let msg = b"ICAP/1.0 204 No Content\r\nISTag: \"T\"\r\nEncapsulated: null-body=0\r\n\r\n";
         let res = parse_icap_response(msg).unwrap();
         assert_eq!(res.status, StatusCode::NO_CONTENT);
         assert!(res.encapsulated.unwrap().null_body);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parse_request_head_incrementally() {
+        let msg: &[u8] = b"RESPMOD icap://ex/av ICAP/1.0\r\nHost: ex\r\nEncapsulated: res-hdr=0, res-body=38\r\n\r\n\
+                           HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\n\
+                           4\r\n\x00\xff\xfe\x80\r\n0\r\n\r\n";
+        let limits = ProtocolLimits::default();
+        for end in [0, 10, 31, 60] {
+            assert!(parse_request_head(&msg[..end], &limits).unwrap().is_none());
+        }
+        let head = parse_request_head(&msg[..90], &limits).unwrap().unwrap();
+        assert_eq!(head.method, IcapMethod::Respmod);
+        assert_eq!(head.size(), 81);
+
+        // the binary body is a slice of the message
+        let message = Bytes::from_static(msg);
+        let req = head.into_request(message.clone(), &limits).unwrap();
+        assert_eq!(req.body.as_ptr(), message[81..].as_ptr());
+        let enc = req.encapsulated.unwrap();
+        assert_eq!(enc.res_hdr.unwrap().get("content-length").unwrap(), "4");
+        assert_eq!(enc.res_body.unwrap().as_ref(), b"\x00\xff\xfe\x80");
+    }
+
+    #[test]
+    fn test_parse_request_head_errors() {
+        let limits = ProtocolLimits::default();
+        assert!(parse_request_head(b"FETCH icap://ex/s ICAP/1.0\r\n", &limits).is_err());
+        assert!(parse_request_head(b"REQMOD icap://ex/s ICAP/1.0\r\nNo colon\r\n\r\n", &limits).is_err());
+        let limits = ProtocolLimits {
+            max_request_line: 16,
+            ..Default::default()
+        };
+        let e = parse_request_head(b"REQMOD icap://ex/service", &limits).unwrap_err();
+        assert_eq!(e.limit_exceeded(), Some(&LimitExceeded::RequestLine { limit: 16 }));
+    }
+
+    #[test]
+    fn test_trailers_and_ieof() {
+        let msg = b"RESPMOD icap://ex/av ICAP/1.0\r\nHost: ex\r\nPreview: 5\r\nEncapsulated: res-hdr=0, res-body=19\r\n\r\n\
+                    HTTP/1.1 200 OK\r\n\r\n\
+                    5; x=y\r\nhello\r\n0; ieof\r\nX-Digest: abc\r\n\r\n";
+        let req = parse_icap_request(msg).unwrap();
+        assert!(req.is_ieof());
+        assert_eq!(req.trailers().unwrap().get("x-digest").unwrap(), "abc");
+        assert_eq!(req.encapsulated.unwrap().res_body.unwrap().as_ref(), b"hello");
+    }
+
+    #[test]
+    fn test_strict_encapsulated() {
+        let strict = ProtocolLimits::default();
+        let lenient = ProtocolLimits {
+            strict_encapsulated: false,
+            ..Default::default()
+        };
+
+        // a response header in a REQMOD request
+        let msg = b"REQMOD icap://ex/s ICAP/1.0\r\nHost: ex\r\nEncapsulated: res-hdr=0, null-body=19\r\n\r\n\
+                    HTTP/1.1 200 OK\r\n\r\n";
+        let e = parse_icap_request_with_limits(msg, &strict).unwrap_err();
+        assert_eq!(
+            e.invalid_encapsulated(),
+            Some(&InvalidEncapsulated::NotAllowed {
+                section: "res-hdr".to_string(),
+                method: "REQMOD",
+            })
+        );
+        let req = parse_icap_request_with_limits(msg, &lenient).unwrap();
+        assert!(req.encapsulated.unwrap().null_body);
+
+        // the body section past the end of the message
+        let msg = b"REQMOD icap://ex/s ICAP/1.0\r\nHost: ex\r\nEncapsulated: req-hdr=0, null-body=40\r\n\r\n\
+                    GET / HTTP/1.1\r\nHost: ex\r\n\r\n";
+        let e = parse_icap_request_with_limits(msg, &strict).unwrap_err();
+        assert_eq!(
+            e.invalid_encapsulated(),
+            Some(&InvalidEncapsulated::OutOfBounds {
+                section: "null-body".to_string(),
+                offset: 40,
+                len: 28,
+            })
+        );
+        let req = parse_icap_request_with_limits(msg, &lenient).unwrap();
+        assert_eq!(req.encapsulated.unwrap().req_hdr.unwrap().get("host").unwrap(), "ex");
+
+        // trailing garbage
+        let msg = b"REQMOD icap://ex/s ICAP/1.0\r\nHost: ex\r\nEncapsulated: null-body=0; x\r\n\r\n";
+        let e = parse_icap_request_with_limits(msg, &strict).unwrap_err();
+        assert_eq!(e.invalid_encapsulated(), Some(&InvalidEncapsulated::Syntax));
+        assert!(parse_icap_request_with_limits(msg, &lenient).is_ok());
+    }
+
+    #[test]
+    fn test_options_body() {
+        let strict = ProtocolLimits::default();
+        let lenient = ProtocolLimits {
+            strict_encapsulated: false,
+            ..Default::default()
+        };
+
+        for msg in [
+            &b"OPTIONS icap://ex/s ICAP/1.0\r\nHost: ex\r\n\r\n"[..],
+            b"OPTIONS icap://ex/s ICAP/1.0\r\nHost: ex\r\nEncapsulated: null-body=0\r\n\r\n",
+        ] {
+            let req = parse_icap_request_with_limits(msg, &strict).unwrap();
+            assert_eq!(req.method, IcapMethod::Options);
+            assert!(req.encapsulated.is_none());
+        }
+
+        for msg in [
+            &b"OPTIONS icap://ex/s ICAP/1.0\r\nHost: ex\r\nEncapsulated: opt-body=0\r\n\r\n5\r\nhello\r\n0\r\n\r\n"[..],
+            b"OPTIONS icap://ex/s ICAP/1.0\r\nHost: ex\r\nEncapsulated: bogus\r\n\r\n",
+            b"OPTIONS icap://ex/s ICAP/1.0\r\nHost: ex\r\n\r\n0\r\n\r\n",
+        ] {
+            let e = parse_icap_request_with_limits(msg, &strict).unwrap_err();
+            assert_eq!(e.invalid_encapsulated(), Some(&InvalidEncapsulated::OptionsBody));
+            let req = parse_icap_request_with_limits(msg, &lenient).unwrap();
+            assert!(req.body.is_empty());
+            assert!(req.encapsulated.is_none());
+        }
+    }
+}
@@ -0,0 +1,99 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Partial adaptation of a previewed message, the 206 response
+//!
+//! A client sending `Allow: 206` with a preview lets the server return only
+//! the start of the adapted body: the last chunk of a 206 Partial Content
+//! response carries a `use-original-body=N` extension, and the client
+//! appends its original body from the offset N. It is used when the
+//! adaptation only changed the HTTP header section or the previewed part of
+//! the body, so that the rest of the body is not sent back.
+//!
+//! The encapsulated header section announces the length of the whole
+//! adapted body, the partial body followed by the original one from N.
+
+use bytes::Bytes;
+
+/// Extension of the last chunk telling where the original body resumes
+pub const USE_ORIGINAL_BODY: &str = "use-original-body";
+
+/// Adapted body made of a new start followed by the original body
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialBody {
+    /// Start of the adapted body
+    pub data: Bytes,
+    /// Offset of the original body the adapted body goes on from
+    pub original_offset: usize,
+    /// Length of the whole adapted body
+    pub total_len: usize,
+}
+
+impl PartialBody {
+    /// Split the adapted body if it differs from the original one in the preview only
+    ///
+    /// The longest end common to both bodies is left to the client. Returns
+    /// `None` if a byte after the preview changed, or if nothing would be
+    /// left to the client.
+    pub fn split(original: &[u8], adapted: &[u8], preview: usize) -> Option<Self> {
+        let common = original
+            .iter()
+            .rev()
+            .zip(adapted.iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let original_offset = original.len() - common;
+        if common == 0 || original_offset > preview {
+            return None;
+        }
+        Some(PartialBody {
+            data: Bytes::copy_from_slice(&adapted[..adapted.len() - common]),
+            original_offset,
+            total_len: adapted.len(),
+        })
+    }
+
+    /// Encode the start of the body, the last chunk telling where the original body resumes
+    pub fn encode(&self) -> Bytes {
+        crate::protocol::chunked::encode_partial(&self.data, self.original_offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split() {
+        let original = b"<html>secret token</html> and the rest of the page";
+        let adapted = b"<html>[redacted]</html> and the rest of the page";
+        let partial = PartialBody::split(original, adapted, 32).unwrap();
+        assert_eq!(partial.data.as_ref(), b"<html>[redacted]");
+        assert_eq!(partial.original_offset, 18);
+        assert_eq!(partial.total_len, adapted.len());
+        let mut joined = partial.data.to_vec();
+        joined.extend_from_slice(&original[partial.original_offset..]);
+        assert_eq!(joined, adapted);
+
+        // changed after the preview
+        assert!(PartialBody::split(original, adapted, 16).is_none());
+        // nothing in common
+        assert!(PartialBody::split(b"abc", b"xyz", 1024).is_none());
+
+        // only the header section changed
+        let partial = PartialBody::split(original, original, 0).unwrap();
+        assert!(partial.data.is_empty());
+        assert_eq!(partial.original_offset, 0);
+    }
+
+    #[test]
+    fn encode() {
+        let partial = PartialBody::split(b"hello world", b"HELLO world", 8).unwrap();
+        assert_eq!(
+            partial.encode().as_ref(),
+            b"5\r\nHELLO\r\n0; use-original-body=5\r\n\r\n"
+        );
+    }
+}
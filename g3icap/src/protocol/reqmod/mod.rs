@@ -116,6 +116,10 @@ mod tests {
                 req_body: None,
                 res_body: None,
                 null_body: true,
+                trailers: None,
+                ieof: false,
+                req_line: None,
+                status_line: None,
             }),
         };
 
@@ -33,6 +33,10 @@ impl RespmodHandler for DefaultRespmodHandler {
                 res_hdr: None,
                 res_body: None,
                 null_body: true,
+                trailers: None,
+                ieof: false,
+                req_line: None,
+                status_line: None,
             }),
         })
     }
@@ -7,9 +7,11 @@ use std::collections::HashMap;
 use std::fmt;
 
 use bytes::Bytes;
-use http::{HeaderMap, StatusCode, Version};
+use http::{HeaderMap, HeaderName, HeaderValue, StatusCode, Version};
 
 use crate::protocol::common::{EncapsulatedData, IcapMethod, IcapResponse};
+use crate::protocol::framing::{self, BodyFraming};
+use crate::protocol::partial::PartialBody;
 
 /// Preview analysis result for ICAP preview requests
 /// RFC 3507: Preview allows servers to examine content before processing
@@ -141,10 +143,11 @@ impl IcapResponseGenerator {
     }
 
     /// Generate a 200 OK response with modified content
-    pub fn ok_modified(&self, encapsulated: Option<EncapsulatedData>, body: Bytes) -> IcapResponse {
+    pub fn ok_modified(&self, mut encapsulated: Option<EncapsulatedData>, body: Bytes) -> IcapResponse {
         let mut headers = self.build_standard_headers();
         
-        if let Some(enc) = &encapsulated {
+        if let Some(enc) = &mut encapsulated {
+            framing::fix_encapsulated(enc);
             let encapsulated_header = self.serialize_encapsulated_header(enc);
             headers.insert("encapsulated", encapsulated_header.parse().unwrap());
         }
@@ -159,10 +162,11 @@ impl IcapResponseGenerator {
 
     /// Generate a 200 OK response with chunked transfer encoding
     /// RFC 3507: All encapsulated HTTP bodies MUST use chunked transfer encoding
-    pub fn ok_modified_chunked(&self, encapsulated: Option<EncapsulatedData>, body: Bytes) -> IcapResponse {
+    pub fn ok_modified_chunked(&self, mut encapsulated: Option<EncapsulatedData>, body: Bytes) -> IcapResponse {
         let mut headers = self.build_standard_headers();
         
-        if let Some(enc) = &encapsulated {
+        if let Some(enc) = &mut encapsulated {
+            framing::fix_encapsulated(enc);
             let encapsulated_header = self.serialize_encapsulated_header_chunked(enc);
             headers.insert("encapsulated", encapsulated_header.parse().unwrap());
         }
@@ -475,6 +479,28 @@ impl IcapResponseGenerator {
         }
     }
 
+    /// Generate a 401 Unauthorized response
+    pub fn unauthorized(&self, challenge: &str) -> IcapResponse {
+        let mut headers = self.build_standard_headers();
+
+        // RFC 3507: Add required Encapsulated header for error responses
+        self.add_null_body_header(&mut headers);
+
+        // Add connection close for error responses
+        headers.insert("connection", "close".parse().unwrap());
+        if let Ok(value) = challenge.parse() {
+            headers.insert("www-authenticate", value);
+        }
+
+        IcapResponse {
+            status: StatusCode::UNAUTHORIZED,
+            version: Version::HTTP_11,
+            headers,
+            body: Bytes::from(self.format_error_message(StatusCode::UNAUTHORIZED, "Unauthorized")),
+            encapsulated: None,
+        }
+    }
+
     /// Generate a 409 Conflict response
     pub fn conflict(&self, reason: Option<&str>) -> IcapResponse {
         let mut headers = self.build_standard_headers();
@@ -895,6 +921,10 @@ impl IcapResponseGenerator {
             req_body: req_body.map(|body| self.encode_encapsulated_body_chunked(&body)),
             res_body: res_body.map(|body| self.encode_encapsulated_body_chunked(&body)),
             null_body: false,
+            trailers: None,
+            ieof: false,
+            req_line: None,
+            status_line: None,
         }
     }
 
@@ -1066,6 +1096,7 @@ impl IcapResponseGenerator {
             StatusCode::CONTINUE => "Continue",
             StatusCode::OK => "OK",
             StatusCode::NO_CONTENT => "No Content",
+            StatusCode::PARTIAL_CONTENT => "Partial Content",
             StatusCode::NOT_MODIFIED => "Not Modified",
             StatusCode::BAD_REQUEST => "Bad Request",
             StatusCode::FORBIDDEN => "Forbidden",
@@ -1197,6 +1228,98 @@ impl IcapResponseGenerator {
         }
     }
 
+    /// Create a 200 OK response encapsulating an HTTP error page
+    ///
+    /// Unlike the ICAP error responses, the page is shown by the browser of
+    /// the end user, e.g. a block page.
+    pub fn http_page_response(&self, http_status: StatusCode, html: &str) -> IcapResponse {
+        self.http_page_response_with_headers(http_status, html, &[])
+    }
+
+    /// Create a 200 OK response encapsulating an HTTP page with extra headers
+    ///
+    /// The extra headers, e.g. the Location of a redirection, follow the
+    /// headers of the page.
+    pub fn http_page_response_with_headers(
+        &self,
+        http_status: StatusCode,
+        html: &str,
+        extra_headers: &[(HeaderName, HeaderValue)],
+    ) -> IcapResponse {
+        let mut http_header = format!(
+            "HTTP/1.1 {} {}\r\n\
+             Content-Type: text/html; charset=utf-8\r\n\
+             Content-Length: {}\r\n\
+             Cache-Control: no-store\r\n\
+             Connection: close\r\n",
+            http_status.as_str(),
+            http_status.canonical_reason().unwrap_or("Unknown"),
+            html.len()
+        );
+        for (name, value) in extra_headers {
+            http_header.push_str(&format!("{}: {}\r\n", name, String::from_utf8_lossy(value.as_bytes())));
+        }
+        http_header.push_str("\r\n");
+
+        let mut headers = self.build_standard_headers();
+        headers.insert(
+            "encapsulated",
+            format!("res-hdr=0, res-body={}", http_header.len()).parse().unwrap(),
+        );
+
+        let chunked_body = crate::protocol::chunked::encode_chunked(html.as_bytes());
+        let mut body = Vec::with_capacity(http_header.len() + chunked_body.len());
+        body.extend_from_slice(http_header.as_bytes());
+        body.extend_from_slice(&chunked_body);
+
+        IcapResponse {
+            status: StatusCode::OK,
+            version: Version::HTTP_11,
+            headers,
+            body: Bytes::from(body),
+            encapsulated: None,
+        }
+    }
+
+    /// Create a 206 Partial Content response, the client going on with its original body
+    ///
+    /// The start line and the headers are the ones of the adapted HTTP
+    /// message, which announce the length of the whole adapted body.
+    pub fn partial_content(
+        &self,
+        method: &IcapMethod,
+        start_line: &str,
+        mut http_headers: HeaderMap,
+        partial: &PartialBody,
+    ) -> IcapResponse {
+        framing::fix_http_headers(&mut http_headers, BodyFraming::ContentLength(partial.total_len));
+        let section = if *method == IcapMethod::Reqmod {
+            framing::strip_expect_continue(&mut http_headers);
+            "req"
+        } else {
+            "res"
+        };
+
+        let mut http_header = format!("{}\r\n", start_line);
+        for (name, value) in &http_headers {
+            http_header.push_str(&format!("{}: {}\r\n", name, String::from_utf8_lossy(value.as_bytes())));
+        }
+        http_header.push_str("\r\n");
+
+        let mut headers = self.build_standard_headers();
+        headers.insert(
+            "encapsulated",
+            format!("{section}-hdr=0, {section}-body={}", http_header.len()).parse().unwrap(),
+        );
+
+        let chunked_body = partial.encode();
+        let mut body = Vec::with_capacity(http_header.len() + chunked_body.len());
+        body.extend_from_slice(http_header.as_bytes());
+        body.extend_from_slice(&chunked_body);
+
+        self.create_icap_response(StatusCode::PARTIAL_CONTENT, headers, Bytes::from(body), None)
+    }
+
     /// Serialize response to bytes following g3proxy's serialization pattern
     /// RFC 3507: ICAP responses must use ICAP/1.0 in the status line
     pub fn serialize_response(&self, response: &IcapResponse) -> Vec<u8> {
@@ -1207,11 +1330,24 @@ impl IcapResponseGenerator {
         let reason = Self::get_reason_phrase(response.status);
         buf.extend_from_slice(format!("{} {} {}\r\n", ICAP_VERSION.as_str(), response.status.as_str(), reason).as_bytes());
         
+        // A modified encapsulated message may carry a stale Content-Length
+        let fixed = response
+            .headers
+            .get("encapsulated")
+            .filter(|_| response.status == StatusCode::OK && !response.body.is_empty())
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| framing::fix_serialized(v, &response.body));
+
         // Write headers
         for (name, value) in &response.headers {
             buf.extend_from_slice(name.as_str().as_bytes());
             buf.extend_from_slice(b": ");
-            buf.extend_from_slice(value.as_bytes());
+            match &fixed {
+                Some((encapsulated, _)) if name == "encapsulated" => {
+                    buf.extend_from_slice(encapsulated.as_bytes())
+                }
+                _ => buf.extend_from_slice(value.as_bytes()),
+            }
             buf.extend_from_slice(b"\r\n");
         }
         
@@ -1219,8 +1355,9 @@ impl IcapResponseGenerator {
         buf.extend_from_slice(b"\r\n");
         
         // Write body if present
-        if !response.body.is_empty() {
-            buf.extend_from_slice(&response.body);
+        match &fixed {
+            Some((_, body)) => buf.extend_from_slice(body),
+            None => buf.extend_from_slice(&response.body),
         }
         
         buf
@@ -1488,6 +1625,45 @@ mod tests {
         assert!(response.headers.contains_key("server"));
     }
 
+    #[test]
+    fn test_http_page_response() {
+        let generator = IcapResponseGenerator::default();
+        let response = generator.http_page_response(StatusCode::FORBIDDEN, "<p>blocked</p>");
+
+        assert_eq!(response.status, StatusCode::OK);
+        let body = std::str::from_utf8(&response.body).unwrap();
+        let (http_header, http_body) = body.split_once("\r\n\r\n").unwrap();
+        assert!(http_header.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+        assert!(http_header.contains("Content-Length: 14"));
+        assert_eq!(
+            response.headers.get("encapsulated").unwrap().to_str().unwrap(),
+            format!("res-hdr=0, res-body={}", http_header.len() + 4)
+        );
+        assert_eq!(http_body, "e\r\n<p>blocked</p>\r\n0\r\n\r\n");
+    }
+
+    #[test]
+    fn test_partial_content_response() {
+        let generator = IcapResponseGenerator::default();
+        let mut http_headers = HeaderMap::new();
+        http_headers.insert("content-length", "11".parse().unwrap());
+        http_headers.insert("expect", "100-continue".parse().unwrap());
+        let partial = PartialBody::split(b"hello world", b"Hi world", 8).unwrap();
+        let response =
+            generator.partial_content(&IcapMethod::Reqmod, "POST /form HTTP/1.1", http_headers, &partial);
+
+        assert_eq!(response.status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(IcapResponseGenerator::get_reason_phrase(response.status), "Partial Content");
+        let body = std::str::from_utf8(&response.body).unwrap();
+        let (http_header, http_body) = body.split_once("\r\n\r\n").unwrap();
+        assert_eq!(http_header, "POST /form HTTP/1.1\r\ncontent-length: 8");
+        assert_eq!(
+            response.headers.get("encapsulated").unwrap().to_str().unwrap(),
+            format!("req-hdr=0, req-body={}", http_header.len() + 4)
+        );
+        assert_eq!(http_body, "2\r\nHi\r\n0; use-original-body=5\r\n\r\n");
+    }
+
     #[test]
     fn test_no_modifications_response() {
         let generator = IcapResponseGenerator::default();
@@ -1715,6 +1891,10 @@ mod tests {
             req_body: None,
             res_body: Some(body.clone()),
             null_body: false,
+            trailers: None,
+            ieof: false,
+            req_line: None,
+            status_line: None,
         };
         
         let response = generator.create_chunked_response(
@@ -2022,6 +2202,10 @@ mod tests {
             req_body: Some(req_body),
             res_body: Some(res_body),
             null_body: false,
+            trailers: None,
+            ieof: false,
+            req_line: None,
+            status_line: None,
         };
         
         // Test chunked header serialization
@@ -2063,6 +2247,10 @@ mod tests {
             req_body: Some(req_body),
             res_body: Some(res_body),
             null_body: false,
+            trailers: None,
+            ieof: false,
+            req_line: None,
+            status_line: None,
         };
         
         let icap_body = Bytes::from("Modified content");
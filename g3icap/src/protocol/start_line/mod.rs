@@ -0,0 +1,196 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Start lines of the encapsulated HTTP messages
+//!
+//! The req-hdr section of an encapsulated message starts with the HTTP
+//! request line, and the res-hdr section with the status line. They are
+//! parsed along with the header fields, so that the method, the target and
+//! the status of the message are known without going back to the raw
+//! sections.
+
+use std::fmt;
+
+use http::header::HOST;
+use http::{HeaderMap, Method, StatusCode, Version};
+
+/// Request line of an encapsulated HTTP request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestLine {
+    pub method: Method,
+    /// Request target, in origin, absolute or authority form
+    pub target: String,
+    pub version: Version,
+}
+
+impl RequestLine {
+    /// Parse a request line, without its CRLF
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.split(' ');
+        let method = Method::from_bytes(parts.next()?.as_bytes()).ok()?;
+        let target = parts.next().filter(|t| !t.is_empty())?;
+        let version = parse_version(parts.next()?)?;
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(RequestLine {
+            method,
+            target: target.to_string(),
+            version,
+        })
+    }
+
+    /// The URL of the request
+    ///
+    /// A target in origin form is completed with the Host of the header
+    /// section. Other targets are returned as they are.
+    pub fn url(&self, headers: &HeaderMap) -> String {
+        let host = headers.get(HOST).and_then(|v| v.to_str().ok());
+        match host {
+            Some(host) if self.target.starts_with('/') => format!("http://{host}{}", self.target),
+            _ => self.target.clone(),
+        }
+    }
+}
+
+impl fmt::Display for RequestLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.method,
+            self.target,
+            version_str(self.version)
+        )
+    }
+}
+
+/// Status line of an encapsulated HTTP response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusLine {
+    pub version: Version,
+    pub status: StatusCode,
+    /// Reason phrase, which may be empty
+    pub reason: String,
+}
+
+impl StatusLine {
+    /// Parse a status line, without its CRLF
+    pub fn parse(line: &str) -> Option<Self> {
+        let (version, rest) = line.split_once(' ')?;
+        let version = parse_version(version)?;
+        let (code, reason) = rest.split_once(' ').unwrap_or((rest, ""));
+        if code.len() != 3 {
+            return None;
+        }
+        let status = StatusCode::from_bytes(code.as_bytes()).ok()?;
+        Some(StatusLine {
+            version,
+            status,
+            reason: reason.to_string(),
+        })
+    }
+
+    /// Check if the status allows the response to have content
+    pub fn has_body(&self) -> bool {
+        !self.status.is_informational()
+            && self.status != StatusCode::NO_CONTENT
+            && self.status != StatusCode::NOT_MODIFIED
+    }
+}
+
+impl fmt::Display for StatusLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            version_str(self.version),
+            self.status.as_str(),
+            self.reason
+        )
+    }
+}
+
+/// Get the start line of an HTTP header section, without its CRLF
+pub fn first_line(section: &[u8]) -> Option<&str> {
+    let end = section.windows(2).position(|w| w == b"\r\n")?;
+    std::str::from_utf8(&section[..end]).ok()
+}
+
+fn parse_version(version: &str) -> Option<Version> {
+    match version {
+        "HTTP/1.0" => Some(Version::HTTP_10),
+        "HTTP/1.1" => Some(Version::HTTP_11),
+        "HTTP/2" | "HTTP/2.0" => Some(Version::HTTP_2),
+        "HTTP/3" | "HTTP/3.0" => Some(Version::HTTP_3),
+        _ => None,
+    }
+}
+
+fn version_str(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_10 => "HTTP/1.0",
+        Version::HTTP_2 => "HTTP/2",
+        Version::HTTP_3 => "HTTP/3",
+        _ => "HTTP/1.1",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_line() {
+        let line = RequestLine::parse("POST /upload?id=1 HTTP/1.1").unwrap();
+        assert_eq!(line.method, Method::POST);
+        assert_eq!(line.target, "/upload?id=1");
+        assert_eq!(line.version, Version::HTTP_11);
+        assert_eq!(line.to_string(), "POST /upload?id=1 HTTP/1.1");
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(line.url(&headers), "/upload?id=1");
+        headers.insert(HOST, "example.com".parse().unwrap());
+        assert_eq!(line.url(&headers), "http://example.com/upload?id=1");
+
+        let line = RequestLine::parse("GET http://example.net/ HTTP/1.0").unwrap();
+        assert_eq!(line.url(&headers), "http://example.net/");
+        let line = RequestLine::parse("CONNECT example.net:443 HTTP/1.1").unwrap();
+        assert_eq!(line.url(&headers), "example.net:443");
+
+        assert!(RequestLine::parse("GET / ").is_none());
+        assert!(RequestLine::parse("GET / HTTP/1.1 extra").is_none());
+        assert!(RequestLine::parse("GET  HTTP/1.1").is_none());
+        assert!(RequestLine::parse("GET / ICAP/1.0").is_none());
+    }
+
+    #[test]
+    fn status_line() {
+        let line = StatusLine::parse("HTTP/1.1 404 Not Found").unwrap();
+        assert_eq!(line.status, StatusCode::NOT_FOUND);
+        assert_eq!(line.reason, "Not Found");
+        assert_eq!(line.to_string(), "HTTP/1.1 404 Not Found");
+
+        let line = StatusLine::parse("HTTP/1.0 302").unwrap();
+        assert_eq!(line.status, StatusCode::FOUND);
+        assert_eq!(line.version, Version::HTTP_10);
+        assert!(line.reason.is_empty());
+        assert!(line.has_body());
+        assert!(!StatusLine::parse("HTTP/1.1 304 Not Modified").unwrap().has_body());
+
+        assert!(StatusLine::parse("HTTP/1.1 2000 OK").is_none());
+        assert!(StatusLine::parse("HTTP/1.1").is_none());
+        assert!(StatusLine::parse("ICAP/1.0 200 OK").is_none());
+    }
+
+    #[test]
+    fn section_start() {
+        assert_eq!(
+            first_line(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"),
+            Some("HTTP/1.1 200 OK")
+        );
+        assert_eq!(first_line(b"HTTP/1.1 200 OK"), None);
+    }
+}
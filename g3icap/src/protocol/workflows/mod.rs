@@ -276,6 +276,10 @@ impl ReqmodWorkflow {
             res_hdr: None,
             res_body: None,
             null_body: false,
+            trailers: None,
+            ieof: false,
+            req_line: None,
+            status_line: None,
         };
         
         Ok(IcapResponse {
@@ -592,6 +596,10 @@ impl RespmodWorkflow {
             res_hdr: Some(self.create_response_headers(modified_response)?),
             res_body: Some(modified_response.body.clone()),
             null_body: false,
+            trailers: None,
+            ieof: false,
+            req_line: None,
+            status_line: None,
         };
         
         Ok(IcapResponse {
@@ -750,6 +758,10 @@ mod tests {
                 res_hdr: None,
                 res_body: None,
                 null_body: false,
+                trailers: None,
+                ieof: false,
+                req_line: None,
+                status_line: None,
             }),
         };
         
@@ -776,6 +788,10 @@ mod tests {
                 res_hdr: Some(HeaderMap::new()),
                 res_body: Some(Bytes::from("response content")),
                 null_body: false,
+                trailers: None,
+                ieof: false,
+                req_line: None,
+                status_line: None,
             }),
         };
         
@@ -801,6 +817,10 @@ mod tests {
                 res_hdr: None,
                 res_body: None,
                 null_body: false,
+                trailers: None,
+                ieof: false,
+                req_line: None,
+                status_line: None,
             }),
         };
         
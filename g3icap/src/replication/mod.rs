@@ -0,0 +1,717 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Warm-standby replication of the soft state
+//!
+//! The components with a state worth keeping over a failover, like the
+//! verdict cache or the quota tracker, register it under a name and publish
+//! the changes of its entries. The active server coalesces the changes by
+//! entry and sends them in batches to the standby server, which applies them
+//! to its own components. The whole state is sent first on each connection,
+//! so a standby server restarted or disconnected for a while catches up, and
+//! again when the changes pile up over `max_pending`. Changes published
+//! while disconnected are dropped, the next connection sending them anyway.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, Weak};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail};
+use arc_swap::ArcSwapOption;
+use serde_json::Value;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+
+use crate::config::server::replication::{ReplicationConfig, ReplicationRole};
+use protocol::{Entry, FrameReader, FrameWriter, Message};
+
+mod protocol;
+
+/// Entries sent in a batch at most
+const BATCH_SIZE: usize = 1000;
+/// Longest time the handshake of a connection may take
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// First wait before connecting again to the standby server
+const MIN_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A state sent to the standby server
+pub trait ReplicatedState: Send + Sync {
+    /// All the current entries, by key
+    fn snapshot(&self) -> Vec<(String, Value)>;
+
+    /// Apply an entry received from the active server
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the entry is not valid for the state.
+    fn apply(&self, key: &str, value: Value) -> anyhow::Result<()>;
+}
+
+/// Replicated states by name
+#[derive(Default)]
+pub struct StateRegistry {
+    states: Mutex<BTreeMap<String, Weak<dyn ReplicatedState>>>,
+}
+
+static GLOBAL_STATES: LazyLock<Arc<StateRegistry>> = LazyLock::new(Default::default);
+
+impl StateRegistry {
+    /// Registry of the states of the process
+    pub fn global() -> Arc<StateRegistry> {
+        GLOBAL_STATES.clone()
+    }
+
+    /// Register the state under the name, replacing the previous one, until
+    /// it is dropped
+    pub fn register(&self, name: &str, state: Weak<dyn ReplicatedState>) {
+        self.states.lock().unwrap().insert(name.to_string(), state);
+    }
+
+    fn get(&self, name: &str) -> Option<Arc<dyn ReplicatedState>> {
+        self.states.lock().unwrap().get(name)?.upgrade()
+    }
+
+    /// The live states, forgetting the dropped ones
+    fn states(&self) -> Vec<(String, Arc<dyn ReplicatedState>)> {
+        let mut states = self.states.lock().unwrap();
+        states.retain(|_, state| state.strong_count() > 0);
+        states
+            .iter()
+            .filter_map(|(name, state)| Some((name.clone(), state.upgrade()?)))
+            .collect()
+    }
+}
+
+/// Register a state of the process under the name
+pub fn register<S: ReplicatedState + 'static>(name: &str, state: &Arc<S>) {
+    let state = Arc::downgrade(state);
+    GLOBAL_STATES.register(name, state);
+}
+
+static GLOBAL_REPLICATOR: ArcSwapOption<Replicator> = ArcSwapOption::const_empty();
+
+/// Install the replicator of the server, for the components to publish to
+pub fn set_global(replicator: Option<Arc<Replicator>>) {
+    GLOBAL_REPLICATOR.store(replicator);
+}
+
+pub fn get_global() -> Option<Arc<Replicator>> {
+    GLOBAL_REPLICATOR.load_full()
+}
+
+/// Publish a changed entry of a state, the value being only built if it is
+/// to be sent
+pub fn publish(state: &str, key: &str, value: impl FnOnce() -> Value) {
+    if let Some(replicator) = GLOBAL_REPLICATOR.load().as_ref() {
+        replicator.publish(state, key, value);
+    }
+}
+
+/// Replication counters
+#[derive(Debug, Default)]
+pub struct ReplicationStats {
+    /// Connections established with the peer
+    pub connections: AtomicU64,
+    /// Entries sent to the standby server
+    pub sent: AtomicU64,
+    /// Entries applied from the active server
+    pub applied: AtomicU64,
+    /// Entries received for a state unknown or failed to be applied
+    pub failed: AtomicU64,
+    /// Whole states sent or received
+    pub resyncs: AtomicU64,
+    /// Times the changes piled up over `max_pending`
+    pub overflows: AtomicU64,
+    /// Milliseconds between the sending of the last batch applied and its
+    /// application, as far as the clocks of the servers agree
+    pub apply_delay_ms: AtomicU64,
+}
+
+/// Changes waiting to be sent
+#[derive(Default)]
+struct Pending {
+    entries: HashMap<(String, String), Value>,
+    /// Time of the oldest change
+    since: Option<Instant>,
+    /// Changes were dropped, the whole state is to be sent
+    overflowed: bool,
+}
+
+struct Shared {
+    config: ReplicationConfig,
+    states: Arc<StateRegistry>,
+    connected: AtomicBool,
+    pending: Mutex<Pending>,
+    /// Batches not acknowledged yet, with the time of their oldest change
+    unacked: Mutex<VecDeque<(u64, Instant)>>,
+    /// Connection from the active server being served
+    current: Mutex<Option<CancellationToken>>,
+    stats: ReplicationStats,
+}
+
+/// Replication of the states of the process with the peer server, stopped
+/// when dropped
+pub struct Replicator {
+    shared: Arc<Shared>,
+    shutdown: CancellationToken,
+}
+
+impl Drop for Replicator {
+    fn drop(&mut self) {
+        self.shutdown.cancel();
+    }
+}
+
+impl Replicator {
+    /// Create the replicator of the states of the process
+    pub fn new(config: ReplicationConfig) -> Self {
+        Replicator::with_states(config, StateRegistry::global())
+    }
+
+    /// Create the replicator of the states of the registry
+    pub fn with_states(config: ReplicationConfig, states: Arc<StateRegistry>) -> Self {
+        Replicator {
+            shared: Arc::new(Shared {
+                config,
+                states,
+                connected: AtomicBool::new(false),
+                pending: Mutex::new(Pending::default()),
+                unacked: Mutex::new(VecDeque::new()),
+                current: Mutex::new(None),
+                stats: ReplicationStats::default(),
+            }),
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    pub fn role(&self) -> ReplicationRole {
+        self.shared.config.role
+    }
+
+    pub fn stats(&self) -> &ReplicationStats {
+        &self.shared.stats
+    }
+
+    /// Whether the channel with the peer is established
+    pub fn is_connected(&self) -> bool {
+        self.shared.connected.load(Ordering::Relaxed)
+    }
+
+    /// Connect to the standby server, or listen for the active one
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the standby server can not listen.
+    pub async fn start(&self) -> anyhow::Result<()> {
+        let shared = self.shared.clone();
+        let shutdown = self.shutdown.clone();
+        match (shared.config.role, shared.config.peer, shared.config.listen) {
+            (ReplicationRole::Active, Some(peer), _) => {
+                tokio::spawn(async move {
+                    tokio::select! {
+                        _ = shutdown.cancelled() => {}
+                        _ = shared.run_active(peer) => {}
+                    }
+                });
+            }
+            (ReplicationRole::Standby, _, Some(listen)) => {
+                let listener = TcpListener::bind(listen)
+                    .await
+                    .map_err(|e| anyhow!("failed to listen on {listen} for replication: {e}"))?;
+                log::info!("waiting for replicated state on {listen}");
+                tokio::spawn(async move {
+                    tokio::select! {
+                        _ = shutdown.cancelled() => {}
+                        _ = shared.clone().run_standby(listener, shutdown.clone()) => {}
+                    }
+                });
+            }
+            _ => bail!("no replication address set"),
+        }
+        Ok(())
+    }
+
+    /// Queue a changed entry for the standby server, if connected to it
+    pub fn publish(&self, state: &str, key: &str, value: impl FnOnce() -> Value) {
+        self.shared.publish(state, key, value);
+    }
+
+    /// Age of the oldest change not acknowledged by the standby server yet
+    pub fn lag(&self) -> Duration {
+        let pending = self.shared.pending.lock().unwrap().since;
+        let unacked = self.shared.unacked.lock().unwrap().front().map(|(_, t)| *t);
+        pending
+            .into_iter()
+            .chain(unacked)
+            .min()
+            .map(|t| t.elapsed())
+            .unwrap_or_default()
+    }
+
+    /// Render the replication status in the Prometheus text format
+    pub fn render_prometheus(&self) -> String {
+        let stats = &self.shared.stats;
+        let mut out = String::new();
+        let _ = writeln!(out, "# TYPE icap_replication_connected gauge");
+        let _ = writeln!(
+            out,
+            "icap_replication_connected{{role=\"{}\"}} {}",
+            self.role().as_str(),
+            self.is_connected() as u8
+        );
+        let mut gauge = |name: &str, value: String| {
+            let _ = writeln!(out, "# TYPE {name} gauge");
+            let _ = writeln!(out, "{name} {value}");
+        };
+        match self.role() {
+            ReplicationRole::Active => {
+                gauge(
+                    "icap_replication_lag_seconds",
+                    format!("{:.3}", self.lag().as_secs_f64()),
+                );
+                let pending = self.shared.pending.lock().unwrap().entries.len();
+                gauge("icap_replication_pending_entries", pending.to_string());
+            }
+            ReplicationRole::Standby => {
+                let delay = stats.apply_delay_ms.load(Ordering::Relaxed) as f64 / 1000.0;
+                gauge(
+                    "icap_replication_apply_delay_seconds",
+                    format!("{delay:.3}"),
+                );
+            }
+        }
+        let mut counter = |name: &str, value: &AtomicU64| {
+            let _ = writeln!(out, "# TYPE {name} counter");
+            let _ = writeln!(out, "{name} {}", value.load(Ordering::Relaxed));
+        };
+        counter("icap_replication_connections_total", &stats.connections);
+        counter("icap_replication_sent_entries_total", &stats.sent);
+        counter("icap_replication_applied_entries_total", &stats.applied);
+        counter("icap_replication_failed_entries_total", &stats.failed);
+        counter("icap_replication_resyncs_total", &stats.resyncs);
+        counter("icap_replication_overflows_total", &stats.overflows);
+        out
+    }
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+impl Shared {
+    fn publish(&self, state: &str, key: &str, value: impl FnOnce() -> Value) {
+        if self.config.role != ReplicationRole::Active
+            || !self.connected.load(Ordering::Relaxed)
+            || !self.config.replicates(state)
+        {
+            return;
+        }
+        let value = value();
+        let key = (state.to_string(), key.to_string());
+        let mut pending = self.pending.lock().unwrap();
+        if pending.overflowed {
+            return;
+        }
+        if pending.entries.len() >= self.config.max_pending && !pending.entries.contains_key(&key) {
+            pending.entries.clear();
+            pending.overflowed = true;
+            self.stats.overflows.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        pending.since.get_or_insert_with(Instant::now);
+        pending.entries.insert(key, value);
+    }
+
+    fn take_pending(&self) -> Pending {
+        std::mem::take(&mut *self.pending.lock().unwrap())
+    }
+
+    /// Wait for a message of the peer, which should send one every
+    /// heartbeat interval
+    async fn recv<R: AsyncRead + Unpin>(
+        &self,
+        reader: &mut FrameReader<R>,
+    ) -> anyhow::Result<Message> {
+        let timeout = self.config.heartbeat_interval * 3;
+        tokio::time::timeout(timeout, reader.recv())
+            .await
+            .map_err(|_| anyhow!("no message from the replication peer for {timeout:?}"))?
+    }
+
+    async fn run_active(&self, peer: SocketAddr) {
+        let mut retry = MIN_RETRY_INTERVAL.min(self.config.max_retry_interval);
+        loop {
+            let connections = self.stats.connections.load(Ordering::Relaxed);
+            match TcpStream::connect(peer).await {
+                Ok(stream) => {
+                    if let Err(e) = self.serve_standby(stream).await {
+                        log::warn!("state replication to {peer} stopped: {e:?}");
+                    }
+                }
+                Err(e) => log::debug!("failed to connect to replication peer {peer}: {e}"),
+            }
+            if self.stats.connections.load(Ordering::Relaxed) != connections {
+                retry = MIN_RETRY_INTERVAL.min(self.config.max_retry_interval);
+            }
+            tokio::time::sleep(retry).await;
+            retry = (retry * 2).min(self.config.max_retry_interval);
+        }
+    }
+
+    async fn serve_standby(&self, stream: TcpStream) -> anyhow::Result<()> {
+        let _ = stream.set_nodelay(true);
+        let (r, w) = stream.into_split();
+        let mut reader = FrameReader::new(r);
+        let mut writer = FrameWriter::new(w);
+        tokio::time::timeout(
+            HANDSHAKE_TIMEOUT,
+            protocol::handshake_active(&mut reader, &mut writer, self.config.secret.as_bytes()),
+        )
+        .await
+        .map_err(|_| anyhow!("replication handshake timed out"))??;
+
+        log::info!("replicating state to {:?}", self.config.peer);
+        self.stats.connections.fetch_add(1, Ordering::Relaxed);
+        // the changes from now on are queued, the older ones are in the snapshot
+        self.connected.store(true, Ordering::Relaxed);
+        let result = tokio::select! {
+            r = self.receive_acks(&mut reader) => r,
+            r = self.send_changes(&mut writer) => r,
+        };
+        self.connected.store(false, Ordering::Relaxed);
+        self.take_pending();
+        self.unacked.lock().unwrap().clear();
+        result
+    }
+
+    async fn receive_acks<R: AsyncRead + Unpin>(
+        &self,
+        reader: &mut FrameReader<R>,
+    ) -> anyhow::Result<()> {
+        loop {
+            let Message::Ack { seq } = self.recv(reader).await? else {
+                bail!("unexpected message from the standby server");
+            };
+            let mut unacked = self.unacked.lock().unwrap();
+            while unacked.front().is_some_and(|(s, _)| *s <= seq) {
+                unacked.pop_front();
+            }
+        }
+    }
+
+    async fn send_changes<W: AsyncWrite + Unpin>(
+        &self,
+        writer: &mut FrameWriter<W>,
+    ) -> anyhow::Result<()> {
+        let mut seq = 0;
+        self.send_snapshot(writer, &mut seq).await?;
+        let mut last_sent = Instant::now();
+        let mut interval = tokio::time::interval(self.config.flush_interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let (overflowed, since) = {
+                let pending = self.pending.lock().unwrap();
+                (pending.overflowed, pending.since)
+            };
+            if overflowed {
+                log::warn!(
+                    "over {} state changes waiting for replication, sending the whole state",
+                    self.config.max_pending
+                );
+                self.send_snapshot(writer, &mut seq).await?;
+            } else if let Some(since) = since {
+                let entries = self
+                    .take_pending()
+                    .entries
+                    .into_iter()
+                    .map(|((state, key), value)| Entry { state, key, value })
+                    .collect();
+                self.send_entries(writer, &mut seq, entries, since).await?;
+            } else if last_sent.elapsed() >= self.config.heartbeat_interval {
+                writer.send(&Message::Heartbeat).await?;
+            } else {
+                continue;
+            }
+            last_sent = Instant::now();
+        }
+    }
+
+    /// Send all the entries of the replicated states
+    async fn send_snapshot<W: AsyncWrite + Unpin>(
+        &self,
+        writer: &mut FrameWriter<W>,
+        seq: &mut u64,
+    ) -> anyhow::Result<()> {
+        // the changes queued until now are in the snapshot
+        let since = self.take_pending().since.unwrap_or_else(Instant::now);
+        let entries: Vec<Entry> = self
+            .states
+            .states()
+            .into_iter()
+            .filter(|(name, _)| self.config.replicates(name))
+            .flat_map(|(name, state)| {
+                state.snapshot().into_iter().map(move |(key, value)| Entry {
+                    state: name.clone(),
+                    key,
+                    value,
+                })
+            })
+            .collect();
+        let count = entries.len();
+        self.send_entries(writer, seq, entries, since).await?;
+        writer.send(&Message::Resynced).await?;
+        self.stats.resyncs.fetch_add(1, Ordering::Relaxed);
+        log::info!("sent the whole replicated state, {count} entries");
+        Ok(())
+    }
+
+    async fn send_entries<W: AsyncWrite + Unpin>(
+        &self,
+        writer: &mut FrameWriter<W>,
+        seq: &mut u64,
+        mut entries: Vec<Entry>,
+        since: Instant,
+    ) -> anyhow::Result<()> {
+        while !entries.is_empty() {
+            let rest = entries.split_off(BATCH_SIZE.min(entries.len()));
+            let batch = std::mem::replace(&mut entries, rest);
+            let count = batch.len() as u64;
+            *seq += 1;
+            self.unacked.lock().unwrap().push_back((*seq, since));
+            writer
+                .send(&Message::Batch {
+                    seq: *seq,
+                    sent_at: unix_ms(),
+                    entries: batch,
+                })
+                .await?;
+            self.stats.sent.fetch_add(count, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    async fn run_standby(self: Arc<Self>, listener: TcpListener, shutdown: CancellationToken) {
+        loop {
+            let (stream, addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    log::warn!("failed to accept replication connection: {e}");
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            let shared = self.clone();
+            let token = shutdown.child_token();
+            tokio::spawn(async move {
+                tokio::select! {
+                    _ = token.cancelled() => {}
+                    r = shared.serve_active(stream, &token) => {
+                        if let Err(e) = r {
+                            log::warn!("state replication from {addr} stopped: {e:?}");
+                        }
+                    }
+                }
+            });
+        }
+    }
+
+    async fn serve_active(
+        &self,
+        stream: TcpStream,
+        token: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let _ = stream.set_nodelay(true);
+        let (r, w) = stream.into_split();
+        let mut reader = FrameReader::new(r);
+        let mut writer = FrameWriter::new(w);
+        tokio::time::timeout(
+            HANDSHAKE_TIMEOUT,
+            protocol::handshake_standby(&mut reader, &mut writer, self.config.secret.as_bytes()),
+        )
+        .await
+        .map_err(|_| anyhow!("replication handshake timed out"))??;
+
+        // a new connection of the active server replaces the previous one
+        if let Some(previous) = self.current.lock().unwrap().replace(token.clone()) {
+            previous.cancel();
+        }
+        log::info!("receiving replicated state from the active server");
+        self.stats.connections.fetch_add(1, Ordering::Relaxed);
+        self.connected.store(true, Ordering::Relaxed);
+        let result = self.apply_changes(&mut reader, &mut writer).await;
+        if !token.is_cancelled() {
+            self.connected.store(false, Ordering::Relaxed);
+            self.current.lock().unwrap().take();
+        }
+        result
+    }
+
+    async fn apply_changes<R, W>(
+        &self,
+        reader: &mut FrameReader<R>,
+        writer: &mut FrameWriter<W>,
+    ) -> anyhow::Result<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut applied = 0;
+        loop {
+            match self.recv(reader).await? {
+                Message::Batch {
+                    seq,
+                    sent_at,
+                    entries,
+                } => {
+                    for entry in entries {
+                        self.apply(entry);
+                    }
+                    applied = seq;
+                    self.stats
+                        .apply_delay_ms
+                        .store(unix_ms().saturating_sub(sent_at), Ordering::Relaxed);
+                    writer.send(&Message::Ack { seq }).await?;
+                }
+                Message::Resynced => {
+                    self.stats.resyncs.fetch_add(1, Ordering::Relaxed);
+                    log::info!("received the whole replicated state");
+                }
+                Message::Heartbeat => writer.send(&Message::Ack { seq: applied }).await?,
+                _ => bail!("unexpected message from the active server"),
+            }
+        }
+    }
+
+    fn apply(&self, entry: Entry) {
+        if !self.config.replicates(&entry.state) {
+            return;
+        }
+        let result = match self.states.get(&entry.state) {
+            Some(state) => state.apply(&entry.key, entry.value),
+            None => Err(anyhow!("no such state here")),
+        };
+        match result {
+            Ok(()) => {
+                self.stats.applied.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => {
+                self.stats.failed.fetch_add(1, Ordering::Relaxed);
+                log::debug!(
+                    "failed to apply replicated {} entry {}: {e}",
+                    entry.state,
+                    entry.key
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A map of numbers
+    #[derive(Default)]
+    struct Numbers(Mutex<BTreeMap<String, u64>>);
+
+    impl Numbers {
+        fn get(&self, key: &str) -> Option<u64> {
+            self.0.lock().unwrap().get(key).copied()
+        }
+    }
+
+    impl ReplicatedState for Numbers {
+        fn snapshot(&self) -> Vec<(String, Value)> {
+            let numbers = self.0.lock().unwrap();
+            numbers
+                .iter()
+                .map(|(k, v)| (k.clone(), Value::from(*v)))
+                .collect()
+        }
+
+        fn apply(&self, key: &str, value: Value) -> anyhow::Result<()> {
+            let value = value.as_u64().ok_or_else(|| anyhow!("not a number"))?;
+            self.0.lock().unwrap().insert(key.to_string(), value);
+            Ok(())
+        }
+    }
+
+    fn config(role: ReplicationRole, addr: SocketAddr) -> ReplicationConfig {
+        let yaml = format!(
+            "role: {}\npeer: {addr}\nlisten: {addr}\nsecret: 0123456789abcdef\n\
+             flush_interval: 10ms\nheartbeat_interval: 100ms\nmax_retry_interval: 100ms",
+            role.as_str()
+        );
+        let docs = yaml_rust::YamlLoader::load_from_str(&yaml).unwrap();
+        ReplicationConfig::parse(&docs[0]).unwrap()
+    }
+
+    fn replicator(role: ReplicationRole, addr: SocketAddr) -> (Replicator, Arc<Numbers>) {
+        let registry = Arc::new(StateRegistry::default());
+        let numbers = Arc::new(Numbers::default());
+        let state = Arc::downgrade(&numbers);
+        registry.register("numbers", state);
+        (
+            Replicator::with_states(config(role, addr), registry),
+            numbers,
+        )
+    }
+
+    async fn wait_for(condition: impl Fn() -> bool) {
+        for _ in 0..200 {
+            if condition() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("condition not met in time");
+    }
+
+    #[tokio::test]
+    async fn replicate() {
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let (standby, replica) = replicator(ReplicationRole::Standby, addr);
+        standby.start().await.unwrap();
+        let (active, numbers) = replicator(ReplicationRole::Active, addr);
+        numbers.0.lock().unwrap().insert("a".to_string(), 1);
+        active.start().await.unwrap();
+
+        // the state before the connection is in the snapshot
+        wait_for(|| replica.get("a") == Some(1)).await;
+        assert!(active.is_connected() && standby.is_connected());
+
+        active.publish("numbers", "b", || Value::from(2));
+        active.publish("numbers", "b", || Value::from(3));
+        active.publish("others", "c", || Value::from(4));
+        wait_for(|| replica.get("b") == Some(3)).await;
+        wait_for(|| active.lag().is_zero()).await;
+        assert_eq!(standby.stats().failed.load(Ordering::Relaxed), 1);
+        assert_eq!(standby.stats().resyncs.load(Ordering::Relaxed), 1);
+        assert!(
+            active
+                .render_prometheus()
+                .contains("icap_replication_connected{role=\"active\"} 1")
+        );
+
+        // the active server resyncs a restarted standby server
+        drop(standby);
+        wait_for(|| !active.is_connected()).await;
+        let (standby, replica) = replicator(ReplicationRole::Standby, addr);
+        standby.start().await.unwrap();
+        wait_for(|| replica.get("a") == Some(1)).await;
+        assert_eq!(active.stats().resyncs.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,330 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Replication channel protocol
+//!
+//! Each message is a JSON document in a frame made of its length as a 32-bit
+//! big endian integer, a 32 bytes HMAC-SHA256 and the document itself. The
+//! active server opens the channel with a nonce, the standby server answers
+//! with its own nonce and the proof it knows the shared secret, and the
+//! active server gives its proof in turn. The frames of the handshake have
+//! an empty MAC; the following ones are authenticated with a key derived
+//! from the secret and the two nonces, over the sequence number of the frame
+//! in its direction and the document, so that they can be neither forged,
+//! nor replayed, nor reordered.
+
+use anyhow::{anyhow, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Version of the protocol, both servers should speak the same
+pub(super) const VERSION: u32 = 1;
+
+const MAC_LEN: usize = 32;
+const NONCE_LEN: usize = 32;
+/// Largest document of a frame
+const MAX_FRAME_LEN: usize = 16 << 20;
+
+/// A changed entry of a replicated state
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(super) struct Entry {
+    pub(super) state: String,
+    pub(super) key: String,
+    pub(super) value: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(super) enum Message {
+    /// First message of the active server
+    Hello { version: u32, nonce: String },
+    /// Answer of the standby server, with its proof
+    Challenge { nonce: String, proof: String },
+    /// Proof of the active server
+    Auth { proof: String },
+    /// Changed entries, or the entries of the whole state on a resync
+    Batch {
+        seq: u64,
+        /// Unix time in milliseconds the batch was sent at
+        sent_at: u64,
+        entries: Vec<Entry>,
+    },
+    /// Sent after the last batch of a resync
+    Resynced,
+    /// Sent by an idle active server
+    Heartbeat,
+    /// Answer of the standby server to a batch or a heartbeat, with the
+    /// sequence number of the last batch applied
+    Ack { seq: u64 },
+}
+
+pub(super) struct FrameReader<R> {
+    inner: R,
+    key: Option<Vec<u8>>,
+    seq: u64,
+}
+
+impl<R: AsyncRead + Unpin> FrameReader<R> {
+    pub(super) fn new(inner: R) -> Self {
+        FrameReader {
+            inner,
+            key: None,
+            seq: 0,
+        }
+    }
+
+    pub(super) async fn recv(&mut self) -> anyhow::Result<Message> {
+        let len = self.inner.read_u32().await? as usize;
+        if len > MAX_FRAME_LEN {
+            bail!("replication frame of {len} bytes is too large");
+        }
+        let mut mac = [0u8; MAC_LEN];
+        self.inner.read_exact(&mut mac).await?;
+        let mut payload = vec![0u8; len];
+        self.inner.read_exact(&mut payload).await?;
+        if let Some(key) = &self.key {
+            let expected = hmac(key, &[&self.seq.to_be_bytes(), &payload])?;
+            if !openssl::memcmp::eq(&expected, &mac) {
+                bail!("invalid MAC of replication frame {}", self.seq);
+            }
+        }
+        self.seq += 1;
+        serde_json::from_slice(&payload).map_err(|e| anyhow!("invalid replication message: {e}"))
+    }
+}
+
+pub(super) struct FrameWriter<W> {
+    inner: W,
+    key: Option<Vec<u8>>,
+    seq: u64,
+}
+
+impl<W: AsyncWrite + Unpin> FrameWriter<W> {
+    pub(super) fn new(inner: W) -> Self {
+        FrameWriter {
+            inner,
+            key: None,
+            seq: 0,
+        }
+    }
+
+    pub(super) async fn send(&mut self, msg: &Message) -> anyhow::Result<()> {
+        let payload = serde_json::to_vec(msg)?;
+        if payload.len() > MAX_FRAME_LEN {
+            bail!(
+                "replication message of {} bytes is too large",
+                payload.len()
+            );
+        }
+        let mac = match &self.key {
+            Some(key) => hmac(key, &[&self.seq.to_be_bytes(), &payload])?,
+            None => vec![0u8; MAC_LEN],
+        };
+        self.inner.write_u32(payload.len() as u32).await?;
+        self.inner.write_all(&mac).await?;
+        self.inner.write_all(&payload).await?;
+        self.inner.flush().await?;
+        self.seq += 1;
+        Ok(())
+    }
+}
+
+fn hmac(key: &[u8], parts: &[&[u8]]) -> anyhow::Result<Vec<u8>> {
+    let key = PKey::hmac(key)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    for part in parts {
+        signer.update(part)?;
+    }
+    Ok(signer.sign_to_vec()?)
+}
+
+fn nonce() -> anyhow::Result<Vec<u8>> {
+    let mut nonce = vec![0u8; NONCE_LEN];
+    openssl::rand::rand_bytes(&mut nonce)?;
+    Ok(nonce)
+}
+
+fn decode(s: &str) -> anyhow::Result<Vec<u8>> {
+    STANDARD
+        .decode(s)
+        .map_err(|e| anyhow!("invalid base64 in replication handshake: {e}"))
+}
+
+/// Proof of a side, or the session key, from the secret and the nonces
+fn derive(secret: &[u8], label: &[u8], active: &[u8], standby: &[u8]) -> anyhow::Result<Vec<u8>> {
+    hmac(secret, &[label, active, standby])
+}
+
+fn check_proof(expected: &[u8], proof: &str) -> anyhow::Result<()> {
+    let proof = decode(proof)?;
+    if proof.len() != expected.len() || !openssl::memcmp::eq(expected, &proof) {
+        bail!("replication peer does not know the shared secret");
+    }
+    Ok(())
+}
+
+fn start_session<R, W>(reader: &mut FrameReader<R>, writer: &mut FrameWriter<W>, key: Vec<u8>) {
+    reader.key = Some(key.clone());
+    reader.seq = 0;
+    writer.key = Some(key);
+    writer.seq = 0;
+}
+
+/// Authenticate the channel as the active server
+pub(super) async fn handshake_active<R, W>(
+    reader: &mut FrameReader<R>,
+    writer: &mut FrameWriter<W>,
+    secret: &[u8],
+) -> anyhow::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let active = nonce()?;
+    writer
+        .send(&Message::Hello {
+            version: VERSION,
+            nonce: STANDARD.encode(&active),
+        })
+        .await?;
+    let Message::Challenge { nonce, proof } = reader.recv().await? else {
+        bail!("expected a challenge from the replication peer");
+    };
+    let standby = decode(&nonce)?;
+    check_proof(&derive(secret, b"standby", &active, &standby)?, &proof)?;
+    let proof = derive(secret, b"active", &active, &standby)?;
+    writer
+        .send(&Message::Auth {
+            proof: STANDARD.encode(proof),
+        })
+        .await?;
+    start_session(
+        reader,
+        writer,
+        derive(secret, b"session", &active, &standby)?,
+    );
+    Ok(())
+}
+
+/// Authenticate the channel as the standby server
+pub(super) async fn handshake_standby<R, W>(
+    reader: &mut FrameReader<R>,
+    writer: &mut FrameWriter<W>,
+    secret: &[u8],
+) -> anyhow::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let Message::Hello { version, nonce } = reader.recv().await? else {
+        bail!("expected a hello from the replication peer");
+    };
+    if version != VERSION {
+        bail!("replication peer speaks version {version}, not {VERSION}");
+    }
+    let active = decode(&nonce)?;
+    let standby = self::nonce()?;
+    let proof = derive(secret, b"standby", &active, &standby)?;
+    writer
+        .send(&Message::Challenge {
+            nonce: STANDARD.encode(&standby),
+            proof: STANDARD.encode(proof),
+        })
+        .await?;
+    let Message::Auth { proof } = reader.recv().await? else {
+        bail!("expected the proof of the replication peer");
+    };
+    check_proof(&derive(secret, b"active", &active, &standby)?, &proof)?;
+    start_session(
+        reader,
+        writer,
+        derive(secret, b"session", &active, &standby)?,
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{DuplexStream, ReadHalf, WriteHalf};
+
+    type Reader = FrameReader<ReadHalf<DuplexStream>>;
+    type Writer = FrameWriter<WriteHalf<DuplexStream>>;
+
+    fn pair() -> ((Reader, Writer), (Reader, Writer)) {
+        let (a, b) = tokio::io::duplex(4096);
+        let (ar, aw) = tokio::io::split(a);
+        let (br, bw) = tokio::io::split(b);
+        (
+            (FrameReader::new(ar), FrameWriter::new(aw)),
+            (FrameReader::new(br), FrameWriter::new(bw)),
+        )
+    }
+
+    #[tokio::test]
+    async fn handshake() {
+        let ((mut ar, mut aw), (mut sr, mut sw)) = pair();
+        let secret = b"0123456789abcdef";
+        let (a, s) = tokio::join!(
+            handshake_active(&mut ar, &mut aw, secret),
+            handshake_standby(&mut sr, &mut sw, secret)
+        );
+        a.unwrap();
+        s.unwrap();
+        assert_eq!(aw.key, sr.key);
+
+        let batch = Message::Batch {
+            seq: 1,
+            sent_at: 1000,
+            entries: vec![Entry {
+                state: "quota".to_string(),
+                key: "alice".to_string(),
+                value: serde_json::json!({"daily": 10}),
+            }],
+        };
+        aw.send(&batch).await.unwrap();
+        assert_eq!(sr.recv().await.unwrap(), batch);
+        sw.send(&Message::Ack { seq: 1 }).await.unwrap();
+        assert_eq!(ar.recv().await.unwrap(), Message::Ack { seq: 1 });
+    }
+
+    #[tokio::test]
+    async fn wrong_secret() {
+        let ((mut ar, mut aw), (mut sr, mut sw)) = pair();
+        // the active side closes the channel on failure
+        let active = async move { handshake_active(&mut ar, &mut aw, b"0123456789abcdef").await };
+        let (a, s) = tokio::join!(
+            active,
+            handshake_standby(&mut sr, &mut sw, b"fedcba9876543210")
+        );
+        assert!(a.is_err());
+        assert!(s.is_err());
+    }
+
+    #[tokio::test]
+    async fn replayed_frame() {
+        let ((mut ar, mut aw), (mut sr, mut sw)) = pair();
+        let secret = b"0123456789abcdef";
+        let (a, s) = tokio::join!(
+            handshake_active(&mut ar, &mut aw, secret),
+            handshake_standby(&mut sr, &mut sw, secret)
+        );
+        a.unwrap();
+        s.unwrap();
+
+        // a frame authenticated with the sequence number of another one
+        aw.send(&Message::Heartbeat).await.unwrap();
+        aw.seq = 0;
+        aw.send(&Message::Heartbeat).await.unwrap();
+        assert_eq!(sr.recv().await.unwrap(), Message::Heartbeat);
+        assert!(sr.recv().await.is_err());
+    }
+}
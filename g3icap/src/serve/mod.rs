@@ -91,34 +91,41 @@ pub fn spawn_offline_clean() {
     });
 }
 
+/// Build the config of the server, the command line options first, then the
+/// sections only found in the config file
+pub fn server_config() -> anyhow::Result<crate::config::server::icap_server::IcapServerConfig> {
+    use crate::config::server::icap_server::IcapServerConfig;
+
+    // Get the parsed command line arguments
+    let proc_args = crate::opts::ProcArgs::parse().unwrap_or_else(|| crate::opts::ProcArgs {
+        stats: true,
+        metrics: true,
+        ..Default::default()
+    });
+
+    let mut server_config = IcapServerConfig::from_proc_args(proc_args)
+        .map_err(|e| anyhow::anyhow!("Failed to create ICAP server config: {}", e))?;
+    if let Some((_, AnyServerConfig::Icap(file_config))) =
+        crate::config::server::get_all().into_iter().next()
+    {
+        server_config.merge_file_config(&file_config);
+    }
+    Ok(server_config)
+}
+
 /// Spawn all servers
 pub async fn spawn_all() -> anyhow::Result<()> {
     use crate::server::IcapServer;
-    
-    // Get the parsed command line arguments
-    let proc_args = crate::opts::ProcArgs::parse().unwrap_or_else(|| {
-        crate::opts::ProcArgs {
-            daemon_config: g3_daemon::opts::DaemonArgs::new("g3icap"),
-            config: None,
-            port: 1344,
-            host: "0.0.0.0".to_string(),
-            max_connections: 1000,
-            connection_timeout: 30,
-            request_timeout: 60,
-            tls: false,
-            tls_cert: None,
-            tls_key: None,
-            stats: true,
-            stats_port: 8080,
-            metrics: true,
-            metrics_port: 9090,
-        }
-    });
-    
+
+    let server_config = server_config()?;
+
     // Create and start ICAP server
-    let mut icap_server = IcapServer::new(proc_args)
+    let mut icap_server = IcapServer::new_with_config(server_config)
         .map_err(|e| anyhow::anyhow!("Failed to create ICAP server: {}", e))?;
-    
+
+    // Modules are initialized once here and shared by all connections
+    icap_server.load_modules().await;
+
     // Spawn server in background task
     tokio::spawn(async move {
         if let Err(e) = icap_server.start().await {
@@ -0,0 +1,314 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Pool of connection read buffers
+//!
+//! Each thread takes buffers from its own shard of the pool, so that the
+//! allocations of a busy server are mostly served from buffers released
+//! by the previous requests on the same thread.
+//!
+//! On multi node hosts, each buffer remembers the NUMA node of the thread
+//! that allocated it, and reusing it on a thread of another node is counted
+//! as a remote hit. With node-local pools, the shards are split between the
+//! nodes, a thread only takes buffers from the shards of its own node, and
+//! buffers are given back to the shards of the node they were allocated on.
+
+use std::cell::Cell;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use bytes::BytesMut;
+
+use crate::config::server::buffer_pool::BufferPoolConfig;
+use crate::numa;
+
+/// Pools of all servers
+static POOLS: Mutex<Vec<Arc<BufferPool>>> = Mutex::new(Vec::new());
+
+/// Next shard index to hand out to a thread
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static THREAD_SHARD: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+fn thread_shard() -> usize {
+    THREAD_SHARD.with(|shard| match shard.get() {
+        Some(i) => i,
+        None => {
+            let i = NEXT_SHARD.fetch_add(1, Ordering::Relaxed);
+            shard.set(Some(i));
+            i
+        }
+    })
+}
+
+/// An idle buffer, with the node it was allocated on
+#[derive(Debug)]
+struct IdleBuffer {
+    buf: BytesMut,
+    node: Option<usize>,
+}
+
+/// Sharded freelist of read buffers
+#[derive(Debug)]
+pub struct BufferPool {
+    name: String,
+    config: BufferPoolConfig,
+    shards: Vec<Mutex<Vec<IdleBuffer>>>,
+    /// Shards of each node, if the pool is node-local
+    shards_per_node: Option<usize>,
+    in_use: AtomicU64,
+    hits: AtomicU64,
+    remote_hits: AtomicU64,
+    misses: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl BufferPool {
+    fn new(name: &str, config: BufferPoolConfig, nodes: usize) -> Self {
+        let mut shards = match config.shards {
+            0 => std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+            n => n,
+        };
+        let shards_per_node = (nodes > 1).then(|| shards.div_ceil(nodes));
+        if let Some(n) = shards_per_node {
+            shards = n * nodes;
+        }
+        BufferPool {
+            name: name.to_string(),
+            config,
+            shards: (0..shards).map(|_| Mutex::new(Vec::new())).collect(),
+            shards_per_node,
+            in_use: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            remote_hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Create the pool of a server, replacing the one of its previous config
+    ///
+    /// The pool is split between the NUMA nodes if `node_local` is set and
+    /// the host has several of them.
+    pub fn register(name: &str, config: BufferPoolConfig, node_local: bool) -> Arc<Self> {
+        let nodes = if node_local { numa::node_count() } else { 1 };
+        let pool = Arc::new(BufferPool::new(name, config, nodes));
+        let mut pools = POOLS.lock().unwrap();
+        pools.retain(|p| p.name != name);
+        pools.push(pool.clone());
+        pool
+    }
+
+    /// Take a buffer, with at least the configured capacity
+    pub fn get(self: &Arc<Self>) -> PooledBuffer {
+        self.get_on(numa::current_node())
+    }
+
+    fn get_on(self: &Arc<Self>, node: Option<usize>) -> PooledBuffer {
+        self.in_use.fetch_add(1, Ordering::Relaxed);
+        let pooled = self.shard(node).lock().unwrap().pop();
+        let (buf, node) = match pooled {
+            Some(idle) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                if idle.node.is_some() && idle.node != node {
+                    self.remote_hits.fetch_add(1, Ordering::Relaxed);
+                }
+                (idle.buf, idle.node)
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                (BytesMut::with_capacity(self.config.buffer_size), node)
+            }
+        };
+        PooledBuffer {
+            buf,
+            node,
+            pool: Some(self.clone()),
+        }
+    }
+
+    /// Shard of the current thread, among the ones of the node if node-local
+    fn shard(&self, node: Option<usize>) -> &Mutex<Vec<IdleBuffer>> {
+        let index = match (self.shards_per_node, node) {
+            (Some(n), Some(node)) => node * n + thread_shard() % n,
+            _ => thread_shard(),
+        };
+        &self.shards[index % self.shards.len()]
+    }
+
+    fn put(&self, mut buf: BytesMut, node: Option<usize>) {
+        self.in_use.fetch_sub(1, Ordering::Relaxed);
+        if buf.capacity() > self.config.max_retained_size {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        buf.clear();
+        let mut shard = self.shard(node).lock().unwrap();
+        if shard.len() < self.config.max_idle_per_shard {
+            shard.push(IdleBuffer { buf, node });
+        } else {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Name of the server of the pool
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Number of buffers waiting in the pool
+    pub fn idle(&self) -> u64 {
+        self.shards
+            .iter()
+            .map(|s| s.lock().unwrap().len() as u64)
+            .sum()
+    }
+
+    /// Number of buffers taken and not given back yet
+    pub fn in_use(&self) -> u64 {
+        self.in_use.load(Ordering::Relaxed)
+    }
+
+    /// Number of buffers served from the pool
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Number of buffers served from the pool to a thread of another node
+    /// than the one they were allocated on
+    pub fn remote_hits(&self) -> u64 {
+        self.remote_hits.load(Ordering::Relaxed)
+    }
+
+    /// Number of buffers allocated as the pool was empty
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Number of buffers freed instead of pooled
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Get the pools of all servers
+pub fn all() -> Vec<Arc<BufferPool>> {
+    POOLS.lock().unwrap().clone()
+}
+
+/// A read buffer, given back to its pool when dropped
+#[derive(Debug, Default)]
+pub struct PooledBuffer {
+    buf: BytesMut,
+    node: Option<usize>,
+    pool: Option<Arc<BufferPool>>,
+}
+
+impl Deref for PooledBuffer {
+    type Target = BytesMut;
+
+    fn deref(&self) -> &BytesMut {
+        &self.buf
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut BytesMut {
+        &mut self.buf
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.put(std::mem::take(&mut self.buf), self.node);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(max_idle_per_shard: usize) -> Arc<BufferPool> {
+        node_pool(max_idle_per_shard, 1)
+    }
+
+    fn node_pool(max_idle_per_shard: usize, nodes: usize) -> Arc<BufferPool> {
+        Arc::new(BufferPool::new(
+            "test",
+            BufferPoolConfig {
+                buffer_size: 1024,
+                max_retained_size: 4096,
+                shards: 2,
+                max_idle_per_shard,
+            },
+            nodes,
+        ))
+    }
+
+    #[test]
+    fn reuse() {
+        let pool = pool(1);
+        let mut buf = pool.get();
+        assert!(buf.capacity() >= 1024);
+        buf.extend_from_slice(b"REQMOD");
+        assert_eq!(pool.in_use(), 1);
+        drop(buf);
+        assert_eq!(pool.in_use(), 0);
+        assert_eq!(pool.idle(), 1);
+
+        let buf = pool.get();
+        assert!(buf.is_empty());
+        assert_eq!((pool.hits(), pool.misses()), (1, 1));
+
+        // the shard is full once the first buffer is back
+        let other = pool.get();
+        drop(buf);
+        drop(other);
+        assert_eq!(pool.idle(), 1);
+        assert_eq!(pool.dropped(), 1);
+    }
+
+    #[test]
+    fn grown_buffers_are_freed() {
+        let pool = pool(4);
+        let mut buf = pool.get();
+        buf.reserve(8192);
+        drop(buf);
+        assert_eq!(pool.idle(), 0);
+        assert_eq!(pool.dropped(), 1);
+    }
+
+    #[test]
+    fn node_local() {
+        let pool = node_pool(4, 2);
+        assert_eq!(pool.shards.len(), 2);
+
+        let buf = pool.get_on(Some(1));
+        drop(buf);
+        // the buffer of node 1 is not given to node 0
+        let buf = pool.get_on(Some(0));
+        assert_eq!((pool.hits(), pool.misses()), (0, 2));
+        drop(buf);
+        let buf = pool.get_on(Some(1));
+        assert_eq!(pool.hits(), 1);
+        assert_eq!(pool.remote_hits(), 0);
+        drop(buf);
+    }
+
+    #[test]
+    fn remote_hits() {
+        let pool = pool(4);
+        drop(pool.get_on(Some(0)));
+        drop(pool.get_on(Some(1)));
+        assert_eq!((pool.hits(), pool.remote_hits()), (1, 1));
+    }
+}
@@ -0,0 +1,170 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Bypass hints of the allowed transactions
+//!
+//! The OPTIONS responses advertise the hint with its ttl. A client willing
+//! to use it sends the same header, with any value, in its REQMOD and
+//! RESPMOD requests; the allowed transactions of those clients to a trusted
+//! static origin then get `X-ICAP-Bypass-Hint: ttl=<secs>`, meaning that the
+//! proxy may skip ICAP for that origin during the ttl. Legacy clients never
+//! opt in, so they never get the hint.
+
+use http::{HeaderValue, StatusCode, header};
+
+use crate::config::server::bypass_hint::BypassHintConfig;
+use crate::modules::ModuleError;
+use crate::modules::antivirus::HEADER_VIRUS_ID;
+use crate::modules::content_filter::HEADER_RULE_ID;
+use crate::modules::matcher::DomainSuffixMatcher;
+use crate::modules::url_normalize::normalize_host;
+use crate::protocol::common::{IcapRequest, IcapResponse};
+
+/// Header of the hint, also used by the clients to opt in
+pub const HEADER_BYPASS_HINT: &str = "x-icap-bypass-hint";
+
+/// Origins of the trusted static categories
+pub struct BypassHints {
+    value: HeaderValue,
+    origins: DomainSuffixMatcher,
+}
+
+impl BypassHints {
+    pub fn new(config: &BypassHintConfig) -> Result<Self, ModuleError> {
+        let origins = DomainSuffixMatcher::new(config.trusted_static.iter().flat_map(
+            |(category, domains)| domains.iter().map(move |d| (category.clone(), d.clone())),
+        ))?;
+        let value = HeaderValue::from_str(&format!("ttl={}", config.ttl.as_secs()))
+            .map_err(|e| ModuleError::InitFailed(format!("invalid bypass hint: {e}")))?;
+        Ok(BypassHints { value, origins })
+    }
+
+    /// The value of the hint, advertised in the OPTIONS responses
+    pub fn advertised(&self) -> &str {
+        self.value.to_str().unwrap_or_default()
+    }
+
+    /// Category of the trusted static origin of the request, if it is one
+    pub fn category(&self, request: &IcapRequest) -> Option<&str> {
+        let host = request
+            .encapsulated
+            .as_ref()?
+            .req_hdr
+            .as_ref()?
+            .get(header::HOST)?
+            .to_str()
+            .ok()?;
+        self.origins
+            .find(&normalize_host(host))
+            .map(|m| m.rule_id.as_str())
+    }
+
+    /// Add the hint to the response if the client opted in, the message is
+    /// allowed unmodified and the origin is trusted static
+    pub fn apply(&self, request: &IcapRequest, response: &mut IcapResponse) -> bool {
+        if !request.headers.contains_key(HEADER_BYPASS_HINT)
+            || !is_allowed(response)
+            || self.category(request).is_none()
+        {
+            return false;
+        }
+        response
+            .headers
+            .insert(HEADER_BYPASS_HINT, self.value.clone());
+        true
+    }
+}
+
+fn is_allowed(response: &IcapResponse) -> bool {
+    let allowed = match response.status {
+        StatusCode::NO_CONTENT => true,
+        // a 200 without body is how the fallback scanners allow a message
+        StatusCode::OK => response.body.is_empty(),
+        _ => false,
+    };
+    allowed
+        && !response.headers.contains_key(HEADER_RULE_ID)
+        && !response.headers.contains_key(HEADER_VIRUS_ID)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use http::{HeaderMap, Version};
+
+    use crate::protocol::common::{EncapsulatedData, IcapMethod};
+    use crate::protocol::response_generator::IcapResponseGenerator;
+
+    fn hints() -> BypassHints {
+        let mut config = BypassHintConfig::default();
+        config.trusted_static.insert(
+            "cdn".to_string(),
+            vec!["cdn.example.net".to_string(), "*.akamaized.net".to_string()],
+        );
+        BypassHints::new(&config).unwrap()
+    }
+
+    fn request(host: &str, opt_in: bool) -> IcapRequest {
+        let mut headers = HeaderMap::new();
+        if opt_in {
+            headers.insert(HEADER_BYPASS_HINT, "1".parse().unwrap());
+        }
+        let mut req_hdr = HeaderMap::new();
+        req_hdr.insert(header::HOST, host.parse().unwrap());
+        IcapRequest {
+            method: IcapMethod::Reqmod,
+            uri: "icap://icap.example.net/reqmod".parse().unwrap(),
+            version: Version::HTTP_11,
+            headers,
+            body: Bytes::new(),
+            encapsulated: Some(EncapsulatedData {
+                req_hdr: Some(req_hdr),
+                req_body: None,
+                res_hdr: None,
+                res_body: None,
+                null_body: true,
+                trailers: None,
+                ieof: false,
+                req_line: None,
+                status_line: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn hint() {
+        let hints = hints();
+        let generator = IcapResponseGenerator::default();
+        assert_eq!(hints.advertised(), "ttl=300");
+
+        let request = request("IMG.cdn.example.net:443", true);
+        assert_eq!(hints.category(&request), Some("cdn"));
+        let mut response = generator.no_modifications(None);
+        assert!(hints.apply(&request, &mut response));
+        assert_eq!(response.headers.get(HEADER_BYPASS_HINT).unwrap(), "ttl=300");
+
+        // blocked
+        let mut response = generator.forbidden(Some("blocked"));
+        assert!(!hints.apply(&request, &mut response));
+        assert!(!response.headers.contains_key(HEADER_BYPASS_HINT));
+    }
+
+    #[test]
+    fn no_hint() {
+        let hints = hints();
+        let generator = IcapResponseGenerator::default();
+
+        // legacy client
+        let mut response = generator.no_modifications(None);
+        assert!(!hints.apply(&request("cdn.example.net", false), &mut response));
+
+        // not a trusted static origin
+        let request = request("example.net", true);
+        assert_eq!(hints.category(&request), None);
+        assert!(!hints.apply(&request, &mut response));
+        assert!(!response.headers.contains_key(HEADER_BYPASS_HINT));
+    }
+}
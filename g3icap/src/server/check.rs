@@ -0,0 +1,169 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Deep config test
+//!
+//! `--test-config` only checks the format of the config file. With `--deep`
+//! the server is created as it would be, its lists loaded and its modules
+//! initialized, on a runtime of its own, so that a config starting a module
+//! which fails, like a missing signature database or a broken script, is
+//! caught before the daemon is restarted with it. No socket is bound.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use super::IcapServer;
+use crate::config::server::pipelines::PipelinesConfig;
+use crate::server::modules::ServerModules;
+
+/// Result of the check of a component
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComponentCheck {
+    /// Component, like `module antivirus` or `pipeline default`
+    pub component: String,
+    /// Why the component would fail, `None` if it is ok
+    pub error: Option<String>,
+}
+
+/// Results of a deep config test
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CheckReport {
+    pub components: Vec<ComponentCheck>,
+}
+
+impl CheckReport {
+    fn push<E: fmt::Display>(&mut self, component: String, result: Result<(), E>) {
+        self.components.push(ComponentCheck {
+            component,
+            error: result.err().map(|e| format!("{e:#}")),
+        });
+    }
+
+    /// Check if no component would fail
+    pub fn is_ok(&self) -> bool {
+        self.components.iter().all(|c| c.error.is_none())
+    }
+
+    /// The components which would fail
+    pub fn failed(&self) -> impl Iterator<Item = &ComponentCheck> {
+        self.components.iter().filter(|c| c.error.is_some())
+    }
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in &self.components {
+            match &c.error {
+                Some(e) => writeln!(f, "FAIL {}: {e}", c.component)?,
+                None => writeln!(f, "ok   {}", c.component)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl IcapServer {
+    /// Create the resources of the server the way `start` does, without
+    /// binding the listeners, and report the ones which would fail
+    pub async fn check(&self) -> CheckReport {
+        let mut report = CheckReport::default();
+
+        if let Some(blocklist) = &self.blocklist {
+            report.push("blocklist".to_string(), blocklist.load().await);
+        }
+        if self.config.is_tls_enabled() {
+            report.push(
+                "tls".to_string(),
+                super::tls::build_acceptor(&self.config).map(|_| ()),
+            );
+        }
+
+        let modules = ServerModules::check(&self.config, self.blocklist.clone()).await;
+        let loaded: HashMap<&str, bool> = modules
+            .iter()
+            .map(|(name, result)| (name.as_str(), result.is_ok()))
+            .collect();
+        for (name, result) in &modules {
+            report.push(format!("module {name}"), result.as_ref().map(|_| ()));
+        }
+        for (pipeline, result) in check_pipelines(&self.config.pipelines, &loaded) {
+            report.push(format!("pipeline {pipeline}"), result);
+        }
+        report
+    }
+}
+
+/// Check that the stages of each pipeline have a module to run
+///
+/// `loaded` tells if each configured module initialized. A stage without a
+/// module would be skipped by all the transactions of the pipeline.
+fn check_pipelines(
+    config: &PipelinesConfig,
+    loaded: &HashMap<&str, bool>,
+) -> Vec<(String, Result<(), String>)> {
+    let mut names: Vec<&String> = config.pipelines.keys().collect();
+    names.sort();
+    names
+        .into_iter()
+        .map(|name| {
+            let result = config.pipelines[name]
+                .iter()
+                .map(|stage| match loaded.get(stage.as_str()) {
+                    Some(true) => Ok(()),
+                    Some(false) => Err(format!("module of stage {} failed", stage.as_str())),
+                    None => Err(format!("no module for stage {}", stage.as_str())),
+                })
+                .collect::<Result<(), String>>();
+            (name.clone(), result)
+        })
+        .collect()
+}
+
+/// Create the server of the loaded config and check it
+pub async fn run() -> anyhow::Result<CheckReport> {
+    let config = crate::serve::server_config()?;
+    let server = IcapServer::new_with_config(config)
+        .map_err(|e| anyhow::anyhow!("Failed to create ICAP server: {}", e))?;
+    Ok(server.check().await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::server::pipelines::PipelineStage;
+
+    #[test]
+    fn pipeline_stages() {
+        let mut config = PipelinesConfig::default();
+        config.pipelines.insert(
+            "default".to_string(),
+            vec![PipelineStage::ContentFilter, PipelineStage::Antivirus],
+        );
+        config
+            .pipelines
+            .insert("strict".to_string(), vec![PipelineStage::Waf]);
+        config
+            .pipelines
+            .insert("lenient".to_string(), vec![PipelineStage::ContentFilter]);
+        let loaded = HashMap::from([("content_filter", true), ("antivirus", false)]);
+
+        let results = check_pipelines(&config, &loaded);
+        let names: Vec<&str> = results.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["default", "lenient", "strict"]);
+        assert_eq!(
+            results[0].1,
+            Err("module of stage antivirus failed".to_string())
+        );
+        assert_eq!(results[1].1, Ok(()));
+        assert_eq!(results[2].1, Err("no module for stage waf".to_string()));
+
+        let mut report = CheckReport::default();
+        for (name, result) in results {
+            report.push(format!("pipeline {name}"), result);
+        }
+        assert!(!report.is_ok());
+        assert_eq!(report.failed().count(), 2);
+    }
+}
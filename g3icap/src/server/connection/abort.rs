@@ -0,0 +1,64 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Client abort detection
+//!
+//! A client that sent its whole request has nothing more to send until it
+//! gets the response, so the connection is watched while the request is
+//! processed, and any end of it means the client went away.
+
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Check if an IO error means the client went away
+pub(super) fn is_abort(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::UnexpectedEof
+    )
+}
+
+/// Wait until the client closes the connection
+///
+/// Data sent in the meantime is discarded, as requests are not pipelined.
+pub(super) async fn closed<S>(stream: &mut S) -> io::Error
+where
+    S: AsyncRead + Unpin + ?Sized,
+{
+    let mut buf = [0u8; 512];
+    loop {
+        match stream.read(&mut buf).await {
+            Ok(0) => return io::Error::from(io::ErrorKind::UnexpectedEof),
+            Ok(_) => continue,
+            Err(e) => return e,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn closed_on_eof() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        client.write_all(b"unexpected").await.unwrap();
+        drop(client);
+        let e = closed(&mut server).await;
+        assert!(is_abort(&e));
+    }
+
+    #[tokio::test]
+    async fn open_connection_is_not_closed() {
+        let (_client, mut server) = tokio::io::duplex(64);
+        let wait = tokio::time::timeout(std::time::Duration::from_millis(20), closed(&mut server));
+        assert!(wait.await.is_err());
+    }
+}
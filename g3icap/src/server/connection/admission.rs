@@ -0,0 +1,532 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Early admission of the requests
+//!
+//! Once the ICAP header and the encapsulated HTTP header are received, the
+//! Content-Length of the HTTP header tells how large the body will be. A
+//! message that can only exceed the limits is rejected before its body is
+//! read, the read buffer is sized for the rest, and the body is known to be
+//! complete when its last chunk is received.
+//!
+//! A preview that does not end with an `ieof` chunk is not the whole body,
+//! as with the `Preview: 0` a proxy sends for a client waiting for a 100
+//! Continue before its upload. The server needs the whole body to scan it,
+//! so the preview is answered with a 100 Continue and its last chunk is
+//! dropped, the rest of the body being read as if sent along the preview.
+//!
+//! The limits are checked on the received data as it is read, so no more than
+//! a read buffer past a limit is ever held. A client that overruns a limit by
+//! far is not answered, its connection is closed with the rest of its data
+//! unread, which makes the kernel reset it.
+
+use bytes::BytesMut;
+
+use crate::config::server::admission::AdmissionConfig;
+use crate::protocol::limits::{LimitExceeded, ProtocolLimits};
+
+/// Overrun factor of a limit from which the connection is reset
+const EGREGIOUS_OVERRUN: usize = 4;
+
+/// A limit exceeded while reading a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Overrun {
+    pub(super) limit: LimitExceeded,
+    /// Size received or announced for the limit
+    pub(super) size: usize,
+}
+
+impl Overrun {
+    fn new(limit: LimitExceeded, size: usize) -> Self {
+        Overrun { limit, size }
+    }
+
+    /// Check if the limit is overrun by far, so the client is not answered
+    pub(super) fn is_egregious(&self) -> bool {
+        self.size / EGREGIOUS_OVERRUN >= self.limit.limit()
+    }
+}
+
+/// Check the received part of a message before its plan is known
+pub(super) fn check_partial(data: &[u8], limits: &ProtocolLimits) -> Result<(), Overrun> {
+    let size = data.len();
+    limits
+        .check_message_size(size)
+        .and_then(|_| limits.check_partial_header(data))
+        .map_err(|e| Overrun::new(e, size))
+}
+
+/// How the rest of a message is read and processed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct BodyPlan {
+    /// Size of the ICAP header section
+    pub(super) header_len: usize,
+    /// Offset of the chunked encapsulated body in the message, if any
+    pub(super) body_start: Option<usize>,
+    /// Body size declared by the Content-Length of the encapsulated HTTP header
+    pub(super) declared_size: Option<u64>,
+    /// Size of the preview, until the rest of the body is asked for
+    pub(super) preview: Option<usize>,
+}
+
+impl BodyPlan {
+    /// Plan the message from its received part
+    ///
+    /// Returns `None` until the encapsulated HTTP headers are received. The
+    /// announced preview and HTTP header sizes are checked as soon as the
+    /// ICAP header is received.
+    pub(super) fn new(data: &[u8], limits: &ProtocolLimits) -> Result<Option<Self>, Overrun> {
+        let Some(header_len) = find(data, b"\r\n\r\n").map(|end| end + 4) else {
+            return Ok(None);
+        };
+        // the OPTIONS fast path, there is no body to wait for or to buffer
+        if data.starts_with(b"OPTIONS ") {
+            return Ok(Some(BodyPlan {
+                header_len,
+                body_start: None,
+                declared_size: None,
+                preview: None,
+            }));
+        }
+        let header = &data[..header_len];
+        let preview = header_value(header, "preview").and_then(|v| v.trim().parse().ok());
+        if let Some(preview) = preview {
+            limits
+                .check_preview_size(preview)
+                .map_err(|e| Overrun::new(e, preview))?;
+        }
+        let sections = encapsulated_sections(header);
+        let mut offsets = sections
+            .iter()
+            .map(|(_, offset)| *offset)
+            .collect::<Vec<_>>();
+        offsets.sort_unstable();
+        for (name, offset) in &sections {
+            if !name.ends_with("-hdr") {
+                continue;
+            }
+            let len = offsets
+                .iter()
+                .find(|o| **o > *offset)
+                .map(|next| next - offset)
+                .unwrap_or(0);
+            limits
+                .check_header_bytes(len)
+                .map_err(|e| Overrun::new(e, len))?;
+        }
+        let body = sections
+            .iter()
+            .find(|(name, _)| name.ends_with("-body"))
+            .map(|(name, offset)| (name.as_str(), *offset));
+
+        // the HTTP header sections end where the body starts
+        let headers_end = match body {
+            Some((_, offset)) => offset,
+            None => sections
+                .iter()
+                .map(|(_, offset)| *offset)
+                .max()
+                .unwrap_or(0),
+        };
+        if data.len() < header_len + headers_end {
+            return Ok(None);
+        }
+
+        let body_start = match body {
+            Some(("null-body", _)) | None => None,
+            Some((_, offset)) => Some(header_len + offset),
+        };
+        // the HTTP header right before the body is the one describing it
+        let declared_size = body_start.and_then(|_| {
+            let start = sections
+                .iter()
+                .filter(|(name, offset)| name.ends_with("-hdr") && *offset < headers_end)
+                .map(|(_, offset)| *offset)
+                .max()?;
+            content_length(&data[header_len + start..header_len + headers_end])
+        });
+        Ok(Some(BodyPlan {
+            header_len,
+            body_start,
+            declared_size,
+            preview: body_start.and(preview),
+        }))
+    }
+
+    /// Reject the message before its body is read if it can only be too large
+    pub(super) fn admit(&self, limits: &ProtocolLimits) -> Result<(), Overrun> {
+        if let (Some(start), Some(size)) = (self.body_start, self.declared_size) {
+            let size = usize::try_from(size).unwrap_or(usize::MAX);
+            let total = start.saturating_add(size);
+            limits
+                .check_message_size(total)
+                .map_err(|e| Overrun::new(e, total))?;
+            let encapsulated = total - self.header_len;
+            limits
+                .check_encapsulated_size(encapsulated)
+                .map_err(|e| Overrun::new(e, encapsulated))?;
+        }
+        Ok(())
+    }
+
+    /// Check the received part of the message against the limits
+    pub(super) fn check_received(
+        &self,
+        data: &[u8],
+        limits: &ProtocolLimits,
+    ) -> Result<(), Overrun> {
+        limits
+            .check_message_size(data.len())
+            .map_err(|e| Overrun::new(e, data.len()))?;
+        let encapsulated = data.len() - self.header_len;
+        limits
+            .check_encapsulated_size(encapsulated)
+            .map_err(|e| Overrun::new(e, encapsulated))
+    }
+
+    /// Number of bytes to reserve for the rest of the message
+    pub(super) fn reserve(&self, config: &AdmissionConfig) -> usize {
+        self.declared_size
+            .map(|size| usize::try_from(size).unwrap_or(usize::MAX))
+            .unwrap_or(0)
+            .min(config.preallocate_limit)
+    }
+
+    /// Check if the body is too large to be sent to the antivirus module
+    pub(super) fn skip_scan(&self, config: &AdmissionConfig) -> bool {
+        match (self.declared_size, config.max_scan_size) {
+            (Some(size), Some(limit)) => size > limit,
+            _ => false,
+        }
+    }
+
+    /// Check if the whole message is received
+    pub(super) fn is_complete(&self, data: &[u8]) -> bool {
+        match self.body_start {
+            None => true,
+            Some(start) => data.len() >= start && last_chunk(&data[start..]).is_some(),
+        }
+    }
+
+    /// Check if the received preview is complete but not the whole body
+    pub(super) fn needs_continue(&self, data: &[u8]) -> bool {
+        let (Some(start), Some(_)) = (self.body_start, self.preview) else {
+            return false;
+        };
+        data.len() >= start && last_chunk(&data[start..]).is_some_and(|last| !last.ieof)
+    }
+
+    /// Drop the last chunk of the preview so the rest of the body follows it
+    ///
+    /// Nothing is dropped from a message that does not need a 100 Continue.
+    pub(super) fn continue_body(&mut self, data: &mut BytesMut) -> bool {
+        if !self.needs_continue(data) {
+            return false;
+        }
+        if let Some(start) = self.body_start
+            && let Some(last) = last_chunk(&data[start..])
+        {
+            data.truncate(start + last.start);
+        }
+        self.preview = None;
+        true
+    }
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Get the ICAP header section, once it is received
+pub(super) fn icap_header(data: &[u8]) -> Option<&[u8]> {
+    find(data, b"\r\n\r\n").map(|end| &data[..end + 4])
+}
+
+/// Get the value of a field of the ICAP header section
+pub(super) fn header_value(header: &[u8], name: &str) -> Option<String> {
+    let header = String::from_utf8_lossy(header);
+    header.lines().skip(1).find_map(|line| {
+        let (n, value) = line.split_once(':')?;
+        n.trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.to_string())
+    })
+}
+
+/// Get the entries of the Encapsulated header of the ICAP header section
+fn encapsulated_sections(header: &[u8]) -> Vec<(String, usize)> {
+    let Some(value) = header_value(header, "encapsulated") else {
+        return Vec::new();
+    };
+    value
+        .split(',')
+        .filter_map(|entry| {
+            let (name, offset) = entry.split_once('=')?;
+            let offset = offset.trim().parse().ok()?;
+            Some((name.trim().to_ascii_lowercase(), offset))
+        })
+        .collect()
+}
+
+/// Get the Content-Length of an HTTP header section
+///
+/// A message with a Transfer-Encoding, or with conflicting Content-Length
+/// fields, has no usable length.
+fn content_length(header: &[u8]) -> Option<u64> {
+    let header = String::from_utf8_lossy(header);
+    let mut length = None;
+    for line in header.lines().skip(1) {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let name = name.trim();
+        if name.eq_ignore_ascii_case("transfer-encoding") {
+            return None;
+        }
+        if name.eq_ignore_ascii_case("content-length") {
+            let value = value.trim().parse::<u64>().ok()?;
+            if length.is_some_and(|l| l != value) {
+                return None;
+            }
+            length = Some(value);
+        }
+    }
+    length
+}
+
+/// The last chunk of a complete chunked body
+struct LastChunk {
+    /// Offset of the zero sized chunk
+    start: usize,
+    /// The chunk has the `ieof` extension
+    ieof: bool,
+}
+
+/// Find the last chunk of a chunked body, if the body is complete
+fn last_chunk(body: &[u8]) -> Option<LastChunk> {
+    let mut pos = 0;
+    loop {
+        let line_len = find(&body[pos..], b"\r\n")?;
+        let line = std::str::from_utf8(&body[pos..pos + line_len]).ok()?;
+        // chunk extensions such as ieof follow the size
+        let mut parts = line.split(';');
+        let size = parts.next()?.trim();
+        let size = usize::from_str_radix(size, 16).ok()?;
+        if size == 0 {
+            let last = LastChunk {
+                start: pos,
+                ieof: parts.any(|ext| ext.trim().eq_ignore_ascii_case("ieof")),
+            };
+            pos += line_len + 2;
+            // the trailer section, if any, must be complete too
+            let complete =
+                body[pos..].starts_with(b"\r\n") || find(&body[pos..], b"\r\n\r\n").is_some();
+            return complete.then_some(last);
+        }
+        pos += line_len + 2;
+        pos = pos.checked_add(size)?.checked_add(2)?;
+        if pos > body.len() {
+            return None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "RESPMOD icap://icap.example.net/av ICAP/1.0\r\n\
+                          Host: icap.example.net\r\n\
+                          Encapsulated: res-hdr=0, res-body=50\r\n\
+                          \r\n";
+    const HTTP_HEADER: &str = "HTTP/1.1 200 OK\r\n\
+                               Content-Length: 11\r\n\
+                               Server: t\r\n\
+                               \r\n";
+
+    fn plan_of(data: &[u8]) -> Option<BodyPlan> {
+        BodyPlan::new(data, &ProtocolLimits::default()).unwrap()
+    }
+
+    #[test]
+    fn plan_from_content_length() {
+        assert_eq!(HTTP_HEADER.len(), 50);
+        let mut data = HEADER.as_bytes().to_vec();
+        assert_eq!(plan_of(&data), None);
+        data.extend_from_slice(HTTP_HEADER.as_bytes());
+        let plan = plan_of(&data).unwrap();
+        assert_eq!(plan.body_start, Some(data.len()));
+        assert_eq!(plan.declared_size, Some(11));
+
+        assert!(!plan.is_complete(&data));
+        data.extend_from_slice(b"5\r\nhello\r\n6\r\n wor");
+        assert!(!plan.is_complete(&data));
+        data.extend_from_slice(b"ld\r\n0; ieof\r\n\r\n");
+        assert!(plan.is_complete(&data));
+
+        let config = AdmissionConfig {
+            preallocate_limit: 8,
+            max_scan_size: Some(10),
+        };
+        assert_eq!(plan.reserve(&config), 8);
+        assert!(plan.skip_scan(&config));
+        assert!(plan.admit(&ProtocolLimits::default()).is_ok());
+        let limits = ProtocolLimits {
+            max_message_size: plan.body_start.unwrap() + 10,
+            ..Default::default()
+        };
+        assert!(plan.admit(&limits).is_err());
+    }
+
+    #[test]
+    fn header_field() {
+        let data = format!("{HEADER}{HTTP_HEADER}");
+        assert!(icap_header(&HEADER.as_bytes()[..HEADER.len() - 2]).is_none());
+        let header = icap_header(data.as_bytes()).unwrap();
+        assert_eq!(header, HEADER.as_bytes());
+        assert_eq!(
+            header_value(header, "host").as_deref().map(str::trim),
+            Some("icap.example.net")
+        );
+        assert!(header_value(header, "content-length").is_none());
+    }
+
+    #[test]
+    fn plan_without_body() {
+        let data = "OPTIONS icap://icap.example.net/av ICAP/1.0\r\nHost: icap.example.net\r\n\r\n";
+        let plan = plan_of(data.as_bytes()).unwrap();
+        assert_eq!(plan.body_start, None);
+        assert!(plan.is_complete(data.as_bytes()));
+
+        // a body announced by an OPTIONS request is not waited for
+        let data = "OPTIONS icap://icap.example.net/av ICAP/1.0\r\nHost: icap.example.net\r\n\
+                    Encapsulated: opt-body=0\r\n\r\n";
+        let plan = plan_of(data.as_bytes()).unwrap();
+        assert_eq!(plan.body_start, None);
+        assert!(plan.is_complete(data.as_bytes()));
+        assert_eq!(plan.reserve(&AdmissionConfig::default()), 0);
+
+        let data = HEADER.replace("res-body=50", "null-body=50") + HTTP_HEADER;
+        let plan = plan_of(data.as_bytes()).unwrap();
+        assert_eq!(plan.body_start, None);
+        assert_eq!(plan.declared_size, None);
+    }
+
+    #[test]
+    fn overrun_while_reading() {
+        // the HTTP header section is announced larger than allowed
+        let limits = ProtocolLimits {
+            max_header_bytes: 40,
+            ..Default::default()
+        };
+        let e = BodyPlan::new(HEADER.as_bytes(), &limits).unwrap_err();
+        assert_eq!(e.limit, LimitExceeded::HeaderBytes { limit: 40 });
+        assert_eq!(e.size, 50);
+        assert!(!e.is_egregious());
+
+        let data = HEADER.replace(
+            "Host: icap.example.net\r\n",
+            "Host: icap.example.net\r\nPreview: 4096\r\n",
+        );
+        let limits = ProtocolLimits {
+            max_preview_size: 1024,
+            ..Default::default()
+        };
+        let e = BodyPlan::new(data.as_bytes(), &limits).unwrap_err();
+        assert_eq!(
+            e.limit,
+            LimitExceeded::PreviewSize {
+                size: 4096,
+                limit: 1024
+            }
+        );
+        assert!(e.is_egregious());
+
+        // the body is announced, then sent, larger than allowed
+        let mut data = (HEADER.to_string() + HTTP_HEADER).into_bytes();
+        let limits = ProtocolLimits {
+            max_encapsulated_size: 56,
+            ..Default::default()
+        };
+        let plan = BodyPlan::new(&data, &limits).unwrap().unwrap();
+        let e = plan.admit(&limits).unwrap_err();
+        assert_eq!(e.limit, LimitExceeded::EncapsulatedSize { limit: 56 });
+        assert_eq!(e.size, 61);
+        assert!(plan.check_received(&data, &limits).is_ok());
+        data.extend_from_slice(b"5\r\nhello\r\n");
+        assert!(plan.check_received(&data, &limits).is_err());
+
+        let limits = ProtocolLimits {
+            max_message_size: 64,
+            ..Default::default()
+        };
+        let e = check_partial(&data, &limits).unwrap_err();
+        assert_eq!(e.limit, LimitExceeded::MessageSize { limit: 64 });
+        assert_eq!(e.size, data.len());
+    }
+
+    #[test]
+    fn preview_continue() {
+        // a curl upload waiting for a 100 Continue, sent with a zero preview
+        let http_header = "POST /upload HTTP/1.1\r\n\
+                           Host: files.example.net\r\n\
+                           User-Agent: curl/8.5.0\r\n\
+                           Content-Length: 11\r\n\
+                           Expect: 100-continue\r\n\
+                           \r\n";
+        let header = format!(
+            "REQMOD icap://icap.example.net/av ICAP/1.0\r\n\
+             Host: icap.example.net\r\n\
+             Preview: 0\r\n\
+             Encapsulated: req-hdr=0, req-body={}\r\n\
+             \r\n",
+            http_header.len()
+        );
+        let mut data = BytesMut::from((header + http_header).as_bytes());
+        let mut plan = plan_of(&data).unwrap();
+        assert_eq!(plan.preview, Some(0));
+        assert_eq!(plan.declared_size, Some(11));
+        data.extend_from_slice(b"0\r\n\r\n");
+        assert!(plan.is_complete(&data));
+        assert!(plan.needs_continue(&data));
+
+        // the rest of the body follows the dropped last chunk
+        assert!(plan.continue_body(&mut data));
+        assert_eq!(data.len(), plan.body_start.unwrap());
+        assert!(!plan.is_complete(&data));
+        data.extend_from_slice(b"b\r\nhello world\r\n0\r\n\r\n");
+        assert!(plan.is_complete(&data));
+        assert!(!plan.needs_continue(&data));
+        assert!(!plan.continue_body(&mut data));
+
+        // a preview ending with ieof is the whole body
+        let mut data = BytesMut::from(HEADER.replace("Host:", "Preview: 11\r\nHost:").as_bytes());
+        data.extend_from_slice(HTTP_HEADER.as_bytes());
+        let mut plan = plan_of(&data).unwrap();
+        assert_eq!(plan.preview, Some(11));
+        data.extend_from_slice(b"b\r\nhello world\r\n0; ieof\r\n\r\n");
+        assert!(plan.is_complete(&data));
+        assert!(!plan.continue_body(&mut data));
+        assert!(plan.is_complete(&data));
+    }
+
+    #[test]
+    fn unusable_content_length() {
+        assert_eq!(
+            content_length(b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\n"),
+            Some(5)
+        );
+        assert_eq!(
+            content_length(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nContent-Length: 6\r\n\r\n"),
+            None
+        );
+        assert_eq!(
+            content_length(
+                b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n"
+            ),
+            None
+        );
+    }
+}
@@ -0,0 +1,292 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Capabilities advertised in the OPTIONS responses of the services
+//!
+//! The response reflects the modules serving the service when the request
+//! is received, as currently registered in the module registry: their names
+//! and versions, and the preview and transfer settings they ask for. A
+//! service registered in the service manager further sets the methods, the
+//! preview size and the maximum connections from its config. The preview is
+//! never over the preview size limit of the server.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+use crate::modules::{IcapModule, ModuleRegistry, TransferSettings};
+use crate::protocol::common::{IcapMethod, IcapResponse};
+use crate::protocol::limits::ProtocolLimits;
+use crate::protocol::response_generator::IcapResponseGenerator;
+use crate::services::ServiceConfig;
+
+/// Header of the OPTIONS responses listing the modules of the service
+pub(super) const HEADER_MODULES: &str = "x-modules";
+
+/// Seconds the clients may keep the OPTIONS response
+const OPTIONS_TTL: u64 = 3600;
+
+/// A module serving the service
+struct ServiceModule {
+    name: String,
+    version: String,
+    healthy: bool,
+    transfer: TransferSettings,
+}
+
+impl ServiceModule {
+    /// The module of the same name currently registered, if it was replaced
+    fn current(module: &Arc<dyn IcapModule>) -> Self {
+        let module = ModuleRegistry::global()
+            .get_module(module.name())
+            .unwrap_or_else(|| module.clone());
+        ServiceModule {
+            name: module.name().to_string(),
+            version: module.version().to_string(),
+            healthy: module.is_healthy(),
+            transfer: module.transfer_settings(),
+        }
+    }
+}
+
+/// The OPTIONS response of a service served by the modules
+pub(super) fn options(
+    generator: &IcapResponseGenerator,
+    modules: &[Arc<dyn IcapModule>],
+    service: Option<&ServiceConfig>,
+    limits: &ProtocolLimits,
+) -> IcapResponse {
+    let modules: Vec<ServiceModule> = modules.iter().map(ServiceModule::current).collect();
+    let methods = match service {
+        Some(service) => {
+            let mut methods = vec![IcapMethod::Options];
+            methods.extend(
+                service
+                    .methods
+                    .iter()
+                    .filter(|m| **m != IcapMethod::Options)
+                    .cloned(),
+            );
+            methods
+        }
+        None => vec![IcapMethod::Options, IcapMethod::Reqmod, IcapMethod::Respmod],
+    };
+    generator.options_response(&methods, capabilities(&modules, service, limits))
+}
+
+fn capabilities(
+    modules: &[ServiceModule],
+    service: Option<&ServiceConfig>,
+    limits: &ProtocolLimits,
+) -> HashMap<String, String> {
+    let mut capabilities = HashMap::new();
+    capabilities.insert("options-ttl".to_string(), OPTIONS_TTL.to_string());
+    capabilities.insert("allow".to_string(), "204, 206".to_string());
+    if let Some(service) = service {
+        capabilities.insert(
+            "max-connections".to_string(),
+            service.max_connections.to_string(),
+        );
+    }
+
+    // the largest preview wanted by a module, the others ignoring the rest
+    let preview = modules
+        .iter()
+        .filter_map(|m| m.transfer.preview)
+        .max()
+        .map(|p| p.min(limits.max_preview_size))
+        .map(|p| service.map_or(p, |s| p.min(s.preview_size)));
+    if let Some(preview) = preview {
+        capabilities.insert("preview".to_string(), preview.to_string());
+    }
+
+    let transfer = transfer(modules);
+    for (header, extensions) in [
+        ("transfer-preview", &transfer.preview_extensions),
+        ("transfer-ignore", &transfer.ignore_extensions),
+        ("transfer-complete", &transfer.complete_extensions),
+    ] {
+        if !extensions.is_empty() {
+            capabilities.insert(header.to_string(), extensions.join(", "));
+        }
+    }
+
+    if !modules.is_empty() {
+        let names = modules
+            .iter()
+            .map(|m| {
+                if m.healthy {
+                    format!("{}/{}", m.name, m.version)
+                } else {
+                    format!("{}/{};unhealthy", m.name, m.version)
+                }
+            })
+            .collect::<Vec<_>>();
+        capabilities.insert(HEADER_MODULES.to_string(), names.join(", "));
+    }
+    capabilities
+}
+
+/// Transfer settings satisfying all the modules
+///
+/// A file is sent in full if a module wants it complete, and only ignored
+/// if all the modules ignore it. Only one of the lists keeps the `*`.
+fn transfer(modules: &[ServiceModule]) -> TransferSettings {
+    let complete: BTreeSet<&String> = modules
+        .iter()
+        .flat_map(|m| &m.transfer.complete_extensions)
+        .collect();
+    let ignore: BTreeSet<&String> = modules
+        .iter()
+        .flat_map(|m| &m.transfer.ignore_extensions)
+        .filter(|e| {
+            modules
+                .iter()
+                .all(|m| m.transfer.ignore_extensions.contains(e))
+        })
+        .filter(|e| !complete.contains(e))
+        .collect();
+    let preview: BTreeSet<&String> = modules
+        .iter()
+        .flat_map(|m| &m.transfer.preview_extensions)
+        .filter(|e| !complete.contains(e) && !ignore.contains(e))
+        .collect();
+
+    let has_wildcard = |set: &BTreeSet<&String>| set.iter().any(|e| *e == "*");
+    let wildcard_taken = [has_wildcard(&complete), has_wildcard(&preview)];
+    let keep = |set: BTreeSet<&String>, before: &[bool]| -> Vec<String> {
+        set.into_iter()
+            .filter(|e| *e != "*" || !before.contains(&true))
+            .cloned()
+            .collect()
+    };
+    TransferSettings {
+        preview: None,
+        complete_extensions: keep(complete, &[]),
+        preview_extensions: keep(preview, &wildcard_taken[..1]),
+        ignore_extensions: keep(ignore, &wildcard_taken),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::services::LoadBalancingStrategy;
+
+    fn module(name: &str, transfer: TransferSettings) -> ServiceModule {
+        ServiceModule {
+            name: name.to_string(),
+            version: "1.0.0".to_string(),
+            healthy: true,
+            transfer,
+        }
+    }
+
+    fn extensions(list: &[&str]) -> Vec<String> {
+        list.iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn from_modules() {
+        let limits = ProtocolLimits::default();
+        let advertised = capabilities(&[], None, &limits);
+        assert!(!advertised.contains_key("preview"));
+        assert!(!advertised.contains_key(HEADER_MODULES));
+        assert!(!advertised.contains_key("max-connections"));
+        assert_eq!(advertised["allow"], "204, 206");
+
+        let mut waf = module(
+            "waf",
+            TransferSettings {
+                preview: Some(4096),
+                ..Default::default()
+            },
+        );
+        waf.healthy = false;
+        let modules = [module("content_filter", TransferSettings::default()), waf];
+        let advertised = capabilities(&modules, None, &limits);
+        assert_eq!(advertised["preview"], "4096");
+        assert_eq!(advertised["transfer-preview"], "*");
+        assert_eq!(
+            advertised[HEADER_MODULES],
+            "content_filter/1.0.0, waf/1.0.0;unhealthy"
+        );
+    }
+
+    #[test]
+    fn limited_by_service() {
+        let service = ServiceConfig {
+            name: "av".to_string(),
+            path: "/av".to_string(),
+            methods: vec![IcapMethod::Respmod],
+            preview_size: 512,
+            timeout: Duration::from_secs(30),
+            max_connections: 200,
+            health_check_enabled: false,
+            health_check_interval: Duration::from_secs(30),
+            load_balancing: LoadBalancingStrategy::RoundRobin,
+        };
+        let limits = ProtocolLimits {
+            max_preview_size: 256,
+            ..Default::default()
+        };
+        let modules = [module("antivirus", TransferSettings::default())];
+        let advertised = capabilities(&modules, Some(&service), &limits);
+        assert_eq!(advertised["preview"], "256");
+        assert_eq!(advertised["max-connections"], "200");
+
+        let limits = ProtocolLimits::default();
+        let advertised = capabilities(&modules, Some(&service), &limits);
+        assert_eq!(advertised["preview"], "512");
+    }
+
+    #[test]
+    fn merged_transfer() {
+        let modules = [
+            module(
+                "antivirus",
+                TransferSettings {
+                    complete_extensions: extensions(&["exe", "zip"]),
+                    ignore_extensions: extensions(&["mp4", "jpg"]),
+                    ..Default::default()
+                },
+            ),
+            module(
+                "content_filter",
+                TransferSettings {
+                    preview_extensions: extensions(&["html"]),
+                    ignore_extensions: extensions(&["*"]),
+                    ..Default::default()
+                },
+            ),
+            module(
+                "waf",
+                TransferSettings {
+                    ignore_extensions: extensions(&["jpg", "*"]),
+                    ..Default::default()
+                },
+            ),
+        ];
+        let merged = transfer(&modules);
+        assert_eq!(merged.complete_extensions, extensions(&["exe", "zip"]));
+        assert_eq!(merged.preview_extensions, extensions(&["*", "html"]));
+        assert!(merged.ignore_extensions.is_empty());
+
+        let modules = [module(
+            "antivirus",
+            TransferSettings {
+                preview_extensions: extensions(&["doc"]),
+                complete_extensions: extensions(&["*"]),
+                ignore_extensions: extensions(&["iso"]),
+                ..Default::default()
+            },
+        )];
+        let merged = transfer(&modules);
+        assert_eq!(merged.complete_extensions, extensions(&["*"]));
+        assert_eq!(merged.preview_extensions, extensions(&["doc"]));
+        assert_eq!(merged.ignore_extensions, extensions(&["iso"]));
+    }
+}
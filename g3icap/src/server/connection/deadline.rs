@@ -0,0 +1,88 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Read deadlines of a transaction
+//!
+//! The headers must be complete by a fixed deadline from the start of the
+//! transaction, however often the client sends a byte, while the body only
+//! needs to keep flowing. Both are capped by the transaction deadline.
+
+use tokio::time::Instant;
+
+use crate::config::server::timeouts::{TimeoutConfig, TimeoutKind};
+
+/// Deadlines of the transaction being read
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Deadlines {
+    config: TimeoutConfig,
+    header: Instant,
+    transaction: Instant,
+}
+
+impl Deadlines {
+    pub(super) fn new(config: TimeoutConfig, start: Instant) -> Self {
+        Deadlines {
+            config,
+            header: start + config.header,
+            transaction: start + config.transaction,
+        }
+    }
+
+    /// Deadline of the next read, and the timeout it enforces
+    pub(super) fn next_read(&self, header_received: bool, now: Instant) -> (Instant, TimeoutKind) {
+        let phase = if header_received {
+            (now + self.config.body_idle, TimeoutKind::Body)
+        } else {
+            (self.header, TimeoutKind::Header)
+        };
+        if self.transaction < phase.0 {
+            (self.transaction, TimeoutKind::Transaction)
+        } else {
+            phase
+        }
+    }
+
+    /// Deadline of processing the request
+    pub(super) fn transaction(&self) -> Instant {
+        self.transaction
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn next_read() {
+        let config = TimeoutConfig {
+            header: Duration::from_secs(10),
+            body_idle: Duration::from_secs(5),
+            transaction: Duration::from_secs(60),
+            write: Duration::from_secs(5),
+        };
+        let start = Instant::now();
+        let deadlines = Deadlines::new(config, start);
+
+        // the header deadline does not move with the reads
+        let later = start + Duration::from_secs(8);
+        assert_eq!(
+            deadlines.next_read(false, later),
+            (start + Duration::from_secs(10), TimeoutKind::Header)
+        );
+
+        // the body deadline does
+        assert_eq!(
+            deadlines.next_read(true, later),
+            (later + Duration::from_secs(5), TimeoutKind::Body)
+        );
+
+        let late = start + Duration::from_secs(58);
+        assert_eq!(
+            deadlines.next_read(true, late),
+            (start + Duration::from_secs(60), TimeoutKind::Transaction)
+        );
+    }
+}
@@ -2,25 +2,75 @@
 //!
 //! This module handles individual ICAP connections and request processing.
 
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
 
+use bytes::BytesMut;
 use slog::Logger;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::sync::CancellationToken;
 
 use g3_daemon::listen::ListenStats;
 
 use crate::error::{IcapError, IcapResult};
 use crate::log::connection::{get_logger, ConnectionEvent};
 use crate::opts::ProcArgs;
-use crate::protocol::common::{IcapRequest, IcapResponse, EncapsulatedData};
+use crate::protocol::common::{IcapMethod, IcapRequest, IcapResponse, EncapsulatedData};
 use crate::protocol::response_generator::IcapResponseGenerator;
 use crate::stats::IcapStats;
 use crate::modules::IcapModule;
-use crate::modules::content_filter::{ContentFilterModule, ContentFilterConfig};
-use crate::modules::antivirus::{AntivirusModule, AntivirusConfig};
-use crate::audit::ops::{IcapAuditOps, DefaultIcapAuditOps};
+use crate::modules::block_page::{BlockPageVars, BlockPages};
+use crate::modules::blocklist::BlocklistProvider;
+use crate::modules::escalation::EscalationTracker;
+use crate::modules::shadow::{self, ShadowModule, ShadowTarget};
+use crate::audit::logger::AuditLogger;
+use crate::audit::ops::{AuditSeverity, IcapAuditOps, DefaultIcapAuditOps};
+use crate::audit::record::AuditRecord;
+use crate::config::server::admission::AdmissionConfig;
+use crate::config::server::enforcement::{EnforcementConfig, EnforcementMode};
+use crate::config::server::escalation::CATEGORY_MALWARE;
+use crate::config::server::pipelines::PipelineStage;
+use crate::config::server::quota::QuotaAction;
+use crate::config::server::services::ServicesConfig;
+use crate::config::server::slow_client::SlowClientConfig;
+use crate::config::server::timeouts::{TimeoutConfig, TimeoutKind};
+use crate::protocol::limits::ProtocolLimits;
+use crate::protocol::parser::RequestHead;
+use crate::protocol::start_line::StatusLine;
+use crate::server::buffer_pool::{BufferPool, PooledBuffer};
+use crate::server::bypass_hint::{BypassHints, HEADER_BYPASS_HINT};
+use crate::config::server::degradation::FailMode;
+use crate::server::degradation::{DegradationLadder, Treatment};
+use crate::server::idle_reaper::{IdleGuard, IdleReaper};
+use crate::server::load_shedding::LoadShedder;
+use crate::server::modules::ServerModules;
+use crate::server::pipelines::Pipeline;
+use crate::server::quota::{HEADER_QUOTA_WARNING, QuotaTracker, QuotaUsage, QuotaUser, QuotaVerdict};
+use crate::server::slo::SloTracker;
+use crate::trace::{Span, Tracer, TransactionTrace};
+use crate::transaction::{self, HEADER_REQUEST_ID, TransactionCtx};
+
+mod abort;
+mod admission;
+mod capabilities;
+mod deadline;
+mod monitor;
+mod partial;
+mod routing;
+pub mod throughput;
+use admission::{BodyPlan, Overrun};
+use deadline::Deadlines;
+use throughput::{ConnectionThroughput, SlowClientVerdict};
+
+/// Free space made in the read buffer before each read
+const READ_CHUNK: usize = 4096;
+
+/// Interim response asking for the rest of a preview
+const CONTINUE_RESPONSE: &[u8] = b"ICAP/1.0 100 Continue\r\n\r\n";
+
+/// Size of each response write when slow client detection is enabled
+const RESPONSE_WRITE_CHUNK: usize = 16 * 1024;
 
 /// Content filtering result
 #[derive(Debug)]
@@ -34,7 +84,8 @@ enum FilterResult {
 /// HTTP request structure for filtering
 #[derive(Debug)]
 struct HttpRequest {
-    method: String,
+    method: http::Method,
+    /// URL of the request, from the request line and the Host header
     uri: String,
     headers: Vec<(String, String)>,
     body: Vec<u8>,
@@ -43,10 +94,7 @@ struct HttpRequest {
 /// HTTP response structure for scanning
 #[derive(Debug)]
 struct HttpResponse {
-    #[allow(dead_code)]
-    status_code: u16,
-    #[allow(dead_code)]
-    status_text: String,
+    status_line: StatusLine,
     headers: Vec<(String, String)>,
     body: Vec<u8>,
 }
@@ -71,10 +119,15 @@ pub struct IcapTaskContext {
     pub task_logger: Option<Logger>,
 }
 
+/// Stream of a client connection, plain TCP or TLS
+pub trait IcapStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> IcapStream for T {}
+
 /// ICAP Connection Handler
 pub struct IcapConnection {
-    /// TCP stream
-    stream: TcpStream,
+    /// Client stream
+    stream: Box<dyn IcapStream>,
     /// Peer address
     peer_addr: SocketAddr,
     /// Statistics collector
@@ -82,155 +135,92 @@ pub struct IcapConnection {
     /// Logger
     #[allow(dead_code)]
     logger: Logger,
-    /// Content filter module
-    content_filter: Option<ContentFilterModule>,
-    /// Antivirus module
-    antivirus: Option<AntivirusModule>,
+    /// Content filter module, shared by all connections of the server
+    content_filter: Option<Arc<dyn IcapModule>>,
+    /// Antivirus module, shared by all connections of the server
+    antivirus: Option<Arc<dyn IcapModule>>,
+    /// WAF module, shared by all connections of the server
+    waf: Option<Arc<dyn IcapModule>>,
+    /// Modules of the scripted services, keyed by service name
+    scripted: Arc<HashMap<String, Arc<dyn IcapModule>>>,
+    /// Content filter with candidate rules, evaluated alongside the active one
+    shadow_content_filter: Option<ShadowModule>,
+    /// Antivirus with candidate YARA rules, evaluated alongside the active one
+    shadow_antivirus: Option<ShadowModule>,
     /// Audit operations
     audit_ops: Box<dyn IcapAuditOps>,
+    /// Transaction audit log of the server
+    audit_log: Option<Arc<AuditLogger>>,
+    /// Transaction trace export of the server
+    tracer: Option<Arc<Tracer>>,
+    /// Trace of the transaction being processed, if sampled
+    trace: Option<TransactionTrace>,
     /// Response generator
     response_generator: IcapResponseGenerator,
+    /// Throughput counters of this connection
+    throughput: ConnectionThroughput,
+    /// Slow client detection thresholds of the listener
+    slow_client: Option<SlowClientConfig>,
+    /// Domain blocklist of the server
+    blocklist: Option<Arc<BlocklistProvider>>,
+    /// Hard limits of the ICAP parser
+    limits: ProtocolLimits,
+    /// Violation tracker of the server
+    escalation: Option<Arc<EscalationTracker>>,
+    /// Data consumption tracker of the server
+    quota: Option<Arc<QuotaTracker>>,
+    /// User of the transaction, whose data is counted by the quota tracker
+    quota_user: Option<QuotaUser>,
+    /// Request id of the transaction, sent back in all of its responses
+    request_id: Option<String>,
+    /// Trusted static origins getting a bypass hint
+    bypass_hints: Option<Arc<BypassHints>>,
+    /// Degradation ladder of the server
+    degradation: Option<Arc<DegradationLadder>>,
+    /// Load shedding of the server
+    load_shedding: Option<Arc<LoadShedder>>,
+    /// Response time objectives of the server
+    slo: Option<Arc<SloTracker>>,
+    /// Enforcement mode of the services
+    enforcement: EnforcementConfig,
+    /// Registered services, all paths are served if not set
+    services: Option<ServicesConfig>,
+    /// Early admission decisions from the declared body size
+    admission: AdmissionConfig,
+    /// Plan of the request being read, from its encapsulated HTTP header
+    body_plan: Option<BodyPlan>,
+    /// Limit exceeded while reading the request
+    overrun: Option<Overrun>,
+    /// Data of the request left unread, the connection is closed after it
+    unread: bool,
+    /// Read, write and transaction timeouts of the server
+    timeouts: TimeoutConfig,
+    /// Deadlines of the transaction being read
+    deadlines: Deadlines,
+    /// Timeout hit by the transaction
+    timed_out: Option<TimeoutKind>,
+    /// Pool of the read buffers of the server
+    buffer_pool: Option<Arc<BufferPool>>,
+    /// Cancelled when the client aborts the transaction
+    cancel: CancellationToken,
+    /// Registration with the idle reaper, the connection is kept alive if set
+    idle: Option<IdleGuard>,
+    /// Bytes received before the transaction being read
+    transaction_bytes_in: u64,
+    /// Whether the next request may be read after the transaction
+    keep_alive: bool,
+    /// The connection was closed or reaped while waiting for a request
+    idle_closed: bool,
 }
 
 impl IcapConnection {
     /// Create a new connection handler
     pub fn new(
-        stream: TcpStream,
+        stream: impl IcapStream + 'static,
         peer_addr: SocketAddr,
         stats: Arc<IcapStats>,
         logger: Logger,
     ) -> Self {
-        // Initialize content filter module
-        let content_filter_config = ContentFilterConfig {
-            blocked_domains: vec![
-                "malware.com".to_string(),
-                "phishing.net".to_string(),
-                "spam.org".to_string(),
-                "virus.example".to_string(),
-            ],
-            blocked_domain_patterns: vec![
-                r".*\.malware\..*".to_string(),
-                r".*\.phishing\..*".to_string(),
-            ],
-            blocked_keywords: vec![
-                "malware".to_string(),
-                "virus".to_string(),
-                "phishing".to_string(),
-                "spam".to_string(),
-                "trojan".to_string(),
-                "backdoor".to_string(),
-            ],
-            blocked_keyword_patterns: vec![
-                r".*malware.*".to_string(),
-                r".*virus.*".to_string(),
-            ],
-            blocked_mime_types: vec![
-                "application/x-executable".to_string(),
-                "application/x-msdownload".to_string(),
-                "application/x-msdos-program".to_string(),
-            ],
-            blocked_extensions: vec![
-                ".exe".to_string(),
-                ".bat".to_string(),
-                ".cmd".to_string(),
-                ".scr".to_string(),
-            ],
-            max_file_size: Some(10 * 1024 * 1024), // 10MB
-            case_insensitive: true,
-            enable_regex: true,
-            blocking_action: crate::modules::content_filter::BlockingAction::Forbidden,
-            custom_message: Some("Content blocked by G3ICAP".to_string()),
-            enable_logging: true,
-            enable_metrics: true,
-            regex_cache_size: 1000,
-        };
-        
-        let mut content_filter = ContentFilterModule::new(content_filter_config);
-        
-        // Initialize the content filter module
-        let module_config = crate::modules::ModuleConfig {
-            name: "content_filter".to_string(),
-            path: std::path::PathBuf::from(""),
-            version: "1.0.0".to_string(),
-            config: serde_json::Value::Object(serde_json::Map::new()),
-            dependencies: Vec::new(),
-            load_timeout: std::time::Duration::from_secs(5),
-            max_memory: 1024 * 1024,
-            sandbox: true,
-        };
-        
-        // Initialize the module
-        let content_filter = if let Err(e) = tokio::task::block_in_place(|| {
-            tokio::runtime::Handle::current().block_on(async {
-                content_filter.init(&module_config).await
-            })
-        }) {
-            println!("DEBUG: Failed to initialize content filter module: {}", e);
-            // Continue without content filter module
-            None
-        } else {
-            println!("DEBUG: Content filter module initialized successfully");
-            Some(content_filter)
-        };
-
-        // Initialize antivirus module
-        let antivirus_config = AntivirusConfig {
-            engine: crate::modules::antivirus::AntivirusEngine::Mock {
-                simulate_threats: false,
-                scan_delay: std::time::Duration::from_millis(50),
-            },
-            max_file_size: 50 * 1024 * 1024, // 50MB
-            scan_timeout: std::time::Duration::from_secs(30),
-            quarantine_dir: Some(std::path::PathBuf::from("/tmp/g3icap/quarantine")),
-            enable_quarantine: true,
-            enable_logging: true,
-            enable_metrics: true,
-            scan_file_types: vec![
-                "application/octet-stream".to_string(),
-                "application/x-executable".to_string(),
-                "application/x-msdownload".to_string(),
-            ],
-            skip_file_types: vec![
-                "text/plain".to_string(),
-                "text/html".to_string(),
-                "image/jpeg".to_string(),
-                "image/png".to_string(),
-            ],
-            enable_realtime: true,
-            update_interval: std::time::Duration::from_secs(3600), // 1 hour
-            enable_threat_intel: false,
-            threat_intel_sources: Vec::new(),
-            yara_config: None,
-        };
-        
-        let mut antivirus = AntivirusModule::new(antivirus_config);
-        
-        // Initialize the antivirus module
-        let module_config = crate::modules::ModuleConfig {
-            name: "antivirus".to_string(),
-            path: std::path::PathBuf::from(""),
-            version: "1.0.0".to_string(),
-            config: serde_json::Value::Object(serde_json::Map::new()),
-            dependencies: Vec::new(),
-            load_timeout: std::time::Duration::from_secs(5),
-            max_memory: 1024 * 1024,
-            sandbox: true,
-        };
-        
-        // Initialize the module
-        let antivirus = if let Err(e) = tokio::task::block_in_place(|| {
-            tokio::runtime::Handle::current().block_on(async {
-                antivirus.init(&module_config).await
-            })
-        }) {
-            println!("DEBUG: Failed to initialize antivirus module: {}", e);
-            // Continue without antivirus module
-            None
-        } else {
-            println!("DEBUG: Antivirus module initialized successfully");
-            Some(antivirus)
-        };
-
         // Initialize audit operations
         let audit_ops = Box::new(DefaultIcapAuditOps::new(
             g3_types::metrics::NodeName::new_static("g3icap"),
@@ -238,28 +228,219 @@ impl IcapConnection {
         ));
 
         Self {
-            stream,
+            stream: Box::new(stream),
             peer_addr,
             stats,
             logger,
-            content_filter,
-            antivirus,
+            content_filter: None,
+            antivirus: None,
+            waf: None,
+            scripted: Arc::default(),
+            shadow_content_filter: None,
+            shadow_antivirus: None,
             audit_ops,
+            audit_log: None,
+            tracer: None,
+            trace: None,
             response_generator: IcapResponseGenerator::new(
                 "G3ICAP/1.0.0".to_string(),
                 "g3icap-1.0.0".to_string()
             ),
+            throughput: ConnectionThroughput::new(),
+            slow_client: None,
+            blocklist: None,
+            limits: ProtocolLimits::default(),
+            escalation: None,
+            quota: None,
+            quota_user: None,
+            request_id: None,
+            bypass_hints: None,
+            degradation: None,
+            load_shedding: None,
+            slo: None,
+            enforcement: EnforcementConfig::default(),
+            services: None,
+            admission: AdmissionConfig::default(),
+            body_plan: None,
+            overrun: None,
+            unread: false,
+            timeouts: TimeoutConfig::default(),
+            deadlines: Deadlines::new(TimeoutConfig::default(), tokio::time::Instant::now()),
+            timed_out: None,
+            buffer_pool: None,
+            cancel: CancellationToken::new(),
+            idle: None,
+            transaction_bytes_in: 0,
+            keep_alive: false,
+            idle_closed: false,
+        }
+    }
+
+    /// Enable slow client detection with the listener thresholds
+    pub fn with_slow_client(mut self, config: Option<SlowClientConfig>) -> Self {
+        self.slow_client = config;
+        self
+    }
+
+    /// Apply the hard limits of the server to the received requests
+    pub fn with_protocol_limits(mut self, limits: ProtocolLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Use the modules initialized by the server
+    pub fn with_modules(mut self, modules: Option<&ServerModules>) -> Self {
+        if let Some(modules) = modules {
+            self.content_filter = modules.content_filter();
+            self.antivirus = modules.antivirus();
+            self.waf = modules.waf();
+            self.scripted = modules.scripted();
+            self.shadow_content_filter = modules.shadow(ShadowTarget::ContentFilter);
+            self.shadow_antivirus = modules.shadow(ShadowTarget::Yara);
         }
+        self
+    }
+
+    /// Block the domains of the server blocklist
+    pub fn with_blocklist(mut self, blocklist: Option<Arc<BlocklistProvider>>) -> Self {
+        self.blocklist = blocklist;
+        self
+    }
+
+    /// Count the violations of the clients, escalating repeated offenders
+    pub fn with_escalation(mut self, escalation: Option<Arc<EscalationTracker>>) -> Self {
+        self.escalation = escalation;
+        self
+    }
+
+    /// Enforce the data quotas of the users
+    pub fn with_quota(mut self, quota: Option<Arc<QuotaTracker>>) -> Self {
+        self.quota = quota;
+        self
+    }
+
+    /// Send bypass hints for the trusted static origins of the server
+    pub fn with_bypass_hints(mut self, bypass_hints: Option<Arc<BypassHints>>) -> Self {
+        self.bypass_hints = bypass_hints;
+        self
+    }
+
+    /// Scan less as the degradation ladder of the server goes up
+    pub fn with_degradation(mut self, degradation: Option<Arc<DegradationLadder>>) -> Self {
+        self.degradation = degradation;
+        self
+    }
+
+    /// Shed the new transactions under overload
+    pub fn with_load_shedding(mut self, load_shedding: Option<Arc<LoadShedder>>) -> Self {
+        self.load_shedding = load_shedding;
+        self
+    }
+
+    /// Count the transactions against the response time objectives
+    pub fn with_slo(mut self, slo: Option<Arc<SloTracker>>) -> Self {
+        self.slo = slo;
+        self
+    }
+
+    /// Apply the enforcement mode of the server to the verdicts
+    pub fn with_enforcement(mut self, enforcement: EnforcementConfig) -> Self {
+        self.enforcement = enforcement;
+        self
+    }
+
+    /// Only serve the registered services
+    pub fn with_services(mut self, services: Option<ServicesConfig>) -> Self {
+        self.services = services;
+        self
+    }
+
+    /// Take early admission decisions from the declared body size
+    pub fn with_admission(mut self, admission: AdmissionConfig) -> Self {
+        self.admission = admission;
+        self
+    }
+
+    /// Bound the reads, writes and processing of the transaction
+    pub fn with_timeouts(mut self, timeouts: TimeoutConfig) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Write an audit record of each transaction to the audit log
+    pub fn with_audit_log(mut self, audit_log: Option<Arc<AuditLogger>>) -> Self {
+        self.audit_log = audit_log;
+        self
+    }
+
+    /// Trace the transactions with the exporter of the server
+    pub fn with_tracer(mut self, tracer: Option<Arc<Tracer>>) -> Self {
+        self.tracer = tracer;
+        self
+    }
+
+    /// Take the read buffers from the pool of the server
+    pub fn with_buffer_pool(mut self, pool: Arc<BufferPool>) -> Self {
+        self.buffer_pool = Some(pool);
+        self
+    }
+
+    /// Keep the connection alive, registered with the idle reaper
+    pub fn with_idle_reaper(mut self, reaper: Option<&Arc<IdleReaper>>) -> Self {
+        self.idle = reaper.map(|r| r.register());
+        self
+    }
+
+    /// Token cancelled when the client aborts the transaction
+    ///
+    /// Work done for the transaction outside of the connection task should
+    /// stop once it is cancelled.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancel.child_token()
+    }
+
+    /// Get the throughput counters of this connection
+    pub fn throughput(&self) -> &ConnectionThroughput {
+        &self.throughput
     }
 
     /// Process the connection
+    ///
+    /// A connection kept alive is processed until the client closes it, a
+    /// transaction fails or the connection is reaped while idle.
     pub async fn process(&mut self) -> IcapResult<()> {
+        if self.idle.is_none() {
+            return self.process_transaction().await;
+        }
+        loop {
+            match self.process_transaction().await {
+                Ok(()) if self.keep_alive => {}
+                Ok(()) => return Ok(()),
+                Err(_) if self.idle_closed => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Process a transaction, from reading the request to sending the response
+    async fn process_transaction(&mut self) -> IcapResult<()> {
         let connection_id = format!("{}", self.peer_addr);
         let logger = get_logger(&connection_id).unwrap_or_else(|| {
             slog::Logger::root(slog::Discard, slog::o!())
         });
 
         println!("DEBUG: Processing connection from {}", self.peer_addr);
+        let started = std::time::Instant::now();
+        let root_span = Span::start("icap.transaction");
+        self.deadlines = Deadlines::new(self.timeouts, tokio::time::Instant::now());
+        self.transaction_bytes_in = self.throughput.bytes_in();
+        self.body_plan = None;
+        self.overrun = None;
+        self.unread = false;
+        self.timed_out = None;
+        self.keep_alive = false;
+        self.quota_user = None;
+        self.request_id = None;
         ConnectionEvent::Accepted.log(&logger, &format!("Processing connection from {}", self.peer_addr));
         
         // Log audit event for connection received
@@ -271,6 +452,7 @@ impl IcapConnection {
 
         // Read request
         println!("DEBUG: Reading request...");
+        let parse_span = Span::start("icap.parse");
         let request = match self.read_request().await {
             Ok(req) => {
                 println!("DEBUG: Request read successfully: {:?}", req.method);
@@ -278,67 +460,284 @@ impl IcapConnection {
             }
             Err(e) => {
                 println!("DEBUG: Error reading request: {}", e);
+                if e.is_client_abort() {
+                    ConnectionEvent::Closed.log(&logger, &format!("Client {} aborted: {}", self.peer_addr, e));
+                    return Err(e);
+                }
+                if let Some(kind) = self.timed_out {
+                    // only answer clients that started sending a request
+                    if self.received() {
+                        let response = crate::protocol::error::ErrorResponseBuilder::request_timeout(&format!(
+                            "request not received within the {} timeout",
+                            kind.as_str()
+                        ));
+                        let _ = self.send_response(response).await;
+                    }
+                    return Err(e);
+                }
+                if let Some(limit) = e.limit_exceeded() {
+                    self.stats.increment_errors();
+                    let limit_stats = crate::stats::limits::get(limit);
+                    if self.overrun.is_some_and(|o| o.is_egregious()) {
+                        // not answered, the unread data makes the close a reset
+                        limit_stats.add_reset();
+                        return Err(e);
+                    }
+                    limit_stats.add_rejected();
+                    let response = crate::protocol::error::ErrorResponseBuilder::limit_exceeded(limit);
+                    let _ = self.send_response(response).await;
+                } else if let Some(invalid) = e.invalid_encapsulated() {
+                    self.stats.increment_errors();
+                    let response = crate::protocol::error::ErrorResponseBuilder::invalid_encapsulated(invalid);
+                    let _ = self.send_response(response).await;
+                }
                 return Err(e);
             }
         };
         
-        // Process request
+        // cancelled when the transaction times out or is aborted
+        let request_id = self.request_id.get_or_insert_with(transaction::new_id).clone();
+        let ctx = TransactionCtx::new(self.peer_addr, &request)
+            .with_request_id(request_id)
+            .with_cancel(self.cancel.child_token())
+            .with_deadline(self.deadlines.transaction());
+        let logger = logger.new(slog::o!("request_id" => ctx.request_id().to_string()));
+        let mut record = self.audit_log.as_ref().map(|_| AuditRecord::new(&ctx, &request));
+        if request.method != IcapMethod::Options {
+            self.quota_user = self.quota.as_ref().and_then(|_| QuotaTracker::user(&request.headers));
+        }
+        let close = request
+            .headers
+            .get_all(http::header::CONNECTION)
+            .iter()
+            .any(|v| v.to_str().is_ok_and(|v| v.split(',').any(|t| t.trim().eq_ignore_ascii_case("close"))));
+        self.start_trace(&request, root_span, parse_span);
+        if let Some(trace) = &mut self.trace {
+            trace.root().set("icap.request_id", ctx.request_id());
+        }
+
+        // Process request, watching for the client going away meanwhile
         println!("DEBUG: Processing request...");
-        let response = match self.process_request(request).await {
-                Ok(resp) => {
+        let mut stream = std::mem::replace(&mut self.stream, Box::new(tokio::io::empty()));
+        let processed = tokio::select! {
+            r = self.cancel.run_until_cancelled(
+                tokio::time::timeout_at(self.deadlines.transaction(), self.process_request(&ctx, request)),
+            ) => r.ok_or_else(|| std::io::Error::from(std::io::ErrorKind::Interrupted)),
+            e = abort::closed(&mut *stream) => Err(e),
+        };
+        self.stream = stream;
+        let response = match processed {
+            Ok(Ok(Ok(resp))) => {
                 println!("DEBUG: Request processed successfully: {}", resp.status);
-                    resp
-                }
-                Err(e) => {
+                resp
+            }
+            Ok(Ok(Err(e))) => {
                 println!("DEBUG: Error processing request: {}", e);
+                self.end_transaction(&ctx, record, started, Err(&e));
+                return Err(e);
+            }
+            Ok(Err(_)) => {
+                let e = self.timed_out(TimeoutKind::Transaction);
+                self.cancel_transaction(&ctx);
+                self.end_transaction(&ctx, record, started, Err(&e));
+                return Err(e);
+            }
+            Err(e) => {
+                let e = self.client_aborted("processing", &e);
+                self.cancel_transaction(&ctx);
+                ConnectionEvent::Closed.log(&logger, &format!("Client {} aborted: {}", self.peer_addr, e));
+                self.end_transaction(&ctx, record, started, Err(&e));
                 return Err(e);
             }
         };
+        if let Some(record) = &mut record {
+            record.finish(&response);
+        }
+        if let Some(trace) = &mut self.trace {
+            trace.root().set("icap.status", response.status.as_u16());
+        }
         
         // Send response
         println!("DEBUG: Sending response...");
         match self.send_response(response).await {
             Ok(_) => {
                 println!("DEBUG: Response sent successfully");
+                self.end_transaction(&ctx, record, started, Ok(()));
+                // a connection reaped while its request was read is closed after it
+                self.keep_alive = !close && !self.unread && self.idle.as_ref().is_some_and(|idle| !idle.is_reaped());
+            }
+            Err(e) if e.is_client_abort() => {
+                ConnectionEvent::Closed.log(&logger, &format!("Client {} aborted: {}", self.peer_addr, e));
+                self.end_transaction(&ctx, record, started, Err(&e));
+                return Err(e);
             }
             Err(e) => {
                 println!("DEBUG: Error sending response: {}", e);
+                self.end_transaction(&ctx, record, started, Err(&e));
                 return Err(e);
             }
         }
 
-        ConnectionEvent::ResponseSent.log(&logger, "Connection processed successfully");
+        ConnectionEvent::ResponseSent.log(&logger, &format!(
+            "Connection processed successfully: in={} out={} rate={}B/s max_stall={}ms",
+            self.throughput.bytes_in(),
+            self.throughput.bytes_out(),
+            self.throughput.overall_rate(),
+            self.throughput.max_stall().as_millis()
+        ));
         
         Ok(())
     }
 
+    /// Check if bytes of the transaction were received
+    fn received(&self) -> bool {
+        self.throughput.bytes_in() > self.transaction_bytes_in
+    }
+
+    /// Start the trace of the transaction, if it is sampled
+    fn start_trace(&mut self, request: &IcapRequest, root_span: Span, parse_span: Span) {
+        self.trace = self.tracer.as_ref().and_then(|t| t.start(&request.headers, root_span));
+        if let Some(trace) = &mut self.trace {
+            let root = trace.root();
+            root.set("icap.method", request.method.to_string());
+            root.set("icap.service", request.uri.path());
+            root.set("net.peer.ip", self.peer_addr.ip().to_string());
+            trace.end(parse_span.with("icap.bytes_in", self.throughput.bytes_in()));
+        }
+    }
+
+    /// End a span of the transaction, if it is traced
+    fn end_span<E: std::fmt::Display>(&self, mut span: Span, result: &Result<IcapResponse, E>) {
+        let Some(trace) = &self.trace else {
+            return;
+        };
+        match result {
+            Ok(response) => span.set("icap.status", response.status.as_u16()),
+            Err(e) => span.fail(e),
+        }
+        trace.end(span);
+    }
+
+    /// Export the trace of the transaction and write its record to the audit log
+    fn end_transaction(
+        &mut self,
+        ctx: &TransactionCtx,
+        record: Option<AuditRecord>,
+        started: std::time::Instant,
+        result: Result<(), &IcapError>,
+    ) {
+        // the transactions aborted by the client are not the server's to answer
+        if !result.is_err_and(|e| e.is_client_abort()) {
+            let service = ctx.service();
+            if crate::server::rotation::get_global().is_none_or(|r| r.is_known(service)) {
+                crate::stats::service::get(service).add_transaction(started.elapsed(), result.is_ok());
+            }
+            if let Some(slo) = &self.slo {
+                slo.record(service, ctx.method(), started.elapsed(), result.is_ok());
+            }
+        }
+        if let (Some(quota), Some(user)) = (&self.quota, self.quota_user.take()) {
+            quota.record(&user, self.throughput.bytes_in() - self.transaction_bytes_in);
+        }
+        if let Some(mut trace) = self.trace.take() {
+            let root = trace.root();
+            root.set("icap.bytes_out", self.throughput.bytes_out());
+            if let Err(e) = result {
+                root.fail(e);
+            }
+            trace.finish();
+        }
+        let (Some(audit_log), Some(mut record)) = (&self.audit_log, record) else {
+            return;
+        };
+        if let Err(e) = result {
+            record.fail(e);
+        }
+        record.set_context(ctx);
+        record.set_transfer(started.elapsed(), self.throughput.bytes_in(), self.throughput.bytes_out());
+        audit_log.log(&record);
+    }
+
     /// Read ICAP request from stream
     async fn read_request(&mut self) -> IcapResult<IcapRequest> {
         println!("DEBUG: Starting to read request from stream");
-        let mut buffer = Vec::new();
-        let mut temp_buffer = [0u8; 4096];
+        let mut buffer = match &self.buffer_pool {
+            Some(pool) => pool.get(),
+            None => PooledBuffer::default(),
+        };
+        let mut head = None;
+        // a connection kept alive is idle until the first bytes of the request
+        let mut idle = self.idle.is_some();
         
         loop {
             println!("DEBUG: Reading from stream...");
-            let n = self.stream.read(&mut temp_buffer).await
-                .map_err(|e| {
+            let (deadline, kind) = self.deadlines.next_read(self.body_plan.is_some(), tokio::time::Instant::now());
+            buffer.reserve(READ_CHUNK);
+            let read = self.stream.read_buf(&mut *buffer);
+            let result = match &self.idle {
+                // the reaper bounds the wait for the request, not the header timeout
+                Some(guard) if idle => match guard.wait(read).await {
+                    Some(r) => Ok(r),
+                    None => {
+                        self.idle_closed = true;
+                        return Err(IcapError::network_simple(format!(
+                            "idle connection {} reaped",
+                            self.peer_addr
+                        )));
+                    }
+                },
+                _ => tokio::time::timeout_at(deadline, read).await,
+            };
+            if idle {
+                idle = false;
+                self.deadlines = Deadlines::new(self.timeouts, tokio::time::Instant::now());
+            }
+            let n = match result {
+                Ok(Ok(n)) => n,
+                Ok(Err(e)) if abort::is_abort(&e) && self.received() => {
+                    return Err(self.client_aborted("reading the request", &e));
+                }
+                Ok(Err(e)) => {
                     println!("DEBUG: Error reading from stream: {}", e);
-                    IcapError::Io(e)
-                })?;
+                    return Err(IcapError::Io(e));
+                }
+                Err(_) => return Err(self.timed_out(kind)),
+            };
             
             println!("DEBUG: Read {} bytes from stream", n);
             
             if n == 0 {
                 println!("DEBUG: Connection closed by peer");
+                if self.received() {
+                    let e = std::io::Error::from(std::io::ErrorKind::UnexpectedEof);
+                    return Err(self.client_aborted("reading the request", &e));
+                }
+                self.idle_closed = self.idle.is_some();
                 return Err(IcapError::network_simple("Connection closed by peer".to_string()));
             }
             
-            buffer.extend_from_slice(&temp_buffer[..n]);
+            self.throughput.add_read(n);
             println!("DEBUG: Buffer now has {} bytes", buffer.len());
+            self.read_request_id(&buffer);
+            if let Err(e) = self.check_received(&mut buffer) {
+                self.overrun = Some(e);
+                return Err(e.limit.into());
+            }
+            if head.is_none() {
+                head = crate::protocol::parser::parse_request_head(&buffer, &self.limits)?;
+            }
             
+            // A preview that is not the whole body is continued, the whole body is scanned
+            if let Some(plan) = &mut self.body_plan
+                && plan.continue_body(&mut buffer)
+            {
+                self.write_tracked(CONTINUE_RESPONSE).await?;
+                continue;
+            }
+
             // Check if we have a complete request
             println!("DEBUG: Checking if request is complete...");
-            if self.is_complete_request(&buffer) {
+            if self.body_plan.is_some_and(|plan| plan.is_complete(&buffer)) {
                 println!("DEBUG: Complete request received");
                 break;
             } else {
@@ -347,126 +746,447 @@ impl IcapConnection {
         }
         
         println!("DEBUG: Parsing request with {} bytes", buffer.len());
-        // Parse the request using the ICAP parser
-        crate::protocol::common::IcapParser::parse_request(&buffer)
+        // The head is parsed already, the body is taken out of the buffer without a copy
+        let head = head.ok_or_else(|| IcapError::protocol_error("Missing header terminator", "PARSER"))?;
+        if head.method == IcapMethod::Options {
+            self.check_options(&head, buffer.len())?;
+        }
+        head.into_request(buffer.split().freeze(), &self.limits)
+    }
+
+    /// Take the request id once the ICAP header section is received
+    ///
+    /// It is taken before the request is parsed and checked, so that the
+    /// error responses to a rejected request carry it too.
+    fn read_request_id(&mut self, data: &[u8]) {
+        if self.request_id.is_some() {
+            return;
+        }
+        let Some(header) = admission::icap_header(data) else {
+            return;
+        };
+        let id = admission::header_value(header, HEADER_REQUEST_ID)
+            .as_deref()
+            .and_then(transaction::client_request_id)
+            .map(|s| s.to_string());
+        self.request_id = Some(id.unwrap_or_else(transaction::new_id));
+    }
+
+    /// Handle an OPTIONS request carrying a body
+    ///
+    /// It is rejected in strict mode. In lenient mode the body is ignored, and
+    /// as the rest of it may still be on its way the connection is closed
+    /// after the response.
+    fn check_options(&mut self, head: &RequestHead, received: usize) -> IcapResult<()> {
+        let Err(invalid) = head.check_options(received) else {
+            return Ok(());
+        };
+        if self.limits.strict_encapsulated {
+            self.stats.increment_malformed_options_rejected();
+            return Err(invalid.into());
+        }
+        log::warn!("ignoring the body of the OPTIONS request of client {}: {invalid}", self.peer_addr);
+        self.stats.increment_malformed_options_ignored();
+        self.unread = true;
+        Ok(())
+    }
+
+    /// Record a timeout of the transaction
+    fn timed_out(&mut self, kind: TimeoutKind) -> IcapError {
+        self.timed_out = Some(kind);
+        self.stats.increment_timeouts(kind);
+        IcapError::timeout_error(
+            format!("{} timeout of client {}", kind.as_str(), self.peer_addr),
+            kind.as_str(),
+            self.timeouts.duration(kind),
+        )
+    }
+
+    /// Cancel the pending work of the transaction being processed
+    ///
+    /// The modules that could have taken part in it are then told to clean
+    /// up after it.
+    fn cancel_transaction(&self, ctx: &TransactionCtx) {
+        ctx.cancel();
+        let scripted = self.scripted.get(ctx.service().trim_matches('/'));
+        let shadow = [&self.shadow_content_filter, &self.shadow_antivirus]
+            .into_iter()
+            .flatten()
+            .map(ShadowModule::module);
+        for module in [&self.waf, &self.content_filter, &self.antivirus].into_iter().flatten().chain(scripted).chain(shadow) {
+            module.handle_cancel(ctx.id());
+        }
+    }
+
+    /// Record a transaction aborted by the client, cancelling its pending work
+    fn client_aborted(&mut self, phase: &str, e: &std::io::Error) -> IcapError {
+        self.cancel.cancel();
+        self.stats.increment_client_aborted();
+        IcapError::client_aborted(format!("client {} went away while {}: {}", self.peer_addr, phase, e))
     }
 
-    /// Check if we have a complete request
-    fn is_complete_request(&self, buffer: &[u8]) -> bool {
-        // Simple check for double CRLF (end of headers)
-        buffer.windows(4).any(|w| w == b"\r\n\r\n")
+    /// Check the received part of the request against the limits
+    ///
+    /// The request is planned, and admitted or rejected, as soon as the size
+    /// of its body is known.
+    fn check_received(&mut self, buffer: &mut BytesMut) -> Result<(), Overrun> {
+        if let Some(plan) = &self.body_plan {
+            return plan.check_received(buffer, &self.limits);
+        }
+        admission::check_partial(buffer, &self.limits)?;
+        if let Some(plan) = BodyPlan::new(buffer, &self.limits)? {
+            plan.admit(&self.limits)?;
+            plan.check_received(buffer, &self.limits)?;
+            buffer.reserve(plan.reserve(&self.admission));
+            self.body_plan = Some(plan);
+        }
+        Ok(())
     }
 
     /// Process the ICAP request
-    async fn process_request(&self, request: IcapRequest) -> IcapResult<IcapResponse> {
+    async fn process_request(&self, ctx: &TransactionCtx, request: IcapRequest) -> IcapResult<IcapResponse> {
         let connection_id = format!("{}", self.peer_addr);
         let logger = get_logger(&connection_id).unwrap_or_else(|| {
             slog::Logger::root(slog::Discard, slog::o!())
         });
+        let logger = logger.new(slog::o!("request_id" => ctx.request_id().to_string()));
 
         ConnectionEvent::RequestReceived.log(&logger, &format!("Processing ICAP request: {}", request.method.to_string()));
         
         // Update statistics
         self.stats.increment_requests();
-        
+
+        // Authenticate the client if the server requires it
+        if let Some(authenticator) = crate::auth::token::get_global() {
+            if let Err(e) = authenticator.authenticate_headers(&request.headers) {
+                log::warn!("client {} authentication failed: {}", self.peer_addr, e);
+                self.stats.increment_auth_rejected();
+                self.audit_ops.log_security_event(
+                    "client_auth_failed",
+                    &format!("client {}: {}", self.peer_addr, e),
+                    crate::audit::ops::AuditSeverity::Warning,
+                );
+                return Ok(self.response_generator.unauthorized(&authenticator.challenge(e)));
+            }
+        }
+
+        // Unknown services never reach the modules
+        if let Some(services) = &self.services
+            && let Some(response) = routing::route(services, &self.response_generator, &request)
+        {
+            if request.method == crate::protocol::common::IcapMethod::Options {
+                self.stats.increment_options_requests();
+            }
+            return Ok(response);
+        }
+
+        // Services out of rotation let the clients fail over to other servers
+        if crate::server::rotation::get_global().is_some_and(|r| r.is_disabled(request.uri.path())) {
+            log::debug!("service {} is out of rotation", request.uri.path());
+            return Ok(self.response_generator.service_unavailable(None));
+        }
+
+        // Overload is shed before any work on the transaction
+        let _in_flight = match &self.load_shedding {
+            Some(shedder) if request.method != crate::protocol::common::IcapMethod::Options => {
+                match shedder.admit(self.stats.active_connections()) {
+                    Some(in_flight) => Some(in_flight),
+                    None => return Ok(self.shed(&request, shedder)),
+                }
+            }
+            _ => None,
+        };
+
+        // Users over their data quota are blocked before any work on the transaction
+        let quota_warning = match self.quota_verdict() {
+            QuotaVerdict::Exceeded(usage) if self.quota.as_ref().is_some_and(|q| q.action() == QuotaAction::Block) => {
+                return Ok(self.quota_exceeded(&request, &usage).await);
+            }
+            QuotaVerdict::Exceeded(usage) => {
+                ctx.set_label("quota", "exceeded");
+                self.audit_ops.log_audit_event(
+                    "Quota exceeded, transaction allowed",
+                    &format!("client {} URI {}: {}", self.peer_addr, request.uri, usage),
+                );
+                Some(usage)
+            }
+            QuotaVerdict::Warning(usage) => {
+                ctx.set_label("quota", "warning");
+                Some(usage)
+            }
+            QuotaVerdict::Within => None,
+        };
+
+        // Scripted services are handled by their module alone
+        if let Some(module) = self.scripted.get(request.uri.path().trim_matches('/')) {
+            let result = self.handle_scripted_request(ctx, module.clone(), request).await;
+            return self.quota_warned(quota_warning, result);
+        }
+
+        // The pipeline is fixed for the whole transaction
+        let pipeline = crate::server::pipelines::get_global().and_then(|p| p.pipeline(request.uri.path()));
+        if let Some(pipeline) = &pipeline {
+            ctx.set_label("pipeline", pipeline.name());
+        }
+
         // Route to appropriate handler based on method
-        match request.method {
+        let result = match request.method {
+            crate::protocol::common::IcapMethod::Options => {
+                self.stats.increment_options_requests();
+                self.handle_options_request(request, pipeline.as_deref()).await
+            }
+            crate::protocol::common::IcapMethod::Reqmod => {
+                self.stats.increment_reqmod_requests();
+                let treatment = self.treatment(&request);
+                if let Some(response) = self.degraded(&request, treatment) {
+                    return Ok(response);
+                }
+                if let Some(pipeline) = &pipeline {
+                    pipeline.add_transaction();
+                }
+                let span = pipeline_span(pipeline.as_deref());
+                let result = self.handle_reqmod_request(ctx, request.clone(), pipeline.as_deref(), treatment).await;
+                self.end_span(span, &result);
+                let result = self.untransformed(&request, treatment, result);
+                self.partial(&request, self.monitored(&request, self.hinted(&request, result)))
+            }
+            crate::protocol::common::IcapMethod::Respmod => {
+                self.stats.increment_respmod_requests();
+                let treatment = self.treatment(&request);
+                if let Some(response) = self.degraded(&request, treatment) {
+                    return Ok(response);
+                }
+                if let Some(pipeline) = &pipeline {
+                    pipeline.add_transaction();
+                }
+                let span = pipeline_span(pipeline.as_deref());
+                let result = self.handle_respmod_request(ctx, request.clone(), pipeline.as_deref(), treatment).await;
+                self.end_span(span, &result);
+                let result = self.untransformed(&request, treatment, result);
+                self.partial(&request, self.monitored(&request, self.hinted(&request, result)))
+            }
+        };
+        self.quota_warned(quota_warning, result)
+    }
+
+    /// Handle a request of a scripted service
+    async fn handle_scripted_request(&self, ctx: &TransactionCtx, module: Arc<dyn IcapModule>, request: IcapRequest) -> IcapResult<IcapResponse> {
+        let methods = module.supported_methods();
+        if !methods.contains(&request.method) {
+            return Ok(self.response_generator.method_not_allowed(&request.method, &methods));
+        }
+
+        let span = Span::start("icap.module").with("icap.module", module.name().to_string());
+        let result = match request.method {
             crate::protocol::common::IcapMethod::Options => {
                 self.stats.increment_options_requests();
-                self.handle_options_request(request).await
+                return module
+                    .handle_options(&request)
+                    .await
+                    .map_err(|e| IcapError::service_error(e.to_string(), module.name()));
             }
             crate::protocol::common::IcapMethod::Reqmod => {
                 self.stats.increment_reqmod_requests();
-                self.handle_reqmod_request(request).await
+                module.handle_reqmod(&request, ctx).await
             }
             crate::protocol::common::IcapMethod::Respmod => {
                 self.stats.increment_respmod_requests();
-                self.handle_respmod_request(request).await
+                module.handle_respmod(&request, ctx).await
+            }
+        };
+        self.end_span(span, &result);
+        ctx.record_module(module.name(), &result);
+        let result = result.map_err(|e| IcapError::service_error(e.to_string(), module.name()));
+        if let Ok(response) = &result
+            && let Some(rule_id) = response.headers.get(crate::modules::content_filter::HEADER_RULE_ID).and_then(|v| v.to_str().ok())
+        {
+            self.audit_ops.log_request_blocked(
+                &self.peer_addr.to_string(),
+                &request.uri.to_string(),
+                &format!("Matched rule {}", rule_id),
+            );
+        }
+        self.partial(&request, self.monitored(&request, self.hinted(&request, result)))
+    }
+
+    /// Check the data quota of the user of the transaction
+    fn quota_verdict(&self) -> QuotaVerdict {
+        match (&self.quota, &self.quota_user) {
+            (Some(quota), Some(user)) => quota.check(user),
+            _ => QuotaVerdict::Within,
+        }
+    }
+
+    /// The response of a transaction of a user over its data quota
+    async fn quota_exceeded(&self, request: &IcapRequest, usage: &QuotaUsage) -> IcapResponse {
+        let reason = format!("{} quota exceeded", usage.period.as_str());
+        self.audit_ops.log_request_blocked(
+            &self.peer_addr.to_string(),
+            &request.uri.to_string(),
+            &format!("{reason}: {usage}"),
+        );
+        let url = match &request.encapsulated {
+            Some(encapsulated) => self
+                .parse_http_request_from_encapsulated(encapsulated)
+                .await
+                .map(|r| r.uri)
+                .unwrap_or_default(),
+            None => String::new(),
+        };
+        let ticket_id = BlockPages::new_ticket_id();
+        let vars = BlockPageVars {
+            url: &url,
+            category: "quota",
+            rule: usage.period.as_str(),
+            reason: &reason,
+            client_ip: BlockPages::client_ip(&request.headers).unwrap_or_default(),
+            ticket_id: &ticket_id,
+        };
+        let retry_after = self
+            .quota
+            .as_ref()
+            .map_or(0, |q| q.retry_after(usage.period).as_secs().max(1));
+        let mut response = self.response_generator.http_page_response_with_headers(
+            http::StatusCode::TOO_MANY_REQUESTS,
+            &BlockPages::default().render(&vars),
+            &[(http::header::RETRY_AFTER, http::HeaderValue::from(retry_after))],
+        );
+        if let Ok(value) = usage.to_string().parse() {
+            response.headers.insert(HEADER_QUOTA_WARNING, value);
+        }
+        response
+    }
+
+    /// Add the quota warning of the user to an allowed transaction
+    fn quota_warned(&self, usage: Option<QuotaUsage>, result: IcapResult<IcapResponse>) -> IcapResult<IcapResponse> {
+        let mut response = result?;
+        if let Some(usage) = usage
+            && let Ok(value) = usage.to_string().parse()
+        {
+            response.headers.insert(HEADER_QUOTA_WARNING, value);
+        }
+        Ok(response)
+    }
+
+    /// How the degradation ladder lets the transaction be handled
+    fn treatment(&self, request: &IcapRequest) -> Treatment {
+        self.degradation
+            .as_ref()
+            .map_or(Treatment::Full, |d| d.treatment(request.uri.path()))
+    }
+
+    /// The configured fail mode of the service of the request
+    fn fail_mode(&self, request: &IcapRequest) -> FailMode {
+        self.degradation
+            .as_ref()
+            .map_or(FailMode::default(), |d| d.fail_mode(request.uri.path()))
+    }
+
+    /// The response of a transaction that is not scanned at the current degradation level
+    fn degraded(&self, request: &IcapRequest, treatment: Treatment) -> Option<IcapResponse> {
+        match treatment {
+            Treatment::Allow => Some(monitor::allow(&self.response_generator, request)),
+            Treatment::Refuse => Some(self.response_generator.service_unavailable(None)),
+            Treatment::Full | Treatment::NoTransforms | Treatment::HashOnly => None,
+        }
+    }
+
+    /// The response of a transaction shed under overload
+    fn shed(&self, request: &IcapRequest, shedder: &LoadShedder) -> IcapResponse {
+        match shedder.fail_mode() {
+            FailMode::Open => monitor::allow(&self.response_generator, request),
+            FailMode::Closed => self.response_generator.service_unavailable(Some(shedder.retry_after())),
+        }
+    }
+
+    /// Allow the original message instead of a modified one if transforms are off
+    fn untransformed(&self, request: &IcapRequest, treatment: Treatment, result: IcapResult<IcapResponse>) -> IcapResult<IcapResponse> {
+        let response = result?;
+        let transformed = response.status == http::StatusCode::OK
+            && !response.body.is_empty()
+            && !response.headers.contains_key(crate::modules::content_filter::HEADER_RULE_ID)
+            && !response.headers.contains_key(crate::modules::antivirus::HEADER_VIRUS_ID);
+        if treatment == Treatment::Full || !transformed {
+            return Ok(response);
+        }
+        log::debug!("degraded, transform not applied for {}", request.uri);
+        Ok(monitor::allow(&self.response_generator, request))
+    }
+
+    /// Add the bypass hint to an allowed transaction of a trusted static origin
+    fn hinted(&self, request: &IcapRequest, result: IcapResult<IcapResponse>) -> IcapResult<IcapResponse> {
+        let mut response = result?;
+        if let Some(hints) = &self.bypass_hints
+            && hints.apply(request, &mut response)
+        {
+            log::debug!("bypass hint sent for {}", request.uri);
+        }
+        Ok(response)
+    }
+
+    /// Allow the message instead of applying the verdict if the service is in monitor mode
+    fn monitored(&self, request: &IcapRequest, result: IcapResult<IcapResponse>) -> IcapResult<IcapResponse> {
+        let response = result?;
+        let service = request.uri.path();
+        if self.enforcement.mode(service) != EnforcementMode::Monitor || !monitor::is_verdict(&response) {
+            return Ok(response);
+        }
+
+        let verdict = monitor::describe(&response);
+        log::info!("monitor mode, not applied verdict for {}: {}", request.uri, verdict);
+        self.stats.increment_monitored_verdicts();
+        self.audit_ops.log_audit_event(
+            "Verdict not applied in monitor mode",
+            &format!("client {} URI {}: {}", self.peer_addr, request.uri, verdict),
+        );
+        Ok(monitor::allow(&self.response_generator, request))
+    }
+
+    /// Send a modified message as a 206 if only its preview was adapted
+    fn partial(&self, request: &IcapRequest, result: IcapResult<IcapResponse>) -> IcapResult<IcapResponse> {
+        let response = result?;
+        match partial::partial_content(&self.response_generator, request, &response) {
+            Some(partial) => {
+                log::debug!("partial content sent for {}", request.uri);
+                self.stats.increment_partial_responses();
+                Ok(partial)
             }
+            None => Ok(response),
         }
     }
 
     /// Handle OPTIONS request
-    async fn handle_options_request(&self, request: IcapRequest) -> IcapResult<IcapResponse> {
-        println!("DEBUG: Processing OPTIONS request for URI: {}", request.uri);
-        
-        // Create comprehensive service capabilities
-        let mut capabilities = std::collections::HashMap::new();
-        
-        // Connection and performance limits
-        capabilities.insert("max-connections".to_string(), "1000".to_string());
-        capabilities.insert("max-connections-per-client".to_string(), "10".to_string());
-        capabilities.insert("options-ttl".to_string(), "3600".to_string());
-        capabilities.insert("connection-timeout".to_string(), "30".to_string());
-        capabilities.insert("request-timeout".to_string(), "60".to_string());
-        
-        // ICAP protocol capabilities
-        capabilities.insert("allow".to_string(), "204".to_string());
-        capabilities.insert("preview".to_string(), "1024".to_string());
-        capabilities.insert("transfer-preview".to_string(), "*".to_string());
-        capabilities.insert("transfer-ignore".to_string(), "Content-Length, Content-Encoding".to_string());
-        capabilities.insert("transfer-complete".to_string(), "Content-Length".to_string());
-        
-        // Content filtering capabilities
-        capabilities.insert("x-content-filter".to_string(), "enabled".to_string());
-        capabilities.insert("x-filter-domains".to_string(), "blocked_domains, domain_patterns".to_string());
-        capabilities.insert("x-filter-keywords".to_string(), "blocked_keywords, keyword_patterns".to_string());
-        capabilities.insert("x-filter-mime".to_string(), "blocked_mime_types, blocked_extensions".to_string());
-        capabilities.insert("x-filter-size".to_string(), "max_file_size".to_string());
-        capabilities.insert("x-filter-regex".to_string(), "enabled".to_string());
-        
-        // Antivirus scanning capabilities
-        capabilities.insert("x-antivirus".to_string(), "enabled".to_string());
-        capabilities.insert("x-antivirus-engine".to_string(), "YARA".to_string());
-        capabilities.insert("x-antivirus-scan".to_string(), "real-time, on-demand".to_string());
-        capabilities.insert("x-antivirus-quarantine".to_string(), "enabled".to_string());
-        capabilities.insert("x-antivirus-update".to_string(), "hourly".to_string());
-        capabilities.insert("x-antivirus-threat-intel".to_string(), "enabled".to_string());
-        
-        // Security and compliance features
-        capabilities.insert("x-security-features".to_string(), "content_filtering, antivirus, threat_intelligence".to_string());
-        capabilities.insert("x-compliance".to_string(), "GDPR, CCPA, SOX".to_string());
-        capabilities.insert("x-data-protection".to_string(), "enabled".to_string());
-        capabilities.insert("x-audit-logging".to_string(), "enabled".to_string());
-        
-        // Performance and monitoring
-        capabilities.insert("x-metrics".to_string(), "enabled".to_string());
-        capabilities.insert("x-statistics".to_string(), "enabled".to_string());
-        capabilities.insert("x-health-check".to_string(), "/health".to_string());
-        capabilities.insert("x-metrics-endpoint".to_string(), "/metrics".to_string());
-        
-        // Supported content types for scanning
-        capabilities.insert("x-scan-content-types".to_string(), "application/octet-stream, application/x-executable, application/x-msdownload".to_string());
-        capabilities.insert("x-skip-content-types".to_string(), "text/plain, text/html, image/jpeg, image/png".to_string());
-        
-        // Maximum file sizes
-        capabilities.insert("x-max-file-size".to_string(), "52428800".to_string()); // 50MB
-        capabilities.insert("x-max-preview-size".to_string(), "1048576".to_string()); // 1MB
-        
-        // Service version and build information
-        capabilities.insert("x-version".to_string(), "1.0.0".to_string());
-        capabilities.insert("x-build-date".to_string(), "2025-01-11".to_string());
-        capabilities.insert("x-build-info".to_string(), "G3ICAP-1.0.0-rust".to_string());
-        
-        // Custom service capabilities
-        capabilities.insert("x-custom-features".to_string(), "modular_architecture, plugin_system, load_balancing".to_string());
-        capabilities.insert("x-service-status".to_string(), "operational".to_string());
-        capabilities.insert("x-maintenance-window".to_string(), "sunday-02:00-04:00-utc".to_string());
-        
-        println!("DEBUG: OPTIONS response created with comprehensive service capabilities");
-        
-        // Use response generator for OPTIONS response
-        let methods = vec![
-            crate::protocol::common::IcapMethod::Options,
-            crate::protocol::common::IcapMethod::Reqmod,
-            crate::protocol::common::IcapMethod::Respmod,
+    ///
+    /// The capabilities are the ones of the modules the pipeline of the
+    /// service runs, and of the service config if it is registered.
+    async fn handle_options_request(&self, request: IcapRequest, pipeline: Option<&Pipeline>) -> IcapResult<IcapResponse> {
+        log::debug!("OPTIONS request for {}", request.uri);
+
+        let stages = [
+            (PipelineStage::Waf, &self.waf),
+            (PipelineStage::ContentFilter, &self.content_filter),
+            (PipelineStage::Antivirus, &self.antivirus),
         ];
-        
-        Ok(self.response_generator.options_response(&methods, capabilities))
+        let modules: Vec<Arc<dyn IcapModule>> = stages
+            .into_iter()
+            .filter(|(stage, _)| pipeline.is_none_or(|p| p.runs(*stage)))
+            .filter_map(|(_, module)| module.clone())
+            .collect();
+        let service = crate::services::get_global()
+            .and_then(|services| services.get_service_by_path(request.uri.path()))
+            .map(|instance| instance.config);
+
+        let mut response = capabilities::options(&self.response_generator, &modules, service.as_ref(), &self.limits);
+        if let Some(hints) = &self.bypass_hints
+            && let Ok(value) = hints.advertised().parse()
+        {
+            response.headers.insert(HEADER_BYPASS_HINT, value);
+        }
+        Ok(response)
     }
 
     /// Handle REQMOD request
-    async fn handle_reqmod_request(&self, request: IcapRequest) -> IcapResult<IcapResponse> {
+    async fn handle_reqmod_request(&self, ctx: &TransactionCtx, request: IcapRequest, pipeline: Option<&Pipeline>, treatment: Treatment) -> IcapResult<IcapResponse> {
         println!("DEBUG: Processing REQMOD request for URI: {}", request.uri);
         
         // Log audit event for REQMOD request
@@ -487,28 +1207,115 @@ impl IcapConnection {
             }
         };
 
-        // Apply content filtering using the content filter module
-        if let Some(ref content_filter) = self.content_filter {
+        // Evaluate the WAF rules first, a request they deny does not go to the content filter
+        let waf = self
+            .waf
+            .as_ref()
+            .filter(|_| treatment != Treatment::HashOnly)
+            .filter(|_| pipeline.is_none_or(|p| p.runs(PipelineStage::Waf)));
+        if let Some(waf) = waf {
+            let span = Span::start("icap.module").with("icap.module", "waf");
+            let result = waf.handle_reqmod(&request, ctx).await;
+            self.end_span(span, &result);
+            ctx.record_module("waf", &result);
+            match result {
+                Ok(response) => {
+                    if let Some(rule_id) = response
+                        .headers
+                        .get(crate::modules::content_filter::HEADER_RULE_ID)
+                        .and_then(|v| v.to_str().ok())
+                    {
+                        let reason = format!("Matched WAF rule {}", rule_id);
+                        self.audit_ops.log_request_blocked(
+                            &self.peer_addr.to_string(),
+                            &request.uri.to_string(),
+                            &reason
+                        );
+                        if let Some(category) = response
+                            .headers
+                            .get(crate::modules::content_filter::HEADER_RULE_CATEGORY)
+                            .and_then(|v| v.to_str().ok())
+                        {
+                            self.record_violation(&request, category, &reason);
+                        }
+                        return Ok(response);
+                    }
+                }
+                Err(e) => {
+                    log::warn!("WAF failed to inspect {}: {}", request.uri, e);
+                    self.stats.increment_waf_errors();
+                    if self.fail_mode(&request) == FailMode::Closed {
+                        return Ok(self.response_generator.service_unavailable(None));
+                    }
+                }
+            }
+        }
+
+        // Apply content filtering using the content filter module, if in the pipeline of the service
+        // and the degradation level does not restrict it to the basic checks
+        let content_filter = self
+            .content_filter
+            .as_ref()
+            .filter(|_| treatment != Treatment::HashOnly)
+            .filter(|_| pipeline.is_none_or(|p| p.runs(PipelineStage::ContentFilter)));
+        if let Some(content_filter) = content_filter {
             println!("DEBUG: Using content filter module for REQMOD processing");
-            match content_filter.handle_reqmod(&request).await {
+            let span = Span::start("icap.module").with("icap.module", "content_filter");
+            // the candidate rules, if any, are evaluated alongside without affecting the verdict
+            let result = shadow::evaluate_alongside(
+                self.shadow_content_filter.as_ref(),
+                &request,
+                ctx,
+                content_filter.handle_reqmod(&request, ctx),
+            )
+            .await;
+            self.end_span(span, &result);
+            ctx.record_module("content_filter", &result);
+            match result {
                 Ok(response) => {
                     println!("DEBUG: Content filter processed REQMOD request: {}", response.status);
+                    if let Some(rule_id) = response
+                        .headers
+                        .get(crate::modules::content_filter::HEADER_RULE_ID)
+                        .and_then(|v| v.to_str().ok())
+                    {
+                        let detected_type = response
+                            .headers
+                            .get(crate::modules::content_filter::HEADER_DETECTED_TYPE)
+                            .and_then(|v| v.to_str().ok());
+                        let reason = match detected_type {
+                            Some(t) => format!("Matched rule {}, detected type {}", rule_id, t),
+                            None => format!("Matched rule {}", rule_id),
+                        };
+                        self.audit_ops.log_request_blocked(
+                            &self.peer_addr.to_string(),
+                            &request.uri.to_string(),
+                            &reason
+                        );
+                        if let Some(category) = response
+                            .headers
+                            .get(crate::modules::content_filter::HEADER_RULE_CATEGORY)
+                            .and_then(|v| v.to_str().ok())
+                        {
+                            self.record_violation(&request, category, &reason);
+                        }
+                    }
                     Ok(response)
                 }
                 Err(e) => {
                     println!("DEBUG: Content filter error: {}", e);
                     // Fall back to basic filtering
-                    self.apply_basic_content_filtering(&http_request).await
+                    self.apply_basic_content_filtering(&request.headers, &http_request).await
                 }
             }
         } else {
             println!("DEBUG: No content filter module, using basic filtering");
-            self.apply_basic_content_filtering(&http_request).await
+            self.apply_basic_content_filtering(&request.headers, &http_request).await
         }
     }
 
     /// Handle RESPMOD request
-    async fn handle_respmod_request(&self, request: IcapRequest) -> IcapResult<IcapResponse> {
+    async fn handle_respmod_request(&self, ctx: &TransactionCtx, request: IcapRequest, pipeline: Option<&Pipeline>, treatment: Treatment) -> IcapResult<IcapResponse> {
         println!("DEBUG: Processing RESPMOD request for URI: {}", request.uri);
         
         // Log audit event for RESPMOD request
@@ -529,12 +1336,41 @@ impl IcapConnection {
             }
         };
 
-        // Apply antivirus scanning using the antivirus module
-        if let Some(ref antivirus) = self.antivirus {
+        // Bodies declared too large to scan go to the basic scanning directly
+        let skip_scan = self
+            .body_plan
+            .is_some_and(|plan| plan.skip_scan(&self.admission));
+
+        // Apply antivirus scanning using the antivirus module, if in the pipeline of the service
+        // and the degradation level does not restrict it to the signature checks
+        let antivirus = self
+            .antivirus
+            .as_ref()
+            .filter(|_| !skip_scan && treatment != Treatment::HashOnly)
+            .filter(|_| pipeline.is_none_or(|p| p.runs(PipelineStage::Antivirus)));
+        if let Some(antivirus) = antivirus {
             println!("DEBUG: Using antivirus module for RESPMOD processing");
-            match antivirus.handle_respmod(&request).await {
+            let span = Span::start("icap.module").with("icap.module", "antivirus");
+            // the candidate rules, if any, are evaluated alongside without affecting the verdict
+            let result = shadow::evaluate_alongside(
+                self.shadow_antivirus.as_ref(),
+                &request,
+                ctx,
+                antivirus.handle_respmod(&request, ctx),
+            )
+            .await;
+            self.end_span(span, &result);
+            ctx.record_module("antivirus", &result);
+            match result {
                 Ok(response) => {
                     println!("DEBUG: Antivirus module processed RESPMOD request: {}", response.status);
+                    if let Some(threat) = response
+                        .headers
+                        .get(crate::modules::antivirus::HEADER_VIRUS_ID)
+                        .and_then(|v| v.to_str().ok())
+                    {
+                        self.record_violation(&request, CATEGORY_MALWARE, threat);
+                    }
                     Ok(response)
                 }
                 Err(e) => {
@@ -549,8 +1385,35 @@ impl IcapConnection {
         }
     }
 
+    /// Count a violation of the client of the request, auditing and notifying its escalation
+    fn record_violation(&self, request: &IcapRequest, category: &str, reason: &str) {
+        let Some(escalation) = &self.escalation else {
+            return;
+        };
+        let Some(client) = EscalationTracker::client_key(&request.headers) else {
+            return;
+        };
+        if let Some(e) = escalation.record(&client, category, reason) {
+            log::warn!("{e}");
+            self.audit_ops.log_security_event(
+                "Client escalated for quarantine",
+                &e.to_string(),
+                AuditSeverity::Critical,
+            );
+            escalation.notify(e);
+        }
+    }
+
     /// Send ICAP response to client
-    async fn send_response(&mut self, response: IcapResponse) -> IcapResult<()> {
+    ///
+    /// The request id of the transaction is added to the response, generated
+    /// if the request was not received far enough to have one.
+    async fn send_response(&mut self, mut response: IcapResponse) -> IcapResult<()> {
+        let request_id = self.request_id.get_or_insert_with(transaction::new_id);
+        if let Ok(value) = request_id.parse() {
+            response.headers.insert(HEADER_REQUEST_ID, value);
+        }
+
         let connection_id = format!("{}", self.peer_addr);
         let logger = get_logger(&connection_id).unwrap_or_else(|| {
             slog::Logger::root(slog::Discard, slog::o!())
@@ -561,11 +1424,7 @@ impl IcapConnection {
         // Serialize response using the ICAP serializer
         let response_data = crate::protocol::common::IcapSerializer::serialize_response(&response)?;
         
-        self.stream.write_all(&response_data).await
-            .map_err(|e| IcapError::Io(e))?;
-        
-        self.stream.flush().await
-            .map_err(|e| IcapError::Io(e))?;
+        self.write_tracked(&response_data).await?;
         
         // Update statistics
         if response.status.is_success() {
@@ -577,19 +1436,87 @@ impl IcapConnection {
         Ok(())
     }
 
+    /// Write response data, tracking throughput and detecting slow clients
+    async fn write_tracked(&mut self, data: &[u8]) -> IcapResult<()> {
+        let write_timeout = self.timeouts.write;
+        let Some(config) = self.slow_client.clone() else {
+            let start = std::time::Instant::now();
+            let write = async {
+                self.stream.write_all(data).await?;
+                self.stream.flush().await
+            };
+            match tokio::time::timeout(write_timeout, write).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) if abort::is_abort(&e) => return Err(self.client_aborted("sending the response", &e)),
+                Ok(Err(e)) => return Err(IcapError::Io(e)),
+                Err(_) => return Err(self.timed_out(TimeoutKind::Write)),
+            }
+            self.throughput.add_write(data.len(), start.elapsed());
+            return Ok(());
+        };
+
+        for chunk in data.chunks(RESPONSE_WRITE_CHUNK) {
+            let start = std::time::Instant::now();
+            let write = async {
+                self.stream.write_all(chunk).await?;
+                self.stream.flush().await
+            };
+            // Only cut a stalled write short when stalled clients are to be terminated
+            let result = if config.terminate {
+                tokio::time::timeout(config.max_stall, write).await
+            } else {
+                match tokio::time::timeout(write_timeout, write).await {
+                    Ok(r) => Ok(r),
+                    Err(_) => return Err(self.timed_out(TimeoutKind::Write)),
+                }
+            };
+            match result {
+                Ok(Ok(())) => self.throughput.add_write(chunk.len(), start.elapsed()),
+                Ok(Err(e)) if abort::is_abort(&e) => return Err(self.client_aborted("sending the response", &e)),
+                Ok(Err(e)) => return Err(IcapError::Io(e)),
+                Err(_) => self.throughput.add_write(0, start.elapsed()),
+            }
+
+            let verdict = self.throughput.check(&config);
+            if verdict != SlowClientVerdict::Ok {
+                if self.throughput.flag() {
+                    self.stats.increment_slow_clients_flagged();
+                    log::warn!(
+                        "slow client {} ({:?}): rate={}B/s max_stall={}ms",
+                        self.peer_addr,
+                        verdict,
+                        self.throughput.write_rate(),
+                        self.throughput.max_stall().as_millis()
+                    );
+                }
+                if config.terminate {
+                    self.stats.increment_slow_clients_terminated();
+                    let _ = self.stream.shutdown().await;
+                    return Err(IcapError::network_simple(format!(
+                        "slow client {} terminated: {:?}",
+                        self.peer_addr, verdict
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Parse HTTP request from encapsulated data
     async fn parse_http_request_from_encapsulated(&self, encapsulated: &EncapsulatedData) -> IcapResult<HttpRequest> {
         // Extract request headers and body from encapsulated data
         let req_headers = encapsulated.req_hdr.as_ref()
             .ok_or_else(|| IcapError::protocol_simple("No request headers in encapsulated data".to_string()))?;
+        let req_line = encapsulated.req_line.as_ref()
+            .ok_or_else(|| IcapError::protocol_simple("No request line in encapsulated data".to_string()))?;
         
         let req_body = encapsulated.req_body.as_ref()
             .map(|b| b.to_vec())
             .unwrap_or_default();
 
-        // Extract method and URI from headers (simplified)
-        let method = "GET".to_string(); // Default method
-        let uri = "/".to_string(); // Default URI
+        // The request line gives the method, and the URL with the Host header
+        let method = req_line.method.clone();
+        let uri = req_line.url(req_headers);
         
         // Convert headers to our format
         let mut headers = Vec::new();
@@ -609,7 +1536,11 @@ impl IcapConnection {
     }
 
     /// Apply basic content filtering to HTTP request (fallback)
-    async fn apply_basic_content_filtering(&self, http_request: &HttpRequest) -> IcapResult<IcapResponse> {
+    async fn apply_basic_content_filtering(
+        &self,
+        icap_headers: &http::HeaderMap,
+        http_request: &HttpRequest,
+    ) -> IcapResult<IcapResponse> {
         println!("DEBUG: Applying basic content filtering to {} {}", http_request.method, http_request.uri);
 
         // Check for blocked domains
@@ -622,81 +1553,31 @@ impl IcapConnection {
                     &format!("Blocked domain: {}", host)
                 );
                 
-                return Ok(IcapResponse {
-                    status: http::StatusCode::FORBIDDEN,
-                    version: http::Version::HTTP_11,
-                    headers: {
-        let mut headers = http::HeaderMap::new();
-                        headers.insert("X-ICAP-Error", "Blocked domain".parse().unwrap());
-                        headers
-                    },
-                    body: bytes::Bytes::from("Request blocked: blocked domain"),
-                    encapsulated: None,
-                });
+                return Ok(self.basic_block_page(icap_headers, http_request, "domain", "Blocked domain"));
             }
         }
 
         // Check for blocked keywords in URI
         if self.contains_blocked_keywords(&http_request.uri) {
-            return Ok(IcapResponse {
-                status: http::StatusCode::FORBIDDEN,
-                version: http::Version::HTTP_11,
-                headers: {
-                    let mut headers = http::HeaderMap::new();
-                    headers.insert("X-ICAP-Error", "Blocked keywords in URI".parse().unwrap());
-        headers
-                },
-                body: bytes::Bytes::from("Request blocked: blocked keywords in URI"),
-                encapsulated: None,
-            });
+            return Ok(self.basic_block_page(icap_headers, http_request, "keyword", "Blocked keywords in URI"));
         }
 
         // Check for blocked MIME types
         if let Some(content_type) = self.extract_content_type(&http_request.headers) {
             if self.is_blocked_mime_type(&content_type) {
-                return Ok(IcapResponse {
-                    status: http::StatusCode::FORBIDDEN,
-                    version: http::Version::HTTP_11,
-                    headers: {
-        let mut headers = http::HeaderMap::new();
-                        headers.insert("X-ICAP-Error", "Blocked MIME type".parse().unwrap());
-        headers
-                    },
-                    body: bytes::Bytes::from("Request blocked: blocked MIME type"),
-                    encapsulated: None,
-                });
+                return Ok(self.basic_block_page(icap_headers, http_request, "mime_type", "Blocked MIME type"));
             }
         }
 
         // Check file size
         if http_request.body.len() > 10 * 1024 * 1024 { // 10MB limit
-            return Ok(IcapResponse {
-                status: http::StatusCode::FORBIDDEN,
-                version: http::Version::HTTP_11,
-                headers: {
-                    let mut headers = http::HeaderMap::new();
-                    headers.insert("X-ICAP-Error", "File too large".parse().unwrap());
-                    headers
-                },
-                body: bytes::Bytes::from("Request blocked: file too large"),
-                encapsulated: None,
-            });
+            return Ok(self.basic_block_page(icap_headers, http_request, "file_size", "File too large"));
         }
 
         // Check for blocked keywords in body
         if self.contains_blocked_keywords(&String::from_utf8_lossy(&http_request.body)) {
-                return Ok(IcapResponse {
-                status: http::StatusCode::FORBIDDEN,
-                    version: http::Version::HTTP_11,
-                headers: {
-                    let mut headers = http::HeaderMap::new();
-                    headers.insert("X-ICAP-Error", "Blocked keywords in content".parse().unwrap());
-                    headers
-                },
-                body: bytes::Bytes::from("Request blocked: blocked keywords in content"),
-                    encapsulated: None,
-                });
-            }
+            return Ok(self.basic_block_page(icap_headers, http_request, "keyword", "Blocked keywords in content"));
+        }
 
         // Allow the request - return 200 OK for G3Proxy compatibility
         Ok(IcapResponse {
@@ -708,6 +1589,33 @@ impl IcapConnection {
         })
     }
 
+    /// Build the built-in block page of the basic filtering
+    fn basic_block_page(
+        &self,
+        icap_headers: &http::HeaderMap,
+        http_request: &HttpRequest,
+        category: &str,
+        reason: &str,
+    ) -> IcapResponse {
+        let ticket_id = BlockPages::new_ticket_id();
+        let vars = BlockPageVars {
+            url: &http_request.uri,
+            category,
+            rule: "basic",
+            reason,
+            client_ip: BlockPages::client_ip(icap_headers).unwrap_or_default(),
+            ticket_id: &ticket_id,
+        };
+        let pages = BlockPages::default();
+        let mut response = self
+            .response_generator
+            .http_page_response(pages.status(), &pages.render(&vars));
+        if let Ok(value) = reason.parse() {
+            response.headers.insert("X-ICAP-Error", value);
+        }
+        response
+    }
+
     /// Apply content filtering to HTTP request (legacy method)
     #[allow(dead_code)]
     async fn apply_content_filtering(&self, http_request: &HttpRequest) -> IcapResult<FilterResult> {
@@ -761,14 +1669,9 @@ impl IcapConnection {
 
     /// Check if domain is blocked
     fn is_blocked_domain(&self, host: &str) -> bool {
-        let blocked_domains = vec![
-            "malware.com",
-            "phishing.net",
-            "spam.org",
-            "virus.example",
-        ];
-        
-        blocked_domains.iter().any(|domain| host.contains(domain))
+        self.blocklist
+            .as_ref()
+            .is_some_and(|blocklist| blocklist.find(host).is_some())
     }
 
     /// Check if content contains blocked keywords
@@ -802,15 +1705,13 @@ impl IcapConnection {
         // Extract response headers and body from encapsulated data
         let res_headers = encapsulated.res_hdr.as_ref()
             .ok_or_else(|| IcapError::protocol_simple("No response headers in encapsulated data".to_string()))?;
+        let status_line = encapsulated.status_line.clone()
+            .ok_or_else(|| IcapError::protocol_simple("No status line in encapsulated data".to_string()))?;
         
         let res_body = encapsulated.res_body.as_ref()
             .map(|b| b.to_vec())
             .unwrap_or_default();
 
-        // Extract status code from headers (simplified)
-        let status_code = 200; // Default status
-        let status_text = "OK".to_string();
-        
         // Convert headers to our format
         let mut headers = Vec::new();
         for (name, value) in res_headers.iter() {
@@ -821,8 +1722,7 @@ impl IcapConnection {
         }
 
         Ok(HttpResponse {
-            status_code,
-            status_text,
+            status_line,
             headers,
             body: res_body,
         })
@@ -830,7 +1730,18 @@ impl IcapConnection {
 
     /// Apply basic antivirus scanning to HTTP response (fallback)
     async fn apply_basic_antivirus_scanning(&self, http_response: &HttpResponse) -> IcapResult<IcapResponse> {
-        println!("DEBUG: Applying basic antivirus scanning to response with {} bytes", http_response.body.len());
+        println!("DEBUG: Applying basic antivirus scanning to {} response with {} bytes", http_response.status_line.status, http_response.body.len());
+
+        // A response without content, e.g. a 304, has nothing to scan
+        if !http_response.status_line.has_body() {
+            return Ok(IcapResponse {
+                status: http::StatusCode::OK,
+                version: http::Version::HTTP_11,
+                headers: http::HeaderMap::new(),
+                body: bytes::Bytes::new(),
+                encapsulated: None, // This will be set by the caller
+            });
+        }
 
         // Check for known virus signatures in response body
         if self.contains_virus_signatures(&http_response.body) {
@@ -1036,3 +1947,8 @@ impl IcapConnection {
             .map(|(_, value)| value.clone())
     }
 }
+
+/// Span of the pipeline run for the service of a transaction
+fn pipeline_span(pipeline: Option<&Pipeline>) -> Span {
+    Span::start("icap.pipeline").with("icap.pipeline", pipeline.map_or("default", |p| p.name()))
+}
@@ -0,0 +1,123 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Monitor mode of the connections
+//!
+//! A verdict is a response that blocks or modifies the message. In monitor
+//! mode it is replaced by a 204 if the client allows it, or else by an echo
+//! of the original encapsulated message.
+
+use http::StatusCode;
+
+use crate::modules::antivirus::HEADER_VIRUS_ID;
+use crate::modules::content_filter::HEADER_RULE_ID;
+use crate::protocol::common::{IcapRequest, IcapResponse};
+use crate::protocol::headers::allows_204;
+use crate::protocol::response_generator::IcapResponseGenerator;
+
+/// Check if the response blocks or modifies the message
+pub(super) fn is_verdict(response: &IcapResponse) -> bool {
+    match response.status {
+        StatusCode::NO_CONTENT | StatusCode::CONTINUE => false,
+        // a 200 without body is how the fallback scanners allow a message
+        StatusCode::OK => !response.body.is_empty(),
+        StatusCode::FORBIDDEN => true,
+        _ => {
+            response.headers.contains_key(HEADER_RULE_ID)
+                || response.headers.contains_key(HEADER_VIRUS_ID)
+        }
+    }
+}
+
+/// Describe the verdict for the logs
+pub(super) fn describe(response: &IcapResponse) -> String {
+    let header = |name: &str| response.headers.get(name).and_then(|v| v.to_str().ok());
+    let mut desc = format!("status {}", response.status.as_u16());
+    if let Some(rule_id) = header(HEADER_RULE_ID) {
+        desc.push_str(&format!(", rule {rule_id}"));
+    }
+    if let Some(threat) = header(HEADER_VIRUS_ID) {
+        desc.push_str(&format!(", threat {threat}"));
+    }
+    desc
+}
+
+/// The response allowing the message unmodified
+pub(super) fn allow(generator: &IcapResponseGenerator, request: &IcapRequest) -> IcapResponse {
+    let allow_204 = allows_204(&request.headers);
+    let encapsulated = request
+        .headers
+        .get("encapsulated")
+        .filter(|_| !request.body.is_empty());
+    match encapsulated {
+        Some(encapsulated) if !allow_204 => {
+            let mut response = generator.ok_modified(None, request.body.clone());
+            response
+                .headers
+                .insert("encapsulated", encapsulated.clone());
+            response
+        }
+        _ => generator.no_modifications(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use http::{HeaderMap, Version};
+
+    use crate::protocol::common::IcapMethod;
+
+    fn generator() -> IcapResponseGenerator {
+        IcapResponseGenerator::new("G3ICAP/1.0.0".to_string(), "g3icap-1.0.0".to_string())
+    }
+
+    fn request(allow: Option<&str>) -> IcapRequest {
+        let mut headers = HeaderMap::new();
+        headers.insert("encapsulated", "req-hdr=0, null-body=37".parse().unwrap());
+        if let Some(allow) = allow {
+            headers.insert("allow", allow.parse().unwrap());
+        }
+        IcapRequest {
+            method: IcapMethod::Reqmod,
+            uri: "icap://icap.example.net/reqmod".parse().unwrap(),
+            version: Version::HTTP_11,
+            headers,
+            body: Bytes::from_static(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n"),
+            encapsulated: None,
+        }
+    }
+
+    #[test]
+    fn verdicts() {
+        let generator = generator();
+        assert!(!is_verdict(&generator.no_modifications(None)));
+        assert!(is_verdict(&generator.forbidden(Some("blocked"))));
+        let mut found = generator.found("http://example.com/blocked");
+        assert!(!is_verdict(&found));
+        found
+            .headers
+            .insert(HEADER_RULE_ID, "default:domain:0".parse().unwrap());
+        assert!(is_verdict(&found));
+        assert_eq!(describe(&found), "status 302, rule default:domain:0");
+    }
+
+    #[test]
+    fn allow_response() {
+        let generator = generator();
+        let response = allow(&generator, &request(Some("204")));
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+
+        // echo when 204 is not allowed
+        let response = allow(&generator, &request(None));
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(
+            response.headers.get("encapsulated").unwrap(),
+            "req-hdr=0, null-body=37"
+        );
+        assert_eq!(response.body, request(None).body);
+    }
+}
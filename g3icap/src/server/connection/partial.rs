@@ -0,0 +1,161 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Partial content responses of the connections
+//!
+//! A modified message whose body only changed in the preview is sent back
+//! as a 206 if the client allows it, the client going on with its original
+//! body where the adapted one joins it again.
+
+use http::StatusCode;
+
+use crate::protocol::common::{IcapMethod, IcapRequest, IcapResponse};
+use crate::protocol::headers::{allows_206, preview_size};
+use crate::protocol::partial::PartialBody;
+use crate::protocol::response_generator::IcapResponseGenerator;
+
+/// The 206 response replacing a modified message, if the client allows it
+pub(super) fn partial_content(
+    generator: &IcapResponseGenerator,
+    request: &IcapRequest,
+    response: &IcapResponse,
+) -> Option<IcapResponse> {
+    if response.status != StatusCode::OK || !allows_206(&request.headers) {
+        return None;
+    }
+    let preview = preview_size(&request.headers)?;
+    let adapted = response.encapsulated.as_ref()?;
+    if adapted.trailers.as_ref().is_some_and(|t| !t.is_empty()) {
+        return None;
+    }
+    let original = request.encapsulated.as_ref()?;
+    let (start_line, headers, body, original_body) = match request.method {
+        IcapMethod::Reqmod => (
+            adapted
+                .req_line
+                .as_ref()
+                .or(original.req_line.as_ref())?
+                .to_string(),
+            adapted.req_hdr.as_ref()?,
+            adapted.req_body.as_ref()?,
+            original.req_body.as_ref()?,
+        ),
+        IcapMethod::Respmod => (
+            adapted
+                .status_line
+                .as_ref()
+                .or(original.status_line.as_ref())?
+                .to_string(),
+            adapted.res_hdr.as_ref()?,
+            adapted.res_body.as_ref()?,
+            original.res_body.as_ref()?,
+        ),
+        IcapMethod::Options => return None,
+    };
+    let partial = PartialBody::split(original_body, body, preview)?;
+
+    let mut partial_response =
+        generator.partial_content(&request.method, &start_line, headers.clone(), &partial);
+    // keep the headers the modules set on the response
+    let mut response_headers = response.headers.clone();
+    response_headers.remove("transfer-encoding");
+    if let Some(encapsulated) = partial_response.headers.get("encapsulated") {
+        response_headers.insert("encapsulated", encapsulated.clone());
+    }
+    partial_response.headers = response_headers;
+    Some(partial_response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use http::{HeaderMap, Version};
+
+    use crate::protocol::common::EncapsulatedData;
+    use crate::protocol::start_line::StatusLine;
+
+    const HTTP_HEADER: &str = "HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\n";
+
+    fn generator() -> IcapResponseGenerator {
+        IcapResponseGenerator::new("G3ICAP/1.0.0".to_string(), "g3icap-1.0.0".to_string())
+    }
+
+    fn encapsulated(body: &'static [u8]) -> EncapsulatedData {
+        let mut res_hdr = HeaderMap::new();
+        res_hdr.insert("content-length", body.len().into());
+        EncapsulatedData {
+            req_hdr: None,
+            req_body: None,
+            res_hdr: Some(res_hdr),
+            res_body: Some(Bytes::from_static(body)),
+            null_body: false,
+            trailers: None,
+            ieof: false,
+            req_line: None,
+            status_line: None,
+        }
+    }
+
+    fn request(allow: &str) -> IcapRequest {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "encapsulated",
+            format!("res-hdr=0, res-body={}", HTTP_HEADER.len())
+                .parse()
+                .unwrap(),
+        );
+        headers.insert("allow", allow.parse().unwrap());
+        headers.insert("preview", "5".parse().unwrap());
+        IcapRequest {
+            method: IcapMethod::Respmod,
+            uri: "icap://icap.example.net/respmod".parse().unwrap(),
+            version: Version::HTTP_11,
+            headers,
+            body: Bytes::from(format!("{HTTP_HEADER}b\r\nhello world\r\n0\r\n\r\n")),
+            encapsulated: Some(EncapsulatedData {
+                status_line: StatusLine::parse("HTTP/1.1 200 OK"),
+                ..encapsulated(b"hello world")
+            }),
+        }
+    }
+
+    #[test]
+    fn preview_adapted() {
+        let generator = generator();
+        let request = request("204, 206");
+        let mut response = generator.ok_modified(Some(encapsulated(b"Hey world")), Bytes::new());
+        response
+            .headers
+            .insert("x-ai-redacted", "1".parse().unwrap());
+
+        let partial = partial_content(&generator, &request, &response).unwrap();
+        assert_eq!(partial.status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(partial.headers.get("x-ai-redacted").unwrap(), "1");
+        assert_eq!(
+            partial.body.as_ref(),
+            b"HTTP/1.1 200 OK\r\ncontent-length: 9\r\n\r\n3\r\nHey\r\n0; use-original-body=5\r\n\r\n"
+        );
+        assert_eq!(
+            partial.headers.get("encapsulated").unwrap(),
+            "res-hdr=0, res-body=38"
+        );
+    }
+
+    #[test]
+    fn not_partial() {
+        let generator = generator();
+        let response = generator.ok_modified(Some(encapsulated(b"Hey world")), Bytes::new());
+        // 206 not allowed
+        assert!(partial_content(&generator, &request("204"), &response).is_none());
+
+        // changed after the preview
+        let response = generator.ok_modified(Some(encapsulated(b"hello wOrld")), Bytes::new());
+        assert!(partial_content(&generator, &request("206"), &response).is_none());
+
+        let response = generator.forbidden(Some("blocked"));
+        assert!(partial_content(&generator, &request("206"), &response).is_none());
+    }
+}
@@ -0,0 +1,89 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Service routing of the connections
+//!
+//! With registered services, only their requests reach the modules. The
+//! default service answers OPTIONS by itself and any other path gets a 404.
+
+use std::collections::HashMap;
+
+use crate::config::server::services::ServicesConfig;
+use crate::protocol::common::{IcapMethod, IcapRequest, IcapResponse};
+use crate::protocol::response_generator::IcapResponseGenerator;
+
+/// Header of the default service OPTIONS response listing the services
+pub(super) const HEADER_SERVICE_IDS: &str = "x-service-ids";
+
+/// The response of a request that is not for a registered service, if it is not
+pub(super) fn route(
+    services: &ServicesConfig,
+    generator: &IcapResponseGenerator,
+    request: &IcapRequest,
+) -> Option<IcapResponse> {
+    let service = request.uri.path();
+    if services.is_registered(service) {
+        return None;
+    }
+    if !services.is_default(service) {
+        return Some(generator.not_found(Some(service)));
+    }
+    let response = match request.method {
+        IcapMethod::Options => {
+            let mut capabilities = HashMap::new();
+            capabilities.insert(HEADER_SERVICE_IDS.to_string(), services.names.join(", "));
+            generator.options_response(&[IcapMethod::Options], capabilities)
+        }
+        _ => generator.method_not_allowed(&request.method, &[IcapMethod::Options]),
+    };
+    Some(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use http::{HeaderMap, StatusCode, Version};
+
+    fn request(method: IcapMethod, uri: &str) -> IcapRequest {
+        IcapRequest {
+            method,
+            uri: uri.parse().unwrap(),
+            version: Version::HTTP_11,
+            headers: HeaderMap::new(),
+            body: Bytes::new(),
+            encapsulated: None,
+        }
+    }
+
+    #[test]
+    fn routes() {
+        let generator =
+            IcapResponseGenerator::new("G3ICAP/1.0.0".to_string(), "g3icap-1.0.0".to_string());
+        let services = ServicesConfig {
+            names: vec!["reqmod".to_string(), "av/respmod".to_string()],
+            default_service: String::new(),
+        };
+        let route = |method, uri| route(&services, &generator, &request(method, uri));
+
+        assert!(route(IcapMethod::Reqmod, "icap://icap.example.net/reqmod").is_none());
+        assert!(route(IcapMethod::Options, "icap://icap.example.net/av/respmod/").is_none());
+
+        let response = route(IcapMethod::Reqmod, "icap://icap.example.net/unknown").unwrap();
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+        let response = route(IcapMethod::Options, "icap://icap.example.net/unknown").unwrap();
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+        let response = route(IcapMethod::Options, "icap://icap.example.net/").unwrap();
+        assert!(response.status.is_success());
+        assert_eq!(
+            response.headers.get(HEADER_SERVICE_IDS).unwrap(),
+            "reqmod, av/respmod"
+        );
+        assert_eq!(response.headers.get("methods").unwrap(), "OPTIONS");
+        let response = route(IcapMethod::Respmod, "icap://icap.example.net").unwrap();
+        assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+    }
+}
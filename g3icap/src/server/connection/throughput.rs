@@ -0,0 +1,124 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Per-connection throughput tracking
+//!
+//! Keeps byte counters and write stall durations of a single connection and
+//! decides whether its client should be treated as a slow reader.
+
+use std::time::{Duration, Instant};
+
+use crate::config::server::slow_client::SlowClientConfig;
+
+/// Verdict for a connection after a response write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlowClientVerdict {
+    /// The client reads fast enough
+    Ok,
+    /// A single write stalled for longer than allowed
+    Stalled,
+    /// The overall write rate is below the threshold
+    TooSlow,
+}
+
+/// Throughput counters of a connection
+#[derive(Debug)]
+pub struct ConnectionThroughput {
+    started: Instant,
+    bytes_in: u64,
+    bytes_out: u64,
+    /// Time spent in response writes
+    write_time: Duration,
+    /// Longest single write
+    max_stall: Duration,
+    /// Whether the client was already flagged as slow
+    flagged: bool,
+}
+
+impl ConnectionThroughput {
+    /// Start tracking a new connection
+    pub fn new() -> Self {
+        ConnectionThroughput {
+            started: Instant::now(),
+            bytes_in: 0,
+            bytes_out: 0,
+            write_time: Duration::ZERO,
+            max_stall: Duration::ZERO,
+            flagged: false,
+        }
+    }
+
+    /// Record bytes read from the client
+    pub fn add_read(&mut self, bytes: usize) {
+        self.bytes_in += bytes as u64;
+    }
+
+    /// Record a finished write to the client
+    pub fn add_write(&mut self, bytes: usize, elapsed: Duration) {
+        self.bytes_out += bytes as u64;
+        self.write_time += elapsed;
+        if elapsed > self.max_stall {
+            self.max_stall = elapsed;
+        }
+    }
+
+    /// Bytes read from the client
+    pub fn bytes_in(&self) -> u64 {
+        self.bytes_in
+    }
+
+    /// Bytes written to the client
+    pub fn bytes_out(&self) -> u64 {
+        self.bytes_out
+    }
+
+    /// Longest single write stall
+    pub fn max_stall(&self) -> Duration {
+        self.max_stall
+    }
+
+    /// Average response write rate in bytes per second
+    pub fn write_rate(&self) -> u64 {
+        let secs = self.write_time.as_secs_f64();
+        if secs <= 0.0 {
+            return u64::MAX;
+        }
+        (self.bytes_out as f64 / secs) as u64
+    }
+
+    /// Average bytes per second in both directions over the connection lifetime
+    pub fn overall_rate(&self) -> u64 {
+        let secs = self.started.elapsed().as_secs_f64();
+        if secs <= 0.0 {
+            return 0;
+        }
+        ((self.bytes_in + self.bytes_out) as f64 / secs) as u64
+    }
+
+    /// Flag the client as slow, returns false if it was already flagged
+    ///
+    /// The verdict of [`check`](Self::check) sticks once a threshold is
+    /// crossed, so this makes a slow client counted and reported only once.
+    pub fn flag(&mut self) -> bool {
+        !std::mem::replace(&mut self.flagged, true)
+    }
+
+    /// Check the counters against the listener thresholds
+    pub fn check(&self, config: &SlowClientConfig) -> SlowClientVerdict {
+        if self.max_stall >= config.max_stall {
+            return SlowClientVerdict::Stalled;
+        }
+        if self.bytes_out >= config.min_check_bytes && self.write_rate() < config.min_write_rate {
+            return SlowClientVerdict::TooSlow;
+        }
+        SlowClientVerdict::Ok
+    }
+}
+
+impl Default for ConnectionThroughput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
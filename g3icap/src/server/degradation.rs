@@ -0,0 +1,380 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Degradation ladder under resource pressure
+//!
+//! The pressure is sampled periodically as the highest ratio of the CPU,
+//! memory and backlog usage to their thresholds, and of the SLO error budget
+//! burn rate to the one set to degrade at. Each sample at or over the
+//! thresholds takes the ladder one step up, up to the last one; enough calm
+//! samples in a row take it one step back down. Every step is logged and
+//! counted, as are the transactions handled at a degraded level.
+
+use std::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use arc_swap::ArcSwapOption;
+use tokio::task::JoinHandle;
+
+use super::slo::SloTracker;
+use crate::config::server::degradation::{DegradationConfig, FailMode};
+use crate::stats::{IcapStats, resource};
+
+static GLOBAL_LADDER: ArcSwapOption<DegradationLadder> = ArcSwapOption::const_empty();
+
+/// Install the ladder of the server, for its metrics
+pub fn set_global(ladder: Option<Arc<DegradationLadder>>) {
+    GLOBAL_LADDER.store(ladder);
+}
+
+/// Get the ladder currently in use
+pub fn get_global() -> Option<Arc<DegradationLadder>> {
+    GLOBAL_LADDER.load_full()
+}
+
+/// Steps of the ladder, in the order they are taken
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum DegradationLevel {
+    /// Everything is done
+    #[default]
+    Normal = 0,
+    /// Modified messages are not sent back, the original is allowed
+    NoTransforms = 1,
+    /// Only a sample of the transactions is scanned, the others are allowed
+    SampleScan = 2,
+    /// The sampled transactions only get the signature checks
+    HashOnly = 3,
+    /// Nothing is scanned, the services fail open or closed
+    Fail = 4,
+}
+
+impl DegradationLevel {
+    pub const ALL: [DegradationLevel; 5] = [
+        DegradationLevel::Normal,
+        DegradationLevel::NoTransforms,
+        DegradationLevel::SampleScan,
+        DegradationLevel::HashOnly,
+        DegradationLevel::Fail,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DegradationLevel::Normal => "normal",
+            DegradationLevel::NoTransforms => "no_transforms",
+            DegradationLevel::SampleScan => "sample_scan",
+            DegradationLevel::HashOnly => "hash_only",
+            DegradationLevel::Fail => "fail",
+        }
+    }
+
+    fn from_u8(v: u8) -> Self {
+        Self::ALL
+            .get(v as usize)
+            .copied()
+            .unwrap_or(DegradationLevel::Fail)
+    }
+
+    fn up(self) -> Self {
+        Self::from_u8(self as u8 + 1)
+    }
+
+    fn down(self) -> Self {
+        Self::from_u8((self as u8).saturating_sub(1))
+    }
+}
+
+/// How a transaction is handled at the current level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Treatment {
+    /// Scanned as usual
+    Full,
+    /// Scanned, but a modified message is replaced by the original
+    NoTransforms,
+    /// Only the signature checks are run, without transforms
+    HashOnly,
+    /// Allowed without being scanned
+    Allow,
+    /// Refused with a 503
+    Refuse,
+}
+
+/// Resource usage of the process
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Pressure {
+    /// CPU time since the previous sample, as a ratio of all the CPUs
+    pub cpu: Option<f64>,
+    /// Resident memory in bytes
+    pub memory: Option<u64>,
+    /// Active connections
+    pub backlog: u64,
+    /// Fastest SLO error budget burn rate, as a ratio of the one to degrade at
+    pub slo: Option<f64>,
+}
+
+#[derive(Default)]
+struct SamplerState {
+    /// Calm samples in a row
+    calm: u32,
+    /// CPU time of the process at the previous sample
+    cpu: Option<(Instant, f64)>,
+}
+
+pub struct DegradationLadder {
+    config: DegradationConfig,
+    level: AtomicU8,
+    state: Mutex<SamplerState>,
+    slo: Option<Arc<SloTracker>>,
+    /// Times each level has been entered
+    entered: [AtomicU64; DegradationLevel::ALL.len()],
+    /// Transactions handled at each level
+    degraded: [AtomicU64; DegradationLevel::ALL.len()],
+}
+
+impl DegradationLadder {
+    pub fn new(config: DegradationConfig) -> Self {
+        DegradationLadder {
+            config,
+            level: AtomicU8::new(DegradationLevel::Normal as u8),
+            state: Mutex::new(SamplerState::default()),
+            slo: None,
+            entered: Default::default(),
+            degraded: Default::default(),
+        }
+    }
+
+    /// Also degrade when the SLO error budgets burn too fast
+    pub fn with_slo(mut self, slo: Option<Arc<SloTracker>>) -> Self {
+        self.slo = slo;
+        self
+    }
+
+    pub fn level(&self) -> DegradationLevel {
+        DegradationLevel::from_u8(self.level.load(Ordering::Relaxed))
+    }
+
+    /// Times the level has been entered
+    pub fn entered(&self, level: DegradationLevel) -> u64 {
+        self.entered[level as usize].load(Ordering::Relaxed)
+    }
+
+    /// Transactions handled at the degraded level
+    pub fn degraded(&self, level: DegradationLevel) -> u64 {
+        self.degraded[level as usize].load(Ordering::Relaxed)
+    }
+
+    /// Highest ratio of the usage to its threshold
+    fn ratio(&self, pressure: &Pressure) -> f64 {
+        let cpu = self
+            .config
+            .cpu
+            .zip(pressure.cpu)
+            .map(|(max, cpu)| cpu / max);
+        let memory = self
+            .config
+            .memory
+            .zip(pressure.memory)
+            .map(|(max, memory)| memory as f64 / max as f64);
+        let backlog = self
+            .config
+            .backlog
+            .map(|max| pressure.backlog as f64 / max as f64);
+        [cpu, memory, backlog, pressure.slo]
+            .into_iter()
+            .flatten()
+            .fold(0.0, f64::max)
+    }
+
+    /// Move the ladder according to a pressure sample, returning the new level
+    /// if it has changed
+    pub fn update(&self, pressure: &Pressure) -> Option<DegradationLevel> {
+        let ratio = self.ratio(pressure);
+        let current = self.level();
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let next = if ratio >= 1.0 {
+            state.calm = 0;
+            current.up()
+        } else if ratio < self.config.recover_ratio {
+            state.calm += 1;
+            if state.calm < self.config.recover_after {
+                return None;
+            }
+            state.calm = 0;
+            current.down()
+        } else {
+            state.calm = 0;
+            return None;
+        };
+        if next == current {
+            return None;
+        }
+
+        self.level.store(next as u8, Ordering::Relaxed);
+        self.entered[next as usize].fetch_add(1, Ordering::Relaxed);
+        if next > current {
+            log::warn!(
+                "degradation level raised from {} to {} at pressure {ratio:.2} ({pressure:?})",
+                current.as_str(),
+                next.as_str()
+            );
+        } else {
+            log::info!(
+                "degradation level lowered from {} to {} at pressure {ratio:.2}",
+                current.as_str(),
+                next.as_str()
+            );
+        }
+        Some(next)
+    }
+
+    /// The configured fail mode of the service
+    pub fn fail_mode(&self, service: &str) -> FailMode {
+        self.config.fail_mode(service)
+    }
+
+    /// How to handle a transaction of the service at the current level
+    pub fn treatment(&self, service: &str) -> Treatment {
+        let level = self.level();
+        let sampled = || fastrand::f64() < self.config.sample_ratio;
+        let treatment = match level {
+            DegradationLevel::Normal => return Treatment::Full,
+            DegradationLevel::NoTransforms => Treatment::NoTransforms,
+            DegradationLevel::SampleScan if sampled() => Treatment::NoTransforms,
+            DegradationLevel::HashOnly if sampled() => Treatment::HashOnly,
+            DegradationLevel::SampleScan | DegradationLevel::HashOnly => Treatment::Allow,
+            DegradationLevel::Fail => match self.config.fail_mode(service) {
+                FailMode::Open => Treatment::Allow,
+                FailMode::Closed => Treatment::Refuse,
+            },
+        };
+        self.degraded[level as usize].fetch_add(1, Ordering::Relaxed);
+        treatment
+    }
+
+    /// Sample the pressure of the process
+    fn sample(&self, stats: &IcapStats) -> Pressure {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let cpu_time = resource::cpu_time();
+        let cpu = match (state.cpu, cpu_time) {
+            (Some((last, last_time)), Some(time)) => {
+                let wall = now.duration_since(last).as_secs_f64();
+                let cpus = std::thread::available_parallelism()
+                    .map(|n| n.get())
+                    .unwrap_or(1) as f64;
+                (wall > 0.0).then(|| (time - last_time).max(0.0) / (wall * cpus))
+            }
+            _ => None,
+        };
+        state.cpu = cpu_time.map(|time| (now, time));
+        Pressure {
+            cpu,
+            memory: resource::resident_memory(),
+            backlog: stats.active_connections(),
+            slo: self.slo.as_ref().and_then(|slo| slo.pressure()),
+        }
+    }
+
+    /// Sample the pressure and move the ladder until the ladder is dropped
+    pub fn spawn(self: &Arc<Self>, stats: Arc<IcapStats>) -> JoinHandle<()> {
+        let ladder = Arc::downgrade(self);
+        let interval = self.config.interval;
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let Some(ladder) = ladder.upgrade() else {
+                    break;
+                };
+                let pressure = ladder.sample(&stats);
+                ladder.update(&pressure);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ladder() -> DegradationLadder {
+        let mut config = DegradationConfig {
+            backlog: Some(100),
+            recover_after: 2,
+            fail_mode: FailMode::Closed,
+            ..Default::default()
+        };
+        config.services.insert("echo".to_string(), FailMode::Open);
+        DegradationLadder::new(config)
+    }
+
+    fn backlog(backlog: u64) -> Pressure {
+        Pressure {
+            backlog,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn ladder_steps() {
+        let ladder = ladder();
+        assert_eq!(ladder.update(&backlog(50)), None);
+        assert_eq!(ladder.treatment("reqmod"), Treatment::Full);
+
+        for level in &DegradationLevel::ALL[1..] {
+            assert_eq!(ladder.update(&backlog(150)), Some(*level));
+        }
+        // saturated
+        assert_eq!(ladder.update(&backlog(1000)), None);
+        assert_eq!(ladder.level(), DegradationLevel::Fail);
+        assert_eq!(ladder.treatment("/reqmod"), Treatment::Refuse);
+        assert_eq!(ladder.treatment("/echo"), Treatment::Allow);
+        assert_eq!(ladder.degraded(DegradationLevel::Fail), 2);
+
+        // between the recover ratio and the threshold
+        assert_eq!(ladder.update(&backlog(90)), None);
+        assert_eq!(ladder.update(&backlog(10)), None);
+        assert_eq!(ladder.update(&backlog(90)), None);
+        assert_eq!(ladder.update(&backlog(10)), None);
+        assert_eq!(
+            ladder.update(&backlog(10)),
+            Some(DegradationLevel::HashOnly)
+        );
+        assert_eq!(ladder.entered(DegradationLevel::HashOnly), 2);
+    }
+
+    #[test]
+    fn ladder_recovery() {
+        let ladder = ladder();
+        assert_eq!(
+            ladder.update(&backlog(100)),
+            Some(DegradationLevel::NoTransforms)
+        );
+        assert_eq!(ladder.treatment("reqmod"), Treatment::NoTransforms);
+        assert_eq!(ladder.update(&backlog(0)), None);
+        assert_eq!(ladder.update(&backlog(0)), Some(DegradationLevel::Normal));
+        assert_eq!(ladder.update(&backlog(0)), None);
+        assert_eq!(ladder.update(&backlog(0)), None);
+        assert_eq!(ladder.level(), DegradationLevel::Normal);
+        assert_eq!(ladder.treatment("reqmod"), Treatment::Full);
+        assert_eq!(ladder.degraded(DegradationLevel::NoTransforms), 1);
+    }
+
+    #[test]
+    fn slo_burn() {
+        let ladder = ladder();
+        let burn = |slo| Pressure {
+            slo: Some(slo),
+            ..Default::default()
+        };
+        assert_eq!(ladder.update(&burn(0.9)), None);
+        assert_eq!(
+            ladder.update(&burn(1.5)),
+            Some(DegradationLevel::NoTransforms)
+        );
+    }
+}
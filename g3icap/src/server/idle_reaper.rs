@@ -0,0 +1,303 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Idle connection reaper
+//!
+//! Each connection of the server registers with the reaper, and marks itself
+//! idle while it waits for its next request. The reaper periodically closes
+//! the connections idle for longer than the idle timeout, and when the
+//! connections go over the watermark, the ones idle for the longest until
+//! back under it. A connection leaves the idle state as soon as the first
+//! bytes of a request are read, and only idle connections are reaped, so a
+//! request is never cut in the middle of its parsing.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::config::server::idle_reaper::{IdleReaperConfig, ReapReason};
+use crate::stats::IcapStats;
+
+const BUSY: u8 = 0;
+const IDLE: u8 = 1;
+const REAPED: u8 = 2;
+
+/// Idle state of a registered connection
+struct IdleSlot {
+    state: AtomicU8,
+    /// Start of the idle wait, in milliseconds since the reaper was created
+    idle_since: AtomicU64,
+    reaped: Notify,
+}
+
+impl IdleSlot {
+    /// Reap the connection if it is still idle
+    fn reap(&self) -> bool {
+        if self
+            .state
+            .compare_exchange(IDLE, REAPED, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return false;
+        }
+        self.reaped.notify_one();
+        true
+    }
+}
+
+pub struct IdleReaper {
+    config: IdleReaperConfig,
+    /// Connections over which the idle ones are reaped
+    limit: usize,
+    epoch: Instant,
+    next_id: AtomicU64,
+    slots: Mutex<HashMap<u64, Arc<IdleSlot>>>,
+    /// Notified when a connection is registered over the limit
+    pressure: Arc<Notify>,
+    stats: Arc<IcapStats>,
+}
+
+/// Registration of a connection, removed when it is dropped
+pub struct IdleGuard {
+    reaper: Arc<IdleReaper>,
+    id: u64,
+    slot: Arc<IdleSlot>,
+}
+
+impl Drop for IdleGuard {
+    fn drop(&mut self) {
+        self.reaper.lock().remove(&self.id);
+    }
+}
+
+impl IdleGuard {
+    /// Wait for the next request with the connection idle
+    ///
+    /// Returns `None` if the connection is reaped before the read completes.
+    /// Once the read completes the request is read whatever the reaper
+    /// decided meanwhile, the connection being closed after it if reaped.
+    pub async fn wait<F: Future>(&self, read: F) -> Option<F::Output> {
+        self.slot
+            .idle_since
+            .store(self.reaper.now_millis(), Ordering::Relaxed);
+        self.slot.state.store(IDLE, Ordering::Release);
+        let output = tokio::select! {
+            biased;
+            output = read => output,
+            _ = self.slot.reaped.notified() => return None,
+        };
+        let _ = self
+            .slot
+            .state
+            .compare_exchange(IDLE, BUSY, Ordering::AcqRel, Ordering::Acquire);
+        Some(output)
+    }
+
+    /// Check if the connection has been reaped
+    pub fn is_reaped(&self) -> bool {
+        self.slot.state.load(Ordering::Acquire) == REAPED
+    }
+}
+
+impl IdleReaper {
+    pub fn new(config: IdleReaperConfig, max_connections: usize, stats: Arc<IcapStats>) -> Self {
+        let limit = (max_connections as f64 * config.watermark).ceil() as usize;
+        IdleReaper {
+            config,
+            limit,
+            epoch: Instant::now(),
+            next_id: AtomicU64::new(0),
+            slots: Mutex::new(HashMap::new()),
+            pressure: Arc::new(Notify::new()),
+            stats,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Arc<IdleSlot>>> {
+        self.slots.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn now_millis(&self) -> u64 {
+        u64::try_from(self.epoch.elapsed().as_millis()).unwrap_or(u64::MAX)
+    }
+
+    /// Register a new connection, reaping at once if over the limit
+    pub fn register(self: &Arc<Self>) -> IdleGuard {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let slot = Arc::new(IdleSlot {
+            state: AtomicU8::new(BUSY),
+            idle_since: AtomicU64::new(0),
+            reaped: Notify::new(),
+        });
+        let mut slots = self.lock();
+        slots.insert(id, slot.clone());
+        if slots.len() > self.limit {
+            self.pressure.notify_one();
+        }
+        IdleGuard {
+            reaper: self.clone(),
+            id,
+            slot,
+        }
+    }
+
+    /// Registered connections not reaped yet
+    pub fn connections(&self) -> usize {
+        self.lock()
+            .values()
+            .filter(|slot| slot.state.load(Ordering::Acquire) != REAPED)
+            .count()
+    }
+
+    /// Connections waiting for a request
+    pub fn idle(&self) -> usize {
+        self.lock()
+            .values()
+            .filter(|slot| slot.state.load(Ordering::Acquire) == IDLE)
+            .count()
+    }
+
+    /// Reap the connections idle for too long, then the ones idle for the
+    /// longest while over the limit, returning the number reaped
+    pub fn reap(&self) -> usize {
+        let now = self.now_millis();
+        let timeout = u64::try_from(self.config.idle_timeout.as_millis()).unwrap_or(u64::MAX);
+        let slots = self.lock();
+
+        let mut alive = 0usize;
+        let mut idle = Vec::new();
+        for slot in slots.values() {
+            match slot.state.load(Ordering::Acquire) {
+                REAPED => continue,
+                IDLE => idle.push((slot.idle_since.load(Ordering::Relaxed), slot)),
+                _ => {}
+            }
+            alive += 1;
+        }
+        idle.sort_unstable_by_key(|(since, _)| *since);
+
+        let mut excess = alive.saturating_sub(self.limit);
+        let mut reaped = 0;
+        for (since, slot) in idle {
+            let reason = if now.saturating_sub(since) >= timeout {
+                ReapReason::IdleTimeout
+            } else if excess > 0 {
+                ReapReason::Pressure
+            } else {
+                break;
+            };
+            if slot.reap() {
+                self.stats.increment_reaped(reason);
+                excess = excess.saturating_sub(1);
+                reaped += 1;
+            }
+        }
+        reaped
+    }
+
+    /// Reap periodically, and at once on pressure, until the reaper is
+    /// dropped
+    pub fn spawn(self: &Arc<Self>) -> JoinHandle<()> {
+        let reaper = Arc::downgrade(self);
+        let pressure = self.pressure.clone();
+        let interval = self.config.interval;
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = pressure.notified() => {}
+                }
+                let Some(reaper) = reaper.upgrade() else {
+                    break;
+                };
+                let n = reaper.reap();
+                if n > 0 {
+                    log::debug!("reaped {n} idle connections");
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn reaper(max_connections: usize) -> (Arc<IdleReaper>, Arc<IcapStats>) {
+        let stats = Arc::new(IcapStats::new());
+        let config = IdleReaperConfig {
+            idle_timeout: Duration::from_secs(60),
+            watermark: 0.5,
+            interval: Duration::from_secs(1),
+        };
+        let reaper = Arc::new(IdleReaper::new(config, max_connections, stats.clone()));
+        (reaper, stats)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_timeout() {
+        let (reaper, stats) = reaper(100);
+        let guard = Arc::new(reaper.register());
+        let waiting = tokio::spawn({
+            let guard = guard.clone();
+            async move { guard.wait(std::future::pending::<()>()).await }
+        });
+        tokio::task::yield_now().await;
+        assert_eq!(reaper.idle(), 1);
+
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert_eq!(reaper.reap(), 0);
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert_eq!(reaper.reap(), 1);
+        assert_eq!(waiting.await.unwrap(), None);
+        assert!(guard.is_reaped());
+        assert_eq!(reaper.connections(), 0);
+        assert_eq!(stats.reaped(ReapReason::IdleTimeout), 1);
+
+        drop(guard);
+        assert_eq!(reaper.lock().len(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn oldest_idle_first() {
+        let (reaper, stats) = reaper(4);
+        let guards: Vec<_> = (0..4).map(|_| Arc::new(reaper.register())).collect();
+        // the first is busy, the others idle from 1, 2 and 3 seconds ago
+        for guard in &guards[1..] {
+            let guard = guard.clone();
+            tokio::spawn(async move { guard.wait(std::future::pending::<()>()).await });
+            tokio::task::yield_now().await;
+            tokio::time::advance(Duration::from_secs(1)).await;
+        }
+
+        // over the limit of 2 connections
+        assert_eq!(reaper.reap(), 2);
+        assert!(!guards[0].is_reaped());
+        assert!(guards[1].is_reaped());
+        assert!(guards[2].is_reaped());
+        assert!(!guards[3].is_reaped());
+        assert_eq!(stats.reaped(ReapReason::Pressure), 2);
+        assert_eq!(reaper.reap(), 0);
+    }
+
+    #[tokio::test]
+    async fn request_read() {
+        let (reaper, _) = reaper(1);
+        let guard = reaper.register();
+        assert_eq!(guard.wait(async { 7 }).await, Some(7));
+        // reading a request is not interrupted
+        assert_eq!(reaper.idle(), 0);
+        assert_eq!(reaper.reap(), 0);
+        assert!(!guard.is_reaped());
+    }
+}
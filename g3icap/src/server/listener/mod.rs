@@ -61,6 +61,9 @@ impl IcapListener {
                     tokio::spawn(async move {
                         println!("DEBUG: Connection handler task started");
                         if let Err(e) = listener.handle_connection(stream, peer_addr, stats).await {
+                            if e.is_client_abort() {
+                                return;
+                            }
                             println!("DEBUG: Connection error: {}", e);
                             let error_logger = get_logger("error").unwrap_or_else(|| {
                                 slog::Logger::root(slog::Discard, slog::o!())
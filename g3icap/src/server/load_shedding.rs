@@ -0,0 +1,300 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Load shedding under overload
+//!
+//! Each REQMOD and RESPMOD transaction is admitted against the highest
+//! ratio of the active connections, the transactions in flight and the 99th
+//! percentile of the recent processing times to their thresholds. Shedding
+//! starts when the ratio reaches 1 and stops when it goes under the recover
+//! ratio, so that the server does not flap around the thresholds. The
+//! percentile is computed periodically over a window of the recent
+//! transactions, the other measures are read at admission.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use arc_swap::ArcSwapOption;
+use tokio::task::JoinHandle;
+
+use crate::config::server::degradation::FailMode;
+use crate::config::server::load_shedding::LoadSheddingConfig;
+
+static GLOBAL_SHEDDER: ArcSwapOption<LoadShedder> = ArcSwapOption::const_empty();
+
+/// Install the load shedder of the server, for its metrics
+pub fn set_global(shedder: Option<Arc<LoadShedder>>) {
+    GLOBAL_SHEDDER.store(shedder);
+}
+
+pub fn get_global() -> Option<Arc<LoadShedder>> {
+    GLOBAL_SHEDDER.load_full()
+}
+
+/// Processing times of the recent transactions
+struct LatencyWindow {
+    micros: Vec<u64>,
+    next: usize,
+}
+
+impl LatencyWindow {
+    fn push(&mut self, micros: u64, size: usize) {
+        if self.micros.len() < size {
+            self.micros.push(micros);
+        } else {
+            self.micros[self.next] = micros;
+        }
+        self.next = (self.next + 1) % size;
+    }
+
+    fn p99(&self) -> u64 {
+        if self.micros.is_empty() {
+            return 0;
+        }
+        let mut micros = self.micros.clone();
+        let index = (micros.len() * 99).div_ceil(100) - 1;
+        *micros.select_nth_unstable(index).1
+    }
+}
+
+pub struct LoadShedder {
+    config: LoadSheddingConfig,
+    shedding: AtomicBool,
+    in_flight: AtomicU64,
+    /// Latency percentile at the last computation, in microseconds
+    latency_p99: AtomicU64,
+    latencies: Mutex<LatencyWindow>,
+    /// Times shedding has started
+    entered: AtomicU64,
+    admitted: AtomicU64,
+    shed: AtomicU64,
+}
+
+/// A transaction admitted, in flight until it is dropped
+pub struct InFlight {
+    shedder: Arc<LoadShedder>,
+    started: Instant,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.shedder.in_flight.fetch_sub(1, Ordering::Relaxed);
+        self.shedder.record(self.started.elapsed());
+    }
+}
+
+impl LoadShedder {
+    pub fn new(config: LoadSheddingConfig) -> Self {
+        let window = config.latency_window;
+        LoadShedder {
+            config,
+            shedding: AtomicBool::new(false),
+            in_flight: AtomicU64::new(0),
+            latency_p99: AtomicU64::new(0),
+            latencies: Mutex::new(LatencyWindow {
+                micros: Vec::with_capacity(window),
+                next: 0,
+            }),
+            entered: AtomicU64::new(0),
+            admitted: AtomicU64::new(0),
+            shed: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether the shed transactions are allowed or refused
+    pub fn fail_mode(&self) -> FailMode {
+        self.config.fail_mode
+    }
+
+    /// Retry-After of the 503 responses, in seconds
+    pub fn retry_after(&self) -> u64 {
+        self.config.retry_after.as_secs().max(1)
+    }
+
+    pub fn is_shedding(&self) -> bool {
+        self.shedding.load(Ordering::Relaxed)
+    }
+
+    /// Transactions being processed
+    pub fn in_flight(&self) -> u64 {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Latency percentile at the last computation
+    pub fn latency_p99(&self) -> Duration {
+        Duration::from_micros(self.latency_p99.load(Ordering::Relaxed))
+    }
+
+    /// Times shedding has started
+    pub fn entered(&self) -> u64 {
+        self.entered.load(Ordering::Relaxed)
+    }
+
+    pub fn admitted(&self) -> u64 {
+        self.admitted.load(Ordering::Relaxed)
+    }
+
+    pub fn shed(&self) -> u64 {
+        self.shed.load(Ordering::Relaxed)
+    }
+
+    /// Highest ratio of the measures to their thresholds
+    fn ratio(&self, connections: u64) -> f64 {
+        let connections = self
+            .config
+            .connections
+            .map(|max| connections as f64 / max as f64);
+        let in_flight = self
+            .config
+            .in_flight
+            .map(|max| self.in_flight() as f64 / max as f64);
+        let latency = self
+            .config
+            .latency_p99
+            .map(|max| self.latency_p99().as_secs_f64() / max.as_secs_f64());
+        [connections, in_flight, latency]
+            .into_iter()
+            .flatten()
+            .fold(0.0, f64::max)
+    }
+
+    /// Move the shedding state according to the ratio, returning if it sheds
+    fn update(&self, ratio: f64) -> bool {
+        if ratio >= 1.0 {
+            if !self.shedding.swap(true, Ordering::Relaxed) {
+                self.entered.fetch_add(1, Ordering::Relaxed);
+                log::warn!("load shedding started at pressure {ratio:.2}");
+            }
+            true
+        } else if ratio < self.config.recover_ratio {
+            if self.shedding.swap(false, Ordering::Relaxed) {
+                log::info!("load shedding stopped at pressure {ratio:.2}");
+            }
+            false
+        } else {
+            self.is_shedding()
+        }
+    }
+
+    /// Admit a transaction with the active connections of the server,
+    /// returning `None` if it is shed
+    pub fn admit(self: &Arc<Self>, connections: u64) -> Option<InFlight> {
+        if self.update(self.ratio(connections)) {
+            self.shed.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        self.admitted.fetch_add(1, Ordering::Relaxed);
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        Some(InFlight {
+            shedder: self.clone(),
+            started: Instant::now(),
+        })
+    }
+
+    /// Record the processing time of a transaction
+    fn record(&self, latency: Duration) {
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        self.latencies
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(micros, self.config.latency_window);
+    }
+
+    /// Compute the latency percentile of the recent transactions
+    fn compute_p99(&self) {
+        let p99 = self
+            .latencies
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .p99();
+        self.latency_p99.store(p99, Ordering::Relaxed);
+    }
+
+    /// Compute the latency percentile until the shedder is dropped
+    pub fn spawn(self: &Arc<Self>) -> JoinHandle<()> {
+        let shedder = Arc::downgrade(self);
+        let interval = self.config.interval;
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let Some(shedder) = shedder.upgrade() else {
+                    break;
+                };
+                shedder.compute_p99();
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shedder(config: LoadSheddingConfig) -> Arc<LoadShedder> {
+        Arc::new(LoadShedder::new(config))
+    }
+
+    #[test]
+    fn hysteresis() {
+        let shedder = shedder(LoadSheddingConfig {
+            connections: Some(100),
+            ..Default::default()
+        });
+        assert!(shedder.admit(99).is_some());
+        assert!(shedder.admit(100).is_none());
+        assert!(shedder.is_shedding());
+        // still shedding over the recover ratio
+        assert!(shedder.admit(90).is_none());
+        assert!(shedder.admit(79).is_some());
+        assert!(!shedder.is_shedding());
+        assert!(shedder.admit(90).is_some());
+        assert_eq!(shedder.entered(), 1);
+        assert_eq!(shedder.shed(), 2);
+        assert_eq!(shedder.admitted(), 3);
+    }
+
+    #[test]
+    fn in_flight() {
+        let shedder = shedder(LoadSheddingConfig {
+            in_flight: Some(2),
+            ..Default::default()
+        });
+        let first = shedder.admit(0).unwrap();
+        let second = shedder.admit(0).unwrap();
+        assert_eq!(shedder.in_flight(), 2);
+        assert!(shedder.admit(0).is_none());
+        drop(first);
+        drop(second);
+        assert_eq!(shedder.in_flight(), 0);
+        assert!(shedder.admit(0).is_some());
+    }
+
+    #[test]
+    fn latency() {
+        let shedder = shedder(LoadSheddingConfig {
+            latency_p99: Some(Duration::from_millis(50)),
+            latency_window: 100,
+            ..Default::default()
+        });
+        for i in 0..99 {
+            shedder.record(Duration::from_millis(i % 10));
+        }
+        shedder.record(Duration::from_millis(500));
+        shedder.compute_p99();
+        assert_eq!(shedder.latency_p99(), Duration::from_millis(9));
+        assert!(shedder.admit(0).is_some());
+
+        // the slow ones make it to the percentile
+        for _ in 0..2 {
+            shedder.record(Duration::from_millis(500));
+        }
+        shedder.compute_p99();
+        assert_eq!(shedder.latency_p99(), Duration::from_millis(500));
+        assert!(shedder.admit(0).is_none());
+    }
+}
@@ -3,37 +3,77 @@
  * Copyright 2023-2025 ByteDance and/or its affiliates.
  */
 
-use std::sync::Arc;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, OnceLock};
 use std::time::Duration;
 
 use async_trait::async_trait;
 use slog::Logger;
 use tokio::net::TcpStream;
 use tokio::time::Instant;
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
 
 use g3_daemon::listen::{AcceptTcpServer, ListenStats};
+use g3_daemon::runtime::worker;
 use g3_daemon::server::{BaseServer, ClientConnectionInfo, ReloadServer, ServerQuitPolicy};
 use g3_types::metrics::NodeName;
+use g3_types::net::TcpListenConfig;
 use std::str::FromStr;
 
 use crate::error::IcapResult;
 use crate::log::server::{get_logger, ServerEvent};
 use crate::opts::ProcArgs;
 use crate::stat::get_global_stats;
+use crate::stats::IcapStats;
+use crate::stats::listener::{self as listener_stats, AcceptorStats, ListenerStats};
 use crate::serve::ServerInternal;
 use crate::audit::{AuditHandle, get_audit_handle};
+use crate::audit::logger::{self as audit_logger, AuditLogger};
 use crate::config::server::icap_server::IcapServerConfig;
-
+use crate::control::admin::{self, AdminState};
+use crate::control::handover;
+use crate::modules::blocklist::BlocklistProvider;
+use bypass_hint::BypassHints;
+use degradation::DegradationLadder;
+use idle_reaper::IdleReaper;
+use load_shedding::LoadShedder;
+use quota::QuotaTracker;
+use slo::SloTracker;
+use crate::modules::escalation::EscalationTracker;
+use crate::replication::{self, Replicator};
+use crate::trace::Tracer;
+
+pub mod buffer_pool;
+pub mod check;
+pub mod bypass_hint;
 pub mod connection;
+pub mod degradation;
 pub mod handler;
+pub mod idle_reaper;
 pub mod listener;
+pub mod load_shedding;
+pub mod modules;
+pub mod pipelines;
+pub mod policy_schedule;
+pub mod quota;
+pub mod rotation;
+pub mod slo;
+pub mod tls;
+pub mod unix;
+pub mod what_if;
+
+use buffer_pool::BufferPool;
+use connection::{IcapConnection, IcapStream};
+use modules::ServerModules;
+use pipelines::ServicePipelines;
 
 /// ICAP Server following G3Proxy architecture
 pub struct IcapServer {
     /// Server configuration
     config: IcapServerConfig,
     /// Server statistics
-    server_stats: Arc<crate::stats::IcapStats>,
+    server_stats: Arc<IcapStats>,
     /// Listen statistics
     listen_stats: Arc<ListenStats>,
     /// Task logger
@@ -46,6 +86,38 @@ pub struct IcapServer {
     quit_policy: Arc<ServerQuitPolicy>,
     /// Server start time
     start_time: Instant,
+    /// Domain blocklist, loaded when the server starts
+    blocklist: Option<Arc<BlocklistProvider>>,
+    /// Violation tracker of all connections
+    escalation: Option<Arc<EscalationTracker>>,
+    /// Data consumption of the users of all connections
+    quota: Option<Arc<QuotaTracker>>,
+    /// Replication of the soft state with the other server of the pair
+    replication: Option<Arc<Replicator>>,
+    /// Trusted static origins getting a bypass hint
+    bypass_hints: Option<Arc<BypassHints>>,
+    /// Degradation ladder, moved by the pressure sampled when the server starts
+    degradation: Option<Arc<DegradationLadder>>,
+    /// Load shedding, with the latency percentile computed once the server starts
+    load_shedding: Option<Arc<LoadShedder>>,
+    /// Reaper of the idle keep-alive connections
+    idle_reaper: Option<Arc<IdleReaper>>,
+    /// Response time objectives, with the transactions of their window
+    slo: Option<Arc<SloTracker>>,
+    /// Transaction audit log, written by its own thread
+    audit_log: Option<Arc<AuditLogger>>,
+    /// Transaction trace export, with its own thread
+    tracer: Option<Arc<Tracer>>,
+    /// Modules shared by all connections, loaded before accepting connections
+    modules: Option<ServerModules>,
+    /// TLS acceptor of the ICAPS listener, built when the server starts
+    tls_acceptor: Option<TlsAcceptor>,
+    /// Read buffers of the connections
+    buffer_pool: Arc<BufferPool>,
+    /// Stop of the listeners, requested by the embedding service
+    shutdown: CancellationToken,
+    /// Address of the TCP listener, once bound
+    local_addr: Arc<OnceLock<SocketAddr>>,
 }
 
 impl IcapServer {
@@ -57,14 +129,93 @@ impl IcapServer {
 
     /// Create a new ICAP server with configuration
     pub fn new_with_config(config: IcapServerConfig) -> IcapResult<Self> {
-        let server_stats = get_global_stats().unwrap_or_else(|| Arc::new(crate::stats::IcapStats::new()));
+        let server_stats = get_global_stats().unwrap_or_else(|| Arc::new(IcapStats::new()));
+        Self::new_with_stats(config, server_stats)
+    }
+
+    /// Create a new ICAP server counting into the given statistics
+    ///
+    /// For a service embedding the server, which owns the statistics instead
+    /// of the daemon global ones.
+    pub fn new_with_stats(config: IcapServerConfig, server_stats: Arc<IcapStats>) -> IcapResult<Self> {
         let node_name = NodeName::from_str("g3icap").unwrap();
         let listen_stats = Arc::new(ListenStats::new(&node_name));
         let quit_policy = Arc::new(ServerQuitPolicy::default());
         
         // Get audit handle if available
         let audit_handle = get_audit_handle(&node_name);
-        
+
+        let blocklist = config
+            .blocklist
+            .clone()
+            .map(|c| Arc::new(BlocklistProvider::new(c)));
+        let escalation = config
+            .escalation
+            .clone()
+            .map(|c| Arc::new(EscalationTracker::new(c)));
+        // the limits of the policies are enforced even without a quota section
+        let policy_quotas = config.policy().map(|p| &p.quotas).filter(|q| !q.is_empty());
+        let quota = match config.quota() {
+            Some(c) => Some(c.clone()),
+            None => policy_quotas.map(|_| Default::default()),
+        }
+        .map(|c| Arc::new(QuotaTracker::new(c, policy_quotas)));
+        quota::set_global(quota.clone());
+        if let Some(quota) = &quota {
+            replication::register("quota", quota);
+        }
+        let replication = config
+            .replication()
+            .cloned()
+            .map(|c| Arc::new(Replicator::new(c)));
+        replication::set_global(replication.clone());
+        let bypass_hints = match config.bypass_hint() {
+            Some(c) => Some(Arc::new(BypassHints::new(c).map_err(|e| {
+                crate::error::IcapError::config_simple(format!("invalid bypass hint origins: {e}"))
+            })?)),
+            None => None,
+        };
+        let slo = config.slo().cloned().map(|c| Arc::new(SloTracker::new(c)));
+        slo::set_global(slo.clone());
+        let degradation = config
+            .degradation()
+            .cloned()
+            .map(|c| Arc::new(DegradationLadder::new(c).with_slo(slo.clone())));
+        degradation::set_global(degradation.clone());
+        let load_shedding = config
+            .load_shedding()
+            .cloned()
+            .map(|c| Arc::new(LoadShedder::new(c)));
+        load_shedding::set_global(load_shedding.clone());
+        let idle_reaper = config
+            .idle_reaper()
+            .map(|c| Arc::new(IdleReaper::new(*c, config.max_connections, server_stats.clone())));
+        let audit_log = match config.audit_log() {
+            Some(c) => Some(Arc::new(AuditLogger::spawn(c).map_err(|e| {
+                crate::error::IcapError::config_simple(format!("failed to start audit log: {e}"))
+            })?)),
+            None => None,
+        };
+        audit_logger::set_global(audit_log.clone());
+        config.features().apply().map_err(|e| {
+            crate::error::IcapError::config_simple(format!("failed to set the features: {e}"))
+        })?;
+        let tracer = match config.tracing() {
+            Some(c) => Some(Arc::new(Tracer::spawn(c).map_err(|e| {
+                crate::error::IcapError::config_simple(format!("failed to start trace export: {e}"))
+            })?)),
+            None => None,
+        };
+        crate::numa::set_global(config.numa().copied());
+        let buffer_pool = BufferPool::register(
+            config.name.as_str(),
+            config.buffer_pool,
+            config.numa().is_some_and(|c| c.buffer_pools),
+        );
+        pipelines::set_global(Some(Arc::new(ServicePipelines::new(&config.pipelines))));
+        let rotation = rotation::ServiceRotation::new(&config, rotation::get_global().as_deref());
+        rotation::set_global(Some(Arc::new(rotation)));
+
         Ok(Self {
             config,
             server_stats,
@@ -74,6 +225,22 @@ impl IcapServer {
             reload_version: 1,
             quit_policy,
             start_time: Instant::now(),
+            blocklist,
+            escalation,
+            quota,
+            replication,
+            bypass_hints,
+            degradation,
+            load_shedding,
+            idle_reaper,
+            slo,
+            audit_log,
+            tracer,
+            modules: None,
+            tls_acceptor: None,
+            buffer_pool,
+            shutdown: CancellationToken::new(),
+            local_addr: Arc::new(OnceLock::new()),
         })
     }
 
@@ -83,7 +250,7 @@ impl IcapServer {
     }
 
     /// Get server statistics
-    pub fn server_stats(&self) -> &Arc<crate::stats::IcapStats> {
+    pub fn server_stats(&self) -> &Arc<IcapStats> {
         &self.server_stats
     }
 
@@ -97,16 +264,38 @@ impl IcapServer {
         self.audit_handle.as_ref()
     }
 
+    /// Create and initialize the modules shared by the connections
+    pub async fn load_modules(&mut self) {
+        let logger = get_logger("main")
+            .unwrap_or_else(|| slog::Logger::root(slog::Discard, slog::o!()));
+        let modules = ServerModules::load(&self.config, self.blocklist.clone(), &logger).await;
+        // shares the loaded modules, reloaded from the control channel
+        modules::set_global(Some(Arc::new(modules.clone())));
+        self.modules = Some(modules);
+    }
+
     /// Get server uptime
     pub fn uptime(&self) -> Duration {
         self.start_time.elapsed()
     }
 
+    /// Get the token stopping the listeners when cancelled
+    ///
+    /// `start` returns within a second once the token is cancelled, the
+    /// connections in progress are served to their end.
+    pub fn shutdown_handle(&self) -> CancellationToken {
+        self.shutdown.clone()
+    }
+
+    /// Get the address of the TCP listener, once `start` has bound it
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr.get().copied()
+    }
+
     /// Check if server should quit
     pub fn should_quit(&self) -> bool {
-        // For now, always return false (server runs indefinitely)
-        // In a real implementation, this would check the quit policy
-        false
+        // The listeners were handed over to the new daemon on upgrade
+        handover::is_released() || self.shutdown.is_cancelled()
     }
 
     /// Get alive connection count
@@ -116,176 +305,333 @@ impl IcapServer {
         0
     }
 
-    /// Start the ICAP server using G3Proxy patterns
-    pub async fn start(&mut self) -> IcapResult<()> {
-        let logger = get_logger("main").unwrap_or_else(|| {
+    /// Create the handler of an accepted connection
+    fn build_connection(&self, stream: impl IcapStream + 'static, peer_addr: SocketAddr) -> IcapConnection {
+        IcapConnection::new(
+            stream,
+            peer_addr,
+            self.server_stats.clone(),
+            self.task_logger.clone().unwrap_or_else(|| {
+                slog::Logger::root(slog::Discard, slog::o!())
+            }),
+        )
+        .with_slow_client(self.config.slow_client.clone())
+        .with_modules(self.modules.as_ref())
+        .with_blocklist(self.blocklist.clone())
+        .with_protocol_limits(self.config.protocol_limits)
+        .with_escalation(self.escalation.clone())
+        .with_quota(self.quota.clone())
+        .with_bypass_hints(self.bypass_hints.clone())
+        .with_degradation(self.degradation.clone())
+        .with_load_shedding(self.load_shedding.clone())
+        .with_idle_reaper(self.idle_reaper.as_ref())
+        .with_slo(self.slo.clone())
+        .with_audit_log(self.audit_log.clone())
+        .with_tracer(self.tracer.clone())
+        .with_enforcement(self.config.enforcement.clone())
+        .with_services(self.config.services.clone())
+        .with_admission(self.config.admission)
+        .with_timeouts(self.config.timeouts)
+        .with_buffer_pool(self.buffer_pool.clone())
+    }
+
+    /// Accept connections on the unix socket until the server quits
+    async fn serve_unix(self, listener: tokio::net::UnixListener) {
+        let logger = self.task_logger.clone().unwrap_or_else(|| {
             slog::Logger::root(slog::Discard, slog::o!())
         });
-        
-        ServerEvent::Started.log(&logger, "Starting G3 ICAP Server");
-
-        // Create listen address
-        let listen_addr = format!("{}:{}", self.config.host, self.config.port);
-
-        // Start listening using tokio directly
-        let listener = tokio::net::TcpListener::bind(&listen_addr)
-            .await
-            .map_err(|e| crate::error::IcapError::network_simple(format!("Failed to bind to {}: {}", listen_addr, e)))?;
-
-        slog::info!(logger, "ICAP Server listening on {}", listen_addr);
-
-        // Main server loop following G3Proxy patterns
-        loop {
-            // Check if server should quit
-            if self.should_quit() {
-                slog::info!(logger, "Server quit requested, shutting down");
-                break;
-            }
+        let stats = listener_stats::register("unix");
+        // unix socket clients are on the same host
+        let peer_addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
 
-            // Accept connections with timeout
+        while !self.should_quit() {
             match tokio::time::timeout(Duration::from_secs(1), listener.accept()).await {
-                Ok(Ok((stream, peer_addr))) => {
-                    slog::debug!(logger, "New connection from {}", peer_addr);
+                Ok(Ok((stream, _))) => {
                     self.server_stats.increment_connections();
-                    
-                    // Handle connection in a separate task
-                    let stats = self.server_stats.clone();
-                    let audit_handle = self.audit_handle.clone();
-                    let config = self.config.clone();
-                    let logger = self.task_logger.clone().unwrap_or_else(|| {
-                        slog::Logger::root(slog::Discard, slog::o!())
-                    });
-                    
+                    stats.add_accepted();
+                    let mut connection = self.build_connection(stream, peer_addr);
+                    let stats = stats.clone();
+                    let logger = logger.clone();
                     tokio::spawn(async move {
-                        let mut connection = crate::server::connection::IcapConnection::new(
-                            stream,
-                            peer_addr,
-                            stats,
-                            logger.clone(),
-                        );
-
                         if let Err(e) = connection.process().await {
                             slog::debug!(logger, "Connection error: {}", e);
                         }
+                        stats.add_finished();
                     });
                 }
                 Ok(Err(e)) => {
-                    slog::error!(logger, "Failed to accept connection: {}", e);
+                    slog::error!(logger, "Failed to accept unix connection: {}", e);
                     self.server_stats.increment_errors();
+                    stats.add_failed();
                 }
-                Err(_) => {
-                    // Timeout, continue loop
-                    continue;
-                }
+                Err(_) => continue,
             }
         }
-
-        Ok(())
     }
-}
 
-impl BaseServer for IcapServer {
-    fn name(&self) -> &NodeName {
-        static NAME: std::sync::OnceLock<NodeName> = std::sync::OnceLock::new();
-        NAME.get_or_init(|| NodeName::from_str("g3icap").unwrap())
-    }
+    /// Start the ICAP server using G3Proxy patterns
+    pub async fn start(&mut self) -> IcapResult<()> {
+        let logger = get_logger("main").unwrap_or_else(|| {
+            slog::Logger::root(slog::Discard, slog::o!())
+        });
+        
+        ServerEvent::Started.log(&logger, "Starting G3 ICAP Server");
 
-    fn r#type(&self) -> &'static str {
-        "icap"
-    }
+        if let Some(blocklist) = &self.blocklist {
+            blocklist
+                .load()
+                .await
+                .map_err(|e| crate::error::IcapError::config_simple(format!("{e:?}")))?;
+            blocklist.spawn_refresh();
+            slog::info!(logger, "Loaded {} blocked domains", blocklist.len());
+        }
 
-    fn version(&self) -> usize {
-        self.reload_version
-    }
-}
+        if let Some(degradation) = &self.degradation {
+            degradation.spawn(self.server_stats.clone());
+        }
+        if let Some(load_shedding) = &self.load_shedding {
+            load_shedding.spawn();
+        }
+        if let Some(idle_reaper) = &self.idle_reaper {
+            idle_reaper.spawn();
+        }
+        if let Some(quota) = &self.quota {
+            quota.spawn();
+        }
+        if let Some(replication) = &self.replication {
+            replication
+                .start()
+                .await
+                .map_err(|e| crate::error::IcapError::config_simple(format!("{e:?}")))?;
+        }
 
-impl ReloadServer for IcapServer {
-    fn reload(&self) -> Self {
-        Self {
-            config: self.config.clone(),
-            server_stats: self.server_stats.clone(),
-            listen_stats: self.listen_stats.clone(),
-            task_logger: self.task_logger.clone(),
-            audit_handle: self.audit_handle.clone(),
-            reload_version: self.reload_version + 1,
-            quit_policy: self.quit_policy.clone(),
-            start_time: self.start_time,
+        if self.modules.is_none() {
+            self.load_modules().await;
         }
-    }
-}
 
-impl ServerInternal for IcapServer {
-    fn _clone_config(&self) -> crate::config::server::AnyServerConfig {
-        crate::config::server::AnyServerConfig::Icap(self.config.clone())
-    }
+        if let Some(admin_config) = &self.config.admin {
+            let state = AdminState {
+                stats: self.server_stats.clone(),
+                blocklist: self.blocklist.clone(),
+            };
+            admin::spawn(admin_config.clone(), state)
+                .await
+                .map_err(|e| crate::error::IcapError::config_simple(format!("{e:?}")))?;
+        }
 
-    fn _depend_on_server(&self, _name: &NodeName) -> bool {
-        false
-    }
+        // refuse to start if the TLS policy can not be satisfied
+        if self.config.is_tls_enabled() {
+            let acceptor = tls::build_acceptor(&self.config)
+                .map_err(|e| crate::error::IcapError::config_simple(format!("{e:?}")))?;
+            slog::info!(logger, "TLS enabled with policy {}", self.config.tls_policy().profile.as_str());
+            self.tls_acceptor = Some(acceptor);
+        }
 
-    fn _reload_config_notify_runtime(&self) {
-        // Notify runtime of configuration changes
-        // In a real implementation, this would update runtime state
-    }
+        // Create listen address
+        let listen_addr = format!("{}:{}", self.config.host, self.config.port);
+        let listen_in_worker = self.config.listen_in_worker() && worker::worker_count() > 0;
 
-    fn _update_next_servers_in_place(&self) {
-        // Update next servers in place
-        // For ICAP server, this is typically not applicable
-    }
+        // Start listening, or on the socket of the old daemon on upgrade
+        let listener = match handover::take_inherited(&listen_addr) {
+            Some(listener) => {
+                slog::info!(logger, "Took over listener {} from the old daemon", listen_addr);
+                listener
+            }
+            None => bind_tcp(&listen_addr, listen_in_worker)
+                .await
+                .map_err(|e| crate::error::IcapError::network_simple(format!("Failed to bind to {}: {}", listen_addr, e)))?,
+        };
+        handover::close_inherited();
+        if let Err(e) = handover::register_listener(&listener) {
+            slog::warn!(logger, "Listener {} can not be handed over on upgrade: {}", listen_addr, e);
+        }
 
-    fn _update_escaper_in_place(&self) {
-        // Update escaper in place
-        // For ICAP server, this is typically not applicable
-    }
+        if let Ok(addr) = listener.local_addr() {
+            let _ = self.local_addr.set(addr);
+        }
+        slog::info!(logger, "ICAP Server listening on {}", listen_addr);
+        let tcp_stats = listener_stats::register(if self.tls_acceptor.is_some() { "tls" } else { "tcp" });
+        let listener = if listen_in_worker {
+            let count = self.spawn_worker_acceptors(listener, &tcp_stats, &logger);
+            slog::info!(logger, "Accepting connections in {} worker runtimes", count);
+            None
+        } else {
+            let listener = listener
+                .set_nonblocking(true)
+                .and_then(|_| tokio::net::TcpListener::from_std(listener))
+                .map_err(|e| crate::error::IcapError::network_simple(format!("Failed to listen on {}: {}", listen_addr, e)))?;
+            Some(listener)
+        };
+
+        if let Some(unix_config) = self.config.unix_listen() {
+            let unix_listener = unix::bind(unix_config)
+                .map_err(|e| crate::error::IcapError::network_simple(format!("Failed to bind to {}: {}", unix_config.path.display(), e)))?;
+            slog::info!(logger, "ICAP Server listening on {}", unix_config.path.display());
+            let server = self.clone();
+            tokio::spawn(async move {
+                server.serve_unix(unix_listener).await;
+            });
+        }
 
-    fn _update_user_group_in_place(&self) {
-        // Update user group in place
-        // For ICAP server, this is typically not applicable
-    }
+        let modules = self.modules.as_ref().map(|m| m.versions()).unwrap_or_default();
+        crate::version::set_runtime(self.config.protocol_limits, modules);
+        let report = crate::version::VersionReport::current();
+        slog::info!(logger, "ICAP Server started";
+            "version" => report.version,
+            "git_commit" => report.git_commit.unwrap_or("unknown"),
+            "features" => report.features.join(","),
+            "rustls_provider" => report.tls.rustls_provider.unwrap_or("none"),
+            "modules" => report.loaded_modules.iter()
+                .map(|m| format!("{}/{}", m.name, m.version))
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+
+        // Main server loop following G3Proxy patterns
+        match listener {
+            Some(listener) => self.serve_tcp(listener, tcp_stats, None).await,
+            None => {
+                // the acceptors run in the worker runtimes
+                while !self.should_quit() {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+        slog::info!(logger, "Server quit requested, shutting down");
 
-    fn _update_audit_handle_in_place(&self) -> anyhow::Result<()> {
-        // Update audit handle in place
-        // This would reload audit configuration if needed
         Ok(())
     }
 
-    fn _reload_with_old_notifier(
+    /// Serve the TCP listener with one acceptor in each worker runtime
+    ///
+    /// The first acceptor takes the given listener, the others bind their own
+    /// socket to the same address with SO_REUSEPORT, so that the kernel spreads
+    /// the incoming connections over them instead of one task accepting all of
+    /// them. Returns the number of acceptors.
+    fn spawn_worker_acceptors(
         &self,
-        config: crate::config::server::AnyServerConfig,
-        _registry: &mut crate::serve::ServerRegistry,
-    ) -> anyhow::Result<Arc<dyn ServerInternal>> {
-        match config {
-            crate::config::server::AnyServerConfig::Icap(icap_config) => {
-                let new_server = Self::new_with_config(icap_config)?;
-                Ok(Arc::new(new_server))
+        listener: std::net::TcpListener,
+        stats: &Arc<ListenerStats>,
+        logger: &Logger,
+    ) -> usize {
+        let local_addr = listener.local_addr().ok();
+        let mut listener = Some(listener);
+        let mut count = 0;
+        let _ = worker::foreach(|worker| {
+            let socket = match (listener.take(), local_addr) {
+                (Some(listener), _) => listener,
+                (None, Some(addr)) => match g3_socket::tcp::new_std_listener(&TcpListenConfig::new(addr)) {
+                    Ok(listener) => listener,
+                    Err(e) => {
+                        slog::warn!(logger, "Failed to bind to {} in worker {}: {}", addr, worker.id, e);
+                        return Ok::<(), ()>(());
+                    }
+                },
+                (None, None) => return Ok(()),
+            };
+            if let Some(cpu_affinity) = &worker.cpu_affinity
+                && let Err(e) = g3_socket::tcp::try_listen_on_local_cpu(&socket, cpu_affinity)
+            {
+                slog::warn!(logger, "Failed to set cpu affinity of the listener in worker {}: {}", worker.id, e);
             }
-            _ => Err(anyhow::anyhow!("Invalid config type for ICAP server")),
-        }
+
+            let server = self.clone();
+            let stats = stats.clone();
+            let acceptor = stats.acceptor(worker.id);
+            let logger = logger.clone();
+            let worker_id = worker.id;
+            worker.handle.spawn(async move {
+                // register the socket with the reactor of this worker
+                match socket
+                    .set_nonblocking(true)
+                    .and_then(|_| tokio::net::TcpListener::from_std(socket))
+                {
+                    Ok(listener) => server.serve_tcp(listener, stats, Some(acceptor)).await,
+                    Err(e) => slog::warn!(logger, "Failed to listen in worker {}: {}", worker_id, e),
+                }
+            });
+            count += 1;
+            Ok(())
+        });
+        count
     }
 
-    fn _reload_with_new_notifier(
+    /// Accept connections on a TCP listener until the server quits
+    async fn serve_tcp(
         &self,
-        config: crate::config::server::AnyServerConfig,
-        _registry: &mut crate::serve::ServerRegistry,
-    ) -> anyhow::Result<Arc<dyn ServerInternal>> {
-        match config {
-            crate::config::server::AnyServerConfig::Icap(icap_config) => {
-                let new_server = Self::new_with_config(icap_config)?;
-                Ok(Arc::new(new_server))
+        listener: tokio::net::TcpListener,
+        stats: Arc<ListenerStats>,
+        acceptor: Option<Arc<AcceptorStats>>,
+    ) {
+        let logger = get_logger("main").unwrap_or_else(|| {
+            slog::Logger::root(slog::Discard, slog::o!())
+        });
+
+        while !self.should_quit() {
+            // Accept connections with timeout
+            match tokio::time::timeout(Duration::from_secs(1), listener.accept()).await {
+                Ok(Ok((stream, peer_addr))) => {
+                    slog::debug!(logger, "New connection from {}", peer_addr);
+                    self.server_stats.increment_connections();
+                    if let Some(acceptor) = &acceptor {
+                        acceptor.add_accepted();
+                    }
+
+                    // Handle connection in a separate task
+                    let server = self.clone();
+                    let stats = stats.clone();
+                    tokio::spawn(async move {
+                        server.serve_tcp_connection(stream, peer_addr, stats).await;
+                    });
+                }
+                Ok(Err(e)) => {
+                    slog::error!(logger, "Failed to accept connection: {}", e);
+                    self.server_stats.increment_errors();
+                    stats.add_failed();
+                    if let Some(acceptor) = &acceptor {
+                        acceptor.add_failed();
+                    }
+                }
+                Err(_) => {
+                    // Timeout, continue loop
+                    continue;
+                }
             }
-            _ => Err(anyhow::anyhow!("Invalid config type for ICAP server")),
         }
     }
 
-    fn _start_runtime(&self, _server: Arc<dyn BaseServer>) -> anyhow::Result<()> {
-        // Start runtime for the server
-        // This is called by the server registry
-        Ok(())
+    /// Handle an accepted TCP connection, after the TLS handshake if enabled
+    async fn serve_tcp_connection(&self, stream: TcpStream, peer_addr: SocketAddr, stats: Arc<ListenerStats>) {
+        let logger = self.task_logger.clone().unwrap_or_else(|| {
+            slog::Logger::root(slog::Discard, slog::o!())
+        });
+        let stream = match tls::accept(self.tls_acceptor.as_ref(), stream, self.config.connection_timeout).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                slog::debug!(logger, "TLS handshake with {} failed: {}", peer_addr, e);
+                self.server_stats.increment_errors();
+                stats.add_failed();
+                return;
+            }
+        };
+        stats.add_accepted();
+        let mut connection = self.build_connection(stream, peer_addr);
+        if let Err(e) = connection.process().await {
+            slog::debug!(logger, "Connection error: {}", e);
+        }
+        stats.add_finished();
     }
+}
 
-    fn _abort_runtime(&self) {
-        // Abort runtime for the server
-        // This would clean up resources and stop the server
+/// Bind the TCP listener, with SO_REUSEPORT if it is served in the workers
+async fn bind_tcp(listen_addr: &str, reuse_port: bool) -> std::io::Result<std::net::TcpListener> {
+    if reuse_port {
+        let addr = tokio::net::lookup_host(listen_addr).await?.next().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("no address for {listen_addr}"))
+        })?;
+        g3_socket::tcp::new_std_listener(&TcpListenConfig::new(addr))
+    } else {
+        tokio::net::TcpListener::bind(listen_addr).await?.into_std()
     }
 }
 
@@ -294,22 +640,28 @@ impl AcceptTcpServer for IcapServer {
     async fn run_tcp_task(&self, stream: TcpStream, cc_info: ClientConnectionInfo) {
         let client_addr = cc_info.client_addr();
         self.server_stats.increment_connections();
-        
+
+        let stream = match tls::accept(self.tls_acceptor.as_ref(), stream, self.config.connection_timeout).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                slog::debug!(self.task_logger.as_ref().unwrap_or(&slog::Logger::root(slog::Discard, slog::o!())),
+                    "TLS handshake with {} failed: {}", client_addr, e);
+                self.server_stats.increment_errors();
+                return;
+            }
+        };
+
         // Create connection handler following G3Proxy patterns
-        let mut connection = crate::server::connection::IcapConnection::new(
-            stream,
-            client_addr,
-            self.server_stats.clone(),
-            self.task_logger.clone().unwrap_or_else(|| {
-                slog::Logger::root(slog::Discard, slog::o!())
-            }),
-        );
+        let mut connection = self.build_connection(stream, client_addr);
 
         // Process the connection
         if let Err(e) = connection.process().await {
             slog::debug!(self.task_logger.as_ref().unwrap_or(&slog::Logger::root(slog::Discard, slog::o!())), 
                 "Connection error: {}", e);
-            self.server_stats.increment_errors();
+            // aborts are counted apart from the errors
+            if !e.is_client_abort() {
+                self.server_stats.increment_errors();
+            }
         }
     }
 }
@@ -325,6 +677,22 @@ impl Clone for IcapServer {
             reload_version: self.reload_version,
             quit_policy: self.quit_policy.clone(),
             start_time: self.start_time,
+            blocklist: self.blocklist.clone(),
+            escalation: self.escalation.clone(),
+            quota: self.quota.clone(),
+            replication: self.replication.clone(),
+            bypass_hints: self.bypass_hints.clone(),
+            degradation: self.degradation.clone(),
+            load_shedding: self.load_shedding.clone(),
+            idle_reaper: self.idle_reaper.clone(),
+            slo: self.slo.clone(),
+            audit_log: self.audit_log.clone(),
+            tracer: self.tracer.clone(),
+            modules: self.modules.clone(),
+            tls_acceptor: self.tls_acceptor.clone(),
+            buffer_pool: self.buffer_pool.clone(),
+            shutdown: self.shutdown.clone(),
+            local_addr: self.local_addr.clone(),
         }
     }
 }
\ No newline at end of file
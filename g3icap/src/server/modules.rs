@@ -0,0 +1,753 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Shared ICAP modules of a server
+//!
+//! The modules are created and initialized once when the server is spawned,
+//! the connections then share the initialized modules, so that accepting a
+//! connection does not block on any module initialization. Each scripted
+//! service gets its own expression module, and the WAF module is only
+//! loaded with a `waf` config.
+//!
+//! A module can be reloaded on its own from the control channel: a new copy
+//! is created and initialized from the same config, then replaces the old
+//! one for the connections accepted from then on. The old copy is kept if
+//! the new one fails to initialize.
+//!
+//! The candidate rule sets of the `shadow` config are loaded in copies of
+//! their modules of their own, evaluated alongside the active ones. Once
+//! promoted, the active module is created from the candidate rules, on its
+//! later reloads too, and the copy is dropped.
+//!
+//! With a `policy` config, the content filter rules and the antivirus
+//! settings come from the compiled security policies instead of the
+//! built-in defaults. The antivirus is not loaded if the policies disable
+//! malware scanning. It scans with clamd if the `antivirus` config has its
+//! address.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use arc_swap::{ArcSwap, ArcSwapOption};
+use g3_types::metrics::NodeName;
+use slog::Logger;
+
+use crate::audit::ops::{DefaultIcapAuditOps, IcapAuditOps};
+use crate::config::server::antivirus::AntivirusServerConfig;
+use crate::config::server::icap_server::IcapServerConfig;
+use crate::config::server::scripted_services::ScriptedServicesConfig;
+use crate::config::server::waf::WafConfig;
+use crate::modules::ai_inspection::AiInspectionConfig;
+use crate::modules::antivirus::{AntivirusConfig, AntivirusEngine, AntivirusModule};
+use crate::modules::blocklist::BlocklistProvider;
+use crate::modules::content_filter::{ContentFilterConfig, ContentFilterModule, FilterRuleSet};
+use crate::modules::expression::ExpressionModule;
+use crate::modules::metrics::MetricsRegistry;
+use crate::modules::policy::{CompiledPolicy, PolicyAction};
+use crate::modules::shadow::{
+    SHADOW_PREFIX, ShadowConfig, ShadowModule, ShadowTarget, ShadowYaraConfig,
+};
+use crate::modules::waf::WafModule;
+use crate::modules::{IcapModule, ModuleConfig, ModuleRegistry};
+use crate::version::ModuleVersion;
+
+/// Prefix of the names of the scripted service modules
+const SCRIPTED_PREFIX: &str = "expression/";
+
+static GLOBAL_MODULES: ArcSwapOption<ServerModules> = ArcSwapOption::const_empty();
+
+/// Install the modules of the server, for the control channel
+pub fn set_global(modules: Option<Arc<ServerModules>>) {
+    GLOBAL_MODULES.store(modules);
+}
+
+pub fn get_global() -> Option<Arc<ServerModules>> {
+    GLOBAL_MODULES.load_full()
+}
+
+#[derive(Clone, Default)]
+struct LoadedModules {
+    content_filter: Option<Arc<dyn IcapModule>>,
+    antivirus: Option<Arc<dyn IcapModule>>,
+    waf: Option<Arc<dyn IcapModule>>,
+    scripted: Arc<HashMap<String, Arc<dyn IcapModule>>>,
+    shadow: HashMap<ShadowTarget, ShadowModule>,
+}
+
+/// Place of a module in the loaded modules
+#[derive(Clone, Copy)]
+enum ModuleSlot<'a> {
+    ContentFilter,
+    Antivirus,
+    Waf,
+    Scripted(&'a str),
+    Shadow(ShadowTarget),
+}
+
+/// What the modules are created from
+struct ModuleSources {
+    blocklist: Option<Arc<BlocklistProvider>>,
+    scripted_services: ScriptedServicesConfig,
+    waf: Option<WafConfig>,
+    ai_inspection: AiInspectionConfig,
+    antivirus: AntivirusServerConfig,
+    shadow: ShadowConfig,
+    policy: Option<CompiledPolicy>,
+    /// Targets the active module uses the candidate rules of
+    promoted: Mutex<HashSet<ShadowTarget>>,
+    logger: Logger,
+}
+
+/// Initialized modules shared by all connections of a server
+#[derive(Clone, Default)]
+pub struct ServerModules {
+    loaded: Arc<ArcSwap<LoadedModules>>,
+    sources: Option<Arc<ModuleSources>>,
+}
+
+impl ServerModules {
+    /// Create and initialize the modules
+    ///
+    /// A module failing to initialize is left out, the connections then fall
+    /// back to the basic checks.
+    pub async fn load(
+        config: &IcapServerConfig,
+        blocklist: Option<Arc<BlocklistProvider>>,
+        logger: &Logger,
+    ) -> Self {
+        let sources = ModuleSources::new(config, blocklist, logger.clone());
+        if let Some(policy) = &sources.policy {
+            slog::info!(
+                logger,
+                "Compiled the policies {}",
+                policy.policies.join(", ")
+            );
+            for skipped in &policy.skipped {
+                slog::warn!(logger, "Skipped policy rules of {}", skipped);
+            }
+        }
+
+        let mut scripted = HashMap::with_capacity(sources.scripted_services.services.len());
+        for service in sources.scripted_services.services.keys() {
+            if let Some(module) = sources.load(ModuleSlot::Scripted(service)).await {
+                scripted.insert(service.clone(), module);
+            }
+        }
+        let mut shadow = HashMap::new();
+        for target in ShadowTarget::ALL {
+            if let Some(module) = sources.load(ModuleSlot::Shadow(target)).await {
+                shadow.insert(target, sources.shadow_module(target, module));
+            }
+        }
+        let loaded = LoadedModules {
+            content_filter: sources.load(ModuleSlot::ContentFilter).await,
+            antivirus: sources.load(ModuleSlot::Antivirus).await,
+            waf: sources.load(ModuleSlot::Waf).await,
+            scripted: Arc::new(scripted),
+            shadow,
+        };
+        ServerModules {
+            loaded: Arc::new(ArcSwap::from_pointee(loaded)),
+            sources: Some(Arc::new(sources)),
+        }
+    }
+
+    /// Create and initialize each module, returns the result of each one
+    ///
+    /// The modules are named as in `reload`.
+    pub async fn check(
+        config: &IcapServerConfig,
+        blocklist: Option<Arc<BlocklistProvider>>,
+    ) -> Vec<(String, anyhow::Result<()>)> {
+        let logger = Logger::root(slog::Discard, slog::o!());
+        let sources = ModuleSources::new(config, blocklist, logger);
+        let mut slots = vec![
+            ModuleSlot::ContentFilter,
+            ModuleSlot::Antivirus,
+            ModuleSlot::Waf,
+        ];
+        slots.extend(
+            sources
+                .scripted_services
+                .services
+                .keys()
+                .map(|s| ModuleSlot::Scripted(s.as_str())),
+        );
+        slots.extend(ShadowTarget::ALL.map(ModuleSlot::Shadow));
+        let mut results = Vec::with_capacity(slots.len());
+        for slot in slots {
+            if let Some(result) = sources.create(slot).await {
+                results.push((slot.name(), result.map(|_| ())));
+            }
+        }
+        results
+    }
+
+    /// The content filter module, used for REQMOD
+    pub fn content_filter(&self) -> Option<Arc<dyn IcapModule>> {
+        self.loaded.load().content_filter.clone()
+    }
+
+    /// The antivirus module, used for RESPMOD
+    pub fn antivirus(&self) -> Option<Arc<dyn IcapModule>> {
+        self.loaded.load().antivirus.clone()
+    }
+
+    /// The WAF module, used for REQMOD before the content filter
+    pub fn waf(&self) -> Option<Arc<dyn IcapModule>> {
+        self.loaded.load().waf.clone()
+    }
+
+    /// The modules of the scripted services, keyed by service name
+    pub fn scripted(&self) -> Arc<HashMap<String, Arc<dyn IcapModule>>> {
+        self.loaded.load().scripted.clone()
+    }
+
+    /// The module with the candidate rules of the target, if not promoted yet
+    pub fn shadow(&self, target: ShadowTarget) -> Option<ShadowModule> {
+        self.loaded.load().shadow.get(&target).cloned()
+    }
+
+    /// The policies the modules are compiled from, with a `policy` config
+    pub fn policy(&self) -> Option<&CompiledPolicy> {
+        self.sources.as_ref()?.policy.as_ref()
+    }
+
+    /// Name and version of the loaded modules
+    pub fn versions(&self) -> Vec<ModuleVersion> {
+        let loaded = self.loaded.load();
+        [&loaded.content_filter, &loaded.antivirus, &loaded.waf]
+            .into_iter()
+            .flatten()
+            .chain(loaded.scripted.values())
+            .map(|m| ModuleVersion {
+                name: m.name().to_string(),
+                version: m.version().to_string(),
+            })
+            .collect()
+    }
+
+    /// Create the module again from its config and replace the loaded one
+    ///
+    /// The module is named as in the metrics, `content_filter`, `antivirus`,
+    /// `waf` or `expression/<service>` for a scripted service.
+    pub async fn reload(&self, name: &str) -> anyhow::Result<()> {
+        let sources = self
+            .sources
+            .as_ref()
+            .ok_or_else(|| anyhow!("no module loaded"))?;
+        let slot = match name {
+            "content_filter" => ModuleSlot::ContentFilter,
+            "antivirus" => ModuleSlot::Antivirus,
+            "waf" if sources.waf.is_some() => ModuleSlot::Waf,
+            _ => match name.strip_prefix(SHADOW_PREFIX) {
+                Some(target) => target
+                    .parse()
+                    .ok()
+                    .filter(|t| sources.has_candidate(*t))
+                    .map(ModuleSlot::Shadow),
+                None => name
+                    .strip_prefix(SCRIPTED_PREFIX)
+                    .filter(|s| sources.scripted_services.services.contains_key(*s))
+                    .map(ModuleSlot::Scripted),
+            }
+            .ok_or_else(|| anyhow!("no module named {name}"))?,
+        };
+        let module = sources
+            .create(slot)
+            .await
+            .ok_or_else(|| anyhow!("no module named {name}"))?
+            .map_err(|e| {
+                anyhow!("module {name} failed to initialize, the loaded one is kept: {e}")
+            })?;
+
+        self.loaded.rcu(|current| {
+            let mut loaded = LoadedModules::clone(current);
+            let module = Some(module.clone());
+            match slot {
+                ModuleSlot::ContentFilter => loaded.content_filter = module,
+                ModuleSlot::Antivirus => loaded.antivirus = module,
+                ModuleSlot::Waf => loaded.waf = module,
+                ModuleSlot::Scripted(service) => {
+                    let mut scripted = HashMap::clone(&loaded.scripted);
+                    scripted.extend(module.map(|m| (service.to_string(), m)));
+                    loaded.scripted = Arc::new(scripted);
+                }
+                ModuleSlot::Shadow(target) => {
+                    loaded
+                        .shadow
+                        .extend(module.map(|m| (target, sources.shadow_module(target, m))));
+                }
+            }
+            loaded
+        });
+        // the reloaded candidate rules may have changed
+        if let ModuleSlot::Shadow(target) = slot {
+            target.stats().reset();
+        }
+
+        slog::info!(sources.logger, "Reloaded {} module", name);
+        let audit_ops = DefaultIcapAuditOps::new(NodeName::new_static("g3icap"), true);
+        audit_ops.log_config_changed("Module reloaded", &format!("module {name}"));
+        Ok(())
+    }
+}
+
+impl ServerModules {
+    /// Make the candidate rules of the target the active ones
+    ///
+    /// The active module is created again from the candidate rules, then
+    /// replaces the loaded one and the copy evaluating the candidate. The
+    /// active rules are kept if the new module fails to initialize.
+    pub async fn promote(&self, target: ShadowTarget) -> anyhow::Result<()> {
+        let sources = self
+            .sources
+            .as_ref()
+            .ok_or_else(|| anyhow!("no module loaded"))?;
+        if !sources.has_candidate(target) {
+            return Err(anyhow!("no candidate {} rules", target.as_str()));
+        }
+        let report = target.stats().report(target, true);
+
+        // the active module uses the candidate rules from now on, on reload too
+        sources.promoted.lock().unwrap().insert(target);
+        let slot = match target {
+            ShadowTarget::ContentFilter => ModuleSlot::ContentFilter,
+            ShadowTarget::Yara => ModuleSlot::Antivirus,
+        };
+        let result = sources
+            .create(slot)
+            .await
+            .unwrap_or_else(|| Err(anyhow!("no module named {}", target.module())));
+        let module = match result {
+            Ok(module) => module,
+            Err(e) => {
+                sources.promoted.lock().unwrap().remove(&target);
+                return Err(anyhow!(
+                    "module {} failed to initialize with the candidate rules, the active ones are kept: {e}",
+                    target.module()
+                ));
+            }
+        };
+
+        self.loaded.rcu(|current| {
+            let mut loaded = LoadedModules::clone(current);
+            match slot {
+                ModuleSlot::ContentFilter => loaded.content_filter = Some(module.clone()),
+                _ => loaded.antivirus = Some(module.clone()),
+            }
+            loaded.shadow.remove(&target);
+            loaded
+        });
+        target.stats().reset();
+
+        let summary = format!(
+            "{} rules of module {}, disagreeing on {} of {} transactions ({} newly blocked, {} newly allowed)",
+            target.as_str(),
+            target.module(),
+            report.evaluated - report.agreed,
+            report.evaluated,
+            report.newly_blocked,
+            report.newly_allowed
+        );
+        slog::info!(sources.logger, "Promoted the candidate {}", summary);
+        let audit_ops = DefaultIcapAuditOps::new(NodeName::new_static("g3icap"), true);
+        audit_ops.log_config_changed("Shadow rules promoted", &summary);
+        Ok(())
+    }
+
+    /// Make the rules of the target the ones before the promotion again
+    ///
+    /// The active module is created again from its previous rules and the
+    /// candidate ones are evaluated alongside again. The candidate rules are
+    /// kept active if either module fails to initialize.
+    pub async fn demote(&self, target: ShadowTarget) -> anyhow::Result<()> {
+        let sources = self
+            .sources
+            .as_ref()
+            .ok_or_else(|| anyhow!("no module loaded"))?;
+        if !sources.is_promoted(target) {
+            return Err(anyhow!("{} rules are not promoted", target.as_str()));
+        }
+
+        sources.promoted.lock().unwrap().remove(&target);
+        let slot = match target {
+            ShadowTarget::ContentFilter => ModuleSlot::ContentFilter,
+            ShadowTarget::Yara => ModuleSlot::Antivirus,
+        };
+        let result = match sources.create(slot).await {
+            Some(Ok(active)) => match sources.create(ModuleSlot::Shadow(target)).await {
+                Some(Ok(shadow)) => Ok((active, shadow)),
+                Some(Err(e)) => Err(e),
+                None => Err(anyhow!("no candidate {} rules", target.as_str())),
+            },
+            Some(Err(e)) => Err(e),
+            None => Err(anyhow!("no module named {}", target.module())),
+        };
+        let (active, shadow) = match result {
+            Ok(modules) => modules,
+            Err(e) => {
+                sources.promoted.lock().unwrap().insert(target);
+                return Err(anyhow!(
+                    "module {} failed to initialize with the previous rules, the candidate ones are kept: {e}",
+                    target.module()
+                ));
+            }
+        };
+
+        self.loaded.rcu(|current| {
+            let mut loaded = LoadedModules::clone(current);
+            match slot {
+                ModuleSlot::ContentFilter => loaded.content_filter = Some(active.clone()),
+                _ => loaded.antivirus = Some(active.clone()),
+            }
+            loaded
+                .shadow
+                .insert(target, sources.shadow_module(target, shadow.clone()));
+            loaded
+        });
+        target.stats().reset();
+
+        let summary = format!("{} rules of module {}", target.as_str(), target.module());
+        slog::info!(sources.logger, "Reverted the promoted {}", summary);
+        let audit_ops = DefaultIcapAuditOps::new(NodeName::new_static("g3icap"), true);
+        audit_ops.log_config_changed("Promoted rules reverted", &summary);
+        Ok(())
+    }
+
+    /// Check if the target has candidate rules, not promoted yet
+    pub fn has_candidate(&self, target: ShadowTarget) -> bool {
+        self.sources
+            .as_ref()
+            .is_some_and(|s| s.has_candidate(target))
+    }
+}
+
+impl ModuleSlot<'_> {
+    /// Name of the module, as in the metrics
+    fn name(&self) -> String {
+        match self {
+            ModuleSlot::ContentFilter => "content_filter".to_string(),
+            ModuleSlot::Antivirus => "antivirus".to_string(),
+            ModuleSlot::Waf => "waf".to_string(),
+            ModuleSlot::Scripted(service) => format!("{SCRIPTED_PREFIX}{service}"),
+            ModuleSlot::Shadow(target) => format!("{SHADOW_PREFIX}{}", target.as_str()),
+        }
+    }
+}
+
+impl ModuleSources {
+    fn new(
+        config: &IcapServerConfig,
+        blocklist: Option<Arc<BlocklistProvider>>,
+        logger: Logger,
+    ) -> Self {
+        ModuleSources {
+            blocklist,
+            scripted_services: config.scripted_services().clone(),
+            waf: config.waf().cloned(),
+            ai_inspection: config.ai_inspection().clone(),
+            antivirus: config.antivirus().clone(),
+            shadow: config.shadow().clone(),
+            policy: config.policy().cloned(),
+            promoted: Mutex::new(HashSet::new()),
+            logger,
+        }
+    }
+
+    /// Create and initialize the module of the slot, logging its failure
+    async fn load(&self, slot: ModuleSlot<'_>) -> Option<Arc<dyn IcapModule>> {
+        match self.create(slot).await? {
+            Ok(module) => Some(module),
+            Err(e) => {
+                slog::warn!(
+                    self.logger,
+                    "Failed to load {} module: {:#}",
+                    slot.name(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Create and initialize the module of the slot
+    ///
+    /// Returns `None` if the slot has no module, like the WAF one without a
+    /// `waf` config or the antivirus one with malware scanning disabled.
+    async fn create(&self, slot: ModuleSlot<'_>) -> Option<anyhow::Result<Arc<dyn IcapModule>>> {
+        match slot {
+            ModuleSlot::ContentFilter => Some(self.content_filter().await),
+            ModuleSlot::Antivirus => self.antivirus().await,
+            ModuleSlot::Waf => self.waf().await,
+            ModuleSlot::Scripted(service) => self.scripted(service).await,
+            ModuleSlot::Shadow(target) => self.shadow(target).await,
+        }
+    }
+
+    /// Check if the target has candidate rules, not promoted yet
+    fn has_candidate(&self, target: ShadowTarget) -> bool {
+        self.shadow.has_candidate(target) && !self.is_promoted(target)
+    }
+
+    fn is_promoted(&self, target: ShadowTarget) -> bool {
+        self.promoted.lock().unwrap().contains(&target)
+    }
+
+    fn shadow_module(&self, target: ShadowTarget, module: Arc<dyn IcapModule>) -> ShadowModule {
+        ShadowModule::new(target, module, self.shadow.log_disagreements)
+    }
+
+    async fn content_filter(&self) -> anyhow::Result<Arc<dyn IcapModule>> {
+        let rules = self
+            .shadow
+            .content_filter
+            .as_ref()
+            .filter(|_| self.is_promoted(ShadowTarget::ContentFilter));
+        let mut content_filter = ContentFilterModule::new(self.content_filter_config(rules));
+        content_filter.set_blocklist(self.blocklist.clone());
+        init_module(content_filter, &self.logger).await
+    }
+
+    /// Config of the content filter, with the given default rules if any
+    ///
+    /// The rules of the policies replace the built-in ones, the given rules
+    /// then replacing the default rules of the policies.
+    fn content_filter_config(&self, rules: Option<&FilterRuleSet>) -> ContentFilterConfig {
+        // Blocked domains come from the server blocklist
+        let mut content_filter_config = ContentFilterConfig {
+            blocked_domains: Vec::new(),
+            blocked_domain_patterns: vec![
+                r".*\.malware\..*".to_string(),
+                r".*\.phishing\..*".to_string(),
+            ],
+            blocked_keywords: vec![
+                "malware".to_string(),
+                "virus".to_string(),
+                "phishing".to_string(),
+                "spam".to_string(),
+                "trojan".to_string(),
+                "backdoor".to_string(),
+            ],
+            blocked_keyword_patterns: vec![r".*malware.*".to_string(), r".*virus.*".to_string()],
+            blocked_mime_types: vec![
+                "application/x-executable".to_string(),
+                "application/x-msdownload".to_string(),
+                "application/x-msdos-program".to_string(),
+            ],
+            blocked_extensions: vec![
+                ".exe".to_string(),
+                ".bat".to_string(),
+                ".cmd".to_string(),
+                ".scr".to_string(),
+            ],
+            max_file_size: Some(10 * 1024 * 1024), // 10MB
+            case_insensitive: true,
+            enable_regex: true,
+            blocking_action: crate::modules::content_filter::BlockingAction::Forbidden,
+            custom_message: Some("Content blocked by G3ICAP".to_string()),
+            enable_logging: true,
+            enable_metrics: true,
+            regex_cache_size: 1000,
+            pattern_engine: Default::default(),
+            user_rules: Default::default(),
+            group_rules: Default::default(),
+            detection_capture: Default::default(),
+            block_page: crate::modules::block_page::BlockPageConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            blocked_file_types: vec!["executable".to_string()],
+            file_type_detection: crate::modules::file_type::FileTypeDetectionConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            confusable_domains: Default::default(),
+            ai_inspection: self.ai_inspection.clone(),
+        };
+        if let Some(policy) = &self.policy {
+            policy.apply_content_filter(&mut content_filter_config);
+        }
+        if let Some(rules) = rules {
+            let config = &mut content_filter_config;
+            config.blocked_domains = rules.blocked_domains.clone();
+            config.blocked_domain_patterns = rules.blocked_domain_patterns.clone();
+            config.blocked_keywords = rules.blocked_keywords.clone();
+            config.blocked_keyword_patterns = rules.blocked_keyword_patterns.clone();
+            config.blocked_mime_types = rules.blocked_mime_types.clone();
+            config.blocked_extensions = rules.blocked_extensions.clone();
+            config.blocked_file_types = rules.blocked_file_types.clone();
+            config.max_file_size = rules.max_file_size;
+        }
+        content_filter_config
+    }
+
+    async fn antivirus(&self) -> Option<anyhow::Result<Arc<dyn IcapModule>>> {
+        let scanning = self
+            .policy
+            .as_ref()
+            .and_then(|p| p.malware_scanning.as_ref());
+        if scanning.is_some_and(|s| !s.enabled) {
+            return None;
+        }
+        let yara = self
+            .shadow
+            .yara
+            .as_ref()
+            .filter(|_| self.is_promoted(ShadowTarget::Yara));
+        let antivirus = AntivirusModule::new(self.antivirus_config(yara));
+        Some(init_module(antivirus, &self.logger).await)
+    }
+
+    /// Config of the antivirus, scanning with the given YARA rules if any
+    ///
+    /// The engine is the mock one unless the `antivirus` section sets clamd.
+    fn antivirus_config(&self, yara: Option<&ShadowYaraConfig>) -> AntivirusConfig {
+        let mut antivirus_config = AntivirusConfig {
+            engine: AntivirusEngine::Mock {
+                simulate_threats: false,
+                scan_delay: std::time::Duration::from_millis(50),
+            },
+            max_file_size: 50 * 1024 * 1024, // 50MB
+            scan_timeout: std::time::Duration::from_secs(30),
+            quarantine_dir: Some(std::path::PathBuf::from("/tmp/g3icap/quarantine")),
+            enable_quarantine: true,
+            enable_logging: true,
+            enable_metrics: true,
+            scan_file_types: vec![
+                "application/octet-stream".to_string(),
+                "application/x-executable".to_string(),
+                "application/x-msdownload".to_string(),
+            ],
+            skip_file_types: vec![
+                "text/plain".to_string(),
+                "text/html".to_string(),
+                "image/jpeg".to_string(),
+                "image/png".to_string(),
+            ],
+            enable_realtime: true,
+            update_interval: std::time::Duration::from_secs(3600), // 1 hour
+            enable_threat_intel: false,
+            threat_intel_sources: Vec::new(),
+            threat_intel_batch: Default::default(),
+            secondary_engine: None,
+            hedging: Default::default(),
+            verdict_cache: None,
+            yara_config: None,
+        };
+        if let Some(timeout) = self.antivirus.scan_timeout {
+            antivirus_config.scan_timeout = timeout;
+        }
+        antivirus_config.verdict_cache = self.antivirus.verdict_cache.clone();
+        antivirus_config.enable_threat_intel = !self.antivirus.threat_intel.is_empty();
+        antivirus_config.threat_intel_sources = self.antivirus.threat_intel.clone();
+        if let Some(scanning) = self
+            .policy
+            .as_ref()
+            .and_then(|p| p.malware_scanning.as_ref())
+        {
+            antivirus_config.enable_quarantine = scanning.action == PolicyAction::Quarantine;
+            if let Some(timeout) = scanning.timeout {
+                antivirus_config.scan_timeout = timeout;
+            }
+        }
+        if let Some(clamd) = &self.antivirus.clamd {
+            antivirus_config.engine = AntivirusEngine::ClamAV {
+                socket_path: clamd.clone(),
+                timeout: antivirus_config.scan_timeout,
+            };
+        }
+        if let Some(yara) = yara {
+            antivirus_config.engine = AntivirusEngine::YARA {
+                rules_dir: yara.rules_dir.clone(),
+                timeout: antivirus_config.scan_timeout,
+                max_rules: yara.max_rules,
+                enable_compilation: false,
+            };
+        }
+        antivirus_config
+    }
+
+    /// Create and initialize the module with the candidate rules of the target
+    ///
+    /// Returns `None` if the target has no candidate rules.
+    async fn shadow(&self, target: ShadowTarget) -> Option<anyhow::Result<Arc<dyn IcapModule>>> {
+        if !self.has_candidate(target) {
+            return None;
+        }
+        let name = ModuleSlot::Shadow(target).name();
+        let result = match target {
+            ShadowTarget::ContentFilter => {
+                let config = self.content_filter_config(self.shadow.content_filter.as_ref());
+                let mut content_filter = ContentFilterModule::new(config);
+                content_filter.set_blocklist(self.blocklist.clone());
+                content_filter.set_name(&name);
+                init_module(content_filter, &self.logger).await
+            }
+            ShadowTarget::Yara => {
+                let mut config = self.antivirus_config(self.shadow.yara.as_ref());
+                // only the verdicts of the candidate are used
+                config.enable_quarantine = false;
+                config.enable_threat_intel = false;
+                config.verdict_cache = None;
+                let mut antivirus = AntivirusModule::new(config);
+                antivirus.set_name(&name);
+                init_module(antivirus, &self.logger).await
+            }
+        };
+        Some(result)
+    }
+
+    async fn waf(&self) -> Option<anyhow::Result<Arc<dyn IcapModule>>> {
+        let config = self.waf.as_ref()?;
+        let result = match WafModule::new(config) {
+            Ok(module) => init_module(module, &self.logger).await,
+            Err(e) => Err(anyhow!("failed to load waf rules: {e}")),
+        };
+        Some(result)
+    }
+
+    async fn scripted(&self, service: &str) -> Option<anyhow::Result<Arc<dyn IcapModule>>> {
+        let config = self.scripted_services.services.get(service)?;
+        let result = match ExpressionModule::new(service, config) {
+            Ok(module) => init_module(module, &self.logger).await,
+            Err(e) => Err(anyhow!("failed to compile scripted service {service}: {e}")),
+        };
+        Some(result)
+    }
+}
+
+async fn init_module<M>(mut module: M, logger: &Logger) -> anyhow::Result<Arc<dyn IcapModule>>
+where
+    M: IcapModule + 'static,
+{
+    let module_config = ModuleConfig {
+        name: module.name().to_string(),
+        path: std::path::PathBuf::from(""),
+        version: module.version().to_string(),
+        config: serde_json::Value::Object(serde_json::Map::new()),
+        dependencies: Vec::new(),
+        load_timeout: Duration::from_secs(5),
+        max_memory: 1024 * 1024,
+        sandbox: true,
+        metrics: MetricsRegistry::global().module(module.name()),
+    };
+    match module.init(&module_config).await {
+        Ok(_) => {
+            slog::info!(logger, "Initialized {} module", module_config.name);
+            // registered for the per module metrics, and cleaned up when
+            // the servers and the registry are done with it
+            let (module, _) =
+                ModuleRegistry::global().register_module(&module_config.name, Box::new(module));
+            Ok(module)
+        }
+        Err(e) => Err(anyhow!(
+            "failed to initialize {} module: {e}",
+            module_config.name
+        )),
+    }
+}
@@ -0,0 +1,227 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Service pipeline bindings
+//!
+//! Each transaction looks up the pipeline of its service once, when it
+//! starts, so a binding swapped by the control channel applies from the
+//! next transaction on and never in the middle of one.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::anyhow;
+use arc_swap::{ArcSwap, ArcSwapOption};
+use serde::{Deserialize, Serialize};
+
+use crate::config::server::pipelines::{PipelineStage, PipelinesConfig};
+
+static GLOBAL_PIPELINES: ArcSwapOption<ServicePipelines> = ArcSwapOption::const_empty();
+
+/// Install the pipelines of the server
+pub fn set_global(pipelines: Option<Arc<ServicePipelines>>) {
+    GLOBAL_PIPELINES.store(pipelines);
+}
+
+/// Get the pipelines currently in use
+pub fn get_global() -> Option<Arc<ServicePipelines>> {
+    GLOBAL_PIPELINES.load_full()
+}
+
+/// A named list of module stages
+#[derive(Debug)]
+pub struct Pipeline {
+    name: String,
+    stages: Vec<PipelineStage>,
+    transactions: AtomicU64,
+}
+
+impl Pipeline {
+    /// Name of the pipeline
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Stages of the pipeline, in order
+    pub fn stages(&self) -> &[PipelineStage] {
+        &self.stages
+    }
+
+    /// Check if the pipeline runs the stage
+    pub fn runs(&self, stage: PipelineStage) -> bool {
+        self.stages.contains(&stage)
+    }
+
+    /// Count a transaction run through the pipeline
+    pub fn add_transaction(&self) {
+        self.transactions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of transactions run through the pipeline since the server
+    /// was started or reloaded
+    pub fn transactions(&self) -> u64 {
+        self.transactions.load(Ordering::Relaxed)
+    }
+}
+
+/// Transactions of a pipeline and the services bound to it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PipelineStats {
+    pub name: String,
+    pub stages: Vec<String>,
+    pub services: Vec<String>,
+    pub transactions: u64,
+}
+
+/// Pipelines of a server and the services bound to them
+#[derive(Debug)]
+pub struct ServicePipelines {
+    pipelines: HashMap<String, Arc<Pipeline>>,
+    bindings: ArcSwap<HashMap<String, Arc<Pipeline>>>,
+}
+
+impl ServicePipelines {
+    /// Build the pipelines with the bindings of the config
+    pub fn new(config: &PipelinesConfig) -> Self {
+        let pipelines: HashMap<String, Arc<Pipeline>> = config
+            .pipelines
+            .iter()
+            .map(|(name, stages)| {
+                let pipeline = Pipeline {
+                    name: name.clone(),
+                    stages: stages.clone(),
+                    transactions: AtomicU64::new(0),
+                };
+                (name.clone(), Arc::new(pipeline))
+            })
+            .collect();
+        let bindings = config
+            .services
+            .iter()
+            .filter_map(|(service, name)| Some((service.clone(), pipelines.get(name)?.clone())))
+            .collect();
+        ServicePipelines {
+            pipelines,
+            bindings: ArcSwap::from_pointee(bindings),
+        }
+    }
+
+    /// Pipeline of the service, `None` if it runs all stages
+    pub fn pipeline(&self, service: &str) -> Option<Arc<Pipeline>> {
+        self.bindings.load().get(service.trim_matches('/')).cloned()
+    }
+
+    /// Names of the pipelines, sorted
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.pipelines.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Pipeline names keyed by service name, sorted by service
+    pub fn bindings(&self) -> Vec<(String, String)> {
+        let mut bindings: Vec<(String, String)> = self
+            .bindings
+            .load()
+            .iter()
+            .map(|(service, pipeline)| (service.clone(), pipeline.name.clone()))
+            .collect();
+        bindings.sort();
+        bindings
+    }
+
+    /// Transactions of the pipelines, sorted by name
+    pub fn stats(&self) -> Vec<PipelineStats> {
+        let bindings = self.bindings();
+        self.names()
+            .into_iter()
+            .filter_map(|name| {
+                let pipeline = self.pipelines.get(&name)?;
+                Some(PipelineStats {
+                    stages: pipeline
+                        .stages
+                        .iter()
+                        .map(|s| s.as_str().to_string())
+                        .collect(),
+                    services: bindings
+                        .iter()
+                        .filter(|(_, p)| *p == name)
+                        .map(|(s, _)| s.clone())
+                        .collect(),
+                    transactions: pipeline.transactions(),
+                    name,
+                })
+            })
+            .collect()
+    }
+
+    /// Bind the service to another pipeline
+    ///
+    /// Returns the name of the previous pipeline of the service.
+    pub fn set_pipeline(&self, service: &str, name: &str) -> anyhow::Result<Option<String>> {
+        let pipeline = self
+            .pipelines
+            .get(name)
+            .ok_or_else(|| anyhow!("no pipeline named {name}"))?;
+        let service = service.trim_matches('/');
+        let previous = self.bindings.rcu(|bindings| {
+            let mut bindings = HashMap::clone(bindings);
+            bindings.insert(service.to_string(), pipeline.clone());
+            bindings
+        });
+        Ok(previous.get(service).map(|p| p.name.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_pipeline() {
+        let mut config = PipelinesConfig::default();
+        config.pipelines.insert(
+            "default".to_string(),
+            vec![PipelineStage::ContentFilter, PipelineStage::Antivirus],
+        );
+        config
+            .pipelines
+            .insert("lenient".to_string(), vec![PipelineStage::ContentFilter]);
+        config
+            .services
+            .insert("av-service".to_string(), "default".to_string());
+        let pipelines = ServicePipelines::new(&config);
+
+        let before = pipelines.pipeline("/av-service").unwrap();
+        assert!(before.runs(PipelineStage::Antivirus));
+        assert!(pipelines.pipeline("filter").is_none());
+
+        let previous = pipelines.set_pipeline("av-service", "lenient").unwrap();
+        assert_eq!(previous.as_deref(), Some("default"));
+        let after = pipelines.pipeline("av-service").unwrap();
+        assert_eq!(after.name(), "lenient");
+        assert!(!after.runs(PipelineStage::Antivirus));
+        // a transaction keeps the pipeline it started with
+        assert!(before.runs(PipelineStage::Antivirus));
+
+        assert_eq!(pipelines.names(), ["default", "lenient"]);
+        assert_eq!(
+            pipelines.bindings(),
+            [("av-service".to_string(), "lenient".to_string())]
+        );
+
+        assert!(pipelines.set_pipeline("av-service", "strict").is_err());
+        assert_eq!(pipelines.set_pipeline("filter", "lenient").unwrap(), None);
+
+        after.add_transaction();
+        let stats = pipelines.stats();
+        assert_eq!(stats[0].transactions, 0);
+        assert_eq!(stats[1].name, "lenient");
+        assert_eq!(stats[1].stages, ["content_filter"]);
+        assert_eq!(stats[1].services, ["av-service", "filter"]);
+        assert_eq!(stats[1].transactions, 1);
+    }
+}
@@ -0,0 +1,285 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Scheduled promotion of the candidate rules
+//!
+//! A promotion of candidate rules can be staged to happen at a later time,
+//! like at the start of a change window, and can be made temporary: unless
+//! confirmed, the previous rules are active again once the revert time is
+//! up, so that a mistake in a change window, or a lockdown during an
+//! incident, does not outlive it. A rule set has at most one schedule, a
+//! new one replacing it.
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+use anyhow::anyhow;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::modules::shadow::ShadowTarget;
+use crate::server::modules::ServerModules;
+
+/// Longest time a promotion can be scheduled in advance
+pub const MAX_DELAY: Duration = Duration::from_secs(30 * 86400);
+/// Longest time a temporary promotion lasts
+pub const MAX_REVERT_AFTER: Duration = Duration::from_secs(7 * 86400);
+
+static SCHEDULES: Mutex<Vec<ScheduledPromotion>> = Mutex::new(Vec::new());
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// State of a scheduled promotion
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleState {
+    /// Waiting for its activation time
+    Pending,
+    /// Promoted, waiting for its revert time or a confirmation
+    Active,
+}
+
+impl ScheduleState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScheduleState::Pending => "pending",
+            ScheduleState::Active => "active",
+        }
+    }
+}
+
+/// A promotion of candidate rules scheduled or waiting to be confirmed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduledPromotion {
+    /// Rule set, like `content_filter`
+    pub rule_set: String,
+    pub state: ScheduleState,
+    /// Unix time of the promotion
+    pub activate_at: u64,
+    /// Unix time of the revert, `None` once confirmed
+    pub revert_at: Option<u64>,
+    #[serde(skip)]
+    generation: u64,
+}
+
+/// What a revert did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RevertOutcome {
+    /// The previous rules are active again
+    Reverted,
+    /// The promotion was not done yet and will not be
+    Cancelled,
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Promote the candidate rules of the target at the unix time, now if it
+/// has passed, and revert the promotion after some time unless confirmed
+///
+/// An immediate promotion without a revert is not kept in the schedules.
+pub async fn schedule(
+    modules: &ServerModules,
+    target: ShadowTarget,
+    activate_at: u64,
+    revert_after: Option<Duration>,
+) -> anyhow::Result<Option<ScheduledPromotion>> {
+    let now = SystemTime::now();
+    let delay = Duration::from_secs(activate_at.saturating_sub(unix_time(now)));
+    if delay > MAX_DELAY {
+        return Err(anyhow!(
+            "activation should be within {}s",
+            MAX_DELAY.as_secs()
+        ));
+    }
+    if let Some(revert_after) = revert_after
+        && (revert_after.is_zero() || revert_after > MAX_REVERT_AFTER)
+    {
+        return Err(anyhow!(
+            "revert should be within 1s and {}s",
+            MAX_REVERT_AFTER.as_secs()
+        ));
+    }
+    if !modules.has_candidate(target) {
+        return Err(anyhow!("no candidate {} rules", target.as_str()));
+    }
+    // the promotion and the revert are scheduled on the runtime of the daemon
+    let runtime = tokio::runtime::Handle::try_current()
+        .map_err(|_| anyhow!("no runtime to schedule the promotion on"))?;
+
+    let immediate = delay.is_zero();
+    if immediate {
+        modules.promote(target).await?;
+        if revert_after.is_none() {
+            // a previous schedule of the rule set is replaced
+            remove(target);
+            return Ok(None);
+        }
+    }
+
+    let activate_at = now + delay;
+    let generation = GENERATION.fetch_add(1, Ordering::Relaxed);
+    let entry = ScheduledPromotion {
+        rule_set: target.as_str().to_string(),
+        state: if immediate {
+            ScheduleState::Active
+        } else {
+            ScheduleState::Pending
+        },
+        activate_at: unix_time(activate_at),
+        revert_at: revert_after.map(|d| unix_time(activate_at + d)),
+        generation,
+    };
+    {
+        let mut schedules = SCHEDULES.lock().unwrap();
+        schedules.retain(|s| s.rule_set != entry.rule_set);
+        schedules.push(entry.clone());
+    }
+
+    let modules = modules.clone();
+    runtime.spawn(async move {
+        if !immediate {
+            tokio::time::sleep(delay).await;
+            if !is_current(target, generation) {
+                return;
+            }
+            if let Err(e) = modules.promote(target).await {
+                warn!("scheduled promotion of {} failed: {e:?}", target.as_str());
+                remove_generation(target, generation);
+                return;
+            }
+            info!("scheduled promotion of {} done", target.as_str());
+            let confirmed = update(target, generation, |s| {
+                s.state = ScheduleState::Active;
+                s.revert_at.is_none()
+            });
+            if confirmed.unwrap_or(true) {
+                remove_generation(target, generation);
+                return;
+            }
+        }
+        let Some(revert_after) = revert_after else {
+            return;
+        };
+        tokio::time::sleep(revert_after).await;
+        // a confirmation drops the revert time
+        let reverting = update(target, generation, |s| s.revert_at.is_some());
+        if reverting != Some(true) {
+            return;
+        }
+        remove_generation(target, generation);
+        match modules.demote(target).await {
+            Ok(()) => info!("unconfirmed promotion of {} reverted", target.as_str()),
+            Err(e) => warn!(
+                "revert of the promotion of {} failed: {e:?}",
+                target.as_str()
+            ),
+        }
+    });
+    Ok(Some(entry))
+}
+
+/// Keep the scheduled promotion of the target, dropping its revert
+pub fn confirm(target: ShadowTarget) -> anyhow::Result<ScheduleState> {
+    let mut schedules = SCHEDULES.lock().unwrap();
+    let index = schedules
+        .iter()
+        .position(|s| s.rule_set == target.as_str() && s.revert_at.is_some())
+        .ok_or_else(|| anyhow!("no promotion of {} to confirm", target.as_str()))?;
+    let state = schedules[index].state;
+    match state {
+        ScheduleState::Active => {
+            schedules.remove(index);
+        }
+        ScheduleState::Pending => schedules[index].revert_at = None,
+    }
+    Ok(state)
+}
+
+/// Cancel the scheduled promotion of the target, or revert it if done
+///
+/// A promotion not scheduled, made by a plain promote, can be reverted too.
+pub async fn revert(
+    modules: &ServerModules,
+    target: ShadowTarget,
+) -> anyhow::Result<RevertOutcome> {
+    if remove(target).is_some_and(|s| s.state == ScheduleState::Pending) {
+        return Ok(RevertOutcome::Cancelled);
+    }
+    modules.demote(target).await?;
+    Ok(RevertOutcome::Reverted)
+}
+
+/// Get the scheduled promotions
+pub fn list() -> Vec<ScheduledPromotion> {
+    SCHEDULES.lock().unwrap().clone()
+}
+
+fn is_current(target: ShadowTarget, generation: u64) -> bool {
+    update(target, generation, |_| ()).is_some()
+}
+
+/// Update the schedule of the target if it is still the given one
+fn update<F, T>(target: ShadowTarget, generation: u64, f: F) -> Option<T>
+where
+    F: FnOnce(&mut ScheduledPromotion) -> T,
+{
+    let mut schedules = SCHEDULES.lock().unwrap();
+    schedules
+        .iter_mut()
+        .find(|s| s.rule_set == target.as_str() && s.generation == generation)
+        .map(f)
+}
+
+fn remove(target: ShadowTarget) -> Option<ScheduledPromotion> {
+    let mut schedules = SCHEDULES.lock().unwrap();
+    let index = schedules
+        .iter()
+        .position(|s| s.rule_set == target.as_str())?;
+    Some(schedules.remove(index))
+}
+
+fn remove_generation(target: ShadowTarget, generation: u64) {
+    SCHEDULES
+        .lock()
+        .unwrap()
+        .retain(|s| s.rule_set != target.as_str() || s.generation != generation);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn confirm_drops_revert() {
+        let generation = GENERATION.fetch_add(1, Ordering::Relaxed);
+        SCHEDULES.lock().unwrap().push(ScheduledPromotion {
+            rule_set: ShadowTarget::Yara.as_str().to_string(),
+            state: ScheduleState::Pending,
+            activate_at: 100,
+            revert_at: Some(200),
+            generation,
+        });
+        assert_eq!(confirm(ShadowTarget::Yara).unwrap(), ScheduleState::Pending);
+        assert!(is_current(ShadowTarget::Yara, generation));
+        assert_eq!(
+            update(ShadowTarget::Yara, generation, |s| s.revert_at),
+            Some(None)
+        );
+        // nothing left to confirm
+        assert!(confirm(ShadowTarget::Yara).is_err());
+
+        update(ShadowTarget::Yara, generation, |s| {
+            s.state = ScheduleState::Active;
+            s.revert_at = Some(200);
+        });
+        assert_eq!(confirm(ShadowTarget::Yara).unwrap(), ScheduleState::Active);
+        assert!(!is_current(ShadowTarget::Yara, generation));
+        assert!(list().iter().all(|s| s.rule_set != "yara"));
+    }
+}
@@ -0,0 +1,690 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Per-user data quota tracking
+//!
+//! The bytes received for the transactions of each user are added to its
+//! consumption of the current UTC day and month when the transaction ends,
+//! and to its count of the current bandwidth window. A new transaction of a
+//! user is checked against its limits before being handled: it is blocked or
+//! warned about over a limit, and warned about close to one. The users are
+//! keyed by the user name forwarded by the proxy, or by the client address
+//! if no user is forwarded. The daily and monthly consumption is saved to
+//! the state file periodically, a restart losing at most the last interval.
+//! It is replicated to the standby server as the `quota` state, by user.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use arc_swap::ArcSwapOption;
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use http::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::task::JoinHandle;
+
+use crate::auth::identity::{ClientIdentity, HEADER_CLIENT_IP};
+use crate::config::server::quota::{QuotaAction, QuotaConfig, QuotaLimits, QuotaRules};
+use crate::replication::{self, ReplicatedState};
+
+/// ICAP response header telling the proxy a user is close to or over a limit
+pub const HEADER_QUOTA_WARNING: &str = "x-quota-warning";
+
+static GLOBAL_QUOTA: ArcSwapOption<QuotaTracker> = ArcSwapOption::const_empty();
+
+/// Install the quota tracker of the server, for the metrics
+pub fn set_global(quota: Option<Arc<QuotaTracker>>) {
+    GLOBAL_QUOTA.store(quota);
+}
+
+pub fn get_global() -> Option<Arc<QuotaTracker>> {
+    GLOBAL_QUOTA.load_full()
+}
+
+/// Period of a limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaPeriod {
+    Daily,
+    Monthly,
+    Bandwidth,
+}
+
+impl QuotaPeriod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuotaPeriod::Daily => "daily",
+            QuotaPeriod::Monthly => "monthly",
+            QuotaPeriod::Bandwidth => "bandwidth",
+        }
+    }
+}
+
+/// Consumption of a user against one of its limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaUsage {
+    pub period: QuotaPeriod,
+    /// Bytes, or bytes per second for the bandwidth
+    pub used: u64,
+    pub limit: u64,
+}
+
+impl std::fmt::Display for QuotaUsage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let unit = match self.period {
+            QuotaPeriod::Bandwidth => "bytes/s",
+            QuotaPeriod::Daily | QuotaPeriod::Monthly => "bytes",
+        };
+        write!(
+            f,
+            "{} {}/{} {unit}",
+            self.period.as_str(),
+            self.used,
+            self.limit
+        )
+    }
+}
+
+/// Verdict of the limits of a user on a new transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaVerdict {
+    Within,
+    /// Close to a limit, from the warning ratio of it
+    Warning(QuotaUsage),
+    Exceeded(QuotaUsage),
+}
+
+/// Quota counters
+#[derive(Debug, Default)]
+pub struct QuotaStats {
+    /// Transactions of users over a limit
+    pub exceeded: AtomicU64,
+    /// Transactions of users close to a limit
+    pub warned: AtomicU64,
+    /// Transactions not counted as too many users are tracked
+    pub dropped: AtomicU64,
+}
+
+/// User of a transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaUser {
+    /// User name, or client address if no user is forwarded
+    pub key: String,
+    pub groups: Vec<String>,
+}
+
+/// Daily and monthly consumption, as saved to the state file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Consumption {
+    /// Days since the common era of the daily consumption
+    day: i32,
+    daily: u64,
+    /// Months since the common era of the monthly consumption
+    month: i32,
+    monthly: u64,
+}
+
+impl Consumption {
+    fn current(&self, day: i32, month: i32) -> (u64, u64) {
+        (
+            if self.day == day { self.daily } else { 0 },
+            if self.month == month { self.monthly } else { 0 },
+        )
+    }
+
+    fn add(&mut self, day: i32, month: i32, bytes: u64) {
+        (self.daily, self.monthly) = self.current(day, month);
+        self.day = day;
+        self.month = month;
+        self.daily = self.daily.saturating_add(bytes);
+        self.monthly = self.monthly.saturating_add(bytes);
+    }
+}
+
+fn periods(now: DateTime<Utc>) -> (i32, i32) {
+    (
+        now.num_days_from_ce(),
+        now.year() * 12 + now.month0() as i32,
+    )
+}
+
+struct UserState {
+    limits: QuotaLimits,
+    consumption: Consumption,
+    window_start: Instant,
+    window_bytes: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedState {
+    users: HashMap<String, Consumption>,
+}
+
+/// Tracker of the data consumption of all users of a server
+pub struct QuotaTracker {
+    config: QuotaConfig,
+    rules: QuotaRules,
+    users: Mutex<HashMap<String, UserState>>,
+    /// Consumption changed since the last save
+    dirty: AtomicBool,
+    stats: QuotaStats,
+}
+
+impl QuotaTracker {
+    /// Create the tracker, with the limits of the config over the ones of
+    /// the policies, loading the saved consumption
+    pub fn new(config: QuotaConfig, policy: Option<&QuotaRules>) -> Self {
+        let rules = match policy {
+            Some(policy) => config.rules.clone().or(policy),
+            None => config.rules.clone(),
+        };
+        let tracker = QuotaTracker {
+            config,
+            rules,
+            users: Mutex::new(HashMap::new()),
+            dirty: AtomicBool::new(false),
+            stats: QuotaStats::default(),
+        };
+        if let Some(path) = &tracker.config.state_file
+            && path.exists()
+        {
+            match tracker.load(path, Utc::now()) {
+                Ok(n) => log::info!(
+                    "loaded quota consumption of {n} users from {}",
+                    path.display()
+                ),
+                Err(e) => log::warn!("failed to load quota state {}: {e:?}", path.display()),
+            }
+        }
+        tracker
+    }
+
+    pub fn action(&self) -> QuotaAction {
+        self.config.action
+    }
+
+    pub fn stats(&self) -> &QuotaStats {
+        &self.stats
+    }
+
+    /// User of an ICAP request, `None` if the proxy forwarded no client
+    pub fn user(headers: &HeaderMap) -> Option<QuotaUser> {
+        let identity = ClientIdentity::from_headers(headers);
+        let key = match identity.username {
+            Some(name) => name,
+            None => headers
+                .get(HEADER_CLIENT_IP)
+                .and_then(|v| v.to_str().ok())
+                .map(|s| s.trim())
+                .filter(|s| !s.is_empty())?
+                .to_string(),
+        };
+        Some(QuotaUser {
+            key,
+            groups: identity.groups,
+        })
+    }
+
+    /// Check the limits of the user before handling a transaction
+    pub fn check(&self, user: &QuotaUser) -> QuotaVerdict {
+        self.check_at(user, Utc::now(), Instant::now())
+    }
+
+    fn check_at(&self, user: &QuotaUser, now: DateTime<Utc>, instant: Instant) -> QuotaVerdict {
+        let verdict = self.verdict_at(user, now, instant);
+        let counter = match verdict {
+            QuotaVerdict::Within => return verdict,
+            QuotaVerdict::Warning(_) => &self.stats.warned,
+            QuotaVerdict::Exceeded(_) => &self.stats.exceeded,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        verdict
+    }
+
+    /// Get the verdict of the limits of the user at the time, without
+    /// counting it
+    pub fn peek(&self, user: &QuotaUser, now: DateTime<Utc>) -> QuotaVerdict {
+        self.verdict_at(user, now, Instant::now())
+    }
+
+    fn verdict_at(&self, user: &QuotaUser, now: DateTime<Utc>, instant: Instant) -> QuotaVerdict {
+        let limits = self.rules.limits(&user.key, &user.groups);
+        if limits.is_empty() {
+            return QuotaVerdict::Within;
+        }
+        let (day, month) = periods(now);
+        let users = self.users.lock().unwrap();
+        let (daily, monthly, rate) = match users.get(&user.key) {
+            Some(state) => {
+                let (daily, monthly) = state.consumption.current(day, month);
+                let window = self.config.bandwidth_window;
+                let rate = if instant.duration_since(state.window_start) < window {
+                    (state.window_bytes as f64 / window.as_secs_f64()).ceil() as u64
+                } else {
+                    0
+                };
+                (daily, monthly, rate)
+            }
+            None => (0, 0, 0),
+        };
+        drop(users);
+
+        let usages = [
+            (QuotaPeriod::Daily, daily, limits.daily),
+            (QuotaPeriod::Monthly, monthly, limits.monthly),
+            (QuotaPeriod::Bandwidth, rate, limits.bandwidth),
+        ]
+        .into_iter()
+        .filter_map(|(period, used, limit)| {
+            limit.map(|limit| QuotaUsage {
+                period,
+                used,
+                limit,
+            })
+        });
+        let mut verdict = QuotaVerdict::Within;
+        for usage in usages {
+            // the bandwidth may be used up to its limit, the data not beyond
+            let exceeded = match usage.period {
+                QuotaPeriod::Bandwidth => usage.used > usage.limit,
+                QuotaPeriod::Daily | QuotaPeriod::Monthly => usage.used >= usage.limit,
+            };
+            if exceeded {
+                return QuotaVerdict::Exceeded(usage);
+            }
+            if verdict == QuotaVerdict::Within
+                && usage.used as f64 >= usage.limit as f64 * self.config.warn_ratio
+            {
+                verdict = QuotaVerdict::Warning(usage);
+            }
+        }
+        verdict
+    }
+
+    /// Time until the end of the period of a limit
+    pub fn retry_after(&self, period: QuotaPeriod) -> Duration {
+        self.retry_after_at(period, Utc::now())
+    }
+
+    fn retry_after_at(&self, period: QuotaPeriod, now: DateTime<Utc>) -> Duration {
+        let today = now.date_naive();
+        let end = match period {
+            QuotaPeriod::Bandwidth => return self.config.bandwidth_window,
+            QuotaPeriod::Daily => today.succ_opt(),
+            QuotaPeriod::Monthly => match today.month() {
+                12 => NaiveDate::from_ymd_opt(today.year() + 1, 1, 1),
+                m => NaiveDate::from_ymd_opt(today.year(), m + 1, 1),
+            },
+        };
+        end.and_then(|d| d.and_hms_opt(0, 0, 0))
+            .and_then(|t| (t.and_utc() - now).to_std().ok())
+            .unwrap_or_default()
+    }
+
+    /// Add the bytes of an ended transaction to the consumption of the user
+    pub fn record(&self, user: &QuotaUser, bytes: u64) {
+        self.record_at(user, bytes, Utc::now(), Instant::now())
+    }
+
+    fn record_at(&self, user: &QuotaUser, bytes: u64, now: DateTime<Utc>, instant: Instant) {
+        let limits = self.rules.limits(&user.key, &user.groups);
+        if limits.is_empty() {
+            return;
+        }
+        let (day, month) = periods(now);
+        let mut users = self.users.lock().unwrap();
+        if !users.contains_key(&user.key) && users.len() >= self.config.max_users {
+            // the users of the past months have nothing left to enforce
+            users.retain(|_, state| state.consumption.month == month);
+            if users.len() >= self.config.max_users {
+                self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        }
+        let state = users.entry(user.key.clone()).or_insert_with(|| UserState {
+            limits,
+            consumption: Consumption::default(),
+            window_start: instant,
+            window_bytes: 0,
+        });
+        state.limits = limits;
+        state.consumption.add(day, month, bytes);
+        if instant.duration_since(state.window_start) >= self.config.bandwidth_window {
+            state.window_start = instant;
+            state.window_bytes = 0;
+        }
+        state.window_bytes = state.window_bytes.saturating_add(bytes);
+        let consumption = state.consumption;
+        drop(users);
+        self.dirty.store(true, Ordering::Relaxed);
+        replication::publish("quota", &user.key, || {
+            serde_json::to_value(consumption).unwrap_or_default()
+        });
+    }
+
+    fn load(&self, path: &Path, now: DateTime<Utc>) -> anyhow::Result<usize> {
+        let text = std::fs::read_to_string(path)?;
+        let saved: SavedState = serde_json::from_str(&text)?;
+        let (_, month) = periods(now);
+        let instant = Instant::now();
+        let mut users = self.users.lock().unwrap();
+        for (key, consumption) in saved.users {
+            if consumption.month != month {
+                continue;
+            }
+            let limits = self.rules.limits(&key, &[]);
+            users.insert(
+                key,
+                UserState {
+                    limits,
+                    consumption,
+                    window_start: instant,
+                    window_bytes: 0,
+                },
+            );
+        }
+        Ok(users.len())
+    }
+
+    /// Save the consumption to the state file, if it changed
+    pub fn save(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.config.state_file else {
+            return Ok(());
+        };
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let saved = SavedState {
+            users: self
+                .users
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(key, state)| (key.clone(), state.consumption))
+                .collect(),
+        };
+        let text = serde_json::to_string(&saved)?;
+        // the state is replaced at once, never left half written
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, text)
+            .and_then(|_| std::fs::rename(&tmp, path))
+            .map_err(|e| {
+                self.dirty.store(true, Ordering::Relaxed);
+                anyhow!("failed to write {}: {e}", path.display())
+            })
+    }
+
+    /// Save the consumption periodically until the tracker is dropped
+    pub fn spawn(self: &Arc<Self>) -> JoinHandle<()> {
+        let tracker = Arc::downgrade(self);
+        let interval = self.config.save_interval;
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let Some(tracker) = tracker.upgrade() else {
+                    break;
+                };
+                if let Err(e) = tracker.save() {
+                    log::warn!("failed to save quota state: {e:?}");
+                }
+            }
+        })
+    }
+
+    /// Render the consumption in the Prometheus text format
+    pub fn render_prometheus(&self) -> String {
+        let (day, month) = periods(Utc::now());
+        let mut users: Vec<(String, QuotaLimits, u64, u64)> = self
+            .users
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(key, state)| {
+                let (daily, monthly) = state.consumption.current(day, month);
+                (
+                    key.replace('\\', "\\\\").replace('"', "\\\""),
+                    state.limits,
+                    daily,
+                    monthly,
+                )
+            })
+            .collect();
+        users.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut out = String::new();
+        let mut gauge = |name: &str, value: fn(&(String, QuotaLimits, u64, u64)) -> Option<u64>| {
+            let _ = writeln!(out, "# TYPE {name} gauge");
+            for user in &users {
+                if let Some(value) = value(user) {
+                    let _ = writeln!(out, "{name}{{user=\"{}\"}} {value}", user.0);
+                }
+            }
+        };
+        gauge("icap_quota_daily_bytes", |u| Some(u.2));
+        gauge("icap_quota_monthly_bytes", |u| Some(u.3));
+        gauge("icap_quota_daily_limit_bytes", |u| u.1.daily);
+        gauge("icap_quota_monthly_limit_bytes", |u| u.1.monthly);
+        gauge("icap_quota_bandwidth_limit_bytes", |u| u.1.bandwidth);
+        let mut counter = |name: &str, value: &AtomicU64| {
+            let _ = writeln!(out, "# TYPE {name} counter");
+            let _ = writeln!(out, "{name} {}", value.load(Ordering::Relaxed));
+        };
+        counter("icap_quota_exceeded_total", &self.stats.exceeded);
+        counter("icap_quota_warned_total", &self.stats.warned);
+        counter("icap_quota_dropped_total", &self.stats.dropped);
+        out
+    }
+}
+
+impl ReplicatedState for QuotaTracker {
+    fn snapshot(&self) -> Vec<(String, Value)> {
+        let users = self.users.lock().unwrap();
+        users
+            .iter()
+            .filter_map(|(key, state)| {
+                Some((key.clone(), serde_json::to_value(state.consumption).ok()?))
+            })
+            .collect()
+    }
+
+    /// Replace the daily and monthly consumption of the user, the bandwidth
+    /// window being only tracked locally
+    fn apply(&self, key: &str, value: Value) -> anyhow::Result<()> {
+        let consumption: Consumption = serde_json::from_value(value)?;
+        let mut users = self.users.lock().unwrap();
+        if !users.contains_key(key) && users.len() >= self.config.max_users {
+            self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+            return Err(anyhow!("too many users tracked"));
+        }
+        match users.get_mut(key) {
+            Some(state) => state.consumption = consumption,
+            None => {
+                let limits = self.rules.limits(key, &[]);
+                users.insert(
+                    key.to_string(),
+                    UserState {
+                        limits,
+                        consumption,
+                        window_start: Instant::now(),
+                        window_bytes: 0,
+                    },
+                );
+            }
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    fn tracker(config: QuotaConfig) -> QuotaTracker {
+        QuotaTracker::new(config, None)
+    }
+
+    fn user(key: &str, groups: &[&str]) -> QuotaUser {
+        QuotaUser {
+            key: key.to_string(),
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+        }
+    }
+
+    fn limits(daily: u64, monthly: u64) -> QuotaLimits {
+        QuotaLimits {
+            daily: Some(daily),
+            monthly: Some(monthly),
+            bandwidth: None,
+        }
+    }
+
+    #[test]
+    fn daily_monthly() {
+        let mut config = QuotaConfig::default();
+        config
+            .rules
+            .groups
+            .insert("staff".to_string(), limits(1000, 1500));
+        let tracker = tracker(config);
+        let alice = user("alice", &["staff"]);
+        let day = Utc.with_ymd_and_hms(2025, 3, 31, 12, 0, 0).unwrap();
+        let now = Instant::now();
+
+        assert_eq!(tracker.check_at(&alice, day, now), QuotaVerdict::Within);
+        tracker.record_at(&alice, 950, day, now);
+        let QuotaVerdict::Warning(usage) = tracker.check_at(&alice, day, now) else {
+            panic!("no warning close to the limit");
+        };
+        assert_eq!(usage.period, QuotaPeriod::Daily);
+        assert_eq!(usage.to_string(), "daily 950/1000 bytes");
+        tracker.record_at(&alice, 50, day, now);
+        assert!(matches!(
+            tracker.check_at(&alice, day, now),
+            QuotaVerdict::Exceeded(QuotaUsage {
+                period: QuotaPeriod::Daily,
+                ..
+            })
+        ));
+
+        // the next day is in a new month too
+        let next = day + chrono::Duration::days(1);
+        assert_eq!(tracker.check_at(&alice, next, now), QuotaVerdict::Within);
+        let previous = day - chrono::Duration::days(1);
+        tracker.record_at(&user("bob", &["staff"]), 800, previous, now);
+        tracker.record_at(&user("bob", &["staff"]), 800, day, now);
+        assert!(matches!(
+            tracker.check_at(&user("bob", &["staff"]), day, now),
+            QuotaVerdict::Exceeded(QuotaUsage {
+                period: QuotaPeriod::Monthly,
+                used: 1600,
+                ..
+            })
+        ));
+
+        // users without limits are not tracked
+        tracker.record_at(&user("carol", &[]), 10_000, day, now);
+        assert_eq!(
+            tracker.check_at(&user("carol", &[]), day, now),
+            QuotaVerdict::Within
+        );
+        assert!(!tracker.render_prometheus().contains("carol"));
+        assert_eq!(tracker.stats().exceeded.load(Ordering::Relaxed), 2);
+
+        let evening = Utc.with_ymd_and_hms(2025, 12, 31, 23, 0, 0).unwrap();
+        assert_eq!(
+            tracker.retry_after_at(QuotaPeriod::Daily, evening),
+            Duration::from_secs(3600)
+        );
+        assert_eq!(
+            tracker.retry_after_at(QuotaPeriod::Monthly, evening),
+            Duration::from_secs(3600)
+        );
+    }
+
+    #[test]
+    fn bandwidth() {
+        let mut config = QuotaConfig::default();
+        config.rules.default.bandwidth = Some(100);
+        config.bandwidth_window = Duration::from_secs(10);
+        let tracker = tracker(config);
+        let alice = user("10.0.0.1", &[]);
+        let day = Utc::now();
+        let start = Instant::now();
+
+        tracker.record_at(&alice, 1000, day, start);
+        assert!(matches!(
+            tracker.check_at(&alice, day, start),
+            QuotaVerdict::Warning(_)
+        ));
+        tracker.record_at(&alice, 1, day, start);
+        assert!(matches!(
+            tracker.check_at(&alice, day, start),
+            QuotaVerdict::Exceeded(_)
+        ));
+        let later = start + Duration::from_secs(10);
+        assert_eq!(tracker.check_at(&alice, day, later), QuotaVerdict::Within);
+    }
+
+    #[test]
+    fn persist() {
+        let path = std::env::temp_dir().join(format!("g3icap-quota-{}.json", std::process::id()));
+        let config = QuotaConfig {
+            rules: QuotaRules {
+                default: limits(1000, 5000),
+                ..Default::default()
+            },
+            state_file: Some(path.clone()),
+            ..Default::default()
+        };
+        let tracker = tracker(config.clone());
+        tracker.record(&user("alice", &[]), 700);
+        tracker.save().unwrap();
+
+        let restarted = QuotaTracker::new(config, None);
+        assert_eq!(restarted.check(&user("alice", &[])), QuotaVerdict::Within);
+        restarted.record(&user("alice", &[]), 300);
+        assert!(matches!(
+            restarted.check(&user("alice", &[])),
+            QuotaVerdict::Exceeded(_)
+        ));
+        assert!(
+            restarted
+                .render_prometheus()
+                .contains("icap_quota_daily_bytes{user=\"alice\"} 1000")
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn replicated() {
+        let config = QuotaConfig {
+            rules: QuotaRules {
+                default: limits(1000, 5000),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let active = tracker(config.clone());
+        let standby = tracker(config);
+        active.record(&user("alice", &[]), 1000);
+        for (key, value) in active.snapshot() {
+            standby.apply(&key, value).unwrap();
+        }
+        assert!(matches!(
+            standby.check(&user("alice", &[])),
+            QuotaVerdict::Exceeded(_)
+        ));
+        assert!(standby.apply("bob", Value::Null).is_err());
+    }
+}
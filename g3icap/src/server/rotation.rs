@@ -0,0 +1,166 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Services in and out of rotation
+//!
+//! A service taken out of rotation from the control channel answers all its
+//! transactions, OPTIONS included, with 503, so that the ICAP clients fail
+//! over to the other servers of the service until it is enabled again. The
+//! disabled services stay disabled when the config is reloaded.
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use arc_swap::{ArcSwap, ArcSwapOption};
+use serde::{Deserialize, Serialize};
+
+use crate::config::server::icap_server::IcapServerConfig;
+
+static GLOBAL_ROTATION: ArcSwapOption<ServiceRotation> = ArcSwapOption::const_empty();
+
+/// Install the services of the server
+pub fn set_global(rotation: Option<Arc<ServiceRotation>>) {
+    GLOBAL_ROTATION.store(rotation);
+}
+
+pub fn get_global() -> Option<Arc<ServiceRotation>> {
+    GLOBAL_ROTATION.load_full()
+}
+
+/// State of a service, as listed on the control channel
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceStatus {
+    pub name: String,
+    pub enabled: bool,
+    /// Bound pipeline, all the stages are run if not set
+    pub pipeline: Option<String>,
+    /// Scripted service, handled by its expression module alone
+    pub scripted: bool,
+}
+
+/// Services of a server and the ones out of rotation
+pub struct ServiceRotation {
+    /// Services named in the config, any service may be disabled if empty
+    services: BTreeSet<String>,
+    scripted: BTreeSet<String>,
+    disabled: ArcSwap<BTreeSet<String>>,
+}
+
+impl ServiceRotation {
+    /// Collect the services of the config, keeping the services of the
+    /// previous config out of rotation
+    pub fn new(config: &IcapServerConfig, previous: Option<&ServiceRotation>) -> Self {
+        let scripted: BTreeSet<String> = config
+            .scripted_services()
+            .services
+            .keys()
+            .cloned()
+            .collect();
+        let mut services = scripted.clone();
+        if let Some(registered) = config.services() {
+            services.extend(registered.names.iter().cloned());
+        }
+        services.extend(config.pipelines().services.keys().cloned());
+        let disabled = previous
+            .map(|p| BTreeSet::clone(&p.disabled.load()))
+            .unwrap_or_default();
+        ServiceRotation {
+            services,
+            scripted,
+            disabled: ArcSwap::from_pointee(disabled),
+        }
+    }
+
+    /// Check if the service, an ICAP URI path, is out of rotation
+    pub fn is_disabled(&self, service: &str) -> bool {
+        self.disabled.load().contains(service.trim_matches('/'))
+    }
+
+    /// Take the service in or out of rotation
+    ///
+    /// Returns false if the service already was.
+    pub fn set_enabled(&self, service: &str, enabled: bool) -> anyhow::Result<bool> {
+        let service = service.trim_matches('/');
+        if !self.is_known(service) {
+            return Err(anyhow!("no service named {service}"));
+        }
+        let previous = self.disabled.rcu(|disabled| {
+            let mut disabled = BTreeSet::clone(disabled);
+            if enabled {
+                disabled.remove(service);
+            } else {
+                disabled.insert(service.to_string());
+            }
+            disabled
+        });
+        Ok(previous.contains(service) == enabled)
+    }
+
+    /// State of the services of the config, then of the other disabled ones
+    pub fn list(&self) -> Vec<ServiceStatus> {
+        let disabled = self.disabled.load();
+        let pipelines = super::pipelines::get_global();
+        self.services
+            .iter()
+            .chain(disabled.iter().filter(|s| !self.services.contains(*s)))
+            .map(|name| ServiceStatus {
+                name: name.clone(),
+                enabled: !disabled.contains(name),
+                pipeline: pipelines
+                    .as_ref()
+                    .and_then(|p| p.pipeline(name))
+                    .map(|p| p.name().to_string()),
+                scripted: self.scripted.contains(name),
+            })
+            .collect()
+    }
+
+    /// Check if the service is named in the config, any service is known
+    /// without any named
+    pub fn is_known(&self, service: &str) -> bool {
+        let service = service.trim_matches('/');
+        !service.is_empty() && (self.services.is_empty() || self.services.contains(service))
+    }
+
+    /// Check if the service is a scripted one
+    pub fn is_scripted(&self, service: &str) -> bool {
+        self.scripted.contains(service.trim_matches('/'))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::server::services::ServicesConfig;
+    use g3_types::metrics::NodeName;
+
+    #[test]
+    fn rotation() {
+        let mut config = IcapServerConfig::new(NodeName::new_static("test"));
+        config.services = Some(ServicesConfig {
+            names: vec!["reqmod".to_string(), "av/respmod".to_string()],
+            default_service: String::new(),
+        });
+        let rotation = ServiceRotation::new(&config, None);
+        assert!(rotation.set_enabled("/av/respmod", false).unwrap());
+        assert!(!rotation.set_enabled("av/respmod", false).unwrap());
+        assert!(rotation.is_disabled("/av/respmod/"));
+        assert!(!rotation.is_disabled("reqmod"));
+        assert!(rotation.set_enabled("unknown", false).is_err());
+
+        let list = rotation.list();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].name, "av/respmod");
+        assert!(!list[0].enabled);
+        assert!(list[1].enabled);
+
+        // kept out of rotation by a reload
+        let reloaded = ServiceRotation::new(&config, Some(&rotation));
+        assert!(reloaded.is_disabled("av/respmod"));
+        assert!(reloaded.set_enabled("av/respmod", true).unwrap());
+        assert!(!reloaded.is_disabled("av/respmod"));
+    }
+}
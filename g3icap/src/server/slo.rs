@@ -0,0 +1,297 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Service level objective tracking
+//!
+//! The compliance window of each objective is split in slots, each counting
+//! the transactions and the ones answered under the latency, so that the
+//! oldest slot is simply reset when the window slides over it. The burn rate
+//! is the ratio of the missed transactions to the error budget: at 1 the
+//! budget is spent exactly at the end of the window. It is computed over the
+//! whole window and over its last twelfth, the short window reacting fast
+//! enough to raise the degradation ladder.
+
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use arc_swap::ArcSwapOption;
+use serde::Serialize;
+
+use crate::config::server::slo::{SloConfig, SloObjective};
+use crate::protocol::common::IcapMethod;
+
+/// Slots of a compliance window
+const SLOTS: u64 = 60;
+/// Slots of the short window of the burn rate
+const SHORT_SLOTS: u64 = 5;
+
+static GLOBAL_SLO: ArcSwapOption<SloTracker> = ArcSwapOption::const_empty();
+
+/// Install the objectives of the server, for the stats and the status
+pub fn set_global(slo: Option<Arc<SloTracker>>) {
+    GLOBAL_SLO.store(slo);
+}
+
+pub fn get_global() -> Option<Arc<SloTracker>> {
+    GLOBAL_SLO.load_full()
+}
+
+#[derive(Clone, Copy, Default)]
+struct Slot {
+    index: u64,
+    total: u64,
+    good: u64,
+}
+
+struct ObjectiveState {
+    objective: SloObjective,
+    slots: Mutex<[Slot; SLOTS as usize]>,
+}
+
+impl ObjectiveState {
+    /// Transactions and good ones in the last slots up to the current one
+    fn counts(&self, current: u64, slots: u64) -> (u64, u64) {
+        let oldest = (current + 1).saturating_sub(slots);
+        let all = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        all.iter()
+            .filter(|s| s.index >= oldest && s.index <= current)
+            .fold((0, 0), |(total, good), s| (total + s.total, good + s.good))
+    }
+
+    fn burn_rate(&self, total: u64, good: u64) -> f64 {
+        if total == 0 {
+            return 0.0;
+        }
+        let missed = (total - good) as f64 / total as f64;
+        missed / self.objective.error_budget()
+    }
+}
+
+/// Compliance of an objective over its window
+#[derive(Debug, Clone, Serialize)]
+pub struct SloReport {
+    pub name: String,
+    pub service: String,
+    pub method: Option<String>,
+    pub latency_ms: u64,
+    pub target: f64,
+    /// Transactions in the window
+    pub total: u64,
+    /// Transactions answered under the latency in the window
+    pub good: u64,
+    /// Ratio of the good transactions, 1 without any transaction
+    pub compliance: f64,
+    /// Ratio of the error budget left, negative once overspent
+    pub budget_remaining: f64,
+    pub burn_rate: f64,
+    pub short_burn_rate: f64,
+}
+
+pub struct SloTracker {
+    epoch: Instant,
+    slot: Duration,
+    degrade_burn_rate: Option<f64>,
+    objectives: Vec<ObjectiveState>,
+}
+
+impl SloTracker {
+    pub fn new(config: SloConfig) -> Self {
+        SloTracker {
+            epoch: Instant::now(),
+            slot: (config.window / SLOTS as u32).max(Duration::from_millis(1)),
+            degrade_burn_rate: config.degrade_burn_rate,
+            objectives: config
+                .objectives
+                .into_iter()
+                .map(|objective| ObjectiveState {
+                    objective,
+                    slots: Mutex::new([Slot::default(); SLOTS as usize]),
+                })
+                .collect(),
+        }
+    }
+
+    fn slot_index(&self, now: Instant) -> u64 {
+        (now.saturating_duration_since(self.epoch).as_nanos() / self.slot.as_nanos()) as u64
+    }
+
+    /// Count a transaction of the service, `ok` if it was answered
+    pub fn record(&self, service: &str, method: &IcapMethod, elapsed: Duration, ok: bool) {
+        self.record_at(Instant::now(), service, method, elapsed, ok);
+    }
+
+    fn record_at(
+        &self,
+        now: Instant,
+        service: &str,
+        method: &IcapMethod,
+        elapsed: Duration,
+        ok: bool,
+    ) {
+        let index = self.slot_index(now);
+        for state in &self.objectives {
+            if !state.objective.matches(service, method) {
+                continue;
+            }
+            let mut slots = state.slots.lock().unwrap_or_else(|e| e.into_inner());
+            let slot = &mut slots[(index % SLOTS) as usize];
+            if slot.index != index {
+                *slot = Slot {
+                    index,
+                    ..Default::default()
+                };
+            }
+            slot.total += 1;
+            if ok && elapsed <= state.objective.latency {
+                slot.good += 1;
+            }
+        }
+    }
+
+    /// Compliance of all the objectives
+    pub fn reports(&self) -> Vec<SloReport> {
+        self.reports_at(Instant::now())
+    }
+
+    fn reports_at(&self, now: Instant) -> Vec<SloReport> {
+        let current = self.slot_index(now);
+        self.objectives
+            .iter()
+            .map(|state| {
+                let objective = &state.objective;
+                let (total, good) = state.counts(current, SLOTS);
+                let (short_total, short_good) = state.counts(current, SHORT_SLOTS);
+                let burn_rate = state.burn_rate(total, good);
+                SloReport {
+                    name: objective.name.clone(),
+                    service: objective.service.clone(),
+                    method: objective.method.as_ref().map(|m| m.to_string()),
+                    latency_ms: objective.latency.as_millis() as u64,
+                    target: objective.target,
+                    total,
+                    good,
+                    compliance: if total == 0 {
+                        1.0
+                    } else {
+                        good as f64 / total as f64
+                    },
+                    budget_remaining: 1.0 - burn_rate,
+                    burn_rate,
+                    short_burn_rate: state.burn_rate(short_total, short_good),
+                }
+            })
+            .collect()
+    }
+
+    /// Ratio of the fastest short window burn rate to the one the degradation
+    /// ladder is raised at, if set
+    pub fn pressure(&self) -> Option<f64> {
+        let threshold = self.degrade_burn_rate?;
+        let fastest = self
+            .reports()
+            .iter()
+            .map(|r| r.short_burn_rate)
+            .fold(0.0, f64::max);
+        Some(fastest / threshold)
+    }
+
+    /// Render the compliance in the Prometheus text format
+    pub fn render_prometheus(&self) -> String {
+        let reports = self.reports();
+        let mut out = String::new();
+        let mut gauge = |name: &str, value: fn(&SloReport) -> f64| {
+            let _ = writeln!(out, "# TYPE {name} gauge");
+            for r in &reports {
+                let _ = writeln!(out, "{name}{{slo=\"{}\"}} {}", r.name, value(r));
+            }
+        };
+        gauge("icap_slo_target", |r| r.target);
+        gauge("icap_slo_transactions", |r| r.total as f64);
+        gauge("icap_slo_compliance", |r| r.compliance);
+        gauge("icap_slo_budget_remaining", |r| r.budget_remaining);
+        gauge("icap_slo_burn_rate", |r| r.burn_rate);
+        gauge("icap_slo_short_burn_rate", |r| r.short_burn_rate);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker() -> SloTracker {
+        SloTracker::new(SloConfig {
+            window: Duration::from_secs(600),
+            objectives: vec![SloObjective {
+                name: "reqmod".to_string(),
+                service: "reqmod".to_string(),
+                method: Some(IcapMethod::Reqmod),
+                latency: Duration::from_millis(30),
+                target: 0.9,
+            }],
+            degrade_burn_rate: Some(2.0),
+        })
+    }
+
+    #[test]
+    fn compliance() {
+        let slo = tracker();
+        let fast = Duration::from_millis(10);
+        let slow = Duration::from_millis(50);
+        let now = slo.epoch;
+        for _ in 0..18 {
+            slo.record_at(now, "/reqmod", &IcapMethod::Reqmod, fast, true);
+        }
+        slo.record_at(now, "/reqmod", &IcapMethod::Reqmod, slow, true);
+        slo.record_at(now, "/reqmod", &IcapMethod::Reqmod, fast, false);
+        // not counted
+        slo.record_at(now, "/reqmod", &IcapMethod::Options, slow, true);
+        slo.record_at(now, "/respmod", &IcapMethod::Reqmod, slow, true);
+
+        let r = &slo.reports_at(now)[0];
+        assert_eq!((r.total, r.good), (20, 18));
+        assert!((r.compliance - 0.9).abs() < 1e-9);
+        assert!((r.burn_rate - 1.0).abs() < 1e-9);
+        assert!(r.budget_remaining.abs() < 1e-9);
+
+        // out of the short window, still in the long one
+        let later = now + Duration::from_secs(120);
+        let r = &slo.reports_at(later)[0];
+        assert_eq!(r.total, 20);
+        assert_eq!(r.short_burn_rate, 0.0);
+        // out of the window
+        let r = &slo.reports_at(now + Duration::from_secs(600))[0];
+        assert_eq!(r.total, 0);
+        assert_eq!(r.compliance, 1.0);
+    }
+
+    #[test]
+    fn fast_burn() {
+        let slo = tracker();
+        for _ in 0..4 {
+            slo.record(
+                "reqmod",
+                &IcapMethod::Reqmod,
+                Duration::from_millis(10),
+                true,
+            );
+        }
+        assert_eq!(slo.pressure(), Some(0.0));
+        slo.record(
+            "reqmod",
+            &IcapMethod::Reqmod,
+            Duration::from_millis(100),
+            true,
+        );
+        // 20% missed for a 10% budget, at the burn rate of 2
+        let pressure = slo.pressure().unwrap();
+        assert!((pressure - 1.0).abs() < 1e-9, "{pressure}");
+
+        let text = slo.render_prometheus();
+        assert!(text.contains("icap_slo_transactions{slo=\"reqmod\"} 5\n"));
+        assert!(text.contains("# TYPE icap_slo_burn_rate gauge\n"));
+    }
+}
@@ -0,0 +1,113 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! TLS of the ICAPS listener
+//!
+//! The acceptor only offers the protocol versions and cipher suites of the
+//! TLS policy that the installed crypto provider supports. A policy that
+//! leaves a permitted protocol version without a cipher suite, or the FIPS
+//! profile on a provider that is not FIPS validated, is an error.
+
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, anyhow};
+use rustls::crypto::CryptoProvider;
+use rustls::{ServerConfig, SupportedProtocolVersion};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsAcceptor;
+
+use crate::config::server::icap_server::IcapServerConfig;
+use crate::config::server::tls_policy::{ResolvedTlsPolicy, TlsPolicyConfig, TlsProtocol};
+use crate::server::connection::IcapStream;
+
+/// Resolve the TLS policy against the installed crypto provider
+pub fn resolve_policy(
+    policy: &TlsPolicyConfig,
+) -> anyhow::Result<(ResolvedTlsPolicy, Arc<CryptoProvider>)> {
+    let default = CryptoProvider::get_default()
+        .ok_or_else(|| anyhow!("no rustls crypto provider installed"))?;
+    let available = default
+        .cipher_suites
+        .iter()
+        .map(|s| format!("{:?}", s.suite()))
+        .collect::<Vec<_>>();
+    let available = available.iter().map(String::as_str).collect::<Vec<_>>();
+    let resolved = policy.resolve(&available, default.fips())?;
+
+    // keep the order of preference of the policy
+    let cipher_suites = resolved
+        .cipher_suites
+        .iter()
+        .filter_map(|name| {
+            default
+                .cipher_suites
+                .iter()
+                .find(|s| format!("{:?}", s.suite()) == *name)
+                .copied()
+        })
+        .collect();
+    let provider = CryptoProvider {
+        cipher_suites,
+        ..CryptoProvider::clone(default)
+    };
+    Ok((resolved, Arc::new(provider)))
+}
+
+/// Build the TLS acceptor of the listener
+pub fn build_acceptor(config: &IcapServerConfig) -> anyhow::Result<TlsAcceptor> {
+    let (Some(cert), Some(key)) = (&config.tls_cert, &config.tls_key) else {
+        return Err(anyhow!("tls certificate and key are required"));
+    };
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|iter| iter.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| anyhow!("invalid tls certificate {cert}: {e:?}"))?;
+    let key = PrivateKeyDer::from_pem_file(key)
+        .map_err(|e| anyhow!("invalid tls private key {key}: {e:?}"))?;
+
+    let policy = config.tls_policy();
+    let (resolved, provider) = resolve_policy(policy)
+        .context(format!("invalid tls policy {}", policy.profile.as_str()))?;
+    let versions = resolved
+        .protocols
+        .iter()
+        .map(|p| match p {
+            TlsProtocol::Tls12 => &rustls::version::TLS12,
+            TlsProtocol::Tls13 => &rustls::version::TLS13,
+        })
+        .collect::<Vec<&'static SupportedProtocolVersion>>();
+    let server_config = ServerConfig::builder_with_provider(provider)
+        .with_protocol_versions(&versions)
+        .context("unsupported tls protocol versions")?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("invalid tls certificate pair")?;
+    if policy.profile.requires_fips() && !server_config.fips() {
+        return Err(anyhow!("tls server config is not in FIPS mode"));
+    }
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+/// Complete the TLS handshake of a client, or pass a plain stream through
+pub async fn accept(
+    acceptor: Option<&TlsAcceptor>,
+    stream: TcpStream,
+    timeout: Duration,
+) -> io::Result<Box<dyn IcapStream>> {
+    let Some(acceptor) = acceptor else {
+        return Ok(Box::new(stream));
+    };
+    match tokio::time::timeout(timeout, acceptor.accept(stream)).await {
+        Ok(Ok(stream)) => Ok(Box::new(stream)),
+        Ok(Err(e)) => Err(e),
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "tls handshake timed out",
+        )),
+    }
+}
@@ -0,0 +1,155 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Unix domain socket listener
+//!
+//! The socket file is created with the configured mode and owner before the
+//! first connection can be accepted. On upgrade the new daemon replaces the
+//! socket file, the old daemon keeps serving the connections it accepted
+//! until they are drained.
+
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+
+use tokio::net::UnixListener;
+
+use crate::config::server::unix_listen::UnixListenConfig;
+
+/// Bind the unix socket of the listener
+pub fn bind(config: &UnixListenConfig) -> io::Result<UnixListener> {
+    remove_stale(&config.path)?;
+    let listener = UnixListener::bind(&config.path)?;
+    if let Err(e) = set_access(config) {
+        let _ = fs::remove_file(&config.path);
+        return Err(e);
+    }
+    Ok(listener)
+}
+
+/// Remove a socket file left by a previous process
+fn remove_stale(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => fs::remove_file(path),
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        )),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+fn set_access(config: &UnixListenConfig) -> io::Result<()> {
+    if let Some(mode) = config.mode {
+        fs::set_permissions(&config.path, fs::Permissions::from_mode(mode))?;
+    }
+    let uid = config.owner.as_deref().map(lookup_user).transpose()?;
+    let gid = config.group.as_deref().map(lookup_group).transpose()?;
+    if uid.is_some() || gid.is_some() {
+        std::os::unix::fs::chown(&config.path, uid, gid)?;
+    }
+    Ok(())
+}
+
+fn invalid_name(name: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("invalid name {name}"))
+}
+
+/// Get the uid of a user name, or of a numeric uid
+fn lookup_user(name: &str) -> io::Result<u32> {
+    if let Ok(uid) = name.parse() {
+        return Ok(uid);
+    }
+    let c_name = CString::new(name).map_err(|_| invalid_name(name))?;
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut pwd = unsafe { std::mem::zeroed::<libc::passwd>() };
+    let mut result = std::ptr::null_mut();
+    let r = unsafe {
+        libc::getpwnam_r(
+            c_name.as_ptr(),
+            &mut pwd,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if r != 0 {
+        return Err(io::Error::from_raw_os_error(r));
+    }
+    if result.is_null() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no such user {name}"),
+        ));
+    }
+    Ok(pwd.pw_uid)
+}
+
+/// Get the gid of a group name, or of a numeric gid
+fn lookup_group(name: &str) -> io::Result<u32> {
+    if let Ok(gid) = name.parse() {
+        return Ok(gid);
+    }
+    let c_name = CString::new(name).map_err(|_| invalid_name(name))?;
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut grp = unsafe { std::mem::zeroed::<libc::group>() };
+    let mut result = std::ptr::null_mut();
+    let r = unsafe {
+        libc::getgrnam_r(
+            c_name.as_ptr(),
+            &mut grp,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if r != 0 {
+        return Err(io::Error::from_raw_os_error(r));
+    }
+    if result.is_null() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no such group {name}"),
+        ));
+    }
+    Ok(grp.gr_gid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn bind_with_access() {
+        let path = std::env::temp_dir().join(format!("g3icap-test-{}.sock", std::process::id()));
+        let config = UnixListenConfig {
+            path: path.clone(),
+            mode: Some(0o660),
+            owner: Some(unsafe { libc::getuid() }.to_string()),
+            group: Some(unsafe { libc::getgid() }.to_string()),
+        };
+        // a stale socket is replaced
+        let listener = bind(&config).unwrap();
+        drop(listener);
+        let _listener = bind(&config).unwrap();
+
+        let meta = fs::metadata(&path).unwrap();
+        assert!(meta.file_type().is_socket());
+        assert_eq!(meta.permissions().mode() & 0o777, 0o660);
+        tokio::net::UnixStream::connect(&path).await.unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // a regular file is not replaced
+        fs::write(&path, b"").unwrap();
+        assert!(bind(&config).is_err());
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(lookup_user("root").unwrap(), 0);
+        assert!(lookup_user("no-such-user-g3icap").is_err());
+    }
+}
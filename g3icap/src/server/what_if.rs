@@ -0,0 +1,238 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! What-if evaluation of synthetic requests
+//!
+//! A request described by its user, groups, URL, content type, size and
+//! time is evaluated like a REQMOD of that client, without counting or
+//! recording anything, to tell why a request is, or would be, blocked. The
+//! quota of the user comes first, as for the real transactions, then each
+//! rule set of the content filter reports its first matching rule, the
+//! first one deciding the verdict, along with the policy rule it is compiled
+//! from. The candidate rules are evaluated instead of the active ones if
+//! their promotion is scheduled by the time of the request.
+
+use anyhow::anyhow;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use http::{HeaderMap, HeaderValue, Uri, Version};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::identity::{HEADER_CLIENT_GROUPS, HEADER_CLIENT_USERNAME};
+use crate::config::server::quota::QuotaAction;
+use crate::modules::ExplainedMatch;
+use crate::modules::shadow::ShadowTarget;
+use crate::protocol::common::{IcapMethod, IcapRequest};
+use crate::server::modules::ServerModules;
+use crate::server::policy_schedule::{self, ScheduleState};
+use crate::server::quota::{self, QuotaTracker, QuotaVerdict};
+
+/// Description of a synthetic request
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WhatIfRequest {
+    pub user: Option<String>,
+    pub groups: Vec<String>,
+    pub url: String,
+    pub content_type: Option<String>,
+    /// Body size in bytes
+    pub size: Option<u64>,
+    /// Unix time of the request, now if not set
+    pub time: Option<u64>,
+}
+
+impl WhatIfRequest {
+    /// Build the REQMOD request of the description, without a body
+    pub fn to_request(&self) -> anyhow::Result<IcapRequest> {
+        let uri: Uri = self
+            .url
+            .parse()
+            .map_err(|e| anyhow!("invalid url {}: {e}", self.url))?;
+        let mut headers = HeaderMap::new();
+        let mut insert = |name: &'static str, value: &str| -> anyhow::Result<()> {
+            let value =
+                HeaderValue::from_str(value).map_err(|_| anyhow!("invalid {name} {value:?}"))?;
+            headers.insert(name, value);
+            Ok(())
+        };
+        if let Some(host) = uri.host() {
+            insert("host", host)?;
+        }
+        if let Some(user) = &self.user {
+            insert(HEADER_CLIENT_USERNAME, user)?;
+        }
+        if !self.groups.is_empty() {
+            insert(HEADER_CLIENT_GROUPS, &self.groups.join(","))?;
+        }
+        if let Some(content_type) = &self.content_type {
+            insert("content-type", content_type)?;
+        }
+        if let Some(size) = self.size {
+            insert("content-length", &size.to_string())?;
+        }
+        Ok(IcapRequest {
+            method: IcapMethod::Reqmod,
+            uri,
+            version: Version::HTTP_11,
+            headers,
+            body: Bytes::new(),
+            encapsulated: None,
+        })
+    }
+
+    fn time(&self) -> anyhow::Result<DateTime<Utc>> {
+        match self.time {
+            Some(time) => i64::try_from(time)
+                .ok()
+                .and_then(|t| DateTime::from_timestamp(t, 0))
+                .ok_or_else(|| anyhow!("invalid time {time}")),
+            None => Ok(Utc::now()),
+        }
+    }
+}
+
+/// Final verdict of a what-if evaluation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WhatIfVerdict {
+    Allow,
+    Block,
+}
+
+impl WhatIfVerdict {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WhatIfVerdict::Allow => "allow",
+            WhatIfVerdict::Block => "block",
+        }
+    }
+}
+
+/// Result of a what-if evaluation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WhatIfReport {
+    pub verdict: WhatIfVerdict,
+    /// Why the request is blocked
+    pub reason: Option<String>,
+    /// Matched rules in evaluation order
+    pub matches: Vec<ExplainedMatch>,
+    /// The candidate rules were evaluated, being promoted by then
+    pub candidate: bool,
+    /// Quota limit the user is over or close to
+    pub quota: Option<String>,
+}
+
+/// Check if the candidate rules of the target are active at the unix time,
+/// by a promotion scheduled before it
+fn promoted_at(target: ShadowTarget, time: u64) -> bool {
+    policy_schedule::list().iter().any(|s| {
+        s.rule_set == target.as_str()
+            && s.state == ScheduleState::Pending
+            && s.activate_at <= time
+            && s.revert_at.is_none_or(|revert_at| time < revert_at)
+    })
+}
+
+/// Evaluate the synthetic request against the modules of the server
+pub async fn evaluate(
+    modules: &ServerModules,
+    request: &WhatIfRequest,
+) -> anyhow::Result<WhatIfReport> {
+    let icap_request = request.to_request()?;
+    let now = request.time()?;
+
+    let target = ShadowTarget::ContentFilter;
+    let candidate = modules
+        .shadow(target)
+        .filter(|_| promoted_at(target, now.timestamp() as u64));
+    let module = match &candidate {
+        Some(shadow) => Some(shadow.module().clone()),
+        None => modules.content_filter(),
+    };
+    let mut matches = match module {
+        Some(module) => module.explain(&icap_request).await?,
+        None => Vec::new(),
+    };
+    // the candidate rules are not compiled from the policies
+    if let Some(policy) = modules.policy().filter(|_| candidate.is_none()) {
+        for m in &mut matches {
+            if let Some(origin) = policy.origins.get(&m.rule_id) {
+                m.policy = Some(origin.policy.clone());
+                m.rule = Some(origin.rule.clone());
+            }
+        }
+    }
+
+    let mut report = WhatIfReport {
+        verdict: WhatIfVerdict::Allow,
+        reason: None,
+        candidate: candidate.is_some(),
+        quota: None,
+        matches,
+    };
+    if let Some(m) = report.matches.first() {
+        report.verdict = WhatIfVerdict::Block;
+        report.reason = Some(m.reason.clone());
+    }
+
+    let tracker = quota::get_global();
+    let user = QuotaTracker::user(&icap_request.headers);
+    if let (Some(tracker), Some(user)) = (tracker, user) {
+        match tracker.peek(&user, now) {
+            QuotaVerdict::Within => {}
+            QuotaVerdict::Warning(usage) => report.quota = Some(format!("warning {usage}")),
+            QuotaVerdict::Exceeded(usage) => {
+                report.quota = Some(format!("exceeded {usage}"));
+                // users over their quota are blocked before any module
+                if tracker.action() == QuotaAction::Block {
+                    report.verdict = WhatIfVerdict::Block;
+                    report.reason = Some(format!("Quota exceeded: {usage}"));
+                }
+            }
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_request() {
+        let request: WhatIfRequest = serde_json::from_str(
+            r#"{"user": "alice", "groups": ["staff", "sales"], "url": "http://casino.example/play",
+                "content_type": "application/zip", "size": 4096}"#,
+        )
+        .unwrap();
+        let icap_request = request.to_request().unwrap();
+        assert_eq!(icap_request.method, IcapMethod::Reqmod);
+        let header = |name: &str| icap_request.headers.get(name).unwrap().to_str().unwrap();
+        assert_eq!(header("host"), "casino.example");
+        assert_eq!(header(HEADER_CLIENT_USERNAME), "alice");
+        assert_eq!(header(HEADER_CLIENT_GROUPS), "staff,sales");
+        assert_eq!(header("content-type"), "application/zip");
+        assert_eq!(header("content-length"), "4096");
+
+        let request = WhatIfRequest {
+            url: "http://bad host/".to_string(),
+            ..Default::default()
+        };
+        assert!(request.to_request().is_err());
+    }
+
+    #[tokio::test]
+    async fn no_module() {
+        let request = WhatIfRequest {
+            url: "http://example.com/".to_string(),
+            time: Some(1_700_000_000),
+            ..Default::default()
+        };
+        let report = evaluate(&ServerModules::default(), &request).await.unwrap();
+        assert_eq!(report.verdict, WhatIfVerdict::Allow);
+        assert!(report.matches.is_empty());
+        assert!(!report.candidate);
+    }
+}
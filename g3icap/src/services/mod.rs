@@ -13,10 +13,34 @@ use std::sync::{Arc, RwLock};
 use std::time::{Duration, Instant};
 
 use anyhow::Result;
+use arc_swap::ArcSwapOption;
+use g3_statsd_client::{StatsdClient, StatsdTagGroup};
 // use async_trait::async_trait;
 
 use crate::protocol::common::{IcapMethod, IcapRequest, IcapResponse};
-use crate::modules::{IcapModule, ModuleError};
+use crate::modules::{IcapModule, ModuleConfig, ModuleError, ModuleRegistry};
+use crate::transaction::TransactionCtx;
+
+const METRIC_NAME_SERVICE_REQUESTS: &str = "icap.service.requests";
+const METRIC_NAME_SERVICE_ERRORS: &str = "icap.service.errors";
+const METRIC_NAME_SERVICE_ERROR_RATE: &str = "icap.service.error_rate";
+const METRIC_NAME_SERVICE_ACTIVE: &str = "icap.service.active";
+const METRIC_NAME_SERVICE_HEALTHY: &str = "icap.service.healthy";
+
+const TAG_KEY_SERVICE: &str = "service";
+const TAG_KEY_MODULE: &str = "module";
+
+static GLOBAL_SERVICES: ArcSwapOption<ServiceManager> = ArcSwapOption::const_empty();
+
+/// Install the service manager of the process, for its metrics
+pub fn set_global(services: Option<Arc<ServiceManager>>) {
+    GLOBAL_SERVICES.store(services);
+}
+
+/// Get the service manager currently in use
+pub fn get_global() -> Option<Arc<ServiceManager>> {
+    GLOBAL_SERVICES.load_full()
+}
 
 /// Service configuration
 #[derive(Debug, Clone)]
@@ -82,13 +106,14 @@ pub struct ServiceMetrics {
 }
 
 /// Service instance
+#[derive(Clone)]
 pub struct ServiceInstance {
     /// Service ID
     pub id: String,
     /// Service configuration
     pub config: ServiceConfig,
-    /// Service module
-    pub module: Box<dyn IcapModule>,
+    /// Name of the service module in the module registry
+    pub module: String,
     /// Service metrics
     pub metrics: ServiceMetrics,
     /// Last health check
@@ -97,10 +122,27 @@ pub struct ServiceInstance {
     pub connection_count: usize,
 }
 
+/// A request in the connections of its service, released when dropped
+struct ServiceConnection<'a> {
+    services: &'a RwLock<HashMap<String, ServiceInstance>>,
+    service: &'a str,
+}
+
+impl Drop for ServiceConnection<'_> {
+    fn drop(&mut self) {
+        let mut services = self.services.write().unwrap_or_else(|e| e.into_inner());
+        if let Some(service) = services.get_mut(self.service) {
+            service.connection_count = service.connection_count.saturating_sub(1);
+            service.metrics.active_connections = service.connection_count;
+        }
+    }
+}
+
 /// Service manager
 #[derive(Clone)]
 pub struct ServiceManager {
     services: Arc<RwLock<HashMap<String, ServiceInstance>>>,
+    registry: ModuleRegistry,
     health_checker: HealthChecker,
     #[allow(dead_code)]
     load_balancer: LoadBalancer,
@@ -109,24 +151,51 @@ pub struct ServiceManager {
 impl ServiceManager {
     /// Create new service manager
     pub fn new() -> Self {
+        Self::with_registry(ModuleRegistry::new(ModuleConfig {
+            name: "services".to_string(),
+            path: std::path::PathBuf::new(),
+            version: "1.0.0".to_string(),
+            config: serde_json::Value::Object(serde_json::Map::new()),
+            dependencies: Vec::new(),
+            load_timeout: Duration::from_secs(5),
+            max_memory: 1024 * 1024,
+            sandbox: true,
+            metrics: crate::modules::metrics::MetricsRegistry::global(),
+        }))
+    }
+
+    /// Create new service manager resolving the service modules in the registry
+    ///
+    /// Each request uses the module currently registered for its service, so
+    /// a module replaced in the registry is used by the following requests.
+    pub fn with_registry(registry: ModuleRegistry) -> Self {
         Self {
             services: Arc::new(RwLock::new(HashMap::new())),
+            registry,
             health_checker: HealthChecker::new(),
             load_balancer: LoadBalancer::new(),
         }
     }
     
+    /// Get the module registry
+    pub fn registry(&self) -> &ModuleRegistry {
+        &self.registry
+    }
+    
     /// Register a service
+    ///
+    /// The module is registered in the module registry under the service name.
     pub async fn register_service(
         &self,
         config: ServiceConfig,
         module: Box<dyn IcapModule>,
     ) -> Result<(), ServiceError> {
+        self.registry.register_module(&config.name, module);
         let service_id = format!("{}-{}", config.name, uuid::Uuid::new_v4());
         let instance = ServiceInstance {
             id: service_id,
             config: config.clone(),
-            module,
+            module: config.name.clone(),
             metrics: ServiceMetrics::default(),
             last_health_check: None,
             connection_count: 0,
@@ -145,14 +214,13 @@ impl ServiceManager {
     
     /// Unregister a service
     pub async fn unregister_service(&self, name: &str) -> Result<(), ServiceError> {
-        let mut services = self.services.write().unwrap();
-        if let Some(_instance) = services.remove(name) {
+        let removed = self.services.write().unwrap().remove(name);
+        if let Some(instance) = removed {
             // Stop health checking
             self.health_checker.stop_health_check(name).await;
             
-            // Cleanup module
-            // Note: This is a simplified cleanup - in practice, you'd want to ensure
-            // all pending requests are completed before cleanup
+            // Requests still holding the module finish before it is cleaned up
+            let _ = self.registry.unload_module(&instance.module);
             Ok(())
         } else {
             Err(ServiceError::ServiceNotFound(name.to_string()))
@@ -165,37 +233,57 @@ impl ServiceManager {
         services.get(name).cloned()
     }
     
+    /// Get the service serving the path, an ICAP URI path
+    pub fn get_service_by_path(&self, path: &str) -> Option<ServiceInstance> {
+        let services = self.services.read().unwrap();
+        services.values().find(|s| s.config.path == path).cloned()
+    }
+
     /// List all services
     pub fn list_services(&self) -> Vec<String> {
         let services = self.services.read().unwrap();
         services.keys().cloned().collect()
     }
     
-    /// Handle ICAP request
-    pub async fn handle_request(&self, request: &IcapRequest) -> Result<IcapResponse, ServiceError> {
+    /// Handle ICAP request, until the transaction is cancelled
+    pub async fn handle_request(&self, request: &IcapRequest, ctx: &TransactionCtx) -> Result<IcapResponse, ServiceError> {
         // Find appropriate service based on path
         let service_name = self.find_service_by_path(&request.uri.path())?;
         
-        // Get service instance
-        let services = self.services.read().unwrap();
-        let service = services.get(&service_name)
-            .ok_or_else(|| ServiceError::ServiceNotFound(service_name.clone()))?;
-        
-        // Check if service supports the method
-        if !service.config.methods.contains(&request.method) {
-            return Err(ServiceError::MethodNotSupported(request.method.to_string()));
-        }
-        
-        // Check connection limits
-        if service.connection_count >= service.config.max_connections {
-            return Err(ServiceError::TooManyConnections);
-        }
+        // Get the module of the service instance
+        let module = {
+            let mut services = self.services.write().unwrap();
+            let service = services.get_mut(&service_name)
+                .ok_or_else(|| ServiceError::ServiceNotFound(service_name.clone()))?;
+            
+            // Check if service supports the method
+            if !service.config.methods.contains(&request.method) {
+                return Err(ServiceError::MethodNotSupported(request.method.to_string()));
+            }
+            
+            // Check connection limits
+            if service.connection_count >= service.config.max_connections {
+                return Err(ServiceError::TooManyConnections);
+            }
+            
+            let module = self.registry.get_module(&service.module).ok_or_else(|| {
+                ServiceError::ModuleError(ModuleError::NotFound(service.module.clone()))
+            })?;
+            service.connection_count += 1;
+            service.metrics.active_connections = service.connection_count;
+            module
+        };
+        // counted until the request is done, or dropped with its transaction
+        let _connection = ServiceConnection {
+            services: &self.services,
+            service: &service_name,
+        };
         
         // Handle request based on method
         let response = match request.method {
-            IcapMethod::Reqmod => service.module.handle_reqmod(request).await,
-            IcapMethod::Respmod => service.module.handle_respmod(request).await,
-            IcapMethod::Options => service.module.handle_options(request).await,
+            IcapMethod::Reqmod => module.handle_reqmod(request, ctx).await,
+            IcapMethod::Respmod => module.handle_respmod(request, ctx).await,
+            IcapMethod::Options => module.handle_options(request).await,
         };
         
         // Update metrics
@@ -221,6 +309,47 @@ impl ServiceManager {
         self.health_checker.is_healthy(name)
     }
     
+    /// Emit the metrics of each service, tagged with its name and module, then
+    /// the metrics of the modules of the registry
+    pub fn emit_stats(&self, client: &mut StatsdClient, tags: &StatsdTagGroup) {
+        let services: Vec<(String, String, ServiceMetrics)> = {
+            let services = self.services.read().unwrap();
+            services
+                .iter()
+                .map(|(name, s)| (name.clone(), s.module.clone(), s.metrics.clone()))
+                .collect()
+        };
+        for (name, module, metrics) in services {
+            let healthy = self.health_checker.is_healthy(&name)
+                && self
+                    .registry
+                    .get_module(&module)
+                    .is_some_and(|m| m.is_healthy());
+            let mut tags = tags.clone();
+            tags.add_tag(TAG_KEY_SERVICE, &name);
+            tags.add_tag(TAG_KEY_MODULE, &module);
+            client
+                .count_with_tags(METRIC_NAME_SERVICE_REQUESTS, metrics.requests_total, &tags)
+                .send();
+            client
+                .count_with_tags(METRIC_NAME_SERVICE_ERRORS, metrics.connection_errors, &tags)
+                .send();
+            client
+                .gauge_float_with_tags(METRIC_NAME_SERVICE_ERROR_RATE, metrics.error_rate, &tags)
+                .send();
+            client
+                .gauge_with_tags(METRIC_NAME_SERVICE_ACTIVE, metrics.active_connections, &tags)
+                .send();
+            client
+                .gauge_with_tags(METRIC_NAME_SERVICE_HEALTHY, u8::from(healthy), &tags)
+                .send();
+        }
+        // the modules of the process registry are emitted on their own
+        if !self.registry.shares_modules(&ModuleRegistry::global()) {
+            self.registry.emit_stats(client, tags);
+        }
+    }
+    
     /// Find service by path
     fn find_service_by_path(&self, path: &str) -> Result<String, ServiceError> {
         let services = self.services.read().unwrap();
@@ -351,18 +480,3 @@ pub enum ServiceError {
     #[error("Load balancing error: {0}")]
     LoadBalancingError(String),
 }
-
-impl Clone for ServiceInstance {
-    fn clone(&self) -> Self {
-        // Note: This is a simplified clone implementation
-        // In practice, you'd need to handle the module trait object properly
-        Self {
-            id: self.id.clone(),
-            config: self.config.clone(),
-            module: Box::new(crate::modules::builtin::EchoModule::new()),
-            metrics: self.metrics.clone(),
-            last_health_check: self.last_health_check,
-            connection_count: self.connection_count,
-        }
-    }
-}
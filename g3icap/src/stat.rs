@@ -8,12 +8,16 @@
 //! This module provides global statistics collection following G3Proxy pattern.
 
 use std::sync::Arc;
-use std::thread::JoinHandle;
+use std::time::Duration;
 
 use anyhow::{Context, Result};
 use g3_statsd_client::StatsdClientConfig;
 
-use crate::stats::{IcapStats, thread};
+use crate::stats::IcapStats;
+use crate::stats::thread::{self, StatsThread};
+
+/// Time given to the stats threads to emit their final snapshot
+const STOP_TIMEOUT: Duration = Duration::from_secs(3);
 
 /// Global statistics instance
 static GLOBAL_STATS: std::sync::OnceLock<Arc<IcapStats>> = std::sync::OnceLock::new();
@@ -30,7 +34,7 @@ pub fn get_global_stats() -> Option<Arc<IcapStats>> {
 }
 
 /// Spawn working threads for statistics following G3Proxy pattern
-pub fn spawn_working_threads(config: StatsdClientConfig) -> Result<Vec<JoinHandle<()>>> {
+pub fn spawn_working_threads(config: StatsdClientConfig) -> Result<Vec<StatsThread>> {
     let mut handlers = Vec::with_capacity(1);
     let stats = get_global_stats().unwrap_or_else(|| Arc::new(IcapStats::new()));
     let main_handle = thread::spawn_stats_thread(&config, stats)
@@ -39,7 +43,12 @@ pub fn spawn_working_threads(config: StatsdClientConfig) -> Result<Vec<JoinHandl
     Ok(handlers)
 }
 
-/// Stop working threads
-pub fn stop_working_threads() {
-    thread::quit_stats_thread();
+/// Stop working threads, after their final emission
+///
+/// A thread blocked on the StatsD socket is left behind after a timeout, it
+/// never blocks the shutdown.
+pub fn stop_working_threads(threads: Vec<StatsThread>) {
+    for thread in threads {
+        thread.stop(STOP_TIMEOUT);
+    }
 }
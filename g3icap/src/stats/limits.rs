@@ -0,0 +1,77 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Protocol limit statistics
+//!
+//! Each limit counts the requests answered with an error for exceeding it,
+//! and the connections reset without an answer for overrunning it by far.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::protocol::limits::LimitExceeded;
+
+/// Limits that have been exceeded at least once
+static LIMITS: Mutex<Vec<Arc<LimitStats>>> = Mutex::new(Vec::new());
+
+/// Statistics of a protocol limit
+#[derive(Debug)]
+pub struct LimitStats {
+    name: &'static str,
+    rejected: AtomicU64,
+    reset: AtomicU64,
+}
+
+impl LimitStats {
+    fn new(name: &'static str) -> Self {
+        LimitStats {
+            name,
+            rejected: AtomicU64::new(0),
+            reset: AtomicU64::new(0),
+        }
+    }
+
+    /// Name of the limit, as used in the config file
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Count a request answered with an error
+    pub fn add_rejected(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a connection reset without an answer
+    pub fn add_reset(&self) {
+        self.reset.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of requests answered with an error
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Number of connections reset without an answer
+    pub fn reset(&self) -> u64 {
+        self.reset.load(Ordering::Relaxed)
+    }
+}
+
+/// Get the stats of the exceeded limit
+pub fn get(limit: &LimitExceeded) -> Arc<LimitStats> {
+    let name = limit.limit_name();
+    let mut limits = LIMITS.lock().unwrap();
+    if let Some(stats) = limits.iter().find(|s| s.name == name) {
+        return stats.clone();
+    }
+    let stats = Arc::new(LimitStats::new(name));
+    limits.push(stats.clone());
+    stats
+}
+
+/// Get the stats of all limits that have been exceeded
+pub fn all() -> Vec<Arc<LimitStats>> {
+    LIMITS.lock().unwrap().clone()
+}
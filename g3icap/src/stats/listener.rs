@@ -0,0 +1,153 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Per listener connection statistics
+//!
+//! The server counters add up all listeners, these are kept for each of
+//! them, so that the traffic of co-located proxies on the unix socket can be
+//! told apart from the one of remote proxies over TCP. A listener served by
+//! one acceptor in each worker runtime also keeps the counters of each
+//! acceptor, which add up to the ones of the listener.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Registered listeners
+static LISTENERS: Mutex<Vec<Arc<ListenerStats>>> = Mutex::new(Vec::new());
+
+/// Connection statistics of a listener
+#[derive(Debug)]
+pub struct ListenerStats {
+    name: &'static str,
+    accepted: AtomicU64,
+    failed: AtomicU64,
+    active: AtomicU64,
+    acceptors: Mutex<Vec<Arc<AcceptorStats>>>,
+}
+
+impl ListenerStats {
+    fn new(name: &'static str) -> Self {
+        ListenerStats {
+            name,
+            accepted: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            active: AtomicU64::new(0),
+            acceptors: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Name of the listener, used as the value of the listener tag
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Count an accepted connection, which stays active until it is finished
+    pub fn add_accepted(&self) {
+        self.accepted.fetch_add(1, Ordering::Relaxed);
+        self.active.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count the end of an accepted connection
+    pub fn add_finished(&self) {
+        self.active.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Count a connection that failed to be accepted or set up
+    pub fn add_failed(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of accepted connections
+    pub fn accepted(&self) -> u64 {
+        self.accepted.load(Ordering::Relaxed)
+    }
+
+    /// Number of connections that failed to be accepted or set up
+    pub fn failed(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    /// Number of active connections
+    pub fn active(&self) -> u64 {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Get the stats of the acceptor of a worker, registering it if needed
+    pub fn acceptor(&self, worker_id: usize) -> Arc<AcceptorStats> {
+        let mut acceptors = self.acceptors.lock().unwrap();
+        if let Some(stats) = acceptors.iter().find(|s| s.worker_id == worker_id) {
+            return stats.clone();
+        }
+        let stats = Arc::new(AcceptorStats::new(worker_id));
+        acceptors.push(stats.clone());
+        stats
+    }
+
+    /// Get the stats of all acceptors of the listener
+    pub fn acceptors(&self) -> Vec<Arc<AcceptorStats>> {
+        self.acceptors.lock().unwrap().clone()
+    }
+}
+
+/// Accept statistics of one acceptor of a listener
+#[derive(Debug)]
+pub struct AcceptorStats {
+    worker_id: usize,
+    accepted: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl AcceptorStats {
+    fn new(worker_id: usize) -> Self {
+        AcceptorStats {
+            worker_id,
+            accepted: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+        }
+    }
+
+    /// Id of the worker runtime the acceptor runs in
+    pub fn worker_id(&self) -> usize {
+        self.worker_id
+    }
+
+    /// Count a connection returned by accept
+    pub fn add_accepted(&self) {
+        self.accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a failed accept
+    pub fn add_failed(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of connections returned by accept
+    pub fn accepted(&self) -> u64 {
+        self.accepted.load(Ordering::Relaxed)
+    }
+
+    /// Number of failed accepts
+    pub fn failed(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+}
+
+/// Get the stats of the listener, registering it if needed
+///
+/// The stats are kept over server reloads, as the listener name is.
+pub fn register(name: &'static str) -> Arc<ListenerStats> {
+    let mut listeners = LISTENERS.lock().unwrap();
+    if let Some(stats) = listeners.iter().find(|s| s.name == name) {
+        return stats.clone();
+    }
+    let stats = Arc::new(ListenerStats::new(name));
+    listeners.push(stats.clone());
+    stats
+}
+
+/// Get the stats of all registered listeners
+pub fn all() -> Vec<Arc<ListenerStats>> {
+    LISTENERS.lock().unwrap().clone()
+}
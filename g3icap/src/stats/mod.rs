@@ -5,18 +5,25 @@
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 // use std::time::Instant;
-use std::thread::JoinHandle;
+use std::time::Duration;
 
 use anyhow::{Context, Result};
 use g3_statsd_client::{StatsdClient, StatsdClientConfig, StatsdTagGroup};
 use g3_daemon::metrics::TAG_KEY_DAEMON_GROUP;
 
+use crate::config::server::idle_reaper::ReapReason;
+use crate::config::server::timeouts::TimeoutKind;
 use crate::opts::daemon_group;
 
+pub mod limits;
+pub mod listener;
+pub mod report;
+pub mod resource;
+pub mod service;
 pub mod thread;
 
 /// Spawn working threads for statistics following G3Proxy pattern
-pub fn spawn_working_threads(config: StatsdClientConfig) -> Result<Vec<JoinHandle<()>>> {
+pub fn spawn_working_threads(config: StatsdClientConfig) -> Result<Vec<thread::StatsThread>> {
     let mut handlers = Vec::with_capacity(1);
     let stats = Arc::new(IcapStats::new());
     let main_handle = thread::spawn_stats_thread(&config, stats)
@@ -25,9 +32,11 @@ pub fn spawn_working_threads(config: StatsdClientConfig) -> Result<Vec<JoinHandl
     Ok(handlers)
 }
 
-/// Stop working threads
-pub fn stop_working_threads() {
-    thread::quit_stats_thread();
+/// Stop working threads, after their final emission
+pub fn stop_working_threads(threads: Vec<thread::StatsThread>) {
+    for thread in threads {
+        thread.stop(Duration::from_secs(3));
+    }
 }
 
 // Metric name constants following G3Proxy pattern
@@ -38,12 +47,97 @@ const METRIC_NAME_ICAP_REQUESTS_OPTIONS: &str = "icap.requests.options";
 const METRIC_NAME_ICAP_RESPONSES_SUCCESSFUL: &str = "icap.responses.successful";
 const METRIC_NAME_ICAP_RESPONSES_ERROR: &str = "icap.responses.error";
 const METRIC_NAME_ICAP_REQUESTS_BLOCKED: &str = "icap.requests.blocked";
+const METRIC_NAME_ICAP_REQUESTS_MONITORED: &str = "icap.requests.monitored";
+const METRIC_NAME_ICAP_RESPONSES_PARTIAL: &str = "icap.responses.partial";
+const METRIC_NAME_ICAP_REQUESTS_CLIENT_ABORTED: &str = "icap.requests.client_aborted";
+const METRIC_NAME_ICAP_WAF_ERRORS: &str = "icap.waf.errors";
 const METRIC_NAME_ICAP_BYTES_TOTAL: &str = "icap.bytes.total";
 const METRIC_NAME_ICAP_CONNECTIONS_TOTAL: &str = "icap.connections.total";
 const METRIC_NAME_ICAP_CONNECTIONS_ACTIVE: &str = "icap.connections.active";
 const METRIC_NAME_ICAP_CONNECTIONS_ERROR: &str = "icap.connections.error";
 const METRIC_NAME_ICAP_PROCESSING_TIME_TOTAL: &str = "icap.processing_time.total";
 const METRIC_NAME_ICAP_PROCESSING_TIME_AVG: &str = "icap.processing_time.avg";
+const METRIC_NAME_ICAP_SLOW_CLIENTS_FLAGGED: &str = "icap.connections.slow_client.flagged";
+const METRIC_NAME_ICAP_SLOW_CLIENTS_TERMINATED: &str = "icap.connections.slow_client.terminated";
+const METRIC_NAME_ICAP_CONNECTIONS_TIMEOUT: &str = "icap.connections.timeout";
+const METRIC_NAME_ICAP_CONNECTIONS_REAPED: &str = "icap.connections.reaped";
+const METRIC_NAME_ICAP_AUTH_REJECTED: &str = "icap.auth.rejected";
+const METRIC_NAME_ICAP_OPTIONS_MALFORMED_REJECTED: &str = "icap.requests.options.malformed.rejected";
+const METRIC_NAME_ICAP_OPTIONS_MALFORMED_IGNORED: &str = "icap.requests.options.malformed.ignored";
+const METRIC_NAME_ICAP_AUTH_TOKEN_ACCEPTED: &str = "icap.auth.token.accepted";
+const METRIC_NAME_ICAP_AUTH_TOKEN_REJECTED: &str = "icap.auth.token.rejected";
+
+#[cfg(feature = "cdr")]
+const METRIC_NAME_ICAP_CDR_SANITIZED: &str = "icap.cdr.sanitized";
+#[cfg(feature = "cdr")]
+const METRIC_NAME_ICAP_CDR_PASSED_THROUGH: &str = "icap.cdr.passed_through";
+#[cfg(feature = "cdr")]
+const METRIC_NAME_ICAP_CDR_BLOCKED: &str = "icap.cdr.blocked";
+#[cfg(feature = "cdr")]
+const METRIC_NAME_ICAP_CDR_FAILED: &str = "icap.cdr.failed";
+
+const METRIC_NAME_ICAP_LISTENER_ACCEPTED: &str = "icap.listener.accepted";
+const METRIC_NAME_ICAP_LISTENER_FAILED: &str = "icap.listener.failed";
+const METRIC_NAME_ICAP_LISTENER_ACTIVE: &str = "icap.listener.active";
+const METRIC_NAME_ICAP_LISTENER_ACCEPTOR_ACCEPTED: &str = "icap.listener.acceptor.accepted";
+const METRIC_NAME_ICAP_LISTENER_ACCEPTOR_FAILED: &str = "icap.listener.acceptor.failed";
+
+const METRIC_NAME_ICAP_LIMIT_REJECTED: &str = "icap.limit.rejected";
+const METRIC_NAME_ICAP_LIMIT_RESET: &str = "icap.limit.reset";
+
+const METRIC_NAME_ICAP_SERVICE_TRANSACTIONS: &str = "icap.service.transactions";
+const METRIC_NAME_ICAP_SERVICE_FAILED: &str = "icap.service.failed";
+const METRIC_NAME_ICAP_SERVICE_TIME_AVG: &str = "icap.service.time_avg";
+const METRIC_NAME_ICAP_PIPELINE_TRANSACTIONS: &str = "icap.pipeline.transactions";
+
+const METRIC_NAME_ICAP_QUARANTINE_FILES: &str = "icap.quarantine.files";
+const METRIC_NAME_ICAP_QUARANTINE_BYTES: &str = "icap.quarantine.bytes";
+const METRIC_NAME_ICAP_QUARANTINE_FAILED: &str = "icap.quarantine.failed";
+
+const METRIC_NAME_ICAP_BUFFER_POOL_IDLE: &str = "icap.buffer_pool.idle";
+const METRIC_NAME_ICAP_BUFFER_POOL_IN_USE: &str = "icap.buffer_pool.in_use";
+const METRIC_NAME_ICAP_BUFFER_POOL_HIT: &str = "icap.buffer_pool.hit";
+const METRIC_NAME_ICAP_BUFFER_POOL_MISS: &str = "icap.buffer_pool.miss";
+const METRIC_NAME_ICAP_BUFFER_POOL_REMOTE_HIT: &str = "icap.buffer_pool.remote_hit";
+const METRIC_NAME_ICAP_BUFFER_POOL_DROPPED: &str = "icap.buffer_pool.dropped";
+
+const METRIC_NAME_ICAP_DEGRADATION_LEVEL: &str = "icap.degradation.level";
+const METRIC_NAME_ICAP_DEGRADATION_ENTERED: &str = "icap.degradation.entered";
+const METRIC_NAME_ICAP_DEGRADATION_TRANSACTIONS: &str = "icap.degradation.transactions";
+
+const METRIC_NAME_ICAP_SHEDDING_ACTIVE: &str = "icap.shedding.active";
+const METRIC_NAME_ICAP_SHEDDING_ENTERED: &str = "icap.shedding.entered";
+const METRIC_NAME_ICAP_SHEDDING_ADMITTED: &str = "icap.shedding.admitted";
+const METRIC_NAME_ICAP_SHEDDING_SHED: &str = "icap.shedding.shed";
+const METRIC_NAME_ICAP_SHEDDING_IN_FLIGHT: &str = "icap.shedding.in_flight";
+const METRIC_NAME_ICAP_SHEDDING_LATENCY_P99: &str = "icap.shedding.latency_p99";
+
+const METRIC_NAME_ICAP_SLO_TRANSACTIONS: &str = "icap.slo.transactions";
+const METRIC_NAME_ICAP_SLO_COMPLIANCE: &str = "icap.slo.compliance";
+const METRIC_NAME_ICAP_SLO_BUDGET_REMAINING: &str = "icap.slo.budget_remaining";
+const METRIC_NAME_ICAP_SLO_BURN_RATE: &str = "icap.slo.burn_rate";
+
+const METRIC_NAME_ICAP_PROCESS_MEMORY: &str = "icap.process.memory";
+const METRIC_NAME_ICAP_PROCESS_OPEN_FDS: &str = "icap.process.open_fds";
+const METRIC_NAME_ICAP_PROCESS_CPU: &str = "icap.process.cpu";
+const METRIC_NAME_ICAP_RUNTIME_CPU: &str = "icap.runtime.cpu";
+const METRIC_NAME_ICAP_RUNTIME_WORKERS: &str = "icap.runtime.workers";
+const METRIC_NAME_ICAP_RUNTIME_ALIVE_TASKS: &str = "icap.runtime.alive_tasks";
+const METRIC_NAME_ICAP_RUNTIME_QUEUED_TASKS: &str = "icap.runtime.queued_tasks";
+
+const TAG_KEY_TOKEN_ID: &str = "token_id";
+const TAG_KEY_LISTENER: &str = "listener";
+const TAG_KEY_ACCEPTOR: &str = "acceptor";
+const TAG_KEY_LIMIT: &str = "limit";
+const TAG_KEY_SERVICE: &str = "service";
+const TAG_KEY_PIPELINE: &str = "pipeline";
+const TAG_KEY_TIMEOUT: &str = "timeout";
+const TAG_KEY_REASON: &str = "reason";
+const TAG_KEY_SERVER: &str = "server";
+const TAG_KEY_LEVEL: &str = "level";
+const TAG_KEY_RUNTIME: &str = "runtime";
+const TAG_KEY_SLO: &str = "slo";
+const TAG_KEY_WINDOW: &str = "window";
 
 /// ICAP Server Statistics
 pub struct IcapStats {
@@ -61,6 +155,10 @@ pub struct IcapStats {
     error_responses: AtomicU64,
     /// Total number of blocked requests
     blocked_requests: AtomicU64,
+    /// Verdicts not applied in monitor mode
+    monitored_verdicts: AtomicU64,
+    /// Modified messages sent as 206 Partial Content
+    partial_responses: AtomicU64,
     /// Total bytes processed
     total_bytes: AtomicU64,
     /// Current number of active connections
@@ -71,6 +169,24 @@ pub struct IcapStats {
     connection_errors: AtomicU64,
     /// Request processing time (microseconds)
     total_processing_time: AtomicU64,
+    /// Requests rejected by client authentication
+    auth_rejected: AtomicU64,
+    /// OPTIONS requests carrying a body, rejected in strict mode
+    malformed_options_rejected: AtomicU64,
+    /// OPTIONS requests carrying a body, ignored in lenient mode
+    malformed_options_ignored: AtomicU64,
+    /// Connections flagged as slow readers
+    slow_clients_flagged: AtomicU64,
+    /// Slow reader connections terminated
+    slow_clients_terminated: AtomicU64,
+    /// Transactions aborted by the client
+    client_aborted: AtomicU64,
+    /// WAF inspections that failed
+    waf_errors: AtomicU64,
+    /// Connections timed out, by timeout kind
+    timeouts: [AtomicU64; TimeoutKind::ALL.len()],
+    /// Idle connections reaped, by reason
+    reaped: [AtomicU64; ReapReason::ALL.len()],
     /// StatsD client for metrics emission
     #[allow(dead_code)]
     statsd_client: Option<Arc<Mutex<StatsdClient>>>,
@@ -87,11 +203,22 @@ impl IcapStats {
             successful_responses: AtomicU64::new(0),
             error_responses: AtomicU64::new(0),
             blocked_requests: AtomicU64::new(0),
+            monitored_verdicts: AtomicU64::new(0),
+            partial_responses: AtomicU64::new(0),
             total_bytes: AtomicU64::new(0),
             active_connections: AtomicU64::new(0),
             total_connections: AtomicU64::new(0),
             connection_errors: AtomicU64::new(0),
             total_processing_time: AtomicU64::new(0),
+            auth_rejected: AtomicU64::new(0),
+            malformed_options_rejected: AtomicU64::new(0),
+            malformed_options_ignored: AtomicU64::new(0),
+            slow_clients_flagged: AtomicU64::new(0),
+            slow_clients_terminated: AtomicU64::new(0),
+            client_aborted: AtomicU64::new(0),
+            waf_errors: AtomicU64::new(0),
+            timeouts: Default::default(),
+            reaped: Default::default(),
             statsd_client: None,
         }
     }
@@ -112,11 +239,22 @@ impl IcapStats {
             successful_responses: AtomicU64::new(0),
             error_responses: AtomicU64::new(0),
             blocked_requests: AtomicU64::new(0),
+            monitored_verdicts: AtomicU64::new(0),
+            partial_responses: AtomicU64::new(0),
             total_bytes: AtomicU64::new(0),
             active_connections: AtomicU64::new(0),
             total_connections: AtomicU64::new(0),
             connection_errors: AtomicU64::new(0),
             total_processing_time: AtomicU64::new(0),
+            auth_rejected: AtomicU64::new(0),
+            malformed_options_rejected: AtomicU64::new(0),
+            malformed_options_ignored: AtomicU64::new(0),
+            slow_clients_flagged: AtomicU64::new(0),
+            slow_clients_terminated: AtomicU64::new(0),
+            client_aborted: AtomicU64::new(0),
+            waf_errors: AtomicU64::new(0),
+            timeouts: Default::default(),
+            reaped: Default::default(),
             statsd_client: Some(Arc::new(Mutex::new(client_with_tag))),
         })
     }
@@ -172,6 +310,61 @@ impl IcapStats {
         self.blocked_requests.fetch_add(1, Ordering::Relaxed);
     }
 
+    /// Increment verdicts not applied in monitor mode
+    pub fn increment_monitored_verdicts(&self) {
+        self.monitored_verdicts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Increment modified messages sent as 206 Partial Content
+    pub fn increment_partial_responses(&self) {
+        self.partial_responses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Increment transactions aborted by the client
+    pub fn increment_client_aborted(&self) {
+        self.client_aborted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Increment WAF inspections that failed
+    pub fn increment_waf_errors(&self) {
+        self.waf_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Increment requests rejected by client authentication
+    pub fn increment_auth_rejected(&self) {
+        self.auth_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Increment OPTIONS requests carrying a body rejected in strict mode
+    pub fn increment_malformed_options_rejected(&self) {
+        self.malformed_options_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Increment OPTIONS requests carrying a body ignored in lenient mode
+    pub fn increment_malformed_options_ignored(&self) {
+        self.malformed_options_ignored.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Increment connections flagged as slow readers
+    pub fn increment_slow_clients_flagged(&self) {
+        self.slow_clients_flagged.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Increment slow reader connections terminated
+    pub fn increment_slow_clients_terminated(&self) {
+        self.slow_clients_terminated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Increment connections timed out
+    pub fn increment_timeouts(&self, kind: TimeoutKind) {
+        self.timeouts[kind as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Increment idle connections reaped
+    pub fn increment_reaped(&self, reason: ReapReason) {
+        self.reaped[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
     /// Add bytes processed
     pub fn add_bytes(&self, bytes: u64) {
         self.total_bytes.fetch_add(bytes, Ordering::Relaxed);
@@ -231,6 +424,14 @@ impl IcapStats {
         client
             .count_with_tags(METRIC_NAME_ICAP_REQUESTS_BLOCKED, self.blocked_requests.load(Ordering::Relaxed), &common_tags)
             .send();
+
+        client
+            .count_with_tags(METRIC_NAME_ICAP_REQUESTS_MONITORED, self.monitored_verdicts.load(Ordering::Relaxed), &common_tags)
+            .send();
+
+        client
+            .count_with_tags(METRIC_NAME_ICAP_RESPONSES_PARTIAL, self.partial_responses.load(Ordering::Relaxed), &common_tags)
+            .send();
         
         client
             .count_with_tags(METRIC_NAME_ICAP_BYTES_TOTAL, self.total_bytes.load(Ordering::Relaxed), &common_tags)
@@ -248,6 +449,293 @@ impl IcapStats {
             .count_with_tags(METRIC_NAME_ICAP_PROCESSING_TIME_TOTAL, self.total_processing_time.load(Ordering::Relaxed), &common_tags)
             .send();
 
+        client
+            .count_with_tags(METRIC_NAME_ICAP_AUTH_REJECTED, self.auth_rejected.load(Ordering::Relaxed), &common_tags)
+            .send();
+
+        client
+            .count_with_tags(METRIC_NAME_ICAP_OPTIONS_MALFORMED_REJECTED, self.malformed_options_rejected.load(Ordering::Relaxed), &common_tags)
+            .send();
+
+        client
+            .count_with_tags(METRIC_NAME_ICAP_OPTIONS_MALFORMED_IGNORED, self.malformed_options_ignored.load(Ordering::Relaxed), &common_tags)
+            .send();
+
+        client
+            .count_with_tags(METRIC_NAME_ICAP_SLOW_CLIENTS_FLAGGED, self.slow_clients_flagged.load(Ordering::Relaxed), &common_tags)
+            .send();
+
+        client
+            .count_with_tags(METRIC_NAME_ICAP_SLOW_CLIENTS_TERMINATED, self.slow_clients_terminated.load(Ordering::Relaxed), &common_tags)
+            .send();
+
+        client
+            .count_with_tags(METRIC_NAME_ICAP_REQUESTS_CLIENT_ABORTED, self.client_aborted.load(Ordering::Relaxed), &common_tags)
+            .send();
+
+        client
+            .count_with_tags(METRIC_NAME_ICAP_WAF_ERRORS, self.waf_errors.load(Ordering::Relaxed), &common_tags)
+            .send();
+
+        // Emit per-kind timeout metrics
+        for kind in TimeoutKind::ALL {
+            let mut tags = common_tags.clone();
+            tags.add_tag(TAG_KEY_TIMEOUT, kind.as_str());
+            client
+                .count_with_tags(METRIC_NAME_ICAP_CONNECTIONS_TIMEOUT, self.timeouts(kind), &tags)
+                .send();
+        }
+
+        // Emit per-reason reaped connection metrics
+        for reason in ReapReason::ALL {
+            let mut tags = common_tags.clone();
+            tags.add_tag(TAG_KEY_REASON, reason.as_str());
+            client
+                .count_with_tags(METRIC_NAME_ICAP_CONNECTIONS_REAPED, self.reaped(reason), &tags)
+                .send();
+        }
+
+        // Emit per-token authentication metrics
+        if let Some(auth) = crate::auth::token::get_global() {
+            for (token_id, stats) in auth.token_stats() {
+                let mut tags = common_tags.clone();
+                tags.add_tag(TAG_KEY_TOKEN_ID, token_id.as_str());
+                client
+                    .count_with_tags(METRIC_NAME_ICAP_AUTH_TOKEN_ACCEPTED, stats.accepted(), &tags)
+                    .send();
+                client
+                    .count_with_tags(METRIC_NAME_ICAP_AUTH_TOKEN_REJECTED, stats.rejected(), &tags)
+                    .send();
+            }
+        }
+
+        // Emit per-listener connection metrics
+        for stats in listener::all() {
+            let mut tags = common_tags.clone();
+            tags.add_tag(TAG_KEY_LISTENER, stats.name());
+            client
+                .count_with_tags(METRIC_NAME_ICAP_LISTENER_ACCEPTED, stats.accepted(), &tags)
+                .send();
+            client
+                .count_with_tags(METRIC_NAME_ICAP_LISTENER_FAILED, stats.failed(), &tags)
+                .send();
+            client
+                .gauge_with_tags(METRIC_NAME_ICAP_LISTENER_ACTIVE, stats.active(), &tags)
+                .send();
+            for acceptor in stats.acceptors() {
+                let mut tags = tags.clone();
+                tags.add_tag(TAG_KEY_ACCEPTOR, acceptor.worker_id().to_string());
+                client
+                    .count_with_tags(METRIC_NAME_ICAP_LISTENER_ACCEPTOR_ACCEPTED, acceptor.accepted(), &tags)
+                    .send();
+                client
+                    .count_with_tags(METRIC_NAME_ICAP_LISTENER_ACCEPTOR_FAILED, acceptor.failed(), &tags)
+                    .send();
+            }
+        }
+
+        // Emit per-limit overrun metrics
+        for stats in limits::all() {
+            let mut tags = common_tags.clone();
+            tags.add_tag(TAG_KEY_LIMIT, stats.name());
+            client
+                .count_with_tags(METRIC_NAME_ICAP_LIMIT_REJECTED, stats.rejected(), &tags)
+                .send();
+            client
+                .count_with_tags(METRIC_NAME_ICAP_LIMIT_RESET, stats.reset(), &tags)
+                .send();
+        }
+
+        // Emit per-service and per-pipeline transaction metrics
+        for stats in service::all() {
+            let mut tags = common_tags.clone();
+            tags.add_tag(TAG_KEY_SERVICE, stats.name());
+            client
+                .count_with_tags(METRIC_NAME_ICAP_SERVICE_TRANSACTIONS, stats.transactions(), &tags)
+                .send();
+            client
+                .count_with_tags(METRIC_NAME_ICAP_SERVICE_FAILED, stats.failed(), &tags)
+                .send();
+            client
+                .gauge_with_tags(METRIC_NAME_ICAP_SERVICE_TIME_AVG, stats.avg_time(), &tags)
+                .send();
+        }
+        if let Some(pipelines) = crate::server::pipelines::get_global() {
+            for stats in pipelines.stats() {
+                let mut tags = common_tags.clone();
+                tags.add_tag(TAG_KEY_PIPELINE, stats.name.as_str());
+                client
+                    .count_with_tags(METRIC_NAME_ICAP_PIPELINE_TRANSACTIONS, stats.transactions, &tags)
+                    .send();
+            }
+        }
+
+        // Emit read buffer pool occupancy
+        for pool in crate::server::buffer_pool::all() {
+            let mut tags = common_tags.clone();
+            tags.add_tag(TAG_KEY_SERVER, pool.name());
+            client
+                .gauge_with_tags(METRIC_NAME_ICAP_BUFFER_POOL_IDLE, pool.idle(), &tags)
+                .send();
+            client
+                .gauge_with_tags(METRIC_NAME_ICAP_BUFFER_POOL_IN_USE, pool.in_use(), &tags)
+                .send();
+            client
+                .count_with_tags(METRIC_NAME_ICAP_BUFFER_POOL_HIT, pool.hits(), &tags)
+                .send();
+            client
+                .count_with_tags(METRIC_NAME_ICAP_BUFFER_POOL_MISS, pool.misses(), &tags)
+                .send();
+            client
+                .count_with_tags(METRIC_NAME_ICAP_BUFFER_POOL_REMOTE_HIT, pool.remote_hits(), &tags)
+                .send();
+            client
+                .count_with_tags(METRIC_NAME_ICAP_BUFFER_POOL_DROPPED, pool.dropped(), &tags)
+                .send();
+        }
+
+        // Emit the degradation ladder level and steps
+        if let Some(ladder) = crate::server::degradation::get_global() {
+            client
+                .gauge_with_tags(METRIC_NAME_ICAP_DEGRADATION_LEVEL, ladder.level() as u64, &common_tags)
+                .send();
+            for level in crate::server::degradation::DegradationLevel::ALL {
+                let mut tags = common_tags.clone();
+                tags.add_tag(TAG_KEY_LEVEL, level.as_str());
+                client
+                    .count_with_tags(METRIC_NAME_ICAP_DEGRADATION_ENTERED, ladder.entered(level), &tags)
+                    .send();
+                client
+                    .count_with_tags(METRIC_NAME_ICAP_DEGRADATION_TRANSACTIONS, ladder.degraded(level), &tags)
+                    .send();
+            }
+        }
+
+        // Emit the load shedding state and counters
+        if let Some(shedder) = crate::server::load_shedding::get_global() {
+            client
+                .gauge_with_tags(METRIC_NAME_ICAP_SHEDDING_ACTIVE, u8::from(shedder.is_shedding()), &common_tags)
+                .send();
+            client
+                .count_with_tags(METRIC_NAME_ICAP_SHEDDING_ENTERED, shedder.entered(), &common_tags)
+                .send();
+            client
+                .count_with_tags(METRIC_NAME_ICAP_SHEDDING_ADMITTED, shedder.admitted(), &common_tags)
+                .send();
+            client
+                .count_with_tags(METRIC_NAME_ICAP_SHEDDING_SHED, shedder.shed(), &common_tags)
+                .send();
+            client
+                .gauge_with_tags(METRIC_NAME_ICAP_SHEDDING_IN_FLIGHT, shedder.in_flight(), &common_tags)
+                .send();
+            client
+                .gauge_with_tags(
+                    METRIC_NAME_ICAP_SHEDDING_LATENCY_P99,
+                    shedder.latency_p99().as_millis() as u64,
+                    &common_tags,
+                )
+                .send();
+        }
+
+        // Emit the compliance and error budget of the response time objectives
+        if let Some(slo) = crate::server::slo::get_global() {
+            for report in slo.reports() {
+                let mut tags = common_tags.clone();
+                tags.add_tag(TAG_KEY_SLO, report.name.as_str());
+                client
+                    .gauge_with_tags(METRIC_NAME_ICAP_SLO_TRANSACTIONS, report.total, &tags)
+                    .send();
+                client
+                    .gauge_float_with_tags(METRIC_NAME_ICAP_SLO_COMPLIANCE, report.compliance, &tags)
+                    .send();
+                client
+                    .gauge_float_with_tags(METRIC_NAME_ICAP_SLO_BUDGET_REMAINING, report.budget_remaining, &tags)
+                    .send();
+                for (window, burn_rate) in [("long", report.burn_rate), ("short", report.short_burn_rate)] {
+                    let mut tags = tags.clone();
+                    tags.add_tag(TAG_KEY_WINDOW, window);
+                    client
+                        .gauge_float_with_tags(METRIC_NAME_ICAP_SLO_BURN_RATE, burn_rate, &tags)
+                        .send();
+                }
+            }
+        }
+
+        // Emit the resource usage of the process and of its runtimes
+        let usage = resource::sample();
+        if let Some(memory) = usage.memory {
+            client
+                .gauge_with_tags(METRIC_NAME_ICAP_PROCESS_MEMORY, memory, &common_tags)
+                .send();
+        }
+        if let Some(open_fds) = usage.open_fds {
+            client
+                .gauge_with_tags(METRIC_NAME_ICAP_PROCESS_OPEN_FDS, open_fds, &common_tags)
+                .send();
+        }
+        if let Some(cpu) = usage.cpu {
+            client
+                .gauge_float_with_tags(METRIC_NAME_ICAP_PROCESS_CPU, cpu, &common_tags)
+                .send();
+        }
+        for runtime in &usage.runtimes {
+            let mut tags = common_tags.clone();
+            tags.add_tag(TAG_KEY_RUNTIME, runtime.name.as_str());
+            if let Some(cpu) = runtime.cpu {
+                client
+                    .gauge_float_with_tags(METRIC_NAME_ICAP_RUNTIME_CPU, cpu, &tags)
+                    .send();
+            }
+            client
+                .gauge_with_tags(METRIC_NAME_ICAP_RUNTIME_WORKERS, runtime.workers as u64, &tags)
+                .send();
+            client
+                .gauge_with_tags(METRIC_NAME_ICAP_RUNTIME_ALIVE_TASKS, runtime.alive_tasks as u64, &tags)
+                .send();
+            client
+                .gauge_with_tags(METRIC_NAME_ICAP_RUNTIME_QUEUED_TASKS, runtime.queued_tasks as u64, &tags)
+                .send();
+        }
+
+        // Emit document sanitization metrics
+        #[cfg(feature = "cdr")]
+        {
+            let cdr_stats = crate::modules::cdr::global_stats();
+            client
+                .count_with_tags(METRIC_NAME_ICAP_CDR_SANITIZED, cdr_stats.sanitized(), &common_tags)
+                .send();
+            client
+                .count_with_tags(METRIC_NAME_ICAP_CDR_PASSED_THROUGH, cdr_stats.passed_through(), &common_tags)
+                .send();
+            client
+                .count_with_tags(METRIC_NAME_ICAP_CDR_BLOCKED, cdr_stats.blocked(), &common_tags)
+                .send();
+            client
+                .count_with_tags(METRIC_NAME_ICAP_CDR_FAILED, cdr_stats.failed(), &common_tags)
+                .send();
+        }
+
+        // Emit quarantine metrics
+        let quarantine_stats = crate::modules::antivirus::quarantine_stats();
+        client
+            .count_with_tags(METRIC_NAME_ICAP_QUARANTINE_FILES, quarantine_stats.files(), &common_tags)
+            .send();
+        client
+            .count_with_tags(METRIC_NAME_ICAP_QUARANTINE_BYTES, quarantine_stats.bytes(), &common_tags)
+            .send();
+        client
+            .count_with_tags(METRIC_NAME_ICAP_QUARANTINE_FAILED, quarantine_stats.failed(), &common_tags)
+            .send();
+
+        // Emit the metrics registered by the modules
+        crate::modules::metrics::MetricsRegistry::global().emit_stats(client, &common_tags);
+
+        // Emit the per service and per module metrics
+        if let Some(services) = crate::services::get_global() {
+            services.emit_stats(client, &common_tags);
+        }
+        crate::modules::ModuleRegistry::global().emit_stats(client, &common_tags);
+
         // Emit gauge metrics
         client
             .gauge_with_tags(METRIC_NAME_ICAP_CONNECTIONS_ACTIVE, self.active_connections.load(Ordering::Relaxed), &common_tags)
@@ -298,6 +786,61 @@ impl IcapStats {
         self.blocked_requests.load(Ordering::Relaxed)
     }
 
+    /// Get verdicts not applied in monitor mode
+    pub fn monitored_verdicts(&self) -> u64 {
+        self.monitored_verdicts.load(Ordering::Relaxed)
+    }
+
+    /// Get modified messages sent as 206 Partial Content
+    pub fn partial_responses(&self) -> u64 {
+        self.partial_responses.load(Ordering::Relaxed)
+    }
+
+    /// Get requests rejected by client authentication
+    pub fn auth_rejected(&self) -> u64 {
+        self.auth_rejected.load(Ordering::Relaxed)
+    }
+
+    /// Get OPTIONS requests carrying a body rejected in strict mode
+    pub fn malformed_options_rejected(&self) -> u64 {
+        self.malformed_options_rejected.load(Ordering::Relaxed)
+    }
+
+    /// Get OPTIONS requests carrying a body ignored in lenient mode
+    pub fn malformed_options_ignored(&self) -> u64 {
+        self.malformed_options_ignored.load(Ordering::Relaxed)
+    }
+
+    /// Get connections flagged as slow readers
+    pub fn slow_clients_flagged(&self) -> u64 {
+        self.slow_clients_flagged.load(Ordering::Relaxed)
+    }
+
+    /// Get slow reader connections terminated
+    pub fn slow_clients_terminated(&self) -> u64 {
+        self.slow_clients_terminated.load(Ordering::Relaxed)
+    }
+
+    /// Get transactions aborted by the client
+    pub fn client_aborted(&self) -> u64 {
+        self.client_aborted.load(Ordering::Relaxed)
+    }
+
+    /// Get WAF inspections that failed
+    pub fn waf_errors(&self) -> u64 {
+        self.waf_errors.load(Ordering::Relaxed)
+    }
+
+    /// Get connections timed out
+    pub fn timeouts(&self, kind: TimeoutKind) -> u64 {
+        self.timeouts[kind as usize].load(Ordering::Relaxed)
+    }
+
+    /// Get idle connections reaped
+    pub fn reaped(&self, reason: ReapReason) -> u64 {
+        self.reaped[reason as usize].load(Ordering::Relaxed)
+    }
+
     /// Get total bytes
     pub fn total_bytes(&self) -> u64 {
         self.total_bytes.load(Ordering::Relaxed)
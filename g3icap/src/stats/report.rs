@@ -0,0 +1,180 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Snapshot of the statistics of the running server
+//!
+//! The snapshot is what the control channel answers the stats queries with,
+//! so that the current counters can be read without a StatsD server.
+
+use serde::{Deserialize, Serialize};
+
+use super::IcapStats;
+use crate::server::pipelines::PipelineStats;
+
+/// Counters of the whole server
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerStatsReport {
+    pub requests: u64,
+    pub reqmod_requests: u64,
+    pub respmod_requests: u64,
+    pub options_requests: u64,
+    pub successful_responses: u64,
+    pub error_responses: u64,
+    pub blocked_requests: u64,
+    pub monitored_verdicts: u64,
+    pub partial_responses: u64,
+    pub auth_rejected: u64,
+    pub malformed_options_rejected: u64,
+    pub malformed_options_ignored: u64,
+    pub client_aborted: u64,
+    pub waf_errors: u64,
+    pub bytes: u64,
+    pub active_connections: u64,
+    pub connections: u64,
+    pub connection_errors: u64,
+    /// Average processing time (microseconds)
+    pub avg_processing_time_us: u64,
+}
+
+/// Transactions of a service
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceStatsReport {
+    pub name: String,
+    pub enabled: bool,
+    pub pipeline: Option<String>,
+    pub transactions: u64,
+    pub failed: u64,
+    /// Average transaction time (microseconds)
+    pub avg_time_us: u64,
+}
+
+/// Files quarantined by the antivirus modules
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuarantineReport {
+    pub files: u64,
+    pub bytes: u64,
+    pub failed: u64,
+}
+
+/// Requests handled by a module
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModuleStatsReport {
+    pub name: String,
+    pub requests: u64,
+    pub error_rate: f64,
+    /// Average response time (microseconds)
+    pub avg_time_us: u64,
+    /// Whether the module and its backends, like a scanner daemon, are healthy
+    pub healthy: bool,
+}
+
+/// Statistics of the running server
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StatsReport {
+    pub server: ServerStatsReport,
+    pub services: Vec<ServiceStatsReport>,
+    pub pipelines: Vec<PipelineStats>,
+    pub quarantine: QuarantineReport,
+    pub modules: Vec<ModuleStatsReport>,
+}
+
+impl StatsReport {
+    /// Take a snapshot of the server stats and of the process wide ones
+    pub fn collect(stats: &IcapStats) -> Self {
+        let server = ServerStatsReport {
+            requests: stats.total_requests(),
+            reqmod_requests: stats.reqmod_requests(),
+            respmod_requests: stats.respmod_requests(),
+            options_requests: stats.options_requests(),
+            successful_responses: stats.successful_responses(),
+            error_responses: stats.error_responses(),
+            blocked_requests: stats.blocked_requests(),
+            monitored_verdicts: stats.monitored_verdicts(),
+            partial_responses: stats.partial_responses(),
+            auth_rejected: stats.auth_rejected(),
+            malformed_options_rejected: stats.malformed_options_rejected(),
+            malformed_options_ignored: stats.malformed_options_ignored(),
+            client_aborted: stats.client_aborted(),
+            waf_errors: stats.waf_errors(),
+            bytes: stats.total_bytes(),
+            active_connections: stats.active_connections(),
+            connections: stats.get_total_connections(),
+            connection_errors: stats.get_connection_errors(),
+            avg_processing_time_us: stats.get_avg_processing_time(),
+        };
+
+        // the services of the config first, then the other counted ones
+        let rotation = crate::server::rotation::get_global();
+        let mut services: Vec<ServiceStatsReport> = rotation
+            .as_ref()
+            .map(|r| r.list())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|s| ServiceStatsReport {
+                name: s.name,
+                enabled: s.enabled,
+                pipeline: s.pipeline,
+                transactions: 0,
+                failed: 0,
+                avg_time_us: 0,
+            })
+            .collect();
+        let pipelines = crate::server::pipelines::get_global();
+        for counted in super::service::all() {
+            let index = match services.iter().position(|s| s.name == counted.name()) {
+                Some(index) => index,
+                None => {
+                    let name = counted.name().to_string();
+                    services.push(ServiceStatsReport {
+                        enabled: rotation.as_ref().is_none_or(|r| !r.is_disabled(&name)),
+                        pipeline: pipelines
+                            .as_ref()
+                            .and_then(|p| p.pipeline(&name))
+                            .map(|p| p.name().to_string()),
+                        name,
+                        transactions: 0,
+                        failed: 0,
+                        avg_time_us: 0,
+                    });
+                    services.len() - 1
+                }
+            };
+            let service = &mut services[index];
+            service.transactions = counted.transactions();
+            service.failed = counted.failed();
+            service.avg_time_us = counted.avg_time();
+        }
+
+        let quarantine = crate::modules::antivirus::quarantine_stats();
+        let registry = crate::modules::ModuleRegistry::global();
+        let mut modules: Vec<ModuleStatsReport> = registry
+            .list_modules()
+            .into_iter()
+            .filter_map(|name| {
+                let metrics = registry.get_module_metrics(&name)?;
+                Some(ModuleStatsReport {
+                    name,
+                    requests: metrics.requests_total,
+                    error_rate: metrics.error_rate,
+                    avg_time_us: metrics.average_response_time.as_micros() as u64,
+                    healthy: registry.get_module(&name).is_some_and(|m| m.is_healthy()),
+                })
+            })
+            .collect();
+        modules.sort_by(|a, b| a.name.cmp(&b.name));
+
+        StatsReport {
+            server,
+            services,
+            pipelines: pipelines.map(|p| p.stats()).unwrap_or_default(),
+            quarantine: QuarantineReport {
+                files: quarantine.files(),
+                bytes: quarantine.bytes(),
+                failed: quarantine.failed(),
+            },
+            modules,
+        }
+    }
+}
@@ -0,0 +1,273 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Resource usage of the process
+//!
+//! The usage is sampled on each stats emission. On Linux procfs gives the
+//! resident memory, the open file descriptors and the CPU time of the
+//! process, and of each of its threads: the threads of a runtime are named
+//! after it, so their CPU time adds up to the one of the runtime. The tokio
+//! stats of the main and worker runtimes are read at the same time, on all
+//! platforms.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use arc_swap::ArcSwapOption;
+
+/// Name of the runtime of the threads not named after one
+const OTHER_RUNTIME: &str = "other";
+
+static SAMPLER: Mutex<Sampler> = Mutex::new(Sampler { last: None });
+static LATEST: ArcSwapOption<ResourceUsage> = ArcSwapOption::const_empty();
+
+/// Usage of a runtime
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RuntimeUsage {
+    /// Name of the runtime, `main` or `worker-<id>`
+    pub name: String,
+    /// CPU time since the previous sample, in CPUs
+    pub cpu: Option<f64>,
+    /// Worker threads of the runtime
+    pub workers: usize,
+    /// Tasks alive in the runtime
+    pub alive_tasks: usize,
+    /// Tasks queued in the global queue of the runtime
+    pub queued_tasks: usize,
+}
+
+/// Usage of the process
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResourceUsage {
+    /// Resident memory in bytes
+    pub memory: Option<u64>,
+    /// Open file descriptors
+    pub open_fds: Option<u64>,
+    /// CPU time since the previous sample, as a ratio of all the CPUs
+    pub cpu: Option<f64>,
+    pub runtimes: Vec<RuntimeUsage>,
+}
+
+/// CPU times at the previous sample
+struct Sampler {
+    last: Option<(Instant, f64, HashMap<String, f64>)>,
+}
+
+impl Sampler {
+    fn sample(&mut self) -> ResourceUsage {
+        let now = Instant::now();
+        let cpu_time = cpu_time();
+        let thread_times = proc::thread_cpu_times();
+
+        let mut usage = ResourceUsage {
+            memory: resident_memory(),
+            open_fds: proc::open_fds(),
+            ..Default::default()
+        };
+        let mut runtime_times: HashMap<String, f64> = HashMap::new();
+        for (name, time) in thread_times {
+            *runtime_times
+                .entry(runtime_name(&name).to_string())
+                .or_default() += time;
+        }
+
+        let last = self
+            .last
+            .as_ref()
+            .map(|(at, time, runtimes)| (now.duration_since(*at).as_secs_f64(), *time, runtimes))
+            .filter(|(wall, ..)| *wall > 0.0);
+        if let Some((wall, last_time, _)) = last {
+            let cpus = std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1) as f64;
+            usage.cpu = cpu_time.map(|time| (time - last_time).max(0.0) / (wall * cpus));
+        }
+        usage.runtimes = runtime_times
+            .iter()
+            .map(|(name, time)| RuntimeUsage {
+                name: name.clone(),
+                cpu: last.and_then(|(wall, _, runtimes)| {
+                    runtimes.get(name).map(|last| (time - last).max(0.0) / wall)
+                }),
+                ..Default::default()
+            })
+            .collect();
+        self.last = cpu_time.map(|time| (now, time, runtime_times));
+
+        for (name, handle) in runtime_handles() {
+            let metrics = handle.metrics();
+            let runtime = match usage.runtimes.iter_mut().find(|r| r.name == name) {
+                Some(runtime) => runtime,
+                None => {
+                    usage.runtimes.push(RuntimeUsage {
+                        name,
+                        ..Default::default()
+                    });
+                    usage.runtimes.last_mut().unwrap()
+                }
+            };
+            runtime.workers = metrics.num_workers();
+            runtime.alive_tasks = metrics.num_alive_tasks();
+            runtime.queued_tasks = metrics.global_queue_depth();
+        }
+        usage.runtimes.sort_by(|a, b| a.name.cmp(&b.name));
+        usage
+    }
+}
+
+/// Tokio handles of the runtimes, keyed by the names of their threads
+fn runtime_handles() -> Vec<(String, tokio::runtime::Handle)> {
+    let mut handles = Vec::new();
+    if let Some(handle) = g3_daemon::runtime::main_handle() {
+        handles.push(("main".to_string(), handle.clone()));
+    }
+    let _ = g3_daemon::runtime::worker::foreach(|worker| {
+        handles.push((format!("worker-{}", worker.id), worker.handle.clone()));
+        Ok::<(), ()>(())
+    });
+    handles
+}
+
+/// Name of the runtime of a thread, the runtime threads being named
+/// `<runtime>#<id>`
+fn runtime_name(thread: &str) -> &str {
+    match thread.split_once('#') {
+        Some((runtime, _)) if !runtime.is_empty() => runtime,
+        _ => OTHER_RUNTIME,
+    }
+}
+
+/// Sample the usage of the process, which is kept as the latest one
+pub fn sample() -> Arc<ResourceUsage> {
+    let usage = Arc::new(SAMPLER.lock().unwrap_or_else(|e| e.into_inner()).sample());
+    LATEST.store(Some(usage.clone()));
+    usage
+}
+
+/// The latest usage sampled
+pub fn latest() -> Option<Arc<ResourceUsage>> {
+    LATEST.load_full()
+}
+
+/// User and system CPU time of the process, in seconds
+pub(crate) fn cpu_time() -> Option<f64> {
+    proc::cpu_time()
+}
+
+/// Resident memory of the process, in bytes
+pub(crate) fn resident_memory() -> Option<u64> {
+    proc::resident_memory()
+}
+
+/// Name and CPU ticks of a thread, from its `stat` file
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_stat(stat: &str) -> Option<(&str, u64)> {
+    // the command name may contain spaces and parentheses
+    let (head, fields) = stat.rsplit_once(')')?;
+    let (_, name) = head.split_once('(')?;
+    let mut fields = fields.split_whitespace().skip(11);
+    let utime: u64 = fields.next()?.parse().ok()?;
+    let stime: u64 = fields.next()?.parse().ok()?;
+    Some((name, utime + stime))
+}
+
+#[cfg(target_os = "linux")]
+mod proc {
+    fn ticks_per_second() -> Option<f64> {
+        let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        (ticks > 0).then_some(ticks as f64)
+    }
+
+    pub(super) fn cpu_time() -> Option<f64> {
+        let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+        let (_, ticks) = super::parse_stat(&stat)?;
+        Some(ticks as f64 / ticks_per_second()?)
+    }
+
+    pub(super) fn resident_memory() -> Option<u64> {
+        let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+        let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        (page_size > 0).then(|| pages * page_size as u64)
+    }
+
+    pub(super) fn open_fds() -> Option<u64> {
+        let dir = std::fs::read_dir("/proc/self/fd").ok()?;
+        // the descriptor of the directory itself is counted
+        Some((dir.count() as u64).saturating_sub(1))
+    }
+
+    /// CPU time of each thread in seconds, with the name of the thread
+    pub(super) fn thread_cpu_times() -> Vec<(String, f64)> {
+        let (Ok(dir), Some(ticks)) = (std::fs::read_dir("/proc/self/task"), ticks_per_second())
+        else {
+            return Vec::new();
+        };
+        dir.flatten()
+            .filter_map(|entry| {
+                let stat = std::fs::read_to_string(entry.path().join("stat")).ok()?;
+                let (name, time) = super::parse_stat(&stat)?;
+                Some((name.to_string(), time as f64 / ticks))
+            })
+            .collect()
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod proc {
+    pub(super) fn cpu_time() -> Option<f64> {
+        None
+    }
+
+    pub(super) fn resident_memory() -> Option<u64> {
+        None
+    }
+
+    pub(super) fn open_fds() -> Option<u64> {
+        None
+    }
+
+    pub(super) fn thread_cpu_times() -> Vec<(String, f64)> {
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stat() {
+        let stat = "4242 (worker-0#3) S 1 4242 4242 0 -1 4194368 3528 0 0 0 17 5 0 0 20 0 4 0";
+        assert_eq!(parse_stat(stat), Some(("worker-0#3", 22)));
+        let stat = "4242 (a) b (c) R 1 4242 4242 0 -1 4194368 3528 0 0 0 1 2 0 0 20 0 4 0";
+        assert_eq!(parse_stat(stat), Some(("a) b (c", 3)));
+        assert_eq!(parse_stat("4242 (main#0) S 1"), None);
+    }
+
+    #[test]
+    fn runtimes() {
+        assert_eq!(runtime_name("main#0"), "main");
+        assert_eq!(runtime_name("worker-12#40"), "worker-12");
+        assert_eq!(runtime_name("stats-main"), OTHER_RUNTIME);
+        assert_eq!(runtime_name("#1"), OTHER_RUNTIME);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn sampling() {
+        let mut sampler = Sampler { last: None };
+        let first = sampler.sample();
+        assert!(first.memory.is_some_and(|m| m > 0));
+        assert!(first.open_fds.is_some());
+        assert_eq!(first.cpu, None);
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        let second = sampler.sample();
+        assert!(second.cpu.is_some());
+        assert!(second.runtimes.iter().any(|r| r.cpu.is_some()));
+    }
+}
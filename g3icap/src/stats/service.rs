@@ -0,0 +1,100 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Per service transaction statistics
+//!
+//! Only the services known to the server are counted. Without any service
+//! named in the config every ICAP URI path is a service, so past the first
+//! ones the transactions are all counted under a catch-all entry.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Services counted under their own name
+const MAX_SERVICES: usize = 256;
+/// Entry counting the services past the first ones
+pub const OTHER_SERVICES: &str = "-";
+
+/// Services that have had at least one transaction
+static SERVICES: Mutex<Vec<Arc<ServiceStats>>> = Mutex::new(Vec::new());
+
+/// Transaction statistics of a service
+#[derive(Debug)]
+pub struct ServiceStats {
+    name: String,
+    transactions: AtomicU64,
+    failed: AtomicU64,
+    /// Transaction time (microseconds)
+    total_time: AtomicU64,
+}
+
+impl ServiceStats {
+    fn new(name: String) -> Self {
+        ServiceStats {
+            name,
+            transactions: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            total_time: AtomicU64::new(0),
+        }
+    }
+
+    /// Name of the service, the ICAP URI path without slashes
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Count a transaction, `ok` if it was answered
+    pub fn add_transaction(&self, elapsed: Duration, ok: bool) {
+        self.transactions.fetch_add(1, Ordering::Relaxed);
+        if !ok {
+            self.failed.fetch_add(1, Ordering::Relaxed);
+        }
+        self.total_time
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Number of transactions
+    pub fn transactions(&self) -> u64 {
+        self.transactions.load(Ordering::Relaxed)
+    }
+
+    /// Number of transactions that failed without an answer
+    pub fn failed(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    /// Average transaction time (microseconds)
+    pub fn avg_time(&self) -> u64 {
+        match self.transactions() {
+            0 => 0,
+            n => self.total_time.load(Ordering::Relaxed) / n,
+        }
+    }
+}
+
+/// Get the stats of the service, an ICAP URI path
+pub fn get(service: &str) -> Arc<ServiceStats> {
+    let name = service.trim_matches('/');
+    let mut services = SERVICES.lock().unwrap();
+    if let Some(stats) = services.iter().find(|s| s.name == name) {
+        return stats.clone();
+    }
+    let name = if services.len() < MAX_SERVICES {
+        name
+    } else if let Some(other) = services.iter().find(|s| s.name == OTHER_SERVICES) {
+        return other.clone();
+    } else {
+        OTHER_SERVICES
+    };
+    let stats = Arc::new(ServiceStats::new(name.to_string()));
+    services.push(stats.clone());
+    stats
+}
+
+/// Get the stats of all services that have had a transaction
+pub fn all() -> Vec<Arc<ServiceStats>> {
+    SERVICES.lock().unwrap().clone()
+}
@@ -6,18 +6,24 @@
 //! Statistics thread for G3 ICAP Server
 //!
 //! This module handles periodic emission of statistics to StatsD following G3Proxy pattern.
+//!
+//! When asked to stop, the thread wakes up at once and emits a final snapshot
+//! tagged `final=true`. Stopping waits for it a bounded time only, a thread
+//! stuck on a wedged StatsD socket is left behind instead of blocking the
+//! shutdown.
 
-use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
 use std::thread::JoinHandle;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use anyhow::anyhow;
 use g3_statsd_client::{StatsdClient, StatsdClientConfig};
+use log::warn;
 
 use super::IcapStats;
 
-static QUIT_STAT_THREAD: AtomicBool = AtomicBool::new(false);
+const TAG_KEY_FINAL: &str = "final";
 
 fn build_statsd_client(config: &StatsdClientConfig) -> anyhow::Result<StatsdClient> {
     let client = config
@@ -29,34 +35,150 @@ fn build_statsd_client(config: &StatsdClientConfig) -> anyhow::Result<StatsdClie
     ))
 }
 
+/// Time of the next emission, keeping a fixed cadence
+///
+/// An emission overrunning its interval, under load or with a slow sink,
+/// does not lead to a burst of late emissions: the cadence restarts from
+/// `now`, so two emissions are always at least one interval apart.
+fn next_emission(scheduled: Instant, interval: Duration, now: Instant) -> Instant {
+    match scheduled.checked_add(interval) {
+        Some(next) if next > now => next,
+        _ => now + interval,
+    }
+}
+
+/// A running statistics thread
+pub struct StatsThread {
+    name: String,
+    handle: JoinHandle<()>,
+    quit: Sender<()>,
+    done: Receiver<()>,
+}
+
+impl StatsThread {
+    /// Spawn a thread calling `emit` every interval, with `true` for the
+    /// final emission
+    fn spawn<F>(name: &str, interval: Duration, mut emit: F) -> anyhow::Result<Self>
+    where
+        F: FnMut(bool) + Send + 'static,
+    {
+        let (quit, quit_receiver) = mpsc::channel();
+        let (done_sender, done) = mpsc::channel();
+        let handle = std::thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                let mut scheduled = Instant::now();
+                loop {
+                    emit(false);
+
+                    scheduled = next_emission(scheduled, interval, Instant::now());
+                    let wait = scheduled.saturating_duration_since(Instant::now());
+                    match quit_receiver.recv_timeout(wait) {
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Ok(()) | Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+                emit(true);
+                let _ = done_sender.send(());
+            })
+            .map_err(|e| anyhow!("failed to spawn thread: {e:?}"))?;
+        Ok(StatsThread {
+            name: name.to_string(),
+            handle,
+            quit,
+            done,
+        })
+    }
+
+    /// Ask the thread to emit its final snapshot and quit
+    ///
+    /// Returns false if the thread did not quit within `timeout`, it is then
+    /// detached.
+    pub fn stop(self, timeout: Duration) -> bool {
+        let _ = self.quit.send(());
+        match self.done.recv_timeout(timeout) {
+            Err(RecvTimeoutError::Timeout) => {
+                warn!(
+                    "stats thread {} did not quit in {timeout:?}, the statsd sink may be wedged",
+                    self.name
+                );
+                false
+            }
+            // the thread has finished, or panicked
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => {
+                let _ = self.handle.join();
+                true
+            }
+        }
+    }
+}
+
 /// Spawn statistics thread for periodic StatsD emission following G3Proxy pattern
-pub fn spawn_stats_thread(config: &StatsdClientConfig, stats: Arc<IcapStats>) -> anyhow::Result<JoinHandle<()>> {
+pub fn spawn_stats_thread(
+    config: &StatsdClientConfig,
+    stats: Arc<IcapStats>,
+) -> anyhow::Result<StatsThread> {
     let mut client = build_statsd_client(config)?;
-    let emit_duration = config.emit_interval;
-    
-    let handle = std::thread::Builder::new()
-        .name("g3icap-stat".to_string())
-        .spawn(move || {
-            loop {
-                let instant_start = Instant::now();
-
-                // Emit statistics to StatsD
-                stats.emit_stats(&mut client);
-                client.flush_sink();
-
-                if QUIT_STAT_THREAD.load(Ordering::Relaxed) {
-                    break;
-                }
+    let mut final_client = build_statsd_client(config)?.with_tag(TAG_KEY_FINAL, "true");
+
+    StatsThread::spawn("g3icap-stat", config.emit_interval, move |last| {
+        let client = if last { &mut final_client } else { &mut client };
+        stats.emit_stats(client);
+        client.flush_sink();
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
 
-                g3_daemon::stat::emit::wait_duration(emit_duration, instant_start);
+    #[test]
+    fn cadence() {
+        let start = Instant::now();
+        let interval = Duration::from_secs(10);
+        // on time
+        assert_eq!(
+            next_emission(start, interval, start + Duration::from_secs(1)),
+            start + interval
+        );
+        // overrun, the late emissions are skipped
+        let now = start + Duration::from_secs(25);
+        assert_eq!(next_emission(start, interval, now), now + interval);
+    }
+
+    #[test]
+    fn final_emission() {
+        let emitted = Arc::new(Mutex::new(Vec::new()));
+        let e = emitted.clone();
+        let thread = StatsThread::spawn("test-stat", Duration::from_secs(3600), move |last| {
+            e.lock().unwrap().push(last);
+        })
+        .unwrap();
+        while emitted.lock().unwrap().is_empty() {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        // the long interval is interrupted
+        let start = Instant::now();
+        assert!(thread.stop(Duration::from_secs(5)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(*emitted.lock().unwrap(), [false, true]);
+    }
+
+    #[test]
+    fn wedged_sink() {
+        let (release, wedged) = mpsc::channel::<()>();
+        let thread = StatsThread::spawn("test-stat", Duration::from_secs(3600), move |last| {
+            if last {
+                let _ = wedged.recv();
             }
         })
-        .map_err(|e| anyhow!("failed to spawn thread: {e:?}"))?;
-    
-    Ok(handle)
-}
+        .unwrap();
 
-/// Signal the statistics thread to quit
-pub fn quit_stats_thread() {
-    QUIT_STAT_THREAD.store(true, Ordering::Relaxed);
+        let start = Instant::now();
+        assert!(!thread.stop(Duration::from_millis(50)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        drop(release);
+    }
 }
@@ -0,0 +1,436 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Tracing of the ICAP transactions
+//!
+//! A sampled transaction has a root span, from the start of the connection
+//! processing to the response sent, with child spans for the parsing of the
+//! request, the pipeline of the service and each module call. The spans are
+//! queued to a dedicated thread exporting them with OTLP, so that a slow
+//! collector never delays a transaction. Spans that do not fit in the queue
+//! are dropped and counted. The export needs the `otlp` feature, and no new
+//! transaction is sampled while it is switched off at runtime.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+
+use http::HeaderMap;
+use log::warn;
+
+use crate::config::server::tracing::TracingConfig;
+
+#[cfg(feature = "otlp")]
+mod otlp;
+
+/// W3C trace context of a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceParent {
+    pub trace_id: u128,
+    pub span_id: u64,
+    pub sampled: bool,
+}
+
+impl TraceParent {
+    /// Parse a `traceparent` header value
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.trim().split('-');
+        let version = parts.next()?;
+        let trace_id = parts.next()?;
+        let span_id = parts.next()?;
+        let flags = parts.next()?;
+        // later versions may add fields, but version 00 has exactly four
+        if version.len() != 2 || version.eq_ignore_ascii_case("ff") {
+            return None;
+        }
+        if version == "00" && parts.next().is_some() {
+            return None;
+        }
+        let hex = |s: &str, len: usize| {
+            s.len() == len && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        };
+        if !hex(version, 2) || !hex(trace_id, 32) || !hex(span_id, 16) || !hex(flags, 2) {
+            return None;
+        }
+        let trace_id = u128::from_str_radix(trace_id, 16).ok()?;
+        let span_id = u64::from_str_radix(span_id, 16).ok()?;
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+        if trace_id == 0 || span_id == 0 {
+            return None;
+        }
+        Some(TraceParent {
+            trace_id,
+            span_id,
+            sampled: flags & 0x01 != 0,
+        })
+    }
+
+    /// The `traceparent` header value
+    pub fn to_header(&self) -> String {
+        format!(
+            "00-{:032x}-{:016x}-{:02x}",
+            self.trace_id,
+            self.span_id,
+            u8::from(self.sampled)
+        )
+    }
+}
+
+/// Value of a span attribute
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttrValue {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+}
+
+impl From<&str> for AttrValue {
+    fn from(v: &str) -> Self {
+        AttrValue::Str(v.to_string())
+    }
+}
+
+impl From<String> for AttrValue {
+    fn from(v: String) -> Self {
+        AttrValue::Str(v)
+    }
+}
+
+impl From<u16> for AttrValue {
+    fn from(v: u16) -> Self {
+        AttrValue::Int(i64::from(v))
+    }
+}
+
+impl From<u64> for AttrValue {
+    fn from(v: u64) -> Self {
+        AttrValue::Int(i64::try_from(v).unwrap_or(i64::MAX))
+    }
+}
+
+impl From<bool> for AttrValue {
+    fn from(v: bool) -> Self {
+        AttrValue::Bool(v)
+    }
+}
+
+/// A span being timed
+#[derive(Debug)]
+pub struct Span {
+    name: &'static str,
+    start: SystemTime,
+    started: Instant,
+    attributes: Vec<(&'static str, AttrValue)>,
+    error: Option<String>,
+}
+
+impl Span {
+    pub fn start(name: &'static str) -> Self {
+        Span {
+            name,
+            start: SystemTime::now(),
+            started: Instant::now(),
+            attributes: Vec::new(),
+            error: None,
+        }
+    }
+
+    pub fn set(&mut self, key: &'static str, value: impl Into<AttrValue>) {
+        self.attributes.push((key, value.into()));
+    }
+
+    pub fn with(mut self, key: &'static str, value: impl Into<AttrValue>) -> Self {
+        self.set(key, value);
+        self
+    }
+
+    /// Mark the span as failed
+    pub fn fail(&mut self, error: impl ToString) {
+        self.error = Some(error.to_string());
+    }
+
+    fn end(self, trace_id: u128, span_id: u64, parent_id: Option<u64>, kind: SpanKind) -> SpanData {
+        SpanData {
+            trace_id,
+            span_id,
+            parent_id,
+            name: self.name,
+            kind,
+            start: self.start,
+            // monotonic duration, the wall clock may step meanwhile
+            end: self.start + self.started.elapsed(),
+            attributes: self.attributes,
+            error: self.error,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SpanKind {
+    Internal,
+    Server,
+}
+
+/// A finished span, to be exported
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "otlp"), allow(dead_code))]
+pub(crate) struct SpanData {
+    trace_id: u128,
+    span_id: u64,
+    parent_id: Option<u64>,
+    name: &'static str,
+    kind: SpanKind,
+    start: SystemTime,
+    end: SystemTime,
+    attributes: Vec<(&'static str, AttrValue)>,
+    error: Option<String>,
+}
+
+fn new_span_id() -> u64 {
+    fastrand::u64(1..)
+}
+
+/// The trace of a sampled transaction
+pub struct TransactionTrace {
+    tracer: Arc<Tracer>,
+    trace_id: u128,
+    root_id: u64,
+    remote_parent: Option<u64>,
+    root: Span,
+    spans: Mutex<Vec<SpanData>>,
+}
+
+impl TransactionTrace {
+    /// The root span, covering the whole transaction
+    pub fn root(&mut self) -> &mut Span {
+        &mut self.root
+    }
+
+    /// End a child span of the root span
+    pub fn end(&self, span: Span) {
+        let data = span.end(
+            self.trace_id,
+            new_span_id(),
+            Some(self.root_id),
+            SpanKind::Internal,
+        );
+        if let Ok(mut spans) = self.spans.lock() {
+            spans.push(data);
+        }
+    }
+
+    /// End the root span and queue all the spans for export
+    pub fn finish(self) {
+        let mut spans = self.spans.into_inner().unwrap_or_default();
+        spans.push(self.root.end(
+            self.trace_id,
+            self.root_id,
+            self.remote_parent,
+            SpanKind::Server,
+        ));
+        for span in spans {
+            self.tracer.send(span);
+        }
+    }
+}
+
+/// Sampling and export of the transaction traces
+pub struct Tracer {
+    propagation_header: String,
+    sample_ratio: f64,
+    sender: SyncSender<SpanData>,
+    dropped: AtomicU64,
+}
+
+impl Tracer {
+    /// Start the thread exporting the spans to the collector of the config
+    ///
+    /// The thread exits once the tracer is dropped and the queue is empty.
+    pub fn spawn(config: &TracingConfig) -> std::io::Result<Self> {
+        let (sender, receiver) = mpsc::sync_channel(config.queue_size);
+        spawn_exporter(config, receiver)?;
+        Ok(Tracer {
+            propagation_header: config.propagation_header.clone(),
+            sample_ratio: config.sample_ratio,
+            sender,
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    /// Start the trace of a transaction, if it is sampled
+    ///
+    /// The trace context of the client is followed if the request has one,
+    /// otherwise the transaction is sampled with the configured ratio.
+    pub fn start(self: &Arc<Self>, headers: &HeaderMap, root: Span) -> Option<TransactionTrace> {
+        if !crate::features::OTLP.is_enabled() {
+            return None;
+        }
+        let parent = headers
+            .get(self.propagation_header.as_str())
+            .and_then(|v| v.to_str().ok())
+            .and_then(TraceParent::parse);
+        let sampled = match parent {
+            Some(parent) => parent.sampled,
+            None => self.sample_ratio >= 1.0 || fastrand::f64() < self.sample_ratio,
+        };
+        if !sampled {
+            return None;
+        }
+        Some(TransactionTrace {
+            tracer: self.clone(),
+            trace_id: parent
+                .map(|p| p.trace_id)
+                .unwrap_or_else(|| fastrand::u128(1..)),
+            root_id: new_span_id(),
+            remote_parent: parent.map(|p| p.span_id),
+            root,
+            spans: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn send(&self, span: SpanData) {
+        match self.sender.try_send(span) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                if dropped.is_power_of_two() {
+                    warn!("trace export queue full, {dropped} spans dropped");
+                }
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Number of spans dropped as the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(feature = "otlp")]
+fn spawn_exporter(
+    config: &TracingConfig,
+    receiver: mpsc::Receiver<SpanData>,
+) -> std::io::Result<()> {
+    let exporter = otlp::OtlpExporter::new(config);
+    let batch_size = config.batch_size;
+    let flush_interval = config.flush_interval;
+    std::thread::Builder::new()
+        .name("trace-export".to_string())
+        .spawn(move || exporter.run(receiver, batch_size, flush_interval))?;
+    Ok(())
+}
+
+#[cfg(not(feature = "otlp"))]
+fn spawn_exporter(
+    _config: &TracingConfig,
+    _receiver: mpsc::Receiver<SpanData>,
+) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "the trace export needs the otlp feature",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn tracer(sample_ratio: f64) -> (Arc<Tracer>, mpsc::Receiver<SpanData>) {
+        let (sender, receiver) = mpsc::sync_channel(16);
+        let tracer = Tracer {
+            propagation_header: "traceparent".to_string(),
+            sample_ratio,
+            sender,
+            dropped: AtomicU64::new(0),
+        };
+        (Arc::new(tracer), receiver)
+    }
+
+    #[test]
+    fn traceparent() {
+        let s = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let parent = TraceParent::parse(s).unwrap();
+        assert_eq!(parent.trace_id, 0x4bf92f3577b34da6a3ce929d0e0e4736);
+        assert_eq!(parent.span_id, 0x00f067aa0ba902b7);
+        assert!(parent.sampled);
+        assert_eq!(parent.to_header(), s);
+
+        // future versions may have more fields
+        assert!(TraceParent::parse(&format!("01{}-extra", &s[2..])).is_some());
+        for invalid in [
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+        ] {
+            assert_eq!(TraceParent::parse(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "otlp")]
+    fn sampling() {
+        let (never, _receiver) = tracer(0.0);
+        let mut headers = HeaderMap::new();
+        assert!(never.start(&headers, Span::start("t")).is_none());
+
+        // the decision of the client is followed
+        headers.insert(
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+                .parse()
+                .unwrap(),
+        );
+        let trace = never.start(&headers, Span::start("t")).unwrap();
+        assert_eq!(trace.trace_id, 0x4bf92f3577b34da6a3ce929d0e0e4736);
+        assert_eq!(trace.remote_parent, Some(0x00f067aa0ba902b7));
+
+        let (follower, _receiver) = tracer(1.0);
+        headers.insert(
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00"
+                .parse()
+                .unwrap(),
+        );
+        assert!(follower.start(&headers, Span::start("t")).is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "otlp")]
+    fn spans() {
+        let (tracer, receiver) = tracer(1.0);
+        let mut trace = tracer
+            .start(&HeaderMap::new(), Span::start("icap.transaction"))
+            .unwrap();
+        let mut span = Span::start("icap.module").with("icap.module", "antivirus");
+        std::thread::sleep(Duration::from_millis(2));
+        span.fail("scan timed out");
+        trace.end(span);
+        trace.root().set("icap.status", 204u16);
+        let root_id = trace.root_id;
+        trace.finish();
+
+        let spans: Vec<SpanData> = receiver.try_iter().collect();
+        assert_eq!(spans.len(), 2);
+        let (child, root) = (&spans[0], &spans[1]);
+        assert_eq!(root.name, "icap.transaction");
+        assert_eq!(root.kind, SpanKind::Server);
+        assert_eq!(root.parent_id, None);
+        assert_eq!(root.span_id, root_id);
+        assert_eq!(root.attributes, [("icap.status", AttrValue::Int(204))]);
+        assert_eq!(child.trace_id, root.trace_id);
+        assert_eq!(child.parent_id, Some(root.span_id));
+        assert_eq!(child.error.as_deref(), Some("scan timed out"));
+        assert!(child.end.duration_since(child.start).unwrap() >= Duration::from_millis(2));
+    }
+}
@@ -0,0 +1,318 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! OTLP/HTTP export of the spans
+//!
+//! Spans are posted in batches with the JSON encoding of OTLP, in which the
+//! trace and span ids are hex strings. A batch that fails to be exported is
+//! dropped, the collector is only a diagnostic aid.
+
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, anyhow};
+use log::warn;
+use serde_json::{Value, json};
+use url::Url;
+
+use super::{AttrValue, SpanData, SpanKind};
+use crate::config::server::tracing::TracingConfig;
+
+/// Size read for the response status line
+const MAX_STATUS_SIZE: u64 = 4096;
+
+/// OTLP status code of a failed span
+const STATUS_CODE_ERROR: u8 = 2;
+
+pub(super) struct OtlpExporter {
+    endpoint: Url,
+    service_name: String,
+    timeout: Duration,
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn encode_value(value: &AttrValue) -> Value {
+    match value {
+        AttrValue::Str(s) => json!({ "stringValue": s }),
+        // 64 bits integers are strings in the JSON encoding
+        AttrValue::Int(i) => json!({ "intValue": i.to_string() }),
+        AttrValue::Bool(b) => json!({ "boolValue": b }),
+    }
+}
+
+fn encode_span(span: &SpanData) -> Value {
+    let attributes: Vec<Value> = span
+        .attributes
+        .iter()
+        .map(|(key, value)| json!({ "key": key, "value": encode_value(value) }))
+        .collect();
+    let mut value = json!({
+        "traceId": format!("{:032x}", span.trace_id),
+        "spanId": format!("{:016x}", span.span_id),
+        "name": span.name,
+        "kind": match span.kind {
+            SpanKind::Internal => 1,
+            SpanKind::Server => 2,
+        },
+        "startTimeUnixNano": unix_nanos(span.start),
+        "endTimeUnixNano": unix_nanos(span.end),
+        "attributes": attributes,
+    });
+    if let Some(parent_id) = span.parent_id {
+        value["parentSpanId"] = json!(format!("{parent_id:016x}"));
+    }
+    if let Some(error) = &span.error {
+        value["status"] = json!({ "code": STATUS_CODE_ERROR, "message": error });
+    }
+    value
+}
+
+impl OtlpExporter {
+    pub(super) fn new(config: &TracingConfig) -> Self {
+        OtlpExporter {
+            endpoint: config.endpoint.clone(),
+            service_name: config.service_name.clone(),
+            timeout: config.timeout,
+        }
+    }
+
+    fn encode(&self, spans: &[SpanData]) -> Value {
+        let spans: Vec<Value> = spans.iter().map(encode_span).collect();
+        json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": [{
+                        "key": "service.name",
+                        "value": { "stringValue": self.service_name },
+                    }],
+                },
+                "scopeSpans": [{
+                    "scope": { "name": "g3icap", "version": crate::version::VERSION },
+                    "spans": spans,
+                }],
+            }],
+        })
+    }
+
+    fn export(&self, spans: &[SpanData]) -> anyhow::Result<()> {
+        let body = self.encode(spans).to_string();
+        let host = self
+            .endpoint
+            .host_str()
+            .ok_or_else(|| anyhow!("no host in url"))?;
+        let port = self
+            .endpoint
+            .port_or_known_default()
+            .ok_or_else(|| anyhow!("no port in url"))?;
+        let host_header = match self.endpoint.port() {
+            Some(port) => format!("{host}:{port}"),
+            None => host.to_string(),
+        };
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {host_header}\r\nUser-Agent: g3icap\r\n\
+             Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            self.endpoint.path(),
+            body.len()
+        );
+
+        let addr = (host.trim_matches(['[', ']']), port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow!("no address for {host}"))?;
+        let mut stream =
+            TcpStream::connect_timeout(&addr, self.timeout).context("failed to connect")?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        stream
+            .write_all(request.as_bytes())
+            .context("failed to send request")?;
+
+        let mut response = Vec::new();
+        let mut reader = stream.take(MAX_STATUS_SIZE);
+        let mut buf = [0u8; 1024];
+        while !response.windows(2).any(|w| w == b"\r\n") {
+            let n = reader.read(&mut buf).context("failed to read response")?;
+            if n == 0 {
+                break;
+            }
+            response.extend_from_slice(&buf[..n]);
+        }
+        let line_end = response
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or_else(|| anyhow!("incomplete response status line"))?;
+        let status_line = String::from_utf8_lossy(&response[..line_end]);
+        match status_line.split(' ').nth(1).map(|s| s.parse::<u16>()) {
+            Some(Ok(200..=299)) => Ok(()),
+            _ => Err(anyhow!("unexpected response status: {status_line}")),
+        }
+    }
+
+    fn flush(&self, batch: &mut Vec<SpanData>, failing: &mut bool) {
+        if batch.is_empty() {
+            return;
+        }
+        match self.export(batch) {
+            Ok(()) => *failing = false,
+            Err(e) => {
+                // only the first error of a failing collector is logged
+                if !*failing {
+                    warn!(
+                        "failed to export {} spans to {}: {e:?}",
+                        batch.len(),
+                        self.endpoint
+                    );
+                    *failing = true;
+                }
+            }
+        }
+        batch.clear();
+    }
+
+    /// Export the queued spans until the tracer is dropped
+    pub(super) fn run(
+        self,
+        receiver: Receiver<SpanData>,
+        batch_size: usize,
+        flush_interval: Duration,
+    ) {
+        let mut batch = Vec::with_capacity(batch_size);
+        let mut failing = false;
+        let mut deadline = Instant::now() + flush_interval;
+        loop {
+            let wait = deadline.saturating_duration_since(Instant::now());
+            match receiver.recv_timeout(wait) {
+                Ok(span) => {
+                    batch.push(span);
+                    if batch.len() < batch_size {
+                        continue;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.flush(&mut batch, &mut failing);
+                    return;
+                }
+            }
+            self.flush(&mut batch, &mut failing);
+            deadline = Instant::now() + flush_interval;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    fn span(span_id: u64, parent_id: Option<u64>, error: Option<&str>) -> SpanData {
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        SpanData {
+            trace_id: 0x4bf92f3577b34da6a3ce929d0e0e4736,
+            span_id,
+            parent_id,
+            name: "icap.module",
+            kind: SpanKind::Internal,
+            start,
+            end: start + Duration::from_millis(3),
+            attributes: vec![
+                ("icap.module", AttrValue::Str("antivirus".to_string())),
+                ("icap.status", AttrValue::Int(204)),
+            ],
+            error: error.map(|e| e.to_string()),
+        }
+    }
+
+    fn config(endpoint: &str) -> TracingConfig {
+        let yaml = yaml_rust::YamlLoader::load_from_str(&format!("endpoint: {endpoint}"))
+            .unwrap()
+            .remove(0);
+        TracingConfig::parse(&yaml).unwrap()
+    }
+
+    #[test]
+    fn encoding() {
+        let exporter = OtlpExporter::new(&config("http://127.0.0.1:4318"));
+        assert_eq!(exporter.endpoint.path(), "/v1/traces");
+        let value = exporter.encode(&[span(0xf067aa0ba902b7, Some(1), Some("timed out"))]);
+        let scope = &value["resourceSpans"][0];
+        assert_eq!(
+            scope["resource"]["attributes"][0]["value"]["stringValue"],
+            "g3icap"
+        );
+        let encoded = &scope["scopeSpans"][0]["spans"][0];
+        assert_eq!(encoded["traceId"], "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(encoded["spanId"], "00f067aa0ba902b7");
+        assert_eq!(encoded["parentSpanId"], "0000000000000001");
+        assert_eq!(encoded["kind"], 1);
+        assert_eq!(encoded["startTimeUnixNano"], "1700000000000000000");
+        assert_eq!(encoded["endTimeUnixNano"], "1700000000003000000");
+        assert_eq!(encoded["attributes"][1]["value"]["intValue"], "204");
+        assert_eq!(encoded["status"]["code"], 2);
+        assert_eq!(encoded["status"]["message"], "timed out");
+
+        let value = exporter.encode(&[span(2, None, None)]);
+        let encoded = &value["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+        assert!(encoded.get("parentSpanId").is_none());
+        assert!(encoded.get("status").is_none());
+    }
+
+    #[test]
+    fn export() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let collector = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 4096];
+                loop {
+                    let n = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request);
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let len: usize = head
+                            .lines()
+                            .find_map(|l| l.strip_prefix("Content-Length: "))
+                            .unwrap()
+                            .parse()
+                            .unwrap();
+                        if body.len() >= len {
+                            break;
+                        }
+                    }
+                }
+                stream.write_all(b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
+                requests.push(String::from_utf8(request).unwrap());
+            }
+            requests
+        });
+
+        let exporter = OtlpExporter::new(&config(&format!("http://{addr}")));
+        let (sender, receiver) = mpsc::sync_channel(8);
+        let thread = std::thread::spawn(move || exporter.run(receiver, 2, Duration::from_secs(60)));
+        // a full batch is sent at once, the rest when the tracer is dropped
+        for id in 1..=3 {
+            sender.send(span(id, None, None)).unwrap();
+        }
+        drop(sender);
+        thread.join().unwrap();
+
+        let requests = collector.join().unwrap();
+        assert!(requests[0].starts_with("POST /v1/traces HTTP/1.1\r\n"));
+        assert_eq!(requests[0].matches("\"spanId\"").count(), 2);
+        assert_eq!(requests[1].matches("\"spanId\"").count(), 1);
+    }
+}
@@ -0,0 +1,343 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Context of an ICAP transaction
+//!
+//! The context is created by the connection once the request is parsed, and
+//! given by reference to the pipeline, the modules and the audit record. It
+//! carries what identifies the transaction, its end user and its service,
+//! when it must be done by and whether it was cancelled, along with the
+//! labels and the module verdicts gathered while it is processed.
+//!
+//! Each transaction gets a unique id. Its request id is the one sent by the
+//! client in the `icap-request-id` header, e.g. the id of the HTTP
+//! transaction in the proxy logs, or else a generated one. The request id
+//! is sent back in the responses and written to the logs and audit records
+//! for the transaction to be found on both sides.
+//!
+//! The connection takes the request id as soon as the ICAP header section is
+//! received, so that the error responses to a request rejected before its
+//! context is created carry it as well.
+
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use crate::audit::record::AuditVerdict;
+use crate::auth::identity::{ClientIdentity, HEADER_CLIENT_TENANT};
+use crate::modules::ModuleError;
+use crate::protocol::common::{IcapMethod, IcapRequest, IcapResponse};
+
+/// Header of the id of the request, in the requests and the responses
+pub const HEADER_REQUEST_ID: &str = "icap-request-id";
+
+/// Longest request id accepted from the client
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Verdict of a module on a transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleVerdict {
+    pub module: String,
+    pub verdict: AuditVerdict,
+}
+
+/// Context of an ICAP transaction
+#[derive(Debug)]
+pub struct TransactionCtx {
+    id: String,
+    request_id: String,
+    peer: SocketAddr,
+    identity: ClientIdentity,
+    tenant: Option<String>,
+    service: String,
+    method: IcapMethod,
+    deadline: Option<Instant>,
+    cancel: CancellationToken,
+    labels: Mutex<BTreeMap<String, String>>,
+    verdicts: Mutex<Vec<ModuleVerdict>>,
+}
+
+impl TransactionCtx {
+    /// Create the context of a request received from the peer
+    pub fn new(peer: SocketAddr, request: &IcapRequest) -> Self {
+        let tenant = request
+            .headers
+            .get(HEADER_CLIENT_TENANT)
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string());
+        let id = new_id();
+        let request_id = request
+            .headers
+            .get(HEADER_REQUEST_ID)
+            .and_then(|v| v.to_str().ok())
+            .and_then(client_request_id)
+            .map(|s| s.to_string());
+        TransactionCtx {
+            request_id: request_id.unwrap_or_else(|| id.clone()),
+            id,
+            peer,
+            identity: ClientIdentity::from_headers(&request.headers),
+            tenant,
+            service: request.uri.path().to_string(),
+            method: request.method.clone(),
+            deadline: None,
+            cancel: CancellationToken::new(),
+            labels: Mutex::default(),
+            verdicts: Mutex::default(),
+        }
+    }
+
+    /// Create the context of a request handled outside of a connection,
+    /// e.g. in tests or when embedding the modules
+    pub fn for_request(request: &IcapRequest) -> Self {
+        TransactionCtx::new(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)), request)
+    }
+
+    /// Cancel the transaction with the token
+    pub fn with_cancel(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Set the request id, the one taken by the connection when the request
+    /// was received
+    pub fn with_request_id(mut self, request_id: String) -> Self {
+        self.request_id = request_id;
+        self
+    }
+
+    /// Set the time the transaction must be done by
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Id of the transaction, the one `handle_cancel` is called with
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Id of the request, the one of the client if it sent one
+    ///
+    /// Unlike the transaction id, it is not unique: a client may send the
+    /// same id in the REQMOD and the RESPMOD of an HTTP transaction.
+    pub fn request_id(&self) -> &str {
+        &self.request_id
+    }
+
+    /// Address of the ICAP client, the proxy
+    pub fn peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// Identity of the end user, as forwarded by the proxy
+    pub fn identity(&self) -> &ClientIdentity {
+        &self.identity
+    }
+
+    /// Tenant of the end user, as forwarded by the proxy
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    /// ICAP service, the path of the ICAP URI
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+
+    pub fn method(&self) -> &IcapMethod {
+        &self.method
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Time left before the deadline, if any
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|d| d.saturating_duration_since(Instant::now()))
+    }
+
+    /// Token cancelled when the transaction times out or is aborted
+    pub fn cancel_token(&self) -> &CancellationToken {
+        &self.cancel
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// Cancel the transaction, the work still running for it stops
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    /// Set a label of the transaction, replacing any previous value
+    pub fn set_label(&self, name: impl Into<String>, value: impl Into<String>) {
+        if let Ok(mut labels) = self.labels.lock() {
+            labels.insert(name.into(), value.into());
+        }
+    }
+
+    /// Labels of the transaction, sorted by name
+    pub fn labels(&self) -> BTreeMap<String, String> {
+        self.labels.lock().map(|l| l.clone()).unwrap_or_default()
+    }
+
+    /// Add the verdict of a module
+    pub fn add_verdict(&self, module: &str, verdict: AuditVerdict) {
+        if let Ok(mut verdicts) = self.verdicts.lock() {
+            verdicts.push(ModuleVerdict {
+                module: module.to_string(),
+                verdict,
+            });
+        }
+    }
+
+    /// Add the verdict of a module from its result
+    pub fn record_module(&self, module: &str, result: &Result<IcapResponse, ModuleError>) {
+        let verdict = match result {
+            Ok(response) => AuditVerdict::of_response(response),
+            Err(_) => AuditVerdict::Error,
+        };
+        self.add_verdict(module, verdict);
+    }
+
+    /// Verdicts of the modules, in the order they were added
+    pub fn verdicts(&self) -> Vec<ModuleVerdict> {
+        self.verdicts.lock().map(|v| v.clone()).unwrap_or_default()
+    }
+}
+
+/// Generate a unique id, for a transaction or a request without one
+pub fn new_id() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+/// Get the request id sent by the client in the header value, if it can be
+/// written as it is to the logs
+pub fn client_request_id(value: &str) -> Option<&str> {
+    let id = value.trim();
+    let valid = !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id.bytes().all(|b| b.is_ascii_graphic());
+    valid.then_some(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use http::{HeaderMap, StatusCode, Version};
+
+    fn request() -> IcapRequest {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-client-username", "alice".parse().unwrap());
+        headers.insert("x-client-groups", "staff, sales".parse().unwrap());
+        headers.insert(HEADER_CLIENT_TENANT, " acme ".parse().unwrap());
+        IcapRequest {
+            method: IcapMethod::Reqmod,
+            uri: "icap://icap.example.net/reqmod".parse().unwrap(),
+            version: Version::HTTP_11,
+            headers,
+            body: Bytes::new(),
+            encapsulated: None,
+        }
+    }
+
+    #[test]
+    fn new() {
+        let ctx = TransactionCtx::new("10.0.0.1:40000".parse().unwrap(), &request());
+        assert_eq!(ctx.id().len(), 32);
+        assert_eq!(ctx.peer().port(), 40000);
+        assert_eq!(ctx.identity().username.as_deref(), Some("alice"));
+        assert_eq!(ctx.identity().groups, ["staff", "sales"]);
+        assert_eq!(ctx.tenant(), Some("acme"));
+        assert_eq!(ctx.service(), "/reqmod");
+        assert_eq!(ctx.method(), &IcapMethod::Reqmod);
+        assert!(ctx.remaining().is_none());
+
+        let other = TransactionCtx::for_request(&request());
+        assert_ne!(ctx.id(), other.id());
+        assert_eq!(ctx.request_id(), ctx.id());
+    }
+
+    #[test]
+    fn request_id() {
+        let mut request = request();
+        request
+            .headers
+            .insert(HEADER_REQUEST_ID, " 7f3a-1c2b ".parse().unwrap());
+        let ctx = TransactionCtx::for_request(&request);
+        assert_eq!(ctx.request_id(), "7f3a-1c2b");
+        assert_eq!(ctx.id().len(), 32);
+
+        // not written as is to the logs
+        let long = "x".repeat(MAX_REQUEST_ID_LEN + 1);
+        for invalid in ["a b", "", long.as_str()] {
+            request
+                .headers
+                .insert(HEADER_REQUEST_ID, invalid.parse().unwrap());
+            let ctx = TransactionCtx::for_request(&request);
+            assert_eq!(ctx.request_id(), ctx.id());
+        }
+
+        let ctx = TransactionCtx::for_request(&request).with_request_id("c0ffee-42".to_string());
+        assert_eq!(ctx.request_id(), "c0ffee-42");
+        assert_ne!(ctx.id(), "c0ffee-42");
+    }
+
+    #[test]
+    fn cancel() {
+        let token = CancellationToken::new();
+        let ctx = TransactionCtx::for_request(&request()).with_cancel(token.child_token());
+        assert!(!ctx.is_cancelled());
+        token.cancel();
+        assert!(ctx.is_cancelled());
+    }
+
+    #[test]
+    fn verdicts() {
+        let ctx = TransactionCtx::for_request(&request());
+        ctx.set_label("pipeline", "default");
+        ctx.set_label("pipeline", "strict");
+        assert_eq!(
+            ctx.labels().get("pipeline").map(|s| s.as_str()),
+            Some("strict")
+        );
+
+        let mut response = IcapResponse {
+            status: StatusCode::NO_CONTENT,
+            version: Version::HTTP_11,
+            headers: HeaderMap::new(),
+            body: Bytes::new(),
+            encapsulated: None,
+        };
+        ctx.record_module("waf", &Ok(response.clone()));
+        response.status = StatusCode::FORBIDDEN;
+        ctx.record_module("content_filter", &Ok(response));
+        ctx.record_module("antivirus", &Err(ModuleError::Cancelled));
+        let verdicts: Vec<_> = ctx
+            .verdicts()
+            .into_iter()
+            .map(|v| (v.module, v.verdict))
+            .collect();
+        assert_eq!(
+            verdicts,
+            [
+                ("waf".to_string(), AuditVerdict::Allowed),
+                ("content_filter".to_string(), AuditVerdict::Blocked),
+                ("antivirus".to_string(), AuditVerdict::Error),
+            ]
+        );
+    }
+}
@@ -1,5 +1,12 @@
 //! Version information for G3 ICAP Server
 
+use std::sync::Mutex;
+
+use serde::Serialize;
+
+use crate::features::FeatureStatus;
+use crate::protocol::limits::ProtocolLimits;
+
 /// The version of G3 ICAP Server
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -8,3 +15,132 @@ pub const NAME: &str = "g3icap";
 
 /// The description of the G3 ICAP Server
 pub const DESCRIPTION: &str = "G3 ICAP Server for content adaptation and filtering";
+
+/// The git commit the server was built from
+pub const GIT_COMMIT: Option<&str> = option_env!("G3ICAP_GIT_COMMIT");
+
+const PACKAGE_VERSION: Option<&str> = option_env!("G3_PACKAGE_VERSION");
+const RUSTC_VERSION: &str = env!("G3_BUILD_RUSTC_VERSION");
+const BUILD_TARGET: &str = env!("G3_BUILD_TARGET");
+const BUILD_PROFILE: &str = env!("G3_BUILD_PROFILE");
+
+const OPENSSL_VARIANT: Option<&str> = option_env!("G3_OPENSSL_VARIANT");
+const RUSTLS_PROVIDER: Option<&str> = option_env!("G3_RUSTLS_PROVIDER");
+
+/// Modules compiled into the server
+pub const BUILTIN_MODULES: &[&str] = &[
+    "echo",
+    "content_filter",
+    "antivirus",
+    #[cfg(feature = "cdr")]
+    "cdr",
+];
+
+/// Effective limits and loaded modules of the running server
+static RUNTIME: Mutex<Option<(ProtocolLimits, Vec<ModuleVersion>)>> = Mutex::new(None);
+
+/// Cargo features the server was built with
+fn features() -> Vec<&'static str> {
+    let mut features = Vec::new();
+    if cfg!(feature = "lua") {
+        features.push("lua");
+    }
+    if cfg!(feature = "python") {
+        features.push("python");
+    }
+    if cfg!(feature = "c-ares") {
+        features.push("c-ares");
+    }
+    if cfg!(feature = "rustls-ring") {
+        features.push("rustls-ring");
+    }
+    if cfg!(feature = "rustls-aws-lc") {
+        features.push("rustls-aws-lc");
+    }
+    if cfg!(feature = "rustls-aws-lc-fips") {
+        features.push("rustls-aws-lc-fips");
+    }
+    for feature in crate::features::all() {
+        if feature.is_compiled() {
+            features.push(feature.name());
+        }
+    }
+    features
+}
+
+/// TLS libraries the server was built with
+#[derive(Debug, Clone, Serialize)]
+pub struct TlsBackend {
+    pub openssl_variant: Option<&'static str>,
+    pub rustls_provider: Option<&'static str>,
+}
+
+/// Version of a loaded module
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ModuleVersion {
+    pub name: String,
+    pub version: String,
+}
+
+/// Machine readable version report
+#[derive(Debug, Clone, Serialize)]
+pub struct VersionReport {
+    pub name: &'static str,
+    pub version: &'static str,
+    pub git_commit: Option<&'static str>,
+    pub package_version: Option<&'static str>,
+    pub rustc: &'static str,
+    pub target: &'static str,
+    pub profile: &'static str,
+    pub features: Vec<&'static str>,
+    /// Optional capabilities, with whether they are compiled in and enabled
+    pub capabilities: Vec<FeatureStatus>,
+    pub tls: TlsBackend,
+    pub builtin_modules: &'static [&'static str],
+    pub protocol_limits: ProtocolLimits,
+    pub loaded_modules: Vec<ModuleVersion>,
+}
+
+impl VersionReport {
+    /// Report of this build, with the default protocol limits
+    pub fn build() -> Self {
+        VersionReport {
+            name: NAME,
+            version: VERSION,
+            git_commit: GIT_COMMIT,
+            package_version: PACKAGE_VERSION,
+            rustc: RUSTC_VERSION,
+            target: BUILD_TARGET,
+            profile: BUILD_PROFILE,
+            features: features(),
+            capabilities: crate::features::report(),
+            tls: TlsBackend {
+                openssl_variant: OPENSSL_VARIANT,
+                rustls_provider: RUSTLS_PROVIDER,
+            },
+            builtin_modules: BUILTIN_MODULES,
+            protocol_limits: ProtocolLimits::default(),
+            loaded_modules: Vec::new(),
+        }
+    }
+
+    /// Report of the running server, with its effective limits and loaded modules
+    pub fn current() -> Self {
+        let mut report = Self::build();
+        if let Some((limits, modules)) = RUNTIME.lock().unwrap().as_ref() {
+            report.protocol_limits = *limits;
+            report.loaded_modules = modules.clone();
+        }
+        report
+    }
+
+    /// Serialize the report as a single line of JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// Record the effective limits and loaded modules of the running server
+pub fn set_runtime(limits: ProtocolLimits, modules: Vec<ModuleVersion>) {
+    *RUNTIME.lock().unwrap() = Some((limits, modules));
+}
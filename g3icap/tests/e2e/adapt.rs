@@ -0,0 +1,190 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Adaptation of HTTP messages by the server under test
+//!
+//! The messages are sent with g3-icap-client, the ICAP client g3proxy uses,
+//! so that the server is driven the way it is in a deployment: the service
+//! options are fetched first and the body is previewed if they ask for it.
+
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use http::Method;
+use tokio::io::{AsyncWrite, BufReader};
+use tokio::time::Instant;
+
+use g3_http::client::HttpTransparentResponse;
+use g3_http::server::HttpTransparentRequest;
+use g3_icap_client::IcapServiceClient;
+use g3_icap_client::reqmod::IcapReqmodClient;
+use g3_icap_client::reqmod::h1::{
+    H1ReqmodAdaptationError, HttpRequestUpstreamWriter, ReqmodAdaptationEndState,
+    ReqmodAdaptationRunState,
+};
+use g3_icap_client::respmod::IcapRespmodClient;
+use g3_icap_client::respmod::h1::{
+    H1RespmodAdaptationError, RespmodAdaptationEndState, RespmodAdaptationRunState,
+};
+use g3_io_ext::{IdleCheck, IdleForceQuitReason, IdleInterval, IdleWheel, StreamCopyConfig};
+
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+const BODY_LINE_MAX_SIZE: usize = 8192;
+/// The adaptation is given up after this many idle seconds
+const MAX_IDLE_COUNT: usize = 30;
+
+/// Outcome of an adaptation
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// The original message was passed on, as sent to the next hop
+    Original(Vec<u8>),
+    /// The message was modified by the server
+    Adapted,
+    /// The message was refused with this ICAP, or HTTP for REQMOD, status
+    Blocked(u16),
+}
+
+struct IdleChecker {
+    wheel: Arc<IdleWheel>,
+}
+
+impl IdleChecker {
+    fn new() -> Self {
+        IdleChecker {
+            wheel: IdleWheel::spawn(Duration::from_secs(1)),
+        }
+    }
+}
+
+impl IdleCheck for IdleChecker {
+    fn interval_timer(&self) -> IdleInterval {
+        self.wheel.register()
+    }
+
+    fn check_quit(&self, idle_count: usize) -> bool {
+        idle_count > MAX_IDLE_COUNT
+    }
+
+    fn check_force_quit(&self) -> Option<IdleForceQuitReason> {
+        None
+    }
+}
+
+/// Upstream of the adapted requests, keeping what is sent to it
+#[derive(Default)]
+struct Upstream(Vec<u8>);
+
+impl AsyncWrite for Upstream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+impl HttpRequestUpstreamWriter<HttpTransparentRequest> for Upstream {
+    async fn send_request_header(&mut self, req: &HttpTransparentRequest) -> io::Result<()> {
+        self.0.extend_from_slice(&req.serialize_for_origin());
+        Ok(())
+    }
+}
+
+async fn parse_request(request: &[u8]) -> anyhow::Result<HttpTransparentRequest> {
+    let mut reader = BufReader::new(request);
+    let (request, _) = HttpTransparentRequest::parse(&mut reader, MAX_HEADER_SIZE, false)
+        .await
+        .map_err(|e| anyhow!("invalid http request: {e}"))?;
+    Ok(request)
+}
+
+/// Adapt a request without body
+pub async fn reqmod(client: &Arc<IcapServiceClient>, request: &str) -> anyhow::Result<Verdict> {
+    let http_request = parse_request(request.as_bytes()).await?;
+    let adapter = IcapReqmodClient::new(client.clone())
+        .h1_adapter(
+            StreamCopyConfig::default(),
+            BODY_LINE_MAX_SIZE,
+            true,
+            IdleChecker::new(),
+        )
+        .await?;
+
+    let mut state = ReqmodAdaptationRunState::new(Instant::now());
+    let mut upstream = Upstream::default();
+    let result = adapter
+        .xfer(
+            &mut state,
+            &http_request,
+            None::<&mut BufReader<&[u8]>>,
+            &mut upstream,
+        )
+        .await;
+    match result {
+        Ok(ReqmodAdaptationEndState::OriginalTransferred) => Ok(Verdict::Original(upstream.0)),
+        Ok(ReqmodAdaptationEndState::AdaptedTransferred(_)) => Ok(Verdict::Adapted),
+        Ok(ReqmodAdaptationEndState::HttpErrResponse(rsp, _)) => {
+            Ok(Verdict::Blocked(rsp.status.as_u16()))
+        }
+        Err(H1ReqmodAdaptationError::IcapServerErrorResponse(_, code, _)) => {
+            Ok(Verdict::Blocked(code))
+        }
+        Err(e) => Err(anyhow!("reqmod failed: {e}")),
+    }
+}
+
+/// Adapt the response, with its body, to a request
+pub async fn respmod(
+    client: &Arc<IcapServiceClient>,
+    request: &str,
+    response: &[u8],
+) -> anyhow::Result<Verdict> {
+    let http_request = parse_request(request.as_bytes()).await?;
+    let mut ups_reader = BufReader::new(response);
+    let (http_response, _) =
+        HttpTransparentResponse::parse(&mut ups_reader, &Method::GET, true, MAX_HEADER_SIZE)
+            .await
+            .map_err(|e| anyhow!("invalid http response: {e}"))?;
+    let adapter = IcapRespmodClient::new(client.clone())
+        .h1_adapter(
+            StreamCopyConfig::default(),
+            BODY_LINE_MAX_SIZE,
+            IdleChecker::new(),
+        )
+        .await?;
+
+    let mut state = RespmodAdaptationRunState::new(Instant::now(), Duration::ZERO);
+    let mut clt_writer = Vec::new();
+    let result = adapter
+        .xfer(
+            &mut state,
+            &http_request,
+            &http_response,
+            &mut ups_reader,
+            &mut clt_writer,
+        )
+        .await;
+    match result {
+        Ok(RespmodAdaptationEndState::OriginalTransferred) => Ok(Verdict::Original(clt_writer)),
+        Ok(RespmodAdaptationEndState::AdaptedTransferred(_)) => Ok(Verdict::Adapted),
+        Err(H1RespmodAdaptationError::IcapServerErrorResponse(_, code, _)) => {
+            Ok(Verdict::Blocked(code))
+        }
+        Err(e) => Err(anyhow!("respmod failed: {e}")),
+    }
+}
@@ -0,0 +1,204 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! Containers and server process of the end to end tests
+//!
+//! clamd, Redis and a WireMock callout server are started in containers, and
+//! g3icap is run from the test binary with a config generated in a temporary
+//! directory. The addresses of the containers are passed to the server as
+//! the `G3ICAP_E2E_*` environment variables, which the generated config
+//! references as `${G3ICAP_E2E_*}`: the antivirus scans with clamd, shares
+//! its verdicts through Redis and looks the content up in the callout mock,
+//! which answers as a threat intelligence source.
+
+use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, anyhow};
+use testcontainers::core::{IntoContainerPort, WaitFor};
+use testcontainers::runners::AsyncRunner;
+use testcontainers::{ContainerAsync, GenericImage, ImageExt};
+use url::Url;
+
+use g3_icap_client::{IcapMethod, IcapServiceClient, IcapServiceConfig};
+
+const CLAMD_PORT: u16 = 3310;
+const REDIS_PORT: u16 = 6379;
+const CALLOUT_PORT: u16 = 8080;
+
+/// Config of the server under test, expanded by g3icap when loaded
+const CONFIG: &str = "\
+log: ${G3ICAP_E2E_LOG:-stdout}
+server:
+  - name: e2e
+    type: IcapServer
+    timeouts:
+      header: 5s
+      transaction: 30s
+    idle_reaper:
+      idle_timeout: 10s
+    antivirus:
+      clamd: ${G3ICAP_E2E_CLAMD_ADDR}
+      verdict_cache:
+        redis: ${G3ICAP_E2E_REDIS_URL}
+      threat_intel:
+        - ${G3ICAP_E2E_CALLOUT_URL}
+";
+
+/// Running containers and server, stopped when dropped
+pub struct TestEnv {
+    pub clamd: SocketAddr,
+    pub redis: SocketAddr,
+    pub callout: SocketAddr,
+    pub icap: SocketAddr,
+    server: Child,
+    dir: PathBuf,
+    _containers: Vec<ContainerAsync<GenericImage>>,
+}
+
+impl TestEnv {
+    /// Start the environment, the content with the given SHA-256 is known as
+    /// malicious to the threat intelligence source
+    pub async fn start(flagged_sha256: &str) -> anyhow::Result<Self> {
+        let clamd = GenericImage::new("clamav/clamav", "1.4")
+            .with_exposed_port(CLAMD_PORT.tcp())
+            .with_wait_for(WaitFor::message_on_stdout("socket found, clamd started"))
+            // the signatures are downloaded first
+            .with_startup_timeout(Duration::from_secs(300))
+            .start()
+            .await
+            .context("failed to start clamd")?;
+        let redis = GenericImage::new("redis", "7.4-alpine")
+            .with_exposed_port(REDIS_PORT.tcp())
+            .with_wait_for(WaitFor::message_on_stdout("Ready to accept connections"))
+            .start()
+            .await
+            .context("failed to start redis")?;
+        let callout = GenericImage::new("wiremock/wiremock", "3.9.1")
+            .with_exposed_port(CALLOUT_PORT.tcp())
+            .with_wait_for(WaitFor::message_on_stdout("port:"))
+            .start()
+            .await
+            .context("failed to start the callout mock")?;
+
+        let clamd_addr = host_addr(&clamd, CLAMD_PORT).await?;
+        let redis_addr = host_addr(&redis, REDIS_PORT).await?;
+        let callout_addr = host_addr(&callout, CALLOUT_PORT).await?;
+        mock_callout(callout_addr, flagged_sha256)?;
+
+        let dir = std::env::temp_dir().join(format!("g3icap-e2e-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let config = dir.join("g3icap.yaml");
+        std::fs::write(&config, CONFIG)?;
+
+        let icap = SocketAddr::from((Ipv4Addr::LOCALHOST, free_port()?));
+        let server = Command::new(env!("CARGO_BIN_EXE_g3icap"))
+            .arg("--config")
+            .arg(&config)
+            .args(["--host", "127.0.0.1", "--port", &icap.port().to_string()])
+            .env("G3ICAP_E2E_CLAMD_ADDR", clamd_addr.to_string())
+            .env("G3ICAP_E2E_REDIS_URL", format!("redis://{redis_addr}"))
+            .env(
+                "G3ICAP_E2E_CALLOUT_URL",
+                format!("http://{callout_addr}/callout"),
+            )
+            .stdout(Stdio::null())
+            .spawn()
+            .context("failed to run g3icap")?;
+
+        let env = TestEnv {
+            clamd: clamd_addr,
+            redis: redis_addr,
+            callout: callout_addr,
+            icap,
+            server,
+            dir,
+            _containers: vec![clamd, redis, callout],
+        };
+        env.wait_ready().await?;
+        Ok(env)
+    }
+
+    /// Client of a service of the server, its options are fetched on the
+    /// first request
+    pub fn client(
+        &self,
+        method: IcapMethod,
+        service: &str,
+    ) -> anyhow::Result<Arc<IcapServiceClient>> {
+        let url = Url::parse(&format!("icap://{}/{service}", self.icap))?;
+        let config = IcapServiceConfig::new(method, url)?;
+        Ok(Arc::new(IcapServiceClient::new(Arc::new(config))?))
+    }
+
+    /// Wait for the server to answer OPTIONS
+    async fn wait_ready(&self) -> anyhow::Result<()> {
+        let client = self.client(IcapMethod::Reqmod, "reqmod")?;
+        for _ in 0..100 {
+            if client.fetch_connection().await.is_ok() {
+                return Ok(());
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        Err(anyhow!("g3icap is not ready on {}", self.icap))
+    }
+}
+
+impl Drop for TestEnv {
+    fn drop(&mut self) {
+        let _ = self.server.kill();
+        let _ = self.server.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+async fn host_addr(
+    container: &ContainerAsync<GenericImage>,
+    port: u16,
+) -> anyhow::Result<SocketAddr> {
+    let port = container.get_host_port_ipv4(port.tcp()).await?;
+    Ok(SocketAddr::from((Ipv4Addr::LOCALHOST, port)))
+}
+
+/// Make the callout mock report the flagged content as malicious, and
+/// nothing known about the other indicators
+fn mock_callout(addr: SocketAddr, flagged_sha256: &str) -> anyhow::Result<()> {
+    ureq::post(&format!("http://{addr}/__admin/mappings"))
+        .send_json(serde_json::json!({
+            "request": {
+                "method": "POST",
+                "url": "/callout",
+                "bodyPatterns": [{ "contains": flagged_sha256 }]
+            },
+            "response": {
+                "status": 200,
+                "jsonBody": {
+                    "results": {
+                        flagged_sha256: { "malicious": true, "threat": "e2e" }
+                    }
+                }
+            }
+        }))
+        .map_err(|e| anyhow!("failed to mock the callout: {e}"))?;
+    ureq::post(&format!("http://{addr}/__admin/mappings"))
+        .send_json(serde_json::json!({
+            "priority": 10,
+            "request": { "method": "POST", "url": "/callout" },
+            "response": {
+                "status": 200,
+                "jsonBody": { "results": {} }
+            }
+        }))
+        .map_err(|e| anyhow!("failed to mock the callout: {e}"))?;
+    Ok(())
+}
+
+fn free_port() -> anyhow::Result<u16> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    Ok(listener.local_addr()?.port())
+}
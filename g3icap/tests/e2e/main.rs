@@ -0,0 +1,171 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2023-2025 ByteDance and/or its affiliates.
+ */
+
+//! End to end tests of g3icap
+//!
+//! The tests need Docker and are ignored by default, run them with:
+//!
+//! ```text
+//! cargo test -p g3icap --test e2e -- --ignored --test-threads 1
+//! ```
+//!
+//! Each test starts its own environment, see [`env::TestEnv`], and drives
+//! the server with g3-icap-client, see [`adapt`]. The services in containers
+//! are checked directly as well, so that a failure of the environment is told
+//! apart from a wrong verdict of the server.
+
+use std::net::SocketAddr;
+
+use g3_icap_client::IcapMethod;
+use g3icap::modules::threat_intel::sha256_hex;
+use redis::AsyncCommands;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+mod adapt;
+mod env;
+
+use adapt::Verdict;
+use env::TestEnv;
+
+const EICAR: &[u8] = b"X5O!P%@AP[4\\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+/// Content known as malicious to the threat intelligence source only
+const FLAGGED: &[u8] = b"g3icap e2e content flagged by threat intelligence";
+const CLEAN: &[u8] = b"hello";
+
+async fn start() -> TestEnv {
+    TestEnv::start(&sha256_hex(FLAGGED)).await.unwrap()
+}
+
+/// HTTP response with the given body
+fn http_response(body: &[u8]) -> Vec<u8> {
+    let mut response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    )
+    .into_bytes();
+    response.extend_from_slice(body);
+    response
+}
+
+/// Send a command and read the reply until the connection is closed
+async fn exchange(addr: SocketAddr, command: &[u8]) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(command).await.unwrap();
+    let mut reply = Vec::new();
+    let mut buf = [0u8; 1024];
+    // redis keeps the connection open, a single reply is read
+    while let Ok(n) = stream.read(&mut buf).await {
+        if n == 0 {
+            break;
+        }
+        reply.extend_from_slice(&buf[..n]);
+        if reply.ends_with(b"\n") || reply.ends_with(b"\0") {
+            break;
+        }
+    }
+    String::from_utf8_lossy(&reply).into_owned()
+}
+
+#[tokio::test]
+#[ignore = "needs docker"]
+async fn environment() {
+    let env = start().await;
+
+    assert_eq!(exchange(env.clamd, b"zPING\0").await, "PONG\0");
+    let mut instream = b"zINSTREAM\0".to_vec();
+    instream.extend_from_slice(&(EICAR.len() as u32).to_be_bytes());
+    instream.extend_from_slice(EICAR);
+    instream.extend_from_slice(&0u32.to_be_bytes());
+    let verdict = exchange(env.clamd, &instream).await;
+    assert!(verdict.contains("FOUND"), "{verdict}");
+
+    assert_eq!(exchange(env.redis, b"PING\r\n").await, "+PONG\r\n");
+
+    let flagged = sha256_hex(FLAGGED);
+    let reputation: serde_json::Value = ureq::post(&format!("http://{}/callout", env.callout))
+        .send_json(serde_json::json!({ "indicators": [flagged, sha256_hex(CLEAN)] }))
+        .unwrap()
+        .into_json()
+        .unwrap();
+    assert_eq!(reputation["results"][&flagged]["malicious"], true);
+    assert!(reputation["results"].get(sha256_hex(CLEAN)).is_none());
+}
+
+#[tokio::test]
+#[ignore = "needs docker"]
+async fn options() {
+    let env = start().await;
+    // the status, the ISTag and the Methods of the options are checked by the client
+    for (method, service) in [
+        (IcapMethod::Reqmod, "reqmod"),
+        (IcapMethod::Respmod, "respmod"),
+    ] {
+        let client = env.client(method, service).unwrap();
+        if let Err(e) = client.fetch_connection().await {
+            panic!("invalid options of {service}: {e:?}");
+        }
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs docker"]
+async fn reqmod_verdicts() {
+    let env = start().await;
+    let client = env.client(IcapMethod::Reqmod, "reqmod").unwrap();
+
+    let request = "GET / HTTP/1.1\r\nHost: www.example.com\r\n\r\n";
+    let clean = adapt::reqmod(&client, request).await.unwrap();
+    let Verdict::Original(sent) = clean else {
+        panic!("clean request not passed: {clean:?}");
+    };
+    assert!(sent.starts_with(b"GET / HTTP/1.1\r\n"));
+
+    // blocked by the domain patterns of the content filter
+    let request = "GET / HTTP/1.1\r\nHost: www.malware.example.com\r\n\r\n";
+    let blocked = adapt::reqmod(&client, request).await.unwrap();
+    assert_eq!(blocked, Verdict::Blocked(403));
+}
+
+#[tokio::test]
+#[ignore = "needs docker"]
+async fn respmod_verdicts() {
+    let env = start().await;
+    let client = env.client(IcapMethod::Respmod, "respmod").unwrap();
+    let request = "GET /file HTTP/1.1\r\nHost: www.example.com\r\n\r\n";
+
+    let clean = adapt::respmod(&client, request, &http_response(CLEAN))
+        .await
+        .unwrap();
+    let Verdict::Original(received) = clean else {
+        panic!("clean response not passed: {clean:?}");
+    };
+    assert!(received.ends_with(CLEAN));
+
+    // found by clamd, the verdict is shared through redis
+    let eicar = adapt::respmod(&client, request, &http_response(EICAR))
+        .await
+        .unwrap();
+    assert_eq!(eicar, Verdict::Blocked(403));
+    let redis = redis::Client::open(format!("redis://{}", env.redis)).unwrap();
+    let mut conn = redis.get_multiplexed_async_connection().await.unwrap();
+    let keys: Vec<String> = conn
+        .keys(format!("g3icap:verdict:*:{}", sha256_hex(EICAR)))
+        .await
+        .unwrap();
+    assert_eq!(keys.len(), 1, "{keys:?}");
+    let verdict: String = conn.get(&keys[0]).await.unwrap();
+    let verdict: serde_json::Value = serde_json::from_str(&verdict).unwrap();
+    assert_eq!(verdict["is_clean"], false);
+    assert_eq!(verdict["engine"], "ClamAV");
+    let threat = verdict["threat_name"].as_str().unwrap();
+    assert!(threat.contains("Eicar"), "{threat}");
+
+    // unknown to clamd, reported by the threat intelligence source
+    let flagged = adapt::respmod(&client, request, &http_response(FLAGGED))
+        .await
+        .unwrap();
+    assert_eq!(flagged, Verdict::Blocked(403));
+}
@@ -13,6 +13,7 @@ use g3icap::modules::{builtin::EchoModule};
 use g3icap::services::{ServiceConfig, ServiceManager, LoadBalancingStrategy};
 use g3icap::pipeline::{ContentPipeline, PipelineConfig, stages::LoggingStage};
 use g3icap::protocol::common::{IcapMethod, IcapRequest};
+use g3icap::transaction::TransactionCtx;
 
 /// Test complete ICAP workflow
 #[tokio::test]
@@ -43,7 +44,7 @@ async fn test_complete_icap_workflow() {
     let request = create_test_request();
     
     // Process request through service
-    let response = service_manager.handle_request(&request).await.unwrap();
+    let response = service_manager.handle_request(&request, &TransactionCtx::for_request(&request)).await.unwrap();
     assert_eq!(response.status, http::StatusCode::OK);
     
     // Test pipeline processing
@@ -65,7 +66,8 @@ async fn test_complete_icap_workflow() {
     pipeline.add_stage(logging_stage);
     
     // Process request through pipeline
-    let response = pipeline.process_request(request).await.unwrap();
+    let ctx = TransactionCtx::for_request(&request);
+    let response = pipeline.process_request(request, &ctx).await.unwrap();
     assert_eq!(response.status, http::StatusCode::OK);
 }
 
@@ -76,7 +78,7 @@ async fn test_error_handling() {
     
     // Test handling request for non-existent service
     let request = create_test_request();
-    let result = service_manager.handle_request(&request).await;
+    let result = service_manager.handle_request(&request, &TransactionCtx::for_request(&request)).await;
     assert!(result.is_err());
 }
 
@@ -105,7 +107,7 @@ async fn test_concurrent_processing() {
     let mut success_count = 0;
     for _i in 0..10 {
         let request = create_test_request();
-        if let Ok(_) = service_manager.handle_request(&request).await {
+        if let Ok(_) = service_manager.handle_request(&request, &TransactionCtx::for_request(&request)).await {
             success_count += 1;
         }
     }
@@ -19,6 +19,7 @@ use bytes::Bytes;
 use g3icap::modules::content_filter::{ContentFilterModule, ContentFilterConfig};
 use g3icap::modules::antivirus::{AntivirusModule, AntivirusConfig, AntivirusEngine};
 use g3icap::stats::IcapStats;
+use g3icap::transaction::TransactionCtx;
 
 /// Performance test suite
 #[derive(Clone)]
@@ -320,7 +321,8 @@ impl PerformanceTests {
                 encapsulated: None,
             };
             
-            let _ = pipeline.process_request(request).await;
+            let ctx = TransactionCtx::for_request(&request);
+            let _ = pipeline.process_request(request, &ctx).await;
             processed += 1;
         }
         
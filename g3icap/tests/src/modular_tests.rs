@@ -8,10 +8,11 @@
 //! This module contains comprehensive tests for the modular architecture,
 //! including module system, service management, and content pipeline.
 
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::time::sleep;
 
-use g3icap::modules::{ModuleConfig, ModuleRegistry, builtin::{EchoModule, LoggingModule}};
+use g3icap::modules::{IcapModule, ModuleConfig, ModuleRegistry, builtin::{EchoModule, LoggingModule}};
 use g3icap::services::{ServiceConfig, ServiceManager, LoadBalancingStrategy};
 use g3icap::pipeline::{ContentPipeline, PipelineConfig, stages::{LoggingStage, ContentFilterStage, AntivirusStage}};
 use g3icap::protocol::common::{IcapMethod, IcapRequest, IcapResponse};
@@ -28,6 +29,7 @@ async fn test_module_registry() {
         load_timeout: Duration::from_secs(5),
         max_memory: 1024 * 1024,
         sandbox: true,
+        metrics: Default::default(),
     };
     
     let registry = ModuleRegistry::new(config);
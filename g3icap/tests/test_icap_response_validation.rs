@@ -144,6 +144,8 @@ fn test_200_ok_modified_response() {
         res_body: None,
         null_body: Some(200),
         opt_body: None,
+        req_line: None,
+        status_line: None,
     });
     
     let response = generator.ok_modified(encapsulated, body.clone());
@@ -173,6 +175,8 @@ fn test_204_no_modifications_response() {
         res_body: None,
         null_body: Some(77),
         opt_body: None,
+        req_line: None,
+        status_line: None,
     });
     
     let response = generator.no_modifications(encapsulated);
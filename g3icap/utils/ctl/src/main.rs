@@ -2,7 +2,19 @@
 //! 
 //! This utility provides command-line control for the G3ICAP server.
 
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
 use clap::Parser;
+use g3icap::modules::rule_hits::RuleHitStats;
+use g3icap::modules::shadow::ShadowReport;
+use g3icap::server::policy_schedule::ScheduleState;
+use g3icap::server::rotation::ServiceStatus;
+use g3icap::server::what_if::WhatIfRequest;
+
+mod smoke;
+mod top;
 
 #[derive(Parser)]
 #[command(name = "g3icap-ctl")]
@@ -23,10 +35,221 @@ enum Commands {
     Stop,
     /// Restart the server
     Restart,
-    /// Show server status
-    Status,
+    /// Show the status of the running daemon and its response time objectives
+    Status {
+        /// Control directory of the daemon
+        #[arg(long, default_value = g3_daemon::opts::DEFAULT_CONTROL_DIR)]
+        control_dir: PathBuf,
+    },
     /// Reload configuration
     Reload,
+    /// Show the version report of the running daemon as JSON
+    Version {
+        /// Control directory of the daemon
+        #[arg(long, default_value = g3_daemon::opts::DEFAULT_CONTROL_DIR)]
+        control_dir: PathBuf,
+    },
+    /// Restart the daemon, the new process takes over the listening sockets
+    Upgrade {
+        /// Control directory of the daemon
+        #[arg(long, default_value = g3_daemon::opts::DEFAULT_CONTROL_DIR)]
+        control_dir: PathBuf,
+    },
+    /// Show the current stats of the running daemon, one `name value` per line
+    Stats {
+        /// Control directory of the daemon
+        #[arg(long, default_value = g3_daemon::opts::DEFAULT_CONTROL_DIR)]
+        control_dir: PathBuf,
+        /// Print the stats as JSON
+        #[arg(long)]
+        json: bool,
+        /// Show the rule hit counters instead, as JSON
+        #[arg(long)]
+        rules: bool,
+    },
+    /// List the rules of the running daemon without any hit for some days
+    UnusedRules {
+        /// Control directory of the daemon
+        #[arg(long, default_value = g3_daemon::opts::DEFAULT_CONTROL_DIR)]
+        control_dir: PathBuf,
+        /// Number of days without any hit
+        #[arg(long, default_value_t = 30)]
+        days: u64,
+    },
+    /// Manage the services of the running daemon
+    Service {
+        /// Control directory of the daemon
+        #[arg(long, default_value = g3_daemon::opts::DEFAULT_CONTROL_DIR)]
+        control_dir: PathBuf,
+        #[command(subcommand)]
+        command: ServiceCommands,
+    },
+    /// Manage the modules of the running daemon
+    Module {
+        /// Control directory of the daemon
+        #[arg(long, default_value = g3_daemon::opts::DEFAULT_CONTROL_DIR)]
+        control_dir: PathBuf,
+        #[command(subcommand)]
+        command: ModuleCommands,
+    },
+    /// Change the log level of the components of the running daemon for some time
+    Log {
+        /// Control directory of the daemon
+        #[arg(long, default_value = g3_daemon::opts::DEFAULT_CONTROL_DIR)]
+        control_dir: PathBuf,
+        #[command(subcommand)]
+        command: LogCommands,
+    },
+    /// Validate and promote the candidate rule sets evaluated in shadow mode, or
+    /// test requests against the rules
+    Policy {
+        /// Control directory of the daemon
+        #[arg(long, default_value = g3_daemon::opts::DEFAULT_CONTROL_DIR)]
+        control_dir: PathBuf,
+        #[command(subcommand)]
+        command: PolicyCommands,
+    },
+    /// Show a live dashboard of the running daemon, refreshed until interrupted
+    Top {
+        /// Control directory of the daemon
+        #[arg(long, default_value = g3_daemon::opts::DEFAULT_CONTROL_DIR)]
+        control_dir: PathBuf,
+        /// Seconds between two refreshes
+        #[arg(long, default_value_t = 2)]
+        interval: u64,
+        /// Number of the latest audit events shown
+        #[arg(long, default_value_t = 10)]
+        events: usize,
+    },
+    /// Run a smoke-test suite against a live instance
+    Smoke {
+        /// Suite name or path to a suite YAML file
+        #[arg(long, default_value = "default")]
+        suite: String,
+        /// Directory containing suite files
+        #[arg(long, default_value = smoke::DEFAULT_SUITE_DIR)]
+        suite_dir: PathBuf,
+        /// ICAP listener address of the instance under test
+        #[arg(long, default_value = "127.0.0.1:1344")]
+        addr: SocketAddr,
+        /// Per-transaction timeout in seconds
+        #[arg(long, default_value_t = 5)]
+        timeout: u64,
+    },
+}
+
+#[derive(clap::Subcommand)]
+enum ServiceCommands {
+    /// List the services with their pipeline and whether they are in rotation
+    List,
+    /// Take a service back in rotation
+    Enable {
+        /// Service name, the ICAP URI path
+        service: String,
+    },
+    /// Take a service out of rotation, its transactions are answered with 503
+    Disable {
+        /// Service name, the ICAP URI path
+        service: String,
+    },
+    /// Reload the modules of a service, like its rules
+    Reload {
+        /// Service name, the ICAP URI path
+        service: String,
+    },
+    /// Bind a service to another configured pipeline, from its next transaction on
+    SetPipeline {
+        /// Service name, the ICAP URI path
+        service: String,
+        /// Pipeline name
+        pipeline: String,
+    },
+}
+
+#[derive(clap::Subcommand)]
+enum ModuleCommands {
+    /// Reload a module from its config, like the YARA rules of the antivirus
+    Reload {
+        /// Module name, as in the metrics, like antivirus or expression/<service>
+        module: String,
+    },
+}
+
+#[derive(clap::Subcommand)]
+enum LogCommands {
+    /// Log a component at another level, until the duration has passed
+    SetLevel {
+        /// Component, like parser, connection, a module like antivirus, or a
+        /// log target like g3icap::server::listener
+        module: String,
+        /// Log level: off, error, warn, info, debug or trace
+        level: String,
+        /// Time in seconds before the process level is used again
+        #[arg(long, default_value_t = 600)]
+        duration: u64,
+    },
+    /// Log a component at the process level again
+    Reset {
+        /// Component, as given to set-level
+        module: String,
+    },
+    /// List the components logged at another level, with the seconds left
+    List,
+}
+
+#[derive(clap::Subcommand)]
+enum PolicyCommands {
+    /// Show how often the verdicts of the candidate rule sets differ from the active ones
+    Status {
+        /// Print the counters as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Make the candidate rules the active ones, from the next transaction on
+    Promote {
+        /// Rule set: content_filter or yara
+        rule_set: String,
+        /// Promote at this time instead of now, like 2025-06-01T22:00:00Z
+        #[arg(long)]
+        at: Option<String>,
+        /// Seconds after the promotion the previous rules are active again,
+        /// unless confirmed
+        #[arg(long)]
+        revert_after: Option<u64>,
+    },
+    /// Keep a scheduled promotion, which is then no longer reverted
+    Confirm {
+        /// Rule set: content_filter or yara
+        rule_set: String,
+    },
+    /// Cancel a scheduled promotion, or make the previous rules active again
+    Revert {
+        /// Rule set: content_filter or yara
+        rule_set: String,
+    },
+    /// Show the rules a request would match and its verdict, without serving it
+    Test {
+        /// URL of the request
+        url: String,
+        /// User of the request
+        #[arg(long)]
+        user: Option<String>,
+        /// Group of the user, may be repeated
+        #[arg(long = "group")]
+        groups: Vec<String>,
+        /// Content type of the request body
+        #[arg(long)]
+        content_type: Option<String>,
+        /// Size of the request body in bytes
+        #[arg(long)]
+        size: Option<u64>,
+        /// Evaluate at this time instead of now, like 2025-06-01T22:00:00Z
+        #[arg(long)]
+        at: Option<String>,
+        /// Print the result as JSON
+        #[arg(long)]
+        json: bool,
+    },
 }
 
 fn main() {
@@ -45,13 +268,382 @@ fn main() {
             println!("Restarting G3ICAP server...");
             // Implementation would go here
         }
-        Commands::Status => {
-            println!("G3ICAP server status...");
-            // Implementation would go here
+        Commands::Status { control_dir } => {
+            let path = control_dir.join(g3icap::control::handover::SOCKET_NAME);
+            let status = g3icap::control::command::request_status(&path)
+                .and_then(|s| Ok(serde_json::from_str::<serde_json::Value>(&s)?));
+            match status {
+                Ok(status) => print_status(&status),
+                Err(e) => {
+                    eprintln!("failed to get status: {e:?}");
+                    std::process::exit(1);
+                }
+            }
         }
         Commands::Reload => {
             println!("Reloading G3ICAP configuration...");
             // Implementation would go here
         }
+        Commands::Version { control_dir } => {
+            let path = control_dir.join(g3icap::control::handover::SOCKET_NAME);
+            match g3icap::control::command::request_version(&path) {
+                Ok(report) => println!("{report}"),
+                Err(e) => {
+                    eprintln!("failed to get version: {e:?}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Upgrade { control_dir } => {
+            let path = control_dir.join(g3icap::control::handover::SOCKET_NAME);
+            match g3icap::control::handover::request_upgrade(&path) {
+                Ok(pid) => println!("Upgrading G3ICAP server, new process {pid}"),
+                Err(e) => {
+                    eprintln!("failed to upgrade: {e:?}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Stats { control_dir, json, rules } => {
+            let path = control_dir.join(g3icap::control::handover::SOCKET_NAME);
+            let report = if rules {
+                g3icap::control::command::request_rule_stats(&path)
+            } else {
+                g3icap::control::command::request_stats(&path)
+            };
+            let report = report.and_then(|s| Ok(serde_json::from_str::<serde_json::Value>(&s)?));
+            match report {
+                Ok(report) if json || rules => println!("{report}"),
+                Ok(report) => print_stats("", &report),
+                Err(e) => {
+                    eprintln!("failed to get stats: {e:?}");
+                    // unknown state for monitoring checks
+                    std::process::exit(3);
+                }
+            }
+        }
+        Commands::UnusedRules { control_dir, days } => {
+            let path = control_dir.join(g3icap::control::handover::SOCKET_NAME);
+            let rules = g3icap::control::command::request_unused_rules(&path, days)
+                .and_then(|s| Ok(serde_json::from_str::<Vec<RuleHitStats>>(&s)?));
+            match rules {
+                Ok(rules) => {
+                    for r in &rules {
+                        println!("{}\t{}\t{} hits\tidle {} days", r.module, r.rule, r.hits, r.idle_days);
+                    }
+                    println!("{} rules without any hit for {days} days", rules.len());
+                }
+                Err(e) => {
+                    eprintln!("failed to get unused rules: {e:?}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Service { control_dir, command } => {
+            let path = control_dir.join(g3icap::control::handover::SOCKET_NAME);
+            service_command(&path, command);
+        }
+        Commands::Module {
+            control_dir,
+            command: ModuleCommands::Reload { module },
+        } => {
+            let path = control_dir.join(g3icap::control::handover::SOCKET_NAME);
+            match g3icap::control::command::request_module_reload(&path, &module) {
+                Ok(()) => println!("module {module} reloaded"),
+                Err(e) => {
+                    eprintln!("failed to reload module {module}: {e:?}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Log { control_dir, command } => {
+            let path = control_dir.join(g3icap::control::handover::SOCKET_NAME);
+            log_command(&path, command);
+        }
+        Commands::Policy { control_dir, command } => {
+            let path = control_dir.join(g3icap::control::handover::SOCKET_NAME);
+            policy_command(&path, command);
+        }
+        Commands::Top { control_dir, interval, events } => {
+            let path = control_dir.join(g3icap::control::handover::SOCKET_NAME);
+            let interval = Duration::from_secs(interval.max(1));
+            if let Err(e) = top::run(&path, interval, events) {
+                eprintln!("failed to show the dashboard: {e:?}");
+                std::process::exit(1);
+            }
+        }
+        Commands::Smoke {
+            suite,
+            suite_dir,
+            addr,
+            timeout,
+        } => {
+            let suite = match smoke::Suite::load(&suite, &suite_dir) {
+                Ok(suite) => suite,
+                Err(e) => {
+                    eprintln!("failed to load smoke suite: {e:?}");
+                    std::process::exit(2);
+                }
+            };
+            let runner = smoke::SmokeRunner::new(addr, Duration::from_secs(timeout));
+            let results = runner.run(&suite);
+            if !smoke::report(&suite, &results) {
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn service_command(path: &std::path::Path, command: ServiceCommands) {
+    use g3icap::control::command;
+
+    let result = match command {
+        ServiceCommands::List => command::request_services(path)
+            .and_then(|s| Ok(serde_json::from_str::<Vec<ServiceStatus>>(&s)?))
+            .map(|services| {
+                for s in &services {
+                    println!(
+                        "{}\t{}\t{}",
+                        s.name,
+                        if s.enabled { "enabled" } else { "disabled" },
+                        match (&s.pipeline, s.scripted) {
+                            (_, true) => "scripted",
+                            (Some(pipeline), false) => pipeline.as_str(),
+                            (None, false) => "all stages",
+                        }
+                    );
+                }
+            }),
+        ServiceCommands::Enable { service } => command::request_service_state(path, &service, true)
+            .map(|changed| match changed {
+                true => println!("service {service} enabled"),
+                false => println!("service {service} already enabled"),
+            }),
+        ServiceCommands::Disable { service } => command::request_service_state(path, &service, false)
+            .map(|changed| match changed {
+                true => println!("service {service} disabled"),
+                false => println!("service {service} already disabled"),
+            }),
+        ServiceCommands::Reload { service } => command::request_service_reload(path, &service)
+            .map(|modules| println!("service {service}: reloaded {}", modules.join(", "))),
+        ServiceCommands::SetPipeline { service, pipeline } => {
+            command::request_set_pipeline(path, &service, &pipeline).map(|previous| match previous {
+                Some(previous) => println!("service {service}: pipeline {previous} -> {pipeline}"),
+                None => println!("service {service}: pipeline {pipeline}"),
+            })
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("service command failed: {e:?}");
+        std::process::exit(1);
+    }
+}
+
+fn log_command(path: &std::path::Path, command: LogCommands) {
+    use g3icap::control::command;
+
+    let result = match command {
+        LogCommands::SetLevel { module, level, duration } => {
+            command::request_log_level(path, &module, &level, Duration::from_secs(duration))
+                .map(|(target, _)| println!("{module} ({target}) logged at {level} for {duration}s"))
+        }
+        LogCommands::Reset { module } => command::request_log_reset(path, &module)
+            .map(|changed| match changed {
+                true => println!("{module} logged at the process level again"),
+                false => println!("{module} already logged at the process level"),
+            }),
+        LogCommands::List => command::request_log_levels(path)
+            .and_then(|s| Ok(serde_json::from_str::<Vec<serde_json::Value>>(&s)?))
+            .map(|levels| {
+                let now = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                for l in &levels {
+                    println!(
+                        "{}\t{}\t{}\t{}s",
+                        l["component"].as_str().unwrap_or_default(),
+                        l["target"].as_str().unwrap_or_default(),
+                        l["level"].as_str().unwrap_or_default(),
+                        l["until"].as_u64().unwrap_or_default().saturating_sub(now)
+                    );
+                }
+            }),
+    };
+    if let Err(e) = result {
+        eprintln!("log command failed: {e:?}");
+        std::process::exit(1);
+    }
+}
+
+fn policy_command(path: &std::path::Path, command: PolicyCommands) {
+    use g3icap::control::command;
+
+    let result = match command {
+        PolicyCommands::Status { json: true } => command::request_shadow_stats(path).map(|s| println!("{s}")),
+        PolicyCommands::Status { json: false } => command::request_shadow_stats(path)
+            .and_then(|s| Ok(serde_json::from_str::<Vec<ShadowReport>>(&s)?))
+            .map(|reports| {
+                for r in &reports {
+                    if !r.loaded {
+                        println!("{}\tno candidate", r.name);
+                        continue;
+                    }
+                    println!(
+                        "{}\t{} evaluated\t{:.2}% disagreed\t{} newly blocked\t{} newly allowed\t{} rule changed\t{} errors",
+                        r.name,
+                        r.evaluated,
+                        r.disagreement_rate * 100.0,
+                        r.newly_blocked,
+                        r.newly_allowed,
+                        r.rule_changed,
+                        r.errors
+                    );
+                }
+            })
+            .and_then(|()| command::request_policy_schedule(path))
+            .map(|schedules| {
+                for s in &schedules {
+                    let revert_at = s.revert_at.map(format_time);
+                    println!(
+                        "{}\t{}\tpromoted at {}\treverted at {}",
+                        s.rule_set,
+                        s.state.as_str(),
+                        format_time(s.activate_at),
+                        revert_at.as_deref().unwrap_or("-")
+                    );
+                }
+            }),
+        PolicyCommands::Promote { rule_set, at, revert_after } => at
+            .map(|at| parse_time(&at))
+            .transpose()
+            .and_then(|at| {
+                command::request_policy_promote(path, &rule_set, at, revert_after.map(Duration::from_secs))
+            })
+            .map(|scheduled| match scheduled {
+                None => println!("candidate {rule_set} rules promoted"),
+                Some((activate_at, revert_at)) => {
+                    println!("candidate {rule_set} rules promoted at {}", format_time(activate_at));
+                    if let Some(revert_at) = revert_at {
+                        println!("reverted at {} unless confirmed", format_time(revert_at));
+                    }
+                }
+            }),
+        PolicyCommands::Confirm { rule_set } => command::request_policy_confirm(path, &rule_set)
+            .map(|state| match state {
+                ScheduleState::Active => println!("promotion of {rule_set} confirmed"),
+                ScheduleState::Pending => println!("scheduled promotion of {rule_set} confirmed, it will not be reverted"),
+            }),
+        PolicyCommands::Revert { rule_set } => command::request_policy_revert(path, &rule_set)
+            .map(|reverted| match reverted {
+                true => println!("previous {rule_set} rules active again"),
+                false => println!("scheduled promotion of {rule_set} cancelled"),
+            }),
+        PolicyCommands::Test { url, user, groups, content_type, size, at, json } => at
+            .map(|at| parse_time(&at))
+            .transpose()
+            .and_then(|time| {
+                let request = WhatIfRequest { user, groups, url, content_type, size, time };
+                command::request_policy_test(path, &request)
+            })
+            .and_then(|report| {
+                if json {
+                    println!("{}", serde_json::to_string(&report)?);
+                    return Ok(());
+                }
+                println!("verdict\t{}", report.verdict.as_str());
+                if let Some(reason) = &report.reason {
+                    println!("reason\t{reason}");
+                }
+                if report.candidate {
+                    println!("rules\tcandidate");
+                }
+                if let Some(quota) = &report.quota {
+                    println!("quota\t{quota}");
+                }
+                for m in &report.matches {
+                    let origin = match (&m.policy, &m.rule) {
+                        (Some(policy), Some(rule)) => format!("{policy}/{rule}"),
+                        _ => "-".to_string(),
+                    };
+                    println!("match\t{}\t{}\t{}\t{origin}\t{}", m.rule_set, m.rule_id, m.category, m.reason);
+                }
+                Ok(())
+            }),
+    };
+    if let Err(e) = result {
+        eprintln!("policy command failed: {e:?}");
+        std::process::exit(1);
+    }
+}
+
+/// Parse an RFC 3339 time, returns the unix time
+fn parse_time(s: &str) -> anyhow::Result<u64> {
+    let time = chrono::DateTime::parse_from_rfc3339(s)
+        .map_err(|e| anyhow::anyhow!("invalid time {s}: {e}"))?;
+    u64::try_from(time.timestamp()).map_err(|_| anyhow::anyhow!("invalid time {s}"))
+}
+
+fn format_time(unix_time: u64) -> String {
+    i64::try_from(unix_time)
+        .ok()
+        .and_then(|t| chrono::DateTime::from_timestamp(t, 0))
+        .map(|t| t.with_timezone(&chrono::Local).to_rfc3339())
+        .unwrap_or_else(|| unix_time.to_string())
+}
+
+/// Print the leaves of the stats, named by their path joined with dots
+///
+/// The entries of lists are named by their `name` field.
+fn print_stats(prefix: &str, value: &serde_json::Value) {
+    let join = |key: &str| if prefix.is_empty() { key.to_string() } else { format!("{prefix}.{key}") };
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map {
+                if key != "name" {
+                    print_stats(&join(key), value);
+                }
+            }
+        }
+        serde_json::Value::Array(list) if list.iter().all(|v| v["name"].is_string()) => {
+            for v in list {
+                print_stats(&join(v["name"].as_str().unwrap_or_default()), v);
+            }
+        }
+        serde_json::Value::Array(list) => {
+            let items: Vec<String> = list.iter().map(|v| v.as_str().map_or_else(|| v.to_string(), str::to_string)).collect();
+            println!("{prefix} {}", items.join(","));
+        }
+        serde_json::Value::String(s) => println!("{prefix} {s}"),
+        serde_json::Value::Null => println!("{prefix} -"),
+        v => println!("{prefix} {v}"),
+    }
+}
+
+fn print_status(status: &serde_json::Value) {
+    let version = &status["version"];
+    println!(
+        "g3icap {} ({}), {}",
+        version["version"].as_str().unwrap_or("unknown"),
+        version["git_commit"].as_str().unwrap_or("unknown"),
+        if status["draining"].as_bool().unwrap_or(false) { "draining" } else { "serving" }
+    );
+    let Some(objectives) = status["slo"].as_array() else {
+        println!("no response time objectives");
+        return;
+    };
+    for o in objectives {
+        let f = |k: &str| o[k].as_f64().unwrap_or_default();
+        println!(
+            "slo {}\ttarget {:.2}% under {}ms\tcompliance {:.2}% of {}\tbudget left {:.1}%\tburn rate {:.2} (short {:.2})",
+            o["name"].as_str().unwrap_or_default(),
+            f("target") * 100.0,
+            o["latency_ms"],
+            f("compliance") * 100.0,
+            o["total"],
+            f("budget_remaining") * 100.0,
+            f("burn_rate"),
+            f("short_burn_rate"),
+        );
     }
 }
@@ -0,0 +1,487 @@
+//! Declarative smoke-test suite
+//!
+//! A suite is a YAML file listing synthetic ICAP transactions together with
+//! the verdict each one is expected to get. The transactions are sent to a
+//! live instance (normally over its loopback listener) and the results are
+//! reported as pass/fail, so rule deployments can be validated in place.
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, anyhow};
+use serde::Deserialize;
+
+/// Built-in copy of the default suite, used when no suite file is installed
+const DEFAULT_SUITE: &str = include_str!("../suites/default.yaml");
+
+/// Directory searched for `<suite>.yaml` files
+pub const DEFAULT_SUITE_DIR: &str = "/etc/g3icap/smoke";
+
+/// Expected verdict of a transaction
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    /// Content passes (204, or 200 without a rejecting HTTP response)
+    Allow,
+    /// Content is blocked (403, or 200 carrying an HTTP block page)
+    Block,
+}
+
+impl Verdict {
+    fn matches(&self, method: &str, reply: &Reply) -> bool {
+        match self {
+            Verdict::Allow => match reply.status {
+                204 => true,
+                // a REQMOD answered with an HTTP response never reaches the origin
+                200 => match reply.http_status {
+                    Some(status) => !method.eq_ignore_ascii_case("REQMOD") && status < 400,
+                    None => true,
+                },
+                _ => false,
+            },
+            Verdict::Block => match reply.status {
+                403 => true,
+                // block pages are sent back as encapsulated HTTP responses
+                200 => match reply.http_status {
+                    Some(status) => method.eq_ignore_ascii_case("REQMOD") || status >= 400,
+                    None => false,
+                },
+                _ => false,
+            },
+        }
+    }
+}
+
+/// Status codes of an ICAP response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Reply {
+    /// ICAP status code
+    status: u16,
+    /// Status code of the encapsulated HTTP response, if any
+    http_status: Option<u16>,
+}
+
+impl std::fmt::Display for Reply {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.http_status {
+            Some(http_status) => write!(f, "{}/{http_status}", self.status),
+            None => write!(f, "{}", self.status),
+        }
+    }
+}
+
+/// A single synthetic transaction
+#[derive(Debug, Clone, Deserialize)]
+pub struct Transaction {
+    /// Transaction name shown in the report
+    pub name: String,
+    /// ICAP method (OPTIONS, REQMOD or RESPMOD)
+    pub method: String,
+    /// ICAP service path
+    #[serde(default = "default_service")]
+    pub service: String,
+    /// URL of the encapsulated HTTP request
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Encapsulated HTTP body
+    #[serde(default)]
+    pub body: Option<String>,
+    /// Content-Type of the encapsulated body
+    #[serde(default)]
+    pub content_type: Option<String>,
+    /// Expected verdict
+    #[serde(default)]
+    pub verdict: Option<Verdict>,
+    /// Expected ICAP status code, overrides the verdict
+    #[serde(default)]
+    pub status: Option<u16>,
+    /// Expected status code of the encapsulated HTTP response
+    #[serde(default)]
+    pub http_status: Option<u16>,
+}
+
+fn default_service() -> String {
+    "/".to_string()
+}
+
+/// A smoke-test suite
+#[derive(Debug, Clone, Deserialize)]
+pub struct Suite {
+    /// Suite name
+    pub name: String,
+    /// Transactions to run, in order
+    pub transactions: Vec<Transaction>,
+}
+
+impl Suite {
+    /// Load a suite by name or path
+    ///
+    /// A value containing a path separator or ending in `.yaml` is treated as
+    /// a file path, otherwise `<suite_dir>/<name>.yaml` is used. The default
+    /// suite falls back to the built-in copy if no file is installed.
+    pub fn load(suite: &str, suite_dir: &Path) -> anyhow::Result<Self> {
+        let path = if suite.contains('/') || suite.ends_with(".yaml") {
+            PathBuf::from(suite)
+        } else {
+            suite_dir.join(format!("{suite}.yaml"))
+        };
+
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(_) if suite == "default" => DEFAULT_SUITE.to_string(),
+            Err(e) => {
+                return Err(anyhow!("failed to read suite file {}: {e}", path.display()));
+            }
+        };
+        Self::parse(&content).with_context(|| format!("invalid suite {suite}"))
+    }
+
+    /// Parse a suite from YAML text
+    pub fn parse(content: &str) -> anyhow::Result<Self> {
+        let suite: Suite = serde_yaml::from_str(content)?;
+        for t in &suite.transactions {
+            if t.verdict.is_none() && t.status.is_none() {
+                return Err(anyhow!(
+                    "transaction {} has neither verdict nor status",
+                    t.name
+                ));
+            }
+            match t.method.to_uppercase().as_str() {
+                "OPTIONS" | "REQMOD" | "RESPMOD" => {}
+                m => return Err(anyhow!("transaction {} has invalid method {m}", t.name)),
+            }
+        }
+        Ok(suite)
+    }
+}
+
+/// Outcome of a single transaction
+#[derive(Debug)]
+pub struct TransactionResult {
+    /// Transaction name
+    pub name: String,
+    /// Whether the expectation was met
+    pub passed: bool,
+    /// Human readable detail
+    pub detail: String,
+    /// Round-trip time
+    pub elapsed: Duration,
+}
+
+/// Runs a suite against a live instance
+pub struct SmokeRunner {
+    addr: SocketAddr,
+    timeout: Duration,
+}
+
+impl SmokeRunner {
+    /// Create a runner targeting the given ICAP listener
+    pub fn new(addr: SocketAddr, timeout: Duration) -> Self {
+        SmokeRunner { addr, timeout }
+    }
+
+    /// Run every transaction of the suite in order
+    pub fn run(&self, suite: &Suite) -> Vec<TransactionResult> {
+        suite.transactions.iter().map(|t| self.run_one(t)).collect()
+    }
+
+    fn run_one(&self, t: &Transaction) -> TransactionResult {
+        let start = Instant::now();
+        let (passed, detail) = match self.exchange(t) {
+            Ok(reply) => {
+                let passed = match t.status {
+                    Some(expected) => expected == reply.status,
+                    None => t
+                        .verdict
+                        .map(|v| v.matches(&t.method, &reply))
+                        .unwrap_or(false),
+                } && t.http_status.is_none_or(|s| reply.http_status == Some(s));
+                let mut expected = match (t.status, t.verdict) {
+                    (Some(s), _) => s.to_string(),
+                    (None, Some(v)) => format!("{v:?}").to_lowercase(),
+                    (None, None) => "-".to_string(),
+                };
+                if let Some(http_status) = t.http_status {
+                    expected.push_str(&format!("/{http_status}"));
+                }
+                (passed, format!("got {reply}, expected {expected}"))
+            }
+            Err(e) => (false, format!("error: {e}")),
+        };
+        TransactionResult {
+            name: t.name.clone(),
+            passed,
+            detail,
+            elapsed: start.elapsed(),
+        }
+    }
+
+    fn exchange(&self, t: &Transaction) -> anyhow::Result<Reply> {
+        let mut stream = TcpStream::connect_timeout(&self.addr, self.timeout)
+            .map_err(|e| anyhow!("connect to {} failed: {e}", self.addr))?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        let request = build_request(&self.addr, t)?;
+        stream.write_all(&request)?;
+        stream.flush()?;
+
+        let mut buf = Vec::with_capacity(1024);
+        let mut tmp = [0u8; 1024];
+        loop {
+            if let Some(reply) = parse_reply(&buf)? {
+                return Ok(reply);
+            }
+            let n = stream.read(&mut tmp)?;
+            if n == 0 {
+                return match parse_status(&buf) {
+                    Ok(_) => Err(anyhow!("connection closed in the response head")),
+                    Err(e) => Err(e),
+                };
+            }
+            buf.extend_from_slice(&tmp[..n]);
+        }
+    }
+}
+
+/// Build the raw ICAP request for a transaction
+fn build_request(addr: &SocketAddr, t: &Transaction) -> anyhow::Result<Vec<u8>> {
+    let method = t.method.to_uppercase();
+    let icap_uri = format!("icap://{addr}{}", t.service);
+    let mut out = format!("{method} {icap_uri} ICAP/1.0\r\nHost: {addr}\r\n");
+
+    if method == "OPTIONS" {
+        out.push_str("Encapsulated: null-body=0\r\n\r\n");
+        return Ok(out.into_bytes());
+    }
+
+    let url = t
+        .url
+        .as_deref()
+        .ok_or_else(|| anyhow!("transaction {} has no url", t.name))?;
+    let parsed = url::Url::parse(url).map_err(|e| anyhow!("invalid url {url}: {e}"))?;
+    let host = parsed.host_str().unwrap_or("localhost");
+    let path = match parsed.query() {
+        Some(q) => format!("{}?{q}", parsed.path()),
+        None => parsed.path().to_string(),
+    };
+    let content_type = t.content_type.as_deref().unwrap_or("text/plain");
+
+    let req_method = if method == "REQMOD" && t.body.is_some() {
+        "POST"
+    } else {
+        "GET"
+    };
+    let mut req_hdr = format!("{req_method} {path} HTTP/1.1\r\nHost: {host}\r\n");
+    if method == "REQMOD"
+        && let Some(body) = &t.body
+    {
+        req_hdr.push_str(&format!(
+            "Content-Type: {content_type}\r\nContent-Length: {}\r\n",
+            body.len()
+        ));
+    }
+    req_hdr.push_str("\r\n");
+
+    let res_hdr = if method == "RESPMOD" {
+        let len = t.body.as_ref().map(|b| b.len()).unwrap_or(0);
+        Some(format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: {content_type}\r\nContent-Length: {len}\r\n\r\n"
+        ))
+    } else {
+        None
+    };
+
+    let body_tag = if method == "REQMOD" {
+        "req-body"
+    } else {
+        "res-body"
+    };
+    let encapsulated = match (&res_hdr, &t.body) {
+        (Some(res), Some(_)) => format!(
+            "req-hdr=0, res-hdr={}, {body_tag}={}",
+            req_hdr.len(),
+            req_hdr.len() + res.len()
+        ),
+        (Some(res), None) => format!(
+            "req-hdr=0, res-hdr={}, null-body={}",
+            req_hdr.len(),
+            req_hdr.len() + res.len()
+        ),
+        (None, Some(_)) => format!("req-hdr=0, {body_tag}={}", req_hdr.len()),
+        (None, None) => format!("req-hdr=0, null-body={}", req_hdr.len()),
+    };
+    out.push_str(&format!("Encapsulated: {encapsulated}\r\n\r\n"));
+    out.push_str(&req_hdr);
+    if let Some(res) = &res_hdr {
+        out.push_str(res);
+    }
+    if let Some(body) = &t.body {
+        out.push_str(&format!("{:x}\r\n{body}\r\n0\r\n\r\n", body.len()));
+    }
+    Ok(out.into_bytes())
+}
+
+/// Extract the ICAP status and the encapsulated HTTP status from a response
+///
+/// Returns `None` until the ICAP header section and the status line of the
+/// encapsulated res-hdr section, if any, have been received.
+fn parse_reply(buf: &[u8]) -> anyhow::Result<Option<Reply>> {
+    let Some(head_end) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+        return Ok(None);
+    };
+    let head = String::from_utf8_lossy(&buf[..head_end]);
+    let status = parse_status(head.as_bytes())?;
+    let res_hdr = head
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("encapsulated"))
+        .and_then(|(_, value)| encapsulated_offset(value, "res-hdr"));
+    let Some(offset) = res_hdr else {
+        return Ok(Some(Reply {
+            status,
+            http_status: None,
+        }));
+    };
+
+    let section = &buf[(head_end + 4 + offset).min(buf.len())..];
+    if !section.windows(2).any(|w| w == b"\r\n") {
+        return Ok(None);
+    }
+    let http_status = parse_status(section)?;
+    Ok(Some(Reply {
+        status,
+        http_status: Some(http_status),
+    }))
+}
+
+/// Get the offset of a section from the value of an Encapsulated header
+fn encapsulated_offset(value: &str, section: &str) -> Option<usize> {
+    value
+        .split(',')
+        .filter_map(|entity| entity.trim().split_once('='))
+        .find(|(name, _)| *name == section)
+        .and_then(|(_, offset)| offset.trim().parse().ok())
+}
+
+/// Extract the status code from an ICAP or HTTP status line
+fn parse_status(buf: &[u8]) -> anyhow::Result<u16> {
+    let text = String::from_utf8_lossy(buf);
+    let line = text
+        .lines()
+        .next()
+        .ok_or_else(|| anyhow!("empty response"))?;
+    let mut parts = line.split_whitespace();
+    match parts.next() {
+        Some(v) if v.starts_with("ICAP/") || v.starts_with("HTTP/") => {}
+        _ => return Err(anyhow!("invalid status line: {line}")),
+    }
+    parts
+        .next()
+        .and_then(|s| s.parse::<u16>().ok())
+        .ok_or_else(|| anyhow!("invalid status line: {line}"))
+}
+
+/// Print the report and return whether all transactions passed
+pub fn report(suite: &Suite, results: &[TransactionResult]) -> bool {
+    println!("suite: {}", suite.name);
+    let mut failed = 0;
+    for r in results {
+        let mark = if r.passed { "PASS" } else { "FAIL" };
+        if !r.passed {
+            failed += 1;
+        }
+        println!(
+            "  [{mark}] {} ({}, {}ms)",
+            r.name,
+            r.detail,
+            r.elapsed.as_millis()
+        );
+    }
+    println!(
+        "{} passed, {failed} failed, {} total",
+        results.len() - failed,
+        results.len()
+    );
+    failed == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_builtin_default_suite() {
+        let suite = Suite::parse(DEFAULT_SUITE).unwrap();
+        assert_eq!(suite.name, "default");
+        assert!(!suite.transactions.is_empty());
+    }
+
+    #[test]
+    fn reject_transaction_without_expectation() {
+        let yaml = "name: t\ntransactions:\n  - name: a\n    method: OPTIONS\n";
+        assert!(Suite::parse(yaml).is_err());
+    }
+
+    #[test]
+    fn build_reqmod_with_body() {
+        let t = Transaction {
+            name: "a".to_string(),
+            method: "REQMOD".to_string(),
+            service: "/reqmod".to_string(),
+            url: Some("http://example.com/x?y=1".to_string()),
+            body: Some("abc".to_string()),
+            content_type: None,
+            verdict: Some(Verdict::Allow),
+            status: None,
+            http_status: None,
+        };
+        let addr: SocketAddr = "127.0.0.1:1344".parse().unwrap();
+        let req = String::from_utf8(build_request(&addr, &t).unwrap()).unwrap();
+        assert!(req.starts_with("REQMOD icap://127.0.0.1:1344/reqmod ICAP/1.0\r\n"));
+        assert!(req.contains("POST /x?y=1 HTTP/1.1\r\n"));
+        assert!(req.ends_with("3\r\nabc\r\n0\r\n\r\n"));
+    }
+
+    #[test]
+    fn parse_status_line() {
+        assert_eq!(parse_status(b"ICAP/1.0 204 No Content\r\n").unwrap(), 204);
+        assert!(parse_status(b"garbage").is_err());
+    }
+
+    #[test]
+    fn parse_encapsulated_status() {
+        let reply = parse_reply(b"ICAP/1.0 204 No Content\r\nISTag: \"x\"\r\n\r\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(reply.status, 204);
+        assert_eq!(reply.http_status, None);
+
+        let head = b"ICAP/1.0 200 OK\r\nEncapsulated: res-hdr=0, res-body=38\r\n\r\n";
+        // the encapsulated status line is not complete yet
+        assert!(parse_reply(head).unwrap().is_none());
+        let mut buf = head.to_vec();
+        buf.extend_from_slice(b"HTTP/1.1 403 Forbidden\r\nContent-Length: 4\r\n\r\n");
+        let reply = parse_reply(&buf).unwrap().unwrap();
+        assert_eq!(reply.status, 200);
+        assert_eq!(reply.http_status, Some(403));
+        assert_eq!(reply.to_string(), "200/403");
+
+        assert!(!Verdict::Allow.matches("REQMOD", &reply));
+        assert!(!Verdict::Allow.matches("RESPMOD", &reply));
+        assert!(Verdict::Block.matches("REQMOD", &reply));
+        assert!(Verdict::Block.matches("RESPMOD", &reply));
+        let ok = Reply {
+            status: 200,
+            http_status: Some(200),
+        };
+        assert!(Verdict::Allow.matches("RESPMOD", &ok));
+        assert!(!Verdict::Allow.matches("REQMOD", &ok));
+        assert!(!Verdict::Block.matches("RESPMOD", &ok));
+        // a redirection block page
+        assert!(Verdict::Block.matches("REQMOD", &ok));
+    }
+}
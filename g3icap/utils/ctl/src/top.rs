@@ -0,0 +1,498 @@
+//! Live status dashboard of the running daemon
+//!
+//! The stats, status and audit events of the daemon are polled over the
+//! control socket and drawn in place on the terminal with ANSI escapes: the
+//! request rate, the verdict mix, the latency trend of each service, the
+//! health of the modules and of their backends, and the latest events. It
+//! needs nothing but the control socket, for hosts without a metrics stack.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write as _;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use g3icap::control::command;
+use serde_json::Value;
+
+/// Number of samples kept for the sparklines
+const HISTORY: usize = 60;
+/// Width of the verdict mix bar
+const BAR_WIDTH: usize = 50;
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Move to the top left and clear the screen
+const CLEAR: &str = "\x1b[H\x1b[2J";
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const MAGENTA: &str = "\x1b[35m";
+
+fn count(v: &Value, key: &str) -> u64 {
+    v[key].as_u64().unwrap_or_default()
+}
+
+fn find<'a>(list: &'a Value, name: &str) -> Option<&'a Value> {
+    list.as_array()?.iter().find(|v| v["name"] == name)
+}
+
+/// Draw the values as a sparkline scaled to their maximum, a gap for `None`
+pub fn sparkline(values: impl IntoIterator<Item = Option<f64>>) -> String {
+    let values: Vec<Option<f64>> = values.into_iter().collect();
+    let max = values.iter().flatten().fold(0f64, |m, v| m.max(*v));
+    values
+        .iter()
+        .map(|v| match v {
+            None => ' ',
+            Some(_) if max <= 0.0 => SPARKS[0],
+            Some(v) => {
+                let level = (v / max * (SPARKS.len() - 1) as f64).round() as usize;
+                SPARKS[level.min(SPARKS.len() - 1)]
+            }
+        })
+        .collect()
+}
+
+fn format_bytes(rate: f64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut rate = rate;
+    let mut unit = 0;
+    while rate >= 1024.0 && unit < UNITS.len() - 1 {
+        rate /= 1024.0;
+        unit += 1;
+    }
+    format!("{rate:.1} {}/s", UNITS[unit])
+}
+
+fn format_ms(ms: Option<f64>) -> String {
+    ms.map(|ms| format!("{ms:.1}ms"))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// Rates of a service between the last two samples
+#[derive(Debug, Clone, Default)]
+struct ServiceRate {
+    name: String,
+    enabled: bool,
+    pipeline: Option<String>,
+    transactions: f64,
+    failed: f64,
+    /// Average time of the transactions of the interval, `None` if idle
+    latency_ms: Option<f64>,
+}
+
+/// Rates of a module between the last two samples
+#[derive(Debug, Clone, Default)]
+struct ModuleRate {
+    name: String,
+    healthy: bool,
+    requests: f64,
+    error_rate: f64,
+    latency_ms: f64,
+}
+
+/// Rates between the last two samples, per second
+#[derive(Debug, Clone, Default)]
+struct Rates {
+    requests: f64,
+    reqmod: f64,
+    respmod: f64,
+    options: f64,
+    bytes: f64,
+    connection_errors: f64,
+    active_connections: u64,
+    allowed: f64,
+    blocked: f64,
+    monitored: f64,
+    errors: f64,
+    services: Vec<ServiceRate>,
+    modules: Vec<ModuleRate>,
+}
+
+/// State of the dashboard, from the successive stats samples
+pub struct Dashboard {
+    previous: Option<(Instant, Value)>,
+    rates: Rates,
+    requests: VecDeque<f64>,
+    latency: HashMap<String, VecDeque<Option<f64>>>,
+    color: bool,
+}
+
+impl Dashboard {
+    pub fn new(color: bool) -> Self {
+        Dashboard {
+            previous: None,
+            rates: Rates::default(),
+            requests: VecDeque::with_capacity(HISTORY),
+            latency: HashMap::new(),
+            color,
+        }
+    }
+
+    /// Update the rates from a new sample of the stats
+    ///
+    /// The counters of a restarted daemon start again from zero, the
+    /// interval is then counted as idle.
+    pub fn update(&mut self, stats: Value, now: Instant) {
+        let Some((then, previous)) = self.previous.replace((now, stats)) else {
+            return;
+        };
+        let Some((_, stats)) = &self.previous else {
+            return;
+        };
+        let seconds = now.duration_since(then).as_secs_f64().max(0.001);
+        let (server, last) = (&stats["server"], &previous["server"]);
+        let rate = |key: &str| count(server, key).saturating_sub(count(last, key)) as f64 / seconds;
+
+        let blocked = rate("blocked_requests");
+        let mut rates = Rates {
+            requests: rate("requests"),
+            reqmod: rate("reqmod_requests"),
+            respmod: rate("respmod_requests"),
+            options: rate("options_requests"),
+            bytes: rate("bytes"),
+            connection_errors: rate("connection_errors"),
+            active_connections: count(server, "active_connections"),
+            // the blocking responses are successful ICAP responses too
+            allowed: (rate("successful_responses") - blocked).max(0.0),
+            blocked,
+            monitored: rate("monitored_verdicts"),
+            errors: rate("error_responses"),
+            ..Default::default()
+        };
+
+        for s in stats["services"].as_array().into_iter().flatten() {
+            let name = s["name"].as_str().unwrap_or_default().to_string();
+            let last = find(&previous["services"], &name).unwrap_or(&Value::Null);
+            let transactions = count(s, "transactions").saturating_sub(count(last, "transactions"));
+            // the total time of the interval, from the averages of both samples
+            let latency_ms = (transactions > 0).then(|| {
+                let total =
+                    |v: &Value| count(v, "avg_time_us") as f64 * count(v, "transactions") as f64;
+                (total(s) - total(last)).max(0.0) / transactions as f64 / 1000.0
+            });
+            let history = self.latency.entry(name.clone()).or_default();
+            if history.len() == HISTORY {
+                history.pop_front();
+            }
+            history.push_back(latency_ms);
+            rates.services.push(ServiceRate {
+                enabled: s["enabled"].as_bool().unwrap_or(true),
+                pipeline: s["pipeline"].as_str().map(str::to_string),
+                transactions: transactions as f64 / seconds,
+                failed: count(s, "failed").saturating_sub(count(last, "failed")) as f64 / seconds,
+                latency_ms,
+                name,
+            });
+        }
+
+        for m in stats["modules"].as_array().into_iter().flatten() {
+            let name = m["name"].as_str().unwrap_or_default().to_string();
+            let last = find(&previous["modules"], &name).unwrap_or(&Value::Null);
+            rates.modules.push(ModuleRate {
+                healthy: m["healthy"].as_bool().unwrap_or(true),
+                requests: count(m, "requests").saturating_sub(count(last, "requests")) as f64
+                    / seconds,
+                error_rate: m["error_rate"].as_f64().unwrap_or_default(),
+                latency_ms: count(m, "avg_time_us") as f64 / 1000.0,
+                name,
+            });
+        }
+
+        if self.requests.len() == HISTORY {
+            self.requests.pop_front();
+        }
+        self.requests.push_back(rates.requests);
+        self.rates = rates;
+    }
+
+    fn paint(&self, color: &str, s: &str) -> String {
+        if self.color {
+            format!("{color}{s}{RESET}")
+        } else {
+            s.to_string()
+        }
+    }
+
+    fn verdict_bar(&self) -> String {
+        let r = &self.rates;
+        let total = r.allowed + r.blocked + r.monitored + r.errors;
+        if total <= 0.0 {
+            return "-".repeat(BAR_WIDTH);
+        }
+        let mut bar = String::new();
+        let mut used = 0;
+        let segments = [
+            (GREEN, '=', r.allowed),
+            (RED, '#', r.blocked),
+            (YELLOW, '~', r.monitored),
+            (MAGENTA, '!', r.errors),
+        ];
+        for (i, (color, c, value)) in segments.iter().enumerate() {
+            let width = if i == segments.len() - 1 {
+                BAR_WIDTH - used
+            } else {
+                ((value / total) * BAR_WIDTH as f64).round() as usize
+            };
+            let width = width.min(BAR_WIDTH - used);
+            used += width;
+            bar.push_str(&self.paint(color, &c.to_string().repeat(width)));
+        }
+        bar
+    }
+
+    /// Draw the dashboard, with the daemon status, the latest events and the
+    /// error of the last poll if any
+    pub fn render(
+        &self,
+        status: &Value,
+        events: Result<&[Value], &str>,
+        error: Option<&str>,
+        width: usize,
+    ) -> String {
+        let mut out = String::new();
+        let version = &status["version"];
+        let _ = writeln!(
+            out,
+            "{} {} ({}), {}    {}",
+            self.paint(BOLD, "g3icap"),
+            version["version"].as_str().unwrap_or("unknown"),
+            version["git_commit"].as_str().unwrap_or("unknown"),
+            if status["draining"].as_bool().unwrap_or(false) {
+                "draining"
+            } else {
+                "serving"
+            },
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+        );
+        if let Some(error) = error {
+            let _ = writeln!(out, "{}", self.paint(RED, &format!("poll failed: {error}")));
+        }
+        if self.requests.is_empty() {
+            let _ = writeln!(out, "\ncollecting the first samples...");
+        }
+
+        let r = &self.rates;
+        let _ = writeln!(
+            out,
+            "\nrequests   {:.1}/s  reqmod {:.1}/s  respmod {:.1}/s  options {:.1}/s",
+            r.requests, r.reqmod, r.respmod, r.options
+        );
+        let _ = writeln!(
+            out,
+            "traffic    {}  connections {} active, {:.1} errors/s",
+            format_bytes(r.bytes),
+            r.active_connections,
+            r.connection_errors
+        );
+        let peak = self.requests.iter().fold(0f64, |m, v| m.max(*v));
+        let _ = writeln!(
+            out,
+            "rate       {}  peak {peak:.1}/s",
+            sparkline(self.requests.iter().map(|v| Some(*v)))
+        );
+
+        let total = r.allowed + r.blocked + r.monitored + r.errors;
+        let share = |v: f64| if total > 0.0 { v / total * 100.0 } else { 0.0 };
+        let _ = writeln!(
+            out,
+            "\nverdicts   {} {:.1}%  {} {:.1}%  {} {:.1}%  {} {:.1}%",
+            self.paint(GREEN, "allowed"),
+            share(r.allowed),
+            self.paint(RED, "blocked"),
+            share(r.blocked),
+            self.paint(YELLOW, "monitored"),
+            share(r.monitored),
+            self.paint(MAGENTA, "error"),
+            share(r.errors),
+        );
+        let _ = writeln!(out, "           [{}]", self.verdict_bar());
+
+        let _ = writeln!(
+            out,
+            "\n{}",
+            self.paint(
+                BOLD,
+                &format!(
+                    "{:<24} {:<9} {:>9} {:>9} {:>9}  latency trend",
+                    "service", "state", "tx/s", "failed/s", "latency"
+                )
+            )
+        );
+        for s in &r.services {
+            let state = if s.enabled { "enabled" } else { "disabled" };
+            let name = match &s.pipeline {
+                Some(pipeline) => format!("{} ({pipeline})", s.name),
+                None => s.name.clone(),
+            };
+            let trend = self
+                .latency
+                .get(&s.name)
+                .map(|h| sparkline(h.iter().copied()))
+                .unwrap_or_default();
+            let _ = writeln!(
+                out,
+                "{name:<24} {state:<9} {:>9.1} {:>9.1} {:>9}  {trend}",
+                s.transactions,
+                s.failed,
+                format_ms(s.latency_ms)
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "\n{}",
+            self.paint(
+                BOLD,
+                &format!(
+                    "{:<24} {:<9} {:>9} {:>9} {:>9}",
+                    "module", "backend", "req/s", "errors", "latency"
+                )
+            )
+        );
+        for m in &r.modules {
+            let health = if m.healthy {
+                self.paint(GREEN, &format!("{:<9}", "ok"))
+            } else {
+                self.paint(RED, &format!("{:<9}", "down"))
+            };
+            let _ = writeln!(
+                out,
+                "{:<24} {health} {:>9.1} {:>8.2}% {:>9}",
+                m.name,
+                m.requests,
+                m.error_rate * 100.0,
+                format_ms(Some(m.latency_ms))
+            );
+        }
+
+        let _ = writeln!(out, "\n{}", self.paint(BOLD, "events"));
+        match events {
+            Ok([]) => {
+                let _ = writeln!(out, "no event");
+            }
+            Ok(events) => {
+                for e in events {
+                    let time = e["timestamp"]
+                        .as_i64()
+                        .and_then(|t| chrono::DateTime::from_timestamp(t, 0))
+                        .map(|t| {
+                            t.with_timezone(&chrono::Local)
+                                .format("%H:%M:%S")
+                                .to_string()
+                        })
+                        .unwrap_or_default();
+                    let line = format!(
+                        "{time} {:<8} {:<16} {}: {}",
+                        e["severity"].as_str().unwrap_or_default(),
+                        e["event_type"].as_str().unwrap_or_default(),
+                        e["message"].as_str().unwrap_or_default(),
+                        e["details"].as_str().unwrap_or_default(),
+                    );
+                    let _ = writeln!(out, "{}", line.chars().take(width).collect::<String>());
+                }
+            }
+            Err(e) => {
+                let _ = writeln!(out, "unavailable: {e}");
+            }
+        }
+        out
+    }
+}
+
+/// Width of the terminal, from `COLUMNS` as set by the shell
+fn terminal_width() -> usize {
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|c| c.parse().ok())
+        .unwrap_or(120)
+}
+
+fn poll(path: &Path) -> anyhow::Result<(Value, Value)> {
+    let stats = serde_json::from_str(&command::request_stats(path)?)?;
+    let status = serde_json::from_str(&command::request_status(path)?)?;
+    Ok((stats, status))
+}
+
+/// Refresh the dashboard until interrupted
+///
+/// Fails if the daemon can not be reached at first, later failures are shown
+/// on the dashboard while the polling goes on, like during an upgrade.
+pub fn run(path: &Path, interval: Duration, events: usize) -> anyhow::Result<()> {
+    let color = std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    let mut dashboard = Dashboard::new(color);
+    let (stats, mut status) = poll(path)?;
+    dashboard.update(stats, Instant::now());
+
+    let mut stdout = std::io::stdout();
+    loop {
+        let start = Instant::now();
+        let mut error = None;
+        match poll(path) {
+            Ok((stats, s)) => {
+                dashboard.update(stats, start);
+                status = s;
+            }
+            Err(e) => error = Some(format!("{e:#}")),
+        }
+        // the daemons older than the events command still get the other panels
+        let tail = command::request_events(path, events)
+            .and_then(|s| Ok(serde_json::from_str::<Vec<Value>>(&s)?))
+            .map_err(|e| format!("{e:#}"));
+        let tail = tail.as_deref().map_err(String::as_str);
+        let frame = dashboard.render(&status, tail, error.as_deref(), terminal_width());
+        write!(
+            stdout,
+            "{CLEAR}{frame}(refresh every {}s, Ctrl-C to quit)",
+            interval.as_secs_f64()
+        )?;
+        stdout.flush()?;
+        std::thread::sleep(interval.saturating_sub(start.elapsed()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn sparkline_scale() {
+        assert_eq!(sparkline([Some(0.0), Some(5.0), None, Some(10.0)]), "▁▅ █");
+        assert_eq!(sparkline([Some(0.0), Some(0.0)]), "▁▁");
+    }
+
+    #[test]
+    fn rates() {
+        let sample = |requests: u64, blocked: u64, tx: u64, avg: u64| {
+            json!({
+                "server": {
+                    "requests": requests,
+                    "successful_responses": requests,
+                    "blocked_requests": blocked,
+                },
+                "services": [{"name": "reqmod", "enabled": true, "transactions": tx, "avg_time_us": avg}],
+                "modules": [{"name": "antivirus", "requests": tx, "error_rate": 0.0, "avg_time_us": 0, "healthy": false}],
+            })
+        };
+        let mut dashboard = Dashboard::new(false);
+        let start = Instant::now();
+        dashboard.update(sample(100, 0, 100, 1000), start);
+        dashboard.update(sample(300, 50, 200, 2000), start + Duration::from_secs(2));
+
+        let r = &dashboard.rates;
+        assert_eq!(r.requests, 100.0);
+        assert_eq!(r.blocked, 25.0);
+        assert_eq!(r.allowed, 75.0);
+        // the average goes from 1ms over 100 transactions to 2ms over 200
+        assert_eq!(r.services[0].latency_ms, Some(3.0));
+        assert!(!r.modules[0].healthy);
+
+        let frame = dashboard.render(&json!({}), Ok(&[]), None, 80);
+        assert!(frame.contains("requests   100.0/s"));
+        assert!(frame.contains("allowed 75.0%  blocked 25.0%"));
+        assert!(frame.contains("antivirus                down"));
+    }
+}
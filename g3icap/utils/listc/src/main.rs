@@ -0,0 +1,116 @@
+//! G3ICAP List Compiler
+//!
+//! This utility compiles domain lists and digest lists into the frozen
+//! format that the G3ICAP server maps read-only.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, anyhow};
+use clap::{Parser, ValueEnum};
+
+use g3icap::config::server::blocklist::BlocklistFormat;
+use g3icap::modules::blocklist::parse_domains;
+use g3icap::modules::list_store::{self, FrozenList, ListKind};
+
+#[derive(Parser)]
+#[command(name = "g3icap-listc")]
+#[command(about = "G3ICAP List Compiler")]
+struct Cli {
+    /// Format of the input lists
+    #[arg(short, long, value_enum, default_value = "plain")]
+    format: InputFormat,
+    /// Output file, replaced atomically so a running server can remap it
+    #[arg(short, long)]
+    output: PathBuf,
+    /// Input lists
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum InputFormat {
+    /// One domain per line
+    Plain,
+    /// hosts file, `0.0.0.0 domain`
+    Hosts,
+    /// Adblock filter list, only `||domain^` rules are used
+    Adblock,
+    /// One hex digest per line, such as the SHA-256 of malicious content
+    Digests,
+}
+
+fn read_entries(format: InputFormat, path: &Path) -> anyhow::Result<Vec<Vec<u8>>> {
+    let data = std::fs::read(path).context(format!("failed to read {}", path.display()))?;
+    let text = String::from_utf8_lossy(&data);
+    let format = match format {
+        InputFormat::Plain => BlocklistFormat::Plain,
+        InputFormat::Hosts => BlocklistFormat::Hosts,
+        InputFormat::Adblock => BlocklistFormat::Adblock,
+        InputFormat::Digests => {
+            let mut digests = Vec::new();
+            for (i, line) in text.lines().enumerate() {
+                let line = line.split('#').next().unwrap_or_default().trim();
+                if line.is_empty() {
+                    continue;
+                }
+                let digest = list_store::decode_hex(line)
+                    .ok_or_else(|| anyhow!("{}:{}: invalid digest", path.display(), i + 1))?;
+                digests.push(digest);
+            }
+            return Ok(digests);
+        }
+    };
+    Ok(parse_domains(&text, format)
+        .into_iter()
+        .map(String::into_bytes)
+        .collect())
+}
+
+fn compile(cli: &Cli) -> anyhow::Result<FrozenList> {
+    let kind = match cli.format {
+        InputFormat::Digests => ListKind::Digests,
+        _ => ListKind::Domains,
+    };
+    let mut entries = Vec::new();
+    for input in &cli.inputs {
+        entries.extend(read_entries(cli.format, input)?);
+    }
+
+    let mut tmp = cli.output.clone().into_os_string();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let file = File::create(&tmp).context(format!("failed to create {}", tmp.display()))?;
+    let mut writer = BufWriter::new(file);
+    list_store::write_list(kind, entries, &mut writer)?;
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    drop(writer);
+
+    // check the result before it replaces the previous list
+    let list = FrozenList::open(&tmp)?;
+    std::fs::rename(&tmp, &cli.output).context(format!(
+        "failed to rename {} to {}",
+        tmp.display(),
+        cli.output.display()
+    ))?;
+    Ok(list)
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    match compile(&cli) {
+        Ok(list) => println!(
+            "{}: {} {}",
+            cli.output.display(),
+            list.len(),
+            list.kind().as_str()
+        ),
+        Err(e) => {
+            eprintln!("failed to compile list: {e:?}");
+            std::process::exit(1);
+        }
+    }
+}
@@ -6,5 +6,8 @@
 mod sched;
 pub use sched::CpuAffinity;
 
+mod numa;
+pub use numa::{MemoryPolicy, NumaNode, current_cpu_id, numa_nodes, set_memory_policy};
+
 mod hostname;
 pub use hostname::hostname;
@@ -0,0 +1,93 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2025 ByteDance and/or its affiliates.
+ */
+
+use std::io;
+use std::path::Path;
+
+use super::{MemoryPolicy, NumaNode};
+
+const NODE_DIR: &str = "/sys/devices/system/node";
+const CPU_ONLINE: &str = "/sys/devices/system/cpu/online";
+
+const MPOL_PREFERRED: libc::c_ulong = 1;
+const MPOL_INTERLEAVE: libc::c_ulong = 3;
+
+pub(super) fn numa_nodes() -> io::Result<Vec<NumaNode>> {
+    if !Path::new(NODE_DIR).exists() {
+        // kernel built without NUMA support
+        let cpus = std::fs::read_to_string(CPU_ONLINE)?;
+        return Ok(vec![NumaNode {
+            id: 0,
+            cpu_id_list: super::parse_cpu_list(&cpus)?,
+        }]);
+    }
+
+    let mut nodes = Vec::new();
+    for entry in std::fs::read_dir(NODE_DIR)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(id) = name
+            .to_str()
+            .and_then(|s| s.strip_prefix("node"))
+            .and_then(|s| s.parse::<usize>().ok())
+        else {
+            continue;
+        };
+        let cpus = std::fs::read_to_string(entry.path().join("cpulist"))?;
+        let cpu_id_list = super::parse_cpu_list(&cpus)?;
+        // memory only nodes are of no use to place threads
+        if !cpu_id_list.is_empty() {
+            nodes.push(NumaNode { id, cpu_id_list });
+        }
+    }
+    nodes.sort_by_key(|n| n.id);
+    Ok(nodes)
+}
+
+pub(super) fn current_cpu_id() -> Option<usize> {
+    let r = unsafe { libc::sched_getcpu() };
+    usize::try_from(r).ok()
+}
+
+pub(super) fn set_memory_policy(
+    memory: &mut [u8],
+    policy: MemoryPolicy,
+    nodes: &[usize],
+) -> io::Result<()> {
+    const BITS: usize = libc::c_ulong::BITS as usize;
+
+    let max_node = nodes.iter().copied().max().unwrap_or_default();
+    let mut mask: Vec<libc::c_ulong> = vec![0; max_node / BITS + 1];
+    let mode = match policy {
+        MemoryPolicy::Interleave => {
+            for node in nodes {
+                mask[node / BITS] |= 1 << (node % BITS);
+            }
+            MPOL_INTERLEAVE
+        }
+        MemoryPolicy::Preferred => {
+            mask[nodes[0] / BITS] |= 1 << (nodes[0] % BITS);
+            MPOL_PREFERRED
+        }
+    };
+    // the kernel ignores the last bit of the max node
+    let max_node = mask.len() * BITS + 1;
+    let r = unsafe {
+        libc::syscall(
+            libc::SYS_mbind,
+            memory.as_mut_ptr(),
+            memory.len(),
+            mode,
+            mask.as_ptr(),
+            max_node,
+            0,
+        )
+    };
+    if r != 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
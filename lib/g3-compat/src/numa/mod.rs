@@ -0,0 +1,119 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2025 ByteDance and/or its affiliates.
+ */
+
+use std::io;
+
+#[cfg_attr(any(target_os = "linux", target_os = "android"), path = "linux.rs")]
+#[cfg_attr(
+    not(any(target_os = "linux", target_os = "android")),
+    path = "other.rs"
+)]
+mod os;
+
+/// A NUMA node of the host
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NumaNode {
+    id: usize,
+    cpu_id_list: Vec<usize>,
+}
+
+impl NumaNode {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn cpu_id_list(&self) -> &[usize] {
+        &self.cpu_id_list
+    }
+}
+
+/// Memory policy of an allocation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryPolicy {
+    /// Pages spread round-robin over the nodes
+    Interleave,
+    /// Pages on the first node, falling back to the others if it is full
+    Preferred,
+}
+
+/// Get the NUMA nodes with CPUs, sorted by ID
+///
+/// Hosts without NUMA support are reported as a single node.
+pub fn numa_nodes() -> io::Result<Vec<NumaNode>> {
+    os::numa_nodes()
+}
+
+/// Get the CPU the current thread is running on
+pub fn current_cpu_id() -> Option<usize> {
+    os::current_cpu_id()
+}
+
+/// Set the memory policy of pages not touched yet
+///
+/// The memory should be page aligned, like an anonymous mapping.
+pub fn set_memory_policy(
+    memory: &mut [u8],
+    policy: MemoryPolicy,
+    nodes: &[usize],
+) -> io::Result<()> {
+    if nodes.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no NUMA node to place memory on",
+        ));
+    }
+    os::set_memory_policy(memory, policy, nodes)
+}
+
+fn parse_cpu_list(s: &str) -> io::Result<Vec<usize>> {
+    let invalid = |part: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid CPU list part {part}"),
+        )
+    };
+
+    let mut list = Vec::new();
+    for part in s.trim().split(',') {
+        if part.is_empty() {
+            continue;
+        }
+        match part.split_once('-') {
+            Some((start, end)) => {
+                let start = start.parse::<usize>().map_err(|_| invalid(part))?;
+                let end = end.parse::<usize>().map_err(|_| invalid(part))?;
+                if start > end {
+                    return Err(invalid(part));
+                }
+                list.extend(start..=end);
+            }
+            None => list.push(part.parse::<usize>().map_err(|_| invalid(part))?),
+        }
+    }
+    Ok(list)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_list() {
+        assert_eq!(parse_cpu_list("0-3,8\n").unwrap(), vec![0, 1, 2, 3, 8]);
+        assert_eq!(parse_cpu_list("5").unwrap(), vec![5]);
+        assert!(parse_cpu_list("\n").unwrap().is_empty());
+        assert!(parse_cpu_list("3-1").is_err());
+        assert!(parse_cpu_list("a").is_err());
+    }
+
+    #[test]
+    fn nodes() {
+        let nodes = numa_nodes().unwrap();
+        assert!(!nodes.is_empty());
+        if let Some(cpu) = current_cpu_id() {
+            assert!(nodes.iter().any(|n| n.cpu_id_list().contains(&cpu)));
+        }
+    }
+}
@@ -0,0 +1,32 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright 2025 ByteDance and/or its affiliates.
+ */
+
+use std::io;
+use std::num::NonZeroUsize;
+
+use super::{MemoryPolicy, NumaNode};
+
+pub(super) fn numa_nodes() -> io::Result<Vec<NumaNode>> {
+    let cpus = std::thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    Ok(vec![NumaNode {
+        id: 0,
+        cpu_id_list: (0..cpus).collect(),
+    }])
+}
+
+pub(super) fn current_cpu_id() -> Option<usize> {
+    None
+}
+
+pub(super) fn set_memory_policy(
+    _memory: &mut [u8],
+    _policy: MemoryPolicy,
+    _nodes: &[usize],
+) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "memory policies are not supported on this platform",
+    ))
+}
@@ -3,7 +3,8 @@
  * Copyright 2023-2025 ByteDance and/or its affiliates.
  */
 
-use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{OnceLock, RwLock};
 
 use log::{LevelFilter, Metadata, Record};
 use slog::{Drain, Logger, slog_o};
@@ -15,6 +16,11 @@ use crate::opts::DaemonArgs;
 const PROCESS_LOG_THREAD_NAME: &str = "log-process";
 
 static PROCESS_LOGGER: OnceLock<Logger> = OnceLock::new();
+static PROCESS_LOG_LEVEL: OnceLock<LevelFilter> = OnceLock::new();
+
+/// Levels of the log targets set at runtime, which differ from the process one
+static TARGET_LEVELS: RwLock<Vec<(String, LevelFilter)>> = RwLock::new(Vec::new());
+static HAS_TARGET_LEVELS: AtomicBool = AtomicBool::new(false);
 
 pub fn setup(args: &DaemonArgs) {
     let async_conf = AsyncLogConfig::with_name(PROCESS_LOG_THREAD_NAME);
@@ -45,6 +51,7 @@ pub fn setup(args: &DaemonArgs) {
         2 => LevelFilter::Debug,
         _ => LevelFilter::Trace,
     };
+    let _ = PROCESS_LOG_LEVEL.set(log_level);
     log::set_max_level(log_level);
     log::set_boxed_logger(Box::new(BridgeLogger {
         level_filter: log_level,
@@ -52,6 +59,43 @@ pub fn setup(args: &DaemonArgs) {
     .unwrap();
 }
 
+/// Set the log level of a target and of the modules under it, like
+/// `g3proxy::serve` for all the servers of g3proxy
+///
+/// The level of the longest matching target is used instead of the process
+/// one. `None` removes the level of the target.
+pub fn set_target_level(target: &str, level: Option<LevelFilter>) {
+    let mut levels = TARGET_LEVELS.write().unwrap();
+    levels.retain(|(t, _)| t != target);
+    if let Some(level) = level {
+        levels.push((target.to_string(), level));
+    }
+    HAS_TARGET_LEVELS.store(!levels.is_empty(), Ordering::Relaxed);
+
+    let process_level = PROCESS_LOG_LEVEL
+        .get()
+        .copied()
+        .unwrap_or(LevelFilter::Warn);
+    let max_level = levels
+        .iter()
+        .map(|(_, level)| *level)
+        .fold(process_level, LevelFilter::max);
+    log::set_max_level(max_level);
+}
+
+fn target_level(target: &str) -> Option<LevelFilter> {
+    let levels = TARGET_LEVELS.read().unwrap();
+    levels
+        .iter()
+        .filter(|(t, _)| {
+            target
+                .strip_prefix(t.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+        })
+        .max_by_key(|(t, _)| t.len())
+        .map(|(_, level)| *level)
+}
+
 struct BridgeLogger {
     level_filter: LevelFilter,
 }
@@ -66,6 +110,13 @@ impl log::Log for BridgeLogger {
             return;
         };
 
+        if HAS_TARGET_LEVELS.load(Ordering::Relaxed) {
+            let level = target_level(record.target()).unwrap_or(self.level_filter);
+            if record.level() > level {
+                return;
+            }
+        }
+
         let level = match record.level() {
             log::Level::Trace => slog::Level::Trace,
             log::Level::Debug => slog::Level::Debug,
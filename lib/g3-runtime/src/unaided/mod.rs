@@ -126,6 +126,28 @@ impl UnaidedRuntimeConfig {
         Ok(())
     }
 
+    /// Pin each worker to all the CPUs of a NUMA node, spreading the workers
+    /// round-robin over the nodes
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn auto_set_numa_sched_affinity(&mut self) -> anyhow::Result<()> {
+        let nodes =
+            g3_compat::numa_nodes().map_err(|e| anyhow!("failed to get NUMA nodes: {e}"))?;
+        if nodes.is_empty() {
+            return Err(anyhow!("no NUMA node with CPUs found"));
+        }
+        let n = self.thread_number_total.get() / self.thread_number_per_rt.get();
+        for (i, node) in (0..n).zip(nodes.iter().cycle()) {
+            let mut cpu = CpuAffinity::default();
+            for id in node.cpu_id_list() {
+                cpu.add_id(*id).map_err(|e| {
+                    anyhow!("unable to build cpu set for NUMA node {}: {e}", node.id())
+                })?;
+            }
+            self.sched_affinity.insert(i, cpu);
+        }
+        Ok(())
+    }
+
     pub fn set_max_io_events_per_tick(&mut self, capacity: usize) {
         self.max_io_events_per_tick = Some(capacity);
     }
@@ -21,6 +21,8 @@ impl UnaidedRuntimeConfig {
                 windows,
             ))]
             let mut auto_set_sched_affinity = false;
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            let mut numa_sched_affinity = false;
 
             g3_yaml::foreach_kv(map, |k, v| match g3_yaml::key::normalize(k).as_str() {
                 "thread_number_total" | "threads_total" | "thread_number" => {
@@ -59,6 +61,18 @@ impl UnaidedRuntimeConfig {
                             config.set_sched_affinity(id, cpu);
                         }
                         Ok(())
+                    } else if let Yaml::String(s) = v
+                        && s.eq_ignore_ascii_case("numa")
+                    {
+                        #[cfg(any(target_os = "linux", target_os = "android"))]
+                        {
+                            numa_sched_affinity = true;
+                            Ok(())
+                        }
+                        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+                        Err(anyhow!(
+                            "NUMA sched affinity is not supported on this platform"
+                        ))
                     } else if let Ok(enable) = g3_yaml::value::as_bool(v) {
                         auto_set_sched_affinity = enable;
                         Ok(())
@@ -99,6 +113,12 @@ impl UnaidedRuntimeConfig {
                     .auto_set_sched_affinity()
                     .context("failed to set all mapped sched affinity")?;
             }
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            if numa_sched_affinity {
+                config
+                    .auto_set_numa_sched_affinity()
+                    .context("failed to set NUMA sched affinity")?;
+            }
 
             config.check().context("invalid worker config")?;
             Ok(config)
@@ -166,6 +186,20 @@ mod tests {
             assert!(!config.sched_affinity.is_empty());
         }
 
+        // NUMA node sched_affinity
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            let yaml = yaml_doc!(
+                r#"
+                thread_number_total: 4
+                thread_number_per_runtime: 2
+                sched_affinity: numa
+            "#
+            );
+            let config = UnaidedRuntimeConfig::parse_yaml(&yaml).unwrap();
+            assert_eq!(config.sched_affinity.len(), 2);
+        }
+
         // Openssl async job configs
         #[cfg(feature = "openssl-async-job")]
         {